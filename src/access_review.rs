@@ -0,0 +1,400 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::info;
+use serde_derive::Serialize;
+
+use crate::compliance;
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::scheduler;
+use crate::server::sessions::{SessionRecord, Sessions};
+use crate::slack::{self, SlackAttachmentBuilder, SlackRequest};
+use crate::worker::Worker;
+
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_USER: &str = "user";
+
+pub const REVOKE_SESSION_ACTION: &str = "revoke-session";
+pub const REVOKE_USER_SESSIONS_ACTION: &str = "revoke-user-sessions";
+pub const REVOKE_OTHER_SESSIONS_ACTION: &str = "revoke-other-sessions";
+pub const REVOKE_ACCOUNT_ACTION: &str = "revoke-account";
+pub const REINSTATE_ACCOUNT_ACTION: &str = "reinstate-account";
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+// Everyone who has logged in to octobot, and whether they may still
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AccountLogin {
+    pub user: String,
+    pub admin: bool,
+    pub first_login: i64,
+    pub last_login: i64,
+    pub revoked_at: Option<i64>,
+    pub revoked_by: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct AccountLogins {
+    db: Database,
+}
+
+impl AccountLogins {
+    pub fn new(db: Database) -> AccountLogins {
+        AccountLogins { db: db }
+    }
+
+    pub fn record_login(&self, user: &str, admin: bool) -> Result<()> {
+        let conn = self.db.connect()?;
+        let now = db::now();
+        conn.execute(
+            r#"INSERT INTO account_logins ("user", admin, first_login, last_login)
+               VALUES (?1, ?2, ?3, ?3)
+               ON CONFLICT DO NOTHING"#,
+            &[&user as &dyn ToSql, &db::to_tinyint(admin), &now],
+        )
+        .map_err(|e| format_err!("Error recording login of {}: {}", user, e))?;
+        conn.execute(
+            r#"UPDATE account_logins SET admin = ?2, last_login = ?3 WHERE "user" = ?1"#,
+            &[&user as &dyn ToSql, &db::to_tinyint(admin), &now],
+        )
+        .map_err(|e| format_err!("Error recording login of {}: {}", user, e))?;
+
+        Ok(())
+    }
+
+    // Stops the user from logging in until they are reinstated
+    pub fn revoke(&self, user: &str, revoked_by: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        let now = db::now();
+        conn.execute(
+            r#"INSERT INTO account_logins ("user", admin, first_login, last_login)
+               VALUES (?1, 0, 0, 0)
+               ON CONFLICT DO NOTHING"#,
+            &[&user],
+        )
+        .map_err(|e| format_err!("Error revoking access of {}: {}", user, e))?;
+        conn.execute(
+            r#"UPDATE account_logins SET revoked_at = ?2, revoked_by = ?3 WHERE "user" = ?1"#,
+            &[&user as &dyn ToSql, &now, &revoked_by],
+        )
+        .map_err(|e| format_err!("Error revoking access of {}: {}", user, e))?;
+
+        Ok(())
+    }
+
+    pub fn reinstate(&self, user: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"UPDATE account_logins SET revoked_at = NULL, revoked_by = NULL WHERE "user" = ?1"#,
+            &[&user],
+        )
+        .map_err(|e| format_err!("Error reinstating access of {}: {}", user, e))?;
+
+        Ok(())
+    }
+
+    pub fn is_revoked(&self, user: &str) -> Result<bool> {
+        Ok(self.get_all()?.iter().any(|a| a.user == user && a.revoked_at.is_some()))
+    }
+
+    pub fn get_all(&self) -> Result<Vec<AccountLogin>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(r#"SELECT * FROM account_logins ORDER BY "user""#)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(db::NO_PARAMS)?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(AccountLogin {
+                user: cols.get(row, "user")?,
+                admin: db::to_bool(cols.get(row, "admin")?),
+                first_login: cols.get(row, "first_login")?,
+                last_login: cols.get(row, "last_login")?,
+                revoked_at: cols.get(row, "revoked_at")?,
+                revoked_by: cols.get(row, "revoked_by")?,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+// One account's access, as of the review
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AccountAccess {
+    pub user: String,
+    pub role: String,
+    pub last_login: Option<i64>,
+    // the last time any of its sessions made a request, or else its last login
+    pub last_used: Option<i64>,
+    pub sessions: Vec<SessionRecord>,
+    // not used in `stale_days`, including the configured admin if it has never logged in
+    pub stale: bool,
+    pub revoked_at: Option<i64>,
+    pub revoked_by: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AccessReview {
+    pub generated_at: i64,
+    pub stale_days: i64,
+    pub accounts: Vec<AccountAccess>,
+}
+
+pub fn is_stale(last_used: Option<i64>, stale_days: i64, now: i64) -> bool {
+    match last_used {
+        Some(t) => now - t > stale_days * SECS_PER_DAY,
+        None => true,
+    }
+}
+
+impl AccessReview {
+    pub fn generate(config: &Config, sessions: &Sessions, now: i64) -> Result<AccessReview> {
+        let stale_days = config.access_review_stale_days();
+        let live = sessions.list();
+        let mut logins = config.account_logins.get_all()?;
+
+        // the configured admin holds credentials whether or not they have been used
+        if let Some(ref admin) = config.admin {
+            if !logins.iter().any(|a| a.user == admin.name) {
+                logins.push(AccountLogin {
+                    user: admin.name.clone(),
+                    admin: true,
+                    first_login: 0,
+                    last_login: 0,
+                    revoked_at: None,
+                    revoked_by: None,
+                });
+            }
+        }
+        for session in &live {
+            if !logins.iter().any(|a| a.user == session.user) {
+                logins.push(AccountLogin {
+                    user: session.user.clone(),
+                    admin: session.admin,
+                    first_login: session.started_at,
+                    last_login: session.started_at,
+                    revoked_at: None,
+                    revoked_by: None,
+                });
+            }
+        }
+        logins.sort_by(|a, b| a.user.cmp(&b.user));
+
+        let accounts = logins
+            .into_iter()
+            .map(|login| {
+                let sessions: Vec<SessionRecord> = live.iter().filter(|s| s.user == login.user).cloned().collect();
+                let last_login = Some(login.last_login).filter(|t| *t > 0);
+                let last_used = sessions.iter().map(|s| s.last_used).chain(last_login).max();
+                let is_admin = login.admin || config.admin.as_ref().map(|a| a.name == login.user).unwrap_or(false);
+                AccountAccess {
+                    role: if is_admin { ROLE_ADMIN } else { ROLE_USER }.into(),
+                    last_login: last_login,
+                    last_used: last_used,
+                    stale: login.revoked_at.is_none() && is_stale(last_used, stale_days, now),
+                    sessions: sessions,
+                    user: login.user,
+                    revoked_at: login.revoked_at,
+                    revoked_by: login.revoked_by,
+                }
+            })
+            .collect();
+
+        Ok(AccessReview {
+            generated_at: now,
+            stale_days: stale_days,
+            accounts: accounts,
+        })
+    }
+
+    pub fn stale(&self) -> Vec<&AccountAccess> {
+        self.accounts.iter().filter(|a| a.stale).collect()
+    }
+
+    pub fn admins(&self) -> Vec<&AccountAccess> {
+        self.accounts.iter().filter(|a| a.role == ROLE_ADMIN && a.revoked_at.is_none()).collect()
+    }
+}
+
+// Posts a summary of who has access each week or month, flagging stale accounts
+pub struct AccessReviewer {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    slack: Arc<dyn Worker<SlackRequest>>,
+}
+
+impl AccessReviewer {
+    pub fn new(
+        config: Arc<Config>,
+        sessions: Arc<Sessions>,
+        slack: Arc<dyn Worker<SlackRequest>>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(AccessReviewer {
+            config: config,
+            sessions: sessions,
+            slack: slack,
+        })
+    }
+}
+
+pub fn summary(review: &AccessReview) -> (String, Vec<slack::SlackAttachment>) {
+    let active = review.accounts.iter().filter(|a| a.revoked_at.is_none()).count();
+    let msg = format!(
+        "Access review: {} accounts with access ({} admins, {} live sessions), {} unused in {} days. \
+         Review and revoke them from /api/access-review",
+        active,
+        review.admins().len(),
+        review.accounts.iter().map(|a| a.sessions.len()).sum::<usize>(),
+        review.stale().len(),
+        review.stale_days
+    );
+
+    let attachments = review
+        .stale()
+        .iter()
+        .map(|a| {
+            let last_used = match a.last_used {
+                Some(t) => format!("last used {}", compliance::format_date(t)),
+                None => "never used".into(),
+            };
+            SlackAttachmentBuilder::new(&format!("{} ({})", last_used, a.role))
+                .title(a.user.clone())
+                .color("warning")
+                .build()
+        })
+        .collect();
+
+    (msg, attachments)
+}
+
+impl scheduler::Task for AccessReviewer {
+    fn run(&self, now: i64) -> Result<()> {
+        let (channel, frequency) = match (self.config.access_review_channel(), self.config.access_review_frequency()) {
+            (Some(c), Some(f)) => (c, f),
+            _ => return Ok(()),
+        };
+        if !compliance::is_due(&frequency, &self.config.digest_weekday(), now) {
+            return Ok(());
+        }
+
+        let review = AccessReview::generate(&self.config, &self.sessions, now)?;
+        info!("Sending access review: {} stale accounts", review.stale().len());
+
+        let (msg, attachments) = summary(&review);
+        self.slack.send(slack::req(&channel, &msg, attachments));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    use crate::config::AdminConfig;
+    use crate::server::sessions::Client;
+
+    fn new_test() -> (Config, TempDir) {
+        let temp_dir = TempDir::new("access_review.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        let mut config = Config::new(db);
+        config.admin = Some(AdminConfig {
+            name: "the-admin".into(),
+            salt: None,
+            pass_hash: "hash".into(),
+        });
+        (config, temp_dir)
+    }
+
+    #[test]
+    fn test_account_logins() {
+        let (config, _temp) = new_test();
+        let logins = &config.account_logins;
+
+        logins.record_login("joe", false).unwrap();
+        logins.record_login("joe", false).unwrap();
+        logins.record_login("the-admin", true).unwrap();
+
+        let all = logins.get_all().unwrap();
+        assert_eq!(vec!["joe", "the-admin"], all.iter().map(|a| a.user.as_str()).collect::<Vec<_>>());
+        assert_eq!(false, all[0].admin);
+        assert_eq!(true, all[1].admin);
+        assert!(all[0].last_login >= all[0].first_login);
+
+        logins.revoke("joe", "the-admin").unwrap();
+        assert_eq!(true, logins.is_revoked("joe").unwrap());
+        assert_eq!(Some("the-admin".to_string()), logins.get_all().unwrap()[0].revoked_by);
+
+        // logging in again doesn't undo a revocation
+        logins.record_login("joe", false).unwrap();
+        assert_eq!(true, logins.is_revoked("joe").unwrap());
+
+        logins.reinstate("joe").unwrap();
+        assert_eq!(false, logins.is_revoked("joe").unwrap());
+
+        // accounts can be revoked before they ever log in
+        logins.revoke("bob", "the-admin").unwrap();
+        assert_eq!(true, logins.is_revoked("bob").unwrap());
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let now = 1556712000;
+        assert_eq!(true, is_stale(None, 90, now));
+        assert_eq!(false, is_stale(Some(now - 89 * SECS_PER_DAY), 90, now));
+        assert_eq!(true, is_stale(Some(now - 91 * SECS_PER_DAY), 90, now));
+    }
+
+    #[test]
+    fn test_generate() {
+        let (config, _temp) = new_test();
+        let sessions = Sessions::new();
+        config.account_logins.record_login("joe", false).unwrap();
+        config.account_logins.record_login("bob", false).unwrap();
+        config.account_logins.revoke("bob", "the-admin").unwrap();
+        sessions.new_session("joe", false, Client::default());
+
+        let now = db::now();
+        let review = AccessReview::generate(&config, &sessions, now).unwrap();
+        assert_eq!(90, review.stale_days);
+        assert_eq!(
+            vec!["bob", "joe", "the-admin"],
+            review.accounts.iter().map(|a| a.user.as_str()).collect::<Vec<_>>()
+        );
+
+        let bob = &review.accounts[0];
+        assert_eq!(false, bob.stale);
+        assert!(bob.revoked_at.is_some());
+
+        let joe = &review.accounts[1];
+        assert_eq!(ROLE_USER, joe.role);
+        assert_eq!(1, joe.sessions.len());
+        assert_eq!(false, joe.stale);
+
+        // the configured admin never logged in
+        let admin = &review.accounts[2];
+        assert_eq!(ROLE_ADMIN, admin.role);
+        assert_eq!(None, admin.last_used);
+        assert_eq!(true, admin.stale);
+
+        assert_eq!(vec!["the-admin"], review.stale().iter().map(|a| a.user.as_str()).collect::<Vec<_>>());
+        assert_eq!(1, review.admins().len());
+
+        // a quarter later, joe's access is stale too
+        let review = AccessReview::generate(&config, &sessions, now + 100 * SECS_PER_DAY).unwrap();
+        assert_eq!(2, review.stale().len());
+
+        let (msg, attachments) = summary(&review);
+        assert_eq!(
+            "Access review: 2 accounts with access (1 admins, 1 live sessions), 2 unused in 90 days. \
+             Review and revoke them from /api/access-review",
+            msg
+        );
+        assert_eq!(2, attachments.len());
+    }
+}
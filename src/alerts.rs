@@ -0,0 +1,352 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::Serialize;
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::messenger::Messenger;
+use crate::opsgenie;
+use crate::scheduler;
+use crate::slack::{self, SlackAction, SlackAttachment, SlackAttachmentBuilder, SlackRequest};
+use crate::worker;
+
+pub const MAIN_BROKEN: &str = "main-broken";
+pub const RELEASE_FAILED: &str = "release-failed";
+
+pub const ACK_ACTION: &str = "acknowledge";
+
+// Both the slack thread key of an alert's messages and the callback id of its button
+const ALERT_KEY_PREFIX: &str = "alert:";
+
+// how often to look for alerts to escalate
+pub const CHECK_INTERVAL_SECS: u64 = 60;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CriticalAlert {
+    pub id: i32,
+    // "owner/name"
+    pub repo: String,
+    pub branch: String,
+    pub kind: String,
+    pub message: String,
+    // how many escalation tiers have been notified
+    pub tier: u32,
+    // when to notify the next tier, or 0 once escalation has ended
+    pub escalate_at: i64,
+    pub acked_by: Option<String>,
+    pub acked_at: i64,
+    pub created_at: i64,
+}
+
+// The kind of critical alert that a failing status on `branch` raises, if any
+pub fn failed_status_kind(branch: &str, release_branch_prefix: &str) -> Option<&'static str> {
+    if github::is_main_branch(branch) {
+        Some(MAIN_BROKEN)
+    } else if !release_branch_prefix.is_empty() && branch.starts_with(release_branch_prefix) {
+        Some(RELEASE_FAILED)
+    } else {
+        None
+    }
+}
+
+pub fn title(kind: &str) -> &'static str {
+    match kind {
+        MAIN_BROKEN => "Main branch is broken",
+        RELEASE_FAILED => "Release branch is failing",
+        _ => "Critical alert",
+    }
+}
+
+pub fn alert_key(id: i32) -> String {
+    format!("{}{}", ALERT_KEY_PREFIX, id)
+}
+
+pub fn parse_alert_key(key: &str) -> Option<i32> {
+    if !key.starts_with(ALERT_KEY_PREFIX) {
+        return None;
+    }
+    key[ALERT_KEY_PREFIX.len()..].parse::<i32>().ok()
+}
+
+pub fn attachment(alert: &CriticalAlert) -> SlackAttachment {
+    SlackAttachmentBuilder::new(&alert.message)
+        .color("danger")
+        .callback_id(alert_key(alert.id))
+        .action(SlackAction::button(ACK_ACTION, "Acknowledge"))
+        .build()
+}
+
+pub fn escalation_message(alert: &CriticalAlert, now: i64) -> String {
+    format!(
+        "{}: {} ({}) has not been acknowledged for {} minutes",
+        title(&alert.kind),
+        alert.branch,
+        alert.repo,
+        (now - alert.created_at) / 60
+    )
+}
+
+pub struct CriticalAlerts {
+    db: Database,
+}
+
+impl CriticalAlerts {
+    pub fn new(db: Database) -> CriticalAlerts {
+        CriticalAlerts { db: db }
+    }
+
+    // Returns None if the branch already has an alert of this kind that is still being escalated
+    pub fn raise(
+        &self,
+        repo: &str,
+        branch: &str,
+        kind: &str,
+        message: &str,
+        escalate_at: i64,
+        now: i64,
+    ) -> Result<Option<CriticalAlert>> {
+        let open = self.select(
+            "repo = ?1 AND branch = ?2 AND kind = ?3 AND acked_at = 0 AND escalate_at > 0",
+            &[&repo as &dyn ToSql, &branch, &kind],
+        )?;
+        if !open.is_empty() {
+            return Ok(None);
+        }
+
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO critical_alerts
+               (repo, branch, kind, message, tier, escalate_at, acked_by, acked_at, created_at)
+               VALUES (?1, ?2, ?3, ?4, 0, ?5, '', 0, ?6)"#,
+            &[&repo as &dyn ToSql, &branch, &kind, &message, &escalate_at, &now],
+        )
+        .map_err(|e| format_err!("Error raising alert for {} {}: {}", repo, branch, e))?;
+
+        self.get(conn.last_insert_rowid()? as i32)
+    }
+
+    pub fn get(&self, id: i32) -> Result<Option<CriticalAlert>> {
+        Ok(self.select("id = ?1", &[&id as &dyn ToSql])?.into_iter().next())
+    }
+
+    // Returns false if it was already acknowledged
+    pub fn acknowledge(&self, id: i32, acked_by: &str, now: i64) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let changed = conn
+            .execute(
+                "UPDATE critical_alerts SET acked_by = ?1, acked_at = ?2 WHERE id = ?3 AND acked_at = 0",
+                &[&acked_by as &dyn ToSql, &now, &id],
+            )
+            .map_err(|e| format_err!("Error acknowledging alert {}: {}", id, e))?;
+
+        Ok(changed > 0)
+    }
+
+    // Unacknowledged alerts whose next tier is due
+    pub fn due(&self, now: i64) -> Result<Vec<CriticalAlert>> {
+        self.select("acked_at = 0 AND escalate_at > 0 AND escalate_at <= ?1", &[&now as &dyn ToSql])
+    }
+
+    pub fn escalated(&self, id: i32, tier: u32, escalate_at: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "UPDATE critical_alerts SET tier = ?1, escalate_at = ?2 WHERE id = ?3",
+            &[&tier as &dyn ToSql, &escalate_at, &id],
+        )
+        .map_err(|e| format_err!("Error escalating alert {}: {}", id, e))?;
+
+        Ok(())
+    }
+
+    fn select(&self, filter: &str, params: &[&dyn ToSql]) -> Result<Vec<CriticalAlert>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(&format!("SELECT * FROM critical_alerts WHERE {} ORDER BY id", filter))?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(params)?;
+
+        let mut alerts = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            let acked_by: String = cols.get(row, "acked_by")?;
+            alerts.push(CriticalAlert {
+                id: cols.get(row, "id")?,
+                repo: cols.get(row, "repo")?,
+                branch: cols.get(row, "branch")?,
+                kind: cols.get(row, "kind")?,
+                message: cols.get(row, "message")?,
+                tier: cols.get(row, "tier")?,
+                escalate_at: cols.get(row, "escalate_at")?,
+                acked_by: Some(acked_by).filter(|a| !a.is_empty()),
+                acked_at: cols.get(row, "acked_at")?,
+                created_at: cols.get(row, "created_at")?,
+            });
+        }
+
+        Ok(alerts)
+    }
+}
+
+// Alerts the branch's channels (and pages its opsgenie responders), threading the alert's messages so that reacting
+// to any of them acknowledges it.
+// Returns None if the branch was already alerted.
+pub fn raise(
+    config: &Config,
+    messenger: &Messenger,
+    opsgenie: Option<&dyn opsgenie::Session>,
+    repo: &github::Repo,
+    branch: &str,
+    kind: &str,
+    message: &str,
+    now: i64,
+) -> Result<Option<CriticalAlert>> {
+    let escalate_at = now + config.alert_ack_secs();
+    let alert = match config.critical_alerts.raise(&repo.full_name, branch, kind, message, escalate_at, now)? {
+        Some(a) => a,
+        None => return Ok(None),
+    };
+    info!("Raised alert {} ({}) for {} {}", alert.id, kind, repo.full_name, branch);
+
+    let mut msg = format!("{}: {}", title(kind), branch);
+    if let Some(session) = opsgenie {
+        match opsgenie::page(session, config, repo, &alert) {
+            Ok(ref on_call) if !on_call.is_empty() => msg += &format!(". On call: {}", on_call.join(", ")),
+            Ok(_) => (),
+            Err(e) => error!("Error paging alert {}: {}", alert.id, e),
+        };
+    }
+    messenger.in_thread(&alert_key(alert.id)).send_to_channel(
+        &msg,
+        &vec![attachment(&alert)],
+        repo,
+        branch,
+        &Vec::<github::PushCommit>::new(),
+    );
+    Ok(Some(alert))
+}
+
+// Stops the alert's escalation, in opsgenie too, returning what to tell whoever acknowledged it
+pub fn acknowledge(
+    config: &Config,
+    opsgenie: Option<&dyn opsgenie::Session>,
+    id: i32,
+    acked_by: &str,
+    now: i64,
+) -> Result<String> {
+    let alert = match config.critical_alerts.get(id)? {
+        Some(a) => a,
+        None => return Ok(format!("Unknown alert: {}", id)),
+    };
+
+    if !config.critical_alerts.acknowledge(id, acked_by, now)? {
+        return Ok(format!("This alert was already acknowledged by {}", alert.acked_by.unwrap_or_default()));
+    }
+    info!("{} acknowledged alert {} for {} {}", acked_by, id, alert.repo, alert.branch);
+    if let Some(session) = opsgenie.filter(|_| config.opsgenie.is_some()) {
+        if let Err(e) = session.acknowledge_alert(&opsgenie::alert_alias(id), acked_by) {
+            error!("{}", e);
+        }
+    }
+    Ok(format!("Acknowledged: {} ({}) will not be escalated any further", alert.branch, alert.repo))
+}
+
+// Notifies the next tier of each alert no one has acknowledged in time
+pub struct AlertEscalator {
+    config: Arc<Config>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+impl AlertEscalator {
+    pub fn new(config: Arc<Config>, slack: Arc<dyn worker::Worker<SlackRequest>>) -> Arc<dyn scheduler::Task> {
+        Arc::new(AlertEscalator {
+            config: config,
+            slack: slack,
+        })
+    }
+
+    fn escalate(&self, alert: &CriticalAlert, now: i64) -> Result<()> {
+        let tiers = self.config.alert_tiers(&alert.kind);
+        match tiers.get(alert.tier as usize) {
+            Some(target) => {
+                info!("Escalating alert {} to {}", alert.id, target);
+                self.slack.send(slack::threaded_req(
+                    target,
+                    &escalation_message(alert, now),
+                    vec![attachment(alert)],
+                    &alert_key(alert.id),
+                ));
+                self.config.critical_alerts.escalated(alert.id, alert.tier + 1, now + self.config.alert_ack_secs())
+            }
+            // the last tier has had its chance too
+            None => self.config.critical_alerts.escalated(alert.id, alert.tier, 0),
+        }
+    }
+}
+
+impl scheduler::Task for AlertEscalator {
+    fn run(&self, now: i64) -> Result<()> {
+        for alert in self.config.critical_alerts.due(now)? {
+            if let Err(e) = self.escalate(&alert, now) {
+                error!("Error escalating alert {}: {}", alert.id, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_failed_status_kind() {
+        assert_eq!(Some(MAIN_BROKEN), failed_status_kind("master", "release/"));
+        assert_eq!(Some(MAIN_BROKEN), failed_status_kind("main", ""));
+        assert_eq!(Some(RELEASE_FAILED), failed_status_kind("release/1.2", "release/"));
+        assert_eq!(None, failed_status_kind("release/1.2", ""));
+        assert_eq!(None, failed_status_kind("some-branch", "release/"));
+    }
+
+    #[test]
+    fn test_alert_key() {
+        assert_eq!("alert:12", alert_key(12));
+        assert_eq!(Some(12), parse_alert_key("alert:12"));
+        assert_eq!(None, parse_alert_key("alert:"));
+        assert_eq!(None, parse_alert_key("some-org/some-repo#12"));
+    }
+
+    #[test]
+    fn test_critical_alerts() {
+        let temp_dir = TempDir::new("alerts.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let alerts = CriticalAlerts::new(Database::new(&db_file.to_string_lossy()).unwrap());
+
+        let alert = alerts.raise("some-org/some-repo", "master", MAIN_BROKEN, "ci/build", 1900, 1000).unwrap().unwrap();
+        assert_eq!(0, alert.tier);
+        assert_eq!(None, alert.acked_by);
+        // already being escalated
+        assert_eq!(None, alerts.raise("some-org/some-repo", "master", MAIN_BROKEN, "ci/lint", 1950, 1050).unwrap());
+        let other = alerts.raise("some-org/some-repo", "release/1.0", RELEASE_FAILED, "ci", 1900, 1000).unwrap();
+        assert!(other.is_some());
+
+        assert!(alerts.due(1899).unwrap().is_empty());
+        assert_eq!(2, alerts.due(1900).unwrap().len());
+
+        alerts.escalated(alert.id, 1, 2800).unwrap();
+        assert_eq!(vec![other.unwrap().id], alerts.due(1900).unwrap().into_iter().map(|a| a.id).collect::<Vec<_>>());
+
+        assert!(alerts.acknowledge(alert.id, "joe", 2000).unwrap());
+        assert!(!alerts.acknowledge(alert.id, "sue", 2100).unwrap());
+        let acked = alerts.get(alert.id).unwrap().unwrap();
+        assert_eq!(Some("joe".to_string()), acked.acked_by);
+        assert_eq!(2000, acked.acked_at);
+        assert_eq!(1, alerts.due(3000).unwrap().len());
+
+        // once acknowledged, the branch can be alerted again
+        assert!(alerts.raise("some-org/some-repo", "master", MAIN_BROKEN, "ci/build", 3900, 3000).unwrap().is_some());
+    }
+}
@@ -34,9 +34,59 @@ function loggedInUser() {
   return sessionStorage['username'];
 }
 
-app.run(function($state, $rootScope, $timeout, sessionHttp, notificationService) {
+// WebAuthn takes binary values, which octobot sends and expects as base64url
+function base64urlToBuffer(value) {
+  var base64 = value.replace(/-/g, '+').replace(/_/g, '/');
+  while (base64.length % 4) {
+    base64 += '=';
+  }
+  var binary = atob(base64);
+  var bytes = new Uint8Array(binary.length);
+  for (var i = 0; i < binary.length; i++) {
+    bytes[i] = binary.charCodeAt(i);
+  }
+  return bytes.buffer;
+}
+
+function bufferToBase64url(buffer) {
+  var bytes = new Uint8Array(buffer);
+  var binary = '';
+  for (var i = 0; i < bytes.length; i++) {
+    binary += String.fromCharCode(bytes[i]);
+  }
+  return btoa(binary).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+}
+
+app.run(function($state, $rootScope, $timeout, $q, sessionHttp, notificationService) {
   $rootScope.isLoggedIn = isLoggedIn;
 
+  $rootScope.registerSecurityKey = function() {
+    var name = prompt('Name of the security key');
+    if (name === null) {
+      return;
+    }
+    sessionHttp.post('/api/v1/webauthn/register/begin', {}).then(function(resp) {
+      var options = resp.data.public_key;
+      options.challenge = base64urlToBuffer(options.challenge);
+      options.user.id = base64urlToBuffer(options.user.id);
+      options.excludeCredentials.forEach(function(c) {
+        c.id = base64urlToBuffer(c.id);
+      });
+      return $q.when(navigator.credentials.create({publicKey: options})).then(function(credential) {
+        return sessionHttp.post('/api/v1/webauthn/register/finish', {
+          challenge_id: resp.data.challenge_id,
+          name: name,
+          client_data_json: bufferToBase64url(credential.response.clientDataJSON),
+          attestation_object: bufferToBase64url(credential.response.attestationObject),
+        });
+      });
+    }).then(function() {
+      notificationService.showSuccess('Security key registered');
+    }).catch(function(e) {
+      notificationService.showError('Error registering security key: ' + parseError(e));
+    });
+  };
+
   $rootScope.logout = function() {
     sessionHttp.logout();
   };
@@ -172,13 +222,26 @@ app.service('notificationService', function($rootScope, $timeout) {
   };
 });
 
-app.controller('LoginController', function($scope, $rootScope, $state, $http, notificationService) {
+app.controller('LoginController', function($scope, $rootScope, $state, $http, $q, notificationService) {
 
   $scope.username = '';
   $scope.password = '';
 
   if (isLoggedIn()) {
     $state.go('users');
+  } else {
+    // single sign-on with the browser's kerberos ticket, if octobot is set up for it. Otherwise log in below.
+    $http.get('/auth/negotiate').then(function(resp) {
+      sessionStorage['session'] = resp.data.session;
+      sessionStorage['username'] = resp.data.username;
+      $rootScope.$emit('octobot.login');
+      $state.go('users');
+    }).catch(function() {});
+
+    // offer logging in through the SAML IdP, if octobot is set up for it
+    $http.get('/auth/saml/metadata').then(function() {
+      $scope.saml = true;
+    }).catch(function() {});
   }
 
   $scope.login = function() {
@@ -194,7 +257,37 @@ app.controller('LoginController', function($scope, $rootScope, $state, $http, no
 
     }).catch(function(e) {
       console.log('Error logging in!' + JSON.stringify(e));
-      notificationService.showError('Login failed');
+      notificationService.showError(e && e.status == 403 && e.data ? e.data : 'Login failed');
+    });
+  };
+
+  $scope.loginWithKey = function() {
+    $http.post('/auth/webauthn/login/begin', {
+      username: $scope.username,
+    }).then(function(resp) {
+      var options = resp.data.public_key;
+      options.challenge = base64urlToBuffer(options.challenge);
+      options.allowCredentials.forEach(function(c) {
+        c.id = base64urlToBuffer(c.id);
+      });
+      return $q.when(navigator.credentials.get({publicKey: options})).then(function(assertion) {
+        return $http.post('/auth/webauthn/login/finish', {
+          challenge_id: resp.data.challenge_id,
+          id: bufferToBase64url(assertion.rawId),
+          client_data_json: bufferToBase64url(assertion.response.clientDataJSON),
+          authenticator_data: bufferToBase64url(assertion.response.authenticatorData),
+          signature: bufferToBase64url(assertion.response.signature),
+        });
+      });
+    }).then(function(resp) {
+      notificationService.showSuccess('Logged in successfully');
+      sessionStorage['session'] = resp.data.session;
+      sessionStorage['username'] = resp.data.username;
+      $rootScope.$emit('octobot.login');
+      $state.go('users');
+    }).catch(function(e) {
+      console.log('Error logging in with security key!' + JSON.stringify(e));
+      notificationService.showError('Login with security key failed');
     });
   };
 });
@@ -221,9 +314,11 @@ app.controller('UsersController', function($scope, sessionHttp, notificationServ
   }
 
   function refresh() {
-    return sessionHttp.get('/api/users').then(function(resp) {
+    return sessionHttp.get('/api/v1/users').then(function(resp) {
       $scope.users = resp.data.users;
-
+      return sessionHttp.get('/api/v1/users/deleted');
+    }).then(function(resp) {
+      $scope.deletedUsers = resp.data.users;
     }).catch(function(e) {
       if (!isLoggedIn()) {
         return;
@@ -232,6 +327,19 @@ app.controller('UsersController', function($scope, sessionHttp, notificationServ
     });
   }
 
+  $scope.deletedAt = function(entity) {
+    return new Date(entity.deleted_at * 1000).toLocaleString();
+  }
+
+  $scope.viewAs = function(user) {
+    sessionHttp.get('/api/v1/view-as?user=' + encodeURIComponent(user.github)).then(function(resp) {
+      $scope.userView = resp.data;
+      $('#view-as-modal').modal('show');
+    }).catch(function(e) {
+      notificationService.showError('Error viewing as user: ' + parseError(e));
+    });
+  }
+
   $scope.editUser = function(user) {
     $scope.theUser = user;
     $('#add-user-modal').modal('show');
@@ -255,7 +363,7 @@ app.controller('UsersController', function($scope, sessionHttp, notificationServ
   }
 
   function doAddUser() {
-    sessionHttp.post('/api/users', $scope.theUser).then(function(resp) {
+    sessionHttp.post('/api/v1/users', $scope.theUser).then(function(resp) {
       notificationService.showSuccess('Added user succesfully');
       refresh()
     }).catch(function(e) {
@@ -267,7 +375,7 @@ app.controller('UsersController', function($scope, sessionHttp, notificationServ
   }
 
   function doEditUser() {
-    sessionHttp.put('/api/user', $scope.theUser).then(function(resp) {
+    sessionHttp.put('/api/v1/user', $scope.theUser).then(function(resp) {
       notificationService.showSuccess('Edited user succesfully');
       refresh()
     }).catch(function(e) {
@@ -282,7 +390,7 @@ app.controller('UsersController', function($scope, sessionHttp, notificationServ
     if (!confirm("Are you sure you want to delete user " + user.github + "?")) {
       return;
     }
-    return sessionHttp.delete('/api/user?id=' + Number(user.id)).then(function(resp) {
+    return sessionHttp.delete('/api/v1/user?id=' + Number(user.id)).then(function(resp) {
       notificationService.showSuccess('Remove user succesfully');
       refresh();
     }).catch(function(e) {
@@ -293,6 +401,18 @@ app.controller('UsersController', function($scope, sessionHttp, notificationServ
     });
   }
 
+  $scope.restoreUser = function(user) {
+    return sessionHttp.post('/api/v1/user/restore?id=' + Number(user.id)).then(function(resp) {
+      notificationService.showSuccess('Restored user succesfully');
+      refresh();
+    }).catch(function(e) {
+      if (!isLoggedIn()) {
+        return;
+      }
+      notificationService.showError('Error restoring user: ' + parseError(e));
+    });
+  }
+
   // init
   init();
 });
@@ -308,8 +428,11 @@ app.controller('ReposController', function($rootScope, $scope, sessionHttp, noti
   }
 
   function refresh() {
-    return sessionHttp.get('/api/repos').then(function(resp) {
+    return sessionHttp.get('/api/v1/repos').then(function(resp) {
       $scope.repos = resp.data.repos;
+      return sessionHttp.get('/api/v1/repos/deleted');
+    }).then(function(resp) {
+      $scope.deletedRepos = resp.data.repos;
     }).catch(function(e) {
       if (!isLoggedIn()) {
         return;
@@ -327,6 +450,10 @@ app.controller('ReposController', function($rootScope, $scope, sessionHttp, noti
     }
   }
 
+  $scope.deletedAt = function(entity) {
+    return new Date(entity.deleted_at * 1000).toLocaleString();
+  }
+
   $scope.editRepo = function(repo) {
     $scope.theRepo = repo;
     $('#add-repo-modal').modal('show');
@@ -336,6 +463,13 @@ app.controller('ReposController', function($rootScope, $scope, sessionHttp, noti
     $scope.theRepo = {
       force_push_notify: true,
       jira_config: [],
+      path_labels: [],
+      routing_rules: [],
+      merge_strategies: [],
+      label_rules: [],
+      components: [],
+      submodules: [],
+      stale_pr_days: 0,
     };
     $('#add-repo-modal').modal('show');
   }
@@ -359,8 +493,82 @@ app.controller('ReposController', function($rootScope, $scope, sessionHttp, noti
    theRepo.jira_config.splice(index, 1);
   }
 
+  $scope.addPathLabel = function(theRepo) {
+    if (!theRepo.path_labels) {
+      theRepo.path_labels = [];
+    }
+    theRepo.path_labels.push({
+    });
+  };
+
+  $scope.removePathLabel = function(theRepo, index) {
+   theRepo.path_labels.splice(index, 1);
+  }
+
+  $scope.addRoutingRule = function(theRepo) {
+    if (!theRepo.routing_rules) {
+      theRepo.routing_rules = [];
+    }
+    theRepo.routing_rules.push({
+    });
+  };
+
+  $scope.removeRoutingRule = function(theRepo, index) {
+   theRepo.routing_rules.splice(index, 1);
+  }
+
+  $scope.addMergeStrategy = function(theRepo) {
+    if (!theRepo.merge_strategies) {
+      theRepo.merge_strategies = [];
+    }
+    theRepo.merge_strategies.push({
+      strategy: 'squash',
+    });
+  };
+
+  $scope.removeMergeStrategy = function(theRepo, index) {
+   theRepo.merge_strategies.splice(index, 1);
+  }
+
+  $scope.addLabelRule = function(theRepo) {
+    if (!theRepo.label_rules) {
+      theRepo.label_rules = [];
+    }
+    theRepo.label_rules.push({
+    });
+  };
+
+  $scope.removeLabelRule = function(theRepo, index) {
+   theRepo.label_rules.splice(index, 1);
+  }
+
+  $scope.addComponent = function(theRepo) {
+    if (!theRepo.components) {
+      theRepo.components = [];
+    }
+    theRepo.components.push({
+    });
+  };
+
+  $scope.removeComponent = function(theRepo, index) {
+   theRepo.components.splice(index, 1);
+  }
+
+  $scope.addSubmodule = function(theRepo) {
+    if (!theRepo.submodules) {
+      theRepo.submodules = [];
+    }
+    theRepo.submodules.push({
+      auto_merge: false,
+    });
+  };
+
+  $scope.removeSubmodule = function(theRepo, index) {
+   theRepo.submodules.splice(index, 1);
+  }
+
   function doAddRepo() {
-    sessionHttp.post('/api/repos', $scope.theRepo).then(function(resp) {
+    sessionHttp.post('/api/v1/repos', $scope.theRepo).then(function(resp) {
       notificationService.showSuccess('Added repo succesfully');
       refresh()
     }).catch(function(e) {
@@ -372,7 +580,7 @@ app.controller('ReposController', function($rootScope, $scope, sessionHttp, noti
   }
 
   function doEditRepo() {
-    sessionHttp.put('/api/repo', $scope.theRepo).then(function(resp) {
+    sessionHttp.put('/api/v1/repo', $scope.theRepo).then(function(resp) {
       notificationService.showSuccess('Edited repo succesfully');
       refresh()
     }).catch(function(e) {
@@ -387,7 +595,7 @@ app.controller('ReposController', function($rootScope, $scope, sessionHttp, noti
     if (!confirm("Are you sure you want to delete repo " + repo.repo + "?")) {
       return;
     }
-    return sessionHttp.delete('/api/repo?id=' + Number(repo.id)).then(function(resp) {
+    return sessionHttp.delete('/api/v1/repo?id=' + Number(repo.id)).then(function(resp) {
       notificationService.showSuccess('Remove repo succesfully');
       refresh();
     }).catch(function(e) {
@@ -398,6 +606,18 @@ app.controller('ReposController', function($rootScope, $scope, sessionHttp, noti
     });
   }
 
+  $scope.restoreRepo = function(repo) {
+    return sessionHttp.post('/api/v1/repo/restore?id=' + Number(repo.id)).then(function(resp) {
+      notificationService.showSuccess('Restored repo succesfully');
+      refresh();
+    }).catch(function(e) {
+      if (!isLoggedIn()) {
+        return;
+      }
+      notificationService.showError('Error restoring repo: ' + parseError(e));
+    });
+  }
+
   // init
   init();
 });
@@ -445,7 +665,7 @@ app.controller('VersionsController', function($rootScope, $scope, sessionHttp, n
       admin_user: $scope.req.admin_user,
       admin_pass: $scope.req.admin_pass,
     };
-    return sessionHttp.post('/api/merge-versions', req).then(function(resp) {
+    return sessionHttp.post('/api/v1/merge-versions', req).then(function(resp) {
       $scope.processing = false;
       if (!jiraBase && resp.data.jira_base) {
         jiraBase = resp.data.jira_base;
@@ -0,0 +1,140 @@
+use failure::format_err;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+
+// Only keep this many audit entries around
+const MAX_ENTRIES: i32 = 5000;
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub created_at: i64,
+    // who did it, i.e. the logged in admin
+    pub actor: String,
+    // i.e. "view-as"
+    pub action: String,
+    // what it was done to, i.e. a github username
+    pub target: String,
+    pub details: String,
+}
+
+// A record of sensitive admin actions.
+#[derive(Clone)]
+pub struct AuditLog {
+    db: Database,
+}
+
+impl AuditLog {
+    pub fn new(db: Database) -> AuditLog {
+        AuditLog { db: db }
+    }
+
+    pub fn record(&self, actor: &str, action: &str, target: &str, details: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "INSERT INTO audit_log (created_at, actor, action, target, details) VALUES (?1, ?2, ?3, ?4, ?5)",
+            &[&db::now() as &dyn ToSql, &actor, &action, &target, &details],
+        )
+        .map_err(|e| format_err!("Error recording audit entry {} by {}: {}", action, actor, e))?;
+
+        conn.execute(
+            "DELETE FROM audit_log WHERE id <= (SELECT MAX(id) FROM audit_log) - ?1",
+            &[&MAX_ENTRIES],
+        )
+        .map_err(|e| format_err!("Error pruning audit log: {}", e))?;
+
+        Ok(())
+    }
+
+    // Most recent entries first
+    pub fn get_recent(&self, limit: u32) -> Result<Vec<AuditEntry>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare("SELECT * FROM audit_log ORDER BY id DESC LIMIT :limit")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":limit", &limit)])?;
+
+        let mut entries = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            entries.push(AuditEntry {
+                id: cols.get(row, "id")?,
+                created_at: cols.get(row, "created_at")?,
+                actor: cols.get(row, "actor")?,
+                action: cols.get(row, "action")?,
+                target: cols.get(row, "target")?,
+                details: cols.get(row, "details")?,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    // Entries recorded in [start, end), oldest first
+    pub fn get_between(&self, start: i64, end: i64) -> Result<Vec<AuditEntry>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM audit_log WHERE created_at >= :start AND created_at < :end ORDER BY id",
+        )?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":start", &start), (":end", &end)])?;
+
+        let mut entries = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            entries.push(AuditEntry {
+                id: cols.get(row, "id")?,
+                created_at: cols.get(row, "created_at")?,
+                actor: cols.get(row, "actor")?,
+                action: cols.get(row, "action")?,
+                target: cols.get(row, "target")?,
+                details: cols.get(row, "details")?,
+            });
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (AuditLog, TempDir) {
+        let temp_dir = TempDir::new("audit.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (AuditLog::new(db), temp_dir)
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let (audit, _temp) = new_test();
+
+        audit.record("admin", "view-as", "joe", "").unwrap();
+        audit.record("admin", "view-as", "bob", "some details").unwrap();
+
+        let entries = audit.get_recent(10).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!("bob", entries[0].target);
+        assert_eq!("some details", entries[0].details);
+        assert_eq!("joe", entries[1].target);
+        assert_eq!("admin", entries[1].actor);
+
+        assert_eq!(1, audit.get_recent(1).unwrap().len());
+    }
+
+    #[test]
+    fn test_audit_log_between() {
+        let (audit, _temp) = new_test();
+
+        audit.record("admin", "view-as", "joe", "").unwrap();
+        audit.record("admin", "view-as", "bob", "").unwrap();
+
+        let now = db::now();
+        let entries = audit.get_between(now - 60, now + 60).unwrap();
+        assert_eq!(vec!["joe", "bob"], entries.iter().map(|e| e.target.as_str()).collect::<Vec<_>>());
+        assert_eq!(0, audit.get_between(0, now - 60).unwrap().len());
+    }
+}
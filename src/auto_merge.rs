@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::Serialize;
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::github::{self, MergeOptions};
+use crate::merge_strategy::{self, MergeMethod};
+use crate::repos::RepoMergeStrategy;
+use crate::scheduler;
+
+pub const CHECK_INTERVAL_SECS: u64 = 60;
+
+// A "merge when green" request: the PR is merged once github says it is mergeable.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AutoMerge {
+    // owner/name
+    pub repo: String,
+    pub number: u32,
+    // only this commit will be merged: new pushes cancel the request
+    pub head_sha: String,
+    pub requested_by: String,
+}
+
+impl AutoMerge {
+    fn owner_and_name(&self) -> Result<(&str, &str)> {
+        let mut parts = self.repo.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(owner), Some(name)) => Ok((owner, name)),
+            _ => Err(format_err!("Invalid repo: {}", self.repo)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AutoMerges {
+    db: Database,
+}
+
+impl AutoMerges {
+    pub fn new(db: Database) -> AutoMerges {
+        AutoMerges { db: db }
+    }
+
+    pub fn add(&self, merge: &AutoMerge) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO auto_merges (repo, number, head_sha, requested_by, created_at)
+               VALUES (?1, ?2, ?3, ?4, ?5)
+               ON CONFLICT (repo, number) DO UPDATE SET head_sha = excluded.head_sha,
+                   requested_by = excluded.requested_by, created_at = excluded.created_at"#,
+            &[&merge.repo as &dyn ToSql, &merge.number, &merge.head_sha, &merge.requested_by, &db::now()],
+        )
+        .map_err(|e| format_err!("Error adding auto-merge for {}#{}: {}", merge.repo, merge.number, e))?;
+
+        Ok(())
+    }
+
+    pub fn remove(&self, repo: &str, number: u32) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "DELETE FROM auto_merges WHERE repo = ?1 AND number = ?2",
+            &[&repo as &dyn ToSql, &number],
+        )
+        .map_err(|e| format_err!("Error removing auto-merge for {}#{}: {}", repo, number, e))?;
+
+        Ok(())
+    }
+
+    pub fn get_all(&self) -> Result<Vec<AutoMerge>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare("SELECT repo, number, head_sha, requested_by FROM auto_merges ORDER BY created_at")?;
+        let found = stmt.query_map(db::NO_PARAMS, |row| {
+            Ok(AutoMerge {
+                repo: row.get(0)?,
+                number: row.get(1)?,
+                head_sha: row.get(2)?,
+                requested_by: row.get(3)?,
+            })
+        })?;
+
+        let mut merges = vec![];
+        for merge in found {
+            merges.push(merge?);
+        }
+
+        Ok(merges)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MergeOutcome {
+    Merged,
+    Waiting,
+    Abandoned(String),
+}
+
+// Merges the PR if it is ready, forgetting about the request once it is merged or can no longer be.
+// It is merged with the strategy for its base branch, if the repo has one.
+pub fn try_merge(
+    github: &dyn Session,
+    merges: &AutoMerges,
+    strategies: &Vec<RepoMergeStrategy>,
+    merge: &AutoMerge,
+) -> Result<MergeOutcome> {
+    let (owner, name) = merge.owner_and_name()?;
+    let pull_request = github.get_pull_request(owner, name, merge.number)?;
+
+    let abandoned = if pull_request.state != "open" {
+        Some("the PR is no longer open".to_string())
+    } else if pull_request.head.sha != merge.head_sha {
+        Some("new commits were pushed".to_string())
+    } else if pull_request.mergeable_state.as_deref() == Some("dirty") {
+        Some("the PR has merge conflicts".to_string())
+    } else {
+        None
+    };
+
+    if let Some(reason) = abandoned {
+        merges.remove(&merge.repo, merge.number)?;
+        if pull_request.state == "open" {
+            let msg = format!("Not merging as requested by @{}: {}", merge.requested_by, reason);
+            github.comment_pull_request(owner, name, merge.number, &msg)?;
+        }
+        return Ok(MergeOutcome::Abandoned(reason));
+    }
+
+    // "clean" means all required checks passed and the PR is approved
+    if pull_request.mergeable_state.as_deref() != Some("clean") {
+        return Ok(MergeOutcome::Waiting);
+    }
+
+    let options = merge_options(github, strategies, &pull_request, merge)?;
+    github.merge_pull_request(owner, name, merge.number, &merge.head_sha, &options)?;
+    merges.remove(&merge.repo, merge.number)?;
+    info!("Merged {}#{} as requested by {}", merge.repo, merge.number, merge.requested_by);
+
+    Ok(MergeOutcome::Merged)
+}
+
+fn merge_options(
+    github: &dyn Session,
+    strategies: &Vec<RepoMergeStrategy>,
+    pull_request: &github::PullRequest,
+    merge: &AutoMerge,
+) -> Result<MergeOptions> {
+    let strategy = match merge_strategy::for_branch(strategies, &pull_request.base.ref_name) {
+        Some(s) => s,
+        None => return Ok(MergeOptions::default()),
+    };
+    let method = strategy.method();
+
+    let mut options = MergeOptions::default();
+    options.merge_method = method.map(|m| m.api_name().to_string());
+
+    // rebasing keeps the commits' own messages
+    if method != Some(MergeMethod::Rebase) && !strategy.commit_template.trim().is_empty() {
+        let (owner, name) = merge.owner_and_name()?;
+        let commits = github.get_pull_request_commits(owner, name, merge.number)?;
+        let vars = merge_strategy::commit_vars(pull_request, &pull_request.base.ref_name, &merge.repo, &commits);
+        if let Some((title, body)) = merge_strategy::commit_message(strategy, &vars) {
+            options.commit_title = Some(title);
+            options.commit_message = Some(body);
+        }
+    }
+
+    Ok(options)
+}
+
+pub struct AutoMerger {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+}
+
+impl AutoMerger {
+    pub fn new(config: Arc<Config>, github_app: Arc<dyn GithubSessionFactory>) -> Arc<dyn scheduler::Task> {
+        Arc::new(AutoMerger {
+            config: config,
+            github_app: github_app,
+        })
+    }
+
+    fn check(&self, merge: &AutoMerge) -> Result<()> {
+        let (owner, name) = merge.owner_and_name()?;
+        if self.config.code_freezes.blocks_merge(&merge.repo, merge.number, db::now())? {
+            // wait for the freeze to end (or an exception)
+            return Ok(());
+        }
+
+        let repo = github::Repo::parse(&format!("https://{}/{}", self.config.github_host(&merge.repo), merge.repo))?;
+        let strategies = self.config.repos().merge_strategies(&repo);
+        let github = self.github_app.new_session(owner, name)?;
+
+        try_merge(&github, &self.config.auto_merges, &strategies, merge)?;
+        Ok(())
+    }
+}
+
+impl scheduler::Task for AutoMerger {
+    fn run(&self, _now: i64) -> Result<()> {
+        for merge in self.config.auto_merges.get_all()? {
+            if let Err(e) = self.check(&merge) {
+                error!("Error auto-merging {}#{}: {}", merge.repo, merge.number, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (AutoMerges, TempDir) {
+        let temp_dir = TempDir::new("auto_merge.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (AutoMerges::new(db), temp_dir)
+    }
+
+    fn merge(number: u32) -> AutoMerge {
+        AutoMerge {
+            repo: "some-user/some-repo".into(),
+            number: number,
+            head_sha: "abcdef".into(),
+            requested_by: "joe".into(),
+        }
+    }
+
+    #[test]
+    fn test_auto_merges() {
+        let (merges, _temp) = new_test();
+
+        merges.add(&merge(1)).unwrap();
+        merges.add(&merge(2)).unwrap();
+        merges.add(&merge(1)).unwrap();
+        assert_eq!(2, merges.get_all().unwrap().len());
+
+        merges.remove("some-user/some-repo", 1).unwrap();
+        assert_eq!(vec![merge(2)], merges.get_all().unwrap());
+    }
+
+    #[test]
+    fn test_owner_and_name() {
+        assert_eq!(("some-user", "some-repo"), merge(1).owner_and_name().unwrap());
+
+        let mut bad = merge(1);
+        bad.repo = "no-slash".into();
+        assert!(bad.owner_and_name().is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::env;
+
+use failure::format_err;
+use ring::{digest, hmac};
+use rustc_serialize::hex::ToHex;
+
+use crate::errors::*;
+
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    // for temporary credentials
+    pub session_token: Option<String>,
+}
+
+impl Credentials {
+    pub fn new(access_key_id: &str, secret_access_key: &str) -> Credentials {
+        Credentials {
+            access_key_id: access_key_id.into(),
+            secret_access_key: secret_access_key.into(),
+            session_token: None,
+        }
+    }
+
+    // The configured keys, else AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and AWS_SESSION_TOKEN
+    pub fn from_config_or_env(
+        access_key_id: Option<&String>,
+        secret_access_key: Option<&String>,
+    ) -> Result<Credentials> {
+        if let (Some(id), Some(secret)) = (access_key_id, secret_access_key) {
+            return Ok(Credentials::new(id, secret));
+        }
+
+        let id = env::var("AWS_ACCESS_KEY_ID").map_err(|_| format_err!("AWS_ACCESS_KEY_ID is not set"))?;
+        let secret = env::var("AWS_SECRET_ACCESS_KEY").map_err(|_| format_err!("AWS_SECRET_ACCESS_KEY is not set"))?;
+        Ok(Credentials {
+            access_key_id: id,
+            secret_access_key: secret,
+            session_token: env::var("AWS_SESSION_TOKEN").ok().filter(|t| !t.is_empty()),
+        })
+    }
+}
+
+// e.g. "20150830T123600Z"
+pub fn amz_date(tm: &time::Tm) -> String {
+    time::strftime("%Y%m%dT%H%M%SZ", &tm.to_utc()).unwrap()
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    digest::digest(&digest::SHA256, data).as_ref().to_hex()
+}
+
+fn sign(key: &[u8], data: &str) -> Vec<u8> {
+    let key = hmac::SigningKey::new(&digest::SHA256, key);
+    hmac::sign(&key, data.as_bytes()).as_ref().to_vec()
+}
+
+pub fn signing_key(secret_access_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = sign(format!("AWS4{}", secret_access_key).as_bytes(), date);
+    let key = sign(&key, region);
+    let key = sign(&key, service);
+    sign(&key, "aws4_request")
+}
+
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub host: &'a str,
+    pub path: &'a str,
+    // already canonical: sorted and url-encoded
+    pub query: &'a str,
+    pub headers: Vec<(&'a str, &'a str)>,
+    pub body: &'a [u8],
+}
+
+// The headers to add to |req| to sign it (Signature Version 4): x-amz-date, the session token if there is one,
+// and authorization.
+pub fn sign_v4(
+    creds: &Credentials,
+    region: &str,
+    service: &str,
+    req: &Request,
+    amz_date: &str,
+) -> Vec<(String, String)> {
+    let mut added = vec![("x-amz-date".to_string(), amz_date.to_string())];
+    if let Some(ref token) = creds.session_token {
+        added.push(("x-amz-security-token".into(), token.clone()));
+    }
+
+    let mut headers = req.headers.iter().map(|(k, v)| (k.to_lowercase(), v.trim().to_string())).collect::<Vec<_>>();
+    headers.push(("host".into(), req.host.into()));
+    headers.extend(added.iter().cloned());
+    headers.sort();
+
+    let canonical_headers = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v)).collect::<String>();
+    let signed_headers = headers.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>().join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        req.method,
+        req.path,
+        req.query,
+        canonical_headers,
+        signed_headers,
+        sha256_hex(req.body)
+    );
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign =
+        format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, sha256_hex(canonical_request.as_bytes()));
+    let key = signing_key(&creds.secret_access_key, date, region, service);
+    let signature = sign(&key, &string_to_sign).to_hex();
+
+    added.push((
+        "authorization".into(),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            creds.access_key_id, scope, signed_headers, signature
+        ),
+    ));
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The examples from the AWS "Signature Version 4 signing process" docs
+    const SECRET: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+
+    #[test]
+    fn test_signing_key() {
+        assert_eq!(
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9",
+            signing_key(SECRET, "20150830", "us-east-1", "iam").to_hex()
+        );
+    }
+
+    #[test]
+    fn test_sign_v4() {
+        let creds = Credentials::new("AKIDEXAMPLE", SECRET);
+        let req = Request {
+            method: "GET",
+            host: "iam.amazonaws.com",
+            path: "/",
+            query: "Action=ListUsers&Version=2010-05-08",
+            headers: vec![("Content-Type", "application/x-www-form-urlencoded; charset=utf-8")],
+            body: b"",
+        };
+
+        assert_eq!(
+            vec![
+                ("x-amz-date".to_string(), "20150830T123600Z".to_string()),
+                (
+                    "authorization".to_string(),
+                    "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+                     SignedHeaders=content-type;host;x-amz-date, \
+                     Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+                        .to_string()
+                ),
+            ],
+            sign_v4(&creds, "us-east-1", "iam", &req, "20150830T123600Z")
+        );
+    }
+
+    #[test]
+    fn test_amz_date() {
+        assert_eq!("20150830T123600Z", amz_date(&time::at_utc(time::Timespec::new(1440938160, 0))));
+    }
+}
@@ -0,0 +1,120 @@
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+use crate::azure_devops::{CommitRef, PullRequestStatus};
+use crate::config::AzureDevOpsConfig;
+use crate::errors::*;
+use crate::http_client::HTTPClient;
+
+const API_VERSION: &str = "7.0";
+// pull request statuses are still in preview
+const STATUS_API_VERSION: &str = "7.0-preview.1";
+
+pub trait Session: Send + Sync {
+    fn get_pull_request_commits(&self, project: &str, repo_id: &str, pr_id: u32) -> Result<Vec<CommitRef>>;
+
+    fn comment_pull_request(&self, project: &str, repo_id: &str, pr_id: u32, content: &str) -> Result<()>;
+
+    fn create_pull_request_status(
+        &self,
+        project: &str,
+        repo_id: &str,
+        pr_id: u32,
+        status: &PullRequestStatus,
+    ) -> Result<()>;
+}
+
+pub struct AzureDevOpsSession {
+    client: HTTPClient,
+}
+
+#[derive(Deserialize)]
+struct ValueList<T> {
+    value: Vec<T>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NewThread {
+    comments: Vec<serde_json::Value>,
+    // active
+    status: u32,
+}
+
+impl AzureDevOpsSession {
+    pub fn new(config: &AzureDevOpsConfig) -> Result<AzureDevOpsSession> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        // personal access tokens are sent as the password of basic auth, with no username
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Basic {}", base64::encode(format!(":{}", config.token).as_bytes())).parse().unwrap(),
+        );
+
+        Ok(AzureDevOpsSession {
+            client: HTTPClient::new_with_headers(config.organization_url.trim_end_matches('/'), headers)?,
+        })
+    }
+
+    fn pull_request_path(project: &str, repo_id: &str, pr_id: u32) -> String {
+        format!(
+            "/{}/_apis/git/repositories/{}/pullRequests/{}",
+            utf8_percent_encode(project, PATH_SEGMENT_ENCODE_SET),
+            utf8_percent_encode(repo_id, PATH_SEGMENT_ENCODE_SET),
+            pr_id
+        )
+    }
+}
+
+impl Session for AzureDevOpsSession {
+    fn get_pull_request_commits(&self, project: &str, repo_id: &str, pr_id: u32) -> Result<Vec<CommitRef>> {
+        let path = format!(
+            "{}/commits?api-version={}",
+            AzureDevOpsSession::pull_request_path(project, repo_id, pr_id),
+            API_VERSION
+        );
+        let commits: ValueList<CommitRef> = self.client.get(&path)?;
+        Ok(commits.value)
+    }
+
+    fn comment_pull_request(&self, project: &str, repo_id: &str, pr_id: u32, content: &str) -> Result<()> {
+        let path = format!(
+            "{}/threads?api-version={}",
+            AzureDevOpsSession::pull_request_path(project, repo_id, pr_id),
+            API_VERSION
+        );
+        let thread = NewThread {
+            comments: vec![json!({ "parentCommentId": 0, "content": content, "commentType": 1 })],
+            status: 1,
+        };
+        self.client.post_void(&path, &thread)
+    }
+
+    fn create_pull_request_status(
+        &self,
+        project: &str,
+        repo_id: &str,
+        pr_id: u32,
+        status: &PullRequestStatus,
+    ) -> Result<()> {
+        let path = format!(
+            "{}/statuses?api-version={}",
+            AzureDevOpsSession::pull_request_path(project, repo_id, pr_id),
+            STATUS_API_VERSION
+        );
+        self.client.post_void(&path, status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pull_request_path() {
+        assert_eq!(
+            "/Fabrikam%20Web/_apis/git/repositories/web-app/pullRequests/12",
+            AzureDevOpsSession::pull_request_path("Fabrikam Web", "web-app", 12)
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use log::info;
+use serde_derive::Deserialize;
+use serde_json::{self, Value};
+
+use crate::azure_devops::api::Session;
+use crate::azure_devops::{Build, PullRequest, PullRequestStatus};
+use crate::config::Config;
+use crate::errors::*;
+use crate::github;
+use crate::jira;
+use crate::messenger::Messenger;
+use crate::slack::SlackAttachmentBuilder;
+use crate::vcs::{self, PullRequestHost};
+
+// A service hook delivery: `resource` depends on the event type
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceHookEvent {
+    pub event_type: String,
+    #[serde(default)]
+    pub resource: Value,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    PullRequestCreated(PullRequest),
+    PullRequestUpdated(PullRequest),
+    BuildCompleted(Build),
+    // events octobot doesn't handle
+    Other(String),
+}
+
+pub fn parse(body: &[u8]) -> Result<Event> {
+    let hook: ServiceHookEvent = serde_json::from_slice(body)?;
+    let event = match hook.event_type.as_str() {
+        "git.pullrequest.created" => Event::PullRequestCreated(serde_json::from_value(hook.resource)?),
+        "git.pullrequest.updated" => Event::PullRequestUpdated(serde_json::from_value(hook.resource)?),
+        "build.complete" => Event::BuildCompleted(serde_json::from_value(hook.resource)?),
+        _ => Event::Other(hook.event_type),
+    };
+    Ok(event)
+}
+
+// Notifies the repo's channels (and people) like github events do, and runs the repo's PR checks
+pub fn handle(
+    event: &Event,
+    config: &Config,
+    session: &dyn Session,
+    jira: Option<&dyn jira::api::Session>,
+    messenger: &Messenger,
+) {
+    match *event {
+        Event::PullRequestCreated(ref pr) => {
+            let host = AzurePullRequest::new(pr, session);
+            let pull_request = pr.to_pull_request();
+            vcs::lint_pull_request(&pull_request, true, config, &host);
+            notify_pull_request(pr, "opened", messenger);
+            vcs::submit_for_review(&pull_request, config, jira, &host);
+        }
+        Event::PullRequestUpdated(ref pr) => match pr.status.as_str() {
+            "completed" => {
+                notify_pull_request(pr, "merged", messenger);
+                vcs::mark_merged(&pr.to_pull_request(), config, jira, &AzurePullRequest::new(pr, session));
+            }
+            "abandoned" => notify_pull_request(pr, "closed", messenger),
+            // pushes, votes and edits
+            _ => vcs::lint_pull_request(&pr.to_pull_request(), false, config, &AzurePullRequest::new(pr, session)),
+        },
+        Event::BuildCompleted(ref build) => notify_build(build, messenger),
+        Event::Other(ref event_type) => info!("Ignoring Azure DevOps event '{}'", event_type),
+    };
+}
+
+fn notify_pull_request(pr: &PullRequest, verb: &str, messenger: &Messenger) {
+    let commits = pr.last_merge_source_commit.iter().map(|c| c.to_commit()).collect::<Vec<_>>();
+    // service hooks don't say who made the change
+    vcs::notify_pull_request(&pr.to_pull_request(), verb, &github::User::new(""), &commits, messenger);
+}
+
+fn notify_build(build: &Build, messenger: &Messenger) {
+    let repo = match build.repo() {
+        Some(r) => r,
+        None => {
+            info!("Not notifying about build {}: it has no repository", build.build_number);
+            return;
+        }
+    };
+
+    let result = build.result.clone().unwrap_or("completed".into());
+    let mut attachment = SlackAttachmentBuilder::new("");
+    attachment
+        .title(format!("{} {}", build.definition.name, build.build_number))
+        .color(if build.succeeded() { "good" } else { "danger" });
+    if let Some(url) = build.html_url() {
+        attachment.title_link(url);
+    }
+    let msg = format!("Build {}", result);
+    let commits = Vec::<github::Commit>::new();
+
+    match build.requested_for {
+        // the person who queued it wants to know when it didn't work out
+        Some(ref user) if !build.succeeded() => messenger.send_to_owner(
+            &msg,
+            &vec![attachment.build()],
+            &user.to_user(),
+            &repo,
+            build.branch(),
+            &commits,
+        ),
+        _ => messenger.send_to_channel(&msg, &vec![attachment.build()], &repo, build.branch(), &commits),
+    };
+}
+
+// An Azure DevOps PR, for the checks shared with other hosts
+struct AzurePullRequest<'a> {
+    session: &'a dyn Session,
+    project: String,
+    repo_id: String,
+    pr_id: u32,
+}
+
+impl<'a> AzurePullRequest<'a> {
+    fn new(pr: &PullRequest, session: &'a dyn Session) -> AzurePullRequest<'a> {
+        AzurePullRequest {
+            session: session,
+            project: pr.repository.project_name(),
+            repo_id: pr.repository.id.clone(),
+            pr_id: pr.pull_request_id,
+        }
+    }
+}
+
+impl<'a> PullRequestHost for AzurePullRequest<'a> {
+    fn commits(&self) -> Result<Vec<github::Commit>> {
+        let commits = self.session.get_pull_request_commits(&self.project, &self.repo_id, self.pr_id)?;
+        Ok(commits.iter().map(|c| c.to_commit()).collect())
+    }
+
+    fn comment(&self, body: &str) -> Result<()> {
+        self.session.comment_pull_request(&self.project, &self.repo_id, self.pr_id, body)
+    }
+
+    fn set_status(&self, context: &str, status: vcs::Status, description: &str) -> Result<()> {
+        let state = match status {
+            vcs::Status::Success => "succeeded",
+            vcs::Status::Failure => "failed",
+        };
+        let status = PullRequestStatus::new(context, state, description);
+        self.session.create_pull_request_status(&self.project, &self.repo_id, self.pr_id, &status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PR_CREATED: &str = r#"{
+        "eventType": "git.pullrequest.created",
+        "resource": {
+            "pullRequestId": 12,
+            "status": "active",
+            "title": "Fix the thing",
+            "description": "It was broken",
+            "sourceRefName": "refs/heads/fix-thing",
+            "targetRefName": "refs/heads/main",
+            "createdBy": { "displayName": "Joe", "uniqueName": "joe@company.com" },
+            "reviewers": [{ "displayName": "Mary", "uniqueName": "mary@company.com" }],
+            "lastMergeSourceCommit": { "commitId": "abcdef0123" },
+            "repository": {
+                "id": "4bc14d40-c903-45e2-872e-0462c7748079",
+                "name": "web-app",
+                "project": { "name": "Fabrikam" },
+                "remoteUrl": "https://dev.azure.com/company/Fabrikam/_git/web-app"
+            }
+        }
+    }"#;
+
+    #[test]
+    fn test_parse_pull_request() {
+        let pr = match parse(PR_CREATED.as_bytes()).unwrap() {
+            Event::PullRequestCreated(pr) => pr,
+            e => panic!("Unexpected event: {:?}", e),
+        };
+
+        assert_eq!(12, pr.pull_request_id);
+        assert_eq!("main", pr.target_branch());
+        assert_eq!("https://dev.azure.com/company/Fabrikam/_git/web-app/pullrequest/12", pr.html_url());
+        assert_eq!("joe@company.com", pr.created_by.to_user().login());
+
+        let repo = pr.repo();
+        assert_eq!("Fabrikam/web-app", repo.full_name);
+        assert_eq!("Fabrikam", repo.owner.login());
+        assert_eq!("https://dev.azure.com/company/Fabrikam/_git/web-app", repo.html_url);
+
+        let pull_request = pr.to_pull_request();
+        assert_eq!(12, pull_request.number);
+        assert_eq!("joe@company.com", pull_request.user.login());
+        assert_eq!("fix-thing", pull_request.head.ref_name);
+        assert_eq!("abcdef0123", pull_request.head.sha);
+        assert_eq!("main", pull_request.base.ref_name);
+        assert_eq!("Fabrikam/web-app", pull_request.base.repo.full_name);
+        assert_eq!(vec![github::User::new("mary@company.com")], pull_request.all_reviewers());
+        assert!(!pull_request.is_merged());
+        assert!(!pull_request.is_draft());
+    }
+
+    #[test]
+    fn test_parse_build() {
+        let body = r#"{
+            "eventType": "build.complete",
+            "resource": {
+                "buildNumber": "20240101.3",
+                "result": "failed",
+                "sourceBranch": "refs/heads/main",
+                "definition": { "name": "web-app CI" },
+                "project": { "name": "Fabrikam" },
+                "repository": { "id": "4bc14d40", "name": "web-app", "type": "TfsGit" },
+                "requestedFor": { "displayName": "Joe", "uniqueName": "joe@company.com" },
+                "_links": { "web": { "href": "https://dev.azure.com/company/Fabrikam/_build/results?buildId=3" } }
+            }
+        }"#;
+        let build = match parse(body.as_bytes()).unwrap() {
+            Event::BuildCompleted(b) => b,
+            e => panic!("Unexpected event: {:?}", e),
+        };
+
+        assert!(!build.succeeded());
+        assert_eq!("main", build.branch());
+        assert_eq!("Fabrikam/web-app", build.repo().unwrap().full_name);
+        assert_eq!(
+            Some("https://dev.azure.com/company/Fabrikam/_build/results?buildId=3".to_string()),
+            build.html_url()
+        );
+    }
+
+    #[test]
+    fn test_parse_other() {
+        assert_eq!(
+            Event::Other("workitem.created".into()),
+            parse(br#"{"eventType": "workitem.created", "resource": {"id": 5}}"#).unwrap()
+        );
+        assert!(parse(br#"{"eventType": "git.pullrequest.created", "resource": {}}"#).is_err());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod api;
+pub mod hooks;
+mod models;
+
+pub use self::models::*;
@@ -0,0 +1,210 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::github;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityRef {
+    pub display_name: String,
+    // usually the user's email
+    #[serde(default)]
+    pub unique_name: String,
+}
+
+impl IdentityRef {
+    // Azure DevOps users go through the same user mappings as github users, by their unique name
+    pub fn to_user(&self) -> github::User {
+        github::User::new(&self.unique_name)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Project {
+    pub name: String,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Repository {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    pub project: Option<Project>,
+    // e.g. "https://dev.azure.com/company/project/_git/repo", which is also its web page
+    pub remote_url: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitRef {
+    pub commit_id: String,
+    pub comment: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PullRequest {
+    pub pull_request_id: u32,
+    pub repository: Repository,
+    // "active", "completed" or "abandoned"
+    pub status: String,
+    pub created_by: IdentityRef,
+    pub title: String,
+    pub description: Option<String>,
+    pub source_ref_name: String,
+    pub target_ref_name: String,
+    #[serde(default)]
+    pub reviewers: Vec<IdentityRef>,
+    pub last_merge_source_commit: Option<CommitRef>,
+    #[serde(default)]
+    pub is_draft: bool,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Definition {
+    pub name: String,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Link {
+    pub href: String,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct BuildLinks {
+    pub web: Option<Link>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Build {
+    pub build_number: String,
+    // "succeeded", "partiallySucceeded", "failed" or "canceled"
+    pub result: Option<String>,
+    #[serde(default)]
+    pub source_branch: String,
+    pub definition: Definition,
+    pub repository: Option<Repository>,
+    pub project: Option<Project>,
+    pub requested_for: Option<IdentityRef>,
+    #[serde(rename = "_links")]
+    pub links: Option<BuildLinks>,
+}
+
+// A status shown on a pull request, like a github commit status
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PullRequestStatus {
+    // "succeeded", "failed" or "pending"
+    pub state: String,
+    pub description: String,
+    pub context: StatusContext,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct StatusContext {
+    pub name: String,
+    pub genre: String,
+}
+
+impl PullRequestStatus {
+    pub fn new(name: &str, state: &str, description: &str) -> PullRequestStatus {
+        PullRequestStatus {
+            state: state.into(),
+            description: description.into(),
+            context: StatusContext {
+                name: name.into(),
+                genre: "octobot".into(),
+            },
+        }
+    }
+}
+
+fn branch_name(ref_name: &str) -> &str {
+    ref_name.trim_start_matches("refs/heads/")
+}
+
+impl Repository {
+    // The repo as octobot's repo config knows it: "project/repo", on the organization's host
+    pub fn to_repo(&self, project: &str) -> github::Repo {
+        let name = if self.name.is_empty() { self.id.clone() } else { self.name.clone() };
+        github::Repo {
+            html_url: self.remote_url.clone().unwrap_or_default(),
+            full_name: format!("{}/{}", project, name),
+            name: name,
+            owner: github::User::new(project),
+            archived: Some(false),
+        }
+    }
+
+    pub fn project_name(&self) -> String {
+        self.project.as_ref().map(|p| p.name.clone()).unwrap_or_default()
+    }
+}
+
+impl PullRequest {
+    pub fn html_url(&self) -> String {
+        format!("{}/pullrequest/{}", self.repository.remote_url.clone().unwrap_or_default(), self.pull_request_id)
+    }
+
+    pub fn repo(&self) -> github::Repo {
+        self.repository.to_repo(&self.repository.project_name())
+    }
+
+    pub fn target_branch(&self) -> &str {
+        branch_name(&self.target_ref_name)
+    }
+
+    // As a github PR, for what is shared with github: messages, lint and JIRA
+    pub fn to_pull_request(&self) -> github::PullRequest {
+        let repo = self.repo();
+        let mut pr = github::PullRequest::new();
+        pr.number = self.pull_request_id;
+        pr.title = self.title.clone();
+        pr.body = self.description.clone();
+        pr.html_url = self.html_url();
+        pr.state = if self.status == "active" { "open".into() } else { "closed".into() };
+        pr.merged = Some(self.status == "completed");
+        pr.user = self.created_by.to_user();
+        pr.draft = Some(self.is_draft);
+        pr.requested_reviewers = Some(self.reviewers.iter().map(|r| r.to_user()).collect());
+        pr.head = github::BranchRef::new(branch_name(&self.source_ref_name));
+        pr.head.sha = self.last_merge_source_commit.as_ref().map(|c| c.commit_id.clone()).unwrap_or_default();
+        pr.head.repo = repo.clone();
+        pr.base = github::BranchRef::new(self.target_branch());
+        pr.base.repo = repo;
+        pr
+    }
+}
+
+impl Build {
+    pub fn repo(&self) -> Option<github::Repo> {
+        let project = match self.project {
+            Some(ref p) => p.name.clone(),
+            None => self.repository.as_ref().map(|r| r.project_name()).unwrap_or_default(),
+        };
+        self.repository.as_ref().map(|r| r.to_repo(&project))
+    }
+
+    pub fn branch(&self) -> &str {
+        branch_name(&self.source_branch)
+    }
+
+    pub fn html_url(&self) -> Option<String> {
+        self.links.as_ref().and_then(|l| l.web.as_ref()).map(|w| w.href.clone())
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.result.as_ref().map(|r| r == "succeeded").unwrap_or(false)
+    }
+}
+
+impl CommitRef {
+    // As a github commit, for the checks shared with github
+    pub fn to_commit(&self) -> github::Commit {
+        let mut commit = github::Commit::new();
+        commit.sha = self.commit_id.clone();
+        commit.commit.message = self.comment.clone().unwrap_or_default();
+        commit
+    }
+}
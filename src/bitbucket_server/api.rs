@@ -0,0 +1,83 @@
+use serde_derive::Deserialize;
+use serde_json::json;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+use crate::bitbucket_server::{BuildStatus, Commit};
+use crate::config::BitbucketServerConfig;
+use crate::errors::*;
+use crate::http_client::HTTPClient;
+
+// PRs with more commits than this are only linted up to it
+const MAX_COMMITS: u32 = 100;
+
+pub trait Session: Send + Sync {
+    fn get_pull_request_commits(&self, project: &str, repo: &str, pr_id: u32) -> Result<Vec<Commit>>;
+
+    fn comment_pull_request(&self, project: &str, repo: &str, pr_id: u32, text: &str) -> Result<()>;
+
+    fn set_build_status(&self, sha: &str, status: &BuildStatus) -> Result<()>;
+}
+
+pub struct BitbucketServerSession {
+    client: HTTPClient,
+}
+
+#[derive(Deserialize)]
+struct Page<T> {
+    values: Vec<T>,
+}
+
+impl BitbucketServerSession {
+    pub fn new(config: &BitbucketServerConfig) -> Result<BitbucketServerSession> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        // personal (or project/repo) access tokens
+        headers.insert(reqwest::header::AUTHORIZATION, format!("Bearer {}", config.token).parse()?);
+
+        Ok(BitbucketServerSession {
+            client: HTTPClient::new_with_headers(config.url.trim_end_matches('/'), headers)?,
+        })
+    }
+
+    fn pull_request_path(project: &str, repo: &str, pr_id: u32) -> String {
+        format!(
+            "/rest/api/1.0/projects/{}/repos/{}/pull-requests/{}",
+            utf8_percent_encode(project, PATH_SEGMENT_ENCODE_SET),
+            utf8_percent_encode(repo, PATH_SEGMENT_ENCODE_SET),
+            pr_id
+        )
+    }
+}
+
+impl Session for BitbucketServerSession {
+    fn get_pull_request_commits(&self, project: &str, repo: &str, pr_id: u32) -> Result<Vec<Commit>> {
+        let path = format!(
+            "{}/commits?limit={}",
+            BitbucketServerSession::pull_request_path(project, repo, pr_id),
+            MAX_COMMITS
+        );
+        let commits: Page<Commit> = self.client.get(&path)?;
+        Ok(commits.values)
+    }
+
+    fn comment_pull_request(&self, project: &str, repo: &str, pr_id: u32, text: &str) -> Result<()> {
+        let path = format!("{}/comments", BitbucketServerSession::pull_request_path(project, repo, pr_id));
+        self.client.post_void(&path, &json!({ "text": text }))
+    }
+
+    fn set_build_status(&self, sha: &str, status: &BuildStatus) -> Result<()> {
+        self.client.post_void(&format!("/rest/build-status/1.0/commits/{}", sha), status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pull_request_path() {
+        assert_eq!(
+            "/rest/api/1.0/projects/WEB/repos/web%20app/pull-requests/12",
+            BitbucketServerSession::pull_request_path("WEB", "web app", 12)
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use log::info;
+use serde_derive::Deserialize;
+use serde_json::{self, Value};
+
+use crate::bitbucket_server::api::Session;
+use crate::bitbucket_server::{BuildStatus, PullRequest, PullRequestEvent};
+use crate::config::Config;
+use crate::errors::*;
+use crate::github;
+use crate::jira;
+use crate::messenger::Messenger;
+use crate::vcs::{self, PullRequestHost};
+
+// Every webhook delivery says what it is about in `eventKey` (and the X-Event-Key header)
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Hook {
+    event_key: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    PullRequest(PullRequestEvent),
+    // events octobot doesn't handle, including "diagnostics:ping" from testing the webhook
+    Other(String),
+}
+
+pub fn parse(body: &[u8]) -> Result<Event> {
+    let value: Value = serde_json::from_slice(body)?;
+    let hook: Hook = serde_json::from_value(value.clone())?;
+    if hook.event_key.starts_with("pr:") {
+        Ok(Event::PullRequest(serde_json::from_value(value)?))
+    } else {
+        Ok(Event::Other(hook.event_key))
+    }
+}
+
+// Notifies the repo's channels (and people) like github events do, and runs the repo's PR checks
+pub fn handle(
+    event: &Event,
+    config: &Config,
+    session: &dyn Session,
+    jira: Option<&dyn jira::api::Session>,
+    messenger: &Messenger,
+) {
+    let event = match *event {
+        Event::PullRequest(ref e) => e,
+        Event::Other(ref key) => {
+            info!("Ignoring Bitbucket Server event '{}'", key);
+            return;
+        }
+    };
+
+    let pr = event.pull_request.to_pull_request();
+    let host = BitbucketPullRequest::new(&event.pull_request, session);
+    let sender = event.actor.to_user();
+    let commits = Vec::<github::Commit>::new();
+
+    match event.event_key.as_str() {
+        "pr:opened" => {
+            vcs::lint_pull_request(&pr, true, config, &host);
+            vcs::notify_pull_request(&pr, "opened", &sender, &commits, messenger);
+            vcs::submit_for_review(&pr, config, jira, &host);
+        }
+        "pr:merged" => {
+            vcs::notify_pull_request(&pr, "merged", &sender, &commits, messenger);
+            vcs::mark_merged(&pr, config, jira, &host);
+        }
+        "pr:declined" | "pr:deleted" => vcs::notify_pull_request(&pr, "closed", &sender, &commits, messenger),
+        "pr:reviewer:approved" => {
+            let approver = event.participant.as_ref().map(|p| p.user.to_user()).unwrap_or(sender);
+            // Bitbucket Server doesn't say how many approvals its merge checks require
+            vcs::notify_approval(&pr, &approver, 0, messenger);
+        }
+        "pr:comment:added" => {
+            if let Some(ref comment) = event.comment {
+                let url = format!("{}/overview?commentId={}", pr.html_url, comment.id);
+                vcs::notify_comment(&pr, &comment.author.to_user(), &comment.text, &url, &commits, messenger);
+            }
+        }
+        // new commits, and edits of the title
+        "pr:from_ref_updated" | "pr:modified" => vcs::lint_pull_request(&pr, false, config, &host),
+        key => info!("Ignoring Bitbucket Server event '{}'", key),
+    };
+}
+
+// A Bitbucket Server PR, for the checks shared with other hosts
+struct BitbucketPullRequest<'a> {
+    session: &'a dyn Session,
+    project: String,
+    repo: String,
+    pr_id: u32,
+    sha: String,
+    url: String,
+}
+
+impl<'a> BitbucketPullRequest<'a> {
+    fn new(pr: &PullRequest, session: &'a dyn Session) -> BitbucketPullRequest<'a> {
+        BitbucketPullRequest {
+            session: session,
+            project: pr.to_ref.repository.project.key.clone(),
+            repo: pr.to_ref.repository.slug.clone(),
+            pr_id: pr.id,
+            sha: pr.from_ref.latest_commit.clone(),
+            url: pr.html_url(),
+        }
+    }
+}
+
+impl<'a> PullRequestHost for BitbucketPullRequest<'a> {
+    fn commits(&self) -> Result<Vec<github::Commit>> {
+        let commits = self.session.get_pull_request_commits(&self.project, &self.repo, self.pr_id)?;
+        Ok(commits.iter().map(|c| c.to_commit()).collect())
+    }
+
+    fn comment(&self, body: &str) -> Result<()> {
+        self.session.comment_pull_request(&self.project, &self.repo, self.pr_id, body)
+    }
+
+    // build statuses go on the head commit, and link back to the PR
+    fn set_status(&self, context: &str, status: vcs::Status, description: &str) -> Result<()> {
+        let state = match status {
+            vcs::Status::Success => "SUCCESSFUL",
+            vcs::Status::Failure => "FAILED",
+        };
+        self.session.set_build_status(&self.sha, &BuildStatus::new(context, state, description, &self.url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pr_event(key: &str) -> String {
+        format!(
+            r#"{{
+            "eventKey": "{}",
+            "date": "2021-03-01T12:00:00+0000",
+            "actor": {{ "name": "mary", "displayName": "Mary", "emailAddress": "mary@company.com" }},
+            "pullRequest": {{
+                "id": 12,
+                "title": "Fix the thing",
+                "description": "It was broken",
+                "state": "OPEN",
+                "fromRef": {{
+                    "id": "refs/heads/fix-thing",
+                    "displayId": "fix-thing",
+                    "latestCommit": "abcdef0123",
+                    "repository": {{ "slug": "web-app", "project": {{ "key": "WEB" }} }}
+                }},
+                "toRef": {{
+                    "id": "refs/heads/main",
+                    "displayId": "main",
+                    "latestCommit": "0123abcdef",
+                    "repository": {{
+                        "slug": "web-app",
+                        "project": {{ "key": "WEB" }},
+                        "links": {{
+                            "self": [{{ "href": "https://bitbucket.company.com/projects/WEB/repos/web-app/browse" }}]
+                        }}
+                    }}
+                }},
+                "author": {{ "user": {{ "name": "joe", "displayName": "Joe" }}, "approved": false }},
+                "reviewers": [{{ "user": {{ "name": "mary", "displayName": "Mary" }}, "approved": true }}],
+                "links": {{
+                    "self": [{{ "href": "https://bitbucket.company.com/projects/WEB/repos/web-app/pull-requests/12" }}]
+                }}
+            }},
+            "participant": {{ "user": {{ "name": "mary", "displayName": "Mary" }}, "approved": true }}
+        }}"#,
+            key
+        )
+    }
+
+    #[test]
+    fn test_parse_pull_request() {
+        let event = match parse(pr_event("pr:reviewer:approved").as_bytes()).unwrap() {
+            Event::PullRequest(e) => e,
+            e => panic!("Unexpected event: {:?}", e),
+        };
+
+        assert_eq!("pr:reviewer:approved", event.event_key);
+        assert_eq!("mary", event.participant.unwrap().user.to_user().login());
+
+        let pr = event.pull_request.to_pull_request();
+        assert_eq!(12, pr.number);
+        assert_eq!("joe", pr.user.login());
+        assert_eq!(vec![github::User::new("mary")], pr.all_reviewers());
+        assert_eq!("fix-thing", pr.head.ref_name);
+        assert_eq!("abcdef0123", pr.head.sha);
+        assert_eq!("main", pr.base.ref_name);
+        assert_eq!("https://bitbucket.company.com/projects/WEB/repos/web-app/pull-requests/12", pr.html_url);
+        assert!(!pr.is_merged());
+
+        let repo = &pr.base.repo;
+        assert_eq!("WEB/web-app", repo.full_name);
+        assert_eq!("WEB", repo.owner.login());
+        assert_eq!("https://bitbucket.company.com/projects/WEB/repos/web-app", repo.html_url);
+    }
+
+    #[test]
+    fn test_parse_comment() {
+        let body = pr_event("pr:comment:added").replace(
+            r#""participant":"#,
+            r#""comment": { "id": 62, "text": "Looks good @joe", "author": { "name": "mary" } }, "participant":"#,
+        );
+        let event = match parse(body.as_bytes()).unwrap() {
+            Event::PullRequest(e) => e,
+            e => panic!("Unexpected event: {:?}", e),
+        };
+
+        let comment = event.comment.unwrap();
+        assert_eq!(62, comment.id);
+        assert_eq!("Looks good @joe", comment.text);
+        assert_eq!("mary", comment.author.to_user().login());
+    }
+
+    #[test]
+    fn test_parse_other() {
+        assert_eq!(
+            Event::Other("diagnostics:ping".into()),
+            parse(br#"{"eventKey": "diagnostics:ping", "test": true}"#).unwrap()
+        );
+        assert!(parse(br#"{"eventKey": "pr:opened", "pullRequest": {}}"#).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::github;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    // the username
+    pub name: String,
+    #[serde(default)]
+    pub display_name: String,
+}
+
+impl User {
+    // Bitbucket Server users go through the same user mappings as github users, by their username
+    pub fn to_user(&self) -> github::User {
+        github::User::new(&self.name)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Link {
+    pub href: String,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Links {
+    #[serde(rename = "self", default)]
+    pub self_links: Vec<Link>,
+}
+
+impl Links {
+    pub fn href(&self) -> String {
+        self.self_links.first().map(|l| l.href.clone()).unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Project {
+    pub key: String,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Repository {
+    pub slug: String,
+    pub project: Project,
+    // e.g. "https://bitbucket.company.com/projects/WEB/repos/web-app/browse"
+    #[serde(default)]
+    pub links: Links,
+}
+
+impl Repository {
+    // The repo as octobot's repo config knows it: "PROJECT/repo", with the project as the owner
+    pub fn to_repo(&self) -> github::Repo {
+        let html_url = self.links.href();
+        github::Repo {
+            html_url: html_url.trim_end_matches("/browse").to_string(),
+            full_name: format!("{}/{}", self.project.key, self.slug),
+            name: self.slug.clone(),
+            owner: github::User::new(&self.project.key),
+            archived: Some(false),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Ref {
+    // e.g. "refs/heads/main"
+    pub id: String,
+    // e.g. "main"
+    pub display_id: String,
+    pub latest_commit: String,
+    pub repository: Repository,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Participant {
+    pub user: User,
+    #[serde(default)]
+    pub approved: bool,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PullRequest {
+    pub id: u32,
+    pub title: String,
+    pub description: Option<String>,
+    // "OPEN", "MERGED" or "DECLINED"
+    pub state: String,
+    pub from_ref: Ref,
+    pub to_ref: Ref,
+    pub author: Participant,
+    #[serde(default)]
+    pub reviewers: Vec<Participant>,
+    #[serde(default)]
+    pub draft: bool,
+    // e.g. "https://bitbucket.company.com/projects/WEB/repos/web-app/pull-requests/12"
+    #[serde(default)]
+    pub links: Links,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub id: u64,
+    pub text: String,
+    pub author: User,
+}
+
+// Every pull request event ("pr:opened", "pr:merged", "pr:comment:added", ...) is shaped like this
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PullRequestEvent {
+    pub event_key: String,
+    // who made the change
+    pub actor: User,
+    pub pull_request: PullRequest,
+    // the reviewer, for review events
+    pub participant: Option<Participant>,
+    // for comment events
+    pub comment: Option<Comment>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Commit {
+    pub id: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+impl Commit {
+    // As a github commit, for the checks shared with github
+    pub fn to_commit(&self) -> github::Commit {
+        let mut commit = github::Commit::new();
+        commit.sha = self.id.clone();
+        commit.commit.message = self.message.clone();
+        commit
+    }
+}
+
+// A build status of a commit, like a github commit status. Bitbucket shows them on the PRs with that commit.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct BuildStatus {
+    // "SUCCESSFUL", "FAILED" or "INPROGRESS"
+    pub state: String,
+    pub key: String,
+    pub name: String,
+    pub url: String,
+    pub description: String,
+}
+
+impl PullRequest {
+    pub fn html_url(&self) -> String {
+        self.links.href()
+    }
+
+    // As a github PR, for what is shared with github: messages, lint and JIRA
+    pub fn to_pull_request(&self) -> github::PullRequest {
+        let mut pr = github::PullRequest::new();
+        pr.number = self.id;
+        pr.title = self.title.clone();
+        pr.body = self.description.clone();
+        pr.html_url = self.html_url();
+        pr.state = if self.state == "OPEN" { "open".into() } else { "closed".into() };
+        pr.merged = Some(self.state == "MERGED");
+        pr.draft = Some(self.draft);
+        pr.user = self.author.user.to_user();
+        pr.requested_reviewers = Some(self.reviewers.iter().map(|r| r.user.to_user()).collect());
+        pr.head = github::BranchRef::new(&self.from_ref.display_id);
+        pr.head.sha = self.from_ref.latest_commit.clone();
+        pr.head.repo = self.from_ref.repository.to_repo();
+        pr.base = github::BranchRef::new(&self.to_ref.display_id);
+        pr.base.sha = self.to_ref.latest_commit.clone();
+        pr.base.repo = self.to_ref.repository.to_repo();
+        pr
+    }
+}
+
+impl BuildStatus {
+    pub fn new(key: &str, state: &str, description: &str, url: &str) -> BuildStatus {
+        BuildStatus {
+            state: state.into(),
+            key: key.into(),
+            name: key.into(),
+            url: url.into(),
+            description: description.into(),
+        }
+    }
+}
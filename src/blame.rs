@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::git::Git;
+use crate::git_clone_manager::GitCloneManager;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::messenger;
+use crate::slack::{SlackAttachmentBuilder, SlackRequest};
+use crate::users;
+use crate::util;
+use crate::worker;
+
+// Blame at a given commit never changes; entries only expire to keep the table small
+pub const CACHE_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+// Per-line authors of a file at a commit
+#[derive(Clone)]
+pub struct BlameCache {
+    db: Database,
+}
+
+impl BlameCache {
+    pub fn new(db: Database) -> BlameCache {
+        BlameCache { db: db }
+    }
+
+    pub fn get(&self, repo: &str, sha: &str, path: &str) -> Result<Option<Vec<String>>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare("SELECT authors FROM blame_cache WHERE repo = ?1 AND sha = ?2 AND path = ?3")?;
+        let mut rows = stmt.query(&[&repo as &dyn ToSql, &sha, &path])?;
+
+        if let Ok(Some(row)) = rows.next() {
+            let authors: String = row.get(0)?;
+            return Ok(Some(authors.split('\n').map(|a| a.to_string()).collect()));
+        }
+        Ok(None)
+    }
+
+    pub fn put(&self, repo: &str, sha: &str, path: &str, authors: &Vec<String>, now: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO blame_cache (repo, sha, path, authors, created_at)
+               VALUES (?1, ?2, ?3, ?4, ?5)
+               ON CONFLICT (repo, sha, path) DO UPDATE SET authors = excluded.authors,
+                   created_at = excluded.created_at"#,
+            &[&repo as &dyn ToSql, &sha, &path, &authors.join("\n"), &now],
+        )
+        .map_err(|e| format_err!("Error caching blame of {}: {}", path, e))?;
+
+        Ok(())
+    }
+
+    pub fn prune(&self, before: i64) -> Result<usize> {
+        let conn = self.db.connect()?;
+        let pruned = conn
+            .execute("DELETE FROM blame_cache WHERE created_at < ?1", &[&before])
+            .map_err(|e| format_err!("Error pruning blame cache: {}", e))?;
+
+        Ok(pruned)
+    }
+}
+
+// "revert" or "hotfix" if the PR is one, going by the title github gives reverts or the branch name
+pub fn kind(pull_request: &github::PullRequest) -> Option<&'static str> {
+    let branch = pull_request.head.ref_name.to_lowercase();
+    if pull_request.title.starts_with("Revert \"") || branch.starts_with("revert-") {
+        Some("revert")
+    } else if branch.starts_with("hotfix") {
+        Some("hotfix")
+    } else {
+        None
+    }
+}
+
+// Line numbers on the old side of a patch that were removed or changed.
+// Lines that were only inserted count against the line above them.
+pub fn changed_lines(patch: &str) -> Vec<usize> {
+    let mut lines = vec![];
+    let mut old_line = 0;
+    let mut prev = ' ';
+    for line in patch.lines() {
+        if line.starts_with("@@") {
+            old_line = hunk_start(line).unwrap_or(0);
+            prev = ' ';
+            continue;
+        }
+        let op = line.chars().next().unwrap_or(' ');
+        match op {
+            '-' => {
+                lines.push(old_line);
+                old_line += 1;
+            }
+            '+' => {
+                if prev != '-' && prev != '+' && old_line > 1 {
+                    lines.push(old_line - 1);
+                }
+            }
+            '\\' => continue,
+            _ => old_line += 1,
+        }
+        prev = op;
+    }
+    lines.dedup();
+    lines
+}
+
+// "@@ -12,5 +12,7 @@" => 12
+fn hunk_start(header: &str) -> Option<usize> {
+    let old = header.split_whitespace().nth(1)?;
+    old.trim_start_matches('-').split(',').next()?.parse().ok()
+}
+
+// The author email of each line in the output of `git blame --line-porcelain`
+pub fn parse_blame(porcelain: &str) -> Vec<String> {
+    porcelain
+        .lines()
+        .filter(|l| l.starts_with("author-mail "))
+        .map(|l| l["author-mail ".len()..].trim_start_matches('<').trim_end_matches('>').to_lowercase())
+        .collect()
+}
+
+// Authors of the given 1-based lines, in order of appearance
+pub fn authors(blame: &Vec<String>, lines: &Vec<usize>) -> Vec<String> {
+    let mut authors: Vec<String> = vec![];
+    for line in lines {
+        if let Some(author) = blame.get(line.wrapping_sub(1)) {
+            if !author.is_empty() && !authors.contains(author) {
+                authors.push(author.clone());
+            }
+        }
+    }
+    authors
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BlameRequest {
+    pub repo: github::Repo,
+    pub pull_request: github::PullRequest,
+}
+
+pub fn req(repo: &github::Repo, pull_request: &github::PullRequest) -> BlameRequest {
+    BlameRequest {
+        repo: repo.clone(),
+        pull_request: pull_request.clone(),
+    }
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+) -> Arc<dyn worker::Runner<BlameRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+        clone_mgr: clone_mgr,
+        slack: slack,
+    })
+}
+
+impl Runner {
+    // Files each author wrote lines of that the PR changes
+    fn affected_authors(&self, github: &dyn Session, req: &BlameRequest) -> Result<HashMap<String, Vec<String>>> {
+        let owner = req.repo.owner.login();
+        let base_sha = &req.pull_request.base.sha;
+        let files = github.get_pull_request_files(owner, &req.repo.name, req.pull_request.number)?;
+        let now = db::now();
+
+        let mut clone = None;
+        let mut affected: HashMap<String, Vec<String>> = HashMap::new();
+        for file in files {
+            // added and renamed files have nothing at the base commit to blame
+            if file.status != "modified" && file.status != "removed" {
+                continue;
+            }
+            let lines = match file.patch {
+                Some(ref p) => changed_lines(p),
+                None => continue,
+            };
+            if lines.is_empty() {
+                continue;
+            }
+
+            let blame = match self.config.blame_cache.get(&req.repo.full_name, base_sha, &file.filename)? {
+                Some(b) => b,
+                None => {
+                    if clone.is_none() {
+                        clone = Some(self.clone_mgr.clone_for_refs(owner, &req.repo.name, &[base_sha])?);
+                    }
+                    let dir = clone.as_ref().unwrap().dir();
+                    let git = Git::new(github.github_host(), github.github_token(), dir);
+                    let porcelain = git.run(&["blame", "--line-porcelain", base_sha, "--", &file.filename])?;
+                    let blame = parse_blame(&porcelain);
+                    self.config.blame_cache.put(&req.repo.full_name, base_sha, &file.filename, &blame, now)?;
+                    blame
+                }
+            };
+
+            for author in authors(&blame, &lines) {
+                affected.entry(author).or_insert_with(Vec::new).push(file.filename.clone());
+            }
+        }
+
+        if let Err(e) = self.config.blame_cache.prune(now - CACHE_TTL_SECS) {
+            error!("{}", e);
+        }
+
+        Ok(affected)
+    }
+}
+
+impl worker::Runner<BlameRequest> for Runner {
+    fn handle(&self, req: BlameRequest) {
+        let github = match self.github_app.new_session(&req.repo.owner.login(), &req.repo.name) {
+            Ok(g) => g,
+            Err(e) => {
+                error!("Error getting new session: {}", e);
+                return;
+            }
+        };
+
+        let affected = match self.affected_authors(&github, &req) {
+            Ok(a) => a,
+            Err(e) => {
+                error!("Error blaming PR #{} in {}: {}", req.pull_request.number, req.repo.full_name, e);
+                return;
+            }
+        };
+
+        let kind = kind(&req.pull_request).unwrap_or("change");
+        let pr_author = req.pull_request.user.login();
+        let messenger = messenger::new(self.config.clone(), self.slack.clone()).for_dm_event(users::DM_PULL_REQUEST);
+        let mut notified: Vec<String> = vec![];
+        for (email, paths) in affected {
+            let login = match self.config.users().lookup_by_email(&email) {
+                Some(u) => u.github,
+                None => continue,
+            };
+            if login == pr_author || notified.contains(&login) {
+                continue;
+            }
+
+            let msg = format!(
+                "{} opened a {} that changes lines you wrote: {}",
+                pr_author,
+                kind,
+                util::make_link(&req.pull_request.html_url, &req.pull_request.title)
+            );
+            let attachments = vec![SlackAttachmentBuilder::new(&paths.join("\n")).title("Files").build()];
+            messenger.send_to_user(&github::User::new(&login), &msg, &attachments);
+            notified.push(login);
+        }
+
+        info!("Notified {} author(s) of lines changed by PR #{}", notified.len(), req.pull_request.number);
+    }
+
+    fn repo(&self, req: &BlameRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &BlameRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    fn pull_request(title: &str, branch: &str) -> github::PullRequest {
+        let mut pr = github::PullRequest::new();
+        pr.title = title.into();
+        pr.head = github::BranchRef::new(branch);
+        pr
+    }
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(Some("revert"), kind(&pull_request("Revert \"Add the thing\"", "revert-12-thing")));
+        assert_eq!(Some("revert"), kind(&pull_request("Undo the thing", "revert-12-thing")));
+        assert_eq!(Some("hotfix"), kind(&pull_request("Fix the thing", "hotfix/thing")));
+        assert_eq!(Some("hotfix"), kind(&pull_request("Fix the thing", "Hotfix-thing")));
+        assert_eq!(None, kind(&pull_request("Reverting to the old way", "feature")));
+    }
+
+    #[test]
+    fn test_changed_lines() {
+        let patch = "@@ -3,4 +3,4 @@ fn main() {\n a\n-b\n+B\n c\n d\n@@ -20,2 +20,4 @@\n x\n+y\n+z\n w";
+        assert_eq!(vec![4, 20], changed_lines(patch));
+
+        let patch = "@@ -1,3 +0,0 @@\n-a\n-b\n-c\n\\ No newline at end of file";
+        assert_eq!(vec![1, 2, 3], changed_lines(patch));
+
+        assert_eq!(Vec::<usize>::new(), changed_lines("@@ -0,0 +1,2 @@\n+a\n+b"));
+    }
+
+    #[test]
+    fn test_parse_blame_and_authors() {
+        let porcelain = r#"1234 1 1 2
+author Some One
+author-mail <Some.One@company.com>
+filename src/main.rs
+	fn main() {
+1234 2 2
+author Some One
+author-mail <Some.One@company.com>
+filename src/main.rs
+	    thing();
+5678 3 3 1
+author Other Person
+author-mail <other@company.com>
+filename src/main.rs
+	}
+"#;
+        let blame = parse_blame(porcelain);
+        assert_eq!(vec!["some.one@company.com", "some.one@company.com", "other@company.com"], blame);
+
+        assert_eq!(vec!["some.one@company.com", "other@company.com"], authors(&blame, &vec![2, 1, 3]));
+        assert_eq!(vec!["other@company.com"], authors(&blame, &vec![3, 4]));
+        assert_eq!(Vec::<String>::new(), authors(&blame, &vec![0]));
+    }
+
+    #[test]
+    fn test_cache() {
+        let temp_dir = TempDir::new("blame.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let cache = BlameCache::new(Database::new(&db_file.to_string_lossy()).expect("create temp database"));
+
+        let blame = vec!["a@company.com".to_string(), "b@company.com".to_string()];
+        cache.put("some-user/some-repo", "abc", "src/main.rs", &blame, 100).unwrap();
+
+        assert_eq!(Some(blame), cache.get("some-user/some-repo", "abc", "src/main.rs").unwrap());
+        assert_eq!(None, cache.get("some-user/some-repo", "def", "src/main.rs").unwrap());
+
+        assert_eq!(0, cache.prune(100).unwrap());
+        assert_eq!(1, cache.prune(101).unwrap());
+        assert_eq!(None, cache.get("some-user/some-repo", "abc", "src/main.rs").unwrap());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::errors::*;
+use crate::github;
+use crate::github::api::Session;
+use crate::github::{CheckAnnotation, CheckRun, Conclusion};
+
+// Long lines (e.g. minified output) are cut to this many characters
+const MAX_LINE_LEN: usize = 200;
+
+// Github actions prefixes errors in job logs with this
+const ERROR_MARKER: &str = "##[error]";
+
+// An excerpt of why a failed status failed, for triaging it from slack: the annotations of the check run of the
+// same name, or else the log of its github actions job. None if github has neither.
+pub fn excerpt(
+    github: &dyn Session,
+    repo: &github::Repo,
+    sha: &str,
+    context: &str,
+    target_url: &str,
+    max_lines: usize,
+) -> Result<Option<String>> {
+    let owner = repo.owner.login();
+    if let Some(job_id) = actions_job_id(target_url) {
+        let log = github.get_job_log(owner, &repo.name, job_id)?;
+        return Ok(format(&log_excerpt(&log, max_lines)));
+    }
+
+    let runs = github.get_check_runs(owner, &repo.name, sha)?;
+    let same_check = |r: &&CheckRun| r.name == context || r.details_url.as_deref() == Some(target_url);
+    let run = match runs.iter().filter(|r| is_failed(r)).find(same_check) {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+
+    let annotations = github.get_check_run_annotations(owner, &repo.name, run.id)?;
+    if !annotations.is_empty() {
+        return Ok(format(&annotation_excerpt(&annotations, max_lines)));
+    }
+
+    match run.details_url.as_ref().and_then(|u| actions_job_id(u)) {
+        Some(job_id) => {
+            let log = github.get_job_log(owner, &repo.name, job_id)?;
+            Ok(format(&log_excerpt(&log, max_lines)))
+        }
+        None => Ok(None),
+    }
+}
+
+fn is_failed(run: &CheckRun) -> bool {
+    run.conclusion == Some(Conclusion::Failure) || run.conclusion == Some(Conclusion::TimedOut)
+}
+
+// https://github.com/<owner>/<repo>/actions/runs/<run id>/job/<job id> => job id
+pub fn actions_job_id(url: &str) -> Option<u64> {
+    let mut parts = url.split('?').next().unwrap_or("").trim_end_matches('/').rsplit('/');
+    let id = parts.next()?.parse().ok()?;
+    match parts.next() {
+        Some("job") | Some("jobs") if url.contains("/actions/runs/") => Some(id),
+        _ => None,
+    }
+}
+
+// The lines leading up to the first error of a github actions log, or else its last lines
+pub fn log_excerpt(log: &str, max_lines: usize) -> Vec<String> {
+    let lines = log.lines().map(|l| clean_line(l)).filter(|l| !l.trim().is_empty()).collect::<Vec<_>>();
+    let end = lines.iter().position(|l| l.starts_with(ERROR_MARKER)).map(|i| i + 1).unwrap_or(lines.len());
+    let start = end.saturating_sub(max_lines);
+
+    lines[start..end].iter().map(|l| truncate(l)).collect()
+}
+
+// Failures first, then warnings and notices
+pub fn annotation_excerpt(annotations: &Vec<CheckAnnotation>, max_lines: usize) -> Vec<String> {
+    let mut sorted = annotations.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|a| a.annotation_level != "failure");
+
+    let mut lines = vec![];
+    for annotation in sorted {
+        let location = format!("{}:{}: ", annotation.path, annotation.start_line);
+        for (i, line) in annotation.message.lines().enumerate() {
+            let prefix = if i == 0 { location.as_str() } else { "" };
+            lines.push(truncate(&format!("{}{}", prefix, line)));
+        }
+    }
+    lines.truncate(max_lines);
+    lines
+}
+
+pub fn format(lines: &Vec<String>) -> Option<String> {
+    if lines.is_empty() {
+        None
+    } else {
+        // slack ends a code block at the first ```
+        Some(format!("```\n{}\n```", lines.join("\n").replace("```", "'''")))
+    }
+}
+
+// Drops the timestamp that github actions puts at the start of each line, and terminal colors
+fn clean_line(line: &str) -> String {
+    let mut parts = line.splitn(2, ' ');
+    let first = parts.next().unwrap_or("");
+    let line = match parts.next() {
+        Some(rest) if first.len() > 20 && first.ends_with('Z') && first.contains('T') => rest,
+        _ => line,
+    };
+
+    let mut cleaned = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // skip to the end of the escape sequence, e.g. \x1b[31m
+            while let Some(c) = chars.next() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            cleaned.push(c);
+        }
+    }
+    cleaned
+}
+
+fn truncate(line: &str) -> String {
+    if line.chars().count() <= MAX_LINE_LEN {
+        line.to_string()
+    } else {
+        format!("{}...", line.chars().take(MAX_LINE_LEN).collect::<String>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotation(path: &str, line: u32, level: &str, message: &str) -> CheckAnnotation {
+        CheckAnnotation {
+            path: path.into(),
+            start_line: line,
+            end_line: line,
+            annotation_level: level.into(),
+            message: message.into(),
+        }
+    }
+
+    #[test]
+    fn test_actions_job_id() {
+        assert_eq!(Some(456), actions_job_id("https://github.com/some-user/some-repo/actions/runs/123/job/456"));
+        assert_eq!(Some(456), actions_job_id("https://github.com/some-user/some-repo/actions/runs/123/jobs/456/"));
+        assert_eq!(Some(456), actions_job_id("https://github.com/some-user/some-repo/actions/runs/123/job/456?pr=1"));
+        assert_eq!(None, actions_job_id("https://github.com/some-user/some-repo/actions/runs/123"));
+        assert_eq!(None, actions_job_id("https://ci.company.com/job/456"));
+        assert_eq!(None, actions_job_id(""));
+    }
+
+    #[test]
+    fn test_log_excerpt() {
+        let log = "2021-03-01T12:00:00.1234567Z ##[group]Run cargo test\n\
+                   2021-03-01T12:00:01.1234567Z running 2 tests\n\
+                   2021-03-01T12:00:02.1234567Z test a ... \u{1b}[32mok\u{1b}[0m\n\
+                   2021-03-01T12:00:02.1234567Z \n\
+                   2021-03-01T12:00:03.1234567Z test b ... \u{1b}[31mFAILED\u{1b}[0m\n\
+                   2021-03-01T12:00:04.1234567Z ##[error]Process completed with exit code 101.\n\
+                   2021-03-01T12:00:05.1234567Z Post job cleanup.";
+
+        assert_eq!(
+            vec!["test a ... ok", "test b ... FAILED", "##[error]Process completed with exit code 101."],
+            log_excerpt(log, 3)
+        );
+
+        let log = "one\ntwo\nthree";
+        assert_eq!(vec!["two", "three"], log_excerpt(log, 2));
+        assert_eq!(vec!["one", "two", "three"], log_excerpt(log, 10));
+        assert_eq!(Vec::<String>::new(), log_excerpt(log, 0));
+    }
+
+    #[test]
+    fn test_annotation_excerpt() {
+        let annotations = vec![
+            annotation("src/lib.rs", 3, "warning", "unused import"),
+            annotation("src/main.rs", 10, "failure", "assertion failed\nleft: 1\nright: 2"),
+        ];
+
+        assert_eq!(
+            vec!["src/main.rs:10: assertion failed", "left: 1", "right: 2", "src/lib.rs:3: unused import"],
+            annotation_excerpt(&annotations, 10)
+        );
+        assert_eq!(vec!["src/main.rs:10: assertion failed", "left: 1"], annotation_excerpt(&annotations, 2));
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(None, format(&vec![]));
+        assert_eq!(
+            Some("```\nsome ''' quote\nline\n```".to_string()),
+            format(&vec!["some ``` quote".into(), "line".into()])
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let long = "x".repeat(MAX_LINE_LEN + 1);
+        assert_eq!(format!("{}...", "x".repeat(MAX_LINE_LEN)), truncate(&long));
+        assert_eq!("short", truncate("short"));
+    }
+}
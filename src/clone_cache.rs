@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use log::{error, info};
+use serde_derive::Serialize;
+
+use crate::errors::*;
+use crate::git::Git;
+use crate::git_clone_manager::GitCloneManager;
+use crate::scheduler;
+
+pub const CHECK_INTERVAL_SECS: u64 = 60 * 60;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RepoCacheStats {
+    // "host/owner/repo"
+    pub repo: String,
+    // disk used by the repo's clones and worktrees
+    pub size_bytes: u64,
+    // when one of its clones was last taken (seconds since the epoch)
+    pub last_used: i64,
+    pub in_use: bool,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CloneCacheStats {
+    pub quota_bytes: Option<u64>,
+    pub total_bytes: u64,
+    pub repos: Vec<RepoCacheStats>,
+    // repos whose clones were deleted to stay under the quota, since startup
+    pub evictions: u64,
+    // when the clones were last measured and cleaned up, if they have been
+    pub last_maintenance: Option<i64>,
+}
+
+// Keeps track of the repos cloned under the clone root dir: when each was last used, and how big they have grown
+pub struct CloneCache {
+    root_dir: PathBuf,
+    quota_bytes: Option<u64>,
+    last_used: Mutex<HashMap<String, i64>>,
+    stats: Mutex<CloneCacheStats>,
+}
+
+impl CloneCache {
+    pub fn new(root_dir: &str, quota_bytes: Option<u64>) -> CloneCache {
+        CloneCache {
+            root_dir: PathBuf::from(root_dir),
+            quota_bytes: quota_bytes,
+            last_used: Mutex::new(HashMap::new()),
+            stats: Mutex::new(CloneCacheStats {
+                quota_bytes: quota_bytes,
+                total_bytes: 0,
+                repos: vec![],
+                evictions: 0,
+                last_maintenance: None,
+            }),
+        }
+    }
+
+    pub fn touch(&self, host: &str, owner: &str, repo: &str, now: i64) {
+        self.last_used.lock().unwrap().insert(format!("{}/{}/{}", host, owner, repo), now);
+    }
+
+    pub fn stats(&self) -> CloneCacheStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    // (host, owner, repo) of each repo with a directory under the root dir
+    fn repos(&self) -> Vec<(String, String, String)> {
+        let mut repos = vec![];
+        for host in sub_dirs(&self.root_dir) {
+            for owner in sub_dirs(&self.root_dir.join(&host)) {
+                for repo in sub_dirs(&self.root_dir.join(&host).join(&owner)) {
+                    repos.push((host.clone(), owner.clone(), repo));
+                }
+            }
+        }
+        repos.sort();
+        repos
+    }
+
+    // The directory of all of a repo's clones and worktrees
+    pub fn repo_dir(&self, host: &str, owner: &str, repo: &str) -> PathBuf {
+        self.root_dir.join(host).join(owner).join(repo)
+    }
+
+    // Repos cloned before the last restart count as last used when their clones last changed
+    fn last_used(&self, key: &str, dir: &Path) -> i64 {
+        match self.last_used.lock().unwrap().get(key) {
+            Some(t) => *t,
+            None => sub_dirs(dir).iter().map(|d| modified_time(&dir.join(d))).max().unwrap_or(0),
+        }
+    }
+
+    // Runs `git gc --auto` in the clones of repos that aren't in use, measures all of them, and deletes the least
+    // recently used ones that aren't in use while they add up to more than the quota.
+    pub fn maintain(&self, clone_mgr: &GitCloneManager, now: i64) {
+        let mut repos = vec![];
+        for (host, owner, repo) in self.repos() {
+            let dir = self.repo_dir(&host, &owner, &repo);
+            let in_use = clone_mgr.in_use(&host, &owner, &repo);
+            if !in_use {
+                collect_garbage(&dir);
+            }
+
+            let key = format!("{}/{}/{}", host, owner, repo);
+            repos.push(RepoCacheStats {
+                last_used: self.last_used(&key, &dir),
+                repo: key,
+                size_bytes: dir_size(&dir),
+                in_use: in_use,
+            });
+        }
+
+        let mut evictions = 0;
+        if let Some(quota) = self.quota_bytes {
+            for key in to_evict(&repos, quota) {
+                let parts = key.splitn(3, '/').collect::<Vec<_>>();
+                match clone_mgr.evict(parts[0], parts[1], parts[2]) {
+                    Ok(true) => {
+                        info!("Evicted clones of {} to stay under the clone cache quota", key);
+                        repos.retain(|r| r.repo != key);
+                        self.last_used.lock().unwrap().remove(&key);
+                        evictions += 1;
+                    }
+                    Ok(false) => info!("Not evicting clones of {}: in use", key),
+                    Err(e) => error!("Error evicting clones of {}: {}", key, e),
+                }
+            }
+        }
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.total_bytes = repos.iter().map(|r| r.size_bytes).sum();
+        stats.repos = repos;
+        stats.evictions += evictions;
+        stats.last_maintenance = Some(now);
+    }
+}
+
+// Keys of the least recently used repos not in use that must go for the rest to fit in |quota|
+pub fn to_evict(repos: &Vec<RepoCacheStats>, quota: u64) -> Vec<String> {
+    let mut total: u64 = repos.iter().map(|r| r.size_bytes).sum();
+
+    let mut candidates = repos.iter().filter(|r| !r.in_use).collect::<Vec<_>>();
+    candidates.sort_by_key(|r| r.last_used);
+
+    let mut evict = vec![];
+    for repo in candidates {
+        if total <= quota {
+            break;
+        }
+        total -= repo.size_bytes;
+        evict.push(repo.repo.clone());
+    }
+    evict
+}
+
+fn sub_dirs(dir: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+fn modified_time(path: &Path) -> i64 {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// Total size of the files under |path|, without following symlinks
+pub fn dir_size(path: &Path) -> u64 {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(_) => return 0,
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+
+    match fs::read_dir(path) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| dir_size(&e.path())).sum(),
+        Err(_) => 0,
+    }
+}
+
+// The repo's own clones and the main clone of its worktrees: worktrees share the main clone's objects
+fn collect_garbage(repo_dir: &Path) {
+    for name in sub_dirs(repo_dir) {
+        let dir = repo_dir.join(&name);
+        if !dir.join(".git").is_dir() {
+            continue;
+        }
+
+        let git = Git::new("", "", &dir);
+        let result = if name == "main" {
+            git.run(&["worktree", "prune"]).and_then(|_| git.run(&["gc", "--auto", "--quiet"]))
+        } else {
+            git.run(&["gc", "--auto", "--quiet"])
+        };
+        if let Err(e) = result {
+            error!("Error collecting garbage in {:?}: {}", dir, e);
+        }
+    }
+}
+
+// Cleans up and enforces the quota on the clone cache every hour
+pub struct CloneCacheMaintainer {
+    clone_mgr: Arc<GitCloneManager>,
+}
+
+impl CloneCacheMaintainer {
+    pub fn new(clone_mgr: Arc<GitCloneManager>) -> Arc<dyn scheduler::Task> {
+        Arc::new(CloneCacheMaintainer { clone_mgr: clone_mgr })
+    }
+}
+
+impl scheduler::Task for CloneCacheMaintainer {
+    fn run(&self, now: i64) -> Result<()> {
+        self.clone_mgr.cache().maintain(&self.clone_mgr, now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn repo(name: &str, size_bytes: u64, last_used: i64, in_use: bool) -> RepoCacheStats {
+        RepoCacheStats {
+            repo: name.into(),
+            size_bytes: size_bytes,
+            last_used: last_used,
+            in_use: in_use,
+        }
+    }
+
+    #[test]
+    fn test_to_evict() {
+        let repos = vec![
+            repo("h/o/recent", 100, 300, false),
+            repo("h/o/oldest", 100, 100, true),
+            repo("h/o/old", 50, 200, false),
+            repo("h/o/older", 50, 150, false),
+        ];
+
+        assert!(to_evict(&repos, 300).is_empty());
+        // the oldest is in use, so the next oldest go instead
+        assert_eq!(vec!["h/o/older"], to_evict(&repos, 250));
+        assert_eq!(vec!["h/o/older", "h/o/old"], to_evict(&repos, 200));
+        // never more than what isn't in use
+        assert_eq!(vec!["h/o/older", "h/o/old", "h/o/recent"], to_evict(&repos, 0));
+    }
+
+    #[test]
+    fn test_dir_size_and_repos() {
+        let root = TempDir::new("clone_cache").unwrap();
+        let clone_dir = root.path().join("the-host").join("some-user").join("some-repo").join("1");
+        fs::create_dir_all(clone_dir.join(".git")).unwrap();
+        fs::write(clone_dir.join("README.md"), "hello").unwrap();
+        fs::write(clone_dir.join(".git").join("HEAD"), "ref: refs/heads/master\n").unwrap();
+
+        assert_eq!(5 + 23, dir_size(root.path()));
+        assert_eq!(0, dir_size(&root.path().join("missing")));
+
+        let cache = CloneCache::new(&root.path().to_string_lossy(), None);
+        assert_eq!(
+            vec![("the-host".to_string(), "some-user".to_string(), "some-repo".to_string())],
+            cache.repos()
+        );
+
+        let repo_dir = cache.repo_dir("the-host", "some-user", "some-repo");
+        assert!(cache.last_used("the-host/some-user/some-repo", &repo_dir) > 0);
+        cache.touch("the-host", "some-user", "some-repo", 12);
+        assert_eq!(12, cache.last_used("the-host/some-user/some-repo", &repo_dir));
+    }
+}
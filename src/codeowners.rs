@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+use regex::Regex;
+
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::util;
+use crate::worker;
+
+// Locations github checks for a CODEOWNERS file, in order of precedence
+const CODEOWNERS_PATHS: [&str; 3] = [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+const CACHE_TTL_SECS: u64 = 10 * 60;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Owners {
+    pub users: Vec<String>,
+    pub teams: Vec<String>,
+}
+
+struct Rule {
+    pattern: Regex,
+    owners: Vec<String>,
+}
+
+pub struct CodeOwners {
+    rules: Vec<Rule>,
+}
+
+impl Owners {
+    pub fn new() -> Owners {
+        Owners {
+            users: vec![],
+            teams: vec![],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.teams.is_empty()
+    }
+}
+
+impl CodeOwners {
+    pub fn parse(contents: &str) -> CodeOwners {
+        let mut rules = vec![];
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let pattern = match parts.next() {
+                Some(p) => p,
+                None => continue,
+            };
+            let owners = parts
+                .take_while(|p| !p.starts_with("#"))
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>();
+
+            match Regex::new(&pattern_to_regex(pattern)) {
+                Ok(r) => rules.push(Rule { pattern: r, owners: owners }),
+                Err(e) => error!("Invalid CODEOWNERS pattern '{}': {}", pattern, e),
+            };
+        }
+
+        CodeOwners { rules: rules }
+    }
+
+    // The last matching rule takes precedence, same as github.
+    pub fn owners_for(&self, path: &str) -> Vec<String> {
+        match self.rules.iter().rev().find(|r| r.pattern.is_match(path)) {
+            Some(rule) => rule.owners.clone(),
+            None => vec![],
+        }
+    }
+
+    pub fn owners_for_paths(&self, paths: &Vec<String>) -> Owners {
+        let mut owners = Owners::new();
+        for path in paths {
+            for owner in self.owners_for(path) {
+                if !owner.starts_with("@") {
+                    // email owners can't be mapped to a github user
+                    continue;
+                }
+                let owner = owner[1..].to_string();
+                match owner.find('/') {
+                    Some(pos) => owners.teams.push(owner[pos + 1..].to_string()),
+                    None => owners.users.push(owner),
+                };
+            }
+        }
+
+        owners.users.sort();
+        owners.users.dedup();
+        owners.teams.sort();
+        owners.teams.dedup();
+        owners
+    }
+}
+
+fn pattern_to_regex(pattern: &str) -> String {
+    let mut pattern = pattern.to_string();
+
+    // a pattern with a slash at the beginning or middle is relative to the repo root.
+    let anchored = pattern.trim_end_matches('/').contains('/');
+    if pattern.starts_with("/") {
+        pattern.remove(0);
+    }
+    if pattern.ends_with("/") {
+        pattern.pop();
+    }
+
+    let prefix = if anchored { "^" } else { "^(.*/)?" };
+
+    // matching a directory matches everything beneath it
+    format!("{}{}(/.*)?$", prefix, util::glob_to_regex(&pattern))
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CodeOwnersRequest {
+    pub repo: github::Repo,
+    pub pull_request: github::PullRequest,
+}
+
+pub fn req(repo: &github::Repo, pull_request: &github::PullRequest) -> CodeOwnersRequest {
+    CodeOwnersRequest {
+        repo: repo.clone(),
+        pull_request: pull_request.clone(),
+    }
+}
+
+struct CacheEntry {
+    fetched: Instant,
+    codeowners: Option<Arc<CodeOwners>>,
+}
+
+struct Runner {
+    github_app: Arc<dyn GithubSessionFactory>,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+}
+
+pub fn new_runner(github_app: Arc<dyn GithubSessionFactory>) -> Arc<dyn worker::Runner<CodeOwnersRequest>> {
+    Arc::new(Runner {
+        github_app: github_app,
+        cache: Mutex::new(HashMap::new()),
+    })
+}
+
+pub fn fetch_codeowners(
+    github: &dyn Session,
+    owner: &str,
+    repo: &str,
+    branch: &str,
+) -> Option<CodeOwners> {
+    for path in CODEOWNERS_PATHS.iter() {
+        if let Ok(contents) = github.get_file_contents(owner, repo, path, branch) {
+            return Some(CodeOwners::parse(&contents));
+        }
+    }
+    None
+}
+
+pub fn request_reviews(
+    github: &dyn Session,
+    codeowners: &CodeOwners,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+) {
+    let files = match github.get_pull_request_files(&repo.owner.login(), &repo.name, pull_request.number) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Error looking up files for PR #{}: {}", pull_request.number, e);
+            return;
+        }
+    };
+
+    let paths = files.into_iter().map(|f| f.filename).collect::<Vec<_>>();
+    let mut owners = codeowners.owners_for_paths(&paths);
+    // github won't let authors review their own PRs
+    owners.users.retain(|u| u != pull_request.user.login());
+
+    if !owners.users.is_empty() {
+        if let Err(e) = github.request_review(&repo.owner.login(), &repo.name, pull_request.number, owners.users) {
+            error!("Error requesting CODEOWNERS reviewers: {}", e);
+        }
+    }
+    if !owners.teams.is_empty() {
+        if let Err(e) = github.request_team_review(&repo.owner.login(), &repo.name, pull_request.number, owners.teams) {
+            error!("Error requesting CODEOWNERS team reviewers: {}", e);
+        }
+    }
+}
+
+impl Runner {
+    fn lookup(&self, github: &dyn Session, repo: &github::Repo, branch: &str) -> Option<Arc<CodeOwners>> {
+        let key = format!("{}:{}", repo.full_name, branch);
+        let ttl = Duration::from_secs(CACHE_TTL_SECS);
+
+        if let Some(entry) = self.cache.lock().unwrap().get(&key) {
+            if entry.fetched.elapsed() < ttl {
+                return entry.codeowners.clone();
+            }
+        }
+
+        let codeowners = fetch_codeowners(github, &repo.owner.login(), &repo.name, branch).map(|c| Arc::new(c));
+        self.cache.lock().unwrap().insert(
+            key,
+            CacheEntry {
+                fetched: Instant::now(),
+                codeowners: codeowners.clone(),
+            },
+        );
+
+        codeowners
+    }
+}
+
+impl worker::Runner<CodeOwnersRequest> for Runner {
+    fn handle(&self, req: CodeOwnersRequest) {
+        let github = match self.github_app.new_session(&req.repo.owner.login(), &req.repo.name) {
+            Ok(g) => g,
+            Err(e) => {
+                error!("Error getting new session: {}", e);
+                return;
+            }
+        };
+
+        let codeowners = match self.lookup(&github, &req.repo, &req.pull_request.base.ref_name) {
+            Some(c) => c,
+            None => {
+                info!("No CODEOWNERS file found for {}", req.repo.full_name);
+                return;
+            }
+        };
+
+        request_reviews(&github, &codeowners, &req.repo, &req.pull_request);
+    }
+
+    fn repo(&self, req: &CodeOwnersRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &CodeOwnersRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: Vec<&str>) -> Vec<String> {
+        paths.into_iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_and_match() {
+        let codeowners = CodeOwners::parse(
+            r#"
+# default owners
+*       @the-org/everyone
+
+*.js    @js-owner # trailing comment
+/docs/  @docs-owner docs@company.com
+src/server/**  @server-owner @the-org/backend
+build   @build-owner
+"#,
+        );
+
+        assert_eq!(vec!["@the-org/everyone"], codeowners.owners_for("README.md"));
+        assert_eq!(vec!["@js-owner"], codeowners.owners_for("web/app.js"));
+        assert_eq!(vec!["@docs-owner", "docs@company.com"], codeowners.owners_for("docs/guide/intro.md"));
+        assert_eq!(vec!["@the-org/everyone"], codeowners.owners_for("other/docs/intro.md"));
+        assert_eq!(vec!["@server-owner", "@the-org/backend"], codeowners.owners_for("src/server/http/mod.rs"));
+        assert_eq!(vec!["@build-owner"], codeowners.owners_for("build/out.txt"));
+        assert_eq!(vec!["@build-owner"], codeowners.owners_for("nested/build/out.txt"));
+    }
+
+    #[test]
+    fn test_no_owners() {
+        let codeowners = CodeOwners::parse("/src/ @src-owner\n/src/generated/\n");
+
+        assert_eq!(vec!["@src-owner"], codeowners.owners_for("src/main.rs"));
+        assert_eq!(Vec::<String>::new(), codeowners.owners_for("src/generated/api.rs"));
+        assert_eq!(Vec::<String>::new(), codeowners.owners_for("README.md"));
+    }
+
+    #[test]
+    fn test_owners_for_paths() {
+        let codeowners = CodeOwners::parse(
+            r#"
+*.rs    @rust-owner @the-org/rust
+*.md    @docs-owner docs@company.com
+*.toml  @rust-owner
+"#,
+        );
+
+        let owners = codeowners.owners_for_paths(&paths(vec!["src/main.rs", "README.md", "Cargo.toml", "other.txt"]));
+
+        assert_eq!(vec!["docs-owner", "rust-owner"], owners.users);
+        assert_eq!(vec!["rust"], owners.teams);
+    }
+}
@@ -0,0 +1,180 @@
+use failure::format_err;
+use log::{error, info};
+
+use crate::config::{CommandPermission, Config};
+use crate::errors::*;
+use crate::github::api::{GithubSession, GithubSessionFactory};
+use crate::teams::TeamMembers;
+
+pub const COMMAND_ACTION: &str = "slack-command";
+
+const ANY_COMMAND: &str = "*";
+
+// Commands that change things for others: nobody may use them unless a permission allows it
+pub const DESTRUCTIVE_COMMANDS: &[&str] = &["merge", "revert", "freeze", "thaw", "freeze-exception"];
+
+pub fn is_destructive(command: &str) -> bool {
+    DESTRUCTIVE_COMMANDS.contains(&command)
+}
+
+fn lists(values: &Option<Vec<String>>, value: &str) -> bool {
+    values.as_ref().map(|v| v.iter().any(|x| x == value)).unwrap_or(false)
+}
+
+fn is_empty(values: &Option<Vec<String>>) -> bool {
+    values.as_ref().map(|v| v.is_empty()).unwrap_or(true)
+}
+
+// Whether `github_login` (None for unmapped slack users) may use `command` in `channel`. Commands no permission
+// mentions are allowed unless destructive; otherwise some permission for the command must allow it.
+pub fn is_allowed(
+    permissions: &Vec<CommandPermission>,
+    command: &str,
+    channel: &str,
+    github_login: Option<&str>,
+    is_team_member: &dyn Fn(&str, &str) -> Result<bool>,
+) -> Result<bool> {
+    let matching = permissions
+        .iter()
+        .filter(|p| p.command == command || p.command == ANY_COMMAND)
+        .collect::<Vec<_>>();
+    if matching.is_empty() {
+        return Ok(!is_destructive(command));
+    }
+
+    for p in matching {
+        if !is_empty(&p.channels) && !lists(&p.channels, channel) {
+            continue;
+        }
+        if is_empty(&p.users) && is_empty(&p.teams) {
+            return Ok(true);
+        }
+
+        let login = match github_login {
+            Some(l) => l,
+            None => continue,
+        };
+        if lists(&p.users, login) {
+            return Ok(true);
+        }
+        for team in p.teams.iter().flatten() {
+            if is_team_member(team, login)? {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+fn is_team_member(
+    config: &Config,
+    github_app: &dyn GithubSessionFactory,
+    team_members: &TeamMembers,
+    team: &str,
+    github_login: &str,
+) -> Result<bool> {
+    let mut parts = team.splitn(2, '/');
+    let (org, slug) = match (parts.next(), parts.next()) {
+        (Some(org), Some(slug)) => (org, slug),
+        _ => return Err(format_err!("Invalid team '{}': expected `org/team-slug`", team)),
+    };
+
+    let github = GithubSession::new(
+        &config.github_host(org),
+        &github_app.owner_bot_name(org),
+        &github_app.get_token_org(org)?,
+        None,
+    )?;
+    Ok(team_members.get(&github, org, slug)?.iter().any(|m| m.login() == github_login))
+}
+
+// Checks the configured permissions, recording denials and uses of destructive commands in the audit log
+pub fn authorize(
+    config: &Config,
+    github_app: &dyn GithubSessionFactory,
+    team_members: &TeamMembers,
+    command: &str,
+    channel: &str,
+    slack_user: &str,
+    github_login: Option<&str>,
+) -> Result<bool> {
+    let allowed = is_allowed(&config.command_permissions(), command, channel, github_login, &|team, login| {
+        is_team_member(config, github_app, team_members, team, login)
+    })?;
+
+    if !allowed || is_destructive(command) {
+        let actor = github_login.unwrap_or(slack_user);
+        let details = format!("{} in #{}", if allowed { "allowed" } else { "denied" }, channel);
+        info!("{} used '{}': {}", actor, command, details);
+        if let Err(e) = config.audit.record(actor, COMMAND_ACTION, command, &details) {
+            error!("Error auditing slack command: {}", e);
+        }
+    }
+
+    Ok(allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permission(command: &str, channels: Vec<&str>, users: Vec<&str>, teams: Vec<&str>) -> CommandPermission {
+        let list = |v: Vec<&str>| Some(v.into_iter().map(|s| s.to_string()).collect());
+        CommandPermission {
+            command: command.into(),
+            channels: list(channels),
+            users: list(users),
+            teams: list(teams),
+        }
+    }
+
+    fn no_teams(_team: &str, _login: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    #[test]
+    fn test_defaults() {
+        let permissions = vec![];
+        assert!(is_allowed(&permissions, "status", "general", None, &no_teams).unwrap());
+        assert!(is_allowed(&permissions, "subscribe", "general", Some("joe"), &no_teams).unwrap());
+        assert!(!is_allowed(&permissions, "merge", "general", Some("joe"), &no_teams).unwrap());
+        assert!(!is_allowed(&permissions, "freeze", "releases", Some("joe"), &no_teams).unwrap());
+    }
+
+    #[test]
+    fn test_channels_and_users() {
+        let permissions = vec![
+            permission("merge", vec!["releases"], vec![], vec![]),
+            permission("freeze", vec![], vec!["the-release-manager"], vec![]),
+            permission("subscribe", vec!["team-channel"], vec!["joe"], vec![]),
+        ];
+
+        assert!(is_allowed(&permissions, "merge", "releases", Some("joe"), &no_teams).unwrap());
+        assert!(is_allowed(&permissions, "merge", "releases", None, &no_teams).unwrap());
+        assert!(!is_allowed(&permissions, "merge", "general", Some("joe"), &no_teams).unwrap());
+
+        assert!(is_allowed(&permissions, "freeze", "general", Some("the-release-manager"), &no_teams).unwrap());
+        assert!(!is_allowed(&permissions, "freeze", "general", Some("joe"), &no_teams).unwrap());
+        assert!(!is_allowed(&permissions, "freeze", "general", None, &no_teams).unwrap());
+
+        assert!(is_allowed(&permissions, "subscribe", "team-channel", Some("joe"), &no_teams).unwrap());
+        assert!(!is_allowed(&permissions, "subscribe", "team-channel", Some("mary"), &no_teams).unwrap());
+        assert!(!is_allowed(&permissions, "subscribe", "general", Some("joe"), &no_teams).unwrap());
+
+        // not mentioned, not destructive
+        assert!(is_allowed(&permissions, "status", "general", None, &no_teams).unwrap());
+    }
+
+    #[test]
+    fn test_teams_and_wildcard() {
+        let permissions = vec![permission("*", vec!["ops"], vec![], vec!["some-org/release-managers"])];
+        let is_member =
+            |team: &str, login: &str| -> Result<bool> { Ok(team == "some-org/release-managers" && login == "mary") };
+
+        assert!(is_allowed(&permissions, "thaw", "ops", Some("mary"), &is_member).unwrap());
+        assert!(is_allowed(&permissions, "status", "ops", Some("mary"), &is_member).unwrap());
+        assert!(!is_allowed(&permissions, "thaw", "ops", Some("joe"), &is_member).unwrap());
+        assert!(!is_allowed(&permissions, "status", "general", Some("mary"), &is_member).unwrap());
+    }
+}
@@ -0,0 +1,320 @@
+use failure::format_err;
+use serde_derive::Serialize;
+
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::util;
+
+// Shorter prefixes would match too many commits to be useful
+const MIN_SHA_LEN: usize = 7;
+
+// Where a commit on a main or release branch came from, and the first release it went out in
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CommitRecord {
+    pub repo: String,
+    pub sha: String,
+    pub branch: String,
+    // the PR that merged it. None for commits pushed to the branch directly.
+    pub pr_number: Option<u32>,
+    pub pr_title: String,
+    pub pr_url: String,
+    // JIRA keys referenced by the commit or its PR
+    pub tickets: Vec<String>,
+    // the version of the first release with it, e.g. "1.2.0". empty until it is released.
+    pub version: String,
+    // the commit it was cherry-picked from (e.g. by a backport), if any
+    pub picked_from: String,
+    pub recorded_at: i64,
+}
+
+impl CommitRecord {
+    pub fn new(repo: &str, sha: &str, branch: &str) -> CommitRecord {
+        CommitRecord {
+            repo: repo.into(),
+            sha: sha.into(),
+            branch: branch.into(),
+            pr_number: None,
+            pr_title: String::new(),
+            pr_url: String::new(),
+            tickets: vec![],
+            version: String::new(),
+            picked_from: String::new(),
+            recorded_at: db::now(),
+        }
+    }
+
+    // What an earlier record of the commit already knew fills in what this one doesn't
+    fn merge(mut self, previous: CommitRecord) -> CommitRecord {
+        if self.pr_number.is_none() {
+            self.pr_number = previous.pr_number;
+            self.pr_title = previous.pr_title;
+            self.pr_url = previous.pr_url;
+        }
+        for ticket in previous.tickets {
+            if !self.tickets.contains(&ticket) {
+                self.tickets.push(ticket);
+            }
+        }
+        // the first release to have it is the one that matters
+        if !previous.version.is_empty() {
+            self.version = previous.version;
+        }
+        if self.picked_from.is_empty() {
+            self.picked_from = previous.picked_from;
+        }
+        self.branch = previous.branch;
+        self.recorded_at = previous.recorded_at;
+        self
+    }
+}
+
+// Maps the commits of main and release branches back to their PRs, tickets and versions
+#[derive(Clone)]
+pub struct CommitIndex {
+    db: Database,
+}
+
+impl CommitIndex {
+    pub fn new(db: Database) -> CommitIndex {
+        CommitIndex { db: db }
+    }
+
+    pub fn record(&self, commit: &CommitRecord) -> Result<()> {
+        let commit = match self.get(&commit.repo, &commit.sha)? {
+            Some(previous) => commit.clone().merge(previous),
+            None => commit.clone(),
+        };
+
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO commit_index
+               (repo, sha, branch, pr_number, pr_title, pr_url, tickets, version, picked_from, recorded_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+               ON CONFLICT (repo, sha) DO UPDATE SET branch = excluded.branch, pr_number = excluded.pr_number,
+                   pr_title = excluded.pr_title, pr_url = excluded.pr_url, tickets = excluded.tickets,
+                   version = excluded.version, picked_from = excluded.picked_from,
+                   recorded_at = excluded.recorded_at"#,
+            &[
+                &commit.repo as &dyn ToSql,
+                &commit.sha,
+                &commit.branch,
+                &commit.pr_number,
+                &commit.pr_title,
+                &commit.pr_url,
+                &db::from_string_vec(&commit.tickets),
+                &commit.version,
+                &commit.picked_from,
+                &commit.recorded_at,
+            ],
+        )
+        .map_err(|e| format_err!("Error indexing commit {} of {}: {}", commit.sha, commit.repo, e))?;
+
+        Ok(())
+    }
+
+    // Marks the commits that weren't in an earlier release as released in |version|, returning how many were
+    pub fn set_version(&self, repo: &str, shas: &[String], version: &str) -> Result<usize> {
+        let conn = self.db.connect()?;
+        let mut updated = 0;
+        for sha in shas {
+            updated += conn
+                .execute(
+                    "UPDATE commit_index SET version = ?1 WHERE repo = ?2 AND sha = ?3 AND version = ''",
+                    &[&version as &dyn ToSql, &repo, sha],
+                )
+                .map_err(|e| format_err!("Error setting the version of commit {} of {}: {}", sha, repo, e))?;
+        }
+        Ok(updated)
+    }
+
+    pub fn get(&self, repo: &str, sha: &str) -> Result<Option<CommitRecord>> {
+        let commits = self.query("WHERE repo = :repo AND sha = :sha", &[(":repo", &repo), (":sha", &sha)])?;
+        Ok(commits.into_iter().next())
+    }
+
+    // The commits a full or abbreviated sha refers to, in any repo
+    pub fn find(&self, sha: &str) -> Result<Vec<CommitRecord>> {
+        if !is_sha(sha) {
+            return Err(format_err!("Invalid commit '{}': expected at least {} hex characters", sha, MIN_SHA_LEN));
+        }
+        let pattern = format!("{}%", sha.to_lowercase());
+        self.query("WHERE sha LIKE :pattern ORDER BY repo, sha", &[(":pattern", &pattern)])
+    }
+
+    fn query(&self, filter: &str, params: &[(&str, &dyn ToSql)]) -> Result<Vec<CommitRecord>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(&format!("SELECT * FROM commit_index {}", filter))?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(params)?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(CommitRecord {
+                repo: cols.get(row, "repo")?,
+                sha: cols.get(row, "sha")?,
+                branch: cols.get(row, "branch")?,
+                pr_number: cols.get(row, "pr_number")?,
+                pr_title: cols.get(row, "pr_title")?,
+                pr_url: cols.get(row, "pr_url")?,
+                tickets: db::to_string_vec(cols.get(row, "tickets")?),
+                version: cols.get(row, "version")?,
+                picked_from: cols.get(row, "picked_from")?,
+                recorded_at: cols.get(row, "recorded_at")?,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+pub fn is_sha(value: &str) -> bool {
+    value.len() >= MIN_SHA_LEN && value.len() <= 40 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// The commit a `git cherry-pick -x` commit was picked from
+pub fn cherry_picked_from(message: &str) -> Option<String> {
+    message
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("(cherry picked from commit "))
+        .filter_map(|l| l.strip_suffix(')'))
+        .filter(|sha| is_sha(sha))
+        .last()
+        .map(|sha| sha.to_string())
+}
+
+// Answers "which release contains this fix?" for slack
+pub fn where_is_message(sha: &str, commits: &[CommitRecord]) -> String {
+    if commits.is_empty() {
+        return format!(
+            "Commit `{}` isn't in the index: only commits merged to main and release branches since it was \
+             enabled are",
+            sha
+        );
+    }
+
+    let mut msg = String::new();
+    for commit in commits {
+        if !msg.is_empty() {
+            msg += "\n";
+        }
+        msg += &format!("`{}` in {} (`{}`)", &commit.sha[..MIN_SHA_LEN], commit.repo, commit.branch);
+        match commit.pr_number {
+            Some(number) => {
+                let title = format!("#{}: {}", number, commit.pr_title);
+                msg += &format!("\n• PR {}", util::make_link(&commit.pr_url, &title))
+            }
+            None => msg += "\n• pushed without a PR",
+        };
+        if !commit.picked_from.is_empty() {
+            msg += &format!(", cherry-picked from `{}`", &commit.picked_from[..MIN_SHA_LEN]);
+        }
+        if !commit.tickets.is_empty() {
+            msg += &format!("\n• Tickets: {}", commit.tickets.join(", "));
+        }
+        if commit.version.is_empty() {
+            msg += "\n• Not released yet";
+        } else {
+            msg += &format!("\n• First released in {}", commit.version);
+        }
+    }
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (CommitIndex, TempDir) {
+        let temp_dir = TempDir::new("commit_index.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (CommitIndex::new(db), temp_dir)
+    }
+
+    fn merged(sha: &str, branch: &str) -> CommitRecord {
+        let mut commit = CommitRecord::new("some-org/some-repo", sha, branch);
+        commit.pr_number = Some(12);
+        commit.pr_title = "Fix the thing".into();
+        commit.pr_url = "http://the-pr".into();
+        commit.tickets = vec!["SER-1".into()];
+        commit
+    }
+
+    #[test]
+    fn test_record_and_find() {
+        let (index, _temp) = new_test();
+
+        // the push usually arrives before the PR is closed
+        let mut pushed = CommitRecord::new("some-org/some-repo", "abcdef0123456789", "master");
+        pushed.tickets = vec!["SER-2".into()];
+        index.record(&pushed).unwrap();
+        index.record(&merged("abcdef0123456789", "master")).unwrap();
+        index.record(&CommitRecord::new("some-org/other-repo", "abcdef0999999999", "master")).unwrap();
+
+        let commit = index.get("some-org/some-repo", "abcdef0123456789").unwrap().unwrap();
+        assert_eq!(Some(12), commit.pr_number);
+        assert_eq!(vec!["SER-1".to_string(), "SER-2".to_string()], commit.tickets);
+        assert_eq!(pushed.recorded_at, commit.recorded_at);
+
+        let found = index.find("ABCDEF0").unwrap();
+        let repos = found.iter().map(|c| c.repo.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["some-org/other-repo", "some-org/some-repo"], repos);
+        assert_eq!(1, index.find("abcdef01").unwrap().len());
+        assert!(index.find("1234567").unwrap().is_empty());
+        assert!(index.find("abc").is_err());
+        assert!(index.find("abcdefg").is_err());
+    }
+
+    #[test]
+    fn test_set_version() {
+        let (index, _temp) = new_test();
+        index.record(&merged("1111111111", "master")).unwrap();
+        index.record(&merged("2222222222", "master")).unwrap();
+
+        let shas = vec!["1111111111".to_string(), "3333333333".to_string()];
+        assert_eq!(1, index.set_version("some-org/some-repo", &shas, "1.2.0").unwrap());
+
+        // later releases don't change it, and neither does indexing it again
+        let shas = vec!["1111111111".to_string(), "2222222222".to_string()];
+        assert_eq!(1, index.set_version("some-org/some-repo", &shas, "1.3.0").unwrap());
+        index.record(&merged("1111111111", "release/1.4")).unwrap();
+
+        let commit = index.get("some-org/some-repo", "1111111111").unwrap().unwrap();
+        assert_eq!("1.2.0", commit.version);
+        assert_eq!("master", commit.branch);
+        assert_eq!("1.3.0", index.get("some-org/some-repo", "2222222222").unwrap().unwrap().version);
+    }
+
+    #[test]
+    fn test_cherry_picked_from() {
+        assert_eq!(
+            Some("abcdef0123".to_string()),
+            cherry_picked_from("Fix the thing\n\n(cherry picked from commit abcdef0123)")
+        );
+        assert_eq!(None, cherry_picked_from("Fix the thing"));
+        assert_eq!(None, cherry_picked_from("(cherry picked from commit nope)"));
+    }
+
+    #[test]
+    fn test_where_is_message() {
+        let mut commit = merged("abcdef0123456789", "release/1.2");
+        commit.version = "1.2.0".into();
+        commit.picked_from = "9876543210fedcba".into();
+        assert_eq!(
+            "`abcdef0` in some-org/some-repo (`release/1.2`)\n\
+             • PR <http://the-pr|#12: Fix the thing>, cherry-picked from `9876543`\n\
+             • Tickets: SER-1\n\
+             • First released in 1.2.0",
+            where_is_message("abcdef0", &[commit])
+        );
+
+        let pushed = CommitRecord::new("some-org/some-repo", "1234567890", "master");
+        assert_eq!(
+            "`1234567` in some-org/some-repo (`master`)\n• pushed without a PR\n• Not released yet",
+            where_is_message("1234567", &[pushed])
+        );
+        assert!(where_is_message("1234567", &[]).contains("isn't in the index"));
+    }
+}
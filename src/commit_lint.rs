@@ -0,0 +1,202 @@
+use conventional::Commit as ConventionalCommit;
+use failure::format_err;
+use regex::Regex;
+
+use crate::errors::*;
+use crate::github;
+use crate::github::api::Session;
+
+const LINT_CONTEXT: &'static str = "commit-lint";
+
+// Marks the bot's lint comment so it can be found and updated on later pushes.
+pub const LINT_COMMENT_MARKER: &'static str = "<!-- octobot:lint -->";
+
+pub struct LintRules {
+    pub conventional: bool,
+    pub title_regex: Option<Regex>,
+    pub commit_regex: Option<Regex>,
+    pub check_run: bool,
+}
+
+impl LintRules {
+    // Returns None if no rules are enabled.
+    pub fn new(conventional: bool, title_regex: &str, commit_regex: &str, check_run: bool) -> Result<Option<LintRules>> {
+        let parse = |value: &str| -> Result<Option<Regex>> {
+            if value.trim().is_empty() {
+                Ok(None)
+            } else {
+                Regex::new(value.trim())
+                    .map(Some)
+                    .map_err(|e| format_err!("Invalid lint regex '{}': {}", value, e))
+            }
+        };
+
+        let title_regex = parse(title_regex)?;
+        let commit_regex = parse(commit_regex)?;
+
+        if !conventional && title_regex.is_none() && commit_regex.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(LintRules {
+            conventional: conventional,
+            title_regex: title_regex,
+            commit_regex: commit_regex,
+            check_run: check_run,
+        }))
+    }
+}
+
+fn check_message(message: &str, regex: &Option<Regex>, conventional: bool) -> Option<String> {
+    if conventional && ConventionalCommit::new(message).is_err() {
+        return Some("is not a conventional commit (e.g. \"fix(parser): handle empty input\")".into());
+    }
+    if let Some(ref regex) = *regex {
+        if !regex.is_match(message) {
+            return Some(format!("does not match `{}`", regex.as_str()));
+        }
+    }
+    None
+}
+
+// Lists the problems with the PR title and the first line of each (non-merge) commit message.
+pub fn violations(rules: &LintRules, title: &str, commits: &Vec<github::Commit>) -> Vec<String> {
+    let mut result = vec![];
+
+    if let Some(problem) = check_message(title, &rules.title_regex, rules.conventional) {
+        result.push(format!("PR title \"{}\" {}", title, problem));
+    }
+
+    for commit in commits {
+        let first_line = github::Commit::title(&commit);
+        if first_line.starts_with("Merge ") {
+            continue;
+        }
+        if let Some(problem) = check_message(&first_line, &rules.commit_regex, rules.conventional) {
+            result.push(format!(
+                "Commit {} \"{}\" {}",
+                github::Commit::short_hash(&commit),
+                first_line,
+                problem
+            ));
+        }
+    }
+
+    result
+}
+
+pub fn lint_comment(violations: &Vec<String>) -> String {
+    if violations.is_empty() {
+        format!("{}\n:white_check_mark: All lint issues have been resolved.", LINT_COMMENT_MARKER)
+    } else {
+        let list = violations.iter().map(|v| format!("* {}", v)).collect::<Vec<_>>().join("\n");
+        format!("{}\n:warning: Please fix the following before merging:\n\n{}", LINT_COMMENT_MARKER, list)
+    }
+}
+
+// Validates the PR title and commits, keeping a single bot comment up to date with any violations.
+// A comment is only posted once there is something to report; after that it is edited in place.
+pub fn lint_pull_request(
+    github: &dyn Session,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    rules: &LintRules,
+) -> Result<()> {
+    let owner = repo.owner.login();
+    let commits = github.get_pull_request_commits(owner, &repo.name, pull_request.number)?;
+    let violations = violations(rules, &pull_request.title, &commits);
+    let comment = lint_comment(&violations);
+
+    let existing = github
+        .get_pull_request_comments(owner, &repo.name, pull_request.number)?
+        .into_iter()
+        .find(|c| c.body().starts_with(LINT_COMMENT_MARKER));
+
+    match existing {
+        Some(existing) => {
+            if existing.body() != comment {
+                github.edit_comment(owner, &repo.name, existing.id, &comment)?;
+            }
+        }
+        None => {
+            if !violations.is_empty() {
+                github.comment_pull_request(owner, &repo.name, pull_request.number, &comment)?;
+            }
+        }
+    };
+
+    if rules.check_run {
+        let mut run = github::CheckRun::new(LINT_CONTEXT, pull_request, None);
+        if violations.is_empty() {
+            run = run.completed(github::Conclusion::Success);
+        } else {
+            run = run.completed(github::Conclusion::Failure);
+            let title = format!("{} lint violation(s)", violations.len());
+            run.output = Some(github::CheckOutput::new(&title, &violations.join("\n")));
+        }
+        github.create_check_run(pull_request, &run)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(sha: &str, message: &str) -> github::Commit {
+        let mut commit = github::Commit::new();
+        commit.sha = sha.into();
+        commit.commit.message = message.into();
+        commit
+    }
+
+    #[test]
+    fn test_lint_rules_new() {
+        assert!(LintRules::new(false, "", " ", true).unwrap().is_none());
+        assert!(LintRules::new(true, "", "", false).unwrap().is_some());
+        assert!(LintRules::new(false, "^[A-Z]", "", false).unwrap().is_some());
+        assert!(LintRules::new(false, "", "(unclosed", false).is_err());
+    }
+
+    #[test]
+    fn test_violations_conventional() {
+        let rules = LintRules::new(true, "", "", false).unwrap().unwrap();
+        let commits = vec![
+            commit("1111111111", "fix: handle empty input\n\nmore details"),
+            commit("2222222222", "handle empty input"),
+            commit("3333333333", "Merge branch 'master' into feature"),
+        ];
+
+        assert_eq!(
+            vec![
+                "PR title \"Fix stuff\" is not a conventional commit (e.g. \"fix(parser): handle empty input\")",
+                "Commit 2222222 \"handle empty input\" is not a conventional commit (e.g. \"fix(parser): handle empty input\")",
+            ],
+            violations(&rules, "Fix stuff", &commits)
+        );
+        assert_eq!(Vec::<String>::new(), violations(&rules, "feat(ui): new button", &commits[0..1].to_vec()));
+    }
+
+    #[test]
+    fn test_violations_regex() {
+        let rules = LintRules::new(false, r"^\[[A-Z]+-\d+\]", r"^[A-Z]", false).unwrap().unwrap();
+        let commits = vec![commit("1111111111", "Add thing"), commit("2222222222", "add thing")];
+
+        assert_eq!(
+            vec![
+                r#"PR title "Add thing" does not match `^\[[A-Z]+-\d+\]`"#,
+                r#"Commit 2222222 "add thing" does not match `^[A-Z]`"#,
+            ],
+            violations(&rules, "Add thing", &commits)
+        );
+        assert_eq!(1, violations(&rules, "[SER-1] Add thing", &commits).len());
+    }
+
+    #[test]
+    fn test_lint_comment() {
+        assert!(lint_comment(&vec![]).starts_with(LINT_COMMENT_MARKER));
+        assert!(lint_comment(&vec![]).contains("resolved"));
+        assert!(lint_comment(&vec!["bad title".into()]).ends_with("\n\n* bad title"));
+    }
+}
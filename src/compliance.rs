@@ -0,0 +1,598 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::info;
+use serde_derive::Serialize;
+
+use crate::audit::AuditEntry;
+use crate::config::Config;
+use crate::db::{self, Database, Row, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::provenance;
+use crate::scheduler;
+use crate::slack::{self, SlackAttachmentBuilder, SlackRequest};
+use crate::stale_prs;
+use crate::util;
+use crate::worker::Worker;
+
+pub const WEEKLY: &str = "weekly";
+pub const MONTHLY: &str = "monthly";
+
+pub const OVERRIDE_NO_APPROVAL: &str = "merged without approval";
+pub const OVERRIDE_CHANGES_REQUESTED: &str = "merged with changes requested";
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+// A merge to a protected branch, as recorded when it happened
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MergeRecord {
+    pub repo: String,
+    pub number: u32,
+    pub title: String,
+    pub html_url: String,
+    pub base_branch: String,
+    pub author: String,
+    pub merged_by: String,
+    pub merged_at: i64,
+    pub approvers: Vec<String>,
+    // JIRA keys referenced by the PR's commits
+    pub tickets: Vec<String>,
+    // why the merge bypassed normal review, e.g. OVERRIDE_NO_APPROVAL. empty for normal merges.
+    pub overrides: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct MergeLog {
+    db: Database,
+}
+
+impl MergeLog {
+    pub fn new(db: Database) -> MergeLog {
+        MergeLog { db: db }
+    }
+
+    pub fn record(&self, merge: &MergeRecord) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO merge_records
+               (repo, number, title, html_url, base_branch, author, merged_by, merged_at, approvers, tickets, overrides)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+               ON CONFLICT (repo, number) DO UPDATE SET title = excluded.title, html_url = excluded.html_url,
+                   base_branch = excluded.base_branch, author = excluded.author, merged_by = excluded.merged_by,
+                   merged_at = excluded.merged_at, approvers = excluded.approvers, tickets = excluded.tickets,
+                   overrides = excluded.overrides"#,
+            &[
+                &merge.repo as &dyn ToSql,
+                &merge.number,
+                &merge.title,
+                &merge.html_url,
+                &merge.base_branch,
+                &merge.author,
+                &merge.merged_by,
+                &merge.merged_at,
+                &merge.approvers.join(","),
+                &merge.tickets.join(","),
+                &merge.overrides.join(","),
+            ],
+        )
+        .map_err(|e| format_err!("Error recording merge of {}#{}: {}", merge.repo, merge.number, e))?;
+
+        Ok(())
+    }
+
+    // Merges in [start, end), oldest first
+    pub fn get_between(&self, start: i64, end: i64) -> Result<Vec<MergeRecord>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM merge_records WHERE merged_at >= :start AND merged_at < :end ORDER BY merged_at, repo, number",
+        )?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":start", &start), (":end", &end)])?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(self.map_row(row, &cols)?);
+        }
+
+        Ok(result)
+    }
+
+    pub fn get(&self, repo: &str, number: u32) -> Result<Option<MergeRecord>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare("SELECT * FROM merge_records WHERE repo = :repo AND number = :number")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":repo", &repo), (":number", &number)])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(self.map_row(row, &cols)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn map_row(&self, row: &Row, cols: &db::Columns) -> Result<MergeRecord> {
+        let approvers: String = cols.get(row, "approvers")?;
+        let tickets: String = cols.get(row, "tickets")?;
+        let overrides: String = cols.get(row, "overrides")?;
+        Ok(MergeRecord {
+            repo: cols.get(row, "repo")?,
+            number: cols.get(row, "number")?,
+            title: cols.get(row, "title")?,
+            html_url: cols.get(row, "html_url")?,
+            base_branch: cols.get(row, "base_branch")?,
+            author: cols.get(row, "author")?,
+            merged_by: cols.get(row, "merged_by")?,
+            merged_at: cols.get(row, "merged_at")?,
+            approvers: split_list(&approvers),
+            tickets: split_list(&tickets),
+            overrides: split_list(&overrides),
+        })
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()
+}
+
+// Main branches and release branches are the ones change management applies to
+pub fn is_protected_branch(branch: &str, release_branch_prefix: &str) -> bool {
+    github::is_main_branch(branch) || (!release_branch_prefix.is_empty() && branch.starts_with(release_branch_prefix))
+}
+
+pub fn overrides(reviews: &[github::Review]) -> Vec<String> {
+    let mut result = vec![];
+    if provenance::approvals(reviews).is_empty() {
+        result.push(OVERRIDE_NO_APPROVAL.to_string());
+    }
+
+    // latest decision by each reviewer
+    let mut decisions: Vec<(&str, &str)> = vec![];
+    for review in reviews.iter().filter(|r| r.state != "COMMENTED" && r.state != "PENDING") {
+        decisions.retain(|d| d.0 != review.user.login());
+        decisions.push((review.user.login(), review.state.as_str()));
+    }
+    if decisions.iter().any(|d| d.1 == "CHANGES_REQUESTED") {
+        result.push(OVERRIDE_CHANGES_REQUESTED.to_string());
+    }
+
+    result
+}
+
+pub fn merge_record(
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    merged_by: &github::User,
+    reviews: &[github::Review],
+    tickets: Vec<String>,
+    merged_at: i64,
+) -> MergeRecord {
+    MergeRecord {
+        repo: repo.full_name.clone(),
+        number: pull_request.number,
+        title: pull_request.title.clone(),
+        html_url: pull_request.html_url.clone(),
+        base_branch: pull_request.base.ref_name.clone(),
+        author: pull_request.user.login().to_string(),
+        merged_by: merged_by.login().to_string(),
+        merged_at: merged_at,
+        approvers: provenance::approvals(reviews).into_iter().map(|a| a.user).collect(),
+        tickets: tickets,
+        overrides: overrides(reviews),
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ComplianceReport {
+    pub start: i64,
+    pub end: i64,
+    pub merges: Vec<MergeRecord>,
+    pub admin_actions: Vec<AuditEntry>,
+}
+
+impl ComplianceReport {
+    pub fn generate(config: &Config, start: i64, end: i64) -> Result<ComplianceReport> {
+        Ok(ComplianceReport {
+            start: start,
+            end: end,
+            merges: config.merges.get_between(start, end)?,
+            admin_actions: config.audit.get_between(start, end)?,
+        })
+    }
+
+    pub fn overridden(&self) -> Vec<&MergeRecord> {
+        self.merges.iter().filter(|m| !m.overrides.is_empty()).collect()
+    }
+
+    pub fn period(&self) -> String {
+        // end is exclusive
+        format!("{} to {}", format_date(self.start), format_date(self.end - 1))
+    }
+
+    pub fn file_name(&self, extension: &str) -> String {
+        format!("compliance-{}-{}.{}", format_date(self.start), format_date(self.end - 1), extension)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        csv += "merged_at,repo,pull_request,title,url,base_branch,author,merged_by,approvers,tickets,overrides\n";
+        for m in &self.merges {
+            let fields = vec![
+                util::format_timestamp(m.merged_at),
+                m.repo.clone(),
+                m.number.to_string(),
+                m.title.clone(),
+                m.html_url.clone(),
+                m.base_branch.clone(),
+                m.author.clone(),
+                m.merged_by.clone(),
+                m.approvers.join(" "),
+                m.tickets.join(" "),
+                m.overrides.join("; "),
+            ];
+            csv += &fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(",");
+            csv += "\n";
+        }
+        csv
+    }
+
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Change management report: {}", self.period()),
+            format!(
+                "{} merges to protected branches, {} with overrides",
+                self.merges.len(),
+                self.overridden().len()
+            ),
+            String::new(),
+            "Merges".to_string(),
+        ];
+        if self.merges.is_empty() {
+            lines.push("  (none)".into());
+        }
+        for m in &self.merges {
+            lines.push(format!(
+                "  {}  {}#{} -> {}  \"{}\"",
+                util::format_timestamp(m.merged_at),
+                m.repo,
+                m.number,
+                m.base_branch,
+                m.title
+            ));
+            lines.push(format!(
+                "      author: {}  merged by: {}  approvers: {}  tickets: {}",
+                m.author,
+                m.merged_by,
+                none_if_empty(&m.approvers.join(", ")),
+                none_if_empty(&m.tickets.join(", "))
+            ));
+            if !m.overrides.is_empty() {
+                lines.push(format!("      OVERRIDES: {}", m.overrides.join("; ")));
+            }
+        }
+
+        lines.push(String::new());
+        lines.push("Admin actions".to_string());
+        if self.admin_actions.is_empty() {
+            lines.push("  (none)".into());
+        }
+        for a in &self.admin_actions {
+            lines.push(format!(
+                "  {}  {} {} {} {}",
+                util::format_timestamp(a.created_at),
+                a.actor,
+                a.action,
+                a.target,
+                a.details
+            ));
+        }
+        lines
+    }
+
+    pub fn to_pdf(&self) -> Vec<u8> {
+        pdf(&self.to_lines())
+    }
+}
+
+fn none_if_empty(value: &str) -> &str {
+    if value.is_empty() {
+        "none"
+    } else {
+        value
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace("\"", "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn format_date(value: i64) -> String {
+    let tm = time::at_utc(time::Timespec::new(value, 0));
+    time::strftime("%Y-%m-%d", &tm).unwrap_or(String::new())
+}
+
+// "YYYY-MM-DD" (UTC) to seconds since the epoch at the start of that day
+pub fn parse_date(value: &str) -> Option<i64> {
+    time::strptime(value.trim(), "%Y-%m-%d").ok().map(|t| t.to_timespec().sec)
+}
+
+fn start_of_day(now: i64) -> i64 {
+    now - now.rem_euclid(SECS_PER_DAY)
+}
+
+// The period a scheduled report run at `now` covers: the previous week, or the previous month
+pub fn report_period(frequency: &str, now: i64) -> (i64, i64) {
+    let end = start_of_day(now);
+    if frequency == MONTHLY {
+        let mut tm = time::at_utc(time::Timespec::new(end - SECS_PER_DAY, 0));
+        tm.tm_mday = 1;
+        (tm.to_timespec().sec, end)
+    } else {
+        (end - 7 * SECS_PER_DAY, end)
+    }
+}
+
+// Weekly reports go out on the digest day, monthly ones on the 1st
+pub fn is_due(frequency: &str, weekly_day: &str, now: i64) -> bool {
+    match frequency {
+        WEEKLY => stale_prs::is_quiet_day(weekly_day, now),
+        MONTHLY => time::at_utc(time::Timespec::new(now, 0)).tm_mday == 1,
+        _ => false,
+    }
+}
+
+// PDF page layout: US letter, landscape, in points
+const PAGE_WIDTH: usize = 792;
+const PAGE_HEIGHT: usize = 612;
+const MARGIN: usize = 36;
+const FONT_SIZE: usize = 8;
+const LINE_HEIGHT: usize = 10;
+const MAX_LINE_CHARS: usize = 150;
+
+fn pdf_escape(line: &str) -> String {
+    let mut escaped = String::new();
+    for c in line.chars().take(MAX_LINE_CHARS) {
+        match c {
+            '(' | ')' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            // the standard fonts only cover ASCII reliably
+            c if c.is_ascii() && !c.is_ascii_control() => escaped.push(c),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+// A minimal PDF of monospaced text lines, paginated
+pub fn pdf(lines: &[String]) -> Vec<u8> {
+    let lines_per_page = (PAGE_HEIGHT - 2 * MARGIN) / LINE_HEIGHT;
+    let pages: Vec<&[String]> = if lines.is_empty() {
+        vec![lines]
+    } else {
+        lines.chunks(lines_per_page).collect()
+    };
+
+    // objects: 1 catalog, 2 page tree, 3 font, then a page and its content stream for each page
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len()).map(|i| format!("{} 0 R", 4 + 2 * i)).collect::<Vec<_>>().join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>".to_string(),
+    ];
+    for (i, page) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH,
+            PAGE_HEIGHT,
+            5 + 2 * i
+        ));
+
+        let mut stream = format!(
+            "BT\n/F1 {} Tf\n{} TL\n{} {} Td\n",
+            FONT_SIZE,
+            LINE_HEIGHT,
+            MARGIN,
+            PAGE_HEIGHT - MARGIN - FONT_SIZE
+        );
+        for line in page.iter() {
+            stream += &format!("({}) Tj T*\n", pdf_escape(line));
+        }
+        stream += "ET";
+        objects.push(format!("<< /Length {} >>\nstream\n{}\nendstream", stream.len(), stream));
+    }
+
+    let mut out = String::from("%PDF-1.4\n");
+    let mut offsets = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out += &format!("{} 0 obj\n{}\nendobj\n", i + 1, object);
+    }
+
+    let xref_offset = out.len();
+    out += &format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        out += &format!("{:010} 00000 n \n", offset);
+    }
+    out += &format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref_offset
+    );
+
+    out.into_bytes()
+}
+
+// Posts a summary of each week's or month's report, with what was merged with overrides
+pub struct ComplianceReporter {
+    config: Arc<Config>,
+    slack: Arc<dyn Worker<SlackRequest>>,
+}
+
+impl ComplianceReporter {
+    pub fn new(config: Arc<Config>, slack: Arc<dyn Worker<SlackRequest>>) -> Arc<dyn scheduler::Task> {
+        Arc::new(ComplianceReporter {
+            config: config,
+            slack: slack,
+        })
+    }
+}
+
+pub fn summary(report: &ComplianceReport) -> (String, Vec<slack::SlackAttachment>) {
+    let msg = format!(
+        "Change management report for {}: {} merges to protected branches, {} with overrides. \
+         Download it from /api/compliance-report?start={}&end={}&format=csv (or format=pdf)",
+        report.period(),
+        report.merges.len(),
+        report.overridden().len(),
+        format_date(report.start),
+        format_date(report.end - 1)
+    );
+
+    let attachments = report
+        .overridden()
+        .iter()
+        .map(|m| {
+            SlackAttachmentBuilder::new(&format!("Merged by {}: {}", m.merged_by, m.overrides.join("; ")))
+                .title(format!("{}#{}: \"{}\"", m.repo, m.number, m.title))
+                .title_link(m.html_url.clone())
+                .color("warning")
+                .build()
+        })
+        .collect();
+
+    (msg, attachments)
+}
+
+impl scheduler::Task for ComplianceReporter {
+    fn run(&self, now: i64) -> Result<()> {
+        let (channel, frequency) = match (self.config.compliance_channel(), self.config.compliance_frequency()) {
+            (Some(c), Some(f)) => (c, f),
+            _ => return Ok(()),
+        };
+        if !is_due(&frequency, &self.config.digest_weekday(), now) {
+            return Ok(());
+        }
+
+        let (start, end) = report_period(&frequency, now);
+        let report = ComplianceReport::generate(&self.config, start, end)?;
+        info!("Sending compliance report for {}", report.period());
+
+        let (msg, attachments) = summary(&report);
+        self.slack.send(slack::req(&channel, &msg, attachments));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2019-05-01T12:00:00Z (a Wednesday)
+    const NOW: i64 = 1556712000;
+
+    fn review(state: &str, user: &str) -> github::Review {
+        let mut review = github::Review::new("", github::User::new(user));
+        review.state = state.into();
+        review
+    }
+
+    fn merge(number: u32, title: &str, overrides: Vec<&str>) -> MergeRecord {
+        MergeRecord {
+            repo: "some-user/some-repo".into(),
+            number: number,
+            title: title.into(),
+            html_url: format!("http://the-github-host/some-user/some-repo/pull/{}", number),
+            base_branch: "master".into(),
+            author: "joe".into(),
+            merged_by: "joe".into(),
+            merged_at: NOW,
+            approvers: vec!["jane".into()],
+            tickets: vec!["SER-1".into(), "SER-2".into()],
+            overrides: overrides.into_iter().map(|o| o.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_is_protected_branch() {
+        assert!(is_protected_branch("master", "release/"));
+        assert!(is_protected_branch("release/1.0", "release/"));
+        assert!(!is_protected_branch("feature", "release/"));
+        assert!(!is_protected_branch("feature", ""));
+    }
+
+    #[test]
+    fn test_overrides() {
+        assert_eq!(vec![OVERRIDE_NO_APPROVAL], overrides(&[]));
+        assert_eq!(Vec::<String>::new(), overrides(&[review("APPROVED", "jane")]));
+        assert_eq!(
+            vec![OVERRIDE_CHANGES_REQUESTED],
+            overrides(&[review("APPROVED", "jane"), review("CHANGES_REQUESTED", "bob")])
+        );
+        assert_eq!(
+            vec![OVERRIDE_NO_APPROVAL, OVERRIDE_CHANGES_REQUESTED],
+            overrides(&[review("APPROVED", "jane"), review("CHANGES_REQUESTED", "jane")])
+        );
+        assert_eq!(
+            Vec::<String>::new(),
+            overrides(&[review("CHANGES_REQUESTED", "jane"), review("COMMENTED", "jane"), review("APPROVED", "jane")])
+        );
+    }
+
+    #[test]
+    fn test_report_period() {
+        assert_eq!((parse_date("2019-04-24").unwrap(), parse_date("2019-05-01").unwrap()), report_period(WEEKLY, NOW));
+        assert_eq!((parse_date("2019-04-01").unwrap(), parse_date("2019-05-01").unwrap()), report_period(MONTHLY, NOW));
+        assert!(is_due(MONTHLY, "Mon", NOW));
+        assert!(!is_due(WEEKLY, "Mon", NOW));
+        assert!(is_due(WEEKLY, "Wed", NOW));
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let report = ComplianceReport {
+            start: parse_date("2019-04-24").unwrap(),
+            end: parse_date("2019-05-01").unwrap(),
+            merges: vec![merge(12, "Fix \"the\" thing, finally", vec![OVERRIDE_NO_APPROVAL])],
+            admin_actions: vec![],
+        };
+
+        assert_eq!(
+            "merged_at,repo,pull_request,title,url,base_branch,author,merged_by,approvers,tickets,overrides\n\
+             2019-05-01 12:00 UTC,some-user/some-repo,12,\"Fix \"\"the\"\" thing, finally\",\
+             http://the-github-host/some-user/some-repo/pull/12,master,joe,joe,jane,SER-1 SER-2,merged without approval\n",
+            report.to_csv()
+        );
+        assert_eq!("compliance-2019-04-24-2019-04-30.csv", report.file_name("csv"));
+    }
+
+    #[test]
+    fn test_pdf() {
+        let mut lines = vec!["Report (draft) \\ done".to_string()];
+        for i in 0..100 {
+            lines.push(format!("line {}", i));
+        }
+
+        let pdf = String::from_utf8(pdf(&lines)).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        assert!(pdf.contains("(Report \\(draft\\) \\\\ done) Tj T*"));
+        assert!(pdf.contains("/Count 2"));
+
+        // the xref table must point at each object
+        let xref = pdf.rfind("\nxref\n").unwrap() + 1;
+        let startxref = pdf.rfind("startxref\n").unwrap();
+        assert_eq!(format!("{}", xref), pdf[startxref + 10..].lines().next().unwrap());
+        for (i, line) in pdf[xref..].lines().skip(3).take(7).enumerate() {
+            let offset: usize = line[0..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj", i + 1)));
+        }
+    }
+}
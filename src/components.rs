@@ -0,0 +1,173 @@
+use failure::format_err;
+use serde_derive::Serialize;
+
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github::{self, PushCommit};
+use crate::repos::RepoComponent;
+use crate::util;
+
+// The latest version of a monorepo component on a branch
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ComponentVersion {
+    pub repo: String,
+    pub component: String,
+    pub branch: String,
+    pub version: String,
+    pub commit_hash: String,
+    pub updated_at: i64,
+}
+
+// Keeps track of the version of each component of a monorepo, i.e. the version matrix.
+#[derive(Clone)]
+pub struct ComponentVersions {
+    db: Database,
+}
+
+impl ComponentVersions {
+    pub fn new(db: Database) -> ComponentVersions {
+        ComponentVersions { db: db }
+    }
+
+    // Records the component's version, returning the version it replaces (if any).
+    pub fn record(
+        &self,
+        repo: &str,
+        component: &str,
+        branch: &str,
+        version: &str,
+        commit_hash: &str,
+    ) -> Result<Option<String>> {
+        let previous = self.get(repo, component, branch)?.map(|v| v.version);
+
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO component_versions (repo, component, branch, version, commit_hash, updated_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+               ON CONFLICT (repo, component, branch) DO UPDATE SET version = excluded.version,
+                   commit_hash = excluded.commit_hash, updated_at = excluded.updated_at"#,
+            &[&repo as &dyn ToSql, &component, &branch, &version, &commit_hash, &db::now()],
+        )
+        .map_err(|e| format_err!("Error recording version of {} in {}: {}", component, repo, e))?;
+
+        Ok(previous)
+    }
+
+    pub fn get(&self, repo: &str, component: &str, branch: &str) -> Result<Option<ComponentVersion>> {
+        let versions = self.query(
+            "WHERE repo = :repo AND component = :component AND branch = :branch",
+            &[(":repo", &repo), (":component", &component), (":branch", &branch)],
+        )?;
+        Ok(versions.into_iter().next())
+    }
+
+    pub fn get_all(&self, repo: &str) -> Result<Vec<ComponentVersion>> {
+        self.query("WHERE repo = :repo ORDER BY component, branch", &[(":repo", &repo)])
+    }
+
+    fn query(&self, filter: &str, params: &[(&str, &dyn ToSql)]) -> Result<Vec<ComponentVersion>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare(&format!("SELECT * FROM component_versions {}", filter))?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(params)?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(ComponentVersion {
+                repo: cols.get(row, "repo")?,
+                component: cols.get(row, "component")?,
+                branch: cols.get(row, "branch")?,
+                version: cols.get(row, "version")?,
+                commit_hash: cols.get(row, "commit_hash")?,
+                updated_at: cols.get(row, "updated_at")?,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+// The pushed commits that changed files in the component
+pub fn commits_touching(component: &RepoComponent, commits: &Vec<PushCommit>) -> Vec<PushCommit> {
+    commits
+        .iter()
+        .filter(|c| c.changed_files().any(|f| component.contains(f)))
+        .cloned()
+        .collect()
+}
+
+pub fn release_notes(component: &RepoComponent, version: &str, commits: &Vec<PushCommit>) -> String {
+    let mut notes = format!("*{} {}*", component.name, version);
+    for commit in commits {
+        notes += &format!(
+            "\n• {}: {}",
+            util::make_link(&commit.url, github::Commit::short_hash(commit)),
+            github::Commit::title(commit)
+        );
+    }
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (ComponentVersions, TempDir) {
+        let temp_dir = TempDir::new("components.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (ComponentVersions::new(db), temp_dir)
+    }
+
+    fn commit(id: &str, message: &str, modified: &[&str]) -> PushCommit {
+        let mut commit = PushCommit::new();
+        commit.id = id.into();
+        commit.message = message.into();
+        commit.url = format!("http://commit/{}", id);
+        commit.modified = modified.iter().map(|f| f.to_string()).collect();
+        commit
+    }
+
+    #[test]
+    fn test_record_versions() {
+        let (versions, _temp) = new_test();
+
+        assert_eq!(None, versions.record("some-user/mono", "api", "master", "1.0.0", "aaa").unwrap());
+        assert_eq!(None, versions.record("some-user/mono", "web", "master", "2.0.0", "aaa").unwrap());
+        assert_eq!(
+            Some("1.0.0".to_string()),
+            versions.record("some-user/mono", "api", "master", "1.1.0", "bbb").unwrap()
+        );
+
+        let all = versions.get_all("some-user/mono").unwrap();
+        assert_eq!(2, all.len());
+        assert_eq!(("api", "1.1.0"), (all[0].component.as_str(), all[0].version.as_str()));
+        assert_eq!("bbb", all[0].commit_hash);
+        assert_eq!(("web", "2.0.0"), (all[1].component.as_str(), all[1].version.as_str()));
+
+        assert_eq!(None, versions.get("some-user/mono", "api", "release/1.0").unwrap());
+        assert_eq!(0, versions.get_all("some-user/other").unwrap().len());
+    }
+
+    #[test]
+    fn test_commits_touching() {
+        let commits = vec![
+            commit("1111111111", "[API-1] api change", &["services/api/main.rs"]),
+            commit("2222222222", "web change", &["web/index.html"]),
+            commit("3333333333", "both", &["services/api/lib.rs", "web/app.js"]),
+        ];
+
+        let api = RepoComponent::new("api", "services/api");
+        let touching = commits_touching(&api, &commits);
+        assert_eq!(vec!["1111111111", "3333333333"], touching.iter().map(|c| c.id.as_str()).collect::<Vec<_>>());
+
+        assert_eq!(
+            "*api 1.1.0*\n\
+             • <http://commit/1111111111|1111111>: [API-1] api change\n\
+             • <http://commit/3333333333|3333333>: both",
+            release_notes(&api, "1.1.0", &touching)
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashSet;
+
+use serde_derive::Serialize;
+
+use crate::alerts;
+use crate::config::Config;
+use crate::db;
+use crate::errors::*;
+use crate::github;
+use crate::jira;
+use crate::label_rules;
+use crate::ldap_auth;
+use crate::repos::RepoInfo;
+use crate::slack::SlackWebApi;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Problem {
+    // the part of the config it is in, e.g. "github" or "repos"
+    pub section: String,
+    pub problem: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ConfigReport {
+    pub ok: bool,
+    pub problems: Vec<Problem>,
+    // checks that can't be made with this config, and why
+    pub skipped: Vec<String>,
+}
+
+impl ConfigReport {
+    fn new() -> ConfigReport {
+        ConfigReport {
+            ok: true,
+            problems: vec![],
+            skipped: vec![],
+        }
+    }
+
+    fn problem<S: Into<String>>(&mut self, section: &str, problem: S) {
+        self.ok = false;
+        self.problems.push(Problem {
+            section: section.into(),
+            problem: problem.into(),
+        });
+    }
+
+    fn skip<S: Into<String>>(&mut self, why: S) {
+        self.skipped.push(why.into());
+    }
+}
+
+// A channel and what sends messages to it, e.g. ("the-reviews", "repo some-org/some-repo")
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelRef {
+    pub channel: String,
+    pub used_by: String,
+}
+
+fn channel_ref(channel: &str, used_by: String) -> ChannelRef {
+    ChannelRef {
+        channel: channel.trim().trim_start_matches('#').to_string(),
+        used_by: used_by,
+    }
+}
+
+// "webex:room", "irc:#chan", "@user" and "#room:matrix.org" aren't slack channels
+pub fn is_slack_channel(channel: &str) -> bool {
+    !channel.is_empty() && !channel.starts_with('@') && !channel.contains(':')
+}
+
+// The slack channels that the repos and the config send messages to
+pub fn referenced_channels(config: &Config, repos: &Vec<RepoInfo>) -> Result<Vec<ChannelRef>> {
+    let mut refs = vec![];
+    for info in repos {
+        let used_by = format!("repo {}", info.repo);
+        refs.push(channel_ref(&info.channel, used_by.clone()));
+        for jira in &info.jira_config {
+            refs.push(channel_ref(&jira.channel, format!("{} (JIRA project {})", used_by, jira.jira_project)));
+        }
+        for rule in &info.routing_rules {
+            for channel in rule.channel_list() {
+                refs.push(channel_ref(&channel, format!("{} (routing rules)", used_by)));
+            }
+        }
+        for rule in &info.label_rules {
+            for channel in rule.channel_list() {
+                refs.push(channel_ref(&channel, format!("{} (label rule {})", used_by, rule.label)));
+            }
+        }
+    }
+
+    if let Some(channel) = config.security_channel() {
+        refs.push(channel_ref(&channel, "security.channel".into()));
+    }
+    for kind in &[alerts::MAIN_BROKEN, alerts::RELEASE_FAILED] {
+        for channel in config.alert_tiers(kind) {
+            refs.push(channel_ref(&channel, format!("alerts.{}", kind)));
+        }
+    }
+    for team in config.team_channels.get_all()? {
+        refs.push(channel_ref(&team.channel, format!("team {}", team.team)));
+    }
+
+    refs.retain(|r| is_slack_channel(&r.channel));
+    Ok(refs)
+}
+
+// The JIRA projects that the repos use, with the repo using each
+pub fn referenced_jira_projects(repos: &Vec<RepoInfo>) -> Vec<(String, String)> {
+    let mut projects = vec![];
+    for info in repos {
+        for jira in &info.jira_config {
+            if !projects.iter().any(|&(ref p, _)| p == &jira.jira_project) {
+                projects.push((jira.jira_project.clone(), info.repo.clone()));
+            }
+        }
+    }
+    projects
+}
+
+pub fn missing_channels(refs: &Vec<ChannelRef>, known: &HashSet<String>) -> Vec<ChannelRef> {
+    refs.iter().filter(|r| !known.contains(&r.channel)).cloned().collect()
+}
+
+// Names of the channels the slack bot can see
+fn slack_channels(slack: &SlackWebApi) -> Result<HashSet<String>> {
+    let mut names = HashSet::new();
+    let mut cursor = String::new();
+    loop {
+        let resp = slack.get(
+            "conversations.list",
+            &[
+                ("types", "public_channel,private_channel"),
+                ("exclude_archived", "true"),
+                ("limit", "1000"),
+                ("cursor", &cursor),
+            ],
+        )?;
+        for channel in resp["channels"].as_array().into_iter().flatten() {
+            if let Some(name) = channel["name"].as_str() {
+                names.insert(name.to_string());
+            }
+        }
+
+        cursor = resp["response_metadata"]["next_cursor"].as_str().unwrap_or("").to_string();
+        if cursor.is_empty() {
+            return Ok(names);
+        }
+    }
+}
+
+fn check_github(config: &Config, report: &mut ConfigReport) {
+    if let Err(e) = github::multi::new_factory(&config.github) {
+        report.problem("github", format!("{}", e));
+    }
+    for instance in config.github_instances.iter().flatten() {
+        if let Err(e) = github::multi::new_factory(instance) {
+            report.problem("github_instances", format!("{}: {}", instance.host, e));
+        }
+    }
+}
+
+fn check_slack(config: &Config, repos: &Vec<RepoInfo>, report: &mut ConfigReport) {
+    let token = match config.slack_bot_token() {
+        Some(t) => t,
+        None => {
+            report.skip("slack: main.slack_bot_token is needed to check slack credentials and channels");
+            return;
+        }
+    };
+
+    let slack = SlackWebApi::new(&token);
+    if let Err(e) = slack.get("auth.test", &[]) {
+        report.problem("main", format!("slack_bot_token: {}", e));
+        return;
+    }
+
+    if config.discord.is_some() {
+        report.skip("slack channels: messages go to discord");
+        return;
+    }
+    let refs = match referenced_channels(config, repos) {
+        Ok(r) => r,
+        Err(e) => {
+            report.problem("repos", format!("Error looking up channels: {}", e));
+            return;
+        }
+    };
+    let known = match slack_channels(&slack) {
+        Ok(k) => k,
+        Err(e) => {
+            report.problem("main", format!("Error listing slack channels: {}", e));
+            return;
+        }
+    };
+    for missing in missing_channels(&refs, &known) {
+        let problem = format!(
+            "channel '{}' of {} does not exist, or the slack bot can't see it",
+            missing.channel, missing.used_by
+        );
+        report.problem("repos", problem);
+    }
+}
+
+fn check_jira(config: &Config, repos: &Vec<RepoInfo>, report: &mut ConfigReport) {
+    let projects = referenced_jira_projects(repos);
+    let session = match jira::multi::new_session(config) {
+        Ok(Some(s)) => s,
+        Ok(None) => {
+            if let Some(&(ref project, ref repo)) = projects.first() {
+                let problem = format!("repo {} uses JIRA project {}, but JIRA isn't configured", repo, project);
+                report.problem("jira", problem);
+            }
+            return;
+        }
+        Err(e) => {
+            report.problem("jira", format!("{}", e));
+            return;
+        }
+    };
+
+    for (project, repo) in projects {
+        if let Err(e) = session.get_project_statuses(&project) {
+            report.problem("repos", format!("JIRA project {} of repo {}: {}", project, repo, e));
+        }
+    }
+}
+
+fn check_ldap(config: &Config, report: &mut ConfigReport) {
+    if let Some(ref ldap) = config.ldap {
+        // each server, since logins only fail over to the others while they are up
+        let client = ldap_auth::LdapClient::new(ldap);
+        let servers = client.check_health(db::now());
+        for server in &servers {
+            if let Some(ref e) = server.last_error {
+                report.problem("ldap", format!("{}: {}", server.url, e));
+            }
+        }
+        if servers.iter().any(|s| s.healthy) {
+            if let Err(e) = client.search(None, 1) {
+                report.problem("ldap", format!("{}", e));
+            }
+        }
+    }
+}
+
+// Checks a config without applying it: its values, and that the credentials, channels and JIRA projects it
+// (and the repos in the database) refer to work
+fn check_label_rules(repos: &Vec<RepoInfo>, report: &mut ConfigReport) {
+    for info in repos {
+        for rule in &info.label_rules {
+            if let Some(problem) = label_rules::check(rule) {
+                report.problem("repos", format!("repo {}: {}", info.repo, problem));
+            }
+        }
+    }
+}
+
+pub fn check(config: &Config) -> ConfigReport {
+    let mut report = ConfigReport::new();
+
+    let errors = config.validate();
+    for error in &errors {
+        let section = error.split(|c| c == '.' || c == ':' || c == ' ').next().unwrap_or("");
+        report.problem(section, error.clone());
+    }
+    if errors.iter().any(|e| e.starts_with("github")) {
+        report.skip("github: fix the github section to check its credentials");
+    } else {
+        check_github(config, &mut report);
+    }
+
+    let repos = match config.repos().get_all() {
+        Ok(r) => r,
+        Err(e) => {
+            report.problem("repos", format!("Error reading repos: {}", e));
+            vec![]
+        }
+    };
+
+    check_label_rules(&repos, &mut report);
+    check_slack(config, &repos, &mut report);
+    check_jira(config, &repos, &mut report);
+    check_ldap(config, &mut report);
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::repos::{RepoJiraConfig, RepoLabelRule, RepoRoutingRule};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_referenced_channels() {
+        let temp_dir = TempDir::new("config_check.rs").unwrap();
+        let db = Database::new(&temp_dir.path().join("db.sqlite3").to_string_lossy()).unwrap();
+        let config = Config::new(db);
+        config.team_channels.set("some-org/the-team", "team-reviews").unwrap();
+
+        let mut info = RepoInfo::new("some-org/some-repo", "#the-reviews")
+            .with_jira_config(RepoJiraConfig::new("SER").with_channel("ser-reviews"));
+        info.routing_rules = vec![RepoRoutingRule::new("", "docs/**", "", "docs, webex:docs, @joe")];
+        info.label_rules = vec![RepoLabelRule::new("security").with_channels("#sec-reviews")];
+
+        let refs = referenced_channels(&config, &vec![info.clone()]).unwrap();
+        assert_eq!(
+            vec!["the-reviews", "ser-reviews", "docs", "sec-reviews", "team-reviews"],
+            refs.iter().map(|r| r.channel.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!("repo some-org/some-repo (JIRA project SER)", refs[1].used_by);
+
+        let mut known = HashSet::new();
+        known.insert("the-reviews".to_string());
+        known.insert("docs".to_string());
+        assert_eq!(
+            vec!["ser-reviews", "sec-reviews", "team-reviews"],
+            missing_channels(&refs, &known).iter().map(|r| r.channel.as_str()).collect::<Vec<_>>()
+        );
+
+        assert_eq!(vec![("SER".to_string(), "some-org/some-repo".to_string())], referenced_jira_projects(&vec![info]));
+    }
+
+    #[test]
+    fn test_is_slack_channel() {
+        assert!(is_slack_channel("the-reviews"));
+        assert!(!is_slack_channel("@joe"));
+        assert!(!is_slack_channel("webex:releases"));
+        assert!(!is_slack_channel("irc:#releases"));
+        assert!(!is_slack_channel(""));
+    }
+
+    #[test]
+    fn test_check_invalid_config() {
+        let temp_dir = TempDir::new("config_check.rs").unwrap();
+        let db = Database::new(&temp_dir.path().join("db.sqlite3").to_string_lossy()).unwrap();
+        let config = Config::new(db);
+
+        // nothing is reachable with an empty config: only the values are checked
+        let report = check(&config);
+        assert!(!report.ok);
+        assert!(report.problems.contains(&Problem {
+            section: "main".into(),
+            problem: "main.clone_root_dir is required".into(),
+        }));
+        assert_eq!(2, report.skipped.len());
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+
+use failure::format_err;
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{self, Value};
+use toml;
+
+use crate::config::Config;
+use crate::db;
+use crate::errors::*;
+use crate::repos::{RepoConfig, RepoInfo};
+use crate::teams::{TeamChannel, TeamChannels};
+use crate::users::{UserConfig, UserInfo};
+
+pub const VERSION: u32 = 1;
+
+// The users, repos and team channels that octobot keeps in its database, so that they can be kept in version
+// control and moved between instances. The config file is left out: it holds credentials, and is a file already.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ConfigDocument {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub users: Vec<UserInfo>,
+    #[serde(default)]
+    pub repos: Vec<RepoInfo>,
+    #[serde(default)]
+    pub teams: Vec<TeamChannel>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Changed {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+// What importing a document changes about one kind of entry
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub changed: Vec<Changed>,
+    // entries the document doesn't have. users and repos are soft-deleted, so they can be restored
+    pub removed: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ConfigDiff {
+    pub users: Changes,
+    pub repos: Changes,
+    pub teams: Changes,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.repos.is_empty() && self.teams.is_empty()
+    }
+}
+
+// Ids and mutes belong to this instance, not to the config
+fn export_user(user: UserInfo) -> UserInfo {
+    UserInfo {
+        id: None,
+        muted_until: 0,
+        deleted_at: None,
+        ..user
+    }
+}
+
+fn export_repo(repo: RepoInfo) -> RepoInfo {
+    let mut repo = repo;
+    repo.id = None;
+    repo.deleted_at = None;
+    repo
+}
+
+fn export_from(users: &UserConfig, repos: &RepoConfig, teams: &TeamChannels) -> Result<ConfigDocument> {
+    Ok(ConfigDocument {
+        version: VERSION,
+        users: users.get_all()?.into_iter().map(export_user).collect(),
+        repos: repos.get_all()?.into_iter().map(export_repo).collect(),
+        teams: teams.get_all()?,
+    })
+}
+
+pub fn export(config: &Config) -> Result<ConfigDocument> {
+    export_from(&config.users(), &config.repos(), &config.team_channels)
+}
+
+pub fn to_toml(doc: &ConfigDocument) -> Result<String> {
+    // going through a toml::Value puts each table's plain values before its nested tables, as toml needs
+    let value = toml::Value::try_from(doc).map_err(|e| format_err!("Error converting config to TOML: {}", e))?;
+    toml::to_string(&value).map_err(|e| format_err!("Error serializing config as TOML: {}", e))
+}
+
+// Documents can be JSON or TOML
+pub fn parse(contents: &str) -> Result<ConfigDocument> {
+    if contents.trim_start().starts_with('{') {
+        serde_json::from_str(contents).map_err(|e| format_err!("Error parsing config JSON: {}", e))
+    } else {
+        toml::from_str(contents).map_err(|e| format_err!("Error parsing config TOML: {}", e))
+    }
+}
+
+fn duplicates<'a, I: Iterator<Item = &'a str>>(names: I) -> Vec<String> {
+    let mut seen = HashMap::new();
+    for name in names {
+        *seen.entry(name).or_insert(0) += 1;
+    }
+    let mut dups: Vec<String> = seen.into_iter().filter(|&(_, n)| n > 1).map(|(s, _)| s.to_string()).collect();
+    dups.sort();
+    dups
+}
+
+pub fn validate(doc: &ConfigDocument) -> Vec<String> {
+    let mut errors = vec![];
+    if doc.version > VERSION {
+        errors.push(format!("version {} is newer than this octobot supports ({})", doc.version, VERSION));
+    }
+
+    for user in &doc.users {
+        if user.github.trim().is_empty() {
+            errors.push("users: every user needs a `github` name".into());
+        } else if let Err(e) = user.validate() {
+            errors.push(format!("user {}: {}", user.github, e));
+        }
+    }
+    for dup in duplicates(doc.users.iter().map(|u| u.github.as_str())) {
+        errors.push(format!("user {} is listed more than once", dup));
+    }
+
+    for repo in &doc.repos {
+        if repo.repo.trim().is_empty() {
+            errors.push("repos: every repo needs a `repo`".into());
+        }
+    }
+    for dup in duplicates(doc.repos.iter().map(|r| r.repo.as_str())) {
+        errors.push(format!("repo {} is listed more than once", dup));
+    }
+
+    for team in &doc.teams {
+        if !team.team.contains('/') || team.channel.trim().is_empty() {
+            errors.push(format!("team '{}': expected a `team` like \"org/team-slug\" and a `channel`", team.team));
+        }
+    }
+    for dup in duplicates(doc.teams.iter().map(|t| t.team.as_str())) {
+        errors.push(format!("team {} is listed more than once", dup));
+    }
+
+    errors
+}
+
+// The top level fields whose values differ
+fn changed_fields(old: &Value, new: &Value) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+
+    let mut fields: Vec<String> =
+        new.iter().filter(|&(k, v)| old.get(k) != Some(v)).map(|(k, _)| k.clone()).collect();
+    fields.sort();
+    fields
+}
+
+fn diff_entries(old: &Vec<(String, Value)>, new: &Vec<(String, Value)>) -> Changes {
+    let mut changes = Changes::default();
+    for &(ref name, ref value) in new {
+        match old.iter().find(|&&(ref n, _)| n == name) {
+            None => changes.added.push(name.clone()),
+            Some(&(_, ref old_value)) => {
+                let fields = changed_fields(old_value, value);
+                if !fields.is_empty() {
+                    changes.changed.push(Changed {
+                        name: name.clone(),
+                        fields: fields,
+                    });
+                }
+            }
+        }
+    }
+    for &(ref name, _) in old {
+        if !new.iter().any(|&(ref n, _)| n == name) {
+            changes.removed.push(name.clone());
+        }
+    }
+    changes
+}
+
+fn entries<T: serde::Serialize>(items: &Vec<T>, name: fn(&T) -> String) -> Result<Vec<(String, Value)>> {
+    items.iter().map(|i| -> Result<(String, Value)> { Ok((name(i), serde_json::to_value(i)?)) }).collect()
+}
+
+// What importing `doc` over `current` changes. Both are compared as they are exported.
+pub fn diff_from(current: &ConfigDocument, doc: &ConfigDocument) -> Result<ConfigDiff> {
+    let doc_users: Vec<UserInfo> = doc.users.iter().cloned().map(export_user).collect();
+    let doc_repos: Vec<RepoInfo> = doc.repos.iter().cloned().map(export_repo).collect();
+
+    let user_name = |u: &UserInfo| u.github.clone();
+    let repo_name = |r: &RepoInfo| r.repo.clone();
+    let team_name = |t: &TeamChannel| t.team.clone();
+    Ok(ConfigDiff {
+        users: diff_entries(&entries(&current.users, user_name)?, &entries(&doc_users, user_name)?),
+        repos: diff_entries(&entries(&current.repos, repo_name)?, &entries(&doc_repos, repo_name)?),
+        teams: diff_entries(&entries(&current.teams, team_name)?, &entries(&doc.teams, team_name)?),
+    })
+}
+
+pub fn diff(config: &Config, doc: &ConfigDocument) -> Result<ConfigDiff> {
+    diff_from(&export(config)?, doc)
+}
+
+fn apply_users(
+    conn: &db::Connection,
+    changes: &Changes,
+    current: &Vec<UserInfo>,
+    doc: &ConfigDocument,
+) -> Result<()> {
+    let now = db::now();
+    for user in doc.users.iter().filter(|u| changes.added.contains(&u.github)) {
+        UserConfig::insert_in(conn, user)?;
+    }
+    for changed in &changes.changed {
+        let old = current.iter().find(|u| u.github == changed.name);
+        let new = doc.users.iter().find(|u| u.github == changed.name);
+        if let (Some(old), Some(new)) = (old, new) {
+            UserConfig::update_in(conn, &UserInfo { id: old.id, ..new.clone() })?;
+        }
+    }
+    for user in current.iter().filter(|u| changes.removed.contains(&u.github)) {
+        UserConfig::delete_in(conn, user.id.unwrap_or_default(), now)?;
+    }
+    Ok(())
+}
+
+fn apply_repos(
+    repos: &mut RepoConfig,
+    tx: &db::Transaction,
+    changes: &Changes,
+    current: &Vec<RepoInfo>,
+    doc: &ConfigDocument,
+) -> Result<()> {
+    let now = db::now();
+    for repo in doc.repos.iter().filter(|r| changes.added.contains(&r.repo)) {
+        repos.insert_in(tx, repo)?;
+    }
+    for changed in &changes.changed {
+        let old = current.iter().find(|r| r.repo == changed.name);
+        let new = doc.repos.iter().find(|r| r.repo == changed.name);
+        if let (Some(old), Some(new)) = (old, new) {
+            let mut repo = new.clone();
+            repo.id = old.id;
+            repos.update_in(tx, &repo)?;
+        }
+    }
+    for repo in current.iter().filter(|r| changes.removed.contains(&r.repo)) {
+        RepoConfig::delete_in(tx, repo.id.unwrap_or_default(), now)?;
+    }
+    Ok(())
+}
+
+fn apply_teams(conn: &db::Connection, changes: &Changes, doc: &ConfigDocument) -> Result<()> {
+    for team in &doc.teams {
+        if changes.added.contains(&team.team) || changes.changed.iter().any(|c| c.name == team.team) {
+            TeamChannels::set_in(conn, &team.team, team.channel.trim())?;
+        }
+    }
+    for team in &changes.removed {
+        TeamChannels::remove_in(conn, team)?;
+    }
+    Ok(())
+}
+
+fn count(changes: &Changes) -> usize {
+    changes.added.len() + changes.changed.len() + changes.removed.len()
+}
+
+// Makes the database match `doc` in one transaction: if anything fails, nothing changes.
+// Returns what changed, or with `dry_run` what would have.
+pub fn import(config: &Config, doc: &ConfigDocument, dry_run: bool) -> Result<ConfigDiff> {
+    let errors = validate(doc);
+    if !errors.is_empty() {
+        return Err(format_err!("Invalid config: {}", errors.join("; ")));
+    }
+
+    // keeps anyone else from changing users and repos between the diff and the import
+    let users = config.users_write();
+    let mut repos = config.repos_write();
+
+    let diff = diff_from(&export_from(&users, &repos, &config.team_channels)?, doc)?;
+    if dry_run || diff.is_empty() {
+        return Ok(diff);
+    }
+
+    let current_users = users.get_all()?;
+    let current_repos = repos.get_all()?;
+
+    let mut conn = config.database().connect()?;
+    let tx = conn.transaction()?;
+    apply_users(&tx, &diff.users, &current_users, doc)?;
+    apply_repos(&mut repos, &tx, &diff.repos, &current_repos, doc)?;
+    apply_teams(&tx, &diff.teams, doc)?;
+    tx.commit()?;
+
+    info!(
+        "Imported config: {} user, {} repo and {} team changes",
+        count(&diff.users),
+        count(&diff.repos),
+        count(&diff.teams)
+    );
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use tempdir::TempDir;
+
+    fn new_test() -> (Config, TempDir) {
+        let temp_dir = TempDir::new("config_export.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let config = Config::new(Database::new(&db_file.to_string_lossy()).expect("create temp database"));
+
+        config.users_write().insert("joe", "joe.slack").unwrap();
+        config.users_write().insert("sue", "sue.slack").unwrap();
+        config.repos_write().insert("some-org/some-repo", "the-reviews").unwrap();
+        config.team_channels.set("some-org/backend", "backend-reviews").unwrap();
+        (config, temp_dir)
+    }
+
+    #[test]
+    fn test_export() {
+        let (config, _temp) = new_test();
+        config.users_write().mute_until("joe", 2000000000).unwrap();
+
+        let doc = export(&config).unwrap();
+        assert_eq!(VERSION, doc.version);
+        assert_eq!(vec!["joe", "sue"], doc.users.iter().map(|u| u.github.as_str()).collect::<Vec<_>>());
+        assert_eq!(None, doc.users[0].id);
+        assert_eq!(0, doc.users[0].muted_until);
+        assert_eq!(None, doc.repos[0].id);
+        assert_eq!(1, doc.teams.len());
+
+        let toml = to_toml(&doc).unwrap();
+        let parsed = parse(&toml).unwrap();
+        assert!(diff_from(&doc, &parsed).unwrap().is_empty());
+
+        let json = serde_json::to_string(&doc).unwrap();
+        assert!(diff_from(&doc, &parse(&json).unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut doc = ConfigDocument::default();
+        assert!(validate(&doc).is_empty());
+
+        doc.version = VERSION + 1;
+        doc.users = vec![UserInfo::new("joe", "joe.slack"), UserInfo::new("joe", "joe.other")];
+        doc.users[1].digest = "hourly".into();
+        doc.repos = vec![RepoInfo::new("", "the-reviews")];
+        doc.teams = vec![TeamChannel {
+            team: "backend".into(),
+            channel: "backend-reviews".into(),
+        }];
+        assert_eq!(
+            vec![
+                "version 2 is newer than this octobot supports (1)",
+                "user joe: Invalid digest (expected daily or weekly): hourly",
+                "user joe is listed more than once",
+                "repos: every repo needs a `repo`",
+                "team 'backend': expected a `team` like \"org/team-slug\" and a `channel`",
+            ],
+            validate(&doc)
+        );
+    }
+
+    #[test]
+    fn test_import() {
+        let (config, _temp) = new_test();
+
+        let mut doc = export(&config).unwrap();
+        doc.users.retain(|u| u.github != "sue");
+        doc.users[0].slack = "joe.renamed".into();
+        doc.users.push(UserInfo::new("bob", "bob.slack"));
+        doc.repos.push(RepoInfo::new("some-org/other-repo", "other-reviews"));
+        doc.teams[0].channel = "backend".into();
+
+        let expected = ConfigDiff {
+            users: Changes {
+                added: vec!["bob".into()],
+                changed: vec![Changed {
+                    name: "joe".into(),
+                    fields: vec!["slack".into()],
+                }],
+                removed: vec!["sue".into()],
+            },
+            repos: Changes {
+                added: vec!["some-org/other-repo".into()],
+                ..Changes::default()
+            },
+            teams: Changes {
+                changed: vec![Changed {
+                    name: "some-org/backend".into(),
+                    fields: vec!["channel".into()],
+                }],
+                ..Changes::default()
+            },
+        };
+
+        // a dry run changes nothing
+        assert_eq!(expected, import(&config, &doc, true).unwrap());
+        assert_eq!(expected, diff(&config, &doc).unwrap());
+
+        assert_eq!(expected, import(&config, &doc, false).unwrap());
+        assert!(diff(&config, &doc).unwrap().is_empty());
+        assert_eq!(Some("joe.renamed".to_string()), config.users().slack_user_name("joe"));
+        let deleted = config.users().get_deleted().unwrap();
+        assert_eq!(vec!["sue"], deleted.iter().map(|u| u.github.as_str()).collect::<Vec<_>>());
+        assert_eq!(2, config.repos().get_all().unwrap().len());
+        assert_eq!(Some("backend".to_string()), config.team_channels.get("some-org/backend").unwrap());
+
+        // invalid documents aren't imported at all
+        doc.users.push(UserInfo::new("sue", "sue.slack"));
+        doc.users.push(UserInfo::new("bob", "bob.again"));
+        assert!(import(&config, &doc, false).is_err());
+        assert_eq!(2, config.users().get_all().unwrap().len());
+    }
+}
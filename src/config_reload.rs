@@ -0,0 +1,322 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use log::{error, info};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::scheduler;
+use crate::worker;
+
+pub const CHECK_INTERVAL_SECS: u64 = 30;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ReloadStatus {
+    // when the config in use was loaded
+    pub loaded_at: i64,
+    // when the config file was last re-read, if it has been
+    pub last_attempt: Option<i64>,
+    // why the last attempt wasn't applied
+    pub errors: Vec<String>,
+    // sections changed since startup that are only read when octobot starts
+    pub restart_required: Vec<String>,
+}
+
+// The config that requests are handled with, swapped for a new one when the config file changes.
+// Requests already being handled keep the config they started with. Users and repos live in the database,
+// so they are always up to date.
+pub struct LiveConfig {
+    config_file: PathBuf,
+    started: Arc<Config>,
+    current: RwLock<Arc<Config>>,
+    modified: Mutex<Option<SystemTime>>,
+    status: Mutex<ReloadStatus>,
+}
+
+impl LiveConfig {
+    pub fn new(config: Arc<Config>, config_file: &Path, now: i64) -> LiveConfig {
+        LiveConfig {
+            config_file: config_file.to_path_buf(),
+            started: config.clone(),
+            current: RwLock::new(config),
+            modified: Mutex::new(modified_time(config_file)),
+            status: Mutex::new(ReloadStatus {
+                loaded_at: now,
+                last_attempt: None,
+                errors: vec![],
+                restart_required: vec![],
+            }),
+        }
+    }
+
+    pub fn config_file(&self) -> &Path {
+        &self.config_file
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn status(&self) -> ReloadStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    // Re-reads the config file and swaps it in, unless it has errors
+    pub fn reload(&self, now: i64) -> ReloadStatus {
+        *self.modified.lock().unwrap() = modified_time(&self.config_file);
+
+        let current = self.get();
+        let (errors, restart_required) = match current.reload(&self.config_file) {
+            Ok(config) => {
+                let errors = config.validate();
+                let restart_required = restart_sections(&self.started, &config);
+                if errors.is_empty() {
+                    *self.current.write().unwrap() = Arc::new(config);
+                }
+                (errors, restart_required)
+            }
+            Err(e) => (vec![format!("{}", e)], vec![]),
+        };
+
+        let mut status = self.status.lock().unwrap();
+        status.last_attempt = Some(now);
+        if errors.is_empty() {
+            info!("Reloaded config from {:?}", self.config_file);
+            if !restart_required.is_empty() {
+                info!("Changes to [{}] take effect after a restart", restart_required.join(", "));
+            }
+            status.loaded_at = now;
+            status.restart_required = restart_required;
+        } else {
+            error!("Not reloading config from {:?}: {}", self.config_file, errors.join("; "));
+        }
+        status.errors = errors;
+        status.clone()
+    }
+
+    pub fn reload_if_changed(&self, now: i64) -> Option<ReloadStatus> {
+        if modified_time(&self.config_file) == *self.modified.lock().unwrap() {
+            return None;
+        }
+        Some(self.reload(now))
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn changed<T: serde::Serialize>(old: &T, new: &T) -> bool {
+    serde_json::to_value(old).ok() != serde_json::to_value(new).ok()
+}
+
+// The changed sections that were read when octobot started: to set up workers, sessions and schedules.
+// Workers and scheduled tasks otherwise get the current config for each job (see `live_runner` and `live_task`).
+pub fn restart_sections(old: &Config, new: &Config) -> Vec<String> {
+    let sections = vec![
+        ("main", changed(&old.main, &new.main)),
+        ("github", changed(&old.github, &new.github)),
+        ("github_instances", changed(&old.github_instances, &new.github_instances)),
+        ("jira", changed(&old.jira, &new.jira)),
+        ("jira_instances", changed(&old.jira_instances, &new.jira_instances)),
+        ("discord", changed(&old.discord, &new.discord)),
+        ("matrix", changed(&old.matrix, &new.matrix)),
+        ("irc", changed(&old.irc, &new.irc)),
+        ("webex", changed(&old.webex, &new.webex)),
+        ("email", changed(&old.email, &new.email)),
+        ("kerberos", changed(&old.kerberos, &new.kerberos)),
+        ("database", changed(&old.database, &new.database)),
+        ("scheduler", changed(&old.scheduler, &new.scheduler)),
+        ("servicenow", changed(&old.servicenow, &new.servicenow)),
+        ("jsm", changed(&old.jsm, &new.jsm)),
+        ("opsgenie", changed(&old.opsgenie, &new.opsgenie)),
+        ("statuspage", changed(&old.statuspage, &new.statuspage)),
+        ("grafana", changed(&old.grafana, &new.grafana)),
+        ("warehouse", changed(&old.warehouse, &new.warehouse)),
+        ("event_bus", changed(&old.event_bus, &new.event_bus)),
+        ("inbound_queue", changed(&old.inbound_queue, &new.inbound_queue)),
+        ("storage", changed(&old.storage, &new.storage)),
+        ("kubernetes", changed(&old.kubernetes, &new.kubernetes)),
+        ("ha", changed(&old.ha, &new.ha)),
+        ("outbox", changed(&old.outbox, &new.outbox)),
+        ("container_images", changed(&old.container_images, &new.container_images)),
+        ("testing", changed(&old.testing, &new.testing)),
+        ("sentry", changed(&old.sentry, &new.sentry)),
+        ("network", changed(&old.network, &new.network)),
+        ("clients", changed(&old.clients, &new.clients)),
+        ("user_discovery", changed(&old.user_discovery, &new.user_discovery)),
+        ("label_taxonomy", changed(&old.label_taxonomy, &new.label_taxonomy)),
+    ];
+    sections.into_iter().filter(|&(_, c)| c).map(|(s, _)| s.to_string()).collect()
+}
+
+// A runner that is made again with the current config for each job, so that jobs see reloaded config
+struct LiveRunner<T> {
+    live_config: Arc<LiveConfig>,
+    new_runner: Box<dyn Fn(Arc<Config>) -> Arc<dyn worker::Runner<T>> + Send + Sync>,
+}
+
+pub fn live_runner<T, F>(live_config: Arc<LiveConfig>, new_runner: F) -> Arc<dyn worker::Runner<T>>
+where
+    T: Send + 'static,
+    F: Fn(Arc<Config>) -> Arc<dyn worker::Runner<T>> + Send + Sync + 'static,
+{
+    Arc::new(LiveRunner {
+        live_config: live_config,
+        new_runner: Box::new(new_runner),
+    })
+}
+
+impl<T: Send + 'static> worker::Runner<T> for LiveRunner<T> {
+    fn handle(&self, req: T) {
+        (self.new_runner)(self.live_config.get()).handle(req)
+    }
+
+    fn repo(&self, req: &T) -> Option<String> {
+        (self.new_runner)(self.live_config.get()).repo(req)
+    }
+
+    fn priority(&self, req: &T) -> worker::Priority {
+        (self.new_runner)(self.live_config.get()).priority(req)
+    }
+}
+
+// The same for scheduled tasks: each run gets the current config
+struct LiveTask {
+    live_config: Arc<LiveConfig>,
+    new_task: Box<dyn Fn(Arc<Config>) -> Arc<dyn scheduler::Task> + Send + Sync>,
+}
+
+pub fn live_task<F>(live_config: Arc<LiveConfig>, new_task: F) -> Arc<dyn scheduler::Task>
+where
+    F: Fn(Arc<Config>) -> Arc<dyn scheduler::Task> + Send + Sync + 'static,
+{
+    Arc::new(LiveTask {
+        live_config: live_config,
+        new_task: Box::new(new_task),
+    })
+}
+
+impl scheduler::Task for LiveTask {
+    fn run(&self, now: i64) -> Result<()> {
+        (self.new_task)(self.live_config.get()).run(now)
+    }
+}
+
+// Reloads the config when its file changes
+pub struct ConfigWatcher {
+    live_config: Arc<LiveConfig>,
+}
+
+impl ConfigWatcher {
+    pub fn new(live_config: Arc<LiveConfig>) -> Arc<dyn scheduler::Task> {
+        Arc::new(ConfigWatcher { live_config: live_config })
+    }
+}
+
+impl scheduler::Task for ConfigWatcher {
+    fn run(&self, now: i64) -> Result<()> {
+        self.live_config.reload_if_changed(now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use tempdir::TempDir;
+
+    const CONFIG: &str = r#"
+[main]
+clone_root_dir = "./repos"
+
+[github]
+webhook_secret = "abcd"
+host = "git.company.com"
+api_token = "the-token"
+"#;
+
+    #[test]
+    fn test_reload() {
+        let temp_dir = TempDir::new("config_reload.rs").unwrap();
+        let config_file = temp_dir.path().join("octobot.toml");
+        fs::write(&config_file, CONFIG).unwrap();
+
+        let config = Arc::new(crate::config::new(config_file.clone()).unwrap());
+        let live = LiveConfig::new(config, &config_file, 10);
+        assert_eq!(None, live.reload_if_changed(20));
+
+        // invalid: the old config stays
+        fs::write(&config_file, CONFIG.replace("./repos", "")).unwrap();
+        let status = live.reload(30);
+        assert_eq!(vec!["main.clone_root_dir is required"], status.errors);
+        assert_eq!(10, status.loaded_at);
+        assert_eq!(Some(30), status.last_attempt);
+        assert_eq!("./repos", live.get().main.clone_root_dir);
+
+        fs::write(&config_file, CONFIG.replace("abcd", "efgh") + "\n[security]\nchannel = \"security\"\n").unwrap();
+        let status = live.reload(40);
+        assert!(status.errors.is_empty());
+        assert_eq!(40, status.loaded_at);
+        assert_eq!(vec!["github"], status.restart_required);
+        assert_eq!(Some("security".to_string()), live.get().security_channel());
+        assert_eq!(status, live.status());
+
+        fs::write(&config_file, "not toml").unwrap();
+        let status = live.reload(50);
+        assert_eq!(1, status.errors.len());
+        assert_eq!(40, status.loaded_at);
+        assert_eq!("efgh", live.get().github.webhook_secret);
+    }
+
+    struct SecurityChannelTask {
+        config: Arc<Config>,
+        seen: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl scheduler::Task for SecurityChannelTask {
+        fn run(&self, _now: i64) -> Result<()> {
+            self.seen.lock().unwrap().push(self.config.security_channel());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_live_task() {
+        let temp_dir = TempDir::new("config_reload.rs").unwrap();
+        let config_file = temp_dir.path().join("octobot.toml");
+        fs::write(&config_file, CONFIG).unwrap();
+
+        let config = Arc::new(crate::config::new(config_file.clone()).unwrap());
+        let live = Arc::new(LiveConfig::new(config, &config_file, 10));
+        let seen = Arc::new(Mutex::new(vec![]));
+        let task = {
+            let seen = seen.clone();
+            live_task(live.clone(), move |config| Arc::new(SecurityChannelTask { config: config, seen: seen.clone() }))
+        };
+
+        task.run(20).unwrap();
+        fs::write(&config_file, CONFIG.to_string() + "\n[security]\nchannel = \"security\"\n").unwrap();
+        live.reload(30);
+        task.run(40).unwrap();
+        assert_eq!(vec![None, Some("security".to_string())], *seen.lock().unwrap());
+    }
+
+    #[test]
+    fn test_restart_sections() {
+        let temp_dir = TempDir::new("config_reload.rs").unwrap();
+        let db = Database::new(&temp_dir.path().join("db.sqlite3").to_string_lossy()).unwrap();
+
+        let old = Config::new(db.clone());
+        let mut new = Config::new(db);
+        assert!(restart_sections(&old, &new).is_empty());
+
+        new.main.listen_addr = Some("0.0.0.0:4000".into());
+        assert_eq!(vec!["main"], restart_sections(&old, &new));
+    }
+}
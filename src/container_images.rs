@@ -0,0 +1,666 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use reqwest;
+use serde_derive::Deserialize;
+
+use crate::auto_merge::AutoMerge;
+use crate::config::{Config, ContainerImageConfig, ContainerImagesConfig};
+use crate::db::{Database, ToSql};
+use crate::errors::*;
+use crate::git::Git;
+use crate::git_clone_manager::GitCloneManager;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::network;
+use crate::scheduler;
+
+// how often to check the registries for new digests, unless configured
+pub const CHECK_INTERVAL_SECS: u64 = 60 * 60;
+
+const DEFAULT_REGISTRY: &str = "docker.io";
+const DEFAULT_TAG: &str = "latest";
+
+// manifest lists first, so that multi-platform images get the digest of the list rather than of one platform
+const MANIFEST_TYPES: &[&str] = &[
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+    "application/vnd.oci.image.manifest.v1+json",
+];
+
+// An image reference, as in a Dockerfile's FROM line: [registry/]repository[:tag][@digest]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageRef {
+    // "docker.io" for docker hub
+    pub registry: String,
+    // with docker hub's "library/" for official images
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl ImageRef {
+    pub fn parse(value: &str) -> Option<ImageRef> {
+        let value = value.trim();
+        let (name, digest) = match value.find('@') {
+            Some(i) => (&value[..i], Some(value[i + 1..].to_string())),
+            None => (value, None),
+        };
+        // a colon after the last slash starts the tag: one before it is the registry's port
+        let (name, tag) = match name.rfind(':') {
+            Some(i) if !name[i..].contains('/') => (&name[..i], Some(name[i + 1..].to_string())),
+            _ => (name, None),
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) || tag.as_ref().map_or(false, |t| t.is_empty()) {
+            return None;
+        }
+
+        let mut parts = name.splitn(2, '/');
+        let first = parts.next().unwrap_or("");
+        let (registry, repository) = match parts.next() {
+            Some(rest) if first.contains('.') || first.contains(':') || first == "localhost" => {
+                (first.to_string(), rest.to_string())
+            }
+            _ => (DEFAULT_REGISTRY.to_string(), name.to_string()),
+        };
+        let registry = if registry == "index.docker.io" || registry == "registry-1.docker.io" {
+            DEFAULT_REGISTRY.to_string()
+        } else {
+            registry
+        };
+        let repository = if registry == DEFAULT_REGISTRY && !repository.contains('/') {
+            format!("library/{}", repository)
+        } else {
+            repository
+        };
+
+        Some(ImageRef {
+            registry: registry,
+            repository: repository,
+            tag: tag,
+            digest: digest,
+        })
+    }
+
+    pub fn tag(&self) -> &str {
+        self.tag.as_ref().map(|t| t.as_str()).unwrap_or(DEFAULT_TAG)
+    }
+
+    // Whether both name the same tag of the same image, however they're spelled
+    pub fn same_tag(&self, other: &ImageRef) -> bool {
+        self.registry == other.registry && self.repository == other.repository && self.tag() == other.tag()
+    }
+
+    // e.g. "docker.io/library/debian:bookworm"
+    pub fn key(&self) -> String {
+        format!("{}/{}:{}", self.registry, self.repository, self.tag())
+    }
+
+    fn api_base(&self) -> String {
+        if self.registry == DEFAULT_REGISTRY {
+            "https://registry-1.docker.io".into()
+        } else {
+            format!("https://{}", self.registry)
+        }
+    }
+}
+
+// Pins FROM lines naming |image|'s tag by digest to |digest|. Lines that aren't pinned by digest already are left
+// alone: they get the new image whenever they're built. Returns None if nothing changed.
+pub fn update_dockerfile(contents: &str, image: &ImageRef, digest: &str) -> Option<String> {
+    let mut changed = false;
+    let mut lines = vec![];
+    for line in contents.split('\n') {
+        match update_from_line(line, image, digest) {
+            Some(updated) => {
+                changed = true;
+                lines.push(updated);
+            }
+            None => lines.push(line.to_string()),
+        }
+    }
+    if changed {
+        Some(lines.join("\n"))
+    } else {
+        None
+    }
+}
+
+fn update_from_line(line: &str, image: &ImageRef, digest: &str) -> Option<String> {
+    let trimmed = line.trim_start();
+    if !trimmed.get(..5).map_or(false, |t| t.eq_ignore_ascii_case("from ")) {
+        return None;
+    }
+
+    // FROM [--platform=<platform>] <image> [AS <name>]
+    let offset = line.len() - trimmed.len() + 5;
+    let mut start = offset;
+    for token in line[offset..].split(' ') {
+        if token.is_empty() || token.starts_with("--") {
+            start += token.len() + 1;
+            continue;
+        }
+        let end = start + token.len();
+        let from = ImageRef::parse(token)?;
+        let pinned = from.digest.as_ref()?;
+        if !from.same_tag(image) || pinned == digest {
+            return None;
+        }
+        let name = &token[..token.find('@')?];
+        return Some(format!("{}{}@{}{}", &line[..start], name, digest, &line[end..]));
+    }
+    None
+}
+
+// Dockerfile, Dockerfile.<something> and <something>.Dockerfile, anywhere in the repo but .git
+pub fn is_dockerfile(file_name: &str) -> bool {
+    file_name == "Dockerfile" || file_name.starts_with("Dockerfile.") || file_name.ends_with(".Dockerfile")
+}
+
+pub fn find_dockerfiles(root: &Path) -> Result<Vec<String>> {
+    let mut found = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_dir() {
+                if file_name != ".git" {
+                    dirs.push(path);
+                }
+            } else if is_dockerfile(&file_name) {
+                if let Ok(relative) = path.strip_prefix(root) {
+                    found.push(relative.to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+pub fn update_branch_name(image: &ImageRef, digest: &str) -> String {
+    let hex = digest.splitn(2, ':').last().unwrap_or(digest);
+    format!(
+        "octobot/image-{}-{}-{}",
+        image.repository.replace('/', "-"),
+        image.tag(),
+        &hex[..hex.len().min(12)]
+    )
+}
+
+pub fn update_title(image: &ImageRef) -> String {
+    format!("Update {}:{} base image", image.repository.trim_start_matches("library/"), image.tag())
+}
+
+pub fn update_body(image: &ImageRef, digest: &str, files: &[String]) -> String {
+    format!(
+        "{} has a new digest: `{}`.\n\nUpdated the pins in:\n{}",
+        image.key(),
+        digest,
+        files.iter().map(|f| format!("* {}", f)).collect::<Vec<_>>().join("\n")
+    )
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Challenge {
+    // "Bearer" or "Basic"
+    pub scheme: String,
+    pub params: HashMap<String, String>,
+}
+
+// A WWW-Authenticate header, e.g. `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`
+pub fn parse_challenge(value: &str) -> Option<Challenge> {
+    let value = value.trim();
+    let space = value.find(' ').unwrap_or(value.len());
+    let scheme = value[..space].to_string();
+    if scheme.is_empty() {
+        return None;
+    }
+
+    let mut params = HashMap::new();
+    let mut rest = value[space..].trim_start();
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim().trim_start_matches(',').trim().to_lowercase();
+        let after = &rest[eq + 1..];
+        let (param, remaining) = if after.starts_with('"') {
+            let close = after[1..].find('"')? + 1;
+            (&after[1..close], &after[close + 1..])
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        params.insert(name, param.to_string());
+        rest = remaining.trim_start_matches(|c| c == ',' || c == ' ');
+    }
+
+    Some(Challenge {
+        scheme: scheme,
+        params: params,
+    })
+}
+
+pub trait Registry: Send + Sync {
+    // The digest |image|'s tag currently points to
+    fn get_digest(&self, image: &ImageRef) -> Result<String>;
+}
+
+#[derive(Deserialize)]
+struct TokenResp {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+// Registries' HTTP API (v2): anonymous, or with the configured credentials of each registry host
+pub struct RegistryClient {
+    client: reqwest::Client,
+    // host => (username, password)
+    credentials: HashMap<String, (String, String)>,
+}
+
+impl RegistryClient {
+    pub fn new(config: &ContainerImagesConfig) -> Result<RegistryClient> {
+        let mut credentials = HashMap::new();
+        for registry in config.registries.iter().flat_map(|r| r.iter()) {
+            if let (Some(username), Some(password)) = (&registry.username, &registry.password) {
+                credentials.insert(registry.host.clone(), (username.clone(), password.clone()));
+            }
+        }
+        Ok(RegistryClient {
+            client: network::client(),
+            credentials: credentials,
+        })
+    }
+
+    fn head(&self, url: &str, authorization: Option<&str>) -> Result<reqwest::Response> {
+        let mut req = self.client.head(url).header(reqwest::header::ACCEPT, MANIFEST_TYPES.join(", "));
+        if let Some(authorization) = authorization {
+            req = req.header(reqwest::header::AUTHORIZATION, authorization);
+        }
+        Ok(req.send()?)
+    }
+
+    // What to authorize with after |challenge|: a bearer token from its realm, or basic auth
+    fn authorization(&self, image: &ImageRef, challenge: &Challenge) -> Result<String> {
+        let credentials = self.credentials.get(&image.registry);
+        if challenge.scheme.eq_ignore_ascii_case("basic") {
+            let (username, password) =
+                credentials.ok_or_else(|| format_err!("{} needs credentials", image.registry))?;
+            return Ok(format!("Basic {}", base64::encode(&format!("{}:{}", username, password))));
+        }
+
+        let realm = challenge.params.get("realm").ok_or_else(|| format_err!("No realm to get a token from"))?;
+        let default_scope = format!("repository:{}:pull", image.repository);
+        let mut query = vec![("scope", challenge.params.get("scope").unwrap_or(&default_scope).as_str())];
+        if let Some(service) = challenge.params.get("service") {
+            query.push(("service", service.as_str()));
+        }
+        let mut req = self.client.get(realm.as_str()).query(&query);
+        if let Some((username, password)) = credentials {
+            req = req.basic_auth(username, Some(password));
+        }
+        let mut resp = req.send()?;
+        if !resp.status().is_success() {
+            return Err(format_err!("Error getting a token from {}: {}", realm, resp.status()));
+        }
+        let token: TokenResp = resp.json()?;
+        token
+            .token
+            .or(token.access_token)
+            .map(|t| format!("Bearer {}", t))
+            .ok_or_else(|| format_err!("No token from {}", realm))
+    }
+}
+
+impl Registry for RegistryClient {
+    fn get_digest(&self, image: &ImageRef) -> Result<String> {
+        let url = format!("{}/v2/{}/manifests/{}", image.api_base(), image.repository, image.tag());
+        let mut resp = self.head(&url, None)?;
+        if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+            let challenge = resp
+                .headers()
+                .get(reqwest::header::WWW_AUTHENTICATE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_challenge)
+                .ok_or_else(|| format_err!("{} refused the request without saying how to log in", image.registry))?;
+            let authorization = self.authorization(image, &challenge)?;
+            resp = self.head(&url, Some(&authorization))?;
+        }
+        if !resp.status().is_success() {
+            return Err(format_err!("Error getting the manifest of {}: {}", image.key(), resp.status()));
+        }
+
+        resp.headers()
+            .get("docker-content-digest")
+            .and_then(|v| v.to_str().ok())
+            .map(|d| d.to_string())
+            .ok_or_else(|| format_err!("{} didn't say the digest of {}", image.registry, image.key()))
+    }
+}
+
+// The digest each watched image had when its repos were last updated
+pub struct ImageDigests {
+    db: Database,
+}
+
+impl ImageDigests {
+    pub fn new(db: Database) -> ImageDigests {
+        ImageDigests { db: db }
+    }
+
+    pub fn get(&self, image: &str) -> Result<Option<String>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare("SELECT digest FROM container_image_digests WHERE image = ?1")?;
+        let mut rows = stmt.query(&[&image])?;
+        if let Ok(Some(row)) = rows.next() {
+            Ok(Some(row.get(0)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn set(&self, image: &str, digest: &str, now: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO container_image_digests (image, digest, updated_at)
+               VALUES (?1, ?2, ?3)
+               ON CONFLICT (image) DO UPDATE SET digest = excluded.digest, updated_at = excluded.updated_at"#,
+            &[&image as &dyn ToSql, &digest, &now],
+        )
+        .map_err(|e| format_err!("Error saving the digest of {}: {}", image, e))?;
+        Ok(())
+    }
+}
+
+fn owner_and_name(repo: &str) -> Result<(&str, &str)> {
+    let mut parts = repo.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(owner), Some(name)) if !owner.is_empty() && !name.is_empty() => Ok((owner, name)),
+        _ => Err(format_err!("Invalid repo: {}", repo)),
+    }
+}
+
+// Checks the watched images for new digests, and opens PRs updating the repos that pin them
+pub struct ImageWatcher {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    registry: Arc<dyn Registry>,
+}
+
+impl ImageWatcher {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        clone_mgr: Arc<GitCloneManager>,
+        registry: Arc<dyn Registry>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(ImageWatcher {
+            config: config,
+            github_app: github_app,
+            clone_mgr: clone_mgr,
+            registry: registry,
+        })
+    }
+
+    // Returns the PR opened, if the repo pins the image to an older digest
+    fn update_repo(
+        &self,
+        repo_name: &str,
+        image: &ImageRef,
+        watched: &ContainerImageConfig,
+        digest: &str,
+    ) -> Result<Option<u32>> {
+        let (owner, repo) = owner_and_name(repo_name)?;
+        let session = self.github_app.new_session(owner, repo)?;
+        let held_clone_dir = GitCloneManager::clone(&self.clone_mgr, owner, repo)?;
+        let github_repo = github::Repo::parse(&format!("https://{}/{}", session.github_host(), repo_name))?;
+        let git = Git::new(session.github_host(), session.github_token(), held_clone_dir.dir())
+            .with_signing(self.config.signing.as_ref())
+            .with_dry_run(self.config.dry_run(&github_repo));
+
+        let branch = update_branch_name(image, digest);
+        let current_remotes = git.run(&["ls-remote", "--heads"])?;
+        if current_remotes.contains(&format!("refs/heads/{}", branch)) {
+            info!("Image update branch already exists on origin: '{}'", branch);
+            return Ok(None);
+        }
+
+        let base_branch = git.default_branch()?;
+        git.checkout_branch(&branch, &format!("origin/{}", base_branch))?;
+
+        let dockerfiles = match watched.dockerfiles {
+            Some(ref files) => files.clone(),
+            None => find_dockerfiles(held_clone_dir.dir())?,
+        };
+        let mut updated = vec![];
+        for file in dockerfiles {
+            let path = held_clone_dir.dir().join(&file);
+            let contents = match fs::read_to_string(&path) {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("Error reading {} in {}: {}", file, repo_name, e);
+                    continue;
+                }
+            };
+            if let Some(contents) = update_dockerfile(&contents, image, digest) {
+                fs::write(&path, contents)?;
+                git.run(&["add", "--", &file])?;
+                updated.push(file);
+            }
+        }
+        if updated.is_empty() {
+            return Ok(None);
+        }
+
+        let title = update_title(image);
+        let body = update_body(image, digest, &updated);
+        let user = format!("user.name={}", session.bot_name());
+        let email = format!("user.email={}@users.noreply.{}", session.bot_name(), session.github_host());
+        git.run_with_stdin(&["-c", &user, "-c", &email, "commit", "-F", "-"], &format!("{}\n\n{}", title, body))?;
+        git.run(&["push", "origin", &format!("HEAD:{}", branch)])?;
+
+        let new_pr = session.create_pull_request(owner, repo, &title, &body, &branch, &base_branch)?;
+        if watched.auto_merge.unwrap_or(false) {
+            let head_sha = if new_pr.head.sha.is_empty() { git.current_commit()? } else { new_pr.head.sha.clone() };
+            self.config.auto_merges.add(&AutoMerge {
+                repo: repo_name.to_string(),
+                number: new_pr.number,
+                head_sha: head_sha,
+                requested_by: session.bot_name().into(),
+            })?;
+        }
+        Ok(Some(new_pr.number))
+    }
+}
+
+impl scheduler::Task for ImageWatcher {
+    fn run(&self, now: i64) -> Result<()> {
+        for watched in self.config.container_images() {
+            let image = match ImageRef::parse(&watched.image) {
+                Some(mut i) => {
+                    if watched.tag.is_some() {
+                        i.tag = watched.tag.clone();
+                    }
+                    i
+                }
+                None => {
+                    error!("Invalid container image: {}", watched.image);
+                    continue;
+                }
+            };
+            let digest = match self.registry.get_digest(&image) {
+                Ok(d) => d,
+                Err(e) => {
+                    error!("Error checking {} for a new digest: {}", image.key(), e);
+                    continue;
+                }
+            };
+            if self.config.image_digests.get(&image.key())?.as_ref() == Some(&digest) {
+                continue;
+            }
+
+            // the digest is only saved once all repos are updated, so that failures are tried again next time
+            let mut failed = false;
+            for repo in &watched.repos {
+                match self.update_repo(repo, &image, watched, &digest) {
+                    Ok(Some(number)) => info!("Opened {}#{} to update {} to {}", repo, number, image.key(), digest),
+                    Ok(None) => (),
+                    Err(e) => {
+                        error!("Error updating {} in {}: {}", image.key(), repo, e);
+                        failed = true;
+                    }
+                }
+            }
+            if !failed {
+                self.config.image_digests.set(&image.key(), &digest, now)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashmap;
+    use tempdir::TempDir;
+
+    const OLD: &str = "sha256:1111111111111111111111111111111111111111111111111111111111111111";
+    const NEW: &str = "sha256:2222222222222222222222222222222222222222222222222222222222222222";
+
+    #[test]
+    fn test_parse_image_ref() {
+        let debian = ImageRef::parse("debian").unwrap();
+        assert_eq!("docker.io", debian.registry);
+        assert_eq!("library/debian", debian.repository);
+        assert_eq!(None, debian.tag);
+        assert_eq!("latest", debian.tag());
+        assert_eq!("docker.io/library/debian:latest", debian.key());
+
+        let pinned = ImageRef::parse(&format!("docker.io/library/debian:latest@{}", OLD)).unwrap();
+        assert_eq!(Some(OLD.to_string()), pinned.digest);
+        assert!(pinned.same_tag(&debian));
+        assert!(ImageRef::parse("index.docker.io/library/debian").unwrap().same_tag(&debian));
+        assert!(!ImageRef::parse("debian:bookworm").unwrap().same_tag(&debian));
+
+        let ghcr = ImageRef::parse("ghcr.io/some-org/base:1.2").unwrap();
+        assert_eq!("ghcr.io", ghcr.registry);
+        assert_eq!("some-org/base", ghcr.repository);
+        assert_eq!(Some("1.2".to_string()), ghcr.tag);
+
+        let port = ImageRef::parse("registry.company.com:5000/base").unwrap();
+        assert_eq!("registry.company.com:5000", port.registry);
+        assert_eq!("base", port.repository);
+        assert_eq!(None, port.tag);
+
+        assert_eq!("some-user/app", ImageRef::parse("some-user/app").unwrap().repository);
+        assert_eq!(None, ImageRef::parse(""));
+        assert_eq!(None, ImageRef::parse("debian:"));
+    }
+
+    #[test]
+    fn test_update_dockerfile() {
+        let image = ImageRef::parse("debian:bookworm").unwrap();
+        let dockerfile = format!(
+            "FROM --platform=linux/amd64 debian:bookworm@{} AS build\n\
+             RUN make\n\
+             from docker.io/library/debian:bookworm@{}\n\
+             FROM debian:bookworm\n\
+             FROM debian:bullseye@{}\n\
+             COPY --from=build /app /app\n",
+            OLD, OLD, OLD
+        );
+
+        assert_eq!(
+            Some(format!(
+                "FROM --platform=linux/amd64 debian:bookworm@{} AS build\n\
+                 RUN make\n\
+                 from docker.io/library/debian:bookworm@{}\n\
+                 FROM debian:bookworm\n\
+                 FROM debian:bullseye@{}\n\
+                 COPY --from=build /app /app\n",
+                NEW, NEW, OLD
+            )),
+            update_dockerfile(&dockerfile, &image, NEW)
+        );
+        assert_eq!(None, update_dockerfile(&dockerfile, &image, OLD));
+        assert_eq!(None, update_dockerfile("FROM debian:bookworm\n", &image, NEW));
+    }
+
+    #[test]
+    fn test_find_dockerfiles() {
+        let temp_dir = TempDir::new("container_images.rs").unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("services/api")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        for file in &["Dockerfile", "services/api/Dockerfile.prod", "services/worker.Dockerfile", ".git/Dockerfile"] {
+            fs::write(root.join(file), "FROM debian\n").unwrap();
+        }
+        fs::write(root.join("services/README.md"), "").unwrap();
+
+        assert_eq!(
+            vec!["Dockerfile", "services/api/Dockerfile.prod", "services/worker.Dockerfile"],
+            find_dockerfiles(root).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_update_description() {
+        let image = ImageRef::parse("debian:bookworm").unwrap();
+        assert_eq!("octobot/image-library-debian-bookworm-222222222222", update_branch_name(&image, NEW));
+        assert_eq!("Update debian:bookworm base image", update_title(&image));
+        assert_eq!(
+            format!(
+                "docker.io/library/debian:bookworm has a new digest: `{}`.\n\nUpdated the pins in:\n* Dockerfile",
+                NEW
+            ),
+            update_body(&image, NEW, &["Dockerfile".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_parse_challenge() {
+        assert_eq!(
+            Some(Challenge {
+                scheme: "Bearer".into(),
+                params: hashmap! {
+                    "realm".to_string() => "https://auth.docker.io/token".to_string(),
+                    "service".to_string() => "registry.docker.io".to_string(),
+                    "scope".to_string() => "repository:library/debian:pull".to_string(),
+                },
+            }),
+            parse_challenge(
+                "Bearer realm=\"https://auth.docker.io/token\",service=\"registry.docker.io\",\
+                 scope=\"repository:library/debian:pull\""
+            )
+        );
+        assert_eq!(
+            Some(Challenge {
+                scheme: "Basic".into(),
+                params: hashmap! { "realm".to_string() => "Registry".to_string() },
+            }),
+            parse_challenge("Basic realm=Registry")
+        );
+        assert_eq!(None, parse_challenge(""));
+    }
+
+    #[test]
+    fn test_image_digests() {
+        let temp_dir = TempDir::new("container_images.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        let digests = ImageDigests::new(db);
+
+        assert_eq!(None, digests.get("docker.io/library/debian:bookworm").unwrap());
+        digests.set("docker.io/library/debian:bookworm", OLD, 100).unwrap();
+        digests.set("docker.io/library/debian:bookworm", NEW, 200).unwrap();
+        assert_eq!(Some(NEW.to_string()), digests.get("docker.io/library/debian:bookworm").unwrap());
+    }
+}
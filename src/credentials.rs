@@ -0,0 +1,271 @@
+use std::fs;
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+
+use crate::compliance;
+use crate::config::Config;
+use crate::errors::*;
+use crate::scheduler;
+use crate::slack::{self, SlackAttachmentBuilder, SlackRequest};
+use crate::util;
+use crate::worker::Worker;
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+const PEM_CERT_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_CERT_END: &str = "-----END CERTIFICATE-----";
+
+// DER tags found on the way to a certificate's validity period
+const DER_SEQUENCE: u8 = 0x30;
+const DER_EXPLICIT_VERSION: u8 = 0xa0;
+const DER_UTC_TIME: u8 = 0x17;
+const DER_GENERALIZED_TIME: u8 = 0x18;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Credential {
+    pub name: String,
+    pub expires_at: i64,
+}
+
+impl Credential {
+    pub fn new(name: &str, expires_at: i64) -> Credential {
+        Credential {
+            name: name.into(),
+            expires_at: expires_at,
+        }
+    }
+
+    pub fn days_left(&self, now: i64) -> i64 {
+        (self.expires_at - now).div_euclid(SECS_PER_DAY)
+    }
+}
+
+// Returns (tag, start of contents, end of contents) of the DER element at `pos`
+fn der_element(data: &[u8], pos: usize) -> Result<(u8, usize, usize)> {
+    let truncated = || format_err!("Truncated certificate");
+
+    let tag = *data.get(pos).ok_or_else(truncated)?;
+    let first = *data.get(pos + 1).ok_or_else(truncated)? as usize;
+    let (len, start) = if first & 0x80 == 0 {
+        (first, pos + 2)
+    } else {
+        let num_bytes = first & 0x7f;
+        if num_bytes == 0 || num_bytes > 4 {
+            return Err(format_err!("Unsupported DER length encoding"));
+        }
+        let mut len = 0;
+        for i in 0..num_bytes {
+            len = (len << 8) | *data.get(pos + 2 + i).ok_or_else(truncated)? as usize;
+        }
+        (len, pos + 2 + num_bytes)
+    };
+
+    if start + len > data.len() {
+        return Err(truncated());
+    }
+    Ok((tag, start, start + len))
+}
+
+fn parse_der_time(tag: u8, value: &[u8]) -> Result<i64> {
+    let value = String::from_utf8_lossy(value);
+    let full = match tag {
+        // YYMMDDHHMMSSZ: years before 50 are 20xx
+        DER_UTC_TIME if value.len() == 13 => {
+            let century = if &value[0..2] < "50" { "20" } else { "19" };
+            format!("{}{}", century, value)
+        }
+        DER_GENERALIZED_TIME if value.len() == 15 => value.to_string(),
+        _ => return Err(format_err!("Unsupported certificate time: {}", value)),
+    };
+
+    let timestamp = format!(
+        "{}-{}-{}T{}:{}:{}Z",
+        &full[0..4],
+        &full[4..6],
+        &full[6..8],
+        &full[8..10],
+        &full[10..12],
+        &full[12..14]
+    );
+    util::parse_timestamp(&timestamp).ok_or_else(|| format_err!("Invalid certificate time: {}", value))
+}
+
+// When the DER-encoded X.509 certificate stops being valid
+pub fn der_cert_expiry(der: &[u8]) -> Result<i64> {
+    let (tag, cert_start, _) = der_element(der, 0)?;
+    let (tbs_tag, tbs_start, tbs_end) = der_element(der, cert_start)?;
+    if tag != DER_SEQUENCE || tbs_tag != DER_SEQUENCE {
+        return Err(format_err!("Not a certificate"));
+    }
+
+    // tbsCertificate: [0] version (optional), serial, signature algorithm, issuer, validity, ...
+    let mut pos = tbs_start;
+    let (first_tag, _, first_end) = der_element(der, pos)?;
+    if first_tag == DER_EXPLICIT_VERSION {
+        pos = first_end;
+    }
+    for _ in 0..3 {
+        let (_, _, end) = der_element(der, pos)?;
+        pos = end;
+    }
+
+    let (validity_tag, validity_start, validity_end) = der_element(der, pos)?;
+    if validity_tag != DER_SEQUENCE || validity_end > tbs_end {
+        return Err(format_err!("Certificate has no validity period"));
+    }
+    let (_, _, not_before_end) = der_element(der, validity_start)?;
+    let (not_after_tag, not_after_start, not_after_end) = der_element(der, not_before_end)?;
+
+    parse_der_time(not_after_tag, &der[not_after_start..not_after_end])
+}
+
+// When the first certificate in a PEM file (the server's own, in a chain) stops being valid
+pub fn pem_cert_expiry(pem: &str) -> Result<i64> {
+    let start = pem.find(PEM_CERT_BEGIN).ok_or_else(|| format_err!("No certificate found"))? + PEM_CERT_BEGIN.len();
+    let end = pem[start..].find(PEM_CERT_END).ok_or_else(|| format_err!("Unterminated certificate"))? + start;
+
+    let encoded = pem[start..end].split_whitespace().collect::<String>();
+    let der = base64::decode(&encoded).map_err(|e| format_err!("Invalid certificate: {}", e))?;
+    der_cert_expiry(&der)
+}
+
+// All credentials with a known expiry: the TLS certificate, plus any configured under [credentials]
+pub fn all(config: &Config) -> Vec<Credential> {
+    let mut creds = vec![];
+
+    if let Some(ref cert_file) = config.main.ssl_cert_file {
+        match fs::read_to_string(cert_file).map_err(|e| format_err!("{}", e)).and_then(|c| pem_cert_expiry(&c)) {
+            Ok(expires_at) => creds.push(Credential::new(&format!("TLS certificate {}", cert_file), expires_at)),
+            Err(e) => error!("Error reading expiry of {}: {}", cert_file, e),
+        };
+    }
+
+    for expiry in config.credential_expiries() {
+        match compliance::parse_date(&expiry.expires) {
+            Some(expires_at) => creds.push(Credential::new(&expiry.name, expires_at)),
+            None => error!("Invalid expiry date for credential '{}': {}", expiry.name, expiry.expires),
+        };
+    }
+
+    creds
+}
+
+// Credentials expiring within `warn_days` of `now` (or already expired), soonest first
+pub fn expiring(creds: Vec<Credential>, now: i64, warn_days: i64) -> Vec<Credential> {
+    let mut expiring = creds.into_iter().filter(|c| c.days_left(now) < warn_days).collect::<Vec<_>>();
+    expiring.sort_by_key(|c| c.expires_at);
+    expiring
+}
+
+pub fn summary(expiring: &Vec<Credential>, now: i64) -> (String, Vec<slack::SlackAttachment>) {
+    let msg = format!("{} octobot credential(s) need rotating soon", expiring.len());
+
+    let attachments = expiring
+        .iter()
+        .map(|c| {
+            let days_left = c.days_left(now);
+            let (text, color) = if c.expires_at <= now {
+                (format!("Expired {}", compliance::format_date(c.expires_at)), "danger")
+            } else {
+                (format!("Expires {} ({} days)", compliance::format_date(c.expires_at), days_left), "warning")
+            };
+            SlackAttachmentBuilder::new(&text).title(c.name.clone()).color(color).build()
+        })
+        .collect();
+
+    (msg, attachments)
+}
+
+// Reminds the ops channel every day until expiring credentials are rotated
+pub struct CredentialExpiryChecker {
+    config: Arc<Config>,
+    slack: Arc<dyn Worker<SlackRequest>>,
+}
+
+impl CredentialExpiryChecker {
+    pub fn new(config: Arc<Config>, slack: Arc<dyn Worker<SlackRequest>>) -> Arc<dyn scheduler::Task> {
+        Arc::new(CredentialExpiryChecker {
+            config: config,
+            slack: slack,
+        })
+    }
+}
+
+impl scheduler::Task for CredentialExpiryChecker {
+    fn run(&self, now: i64) -> Result<()> {
+        let channel = match self.config.credentials_channel() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+
+        let expiring = expiring(all(&self.config), now, self.config.credentials_warn_days());
+        if expiring.is_empty() {
+            return Ok(());
+        }
+        info!("Sending reminder about {} expiring credentials", expiring.len());
+
+        let (msg, attachments) = summary(&expiring, now);
+        self.slack.send(slack::req(&channel, &msg, attachments));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // self-signed, valid until 2036-10-12 10:11:08 UTC
+    const CERT: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBhDCCASmgAwIBAgIUNK4UFTy1AiDZbk6mKGYpfMImImgwCgYIKoZIzj0EAwIw
+FzEVMBMGA1UEAwwMb2N0b2JvdC50ZXN0MB4XDTI2MTAxNTEwMTEwOFoXDTM2MTAx
+MjEwMTEwOFowFzEVMBMGA1UEAwwMb2N0b2JvdC50ZXN0MFkwEwYHKoZIzj0CAQYI
+KoZIzj0DAQcDQgAE3h21k6XnKRB0fD9ro1f6adHaNEDv7yEZscHrTYHbNwu/TfwC
+CZLXzxl9GMRBstT/3iafFSFVhVgoAy8HTJlVbaNTMFEwHQYDVR0OBBYEFJl2I1Wj
+b+CnQB9CBMjh0Fx2jEu0MB8GA1UdIwQYMBaAFJl2I1Wjb+CnQB9CBMjh0Fx2jEu0
+MA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSQAwRgIhAPA3hntBsYw+Opa6
+65LGq2yH04giY/WLNHl6GysrA4+EAiEAtDHIb1NK7ij2dTtMfIyo/JJMxHB8ZiVA
+BdNpdyh9Cac=
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn test_pem_cert_expiry() {
+        assert_eq!(2107419068, pem_cert_expiry(CERT).unwrap());
+        assert!(pem_cert_expiry("not a cert").is_err());
+        assert!(pem_cert_expiry(&CERT.replace("MIIBhDCC", "MIIBhDC")).is_err());
+    }
+
+    #[test]
+    fn test_parse_der_time() {
+        assert_eq!(2107419068, parse_der_time(DER_UTC_TIME, b"361012101108Z").unwrap());
+        assert_eq!(2107419068, parse_der_time(DER_GENERALIZED_TIME, b"20361012101108Z").unwrap());
+        assert_eq!(946684800, parse_der_time(DER_UTC_TIME, b"000101000000Z").unwrap());
+        assert!(parse_der_time(DER_UTC_TIME, b"9912").is_err());
+    }
+
+    #[test]
+    fn test_expiring() {
+        let now = compliance::parse_date("2019-03-01").unwrap();
+        let creds = vec![
+            Credential::new("slack bot token", compliance::parse_date("2019-03-20").unwrap()),
+            Credential::new("github token", compliance::parse_date("2019-03-10").unwrap()),
+            Credential::new("old github token", compliance::parse_date("2019-02-01").unwrap()),
+            Credential::new("github app key", compliance::parse_date("2020-01-01").unwrap()),
+        ];
+
+        let expiring = expiring(creds, now, 14);
+        assert_eq!(
+            vec!["old github token", "github token"],
+            expiring.iter().map(|c| c.name.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(9, expiring[1].days_left(now));
+
+        let (msg, attachments) = summary(&expiring, now);
+        assert_eq!("2 octobot credential(s) need rotating soon", msg);
+        assert_eq!("Expired 2019-02-01", attachments[0].text);
+        assert_eq!("Expires 2019-03-10 (9 days)", attachments[1].text);
+    }
+}
@@ -0,0 +1,275 @@
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use failure::format_err;
+use log::error;
+
+use crate::db::store::{self, Backend, FromSql, QueryResult, Store, ToSql, Value};
+use crate::errors::*;
+
+pub const NO_PARAMS: &[&dyn ToSql] = &[];
+
+// A connection to either kind of database, with the same interface whichever it is
+pub struct Connection {
+    store: RefCell<Box<dyn Store>>,
+}
+
+impl Connection {
+    pub fn new(store: Box<dyn Store>) -> Connection {
+        Connection {
+            store: RefCell::new(store),
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.store.borrow().backend()
+    }
+
+    pub fn prepare(&self, sql: &str) -> Result<Statement<'_>> {
+        let (sql, names) = store::named_placeholders(sql);
+        let columns = self.store.borrow_mut().columns(&sql)?;
+        Ok(Statement {
+            conn: self,
+            sql: sql,
+            names: names,
+            columns: Arc::new(columns),
+        })
+    }
+
+    pub fn execute<P>(&self, sql: &str, params: P) -> Result<usize>
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+    {
+        self.store.borrow_mut().execute(sql, &values(params))
+    }
+
+    pub fn execute_named(&self, sql: &str, params: &[(&str, &dyn ToSql)]) -> Result<usize> {
+        let (sql, params) = named(sql, params)?;
+        self.store.borrow_mut().execute(&sql, &params)
+    }
+
+    pub fn execute_batch(&self, sql: &str) -> Result<()> {
+        self.store.borrow_mut().execute_batch(sql)
+    }
+
+    pub fn query_row<T, P, F>(&self, sql: &str, params: P, f: F) -> Result<T>
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+        F: FnOnce(&Row) -> Result<T>,
+    {
+        let result = self.store.borrow_mut().query(sql, &values(params))?;
+        first_row(result, f)
+    }
+
+    pub fn query_row_named<T, F>(&self, sql: &str, params: &[(&str, &dyn ToSql)], f: F) -> Result<T>
+    where
+        F: FnOnce(&Row) -> Result<T>,
+    {
+        let (sql, params) = named(sql, params)?;
+        let result = self.store.borrow_mut().query(&sql, &params)?;
+        first_row(result, f)
+    }
+
+    pub fn last_insert_rowid(&self) -> Result<i64> {
+        self.store.borrow_mut().last_insert_rowid()
+    }
+
+    // Rolled back when dropped without being committed
+    pub fn transaction(&mut self) -> Result<Transaction<'_>> {
+        self.execute_batch("BEGIN")?;
+        Ok(Transaction {
+            conn: self,
+            done: false,
+        })
+    }
+}
+
+pub struct Transaction<'conn> {
+    conn: &'conn Connection,
+    done: bool,
+}
+
+impl<'conn> Transaction<'conn> {
+    pub fn commit(mut self) -> Result<()> {
+        self.done = true;
+        self.conn.execute_batch("COMMIT")
+    }
+
+    pub fn rollback(mut self) -> Result<()> {
+        self.done = true;
+        self.conn.execute_batch("ROLLBACK")
+    }
+}
+
+impl<'conn> Deref for Transaction<'conn> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
+impl<'conn> Drop for Transaction<'conn> {
+    fn drop(&mut self) {
+        if !self.done {
+            if let Err(e) = self.conn.execute_batch("ROLLBACK") {
+                error!("Error rolling back transaction: {}", e);
+            }
+        }
+    }
+}
+
+pub struct Statement<'conn> {
+    conn: &'conn Connection,
+    // with any `:name` parameters made positional, in the order of |names|
+    sql: String,
+    names: Vec<String>,
+    columns: Arc<Vec<String>>,
+}
+
+impl<'conn> Statement<'conn> {
+    pub fn column_names(&self) -> Vec<&str> {
+        self.columns.iter().map(|c| c.as_str()).collect()
+    }
+
+    pub fn column_index(&self, name: &str) -> Result<usize> {
+        name.index(&self.columns)
+    }
+
+    pub fn execute<P>(&mut self, params: P) -> Result<usize>
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+    {
+        self.conn.execute(&self.sql, params)
+    }
+
+    pub fn query<P>(&mut self, params: P) -> Result<Rows>
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+    {
+        let result = self.conn.store.borrow_mut().query(&self.sql, &values(params))?;
+        Ok(Rows::new(result))
+    }
+
+    pub fn query_named(&mut self, params: &[(&str, &dyn ToSql)]) -> Result<Rows> {
+        let params = named_values(&self.names, params)?;
+        let result = self.conn.store.borrow_mut().query(&self.sql, &params)?;
+        Ok(Rows::new(result))
+    }
+
+    pub fn query_map<T, P, F>(&mut self, params: P, f: F) -> Result<std::vec::IntoIter<Result<T>>>
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+        F: FnMut(&Row) -> Result<T>,
+    {
+        let rows = self.query(params)?;
+        Ok(rows.rows.iter().map(f).collect::<Vec<_>>().into_iter())
+    }
+}
+
+pub struct Rows {
+    rows: Vec<Row>,
+    next: usize,
+}
+
+impl Rows {
+    fn new(result: QueryResult) -> Rows {
+        let columns = Arc::new(result.columns);
+        Rows {
+            rows: result
+                .rows
+                .into_iter()
+                .map(|values| Row {
+                    columns: columns.clone(),
+                    values: values,
+                })
+                .collect(),
+            next: 0,
+        }
+    }
+
+    pub fn next(&mut self) -> Result<Option<&Row>> {
+        self.next += 1;
+        Ok(self.rows.get(self.next - 1))
+    }
+}
+
+pub struct Row {
+    columns: Arc<Vec<String>>,
+    values: Vec<Value>,
+}
+
+impl Row {
+    pub fn get<I: RowIndex, T: FromSql>(&self, idx: I) -> Result<T> {
+        let index = idx.index(&self.columns)?;
+        let value = self.values.get(index).ok_or_else(|| format_err!("Invalid column index {}", index))?;
+        T::from_sql(value).map_err(|e| format_err!("Error getting column {}: {}", self.columns[index], e))
+    }
+}
+
+// A column, by index or by name
+pub trait RowIndex {
+    fn index(&self, columns: &[String]) -> Result<usize>;
+}
+
+impl RowIndex for usize {
+    fn index(&self, columns: &[String]) -> Result<usize> {
+        if *self < columns.len() {
+            Ok(*self)
+        } else {
+            Err(format_err!("Invalid column index {}", self))
+        }
+    }
+}
+
+impl<'a> RowIndex for &'a str {
+    fn index(&self, columns: &[String]) -> Result<usize> {
+        columns
+            .iter()
+            .position(|c| c == self)
+            .ok_or_else(|| format_err!("Invalid column '{}'", self))
+    }
+}
+
+fn values<P>(params: P) -> Vec<Value>
+where
+    P: IntoIterator,
+    P::Item: ToSql,
+{
+    params.into_iter().map(|p| p.to_sql()).collect()
+}
+
+fn named(sql: &str, params: &[(&str, &dyn ToSql)]) -> Result<(String, Vec<Value>)> {
+    let (sql, names) = store::named_placeholders(sql);
+    Ok((sql, named_values(&names, params)?))
+}
+
+fn named_values(names: &[String], params: &[(&str, &dyn ToSql)]) -> Result<Vec<Value>> {
+    names
+        .iter()
+        .map(|name| {
+            params
+                .iter()
+                .find(|p| p.0 == name)
+                .map(|p| p.1.to_sql())
+                .ok_or_else(|| format_err!("Missing parameter {}", name))
+        })
+        .collect()
+}
+
+fn first_row<T, F>(result: QueryResult, f: F) -> Result<T>
+where
+    F: FnOnce(&Row) -> Result<T>,
+{
+    let mut rows = Rows::new(result);
+    match rows.next()? {
+        Some(row) => f(row),
+        None => Err(format_err!("Query returned no rows")),
+    }
+}
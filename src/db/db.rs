@@ -1,44 +1,190 @@
 use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use failure::format_err;
-use log::error;
-use rusqlite::types::FromSql;
-use rusqlite::{Connection, Row, Statement};
+use log::{error, warn};
 
+use crate::db::migrations::{self, SchemaStatus};
+use crate::db::postgres_store::{self, PostgresPool};
+use crate::db::sqlite_store::SqliteStore;
+use crate::db::store::{Backend, FromSql};
+use crate::db::{Connection, Row, Statement, NO_PARAMS};
 use crate::errors::*;
 
 #[derive(Clone)]
 pub struct Database {
-    db_file: String,
+    location: Location,
+    replica: Option<ReadReplica>,
+}
+
+// Either a sqlite file, or a PostgreSQL database given by its connection string
+#[derive(Clone)]
+enum Location {
+    Sqlite(String),
+    Postgres(Arc<PostgresPool>),
+}
+
+impl Location {
+    fn new(db: &str) -> Location {
+        if postgres_store::is_postgres_url(db) {
+            Location::Postgres(Arc::new(PostgresPool::new(db)))
+        } else {
+            Location::Sqlite(db.to_string())
+        }
+    }
+
+    fn name(&self) -> String {
+        match *self {
+            Location::Sqlite(ref db_file) => db_file.clone(),
+            Location::Postgres(ref pool) => pool.name(),
+        }
+    }
+}
+
+// A read-only copy of the database (e.g. kept in sync by litestream or a periodic backup, or a postgres standby)
+// used to keep heavy reporting queries off of the primary.
+#[derive(Clone)]
+struct ReadReplica {
+    location: Location,
+    max_lag: Duration,
 }
 
 impl Database {
-    pub fn new(db_file: &str) -> Result<Database> {
-        let mut db = Database {
-            db_file: db_file.to_string(),
-        };
+    // |db| is the sqlite file, or a "postgres://" connection string
+    pub fn new(db: &str) -> Result<Database> {
+        let mut db = Database::open(db);
 
         db.migrate()?;
         Ok(db)
     }
 
+    // Opens the database without migrating it, e.g. to inspect or roll back its schema
+    pub fn open(db: &str) -> Database {
+        Database {
+            location: Location::new(db),
+            replica: None,
+        }
+    }
+
+    pub fn with_read_replica(self, replica: &str, max_lag: Duration) -> Database {
+        let mut db = self;
+        db.replica = Some(ReadReplica {
+            location: Location::new(replica),
+            max_lag: max_lag,
+        });
+        db
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.location {
+            Location::Sqlite(_) => Backend::Sqlite,
+            Location::Postgres(_) => Backend::Postgres,
+        }
+    }
+
     pub fn connect(&self) -> Result<Connection> {
-        Connection::open(&self.db_file)
-            .map_err(|e| format_err!("Error opening database {}: {}", self.db_file, e))
+        match self.location {
+            Location::Sqlite(ref db_file) => Ok(Connection::new(Box::new(SqliteStore::open(db_file)?))),
+            Location::Postgres(ref pool) => Ok(Connection::new(Box::new(PostgresPool::connect(pool)?))),
+        }
+    }
+
+    // Connect for read-only analytics queries. Uses the read replica if one is configured and
+    // it is within the configured lag of the primary; otherwise falls back to the primary.
+    pub fn connect_read(&self) -> Result<Connection> {
+        let replica = match self.replica {
+            Some(ref r) => r,
+            None => return self.connect(),
+        };
+
+        match self.replica_lag(replica) {
+            Some(lag) if lag <= replica.max_lag => (),
+            Some(lag) => {
+                warn!("Read replica {} is {}s behind: using primary", replica.location.name(), lag.as_secs());
+                return self.connect();
+            }
+            None => {
+                warn!("Could not determine lag for read replica {}: using primary", replica.location.name());
+                return self.connect();
+            }
+        };
+
+        let conn = match replica.location {
+            Location::Sqlite(ref db_file) => SqliteStore::open_read_only(db_file).map(|s| Connection::new(Box::new(s))),
+            Location::Postgres(ref pool) => PostgresPool::connect(pool).map(|s| Connection::new(Box::new(s))),
+        };
+        conn.or_else(|e| {
+            error!("Error opening read replica {}: {}", replica.location.name(), e);
+            self.connect()
+        })
+    }
+
+    fn replica_lag(&self, replica: &ReadReplica) -> Option<Duration> {
+        let db_file = match (&self.location, &replica.location) {
+            (&Location::Sqlite(ref db_file), &Location::Sqlite(_)) => db_file,
+            (_, &Location::Postgres(ref pool)) => return Self::standby_lag(pool),
+            _ => return None,
+        };
+        let modified = |file: &str| fs::metadata(file).and_then(|m| m.modified()).ok();
+
+        // in WAL mode, recent writes land in the -wal file
+        let primary = match (modified(db_file), modified(&format!("{}-wal", db_file))) {
+            (Some(db), Some(wal)) => std::cmp::max(db, wal),
+            (Some(db), None) => db,
+            _ => return None,
+        };
+        let replica: SystemTime = match replica.location {
+            Location::Sqlite(ref replica_file) => modified(replica_file)?,
+            Location::Postgres(_) => return None,
+        };
+
+        Some(primary.duration_since(replica).unwrap_or(Duration::from_secs(0)))
+    }
+
+    // How far behind the primary a postgres standby has replayed. None if it isn't a standby. A standby that replayed
+    // all it received is caught up, however long ago the primary last wrote.
+    fn standby_lag(pool: &Arc<PostgresPool>) -> Option<Duration> {
+        let conn = Connection::new(Box::new(PostgresPool::connect(pool).ok()?));
+        let lag: Option<i64> = conn
+            .query_row(
+                r#"SELECT CASE WHEN pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() THEN 0
+                               ELSE CAST(EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp()) AS BIGINT) END"#,
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .ok()?;
+
+        lag.map(|l| Duration::from_secs(l.max(0) as u64))
     }
 
     fn migrate(&mut self) -> Result<()> {
         let mut conn = self.connect()?;
-        let mode: String = conn
-            .query_row("PRAGMA journal_mode=WAL", rusqlite::NO_PARAMS, |row| row.get(0))
-            .map_err(|e| format_err!("Error turning on WAL mode: {}", e))?;
+        if let Location::Sqlite(_) = self.location {
+            let mode: String = conn
+                .query_row("PRAGMA journal_mode=WAL", NO_PARAMS, |row| row.get(0))
+                .map_err(|e| format_err!("Error turning on WAL mode: {}", e))?;
 
-        if mode.trim() != "wal" {
-            error!("Error setting WAL mode. Result: {}", mode);
+            if mode.trim() != "wal" {
+                error!("Error setting WAL mode. Result: {}", mode);
+            }
         }
 
         crate::db::migrations::migrate(&mut conn)
     }
+
+    pub fn schema_status(&self) -> Result<SchemaStatus> {
+        migrations::status(&self.connect()?)
+    }
+
+    pub fn migrate_to(&self, version: i32) -> Result<()> {
+        migrations::migrate_to(&mut self.connect()?, version)
+    }
+
+    pub fn rollback_to(&self, version: i32) -> Result<()> {
+        migrations::rollback_to(&mut self.connect()?, version)
+    }
 }
 
 pub struct Columns {
@@ -68,7 +214,6 @@ impl Columns {
     pub fn get<T: FromSql>(&self, row: &Row, col: &str) -> Result<T> {
         let index = self.get_index(col)?;
         row.get(index)
-            .map_err(|e| format_err!("Error getting column {}: {}", col, e))
     }
 }
 
@@ -84,6 +229,13 @@ pub fn to_string_vec(val: String) -> Vec<String> {
     }
 }
 
+// How long soft-deleted users and repos can be restored before they are purged
+pub const DELETED_RETENTION_SECS: i64 = 30 * 24 * 60 * 60;
+
+pub fn now() -> i64 {
+    time::get_time().sec
+}
+
 pub fn to_bool(val: i32) -> bool {
     val != 0
 }
@@ -99,6 +251,47 @@ pub fn to_tinyint(val: bool) -> i8 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempdir::TempDir;
+
+    fn db_list(conn: &Connection) -> String {
+        conn.query_row("PRAGMA database_list", NO_PARAMS, |row| row.get(2))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_connect_read_no_replica() {
+        let temp_dir = TempDir::new("db.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).unwrap();
+
+        assert!(db_list(&db.connect_read().unwrap()).ends_with("db.sqlite3"));
+    }
+
+    #[test]
+    fn test_connect_read_replica() {
+        let temp_dir = TempDir::new("db.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let replica_file = temp_dir.path().join("replica.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).unwrap();
+        fs::copy(&db_file, &replica_file).unwrap();
+
+        let db = db.with_read_replica(&replica_file.to_string_lossy(), Duration::from_secs(60));
+
+        assert!(db_list(&db.connect_read().unwrap()).ends_with("replica.sqlite3"));
+        assert!(db_list(&db.connect().unwrap()).ends_with("db.sqlite3"));
+    }
+
+    #[test]
+    fn test_connect_read_missing_replica() {
+        let temp_dir = TempDir::new("db.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let replica_file = temp_dir.path().join("replica.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy())
+            .unwrap()
+            .with_read_replica(&replica_file.to_string_lossy(), Duration::from_secs(60));
+
+        assert!(db_list(&db.connect_read().unwrap()).ends_with("db.sqlite3"));
+    }
 
     #[test]
     fn test_bools() {
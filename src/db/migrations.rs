@@ -1,8 +1,8 @@
 use failure::format_err;
 use log::info;
-use rusqlite::{Connection, Transaction};
 
 use crate::db::migrations_code;
+use crate::db::{Backend, Connection, Transaction};
 use crate::errors::*;
 
 const CREATE_VERSIONS: &'static str = r#"
@@ -11,10 +11,20 @@ create table __version ( current_version integer primary key )
 
 pub trait Migration {
     fn run(&self, tx: &Transaction) -> Result<()>;
+
+    fn can_rollback(&self) -> bool {
+        false
+    }
+
+    // Undoes run(), for going back to the previous schema version
+    fn rollback(&self, _tx: &Transaction) -> Result<()> {
+        Err(format_err!("Migration can't be rolled back"))
+    }
 }
 
 struct SQLMigration {
     sql: &'static str,
+    rollback: Option<&'static str>,
 }
 
 impl Migration for SQLMigration {
@@ -22,13 +32,41 @@ impl Migration for SQLMigration {
         tx.execute_batch(self.sql)
             .map_err(|e| format_err!("Error running migration: \n---\n{}\n---\n. Error: {}", self.sql, e))
     }
+
+    fn can_rollback(&self) -> bool {
+        self.rollback.is_some()
+    }
+
+    fn rollback(&self, tx: &Transaction) -> Result<()> {
+        let sql = self.rollback.ok_or_else(|| format_err!("Migration can't be rolled back"))?;
+        tx.execute_batch(sql)
+            .map_err(|e| format_err!("Error rolling back migration: \n---\n{}\n---\n. Error: {}", sql, e))
+    }
 }
 
 fn sql(s: &'static str) -> Box<dyn Migration> {
-    Box::new(SQLMigration { sql: s })
+    Box::new(SQLMigration {
+        sql: s,
+        rollback: None,
+    })
 }
 
-fn all_migrations() -> Vec<Box<dyn Migration>> {
+// Note: columns can't be dropped in sqlite, so rolling back an added column means rebuilding the table
+fn reversible(s: &'static str, rollback: &'static str) -> Box<dyn Migration> {
+    Box::new(SQLMigration {
+        sql: s,
+        rollback: Some(rollback),
+    })
+}
+
+fn all_migrations(backend: Backend) -> Vec<Box<dyn Migration>> {
+    match backend {
+        Backend::Sqlite => sqlite_migrations(),
+        Backend::Postgres => postgres_migrations(),
+    }
+}
+
+fn sqlite_migrations() -> Vec<Box<dyn Migration>> {
     vec![
         sql(r#"
     create table users (
@@ -57,7 +95,8 @@ fn all_migrations() -> Vec<Box<dyn Migration>> {
     "#),
         sql(r#"alter table users add column mute_direct_messages tinyint not null default 0"#),
         sql(r#"alter table repos add column next_branch_suffix varchar not null default ''"#),
-        sql(r#"
+        reversible(
+            r#"
     create table repos_jiras (
         repo_id integer not null,
         jira varchar not null,
@@ -67,7 +106,9 @@ fn all_migrations() -> Vec<Box<dyn Migration>> {
 
         PRIMARY KEY( repo_id, jira )
     );
-    "#),
+    "#,
+            "drop table repos_jiras;",
+        ),
         Box::new(migrations_code::MigrationReposJiras {}),
         sql(r#"
     create table repos_new (
@@ -89,12 +130,1649 @@ fn all_migrations() -> Vec<Box<dyn Migration>> {
 
     alter table repos_new rename to repos;
     "#),
+        sql(r#"
+    alter table repos add column codeowners_reviews tinyint not null default 0;
+    alter table repos add column codeowners_ignore_bots tinyint not null default 0;
+    "#),
+        reversible(
+            r#"
+    create table jobs (
+      id integer not null,
+      kind varchar not null,
+      description varchar not null,
+      status varchar not null,
+      total_items integer not null,
+      done_items integer not null,
+      cancel_requested tinyint not null,
+      created_at integer not null,
+      updated_at integer not null,
+
+      PRIMARY KEY( id )
+    );
+
+    create table job_items (
+      job_id integer not null,
+      item varchar not null,
+      success tinyint not null,
+      message varchar not null
+    );
+    "#,
+            "drop table job_items; drop table jobs;",
+        ),
+        reversible(
+            r#"
+    create table repos_path_labels (
+        repo_id integer not null,
+        path varchar not null,
+        label varchar not null
+    );
+    "#,
+            "drop table repos_path_labels;",
+        ),
+        sql(r#"
+    alter table repos add column size_labels tinyint not null default 0;
+    alter table repos add column size_label_thresholds varchar not null default '';
+    alter table repos add column size_label_excludes varchar not null default '';
+    "#),
+        sql(r#"
+    alter table users add column deleted_at integer not null default 0;
+    alter table repos add column deleted_at integer not null default 0;
+    "#),
+        sql(r#"
+    alter table repos add column stale_pr_days integer not null default 0;
+    alter table repos add column stale_pr_digest tinyint not null default 0;
+    alter table repos add column stale_pr_quiet_days varchar not null default '';
+    "#),
+        sql(r#"
+    alter table repos add column lint_conventional tinyint not null default 0;
+    alter table repos add column lint_title_regex varchar not null default '';
+    alter table repos add column lint_commit_regex varchar not null default '';
+    alter table repos add column lint_check_run tinyint not null default 0;
+    "#),
+        sql(r#"
+    alter table repos add column webhook_secret varchar not null default '';
+    "#),
+        reversible(
+            r#"
+    create table audit_log (
+      id integer not null,
+      created_at integer not null,
+      actor varchar not null,
+      action varchar not null,
+      target varchar not null,
+      details varchar not null,
+
+      PRIMARY KEY( id )
+    );
+    "#,
+            "drop table audit_log;",
+        ),
+        sql(r#"
+    alter table repos add column conflict_notify tinyint not null default 0;
+
+    create table pr_conflicts (
+      repo varchar not null,
+      number integer not null,
+      base_sha varchar not null,
+      notified_at integer not null,
+
+      PRIMARY KEY( repo, number )
+    );
+    "#),
+        reversible(
+            r#"
+    create table event_diagnoses (
+      delivery_id varchar not null,
+      event varchar not null,
+      action varchar not null,
+      repo varchar not null,
+      result varchar not null,
+      notifications integer not null,
+      steps varchar not null,
+      created_at integer not null,
+
+      PRIMARY KEY( delivery_id )
+    );
+    "#,
+            "drop table event_diagnoses;",
+        ),
+        reversible(
+            r#"
+    create table auto_merges (
+      repo varchar not null,
+      number integer not null,
+      head_sha varchar not null,
+      requested_by varchar not null,
+      created_at integer not null,
+
+      PRIMARY KEY( repo, number )
+    );
+    "#,
+            "drop table auto_merges;",
+        ),
+        sql(r#"
+    alter table repos add column subscribed_channels varchar not null default '';
+    "#),
+        sql(r#"
+    alter table users add column muted_until integer not null default 0;
+    "#),
+        sql(r#"
+    alter table repos add column slack_threads tinyint not null default 0;
+    "#),
+        reversible(
+            r#"
+    create table slack_threads (
+      thread_key varchar not null,
+      channel varchar not null,
+      ts varchar not null,
+      created_at integer not null,
+
+      PRIMARY KEY( thread_key, channel )
+    );
+    "#,
+            "drop table slack_threads;",
+        ),
+        sql(r#"
+    alter table repos add column ecosystem varchar not null default '';
+    alter table repos add column version_files varchar not null default '';
+    alter table repos add column lockfiles varchar not null default '';
+    alter table repos add column changelog_file varchar not null default '';
+    "#),
+        reversible(
+            r#"
+    create table repos_components (
+        repo_id integer not null,
+        name varchar not null,
+        path varchar not null,
+        version_script varchar not null default '',
+        jira varchar not null default ''
+    );
+
+    create table component_versions (
+        repo varchar not null,
+        component varchar not null,
+        branch varchar not null,
+        version varchar not null,
+        commit_hash varchar not null,
+        updated_at integer not null,
+        primary key (repo, component, branch)
+    );
+    "#,
+            "drop table component_versions; drop table repos_components;",
+        ),
+        sql(r#"
+    alter table users add column dm_events varchar not null default '';
+    alter table users add column quiet_hours_start varchar not null default '';
+    alter table users add column quiet_hours_end varchar not null default '';
+    alter table users add column timezone varchar not null default '';
+    "#),
+        sql(r#"
+    alter table repos add column channel_digest varchar not null default '';
+    alter table users add column digest varchar not null default '';
+
+    create table digest_items (
+        repo varchar not null,
+        kind varchar not null,
+        item varchar not null,
+        github_user varchar not null,
+        title varchar not null,
+        url varchar not null,
+        updated_at integer not null,
+        primary key (repo, kind, item, github_user)
+    );
+    "#),
+        reversible(
+            r#"
+    create table repos_submodules (
+        repo_id integer not null,
+        upstream varchar not null,
+        path varchar not null,
+        auto_merge tinyint not null default 0
+    );
+    "#,
+            "drop table repos_submodules;",
+        ),
+        sql(r#"
+    alter table repos add column depends_on varchar not null default '';
+    "#),
+        sql(r#"
+    alter table repos add column sbom tinyint not null default 0;
+    alter table repos add column sbom_script varchar not null default '';
+
+    create table release_sboms (
+        repo varchar not null,
+        tag varchar not null,
+        commit_hash varchar not null,
+        asset_name varchar not null,
+        asset_url varchar not null,
+        sha256 varchar not null,
+        source varchar not null,
+        recorded_at integer not null,
+        primary key (repo, tag)
+    );
+    "#),
+        sql(r#"
+    alter table users add column email varchar not null default '';
+    "#),
+        sql(r#"
+    alter table repos add column provenance tinyint not null default 0;
+
+    create table release_attestations (
+        repo varchar not null,
+        tag varchar not null,
+        document text not null,
+        sha256 varchar not null,
+        recorded_at integer not null,
+        primary key (repo, tag)
+    );
+    "#),
+        reversible(
+            r#"
+    create table merge_records (
+        repo varchar not null,
+        number integer not null,
+        title varchar not null,
+        html_url varchar not null,
+        base_branch varchar not null,
+        author varchar not null,
+        merged_by varchar not null,
+        merged_at integer not null,
+        approvers varchar not null,
+        tickets varchar not null,
+        overrides varchar not null,
+        primary key (repo, number)
+    );
+
+    create index merge_records_merged_at on merge_records (merged_at);
+    "#,
+            "drop table merge_records;",
+        ),
+        reversible(
+            r#"
+    create table account_logins (
+        user varchar not null primary key,
+        admin tinyint not null,
+        first_login integer not null,
+        last_login integer not null,
+        revoked_at integer,
+        revoked_by varchar
+    );
+    "#,
+            "drop table account_logins;",
+        ),
+        reversible(
+            r#"
+    create table repos_routing_rules (
+        repo_id integer not null,
+        branch varchar not null,
+        path varchar not null,
+        label varchar not null,
+        channels varchar not null
+    );
+    "#,
+            "drop table repos_routing_rules;",
+        ),
+        reversible(
+            r#"
+    create table team_channels (
+        team varchar not null primary key,
+        channel varchar not null
+    );
+    "#,
+            "drop table team_channels;",
+        ),
+        reversible(
+            r#"
+    create table code_freezes (
+        org varchar not null primary key,
+        until integer not null,
+        frozen_by varchar not null,
+        created_at integer not null
+    );
+
+    create table code_freeze_pull_requests (
+        org varchar not null,
+        repo varchar not null,
+        number integer not null,
+        exception_by varchar not null default '',
+        primary key (repo, number)
+    );
+    "#,
+            "drop table code_freeze_pull_requests; drop table code_freezes;",
+        ),
+        reversible(
+            r#"
+    create table slack_messages (
+        channel_id varchar not null,
+        ts varchar not null,
+        thread_key varchar not null,
+        created_at integer not null,
+
+        PRIMARY KEY( channel_id, ts )
+    );
+    "#,
+            "drop table slack_messages;",
+        ),
+        sql(r#"
+    alter table repos add column smart_commits tinyint not null default 0;
+    alter table repos add column smart_commit_commands varchar not null default '';
+
+    create table jira_smart_commits (
+        repo varchar not null,
+        sha varchar not null,
+        applied_at integer not null,
+
+        PRIMARY KEY( repo, sha )
+    );
+    "#),
+        sql(r#"
+    alter table repos add column slack_pr_bridge tinyint not null default 0;
+    "#),
+        sql(r#"
+    alter table repos add column jira_release_versions tinyint not null default 0;
+    "#),
+        sql(r#"
+    alter table repos add column huddle_comments integer not null default 0;
+
+    create table review_comments (
+        id integer not null,
+        repo varchar not null,
+        number integer not null,
+        login varchar not null,
+        created_at integer not null,
+
+        PRIMARY KEY( id )
+    );
+    create index review_comments_pr on review_comments (repo, number);
+
+    create table huddle_suggestions (
+        repo varchar not null,
+        number integer not null,
+        suggested_at integer not null,
+
+        PRIMARY KEY( repo, number )
+    );
+    "#),
+        sql(r#"
+    alter table event_diagnoses add column number integer not null default 0;
+
+    create index event_diagnoses_pr on event_diagnoses (repo, number);
+    "#),
+        reversible(
+            r#"
+    create table critical_alerts (
+        id integer not null,
+        repo varchar not null,
+        branch varchar not null,
+        kind varchar not null,
+        message varchar not null,
+        tier integer not null,
+        escalate_at integer not null,
+        acked_by varchar not null,
+        acked_at integer not null,
+        created_at integer not null,
+
+        PRIMARY KEY( id )
+    );
+    create index critical_alerts_escalate_at on critical_alerts (escalate_at);
+    "#,
+            "drop table critical_alerts;",
+        ),
+        reversible(
+            r#"
+    create table repo_mutes (
+        repo varchar not null,
+        start_at integer not null,
+        until integer not null,
+        muted_by varchar not null,
+        created_at integer not null,
+
+        PRIMARY KEY( repo )
+    );
+
+    create table repo_muted_messages (
+        id integer not null,
+        repo varchar not null,
+        msg varchar not null,
+        created_at integer not null,
+
+        PRIMARY KEY( id )
+    );
+    create index repo_muted_messages_repo on repo_muted_messages (repo);
+    "#,
+            "drop table repo_muted_messages; drop table repo_mutes;",
+        ),
+        reversible(
+            r#"
+    create table repos_merge_strategies (
+        repo_id integer not null,
+        branch varchar not null,
+        strategy varchar not null,
+        commit_template varchar not null
+    );
+    "#,
+            "drop table repos_merge_strategies;",
+        ),
+        reversible(
+            r#"
+    create table repo_files (
+        repo varchar not null,
+        contents varchar not null,
+        error varchar not null,
+        fetched_at integer not null,
+
+        PRIMARY KEY( repo )
+    );
+    "#,
+            "drop table repo_files;",
+        ),
+        reversible(
+            r#"
+    create table change_requests (
+        repo varchar not null,
+        number integer not null,
+        branch varchar not null,
+        change_number varchar not null,
+        sys_id varchar not null,
+        approval varchar not null,
+        created_at integer not null,
+
+        PRIMARY KEY( repo, number )
+    );
+    "#,
+            "drop table change_requests;",
+        ),
+        reversible(
+            r#"
+    create table blocked_tags (
+        repo varchar not null,
+        tag varchar not null,
+        change_number varchar not null,
+        blocked_at integer not null,
+
+        PRIMARY KEY( repo, tag )
+    );
+    "#,
+            "drop table blocked_tags;",
+        ),
+        reversible(
+            r#"
+    alter table repos_routing_rules add column opsgenie varchar not null default '';
+    "#,
+            r#"
+    create table repos_routing_rules_old (
+        repo_id integer not null,
+        branch varchar not null,
+        path varchar not null,
+        label varchar not null,
+        channels varchar not null
+    );
+
+    insert into repos_routing_rules_old
+        select repo_id, branch, path, label, channels
+        from repos_routing_rules;
+
+    drop table repos_routing_rules;
+
+    alter table repos_routing_rules_old rename to repos_routing_rules;
+    "#,
+        ),
+        reversible(
+            r#"
+    create table provider_incidents (
+        id integer not null,
+        provider varchar not null,
+        indicator varchar not null,
+        description varchar not null,
+        started_at integer not null,
+        resolved_at integer not null,
+
+        PRIMARY KEY( id )
+    );
+
+    create table provider_paused_messages (
+        id integer not null,
+        incident_id integer not null,
+        msg varchar not null,
+        created_at integer not null,
+
+        PRIMARY KEY( id )
+    );
+    create index provider_paused_messages_incident_id on provider_paused_messages (incident_id);
+    "#,
+            "drop table provider_paused_messages; drop table provider_incidents;",
+        ),
+        reversible(
+            r#"
+    alter table event_diagnoses add column sender varchar not null default '';
+    alter table event_diagnoses add column summary varchar not null default '';
+    alter table event_diagnoses add column status integer not null default 200;
+    alter table event_diagnoses add column error varchar not null default '';
+
+    create index event_diagnoses_created_at on event_diagnoses (created_at);
+    "#,
+            r#"
+    create table event_diagnoses_old (
+      delivery_id varchar not null,
+      event varchar not null,
+      action varchar not null,
+      repo varchar not null,
+      result varchar not null,
+      notifications integer not null,
+      steps varchar not null,
+      created_at integer not null,
+      number integer not null default 0,
+
+      PRIMARY KEY( delivery_id )
+    );
+
+    insert into event_diagnoses_old
+        select delivery_id, event, action, repo, result, notifications, steps, created_at, number
+        from event_diagnoses;
+
+    drop table event_diagnoses;
+
+    alter table event_diagnoses_old rename to event_diagnoses;
+
+    create index event_diagnoses_pr on event_diagnoses (repo, number);
+    "#,
+        ),
+        reversible(
+            r#"
+    create table webhook_retries (
+        delivery_id varchar not null,
+        event varchar not null,
+        repo varchar not null,
+        envelope varchar not null,
+        attempts integer not null,
+        next_attempt integer not null,
+        last_error varchar not null,
+        state varchar not null,
+        created_at integer not null,
+        updated_at integer not null,
+
+        PRIMARY KEY( delivery_id )
+    );
+    create index webhook_retries_due on webhook_retries (state, next_attempt);
+    "#,
+            "drop table webhook_retries;",
+        ),
+        reversible(
+            r#"
+    create table container_image_digests (
+        image varchar not null,
+        digest varchar not null,
+        updated_at integer not null,
+
+        PRIMARY KEY( image )
+    );
+    "#,
+            "drop table container_image_digests;",
+        ),
+        reversible(
+            r#"
+    create table scheduled_job_runs (
+        name varchar not null,
+        started_at integer not null,
+        finished_at integer not null,
+        error varchar not null,
+
+        PRIMARY KEY( name )
+    );
+    "#,
+            "drop table scheduled_job_runs;",
+        ),
+        reversible(
+            r#"
+    create table jsm_approvals (
+        repo varchar not null,
+        number integer not null,
+        branch varchar not null,
+        issue_key varchar not null,
+        decision varchar not null,
+        created_at integer not null,
+
+        PRIMARY KEY( repo, number )
+    );
+    "#,
+            "drop table jsm_approvals;",
+        ),
+        reversible(
+            r#"
+    alter table repos add column dry_run varchar not null default '';
+    "#,
+            r#"
+    create table repos_old (
+        id integer not null,
+        repo varchar not null,
+        channel varchar not null,
+        force_push_notify tinyint not null,
+        release_branch_prefix varchar not null,
+        codeowners_reviews tinyint not null default 0,
+        codeowners_ignore_bots tinyint not null default 0,
+        size_labels tinyint not null default 0,
+        size_label_thresholds varchar not null default '',
+        size_label_excludes varchar not null default '',
+        deleted_at integer not null default 0,
+        stale_pr_days integer not null default 0,
+        stale_pr_digest tinyint not null default 0,
+        stale_pr_quiet_days varchar not null default '',
+        lint_conventional tinyint not null default 0,
+        lint_title_regex varchar not null default '',
+        lint_commit_regex varchar not null default '',
+        lint_check_run tinyint not null default 0,
+        webhook_secret varchar not null default '',
+        conflict_notify tinyint not null default 0,
+        subscribed_channels varchar not null default '',
+        slack_threads tinyint not null default 0,
+        ecosystem varchar not null default '',
+        version_files varchar not null default '',
+        lockfiles varchar not null default '',
+        changelog_file varchar not null default '',
+        channel_digest varchar not null default '',
+        depends_on varchar not null default '',
+        sbom tinyint not null default 0,
+        sbom_script varchar not null default '',
+        provenance tinyint not null default 0,
+        smart_commits tinyint not null default 0,
+        smart_commit_commands varchar not null default '',
+        slack_pr_bridge tinyint not null default 0,
+        jira_release_versions tinyint not null default 0,
+        huddle_comments integer not null default 0,
+
+        UNIQUE( repo ),
+        PRIMARY KEY( id )
+    );
+
+    insert into repos_old
+        select id, repo, channel, force_push_notify, release_branch_prefix, codeowners_reviews,
+            codeowners_ignore_bots, size_labels, size_label_thresholds, size_label_excludes, deleted_at,
+            stale_pr_days, stale_pr_digest, stale_pr_quiet_days, lint_conventional, lint_title_regex,
+            lint_commit_regex, lint_check_run, webhook_secret, conflict_notify, subscribed_channels,
+            slack_threads, ecosystem, version_files, lockfiles, changelog_file, channel_digest, depends_on,
+            sbom, sbom_script, provenance, smart_commits, smart_commit_commands, slack_pr_bridge,
+            jira_release_versions, huddle_comments
+        from repos;
+
+    drop table repos;
+
+    alter table repos_old rename to repos;
+    "#,
+        ),
+        reversible(
+            r#"
+    create table webauthn_credentials (
+        id varchar not null,
+        user varchar not null,
+        name varchar not null,
+        algorithm integer not null,
+        public_key varchar not null,
+        sign_count integer not null,
+        created_at integer not null,
+        last_used integer not null,
+
+        PRIMARY KEY( id )
+    );
+    "#,
+            "drop table webauthn_credentials;",
+        ),
+        reversible(
+            r#"
+    create table webhook_forwards (
+        delivery_id varchar not null,
+        url varchar not null,
+        event varchar not null,
+        repo varchar not null,
+        request varchar not null,
+        attempts integer not null,
+        next_attempt integer not null,
+        last_error varchar not null,
+        state varchar not null,
+        created_at integer not null,
+        updated_at integer not null,
+
+        PRIMARY KEY( delivery_id, url )
+    );
+    create index webhook_forwards_due on webhook_forwards (state, next_attempt);
+    "#,
+            "drop table webhook_forwards;",
+        ),
+        reversible(
+            r#"
+    create table user_mapping_proposals (
+        id integer primary key autoincrement,
+        github_name varchar not null,
+        slack_name varchar not null,
+        email varchar not null,
+        jira_name varchar not null,
+        state varchar not null,
+        proposed_at integer not null,
+        decided_by varchar not null,
+        decided_at integer not null,
+
+        UNIQUE( github_name, slack_name, email )
+    );
+    "#,
+            "drop table user_mapping_proposals;",
+        ),
+        reversible(
+            r#"
+    alter table repos_routing_rules add column head varchar not null default '';
+    alter table repos_routing_rules add column silent tinyint not null default 0;
+    "#,
+            r#"
+    create table repos_routing_rules_old (
+        repo_id integer not null,
+        branch varchar not null,
+        path varchar not null,
+        label varchar not null,
+        channels varchar not null,
+        opsgenie varchar not null default ''
+    );
+
+    insert into repos_routing_rules_old
+        select repo_id, branch, path, label, channels, opsgenie
+        from repos_routing_rules;
+
+    drop table repos_routing_rules;
+
+    alter table repos_routing_rules_old rename to repos_routing_rules;
+    "#,
+        ),
+        reversible(
+            r#"
+    alter table repos add column blame_notify tinyint not null default 0;
+    "#,
+            r#"
+    create table repos_old (
+        id integer not null,
+        repo varchar not null,
+        channel varchar not null,
+        force_push_notify tinyint not null,
+        release_branch_prefix varchar not null,
+        codeowners_reviews tinyint not null default 0,
+        codeowners_ignore_bots tinyint not null default 0,
+        size_labels tinyint not null default 0,
+        size_label_thresholds varchar not null default '',
+        size_label_excludes varchar not null default '',
+        deleted_at integer not null default 0,
+        stale_pr_days integer not null default 0,
+        stale_pr_digest tinyint not null default 0,
+        stale_pr_quiet_days varchar not null default '',
+        lint_conventional tinyint not null default 0,
+        lint_title_regex varchar not null default '',
+        lint_commit_regex varchar not null default '',
+        lint_check_run tinyint not null default 0,
+        webhook_secret varchar not null default '',
+        conflict_notify tinyint not null default 0,
+        subscribed_channels varchar not null default '',
+        slack_threads tinyint not null default 0,
+        ecosystem varchar not null default '',
+        version_files varchar not null default '',
+        lockfiles varchar not null default '',
+        changelog_file varchar not null default '',
+        channel_digest varchar not null default '',
+        depends_on varchar not null default '',
+        sbom tinyint not null default 0,
+        sbom_script varchar not null default '',
+        provenance tinyint not null default 0,
+        smart_commits tinyint not null default 0,
+        smart_commit_commands varchar not null default '',
+        slack_pr_bridge tinyint not null default 0,
+        jira_release_versions tinyint not null default 0,
+        huddle_comments integer not null default 0,
+        dry_run varchar not null default '',
+
+        UNIQUE( repo ),
+        PRIMARY KEY( id )
+    );
+
+    insert into repos_old
+        select id, repo, channel, force_push_notify, release_branch_prefix, codeowners_reviews,
+            codeowners_ignore_bots, size_labels, size_label_thresholds, size_label_excludes, deleted_at,
+            stale_pr_days, stale_pr_digest, stale_pr_quiet_days, lint_conventional, lint_title_regex,
+            lint_commit_regex, lint_check_run, webhook_secret, conflict_notify, subscribed_channels,
+            slack_threads, ecosystem, version_files, lockfiles, changelog_file, channel_digest, depends_on,
+            sbom, sbom_script, provenance, smart_commits, smart_commit_commands, slack_pr_bridge,
+            jira_release_versions, huddle_comments, dry_run
+        from repos;
+
+    drop table repos;
+
+    alter table repos_old rename to repos;
+    "#,
+        ),
+        reversible(
+            r#"
+    create table blame_cache (
+        repo varchar not null,
+        sha varchar not null,
+        path varchar not null,
+        authors text not null,
+        created_at integer not null,
+
+        PRIMARY KEY( repo, sha, path )
+    );
+    "#,
+            "drop table blame_cache;",
+        ),
+        reversible(
+            r#"
+    create table commit_index (
+        repo varchar not null,
+        sha varchar not null,
+        branch varchar not null,
+        pr_number integer,
+        pr_title varchar not null,
+        pr_url varchar not null,
+        tickets varchar not null,
+        version varchar not null,
+        picked_from varchar not null,
+        recorded_at integer not null,
+
+        PRIMARY KEY( repo, sha )
+    );
+
+    create index commit_index_sha on commit_index (sha);
+    "#,
+            "drop table commit_index;",
+        ),
+        reversible(
+            r#"
+    create table outbox (
+        receipt varchar not null,
+        request varchar not null,
+        attempts integer not null,
+        attempted_at integer not null,
+        delivered_at integer,
+        created_at integer not null,
+
+        PRIMARY KEY( receipt )
+    );
+
+    create index outbox_undelivered on outbox (delivered_at, attempted_at);
+    "#,
+            "drop table outbox;",
+        ),
+        reversible(
+            r#"
+    create table onboarding_plans (
+        id integer primary key autoincrement,
+        org varchar not null,
+        webhook_url varchar not null,
+        repos varchar not null,
+        teams varchar not null,
+        state varchar not null,
+        created_by varchar not null,
+        created_at integer not null,
+        applied_by varchar not null,
+        applied_at integer not null,
+        results varchar not null
+    );
+    "#,
+            "drop table onboarding_plans;",
+        ),
+        reversible(
+            r#"
+    alter table repos add column gate_required_labels varchar not null default '';
+    alter table repos add column gate_team_approvals varchar not null default '';
+    alter table repos add column gate_resolved_threads tinyint not null default 0;
+    "#,
+            r#"
+    create table repos_old (
+        id integer not null,
+        repo varchar not null,
+        channel varchar not null,
+        force_push_notify tinyint not null,
+        release_branch_prefix varchar not null,
+        codeowners_reviews tinyint not null default 0,
+        codeowners_ignore_bots tinyint not null default 0,
+        size_labels tinyint not null default 0,
+        size_label_thresholds varchar not null default '',
+        size_label_excludes varchar not null default '',
+        deleted_at integer not null default 0,
+        stale_pr_days integer not null default 0,
+        stale_pr_digest tinyint not null default 0,
+        stale_pr_quiet_days varchar not null default '',
+        lint_conventional tinyint not null default 0,
+        lint_title_regex varchar not null default '',
+        lint_commit_regex varchar not null default '',
+        lint_check_run tinyint not null default 0,
+        webhook_secret varchar not null default '',
+        conflict_notify tinyint not null default 0,
+        subscribed_channels varchar not null default '',
+        slack_threads tinyint not null default 0,
+        ecosystem varchar not null default '',
+        version_files varchar not null default '',
+        lockfiles varchar not null default '',
+        changelog_file varchar not null default '',
+        channel_digest varchar not null default '',
+        depends_on varchar not null default '',
+        sbom tinyint not null default 0,
+        sbom_script varchar not null default '',
+        provenance tinyint not null default 0,
+        smart_commits tinyint not null default 0,
+        smart_commit_commands varchar not null default '',
+        slack_pr_bridge tinyint not null default 0,
+        jira_release_versions tinyint not null default 0,
+        huddle_comments integer not null default 0,
+        dry_run varchar not null default '',
+        blame_notify tinyint not null default 0,
+
+        UNIQUE( repo ),
+        PRIMARY KEY( id )
+    );
+
+    insert into repos_old
+        select id, repo, channel, force_push_notify, release_branch_prefix, codeowners_reviews,
+            codeowners_ignore_bots, size_labels, size_label_thresholds, size_label_excludes, deleted_at,
+            stale_pr_days, stale_pr_digest, stale_pr_quiet_days, lint_conventional, lint_title_regex,
+            lint_commit_regex, lint_check_run, webhook_secret, conflict_notify, subscribed_channels,
+            slack_threads, ecosystem, version_files, lockfiles, changelog_file, channel_digest, depends_on,
+            sbom, sbom_script, provenance, smart_commits, smart_commit_commands, slack_pr_bridge,
+            jira_release_versions, huddle_comments, dry_run, blame_notify
+        from repos;
+
+    drop table repos;
+
+    alter table repos_old rename to repos;
+    "#,
+        ),
+        reversible(
+            r#"
+    create table deliveries (
+        id integer primary key autoincrement,
+        provider varchar not null,
+        target varchar not null,
+        delivery_id varchar not null,
+        event varchar not null,
+        repo varchar not null,
+        number integer not null,
+        latency_ms integer not null,
+        error varchar not null,
+        created_at integer not null
+    );
+    create index deliveries_target on deliveries (target, created_at);
+    create index deliveries_created_at on deliveries (created_at);
+    "#,
+            "drop table deliveries;",
+        ),
+        reversible(
+            r#"
+    create table trace_entries (
+        id integer primary key autoincrement,
+        request_id varchar not null,
+        delivery_id varchar not null,
+        event varchar not null,
+        repo varchar not null,
+        number integer not null,
+        job varchar not null,
+        at integer not null,
+        level varchar not null,
+        thread varchar not null,
+        message varchar not null
+    );
+    create index trace_entries_request_id on trace_entries (request_id);
+    create index trace_entries_delivery_id on trace_entries (delivery_id);
+    "#,
+            "drop table trace_entries;",
+        ),
+        reversible(
+            r#"
+    create table repos_label_rules (
+        repo_id integer not null,
+        label varchar not null,
+        review_team varchar not null,
+        channels varchar not null,
+        jira_transition varchar not null,
+        merge tinyint not null
+    );
+    "#,
+            "drop table repos_label_rules;",
+        ),
+    ]
+}
+
+// Postgres databases start at the schema sqlite databases had migrated to by then. Tables sqlite lists in
+// rowid order get a rowid column to do the same. New migrations go at the end of both lists.
+fn postgres_migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        sql(r#"
+    create table account_logins (
+        "user" varchar not null,
+        admin smallint not null,
+        first_login bigint not null,
+        last_login bigint not null,
+        revoked_at bigint,
+        revoked_by varchar,
+        PRIMARY KEY( "user" )
+    );
+    create table audit_log (
+        id bigserial not null,
+        created_at bigint not null,
+        actor varchar not null,
+        action varchar not null,
+        target varchar not null,
+        details varchar not null,
+        PRIMARY KEY( id )
+    );
+    create table auto_merges (
+        repo varchar not null,
+        number bigint not null,
+        head_sha varchar not null,
+        requested_by varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( repo, number )
+    );
+    create table blocked_tags (
+        repo varchar not null,
+        tag varchar not null,
+        change_number varchar not null,
+        blocked_at bigint not null,
+        PRIMARY KEY( repo, tag )
+    );
+    create table change_requests (
+        repo varchar not null,
+        number bigint not null,
+        branch varchar not null,
+        change_number varchar not null,
+        sys_id varchar not null,
+        approval varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( repo, number )
+    );
+    create table code_freeze_pull_requests (
+        org varchar not null,
+        repo varchar not null,
+        number bigint not null,
+        exception_by varchar not null default '',
+        PRIMARY KEY( repo, number )
+    );
+    create table code_freezes (
+        org varchar not null,
+        until bigint not null,
+        frozen_by varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( org )
+    );
+    create table component_versions (
+        repo varchar not null,
+        component varchar not null,
+        branch varchar not null,
+        version varchar not null,
+        commit_hash varchar not null,
+        updated_at bigint not null,
+        PRIMARY KEY( repo, component, branch )
+    );
+    create table critical_alerts (
+        id bigserial not null,
+        repo varchar not null,
+        branch varchar not null,
+        kind varchar not null,
+        message varchar not null,
+        tier bigint not null,
+        escalate_at bigint not null,
+        acked_by varchar not null,
+        acked_at bigint not null,
+        created_at bigint not null,
+        PRIMARY KEY( id )
+    );
+    create index critical_alerts_escalate_at on critical_alerts (escalate_at);
+    create table digest_items (
+        repo varchar not null,
+        kind varchar not null,
+        item varchar not null,
+        github_user varchar not null,
+        title varchar not null,
+        url varchar not null,
+        updated_at bigint not null,
+        PRIMARY KEY( repo, kind, item, github_user )
+    );
+    create table event_diagnoses (
+        delivery_id varchar not null,
+        event varchar not null,
+        action varchar not null,
+        repo varchar not null,
+        result varchar not null,
+        notifications bigint not null,
+        steps varchar not null,
+        created_at bigint not null,
+        number bigint not null default 0,
+        rowid bigserial not null,
+        PRIMARY KEY( delivery_id )
+    );
+    create index event_diagnoses_pr on event_diagnoses (repo, number);
+    create table huddle_suggestions (
+        repo varchar not null,
+        number bigint not null,
+        suggested_at bigint not null,
+        PRIMARY KEY( repo, number )
+    );
+    create table jira_smart_commits (
+        repo varchar not null,
+        sha varchar not null,
+        applied_at bigint not null,
+        PRIMARY KEY( repo, sha )
+    );
+    create table job_items (
+        job_id bigint not null,
+        item varchar not null,
+        success smallint not null,
+        message varchar not null,
+        rowid bigserial not null
+    );
+    create table jobs (
+        id bigserial not null,
+        kind varchar not null,
+        description varchar not null,
+        status varchar not null,
+        total_items bigint not null,
+        done_items bigint not null,
+        cancel_requested smallint not null,
+        created_at bigint not null,
+        updated_at bigint not null,
+        PRIMARY KEY( id )
+    );
+    create table merge_records (
+        repo varchar not null,
+        number bigint not null,
+        title varchar not null,
+        html_url varchar not null,
+        base_branch varchar not null,
+        author varchar not null,
+        merged_by varchar not null,
+        merged_at bigint not null,
+        approvers varchar not null,
+        tickets varchar not null,
+        overrides varchar not null,
+        PRIMARY KEY( repo, number )
+    );
+    create index merge_records_merged_at on merge_records (merged_at);
+    create table pr_conflicts (
+        repo varchar not null,
+        number bigint not null,
+        base_sha varchar not null,
+        notified_at bigint not null,
+        PRIMARY KEY( repo, number )
+    );
+    create table provider_incidents (
+        id bigserial not null,
+        provider varchar not null,
+        indicator varchar not null,
+        description varchar not null,
+        started_at bigint not null,
+        resolved_at bigint not null,
+        PRIMARY KEY( id )
+    );
+    create table provider_paused_messages (
+        id bigserial not null,
+        incident_id bigint not null,
+        msg varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( id )
+    );
+    create index provider_paused_messages_incident_id on provider_paused_messages (incident_id);
+    create table release_attestations (
+        repo varchar not null,
+        tag varchar not null,
+        document text not null,
+        sha256 varchar not null,
+        recorded_at bigint not null,
+        PRIMARY KEY( repo, tag )
+    );
+    create table release_sboms (
+        repo varchar not null,
+        tag varchar not null,
+        commit_hash varchar not null,
+        asset_name varchar not null,
+        asset_url varchar not null,
+        sha256 varchar not null,
+        source varchar not null,
+        recorded_at bigint not null,
+        PRIMARY KEY( repo, tag )
+    );
+    create table repo_files (
+        repo varchar not null,
+        contents varchar not null,
+        error varchar not null,
+        fetched_at bigint not null,
+        PRIMARY KEY( repo )
+    );
+    create table repo_muted_messages (
+        id bigserial not null,
+        repo varchar not null,
+        msg varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( id )
+    );
+    create index repo_muted_messages_repo on repo_muted_messages (repo);
+    create table repo_mutes (
+        repo varchar not null,
+        start_at bigint not null,
+        until bigint not null,
+        muted_by varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( repo )
+    );
+    create table repos (
+        id bigserial not null,
+        repo varchar not null,
+        channel varchar not null,
+        force_push_notify smallint not null,
+        release_branch_prefix varchar not null,
+        codeowners_reviews smallint not null default 0,
+        codeowners_ignore_bots smallint not null default 0,
+        size_labels smallint not null default 0,
+        size_label_thresholds varchar not null default '',
+        size_label_excludes varchar not null default '',
+        deleted_at bigint not null default 0,
+        stale_pr_days bigint not null default 0,
+        stale_pr_digest smallint not null default 0,
+        stale_pr_quiet_days varchar not null default '',
+        lint_conventional smallint not null default 0,
+        lint_title_regex varchar not null default '',
+        lint_commit_regex varchar not null default '',
+        lint_check_run smallint not null default 0,
+        webhook_secret varchar not null default '',
+        conflict_notify smallint not null default 0,
+        subscribed_channels varchar not null default '',
+        slack_threads smallint not null default 0,
+        ecosystem varchar not null default '',
+        version_files varchar not null default '',
+        lockfiles varchar not null default '',
+        changelog_file varchar not null default '',
+        channel_digest varchar not null default '',
+        depends_on varchar not null default '',
+        sbom smallint not null default 0,
+        sbom_script varchar not null default '',
+        provenance smallint not null default 0,
+        smart_commits smallint not null default 0,
+        smart_commit_commands varchar not null default '',
+        slack_pr_bridge smallint not null default 0,
+        jira_release_versions smallint not null default 0,
+        huddle_comments bigint not null default 0,
+        UNIQUE( repo ),
+        PRIMARY KEY( id )
+    );
+    create table repos_components (
+        repo_id bigint not null,
+        name varchar not null,
+        path varchar not null,
+        version_script varchar not null default '',
+        jira varchar not null default '',
+        rowid bigserial not null
+    );
+    create table repos_jiras (
+        repo_id bigint not null,
+        jira varchar not null,
+        channel varchar not null,
+        version_script varchar not null,
+        release_branch_regex varchar not null,
+        PRIMARY KEY( repo_id, jira )
+    );
+    create table repos_merge_strategies (
+        repo_id bigint not null,
+        branch varchar not null,
+        strategy varchar not null,
+        commit_template varchar not null,
+        rowid bigserial not null
+    );
+    create table repos_path_labels (
+        repo_id bigint not null,
+        path varchar not null,
+        label varchar not null,
+        rowid bigserial not null
+    );
+    create table repos_routing_rules (
+        repo_id bigint not null,
+        branch varchar not null,
+        path varchar not null,
+        label varchar not null,
+        channels varchar not null,
+        opsgenie varchar not null default '',
+        rowid bigserial not null
+    );
+    create table repos_submodules (
+        repo_id bigint not null,
+        upstream varchar not null,
+        path varchar not null,
+        auto_merge smallint not null default 0,
+        rowid bigserial not null
+    );
+    create table review_comments (
+        id bigserial not null,
+        repo varchar not null,
+        number bigint not null,
+        login varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( id )
+    );
+    create index review_comments_pr on review_comments (repo, number);
+    create table slack_messages (
+        channel_id varchar not null,
+        ts varchar not null,
+        thread_key varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( channel_id, ts )
+    );
+    create table slack_threads (
+        thread_key varchar not null,
+        channel varchar not null,
+        ts varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( thread_key, channel )
+    );
+    create table team_channels (
+        team varchar not null,
+        channel varchar not null,
+        PRIMARY KEY( team )
+    );
+    create table users (
+        id bigserial not null,
+        github_name varchar not null,
+        slack_name varchar not null,
+        mute_direct_messages smallint not null default 0,
+        deleted_at bigint not null default 0,
+        muted_until bigint not null default 0,
+        dm_events varchar not null default '',
+        quiet_hours_start varchar not null default '',
+        quiet_hours_end varchar not null default '',
+        timezone varchar not null default '',
+        digest varchar not null default '',
+        email varchar not null default '',
+        UNIQUE( github_name ),
+        PRIMARY KEY( id )
+    );
+    "#),
+        reversible(
+            r#"
+    alter table event_diagnoses add column sender varchar not null default '';
+    alter table event_diagnoses add column summary varchar not null default '';
+    alter table event_diagnoses add column status bigint not null default 200;
+    alter table event_diagnoses add column error varchar not null default '';
+
+    create index event_diagnoses_created_at on event_diagnoses (created_at);
+    "#,
+            r#"
+    drop index event_diagnoses_created_at;
+
+    alter table event_diagnoses drop column sender;
+    alter table event_diagnoses drop column summary;
+    alter table event_diagnoses drop column status;
+    alter table event_diagnoses drop column error;
+    "#,
+        ),
+        reversible(
+            r#"
+    create table webhook_retries (
+        delivery_id varchar not null,
+        event varchar not null,
+        repo varchar not null,
+        envelope varchar not null,
+        attempts bigint not null,
+        next_attempt bigint not null,
+        last_error varchar not null,
+        state varchar not null,
+        created_at bigint not null,
+        updated_at bigint not null,
+        PRIMARY KEY( delivery_id )
+    );
+    create index webhook_retries_due on webhook_retries (state, next_attempt);
+    "#,
+            "drop table webhook_retries;",
+        ),
+        reversible(
+            r#"
+    create table container_image_digests (
+        image varchar not null,
+        digest varchar not null,
+        updated_at bigint not null,
+        PRIMARY KEY( image )
+    );
+    "#,
+            "drop table container_image_digests;",
+        ),
+        reversible(
+            r#"
+    create table scheduled_job_runs (
+        name varchar not null,
+        started_at bigint not null,
+        finished_at bigint not null,
+        error varchar not null,
+        PRIMARY KEY( name )
+    );
+    "#,
+            "drop table scheduled_job_runs;",
+        ),
+        reversible(
+            r#"
+    create table jsm_approvals (
+        repo varchar not null,
+        number bigint not null,
+        branch varchar not null,
+        issue_key varchar not null,
+        decision varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( repo, number )
+    );
+    "#,
+            "drop table jsm_approvals;",
+        ),
+        reversible(
+            r#"
+    alter table repos add column dry_run varchar not null default '';
+    "#,
+            "alter table repos drop column dry_run;",
+        ),
+        reversible(
+            r#"
+    create table webauthn_credentials (
+        id varchar not null,
+        "user" varchar not null,
+        name varchar not null,
+        algorithm bigint not null,
+        public_key varchar not null,
+        sign_count bigint not null,
+        created_at bigint not null,
+        last_used bigint not null,
+        PRIMARY KEY( id )
+    );
+    "#,
+            "drop table webauthn_credentials;",
+        ),
+        reversible(
+            r#"
+    create table webhook_forwards (
+        delivery_id varchar not null,
+        url varchar not null,
+        event varchar not null,
+        repo varchar not null,
+        request varchar not null,
+        attempts bigint not null,
+        next_attempt bigint not null,
+        last_error varchar not null,
+        state varchar not null,
+        created_at bigint not null,
+        updated_at bigint not null,
+        PRIMARY KEY( delivery_id, url )
+    );
+    create index webhook_forwards_due on webhook_forwards (state, next_attempt);
+    "#,
+            "drop table webhook_forwards;",
+        ),
+        reversible(
+            r#"
+    create table user_mapping_proposals (
+        id bigserial not null,
+        github_name varchar not null,
+        slack_name varchar not null,
+        email varchar not null,
+        jira_name varchar not null,
+        state varchar not null,
+        proposed_at bigint not null,
+        decided_by varchar not null,
+        decided_at bigint not null,
+        UNIQUE( github_name, slack_name, email ),
+        PRIMARY KEY( id )
+    );
+    "#,
+            "drop table user_mapping_proposals;",
+        ),
+        reversible(
+            r#"
+    alter table repos_routing_rules add column head varchar not null default '';
+    alter table repos_routing_rules add column silent smallint not null default 0;
+    "#,
+            r#"
+    alter table repos_routing_rules drop column head;
+    alter table repos_routing_rules drop column silent;
+    "#,
+        ),
+        reversible(
+            r#"
+    alter table repos add column blame_notify smallint not null default 0;
+    "#,
+            "alter table repos drop column blame_notify;",
+        ),
+        reversible(
+            r#"
+    create table blame_cache (
+        repo varchar not null,
+        sha varchar not null,
+        path varchar not null,
+        authors text not null,
+        created_at bigint not null,
+        PRIMARY KEY( repo, sha, path )
+    );
+    "#,
+            "drop table blame_cache;",
+        ),
+        reversible(
+            r#"
+    create table commit_index (
+        repo varchar not null,
+        sha varchar not null,
+        branch varchar not null,
+        pr_number bigint,
+        pr_title varchar not null,
+        pr_url varchar not null,
+        tickets varchar not null,
+        version varchar not null,
+        picked_from varchar not null,
+        recorded_at bigint not null,
+        PRIMARY KEY( repo, sha )
+    );
+    create index commit_index_sha on commit_index (sha);
+    "#,
+            "drop table commit_index;",
+        ),
+        reversible(
+            r#"
+    create table outbox (
+        receipt varchar not null,
+        request varchar not null,
+        attempts bigint not null,
+        attempted_at bigint not null,
+        delivered_at bigint,
+        created_at bigint not null,
+        PRIMARY KEY( receipt )
+    );
+    create index outbox_undelivered on outbox (delivered_at, attempted_at);
+    "#,
+            "drop table outbox;",
+        ),
+        reversible(
+            r#"
+    create table onboarding_plans (
+        id bigserial not null,
+        org varchar not null,
+        webhook_url varchar not null,
+        repos varchar not null,
+        teams varchar not null,
+        state varchar not null,
+        created_by varchar not null,
+        created_at bigint not null,
+        applied_by varchar not null,
+        applied_at bigint not null,
+        results varchar not null,
+        PRIMARY KEY( id )
+    );
+    "#,
+            "drop table onboarding_plans;",
+        ),
+        reversible(
+            r#"
+    alter table repos add column gate_required_labels varchar not null default '';
+    alter table repos add column gate_team_approvals varchar not null default '';
+    alter table repos add column gate_resolved_threads smallint not null default 0;
+    "#,
+            r#"
+    alter table repos drop column gate_required_labels;
+    alter table repos drop column gate_team_approvals;
+    alter table repos drop column gate_resolved_threads;
+    "#,
+        ),
+        reversible(
+            r#"
+    create table deliveries (
+        id bigserial not null,
+        provider varchar not null,
+        target varchar not null,
+        delivery_id varchar not null,
+        event varchar not null,
+        repo varchar not null,
+        number bigint not null,
+        latency_ms bigint not null,
+        error varchar not null,
+        created_at bigint not null,
+        PRIMARY KEY( id )
+    );
+    create index deliveries_created_at on deliveries (created_at);
+    create index deliveries_target on deliveries (target, created_at);
+    "#,
+            "drop table deliveries;",
+        ),
+        reversible(
+            r#"
+    create table trace_entries (
+        id bigserial not null,
+        request_id varchar not null,
+        delivery_id varchar not null,
+        event varchar not null,
+        repo varchar not null,
+        number bigint not null,
+        job varchar not null,
+        at bigint not null,
+        level varchar not null,
+        thread varchar not null,
+        message varchar not null,
+        PRIMARY KEY( id )
+    );
+    create index trace_entries_delivery_id on trace_entries (delivery_id);
+    create index trace_entries_request_id on trace_entries (request_id);
+    "#,
+            "drop table trace_entries;",
+        ),
+        reversible(
+            r#"
+    create table repos_label_rules (
+        repo_id bigint not null,
+        label varchar not null,
+        review_team varchar not null,
+        channels varchar not null,
+        jira_transition varchar not null,
+        merge smallint not null,
+        rowid bigserial not null
+    );
+    "#,
+            "drop table repos_label_rules;",
+        ),
     ]
 }
 
 fn current_version(conn: &Connection) -> Result<Option<i32>> {
     let mut version: Option<i32> = None;
-    conn.query_row("SELECT current_version from __version", rusqlite::NO_PARAMS, |row| {
+    conn.query_row("SELECT current_version from __version", crate::db::NO_PARAMS, |row| {
         version = row.get(0).ok();
         Ok(())
     })
@@ -103,12 +1781,65 @@ fn current_version(conn: &Connection) -> Result<Option<i32>> {
     Ok(version)
 }
 
+// Where the schema is, and how far it can go either way
+#[derive(Debug, PartialEq)]
+pub struct SchemaStatus {
+    // None for a new database
+    pub current: Option<i32>,
+    pub latest: i32,
+    // the oldest version it can be rolled back to
+    pub rollback_limit: Option<i32>,
+}
+
+pub fn latest_version(backend: Backend) -> i32 {
+    all_migrations(backend).len() as i32 - 1
+}
+
+pub fn status(conn: &Connection) -> Result<SchemaStatus> {
+    let current = match current_version(conn) {
+        Ok(v) => v,
+        // no versions table yet
+        Err(_) => None,
+    };
+    let migrations = all_migrations(conn.backend());
+
+    let mut rollback_limit = current.filter(|v| *v < migrations.len() as i32);
+    while let Some(version) = rollback_limit {
+        if version == 0 || !migrations[version as usize].can_rollback() {
+            break;
+        }
+        rollback_limit = Some(version - 1);
+    }
+
+    Ok(SchemaStatus {
+        current: current,
+        latest: migrations.len() as i32 - 1,
+        rollback_limit: rollback_limit,
+    })
+}
+
+// Brings the schema up to date, as octobot does when it starts
 pub fn migrate(conn: &mut Connection) -> Result<()> {
+    let latest = latest_version(conn.backend());
+    if let Ok(Some(version)) = current_version(conn) {
+        if version > latest {
+            return Err(format_err!(
+                "Database schema version {} is newer than this octobot's ({}): roll it back with the octobot that \
+                 migrated it first",
+                version,
+                latest
+            ));
+        }
+    }
+    migrate_to(conn, latest)
+}
+
+pub fn migrate_to(conn: &mut Connection, target: i32) -> Result<()> {
     let version: Option<i32> = match current_version(conn) {
         Ok(v) => v,
         Err(_) => {
             // versions table probably doesn't exist.
-            conn.execute(CREATE_VERSIONS, rusqlite::NO_PARAMS)
+            conn.execute(CREATE_VERSIONS, crate::db::NO_PARAMS)
                 .map_err(|e| format_err!("Error creating versions table: {}", e))?;
             None
         }
@@ -116,10 +1847,16 @@ pub fn migrate(conn: &mut Connection) -> Result<()> {
 
     info!("Current schema version: {:?}", version);
 
-    let migrations = all_migrations();
+    let migrations = all_migrations(conn.backend());
+    if target < 0 || target >= migrations.len() as i32 {
+        return Err(format_err!("Unknown schema version {} (latest is {})", target, migrations.len() - 1));
+    }
+    if let Some(version) = version.filter(|v| *v > target) {
+        return Err(format_err!("Schema version {} is past {}: roll it back instead", version, target));
+    }
 
     let mut next_version = version.map(|v| v + 1).unwrap_or(0);
-    while next_version < migrations.len() as i32 {
+    while next_version <= target {
         info!("Migrating to schema version: {:}", next_version);
         let tx = conn.transaction()?;
 
@@ -142,27 +1879,66 @@ pub fn migrate(conn: &mut Connection) -> Result<()> {
     Ok(())
 }
 
+// Rolls back one version at a time, each in its own transaction, after checking they all can be
+pub fn rollback_to(conn: &mut Connection, target: i32) -> Result<()> {
+    let status = status(conn)?;
+    let version = status.current.ok_or_else(|| format_err!("No schema version to roll back"))?;
+    if version > status.latest {
+        return Err(format_err!("Schema version {} is newer than this octobot's ({})", version, status.latest));
+    }
+    if target < 0 || target > version {
+        return Err(format_err!("Can't roll back schema version {} to {}", version, target));
+    }
+    match status.rollback_limit {
+        Some(limit) if limit <= target => (),
+        _ => {
+            return Err(format_err!(
+                "Can't roll back schema version {} to {}: it can only go back to {}",
+                version,
+                target,
+                status.rollback_limit.unwrap_or(version)
+            ))
+        }
+    };
+
+    let migrations = all_migrations(conn.backend());
+    for version in ((target + 1)..=version).rev() {
+        info!("Rolling back schema version: {}", version);
+        let tx = conn.transaction()?;
+
+        migrations[version as usize].rollback(&tx)?;
+
+        tx.execute("UPDATE __version set current_version = ?1", &[&(version - 1)])
+            .map_err(|e| format_err!("Error updating version: {}", e))?;
+
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::Database;
     use tempdir::TempDir;
 
     #[test]
     fn test_migration_versions() {
         let temp_dir = TempDir::new("users.rs").unwrap();
         let db_file = temp_dir.path().join("db.sqlite3");
-        let mut conn = Connection::open(&db_file).expect("create temp database");
+        let mut conn = Database::open(&db_file.to_string_lossy()).connect().expect("create temp database");
 
         migrate(&mut conn).unwrap();
 
-        assert_eq!(Some((all_migrations().len() as i32) - 1), current_version(&conn).unwrap());
+        assert_eq!(Some((all_migrations(Backend::Sqlite).len() as i32) - 1), current_version(&conn).unwrap());
     }
 
     #[test]
     fn test_multiple_migration() {
         let temp_dir = TempDir::new("users.rs").unwrap();
         let db_file = temp_dir.path().join("db.sqlite3");
-        let mut conn = Connection::open(&db_file).expect("create temp database");
+        let mut conn = Database::open(&db_file.to_string_lossy()).connect().expect("create temp database");
 
         // migration #1
         migrate(&mut conn).unwrap();
@@ -172,4 +1948,76 @@ mod tests {
             panic!("Failed: expected second migration to be a noop: {}", e);
         }
     }
+
+    #[test]
+    fn test_rollback() {
+        let temp_dir = TempDir::new("migrations.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let mut conn = Database::open(&db_file.to_string_lossy()).connect().expect("create temp database");
+
+        let latest = latest_version(Backend::Sqlite);
+        migrate(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO repos_routing_rules (repo_id, branch, path, label, channels, opsgenie) \
+             VALUES (1, 'main', '', '', 'the-channel', 'the-team')",
+            crate::db::NO_PARAMS,
+        )
+        .unwrap();
+
+        let limit = status(&conn).unwrap().rollback_limit.unwrap();
+        assert!(limit < latest - 2);
+
+        rollback_to(&mut conn, limit).unwrap();
+        assert_eq!(Some(limit), current_version(&conn).unwrap());
+        assert!(conn.prepare("SELECT * FROM provider_incidents").is_err());
+        let channels: String = conn
+            .query_row("SELECT channels FROM repos_routing_rules", crate::db::NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!("the-channel", channels);
+        assert!(conn.prepare("SELECT opsgenie FROM repos_routing_rules").is_err());
+
+        // and forward again
+        migrate(&mut conn).unwrap();
+        assert_eq!(Some(latest), current_version(&conn).unwrap());
+        assert!(conn.prepare("SELECT opsgenie FROM repos_routing_rules").is_ok());
+    }
+
+    #[test]
+    fn test_rollback_limit() {
+        let temp_dir = TempDir::new("migrations.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let mut conn = Database::open(&db_file.to_string_lossy()).connect().expect("create temp database");
+
+        assert_eq!(
+            SchemaStatus {
+                current: None,
+                latest: latest_version(Backend::Sqlite),
+                rollback_limit: None,
+            },
+            status(&conn).unwrap()
+        );
+
+        migrate(&mut conn).unwrap();
+        let limit = status(&conn).unwrap().rollback_limit.unwrap();
+        assert!(rollback_to(&mut conn, limit - 1).is_err());
+        assert_eq!(Some(latest_version(Backend::Sqlite)), current_version(&conn).unwrap());
+
+        rollback_to(&mut conn, limit).unwrap();
+        assert!(migrate_to(&mut conn, limit - 1).is_err());
+        migrate_to(&mut conn, limit + 1).unwrap();
+        assert_eq!(Some(limit + 1), current_version(&conn).unwrap());
+    }
+
+    #[test]
+    fn test_newer_schema() {
+        let temp_dir = TempDir::new("migrations.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let mut conn = Database::open(&db_file.to_string_lossy()).connect().expect("create temp database");
+
+        migrate(&mut conn).unwrap();
+        conn.execute("UPDATE __version set current_version = ?1", &[&(latest_version(Backend::Sqlite) + 1)]).unwrap();
+
+        assert!(migrate(&mut conn).is_err());
+        assert!(rollback_to(&mut conn, latest_version(Backend::Sqlite)).is_err());
+    }
 }
@@ -1,9 +1,8 @@
 use failure::format_err;
-use rusqlite::types::ToSql;
-use rusqlite::Transaction;
 
 use crate::db;
 use crate::db::migrations::Migration;
+use crate::db::{ToSql, Transaction};
 use crate::errors::*;
 
 pub struct MigrationReposJiras {}
@@ -12,7 +11,7 @@ impl Migration for MigrationReposJiras {
     fn run(&self, tx: &Transaction) -> Result<()> {
         let mut stmt = tx.prepare(r#"SELECT * FROM repos"#)?;
         let cols = db::Columns::from_stmt(&stmt)?;
-        let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+        let mut rows = stmt.query(db::NO_PARAMS)?;
 
         while let Ok(Some(row)) = rows.next() {
             let id = cols.get::<i32>(row, "id")?;
@@ -1,5 +1,12 @@
+mod connection;
 mod db;
 mod migrations;
 mod migrations_code;
+mod postgres_store;
+mod sqlite_store;
+mod store;
 
+pub use self::connection::*;
 pub use self::db::*;
+pub use self::migrations::SchemaStatus;
+pub use self::store::{Backend, FromSql, Store, ToSql, Value};
@@ -0,0 +1,88 @@
+use failure::format_err;
+use rusqlite::types::Value as SqliteValue;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::db::store::{Backend, QueryResult, Store, Value};
+use crate::errors::*;
+
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(db_file: &str) -> Result<SqliteStore> {
+        let conn = Connection::open(db_file).map_err(|e| format_err!("Error opening database {}: {}", db_file, e))?;
+        Ok(SqliteStore { conn: conn })
+    }
+
+    pub fn open_read_only(db_file: &str) -> Result<SqliteStore> {
+        let conn = Connection::open_with_flags(db_file, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| format_err!("Error opening database {}: {}", db_file, e))?;
+        Ok(SqliteStore { conn: conn })
+    }
+}
+
+impl Store for SqliteStore {
+    fn backend(&self) -> Backend {
+        Backend::Sqlite
+    }
+
+    fn columns(&mut self, sql: &str) -> Result<Vec<String>> {
+        let stmt = self.conn.prepare_cached(sql)?;
+        let columns = stmt.column_names().into_iter().map(|c| c.to_string()).collect();
+        Ok(columns)
+    }
+
+    fn execute(&mut self, sql: &str, params: &[Value]) -> Result<usize> {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        Ok(stmt.execute(params.iter().map(to_sqlite))?)
+    }
+
+    fn query(&mut self, sql: &str, params: &[Value]) -> Result<QueryResult> {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let columns = stmt.column_names().into_iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let mut rows = stmt.query(params.iter().map(to_sqlite))?;
+
+        let mut values = vec![];
+        while let Some(row) = rows.next()? {
+            let mut row_values = vec![];
+            for i in 0..columns.len() {
+                row_values.push(from_sqlite(row.get(i)?));
+            }
+            values.push(row_values);
+        }
+
+        Ok(QueryResult {
+            columns: columns,
+            rows: values,
+        })
+    }
+
+    fn execute_batch(&mut self, sql: &str) -> Result<()> {
+        Ok(self.conn.execute_batch(sql)?)
+    }
+
+    fn last_insert_rowid(&mut self) -> Result<i64> {
+        Ok(self.conn.last_insert_rowid())
+    }
+}
+
+fn to_sqlite(value: &Value) -> SqliteValue {
+    match *value {
+        Value::Null => SqliteValue::Null,
+        Value::Integer(i) => SqliteValue::Integer(i),
+        Value::Real(f) => SqliteValue::Real(f),
+        Value::Text(ref s) => SqliteValue::Text(s.clone()),
+        Value::Blob(ref b) => SqliteValue::Blob(b.clone()),
+    }
+}
+
+fn from_sqlite(value: SqliteValue) -> Value {
+    match value {
+        SqliteValue::Null => Value::Null,
+        SqliteValue::Integer(i) => Value::Integer(i),
+        SqliteValue::Real(f) => Value::Real(f),
+        SqliteValue::Text(s) => Value::Text(s),
+        SqliteValue::Blob(b) => Value::Blob(b),
+    }
+}
@@ -0,0 +1,294 @@
+use failure::format_err;
+
+use crate::errors::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+// What the rest of octobot needs from a database: sqlite (a file next to the config), or PostgreSQL.
+// Statements use sqlite's `?1` placeholders and standard SQL that both understand.
+pub trait Store: Send {
+    fn backend(&self) -> Backend;
+
+    // The names of the columns |sql| would return, also checking that it is valid
+    fn columns(&mut self, sql: &str) -> Result<Vec<String>>;
+
+    // Returns the number of rows changed
+    fn execute(&mut self, sql: &str, params: &[Value]) -> Result<usize>;
+
+    fn query(&mut self, sql: &str, params: &[Value]) -> Result<QueryResult>;
+
+    // Runs several statements, without parameters, e.g. for migrations
+    fn execute_batch(&mut self, sql: &str) -> Result<()>;
+
+    // The id generated by the last insert on this connection
+    fn last_insert_rowid(&mut self) -> Result<i64>;
+}
+
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+pub trait ToSql {
+    fn to_sql(&self) -> Value;
+}
+
+pub trait FromSql: Sized {
+    fn from_sql(value: &Value) -> Result<Self>;
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl<'a, T: ToSql + ?Sized> ToSql for &'a T {
+    fn to_sql(&self) -> Value {
+        (*self).to_sql()
+    }
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn to_sql(&self) -> Value {
+        match *self {
+            Some(ref v) => v.to_sql(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: ToSql + ?Sized> ToSql for Box<T> {
+    fn to_sql(&self) -> Value {
+        (**self).to_sql()
+    }
+}
+
+macro_rules! integer_sql {
+    ($($t:ty),*) => {$(
+        impl ToSql for $t {
+            fn to_sql(&self) -> Value {
+                Value::Integer(*self as i64)
+            }
+        }
+
+        impl FromSql for $t {
+            fn from_sql(value: &Value) -> Result<$t> {
+                match *value {
+                    Value::Integer(i) if i >= <$t>::min_value() as i64 && i <= <$t>::max_value() as i64 => Ok(i as $t),
+                    Value::Integer(i) => Err(format_err!("Integer {} out of range for {}", i, stringify!($t))),
+                    ref v => Err(format_err!("Expected an integer, got {:?}", v)),
+                }
+            }
+        }
+    )*};
+}
+
+integer_sql!(i8, i16, i32, i64, isize, u8, u16, u32);
+
+impl ToSql for bool {
+    fn to_sql(&self) -> Value {
+        Value::Integer(*self as i64)
+    }
+}
+
+impl FromSql for bool {
+    fn from_sql(value: &Value) -> Result<bool> {
+        i64::from_sql(value).map(|i| i != 0)
+    }
+}
+
+impl ToSql for f64 {
+    fn to_sql(&self) -> Value {
+        Value::Real(*self)
+    }
+}
+
+impl FromSql for f64 {
+    fn from_sql(value: &Value) -> Result<f64> {
+        match *value {
+            Value::Real(f) => Ok(f),
+            Value::Integer(i) => Ok(i as f64),
+            ref v => Err(format_err!("Expected a number, got {:?}", v)),
+        }
+    }
+}
+
+impl ToSql for str {
+    fn to_sql(&self) -> Value {
+        Value::Text(self.to_string())
+    }
+}
+
+impl ToSql for String {
+    fn to_sql(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+impl FromSql for String {
+    fn from_sql(value: &Value) -> Result<String> {
+        match *value {
+            Value::Text(ref s) => Ok(s.clone()),
+            ref v => Err(format_err!("Expected text, got {:?}", v)),
+        }
+    }
+}
+
+impl ToSql for [u8] {
+    fn to_sql(&self) -> Value {
+        Value::Blob(self.to_vec())
+    }
+}
+
+impl ToSql for Vec<u8> {
+    fn to_sql(&self) -> Value {
+        Value::Blob(self.clone())
+    }
+}
+
+impl FromSql for Vec<u8> {
+    fn from_sql(value: &Value) -> Result<Vec<u8>> {
+        match *value {
+            Value::Blob(ref b) => Ok(b.clone()),
+            ref v => Err(format_err!("Expected a blob, got {:?}", v)),
+        }
+    }
+}
+
+impl<T: FromSql> FromSql for Option<T> {
+    fn from_sql(value: &Value) -> Result<Option<T>> {
+        match *value {
+            Value::Null => Ok(None),
+            ref v => T::from_sql(v).map(Some),
+        }
+    }
+}
+
+impl FromSql for Value {
+    fn from_sql(value: &Value) -> Result<Value> {
+        Ok(value.clone())
+    }
+}
+
+// Turns sqlite's `?1` (or `?`) placeholders into postgres' `$1`, leaving quoted strings and names alone
+pub fn numbered_placeholders(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut next = 1;
+    let mut quote = None;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '?' => {
+                let mut digits = String::new();
+                while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    chars.next();
+                }
+                if digits.is_empty() {
+                    digits = next.to_string();
+                }
+                next = digits.parse::<usize>().unwrap_or(next) + 1;
+                out.push('$');
+                out.push_str(&digits);
+                continue;
+            }
+            None => (),
+        };
+        out.push(c);
+    }
+
+    out
+}
+
+// Turns `:name` parameters into positional ones, returning the names in the order of their positions
+pub fn named_placeholders(sql: &str) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(sql.len());
+    let mut names: Vec<String> = vec![];
+    let mut quote = None;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '\'' || c == '"' => quote = Some(c),
+            // a postgres cast, e.g. `::text`
+            None if c == ':' && chars.peek() == Some(&':') => {
+                out.push(c);
+                out.push(chars.next().unwrap_or(':'));
+                continue;
+            }
+            None if c == ':' && chars.peek().map_or(false, |n| n.is_ascii_alphabetic() || *n == '_') => {
+                let mut name = ":".to_string();
+                while let Some(n) = chars.peek().filter(|n| n.is_ascii_alphanumeric() || **n == '_') {
+                    name.push(*n);
+                    chars.next();
+                }
+                let index = match names.iter().position(|n| *n == name) {
+                    Some(i) => i,
+                    None => {
+                        names.push(name);
+                        names.len() - 1
+                    }
+                };
+                out.push_str(&format!("?{}", index + 1));
+                continue;
+            }
+            None => (),
+        };
+        out.push(c);
+    }
+
+    (out, names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbered_placeholders() {
+        assert_eq!(
+            "SELECT * FROM repos WHERE repo = $1 AND channel = $2",
+            numbered_placeholders("SELECT * FROM repos WHERE repo = ?1 AND channel = ?2")
+        );
+        assert_eq!("UPDATE t SET a = $1 WHERE b = $2", numbered_placeholders("UPDATE t SET a = ? WHERE b = ?"));
+        assert_eq!("SELECT '?1' FROM t WHERE a = $1", numbered_placeholders("SELECT '?1' FROM t WHERE a = ?1"));
+    }
+
+    #[test]
+    fn test_named_placeholders() {
+        let (sql, names) = named_placeholders("SELECT * FROM t WHERE a = :a AND (b = :b OR c = :a) AND d = '1:00'");
+        assert_eq!("SELECT * FROM t WHERE a = ?1 AND (b = ?2 OR c = ?1) AND d = '1:00'", sql);
+        assert_eq!(vec![":a".to_string(), ":b".to_string()], names);
+    }
+
+    #[test]
+    fn test_values() {
+        assert_eq!(Value::Integer(5), 5u32.to_sql());
+        assert_eq!(Value::Null, None::<String>.to_sql());
+        assert_eq!(Value::Text("hi".into()), "hi".to_sql());
+        assert_eq!(Some(7), Option::<i32>::from_sql(&Value::Integer(7)).unwrap());
+        assert_eq!(None, Option::<i32>::from_sql(&Value::Null).unwrap());
+        assert!(u8::from_sql(&Value::Integer(300)).is_err());
+        assert!(String::from_sql(&Value::Integer(3)).is_err());
+    }
+}
@@ -0,0 +1,470 @@
+use std::time::{Duration, Instant};
+
+use failure::format_err;
+use log::error;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::db::{self, Database, Row, ToSql};
+use crate::error_reports;
+use crate::errors::*;
+
+pub const SLACK: &str = "slack";
+pub const EMAIL: &str = "email";
+pub const DISCORD: &str = "discord";
+pub const MATRIX: &str = "matrix";
+pub const WEBEX: &str = "webex";
+pub const IRC: &str = "irc";
+
+// By default, only keep this many recent attempts
+pub const MAX_DELIVERIES: u32 = 20000;
+
+// One attempt to send a notification
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Delivery {
+    pub id: i32,
+    // i.e. "slack" or "email"
+    pub provider: String,
+    // the channel, "@" and a slack user id for direct messages, or an email address
+    pub target: String,
+    // the github delivery and event that sent it, empty for e.g. scheduled reminders
+    pub delivery_id: String,
+    pub event: String,
+    pub repo: String,
+    // the PR or issue it was about, 0 if none
+    pub number: u32,
+    pub latency_ms: i64,
+    // empty if it was delivered
+    pub error: String,
+    pub created_at: i64,
+}
+
+impl Delivery {
+    // An attempt that took |latency| in the context of the delivery that sent it
+    pub fn new(
+        provider: &str,
+        target: &str,
+        context: &error_reports::Context,
+        latency: Duration,
+        error: Option<String>,
+        now: i64,
+    ) -> Delivery {
+        Delivery {
+            id: 0,
+            provider: provider.into(),
+            target: target.into(),
+            delivery_id: context.delivery_id.clone(),
+            event: context.event.clone(),
+            repo: context.repo.clone(),
+            number: context.number,
+            latency_ms: latency.as_secs() as i64 * 1000 + latency.subsec_millis() as i64,
+            error: error.unwrap_or_default(),
+            created_at: now,
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.error.is_empty()
+    }
+}
+
+// Which attempts to list. All of them by default
+#[derive(Default, Debug)]
+pub struct DeliveryFilter {
+    pub provider: Option<String>,
+    pub target: Option<String>,
+    pub delivery_id: Option<String>,
+    pub repo: Option<String>,
+    pub number: Option<u32>,
+    // only the ones that failed, or only the ones that didn't
+    pub failed: Option<bool>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+impl DeliveryFilter {
+    fn conditions<'a>(&'a self) -> (String, Vec<(&'static str, &'a dyn ToSql)>) {
+        let mut conditions = vec![];
+        let mut params: Vec<(&'static str, &dyn ToSql)> = vec![];
+
+        let mut add = |condition: &str, name: &'static str, value: &'a dyn ToSql| {
+            conditions.push(condition.to_string());
+            params.push((name, value));
+        };
+        if let Some(ref provider) = self.provider {
+            add("provider = :provider", ":provider", provider);
+        }
+        if let Some(ref target) = self.target {
+            add("target = :target", ":target", target);
+        }
+        if let Some(ref delivery_id) = self.delivery_id {
+            add("delivery_id = :delivery_id", ":delivery_id", delivery_id);
+        }
+        if let Some(ref repo) = self.repo {
+            add("repo = :repo", ":repo", repo);
+        }
+        if let Some(ref number) = self.number {
+            add("number = :number", ":number", number);
+        }
+        if let Some(ref since) = self.since {
+            add("created_at >= :since", ":since", since);
+        }
+        if let Some(ref until) = self.until {
+            add("created_at < :until", ":until", until);
+        }
+        match self.failed {
+            Some(true) => conditions.push("error != ''".into()),
+            Some(false) => conditions.push("error = ''".into()),
+            None => (),
+        };
+
+        if conditions.is_empty() {
+            (String::new(), params)
+        } else {
+            (format!(" WHERE {}", conditions.join(" AND ")), params)
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DeliveryPage {
+    // newest first
+    pub deliveries: Vec<Delivery>,
+    pub page: u32,
+    pub per_page: u32,
+    // how many attempts match, over all pages
+    pub total: u32,
+}
+
+// How a provider has been doing
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DeliveryStats {
+    pub provider: String,
+    pub attempts: u32,
+    pub failures: u32,
+    pub avg_latency_ms: i64,
+    pub max_latency_ms: i64,
+    // 0 if none
+    pub last_success_at: i64,
+    pub last_failure_at: i64,
+    pub last_error: String,
+    // its latest attempt failed, e.g. because its token was revoked
+    pub failing: bool,
+}
+
+// A record of each attempt to send a notification, to tell whether someone was actually notified
+#[derive(Clone)]
+pub struct Deliveries {
+    db: Database,
+    max_deliveries: u32,
+}
+
+impl Deliveries {
+    pub fn new(db: Database) -> Deliveries {
+        Deliveries {
+            db: db,
+            max_deliveries: MAX_DELIVERIES,
+        }
+    }
+
+    pub fn with_max_deliveries(mut self, max_deliveries: u32) -> Deliveries {
+        self.max_deliveries = max_deliveries;
+        self
+    }
+
+    pub fn record(&self, delivery: &Delivery) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO deliveries
+                 (provider, target, delivery_id, event, repo, number, latency_ms, error, created_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"#,
+            &[
+                &delivery.provider as &dyn ToSql,
+                &delivery.target,
+                &delivery.delivery_id,
+                &delivery.event,
+                &delivery.repo,
+                &delivery.number,
+                &delivery.latency_ms,
+                &delivery.error,
+                &delivery.created_at,
+            ],
+        )
+        .map_err(|e| format_err!("Error recording delivery to {}: {}", delivery.target, e))?;
+
+        conn.execute(
+            "DELETE FROM deliveries WHERE id <= (SELECT MAX(id) FROM deliveries) - ?1",
+            &[&self.max_deliveries],
+        )
+        .map_err(|e| format_err!("Error pruning deliveries: {}", e))?;
+
+        Ok(())
+    }
+
+    // Records an attempt that started at |started|, in the current context. Errors are only logged, so that failing
+    // to record an attempt doesn't fail it.
+    pub fn attempted(&self, provider: &str, target: &str, started: Instant, error: Option<String>) {
+        self.attempted_in(&error_reports::current_context(), provider, target, started, error)
+    }
+
+    // The same, for an attempt that finishes outside the context that started it, e.g. in a future
+    pub fn attempted_in(
+        &self,
+        context: &error_reports::Context,
+        provider: &str,
+        target: &str,
+        started: Instant,
+        error: Option<String>,
+    ) {
+        let delivery = Delivery::new(provider, target, context, started.elapsed(), error, db::now());
+        if let Err(e) = self.record(&delivery) {
+            error!("{}", e);
+        }
+    }
+
+    // Sends with |send|, and records the attempt
+    pub fn track<F>(&self, provider: &str, target: &str, send: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let started = Instant::now();
+        let result = send();
+        self.attempted(provider, target, started, result.as_ref().err().map(|e| format!("{}", e)));
+        result
+    }
+
+    // One page (starting at 1) of the recorded attempts that match |filter|
+    pub fn search(&self, filter: &DeliveryFilter, page: u32, per_page: u32) -> Result<DeliveryPage> {
+        let page = page.max(1);
+        let conn = self.db.connect_read()?;
+        let (conditions, params) = filter.conditions();
+
+        let total: u32 = conn
+            .query_row_named(&format!("SELECT COUNT(*) FROM deliveries{}", conditions), &params, |row| row.get(0))
+            .map_err(|e| format_err!("Error counting deliveries: {}", e))?;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT * FROM deliveries{} ORDER BY id DESC LIMIT {} OFFSET {}",
+            conditions,
+            per_page,
+            (page as u64 - 1) * per_page as u64
+        ))?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&params)?;
+
+        let mut deliveries = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            deliveries.push(map_row(row, &cols)?);
+        }
+
+        Ok(DeliveryPage {
+            deliveries: deliveries,
+            page: page,
+            per_page: per_page,
+            total: total,
+        })
+    }
+
+    // Per provider, over the attempts since |since|
+    pub fn stats(&self, since: i64) -> Result<Vec<DeliveryStats>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT provider,
+                      COUNT(*) AS attempts,
+                      SUM(CASE WHEN error != '' THEN 1 ELSE 0 END) AS failures,
+                      CAST(AVG(latency_ms) AS INTEGER) AS avg_latency_ms,
+                      MAX(latency_ms) AS max_latency_ms,
+                      MAX(CASE WHEN error = '' THEN created_at ELSE 0 END) AS last_success_at,
+                      MAX(CASE WHEN error != '' THEN created_at ELSE 0 END) AS last_failure_at,
+                      MAX(CASE WHEN error = '' THEN id ELSE 0 END) AS last_success_id,
+                      MAX(CASE WHEN error != '' THEN id ELSE 0 END) AS last_failure_id
+               FROM deliveries WHERE created_at >= :since GROUP BY provider ORDER BY provider"#,
+        )?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":since", &since)])?;
+
+        let mut stats = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            let last_success_id: i32 = cols.get(row, "last_success_id")?;
+            let last_failure_id: i32 = cols.get(row, "last_failure_id")?;
+            let last_error = if last_failure_id > 0 {
+                conn.query_row_named("SELECT error FROM deliveries WHERE id = :id", &[(":id", &last_failure_id)], |r| {
+                    r.get(0)
+                })
+                .map_err(|e| format_err!("Error getting the last delivery error: {}", e))?
+            } else {
+                String::new()
+            };
+
+            stats.push(DeliveryStats {
+                provider: cols.get(row, "provider")?,
+                attempts: cols.get(row, "attempts")?,
+                failures: cols.get(row, "failures")?,
+                avg_latency_ms: cols.get(row, "avg_latency_ms")?,
+                max_latency_ms: cols.get(row, "max_latency_ms")?,
+                last_success_at: cols.get(row, "last_success_at")?,
+                last_failure_at: cols.get(row, "last_failure_at")?,
+                last_error: last_error,
+                failing: last_failure_id > last_success_id,
+            });
+        }
+
+        Ok(stats)
+    }
+}
+
+fn map_row(row: &Row, cols: &db::Columns) -> Result<Delivery> {
+    Ok(Delivery {
+        id: cols.get(row, "id")?,
+        provider: cols.get(row, "provider")?,
+        target: cols.get(row, "target")?,
+        delivery_id: cols.get(row, "delivery_id")?,
+        event: cols.get(row, "event")?,
+        repo: cols.get(row, "repo")?,
+        number: cols.get(row, "number")?,
+        latency_ms: cols.get(row, "latency_ms")?,
+        error: cols.get(row, "error")?,
+        created_at: cols.get(row, "created_at")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (Deliveries, TempDir) {
+        let temp_dir = TempDir::new("deliveries.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (Deliveries::new(db), temp_dir)
+    }
+
+    fn context(delivery_id: &str, number: u32) -> error_reports::Context {
+        error_reports::Context {
+            request_id: String::new(),
+            delivery_id: delivery_id.into(),
+            event: "pull_request".into(),
+            repo: "some-org/some-repo".into(),
+            number: number,
+            job: "slack".into(),
+        }
+    }
+
+    fn delivery(provider: &str, target: &str, number: u32, latency_ms: u64, error: &str, now: i64) -> Delivery {
+        let error = if error.is_empty() { None } else { Some(error.to_string()) };
+        let context = context(&format!("delivery-{}", now), number);
+        Delivery::new(provider, target, &context, Duration::from_millis(latency_ms), error, now)
+    }
+
+    #[test]
+    fn test_search() {
+        let (deliveries, _temp) = new_test();
+        deliveries.record(&delivery(SLACK, "@U123", 456, 120, "", 1000)).unwrap();
+        deliveries.record(&delivery(SLACK, "reviews", 456, 80, "", 1000)).unwrap();
+        deliveries.record(&delivery(SLACK, "@U123", 457, 2500, "invalid_auth", 2000)).unwrap();
+        deliveries.record(&delivery(EMAIL, "joe@company.com", 456, 900, "", 3000)).unwrap();
+
+        let filter = DeliveryFilter {
+            target: Some("@U123".into()),
+            repo: Some("some-org/some-repo".into()),
+            number: Some(456),
+            ..Default::default()
+        };
+        let page = deliveries.search(&filter, 1, 10).unwrap();
+        assert_eq!(1, page.total);
+        let found = &page.deliveries[0];
+        assert_eq!((SLACK, "delivery-1000", "pull_request"), (&*found.provider, &*found.delivery_id, &*found.event));
+        assert_eq!(120, found.latency_ms);
+        assert!(found.succeeded());
+
+        let filter = DeliveryFilter {
+            failed: Some(true),
+            ..Default::default()
+        };
+        let page = deliveries.search(&filter, 1, 10).unwrap();
+        assert_eq!(vec!["invalid_auth"], page.deliveries.iter().map(|d| d.error.as_str()).collect::<Vec<_>>());
+
+        // newest first
+        let page = deliveries.search(&DeliveryFilter::default(), 2, 3).unwrap();
+        assert_eq!(4, page.total);
+        assert_eq!(vec!["@U123"], page.deliveries.iter().map(|d| d.target.as_str()).collect::<Vec<_>>());
+
+        let filter = DeliveryFilter {
+            provider: Some(SLACK.into()),
+            since: Some(2000),
+            ..Default::default()
+        };
+        assert_eq!(1, deliveries.search(&filter, 1, 10).unwrap().total);
+    }
+
+    #[test]
+    fn test_stats() {
+        let (deliveries, _temp) = new_test();
+        deliveries.record(&delivery(SLACK, "reviews", 1, 100, "", 1000)).unwrap();
+        deliveries.record(&delivery(SLACK, "reviews", 1, 300, "", 2000)).unwrap();
+        deliveries.record(&delivery(SLACK, "@U123", 2, 50, "invalid_auth", 3000)).unwrap();
+        deliveries.record(&delivery(EMAIL, "joe@company.com", 2, 900, "", 3000)).unwrap();
+
+        assert_eq!(
+            vec![
+                DeliveryStats {
+                    provider: EMAIL.into(),
+                    attempts: 1,
+                    failures: 0,
+                    avg_latency_ms: 900,
+                    max_latency_ms: 900,
+                    last_success_at: 3000,
+                    last_failure_at: 0,
+                    last_error: "".into(),
+                    failing: false,
+                },
+                DeliveryStats {
+                    provider: SLACK.into(),
+                    attempts: 3,
+                    failures: 1,
+                    avg_latency_ms: 150,
+                    max_latency_ms: 300,
+                    last_success_at: 2000,
+                    last_failure_at: 3000,
+                    last_error: "invalid_auth".into(),
+                    failing: true,
+                },
+            ],
+            deliveries.stats(0).unwrap()
+        );
+
+        let stats = deliveries.stats(1500).unwrap();
+        assert_eq!(vec![1, 2], stats.iter().map(|s| s.attempts).collect::<Vec<_>>());
+        assert!(deliveries.stats(5000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_track() {
+        let (deliveries, _temp) = new_test();
+
+        error_reports::with_context(context("delivery-1", 32), || {
+            assert!(deliveries.track(SLACK, "reviews", || Ok(())).is_ok());
+            assert!(deliveries.track(SLACK, "reviews", || Err(format_err!("channel_not_found"))).is_err());
+        });
+
+        let page = deliveries.search(&DeliveryFilter::default(), 1, 10).unwrap();
+        assert_eq!(
+            vec![("delivery-1", 32, "channel_not_found"), ("delivery-1", 32, "")],
+            page.deliveries.iter().map(|d| (d.delivery_id.as_str(), d.number, d.error.as_str())).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        let (deliveries, _temp) = new_test();
+        let deliveries = deliveries.with_max_deliveries(2);
+        for i in 0..4 {
+            deliveries.record(&delivery(SLACK, &format!("channel-{}", i), 1, 10, "", 1000 + i)).unwrap();
+        }
+
+        let page = deliveries.search(&DeliveryFilter::default(), 1, 10).unwrap();
+        let targets = page.deliveries.iter().map(|d| d.target.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["channel-3", "channel-2"], targets);
+    }
+}
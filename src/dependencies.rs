@@ -0,0 +1,136 @@
+use log::error;
+use serde_derive::Serialize;
+
+use crate::errors::*;
+use crate::github;
+use crate::messenger::Messenger;
+use crate::repos::RepoInfo;
+use crate::slack::{SlackAttachment, SlackAttachmentBuilder};
+use crate::submodules;
+
+// PRs with any of these labels are breaking changes for downstream repos
+pub const BREAKING_LABELS: &[&str] = &["breaking", "breaking-change", "breaking change"];
+
+// An edge in the dependency graph between configured repos
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Dependency {
+    pub upstream: String,
+    pub downstream: String,
+    // "submodule" or "declared"
+    pub kind: String,
+}
+
+pub fn dependency_graph(repos: &Vec<RepoInfo>) -> Vec<Dependency> {
+    let mut graph = vec![];
+    for info in repos {
+        for submodule in &info.submodules {
+            graph.push(Dependency {
+                upstream: submodule.upstream.clone(),
+                downstream: info.repo.clone(),
+                kind: "submodule".into(),
+            });
+        }
+        for upstream in info.upstream_repos() {
+            if !graph.iter().any(|d| d.upstream == upstream && d.downstream == info.repo) {
+                graph.push(Dependency {
+                    upstream: upstream,
+                    downstream: info.repo.clone(),
+                    kind: "declared".into(),
+                });
+            }
+        }
+    }
+    graph
+}
+
+pub fn is_breaking(labels: &Vec<github::Label>) -> bool {
+    labels.iter().any(|l| BREAKING_LABELS.iter().any(|b| l.name.eq_ignore_ascii_case(b)))
+}
+
+// Downstream repos live on the same host as their upstream
+pub fn downstream_repo(upstream: &github::Repo, downstream: &str) -> Result<github::Repo> {
+    let host_url = upstream.html_url.trim_end_matches(&upstream.full_name);
+    github::Repo::parse(&format!("{}{}", host_url, downstream))
+}
+
+pub fn breaking_change_attachment(pull_request: &github::PullRequest) -> SlackAttachment {
+    SlackAttachmentBuilder::new("")
+        .title(format!("Pull Request #{}: \"{}\"", pull_request.number, pull_request.title))
+        .title_link(pull_request.html_url.clone())
+        .color("warning")
+        .build()
+}
+
+pub fn release_attachment(upstream: &github::Repo, release: &github::Release) -> SlackAttachment {
+    let title = match release.name {
+        Some(ref name) if !name.is_empty() => name.clone(),
+        _ => release.tag_name.clone(),
+    };
+    SlackAttachmentBuilder::new("")
+        .title(title)
+        .title_link(submodules::release_notes_url(upstream, release))
+        .build()
+}
+
+// Posts the message to the channel of each repo that depends on the upstream one
+pub fn notify_downstream(
+    messenger: &Messenger,
+    upstream: &github::Repo,
+    dependents: &Vec<RepoInfo>,
+    msg: &str,
+    attachments: &Vec<SlackAttachment>,
+) {
+    for dependent in dependents {
+        match downstream_repo(upstream, &dependent.repo) {
+            Ok(repo) => messenger.send_to_channel(msg, attachments, &repo, "", &Vec::<github::Commit>::new()),
+            Err(e) => error!("Error notifying downstream repo {}: {}", dependent.repo, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repos::RepoSubmodule;
+
+    #[test]
+    fn test_dependency_graph() {
+        let repos = vec![
+            RepoInfo::new("some-user/app", "reviews")
+                .with_submodule(RepoSubmodule::new("some-user/lib", "lib"))
+                .with_depends_on("some-user/lib,some-user/sdk"),
+            RepoInfo::new("some-user/tool", "reviews").with_depends_on("some-user/sdk"),
+        ];
+
+        let graph = dependency_graph(&repos)
+            .into_iter()
+            .map(|d| (d.upstream, d.downstream, d.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("some-user/lib".to_string(), "some-user/app".to_string(), "submodule".to_string()),
+                ("some-user/sdk".to_string(), "some-user/app".to_string(), "declared".to_string()),
+                ("some-user/sdk".to_string(), "some-user/tool".to_string(), "declared".to_string()),
+            ],
+            graph
+        );
+    }
+
+    #[test]
+    fn test_is_breaking() {
+        assert!(is_breaking(&vec![github::Label::new("bug"), github::Label::new("Breaking-Change")]));
+        assert!(is_breaking(&vec![github::Label::new("breaking")]));
+        assert!(!is_breaking(&vec![github::Label::new("bug")]));
+        assert!(!is_breaking(&vec![]));
+    }
+
+    #[test]
+    fn test_downstream_repo() {
+        let upstream = github::Repo::parse("https://git.company.com/some-user/lib").unwrap();
+        let repo = downstream_repo(&upstream, "other-user/app").unwrap();
+        assert_eq!("https://git.company.com/other-user/app", repo.html_url);
+        assert_eq!("other-user/app", repo.full_name);
+
+        assert!(downstream_repo(&upstream, "some-org").is_err());
+    }
+}
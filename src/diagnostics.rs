@@ -0,0 +1,579 @@
+use std::sync::Mutex;
+
+use failure::format_err;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::db::{self, Database, Row, ToSql};
+use crate::errors::*;
+use crate::github::HookBody;
+
+// By default, only keep diagnoses for this many recent events
+pub const MAX_EVENTS: u32 = 2000;
+
+// Collects the decisions made while handling a single event: who was (and wasn't) notified and why.
+#[derive(Default)]
+pub struct Trace {
+    steps: Mutex<Vec<String>>,
+    notifications: Mutex<u32>,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace {
+            steps: Mutex::new(vec![]),
+            notifications: Mutex::new(0),
+        }
+    }
+
+    pub fn note<S: Into<String>>(&self, step: S) {
+        self.steps.lock().unwrap().push(step.into());
+    }
+
+    pub fn sent<S: Into<String>>(&self, step: S) {
+        *self.notifications.lock().unwrap() += 1;
+        self.note(step);
+    }
+
+    pub fn steps(&self) -> Vec<String> {
+        self.steps.lock().unwrap().clone()
+    }
+
+    pub fn notifications(&self) -> u32 {
+        *self.notifications.lock().unwrap()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct EventDiagnosis {
+    // the X-GitHub-Delivery id
+    pub delivery_id: String,
+    pub event: String,
+    pub action: String,
+    pub repo: String,
+    // the PR or issue the event was about, 0 if none
+    pub number: u32,
+    // the github login that caused it
+    pub sender: String,
+    // a one line description of the payload, e.g. "PR #32 'Fix the thing' (feature -> main)"
+    pub summary: String,
+    // what the webhook handler responded with
+    pub result: String,
+    pub status: u16,
+    // why it was refused, e.g. "Invalid signature"
+    pub error: String,
+    pub notifications: u32,
+    pub steps: Vec<String>,
+    pub created_at: i64,
+}
+
+impl EventDiagnosis {
+    pub fn new(
+        delivery_id: &str,
+        event: &str,
+        action: &str,
+        repo: &str,
+        number: u32,
+        result: &str,
+        trace: &Trace,
+    ) -> EventDiagnosis {
+        EventDiagnosis {
+            delivery_id: delivery_id.into(),
+            event: event.into(),
+            action: action.into(),
+            repo: repo.into(),
+            number: number,
+            sender: String::new(),
+            summary: String::new(),
+            result: result.into(),
+            status: 200,
+            error: String::new(),
+            notifications: trace.notifications(),
+            steps: trace.steps(),
+            created_at: db::now(),
+        }
+    }
+
+    // A delivery that wasn't handled. Its body may not be valid, or trusted: it's only used to show what it claims
+    // to be about.
+    pub fn rejected(delivery_id: &str, event: &str, body: &[u8], status: u16, error: &str) -> EventDiagnosis {
+        let body = serde_json::from_slice::<serde_json::Value>(body).unwrap_or_default();
+        let field = |value: &serde_json::Value| value.as_str().unwrap_or_default().to_string();
+
+        let mut diagnosis = EventDiagnosis::new(
+            delivery_id,
+            event,
+            &field(&body["action"]),
+            &field(&body["repository"]["full_name"]),
+            0,
+            "",
+            &Trace::new(),
+        );
+        diagnosis.sender = field(&body["sender"]["login"]);
+        diagnosis.status = status;
+        diagnosis.error = error.into();
+        diagnosis
+    }
+
+    pub fn with_payload(mut self, sender: &str, summary: &str) -> EventDiagnosis {
+        self.sender = sender.into();
+        self.summary = summary.into();
+        self
+    }
+
+    pub fn with_status(mut self, status: u16) -> EventDiagnosis {
+        self.status = status;
+        self
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status >= 400
+    }
+}
+
+fn short_sha(sha: &str) -> &str {
+    &sha[..sha.len().min(7)]
+}
+
+// What a delivery was about, in a line
+pub fn summarize(event: &str, data: &HookBody) -> String {
+    let action = data.action.as_ref().map(|a| format!(" {}", a)).unwrap_or_default();
+
+    if let Some(ref pr) = data.pull_request {
+        let mut summary = format!(
+            "PR #{} '{}' ({} -> {}){}",
+            pr.number, pr.title, pr.head.ref_name, pr.base.ref_name, action
+        );
+        if let Some(ref review) = data.review {
+            summary += &format!(": review {} by {}", review.state, review.user.login());
+        } else if let Some(ref comment) = data.comment {
+            summary += &format!(": comment by {}", comment.user.login());
+        }
+        return summary;
+    }
+    if let Some(ref issue) = data.issue {
+        let mut summary = format!("issue #{} '{}'{}", issue.number, issue.title, action);
+        if let Some(ref comment) = data.comment {
+            summary += &format!(": comment by {}", comment.user.login());
+        }
+        return summary;
+    }
+    if let Some(ref release) = data.release {
+        return format!("release {}{}", release.tag_name, action);
+    }
+    if let Some(ref ref_name) = data.ref_name {
+        let commits = data.commits.as_ref().map(|c| c.len()).unwrap_or(0);
+        return format!(
+            "{} to {} ({}..{}, {} commits){}",
+            event,
+            ref_name,
+            short_sha(data.before.as_ref().map(|s| s.as_str()).unwrap_or("")),
+            short_sha(data.after.as_ref().map(|s| s.as_str()).unwrap_or("")),
+            commits,
+            if data.forced == Some(true) { ", forced" } else { "" }
+        );
+    }
+    if let (Some(sha), Some(state)) = (&data.sha, &data.state) {
+        return format!(
+            "{} {} for {}: {}",
+            data.context.as_ref().map(|c| c.as_str()).unwrap_or(event),
+            state,
+            short_sha(sha),
+            data.description.as_ref().map(|d| d.as_str()).unwrap_or("")
+        );
+    }
+    if let Some(ref comment) = data.comment {
+        return format!("commit comment by {}", comment.user.login());
+    }
+
+    format!("{}{}", event, action)
+}
+
+// Which deliveries to list. All of them by default
+#[derive(Default, Debug)]
+pub struct EventFilter {
+    pub repo: Option<String>,
+    pub event: Option<String>,
+    pub action: Option<String>,
+    pub sender: Option<String>,
+    pub number: Option<u32>,
+    // only the ones that weren't handled, or only the ones that were
+    pub failed: Option<bool>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+impl EventFilter {
+    fn conditions<'a>(&'a self) -> (String, Vec<(&'static str, &'a dyn ToSql)>) {
+        let mut conditions = vec![];
+        let mut params: Vec<(&'static str, &dyn ToSql)> = vec![];
+
+        let mut add = |condition: &str, name: &'static str, value: &'a dyn ToSql| {
+            conditions.push(condition.to_string());
+            params.push((name, value));
+        };
+        if let Some(ref repo) = self.repo {
+            add("repo = :repo", ":repo", repo);
+        }
+        if let Some(ref event) = self.event {
+            add("event = :event", ":event", event);
+        }
+        if let Some(ref action) = self.action {
+            add("action = :action", ":action", action);
+        }
+        if let Some(ref sender) = self.sender {
+            add("sender = :sender", ":sender", sender);
+        }
+        if let Some(ref number) = self.number {
+            add("number = :number", ":number", number);
+        }
+        if let Some(ref since) = self.since {
+            add("created_at >= :since", ":since", since);
+        }
+        if let Some(ref until) = self.until {
+            add("created_at < :until", ":until", until);
+        }
+        match self.failed {
+            Some(true) => conditions.push("status >= 400".into()),
+            Some(false) => conditions.push("status < 400".into()),
+            None => (),
+        };
+
+        if conditions.is_empty() {
+            (String::new(), params)
+        } else {
+            (format!(" WHERE {}", conditions.join(" AND ")), params)
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EventPage {
+    // newest first
+    pub events: Vec<EventDiagnosis>,
+    pub page: u32,
+    pub per_page: u32,
+    // how many events match, over all pages
+    pub total: u32,
+}
+
+#[derive(Clone)]
+pub struct EventDiagnostics {
+    db: Database,
+    max_events: u32,
+}
+
+impl EventDiagnostics {
+    pub fn new(db: Database) -> EventDiagnostics {
+        EventDiagnostics {
+            db: db,
+            max_events: MAX_EVENTS,
+        }
+    }
+
+    pub fn with_max_events(mut self, max_events: u32) -> EventDiagnostics {
+        self.max_events = max_events;
+        self
+    }
+
+    // Replaces what was recorded for the delivery before, e.g. when github redelivers it
+    pub fn record(&self, diagnosis: &EventDiagnosis) -> Result<()> {
+        self.insert(true, diagnosis)
+    }
+
+    // Records a delivery that wasn't handled. Anyone can send those, so they don't replace what was recorded for
+    // the delivery id before.
+    pub fn record_rejected(&self, diagnosis: &EventDiagnosis) -> Result<()> {
+        self.insert(false, diagnosis)
+    }
+
+    fn insert(&self, replace: bool, diagnosis: &EventDiagnosis) -> Result<()> {
+        let mut conn = self.db.connect()?;
+        let tx = conn.transaction()?;
+        if replace {
+            // deleted rather than updated so that it moves to the end, like a new event
+            tx.execute("DELETE FROM event_diagnoses WHERE delivery_id = ?1", &[&diagnosis.delivery_id])
+                .map_err(|e| format_err!("Error recording diagnosis for event {}: {}", diagnosis.delivery_id, e))?;
+        }
+        tx.execute(
+            r#"INSERT INTO event_diagnoses
+                 (delivery_id, event, action, repo, number, sender, summary, result, status, error, notifications,
+                  steps, created_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+               ON CONFLICT DO NOTHING"#,
+            &[
+                &diagnosis.delivery_id as &dyn ToSql,
+                &diagnosis.event,
+                &diagnosis.action,
+                &diagnosis.repo,
+                &diagnosis.number,
+                &diagnosis.sender,
+                &diagnosis.summary,
+                &diagnosis.result,
+                &diagnosis.status,
+                &diagnosis.error,
+                &diagnosis.notifications,
+                &diagnosis.steps.join("\n"),
+                &diagnosis.created_at,
+            ],
+        )
+        .map_err(|e| format_err!("Error recording diagnosis for event {}: {}", diagnosis.delivery_id, e))?;
+
+        tx.execute(
+            "DELETE FROM event_diagnoses WHERE rowid <= (SELECT MAX(rowid) FROM event_diagnoses) - ?1",
+            &[&self.max_events],
+        )
+        .map_err(|e| format_err!("Error pruning event diagnoses: {}", e))?;
+
+        tx.commit()
+    }
+
+    // One page (starting at 1) of the recorded events that match |filter|
+    pub fn search(&self, filter: &EventFilter, page: u32, per_page: u32) -> Result<EventPage> {
+        let page = page.max(1);
+        let conn = self.db.connect_read()?;
+        let (conditions, params) = filter.conditions();
+
+        let total: u32 = conn
+            .query_row_named(&format!("SELECT COUNT(*) FROM event_diagnoses{}", conditions), &params, |row| {
+                row.get(0)
+            })
+            .map_err(|e| format_err!("Error counting events: {}", e))?;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT * FROM event_diagnoses{} ORDER BY created_at DESC, rowid DESC LIMIT {} OFFSET {}",
+            conditions,
+            per_page,
+            (page as u64 - 1) * per_page as u64
+        ))?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&params)?;
+
+        let mut events = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            events.push(self.map_row(row, &cols)?);
+        }
+
+        Ok(EventPage {
+            events: events,
+            page: page,
+            per_page: per_page,
+            total: total,
+        })
+    }
+
+    pub fn get(&self, delivery_id: &str) -> Result<Option<EventDiagnosis>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare("SELECT * FROM event_diagnoses WHERE delivery_id = :id")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &delivery_id)])?;
+
+        if let Ok(Some(row)) = rows.next() {
+            Ok(Some(self.map_row(row, &cols)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // The recorded events about a PR (or issue), oldest first
+    pub fn get_timeline(&self, repo: &str, number: u32) -> Result<Vec<EventDiagnosis>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM event_diagnoses WHERE repo = :repo AND number = :number ORDER BY created_at, rowid",
+        )?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":repo", &repo), (":number", &number)])?;
+
+        let mut timeline = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            timeline.push(self.map_row(row, &cols)?);
+        }
+
+        Ok(timeline)
+    }
+
+    fn map_row(&self, row: &Row, cols: &db::Columns) -> Result<EventDiagnosis> {
+        let steps: String = cols.get(row, "steps")?;
+        Ok(EventDiagnosis {
+            delivery_id: cols.get(row, "delivery_id")?,
+            event: cols.get(row, "event")?,
+            action: cols.get(row, "action")?,
+            repo: cols.get(row, "repo")?,
+            number: cols.get(row, "number")?,
+            sender: cols.get(row, "sender")?,
+            summary: cols.get(row, "summary")?,
+            result: cols.get(row, "result")?,
+            status: cols.get(row, "status")?,
+            error: cols.get(row, "error")?,
+            notifications: cols.get(row, "notifications")?,
+            steps: steps.lines().map(|s| s.to_string()).collect(),
+            created_at: cols.get(row, "created_at")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    use crate::github;
+
+    fn new_test() -> (EventDiagnostics, TempDir) {
+        let temp_dir = TempDir::new("diagnostics.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (EventDiagnostics::new(db), temp_dir)
+    }
+
+    #[test]
+    fn test_event_diagnostics() {
+        let (diagnostics, _temp) = new_test();
+
+        let trace = Trace::new();
+        trace.note("No slack user mapped to github user 'joe'");
+        trace.sent("Sent to channel 'reviews'");
+        let diagnosis =
+            EventDiagnosis::new("abc-123", "pull_request", "opened", "some-user/some-repo", 32, "pr", &trace);
+
+        diagnostics.record(&diagnosis).unwrap();
+
+        assert_eq!(Some(diagnosis), diagnostics.get("abc-123").unwrap());
+        assert_eq!(None, diagnostics.get("def-456").unwrap());
+    }
+
+    #[test]
+    fn test_get_timeline() {
+        let (diagnostics, _temp) = new_test();
+
+        let trace = Trace::new();
+        let opened = EventDiagnosis::new("abc-1", "pull_request", "opened", "some-user/some-repo", 32, "pr", &trace);
+        let other = EventDiagnosis::new("abc-2", "pull_request", "opened", "some-user/some-repo", 33, "pr", &trace);
+        let push = EventDiagnosis::new("abc-3", "push", "", "some-user/some-repo", 0, "push", &trace);
+        let comment = EventDiagnosis::new("abc-4", "issue_comment", "created", "some-user/some-repo", 32, "", &trace);
+        for d in &[&opened, &other, &push, &comment] {
+            diagnostics.record(d).unwrap();
+        }
+
+        assert_eq!(vec![opened, comment], diagnostics.get_timeline("some-user/some-repo", 32).unwrap());
+        assert!(diagnostics.get_timeline("some-user/other-repo", 32).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search() {
+        let (diagnostics, _temp) = new_test();
+
+        let trace = Trace::new();
+        let at = |d: EventDiagnosis, created_at: i64| EventDiagnosis { created_at: created_at, ..d };
+        let opened = at(
+            EventDiagnosis::new("abc-1", "pull_request", "opened", "some-user/some-repo", 32, "pr", &trace)
+                .with_payload("joe", "PR #32 'Fix it' (fix -> main) opened"),
+            100,
+        );
+        let push = at(EventDiagnosis::new("abc-2", "push", "", "some-user/some-repo", 0, "push", &trace), 200);
+        let other = at(
+            EventDiagnosis::new("abc-3", "pull_request", "closed", "some-user/other-repo", 7, "pr", &trace)
+                .with_payload("mary", ""),
+            300,
+        );
+        let refused = at(EventDiagnosis::rejected("abc-4", "push", b"{}", 403, "Invalid signature"), 400);
+        for d in &[&opened, &push, &other, &refused] {
+            diagnostics.record(d).unwrap();
+        }
+
+        let search = |filter: EventFilter, page: u32, per_page: u32| {
+            let page = diagnostics.search(&filter, page, per_page).unwrap();
+            (page.total, page.events.into_iter().map(|e| e.delivery_id).collect::<Vec<_>>())
+        };
+
+        assert_eq!(
+            (4, vec!["abc-4".to_string(), "abc-3".into(), "abc-2".into(), "abc-1".into()]),
+            search(EventFilter::default(), 1, 10)
+        );
+        assert_eq!((4, vec!["abc-2".to_string(), "abc-1".into()]), search(EventFilter::default(), 2, 2));
+        assert_eq!((4, vec![]), search(EventFilter::default(), 3, 2));
+
+        let repo = EventFilter {
+            repo: Some("some-user/some-repo".into()),
+            ..EventFilter::default()
+        };
+        assert_eq!((2, vec!["abc-2".to_string(), "abc-1".into()]), search(repo, 1, 10));
+
+        let failed = EventFilter {
+            failed: Some(true),
+            ..EventFilter::default()
+        };
+        assert_eq!((1, vec!["abc-4".to_string()]), search(failed, 1, 10));
+
+        let filter = EventFilter {
+            event: Some("pull_request".into()),
+            sender: Some("joe".into()),
+            number: Some(32),
+            failed: Some(false),
+            since: Some(100),
+            until: Some(200),
+            ..EventFilter::default()
+        };
+        assert_eq!(vec![opened], diagnostics.search(&filter, 1, 10).unwrap().events);
+    }
+
+    #[test]
+    fn test_record_rejected() {
+        let (diagnostics, _temp) = new_test();
+
+        let body = serde_json::json!({
+            "action": "opened",
+            "repository": { "full_name": "some-user/some-repo" },
+            "sender": { "login": "joe" },
+        });
+        let body = serde_json::to_vec(&body).unwrap();
+        let refused = EventDiagnosis::rejected("abc-1", "pull_request", &body, 403, "Invalid signature");
+        assert_eq!("opened", refused.action);
+        assert_eq!("some-user/some-repo", refused.repo);
+        assert_eq!("joe", refused.sender);
+        assert!(refused.failed());
+
+        let trace = Trace::new();
+        let handled = EventDiagnosis::new("abc-2", "pull_request", "opened", "some-user/some-repo", 32, "pr", &trace);
+        diagnostics.record(&handled).unwrap();
+
+        // a forged delivery doesn't replace the real one
+        diagnostics.record_rejected(&EventDiagnosis { delivery_id: "abc-2".into(), ..refused.clone() }).unwrap();
+        diagnostics.record_rejected(&refused).unwrap();
+        assert_eq!(Some(handled), diagnostics.get("abc-2").unwrap());
+        assert_eq!(Some(refused), diagnostics.get("abc-1").unwrap());
+
+        let garbage = EventDiagnosis::rejected("abc-3", "push", b"not json", 400, "Error parsing JSON");
+        assert_eq!("", garbage.repo);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let mut data = HookBody::new();
+        data.action = Some("opened".into());
+        data.pull_request = Some(github::PullRequest::new());
+        if let Some(ref mut pr) = data.pull_request {
+            pr.number = 32;
+            pr.title = "Fix the thing".into();
+            pr.head = github::BranchRef::new("fix");
+            pr.base = github::BranchRef::new("main");
+        }
+        assert_eq!("PR #32 'Fix the thing' (fix -> main) opened", summarize("pull_request", &data));
+
+        let mut data = HookBody::new();
+        data.ref_name = Some("refs/heads/main".into());
+        data.before = Some("1111111111111111".into());
+        data.after = Some("2222222222222222".into());
+        data.forced = Some(true);
+        assert_eq!("push to refs/heads/main (1111111..2222222, 0 commits), forced", summarize("push", &data));
+
+        let mut data = HookBody::new();
+        data.sha = Some("3333333333".into());
+        data.state = Some("failure".into());
+        data.context = Some("ci/build".into());
+        data.description = Some("Tests failed".into());
+        assert_eq!("ci/build failure for 3333333: Tests failed", summarize("status", &data));
+
+        assert_eq!("ping", summarize("ping", &HookBody::new()));
+    }
+}
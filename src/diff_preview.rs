@@ -0,0 +1,162 @@
+use crate::github::PullRequestFile;
+use crate::util;
+
+// keeps the preview to a few screens of slack, well under its limit for attachment text
+const MAX_PREVIEW_CHARS: usize = 2000;
+const MAX_LINE_CHARS: usize = 200;
+
+// The diff of a PR with at most `max_lines` changed lines, as a slack code block. If the diff itself is too long to
+// show, this lists the functions it changes instead (from the hunk headers), or else the start of the diff.
+pub fn preview(files: &Vec<PullRequestFile>, max_lines: u32) -> Option<String> {
+    if max_lines == 0 || files.is_empty() {
+        return None;
+    }
+    let changed: u32 = files.iter().map(|f| f.additions + f.deletions).sum();
+    if changed == 0 || changed > max_lines {
+        return None;
+    }
+
+    let lines = diff_lines(files);
+    if lines.iter().map(|l| l.len() + 1).sum::<usize>() <= MAX_PREVIEW_CHARS {
+        return Some(code_block(&lines));
+    }
+
+    let signatures = changed_signatures(files);
+    if !signatures.is_empty() {
+        return Some(format!("Changed functions:\n{}", code_block(&truncate(signatures))));
+    }
+
+    Some(code_block(&truncate(lines)))
+}
+
+fn diff_lines(files: &Vec<PullRequestFile>) -> Vec<String> {
+    let mut lines = vec![];
+    for file in files {
+        lines.push(format!("--- {}", file.filename));
+        match file.patch {
+            Some(ref patch) => lines.extend(patch.lines().map(shorten)),
+            None => lines.push("(binary or too large to show)".into()),
+        }
+    }
+    lines
+}
+
+// "@@ -10,6 +10,8 @@ fn foo(bar: u32) {" has the function the hunk is in, when git could tell
+fn changed_signatures(files: &Vec<PullRequestFile>) -> Vec<String> {
+    let mut signatures = vec![];
+    for file in files {
+        if let Some(ref patch) = file.patch {
+            for line in patch.lines().filter(|l| l.starts_with("@@")) {
+                let signature = match line[2..].splitn(2, "@@").nth(1) {
+                    Some(s) if !s.trim().is_empty() => format!("{}: {}", file.filename, shorten(s.trim())),
+                    _ => continue,
+                };
+                if !signatures.contains(&signature) {
+                    signatures.push(signature);
+                }
+            }
+        }
+    }
+    signatures
+}
+
+fn shorten(line: &str) -> String {
+    if line.chars().count() <= MAX_LINE_CHARS {
+        line.to_string()
+    } else {
+        format!("{}...", line.chars().take(MAX_LINE_CHARS).collect::<String>())
+    }
+}
+
+// Cuts at a line boundary, leaving room to say how much was left out
+fn truncate(lines: Vec<String>) -> Vec<String> {
+    let mut kept = vec![];
+    let mut len = 0;
+    for (i, line) in lines.iter().enumerate() {
+        if len + line.len() + 1 > MAX_PREVIEW_CHARS - 50 {
+            kept.push(format!("... {} more lines", lines.len() - i));
+            break;
+        }
+        len += line.len() + 1;
+        kept.push(line.clone());
+    }
+    kept
+}
+
+fn code_block(lines: &Vec<String>) -> String {
+    // a ``` in the diff would end the block early
+    format!("```\n{}\n```", util::escape_for_slack(&lines.join("\n")).replace("```", "'''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, additions: u32, deletions: u32, patch: Option<&str>) -> PullRequestFile {
+        let mut f = PullRequestFile::new(name);
+        f.additions = additions;
+        f.deletions = deletions;
+        f.patch = patch.map(|p| p.to_string());
+        f
+    }
+
+    #[test]
+    fn test_preview() {
+        let files = vec![
+            file("src/main.rs", 1, 1, Some("@@ -1,3 +1,3 @@ fn main() {\n-    a < b\n+    a > b\n }")),
+            file("logo.png", 0, 0, None),
+        ];
+        assert_eq!(
+            Some(
+                "```\n--- src/main.rs\n@@ -1,3 +1,3 @@ fn main() {\n-    a &lt; b\n+    a &gt; b\n }\n--- logo.png\n\
+                 (binary or too large to show)\n```"
+                    .to_string()
+            ),
+            preview(&files, 10)
+        );
+    }
+
+    #[test]
+    fn test_preview_too_big() {
+        let files = vec![file("src/main.rs", 8, 3, Some("@@ -1 +1 @@\n-a\n+b"))];
+        assert_eq!(None, preview(&files, 10));
+        assert_eq!(None, preview(&files, 0));
+        assert_eq!(None, preview(&vec![], 10));
+    }
+
+    #[test]
+    fn test_preview_signatures() {
+        let mut patch = String::from("@@ -1,100 +1,100 @@ fn first() {\n");
+        for i in 0..100 {
+            patch += &format!("+    let x{} = some_really_long_function_name_to_fill_the_preview();\n", i);
+        }
+        patch += "@@ -200,3 +200,3 @@\n-a\n+b\n@@ -300,3 +300,3 @@ impl Thing {\n-c\n+d\n";
+
+        let files = vec![file("src/lib.rs", 102, 2, Some(&patch))];
+        assert_eq!(
+            Some("Changed functions:\n```\nsrc/lib.rs: fn first() {\nsrc/lib.rs: impl Thing {\n```".to_string()),
+            preview(&files, 200)
+        );
+    }
+
+    #[test]
+    fn test_preview_truncated() {
+        let mut patch = String::from("@@ -1,100 +1,100 @@\n");
+        for i in 0..100 {
+            patch += &format!("+{} a line that is long enough to go over the limit sooner or later\n", i);
+        }
+
+        let files = vec![file("notes.txt", 100, 0, Some(&patch))];
+        let preview = preview(&files, 100).unwrap();
+        assert!(preview.len() <= MAX_PREVIEW_CHARS + 8);
+        assert!(preview.starts_with("```\n--- notes.txt\n@@ -1,100 +1,100 @@\n+0 a line"));
+        assert!(preview.ends_with(" more lines\n```"));
+    }
+
+    #[test]
+    fn test_shorten() {
+        assert_eq!("abc", shorten("abc"));
+        let long = "é".repeat(MAX_LINE_CHARS + 1);
+        assert_eq!(format!("{}...", "é".repeat(MAX_LINE_CHARS)), shorten(&long));
+    }
+}
@@ -0,0 +1,474 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::Serialize;
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::repos::RepoInfo;
+use crate::scheduler;
+use crate::slack::{self, SlackAttachment, SlackAttachmentBuilder, SlackRequest};
+use crate::stale_prs;
+use crate::users::{self, UserInfo};
+use crate::util;
+use crate::worker::Worker;
+
+pub const DAILY: &str = "daily";
+pub const WEEKLY: &str = "weekly";
+
+// PRs waiting for the user's review
+pub const KIND_REVIEW_REQUESTED: &str = "review_requested";
+// The user's approved PRs that haven't been merged yet
+pub const KIND_AWAITING_MERGE: &str = "awaiting_merge";
+// Branches whose latest commit (by the user) failed CI
+pub const KIND_CI_FAILED: &str = "ci_failed";
+
+pub fn is_valid_frequency(value: &str) -> bool {
+    value == DAILY || value == WEEKLY
+}
+
+// Something that goes in a digest. Kept up to date from webhooks so that digests don't need to
+// query github for every user.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DigestItem {
+    pub repo: String,
+    pub kind: String,
+    // The PR number, or the branch name for CI failures
+    pub item: String,
+    pub github_user: String,
+    pub title: String,
+    pub url: String,
+    pub updated_at: i64,
+}
+
+impl DigestItem {
+    pub fn new(repo: &str, kind: &str, item: &str, github_user: &str) -> DigestItem {
+        DigestItem {
+            repo: repo.into(),
+            kind: kind.into(),
+            item: item.into(),
+            github_user: github_user.into(),
+            title: String::new(),
+            url: String::new(),
+            updated_at: 0,
+        }
+    }
+
+    fn for_pr(kind: &str, repo: &github::Repo, pull_request: &github::PullRequest, user: &str) -> DigestItem {
+        let mut item = DigestItem::new(&repo.full_name, kind, &pull_request.number.to_string(), user);
+        item.title = format!("{}#{}: \"{}\"", repo.full_name, pull_request.number, pull_request.title);
+        item.url = pull_request.html_url.clone();
+        item
+    }
+}
+
+#[derive(Clone)]
+pub struct DigestItems {
+    db: Database,
+}
+
+impl DigestItems {
+    pub fn new(db: Database) -> DigestItems {
+        DigestItems { db: db }
+    }
+
+    pub fn add(&self, item: &DigestItem) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO digest_items (repo, kind, item, github_user, title, url, updated_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+               ON CONFLICT (repo, kind, item, github_user) DO UPDATE SET title = excluded.title,
+                   url = excluded.url, updated_at = excluded.updated_at"#,
+            &[
+                &item.repo as &dyn ToSql,
+                &item.kind,
+                &item.item,
+                &item.github_user,
+                &item.title,
+                &item.url,
+                &db::now(),
+            ],
+        )
+        .map_err(|e| format_err!("Error adding digest item {} {}: {}", item.repo, item.item, e))?;
+
+        Ok(())
+    }
+
+    // Removes the item for all users
+    pub fn remove(&self, repo: &str, kind: &str, item: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "DELETE FROM digest_items WHERE repo = ?1 AND kind = ?2 AND item = ?3",
+            &[&repo as &dyn ToSql, &kind, &item],
+        )
+        .map_err(|e| format_err!("Error removing digest item {} {}: {}", repo, item, e))?;
+
+        Ok(())
+    }
+
+    pub fn for_user(&self, github_user: &str) -> Result<Vec<DigestItem>> {
+        self.query("WHERE github_user = :user", &[(":user", &github_user)])
+    }
+
+    // Org-level repo entries (e.g. "some-org") get the items of all the org's repos
+    pub fn for_repo(&self, repo: &str) -> Result<Vec<DigestItem>> {
+        if repo.contains('/') {
+            self.query("WHERE repo = :repo", &[(":repo", &repo)])
+        } else {
+            let prefix = format!("{}/%", repo);
+            self.query("WHERE repo LIKE :prefix", &[(":prefix", &prefix)])
+        }
+    }
+
+    fn query(&self, filter: &str, params: &[(&str, &dyn ToSql)]) -> Result<Vec<DigestItem>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT * FROM digest_items {} ORDER BY kind, repo, updated_at",
+            filter
+        ))?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(params)?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(DigestItem {
+                repo: cols.get(row, "repo")?,
+                kind: cols.get(row, "kind")?,
+                item: cols.get(row, "item")?,
+                github_user: cols.get(row, "github_user")?,
+                title: cols.get(row, "title")?,
+                url: cols.get(row, "url")?,
+                updated_at: cols.get(row, "updated_at")?,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+// Keeps the review requests and approvals of a PR up to date from pull_request and pull_request_review events
+pub fn record_pull_request(
+    items: &DigestItems,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    review: Option<&github::Review>,
+) -> Result<()> {
+    let number = pull_request.number.to_string();
+    items.remove(&repo.full_name, KIND_REVIEW_REQUESTED, &number)?;
+
+    if pull_request.state == "closed" {
+        return items.remove(&repo.full_name, KIND_AWAITING_MERGE, &number);
+    }
+
+    if !pull_request.is_draft() {
+        for reviewer in pull_request.requested_reviewers.as_ref().unwrap_or(&vec![]) {
+            items.add(&DigestItem::for_pr(KIND_REVIEW_REQUESTED, repo, pull_request, reviewer.login()))?;
+        }
+    }
+
+    match review.map(|r| r.state.to_lowercase()) {
+        Some(ref state) if state == "approved" => items.add(&DigestItem::for_pr(
+            KIND_AWAITING_MERGE,
+            repo,
+            pull_request,
+            pull_request.user.login(),
+        )),
+        Some(ref state) if state == "changes_requested" => {
+            items.remove(&repo.full_name, KIND_AWAITING_MERGE, &number)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Remembers failed CI on branches from status events, until a later status succeeds
+pub fn record_status(items: &DigestItems, data: &github::HookBody) -> Result<()> {
+    let state = data.state.as_ref().map(|s| s.as_str()).unwrap_or("");
+    let author = data.commit.as_ref().and_then(|c| c.author.as_ref());
+    let branches = match data.branches {
+        Some(ref b) => b,
+        None => return Ok(()),
+    };
+
+    for branch in branches {
+        match state {
+            "failure" | "error" => {
+                if let Some(author) = author {
+                    let repo = &data.repository.full_name;
+                    let mut item = DigestItem::new(repo, KIND_CI_FAILED, &branch.name, author.login());
+                    item.title = format!(
+                        "{} {}: {}",
+                        repo,
+                        branch.name,
+                        data.context.as_ref().map(|c| c.as_str()).unwrap_or("CI")
+                    );
+                    item.url = data.target_url.clone().unwrap_or(String::new());
+                    items.add(&item)?;
+                }
+            }
+            "success" => items.remove(&data.repository.full_name, KIND_CI_FAILED, &branch.name)?,
+            _ => (),
+        };
+    }
+
+    Ok(())
+}
+
+fn section(items: &Vec<DigestItem>, kind: &str, title: &str) -> Option<SlackAttachment> {
+    let lines = items
+        .iter()
+        .filter(|i| i.kind == kind)
+        .map(|i| {
+            if i.url.is_empty() {
+                i.title.clone()
+            } else {
+                util::make_link(&i.url, &i.title)
+            }
+        })
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        return None;
+    }
+
+    Some(
+        SlackAttachmentBuilder::new(&lines.join("\n"))
+            .title(format!("{} ({})", title, lines.len()))
+            .build(),
+    )
+}
+
+// A user's digest, or None if there's nothing to tell them about
+pub fn user_digest(items: &Vec<DigestItem>) -> Option<(String, Vec<SlackAttachment>)> {
+    let attachments = vec![
+        section(items, KIND_REVIEW_REQUESTED, "Waiting for your review"),
+        section(items, KIND_AWAITING_MERGE, "Your approved pull requests"),
+        section(items, KIND_CI_FAILED, "Failed CI on your branches"),
+    ]
+    .into_iter()
+    .filter_map(|a| a)
+    .collect::<Vec<_>>();
+
+    if attachments.is_empty() {
+        None
+    } else {
+        Some(("Here's your octobot digest".into(), attachments))
+    }
+}
+
+// A channel's digest: the same items for everyone working in the repo
+pub fn channel_digest(repo: &str, items: &Vec<DigestItem>) -> Option<(String, Vec<SlackAttachment>)> {
+    // items are per-user, but the channel only needs to hear about each PR or branch once
+    let mut unique: Vec<DigestItem> = vec![];
+    for item in items {
+        if !unique.iter().any(|u| u.repo == item.repo && u.kind == item.kind && u.item == item.item) {
+            unique.push(item.clone());
+        }
+    }
+
+    let attachments = vec![
+        section(&unique, KIND_REVIEW_REQUESTED, "Waiting for review"),
+        section(&unique, KIND_AWAITING_MERGE, "Approved and waiting to be merged"),
+        section(&unique, KIND_CI_FAILED, "Failed CI"),
+    ]
+    .into_iter()
+    .filter_map(|a| a)
+    .collect::<Vec<_>>();
+
+    if attachments.is_empty() {
+        None
+    } else {
+        Some((format!("Digest for {}", repo), attachments))
+    }
+}
+
+// Weekly digests go out on the configured day (e.g. "Mon")
+fn is_due(frequency: &str, weekly_day: &str, now: i64) -> bool {
+    match frequency {
+        DAILY => true,
+        WEEKLY => stale_prs::is_quiet_day(weekly_day, now),
+        _ => false,
+    }
+}
+
+pub struct DigestSender {
+    config: Arc<Config>,
+    slack: Arc<dyn Worker<SlackRequest>>,
+}
+
+impl DigestSender {
+    pub fn new(config: Arc<Config>, slack: Arc<dyn Worker<SlackRequest>>) -> Arc<dyn scheduler::Task> {
+        Arc::new(DigestSender {
+            config: config,
+            slack: slack,
+        })
+    }
+
+    fn send_user_digest(&self, user: &UserInfo) -> Result<()> {
+        let items = self.config.digest_items.for_user(&user.github)?;
+        if let Some((msg, attachments)) = user_digest(&items) {
+            self.slack.send(slack::req(&users::mention(&user.slack), &msg, attachments));
+        }
+        Ok(())
+    }
+
+    fn send_channel_digest(&self, info: &RepoInfo) -> Result<()> {
+        let items = self.config.digest_items.for_repo(&info.repo)?;
+        if let Some((msg, attachments)) = channel_digest(&info.repo, &items) {
+            self.slack.send(slack::req(&info.channel, &msg, attachments));
+        }
+        Ok(())
+    }
+}
+
+impl scheduler::Task for DigestSender {
+    fn run(&self, now: i64) -> Result<()> {
+        let weekly_day = self.config.digest_weekday();
+
+        let users = self.config.users().get_all()?;
+        for user in users.iter().filter(|u| is_due(&u.digest, &weekly_day, now)) {
+            // digests are still direct messages
+            if user.direct_messages_muted() {
+                info!("Skipping digest for {}: direct messages are muted", user.github);
+                continue;
+            }
+            if let Err(e) = self.send_user_digest(user) {
+                error!("Error sending digest to {}: {}", user.github, e);
+            }
+        }
+
+        let repos = self.config.repos().get_all()?;
+        for info in repos.iter().filter(|r| is_due(&r.channel_digest, &weekly_day, now)) {
+            if let Err(e) = self.send_channel_digest(info) {
+                error!("Error sending digest for {}: {}", info.repo, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    // 2019-05-01T12:00:00Z (a Wednesday)
+    const NOW: i64 = 1556712000;
+
+    fn new_test() -> (DigestItems, TempDir) {
+        let temp_dir = TempDir::new("digests.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (DigestItems::new(db), temp_dir)
+    }
+
+    fn repo() -> github::Repo {
+        github::Repo::parse("http://git.company.com/some-user/some-repo").unwrap()
+    }
+
+    fn pr(number: u32, reviewers: Vec<&str>) -> github::PullRequest {
+        let mut pr = github::PullRequest::new();
+        pr.number = number;
+        pr.title = format!("PR {}", number);
+        pr.html_url = format!("http://the-pr/{}", number);
+        pr.state = "open".into();
+        pr.user = github::User::new("the-author");
+        pr.requested_reviewers = Some(reviewers.into_iter().map(|r| github::User::new(r)).collect());
+        pr
+    }
+
+    fn review(state: &str) -> github::Review {
+        let mut review = github::Review::new("", github::User::new("joe"));
+        review.state = state.into();
+        review
+    }
+
+    fn kinds(items: Vec<DigestItem>) -> Vec<(String, String)> {
+        items.into_iter().map(|i| (i.kind, i.item)).collect()
+    }
+
+    #[test]
+    fn test_record_pull_request() {
+        let (items, _temp) = new_test();
+
+        record_pull_request(&items, &repo(), &pr(1, vec!["joe", "bob"]), None).unwrap();
+        record_pull_request(&items, &repo(), &pr(2, vec!["joe"]), None).unwrap();
+        assert_eq!(2, items.for_user("joe").unwrap().len());
+        assert_eq!(1, items.for_user("bob").unwrap().len());
+
+        // joe reviewed: github drops him from the requested reviewers
+        record_pull_request(&items, &repo(), &pr(1, vec!["bob"]), Some(&review("APPROVED"))).unwrap();
+        assert_eq!(
+            vec![(KIND_REVIEW_REQUESTED.to_string(), "2".to_string())],
+            kinds(items.for_user("joe").unwrap())
+        );
+        assert_eq!(
+            vec![(KIND_AWAITING_MERGE.to_string(), "1".to_string())],
+            kinds(items.for_user("the-author").unwrap())
+        );
+
+        let mut merged = pr(1, vec![]);
+        merged.state = "closed".into();
+        record_pull_request(&items, &repo(), &merged, None).unwrap();
+        assert_eq!(0, items.for_user("the-author").unwrap().len());
+        assert_eq!(0, items.for_user("bob").unwrap().len());
+    }
+
+    #[test]
+    fn test_record_status() {
+        let (items, _temp) = new_test();
+
+        let mut data = github::HookBody::new();
+        data.repository = repo();
+        data.state = Some("failure".into());
+        data.context = Some("ci/build".into());
+        data.target_url = Some("http://ci/build/1".into());
+        data.branches = Some(vec![github::StatusBranch { name: "feature".into() }]);
+        let mut commit = github::Commit::new();
+        commit.author = Some(github::User::new("the-author"));
+        data.commit = Some(commit);
+
+        record_status(&items, &data).unwrap();
+        let failed = items.for_user("the-author").unwrap();
+        assert_eq!(1, failed.len());
+        assert_eq!("some-user/some-repo feature: ci/build", failed[0].title);
+
+        data.state = Some("pending".into());
+        record_status(&items, &data).unwrap();
+        assert_eq!(1, items.for_user("the-author").unwrap().len());
+
+        data.state = Some("success".into());
+        record_status(&items, &data).unwrap();
+        assert_eq!(0, items.for_user("the-author").unwrap().len());
+    }
+
+    #[test]
+    fn test_digests() {
+        let (items, _temp) = new_test();
+        assert_eq!(None, user_digest(&items.for_user("joe").unwrap()));
+
+        record_pull_request(&items, &repo(), &pr(1, vec!["joe", "bob"]), None).unwrap();
+
+        let (_, attachments) = user_digest(&items.for_user("joe").unwrap()).unwrap();
+        assert_eq!(1, attachments.len());
+        assert_eq!(Some("Waiting for your review (1)".to_string()), attachments[0].title);
+        assert_eq!("<http://the-pr/1|some-user/some-repo#1: \"PR 1\">", attachments[0].text);
+
+        // the channel hears about the PR once, not once per reviewer
+        let (msg, attachments) = channel_digest("some-user", &items.for_repo("some-user").unwrap()).unwrap();
+        assert_eq!("Digest for some-user", msg);
+        assert_eq!(Some("Waiting for review (1)".to_string()), attachments[0].title);
+        assert_eq!(0, items.for_repo("other-user").unwrap().len());
+    }
+
+    #[test]
+    fn test_is_due() {
+        assert!(is_due(DAILY, "Mon", NOW));
+        assert!(!is_due(WEEKLY, "Mon", NOW));
+        assert!(is_due(WEEKLY, "Wed", NOW));
+        assert!(!is_due("", "Wed", NOW));
+    }
+}
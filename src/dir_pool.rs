@@ -2,13 +2,15 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Mutex;
 
+use crate::errors::*;
+
 pub struct DirPool {
     root_dir: PathBuf,
     available_dirs: Mutex<HashMap<String, AvailableDirs>>,
 }
 
 #[derive(Eq, PartialEq)]
-struct AvailableDirs {
+pub struct AvailableDirs {
     next_new: u32,
     unused: Vec<u32>,
 }
@@ -42,6 +44,35 @@ impl DirPool {
         HeldDir::new(id, repo_root, &self)
     }
 
+    // Runs |f| unless one of the repo's directories is held, and keeps them from being taken until it is done.
+    // Returns None if they were in use.
+    pub fn with_unused_repo<T, F>(&self, host: &str, owner: &str, repo: &str, f: F) -> Result<Option<T>>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let dirs = self.available_dirs.lock().unwrap();
+        if DirPool::repo_in_use(&dirs, &self.repo_key(host, owner, repo)) {
+            return Ok(None);
+        }
+
+        let result = f().map(Some);
+        drop(dirs);
+        result
+    }
+
+    pub fn in_use(&self, host: &str, owner: &str, repo: &str) -> bool {
+        let dirs = self.available_dirs.lock().unwrap();
+        DirPool::repo_in_use(&dirs, &self.repo_key(host, owner, repo))
+    }
+
+    fn repo_key(&self, host: &str, owner: &str, repo: &str) -> String {
+        self.root_dir.join(host).join(owner).join(repo).to_string_lossy().into_owned()
+    }
+
+    fn repo_in_use(dirs: &HashMap<String, AvailableDirs>, key: &str) -> bool {
+        dirs.get(key).map(|d| d.in_use() > 0).unwrap_or(false)
+    }
+
     fn return_dir(&self, id: u32, repo_root: &PathBuf) -> () {
         let key = repo_root.to_string_lossy().into_owned();
         {
@@ -73,6 +104,11 @@ impl AvailableDirs {
     pub fn return_id(&mut self, id: u32) {
         self.unused.push(id);
     }
+
+    // how many ids are taken and not yet returned
+    pub fn in_use(&self) -> u32 {
+        self.next_new - self.unused.len() as u32
+    }
 }
 
 
@@ -137,4 +173,18 @@ mod tests {
         assert_eq!("<root>/h1/o1/repo-A/1", dir_a1_again.dir().to_string_lossy());
     }
 
+    #[test]
+    fn test_with_unused_repo() {
+        let dir_pool = DirPool::new("<root>");
+
+        let held = dir_pool.take_directory("h1", "o1", "repo-A");
+        assert!(dir_pool.in_use("h1", "o1", "repo-A"));
+        assert_eq!(None, dir_pool.with_unused_repo("h1", "o1", "repo-A", || Ok(1)).unwrap());
+        assert_eq!(Some(2), dir_pool.with_unused_repo("h1", "o1", "repo-B", || Ok(2)).unwrap());
+
+        drop(held);
+        assert!(!dir_pool.in_use("h1", "o1", "repo-A"));
+        assert_eq!(Some(3), dir_pool.with_unused_repo("h1", "o1", "repo-A", || Ok(3)).unwrap());
+    }
+
 }
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use log::{error, info};
+use regex::{Captures, Regex};
+use reqwest;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::DiscordConfig;
+use crate::deliveries::{self, Deliveries};
+use crate::errors::*;
+use crate::http_client::HTTPClient;
+use crate::slack::{SlackAttachment, SlackRequest};
+use crate::util;
+use crate::worker;
+
+const DEFAULT_API_URL: &str = "https://discord.com/api/v10";
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct DiscordMessage {
+    pub content: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub embeds: Vec<DiscordEmbed>,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct DiscordEmbed {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<DiscordEmbedField>,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct DiscordEmbedField {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
+
+#[derive(Serialize)]
+struct CreateDM {
+    recipient_id: String,
+}
+
+#[derive(Deserialize)]
+struct DiscordChannel {
+    id: String,
+}
+
+// Where a request goes. Routing config is shared with slack: channels hold discord channel IDs and
+// user mappings hold discord user IDs.
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    Channel(String),
+    User(String),
+}
+
+pub fn target(channel: &str) -> Target {
+    if channel.starts_with('@') {
+        Target::User(channel[1..].to_string())
+    } else {
+        Target::Channel(channel.trim_start_matches('#').to_string())
+    }
+}
+
+// Converts slack's "<url|text>" links to markdown links, and unescapes what slack needed escaped
+pub fn from_slack_markup(text: &str) -> String {
+    let link = Regex::new(r"<([^<>|]+)\|([^<>]*)>").unwrap();
+    let converted = link.replace_all(text, |caps: &Captures| format!("[{}]({})", &caps[2], &caps[1]));
+    converted.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+// Slack's named attachment colors, or a "#rrggbb" hex color
+pub fn color(color: &str) -> Option<u32> {
+    match color {
+        "good" => Some(0x2eb886),
+        "warning" => Some(0xdaa038),
+        "danger" => Some(0xa30200),
+        _ => u32::from_str_radix(color.trim_start_matches('#'), 16).ok(),
+    }
+}
+
+pub fn to_embed(attachment: &SlackAttachment) -> DiscordEmbed {
+    DiscordEmbed {
+        title: attachment.title.as_ref().map(|t| from_slack_markup(t)),
+        url: attachment.title_link.clone(),
+        description: from_slack_markup(&attachment.text),
+        color: attachment.color.as_ref().and_then(|c| color(c)),
+        fields: attachment
+            .fields
+            .iter()
+            .map(|f| DiscordEmbedField {
+                name: f.title.clone(),
+                value: from_slack_markup(&f.value),
+                inline: f.short,
+            })
+            .collect(),
+    }
+}
+
+pub fn to_message(req: &SlackRequest) -> DiscordMessage {
+    DiscordMessage {
+        content: from_slack_markup(&req.msg),
+        embeds: req.attachments.iter().map(to_embed).collect(),
+    }
+}
+
+// the main object for sending messages to discord
+struct Discord {
+    client: HTTPClient,
+    // user ID => DM channel ID
+    dm_channels: Mutex<HashMap<String, String>>,
+    recent_messages: Mutex<Vec<(String, DiscordMessage)>>,
+}
+
+const TRIM_MESSAGES_AT: usize = 200;
+const TRIM_MESSAGES_TO: usize = 20;
+
+impl Discord {
+    fn new(config: &DiscordConfig) -> Result<Discord> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Bot {}", config.bot_token).parse().unwrap(),
+        );
+
+        let api_url = config.api_url.clone().unwrap_or(DEFAULT_API_URL.into());
+        Ok(Discord {
+            client: HTTPClient::new_with_headers(&api_url, headers)?,
+            dm_channels: Mutex::new(HashMap::new()),
+            recent_messages: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn send(&self, req: &SlackRequest) -> Result<()> {
+        let message = to_message(req);
+        if !self.is_unique(&req.channel, &message) {
+            info!("Skipping duplicate message to {}", req.channel);
+            return Ok(());
+        }
+
+        let channel_id = match target(&req.channel) {
+            Target::Channel(id) => id,
+            Target::User(id) => self.dm_channel(&id)?,
+        };
+
+        info!("Sending discord message to {}", req.channel);
+        self.client.post_void(&format!("/channels/{}/messages", channel_id), &message)
+    }
+
+    fn dm_channel(&self, user_id: &str) -> Result<String> {
+        if let Some(id) = self.dm_channels.lock().unwrap().get(user_id) {
+            return Ok(id.clone());
+        }
+
+        let channel: DiscordChannel = self.client.post(
+            "/users/@me/channels",
+            &CreateDM { recipient_id: user_id.into() },
+        )?;
+        self.dm_channels.lock().unwrap().insert(user_id.into(), channel.id.clone());
+        Ok(channel.id)
+    }
+
+    fn is_unique(&self, channel: &str, message: &DiscordMessage) -> bool {
+        let mut recent_messages = self.recent_messages.lock().unwrap();
+        util::check_unique_event(
+            (channel.to_string(), message.clone()),
+            &mut *recent_messages,
+            TRIM_MESSAGES_AT,
+            TRIM_MESSAGES_TO,
+        )
+    }
+}
+
+struct Runner {
+    discord: Arc<Discord>,
+    deliveries: Deliveries,
+}
+
+// Sends messenger requests to discord instead of slack
+pub fn new_runner(config: &DiscordConfig, deliveries: Deliveries) -> Result<Arc<dyn worker::Runner<SlackRequest>>> {
+    Ok(Arc::new(Runner {
+        discord: Arc::new(Discord::new(config)?),
+        deliveries: deliveries,
+    }))
+}
+
+impl worker::Runner<SlackRequest> for Runner {
+    fn handle(&self, req: SlackRequest) {
+        if let Err(e) = self.deliveries.track(deliveries::DISCORD, &req.channel, || self.discord.send(&req)) {
+            error!("Error sending discord message: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slack::{self, SlackAttachmentBuilder};
+
+    #[test]
+    fn test_target() {
+        assert_eq!(Target::Channel("1234".into()), target("1234"));
+        assert_eq!(Target::Channel("1234".into()), target("#1234"));
+        assert_eq!(Target::User("5678".into()), target("@5678"));
+    }
+
+    #[test]
+    fn test_from_slack_markup() {
+        assert_eq!(
+            "Pull Request merged ([some-user/some-repo](http://the-github-host/some-user/some-repo))",
+            from_slack_markup("Pull Request merged (<http://the-github-host/some-user/some-repo|some-user/some-repo>)")
+        );
+        assert_eq!("a <b> & c", from_slack_markup("a &lt;b&gt; &amp; c"));
+    }
+
+    #[test]
+    fn test_color() {
+        assert_eq!(Some(0x2eb886), color("good"));
+        assert_eq!(Some(0xa30200), color("danger"));
+        assert_eq!(Some(0x439fe0), color("#439FE0"));
+        assert_eq!(None, color("blurple"));
+    }
+
+    #[test]
+    fn test_to_message() {
+        let attach = SlackAttachmentBuilder::new("some <http://the-commit|commit>")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .color("good")
+            .field("Reviewers", "joe.reviewer")
+            .build();
+        let message = to_message(&slack::req("1234", "Pull Request opened", vec![attach]));
+
+        assert_eq!(
+            DiscordMessage {
+                content: "Pull Request opened".into(),
+                embeds: vec![DiscordEmbed {
+                    title: Some("Pull Request #32: \"The PR\"".into()),
+                    url: Some("http://the-pr".into()),
+                    description: "some [commit](http://the-commit)".into(),
+                    color: Some(0x2eb886),
+                    fields: vec![DiscordEmbedField {
+                        name: "Reviewers".into(),
+                        value: "joe.reviewer".into(),
+                        inline: true,
+                    }],
+                }],
+            },
+            message
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use log::debug;
+
+use crate::github;
+use crate::github::api::Session;
+use crate::repos::RepoInfo;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ecosystem {
+    Cargo,
+    Npm,
+    Go,
+}
+
+// The files octobot looks at for an ecosystem unless the repo config says otherwise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EcosystemDefaults {
+    // files that hold the project's version
+    pub version_files: Vec<&'static str>,
+    // PRs changing only these files are dependency updates
+    pub lockfiles: Vec<&'static str>,
+    pub changelog_file: &'static str,
+}
+
+const ALL: &[Ecosystem] = &[Ecosystem::Cargo, Ecosystem::Npm, Ecosystem::Go];
+
+impl Ecosystem {
+    pub fn parse(value: &str) -> Option<Ecosystem> {
+        match value.trim().to_lowercase().as_str() {
+            "cargo" | "rust" => Some(Ecosystem::Cargo),
+            "npm" | "node" => Some(Ecosystem::Npm),
+            "go" | "gomod" => Some(Ecosystem::Go),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Ecosystem::Cargo => "cargo",
+            Ecosystem::Npm => "npm",
+            Ecosystem::Go => "go",
+        }
+    }
+
+    // The file at the repo root that marks a repo as using this ecosystem
+    pub fn manifest(&self) -> &'static str {
+        match *self {
+            Ecosystem::Cargo => "Cargo.toml",
+            Ecosystem::Npm => "package.json",
+            Ecosystem::Go => "go.mod",
+        }
+    }
+
+    pub fn defaults(&self) -> EcosystemDefaults {
+        match *self {
+            Ecosystem::Cargo => EcosystemDefaults {
+                version_files: vec!["Cargo.toml"],
+                lockfiles: vec!["Cargo.lock"],
+                changelog_file: "CHANGELOG.md",
+            },
+            Ecosystem::Npm => EcosystemDefaults {
+                version_files: vec!["package.json"],
+                lockfiles: vec!["package-lock.json", "yarn.lock", "npm-shrinkwrap.json"],
+                changelog_file: "CHANGELOG.md",
+            },
+            // go modules are versioned by tags only
+            Ecosystem::Go => EcosystemDefaults {
+                version_files: vec![],
+                lockfiles: vec!["go.sum"],
+                changelog_file: "CHANGELOG.md",
+            },
+        }
+    }
+}
+
+// Looks for a known manifest at the root of the repo. The first match wins.
+pub fn detect(github: &dyn Session, owner: &str, repo: &str, git_ref: &str) -> Option<Ecosystem> {
+    for ecosystem in ALL {
+        if github.get_file_contents(owner, repo, ecosystem.manifest(), git_ref).is_ok() {
+            debug!("Detected {} ecosystem for {}/{}", ecosystem.as_str(), owner, repo);
+            return Some(*ecosystem);
+        }
+    }
+    None
+}
+
+// Fills in the ecosystem of a newly added repo unless one was given. Org-level entries are left alone.
+pub fn onboard(github: &dyn Session, info: RepoInfo) -> RepoInfo {
+    if !info.ecosystem.trim().is_empty() {
+        return info;
+    }
+
+    let detected = {
+        let mut parts = info.repo.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(owner), Some(name)) => detect(github, owner, name, "HEAD"),
+            _ => None,
+        }
+    };
+
+    match detected {
+        Some(ecosystem) => info.with_ecosystem(ecosystem),
+        None => info,
+    }
+}
+
+// True if every file changed is one of the lockfiles (in any directory)
+pub fn is_lockfile_only(files: &Vec<github::PullRequestFile>, lockfiles: &Vec<String>) -> bool {
+    if files.is_empty() || lockfiles.is_empty() {
+        return false;
+    }
+
+    files.iter().all(|f| {
+        let name = f.filename.rsplit('/').next().unwrap_or("");
+        lockfiles.iter().any(|l| l == name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str) -> github::PullRequestFile {
+        github::PullRequestFile::new(name)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some(Ecosystem::Cargo), Ecosystem::parse("cargo"));
+        assert_eq!(Some(Ecosystem::Npm), Ecosystem::parse(" NPM "));
+        assert_eq!(Some(Ecosystem::Go), Ecosystem::parse("go"));
+        assert_eq!(None, Ecosystem::parse(""));
+        assert_eq!(None, Ecosystem::parse("maven"));
+
+        for e in ALL {
+            assert_eq!(Some(*e), Ecosystem::parse(e.as_str()));
+        }
+    }
+
+    #[test]
+    fn test_is_lockfile_only() {
+        let lockfiles = vec!["Cargo.lock".to_string()];
+
+        assert!(is_lockfile_only(&vec![file("Cargo.lock"), file("sub/crate/Cargo.lock")], &lockfiles));
+        assert!(!is_lockfile_only(&vec![file("Cargo.lock"), file("Cargo.toml")], &lockfiles));
+        assert!(!is_lockfile_only(&vec![file("Cargo.lock.bak")], &lockfiles));
+        assert!(!is_lockfile_only(&vec![], &lockfiles));
+        assert!(!is_lockfile_only(&vec![file("Cargo.lock")], &vec![]));
+    }
+}
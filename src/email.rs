@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use lettre::smtp::authentication::Credentials;
+use lettre::{ClientSecurity, ClientTlsParameters, SmtpClient, SmtpTransport, Transport};
+use lettre_email::EmailBuilder;
+use log::{error, info};
+use native_tls::TlsConnector;
+use regex::{Captures, Regex};
+
+use crate::config::{Config, EmailConfig};
+use crate::deliveries;
+use crate::errors::*;
+use crate::slack::SlackAttachment;
+use crate::users::UserInfo;
+use crate::worker;
+
+const HTML_TEMPLATE: &str = include_str!("assets/email.html");
+const TEXT_FOOTER: &str = "--\nSent by octobot because you have no slack user mapped.";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EmailRequest {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+pub fn req(to: &str, msg: &str, attachments: &Vec<SlackAttachment>) -> EmailRequest {
+    EmailRequest {
+        to: to.into(),
+        subject: subject(msg),
+        text: render_text(msg, attachments),
+        html: render_html(msg, attachments),
+    }
+}
+
+// The user's own address if they set one, otherwise their github login at the default domain
+pub fn address(config: &EmailConfig, github_login: &str, user: Option<&UserInfo>) -> Option<String> {
+    if let Some(email) = user.map(|u| u.email.trim()).filter(|e| !e.is_empty()) {
+        return Some(email.to_string());
+    }
+    config
+        .default_domain
+        .as_ref()
+        .filter(|d| !d.is_empty())
+        .map(|d| format!("{}@{}", github_login, d.trim_start_matches('@')))
+}
+
+fn slack_link_regex() -> Regex {
+    Regex::new(r"<([^<>|]+)\|([^<>]*)>").unwrap()
+}
+
+fn unescape_slack(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+// Slack links become "text (url)"
+pub fn to_text(slack_text: &str) -> String {
+    let converted = slack_link_regex().replace_all(slack_text, |caps: &Captures| {
+        format!("{} ({})", &caps[2], &caps[1])
+    });
+    unescape_slack(&converted)
+}
+
+// Slack links become anchors; everything else is escaped
+pub fn to_html(slack_text: &str) -> String {
+    let link = slack_link_regex();
+    let mut html = String::new();
+    let mut last = 0;
+    for caps in link.captures_iter(slack_text) {
+        let whole = caps.get(0).unwrap();
+        html += &escape_html(&unescape_slack(&slack_text[last..whole.start()]));
+        html += &format!(
+            "<a href=\"{}\">{}</a>",
+            escape_html(&unescape_slack(&caps[1])),
+            escape_html(&unescape_slack(&caps[2]))
+        );
+        last = whole.end();
+    }
+    html += &escape_html(&unescape_slack(&slack_text[last..]));
+    html.replace("\n", "<br>\n")
+}
+
+pub fn subject(msg: &str) -> String {
+    let subject = to_text(msg.lines().next().unwrap_or(""));
+    format!("[octobot] {}", subject.trim())
+}
+
+pub fn render_text(msg: &str, attachments: &Vec<SlackAttachment>) -> String {
+    let mut text = to_text(msg) + "\n";
+    for attachment in attachments {
+        text += "\n";
+        if let Some(ref title) = attachment.title {
+            text += &to_text(title);
+            if let Some(ref link) = attachment.title_link {
+                text += &format!(" ({})", link);
+            }
+            text += "\n";
+        }
+        if !attachment.text.is_empty() {
+            text += &to_text(&attachment.text);
+            text += "\n";
+        }
+        for field in &attachment.fields {
+            text += &format!("{}: {}\n", field.title, to_text(&field.value));
+        }
+    }
+    text + "\n" + TEXT_FOOTER + "\n"
+}
+
+pub fn render_html(msg: &str, attachments: &Vec<SlackAttachment>) -> String {
+    let mut body = format!("<p>{}</p>\n", to_html(msg));
+    for attachment in attachments {
+        body += "<div style=\"border-left: 4px solid #dfe2e5; padding-left: 12px; margin: 12px 0\">\n";
+        if let Some(ref title) = attachment.title {
+            let title = match attachment.title_link {
+                Some(ref link) => format!("<a href=\"{}\">{}</a>", escape_html(link), to_html(title)),
+                None => to_html(title),
+            };
+            body += &format!("<p><strong>{}</strong></p>\n", title);
+        }
+        if !attachment.text.is_empty() {
+            body += &format!("<p>{}</p>\n", to_html(&attachment.text));
+        }
+        for field in &attachment.fields {
+            body += &format!("<p><strong>{}:</strong> {}</p>\n", escape_html(&field.title), to_html(&field.value));
+        }
+        body += "</div>\n";
+    }
+
+    HTML_TEMPLATE.replace("{{subject}}", &escape_html(&subject(msg))).replace("{{body}}", &body)
+}
+
+fn new_transport(config: &EmailConfig) -> Result<SmtpTransport> {
+    let tls = config.tls.clone().unwrap_or("starttls".into());
+    let security = match tls.as_str() {
+        "none" => ClientSecurity::None,
+        "starttls" | "tls" => {
+            let connector = TlsConnector::new().map_err(|e| format_err!("Error setting up TLS: {}", e))?;
+            let params = ClientTlsParameters::new(config.smtp_host.clone(), connector);
+            if tls == "tls" {
+                ClientSecurity::Wrapper(params)
+            } else {
+                ClientSecurity::Required(params)
+            }
+        }
+        _ => return Err(format_err!("Unknown email tls mode (expected starttls, tls or none): {}", tls)),
+    };
+
+    let port = config.smtp_port.unwrap_or(match tls.as_str() {
+        "none" => 25,
+        "tls" => 465,
+        _ => 587,
+    });
+
+    let mut client = SmtpClient::new((config.smtp_host.as_str(), port), security)
+        .map_err(|e| format_err!("Error connecting to {}: {}", config.smtp_host, e))?;
+    if let (Some(ref username), Some(ref password)) = (&config.username, &config.password) {
+        client = client.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    Ok(client.transport())
+}
+
+struct Runner {
+    config: Arc<Config>,
+}
+
+pub fn new_runner(config: Arc<Config>) -> Arc<dyn worker::Runner<EmailRequest>> {
+    Arc::new(Runner { config: config })
+}
+
+impl Runner {
+    fn send(&self, req: &EmailRequest) -> Result<()> {
+        let email_config = match self.config.email {
+            Some(ref c) => c,
+            None => return Err(format_err!("Email is not configured")),
+        };
+
+        let email = EmailBuilder::new()
+            .to(req.to.as_str())
+            .from(email_config.from.as_str())
+            .subject(req.subject.as_str())
+            .alternative(req.html.as_str(), req.text.as_str())
+            .build()
+            .map_err(|e| format_err!("Error building email: {}", e))?;
+
+        let mut transport = new_transport(email_config)?;
+        transport.send(email.into()).map_err(|e| format_err!("{}", e))?;
+        Ok(())
+    }
+}
+
+impl worker::Runner<EmailRequest> for Runner {
+    fn handle(&self, req: EmailRequest) {
+        match self.config.deliveries.track(deliveries::EMAIL, &req.to, || self.send(&req)) {
+            Ok(()) => info!("Sent email to {}", req.to),
+            Err(e) => error!("Error sending email to {}: {}", req.to, e),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slack::SlackAttachmentBuilder;
+
+    fn email_config(default_domain: Option<&str>) -> EmailConfig {
+        EmailConfig {
+            smtp_host: "smtp.company.com".into(),
+            smtp_port: None,
+            username: None,
+            password: None,
+            tls: None,
+            from: "octobot@company.com".into(),
+            default_domain: default_domain.map(|d| d.into()),
+            imap_host: None,
+            imap_port: None,
+            imap_username: None,
+            imap_password: None,
+            imap_mailbox: None,
+        }
+    }
+
+    #[test]
+    fn test_address() {
+        let mut user = UserInfo::new("joe-reviewer", "");
+        assert_eq!(None, address(&email_config(None), "joe-reviewer", Some(&user)));
+        assert_eq!(
+            Some("joe-reviewer@company.com".to_string()),
+            address(&email_config(Some("company.com")), "joe-reviewer", Some(&user))
+        );
+        assert_eq!(
+            Some("joe-reviewer@company.com".to_string()),
+            address(&email_config(Some("@company.com")), "joe-reviewer", None)
+        );
+
+        user.email = "joe@elsewhere.com".into();
+        assert_eq!(
+            Some("joe@elsewhere.com".to_string()),
+            address(&email_config(Some("company.com")), "joe-reviewer", Some(&user))
+        );
+    }
+
+    #[test]
+    fn test_markup() {
+        let slack = "Comment on <http://the-pr?a=1&amp;b=2|\"The &lt;PR&gt;\">";
+        assert_eq!("Comment on \"The <PR>\" (http://the-pr?a=1&b=2)", to_text(slack));
+        assert_eq!(
+            "Comment on <a href=\"http://the-pr?a=1&amp;b=2\">&quot;The &lt;PR&gt;&quot;</a>",
+            to_html(slack)
+        );
+        assert_eq!("[octobot] Comment on \"The <PR>\" (http://the-pr?a=1&b=2)", subject(slack));
+    }
+
+    #[test]
+    fn test_render() {
+        let attachments = vec![SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .field("Reviewers", "joe.reviewer")
+            .build()];
+        let msg = "Pull Request submitted for review to joe.reviewer";
+
+        assert_eq!(
+            "Pull Request submitted for review to joe.reviewer\n\n\
+             Pull Request #32: \"The PR\" (http://the-pr)\n\
+             Reviewers: joe.reviewer\n\n\
+             --\nSent by octobot because you have no slack user mapped.\n",
+            render_text(msg, &attachments)
+        );
+
+        let html = render_html(msg, &attachments);
+        assert!(html.contains("<title>[octobot] Pull Request submitted for review to joe.reviewer</title>"));
+        assert!(html.contains("<strong><a href=\"http://the-pr\">Pull Request #32: &quot;The PR&quot;</a></strong>"));
+        assert!(html.contains("<strong>Reviewers:</strong> joe.reviewer"));
+    }
+}
@@ -0,0 +1,430 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use regex::Regex;
+
+use crate::command_permissions;
+use crate::config::{Config, EmailConfig};
+use crate::email::{self, EmailRequest};
+use crate::errors::*;
+use crate::github::api::GithubSessionFactory;
+use crate::imap::ImapClient;
+use crate::scheduler;
+use crate::server::slack_actions::{self, APPROVE_ACTION, MERGE_ACTION};
+use crate::server::slack_command;
+use crate::teams::TeamMembers;
+use crate::users::UserInfo;
+use crate::util;
+use crate::worker;
+
+// how often to check the mailbox for replies
+pub const CHECK_INTERVAL_SECS: u64 = 60;
+
+pub const USAGE: &str = "Reply to a notification about a pull request with one of these commands on the first line:
+approve: approve the pull request
+merge: merge the pull request once all checks pass
+snooze <duration>: mute your notifications, e.g. `snooze 2d`, `snooze 4h`";
+
+// The parts of a reply that matter to octobot
+#[derive(Debug, PartialEq, Clone)]
+pub struct InboundEmail {
+    pub from: String,
+    pub subject: String,
+    pub text: String,
+    // out of office replies and the like, which mustn't be answered
+    pub automatic: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EmailCommand {
+    Approve,
+    Merge,
+    // for this many seconds
+    Snooze(i64),
+}
+
+// Splits a raw message into its (unfolded) headers and body
+fn split_message(raw: &str) -> (Vec<(String, String)>, String) {
+    let raw = raw.replace("\r\n", "\n");
+    let (head, body) = match raw.find("\n\n") {
+        Some(i) => (&raw[..i], &raw[i + 2..]),
+        None => (raw.as_str(), ""),
+    };
+
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in head.lines() {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = headers.last_mut() {
+                last.1 = format!("{} {}", last.1, line.trim());
+            }
+        } else if let Some(i) = line.find(':') {
+            headers.push((line[..i].trim().to_lowercase(), line[i + 1..].trim().to_string()));
+        }
+    }
+
+    (headers, body.to_string())
+}
+
+fn header(headers: &Vec<(String, String)>, name: &str) -> String {
+    headers.iter().find(|h| h.0 == name).map(|h| h.1.clone()).unwrap_or_default()
+}
+
+// e.g. the boundary of `multipart/alternative; boundary="abc"`
+fn header_param(value: &str, param: &str) -> Option<String> {
+    value
+        .split(';')
+        .skip(1)
+        .filter_map(|p| {
+            let mut parts = p.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) if k.trim().eq_ignore_ascii_case(param) => {
+                    Some(v.trim().trim_matches('"').to_string())
+                }
+                _ => None,
+            }
+        })
+        .next()
+}
+
+fn decode_quoted_printable(text: &str) -> String {
+    // "=" at the end of a line is a soft line break
+    let text = text.replace("=\n", "");
+    let bytes = text.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'=' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok());
+            if let Some(b) = hex {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn decode_body(encoding: &str, body: &str) -> String {
+    match encoding.to_lowercase().as_str() {
+        "quoted-printable" => decode_quoted_printable(body),
+        "base64" => {
+            let body = body.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+            match base64::decode(&body) {
+                Ok(decoded) => String::from_utf8_lossy(&decoded).into_owned(),
+                Err(e) => {
+                    error!("Error decoding base64 email body: {}", e);
+                    String::new()
+                }
+            }
+        }
+        _ => body.to_string(),
+    }
+}
+
+// The plain text of a message (or of its first text/plain part)
+fn plain_text(headers: &Vec<(String, String)>, body: &str) -> Option<String> {
+    let content_type = header(headers, "content-type").to_lowercase();
+    if content_type.starts_with("multipart/") {
+        let boundary = header_param(&header(headers, "content-type"), "boundary")?;
+        let delimiter = format!("--{}", boundary);
+        return body
+            .split(delimiter.as_str())
+            .skip(1)
+            // the closing delimiter is followed by "--"
+            .filter(|part| !part.starts_with("--"))
+            .find_map(|part| {
+                let (part_headers, part_body) = split_message(part.trim_start_matches('\n'));
+                plain_text(&part_headers, &part_body)
+            });
+    }
+    if content_type.is_empty() || content_type.starts_with("text/plain") {
+        return Some(decode_body(&header(headers, "content-transfer-encoding"), body));
+    }
+    None
+}
+
+pub fn parse_email(raw: &str) -> InboundEmail {
+    let (headers, body) = split_message(raw);
+    let auto_submitted = header(&headers, "auto-submitted").to_lowercase();
+    let precedence = header(&headers, "precedence").to_lowercase();
+
+    InboundEmail {
+        from: header(&headers, "from"),
+        subject: header(&headers, "subject"),
+        text: plain_text(&headers, &body).unwrap_or_default(),
+        automatic: (!auto_submitted.is_empty() && auto_submitted != "no")
+            || ["bulk", "junk", "list", "auto_reply"].contains(&precedence.as_str()),
+    }
+}
+
+// The command on the first line of the reply, above the quoted notification
+pub fn parse_command(text: &str) -> Option<EmailCommand> {
+    let line = text.lines().map(|l| l.trim()).find(|l| !l.is_empty())?;
+    if line.starts_with('>') {
+        return None;
+    }
+    let line = line.trim_end_matches(|c| c == '.' || c == '!').to_lowercase();
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        ["approve"] => Some(EmailCommand::Approve),
+        ["merge"] => Some(EmailCommand::Merge),
+        ["snooze", duration] => slack_command::parse_duration(duration).map(EmailCommand::Snooze),
+        _ => None,
+    }
+}
+
+// The first pull request linked in the subject or (quoted) notification, as (owner, repo, number)
+pub fn find_pull_request(github_host: &str, email: &InboundEmail) -> Option<(String, String, u32)> {
+    let regex = Regex::new(&format!(r"https?://{}/([\w.-]+)/([\w.-]+)/pull/(\d+)", regex::escape(github_host))).ok()?;
+    let text = format!("{}\n{}", email.subject, email.text);
+    let caps = regex.captures(&text)?;
+    Some((caps[1].to_string(), caps[2].to_string(), caps[3].parse::<u32>().ok()?))
+}
+
+// e.g. "joe@company.com" for "Joe <Joe@Company.com>"
+fn sender_address(from: &str) -> String {
+    let address = match (from.rfind('<'), from.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &from[start + 1..end],
+        _ => from,
+    };
+    address.trim().to_lowercase()
+}
+
+// The user with the sender's email, or whose github login it is at the default domain
+pub fn lookup_sender(config: &Config, email_config: &EmailConfig, from: &str) -> Option<UserInfo> {
+    let address = sender_address(from);
+    if let Some(user) = config.users().lookup_by_email(&address) {
+        return Some(user);
+    }
+
+    let mut parts = address.splitn(2, '@');
+    let (login, domain) = (parts.next()?, parts.next()?);
+    let default_domain = email_config.default_domain.as_ref()?.trim_start_matches('@').to_lowercase();
+    if domain != default_domain {
+        return None;
+    }
+    // users with an email of their own don't get (or reply from) the default address
+    config.users().lookup_info(login).filter(|u| u.email.trim().is_empty())
+}
+
+pub fn snooze(config: &Config, user: &UserInfo, secs: i64, now: i64) -> Result<String> {
+    let until = now + secs;
+    config.users_write().mute_until(&user.github, until)?;
+    Ok(format!("Notifications are snoozed until {}", util::format_timestamp(until)))
+}
+
+pub fn reply(to: &str, email: &InboundEmail, text: &str) -> EmailRequest {
+    let subject = if email.subject.to_lowercase().starts_with("re:") {
+        email.subject.clone()
+    } else {
+        format!("Re: {}", email.subject)
+    };
+
+    EmailRequest {
+        to: to.into(),
+        subject: subject,
+        text: email::render_text(text, &vec![]),
+        html: email::render_html(text, &vec![]),
+    }
+}
+
+// Carries out commands replied to octobot's emails
+pub struct EmailGateway {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    team_members: Arc<TeamMembers>,
+    email: Arc<dyn worker::Worker<EmailRequest>>,
+}
+
+impl EmailGateway {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        team_members: Arc<TeamMembers>,
+        email: Arc<dyn worker::Worker<EmailRequest>>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(EmailGateway {
+            config: config,
+            github_app: github_app,
+            team_members: team_members,
+            email: email,
+        })
+    }
+
+    // Returns what to reply, or None to ignore the email
+    fn handle(&self, email_config: &EmailConfig, email: &InboundEmail, now: i64) -> Option<String> {
+        if email.automatic {
+            info!("Ignoring automatic email from {}", email.from);
+            return None;
+        }
+        let user = match lookup_sender(&self.config, email_config, &email.from) {
+            Some(u) => u,
+            None => {
+                info!("Ignoring email from unknown sender {}", email.from);
+                return None;
+            }
+        };
+
+        let result = match parse_command(&email.text) {
+            None => return Some(USAGE.into()),
+            Some(EmailCommand::Snooze(secs)) => snooze(&self.config, &user, secs, now),
+            Some(EmailCommand::Approve) => self.pull_request_action(&user, APPROVE_ACTION, email),
+            Some(EmailCommand::Merge) => self.pull_request_action(&user, MERGE_ACTION, email),
+        };
+
+        Some(result.unwrap_or_else(|e| {
+            error!("Error handling email command from {}: {}", user.github, e);
+            format!("Error: {}", e)
+        }))
+    }
+
+    fn pull_request_action(&self, user: &UserInfo, action: &str, email: &InboundEmail) -> Result<String> {
+        let hosts = self.config.github_hosts();
+        let (owner, repo, number) = match hosts.iter().find_map(|host| find_pull_request(host, email)) {
+            Some(pr) => pr,
+            None => return Ok("Could not tell which pull request this is about: reply to its notification".into()),
+        };
+        info!("{} replied '{}' to {}/{} #{}", user.github, action, owner, repo, number);
+
+        let allowed = command_permissions::authorize(
+            &self.config,
+            &*self.github_app,
+            &self.team_members,
+            action,
+            "",
+            "",
+            Some(&user.github),
+        )?;
+        if !allowed {
+            return Ok(format!("You are not allowed to use `{}`", action));
+        }
+
+        let github = self.github_app.new_session(&owner, &repo)?;
+        slack_actions::perform_action_from(&self.config, &github, user, action, &owner, &repo, number, "email")
+    }
+
+    fn check_mailbox(&self, email_config: &EmailConfig, now: i64) -> Result<()> {
+        let host = email_config.imap_host.clone().unwrap_or_default();
+        let username = email_config.imap_username.as_ref().or(email_config.username.as_ref());
+        let password = email_config.imap_password.as_ref().or(email_config.password.as_ref());
+        let (username, password) = match (username, password) {
+            (Some(u), Some(p)) => (u, p),
+            _ => return Err(format_err!("No IMAP credentials configured")),
+        };
+
+        let mut client = ImapClient::connect(&host, email_config.imap_port.unwrap_or(993))?;
+        client.login(username, password)?;
+        client.select(email_config.imap_mailbox.as_ref().map(|m| m.as_str()).unwrap_or("INBOX"))?;
+
+        for id in client.search_unseen()? {
+            let email = parse_email(&client.fetch(id)?);
+            // mark it read first, so that a failing command isn't carried out over and over
+            client.mark_seen(id)?;
+
+            if let Some(text) = self.handle(email_config, &email, now) {
+                self.email.send(reply(&sender_address(&email.from), &email, &text));
+            }
+        }
+
+        client.logout()
+    }
+}
+
+impl scheduler::Task for EmailGateway {
+    fn run(&self, now: i64) -> Result<()> {
+        match self.config.email {
+            Some(ref email_config) => self.check_mailbox(email_config, now),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_email() {
+        let raw = "From: Joe Reviewer <Joe@Company.com>\r\n\
+                   Subject: Re: [octobot] Pull Request submitted\r\n \
+                   for review\r\n\
+                   Content-Type: text/plain; charset=utf-8\r\n\
+                   \r\n\
+                   approve\r\n\
+                   \r\n\
+                   > Pull Request submitted\r\n";
+
+        let email = parse_email(raw);
+        assert_eq!("Joe Reviewer <Joe@Company.com>", email.from);
+        assert_eq!("Re: [octobot] Pull Request submitted for review", email.subject);
+        assert_eq!("approve\n\n> Pull Request submitted\n", email.text);
+        assert_eq!("joe@company.com", sender_address(&email.from));
+        assert!(!email.automatic);
+
+        let email = parse_email("From: joe@company.com\nAuto-Submitted: auto-replied\n\nI am out of office");
+        assert!(email.automatic);
+        assert_eq!("I am out of office", email.text);
+    }
+
+    #[test]
+    fn test_parse_multipart_email() {
+        let raw = "From: joe@company.com\n\
+                   Subject: Re: [octobot] hello\n\
+                   Content-Type: multipart/alternative; boundary=\"xyz\"\n\
+                   \n\
+                   --xyz\n\
+                   Content-Type: text/plain; charset=utf-8\n\
+                   Content-Transfer-Encoding: quoted-printable\n\
+                   \n\
+                   snooze 2d\n\
+                   > see https://github.com/some-org/some-repo/pull/=\n\
+                   12 or a=3Db\n\
+                   --xyz\n\
+                   Content-Type: text/html\n\
+                   \n\
+                   <p>snooze 2d</p>\n\
+                   --xyz--\n";
+
+        let email = parse_email(raw);
+        assert_eq!("snooze 2d\n> see https://github.com/some-org/some-repo/pull/12 or a=b\n", email.text);
+        assert_eq!(Some(EmailCommand::Snooze(2 * 24 * 60 * 60)), parse_command(&email.text));
+        assert_eq!(
+            Some(("some-org".to_string(), "some-repo".to_string(), 12)),
+            find_pull_request("github.com", &email)
+        );
+        assert_eq!(None, find_pull_request("git.company.com", &email));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Some(EmailCommand::Approve), parse_command("\n  Approve.\n\n> quoted"));
+        assert_eq!(Some(EmailCommand::Merge), parse_command("merge"));
+        assert_eq!(Some(EmailCommand::Snooze(4 * 60 * 60)), parse_command("snooze 4h"));
+        assert_eq!(None, parse_command("snooze"));
+        assert_eq!(None, parse_command("snooze forever"));
+        assert_eq!(None, parse_command("> approve"));
+        assert_eq!(None, parse_command("looks good, approve it"));
+        assert_eq!(None, parse_command(""));
+    }
+
+    #[test]
+    fn test_reply() {
+        let email = InboundEmail {
+            from: "joe@company.com".into(),
+            subject: "[octobot] hello".into(),
+            text: "approve".into(),
+            automatic: false,
+        };
+        assert_eq!("Re: [octobot] hello", reply("joe@company.com", &email, "done").subject);
+
+        let email = InboundEmail {
+            subject: "RE: [octobot] hello".into(),
+            ..email
+        };
+        assert_eq!("RE: [octobot] hello", reply("joe@company.com", &email, "done").subject);
+    }
+}
@@ -0,0 +1,309 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use failure::format_err;
+use log::debug;
+use native_tls::TlsConnector;
+use reqwest;
+use serde_json;
+use url::Url;
+
+use crate::config::{Config, EventBusConfig};
+use crate::errors::*;
+use crate::network;
+use crate::outbound_webhooks::Delivery;
+
+pub const KAFKA: &str = "kafka";
+pub const NATS: &str = "nats";
+
+const KAFKA_CONTENT_TYPE: &str = "application/vnd.kafka.json.v2+json";
+const NATS_PORT: u16 = 4222;
+const TIMEOUT_SECS: u64 = 30;
+
+pub fn is_nats_url(url: &Url) -> bool {
+    (url.scheme() == "nats" || url.scheme() == "tls") && url.host_str().is_some()
+}
+
+// e.g. "octobot.pull_request.merged", so that consumers can subscribe to "octobot.pull_request.>"
+pub fn subject(prefix: &str, event: &str) -> String {
+    format!("{}.{}", prefix, event)
+}
+
+// Records are keyed by repo, so that each repo's events stay in order
+pub fn kafka_body(delivery: &Delivery) -> serde_json::Value {
+    serde_json::json!({
+        "records": [{ "key": delivery.event.repo, "value": delivery }],
+    })
+}
+
+pub trait Publisher: Send + Sync {
+    fn publish(&self, delivery: &Delivery) -> Result<()>;
+}
+
+// Produces to the topic through a Kafka REST Proxy
+pub struct KafkaPublisher {
+    client: reqwest::Client,
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl KafkaPublisher {
+    pub fn new(config: &EventBusConfig, topic: &str) -> KafkaPublisher {
+        KafkaPublisher {
+            client: network::client(),
+            url: format!("{}/topics/{}", config.url.trim_end_matches('/'), topic),
+            username: config.username.clone().filter(|u| !u.is_empty()),
+            password: config.password.clone(),
+        }
+    }
+}
+
+impl Publisher for KafkaPublisher {
+    fn publish(&self, delivery: &Delivery) -> Result<()> {
+        let mut req = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, KAFKA_CONTENT_TYPE)
+            .body(serde_json::to_vec(&kafka_body(delivery))?);
+        if let Some(ref username) = self.username {
+            req = req.basic_auth(username, self.password.clone());
+        }
+        req.send()
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format_err!("Error producing to {}: {}", self.url, e))
+    }
+}
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+// Publishes to "<topic>.<event>" on a NATS server, over a connection per event
+pub struct NatsPublisher {
+    config: EventBusConfig,
+    prefix: String,
+    // one connection at a time
+    publishing: Mutex<()>,
+}
+
+impl NatsPublisher {
+    pub fn new(config: &EventBusConfig, prefix: &str) -> NatsPublisher {
+        NatsPublisher {
+            config: config.clone(),
+            prefix: prefix.into(),
+            publishing: Mutex::new(()),
+        }
+    }
+
+    // Connects and reads the server's INFO, upgrading to TLS after it for "tls://" urls
+    fn connect(&self) -> Result<BufReader<Box<dyn Stream>>> {
+        let url = Url::parse(&self.config.url)?;
+        let host = url.host_str().ok_or_else(|| format_err!("No host in {}", self.config.url))?;
+        let port = url.port().unwrap_or(NATS_PORT);
+
+        let tcp = TcpStream::connect((host, port)).map_err(|e| format_err!("Error connecting to {}: {}", host, e))?;
+        tcp.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+        tcp.set_write_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+
+        let mut tcp = BufReader::new(tcp);
+        let info = read_line(&mut tcp)?;
+        if !info.starts_with("INFO ") {
+            return Err(format_err!("Expected INFO from NATS server, got: {}", info));
+        }
+        let tcp = tcp.into_inner();
+
+        let stream: Box<dyn Stream> = if url.scheme() == "tls" {
+            let connector = TlsConnector::new().map_err(|e| format_err!("Error setting up TLS: {}", e))?;
+            let tls_stream =
+                connector.connect(host, tcp).map_err(|e| format_err!("Error connecting to {}: {}", host, e))?;
+            Box::new(tls_stream)
+        } else {
+            Box::new(tcp)
+        };
+
+        Ok(BufReader::new(stream))
+    }
+
+    fn connect_options(&self) -> serde_json::Value {
+        let mut options = serde_json::json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "octobot",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        if let Some(ref token) = self.config.token {
+            options["auth_token"] = token.as_str().into();
+        }
+        if let Some(ref username) = self.config.username {
+            options["user"] = username.as_str().into();
+            options["pass"] = self.config.password.clone().unwrap_or_default().into();
+        }
+        options
+    }
+}
+
+impl Publisher for NatsPublisher {
+    fn publish(&self, delivery: &Delivery) -> Result<()> {
+        let _publishing = self.publishing.lock().unwrap();
+
+        let mut stream = self.connect()?;
+        publish(
+            &mut stream,
+            &self.connect_options(),
+            &subject(&self.prefix, &delivery.event.event),
+            &serde_json::to_vec(delivery)?,
+        )
+    }
+}
+
+// Says CONNECT, publishes |payload| to |subject| and waits for the server's PONG, so errors aren't missed
+pub fn publish<S: Read + Write>(
+    stream: &mut BufReader<S>,
+    options: &serde_json::Value,
+    subject: &str,
+    payload: &[u8],
+) -> Result<()> {
+    let mut out = format!("CONNECT {}\r\n", options).into_bytes();
+    out.extend(format!("PUB {} {}\r\n", subject, payload.len()).as_bytes());
+    out.extend(payload);
+    out.extend(b"\r\nPING\r\n");
+    debug!("NATS PUB {} ({} bytes)", subject, payload.len());
+    stream.get_mut().write_all(&out)?;
+    stream.get_mut().flush()?;
+
+    loop {
+        let line = read_line(stream)?;
+        if line == "PONG" {
+            return Ok(());
+        }
+        if line.starts_with("-ERR") {
+            return Err(format_err!("NATS server error: {}", line));
+        }
+        if line == "PING" {
+            stream.get_mut().write_all(b"PONG\r\n")?;
+        }
+    }
+}
+
+fn read_line<S: Read>(stream: &mut BufReader<S>) -> Result<String> {
+    let mut line = vec![];
+    if stream.read_until(b'\n', &mut line)? == 0 {
+        return Err(format_err!("NATS server closed the connection"));
+    }
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+pub fn new_publisher(config: &Config) -> Result<Option<Arc<dyn Publisher>>> {
+    let event_bus = match config.event_bus {
+        Some(ref b) => b,
+        None => return Ok(None),
+    };
+    let publisher: Arc<dyn Publisher> = match event_bus.kind.as_str() {
+        KAFKA => Arc::new(KafkaPublisher::new(event_bus, &config.event_bus_topic())),
+        NATS => Arc::new(NatsPublisher::new(event_bus, &config.event_bus_topic())),
+        kind => return Err(format_err!("Invalid event bus kind: '{}'", kind)),
+    };
+    Ok(Some(publisher))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+
+    use crate::github;
+    use crate::outbound_webhooks::{OutboundEvent, PULL_REQUEST_MERGED};
+
+    struct FakeServer {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for FakeServer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeServer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fake_server(replies: &str) -> BufReader<FakeServer> {
+        BufReader::new(FakeServer {
+            input: Cursor::new(replies.as_bytes().to_vec()),
+            output: vec![],
+        })
+    }
+
+    fn delivery() -> Delivery {
+        let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
+        Delivery {
+            event: OutboundEvent::new(PULL_REQUEST_MERGED, &repo, "the-sender"),
+            timestamp: 1234,
+        }
+    }
+
+    #[test]
+    fn test_is_nats_url() {
+        assert!(is_nats_url(&Url::parse("nats://nats.company.com:4222").unwrap()));
+        assert!(is_nats_url(&Url::parse("tls://nats.company.com").unwrap()));
+        assert!(!is_nats_url(&Url::parse("https://nats.company.com").unwrap()));
+    }
+
+    #[test]
+    fn test_kafka_body() {
+        assert_eq!(
+            serde_json::json!({
+                "records": [{
+                    "key": "the-owner/the-repo",
+                    "value": {
+                        "event": "pull_request.merged",
+                        "repo": "the-owner/the-repo",
+                        "sender": "the-sender",
+                        "timestamp": 1234,
+                    },
+                }],
+            }),
+            kafka_body(&delivery())
+        );
+    }
+
+    #[test]
+    fn test_publish() {
+        let mut stream = fake_server("PING\r\nPONG\r\n");
+        let options = serde_json::json!({"verbose": false});
+        publish(&mut stream, &options, &subject("octobot", PULL_REQUEST_MERGED), b"{\"a\":1}").unwrap();
+
+        assert_eq!(
+            "CONNECT {\"verbose\":false}\r\n\
+             PUB octobot.pull_request.merged 7\r\n\
+             {\"a\":1}\r\n\
+             PING\r\n\
+             PONG\r\n",
+            String::from_utf8(stream.get_ref().output.clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_publish_refused() {
+        let mut stream = fake_server("-ERR 'Authorization Violation'\r\n");
+        let err = publish(&mut stream, &serde_json::json!({}), "octobot.ci.failed", b"{}").unwrap_err();
+        assert_eq!("NATS server error: -ERR 'Authorization Violation'", format!("{}", err));
+
+        let mut stream = fake_server("");
+        let err = publish(&mut stream, &serde_json::json!({}), "octobot.ci.failed", b"{}").unwrap_err();
+        assert_eq!("NATS server closed the connection", format!("{}", err));
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+use crate::errors::*;
+use crate::github;
+
+// Bump this whenever the shape of `Event` changes, and teach `Event::upgrade` how to read the old one.
+pub const EVENT_VERSION: u32 = 2;
+
+pub const SCRUBBED: &str = "<scrubbed>";
+
+// Keys whose values are never recorded
+const SECRET_KEYS: &[&str] = &["token", "secret", "password", "email"];
+// Free text that may say anything: only its shape is kept
+const TEXT_KEYS: &[&str] = &["body", "message"];
+
+// A webhook delivery as octobot sees it, independent of how github happened to deliver it.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    pub version: u32,
+    pub delivery_id: String,
+    // the X-GitHub-Event header, e.g. "pull_request"
+    pub event: String,
+    #[serde(default)]
+    pub action: String,
+    pub repo: String,
+    pub payload: Value,
+    // when octobot received it, in milliseconds since the epoch: replaying a trace keeps the gaps between events.
+    // 0 for events recorded before version 2.
+    #[serde(default)]
+    pub received_at: i64,
+}
+
+impl Event {
+    pub fn from_webhook(delivery_id: &str, event: &str, body: &[u8]) -> Result<Event> {
+        let payload: Value = serde_json::from_slice(body).map_err(|e| format_err!("Error parsing webhook: {}", e))?;
+
+        let action = payload["action"].as_str().unwrap_or("").to_string();
+        let repo = payload["repository"]["full_name"].as_str().unwrap_or("").to_string();
+
+        Ok(Event {
+            version: EVENT_VERSION,
+            delivery_id: delivery_id.into(),
+            event: event.into(),
+            action: action,
+            repo: repo,
+            payload: payload,
+            received_at: now_ms(),
+        })
+    }
+
+    // Reads a recorded event, upgrading it from older versions as needed
+    pub fn parse(data: &str) -> Result<Event> {
+        let value: Value = serde_json::from_str(data).map_err(|e| format_err!("Error parsing event: {}", e))?;
+        Event::upgrade(value)
+    }
+
+    fn upgrade(mut value: Value) -> Result<Event> {
+        let version = value["version"].as_u64().unwrap_or(0) as u32;
+        if version == 0 || version > EVENT_VERSION {
+            return Err(format_err!("Unsupported event version: {}", version));
+        }
+
+        // version 1 didn't say when the event was received
+        if version == 1 {
+            value["received_at"] = Value::from(0);
+        }
+
+        value["version"] = Value::from(EVENT_VERSION);
+        serde_json::from_value(value).map_err(|e| format_err!("Error reading event: {}", e))
+    }
+
+    pub fn hook_body(&self) -> Result<github::HookBody> {
+        serde_json::from_value(self.payload.clone())
+            .map_err(|e| format_err!("Error reading {} event {}: {}", self.event, self.delivery_id, e))
+    }
+
+    pub fn scrubbed(&self) -> Event {
+        let mut event = self.clone();
+        scrub(&mut event.payload);
+        event
+    }
+}
+
+fn now_ms() -> i64 {
+    let now = time::get_time();
+    now.sec * 1000 + (now.nsec / 1_000_000) as i64
+}
+
+// Blanks out secrets and free text, keeping the structure of the payload intact.
+pub fn scrub(value: &mut Value) {
+    match *value {
+        Value::Object(ref mut map) => {
+            for (key, v) in map.iter_mut() {
+                let key = key.to_lowercase();
+                let is_secret = SECRET_KEYS.iter().any(|k| key.contains(k));
+                let is_text = TEXT_KEYS.iter().any(|k| key == *k);
+                if (is_secret && !v.is_null()) || (is_text && v.is_string()) {
+                    *v = Value::String(SCRUBBED.into());
+                } else {
+                    scrub(v);
+                }
+            }
+        }
+        Value::Array(ref mut values) => {
+            for v in values.iter_mut() {
+                scrub(v);
+            }
+        }
+        _ => (),
+    }
+}
+
+// Saves a sample of incoming events as fixtures for replay tests.
+pub struct FixtureRecorder {
+    dir: PathBuf,
+    // record one in this many events
+    sample_rate: u32,
+}
+
+impl FixtureRecorder {
+    pub fn new(dir: &Path, sample_rate: u32) -> FixtureRecorder {
+        FixtureRecorder {
+            dir: dir.to_path_buf(),
+            sample_rate: if sample_rate == 0 { 1 } else { sample_rate },
+        }
+    }
+
+    // Sampling is by delivery id so that redeliveries make the same decision
+    pub fn is_sampled(&self, event: &Event) -> bool {
+        let mut hasher = DefaultHasher::new();
+        event.delivery_id.hash(&mut hasher);
+        hasher.finish() % self.sample_rate as u64 == 0
+    }
+
+    pub fn fixture_path(&self, event: &Event) -> PathBuf {
+        let mut name = event.event.clone();
+        if !event.action.is_empty() {
+            name += "-";
+            name += &event.action;
+        }
+        let id = event.delivery_id.chars().filter(|c| c.is_alphanumeric() || *c == '-').collect::<String>();
+        self.dir.join(format!("{}-{}.json", name, id))
+    }
+
+    pub fn record(&self, event: &Event) -> Result<Option<PathBuf>> {
+        if !self.is_sampled(event) {
+            return Ok(None);
+        }
+
+        let data = serde_json::to_string_pretty(&event.scrubbed())?;
+        fs::create_dir_all(&self.dir)?;
+        let path = self.fixture_path(event);
+        fs::write(&path, data)?;
+
+        Ok(Some(path))
+    }
+
+    pub fn maybe_record(&self, event: &Event) {
+        match self.record(event) {
+            Ok(Some(path)) => info!("Recorded event fixture: {}", path.display()),
+            Ok(None) => (),
+            Err(e) => error!("Error recording event fixture: {}", e),
+        }
+    }
+}
+
+// Loads every recorded event in the directory
+pub fn load_fixtures(dir: &Path) -> Result<Vec<(PathBuf, Event)>> {
+    let mut paths = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().map(|e| e == "json").unwrap_or(false))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut events = vec![];
+    for path in paths {
+        let data = fs::read_to_string(&path)?;
+        let event = Event::parse(&data).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        events.push((path, event));
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempdir::TempDir;
+
+    fn the_event() -> Event {
+        let body = json!({
+            "action": "opened",
+            "repository": {"full_name": "some-user/some-repo"},
+            "pull_request": {"body": "secret plans", "user": {"login": "joe", "email": "joe@company.com"}},
+            "installation": {"access_token": "abcdef"},
+            "commits": [{"message": "fix it", "author": {"email": "joe@company.com"}}],
+        });
+        Event::from_webhook("1234-abcd", "pull_request", body.to_string().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_from_webhook() {
+        let event = the_event();
+        assert_eq!(EVENT_VERSION, event.version);
+        assert!(event.received_at > 0);
+        assert_eq!("opened", event.action);
+        assert_eq!("some-user/some-repo", event.repo);
+
+        assert!(Event::from_webhook("1", "push", b"not json").is_err());
+    }
+
+    #[test]
+    fn test_scrub() {
+        let event = the_event().scrubbed();
+        assert_eq!(
+            json!({
+                "action": "opened",
+                "repository": {"full_name": "some-user/some-repo"},
+                "pull_request": {"body": SCRUBBED, "user": {"login": "joe", "email": SCRUBBED}},
+                "installation": {"access_token": SCRUBBED},
+                "commits": [{"message": SCRUBBED, "author": {"email": SCRUBBED}}],
+            }),
+            event.payload
+        );
+    }
+
+    #[test]
+    fn test_parse_versions() {
+        let event = the_event();
+        let data = serde_json::to_string(&event).unwrap();
+        assert_eq!(event, Event::parse(&data).unwrap());
+
+        let mut future = serde_json::to_value(&event).unwrap();
+        future["version"] = json!(EVENT_VERSION + 1);
+        assert!(Event::parse(&future.to_string()).is_err());
+        assert!(Event::parse("{}").is_err());
+    }
+
+    #[test]
+    fn test_upgrade_version_1() {
+        let mut old = serde_json::to_value(&the_event()).unwrap();
+        old["version"] = json!(1);
+        old.as_object_mut().unwrap().remove("received_at");
+
+        let event = Event::parse(&old.to_string()).unwrap();
+        assert_eq!(EVENT_VERSION, event.version);
+        assert_eq!(0, event.received_at);
+        assert_eq!("some-user/some-repo", event.repo);
+    }
+
+    #[test]
+    fn test_record() {
+        let temp_dir = TempDir::new("events.rs").unwrap();
+        let recorder = FixtureRecorder::new(&temp_dir.path().join("fixtures"), 1);
+
+        let event = the_event();
+        let path = recorder.record(&event).unwrap().unwrap();
+        assert_eq!(temp_dir.path().join("fixtures/pull_request-opened-1234-abcd.json"), path);
+
+        let fixtures = load_fixtures(&temp_dir.path().join("fixtures")).unwrap();
+        assert_eq!(1, fixtures.len());
+        assert_eq!(event.scrubbed(), fixtures[0].1);
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use failure::format_err;
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::errors::*;
+
+// Simulated failures for the external services octobot talks to, so that retry behavior
+// can be exercised in staging. Only active when `[testing] fault_injection` is set.
+
+// Keep failing until the fault is cleared
+pub const UNTIL_CLEARED: u32 = u32::MAX;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SLACK_FAULTS: AtomicU32 = AtomicU32::new(0);
+static GITHUB_FAULTS: AtomicU32 = AtomicU32::new(0);
+static JIRA_FAULTS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    Slack,
+    Github,
+    Jira,
+}
+
+impl Service {
+    pub fn all() -> Vec<Service> {
+        vec![Service::Slack, Service::Github, Service::Jira]
+    }
+
+    fn counter(&self) -> &'static AtomicU32 {
+        match *self {
+            Service::Slack => &SLACK_FAULTS,
+            Service::Github => &GITHUB_FAULTS,
+            Service::Jira => &JIRA_FAULTS,
+        }
+    }
+
+    // Each service fails the way it does most often in practice
+    pub fn simulated_error(&self) -> String {
+        match *self {
+            Service::Slack => "429 Too Many Requests (injected fault)".into(),
+            Service::Github => "502 Bad Gateway (injected fault)".into(),
+            Service::Jira => "operation timed out (injected fault)".into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Fault {
+    pub service: Service,
+    // how many more requests will fail. UNTIL_CLEARED fails them all.
+    pub remaining: u32,
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+    if !enabled {
+        clear_all();
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+// Fail the next `count` requests to the given service. Zero clears the fault.
+pub fn inject(service: Service, count: u32) -> Result<()> {
+    if !is_enabled() {
+        return Err(format_err!("Fault injection is not enabled"));
+    }
+    service.counter().store(count, Ordering::SeqCst);
+    Ok(())
+}
+
+pub fn clear_all() {
+    for service in Service::all() {
+        service.counter().store(0, Ordering::SeqCst);
+    }
+}
+
+pub fn active() -> Vec<Fault> {
+    Service::all()
+        .into_iter()
+        .map(|s| Fault {
+            service: s,
+            remaining: s.counter().load(Ordering::SeqCst),
+        })
+        .filter(|f| f.remaining > 0)
+        .collect()
+}
+
+// Returns the simulated error if the next request to this service should fail.
+pub fn check(service: Service) -> Result<()> {
+    if !is_enabled() {
+        return Ok(());
+    }
+
+    let injected = service
+        .counter()
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| match n {
+            0 => None,
+            UNTIL_CLEARED => Some(n),
+            _ => Some(n - 1),
+        })
+        .is_ok();
+
+    if injected {
+        warn!("Injecting {:?} fault", service);
+        Err(format_err!("{}", service.simulated_error()))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the fault registry is global, so keep everything in one test
+    #[test]
+    fn test_fault_injection() {
+        assert!(check(Service::Github).is_ok());
+        assert!(inject(Service::Github, 1).is_err());
+
+        set_enabled(true);
+        inject(Service::Github, 2).unwrap();
+        inject(Service::Slack, UNTIL_CLEARED).unwrap();
+        assert_eq!(
+            vec![
+                Fault { service: Service::Slack, remaining: UNTIL_CLEARED },
+                Fault { service: Service::Github, remaining: 2 },
+            ],
+            active()
+        );
+
+        assert_eq!("502 Bad Gateway (injected fault)", format!("{}", check(Service::Github).unwrap_err()));
+        assert!(check(Service::Github).is_err());
+        assert!(check(Service::Github).is_ok());
+        assert!(check(Service::Jira).is_ok());
+
+        for _ in 0..5 {
+            assert!(check(Service::Slack).is_err());
+        }
+
+        set_enabled(false);
+        assert_eq!(Vec::<Fault>::new(), active());
+        assert!(check(Service::Slack).is_ok());
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use failure::format_err;
+use log::error;
+use reqwest;
+use serde_derive::{Deserialize, Serialize};
+use url::percent_encoding::{utf8_percent_encode, QUERY_ENCODE_SET};
+
+use crate::config::{FederationConfig, FederationPeer};
+use crate::errors::*;
+use crate::http_client::HTTPClient;
+use crate::users::UserInfo;
+
+// how long answers from peers, including that they don't know someone, are kept
+const DEFAULT_CACHE_MINUTES: u64 = 10;
+
+// What an instance shares about one of its users with its peers: who they are in slack, and whether they are away
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FederatedUser {
+    pub github: String,
+    pub slack: String,
+    #[serde(default)]
+    pub email: String,
+    // direct messages are muted for good, or until `muted_until`
+    #[serde(default)]
+    pub mute_direct_messages: bool,
+    #[serde(default)]
+    pub muted_until: i64,
+}
+
+impl FederatedUser {
+    pub fn from_info(user: &UserInfo) -> FederatedUser {
+        FederatedUser {
+            github: user.github.clone(),
+            slack: user.slack.clone(),
+            email: user.email.clone(),
+            mute_direct_messages: user.mute_direct_messages,
+            muted_until: user.muted_until,
+        }
+    }
+
+    pub fn to_info(&self) -> UserInfo {
+        let mut user = UserInfo::new(&self.github, &self.slack);
+        user.email = self.email.clone();
+        user.mute_direct_messages = self.mute_direct_messages;
+        user.muted_until = self.muted_until;
+        user
+    }
+}
+
+// Another octobot instance's user directory
+pub trait Directory: Send + Sync {
+    fn lookup_user(&self, github: &str) -> Result<Option<FederatedUser>>;
+}
+
+pub struct PeerDirectory {
+    name: String,
+    client: HTTPClient,
+}
+
+impl PeerDirectory {
+    pub fn new(peer: &FederationPeer) -> Result<PeerDirectory> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::ACCEPT, "application/json".parse().unwrap());
+        headers.insert(reqwest::header::AUTHORIZATION, format!("Bearer {}", peer.token).parse()?);
+
+        Ok(PeerDirectory {
+            name: peer.name.clone(),
+            client: HTTPClient::new_with_headers(peer.url.trim_end_matches('/'), headers)?,
+        })
+    }
+}
+
+impl Directory for PeerDirectory {
+    fn lookup_user(&self, github: &str) -> Result<Option<FederatedUser>> {
+        self.client
+            .get(&format!("federation/users?github={}", utf8_percent_encode(github, QUERY_ENCODE_SET)))
+            .map_err(|e| format_err!("Error looking up {} in {}: {}", github, self.name, e))
+    }
+}
+
+struct CacheEntry {
+    fetched: Instant,
+    user: Option<UserInfo>,
+}
+
+// Users who aren't mapped here are looked up in the peers, in order
+pub struct Peers {
+    directories: Vec<Box<dyn Directory>>,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl Peers {
+    pub fn new(config: &FederationConfig) -> Result<Peers> {
+        let mut directories: Vec<Box<dyn Directory>> = vec![];
+        for peer in &config.peers {
+            directories.push(Box::new(PeerDirectory::new(peer)?));
+        }
+        let minutes = config.cache_minutes.unwrap_or(DEFAULT_CACHE_MINUTES);
+
+        Ok(Peers::with_directories(directories, Duration::from_secs(minutes * 60)))
+    }
+
+    pub fn with_directories(directories: Vec<Box<dyn Directory>>, ttl: Duration) -> Peers {
+        Peers {
+            directories: directories,
+            ttl: ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn lookup(&self, github: &str) -> Option<UserInfo> {
+        if self.directories.is_empty() || github.is_empty() {
+            return None;
+        }
+        if let Some(entry) = self.cache.lock().unwrap().get(github) {
+            if entry.fetched.elapsed() < self.ttl {
+                return entry.user.clone();
+            }
+        }
+
+        let mut user = None;
+        let mut failed = false;
+        for directory in &self.directories {
+            match directory.lookup_user(github) {
+                Ok(Some(u)) => {
+                    user = Some(u.to_info());
+                    break;
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    error!("{}", e);
+                    failed = true;
+                }
+            }
+        }
+
+        // a peer that is down may know them: ask again next time
+        if user.is_none() && failed {
+            return None;
+        }
+        self.cache.lock().unwrap().insert(
+            github.to_string(),
+            CacheEntry {
+                fetched: Instant::now(),
+                user: user.clone(),
+            },
+        );
+        user
+    }
+}
+
+pub fn new_peers(config: &Option<FederationConfig>) -> Option<Arc<Peers>> {
+    let config = config.as_ref().filter(|c| !c.peers.is_empty())?;
+    match Peers::new(config) {
+        Ok(p) => Some(Arc::new(p)),
+        Err(e) => {
+            error!("Error setting up federation peers: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct FakeDirectory {
+        users: Vec<FederatedUser>,
+        lookups: Arc<AtomicUsize>,
+    }
+
+    impl Directory for FakeDirectory {
+        fn lookup_user(&self, github: &str) -> Result<Option<FederatedUser>> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            if github == "broken" {
+                return Err(format_err!("connection refused"));
+            }
+            Ok(self.users.iter().find(|u| u.github == github).cloned())
+        }
+    }
+
+    fn user(github: &str, slack: &str) -> FederatedUser {
+        FederatedUser {
+            github: github.into(),
+            slack: slack.into(),
+            email: format!("{}@company.com", github),
+            mute_direct_messages: false,
+            muted_until: 0,
+        }
+    }
+
+    fn directory(users: Vec<FederatedUser>, lookups: &Arc<AtomicUsize>) -> Box<dyn Directory> {
+        Box::new(FakeDirectory {
+            users: users,
+            lookups: lookups.clone(),
+        })
+    }
+
+    #[test]
+    fn test_to_and_from_info() {
+        let mut away = user("joe", "joe.slack");
+        away.muted_until = 1556712000;
+
+        let info = away.to_info();
+        assert_eq!(None, info.id);
+        assert_eq!("joe.slack", info.slack);
+        assert_eq!("joe@company.com", info.email);
+        assert_eq!(1556712000, info.muted_until);
+        assert_eq!(away, FederatedUser::from_info(&info));
+    }
+
+    #[test]
+    fn test_lookup_in_order() {
+        let first = Arc::new(AtomicUsize::new(0));
+        let second = Arc::new(AtomicUsize::new(0));
+        let peers = Peers::with_directories(
+            vec![
+                directory(vec![user("joe", "joe.payments")], &first),
+                directory(vec![user("joe", "joe.retail"), user("sue", "sue.retail")], &second),
+            ],
+            Duration::from_secs(600),
+        );
+
+        assert_eq!("joe.payments", peers.lookup("joe").unwrap().slack);
+        assert_eq!("sue.retail", peers.lookup("sue").unwrap().slack);
+        assert!(peers.lookup("nobody").is_none());
+        assert_eq!(3, first.load(Ordering::SeqCst));
+        assert_eq!(2, second.load(Ordering::SeqCst));
+
+        // answers, including unknown users, are cached
+        assert_eq!("joe.payments", peers.lookup("joe").unwrap().slack);
+        assert!(peers.lookup("nobody").is_none());
+        assert_eq!(3, first.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_lookup_skips_failed_peers() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let peers = Peers::with_directories(
+            vec![directory(vec![], &lookups), directory(vec![user("joe", "joe.retail")], &lookups)],
+            Duration::from_secs(600),
+        );
+
+        assert_eq!("joe.retail", peers.lookup("joe").unwrap().slack);
+        // not found because a peer failed: not cached
+        assert!(peers.lookup("broken").is_none());
+        assert!(peers.lookup("broken").is_none());
+        assert_eq!(6, lookups.load(Ordering::SeqCst));
+    }
+}
@@ -9,8 +9,99 @@ use crate::git_clone_manager::GitCloneManager;
 use crate::github;
 use crate::github::Commit;
 use crate::github::api::GithubSessionFactory;
+use crate::slack::{SlackAttachment, SlackAttachmentBuilder};
+use crate::util;
 use crate::worker;
 
+// What a force-push did to a PR: the commits it dropped and added, and what became of the PR's approvals
+#[derive(Debug, PartialEq)]
+pub struct ForcePushSummary {
+    pub compare_url: String,
+    pub dropped: Vec<Commit>,
+    pub added: Vec<Commit>,
+    // Reviewers whose approvals of a dropped commit were dismissed
+    pub dismissed: Vec<String>,
+    // Reviewers whose approvals still stand
+    pub approved: Vec<String>,
+}
+
+pub fn summarize(
+    github: &dyn github::api::Session,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    before_hash: &str,
+    after_hash: &str,
+    compare_url: &str,
+) -> Result<ForcePushSummary> {
+    let owner = repo.owner.login();
+    let dropped = github.compare_commits(owner, &repo.name, after_hash, before_hash)?;
+    let added = github.compare_commits(owner, &repo.name, before_hash, after_hash)?;
+    let reviews = github.get_pull_request_reviews(owner, &repo.name, pull_request.number)?;
+
+    let was_dropped = |sha: &str| sha == before_hash || dropped.iter().any(|c| c.sha == sha);
+    let mut approved: Vec<String> = vec![];
+    let mut dismissed: Vec<String> = vec![];
+    for review in &reviews {
+        let login = review.user.login().to_string();
+        if review.state == "APPROVED" && !approved.contains(&login) {
+            approved.push(login);
+        } else if review.state == "DISMISSED"
+            && review.commit_id.as_ref().map_or(false, |c| was_dropped(c.as_str()))
+            && !dismissed.contains(&login)
+        {
+            dismissed.push(login);
+        }
+    }
+    // reapproved since
+    dismissed.retain(|l| !approved.contains(l));
+
+    Ok(ForcePushSummary {
+        compare_url: compare_url.into(),
+        dropped: dropped,
+        added: added,
+        dismissed: dismissed,
+        approved: approved,
+    })
+}
+
+pub fn message(summary: &ForcePushSummary, sender: &str, branch: &str) -> String {
+    format!("{} force-pushed branch {} ({})", sender, branch, util::make_link(&summary.compare_url, "compare"))
+}
+
+pub fn attachments(summary: &ForcePushSummary) -> Vec<SlackAttachment> {
+    let mut attachments = vec![];
+    if !summary.dropped.is_empty() {
+        attachments.push(
+            SlackAttachmentBuilder::new(&commit_lines(&summary.dropped))
+                .title(format!("Dropped {} commit(s)", summary.dropped.len()))
+                .build(),
+        );
+    }
+    if !summary.added.is_empty() {
+        attachments.push(
+            SlackAttachmentBuilder::new(&commit_lines(&summary.added))
+                .title(format!("Added {} commit(s)", summary.added.len()))
+                .build(),
+        );
+    }
+    if !summary.dismissed.is_empty() {
+        let msg = format!("Approvals by {} were dismissed", summary.dismissed.join(", "));
+        attachments.push(SlackAttachmentBuilder::new(&msg).color("warning").build());
+    } else if !summary.approved.is_empty() {
+        let msg = format!("Approvals by {} still stand", summary.approved.join(", "));
+        attachments.push(SlackAttachmentBuilder::new(&msg).build());
+    }
+    attachments
+}
+
+fn commit_lines(commits: &Vec<Commit>) -> String {
+    commits
+        .iter()
+        .map(|c| format!("{}: {}", util::make_link(&c.html_url, Commit::short_hash(c)), Commit::title(c)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 pub fn comment_force_push(
     diffs: Result<DiffOfDiffs>,
     github: &dyn github::api::Session,
@@ -218,4 +309,12 @@ impl worker::Runner<ForcePushRequest> for Runner {
             error!("Error diffing force push: {}", e);
         }
     }
+
+    fn repo(&self, req: &ForcePushRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &ForcePushRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
 }
@@ -0,0 +1,420 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::Serialize;
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::scheduler;
+use crate::util;
+
+pub const FREEZE_CONTEXT: &str = "code-freeze";
+
+// how often to look for freezes to thaw
+pub const CHECK_INTERVAL_SECS: u64 = 60;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Freeze {
+    pub org: String,
+    pub until: i64,
+    pub frozen_by: String,
+    pub created_at: i64,
+}
+
+// A PR that octobot has marked while its org is frozen
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct FrozenPullRequest {
+    // "owner/name"
+    pub repo: String,
+    pub number: u32,
+    // who allowed it to merge anyway, if anyone
+    pub exception_by: Option<String>,
+}
+
+impl FrozenPullRequest {
+    pub fn owner_and_name(&self) -> Result<(&str, &str)> {
+        let mut parts = self.repo.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(owner), Some(name)) => Ok((owner, name)),
+            _ => Err(format_err!("Invalid repo: {}", self.repo)),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct FreezeStatus {
+    pub freeze: Freeze,
+    pub pull_requests: Vec<FrozenPullRequest>,
+}
+
+#[derive(Clone)]
+pub struct CodeFreezes {
+    db: Database,
+}
+
+impl CodeFreezes {
+    pub fn new(db: Database) -> CodeFreezes {
+        CodeFreezes { db: db }
+    }
+
+    pub fn freeze(&self, org: &str, until: i64, frozen_by: &str, now: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO code_freezes (org, until, frozen_by, created_at)
+               VALUES (?1, ?2, ?3, ?4)
+               ON CONFLICT (org) DO UPDATE SET until = excluded.until, frozen_by = excluded.frozen_by,
+                   created_at = excluded.created_at"#,
+            &[&org as &dyn ToSql, &until, &frozen_by, &now],
+        )
+        .map_err(|e| format_err!("Error freezing {}: {}", org, e))?;
+
+        Ok(())
+    }
+
+    // Forgets the freeze and the PRs marked during it
+    pub fn thaw(&self, org: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute("DELETE FROM code_freezes WHERE org = ?1", &[&org])
+            .map_err(|e| format_err!("Error thawing {}: {}", org, e))?;
+        conn.execute("DELETE FROM code_freeze_pull_requests WHERE org = ?1", &[&org])
+            .map_err(|e| format_err!("Error thawing {}: {}", org, e))?;
+
+        Ok(())
+    }
+
+    pub fn get_all(&self) -> Result<Vec<Freeze>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare("SELECT * FROM code_freezes ORDER BY org")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(db::NO_PARAMS)?;
+
+        let mut freezes = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            freezes.push(Freeze {
+                org: cols.get(row, "org")?,
+                until: cols.get(row, "until")?,
+                frozen_by: cols.get(row, "frozen_by")?,
+                created_at: cols.get(row, "created_at")?,
+            });
+        }
+
+        Ok(freezes)
+    }
+
+    // The org's freeze, unless it has already ended
+    pub fn active(&self, org: &str, now: i64) -> Result<Option<Freeze>> {
+        Ok(self.get_all()?.into_iter().find(|f| f.org == org && f.until > now))
+    }
+
+    pub fn expired(&self, now: i64) -> Result<Vec<Freeze>> {
+        Ok(self.get_all()?.into_iter().filter(|f| f.until <= now).collect())
+    }
+
+    pub fn mark_pull_request(&self, repo: &github::Repo, number: u32) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO code_freeze_pull_requests (org, repo, number, exception_by)
+               VALUES (?1, ?2, ?3, '')
+               ON CONFLICT DO NOTHING"#,
+            &[&repo.owner.login() as &dyn ToSql, &repo.full_name, &number],
+        )
+        .map_err(|e| format_err!("Error marking {}#{} as frozen: {}", repo.full_name, number, e))?;
+
+        Ok(())
+    }
+
+    pub fn grant_exception(&self, repo: &github::Repo, number: u32, approved_by: &str) -> Result<()> {
+        self.mark_pull_request(repo, number)?;
+
+        let conn = self.db.connect()?;
+        conn.execute(
+            "UPDATE code_freeze_pull_requests SET exception_by = ?1 WHERE repo = ?2 AND number = ?3",
+            &[&approved_by as &dyn ToSql, &repo.full_name, &number],
+        )
+        .map_err(|e| format_err!("Error granting freeze exception to {}#{}: {}", repo.full_name, number, e))?;
+
+        Ok(())
+    }
+
+    pub fn pull_requests(&self, org: &str) -> Result<Vec<FrozenPullRequest>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt =
+            conn.prepare("SELECT * FROM code_freeze_pull_requests WHERE org = ?1 ORDER BY repo, number")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(&[&org])?;
+
+        let mut prs = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            let exception_by: String = cols.get(row, "exception_by")?;
+            prs.push(FrozenPullRequest {
+                repo: cols.get(row, "repo")?,
+                number: cols.get(row, "number")?,
+                exception_by: Some(exception_by).filter(|e| !e.is_empty()),
+            });
+        }
+
+        Ok(prs)
+    }
+
+    pub fn exception(&self, repo: &github::Repo, number: u32) -> Result<Option<String>> {
+        Ok(self
+            .pull_requests(repo.owner.login())?
+            .into_iter()
+            .find(|p| p.repo == repo.full_name && p.number == number)
+            .and_then(|p| p.exception_by))
+    }
+
+    // Whether the PR ("owner/name") is held back by a freeze of its org
+    pub fn blocks_merge(&self, repo: &str, number: u32, now: i64) -> Result<bool> {
+        let org = repo.split('/').next().unwrap_or("");
+        if self.active(org, now)?.is_none() {
+            return Ok(false);
+        }
+
+        let exception = self.pull_requests(org)?.into_iter().find(|p| p.repo == repo && p.number == number);
+        Ok(exception.and_then(|p| p.exception_by).is_none())
+    }
+
+    pub fn status(&self, now: i64) -> Result<Vec<FreezeStatus>> {
+        let mut status = vec![];
+        for freeze in self.get_all()?.into_iter().filter(|f| f.until > now) {
+            let pull_requests = self.pull_requests(&freeze.org)?;
+            status.push(FreezeStatus {
+                freeze: freeze,
+                pull_requests: pull_requests,
+            });
+        }
+        Ok(status)
+    }
+}
+
+pub fn check_run(pull_request: &github::PullRequest, freeze: Option<&Freeze>, exception_by: Option<&str>) -> github::CheckRun {
+    let run = github::CheckRun::new(FREEZE_CONTEXT, pull_request, None);
+    let (mut run, title, summary) = match (freeze, exception_by) {
+        (Some(f), None) => (
+            run.completed(github::Conclusion::Failure),
+            "Code freeze",
+            format!(
+                "{} is frozen until {}: merges need an exception (`/octobot freeze-exception`)",
+                f.org,
+                util::format_timestamp(f.until)
+            ),
+        ),
+        (Some(_), Some(by)) => (
+            run.completed(github::Conclusion::Success),
+            "Code freeze exception",
+            format!("{} granted an exception to the code freeze", by),
+        ),
+        (None, _) => (
+            run.completed(github::Conclusion::Success),
+            "No code freeze",
+            "The code freeze has ended".to_string(),
+        ),
+    };
+
+    run.output = Some(github::CheckOutput::new(title, &summary));
+    run
+}
+
+// Fails the PR's freeze check if its org is frozen (unless it has an exception)
+pub fn check_pull_request(
+    github: &dyn Session,
+    freezes: &CodeFreezes,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    now: i64,
+) -> Result<()> {
+    let freeze = match freezes.active(repo.owner.login(), now)? {
+        Some(f) => f,
+        None => return Ok(()),
+    };
+
+    freezes.mark_pull_request(repo, pull_request.number)?;
+    let exception = freezes.exception(repo, pull_request.number)?;
+    github.create_check_run(pull_request, &check_run(pull_request, Some(&freeze), exception.as_ref().map(|e| e.as_str())))?;
+    Ok(())
+}
+
+// Freezes the org, and fails the freeze check of open PRs in its configured repos. PRs in other repos of the
+// org are checked when they next change.
+pub fn freeze_org(
+    config: &Config,
+    github_app: &dyn GithubSessionFactory,
+    org: &str,
+    until: i64,
+    frozen_by: &str,
+    now: i64,
+) -> Result<()> {
+    config.code_freezes.freeze(org, until, frozen_by, now)?;
+    info!("{} froze {} until {}", frozen_by, org, util::format_timestamp(until));
+
+    let repos = config.repos().get_all()?;
+    for info in repos.iter().filter(|r| r.repo.starts_with(&format!("{}/", org))) {
+        let repo = github::Repo::parse(&format!("https://{}/{}", config.github_host(&info.repo), info.repo))?;
+        let github = github_app.new_session(org, &repo.name)?;
+        for pull_request in github.get_pull_requests(org, &repo.name, Some("open"), None)? {
+            if let Err(e) = check_pull_request(&github, &config.code_freezes, &repo, &pull_request, now) {
+                error!("Error checking {}#{} for the code freeze: {}", info.repo, pull_request.number, e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn grant_exception(
+    config: &Config,
+    github_app: &dyn GithubSessionFactory,
+    owner: &str,
+    name: &str,
+    number: u32,
+    approved_by: &str,
+    now: i64,
+) -> Result<()> {
+    let github = github_app.new_session(owner, name)?;
+    let pull_request = github.get_pull_request(owner, name, number)?;
+    let repo = github::Repo::parse(&format!("https://{}/{}/{}", config.github_host(owner), owner, name))?;
+
+    config.code_freezes.grant_exception(&repo, number, approved_by)?;
+    info!("{} granted {}/{}#{} a code freeze exception", approved_by, owner, name, number);
+
+    let freeze = config.code_freezes.active(owner, now)?;
+    github.create_check_run(&pull_request, &check_run(&pull_request, freeze.as_ref(), Some(approved_by)))?;
+    Ok(())
+}
+
+// Passes the freeze check of every PR marked during the freeze, then forgets the freeze
+pub fn thaw_org(config: &Config, github_app: &dyn GithubSessionFactory, org: &str) -> Result<()> {
+    for frozen in config.code_freezes.pull_requests(org)? {
+        let (owner, name) = frozen.owner_and_name()?;
+        let result = github_app.new_session(owner, name).and_then(|github| {
+            let pull_request = github.get_pull_request(owner, name, frozen.number)?;
+            if pull_request.state == "open" {
+                github.create_check_run(&pull_request, &check_run(&pull_request, None, None))?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!("Error lifting the code freeze from {}#{}: {}", frozen.repo, frozen.number, e);
+        }
+    }
+
+    config.code_freezes.thaw(org)?;
+    info!("Thawed {}", org);
+    Ok(())
+}
+
+pub struct FreezeThawer {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+}
+
+impl FreezeThawer {
+    pub fn new(config: Arc<Config>, github_app: Arc<dyn GithubSessionFactory>) -> Arc<dyn scheduler::Task> {
+        Arc::new(FreezeThawer {
+            config: config,
+            github_app: github_app,
+        })
+    }
+}
+
+impl scheduler::Task for FreezeThawer {
+    fn run(&self, now: i64) -> Result<()> {
+        for freeze in self.config.code_freezes.expired(now)? {
+            if let Err(e) = thaw_org(&self.config, &*self.github_app, &freeze.org) {
+                error!("Error thawing {}: {}", freeze.org, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (CodeFreezes, TempDir) {
+        let temp_dir = TempDir::new("freeze.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        (CodeFreezes::new(db), temp_dir)
+    }
+
+    #[test]
+    fn test_freeze_and_thaw() {
+        let (freezes, _temp_dir) = new_test();
+        let repo = github::Repo::parse("http://the-github-host/some-org/some-repo").unwrap();
+
+        freezes.freeze("some-org", 2000, "the-release-manager", 1000).unwrap();
+        assert_eq!("the-release-manager", freezes.active("some-org", 1500).unwrap().unwrap().frozen_by);
+        assert_eq!(None, freezes.active("other-org", 1500).unwrap());
+        assert_eq!(None, freezes.active("some-org", 2000).unwrap());
+        assert!(freezes.expired(1999).unwrap().is_empty());
+        assert_eq!(1, freezes.expired(2000).unwrap().len());
+
+        freezes.mark_pull_request(&repo, 1).unwrap();
+        freezes.mark_pull_request(&repo, 2).unwrap();
+        freezes.grant_exception(&repo, 2, "the-release-manager").unwrap();
+        freezes.mark_pull_request(&repo, 2).unwrap();
+
+        assert_eq!(None, freezes.exception(&repo, 1).unwrap());
+        assert_eq!(Some("the-release-manager".to_string()), freezes.exception(&repo, 2).unwrap());
+
+        assert!(freezes.blocks_merge("some-org/some-repo", 1, 1500).unwrap());
+        assert!(freezes.blocks_merge("some-org/some-repo", 3, 1500).unwrap());
+        assert!(!freezes.blocks_merge("some-org/some-repo", 2, 1500).unwrap());
+        assert!(!freezes.blocks_merge("some-org/some-repo", 1, 2000).unwrap());
+        assert!(!freezes.blocks_merge("other-org/some-repo", 1, 1500).unwrap());
+
+        let status = freezes.status(1500).unwrap();
+        assert_eq!(1, status.len());
+        assert_eq!(
+            vec![
+                FrozenPullRequest {
+                    repo: "some-org/some-repo".into(),
+                    number: 1,
+                    exception_by: None,
+                },
+                FrozenPullRequest {
+                    repo: "some-org/some-repo".into(),
+                    number: 2,
+                    exception_by: Some("the-release-manager".into()),
+                },
+            ],
+            status[0].pull_requests
+        );
+
+        freezes.thaw("some-org").unwrap();
+        assert!(freezes.get_all().unwrap().is_empty());
+        assert!(freezes.pull_requests("some-org").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_check_run() {
+        let mut pr = github::PullRequest::new();
+        pr.head.sha = "abcdef".into();
+        let freeze = Freeze {
+            org: "some-org".into(),
+            until: 0,
+            frozen_by: "the-release-manager".into(),
+            created_at: 0,
+        };
+
+        let run = check_run(&pr, Some(&freeze), None);
+        assert_eq!(Some(github::Conclusion::Failure), run.conclusion);
+        assert_eq!("abcdef", run.head_sha);
+
+        let run = check_run(&pr, Some(&freeze), Some("joe"));
+        assert_eq!(Some(github::Conclusion::Success), run.conclusion);
+        assert_eq!(Some("Code freeze exception".to_string()), run.output.unwrap().title);
+
+        let run = check_run(&pr, None, None);
+        assert_eq!(Some(github::Conclusion::Success), run.conclusion);
+    }
+}
@@ -3,15 +3,22 @@ use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
-use log::debug;
+use log::{debug, info};
 use failure::format_err;
 
+use crate::config::SigningConfig;
 use crate::errors::*;
 
+// how many more commits shallow clones fetch each time they turn out to be missing history
+const DEEPEN_STEPS: [u32; 3] = [50, 500, 5000];
+
 pub struct Git {
     pub host: String,
     pub token: String,
     repo_dir: PathBuf,
+    signing: Option<SigningConfig>,
+    // log pushes instead of making them
+    dry_run: bool,
 }
 
 impl Git {
@@ -20,9 +27,23 @@ impl Git {
             host: host.to_string(),
             token: token.to_string(),
             repo_dir: repo_dir.to_owned(),
+            signing: None,
+            dry_run: false,
         }
     }
 
+    // Signs the commits made with this git, if given a signing key
+    pub fn with_signing(mut self, signing: Option<&SigningConfig>) -> Git {
+        self.signing = signing.cloned();
+        self
+    }
+
+    // Pushes are only logged in dry runs: everything else still happens in the local clone
+    pub fn with_dry_run(mut self, dry_run: bool) -> Git {
+        self.dry_run = dry_run;
+        self
+    }
+
     pub fn run(&self, args: &[&str]) -> Result<String> {
         self.do_run(args, None)
     }
@@ -54,6 +75,19 @@ impl Git {
         self.run(&["rev-parse", "HEAD"])
     }
 
+    // The remote's default branch, e.g. "master"
+    pub fn default_branch(&self) -> Result<String> {
+        let head = match self.run(&["rev-parse", "--abbrev-ref", "origin/HEAD"]) {
+            Ok(h) => h,
+            // clones of empty repos don't know origin's HEAD yet
+            Err(_) => {
+                self.run(&["remote", "set-head", "origin", "--auto"])?;
+                self.run(&["rev-parse", "--abbrev-ref", "origin/HEAD"])?
+            }
+        };
+        Ok(head.trim_start_matches("origin/").to_string())
+    }
+
     pub fn does_branch_contain(&self, git_ref: &str, branch: &str) -> Result<bool> {
         let output = self.run(&["branch", "--contains", git_ref])?;
         Ok(Git::branches_output_contains(&output, branch))
@@ -68,13 +102,72 @@ impl Git {
     // Find the commit at which |leaf_ref| forked from |base_branch|.
     // This can find which commits belong to a PR.
     // Returns the ref found in the base branch that this git_ref came from.
+    // Shallow clones are deepened until the two share history.
     pub fn find_base_branch_commit(&self, leaf_ref: &str, base_branch: &str) -> Result<String> {
+        self.deepen_until(|| self.merge_base(leaf_ref, base_branch).is_ok())?;
+        self.merge_base(leaf_ref, base_branch)
+    }
+
+    fn merge_base(&self, leaf_ref: &str, base_branch: &str) -> Result<String> {
         match self.run(&["merge-base", "--fork-point", base_branch, leaf_ref]) {
             Ok(base) => Ok(base),
             Err(_) => self.run(&["merge-base", base_branch, leaf_ref]),
         }
     }
 
+    // Whether this is a shallow clone, i.e. its history stops at some depth
+    pub fn is_shallow(&self) -> bool {
+        self.repo_dir.join(".git").join("shallow").exists()
+    }
+
+    // Fetches only |refs| from origin: branch names or commit hashes.
+    // Shallow clones fetch them with |depth| commits of history, and at least their parents.
+    pub fn fetch_refs(&self, refs: &[&str], depth: u32) -> Result<()> {
+        let mut args = vec!["fetch".to_string()];
+        if depth > 0 && self.is_shallow() {
+            args.push(format!("--depth={}", depth.max(2)));
+        }
+        args.push("origin".into());
+        args.extend(refs.iter().map(|r| Git::refspec(r)));
+
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        self.run(&args)?;
+        Ok(())
+    }
+
+    fn refspec(git_ref: &str) -> String {
+        let is_hash = git_ref.len() == 40 && git_ref.chars().all(|c| c.is_ascii_hexdigit());
+        if is_hash {
+            git_ref.to_string()
+        } else {
+            format!("+refs/heads/{0}:refs/remotes/origin/{0}", git_ref)
+        }
+    }
+
+    // Makes sure a shallow clone has the parents of |commit_hash|, e.g. to cherry-pick it
+    pub fn ensure_parents(&self, commit_hash: &str) -> Result<()> {
+        let parent = format!("{}^", commit_hash);
+        self.deepen_until(|| self.run(&["rev-parse", "--verify", "--quiet", &parent]).is_ok())
+    }
+
+    // Deepens a shallow clone step by step until |has_history| is satisfied, fetching the whole history as a last
+    // resort. Does nothing for full clones.
+    fn deepen_until<F: Fn() -> bool>(&self, has_history: F) -> Result<()> {
+        for deepen_by in DEEPEN_STEPS.iter() {
+            if !self.is_shallow() || has_history() {
+                return Ok(());
+            }
+            debug!("Deepening shallow clone by {} commits", deepen_by);
+            self.run(&["fetch", &format!("--deepen={}", deepen_by), "origin"])?;
+        }
+
+        if self.is_shallow() && !has_history() {
+            debug!("Unshallowing clone");
+            self.run(&["fetch", "--unshallow", "origin"])?;
+        }
+        Ok(())
+    }
+
     pub fn clean(&self) -> Result<()> {
         self.run(&["reset", "--hard"])?;
         self.run(&["clean", "-fdx"])?;
@@ -103,6 +196,23 @@ impl Git {
         Ok((title, body.join("\n")))
     }
 
+    // returns (hash, message) of the commits reachable from |head| but not from |base|, newest first
+    pub fn get_commit_messages(&self, base: &str, head: &str) -> Result<Vec<(String, String)>> {
+        let output = self.run(&["log", "--pretty=%H%n%B%x1e", &format!("{}..{}", base, head)])?;
+
+        Ok(output
+            .split('\x1e')
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .map(|c| {
+                let mut parts = c.splitn(2, '\n');
+                let hash = parts.next().unwrap_or("").to_string();
+                let message = parts.next().unwrap_or("").trim().to_string();
+                (hash, message)
+            })
+            .collect())
+    }
+
     pub fn get_commit_author(&self, commit_hash: &str) -> Result<(String, String)> {
         let message = self.run(&["log", "-1", "--pretty=%an\n%ae", commit_hash])?;
 
@@ -113,8 +223,55 @@ impl Git {
         Ok((name, email))
     }
 
+    // Creates an annotated tag of |commit_hash| by the given tagger, signed if there is a signing key
+    pub fn create_tag(&self, name: &str, commit_hash: &str, message: &str, tagger: (&str, &str)) -> Result<()> {
+        let user = format!("user.name={}", tagger.0);
+        let email = format!("user.email={}", tagger.1);
+        let sign = if self.signing.is_some() { "-s" } else { "-a" };
+        self.run_with_stdin(&["-c", &user, "-c", &email, "tag", sign, "-F", "-", name, commit_hash], message)?;
+        Ok(())
+    }
+
+    // `-c` options that turn on signing of new commits
+    fn signing_args(&self) -> Result<Vec<String>> {
+        let signing = match self.signing {
+            Some(ref s) => s,
+            None => return Ok(vec![]),
+        };
+
+        let (format, program_key) = match signing.format.as_ref().map(|f| f.trim().to_lowercase()) {
+            None => ("openpgp", "gpg.program"),
+            Some(ref f) if f == "gpg" || f == "openpgp" || f.is_empty() => ("openpgp", "gpg.program"),
+            Some(ref f) if f == "ssh" => ("ssh", "gpg.ssh.program"),
+            Some(f) => return Err(format_err!("Unknown commit signing format '{}': use 'gpg' or 'ssh'", f)),
+        };
+
+        let mut args = vec![
+            format!("gpg.format={}", format),
+            format!("user.signingkey={}", signing.key),
+            "commit.gpgsign=true".to_string(),
+        ];
+        if let Some(ref program) = signing.program {
+            args.push(format!("{}={}", program_key, program));
+        }
+        Ok(args.into_iter().flat_map(|a| vec!["-c".to_string(), a]).collect())
+    }
+
+    // Whether git failed because the commit or tag couldn't be signed
+    fn is_signing_error(output: &str) -> bool {
+        output.contains("failed to sign the data")
+            || output.contains("unable to sign the tag")
+            || output.contains("Couldn't load public key")
+            || output.contains("failed to write commit object")
+    }
+
     fn do_run(&self, args: &[&str], stdin: Option<&str>) -> Result<String> {
+        if self.dry_run && args.first() == Some(&"push") {
+            info!("Dry run: not pushing from {}: git {}", self.repo_dir.display(), args.join(" "));
+            return Ok(String::new());
+        }
         debug!("Running git with args: {:?}", args);
+        let signing_args = self.signing_args()?;
         let mut cmd = Command::new("git");
         cmd.current_dir(&self.repo_dir)
             .stdin(if stdin.is_some() {
@@ -124,6 +281,7 @@ impl Git {
             })
             .stderr(Stdio::piped())
             .stdout(Stdio::piped())
+            .args(&signing_args)
             .args(args)
             .env("GIT_ASKPASS", &self.ask_pass_path())
             .env("OCTOBOT_HOST", &self.host)
@@ -154,6 +312,15 @@ impl Git {
             if result.stderr.len() > 0 {
                 output += String::from_utf8_lossy(&result.stderr).as_ref();
             }
+            if let Some(ref signing) = self.signing {
+                if Git::is_signing_error(&output) {
+                    return Err(format_err!(
+                        "Error signing with key {} (is it usable by octobot?):\n{}",
+                        signing.key,
+                        output
+                    ));
+                }
+            }
             Err(
                 format_err!(
                     "Error running git (exit code {}, args: {:?}):\n{}",
@@ -182,4 +349,65 @@ mod tests {
         assert!(Git::branches_output_contains("test\n* two", "two"));
         assert!(!Git::branches_output_contains("test\n* twos", "two"));
     }
+
+    #[test]
+    fn test_refspec() {
+        assert_eq!("+refs/heads/release/1.0:refs/remotes/origin/release/1.0", Git::refspec("release/1.0"));
+        assert_eq!(
+            "0123456789abcdef0123456789abcdef01234567",
+            Git::refspec("0123456789abcdef0123456789abcdef01234567")
+        );
+        // too short to be a full hash
+        assert_eq!("+refs/heads/abc123:refs/remotes/origin/abc123", Git::refspec("abc123"));
+    }
+
+    #[test]
+    fn test_dry_run_push() {
+        let git = Git::new("the-host", "the-token", Path::new("/does/not/exist")).with_dry_run(true);
+        assert_eq!("", git.run(&["push", "origin", "HEAD:some-branch"]).unwrap());
+        // only pushes are skipped
+        assert!(git.run(&["status"]).is_err());
+    }
+
+    #[test]
+    fn test_signing_args() {
+        let git = Git::new("the-host", "the-token", Path::new("/tmp"));
+        assert!(git.signing_args().unwrap().is_empty());
+
+        let git = git.with_signing(Some(&SigningConfig {
+            format: None,
+            key: "ABCDEF01".into(),
+            program: Some("gpg2".into()),
+        }));
+        assert_eq!(
+            vec![
+                "-c",
+                "gpg.format=openpgp",
+                "-c",
+                "user.signingkey=ABCDEF01",
+                "-c",
+                "commit.gpgsign=true",
+                "-c",
+                "gpg.program=gpg2",
+            ],
+            git.signing_args().unwrap()
+        );
+
+        let git = git.with_signing(Some(&SigningConfig {
+            format: Some("ssh".into()),
+            key: "/etc/octobot/signing_key".into(),
+            program: None,
+        }));
+        assert_eq!(
+            vec!["-c", "gpg.format=ssh", "-c", "user.signingkey=/etc/octobot/signing_key", "-c", "commit.gpgsign=true"],
+            git.signing_args().unwrap()
+        );
+
+        let git = git.with_signing(Some(&SigningConfig {
+            format: Some("pgp".into()),
+            key: "ABCDEF01".into(),
+            program: None,
+        }));
+        assert!(git.signing_args().is_err());
+    }
 }
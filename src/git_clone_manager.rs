@@ -1,30 +1,57 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use log::info;
+use log::{error, info};
 use failure::format_err;
 
+use crate::clone_cache::CloneCache;
 use crate::config::Config;
+use crate::db;
 use crate::dir_pool::{DirPool, HeldDir};
 use crate::errors::*;
 use crate::git::Git;
 use crate::github;
 use crate::github::api::Session;
+use crate::storage::{self, Store};
+use crate::worktree_pool::{BranchLock, HeldWorktree, WorktreePool, WorktreePoolStats};
 
 // clones git repos with given github session into a managed directory pool
 pub struct GitCloneManager {
     dir_pool: Arc<DirPool>,
+    worktree_pool: Arc<WorktreePool>,
+    cache: CloneCache,
     github_app: Arc<dyn github::api::GithubSessionFactory>,
+    // shallow clone depth, 0 for full clones
+    depth: u32,
+    // partial clone filter, e.g. "blob:none"
+    filter: Option<String>,
+    // where git bundles of the cloned repos are kept, so that new clones (e.g. in a fresh container) don't have
+    // to fetch everything from github
+    bundles: Option<Arc<dyn Store>>,
 }
 
 impl GitCloneManager {
     pub fn new(github_app: Arc<dyn github::api::GithubSessionFactory>, config: Arc<Config>) -> GitCloneManager {
         let clone_root_dir = config.main.clone_root_dir.to_string();
 
+        let bundles = if config.storage_git_cache() {
+            storage::new_store(&config).unwrap_or_else(|e| {
+                error!("Error setting up the git cache storage: {}", e);
+                None
+            })
+        } else {
+            None
+        };
+
         GitCloneManager {
             dir_pool: Arc::new(DirPool::new(&clone_root_dir)),
+            worktree_pool: Arc::new(WorktreePool::new(&clone_root_dir)),
+            cache: CloneCache::new(&clone_root_dir, config.clone_cache_quota_bytes()),
             github_app: github_app.clone(),
+            depth: config.clone_depth(),
+            filter: config.clone_filter(),
+            bundles: bundles,
         }
     }
 
@@ -32,12 +59,113 @@ impl GitCloneManager {
         let session = self.github_app.new_session(owner, repo)?;
 
         let held_clone_dir = self.dir_pool.take_directory(session.github_host(), owner, repo);
-        self.clone_repo(&session, owner, repo, &held_clone_dir.dir())?;
+        self.cache.touch(session.github_host(), owner, repo, db::now());
+        self.clone_repo(&session, owner, repo, &held_clone_dir.dir(), None)?;
+
+        Ok(held_clone_dir)
+    }
+
+    // Like `clone`, but only fetches |refs| (branch names or commit hashes) instead of all branches and tags,
+    // e.g. the target branch and commit of a cherry-pick.
+    pub fn clone_for_refs(&self, owner: &str, repo: &str, refs: &[&str]) -> Result<HeldDir> {
+        let session = self.github_app.new_session(owner, repo)?;
+
+        let held_clone_dir = self.dir_pool.take_directory(session.github_host(), owner, repo);
+        self.cache.touch(session.github_host(), owner, repo, db::now());
+        self.clone_repo(&session, owner, repo, &held_clone_dir.dir(), Some(refs))?;
 
         Ok(held_clone_dir)
     }
 
-    fn clone_repo(&self, session: &dyn github::api::Session, owner: &str, repo: &str, clone_dir: &PathBuf) -> Result<()> {
+    // Takes a worktree of the repo's shared clone, with |refs| fetched. Unlike separate clones, worktrees share
+    // the objects they fetch, so that concurrent merges into the same repo are cheap.
+    pub fn worktree(&self, owner: &str, repo: &str, refs: &[&str]) -> Result<HeldWorktree> {
+        let session = self.github_app.new_session(owner, repo)?;
+        let host = session.github_host();
+
+        let held_worktree = self.worktree_pool.take_worktree(host, owner, repo);
+        self.cache.touch(host, owner, repo, db::now());
+        {
+            let clone_lock = self.worktree_pool.clone_lock(host, owner, repo);
+            let _guard = clone_lock.lock().unwrap();
+
+            let main_dir = self.worktree_pool.main_dir(host, owner, repo);
+            self.clone_repo(&session, owner, repo, &main_dir, Some(refs))?;
+
+            if !held_worktree.dir().join(".git").exists() {
+                info!("Adding worktree {:?} of {:?}", held_worktree.dir(), main_dir);
+                let main_git = Git::new(host, session.github_token(), &main_dir);
+                // forget worktrees whose directories were removed
+                main_git.run(&["worktree", "prune"])?;
+                main_git.run(&["worktree", "add", "--detach", &held_worktree.dir().to_string_lossy(), "HEAD"])?;
+            }
+        }
+
+        // clean up state left behind by its last use
+        let git = Git::new(host, session.github_token(), held_worktree.dir());
+        git.run(&["checkout", "--detach"])?;
+        git.clean()?;
+
+        Ok(held_worktree)
+    }
+
+    // Blocks until no other merge holds the lock on |branch|: hold it while pushing to the branch
+    pub fn lock_branch(&self, host: &str, owner: &str, repo: &str, branch: &str) -> BranchLock {
+        self.worktree_pool.lock_branch(host, owner, repo, branch)
+    }
+
+    pub fn worktree_stats(&self) -> Vec<WorktreePoolStats> {
+        self.worktree_pool.stats()
+    }
+
+    pub fn cache(&self) -> &CloneCache {
+        &self.cache
+    }
+
+    // Whether one of the repo's clones or worktrees is held
+    pub fn in_use(&self, host: &str, owner: &str, repo: &str) -> bool {
+        self.dir_pool.in_use(host, owner, repo) || self.worktree_pool.in_use(host, owner, repo)
+    }
+
+    // Deletes all of the repo's clones and worktrees, unless some are in use. Returns whether it did.
+    pub fn evict(&self, host: &str, owner: &str, repo: &str) -> Result<bool> {
+        let repo_dir = self.cache.repo_dir(host, owner, repo);
+        let evicted = self.dir_pool.with_unused_repo(host, owner, repo, || {
+            self.worktree_pool.with_unused_repo(host, owner, repo, || {
+                // the next clone starts from where these got to
+                if let Some(clone_dir) = self.latest_clone(&repo_dir) {
+                    self.save_bundle(host, owner, repo, &clone_dir);
+                }
+                if repo_dir.exists() {
+                    fs::remove_dir_all(&repo_dir)?;
+                }
+                Ok(())
+            })
+        })?;
+        Ok(evicted.flatten().is_some())
+    }
+
+    // Takes a cached clone of the repo without fetching, so its refs are still as they were before the
+    // latest pushes. Returns None if there is no cached clone.
+    pub fn cached(&self, owner: &str, repo: &str) -> Result<Option<HeldDir>> {
+        let session = self.github_app.new_session(owner, repo)?;
+
+        let held_clone_dir = self.dir_pool.take_directory(session.github_host(), owner, repo);
+        if held_clone_dir.dir().join(".git").exists() {
+            Ok(Some(held_clone_dir))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn clone_repo(
+        &self,
+        session: &dyn github::api::Session,
+        owner: &str,
+        repo: &str,
+        clone_dir: &PathBuf,
+        refs: Option<&[&str]>,
+    ) -> Result<()> {
         let url = format!(
             "https://x-access-token@{}/{}/{}",
             session.github_host(),
@@ -47,7 +175,8 @@ impl GitCloneManager {
 
         let git = Git::new(session.github_host(), session.github_token(), clone_dir);
 
-        if clone_dir.join(".git").exists() {
+        let reused = clone_dir.join(".git").exists();
+        if reused {
             info!(
                 "Reusing cloned repo https://{}/{}/{} in {:?}",
                 session.github_host(),
@@ -55,8 +184,6 @@ impl GitCloneManager {
                 repo,
                 clone_dir
             );
-            // prune local tags deleted from remotes: important to avoid stale/bad version tags
-            git.run(&["fetch", "--prune", "origin", "+refs/tags/*:refs/tags/*"])?;
         } else {
             info!(
                 "Cloning https://{}/{}/{} into {:?}",
@@ -68,14 +195,156 @@ impl GitCloneManager {
             if let Err(e) = fs::create_dir_all(&clone_dir) {
                 return Err(format_err!("Error creating clone directory '{:?}': {}", clone_dir, e));
             }
-            git.run(&["clone", &url, "."])?;
+            if !self.restore_bundle(session.github_host(), owner, repo, &git, &url, clone_dir) {
+                git.run(&self.clone_args(&url).iter().map(|a| a.as_str()).collect::<Vec<_>>())?;
+                self.save_bundle(session.github_host(), owner, repo, clone_dir);
+            }
         }
 
-        // always fetch latest tags
-        git.run(&["fetch", "--tags"])?;
+        match refs {
+            Some(refs) => git.fetch_refs(refs, self.depth)?,
+            None => {
+                let depth = format!("--depth={}", self.depth);
+                let shallow = self.depth > 0 && git.is_shallow();
+                if reused {
+                    // prune local tags deleted from remotes: important to avoid stale/bad version tags
+                    let mut args = vec!["fetch", "--prune", "origin", "+refs/tags/*:refs/tags/*"];
+                    if shallow {
+                        args.push(&depth);
+                    }
+                    git.run(&args)?;
+                }
+
+                // always fetch latest tags
+                let mut args = vec!["fetch", "--tags"];
+                if shallow {
+                    args.push(&depth);
+                }
+                git.run(&args)?;
+            }
+        };
+
         // clean up state
         git.clean()?;
 
         Ok(())
     }
+
+    // Sets up |clone_dir| from the repo's saved bundle, if there is one, with |url| as the origin to fetch the rest
+    // from. Returns whether it did: if not, |clone_dir| is left empty.
+    fn restore_bundle(&self, host: &str, owner: &str, repo: &str, git: &Git, url: &str, clone_dir: &Path) -> bool {
+        let bundles = match self.bundles {
+            Some(ref b) => b,
+            None => return false,
+        };
+
+        let key = bundle_key(host, owner, repo);
+        let bundle_file = bundle_file(clone_dir);
+        let restored = bundles.download(&key, &bundle_file).and_then(|found| {
+            if found {
+                info!("Cloning https://{}/{}/{} from the saved bundle", host, owner, repo);
+                git.run(&["init"])?;
+                git.run(&["remote", "add", "origin", url])?;
+                git.run(&[
+                    "fetch",
+                    &bundle_file.to_string_lossy(),
+                    "+refs/remotes/origin/*:refs/remotes/origin/*",
+                    "+refs/tags/*:refs/tags/*",
+                ])?;
+                // check out the default branch, as `git clone` would
+                git.run(&["remote", "set-head", "origin", "--auto"])?;
+                let branch = git.default_branch()?;
+                git.checkout_branch(&branch, &format!("origin/{}", branch))?;
+            }
+            Ok(found)
+        });
+        remove_bundle_file(&bundle_file);
+
+        match restored {
+            Ok(found) => found,
+            Err(e) => {
+                error!("Error cloning https://{}/{}/{} from the saved bundle: {}", host, owner, repo, e);
+                // start over
+                let _ = fs::remove_dir_all(clone_dir);
+                let _ = fs::create_dir_all(clone_dir);
+                false
+            }
+        }
+    }
+
+    // Saves the remote branches and tags of |clone_dir| as the repo's bundle. Failures are only logged: the
+    // bundle is only a cache.
+    fn save_bundle(&self, host: &str, owner: &str, repo: &str, clone_dir: &Path) {
+        let bundles = match self.bundles {
+            Some(ref b) => b,
+            None => return,
+        };
+
+        let key = bundle_key(host, owner, repo);
+        let bundle_file = bundle_file(clone_dir);
+        let git = Git::new(host, "", clone_dir);
+        let saved = git
+            .run(&["for-each-ref", "--format=%(refname)", "refs/remotes/origin", "refs/tags"])
+            .and_then(|refs| {
+                // the symbolic origin/HEAD would clash with the branch it points to when fetched
+                let refs = refs.lines().filter(|r| *r != "refs/remotes/origin/HEAD").collect::<Vec<_>>();
+                git.run_with_stdin(&["bundle", "create", &bundle_file.to_string_lossy(), "--stdin"], &refs.join("\n"))
+            })
+            .and_then(|_| bundles.upload(&key, &bundle_file));
+        match saved {
+            Ok(()) => info!("Saved the bundle of https://{}/{}/{}", host, owner, repo),
+            Err(e) => error!("Error saving the bundle of https://{}/{}/{}: {}", host, owner, repo, e),
+        }
+        remove_bundle_file(&bundle_file);
+    }
+
+    // The repo's most recently modified clone or worktree
+    fn latest_clone(&self, repo_dir: &Path) -> Option<PathBuf> {
+        if self.bundles.is_none() {
+            return None;
+        }
+        let mut clones = fs::read_dir(repo_dir)
+            .ok()?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.join(".git").is_dir())
+            .filter_map(|p| fs::metadata(&p).and_then(|m| m.modified()).ok().map(|t| (t, p)))
+            .collect::<Vec<_>>();
+        clones.sort();
+        clones.pop().map(|(_, p)| p)
+    }
+
+    fn clone_args(&self, url: &str) -> Vec<String> {
+        let mut args = vec!["clone".to_string()];
+        if self.depth > 0 {
+            // shallow clones only get the default branch unless asked otherwise
+            args.push(format!("--depth={}", self.depth));
+            args.push("--no-single-branch".into());
+        }
+        if let Some(ref filter) = self.filter {
+            args.push(format!("--filter={}", filter));
+        }
+        args.push(url.into());
+        args.push(".".into());
+        args
+    }
+}
+
+pub fn bundle_key(host: &str, owner: &str, repo: &str) -> String {
+    format!("git-cache/{}/{}/{}.bundle", host, owner, repo)
+}
+
+// Next to the clone rather than in it, since git only clones into empty directories
+fn bundle_file(clone_dir: &Path) -> PathBuf {
+    let mut name = clone_dir.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(".bundle");
+    clone_dir.with_file_name(name)
+}
+
+fn remove_bundle_file(bundle_file: &Path) {
+    if bundle_file.exists() {
+        if let Err(e) = fs::remove_file(bundle_file) {
+            error!("Error removing {:?}: {}", bundle_file, e);
+        }
+    }
 }
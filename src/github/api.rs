@@ -1,8 +1,10 @@
 use failure::format_err;
 use log::{info, error};
 use serde_derive::{Deserialize, Serialize};
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
 
 use crate::errors::*;
+use crate::faults;
 use crate::github::models::*;
 use crate::github::models_checks::*;
 use crate::http_client::HTTPClient;
@@ -33,19 +35,67 @@ pub trait Session: Send + Sync {
         base: &str,
     ) -> Result<PullRequest>;
 
+    // Draft PRs can't be merged until someone marks them ready for review
+    fn create_draft_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        title: &str,
+        body: &str,
+        head: &str,
+        base: &str,
+    ) -> Result<PullRequest>;
+
     fn get_pull_request_labels(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<Label>>;
 
     fn add_pull_request_labels(&self, owner: &str, repo: &str, number: u32, labels: Vec<String>) -> Result<()>;
 
+    fn remove_pull_request_label(&self, owner: &str, repo: &str, number: u32, label: &str) -> Result<()>;
+
+    fn get_repo_labels(&self, owner: &str, repo: &str) -> Result<Vec<Label>>;
+
+    fn create_label(&self, owner: &str, repo: &str, label: &Label) -> Result<()>;
+
+    // Also renames the label if |label| has another name: the issues and PRs that have it keep it
+    fn update_label(&self, owner: &str, repo: &str, name: &str, label: &Label) -> Result<()>;
+
     fn get_pull_request_commits(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<Commit>>;
 
     fn get_pull_request_reviews(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<Review>>;
 
+    // How many of the PR's review threads nobody has resolved yet, from the graphql API
+    fn get_unresolved_review_threads(&self, owner: &str, repo: &str, number: u32) -> Result<u32>;
+
+    fn create_commit_status(&self, owner: &str, repo: &str, sha: &str, status: &Status) -> Result<()>;
+
+    fn get_pull_request_files(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<PullRequestFile>>;
+
+    fn get_file_contents(&self, owner: &str, repo: &str, path: &str, git_ref: &str) -> Result<String>;
+
     fn assign_pull_request(&self, owner: &str, repo: &str, number: u32, assignees: Vec<String>) -> Result<()>;
 
     fn request_review(&self, owner: &str, repo: &str, number: u32, reviewers: Vec<String>) -> Result<()>;
 
+    fn request_team_review(&self, owner: &str, repo: &str, number: u32, teams: Vec<String>) -> Result<()>;
+
+    fn get_team_members(&self, org: &str, team_slug: &str) -> Result<Vec<User>>;
+
+    fn get_org_members(&self, org: &str) -> Result<Vec<User>>;
+
+    fn get_org_repos(&self, org: &str) -> Result<Vec<Repo>>;
+
+    fn get_org_teams(&self, org: &str) -> Result<Vec<Team>>;
+
+    // Sends all of the repo's events to |url|, signed with |secret|
+    fn create_webhook(&self, owner: &str, repo: &str, url: &str, secret: &str) -> Result<()>;
+
+    fn get_user(&self, login: &str) -> Result<User>;
+
     fn comment_pull_request(&self, owner: &str, repo: &str, number: u32, comment: &str) -> Result<()>;
+
+    fn get_pull_request_comments(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<IssueComment>>;
+
+    fn edit_comment(&self, owner: &str, repo: &str, comment_id: u64, comment: &str) -> Result<()>;
     fn create_branch(&self, owner: &str, repo: &str, branch_name: &str, sha: &str) -> Result<()>;
     fn delete_branch(&self, owner: &str, repo: &str, branch_name: &str) -> Result<()>;
     fn approve_pull_request(
@@ -56,13 +106,30 @@ pub trait Session: Send + Sync {
         commit_hash: &str,
         comment: Option<&str>,
     ) -> Result<()>;
+    fn merge_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        number: u32,
+        commit_hash: &str,
+        options: &MergeOptions,
+    ) -> Result<()>;
     fn get_timeline(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<TimelineEvent>>;
+    fn get_release_by_tag(&self, owner: &str, repo: &str, tag: &str) -> Result<Release>;
+    fn get_latest_release(&self, owner: &str, repo: &str) -> Result<Release>;
+    // commits reachable from |head| but not from |base|, oldest first
+    fn compare_commits(&self, owner: &str, repo: &str, base: &str, head: &str) -> Result<Vec<Commit>>;
+    fn upload_release_asset(&self, release: &Release, name: &str, content_type: &str, contents: &[u8]) -> Result<ReleaseAsset>;
 
     // checks api
     fn get_suites(&self, pr: &PullRequest) -> Result<Vec<CheckSuite>>;
+    fn get_check_runs(&self, owner: &str, repo: &str, git_ref: &str) -> Result<Vec<CheckRun>>;
     fn get_check_run(&self, pr: &PullRequest, id: u32) -> Result<CheckRun>;
     fn create_check_run(&self, pr: &PullRequest, run: &CheckRun) -> Result<u32>;
     fn update_check_run(&self, pr: &PullRequest, check_run_id: u32, run: &CheckRun) -> Result<()>;
+    fn get_check_run_annotations(&self, owner: &str, repo: &str, check_run_id: u64) -> Result<Vec<CheckAnnotation>>;
+    // the plain text log of a github actions job
+    fn get_job_log(&self, owner: &str, repo: &str, job_id: u64) -> Result<String>;
 }
 
 pub trait GithubSessionFactory: Send + Sync {
@@ -70,16 +137,34 @@ pub trait GithubSessionFactory: Send + Sync {
     fn get_token_org(&self, org: &str) -> Result<String>;
     fn get_token_repo(&self, owner: &str, repo: &str) -> Result<String>;
     fn bot_name(&self) -> String;
+
+    // The bot's login on the host that has the owner's repos
+    fn owner_bot_name(&self, _owner: &str) -> String {
+        self.bot_name()
+    }
 }
 
 pub fn api_base(host: &str) -> String {
     if host == "github.com" {
         "https://api.github.com".to_string()
+    } else if host.starts_with("http://") || host.starts_with("https://") {
+        // explicit scheme, e.g. for pointing at octobot-mock-server
+        format!("{}/api/v3", host.trim_end_matches('/'))
     } else {
         format!("https://{}/api/v3", host)
     }
 }
 
+pub fn graphql_url(host: &str) -> String {
+    if host == "github.com" {
+        "https://api.github.com/graphql".to_string()
+    } else if host.starts_with("http://") || host.starts_with("https://") {
+        format!("{}/api/graphql", host.trim_end_matches('/'))
+    } else {
+        format!("https://{}/api/graphql", host)
+    }
+}
+
 pub struct GithubApp {
     host: String,
     app_id: u32,
@@ -128,7 +213,7 @@ impl GithubApp {
             format!("Bearer {}", jwt_token).parse().unwrap(),
         );
 
-        HTTPClient::new_with_headers(&api_base(&self.host), headers)
+        Ok(HTTPClient::new_for_service(&api_base(&self.host), headers, faults::Service::Github)?)
     }
 
     fn new_token(&self, installation_url: &str) -> Result<String> {
@@ -245,7 +330,7 @@ impl GithubSession {
             format!("Token {}", token).parse().unwrap(),
         );
 
-        let client = HTTPClient::new_with_headers(&api_base(host), headers)?;
+        let client = HTTPClient::new_for_service(&api_base(host), headers, faults::Service::Github)?;
 
         Ok(GithubSession {
             client: client,
@@ -255,6 +340,35 @@ impl GithubSession {
             app_id: app_id,
         })
     }
+
+    fn post_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        title: &str,
+        body: &str,
+        head: &str,
+        base: &str,
+        draft: bool,
+    ) -> Result<PullRequest> {
+        #[derive(Serialize)]
+        struct CreatePR {
+            title: String,
+            body: String,
+            head: String,
+            base: String,
+            draft: bool,
+        }
+        let pr = CreatePR {
+            title: title.to_string(),
+            body: body.to_string(),
+            head: head.to_string(),
+            base: base.to_string(),
+            draft: draft,
+        };
+
+        self.client.post(&format!("repos/{}/{}/pulls", owner, repo), &pr)
+    }
 }
 
 impl Session for GithubSession {
@@ -358,21 +472,19 @@ impl Session for GithubSession {
         head: &str,
         base: &str,
     ) -> Result<PullRequest> {
-        #[derive(Serialize)]
-        struct CreatePR {
-            title: String,
-            body: String,
-            head: String,
-            base: String,
-        }
-        let pr = CreatePR {
-            title: title.to_string(),
-            body: body.to_string(),
-            head: head.to_string(),
-            base: base.to_string(),
-        };
+        self.post_pull_request(owner, repo, title, body, head, base, false)
+    }
 
-        self.client.post(&format!("repos/{}/{}/pulls", owner, repo), &pr)
+    fn create_draft_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        title: &str,
+        body: &str,
+        head: &str,
+        base: &str,
+    ) -> Result<PullRequest> {
+        self.post_pull_request(owner, repo, title, body, head, base, true)
     }
 
     fn get_pull_request_labels(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<Label>> {
@@ -395,6 +507,68 @@ impl Session for GithubSession {
             .map_err(|e| format_err!("Error adding label: {}/{} #{}: {}", owner, repo, number, e))
     }
 
+    fn remove_pull_request_label(&self, owner: &str, repo: &str, number: u32, label: &str) -> Result<()> {
+        self.client
+            .delete_void(&format!(
+                "repos/{}/{}/issues/{}/labels/{}",
+                owner,
+                repo,
+                number,
+                utf8_percent_encode(label, PATH_SEGMENT_ENCODE_SET)
+            ))
+            .map_err(|e| format_err!("Error removing label {}: {}/{} #{}: {}", label, owner, repo, number, e))
+    }
+
+    fn get_repo_labels(&self, owner: &str, repo: &str) -> Result<Vec<Label>> {
+        let mut labels = vec![];
+        let mut page = 1;
+        loop {
+            let next_labels: Vec<Label> = self
+                .client
+                .get(&format!("repos/{}/{}/labels?per_page=100&page={}", owner, repo, page))
+                .map_err(|e| format_err!("Error looking up labels of {}/{}: {}", owner, repo, e))?;
+
+            if next_labels.is_empty() {
+                break;
+            }
+
+            labels.extend(next_labels.into_iter());
+            page += 1;
+        }
+
+        Ok(labels)
+    }
+
+    fn create_label(&self, owner: &str, repo: &str, label: &Label) -> Result<()> {
+        self.client
+            .post_void(&format!("repos/{}/{}/labels", owner, repo), label)
+            .map_err(|e| format_err!("Error creating label {} in {}/{}: {}", label.name, owner, repo, e))
+    }
+
+    fn update_label(&self, owner: &str, repo: &str, name: &str, label: &Label) -> Result<()> {
+        #[derive(Serialize)]
+        struct UpdateLabel<'a> {
+            new_name: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            color: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            description: Option<&'a str>,
+        }
+
+        let body = UpdateLabel {
+            new_name: &label.name,
+            color: label.color.as_ref().map(|c| c.as_str()),
+            description: label.description.as_ref().map(|d| d.as_str()),
+        };
+
+        self.client
+            .patch_void(
+                &format!("repos/{}/{}/labels/{}", owner, repo, utf8_percent_encode(name, PATH_SEGMENT_ENCODE_SET)),
+                &body,
+            )
+            .map_err(|e| format_err!("Error updating label {} in {}/{}: {}", name, owner, repo, e))
+    }
+
     fn get_pull_request_commits(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<Commit>> {
         self.client
             .get(&format!("repos/{}/{}/pulls/{}/commits", owner, repo, number))
@@ -407,6 +581,109 @@ impl Session for GithubSession {
             .map_err(|e| format_err!("Error looking up PR reviews: {}/{} #{}: {}", owner, repo, number, e))
     }
 
+    fn get_unresolved_review_threads(&self, owner: &str, repo: &str, number: u32) -> Result<u32> {
+        #[derive(Serialize)]
+        struct Variables<'a> {
+            owner: &'a str,
+            repo: &'a str,
+            number: u32,
+        }
+        #[derive(Serialize)]
+        struct Query<'a> {
+            query: &'a str,
+            variables: Variables<'a>,
+        }
+        // only the first 100 threads are looked at: a PR with more has bigger problems
+        let query = Query {
+            query: r#"query($owner: String!, $repo: String!, $number: Int!) {
+                repository(owner: $owner, name: $repo) {
+                    pullRequest(number: $number) { reviewThreads(first: 100) { nodes { isResolved } } }
+                }
+            }"#,
+            variables: Variables {
+                owner: owner,
+                repo: repo,
+                number: number,
+            },
+        };
+
+        let resp: serde_json::Value = self
+            .client
+            .post(&graphql_url(&self.host), &query)
+            .map_err(|e| format_err!("Error looking up PR review threads: {}/{} #{}: {}", owner, repo, number, e))?;
+        let threads = resp["data"]["repository"]["pullRequest"]["reviewThreads"]["nodes"]
+            .as_array()
+            .ok_or_else(|| format_err!("No review threads for {}/{} #{}: {}", owner, repo, number, resp["errors"]))?;
+
+        Ok(threads.iter().filter(|t| t["isResolved"] == false).count() as u32)
+    }
+
+    fn create_commit_status(&self, owner: &str, repo: &str, sha: &str, status: &Status) -> Result<()> {
+        #[derive(Serialize)]
+        struct CreateStatus<'a> {
+            state: &'a str,
+            context: &'a Option<String>,
+            description: &'a Option<String>,
+            target_url: &'a Option<String>,
+        }
+        let body = CreateStatus {
+            state: &status.state,
+            context: &status.context,
+            description: &status.description,
+            target_url: &status.target_url,
+        };
+
+        self.client
+            .post_void(&format!("repos/{}/{}/statuses/{}", owner, repo, sha), &body)
+            .map_err(|e| format_err!("Error creating commit status: {}/{} {}: {}", owner, repo, sha, e))
+    }
+
+    fn get_pull_request_files(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<PullRequestFile>> {
+        let mut files = vec![];
+        let mut page = 1;
+        loop {
+            let next_files: Vec<PullRequestFile> = self
+                .client
+                .get(&format!(
+                    "repos/{}/{}/pulls/{}/files?per_page=100&page={}",
+                    owner, repo, number, page
+                ))
+                .map_err(|e| format_err!("Error looking up PR files: {}/{} #{}: {}", owner, repo, number, e))?;
+
+            if next_files.is_empty() {
+                break;
+            }
+
+            files.extend(next_files.into_iter());
+            page += 1;
+        }
+
+        Ok(files)
+    }
+
+    fn get_file_contents(&self, owner: &str, repo: &str, path: &str, git_ref: &str) -> Result<String> {
+        #[derive(Deserialize)]
+        struct Contents {
+            content: String,
+            encoding: String,
+        }
+
+        let contents: Contents = self
+            .client
+            .get(&format!("repos/{}/{}/contents/{}?ref={}", owner, repo, path, git_ref))
+            .map_err(|e| format_err!("Error looking up file {} in {}/{} @ {}: {}", path, owner, repo, git_ref, e))?;
+
+        if contents.encoding != "base64" {
+            return Err(format_err!("Unexpected encoding for {}: {}", path, contents.encoding));
+        }
+
+        // github wraps the base64 content across lines
+        let data = base64::decode(&contents.content.replace("\n", ""))
+            .map_err(|e| format_err!("Error decoding contents of {}: {}", path, e))?;
+
+        String::from_utf8(data).map_err(|e| format_err!("File {} is not valid UTF-8: {}", path, e))
+    }
+
     fn assign_pull_request(&self, owner: &str, repo: &str, number: u32, assignees: Vec<String>) -> Result<()> {
         #[derive(Serialize)]
         struct AssignPR {
@@ -436,6 +713,138 @@ impl Session for GithubSession {
             .map_err(|e| format_err!("Error requesting review for PR: {}/{} #{}: {}", owner, repo, number, e))
     }
 
+    fn request_team_review(&self, owner: &str, repo: &str, number: u32, teams: Vec<String>) -> Result<()> {
+        #[derive(Serialize)]
+        struct ReviewPR {
+            team_reviewers: Vec<String>,
+        }
+
+        let body = ReviewPR { team_reviewers: teams };
+
+        self.client
+            .post_void(
+                &format!("repos/{}/{}/pulls/{}/requested_reviewers", owner, repo, number),
+                &body,
+            )
+            .map_err(|e| format_err!("Error requesting team review for PR: {}/{} #{}: {}", owner, repo, number, e))
+    }
+
+    fn get_team_members(&self, org: &str, team_slug: &str) -> Result<Vec<User>> {
+        let mut members = vec![];
+        let mut page = 1;
+        loop {
+            let next_members: Vec<User> = self
+                .client
+                .get(&format!("orgs/{}/teams/{}/members?per_page=100&page={}", org, team_slug, page))
+                .map_err(|e| format_err!("Error looking up members of team {}/{}: {}", org, team_slug, e))?;
+
+            if next_members.is_empty() {
+                break;
+            }
+
+            members.extend(next_members.into_iter());
+            page += 1;
+        }
+
+        Ok(members)
+    }
+
+    fn get_org_members(&self, org: &str) -> Result<Vec<User>> {
+        let mut members = vec![];
+        let mut page = 1;
+        loop {
+            let next_members: Vec<User> = self
+                .client
+                .get(&format!("orgs/{}/members?per_page=100&page={}", org, page))
+                .map_err(|e| format_err!("Error looking up members of org {}: {}", org, e))?;
+
+            if next_members.is_empty() {
+                break;
+            }
+
+            members.extend(next_members.into_iter());
+            page += 1;
+        }
+
+        Ok(members)
+    }
+
+    fn get_org_repos(&self, org: &str) -> Result<Vec<Repo>> {
+        let mut repos = vec![];
+        let mut page = 1;
+        loop {
+            let next_repos: Vec<Repo> = self
+                .client
+                .get(&format!("orgs/{}/repos?per_page=100&page={}", org, page))
+                .map_err(|e| format_err!("Error looking up repos of org {}: {}", org, e))?;
+
+            if next_repos.is_empty() {
+                break;
+            }
+
+            repos.extend(next_repos.into_iter());
+            page += 1;
+        }
+
+        Ok(repos)
+    }
+
+    fn get_org_teams(&self, org: &str) -> Result<Vec<Team>> {
+        let mut teams = vec![];
+        let mut page = 1;
+        loop {
+            let next_teams: Vec<Team> = self
+                .client
+                .get(&format!("orgs/{}/teams?per_page=100&page={}", org, page))
+                .map_err(|e| format_err!("Error looking up teams of org {}: {}", org, e))?;
+
+            if next_teams.is_empty() {
+                break;
+            }
+
+            teams.extend(next_teams.into_iter());
+            page += 1;
+        }
+
+        Ok(teams)
+    }
+
+    fn create_webhook(&self, owner: &str, repo: &str, url: &str, secret: &str) -> Result<()> {
+        #[derive(Serialize)]
+        struct HookConfig<'a> {
+            url: &'a str,
+            content_type: &'a str,
+            secret: &'a str,
+        }
+        #[derive(Serialize)]
+        struct CreateHook<'a> {
+            name: &'a str,
+            active: bool,
+            events: Vec<&'a str>,
+            config: HookConfig<'a>,
+        }
+        let body = CreateHook {
+            name: "web",
+            active: true,
+            events: vec!["*"],
+            config: HookConfig {
+                url: url,
+                content_type: "json",
+                secret: secret,
+            },
+        };
+
+        self.client
+            .post_void(&format!("repos/{}/{}/hooks", owner, repo), &body)
+            .map_err(|e| format_err!("Error creating webhook for {}/{}: {}", owner, repo, e))
+    }
+
+    fn get_user(&self, login: &str) -> Result<User> {
+        self.client
+            .get(&format!("users/{}", login))
+            .map_err(|e| format_err!("Error looking up user {}: {}", login, e))
+    }
+
     fn comment_pull_request(&self, owner: &str, repo: &str, number: u32, comment: &str) -> Result<()> {
         #[derive(Serialize)]
         struct CommentPR {
@@ -450,6 +859,43 @@ impl Session for GithubSession {
             .map_err(|e| format_err!("Error commenting on PR: {}/{} #{}: {}", owner, repo, number, e))
     }
 
+    fn get_pull_request_comments(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<IssueComment>> {
+        let mut comments = vec![];
+        let mut page = 1;
+        loop {
+            let next_comments: Vec<IssueComment> = self
+                .client
+                .get(&format!(
+                    "repos/{}/{}/issues/{}/comments?per_page=100&page={}",
+                    owner, repo, number, page
+                ))
+                .map_err(|e| format_err!("Error looking up PR comments: {}/{} #{}: {}", owner, repo, number, e))?;
+
+            if next_comments.is_empty() {
+                break;
+            }
+
+            comments.extend(next_comments.into_iter());
+            page += 1;
+        }
+
+        Ok(comments)
+    }
+
+    fn edit_comment(&self, owner: &str, repo: &str, comment_id: u64, comment: &str) -> Result<()> {
+        #[derive(Serialize)]
+        struct EditComment {
+            body: String,
+        }
+        let body = EditComment {
+            body: comment.to_string(),
+        };
+
+        self.client
+            .patch_void(&format!("repos/{}/{}/issues/comments/{}", owner, repo, comment_id), &body)
+            .map_err(|e| format_err!("Error editing comment: {}/{} {}: {}", owner, repo, comment_id, e))
+    }
+
     fn create_branch(&self, owner: &str, repo: &str, branch_name: &str, sha: &str) -> Result<()> {
         #[derive(Serialize)]
         struct CreateRef {
@@ -507,6 +953,38 @@ impl Session for GithubSession {
             .map_err(|e| format_err!("Error approving PR {}/{} #{}: {}", owner, repo, number, e))
     }
 
+    fn merge_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        number: u32,
+        commit_hash: &str,
+        options: &MergeOptions,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        struct MergeReq {
+            sha: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            merge_method: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            commit_title: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            commit_message: Option<String>,
+        }
+
+        // github refuses the merge if the head has moved on since the merge was requested
+        let body = MergeReq {
+            sha: commit_hash.into(),
+            merge_method: options.merge_method.clone(),
+            commit_title: options.commit_title.clone(),
+            commit_message: options.commit_message.clone(),
+        };
+
+        self.client
+            .put_void(&format!("repos/{}/{}/pulls/{}/merge", owner, repo, number), &body)
+            .map_err(|e| format_err!("Error merging PR {}/{} #{}: {}", owner, repo, number, e))
+    }
+
     fn get_timeline(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<TimelineEvent>> {
         let mut events = vec![];
         let mut page = 1;
@@ -535,6 +1013,55 @@ impl Session for GithubSession {
         Ok(events)
     }
 
+    fn get_release_by_tag(&self, owner: &str, repo: &str, tag: &str) -> Result<Release> {
+        self.client
+            .get(&format!("repos/{}/{}/releases/tags/{}", owner, repo, tag))
+            .map_err(|e| format_err!("Error looking up release {} in {}/{}: {}", tag, owner, repo, e))
+    }
+
+    fn get_latest_release(&self, owner: &str, repo: &str) -> Result<Release> {
+        self.client
+            .get(&format!("repos/{}/{}/releases/latest", owner, repo))
+            .map_err(|e| format_err!("Error looking up latest release in {}/{}: {}", owner, repo, e))
+    }
+
+    fn compare_commits(&self, owner: &str, repo: &str, base: &str, head: &str) -> Result<Vec<Commit>> {
+        #[derive(Deserialize)]
+        struct CompareResp {
+            commits: Vec<Commit>,
+        }
+
+        // Note: github lists at most 250 commits
+        let resp: CompareResp = self
+            .client
+            .get(&format!("repos/{}/{}/compare/{}...{}", owner, repo, base, head))
+            .map_err(|e| format_err!("Error comparing {}...{} in {}/{}: {}", base, head, owner, repo, e))?;
+
+        Ok(resp.commits)
+    }
+
+    fn upload_release_asset(&self, release: &Release, name: &str, content_type: &str, contents: &[u8]) -> Result<ReleaseAsset> {
+        // uploads go to a separate host, given by the release's upload_url template
+        let upload_url = match release.upload_url.find('{') {
+            Some(i) => &release.upload_url[0..i],
+            None => release.upload_url.as_str(),
+        };
+        if upload_url.is_empty() {
+            return Err(format_err!("Release {} has no upload url", release.tag_name));
+        }
+
+        let url = reqwest::Url::parse_with_params(upload_url, &[("name", name)])?;
+        self.client
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(contents.to_vec())
+            .send()
+            .and_then(|r| r.error_for_status())
+            .and_then(|mut r| r.json::<ReleaseAsset>())
+            .map_err(|e| format_err!("Error uploading {} to release {}: {}", name, release.tag_name, e))
+    }
+
     fn get_suites(&self, pr: &PullRequest) -> Result<Vec<CheckSuite>> {
         #[derive(Deserialize)]
         pub struct CheckSuiteList {
@@ -563,6 +1090,13 @@ impl Session for GithubSession {
             })
     }
 
+    fn get_check_runs(&self, owner: &str, repo: &str, git_ref: &str) -> Result<Vec<CheckRun>> {
+        self.client
+            .get::<CheckRunList>(&format!("repos/{}/{}/commits/{}/check-runs", owner, repo, git_ref))
+            .map(|list| list.check_runs)
+            .map_err(|e| format_err!("Error getting check runs for {}/{} {}: {}", owner, repo, git_ref, e))
+    }
+
     fn get_check_run(&self, pr: &PullRequest, id: u32) -> Result<CheckRun> {
         self.client
             .get(&format!(
@@ -620,4 +1154,18 @@ impl Session for GithubSession {
                 )
             })
     }
+
+    fn get_check_run_annotations(&self, owner: &str, repo: &str, check_run_id: u64) -> Result<Vec<CheckAnnotation>> {
+        self.client
+            .get(&format!("repos/{}/{}/check-runs/{}/annotations", owner, repo, check_run_id))
+            .map_err(|e| {
+                format_err!("Error getting annotations of check run #{} for {}/{}: {}", check_run_id, owner, repo, e)
+            })
+    }
+
+    fn get_job_log(&self, owner: &str, repo: &str, job_id: u64) -> Result<String> {
+        self.client
+            .get_text(&format!("repos/{}/{}/actions/jobs/{}/logs", owner, repo, job_id))
+            .map_err(|e| format_err!("Error getting log of job #{} for {}/{}: {}", job_id, owner, repo, e))
+    }
 }
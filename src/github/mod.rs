@@ -1,4 +1,5 @@
 pub mod api;
+pub mod multi;
 mod models;
 mod models_checks;
 
@@ -20,6 +20,10 @@ pub struct HookBody {
     pub pull_request: Option<PullRequest>,
     pub review: Option<Review>,
     pub label: Option<Label>,
+    // set when a review is requested from a team instead of a user
+    pub requested_team: Option<Team>,
+    // what an "edited" event changed
+    pub changes: Option<Changes>,
 
     // push event related stuff
     #[serde(rename = "ref")]
@@ -31,6 +35,40 @@ pub struct HookBody {
     pub deleted: Option<bool>,
     pub created: Option<bool>,
     pub commits: Option<Vec<PushCommit>>,
+
+    // status event related stuff
+    pub sha: Option<String>,
+    pub state: Option<String>,
+    pub context: Option<String>,
+    pub description: Option<String>,
+    pub target_url: Option<String>,
+    pub branches: Option<Vec<StatusBranch>>,
+    pub commit: Option<Commit>,
+
+    // release event related stuff
+    pub release: Option<Release>,
+
+    // deployment_status event related stuff
+    pub deployment: Option<Deployment>,
+    pub deployment_status: Option<DeploymentStatus>,
+}
+
+// The previous values of what an "edited" event changed
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Changes {
+    pub title: Option<ChangedValue>,
+    pub body: Option<ChangedValue>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ChangedValue {
+    pub from: String,
+}
+
+// A branch whose head is the commit of a status event
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct StatusBranch {
+    pub name: String,
 }
 
 impl HookBody {
@@ -44,6 +82,8 @@ impl HookBody {
             pull_request: None,
             review: None,
             label: None,
+            requested_team: None,
+            changes: None,
             ref_name: None,
             after: None,
             before: None,
@@ -52,6 +92,16 @@ impl HookBody {
             deleted: None,
             created: None,
             commits: None,
+            sha: None,
+            state: None,
+            context: None,
+            description: None,
+            target_url: None,
+            branches: None,
+            commit: None,
+            release: None,
+            deployment: None,
+            deployment_status: None,
         }
     }
 
@@ -100,6 +150,12 @@ pub struct App {
 pub struct User {
     pub login: Option<String>,
     pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub user_type: Option<String>,
+    // The public email of the user's profile, which github only allows to be one of their verified addresses.
+    // Only the users API has it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
 }
 
 impl User {
@@ -107,9 +163,15 @@ impl User {
         User {
             login: Some(login.to_string()),
             name: Some(login.to_string()),
+            user_type: None,
+            email: None,
         }
     }
 
+    pub fn is_bot(&self) -> bool {
+        self.user_type.as_ref().map(|t| t == "Bot").unwrap_or(false) || self.login().ends_with("[bot]")
+    }
+
     pub fn login(&self) -> &str {
         if let Some(ref login) = self.login {
             login
@@ -201,6 +263,8 @@ pub trait PullRequestLike {
     fn html_url(&self) -> &str;
     fn number(&self) -> u32;
     fn has_commits(&self) -> bool;
+    // Empty when not known, as for comments on the PR's issue
+    fn head_branch(&self) -> &str;
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -219,6 +283,14 @@ pub struct PullRequest {
     pub requested_reviewers: Option<Vec<User>>,
     pub reviews: Option<Vec<Review>>,
     pub draft: Option<bool>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    // Only known when fetching a single PR, and may be null while github is still computing it
+    #[serde(default)]
+    pub mergeable: Option<bool>,
+    // e.g. "clean" once all required checks and reviews pass. Also only known when fetching a single PR.
+    #[serde(default)]
+    pub mergeable_state: Option<String>,
 }
 
 impl PullRequest {
@@ -238,6 +310,9 @@ impl PullRequest {
             head: BranchRef::new(""),
             base: BranchRef::new(""),
             draft: None,
+            created_at: None,
+            mergeable: None,
+            mergeable_state: None,
         }
     }
 
@@ -294,6 +369,45 @@ impl<'a> PullRequestLike for &'a PullRequest {
     fn has_commits(&self) -> bool {
         true
     }
+
+    fn head_branch(&self) -> &str {
+        &self.head.ref_name
+    }
+}
+
+// How github should merge a PR. Unset fields use github's defaults.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MergeOptions {
+    // "merge", "squash", or "rebase"
+    pub merge_method: Option<String>,
+    pub commit_title: Option<String>,
+    pub commit_message: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct PullRequestFile {
+    pub filename: String,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub additions: u32,
+    #[serde(default)]
+    pub deletions: u32,
+    // unified diff of the file. github leaves it out for binary and very large files
+    #[serde(default)]
+    pub patch: Option<String>,
+}
+
+impl PullRequestFile {
+    pub fn new(filename: &str) -> PullRequestFile {
+        PullRequestFile {
+            filename: filename.into(),
+            status: "modified".into(),
+            additions: 0,
+            deletions: 0,
+            patch: None,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -329,16 +443,66 @@ impl<'a> PullRequestLike for &'a Issue {
     fn has_commits(&self) -> bool {
         false
     }
+
+    fn head_branch(&self) -> &str {
+        ""
+    }
+}
+
+// A top-level (i.e. not review) comment on an issue or pull request
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct IssueComment {
+    pub id: u64,
+    pub body: Option<String>,
+    pub user: User,
+}
+
+impl IssueComment {
+    pub fn new(id: u64, body: &str, user: User) -> IssueComment {
+        IssueComment {
+            id: id,
+            body: Some(body.into()),
+            user: user,
+        }
+    }
+
+    pub fn body(&self) -> &str {
+        self.body.as_ref().map(|b| b.as_str()).unwrap_or("")
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Label {
     pub name: String,
+    // hex, without the leading '#'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 impl Label {
     pub fn new(name: &str) -> Label {
-        Label { name: name.into() }
+        Label {
+            name: name.into(),
+            color: None,
+            description: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Team {
+    pub name: String,
+    pub slug: String,
+}
+
+impl Team {
+    pub fn new(slug: &str, name: &str) -> Team {
+        Team {
+            name: name.into(),
+            slug: slug.into(),
+        }
     }
 }
 
@@ -356,6 +520,11 @@ pub struct Review {
     pub body: Option<String>,
     pub html_url: String,
     pub user: User,
+    #[serde(default)]
+    pub submitted_at: Option<String>,
+    // The commit that was reviewed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_id: Option<String>,
 }
 
 impl Review {
@@ -365,6 +534,8 @@ impl Review {
             body: Some(body.into()),
             html_url: String::new(),
             user: user,
+            submitted_at: None,
+            commit_id: None,
         }
     }
 }
@@ -427,6 +598,13 @@ pub struct PushCommit {
     pub tree_id: String,
     pub message: String,
     pub url: String,
+    // paths of the files the commit touched
+    #[serde(default)]
+    pub added: Vec<String>,
+    #[serde(default)]
+    pub removed: Vec<String>,
+    #[serde(default)]
+    pub modified: Vec<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -534,8 +712,15 @@ impl PushCommit {
             tree_id: String::new(),
             url: String::new(),
             message: String::new(),
+            added: vec![],
+            removed: vec![],
+            modified: vec![],
         }
     }
+
+    pub fn changed_files(&self) -> impl Iterator<Item = &String> {
+        self.added.iter().chain(self.removed.iter()).chain(self.modified.iter())
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -548,6 +733,105 @@ pub struct Status {
     pub updated_at: Option<String>,
 }
 
+impl Status {
+    // |state| is one of "pending", "success", "failure" or "error"
+    pub fn new(context: &str, state: &str, description: &str) -> Status {
+        Status {
+            state: state.into(),
+            target_url: None,
+            context: Some(context.into()),
+            description: Some(description.into()),
+            creator: None,
+            updated_at: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Release {
+    #[serde(default)]
+    pub id: u64,
+    pub tag_name: String,
+    pub name: Option<String>,
+    pub html_url: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    // hypermedia template, e.g. "https://uploads.github.com/repos/o/r/releases/1/assets{?name,label}"
+    #[serde(default)]
+    pub upload_url: String,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+impl Release {
+    pub fn new(tag_name: &str) -> Release {
+        Release {
+            id: 0,
+            tag_name: tag_name.into(),
+            name: None,
+            html_url: String::new(),
+            draft: false,
+            prerelease: false,
+            upload_url: String::new(),
+            assets: vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Deployment {
+    #[serde(default)]
+    pub id: u64,
+    pub sha: String,
+    // the branch, tag or sha that was deployed
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+    pub environment: String,
+    pub description: Option<String>,
+}
+
+impl Deployment {
+    pub fn new(ref_name: &str, sha: &str, environment: &str) -> Deployment {
+        Deployment {
+            id: 0,
+            sha: sha.into(),
+            ref_name: ref_name.into(),
+            environment: environment.into(),
+            description: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct DeploymentStatus {
+    // "success", "failure", "error", "inactive", "in_progress", "queued" or "pending"
+    pub state: String,
+    pub target_url: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ReleaseAsset {
+    pub id: u64,
+    pub name: String,
+    pub browser_download_url: String,
+    #[serde(default)]
+    pub size: u64,
+}
+
+impl ReleaseAsset {
+    pub fn new(name: &str) -> ReleaseAsset {
+        ReleaseAsset {
+            id: 0,
+            name: name.into(),
+            browser_download_url: String::new(),
+            size: 0,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct TimelineEvent {
     pub id: Option<u32>,
@@ -21,6 +21,9 @@ pub struct CheckSuite {
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct CheckRun {
+    // github's id, which isn't sent when creating or updating a check run
+    #[serde(default, skip_serializing)]
+    pub id: u64,
     pub name: String,
     pub head_sha: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -76,6 +79,8 @@ pub enum Conclusion {
     Cancelled,
     TimedOut,
     ActionRequired,
+    Skipped,
+    Stale,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -88,6 +93,7 @@ pub enum CheckStatus {
 impl CheckRun {
     pub fn new(name: &str, pr: &models::PullRequest, url: Option<String>) -> CheckRun {
         CheckRun {
+            id: 0,
             name: name.into(),
             head_sha: pr.head.sha.clone(),
             status: CheckStatus::InProgress,
@@ -132,6 +138,8 @@ impl Serialize2 for Conclusion {
             Conclusion::Cancelled => "cancelled",
             Conclusion::TimedOut => "timed_out",
             Conclusion::ActionRequired => "action_required",
+            Conclusion::Skipped => "skipped",
+            Conclusion::Stale => "stale",
         };
         serializer.serialize_str(st)
     }
@@ -162,6 +170,8 @@ impl<'de> Deserialize2<'de> for Conclusion {
                     "cancelled" => Ok(Conclusion::Cancelled),
                     "timed_out" => Ok(Conclusion::TimedOut),
                     "action_required" => Ok(Conclusion::ActionRequired),
+                    "skipped" => Ok(Conclusion::Skipped),
+                    "stale" => Ok(Conclusion::Stale),
                     _ => Err(E::custom(format!("unexpected conclusion: '{}'", value))),
                 }
             }
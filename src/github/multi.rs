@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use failure::format_err;
+
+use crate::config::{Config, GithubConfig};
+use crate::errors::*;
+use crate::github::api::{GithubApp, GithubOauthApp, GithubSession, GithubSessionFactory};
+
+struct Instance {
+    orgs: Vec<String>,
+    factory: Arc<dyn GithubSessionFactory>,
+}
+
+// Sends each call to the github host that has the owner's repos
+pub struct MultiSessionFactory {
+    default: Arc<dyn GithubSessionFactory>,
+    instances: Vec<Instance>,
+}
+
+impl MultiSessionFactory {
+    pub fn new(default: Arc<dyn GithubSessionFactory>) -> MultiSessionFactory {
+        MultiSessionFactory {
+            default: default,
+            instances: vec![],
+        }
+    }
+
+    pub fn with_instance(mut self, orgs: Vec<String>, factory: Arc<dyn GithubSessionFactory>) -> MultiSessionFactory {
+        self.instances.push(Instance {
+            orgs: orgs,
+            factory: factory,
+        });
+        self
+    }
+
+    fn for_owner(&self, owner: &str) -> &dyn GithubSessionFactory {
+        match self.instances.iter().find(|i| i.orgs.iter().any(|o| o.eq_ignore_ascii_case(owner))) {
+            Some(i) => i.factory.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+}
+
+impl GithubSessionFactory for MultiSessionFactory {
+    fn new_session(&self, owner: &str, repo: &str) -> Result<GithubSession> {
+        self.for_owner(owner).new_session(owner, repo)
+    }
+
+    fn get_token_org(&self, org: &str) -> Result<String> {
+        self.for_owner(org).get_token_org(org)
+    }
+
+    fn get_token_repo(&self, owner: &str, repo: &str) -> Result<String> {
+        self.for_owner(owner).get_token_repo(owner, repo)
+    }
+
+    fn bot_name(&self) -> String {
+        self.default.bot_name()
+    }
+
+    fn owner_bot_name(&self, owner: &str) -> String {
+        self.for_owner(owner).owner_bot_name(owner)
+    }
+}
+
+// Logs in to the host as the app, or with the token
+pub fn new_factory(github: &GithubConfig) -> Result<Arc<dyn GithubSessionFactory>> {
+    match github.app_id {
+        Some(app_id) => Ok(Arc::new(GithubApp::new(&github.host, app_id, &github.app_key()?)?)),
+        None => {
+            let token = github.api_token.as_ref().ok_or_else(|| format_err!("expected an api_token"))?;
+            Ok(Arc::new(GithubOauthApp::new(&github.host, token)?))
+        }
+    }
+}
+
+// A session factory for [github], which also routes to any [[github_instances]]
+pub fn new_session_factory(config: &Config) -> Result<Arc<dyn GithubSessionFactory>> {
+    let default = new_factory(&config.github)?;
+
+    let instances = match config.github_instances {
+        Some(ref i) if !i.is_empty() => i,
+        _ => return Ok(default),
+    };
+
+    let mut factory = MultiSessionFactory::new(default);
+    for instance in instances {
+        let orgs = instance.orgs.clone().unwrap_or(vec![]);
+        if orgs.is_empty() {
+            return Err(format_err!("github instance {} has no orgs", instance.host));
+        }
+        let instance_factory = new_factory(instance)
+            .map_err(|e| format_err!("Error initiating session for github instance {}: {}", instance.host, e))?;
+        factory = factory.with_instance(orgs, instance_factory);
+    }
+
+    Ok(Arc::new(factory))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::github::api::Session;
+
+    struct FakeFactory {
+        host: String,
+    }
+
+    impl GithubSessionFactory for FakeFactory {
+        fn new_session(&self, owner: &str, repo: &str) -> Result<GithubSession> {
+            GithubSession::new(&self.host, &self.bot_name(), &self.get_token_repo(owner, repo)?, None)
+        }
+
+        fn get_token_org(&self, org: &str) -> Result<String> {
+            Ok(format!("{}:{}", self.host, org))
+        }
+
+        fn get_token_repo(&self, owner: &str, repo: &str) -> Result<String> {
+            Ok(format!("{}:{}/{}", self.host, owner, repo))
+        }
+
+        fn bot_name(&self) -> String {
+            format!("{}-bot", self.host)
+        }
+    }
+
+    fn fake(host: &str) -> Arc<dyn GithubSessionFactory> {
+        Arc::new(FakeFactory { host: host.into() })
+    }
+
+    #[test]
+    fn test_routes_by_owner() {
+        let factory = MultiSessionFactory::new(fake("github.com"))
+            .with_instance(vec!["platform".into(), "Infra".into()], fake("ghe.company.com"))
+            .with_instance(vec!["acquired-co".into()], fake("git.acquired.com"));
+
+        assert_eq!("github.com:some-org", factory.get_token_org("some-org").unwrap());
+        assert_eq!("ghe.company.com:infra/tools", factory.get_token_repo("infra", "tools").unwrap());
+        assert_eq!("git.acquired.com:acquired-co", factory.get_token_org("acquired-co").unwrap());
+
+        let session = factory.new_session("platform", "api").unwrap();
+        assert_eq!("ghe.company.com", session.github_host());
+        assert_eq!("ghe.company.com:platform/api", session.github_token());
+        assert_eq!("ghe.company.com-bot", session.bot_name());
+
+        assert_eq!("github.com-bot", factory.bot_name());
+    }
+
+    #[test]
+    fn test_bot_name_by_owner() {
+        let factory =
+            MultiSessionFactory::new(fake("github.com")).with_instance(vec!["Infra".into()], fake("ghe.company.com"));
+
+        assert_eq!("ghe.company.com-bot", factory.owner_bot_name("infra"));
+        assert_eq!("github.com-bot", factory.owner_bot_name("some-org"));
+        assert_eq!("ghe.company.com-bot", factory.new_session("infra", "tools").unwrap().bot_name());
+        assert_eq!("github.com-bot", factory.new_session("some-org", "api").unwrap().bot_name());
+    }
+}
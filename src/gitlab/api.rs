@@ -0,0 +1,76 @@
+use serde_json::json;
+
+use crate::config::GitlabConfig;
+use crate::errors::*;
+use crate::gitlab::{Approvals, Commit, CommitStatus, MergeRequest};
+use crate::http_client::HTTPClient;
+
+pub trait Session: Send + Sync {
+    fn get_merge_request(&self, project_id: u64, iid: u32) -> Result<MergeRequest>;
+
+    fn get_merge_request_commits(&self, project_id: u64, iid: u32) -> Result<Vec<Commit>>;
+
+    fn get_merge_request_approvals(&self, project_id: u64, iid: u32) -> Result<Approvals>;
+
+    fn comment_merge_request(&self, project_id: u64, iid: u32, body: &str) -> Result<()>;
+
+    fn create_commit_status(&self, project_id: u64, sha: &str, status: &CommitStatus) -> Result<()>;
+}
+
+pub struct GitlabSession {
+    client: HTTPClient,
+}
+
+impl GitlabSession {
+    pub fn new(config: &GitlabConfig) -> Result<GitlabSession> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("PRIVATE-TOKEN", config.token.parse()?);
+
+        Ok(GitlabSession {
+            client: HTTPClient::new_with_headers(&api_base(&config.url), headers)?,
+        })
+    }
+
+    fn merge_request_path(project_id: u64, iid: u32) -> String {
+        format!("/projects/{}/merge_requests/{}", project_id, iid)
+    }
+}
+
+fn api_base(url: &str) -> String {
+    format!("{}/api/v4", url.trim_end_matches('/'))
+}
+
+impl Session for GitlabSession {
+    fn get_merge_request(&self, project_id: u64, iid: u32) -> Result<MergeRequest> {
+        self.client.get(&GitlabSession::merge_request_path(project_id, iid))
+    }
+
+    fn get_merge_request_commits(&self, project_id: u64, iid: u32) -> Result<Vec<Commit>> {
+        self.client.get(&format!("{}/commits?per_page=100", GitlabSession::merge_request_path(project_id, iid)))
+    }
+
+    fn get_merge_request_approvals(&self, project_id: u64, iid: u32) -> Result<Approvals> {
+        self.client.get(&format!("{}/approvals", GitlabSession::merge_request_path(project_id, iid)))
+    }
+
+    fn comment_merge_request(&self, project_id: u64, iid: u32, body: &str) -> Result<()> {
+        let path = format!("{}/notes", GitlabSession::merge_request_path(project_id, iid));
+        self.client.post_void(&path, &json!({ "body": body }))
+    }
+
+    fn create_commit_status(&self, project_id: u64, sha: &str, status: &CommitStatus) -> Result<()> {
+        self.client.post_void(&format!("/projects/{}/statuses/{}", project_id, sha), status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_base() {
+        assert_eq!("https://gitlab.com/api/v4", api_base("https://gitlab.com"));
+        assert_eq!("https://gitlab.company.com/api/v4", api_base("https://gitlab.company.com/"));
+        assert_eq!("/projects/42/merge_requests/7", GitlabSession::merge_request_path(42, 7));
+    }
+}
@@ -0,0 +1,332 @@
+use log::{error, info};
+use serde_derive::Deserialize;
+use serde_json::{self, Value};
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::github;
+use crate::gitlab::api::Session;
+use crate::gitlab::{CommitStatus, MergeRequest, MergeRequestHook, NoteHook, PipelineHook, Project, User};
+use crate::jira;
+use crate::messenger::Messenger;
+use crate::slack::SlackAttachmentBuilder;
+use crate::util;
+use crate::vcs::{self, PullRequestHost};
+
+// Every webhook delivery says what it is about in `object_kind`
+#[derive(Deserialize, Debug)]
+struct Hook {
+    object_kind: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    MergeRequest(MergeRequestHook),
+    Note(NoteHook),
+    Pipeline(PipelineHook),
+    // events octobot doesn't handle
+    Other(String),
+}
+
+pub fn parse(body: &[u8]) -> Result<Event> {
+    let value: Value = serde_json::from_slice(body)?;
+    let hook: Hook = serde_json::from_value(value.clone())?;
+    let event = match hook.object_kind.as_str() {
+        "merge_request" => Event::MergeRequest(serde_json::from_value(value)?),
+        "note" => Event::Note(serde_json::from_value(value)?),
+        "pipeline" => Event::Pipeline(serde_json::from_value(value)?),
+        _ => Event::Other(hook.object_kind),
+    };
+    Ok(event)
+}
+
+// Notifies the repo's channels (and people) like github events do, and runs the repo's PR checks
+pub fn handle(
+    event: &Event,
+    config: &Config,
+    session: &dyn Session,
+    jira: Option<&dyn jira::api::Session>,
+    messenger: &Messenger,
+) {
+    match *event {
+        Event::MergeRequest(ref hook) => handle_merge_request(hook, config, session, jira, messenger),
+        Event::Note(ref hook) => handle_note(hook, session, messenger),
+        Event::Pipeline(ref hook) => handle_pipeline(hook, messenger),
+        Event::Other(ref kind) => info!("Ignoring GitLab event '{}'", kind),
+    };
+}
+
+fn handle_merge_request(
+    hook: &MergeRequestHook,
+    config: &Config,
+    session: &dyn Session,
+    jira: Option<&dyn jira::api::Session>,
+    messenger: &Messenger,
+) {
+    let action = hook.object_attributes.action.clone().unwrap_or_default();
+    let mr = match fetch_merge_request(&hook.project, hook.object_attributes.iid, session) {
+        Some(mr) => mr,
+        None => return,
+    };
+    let pr = mr.to_pull_request(&hook.project);
+    let host = GitlabMergeRequest::new(&hook.project, &mr, session);
+    let sender = hook.user.to_user();
+    let commits = hook.object_attributes.last_commit.iter().map(|c| c.to_commit()).collect::<Vec<_>>();
+
+    match action.as_str() {
+        "open" | "reopen" => {
+            vcs::lint_pull_request(&pr, true, config, &host);
+            vcs::notify_pull_request(&pr, &format!("{}ed", action), &sender, &commits, messenger);
+            vcs::submit_for_review(&pr, config, jira, &host);
+        }
+        "merge" => {
+            vcs::notify_pull_request(&pr, "merged", &sender, &commits, messenger);
+            vcs::mark_merged(&pr, config, jira, &host);
+        }
+        "close" => vcs::notify_pull_request(&pr, "closed", &sender, &commits, messenger),
+        // "approval" is each approval, "approved" is the one that met the approval rules
+        "approval" | "approved" => notify_approval(&hook.project, &pr, &hook.user, session, messenger),
+        // new commits have the previous head as `oldrev`; edits only matter for the title
+        "update" => vcs::lint_pull_request(&pr, false, config, &host),
+        _ => info!("Ignoring GitLab merge request action '{}'", action),
+    };
+}
+
+fn notify_approval(
+    project: &Project,
+    pr: &github::PullRequest,
+    approver: &User,
+    session: &dyn Session,
+    messenger: &Messenger,
+) {
+    let approvals_left = match session.get_merge_request_approvals(project.id, pr.number) {
+        Ok(a) => a.approvals_left,
+        Err(e) => {
+            error!("Error getting approvals of {}!{}: {}", project.path_with_namespace, pr.number, e);
+            0
+        }
+    };
+    vcs::notify_approval(pr, &approver.to_user(), approvals_left, messenger);
+}
+
+// Comments on merge requests go to its author, reviewers, assignees and anyone mentioned
+fn handle_note(hook: &NoteHook, session: &dyn Session, messenger: &Messenger) {
+    let iid = match hook.merge_request {
+        Some(ref mr) if hook.object_attributes.noteable_type == "MergeRequest" => mr.iid,
+        _ => {
+            info!("Ignoring GitLab comment on a {}", hook.object_attributes.noteable_type);
+            return;
+        }
+    };
+    let mr = match fetch_merge_request(&hook.project, iid, session) {
+        Some(mr) => mr,
+        None => return,
+    };
+
+    let commits = Vec::<github::Commit>::new();
+    vcs::notify_comment(
+        &mr.to_pull_request(&hook.project),
+        &hook.user.to_user(),
+        &hook.object_attributes.note,
+        &hook.object_attributes.url,
+        &commits,
+        messenger,
+    );
+}
+
+fn handle_pipeline(hook: &PipelineHook, messenger: &Messenger) {
+    let pipeline = &hook.object_attributes;
+    if !pipeline.is_finished() {
+        return;
+    }
+
+    let repo = hook.project.to_repo();
+    let mut attachment = SlackAttachmentBuilder::new("");
+    attachment
+        .title(format!("Pipeline #{} for {}", pipeline.id, pipeline.ref_name))
+        .title_link(pipeline.html_url(&hook.project))
+        .color(if pipeline.succeeded() { "good" } else { "danger" });
+    if let Some(ref mr) = hook.merge_request {
+        attachment.text(format!("Merge request {}", util::make_link(&mr.url, &format!("!{}: {}", mr.iid, mr.title))));
+    }
+    let msg = format!("Pipeline {}", pipeline.status);
+    let commits = Vec::<github::Commit>::new();
+
+    if pipeline.succeeded() {
+        messenger.send_to_channel(&msg, &vec![attachment.build()], &repo, &pipeline.ref_name, &commits);
+    } else {
+        // the person who pushed (or ran it) wants to know when it didn't work out
+        let owner = hook.user.to_user();
+        messenger.send_to_owner(&msg, &vec![attachment.build()], &owner, &repo, &pipeline.ref_name, &commits);
+    }
+}
+
+// Hooks don't include the merge request's author (only its id), so it comes from the API
+fn fetch_merge_request(project: &Project, iid: u32, session: &dyn Session) -> Option<MergeRequest> {
+    match session.get_merge_request(project.id, iid) {
+        Ok(mr) => Some(mr),
+        Err(e) => {
+            error!("Error getting GitLab merge request {}!{}: {}", project.path_with_namespace, iid, e);
+            None
+        }
+    }
+}
+
+// A GitLab merge request, for the checks shared with other hosts
+struct GitlabMergeRequest<'a> {
+    session: &'a dyn Session,
+    project_id: u64,
+    iid: u32,
+    sha: String,
+}
+
+impl<'a> GitlabMergeRequest<'a> {
+    fn new(project: &Project, mr: &MergeRequest, session: &'a dyn Session) -> GitlabMergeRequest<'a> {
+        GitlabMergeRequest {
+            session: session,
+            project_id: project.id,
+            iid: mr.iid,
+            sha: mr.head_sha(),
+        }
+    }
+}
+
+impl<'a> PullRequestHost for GitlabMergeRequest<'a> {
+    fn commits(&self) -> Result<Vec<github::Commit>> {
+        let commits = self.session.get_merge_request_commits(self.project_id, self.iid)?;
+        Ok(commits.iter().map(|c| c.to_commit()).collect())
+    }
+
+    fn comment(&self, body: &str) -> Result<()> {
+        self.session.comment_merge_request(self.project_id, self.iid, body)
+    }
+
+    // statuses go on the head commit, where they show up in the merge request's pipeline widget
+    fn set_status(&self, context: &str, status: vcs::Status, description: &str) -> Result<()> {
+        let state = match status {
+            vcs::Status::Success => "success",
+            vcs::Status::Failure => "failed",
+        };
+        self.session.create_commit_status(self.project_id, &self.sha, &CommitStatus::new(context, state, description))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = r#"{
+        "id": 42,
+        "name": "web-app",
+        "path_with_namespace": "platform/frontend/web-app",
+        "web_url": "https://gitlab.company.com/platform/frontend/web-app"
+    }"#;
+
+    #[test]
+    fn test_parse_merge_request() {
+        let body = format!(
+            r#"{{
+            "object_kind": "merge_request",
+            "event_type": "merge_request",
+            "user": {{ "id": 1, "name": "Joe", "username": "joe" }},
+            "project": {},
+            "object_attributes": {{
+                "id": 99,
+                "iid": 7,
+                "title": "Draft: Fix the thing",
+                "description": "It was broken",
+                "state": "opened",
+                "action": "open",
+                "source_branch": "fix-thing",
+                "target_branch": "main",
+                "url": "https://gitlab.company.com/platform/frontend/web-app/-/merge_requests/7",
+                "draft": true,
+                "last_commit": {{ "id": "abcdef0123", "message": "fix: the thing", "url": "" }}
+            }}
+        }}"#,
+            PROJECT
+        );
+        let hook = match parse(body.as_bytes()).unwrap() {
+            Event::MergeRequest(h) => h,
+            e => panic!("Unexpected event: {:?}", e),
+        };
+
+        assert_eq!("joe", hook.user.to_user().login());
+        assert_eq!(Some("open".to_string()), hook.object_attributes.action);
+        assert_eq!("abcdef0123", hook.object_attributes.head_sha());
+
+        let repo = hook.project.to_repo();
+        assert_eq!("platform/frontend/web-app", repo.full_name);
+        assert_eq!("platform/frontend", repo.owner.login());
+        assert_eq!("web-app", repo.name);
+
+        let pr = hook.object_attributes.to_pull_request(&hook.project);
+        assert_eq!(7, pr.number);
+        assert_eq!("main", pr.base.ref_name);
+        assert_eq!("fix-thing", pr.head.ref_name);
+        assert_eq!("platform/frontend/web-app", pr.base.repo.full_name);
+        assert!(pr.is_draft());
+        assert!(!pr.is_merged());
+    }
+
+    #[test]
+    fn test_merge_request_from_api() {
+        let body = r#"{
+            "iid": 7,
+            "title": "Fix the thing",
+            "description": null,
+            "state": "merged",
+            "source_branch": "fix-thing",
+            "target_branch": "main",
+            "web_url": "https://gitlab.company.com/platform/frontend/web-app/-/merge_requests/7",
+            "sha": "abcdef0123",
+            "author": { "id": 1, "name": "Joe", "username": "joe" },
+            "reviewers": [{ "id": 2, "name": "Mary", "username": "mary" }],
+            "assignees": []
+        }"#;
+        let mr: MergeRequest = serde_json::from_str(body).unwrap();
+        let project: Project = serde_json::from_str(PROJECT).unwrap();
+        let pr = mr.to_pull_request(&project);
+
+        assert_eq!("joe", pr.user.login());
+        assert_eq!(vec![github::User::new("mary")], pr.all_reviewers());
+        assert_eq!("abcdef0123", pr.head.sha);
+        assert_eq!("https://gitlab.company.com/platform/frontend/web-app/-/merge_requests/7", pr.html_url);
+        assert!(pr.is_merged());
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let body = format!(
+            r#"{{
+            "object_kind": "pipeline",
+            "user": {{ "id": 1, "name": "Joe", "username": "joe" }},
+            "project": {},
+            "object_attributes": {{ "id": 31, "ref": "main", "tag": false, "sha": "abcdef0123", "status": "failed" }},
+            "merge_request": null
+        }}"#,
+            PROJECT
+        );
+        let hook = match parse(body.as_bytes()).unwrap() {
+            Event::Pipeline(h) => h,
+            e => panic!("Unexpected event: {:?}", e),
+        };
+
+        assert!(hook.object_attributes.is_finished());
+        assert!(!hook.object_attributes.succeeded());
+        assert_eq!(
+            "https://gitlab.company.com/platform/frontend/web-app/-/pipelines/31",
+            hook.object_attributes.html_url(&hook.project)
+        );
+    }
+
+    #[test]
+    fn test_parse_other() {
+        assert_eq!(
+            Event::Other("push".into()),
+            parse(br#"{"object_kind": "push", "ref": "refs/heads/main"}"#).unwrap()
+        );
+        assert!(parse(br#"{"object_kind": "merge_request", "object_attributes": {}}"#).is_err());
+        assert!(parse(br#"{"ref": "refs/heads/main"}"#).is_err());
+    }
+}
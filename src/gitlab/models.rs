@@ -0,0 +1,225 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::github;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct User {
+    #[serde(default)]
+    pub name: String,
+    pub username: String,
+}
+
+impl User {
+    // GitLab users go through the same user mappings as github users, by their username
+    pub fn to_user(&self) -> github::User {
+        github::User::new(&self.username)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Project {
+    pub id: u64,
+    // e.g. "group/subgroup/project"
+    pub path_with_namespace: String,
+    pub web_url: String,
+}
+
+impl Project {
+    // The project as octobot's repo config knows it: by its path, with its group as the owner
+    pub fn to_repo(&self) -> github::Repo {
+        let mut parts = self.path_with_namespace.rsplitn(2, '/');
+        let name = parts.next().unwrap_or("").to_string();
+        let namespace = parts.next().unwrap_or("");
+        github::Repo {
+            html_url: self.web_url.clone(),
+            full_name: self.path_with_namespace.clone(),
+            name: name,
+            owner: github::User::new(namespace),
+            archived: Some(false),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Commit {
+    pub id: String,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+impl Commit {
+    // As a github commit, for the checks shared with github
+    pub fn to_commit(&self) -> github::Commit {
+        let mut commit = github::Commit::new();
+        commit.sha = self.id.clone();
+        commit.html_url = self.url.clone();
+        commit.commit.message = self.message.clone();
+        commit
+    }
+}
+
+// The merge request of a merge request hook, or as the API returns it. Hooks don't include its author, so handling
+// them goes by the API's.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct MergeRequest {
+    pub iid: u32,
+    pub title: String,
+    pub description: Option<String>,
+    // "opened", "closed", "locked" or "merged"
+    pub state: String,
+    pub source_branch: String,
+    pub target_branch: String,
+    // "url" in hooks, "web_url" from the API
+    #[serde(alias = "web_url")]
+    pub url: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub work_in_progress: bool,
+    // only in hooks: "open", "close", "reopen", "update", "merge", "approved", "unapproved", "approval" or "unapproval"
+    pub action: Option<String>,
+    // only in hooks
+    pub last_commit: Option<Commit>,
+    // only from the API
+    pub author: Option<User>,
+    #[serde(default)]
+    pub reviewers: Vec<User>,
+    #[serde(default)]
+    pub assignees: Vec<User>,
+    // only from the API
+    pub sha: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct MergeRequestHook {
+    // who made the change
+    pub user: User,
+    pub project: Project,
+    pub object_attributes: MergeRequest,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Note {
+    pub note: String,
+    // "MergeRequest", "Commit", "Issue" or "Snippet"
+    pub noteable_type: String,
+    pub url: String,
+}
+
+// Only the merge request's iid is used from note hooks: the merge request is fetched for its author
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct NoteMergeRequest {
+    pub iid: u32,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct NoteHook {
+    pub user: User,
+    pub project: Project,
+    pub object_attributes: Note,
+    pub merge_request: Option<NoteMergeRequest>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Pipeline {
+    pub id: u64,
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+    #[serde(default)]
+    pub tag: bool,
+    pub sha: String,
+    // "success", "failed", "canceled", "skipped", "running", "pending", ...
+    pub status: String,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct PipelineMergeRequest {
+    pub iid: u32,
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct PipelineHook {
+    // who triggered the pipeline
+    pub user: User,
+    pub project: Project,
+    pub object_attributes: Pipeline,
+    pub merge_request: Option<PipelineMergeRequest>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Approvals {
+    #[serde(default)]
+    pub approvals_left: u32,
+}
+
+// A status shown on a commit, like a github commit status
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CommitStatus {
+    // "success", "failed" or "pending"
+    pub state: String,
+    pub name: String,
+    pub description: String,
+}
+
+impl MergeRequest {
+    pub fn is_draft(&self) -> bool {
+        self.draft || self.work_in_progress
+    }
+
+    // As a github PR, for what is shared with github: messages, lint and JIRA
+    pub fn to_pull_request(&self, project: &Project) -> github::PullRequest {
+        let repo = project.to_repo();
+        let mut pr = github::PullRequest::new();
+        pr.number = self.iid;
+        pr.title = self.title.clone();
+        pr.body = self.description.clone();
+        pr.html_url = self.url.clone();
+        pr.state = if self.state == "opened" { "open".into() } else { "closed".into() };
+        pr.merged = Some(self.state == "merged");
+        pr.draft = Some(self.is_draft());
+        pr.user = self.author.as_ref().map(|a| a.to_user()).unwrap_or(github::User::new(""));
+        pr.assignees = self.assignees.iter().map(|u| u.to_user()).collect();
+        pr.requested_reviewers = Some(self.reviewers.iter().map(|u| u.to_user()).collect());
+        pr.head = github::BranchRef::new(&self.source_branch);
+        pr.head.sha = self.head_sha();
+        pr.head.repo = repo.clone();
+        pr.base = github::BranchRef::new(&self.target_branch);
+        pr.base.repo = repo;
+        pr
+    }
+
+    pub fn head_sha(&self) -> String {
+        match self.last_commit {
+            Some(ref c) => c.id.clone(),
+            None => self.sha.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn is_finished(&self) -> bool {
+        self.status == "success" || self.status == "failed" || self.status == "canceled"
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.status == "success"
+    }
+
+    pub fn html_url(&self, project: &Project) -> String {
+        format!("{}/-/pipelines/{}", project.web_url.trim_end_matches('/'), self.id)
+    }
+}
+
+impl CommitStatus {
+    pub fn new(name: &str, state: &str, description: &str) -> CommitStatus {
+        CommitStatus {
+            state: state.into(),
+            name: name.into(),
+            description: description.into(),
+        }
+    }
+}
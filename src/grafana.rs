@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use reqwest;
+use serde_derive::Serialize;
+
+use crate::config::{Config, GrafanaConfig, GrafanaDashboard};
+use crate::db;
+use crate::errors::*;
+use crate::github;
+use crate::http_client::HTTPClient;
+use crate::worker;
+
+pub const MERGE: &str = "merge";
+pub const RELEASE: &str = "release";
+pub const DEPLOY: &str = "deploy";
+
+// Lets dashboards filter for octobot's annotations
+const TAG: &str = "octobot";
+
+pub fn is_event(event: &str) -> bool {
+    [MERGE, RELEASE, DEPLOY].contains(&event)
+}
+
+// A change to annotate on the dashboards of its repo
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnnotationRequest {
+    pub event: String,
+    // "owner/name"
+    pub repo: String,
+    pub text: String,
+    // besides "octobot", the event and the repo
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Annotation {
+    #[serde(rename = "dashboardUID")]
+    pub dashboard_uid: String,
+    #[serde(rename = "panelId", skip_serializing_if = "Option::is_none")]
+    pub panel_id: Option<u32>,
+    // milliseconds since the epoch
+    pub time: i64,
+    pub tags: Vec<String>,
+    pub text: String,
+}
+
+pub trait Session: Send + Sync {
+    fn create_annotation(&self, annotation: &Annotation) -> Result<()>;
+}
+
+pub struct GrafanaSession {
+    client: HTTPClient,
+}
+
+impl GrafanaSession {
+    pub fn new(config: &GrafanaConfig) -> Result<GrafanaSession> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::ACCEPT, "application/json".parse().unwrap());
+        headers.insert(reqwest::header::AUTHORIZATION, format!("Bearer {}", config.api_key).parse()?);
+
+        Ok(GrafanaSession {
+            client: HTTPClient::new_with_headers(&format!("{}/api", config.url.trim_end_matches('/')), headers)?,
+        })
+    }
+}
+
+impl Session for GrafanaSession {
+    fn create_annotation(&self, annotation: &Annotation) -> Result<()> {
+        self.client
+            .post_void("/annotations", annotation)
+            .map_err(|e| format_err!("Error annotating grafana dashboard {}: {}", annotation.dashboard_uid, e))
+    }
+}
+
+pub fn merged(repo: &github::Repo, pull_request: &github::PullRequest, sender: &github::User) -> AnnotationRequest {
+    AnnotationRequest {
+        event: MERGE.into(),
+        repo: repo.full_name.clone(),
+        text: format!(
+            "Merged <a href=\"{}\">#{}: {}</a> into {} ({})",
+            pull_request.html_url,
+            pull_request.number,
+            pull_request.title,
+            pull_request.base.ref_name,
+            sender.login()
+        ),
+        tags: vec![pull_request.base.ref_name.clone()],
+    }
+}
+
+pub fn released(repo: &github::Repo, release: &github::Release) -> AnnotationRequest {
+    AnnotationRequest {
+        event: RELEASE.into(),
+        repo: repo.full_name.clone(),
+        text: format!("Released <a href=\"{}\">{}</a>", release.html_url, release.tag_name),
+        tags: vec![release.tag_name.clone()],
+    }
+}
+
+pub fn deployed(
+    repo: &github::Repo,
+    deployment: &github::Deployment,
+    status: &github::DeploymentStatus,
+) -> AnnotationRequest {
+    let short_sha = deployment.sha.chars().take(7).collect::<String>();
+    let mut text = format!("Deployed {} ({}) to {}", deployment.ref_name, short_sha, deployment.environment);
+    if let Some(url) = status.target_url.as_ref().filter(|u| !u.is_empty()) {
+        text = format!("<a href=\"{}\">{}</a>", url, text);
+    }
+    AnnotationRequest {
+        event: DEPLOY.into(),
+        repo: repo.full_name.clone(),
+        text: text,
+        tags: vec![deployment.environment.clone()],
+    }
+}
+
+pub fn annotation(dashboard: &GrafanaDashboard, req: &AnnotationRequest, now: i64) -> Annotation {
+    let mut tags = vec![TAG.to_string(), req.event.clone(), req.repo.clone()];
+    tags.extend(req.tags.iter().cloned());
+
+    Annotation {
+        dashboard_uid: dashboard.dashboard_uid.clone(),
+        panel_id: dashboard.panel_id,
+        time: now * 1000,
+        tags: tags,
+        text: req.text.clone(),
+    }
+}
+
+// Annotates each dashboard of the request's repo. Returns how many were annotated.
+pub fn publish(session: &dyn Session, config: &Config, req: &AnnotationRequest, now: i64) -> usize {
+    let mut count = 0;
+    for dashboard in config.grafana_dashboards(&req.repo, &req.event) {
+        match session.create_annotation(&annotation(&dashboard, req, now)) {
+            Ok(()) => count += 1,
+            Err(e) => error!("{}", e),
+        };
+    }
+    count
+}
+
+struct Runner {
+    config: Arc<Config>,
+    session: Option<Arc<dyn Session>>,
+}
+
+pub fn new_runner(config: Arc<Config>) -> Result<Arc<dyn worker::Runner<AnnotationRequest>>> {
+    let session = match config.grafana {
+        Some(ref grafana) => Some(Arc::new(GrafanaSession::new(grafana)?) as Arc<dyn Session>),
+        None => None,
+    };
+    Ok(Arc::new(Runner {
+        config: config,
+        session: session,
+    }))
+}
+
+impl worker::Runner<AnnotationRequest> for Runner {
+    fn handle(&self, req: AnnotationRequest) {
+        if let Some(ref session) = self.session {
+            let count = publish(session.as_ref(), &self.config, &req, db::now());
+            info!("Annotated {} {} on {} grafana dashboard(s)", req.repo, req.event, count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use tempdir::TempDir;
+
+    use crate::db::Database;
+
+    struct FakeGrafana {
+        annotations: Mutex<Vec<Annotation>>,
+    }
+
+    impl Session for FakeGrafana {
+        fn create_annotation(&self, annotation: &Annotation) -> Result<()> {
+            if annotation.dashboard_uid == "broken" {
+                return Err(format_err!("500 Internal Server Error"));
+            }
+            self.annotations.lock().unwrap().push(annotation.clone());
+            Ok(())
+        }
+    }
+
+    fn dashboard(repo: &str, dashboard_uid: &str, events: Option<Vec<&str>>) -> GrafanaDashboard {
+        GrafanaDashboard {
+            repo: repo.into(),
+            dashboard_uid: dashboard_uid.into(),
+            panel_id: None,
+            events: events.map(|e| e.into_iter().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    fn new_test() -> (Config, TempDir) {
+        let temp_dir = TempDir::new("grafana.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        let mut config = Config::new(db);
+        config.grafana = Some(GrafanaConfig {
+            url: "https://grafana.company.com".into(),
+            api_key: "the-key".into(),
+            dashboards: vec![
+                dashboard("some-org/some-repo", "service", None),
+                dashboard("some-org", "org", Some(vec![RELEASE, DEPLOY])),
+                dashboard("other-org/other-repo", "other", None),
+                dashboard("some-org/some-repo", "broken", Some(vec![DEPLOY])),
+            ],
+        });
+        (config, temp_dir)
+    }
+
+    #[test]
+    fn test_grafana_dashboards() {
+        let (config, _temp_dir) = new_test();
+
+        let uids = |repo: &str, event: &str| {
+            config.grafana_dashboards(repo, event).into_iter().map(|d| d.dashboard_uid).collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["service"], uids("some-org/some-repo", MERGE));
+        assert_eq!(vec!["service", "org"], uids("some-org/some-repo", RELEASE));
+        assert_eq!(vec!["org"], uids("some-org/another-repo", RELEASE));
+        assert!(uids("another-org/some-repo", MERGE).is_empty());
+    }
+
+    #[test]
+    fn test_publish() {
+        let (config, _temp_dir) = new_test();
+        let session = FakeGrafana {
+            annotations: Mutex::new(vec![]),
+        };
+        let repo = github::Repo::parse("https://the-github-host/some-org/some-repo").unwrap();
+        let mut deployment = github::Deployment::new("master", "0123456789abcdef", "production");
+        deployment.description = Some("ignored".into());
+        let status = github::DeploymentStatus {
+            state: "success".into(),
+            target_url: Some("https://ci.company.com/deploys/1".into()),
+            description: None,
+        };
+
+        // the broken dashboard's error doesn't stop the others
+        let req = deployed(&repo, &deployment, &status);
+        assert_eq!(2, publish(&session, &config, &req, 1000));
+
+        let annotations = session.annotations.lock().unwrap();
+        assert_eq!(
+            vec![
+                Annotation {
+                    dashboard_uid: "service".into(),
+                    panel_id: None,
+                    time: 1000000,
+                    tags: vec!["octobot".into(), "deploy".into(), "some-org/some-repo".into(), "production".into()],
+                    text: "<a href=\"https://ci.company.com/deploys/1\">Deployed master (0123456) to production</a>"
+                        .into(),
+                },
+                Annotation {
+                    dashboard_uid: "org".into(),
+                    panel_id: None,
+                    time: 1000000,
+                    tags: vec!["octobot".into(), "deploy".into(), "some-org/some-repo".into(), "production".into()],
+                    text: "<a href=\"https://ci.company.com/deploys/1\">Deployed master (0123456) to production</a>"
+                        .into(),
+                },
+            ],
+            *annotations
+        );
+    }
+
+    #[test]
+    fn test_annotation_json() {
+        let mut dashboard = dashboard("some-org/some-repo", "service", None);
+        dashboard.panel_id = Some(4);
+        let repo = github::Repo::parse("https://the-github-host/some-org/some-repo").unwrap();
+        let mut release = github::Release::new("v1.2.0");
+        release.html_url = "https://the-github-host/some-org/some-repo/releases/v1.2.0".into();
+
+        let json = serde_json::to_value(&annotation(&dashboard, &released(&repo, &release), 1000)).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "dashboardUID": "service",
+                "panelId": 4,
+                "time": 1000000,
+                "tags": ["octobot", "release", "some-org/some-repo", "v1.2.0"],
+                "text": "Released <a href=\"https://the-github-host/some-org/some-repo/releases/v1.2.0\">v1.2.0</a>",
+            }),
+            json
+        );
+    }
+}
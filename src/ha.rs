@@ -0,0 +1,341 @@
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use failure::format_err;
+use log::{error, info};
+use serde_json;
+
+use crate::config::{Config, HaConfig};
+use crate::db;
+use crate::errors::*;
+use crate::inbound_queue::{Consumer, Envelope, Message};
+use crate::kubernetes;
+use crate::redis::{self, Value};
+use crate::scheduler::{Leadership, Task};
+
+// Takes the lease if it is free, or extends it if this instance holds it
+const RENEW_SCRIPT: &str = r#"
+if redis.call('get', KEYS[1]) == ARGV[1] then
+    return redis.call('pexpire', KEYS[1], ARGV[2])
+end
+if redis.call('set', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+    return 1
+end
+return 0
+"#;
+
+const RELEASE_SCRIPT: &str = r#"
+if redis.call('get', KEYS[1]) == ARGV[1] then
+    return redis.call('del', KEYS[1])
+end
+return 0
+"#;
+
+// Puts a delivery taken by this instance back at the front of the queue
+const RETRY_SCRIPT: &str = r#"
+if redis.call('lrem', KEYS[1], -1, ARGV[1]) > 0 then
+    return redis.call('rpush', KEYS[2], ARGV[1])
+end
+return 0
+"#;
+
+// Puts all the deliveries an instance had taken back at the front of the queue, oldest first
+const REQUEUE_SCRIPT: &str = r#"
+local count = 0
+local value = redis.call('lpop', KEYS[1])
+while value do
+    redis.call('rpush', KEYS[2], value)
+    count = count + 1
+    value = redis.call('lpop', KEYS[1])
+end
+return count
+"#;
+
+// Redis keys, all under the configured prefix
+pub struct Keys {
+    prefix: String,
+}
+
+impl Keys {
+    pub fn new(prefix: &str) -> Keys {
+        Keys { prefix: prefix.to_string() }
+    }
+
+    pub fn leader(&self) -> String {
+        format!("{}:leader", self.prefix)
+    }
+
+    pub fn delivery(&self, id: &str) -> String {
+        format!("{}:delivery:{}", self.prefix, id)
+    }
+
+    pub fn queue(&self) -> String {
+        format!("{}:deliveries", self.prefix)
+    }
+
+    // the deliveries an instance took from the queue and hasn't handled yet
+    pub fn processing(&self, identity: &str) -> String {
+        format!("{}:processing:{}", self.prefix, identity)
+    }
+
+    pub fn instances(&self) -> String {
+        format!("{}:instances", self.prefix)
+    }
+
+    // expires unless the instance keeps it alive
+    pub fn heartbeat(&self, identity: &str) -> String {
+        format!("{}:instance:{}", self.prefix, identity)
+    }
+}
+
+// Defaults to the pod or host name, with the process id so that a restarted instance doesn't take the old one's
+// place before its deliveries are requeued
+pub fn identity(config: &HaConfig) -> Result<String> {
+    if let Some(ref id) = config.instance_id {
+        return Ok(id.clone());
+    }
+    let host = env::var("POD_NAME")
+        .or_else(|_| env::var("HOSTNAME"))
+        .map_err(|_| format_err!("Neither POD_NAME nor HOSTNAME is set (set ha.instance_id)"))?;
+    Ok(format!("{}-{}", host, std::process::id()))
+}
+
+// Several instances sharing a redis: github deliveries are only handled once whichever instance receives them,
+// through a shared queue that any instance consumes, and one instance, holding the lease, runs the scheduled tasks
+pub struct Cluster {
+    client: redis::Client,
+    keys: Keys,
+    identity: String,
+    lease_secs: i64,
+    dedupe_secs: u64,
+    // until when the lease is ours, if it is
+    held_until: Mutex<i64>,
+}
+
+impl Cluster {
+    pub fn new(config: &Config) -> Result<Option<Cluster>> {
+        let ha = match config.ha {
+            Some(ref ha) => ha,
+            None => return Ok(None),
+        };
+        Ok(Some(Cluster {
+            client: redis::Client::new(&ha.redis_url)?,
+            keys: Keys::new(&config.ha_key_prefix()),
+            identity: identity(ha)?,
+            lease_secs: config.ha_lease_duration_secs() as i64,
+            dedupe_secs: config.ha_dedupe_secs(),
+            held_until: Mutex::new(0),
+        }))
+    }
+
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    fn eval(&self, script: &str, keys: &[&str], args: &[&str]) -> Result<Value> {
+        let num_keys = keys.len().to_string();
+        let mut cmd = vec!["EVAL", script, num_keys.as_str()];
+        cmd.extend(keys);
+        cmd.extend(args);
+        self.client.cmd(&cmd)
+    }
+
+    // Renews the lease if this instance holds it, or takes it if it is free. Returns whether this instance leads.
+    pub fn renew(&self, now: i64) -> Result<bool> {
+        let millis = (self.lease_secs * 1000).to_string();
+        let leads = self.eval(RENEW_SCRIPT, &[&self.keys.leader()], &[&self.identity, &millis])?.as_int() == 1;
+
+        let mut held_until = self.held_until.lock().unwrap();
+        if leads && *held_until <= now {
+            info!("Became the leader ({} holds {})", self.identity, self.keys.leader());
+        } else if !leads && *held_until > now {
+            info!("No longer the leader: {} was taken", self.keys.leader());
+        }
+        *held_until = if leads { now + self.lease_secs } else { 0 };
+        Ok(leads)
+    }
+
+    // Gives up the lease, so that another instance can take over without waiting for it to expire
+    pub fn release(&self) -> Result<()> {
+        if !self.is_leader() {
+            return Ok(());
+        }
+        *self.held_until.lock().unwrap() = 0;
+        self.eval(RELEASE_SCRIPT, &[&self.keys.leader()], &[&self.identity])?;
+        info!("Released {}", self.keys.leader());
+        Ok(())
+    }
+
+    // Whether no instance saw the delivery before. Redelivered or replayed deliveries keep their id.
+    pub fn claim_delivery(&self, id: &str) -> Result<bool> {
+        let ttl = self.dedupe_secs.to_string();
+        let claimed = self.client.cmd(&["SET", &self.keys.delivery(id), &self.identity, "NX", "EX", &ttl])?;
+        Ok(!claimed.is_nil())
+    }
+
+    // For a delivery that couldn't be queued after all: github can deliver it again
+    pub fn forget_delivery(&self, id: &str) -> Result<()> {
+        self.client.cmd(&["DEL", &self.keys.delivery(id)])?;
+        Ok(())
+    }
+
+    pub fn push(&self, envelope: &Envelope) -> Result<()> {
+        let data = serde_json::to_string(envelope)?;
+        self.client.cmd(&["LPUSH", &self.keys.queue(), &data])?;
+        Ok(())
+    }
+
+    pub fn queue_len(&self) -> Result<i64> {
+        Ok(self.client.cmd(&["LLEN", &self.keys.queue()])?.as_int())
+    }
+
+    // Tells the others this instance is alive, so that they leave the deliveries it took alone
+    pub fn heartbeat(&self, now: i64) -> Result<()> {
+        let ttl = (self.lease_secs * 2).to_string();
+        self.client.cmd(&["SET", &self.keys.heartbeat(&self.identity), &now.to_string(), "EX", &ttl])?;
+        self.client.cmd(&["SADD", &self.keys.instances(), &self.identity])?;
+        Ok(())
+    }
+
+    // Puts the deliveries taken by instances that stopped without handling them back in the queue. Returns how
+    // many there were.
+    pub fn requeue_abandoned(&self) -> Result<i64> {
+        let mut count = 0;
+        for instance in self.client.cmd(&["SMEMBERS", &self.keys.instances()])?.into_strings() {
+            if instance == self.identity {
+                continue;
+            }
+            if self.client.cmd(&["EXISTS", &self.keys.heartbeat(&instance)])?.as_int() > 0 {
+                continue;
+            }
+
+            let requeued = self
+                .eval(REQUEUE_SCRIPT, &[&self.keys.processing(&instance), &self.keys.queue()], &[])?
+                .as_int();
+            if requeued > 0 {
+                info!("Requeued {} deliveries abandoned by {}", requeued, instance);
+            }
+            count += requeued;
+            self.client.cmd(&["SREM", &self.keys.instances(), &instance])?;
+        }
+        Ok(count)
+    }
+}
+
+impl Leadership for Cluster {
+    // Only while the lease is ours: if renewing fails for long enough, another instance may have taken it
+    fn is_leader(&self) -> bool {
+        *self.held_until.lock().unwrap() > db::now()
+    }
+}
+
+// Deliveries are taken one at a time, so that one to retry doesn't hold back others taken with it
+impl Consumer for Cluster {
+    fn receive(&self) -> Result<Vec<Message>> {
+        let processing = self.keys.processing(&self.identity);
+        let data = match self.client.cmd(&["RPOPLPUSH", &self.keys.queue(), &processing])?.into_string() {
+            Some(d) => d,
+            None => return Ok(vec![]),
+        };
+        let id = Envelope::parse(data.as_bytes())
+            .ok()
+            .and_then(|e| e.headers.into_iter().find(|(k, _)| k.eq_ignore_ascii_case("x-github-delivery")))
+            .map(|(_, v)| v)
+            .unwrap_or_default();
+
+        Ok(vec![Message {
+            id: id,
+            data: data.clone().into_bytes(),
+            receipt: data,
+        }])
+    }
+
+    fn ack(&self, message: &Message) -> Result<()> {
+        self.client.cmd(&["LREM", &self.keys.processing(&self.identity), "-1", &message.receipt])?;
+        Ok(())
+    }
+
+    fn retry(&self, message: &Message) -> Result<()> {
+        let keys = [self.keys.processing(&self.identity), self.keys.queue()];
+        self.eval(RETRY_SCRIPT, &[&keys[0], &keys[1]], &[&message.receipt])?;
+        Ok(())
+    }
+}
+
+// Keeps the lease and the heartbeat up, has the leader requeue what stopped instances left behind, and keeps
+// consuming the shared queue, until this instance is draining
+pub fn start(cluster: Arc<Cluster>, consumer: Arc<dyn Task>) {
+    // deliveries this instance took before it restarted under the same identity
+    let own = cluster.eval(
+        REQUEUE_SCRIPT,
+        &[&cluster.keys.processing(&cluster.identity), &cluster.keys.queue()],
+        &[],
+    );
+    match own {
+        Ok(count) if count.as_int() > 0 => info!("Requeued {} deliveries taken before restarting", count.as_int()),
+        Ok(_) => (),
+        Err(e) => error!("Error requeueing deliveries taken before restarting: {}", e),
+    };
+
+    let renewer = cluster.clone();
+    thread::spawn(move || {
+        let interval = Duration::from_secs(std::cmp::max(1, renewer.lease_secs / 3) as u64);
+        while !kubernetes::is_draining() {
+            let now = db::now();
+            if let Err(e) = renewer.heartbeat(now) {
+                error!("Error sending heartbeat: {}", e);
+            }
+            match renewer.renew(now) {
+                Ok(true) => {
+                    if let Err(e) = renewer.requeue_abandoned() {
+                        error!("Error requeueing abandoned deliveries: {}", e);
+                    }
+                }
+                Ok(false) => (),
+                Err(e) => error!("Error renewing {}: {}", renewer.keys.leader(), e),
+            }
+            thread::sleep(interval);
+        }
+        if let Err(e) = renewer.release() {
+            error!("Error releasing {}: {}", renewer.keys.leader(), e);
+        }
+    });
+
+    thread::spawn(move || {
+        while !kubernetes::is_draining() {
+            if let Err(e) = consumer.run(db::now()) {
+                error!("Error consuming the shared queue: {}", e);
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let keys = Keys::new("octobot");
+        assert_eq!("octobot:leader", keys.leader());
+        assert_eq!("octobot:delivery:abc-123", keys.delivery("abc-123"));
+        assert_eq!("octobot:deliveries", keys.queue());
+        assert_eq!("octobot:processing:octobot-0-42", keys.processing("octobot-0-42"));
+        assert_eq!("octobot:instance:octobot-0-42", keys.heartbeat("octobot-0-42"));
+    }
+
+    #[test]
+    fn test_identity() {
+        let config = HaConfig {
+            redis_url: "redis://localhost".into(),
+            key_prefix: None,
+            instance_id: Some("octobot-a".into()),
+            lease_duration_secs: None,
+            dedupe_secs: None,
+        };
+        assert_eq!("octobot-a", identity(&config).unwrap());
+    }
+}
@@ -1,31 +1,46 @@
+use std::sync::Arc;
+
 use failure::format_err;
 use reqwest;
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 
 use crate::errors::*;
+use crate::faults;
+use crate::network;
+use crate::resilience;
 
 pub use reqwest::header::HeaderMap;
 
+// Supplies the Authorization header for each request, for credentials that change over time (e.g. OAuth tokens)
+pub trait Authorizer: Send + Sync {
+    fn authorization(&self) -> Result<String>;
+}
+
 pub struct HTTPClient {
     pub api_base: String,
     pub client: reqwest::Client,
+    // whose timeouts, retries, circuit breaker and injected faults apply
+    service: Option<faults::Service>,
+    authorizer: Option<Arc<dyn Authorizer>>,
 }
 
 impl HTTPClient {
     pub fn new(api_base: &str) -> Result<HTTPClient> {
-        let client = reqwest::Client::builder()
+        let client = network::client_builder()
             .redirect(reqwest::RedirectPolicy::none())
             .build()?;
 
         Ok(HTTPClient {
             api_base: api_base.into(),
             client: client,
+            service: None,
+            authorizer: None,
         })
     }
 
     pub fn new_with_headers(api_base: &str, headers: HeaderMap) -> Result<HTTPClient> {
-        let client = reqwest::Client::builder()
+        let client = network::client_builder()
             .redirect(reqwest::RedirectPolicy::none())
             .default_headers(headers)
             .build()?;
@@ -33,9 +48,39 @@ impl HTTPClient {
         Ok(HTTPClient {
             api_base: api_base.into(),
             client: client,
+            service: None,
+            authorizer: None,
         })
     }
 
+    // A client of GitHub, Slack or JIRA, with the service's timeouts and retries
+    pub fn new_for_service(api_base: &str, headers: HeaderMap, service: faults::Service) -> Result<HTTPClient> {
+        let client = resilience::client_builder(service)
+            .redirect(reqwest::RedirectPolicy::none())
+            .default_headers(headers)
+            .build()?;
+
+        Ok(HTTPClient {
+            api_base: api_base.into(),
+            client: client,
+            service: Some(service),
+            authorizer: None,
+        })
+    }
+
+    pub fn with_authorizer(mut self, authorizer: Arc<dyn Authorizer>) -> HTTPClient {
+        self.authorizer = Some(authorizer);
+        self
+    }
+
+    // Has the authorizer refresh its credentials if they are about to expire
+    pub fn refresh_authorization(&self) -> Result<()> {
+        if let Some(ref authorizer) = self.authorizer {
+            authorizer.authorization()?;
+        }
+        Ok(())
+    }
+
     fn make_url(&self, path: &str) -> String {
         if path.is_empty() {
             self.api_base.clone()
@@ -48,70 +93,106 @@ impl HTTPClient {
         }
     }
 
+    // Makes the request with `send`, which may be called again when the service's policy retries it
+    fn request<T, F>(&self, method: reqwest::Method, path: &str, send: F) -> Result<T>
+    where
+        F: Fn(reqwest::RequestBuilder) -> reqwest::Result<T>,
+    {
+        let url = self.make_url(path);
+        let authorization = match self.authorizer {
+            Some(ref authorizer) => Some(authorizer.authorization()?),
+            None => None,
+        };
+        let attempt = || {
+            let req = self.client.request(method.clone(), &url);
+            match authorization {
+                Some(ref a) => send(req.header(reqwest::header::AUTHORIZATION, a.as_str())),
+                None => send(req),
+            }
+        };
+
+        match self.service {
+            Some(service) => {
+                let idempotent = method != reqwest::Method::POST && method != reqwest::Method::PATCH;
+                resilience::run(service, idempotent, attempt)
+            }
+            None => attempt().map_err(|e| format_err!("{}", e)),
+        }
+    }
+
     pub fn get<T>(&self, path: &str) -> Result<T>
     where
         T: DeserializeOwned + Send + 'static,
     {
-        self.client
-            .get(&self.make_url(path))
-            .send()
-            .and_then(|r| r.error_for_status())
-            .and_then(|mut r| r.json::<T>())
-            .map_err(|e| format_err!("{}", e))
+        self.request(reqwest::Method::GET, path, |req| {
+            req.send().and_then(|r| r.error_for_status()).and_then(|mut r| r.json::<T>())
+        })
+    }
+
+    // Like `get`, for plain text. A redirect (e.g. to a signed download URL) is followed without our credentials.
+    pub fn get_text(&self, path: &str) -> Result<String> {
+        let resp = self.request(reqwest::Method::GET, path, |req| {
+            req.send().and_then(|r| r.error_for_status()).and_then(|mut r| {
+                if r.status().is_redirection() {
+                    let location = r.headers().get(reqwest::header::LOCATION).and_then(|l| l.to_str().ok());
+                    Ok(Err(location.unwrap_or("").to_string()))
+                } else {
+                    r.text().map(Ok)
+                }
+            })
+        })?;
+
+        match resp {
+            Ok(text) => Ok(text),
+            Err(location) => network::client_builder()
+                .build()?
+                .get(&location)
+                .send()
+                .and_then(|r| r.error_for_status())
+                .and_then(|mut r| r.text())
+                .map_err(|e| format_err!("{}", e)),
+        }
     }
 
     pub fn post<T, U: Serialize>(&self, path: &str, body: &U) -> Result<T>
     where
         T: DeserializeOwned + Send + 'static,
     {
-        self.client
-            .post(&self.make_url(path))
-            .json(body)
-            .send()
-            .and_then(|r| r.error_for_status())
-            .and_then(|mut r| r.json::<T>())
-            .map_err(|e| format_err!("{}", e))
+        self.request(reqwest::Method::POST, path, |req| {
+            req.json(body).send().and_then(|r| r.error_for_status()).and_then(|mut r| r.json::<T>())
+        })
     }
 
     pub fn post_void<U: Serialize>(&self, path: &str, body: &U) -> Result<()> {
-        self.client
-            .post(&self.make_url(path))
-            .json(body)
-            .send()
-            .and_then(|r| r.error_for_status())
-            .and_then(|_| Ok(()))
-            .map_err(|e| format_err!("{}", e))
+        self.request(reqwest::Method::POST, path, |req| {
+            req.json(body).send().and_then(|r| r.error_for_status()).and_then(|_| Ok(()))
+        })
     }
 
     pub fn put<T, U: Serialize>(&self, path: &str, body: &U) -> Result<T>
     where
         T: DeserializeOwned + Send + 'static,
     {
-        self.client
-            .put(&self.make_url(path))
-            .json(body)
-            .send()
-            .and_then(|r| r.error_for_status())
-            .and_then(|mut r| r.json::<T>())
-            .map_err(|e| format_err!("{}", e))
+        self.request(reqwest::Method::PUT, path, |req| {
+            req.json(body).send().and_then(|r| r.error_for_status()).and_then(|mut r| r.json::<T>())
+        })
     }
 
     pub fn put_void<U: Serialize>(&self, path: &str, body: &U) -> Result<()> {
-        self.client
-            .put(&self.make_url(path))
-            .json(body)
-            .send()
-            .and_then(|r| r.error_for_status())
-            .and_then(|_| Ok(()))
-            .map_err(|e| format_err!("{}", e))
+        self.request(reqwest::Method::PUT, path, |req| {
+            req.json(body).send().and_then(|r| r.error_for_status()).and_then(|_| Ok(()))
+        })
+    }
+
+    pub fn patch_void<U: Serialize>(&self, path: &str, body: &U) -> Result<()> {
+        self.request(reqwest::Method::PATCH, path, |req| {
+            req.json(body).send().and_then(|r| r.error_for_status()).and_then(|_| Ok(()))
+        })
     }
 
     pub fn delete_void(&self, path: &str) -> Result<()> {
-        self.client
-            .delete(&self.make_url(path))
-            .send()
-            .and_then(|r| r.error_for_status())
-            .and_then(|_| Ok(()))
-            .map_err(|e| format_err!("{}", e))
+        self.request(reqwest::Method::DELETE, path, |req| {
+            req.send().and_then(|r| r.error_for_status()).and_then(|_| Ok(()))
+        })
     }
 }
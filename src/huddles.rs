@@ -0,0 +1,113 @@
+use failure::format_err;
+
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+
+// Counts the exchanges in a discussion: each comment answering someone else's rather than following up on one's own
+pub fn back_and_forth(commenters: &Vec<String>) -> u32 {
+    commenters.windows(2).filter(|w| w[0] != w[1]).count() as u32
+}
+
+// The people in the discussion, in the order they joined it
+pub fn participants(commenters: &Vec<String>) -> Vec<String> {
+    let mut participants: Vec<String> = vec![];
+    for c in commenters {
+        if !participants.contains(c) {
+            participants.push(c.clone());
+        }
+    }
+    participants
+}
+
+pub fn suggestion(back_and_forth: u32, mentions: &Vec<String>) -> String {
+    format!(
+        "This review has gone back and forth {} times. A quick huddle might settle it faster: consider inviting {}",
+        back_and_forth,
+        mentions.join(", ")
+    )
+}
+
+// The review discussion on each PR, so a huddle can be suggested once it has gone on long enough
+pub struct ReviewDiscussions {
+    db: Database,
+}
+
+impl ReviewDiscussions {
+    pub fn new(db: Database) -> ReviewDiscussions {
+        ReviewDiscussions { db: db }
+    }
+
+    // Records a comment and returns who made each of the PR's comments so far, oldest first
+    pub fn add_comment(&self, repo: &str, number: u32, login: &str) -> Result<Vec<String>> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "INSERT INTO review_comments (repo, number, login, created_at) VALUES (?1, ?2, ?3, ?4)",
+            &[&repo as &dyn ToSql, &number, &login, &db::now()],
+        )
+        .map_err(|e| format_err!("Error recording comment on PR {}#{}: {}", repo, number, e))?;
+
+        let mut stmt =
+            conn.prepare("SELECT login FROM review_comments WHERE repo = :repo AND number = :number ORDER BY id")?;
+        let mut rows = stmt.query_named(&[(":repo", &repo), (":number", &number)])?;
+
+        let mut commenters = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            commenters.push(row.get(0)?);
+        }
+
+        Ok(commenters)
+    }
+
+    // Returns false if a huddle was already suggested for the PR
+    pub fn mark_suggested(&self, repo: &str, number: u32) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let changed = conn
+            .execute(
+                r#"INSERT INTO huddle_suggestions (repo, number, suggested_at)
+                   VALUES (?1, ?2, ?3)
+                   ON CONFLICT DO NOTHING"#,
+                &[&repo as &dyn ToSql, &number, &db::now()],
+            )
+            .map_err(|e| format_err!("Error saving huddle suggestion for PR {}#{}: {}", repo, number, e))?;
+
+        Ok(changed > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn logins(l: &[&str]) -> Vec<String> {
+        l.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_back_and_forth() {
+        assert_eq!(0, back_and_forth(&logins(&[])));
+        assert_eq!(0, back_and_forth(&logins(&["joe", "joe", "joe"])));
+        assert_eq!(1, back_and_forth(&logins(&["joe", "joe", "sue"])));
+        assert_eq!(4, back_and_forth(&logins(&["joe", "sue", "joe", "sue", "sue", "bob"])));
+    }
+
+    #[test]
+    fn test_participants() {
+        assert_eq!(logins(&["joe", "sue", "bob"]), participants(&logins(&["joe", "sue", "joe", "bob", "sue"])));
+    }
+
+    #[test]
+    fn test_review_discussions() {
+        let temp_dir = TempDir::new("huddles.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let discussions = ReviewDiscussions::new(Database::new(&db_file.to_string_lossy()).unwrap());
+
+        assert_eq!(logins(&["joe"]), discussions.add_comment("some-org/some-repo", 32, "joe").unwrap());
+        assert_eq!(logins(&["sue"]), discussions.add_comment("some-org/some-repo", 33, "sue").unwrap());
+        assert_eq!(logins(&["joe", "sue"]), discussions.add_comment("some-org/some-repo", 32, "sue").unwrap());
+
+        assert!(discussions.mark_suggested("some-org/some-repo", 32).unwrap());
+        assert!(!discussions.mark_suggested("some-org/some-repo", 32).unwrap());
+        assert!(discussions.mark_suggested("some-org/other-repo", 32).unwrap());
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use failure::format_err;
+use log::debug;
+use native_tls::{TlsConnector, TlsStream};
+
+use crate::errors::*;
+
+const TIMEOUT_SECS: u64 = 60;
+
+// A minimal IMAP client over TLS: just enough to read new messages from a mailbox
+pub struct ImapClient {
+    stream: BufReader<TlsStream<TcpStream>>,
+    next_tag: u32,
+}
+
+struct ImapResponse {
+    // untagged lines
+    lines: Vec<String>,
+    // the contents of `{N}` literals, e.g. fetched messages
+    literals: Vec<Vec<u8>>,
+}
+
+impl ImapClient {
+    pub fn connect(host: &str, port: u16) -> Result<ImapClient> {
+        let tcp = TcpStream::connect((host, port)).map_err(|e| format_err!("Error connecting to {}: {}", host, e))?;
+        tcp.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+        tcp.set_write_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+
+        let connector = TlsConnector::new().map_err(|e| format_err!("Error setting up TLS: {}", e))?;
+        let tls = connector.connect(host, tcp).map_err(|e| format_err!("Error connecting to {}: {}", host, e))?;
+
+        let mut client = ImapClient {
+            stream: BufReader::new(tls),
+            next_tag: 0,
+        };
+        let greeting = client.read_line()?;
+        if !greeting.starts_with("* OK") {
+            return Err(format_err!("Unexpected IMAP greeting from {}: {}", host, greeting));
+        }
+
+        Ok(client)
+    }
+
+    pub fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        self.command(&format!("LOGIN {} {}", quote(username), quote(password)))?;
+        Ok(())
+    }
+
+    pub fn select(&mut self, mailbox: &str) -> Result<()> {
+        self.command(&format!("SELECT {}", quote(mailbox)))?;
+        Ok(())
+    }
+
+    // Sequence numbers of the messages not read yet
+    pub fn search_unseen(&mut self) -> Result<Vec<u32>> {
+        let resp = self.command("SEARCH UNSEEN")?;
+        Ok(resp.lines.iter().flat_map(|l| parse_search(l)).collect())
+    }
+
+    // The whole message, without marking it as read
+    pub fn fetch(&mut self, id: u32) -> Result<String> {
+        let resp = self.command(&format!("FETCH {} BODY.PEEK[]", id))?;
+        match resp.literals.into_iter().next() {
+            Some(message) => Ok(String::from_utf8_lossy(&message).into_owned()),
+            None => Err(format_err!("No message {} in IMAP response", id)),
+        }
+    }
+
+    pub fn mark_seen(&mut self, id: u32) -> Result<()> {
+        self.command(&format!("STORE {} +FLAGS (\\Seen)", id))?;
+        Ok(())
+    }
+
+    pub fn logout(&mut self) -> Result<()> {
+        self.command("LOGOUT")?;
+        Ok(())
+    }
+
+    fn command(&mut self, command: &str) -> Result<ImapResponse> {
+        self.next_tag += 1;
+        let tag = format!("a{}", self.next_tag);
+        // don't log LOGIN's password
+        let name = command.split(' ').next().unwrap_or("");
+        debug!("IMAP {} {}", tag, name);
+
+        self.stream.get_mut().write_all(format!("{} {}\r\n", tag, command).as_bytes())?;
+        self.stream.get_mut().flush()?;
+
+        let mut resp = ImapResponse {
+            lines: vec![],
+            literals: vec![],
+        };
+        let tagged = format!("{} ", tag);
+        loop {
+            let line = self.read_line()?;
+            if line.starts_with(&tagged) {
+                let status = &line[tagged.len()..];
+                if status.starts_with("OK") {
+                    return Ok(resp);
+                }
+                return Err(format_err!("IMAP {} failed: {}", name, status));
+            }
+
+            if let Some(len) = literal_len(&line) {
+                let mut literal = vec![0; len];
+                self.stream.read_exact(&mut literal)?;
+                resp.literals.push(literal);
+            }
+            resp.lines.push(line);
+        }
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = vec![];
+        if self.stream.read_until(b'\n', &mut line)? == 0 {
+            return Err(format_err!("IMAP server closed the connection"));
+        }
+        Ok(String::from_utf8_lossy(&line).trim_end_matches(|c| c == '\r' || c == '\n').to_string())
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace("\\", "\\\\").replace("\"", "\\\""))
+}
+
+// The N of a line ending with a `{N}` literal
+fn literal_len(line: &str) -> Option<usize> {
+    if !line.ends_with('}') {
+        return None;
+    }
+    let start = line.rfind('{')?;
+    line[start + 1..line.len() - 1].parse::<usize>().ok()
+}
+
+fn parse_search(line: &str) -> Vec<u32> {
+    if !line.starts_with("* SEARCH") {
+        return vec![];
+    }
+    line["* SEARCH".len()..].split_whitespace().filter_map(|id| id.parse::<u32>().ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!("\"INBOX\"", quote("INBOX"));
+        assert_eq!("\"pa\\\"ss\\\\word\"", quote("pa\"ss\\word"));
+    }
+
+    #[test]
+    fn test_literal_len() {
+        assert_eq!(Some(1234), literal_len("* 1 FETCH (BODY[] {1234}"));
+        assert_eq!(None, literal_len("* 1 FETCH (FLAGS (\\Seen))"));
+        assert_eq!(None, literal_len("* OK {not a number}"));
+    }
+
+    #[test]
+    fn test_parse_search() {
+        assert_eq!(vec![2, 5, 7], parse_search("* SEARCH 2 5 7"));
+        assert_eq!(Vec::<u32>::new(), parse_search("* SEARCH"));
+        assert_eq!(Vec::<u32>::new(), parse_search("* 3 EXISTS"));
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use failure::format_err;
+use hyper::{Body, Method, Request};
+use log::{error, info};
+use reqwest;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use url::Url;
+
+use crate::aws;
+use crate::config::{Config, InboundQueueConfig};
+use crate::errors::*;
+use crate::network;
+
+pub const SQS: &str = "sqs";
+pub const KAFKA: &str = "kafka";
+
+const SQS_CONTENT_TYPE: &str = "application/x-amz-json-1.0";
+const KAFKA_CONTENT_TYPE: &str = "application/vnd.kafka.v2+json";
+const KAFKA_JSON_CONTENT_TYPE: &str = "application/vnd.kafka.json.v2+json";
+const MAX_MESSAGES: usize = 10;
+
+// A webhook delivery, as queued by the edge receiver
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Envelope {
+    // at least X-GitHub-Event, X-GitHub-Delivery and X-Hub-Signature
+    pub headers: HashMap<String, String>,
+    // exactly as github sent it, so that the signature can be checked
+    pub body: String,
+}
+
+impl Envelope {
+    pub fn parse(data: &[u8]) -> Result<Envelope> {
+        serde_json::from_slice(data).map_err(|e| format_err!("Invalid queued delivery: {}", e))
+    }
+
+    // The request github would have sent to /hooks/github
+    pub fn into_request(self) -> Result<Request<Body>> {
+        let mut req = Request::builder();
+        req.method(Method::POST).uri("/hooks/github");
+        for (name, value) in &self.headers {
+            req.header(name.as_str(), value.as_str());
+        }
+        req.body(Body::from(self.body)).map_err(|e| format_err!("Invalid queued delivery: {}", e))
+    }
+}
+
+pub struct Message {
+    // for logging
+    pub id: String,
+    pub data: Vec<u8>,
+    // what the queue needs to acknowledge it
+    pub receipt: String,
+}
+
+pub trait Consumer: Send + Sync {
+    // The next deliveries, in order
+    fn receive(&self) -> Result<Vec<Message>>;
+
+    // Removes a handled message from the queue
+    fn ack(&self, message: &Message) -> Result<()>;
+
+    // Leaves a message that couldn't be handled to be received again, along with any after it
+    fn retry(&self, message: &Message) -> Result<()>;
+}
+
+// e.g. "us-east-1" for "https://sqs.us-east-1.amazonaws.com/123456789012/github-events"
+pub fn sqs_region(queue_url: &Url) -> Option<String> {
+    let parts = queue_url.host_str()?.split('.').collect::<Vec<_>>();
+    match parts.as_slice() {
+        ["sqs", region, "amazonaws", ..] => Some(region.to_string()),
+        [region, "queue", "amazonaws", ..] => Some(region.to_string()),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SqsMessage {
+    #[serde(rename = "MessageId")]
+    message_id: String,
+    #[serde(rename = "ReceiptHandle")]
+    receipt_handle: String,
+    #[serde(rename = "Body")]
+    body: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReceiveMessageResponse {
+    #[serde(rename = "Messages", default)]
+    messages: Vec<SqsMessage>,
+}
+
+// Receives from an SQS queue, through its JSON API
+pub struct SqsConsumer {
+    client: reqwest::Client,
+    queue_url: String,
+    host: String,
+    region: String,
+    credentials: aws::Credentials,
+}
+
+impl SqsConsumer {
+    pub fn new(config: &InboundQueueConfig) -> Result<SqsConsumer> {
+        let url = Url::parse(&config.url)?;
+        let host = url.host_str().ok_or_else(|| format_err!("No host in {}", config.url))?.to_string();
+        let region = config
+            .region
+            .clone()
+            .or_else(|| sqs_region(&url))
+            .ok_or_else(|| format_err!("No region in queue url {}", config.url))?;
+        Ok(SqsConsumer {
+            client: network::client(),
+            queue_url: config.url.clone(),
+            host: host,
+            region: region,
+            credentials: aws::Credentials::from_config_or_env(
+                config.access_key_id.as_ref(),
+                config.secret_access_key.as_ref(),
+            )?,
+        })
+    }
+
+    fn call(&self, action: &str, params: serde_json::Value) -> Result<String> {
+        let body = serde_json::to_vec(&params)?;
+        let target = format!("AmazonSQS.{}", action);
+        let signed = aws::sign_v4(
+            &self.credentials,
+            &self.region,
+            "sqs",
+            &aws::Request {
+                method: "POST",
+                host: &self.host,
+                path: "/",
+                query: "",
+                headers: vec![("Content-Type", SQS_CONTENT_TYPE), ("X-Amz-Target", &target)],
+                body: &body,
+            },
+            &aws::amz_date(&time::now_utc()),
+        );
+
+        let mut req = self
+            .client
+            .post(&format!("https://{}/", self.host))
+            .header(reqwest::header::CONTENT_TYPE, SQS_CONTENT_TYPE)
+            .header("X-Amz-Target", target.as_str());
+        for (name, value) in &signed {
+            req = req.header(name.as_str(), value.as_str());
+        }
+        let mut resp = req.body(body).send().map_err(|e| format_err!("Error calling SQS {}: {}", action, e))?;
+        let text = resp.text().unwrap_or_default();
+        if !resp.status().is_success() {
+            return Err(format_err!("Error calling SQS {} ({}): {}", action, resp.status(), text));
+        }
+        Ok(text)
+    }
+}
+
+impl Consumer for SqsConsumer {
+    fn receive(&self) -> Result<Vec<Message>> {
+        let resp = self.call(
+            "ReceiveMessage",
+            serde_json::json!({
+                "QueueUrl": self.queue_url,
+                "MaxNumberOfMessages": MAX_MESSAGES,
+                "WaitTimeSeconds": 0,
+            }),
+        )?;
+        let resp: ReceiveMessageResponse = serde_json::from_str(&resp)?;
+        Ok(resp
+            .messages
+            .into_iter()
+            .map(|m| Message {
+                id: m.message_id,
+                data: m.body.into_bytes(),
+                receipt: m.receipt_handle,
+            })
+            .collect())
+    }
+
+    fn ack(&self, message: &Message) -> Result<()> {
+        self.call(
+            "DeleteMessage",
+            serde_json::json!({ "QueueUrl": self.queue_url, "ReceiptHandle": message.receipt }),
+        )
+        .map(|_| ())
+    }
+
+    // SQS delivers it again once its visibility timeout passes
+    fn retry(&self, _: &Message) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ConsumerInstance {
+    base_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Record {
+    topic: String,
+    partition: i64,
+    offset: i64,
+    value: serde_json::Value,
+}
+
+// The offset to commit once |record| is handled. The proxy commits the position after it.
+fn kafka_receipt(record: &Record) -> String {
+    serde_json::json!({ "topic": record.topic, "partition": record.partition, "offset": record.offset }).to_string()
+}
+
+// Consumes a topic through a Kafka REST Proxy, committing each record's offset once it is handled
+pub struct KafkaConsumer {
+    client: reqwest::Client,
+    url: String,
+    topic: String,
+    group: String,
+    username: Option<String>,
+    password: Option<String>,
+    // the consumer instance's url, once it is created and subscribed
+    instance: Mutex<Option<String>>,
+}
+
+impl KafkaConsumer {
+    pub fn new(config: &InboundQueueConfig) -> KafkaConsumer {
+        KafkaConsumer {
+            client: network::client(),
+            url: config.url.trim_end_matches('/').to_string(),
+            topic: config.topic.clone().filter(|t| !t.is_empty()).unwrap_or("github-events".into()),
+            group: config.group.clone().filter(|g| !g.is_empty()).unwrap_or("octobot".into()),
+            username: config.username.clone().filter(|u| !u.is_empty()),
+            password: config.password.clone(),
+            instance: Mutex::new(None),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let req = self.client.request(method, url).header(reqwest::header::CONTENT_TYPE, KAFKA_CONTENT_TYPE);
+        match self.username {
+            Some(ref username) => req.basic_auth(username, self.password.clone()),
+            None => req,
+        }
+    }
+
+    fn send(&self, req: reqwest::RequestBuilder, what: &str) -> Result<reqwest::Response> {
+        req.send().and_then(|r| r.error_for_status()).map_err(|e| format_err!("Error {}: {}", what, e))
+    }
+
+    // Records are only consumed from the last committed offset when the instance is created
+    fn instance(&self) -> Result<String> {
+        let mut instance = self.instance.lock().unwrap();
+        if let Some(ref base_uri) = *instance {
+            return Ok(base_uri.clone());
+        }
+
+        let create = self.request(reqwest::Method::POST, &format!("{}/consumers/{}", self.url, self.group)).json(
+            &serde_json::json!({
+                "format": "json",
+                "auto.offset.reset": "earliest",
+                "auto.commit.enable": "false",
+            }),
+        );
+        let created: ConsumerInstance = self.send(create, "creating a Kafka consumer")?.json()?;
+
+        let subscribe = self
+            .request(reqwest::Method::POST, &format!("{}/subscription", created.base_uri))
+            .json(&serde_json::json!({ "topics": [self.topic] }));
+        self.send(subscribe, &format!("subscribing to {}", self.topic))?;
+
+        info!("Consuming {} as {}", self.topic, created.base_uri);
+        *instance = Some(created.base_uri.clone());
+        Ok(created.base_uri)
+    }
+
+    fn close(&self) {
+        if let Some(base_uri) = self.instance.lock().unwrap().take() {
+            if let Err(e) = self.send(self.request(reqwest::Method::DELETE, &base_uri), "closing the Kafka consumer") {
+                error!("{}", e);
+            }
+        }
+    }
+}
+
+impl Consumer for KafkaConsumer {
+    fn receive(&self) -> Result<Vec<Message>> {
+        let base_uri = self.instance()?;
+        let records = self
+            .request(reqwest::Method::GET, &format!("{}/records", base_uri))
+            .header(reqwest::header::ACCEPT, KAFKA_JSON_CONTENT_TYPE);
+        let records: Vec<Record> = match self.send(records, &format!("consuming {}", self.topic)) {
+            Ok(mut r) => r.json()?,
+            Err(e) => {
+                // e.g. the proxy expired the instance: start over from the committed offsets
+                *self.instance.lock().unwrap() = None;
+                return Err(e);
+            }
+        };
+
+        Ok(records
+            .iter()
+            .map(|r| Message {
+                id: format!("{}/{}/{}", r.topic, r.partition, r.offset),
+                data: serde_json::to_vec(&r.value).unwrap_or_default(),
+                receipt: kafka_receipt(r),
+            })
+            .collect())
+    }
+
+    fn ack(&self, message: &Message) -> Result<()> {
+        let base_uri = self.instance()?;
+        let offset: serde_json::Value = serde_json::from_str(&message.receipt)?;
+        let commit = self
+            .request(reqwest::Method::POST, &format!("{}/offsets", base_uri))
+            .json(&serde_json::json!({ "offsets": [offset] }));
+        self.send(commit, &format!("committing {}", message.id)).map(|_| ())
+    }
+
+    // The next instance starts from the last committed offset, which is before this record
+    fn retry(&self, _: &Message) -> Result<()> {
+        self.close();
+        Ok(())
+    }
+}
+
+pub fn new_consumer(config: &Config) -> Result<Option<Arc<dyn Consumer>>> {
+    let queue = match config.inbound_queue {
+        Some(ref q) => q,
+        None => return Ok(None),
+    };
+    let consumer: Arc<dyn Consumer> = match queue.kind.as_str() {
+        SQS => Arc::new(SqsConsumer::new(queue)?),
+        KAFKA => Arc::new(KafkaConsumer::new(queue)),
+        kind => return Err(format_err!("Invalid inbound queue kind: '{}'", kind)),
+    };
+    Ok(Some(consumer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqs_region() {
+        let region = |url: &str| sqs_region(&Url::parse(url).unwrap());
+        assert_eq!(Some("us-east-1".into()), region("https://sqs.us-east-1.amazonaws.com/123456789012/github-events"));
+        assert_eq!(Some("eu-west-2".into()), region("https://eu-west-2.queue.amazonaws.com/123456789012/events"));
+        assert_eq!(None, region("https://vpce-1234.sqs.company.com/123456789012/github-events"));
+    }
+
+    #[test]
+    fn test_envelope_into_request() {
+        let envelope = Envelope::parse(
+            br#"{
+                "headers": {
+                    "X-GitHub-Event": "ping",
+                    "X-GitHub-Delivery": "the-delivery",
+                    "X-Hub-Signature": "sha1=abcd"
+                },
+                "body": "{\"zen\": \"Keep it simple\"}"
+            }"#,
+        )
+        .unwrap();
+        let req = envelope.into_request().unwrap();
+
+        assert_eq!(Method::POST, *req.method());
+        assert_eq!("/hooks/github", req.uri().path());
+        assert_eq!("ping", req.headers()["x-github-event"]);
+        assert_eq!("the-delivery", req.headers()["x-github-delivery"]);
+        assert_eq!("sha1=abcd", req.headers()["x-hub-signature"]);
+    }
+
+    #[test]
+    fn test_envelope_invalid() {
+        assert!(Envelope::parse(b"{\"zen\": \"Keep it simple\"}").is_err());
+
+        let mut headers = HashMap::new();
+        headers.insert("X-GitHub Event".to_string(), "ping".to_string());
+        let envelope = Envelope {
+            headers: headers,
+            body: "{}".into(),
+        };
+        assert!(envelope.into_request().is_err());
+    }
+
+    #[test]
+    fn test_kafka_receipt() {
+        let record = Record {
+            topic: "github-events".into(),
+            partition: 2,
+            offset: 42,
+            value: serde_json::json!({}),
+        };
+        assert_eq!(r#"{"offset":42,"partition":2,"topic":"github-events"}"#, kafka_receipt(&record));
+    }
+}
@@ -0,0 +1,334 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use failure::format_err;
+use log::{debug, error, info};
+use native_tls::TlsConnector;
+
+use crate::config::IrcConfig;
+use crate::deliveries::{self, Deliveries};
+use crate::errors::*;
+use crate::matrix;
+use crate::slack::SlackRequest;
+use crate::worker;
+
+// Messenger channels starting with this go to IRC, e.g. "irc:#ops"
+pub const CHANNEL_PREFIX: &str = "irc:";
+
+const TIMEOUT_SECS: u64 = 30;
+// IRC lines are limited to 512 bytes, including the prefix the server adds when relaying them
+const MAX_LINE_BYTES: usize = 400;
+// so long attachments don't flood the channel
+const MAX_LINES: usize = 6;
+
+// The IRC channel of a messenger channel like "irc:#ops"
+pub fn target(channel: &str) -> Option<String> {
+    if !channel.starts_with(CHANNEL_PREFIX) {
+        return None;
+    }
+
+    let name = channel[CHANNEL_PREFIX.len()..].trim();
+    if name.is_empty() {
+        None
+    } else if name.starts_with('#') || name.starts_with('&') {
+        Some(name.to_string())
+    } else {
+        Some(format!("#{}", name))
+    }
+}
+
+fn truncate(line: &str) -> String {
+    if line.len() <= MAX_LINE_BYTES {
+        return line.to_string();
+    }
+
+    let mut end = MAX_LINE_BYTES;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &line[..end])
+}
+
+// The message as plain text lines
+pub fn to_lines(req: &SlackRequest) -> Vec<String> {
+    let mut text = vec![matrix::to_plain(&req.msg)];
+    for attachment in &req.attachments {
+        if let Some(ref title) = attachment.title {
+            match attachment.title_link {
+                Some(ref url) => text.push(format!("{} ({})", matrix::to_plain(title), url)),
+                None => text.push(matrix::to_plain(title)),
+            };
+        }
+        text.push(matrix::to_plain(&attachment.text));
+    }
+
+    let mut lines = text
+        .iter()
+        .flat_map(|t| t.lines())
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .map(truncate)
+        .collect::<Vec<_>>();
+    if lines.len() > MAX_LINES {
+        lines.truncate(MAX_LINES);
+        lines[MAX_LINES - 1] += " …";
+    }
+    lines
+}
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+fn connect(config: &IrcConfig) -> Result<BufReader<Box<dyn Stream>>> {
+    let tls = config.tls.unwrap_or(true);
+    let port = config.port.unwrap_or(if tls { 6697 } else { 6667 });
+
+    let tcp = TcpStream::connect((config.server.as_str(), port))
+        .map_err(|e| format_err!("Error connecting to {}: {}", config.server, e))?;
+    tcp.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+    tcp.set_write_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+
+    let stream: Box<dyn Stream> = if tls {
+        let connector = TlsConnector::new().map_err(|e| format_err!("Error setting up TLS: {}", e))?;
+        let tls_stream = connector
+            .connect(&config.server, tcp)
+            .map_err(|e| format_err!("Error connecting to {}: {}", config.server, e))?;
+        Box::new(tls_stream)
+    } else {
+        Box::new(tcp)
+    };
+
+    Ok(BufReader::new(stream))
+}
+
+// Registers as |nick|, joins |channel|, says |lines| there and quits
+pub fn deliver<S: Read + Write>(
+    stream: &mut BufReader<S>,
+    nick: &str,
+    password: Option<&str>,
+    channel: &str,
+    lines: &Vec<String>,
+) -> Result<()> {
+    if let Some(password) = password {
+        send_line(stream, &format!("PASS {}", password))?;
+    }
+    send_line(stream, &format!("NICK {}", nick))?;
+    send_line(stream, &format!("USER {} 0 * :octobot", nick))?;
+    // RPL_WELCOME
+    wait_for(stream, "001")?;
+
+    send_line(stream, &format!("JOIN {}", channel))?;
+    // RPL_ENDOFNAMES: joined
+    wait_for(stream, "366")?;
+
+    for line in lines {
+        send_line(stream, &format!("PRIVMSG {} :{}", channel, line))?;
+    }
+    send_line(stream, "QUIT")?;
+
+    Ok(())
+}
+
+fn send_line<S: Read + Write>(stream: &mut BufReader<S>, line: &str) -> Result<()> {
+    // don't log PASS's password
+    debug!("IRC {}", line.split(' ').next().unwrap_or(""));
+    stream.get_mut().write_all(format!("{}\r\n", line).as_bytes())?;
+    stream.get_mut().flush()?;
+    Ok(())
+}
+
+// Reads until the server sends the |expected| reply, answering its pings along the way
+fn wait_for<S: Read + Write>(stream: &mut BufReader<S>, expected: &str) -> Result<()> {
+    loop {
+        let mut line = vec![];
+        if stream.read_until(b'\n', &mut line)? == 0 {
+            return Err(format_err!("IRC server closed the connection"));
+        }
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+
+        if line.starts_with("PING ") {
+            send_line(stream, &format!("PONG {}", &line["PING ".len()..]))?;
+            continue;
+        }
+        if line.starts_with("ERROR") {
+            return Err(format_err!("IRC server error: {}", line));
+        }
+
+        // e.g. ":irc.company.com 001 octobot :Welcome"
+        let mut parts = line.split(' ');
+        let command = if line.starts_with(':') { parts.nth(1) } else { parts.next() };
+        match command {
+            Some(c) if c == expected => return Ok(()),
+            Some(c) if is_error_reply(c) => return Err(format_err!("IRC server refused: {}", line)),
+            _ => (),
+        }
+    }
+}
+
+fn is_error_reply(command: &str) -> bool {
+    match command.parse::<u16>() {
+        Ok(n) => command.len() == 3 && n >= 400 && n < 600,
+        Err(_) => false,
+    }
+}
+
+struct Runner {
+    config: IrcConfig,
+    fallback: Arc<dyn worker::Runner<SlackRequest>>,
+    // one connection at a time, since they all use the same nick
+    sending: Mutex<()>,
+    deliveries: Deliveries,
+}
+
+// Sends messenger requests for "irc:" channels to IRC, and all others to |fallback|
+pub fn new_runner(
+    config: &IrcConfig,
+    fallback: Arc<dyn worker::Runner<SlackRequest>>,
+    deliveries: Deliveries,
+) -> Arc<dyn worker::Runner<SlackRequest>> {
+    Arc::new(Runner {
+        config: config.clone(),
+        fallback: fallback,
+        sending: Mutex::new(()),
+        deliveries: deliveries,
+    })
+}
+
+impl Runner {
+    fn send(&self, channel: &str, req: &SlackRequest) -> Result<()> {
+        let _sending = self.sending.lock().unwrap();
+
+        info!("Sending IRC message to {}", channel);
+        let mut stream = connect(&self.config)?;
+        deliver(
+            &mut stream,
+            &self.config.nick,
+            self.config.password.as_ref().map(|p| p.as_str()),
+            channel,
+            &to_lines(req),
+        )
+    }
+}
+
+impl worker::Runner<SlackRequest> for Runner {
+    fn handle(&self, req: SlackRequest) {
+        let channel = match target(&req.channel) {
+            Some(c) => c,
+            None => return self.fallback.handle(req),
+        };
+
+        if let Err(e) = self.deliveries.track(deliveries::IRC, &req.channel, || self.send(&channel, &req)) {
+            error!("Error sending IRC message to {}: {}", channel, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slack::{self, SlackAttachmentBuilder};
+    use std::io::{self, Cursor};
+
+    struct FakeServer {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for FakeServer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeServer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fake_server(replies: &str) -> BufReader<FakeServer> {
+        BufReader::new(FakeServer {
+            input: Cursor::new(replies.as_bytes().to_vec()),
+            output: vec![],
+        })
+    }
+
+    #[test]
+    fn test_target() {
+        assert_eq!(Some("#ops".into()), target("irc:#ops"));
+        assert_eq!(Some("#ops".into()), target("irc:ops"));
+        assert_eq!(Some("&local".into()), target("irc:&local"));
+        assert_eq!(None, target("irc:"));
+        assert_eq!(None, target("ops"));
+        assert_eq!(None, target("@joe"));
+    }
+
+    #[test]
+    fn test_to_lines() {
+        let attach = SlackAttachmentBuilder::new("some <http://the-commit|commit>\n\nand more")
+            .title("Release 1.2.0")
+            .title_link("http://the-release")
+            .build();
+        let req = slack::req("irc:#ops", "Release published (<http://the-repo|some-user/some-repo>)", vec![attach]);
+        assert_eq!(
+            vec![
+                "Release published (some-user/some-repo (http://the-repo))",
+                "Release 1.2.0 (http://the-release)",
+                "some commit (http://the-commit)",
+                "and more",
+            ],
+            to_lines(&req)
+        );
+
+        let long = slack::req("irc:#ops", &"é".repeat(300), vec![]);
+        let lines = to_lines(&long);
+        assert_eq!(format!("{}…", "é".repeat(200)), lines[0]);
+
+        let many = slack::req("irc:#ops", "1\n2\n3\n4\n5\n6\n7\n8", vec![]);
+        assert_eq!(vec!["1", "2", "3", "4", "5", "6 …"], to_lines(&many));
+    }
+
+    #[test]
+    fn test_deliver() {
+        let mut stream = fake_server(
+            ":irc.local NOTICE * :Looking up your hostname\r\n\
+             PING :irc.local\r\n\
+             :irc.local 001 octobot :Welcome\r\n\
+             :octobot!octobot@host JOIN #ops\r\n\
+             :irc.local 353 octobot = #ops :octobot\r\n\
+             :irc.local 366 octobot #ops :End of /NAMES list.\r\n",
+        );
+        let lines = vec!["Release published".to_string(), "Release 1.2.0".to_string()];
+        deliver(&mut stream, "octobot", Some("secret"), "#ops", &lines).unwrap();
+
+        assert_eq!(
+            "PASS secret\r\n\
+             NICK octobot\r\n\
+             USER octobot 0 * :octobot\r\n\
+             PONG :irc.local\r\n\
+             JOIN #ops\r\n\
+             PRIVMSG #ops :Release published\r\n\
+             PRIVMSG #ops :Release 1.2.0\r\n\
+             QUIT\r\n",
+            String::from_utf8(stream.get_ref().output.clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deliver_refused() {
+        let mut stream = fake_server(":irc.local 433 * octobot :Nickname is already in use\r\n");
+        let err = deliver(&mut stream, "octobot", None, "#ops", &vec![]).unwrap_err();
+        assert!(format!("{}", err).contains("Nickname is already in use"));
+
+        let mut stream = fake_server(":irc.local 001 octobot :Welcome\r\n");
+        let err = deliver(&mut stream, "octobot", None, "#ops", &vec![]).unwrap_err();
+        assert_eq!("IRC server closed the connection", format!("{}", err));
+    }
+}
@@ -1,27 +1,38 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
-use base64;
 use failure::format_err;
 use log::{debug, info};
 use regex::Regex;
 use serde_json;
 use serde_json::json;
 use serde_derive::{Deserialize, Serialize};
+use url::form_urlencoded;
 use url::percent_encoding::{DEFAULT_ENCODE_SET, utf8_percent_encode};
 
 use crate::config::JiraConfig;
 use crate::errors::*;
+use crate::faults;
 use crate::http_client::HTTPClient;
+use crate::jira::auth::{self, OAuthTokens};
 use crate::jira::models::*;
 use crate::version;
 
 pub trait Session: Send + Sync {
     fn get_issue(&self, key: &str) -> Result<Issue>;
     fn get_transitions(&self, key: &str) -> Result<Vec<Transition>>;
+    fn get_project_statuses(&self, proj: &str) -> Result<Vec<Status>>;
 
     fn transition_issue(&self, key: &str, transition: &TransitionRequest) -> Result<()>;
 
     fn comment_issue(&self, key: &str, comment: &str) -> Result<()>;
+    fn get_comments(&self, key: &str) -> Result<Vec<Comment>>;
+    fn update_comment(&self, key: &str, comment_id: &str, comment: &str) -> Result<()>;
+
+    fn add_remote_link(&self, key: &str, link: &RemoteLink) -> Result<()>;
+
+    // `time_spent` is in JIRA's duration format, e.g. "1d 2h 30m"
+    fn add_worklog(&self, key: &str, time_spent: &str, comment: &str) -> Result<()>;
 
     fn add_version(&self, proj: &str, version: &str) -> Result<()>;
     fn get_versions(&self, proj: &str) -> Result<Vec<Version>>;
@@ -31,6 +42,14 @@ pub trait Session: Send + Sync {
     fn add_pending_version(&self, key: &str, version: &str) -> Result<()>;
     fn remove_pending_versions(&self, key: &str, versions: &Vec<version::Version>) -> Result<()>;
     fn find_pending_versions(&self, proj: &str) -> Result<HashMap<String, Vec<version::Version>>>;
+
+    // Users whose username, name or email matches
+    fn search_users(&self, query: &str) -> Result<Vec<User>>;
+
+    // Refreshes an OAuth access token that is about to expire. Nothing to do with basic auth.
+    fn refresh_auth(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -44,12 +63,8 @@ pub struct JiraSession {
     fix_versions_field: String,
     pending_versions_field: Option<String>,
     pending_versions_field_id: Option<String>,
-    restrict_comment_visibility_to_role: Option<String>
-}
-
-#[derive(Deserialize)]
-struct AuthResp {
-    pub name: String,
+    restrict_comment_visibility_to_role: Option<String>,
+    cloud: bool,
 }
 
 fn lookup_field(field: &str, fields: &Vec<Field>) -> Result<String> {
@@ -63,24 +78,26 @@ fn lookup_field(field: &str, fields: &Vec<Field>) -> Result<String> {
 
 impl JiraSession {
     pub fn new(config: &JiraConfig) -> Result<JiraSession> {
-        let jira_base = config.base_url();
-        let api_base = format!("{}/rest/api/2", jira_base);
-
-        let auth = base64::encode(format!("{}:{}", config.username, config.password).as_bytes());
-
         let mut headers = reqwest::header::HeaderMap::new();
         headers.insert(reqwest::header::ACCEPT, "application/json".parse().unwrap());
-        headers.insert(
-            reqwest::header::AUTHORIZATION,
-            format!("Basic {}", auth).parse().unwrap(),
-        );
 
-        let client = HTTPClient::new_with_headers(&api_base, headers)?;
+        let client = match config.oauth {
+            Some(ref oauth) => HTTPClient::new_for_service(&config.api_base(), headers, faults::Service::Jira)?
+                .with_authorizer(Arc::new(OAuthTokens::new(oauth))),
+            None => {
+                headers.insert(reqwest::header::AUTHORIZATION, auth::basic_auth(config).parse()?);
+                HTTPClient::new_for_service(&config.api_base(), headers, faults::Service::Jira)?
+            }
+        };
 
-        let auth_resp = client.get::<AuthResp>(&format!("{}/rest/auth/1/session", jira_base)).map_err(
-            |e| format_err!("Error authenticating to JIRA: {}", e),
-        )?;
-        info!("Logged into JIRA as {}", auth_resp.name);
+        // Cloud has no session resource, and its users have no username
+        let myself = if config.is_cloud() {
+            client.get::<User>("/myself")
+        } else {
+            client.get::<User>(&format!("{}/rest/auth/1/session", config.base_url()))
+        };
+        let myself = myself.map_err(|e| format_err!("Error authenticating to JIRA: {}", e))?;
+        info!("Logged into JIRA as {}", myself.display_name.as_ref().map(|n| n.as_str()).unwrap_or(myself.id()));
 
         let fields = client.get::<Vec<Field>>("/field")?;
 
@@ -99,11 +116,16 @@ impl JiraSession {
             pending_versions_field: config.pending_versions_field.clone(),
             pending_versions_field_id: pending_versions_field_id,
             restrict_comment_visibility_to_role: config.restrict_comment_visibility_to_role.clone(),
+            cloud: config.is_cloud(),
         })
     }
 }
 
 impl Session for JiraSession {
+    fn refresh_auth(&self) -> Result<()> {
+        self.client.refresh_authorization()
+    }
+
     fn get_issue(&self, key: &str) -> Result<Issue> {
         self.client.get::<Issue>(&format!("/issue/{}", key)).map_err(|e| {
             format_err!("Error creating getting issue [{}]: {}", key, e)
@@ -121,6 +143,25 @@ impl Session for JiraSession {
         Ok(resp.transitions)
     }
 
+    fn get_project_statuses(&self, proj: &str) -> Result<Vec<Status>> {
+        #[derive(Deserialize)]
+        struct IssueTypeStatuses {
+            statuses: Vec<Status>,
+        }
+        let resp = self.client
+            .get::<Vec<IssueTypeStatuses>>(&format!("/project/{}/statuses", proj))
+            .map_err(|e| format_err!("Error getting statuses for project {}: {}", proj, e))?;
+
+        // each issue type lists its own statuses, which mostly overlap
+        let mut statuses: Vec<Status> = vec![];
+        for status in resp.into_iter().flat_map(|t| t.statuses) {
+            if !statuses.iter().any(|s| s.name == status.name) {
+                statuses.push(status);
+            }
+        }
+        Ok(statuses)
+    }
+
     fn transition_issue(&self, key: &str, req: &TransitionRequest) -> Result<()> {
         self.client.post_void(&format!("/issue/{}/transitions", key), &req).map_err(|e| {
             format_err!("Error transitioning [{}]: {}", key, e)
@@ -168,6 +209,52 @@ impl Session for JiraSession {
         )
     }
 
+    fn get_comments(&self, key: &str) -> Result<Vec<Comment>> {
+        #[derive(Deserialize)]
+        struct CommentsResp {
+            comments: Vec<Comment>,
+        }
+        let resp = self.client
+            .get::<CommentsResp>(&format!("/issue/{}/comment?maxResults=1000", key))
+            .map_err(|e| format_err!("Error getting comments for [{}]: {}", key, e))?;
+        Ok(resp.comments)
+    }
+
+    fn update_comment(&self, key: &str, comment_id: &str, comment: &str) -> Result<()> {
+        #[derive(Serialize)]
+        struct CommentReq {
+            body: String,
+        }
+
+        let req = CommentReq { body: comment.to_string() };
+        self.client.put_void(&format!("/issue/{}/comment/{}", key, comment_id), &req).map_err(|e| {
+            format_err!("Error updating comment {} on [{}]: {}", comment_id, key, e)
+        })
+    }
+
+    fn add_remote_link(&self, key: &str, link: &RemoteLink) -> Result<()> {
+        self.client.post_void(&format!("/issue/{}/remotelink", key), link).map_err(|e| {
+            format_err!("Error linking [{}] to {}: {}", key, link.object.url, e)
+        })
+    }
+
+    fn add_worklog(&self, key: &str, time_spent: &str, comment: &str) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct WorklogReq {
+            time_spent: String,
+            comment: String,
+        }
+
+        let req = WorklogReq {
+            time_spent: time_spent.into(),
+            comment: comment.into(),
+        };
+        self.client.post_void(&format!("/issue/{}/worklog", key), &req).map_err(|e| {
+            format_err!("Error logging {} of work on [{}]: {}", time_spent, key, e)
+        })
+    }
+
     fn add_version(&self, proj: &str, version: &str) -> Result<()> {
         #[derive(Serialize)]
         struct AddVersionReq {
@@ -297,6 +384,15 @@ impl Session for JiraSession {
 
         Ok(HashMap::new())
     }
+
+    // Server searches by `username` (which matches emails too), cloud by `query`
+    fn search_users(&self, query: &str) -> Result<Vec<User>> {
+        let param = if self.cloud { "query" } else { "username" };
+        let query_str = form_urlencoded::byte_serialize(query.as_bytes()).collect::<String>();
+        self.client.get::<Vec<User>>(&format!("/user/search?{}={}", param, query_str)).map_err(|e| {
+            format_err!("Error searching for JIRA users matching {}: {}", query, e)
+        })
+    }
 }
 
 fn parse_pending_version_field(field: &serde_json::Value) -> Vec<version::Version> {
@@ -0,0 +1,189 @@
+use std::fs;
+use std::sync::Mutex;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::{JiraConfig, JiraOAuthConfig};
+use crate::db;
+use crate::errors::*;
+use crate::faults;
+use crate::http_client::Authorizer;
+use crate::resilience;
+
+const TOKEN_URL: &str = "https://auth.atlassian.com/oauth/token";
+
+// refresh access tokens a little before they actually expire
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+pub fn basic_auth(config: &JiraConfig) -> String {
+    format!("Basic {}", base64::encode(format!("{}:{}", config.username, config.password).as_bytes()))
+}
+
+#[derive(Serialize)]
+struct RefreshReq<'a> {
+    grant_type: &'a str,
+    client_id: &'a str,
+    client_secret: &'a str,
+    refresh_token: &'a str,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct TokenResp {
+    access_token: String,
+    expires_in: i64,
+    // only present if refresh tokens rotate
+    refresh_token: Option<String>,
+}
+
+struct Tokens {
+    access_token: String,
+    expires_at: i64,
+    refresh_token: String,
+}
+
+impl Tokens {
+    fn needs_refresh(&self, now: i64) -> bool {
+        self.access_token.is_empty() || now + EXPIRY_MARGIN_SECS >= self.expires_at
+    }
+
+    fn update(&mut self, resp: TokenResp, now: i64) -> bool {
+        self.access_token = resp.access_token;
+        self.expires_at = now + resp.expires_in;
+        match resp.refresh_token {
+            Some(ref token) if *token != self.refresh_token => {
+                self.refresh_token = token.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Atlassian Cloud OAuth 2.0 (3LO) access tokens, refreshed as they expire
+pub struct OAuthTokens {
+    config: JiraOAuthConfig,
+    client: reqwest::Client,
+    tokens: Mutex<Tokens>,
+}
+
+impl OAuthTokens {
+    pub fn new(config: &JiraOAuthConfig) -> OAuthTokens {
+        OAuthTokens {
+            config: config.clone(),
+            client: resilience::client(faults::Service::Jira),
+            tokens: Mutex::new(Tokens {
+                access_token: String::new(),
+                expires_at: 0,
+                refresh_token: initial_refresh_token(config),
+            }),
+        }
+    }
+
+    fn refresh(&self, refresh_token: &str) -> Result<TokenResp> {
+        let req = RefreshReq {
+            grant_type: "refresh_token",
+            client_id: &self.config.client_id,
+            client_secret: &self.config.client_secret,
+            refresh_token: refresh_token,
+        };
+        self.client
+            .post(TOKEN_URL)
+            .json(&req)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .and_then(|mut r| r.json::<TokenResp>())
+            .map_err(|e| format_err!("Error refreshing JIRA access token: {}", e))
+    }
+
+    fn save_refresh_token(&self, refresh_token: &str) {
+        if let Some(ref token_file) = self.config.token_file {
+            if let Err(e) = fs::write(token_file, refresh_token) {
+                error!("Error saving JIRA refresh token to {}: {}", token_file, e);
+            }
+        }
+    }
+}
+
+impl Authorizer for OAuthTokens {
+    fn authorization(&self) -> Result<String> {
+        let now = db::now();
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.needs_refresh(now) {
+            let resp = self.refresh(&tokens.refresh_token)?;
+            if tokens.update(resp, now) {
+                info!("JIRA refresh token rotated");
+                self.save_refresh_token(&tokens.refresh_token);
+            }
+        }
+
+        Ok(format!("Bearer {}", tokens.access_token))
+    }
+}
+
+// The latest saved refresh token, if any, since the configured one stops working once it has rotated
+fn initial_refresh_token(config: &JiraOAuthConfig) -> String {
+    config
+        .token_file
+        .as_ref()
+        .and_then(|f| fs::read_to_string(f).ok())
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .unwrap_or(config.refresh_token.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn oauth_config(token_file: Option<String>) -> JiraOAuthConfig {
+        JiraOAuthConfig {
+            client_id: "the-client".into(),
+            client_secret: "the-secret".into(),
+            cloud_id: "the-cloud".into(),
+            refresh_token: "configured-refresh-token".into(),
+            token_file: token_file,
+        }
+    }
+
+    #[test]
+    fn test_tokens_update() {
+        let mut tokens = Tokens {
+            access_token: String::new(),
+            expires_at: 0,
+            refresh_token: "refresh-1".into(),
+        };
+        assert!(tokens.needs_refresh(1000));
+
+        let resp: TokenResp =
+            serde_json::from_str(r#"{"access_token": "access-1", "expires_in": 3600, "scope": "read:jira-work"}"#)
+                .unwrap();
+        assert_eq!(false, tokens.update(resp, 1000));
+        assert_eq!("access-1", tokens.access_token);
+        assert!(!tokens.needs_refresh(1000));
+        assert!(!tokens.needs_refresh(1000 + 3600 - EXPIRY_MARGIN_SECS - 1));
+        assert!(tokens.needs_refresh(1000 + 3600 - EXPIRY_MARGIN_SECS));
+
+        let resp: TokenResp = serde_json::from_str(
+            r#"{"access_token": "access-2", "expires_in": 3600, "refresh_token": "refresh-2"}"#,
+        )
+        .unwrap();
+        assert_eq!(true, tokens.update(resp, 5000));
+        assert_eq!("refresh-2", tokens.refresh_token);
+        assert_eq!(8600, tokens.expires_at);
+    }
+
+    #[test]
+    fn test_initial_refresh_token() {
+        let temp_dir = TempDir::new("auth.rs").unwrap();
+        let token_file = temp_dir.path().join("jira-token").to_string_lossy().into_owned();
+
+        assert_eq!("configured-refresh-token", initial_refresh_token(&oauth_config(None)));
+        assert_eq!("configured-refresh-token", initial_refresh_token(&oauth_config(Some(token_file.clone()))));
+
+        fs::write(&token_file, "saved-refresh-token\n").unwrap();
+        assert_eq!("saved-refresh-token", initial_refresh_token(&oauth_config(Some(token_file))));
+    }
+}
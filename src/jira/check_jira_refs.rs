@@ -29,7 +29,8 @@ pub fn check_jira_refs(
 
     // Skip PRs titled accordingly.
     if let Some(commit_type) = conventional_commit_jira_skip_type(&pull_request.title) {
-        if let Err(e) = do_skip_jira_check(pull_request, commit_type, github) {
+        let reason = format!("Skipped JIRA check for commit type: {}", commit_type);
+        if let Err(e) = do_skip_jira_check(pull_request, &reason, github) {
             log::error!("Error marking skipped jira refs: {}", e);
         }
         return;
@@ -86,17 +87,32 @@ fn conventional_commit_jira_skip_type(title: &str) -> Option<&str> {
     }
 }
 
+// Marks the JIRA check as skipped, e.g. for dependency updates that only change lockfiles.
+pub fn skip_jira_refs(
+    pull_request: &github::PullRequest,
+    projects: &Vec<String>,
+    reason: &str,
+    github: &dyn github::api::Session) {
+
+    if projects.is_empty() {
+        return;
+    }
+
+    if let Err(e) = do_skip_jira_check(pull_request, reason, github) {
+        log::error!("Error marking skipped jira refs: {}", e);
+    }
+}
+
 fn do_skip_jira_check(
     pull_request: &github::PullRequest,
-    commit_type: &str,
+    reason: &str,
     github: &dyn github::api::Session) -> Result<()> {
 
     let msg = "Skipped JIRA check";
-    let body = format!("Skipped JIRA check for commit type: {}", commit_type);
 
     let mut run = github::CheckRun::new(JIRA_REF_CONTEXT, pull_request, None);
     run = run.completed(github::Conclusion::Neutral);
-    run.output = Some(github::CheckOutput::new(&msg, &body));
+    run.output = Some(github::CheckOutput::new(&msg, reason));
 
     github.create_check_run(pull_request, &run)?;
 
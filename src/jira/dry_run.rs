@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::info;
+
+use crate::errors::*;
+use crate::jira::api::{JiraVersionPosition, Session};
+use crate::jira::models::*;
+use crate::version;
+
+// Looks things up in JIRA as usual, but only logs the changes it would make
+pub struct DryRunSession {
+    session: Arc<dyn Session>,
+}
+
+impl DryRunSession {
+    pub fn new(session: Arc<dyn Session>) -> DryRunSession {
+        DryRunSession { session: session }
+    }
+}
+
+// The session to use for a repo: a dry run one if the repo is in dry run mode
+pub fn for_repo(session: Option<Arc<dyn Session>>, dry_run: bool) -> Option<Arc<dyn Session>> {
+    match session {
+        Some(s) if dry_run => Some(Arc::new(DryRunSession::new(s))),
+        s => s,
+    }
+}
+
+impl Session for DryRunSession {
+    fn refresh_auth(&self) -> Result<()> {
+        self.session.refresh_auth()
+    }
+
+    fn get_issue(&self, key: &str) -> Result<Issue> {
+        self.session.get_issue(key)
+    }
+
+    fn get_transitions(&self, key: &str) -> Result<Vec<Transition>> {
+        self.session.get_transitions(key)
+    }
+
+    fn get_project_statuses(&self, proj: &str) -> Result<Vec<Status>> {
+        self.session.get_project_statuses(proj)
+    }
+
+    fn transition_issue(&self, key: &str, transition: &TransitionRequest) -> Result<()> {
+        let target = transition.transition.name.as_ref().or(transition.transition.id.as_ref());
+        info!("Dry run: not transitioning {} with {}", key, target.map(|t| t.as_str()).unwrap_or(""));
+        Ok(())
+    }
+
+    fn comment_issue(&self, key: &str, comment: &str) -> Result<()> {
+        info!("Dry run: not commenting on {}: {}", key, comment);
+        Ok(())
+    }
+
+    fn get_comments(&self, key: &str) -> Result<Vec<Comment>> {
+        self.session.get_comments(key)
+    }
+
+    fn update_comment(&self, key: &str, comment_id: &str, comment: &str) -> Result<()> {
+        info!("Dry run: not updating comment {} on {}: {}", comment_id, key, comment);
+        Ok(())
+    }
+
+    fn add_remote_link(&self, key: &str, link: &RemoteLink) -> Result<()> {
+        info!("Dry run: not linking {} to {}", key, link.object.url);
+        Ok(())
+    }
+
+    fn add_worklog(&self, key: &str, time_spent: &str, comment: &str) -> Result<()> {
+        info!("Dry run: not logging {} of work on {}: {}", time_spent, key, comment);
+        Ok(())
+    }
+
+    fn add_version(&self, proj: &str, version: &str) -> Result<()> {
+        info!("Dry run: not adding version {} to {}", version, proj);
+        Ok(())
+    }
+
+    fn get_versions(&self, proj: &str) -> Result<Vec<Version>> {
+        self.session.get_versions(proj)
+    }
+
+    fn assign_fix_version(&self, key: &str, version: &str) -> Result<()> {
+        info!("Dry run: not assigning fix version {} to {}", version, key);
+        Ok(())
+    }
+
+    fn reorder_version(&self, version: &Version, position: JiraVersionPosition) -> Result<()> {
+        info!("Dry run: not moving version {} ({:?})", version.name, position);
+        Ok(())
+    }
+
+    fn add_pending_version(&self, key: &str, version: &str) -> Result<()> {
+        info!("Dry run: not adding pending version {} to {}", version, key);
+        Ok(())
+    }
+
+    fn remove_pending_versions(&self, key: &str, versions: &Vec<version::Version>) -> Result<()> {
+        let versions = versions.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        info!("Dry run: not removing pending versions {} from {}", versions.join(", "), key);
+        Ok(())
+    }
+
+    fn find_pending_versions(&self, proj: &str) -> Result<HashMap<String, Vec<version::Version>>> {
+        self.session.find_pending_versions(proj)
+    }
+
+    fn search_users(&self, query: &str) -> Result<Vec<User>> {
+        self.session.search_users(query)
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::jira;
+use crate::scheduler;
+
+pub const VERSION_SYNC_INTERVAL_SECS: u64 = 6 * 60 * 60;
+pub const TOKEN_REFRESH_INTERVAL_SECS: u64 = 12 * 60 * 60;
+
+// The JIRA projects of all configured repos
+fn all_projects(config: &Config) -> Result<Vec<String>> {
+    let mut projects: Vec<String> = vec![];
+    for info in config.repos().get_all()? {
+        for jira_config in info.jira_config {
+            if !jira_config.jira_project.is_empty() && !projects.contains(&jira_config.jira_project) {
+                projects.push(jira_config.jira_project);
+            }
+        }
+    }
+    Ok(projects)
+}
+
+// Keeps the versions of every configured project sorted, as merging pending versions does
+pub struct VersionSorter {
+    config: Arc<Config>,
+    jira: Arc<dyn jira::api::Session>,
+}
+
+impl VersionSorter {
+    pub fn new(config: Arc<Config>, jira: Arc<dyn jira::api::Session>) -> Arc<dyn scheduler::Task> {
+        Arc::new(VersionSorter {
+            config: config,
+            jira: jira,
+        })
+    }
+}
+
+impl scheduler::Task for VersionSorter {
+    fn run(&self, _now: i64) -> Result<()> {
+        let projects = all_projects(&self.config)?;
+        let mut failed = vec![];
+        for project in &projects {
+            if let Err(e) = jira::workflow::sort_versions(project, self.jira.as_ref()) {
+                error!("Error sorting versions of JIRA project {}: {}", project, e);
+                failed.push(project.clone());
+            }
+        }
+
+        info!("Sorted the versions of {} JIRA project(s)", projects.len() - failed.len());
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format_err!("Error sorting versions of JIRA projects: {}", failed.join(", ")))
+        }
+    }
+}
+
+// Refreshes JIRA's OAuth access token even while nothing else uses JIRA, since Atlassian's refresh tokens expire
+// once they go unused for long enough
+pub struct TokenRefresher {
+    jira: Arc<dyn jira::api::Session>,
+}
+
+impl TokenRefresher {
+    pub fn new(jira: Arc<dyn jira::api::Session>) -> Arc<dyn scheduler::Task> {
+        Arc::new(TokenRefresher { jira: jira })
+    }
+}
+
+impl scheduler::Task for TokenRefresher {
+    fn run(&self, _now: i64) -> Result<()> {
+        self.jira.refresh_auth()
+    }
+}
@@ -1,8 +1,14 @@
 pub mod api;
+pub mod auth;
+pub mod dry_run;
+pub mod maintenance;
 mod models;
+pub mod multi;
+pub mod smart_commits;
+pub mod webhooks;
 pub mod workflow;
 mod check_jira_refs;
 
 pub use self::models::*;
 
-pub use self::check_jira_refs::check_jira_refs;
+pub use self::check_jira_refs::{check_jira_refs, skip_jira_refs};
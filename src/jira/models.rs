@@ -4,6 +4,33 @@ use serde_derive::{Deserialize, Serialize};
 pub struct Issue {
     pub key: String,
     pub status: Option<Status>,
+    #[serde(default)]
+    pub fields: IssueFields,
+}
+
+// Only the fields octobot uses
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct IssueFields {
+    pub summary: Option<String>,
+}
+
+// Server/Data Center identifies users by `name`; Cloud only by `accountId`
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct User {
+    pub name: Option<String>,
+    #[serde(rename = "accountId")]
+    pub account_id: Option<String>,
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
+    // Cloud only includes it if the user's profile allows
+    #[serde(rename = "emailAddress")]
+    pub email_address: Option<String>,
+}
+
+impl User {
+    pub fn id(&self) -> &str {
+        self.account_id.as_ref().or(self.name.as_ref()).map(|s| s.as_str()).unwrap_or("")
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -11,11 +38,63 @@ pub struct Status {
     pub name: String,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Comment {
+    #[serde(default)]
+    pub id: String,
     pub body: String,
 }
 
+impl Comment {
+    pub fn new(id: &str, body: &str) -> Comment {
+        Comment {
+            id: id.into(),
+            body: body.into(),
+        }
+    }
+}
+
+// A link from an issue to something outside JIRA, e.g. a pull request. Links with the same global id are updated
+// rather than added again.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RemoteLink {
+    #[serde(rename = "globalId")]
+    pub global_id: String,
+    pub application: RemoteLinkApplication,
+    pub relationship: String,
+    pub object: RemoteLinkObject,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RemoteLinkApplication {
+    #[serde(rename = "type")]
+    pub app_type: String,
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RemoteLinkObject {
+    pub url: String,
+    pub title: String,
+    pub summary: String,
+    pub icon: RemoteLinkIcon,
+    pub status: RemoteLinkStatus,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RemoteLinkIcon {
+    #[serde(rename = "url16x16")]
+    pub url: String,
+    pub title: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RemoteLinkStatus {
+    // resolved links are shown struck through
+    pub resolved: bool,
+    pub icon: RemoteLinkIcon,
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Transition {
     pub id: String,
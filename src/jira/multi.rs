@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use failure::format_err;
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::jira::api::{JiraSession, JiraVersionPosition, Session};
+use crate::jira::models::*;
+use crate::version;
+
+struct Instance {
+    projects: Vec<String>,
+    // versions are only identified by their URL
+    api_base: String,
+    session: Arc<dyn Session>,
+}
+
+// Sends each call to the JIRA instance that has the issue's (or version's) project
+pub struct MultiSession {
+    default: Arc<dyn Session>,
+    instances: Vec<Instance>,
+}
+
+fn project_of(key: &str) -> &str {
+    match key.rfind('-') {
+        Some(i) => &key[0..i],
+        None => key,
+    }
+}
+
+impl MultiSession {
+    pub fn new(default: Arc<dyn Session>) -> MultiSession {
+        MultiSession {
+            default: default,
+            instances: vec![],
+        }
+    }
+
+    pub fn with_instance(mut self, projects: Vec<String>, api_base: &str, session: Arc<dyn Session>) -> MultiSession {
+        self.instances.push(Instance {
+            projects: projects,
+            api_base: api_base.into(),
+            session: session,
+        });
+        self
+    }
+
+    fn for_project(&self, project: &str) -> &dyn Session {
+        match self.instances.iter().find(|i| i.projects.iter().any(|p| p == project)) {
+            Some(i) => i.session.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+
+    fn for_key(&self, key: &str) -> &dyn Session {
+        self.for_project(project_of(key))
+    }
+
+    fn for_version(&self, version: &Version) -> &dyn Session {
+        match self.instances.iter().find(|i| version.uri.starts_with(&i.api_base)) {
+            Some(i) => i.session.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+}
+
+impl Session for MultiSession {
+    fn refresh_auth(&self) -> Result<()> {
+        self.default.refresh_auth()?;
+        for instance in &self.instances {
+            instance.session.refresh_auth()?;
+        }
+        Ok(())
+    }
+
+    fn get_issue(&self, key: &str) -> Result<Issue> {
+        self.for_key(key).get_issue(key)
+    }
+
+    fn get_transitions(&self, key: &str) -> Result<Vec<Transition>> {
+        self.for_key(key).get_transitions(key)
+    }
+
+    fn get_project_statuses(&self, proj: &str) -> Result<Vec<Status>> {
+        self.for_project(proj).get_project_statuses(proj)
+    }
+
+    fn transition_issue(&self, key: &str, transition: &TransitionRequest) -> Result<()> {
+        self.for_key(key).transition_issue(key, transition)
+    }
+
+    fn comment_issue(&self, key: &str, comment: &str) -> Result<()> {
+        self.for_key(key).comment_issue(key, comment)
+    }
+
+    fn get_comments(&self, key: &str) -> Result<Vec<Comment>> {
+        self.for_key(key).get_comments(key)
+    }
+
+    fn update_comment(&self, key: &str, comment_id: &str, comment: &str) -> Result<()> {
+        self.for_key(key).update_comment(key, comment_id, comment)
+    }
+
+    fn add_remote_link(&self, key: &str, link: &RemoteLink) -> Result<()> {
+        self.for_key(key).add_remote_link(key, link)
+    }
+
+    fn add_worklog(&self, key: &str, time_spent: &str, comment: &str) -> Result<()> {
+        self.for_key(key).add_worklog(key, time_spent, comment)
+    }
+
+    fn add_version(&self, proj: &str, version: &str) -> Result<()> {
+        self.for_project(proj).add_version(proj, version)
+    }
+
+    fn get_versions(&self, proj: &str) -> Result<Vec<Version>> {
+        self.for_project(proj).get_versions(proj)
+    }
+
+    fn assign_fix_version(&self, key: &str, version: &str) -> Result<()> {
+        self.for_key(key).assign_fix_version(key, version)
+    }
+
+    fn reorder_version(&self, version: &Version, position: JiraVersionPosition) -> Result<()> {
+        self.for_version(version).reorder_version(version, position)
+    }
+
+    fn add_pending_version(&self, key: &str, version: &str) -> Result<()> {
+        self.for_key(key).add_pending_version(key, version)
+    }
+
+    fn remove_pending_versions(&self, key: &str, versions: &Vec<version::Version>) -> Result<()> {
+        self.for_key(key).remove_pending_versions(key, versions)
+    }
+
+    fn find_pending_versions(&self, proj: &str) -> Result<HashMap<String, Vec<version::Version>>> {
+        self.for_project(proj).find_pending_versions(proj)
+    }
+
+    // users are looked up on the default instance
+    fn search_users(&self, query: &str) -> Result<Vec<User>> {
+        self.default.search_users(query)
+    }
+}
+
+// A session for [jira], which also routes to any [[jira_instances]]
+pub fn new_session(config: &Config) -> Result<Option<Arc<dyn Session>>> {
+    let jira_config = match config.jira {
+        Some(ref j) => j,
+        None => return Ok(None),
+    };
+    let default: Arc<dyn Session> = Arc::new(JiraSession::new(jira_config)?);
+
+    let instances = match config.jira_instances {
+        Some(ref i) if !i.is_empty() => i,
+        _ => return Ok(Some(default)),
+    };
+
+    let mut session = MultiSession::new(default);
+    for instance in instances {
+        let projects = instance.projects.clone().unwrap_or(vec![]);
+        if projects.is_empty() {
+            return Err(format_err!("JIRA instance {} has no projects", instance.host));
+        }
+        let instance_session = JiraSession::new(instance)
+            .map_err(|e| format_err!("Error initiating session for JIRA instance {}: {}", instance.host, e))?;
+        session = session.with_instance(projects, &instance.api_base(), Arc::new(instance_session));
+    }
+
+    Ok(Some(Arc::new(session)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_of() {
+        assert_eq!("SER", project_of("SER-123"));
+        assert_eq!("SER2", project_of("SER2-1"));
+        assert_eq!("SER", project_of("SER"));
+    }
+}
@@ -0,0 +1,310 @@
+use failure::format_err;
+use log::{error, info};
+use regex::Regex;
+
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github::{Commit, CommitLike};
+use crate::jira::api::Session;
+use crate::jira::Transition;
+
+// Commands allowed when a repo enables smart commits without listing any: transitions have to be opted into
+pub const DEFAULT_COMMANDS: [&str; 2] = ["comment", "time"];
+
+// One command of a smart commit, e.g. `#time 2h 30m Wrote the tests`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SmartCommand {
+    Comment(String),
+    // `spent` is in JIRA's duration format, e.g. "1d 2h"
+    Time { spent: String, comment: String },
+    // any other command names a transition, e.g. `#resolve` or `#start-progress`
+    Transition { name: String, comment: String },
+}
+
+impl SmartCommand {
+    // What a repo's list of allowed commands names it by
+    pub fn name(&self) -> &str {
+        match *self {
+            SmartCommand::Comment(_) => "comment",
+            SmartCommand::Time { .. } => "time",
+            SmartCommand::Transition { ref name, .. } => name,
+        }
+    }
+}
+
+// The commands on one line of a commit message, for all the issue keys before them
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmartCommit {
+    pub keys: Vec<String>,
+    pub commands: Vec<SmartCommand>,
+}
+
+fn is_duration(word: &str) -> bool {
+    let re = Regex::new(r"^[0-9]+(\.[0-9]+)?[wdhm]$").unwrap();
+    re.is_match(word)
+}
+
+fn parse_command(name: &str, args: &str) -> Option<SmartCommand> {
+    match name {
+        "comment" if !args.is_empty() => Some(SmartCommand::Comment(args.into())),
+        "comment" => None,
+        "time" => {
+            let words: Vec<&str> = args.split_whitespace().collect();
+            let n = words.iter().take_while(|w| is_duration(w)).count();
+            if n == 0 {
+                return None;
+            }
+            Some(SmartCommand::Time {
+                spent: words[0..n].join(" "),
+                comment: words[n..].join(" "),
+            })
+        }
+        _ => Some(SmartCommand::Transition {
+            name: name.into(),
+            comment: args.into(),
+        }),
+    }
+}
+
+// Parses `KEY-1 [KEY-2 ...] <ignored text> #command <args> [#command <args> ...]` from each line of `message`,
+// for keys of `projects`. Commands must start with a letter, so that "#123" PR references are left alone.
+pub fn parse(message: &str, projects: &Vec<String>) -> Vec<SmartCommit> {
+    let key_re = Regex::new(r"\b([A-Z][A-Z0-9]*-[0-9]+)\b").unwrap();
+    let command_re = Regex::new(r"(?:^|\s)#([A-Za-z][A-Za-z0-9_-]*)").unwrap();
+
+    let mut result = vec![];
+    for line in message.lines() {
+        let mut keys: Vec<String> = vec![];
+        let mut first_key_end = None;
+        for m in key_re.find_iter(line) {
+            let key = m.as_str().to_string();
+            let project = &key[0..key.rfind('-').unwrap_or(key.len())];
+            if projects.iter().any(|p| p == project) && !keys.contains(&key) {
+                keys.push(key);
+                first_key_end = first_key_end.or(Some(m.end()));
+            }
+        }
+        let first_key_end = match first_key_end {
+            Some(e) => e,
+            None => continue,
+        };
+
+        let found: Vec<_> =
+            command_re.captures_iter(line).filter(|c| c.get(0).unwrap().start() >= first_key_end).collect();
+        let mut commands = vec![];
+        for (i, c) in found.iter().enumerate() {
+            let name = c.get(1).unwrap();
+            let args_end = found.get(i + 1).map(|next| next.get(0).unwrap().start()).unwrap_or(line.len());
+            if let Some(command) = parse_command(&name.as_str().to_lowercase(), line[name.end()..args_end].trim()) {
+                commands.push(command);
+            }
+        }
+
+        if !commands.is_empty() {
+            result.push(SmartCommit {
+                keys: keys,
+                commands: commands,
+            });
+        }
+    }
+    result
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase().split_whitespace().collect::<Vec<_>>().join("-")
+}
+
+// Like JIRA: `#start-progress` matches "Start Progress", and `#resolve` "Resolve Issue" if no other one starts so
+fn pick_transition(name: &str, transitions: &Vec<Transition>) -> Option<Transition> {
+    let exact = transitions.iter().find(|t| normalize(&t.name) == name || normalize(&t.to.name) == name);
+    if let Some(t) = exact {
+        return Some(t.clone());
+    }
+
+    let prefixed: Vec<&Transition> = transitions.iter().filter(|t| normalize(&t.name).starts_with(name)).collect();
+    if prefixed.len() == 1 {
+        Some(prefixed[0].clone())
+    } else {
+        None
+    }
+}
+
+fn attributed(text: &str, author: &str, commit: &dyn CommitLike) -> String {
+    let via = format!("{} in commit [{}|{}]", author, Commit::short_hash(commit), commit.html_url());
+    if text.is_empty() {
+        format!("Smart commit by {}", via)
+    } else {
+        format!("{}\n\n(smart commit by {})", text, via)
+    }
+}
+
+fn apply_command(
+    key: &str,
+    command: &SmartCommand,
+    author: &str,
+    commit: &dyn CommitLike,
+    jira: &dyn Session,
+) -> Result<()> {
+    match *command {
+        SmartCommand::Comment(ref text) => jira.comment_issue(key, &attributed(text, author, commit)),
+        SmartCommand::Time {
+            ref spent,
+            ref comment,
+        } => jira.add_worklog(key, spent, &attributed(comment, author, commit)),
+        SmartCommand::Transition {
+            ref name,
+            ref comment,
+        } => {
+            let transition = match pick_transition(name, &jira.get_transitions(key)?) {
+                Some(t) => t,
+                None => return Err(format_err!("[{}] has no transition matching #{}", key, name)),
+            };
+            jira.transition_issue(key, &transition.new_request())?;
+            info!("Transitioned [{}] to {} for a smart commit", key, transition.to.name);
+            if !comment.is_empty() {
+                jira.comment_issue(key, &attributed(comment, author, commit))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// Applies the smart commit commands in `commit` that are in `allowed`. `author` is who gets credit in JIRA, since
+// octobot makes the changes as its own user.
+pub fn apply(commit: &dyn CommitLike, author: &str, projects: &Vec<String>, allowed: &Vec<String>, jira: &dyn Session) {
+    for smart_commit in parse(commit.message(), projects) {
+        for command in &smart_commit.commands {
+            if !allowed.iter().any(|a| a == command.name()) {
+                info!("Ignoring #{} in commit {}: not allowed for this repo", command.name(), commit.sha());
+                continue;
+            }
+            for key in &smart_commit.keys {
+                if let Err(e) = apply_command(key, command, author, commit, jira) {
+                    error!("Error applying #{} to [{}]: {}", command.name(), key, e);
+                }
+            }
+        }
+    }
+}
+
+// Remembers the commits whose smart commands were applied: a commit is usually pushed to a branch and then again to
+// master when its PR is merged, but should only log its time once.
+#[derive(Clone)]
+pub struct AppliedSmartCommits {
+    db: Database,
+}
+
+impl AppliedSmartCommits {
+    pub fn new(db: Database) -> AppliedSmartCommits {
+        AppliedSmartCommits { db: db }
+    }
+
+    // Returns false if the commit was already applied
+    pub fn mark_applied(&self, repo: &str, sha: &str) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let changed = conn
+            .execute(
+                r#"INSERT INTO jira_smart_commits (repo, sha, applied_at)
+                   VALUES (?1, ?2, ?3)
+                   ON CONFLICT DO NOTHING"#,
+                &[&repo as &dyn ToSql, &sha, &db::now()],
+            )
+            .map_err(|e| format_err!("Error saving smart commit {}: {}", sha, e))?;
+
+        Ok(changed > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jira::TransitionTo;
+    use tempdir::TempDir;
+
+    fn projects() -> Vec<String> {
+        vec!["SER".into(), "CLI".into()]
+    }
+
+    fn transition(name: &str, to: &str) -> Transition {
+        Transition {
+            id: String::new(),
+            name: name.into(),
+            to: TransitionTo {
+                id: String::new(),
+                name: to.into(),
+            },
+            fields: None,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            vec![SmartCommit {
+                keys: vec!["SER-123".into()],
+                commands: vec![
+                    SmartCommand::Time {
+                        spent: "1d 2.5h".into(),
+                        comment: "wrote tests".into(),
+                    },
+                    SmartCommand::Comment("fixed it".into()),
+                    SmartCommand::Transition {
+                        name: "resolve".into(),
+                        comment: String::new(),
+                    },
+                ],
+            }],
+            parse("SER-123 #time 1d 2.5h wrote tests #comment fixed it #Resolve", &projects())
+        );
+    }
+
+    #[test]
+    fn test_parse_lines_and_keys() {
+        let message = "Fix the thing (#42)\n\nSER-1 CLI-2 OTHER-3 #start-progress\nCLI-3 #comment \n#comment SER-4";
+        assert_eq!(
+            vec![SmartCommit {
+                keys: vec!["SER-1".into(), "CLI-2".into()],
+                commands: vec![SmartCommand::Transition {
+                    name: "start-progress".into(),
+                    comment: String::new(),
+                }],
+            }],
+            parse(message, &projects())
+        );
+    }
+
+    #[test]
+    fn test_parse_ignored() {
+        assert!(parse("SER-1 fixes #123", &projects()).is_empty());
+        assert!(parse("SER-1 #time lots", &projects()).is_empty());
+        assert!(parse("SER-1 issue#comment nope", &projects()).is_empty());
+        assert!(parse("OTHER-1 #comment not ours", &projects()).is_empty());
+    }
+
+    #[test]
+    fn test_pick_transition() {
+        let transitions = vec![
+            transition("Start Progress", "In Progress"),
+            transition("Resolve Issue", "Resolved"),
+            transition("Reopen", "Open"),
+            transition("Review", "In Review"),
+        ];
+        assert_eq!("Start Progress", pick_transition("start-progress", &transitions).unwrap().name);
+        assert_eq!("Start Progress", pick_transition("in-progress", &transitions).unwrap().name);
+        assert_eq!("Resolve Issue", pick_transition("resolve", &transitions).unwrap().name);
+        // ambiguous
+        assert_eq!(None, pick_transition("re", &transitions));
+        assert_eq!(None, pick_transition("close", &transitions));
+    }
+
+    #[test]
+    fn test_mark_applied() {
+        let temp_dir = TempDir::new("smart_commits.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let applied = AppliedSmartCommits::new(Database::new(&db_file.to_string_lossy()).unwrap());
+
+        assert!(applied.mark_applied("some-org/some-repo", "abc").unwrap());
+        assert!(!applied.mark_applied("some-org/some-repo", "abc").unwrap());
+        assert!(applied.mark_applied("some-org/other-repo", "abc").unwrap());
+    }
+}
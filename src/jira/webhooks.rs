@@ -0,0 +1,293 @@
+use log::{error, info};
+use regex::Regex;
+use serde_derive::Deserialize;
+
+use crate::config::{Config, JiraConfig};
+use crate::github;
+use crate::jira::User;
+use crate::messenger::Messenger;
+use crate::slack::{SlackAttachment, SlackAttachmentBuilder};
+use crate::util;
+
+// comments can be long: the DM only needs enough to know whether to go read it
+const MAX_COMMENT_CHARS: usize = 500;
+
+// The parts of a JIRA webhook delivery that octobot notifies about
+#[derive(Deserialize, Debug)]
+pub struct WebhookEvent {
+    #[serde(rename = "webhookEvent")]
+    pub event: String,
+    // JIRA Server sends comments as issue updates of this type
+    pub issue_event_type_name: Option<String>,
+    // who made the change
+    pub user: Option<User>,
+    pub issue: Option<WebhookIssue>,
+    pub comment: Option<WebhookComment>,
+    pub changelog: Option<Changelog>,
+    pub version: Option<WebhookVersion>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookIssue {
+    pub key: String,
+    pub fields: WebhookIssueFields,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookIssueFields {
+    #[serde(default)]
+    pub summary: String,
+    pub assignee: Option<User>,
+    pub reporter: Option<User>,
+    pub project: Option<WebhookProject>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookProject {
+    pub key: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookComment {
+    #[serde(default)]
+    pub body: String,
+    pub author: Option<User>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Changelog {
+    #[serde(default)]
+    pub items: Vec<ChangelogItem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangelogItem {
+    pub field: String,
+    #[serde(rename = "fromString")]
+    pub from: Option<String>,
+    #[serde(rename = "toString")]
+    pub to: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookVersion {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+impl WebhookEvent {
+    // The status change, if this is an issue transition
+    pub fn transition(&self) -> Option<&ChangelogItem> {
+        if self.event != "jira:issue_updated" {
+            return None;
+        }
+        self.changelog.as_ref().and_then(|c| c.items.iter().find(|i| i.field == "status"))
+    }
+
+    pub fn is_comment(&self) -> bool {
+        self.comment.is_some() &&
+            (self.event == "comment_created" ||
+                 self.issue_event_type_name.as_ref().map(|t| t == "issue_commented").unwrap_or(false))
+    }
+
+    pub fn is_version_released(&self) -> bool {
+        self.event == "jira:version_released" && self.version.is_some()
+    }
+}
+
+fn user_name(user: Option<&User>) -> String {
+    user.and_then(|u| u.display_name.clone().or(u.name.clone())).unwrap_or("Someone".into())
+}
+
+fn issue_link(jira: &JiraConfig, issue: &WebhookIssue) -> String {
+    let url = format!("{}/browse/{}", jira.base_url(), issue.key);
+    if issue.fields.summary.is_empty() {
+        util::make_link(&url, &issue.key)
+    } else {
+        util::make_link(&url, &format!("{}: {}", issue.key, issue.fields.summary))
+    }
+}
+
+// JIRA Server mentions look like [~username]. Cloud's [~accountid:...] can't be mapped back to our users.
+fn mentioned_users(comment: &str) -> Vec<User> {
+    let re = Regex::new(r"\[~([^\]]+)\]").unwrap();
+    re.captures_iter(comment)
+        .map(|c| c[1].to_string())
+        .filter(|name| !name.starts_with("accountid:"))
+        .map(|name| User {
+            name: Some(name),
+            account_id: None,
+            display_name: None,
+            email_address: None,
+        })
+        .collect()
+}
+
+// Reverse of the github -> slack user mapping: a JIRA user is ours if their email address matches, or if their
+// username is a github login (plus the instance's login_suffix, if it has one)
+pub fn github_login(user: &User, jira: &JiraConfig, config: &Config) -> Option<String> {
+    if let Some(ref email) = user.email_address {
+        if let Some(u) = config.users().lookup_by_email(email) {
+            return Some(u.github);
+        }
+    }
+
+    let name = user.name.as_ref()?;
+    let name = match jira.login_suffix {
+        Some(ref suffix) if !suffix.is_empty() && name.ends_with(suffix.as_str()) => &name[..name.len() - suffix.len()],
+        _ => name.as_str(),
+    };
+    config.users().lookup_info(name).map(|u| u.github)
+}
+
+fn shorten(text: &str) -> String {
+    if text.chars().count() <= MAX_COMMENT_CHARS {
+        text.to_string()
+    } else {
+        format!("{}...", text.chars().take(MAX_COMMENT_CHARS).collect::<String>())
+    }
+}
+
+// Notifies slack of a JIRA webhook event. The issue's assignee and reporter (and anyone mentioned in a comment) get
+// direct messages, except for whoever made the change. Transitions and releases also go to the channels of the
+// repos using the project. `project` is from the webhook URL, since version events only have the project's ID.
+pub fn notify(event: &WebhookEvent, project: Option<&str>, jira: &JiraConfig, config: &Config, messenger: &Messenger) {
+    if event.is_version_released() {
+        let version = event.version.as_ref().unwrap();
+        let project = match project {
+            Some(p) => p,
+            None => {
+                messenger.note("Version release has no project: add ?project=${project.key} to the webhook URL");
+                return;
+            }
+        };
+        let msg = format!("{} version {} was released", project, util::escape_for_slack(&version.name));
+        let attachments = match version.description {
+            Some(ref d) if !d.is_empty() => vec![SlackAttachmentBuilder::new(&util::escape_for_slack(d)).build()],
+            _ => vec![],
+        };
+        send_to_project_channels(project, &msg, &attachments, config, messenger);
+        return;
+    }
+
+    let issue = match event.issue {
+        Some(ref i) => i,
+        None => {
+            messenger.note(format!("Ignoring '{}' event without an issue", event.event));
+            return;
+        }
+    };
+    let project = issue.fields.project.as_ref().map(|p| p.key.clone()).unwrap_or_else(|| {
+        issue.key[0..issue.key.rfind('-').unwrap_or(issue.key.len())].to_string()
+    });
+
+    let mentioned = event.comment.as_ref().map(|c| mentioned_users(&c.body)).unwrap_or(vec![]);
+    let mut recipients: Vec<&User> = issue.fields.assignee.iter().chain(issue.fields.reporter.iter()).collect();
+    let (actor, msg, attachments, to_channels) = if let Some(transition) = event.transition() {
+        let msg = format!(
+            "{} moved {} from {} to {}",
+            util::escape_for_slack(&user_name(event.user.as_ref())),
+            issue_link(jira, issue),
+            util::escape_for_slack(transition.from.as_ref().map(|s| s.as_str()).unwrap_or("?")),
+            util::escape_for_slack(transition.to.as_ref().map(|s| s.as_str()).unwrap_or("?")),
+        );
+        (event.user.as_ref(), msg, vec![], true)
+    } else if event.is_comment() {
+        let comment = event.comment.as_ref().unwrap();
+        let msg = format!(
+            "{} commented on {}",
+            util::escape_for_slack(&user_name(comment.author.as_ref())),
+            issue_link(jira, issue)
+        );
+        let attachments = vec![SlackAttachmentBuilder::new(&util::escape_for_slack(&shorten(&comment.body))).build()];
+        (comment.author.as_ref(), msg, attachments, false)
+    } else {
+        messenger.note(format!("Ignoring '{}' event for [{}]", event.event, issue.key));
+        return;
+    };
+
+    if event.is_comment() {
+        recipients.extend(mentioned.iter());
+    }
+
+    let actor_login = actor.and_then(|a| github_login(a, jira, config));
+    let mut logins: Vec<String> = vec![];
+    for user in recipients {
+        match github_login(user, jira, config) {
+            Some(ref login) if Some(login) == actor_login.as_ref() => (),
+            Some(login) => {
+                if !logins.contains(&login) {
+                    logins.push(login);
+                }
+            }
+            None => messenger.note(format!("JIRA user '{}' is not mapped to an octobot user", user.id())),
+        };
+    }
+    for login in logins {
+        messenger.send_to_user(&github::User::new(&login), &msg, &attachments);
+    }
+
+    if to_channels {
+        send_to_project_channels(&project, &msg, &attachments, config, messenger);
+    }
+}
+
+fn send_to_project_channels(
+    project: &str,
+    msg: &str,
+    attachments: &Vec<SlackAttachment>,
+    config: &Config,
+    messenger: &Messenger,
+) {
+    match config.repos().jira_project_channels(project) {
+        Ok(ref channels) if channels.is_empty() => info!("No repos use JIRA project {}", project),
+        Ok(channels) => messenger.send_to_channels(&channels, msg, attachments),
+        Err(e) => error!("Error looking up channels for JIRA project {}: {}", project, e),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_parse_transition() {
+        let event: WebhookEvent = serde_json::from_str(
+            r#"{"webhookEvent": "jira:issue_updated", "issue_event_type_name": "issue_generic",
+                "user": {"name": "joe", "displayName": "Joe"},
+                "issue": {"key": "SER-1", "fields": {"summary": "Fix it", "project": {"key": "SER"},
+                          "assignee": {"name": "bob", "emailAddress": "bob@company.com"}}},
+                "changelog": {"items": [{"field": "resolution", "fromString": null, "toString": "Fixed"},
+                                        {"field": "status", "fromString": "Open", "toString": "Resolved"}]}}"#,
+        )
+        .unwrap();
+
+        let transition = event.transition().unwrap();
+        assert_eq!(Some("Open".to_string()), transition.from);
+        assert_eq!(Some("Resolved".to_string()), transition.to);
+        assert!(!event.is_comment());
+        assert!(!event.is_version_released());
+        assert_eq!(Some("bob@company.com".to_string()), event.issue.unwrap().fields.assignee.unwrap().email_address);
+    }
+
+    #[test]
+    fn test_parse_comment() {
+        let event: WebhookEvent = serde_json::from_str(
+            r#"{"webhookEvent": "jira:issue_updated", "issue_event_type_name": "issue_commented",
+                "issue": {"key": "SER-1", "fields": {}},
+                "comment": {"body": "Looks good [~bob]", "author": {"name": "joe"}}}"#,
+        )
+        .unwrap();
+
+        assert!(event.transition().is_none());
+        assert!(event.is_comment());
+    }
+
+    #[test]
+    fn test_mentioned_users() {
+        let users = mentioned_users("Hey [~bob] and [~accountid:1234abc], see [this|http://x] [~joe.smith]");
+        let names: Vec<_> = users.iter().map(|u| u.name.clone().unwrap()).collect();
+        assert_eq!(vec!["bob", "joe.smith"], names);
+    }
+}
@@ -4,12 +4,15 @@ use std::collections::HashMap;
 use log::{error, info};
 use failure::format_err;
 use regex::Regex;
+use serde_derive::Serialize;
+use serde_json::json;
 
 use crate::config::JiraConfig;
 use crate::errors::*;
 use crate::github::{Commit, CommitLike, PullRequest, PushCommit};
 use crate::jira;
 use crate::jira::Transition;
+use crate::templates;
 use crate::version;
 
 fn get_jira_keys(strings: Vec<String>, projects: &Vec<String>) -> Vec<String> {
@@ -84,6 +87,104 @@ fn needs_transition(state: &Option<jira::Status>, target: &Vec<String>) -> bool
     }
 }
 
+// The comment octobot keeps up to date on issues whose project has "links" pr_updates
+const SUMMARY_HEADER: &str = "Pull requests and commits (kept up to date by octobot):";
+
+const GITHUB_ICON: &str = "https://github.githubassets.com/favicons/favicon.png";
+const OPEN_ICON: &str = "https://raw.githubusercontent.com/primer/octicons/main/icons/git-pull-request-16.svg";
+const MERGED_ICON: &str = "https://raw.githubusercontent.com/primer/octicons/main/icons/git-merge-16.svg";
+
+pub fn pr_remote_link(pr: &PullRequest, merged: bool) -> jira::RemoteLink {
+    let (status, status_icon) = if merged { ("Merged", MERGED_ICON) } else { ("Open", OPEN_ICON) };
+    jira::RemoteLink {
+        global_id: format!("github-pr={}", pr.html_url),
+        application: jira::RemoteLinkApplication {
+            app_type: "com.github".into(),
+            name: "GitHub".into(),
+        },
+        relationship: "pull request".into(),
+        object: jira::RemoteLinkObject {
+            url: pr.html_url.clone(),
+            title: format!("{}#{}", pr.base.repo.full_name, pr.number),
+            summary: pr.title.clone(),
+            icon: jira::RemoteLinkIcon {
+                url: GITHUB_ICON.into(),
+                title: "GitHub".into(),
+            },
+            status: jira::RemoteLinkStatus {
+                resolved: merged,
+                icon: jira::RemoteLinkIcon {
+                    url: status_icon.into(),
+                    title: status.into(),
+                },
+            },
+        },
+    }
+}
+
+// Replaces the line about `url` in octobot's summary comment, or adds one
+fn set_summary_line(body: &str, url: &str, summary: &str) -> String {
+    let new_line = format!("* {}", summary);
+    let mut found = false;
+    let mut lines = vec![];
+    for line in body.lines() {
+        if !found && line.starts_with("* ") && line.contains(url) {
+            found = true;
+            lines.push(new_line.clone());
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    if !found {
+        lines.push(new_line);
+    }
+    lines.join("\n")
+}
+
+fn update_summary_comment(key: &str, url: &str, summary: &str, jira: &dyn jira::api::Session) -> Result<()> {
+    let comments = jira.get_comments(key)?;
+    match comments.into_iter().find(|c| c.body.starts_with(SUMMARY_HEADER)) {
+        Some(comment) => {
+            let body = set_summary_line(&comment.body, url, summary);
+            if body == comment.body {
+                return Ok(());
+            }
+            jira.update_comment(key, &comment.id, &body)
+        }
+        None => jira.comment_issue(key, &set_summary_line(SUMMARY_HEADER, url, summary)),
+    }
+}
+
+// Tells the issue about `url` (a PR or commit): with a new comment, or in "links" mode by updating its line in
+// octobot's summary comment
+fn note_issue(
+    key: &str,
+    url: &str,
+    comment: &str,
+    summary: &str,
+    jira: &dyn jira::api::Session,
+    config: &JiraConfig,
+) -> Result<()> {
+    if config.pr_updates(get_jira_project(key)).comments() {
+        jira.comment_issue(key, comment)
+    } else {
+        update_summary_comment(key, url, summary, jira)
+    }
+}
+
+fn link_pull_request(key: &str, pr: &PullRequest, merged: bool, jira: &dyn jira::api::Session, config: &JiraConfig) {
+    if !config.pr_updates(get_jira_project(key)).links() {
+        return;
+    }
+    if let Err(e) = jira.add_remote_link(key, &pr_remote_link(pr, merged)) {
+        error!("Error linking [{}] to PR: {}", key, e);
+    }
+}
+
+fn pr_summary(pr: &PullRequest, status: &str) -> String {
+    format!("Pull request [{}#{}|{}] {}", pr.base.repo.full_name, pr.number, pr.html_url, status)
+}
+
 pub fn submit_for_review(
     pr: &PullRequest,
     commits: &Vec<Commit>,
@@ -91,46 +192,68 @@ pub fn submit_for_review(
     jira: &dyn jira::api::Session,
     config: &JiraConfig,
 ) {
-    let review_states = config.review_states();
-    let progress_states = config.progress_states();
+    let repo = &pr.base.repo.full_name;
+    let comment_templates = config.templates();
+    let vars = templates::pr_vars(&pr, &pr.base.ref_name, repo, commits);
 
     for key in get_fixed_jira_keys(commits, projects) {
+        let transitions = config.transitions(get_jira_project(&key), repo);
+        link_pull_request(&key, pr, false, jira, config);
+        let comment = templates::render_or(
+            &comment_templates.review_submitted,
+            &templates::with_vars(vars.clone(), json!({ "key": key })),
+            format!("Review submitted for branch {}: {}", pr.base.ref_name, pr.html_url),
+        );
         // add comment
-        if let Err(e) = jira.comment_issue(
+        if let Err(e) = note_issue(
             &key,
-            &format!("Review submitted for branch {}: {}", pr.base.ref_name, pr.html_url),
-        )
-        {
+            &pr.html_url,
+            &comment,
+            &pr_summary(pr, &format!("is in review for branch {}", pr.base.ref_name)),
+            jira,
+            config,
+        ) {
             error!("Error commenting on key [{}]: {}", key, e);
             continue; // give up on transitioning if we can't comment.
         }
 
+        // a revert reopens the issues it undoes the fix for, rather than putting them back in review
+        if is_revert_pr(pr) {
+            continue;
+        }
+
         let issue_state = try_get_issue_state(&key, jira);
 
-        if !needs_transition(&issue_state, &review_states) {
+        if !needs_transition(&issue_state, &transitions.review) {
             continue;
         }
 
         // try to transition to in-progress
-        if needs_transition(&issue_state, &progress_states) {
-            try_transition(&key, &progress_states, jira);
+        if needs_transition(&issue_state, &transitions.progress) {
+            try_transition(&key, &transitions.progress, jira);
         }
 
         // try transition to pending-review
-        try_transition(&key, &review_states, jira);
+        try_transition(&key, &transitions.review, jira);
     }
 
     for key in get_referenced_jira_keys(commits, projects) {
+        let progress_states = config.transitions(get_jira_project(&key), repo).progress;
+        link_pull_request(&key, pr, false, jira, config);
+        let comment = templates::render_or(
+            &comment_templates.review_referenced,
+            &templates::with_vars(vars.clone(), json!({ "key": key })),
+            format!("Referenced by review submitted for branch {}: {}", pr.base.ref_name, pr.html_url),
+        );
         // add comment
-        if let Err(e) = jira.comment_issue(
+        if let Err(e) = note_issue(
             &key,
-            &format!(
-                "Referenced by review submitted for branch {}: {}",
-                pr.base.ref_name,
-                pr.html_url
-            ),
-        )
-        {
+            &pr.html_url,
+            &comment,
+            &pr_summary(pr, &format!("references this issue, in review for branch {}", pr.base.ref_name)),
+            jira,
+            config,
+        ) {
             error!("Error commenting on key [{}]: {}", key, e);
             continue; // give up on transitioning if we can't comment.
         }
@@ -146,7 +269,31 @@ pub fn submit_for_review(
     }
 }
 
+// Marks the PR's remote links as merged. Issues are resolved (and commented on) when the merge is pushed.
+pub fn mark_merged(
+    pr: &PullRequest,
+    commits: &Vec<Commit>,
+    projects: &Vec<String>,
+    jira: &dyn jira::api::Session,
+    config: &JiraConfig,
+) {
+    for key in get_all_jira_keys(commits, projects) {
+        let updates = config.pr_updates(get_jira_project(&key));
+        if !updates.links() {
+            continue;
+        }
+        link_pull_request(&key, pr, true, jira, config);
+        if !updates.comments() {
+            let summary = pr_summary(pr, &format!("was merged into branch {}", pr.base.ref_name));
+            if let Err(e) = update_summary_comment(&key, &pr.html_url, &summary, jira) {
+                error!("Error commenting on key [{}]: {}", key, e);
+            }
+        }
+    }
+}
+
 pub fn resolve_issue(
+    repo: &str,
     branch: &str,
     version: Option<&str>,
     commits: &Vec<PushCommit>,
@@ -154,7 +301,16 @@ pub fn resolve_issue(
     jira: &dyn jira::api::Session,
     config: &JiraConfig,
 ) {
+    let comment_templates = config.templates();
+
     for commit in commits {
+        let vars = json!({
+            "branch": branch,
+            "repo": repo,
+            "version": version,
+            "commit": templates::commit_vars(commit),
+        });
+
         let desc = format!(
             "[{}|{}]\n{{quote}}{}{{quote}}",
             Commit::short_hash(&commit),
@@ -169,13 +325,52 @@ pub fn resolve_issue(
 
         let fix_msg = format!("Merged into branch {}: {}{}", branch, desc, version_desc);
         let ref_msg = format!("Referenced by commit merged into branch {}: {}{}", branch, desc, version_desc);
-        let resolved_states = config.resolved_states();
+
+        // one-line versions for octobot's summary comment
+        let commit_link = format!("[{}|{}]", Commit::short_hash(&commit), commit.html_url());
+        let summary_version = match version {
+            None => String::new(),
+            Some(v) => format!(" (version {})", v),
+        };
+        let fix_summary = format!("Commit {} merged into branch {}{}", commit_link, branch, summary_version);
+        let ref_summary =
+            format!("Commit {} referencing this issue merged into branch {}{}", commit_link, branch, summary_version);
+
+        // a revert of a fix undoes it: reopen the issue instead of resolving it
+        if is_revert(commit) {
+            let revert_msg = format!("Reverted in branch {}: {}{}", branch, desc, version_desc);
+            let revert_summary =
+                format!("Commit {} reverted this in branch {}{}", commit_link, branch, summary_version);
+            for key in get_fixed_jira_keys(&vec![commit], projects) {
+                let revert_msg = templates::render_or(
+                    &comment_templates.reverted,
+                    &templates::with_vars(vars.clone(), json!({ "key": key })),
+                    revert_msg.clone(),
+                );
+                if let Err(e) = note_issue(&key, commit.html_url(), &revert_msg, &revert_summary, jira, config) {
+                    error!("Error commenting on key [{}]: {}", key, e);
+                }
+
+                let reopened_states = config.transitions(get_jira_project(&key), repo).reopened;
+                if needs_transition(&try_get_issue_state(&key, jira), &reopened_states) {
+                    try_transition(&key, &reopened_states, jira);
+                }
+            }
+            continue;
+        }
 
         for key in get_fixed_jira_keys(&vec![commit], projects) {
-            if let Err(e) = jira.comment_issue(&key, &fix_msg) {
+            let fix_msg = templates::render_or(
+                &comment_templates.merged,
+                &templates::with_vars(vars.clone(), json!({ "key": key })),
+                fix_msg.clone(),
+            );
+            if let Err(e) = note_issue(&key, commit.html_url(), &fix_msg, &fix_summary, jira, config) {
                 error!("Error commenting on key [{}]: {}", key, e);
             }
 
+            let resolved_states = config.transitions(get_jira_project(&key), repo).resolved;
+
             let issue_state = try_get_issue_state(&key, jira);
             if !needs_transition(&issue_state, &resolved_states) {
                 continue;
@@ -220,13 +415,49 @@ pub fn resolve_issue(
 
         // add comment only to referenced jiras
         for key in get_referenced_jira_keys(&vec![commit], projects) {
-            if let Err(e) = jira.comment_issue(&key, &ref_msg) {
+            let ref_msg = templates::render_or(
+                &comment_templates.merged_referenced,
+                &templates::with_vars(vars.clone(), json!({ "key": key })),
+                ref_msg.clone(),
+            );
+            if let Err(e) = note_issue(&key, commit.html_url(), &ref_msg, &ref_summary, jira, config) {
                 error!("Error commenting on key [{}]: {}", key, e);
             }
         }
     }
 }
 
+// Tells the issues the reverted PR fixed about the revert PR and reopens them
+pub fn reopen_reverted(
+    revert_pr: &PullRequest,
+    reverted_commits: &Vec<Commit>,
+    projects: &Vec<String>,
+    jira: &dyn jira::api::Session,
+    config: &JiraConfig,
+) {
+    let repo = &revert_pr.base.repo.full_name;
+    for key in get_fixed_jira_keys(reverted_commits, projects) {
+        link_pull_request(&key, revert_pr, false, jira, config);
+        let comment =
+            format!("Reverted by pull request for branch {}: {}", revert_pr.base.ref_name, revert_pr.html_url);
+        if let Err(e) = note_issue(
+            &key,
+            &revert_pr.html_url,
+            &comment,
+            &pr_summary(revert_pr, &format!("reverts this in branch {}", revert_pr.base.ref_name)),
+            jira,
+            config,
+        ) {
+            error!("Error commenting on key [{}]: {}", key, e);
+        }
+
+        let reopened_states = config.transitions(get_jira_project(&key), repo).reopened;
+        if needs_transition(&try_get_issue_state(&key, jira), &reopened_states) {
+            try_transition(&key, &reopened_states, jira);
+        }
+    }
+}
+
 pub fn add_pending_version(
     maybe_version: Option<&str>,
     commits: &Vec<PushCommit>,
@@ -243,6 +474,49 @@ pub fn add_pending_version(
     }
 }
 
+// Creates |version| in each project (unless it already exists) and makes it the fix version of the issues
+// |commits| reference. Returns the keys that were assigned the version in each project.
+pub fn assign_release_version<T: CommitLike>(
+    version: &str,
+    commits: &Vec<T>,
+    projects: &Vec<String>,
+    jira: &dyn jira::api::Session,
+) -> Vec<(String, Result<Vec<String>>)> {
+    projects
+        .iter()
+        .map(|project| (project.clone(), assign_project_release_version(version, commits, project, jira)))
+        .collect()
+}
+
+fn assign_project_release_version<T: CommitLike>(
+    version: &str,
+    commits: &Vec<T>,
+    project: &str,
+    jira: &dyn jira::api::Session,
+) -> Result<Vec<String>> {
+    if jira.get_versions(project)?.iter().any(|v| v.name == version) {
+        info!("JIRA version {} already exists for project {}", version, project);
+    } else {
+        info!("Creating new JIRA version {} for project {}", version, project);
+        jira.add_version(project, version)?;
+    }
+
+    let keys = get_all_jira_keys(commits, &vec![project.to_string()]);
+    Ok(keys
+        .into_iter()
+        .filter(|key| {
+            info!("Assigning JIRA version key {}: {}", key, version);
+            match jira.assign_fix_version(key, version) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("Error assigning version {} to key {}: {}", version, key, e);
+                    false
+                }
+            }
+        })
+        .collect())
+}
+
 fn parse_jira_versions(versions: &Vec<jira::Version>) -> Vec<version::Version> {
     versions.iter().filter_map(|v| version::Version::parse(&v.name)).collect::<Vec<_>>()
 }
@@ -348,6 +622,35 @@ fn find_relevant_versions(
         .collect::<Vec<_>>()
 }
 
+fn is_revert<T: CommitLike>(commit: &T) -> bool {
+    commit.message().starts_with("Revert \"")
+}
+
+fn is_revert_pr(pr: &PullRequest) -> bool {
+    pr.title.starts_with("Revert \"")
+}
+
+// Moves the issues that the PR's title or commits reference to the first of `to` available, e.g. for a label rule.
+// Returns the issues it moved.
+pub fn transition_referenced(
+    pr: &PullRequest,
+    commits: &Vec<Commit>,
+    projects: &Vec<String>,
+    to: &Vec<String>,
+    jira: &dyn jira::api::Session,
+) -> Vec<String> {
+    let mut strings = vec![pr.title.clone()];
+    strings.extend(commits.iter().map(|c| c.message().to_string()));
+
+    let mut moved = vec![];
+    for key in get_jira_keys(strings, projects) {
+        if needs_transition(&try_get_issue_state(&key, jira), to) && try_transition(&key, to, jira) {
+            moved.push(key);
+        }
+    }
+    moved
+}
+
 fn try_get_issue_state(key: &str, jira: &dyn jira::api::Session) -> Option<jira::Status> {
     match jira.get_issue(key) {
         Ok(issue) => issue.status,
@@ -358,19 +661,28 @@ fn try_get_issue_state(key: &str, jira: &dyn jira::api::Session) -> Option<jira:
     }
 }
 
-fn try_transition(key: &str, to: &Vec<String>, jira: &dyn jira::api::Session) {
+// Whether the issue was transitioned
+fn try_transition(key: &str, to: &Vec<String>, jira: &dyn jira::api::Session) -> bool {
     match find_transition(&key, to, jira) {
         Ok(Some(transition)) => {
             let req = transition.new_request();
             if let Err(e) = jira.transition_issue(&key, &req) {
                 error!("Error transitioning JIRA issue [{}] to one of [{:?}]: {}", key, to, e);
+                false
             } else {
                 info!("Transitioned [{}] to one of [{:?}]", key, to);
+                true
             }
         }
-        Ok(None) => info!("JIRA [{}] cannot be transitioned to any of [{:?}]", key, to),
-        Err(e) => error!("{}", e),
-    };
+        Ok(None) => {
+            info!("JIRA [{}] cannot be transitioned to any of [{:?}]", key, to);
+            false
+        }
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    }
 }
 
 fn find_transition(key: &str, to: &Vec<String>, jira: &dyn jira::api::Session) -> Result<Option<Transition>> {
@@ -382,7 +694,7 @@ fn find_transition(key: &str, to: &Vec<String>, jira: &dyn jira::api::Session) -
 fn pick_transition(to: &Vec<String>, choices: &Vec<Transition>) -> Option<Transition> {
     for t in choices {
         for name in to {
-            if &t.name == name || &t.to.name == name {
+            if &t.name == name || &t.to.name == name || &t.id == name {
                 return Some(t.clone());
             }
         }
@@ -391,6 +703,52 @@ fn pick_transition(to: &Vec<String>, choices: &Vec<Transition>) -> Option<Transi
     None
 }
 
+// Whether one of the transitions configured for an action exists
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TransitionCheck {
+    pub action: String,
+    pub configured: Vec<String>,
+    // the first configured one that exists, if any
+    pub found: Option<String>,
+}
+
+// Checks the transitions configured for `project` and `repo` without changing anything. Configured names are
+// looked up in the project's statuses, and also in the transitions currently available to `sample_issue`, if
+// given, since transition names and IDs can't be listed for a whole project.
+pub fn validate_transitions(
+    project: &str,
+    repo: &str,
+    sample_issue: Option<&str>,
+    jira: &dyn jira::api::Session,
+    config: &JiraConfig,
+) -> Result<Vec<TransitionCheck>> {
+    let mut known = jira.get_project_statuses(project)?.into_iter().map(|s| s.name).collect::<Vec<_>>();
+    if let Some(key) = sample_issue {
+        for t in jira.get_transitions(key)? {
+            known.push(t.id);
+            known.push(t.name);
+            known.push(t.to.name);
+        }
+    }
+
+    let transitions = config.transitions(project, repo);
+    let actions = vec![
+        ("progress", transitions.progress),
+        ("review", transitions.review),
+        ("resolved", transitions.resolved),
+        ("reopened", transitions.reopened),
+    ];
+
+    Ok(actions
+        .into_iter()
+        .map(|(action, configured)| TransitionCheck {
+            action: action.into(),
+            found: configured.iter().find(|c| known.contains(c)).cloned(),
+            configured: configured,
+        })
+        .collect())
+}
+
 pub fn sort_versions(project: &str, jira: &dyn jira::api::Session) -> Result<()> {
     let mut versions = jira.get_versions(project)?;
 
@@ -495,6 +853,7 @@ mod tests {
             pick_transition(&vec!["inside-t1".into(), "t2".into()], &vec![t1.clone(), t2.clone()])
         );
         assert_eq!(Some(t2.clone()), pick_transition(&vec!["inside-t2".into()], &vec![t1.clone(), t2.clone()]));
+        assert_eq!(Some(t2.clone()), pick_transition(&vec!["2".into()], &vec![t1.clone(), t2.clone()]));
         assert_eq!(None, pick_transition(&vec!["something-else".into()], &vec![t1.clone(), t2.clone()]));
     }
 
@@ -505,6 +864,16 @@ mod tests {
         assert_eq!("doesn't match", get_jira_project("doesn't match"));
     }
 
+    #[test]
+    fn test_set_summary_line() {
+        let body = set_summary_line(SUMMARY_HEADER, "http://pr/1", "[PR 1|http://pr/1] in review");
+        assert_eq!(format!("{}\n* [PR 1|http://pr/1] in review", SUMMARY_HEADER), body);
+
+        let body = set_summary_line(&body, "http://pr/2", "[PR 2|http://pr/2] in review");
+        let body = set_summary_line(&body, "http://pr/1", "[PR 1|http://pr/1] merged");
+        assert_eq!(format!("{}\n* [PR 1|http://pr/1] merged\n* [PR 2|http://pr/2] in review", SUMMARY_HEADER), body);
+    }
+
 
     #[test]
     fn test_find_relevant_versions() {
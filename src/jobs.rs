@@ -0,0 +1,291 @@
+use failure::format_err;
+use log::error;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::db::{self, Connection, Database, Row, ToSql};
+use crate::errors::*;
+
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_COMPLETED: &str = "completed";
+pub const STATUS_FAILED: &str = "failed";
+pub const STATUS_CANCELLED: &str = "cancelled";
+
+// Only keep this many finished jobs around
+const MAX_FINISHED_JOBS: i32 = 500;
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct JobInfo {
+    pub id: i32,
+    // i.e. "backport" or "repo-version"
+    pub kind: String,
+    pub description: String,
+    pub status: String,
+    // percent complete: 0-100
+    pub progress: u32,
+    pub total_items: u32,
+    pub done_items: u32,
+    pub cancel_requested: bool,
+    pub created_at: i64,
+    pub updated_at: i64,
+    // only populated when looking up a single job
+    #[serde(default)]
+    pub items: Vec<JobItem>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct JobItem {
+    pub item: String,
+    pub success: bool,
+    pub message: String,
+}
+
+#[derive(Clone)]
+pub struct Jobs {
+    db: Database,
+}
+
+impl JobInfo {
+    pub fn is_running(&self) -> bool {
+        self.status == STATUS_RUNNING
+    }
+}
+
+fn now() -> i64 {
+    time::get_time().sec
+}
+
+impl Jobs {
+    pub fn new(db: Database) -> Jobs {
+        Jobs { db: db }
+    }
+
+    pub fn start(&self, kind: &str, description: &str, total_items: u32) -> Result<i32> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO jobs (kind, description, status, total_items, done_items, cancel_requested, created_at, updated_at)
+               VALUES (?1, ?2, ?3, ?4, 0, 0, ?5, ?5)"#,
+            &[&kind, &description, &STATUS_RUNNING, &total_items as &dyn ToSql, &now()],
+        )
+        .map_err(|e| format_err!("Error creating job {}: {}", kind, e))?;
+
+        let id = conn.last_insert_rowid()? as i32;
+        self.prune(&conn);
+
+        Ok(id)
+    }
+
+    pub fn item_done(&self, id: i32, item: &str, success: bool, message: &str) -> Result<()> {
+        let mut conn = self.db.connect()?;
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO job_items (job_id, item, success, message) VALUES (?1, ?2, ?3, ?4)",
+            &[&id, &item as &dyn ToSql, &db::to_tinyint(success), &message],
+        )
+        .map_err(|e| format_err!("Error adding item to job {}: {}", id, e))?;
+
+        tx.execute(
+            "UPDATE jobs SET done_items = done_items + 1, updated_at = ?1 WHERE id = ?2",
+            &[&now(), &(id as i64)],
+        )
+        .map_err(|e| format_err!("Error updating job {}: {}", id, e))?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn finish(&self, id: i32, status: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "UPDATE jobs SET status = ?1, updated_at = ?2 WHERE id = ?3",
+            &[&status as &dyn ToSql, &now(), &id],
+        )
+        .map_err(|e| format_err!("Error finishing job {}: {}", id, e))?;
+
+        Ok(())
+    }
+
+    // Returns false if the job is not running. Jobs stop at the next item boundary.
+    pub fn cancel(&self, id: i32) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let count = conn
+            .execute(
+                "UPDATE jobs SET cancel_requested = 1, updated_at = ?1 WHERE id = ?2 AND status = ?3",
+                &[&now(), &(id as i64) as &dyn ToSql, &STATUS_RUNNING],
+            )
+            .map_err(|e| format_err!("Error cancelling job {}: {}", id, e))?;
+
+        Ok(count > 0)
+    }
+
+    pub fn is_cancelled(&self, id: i32) -> bool {
+        match self.get(id) {
+            Ok(Some(job)) => job.cancel_requested,
+            Ok(None) => false,
+            Err(e) => {
+                error!("Error looking up job {}: {}", id, e);
+                false
+            }
+        }
+    }
+
+    pub fn get(&self, id: i32) -> Result<Option<JobInfo>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare("SELECT * FROM jobs WHERE id = :id")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &id)])?;
+
+        if let Ok(Some(row)) = rows.next() {
+            let mut job = self.map_row(&row, &cols)?;
+            job.items = self.load_items(&conn, id)?;
+            Ok(Some(job))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn get_all(&self) -> Result<Vec<JobInfo>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare("SELECT * FROM jobs ORDER BY id DESC")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(db::NO_PARAMS)?;
+
+        let mut jobs = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            jobs.push(self.map_row(&row, &cols)?);
+        }
+
+        Ok(jobs)
+    }
+
+    pub fn get_running(&self) -> Result<Vec<JobInfo>> {
+        Ok(self.get_all()?.into_iter().filter(|j| j.is_running()).collect())
+    }
+
+    fn map_row(&self, row: &Row, cols: &db::Columns) -> Result<JobInfo> {
+        let total_items: u32 = cols.get(row, "total_items")?;
+        let done_items: u32 = cols.get(row, "done_items")?;
+        let status: String = cols.get(row, "status")?;
+
+        let progress = if status == STATUS_COMPLETED {
+            100
+        } else if total_items == 0 {
+            0
+        } else {
+            std::cmp::min(100, done_items * 100 / total_items)
+        };
+
+        Ok(JobInfo {
+            id: cols.get(row, "id")?,
+            kind: cols.get(row, "kind")?,
+            description: cols.get(row, "description")?,
+            status: status,
+            progress: progress,
+            total_items: total_items,
+            done_items: done_items,
+            cancel_requested: db::to_bool(cols.get(row, "cancel_requested")?),
+            created_at: cols.get(row, "created_at")?,
+            updated_at: cols.get(row, "updated_at")?,
+            items: vec![],
+        })
+    }
+
+    fn load_items(&self, conn: &Connection, id: i32) -> Result<Vec<JobItem>> {
+        let mut stmt = conn.prepare("SELECT * FROM job_items WHERE job_id = :id ORDER BY rowid")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &id)])?;
+
+        let mut items = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            items.push(JobItem {
+                item: cols.get(row, "item")?,
+                success: db::to_bool(cols.get(row, "success")?),
+                message: cols.get(row, "message")?,
+            });
+        }
+
+        Ok(items)
+    }
+
+    fn prune(&self, conn: &Connection) {
+        let res = conn.execute_batch(&format!(
+            r#"DELETE FROM jobs WHERE status != '{running}' AND id NOT IN
+                  (SELECT id FROM jobs WHERE status != '{running}' ORDER BY id DESC LIMIT {max});
+               DELETE FROM job_items WHERE job_id NOT IN (SELECT id FROM jobs);"#,
+            running = STATUS_RUNNING,
+            max = MAX_FINISHED_JOBS
+        ));
+        if let Err(e) = res {
+            error!("Error pruning old jobs: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (Jobs, TempDir) {
+        let temp_dir = TempDir::new("jobs.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (Jobs::new(db), temp_dir)
+    }
+
+    #[test]
+    fn test_job_progress() {
+        let (jobs, _temp) = new_test();
+
+        let id = jobs.start("backport", "Backport all the things", 4).unwrap();
+        jobs.item_done(id, "release/1.0", true, "").unwrap();
+        jobs.item_done(id, "release/2.0", false, "Merge conflict").unwrap();
+
+        let job = jobs.get(id).unwrap().unwrap();
+        assert_eq!("backport", job.kind);
+        assert_eq!(STATUS_RUNNING, job.status);
+        assert_eq!(50, job.progress);
+        assert_eq!(2, job.done_items);
+        assert_eq!(
+            vec![
+                JobItem { item: "release/1.0".into(), success: true, message: "".into() },
+                JobItem { item: "release/2.0".into(), success: false, message: "Merge conflict".into() },
+            ],
+            job.items
+        );
+
+        jobs.finish(id, STATUS_COMPLETED).unwrap();
+        let job = jobs.get(id).unwrap().unwrap();
+        assert_eq!(100, job.progress);
+        assert_eq!(0, jobs.get_running().unwrap().len());
+    }
+
+    #[test]
+    fn test_job_cancel() {
+        let (jobs, _temp) = new_test();
+
+        let id = jobs.start("backport", "Backport all the things", 2).unwrap();
+        assert_eq!(false, jobs.is_cancelled(id));
+
+        assert_eq!(true, jobs.cancel(id).unwrap());
+        assert_eq!(true, jobs.is_cancelled(id));
+
+        // can't cancel finished jobs
+        jobs.finish(id, STATUS_CANCELLED).unwrap();
+        assert_eq!(false, jobs.cancel(id).unwrap());
+        assert_eq!(false, jobs.cancel(1234).unwrap());
+    }
+
+    #[test]
+    fn test_job_list() {
+        let (jobs, _temp) = new_test();
+
+        let id1 = jobs.start("backport", "one", 1).unwrap();
+        let id2 = jobs.start("repo-version", "two", 1).unwrap();
+
+        let all = jobs.get_all().unwrap();
+        assert_eq!(vec![id2, id1], all.iter().map(|j| j.id).collect::<Vec<_>>());
+        assert!(jobs.get(999).unwrap().is_none());
+    }
+}
@@ -0,0 +1,516 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use reqwest;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::{Config, JsmConfig};
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session as GithubSession};
+use crate::http_client::HTTPClient;
+use crate::jira;
+use crate::scheduler;
+
+pub const APPROVAL_CONTEXT: &str = "itsm-approval";
+
+// how often to look for decisions on pending approvals, for when the webhook isn't set up (or missed one)
+pub const CHECK_INTERVAL_SECS: u64 = 5 * 60;
+
+// values of an approval's `finalDecision`
+pub const APPROVED: &str = "approved";
+pub const DECLINED: &str = "declined";
+pub const PENDING: &str = "pending";
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RequestFields {
+    pub summary: String,
+    pub description: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NewRequest {
+    pub service_desk_id: String,
+    pub request_type_id: String,
+    pub request_field_values: RequestFields,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomerRequest {
+    pub issue_id: String,
+    // e.g. "ITSM-12"
+    pub issue_key: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Approval {
+    pub id: String,
+    pub name: String,
+    // "approved", "declined" or "pending"
+    pub final_decision: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct Approvals {
+    values: Vec<Approval>,
+}
+
+pub trait Session: Send + Sync {
+    fn create_request(&self, request: &NewRequest) -> Result<CustomerRequest>;
+    fn get_approvals(&self, issue_key: &str) -> Result<Vec<Approval>>;
+    // where people can look at (and approve) the request
+    fn request_url(&self, issue_key: &str) -> String;
+}
+
+pub struct JsmSession {
+    client: HTTPClient,
+    base_url: String,
+}
+
+impl JsmSession {
+    pub fn new(config: &JsmConfig) -> Result<JsmSession> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::ACCEPT, "application/json".parse().unwrap());
+        let auth = base64::encode(format!("{}:{}", config.username, config.api_token).as_bytes());
+        headers.insert(reqwest::header::AUTHORIZATION, format!("Basic {}", auth).parse()?);
+
+        let base_url = config.base_url.trim_end_matches('/').to_string();
+        Ok(JsmSession {
+            client: HTTPClient::new_with_headers(&format!("{}/rest/servicedeskapi", base_url), headers)?,
+            base_url: base_url,
+        })
+    }
+}
+
+impl Session for JsmSession {
+    fn create_request(&self, request: &NewRequest) -> Result<CustomerRequest> {
+        self.client
+            .post::<CustomerRequest, NewRequest>("/request", request)
+            .map_err(|e| format_err!("Error creating service desk request: {}", e))
+    }
+
+    fn get_approvals(&self, issue_key: &str) -> Result<Vec<Approval>> {
+        self.client
+            .get::<Approvals>(&format!("/request/{}/approval", issue_key))
+            .map(|a| a.values)
+            .map_err(|e| format_err!("Error getting approvals of {}: {}", issue_key, e))
+    }
+
+    fn request_url(&self, issue_key: &str) -> String {
+        format!("{}/browse/{}", self.base_url, issue_key)
+    }
+}
+
+// The request's decision: declined as soon as one approval is, approved once all of them are
+pub fn decision(approvals: &[Approval]) -> &'static str {
+    if approvals.iter().any(|a| a.final_decision == DECLINED) {
+        DECLINED
+    } else if !approvals.is_empty() && approvals.iter().all(|a| a.final_decision == APPROVED) {
+        APPROVED
+    } else {
+        PENDING
+    }
+}
+
+// The service desk request opened for a release PR
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ApprovalRequest {
+    // "owner/name"
+    pub repo: String,
+    pub number: u32,
+    // the release branch the PR merges into
+    pub branch: String,
+    pub issue_key: String,
+    pub decision: String,
+    pub created_at: i64,
+}
+
+impl ApprovalRequest {
+    pub fn is_approved(&self) -> bool {
+        self.decision == APPROVED
+    }
+
+    pub fn is_declined(&self) -> bool {
+        self.decision == DECLINED
+    }
+
+    pub fn owner_and_name(&self) -> Result<(&str, &str)> {
+        let mut parts = self.repo.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(owner), Some(name)) => Ok((owner, name)),
+            _ => Err(format_err!("Invalid repo: {}", self.repo)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ApprovalRequests {
+    db: Database,
+}
+
+impl ApprovalRequests {
+    pub fn new(db: Database) -> ApprovalRequests {
+        ApprovalRequests { db: db }
+    }
+
+    pub fn add(&self, request: &ApprovalRequest) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO jsm_approvals
+               (repo, number, branch, issue_key, decision, created_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+               ON CONFLICT (repo, number) DO UPDATE SET branch = excluded.branch, issue_key = excluded.issue_key,
+                   decision = excluded.decision, created_at = excluded.created_at"#,
+            &[
+                &request.repo as &dyn ToSql,
+                &request.number,
+                &request.branch,
+                &request.issue_key,
+                &request.decision,
+                &request.created_at,
+            ],
+        )
+        .map_err(|e| format_err!("Error saving approval request {}: {}", request.issue_key, e))?;
+
+        Ok(())
+    }
+
+    pub fn set_decision(&self, issue_key: &str, decision: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "UPDATE jsm_approvals SET decision = ?1 WHERE issue_key = ?2",
+            &[&decision as &dyn ToSql, &issue_key],
+        )
+        .map_err(|e| format_err!("Error updating approval request {}: {}", issue_key, e))?;
+
+        Ok(())
+    }
+
+    // Forgets the approval requests of closed PRs
+    pub fn remove(&self, repo: &str, number: u32) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "DELETE FROM jsm_approvals WHERE repo = ?1 AND number = ?2",
+            &[&repo as &dyn ToSql, &number],
+        )
+        .map_err(|e| format_err!("Error removing approval request of {}#{}: {}", repo, number, e))?;
+
+        Ok(())
+    }
+
+    fn query(&self, sql: &str, params: &[&dyn ToSql]) -> Result<Vec<ApprovalRequest>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(sql)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(params)?;
+
+        let mut requests = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            requests.push(ApprovalRequest {
+                repo: cols.get(row, "repo")?,
+                number: cols.get(row, "number")?,
+                branch: cols.get(row, "branch")?,
+                issue_key: cols.get(row, "issue_key")?,
+                decision: cols.get(row, "decision")?,
+                created_at: cols.get(row, "created_at")?,
+            });
+        }
+
+        Ok(requests)
+    }
+
+    pub fn get(&self, repo: &str, number: u32) -> Result<Option<ApprovalRequest>> {
+        let requests = self.query(
+            "SELECT * FROM jsm_approvals WHERE repo = ?1 AND number = ?2",
+            &[&repo as &dyn ToSql, &number],
+        )?;
+        Ok(requests.into_iter().next())
+    }
+
+    pub fn get_by_key(&self, issue_key: &str) -> Result<Option<ApprovalRequest>> {
+        let requests = self.query("SELECT * FROM jsm_approvals WHERE issue_key = ?1", &[&issue_key as &dyn ToSql])?;
+        Ok(requests.into_iter().next())
+    }
+
+    // Approval requests still waiting for a decision
+    pub fn pending(&self) -> Result<Vec<ApprovalRequest>> {
+        let requests = self.query("SELECT * FROM jsm_approvals ORDER BY repo, number", &[])?;
+        Ok(requests.into_iter().filter(|r| !r.is_approved() && !r.is_declined()).collect())
+    }
+}
+
+pub fn new_request(
+    config: &JsmConfig,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    jira_keys: &Vec<String>,
+) -> NewRequest {
+    let mut description = format!(
+        "Release of {} to {}: {}\n\nPull request: {}",
+        repo.full_name, pull_request.base.ref_name, pull_request.title, pull_request.html_url
+    );
+    if !jira_keys.is_empty() {
+        description += &format!("\nJIRAs: {}", jira_keys.join(", "));
+    }
+
+    NewRequest {
+        service_desk_id: config.service_desk_id.clone(),
+        request_type_id: config.request_type_id.clone(),
+        request_field_values: RequestFields {
+            summary: format!("Release {} {}", repo.full_name, pull_request.base.ref_name),
+            description: description,
+        },
+    }
+}
+
+// Passes once the request is approved: pending while it waits, and failed if it was declined
+pub fn check_run(pull_request: &github::PullRequest, request: &ApprovalRequest, url: &str) -> github::CheckRun {
+    let run = github::CheckRun::new(APPROVAL_CONTEXT, pull_request, Some(url.to_string()));
+    let (mut run, title, summary) = if request.is_approved() {
+        (
+            run.completed(github::Conclusion::Success),
+            "Release approved",
+            format!("{} was approved", request.issue_key),
+        )
+    } else if request.is_declined() {
+        (
+            run.completed(github::Conclusion::Failure),
+            "Release declined",
+            format!("{} was declined: this release can't go out", request.issue_key),
+        )
+    } else {
+        (
+            run,
+            "Waiting for approval",
+            format!("{} must be approved before this release merges", request.issue_key),
+        )
+    };
+
+    run.output = Some(github::CheckOutput::new(title, &summary));
+    run
+}
+
+// Opens a service desk request for the release PR (unless it already has one), notes its key on the PR and on the
+// JIRAs it references, and holds the PR's approval check until it is approved
+pub fn open_approval_request(
+    jsm: &dyn Session,
+    config: &Config,
+    github: &dyn GithubSession,
+    jira: Option<&dyn jira::api::Session>,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    jira_keys: &Vec<String>,
+    now: i64,
+) -> Result<ApprovalRequest> {
+    let jsm_config = match config.jsm {
+        Some(ref c) => c,
+        None => return Err(format_err!("Jira Service Management is not configured")),
+    };
+
+    if let Some(request) = config.jsm_approvals.get(&repo.full_name, pull_request.number)? {
+        let url = jsm.request_url(&request.issue_key);
+        github.create_check_run(pull_request, &check_run(pull_request, &request, &url))?;
+        return Ok(request);
+    }
+
+    let created = jsm.create_request(&new_request(jsm_config, repo, pull_request, jira_keys))?;
+    let request = ApprovalRequest {
+        repo: repo.full_name.clone(),
+        number: pull_request.number,
+        branch: pull_request.base.ref_name.clone(),
+        issue_key: created.issue_key,
+        decision: PENDING.into(),
+        created_at: now,
+    };
+    config.jsm_approvals.add(&request)?;
+    info!("Opened approval request {} for {}#{}", request.issue_key, repo.full_name, pull_request.number);
+
+    let url = jsm.request_url(&request.issue_key);
+    let comment = format!("Approval request [{}]({}) was opened for this release.", request.issue_key, url);
+    if let Err(e) = github.comment_pull_request(repo.owner.login(), &repo.name, pull_request.number, &comment) {
+        error!("Error commenting approval request on {}#{}: {}", repo.full_name, pull_request.number, e);
+    }
+
+    if let Some(jira) = jira {
+        let comment =
+            format!("Approval request {} was opened for release {}", request.issue_key, pull_request.html_url);
+        for key in jira_keys {
+            if let Err(e) = jira.comment_issue(key, &comment) {
+                error!("Error commenting approval request on {}: {}", key, e);
+            }
+        }
+    }
+
+    github.create_check_run(pull_request, &check_run(pull_request, &request, &url))?;
+    Ok(request)
+}
+
+// Looks up the decision on a pending request, and updates its PR's approval check when it was decided
+pub fn refresh_decision(
+    jsm: &dyn Session,
+    config: &Config,
+    github_app: &dyn GithubSessionFactory,
+    request: &ApprovalRequest,
+) -> Result<()> {
+    let decision = decision(&jsm.get_approvals(&request.issue_key)?);
+    if decision == request.decision {
+        return Ok(());
+    }
+
+    config.jsm_approvals.set_decision(&request.issue_key, decision)?;
+    info!("Approval request {} of {}#{} is now {}", request.issue_key, request.repo, request.number, decision);
+
+    let mut request = request.clone();
+    request.decision = decision.into();
+
+    let (owner, name) = request.owner_and_name()?;
+    let github = github_app.new_session(owner, name)?;
+    let pull_request = github.get_pull_request(owner, name, request.number)?;
+    if pull_request.state == "open" {
+        let url = jsm.request_url(&request.issue_key);
+        github.create_check_run(&pull_request, &check_run(&pull_request, &request, &url))?;
+    }
+    Ok(())
+}
+
+pub struct DecisionPoller {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    jsm: Arc<dyn Session>,
+}
+
+impl DecisionPoller {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        jsm: Arc<dyn Session>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(DecisionPoller {
+            config: config,
+            github_app: github_app,
+            jsm: jsm,
+        })
+    }
+}
+
+impl scheduler::Task for DecisionPoller {
+    fn run(&self, _now: i64) -> Result<()> {
+        for request in self.config.jsm_approvals.pending()? {
+            if let Err(e) = refresh_decision(&*self.jsm, &self.config, &*self.github_app, &request) {
+                error!("Error checking decision on approval request {}: {}", request.issue_key, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (ApprovalRequests, TempDir) {
+        let temp_dir = TempDir::new("jsm.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        (ApprovalRequests::new(db), temp_dir)
+    }
+
+    fn request(number: u32, issue_key: &str, decision: &str) -> ApprovalRequest {
+        ApprovalRequest {
+            repo: "some-org/some-repo".into(),
+            number: number,
+            branch: "release/1.0".into(),
+            issue_key: issue_key.into(),
+            decision: decision.into(),
+            created_at: 10,
+        }
+    }
+
+    fn approval(decision: &str) -> Approval {
+        Approval {
+            id: "1".into(),
+            name: "Change approval".into(),
+            final_decision: decision.into(),
+        }
+    }
+
+    #[test]
+    fn test_approval_requests() {
+        let (requests, _temp_dir) = new_test();
+
+        requests.add(&request(1, "ITSM-1", PENDING)).unwrap();
+        requests.add(&request(2, "ITSM-2", PENDING)).unwrap();
+        assert_eq!("ITSM-1", requests.get("some-org/some-repo", 1).unwrap().unwrap().issue_key);
+        assert_eq!(None, requests.get("some-org/some-repo", 3).unwrap());
+        assert_eq!(2, requests.get_by_key("ITSM-2").unwrap().unwrap().number);
+        assert_eq!(2, requests.pending().unwrap().len());
+
+        requests.set_decision("ITSM-1", APPROVED).unwrap();
+        requests.set_decision("ITSM-2", DECLINED).unwrap();
+        assert!(requests.pending().unwrap().is_empty());
+        assert!(requests.get("some-org/some-repo", 1).unwrap().unwrap().is_approved());
+
+        requests.remove("some-org/some-repo", 2).unwrap();
+        assert_eq!(None, requests.get_by_key("ITSM-2").unwrap());
+    }
+
+    #[test]
+    fn test_decision() {
+        assert_eq!(PENDING, decision(&[]));
+        assert_eq!(PENDING, decision(&[approval(APPROVED), approval(PENDING)]));
+        assert_eq!(APPROVED, decision(&[approval(APPROVED), approval(APPROVED)]));
+        assert_eq!(DECLINED, decision(&[approval(APPROVED), approval(DECLINED)]));
+    }
+
+    #[test]
+    fn test_check_run() {
+        let mut pr = github::PullRequest::new();
+        pr.head.sha = "abcdef".into();
+
+        let run = check_run(&pr, &request(1, "ITSM-1", PENDING), "http://the-request");
+        assert_eq!(None, run.conclusion);
+        assert_eq!("itsm-approval", run.name);
+        assert_eq!(Some("http://the-request".to_string()), run.details_url);
+        assert_eq!(Some("Waiting for approval".to_string()), run.output.unwrap().title);
+
+        let run = check_run(&pr, &request(1, "ITSM-1", APPROVED), "http://the-request");
+        assert_eq!(Some(github::Conclusion::Success), run.conclusion);
+
+        let run = check_run(&pr, &request(1, "ITSM-1", DECLINED), "http://the-request");
+        assert_eq!(Some(github::Conclusion::Failure), run.conclusion);
+    }
+
+    #[test]
+    fn test_new_request() {
+        let config = JsmConfig {
+            base_url: "https://company.atlassian.net".into(),
+            username: "octobot@company.com".into(),
+            api_token: "the-token".into(),
+            service_desk_id: "4".into(),
+            request_type_id: "25".into(),
+            webhook_token: None,
+            repos: vec!["some-org".into()],
+        };
+        let repo = github::Repo::parse("http://the-github-host/some-org/some-repo").unwrap();
+        let mut pr = github::PullRequest::new();
+        pr.base.ref_name = "release/1.0".into();
+        pr.title = "Release 1.0".into();
+        pr.html_url = "http://the-pr".into();
+
+        let request = new_request(&config, &repo, &pr, &vec!["SER-1".into()]);
+        assert_eq!("4", request.service_desk_id);
+        assert_eq!("25", request.request_type_id);
+        assert_eq!("Release some-org/some-repo release/1.0", request.request_field_values.summary);
+        assert_eq!(
+            "Release of some-org/some-repo to release/1.0: Release 1.0\n\nPull request: http://the-pr\nJIRAs: SER-1",
+            request.request_field_values.description
+        );
+    }
+}
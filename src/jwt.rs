@@ -1,8 +1,11 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use failure::format_err;
 use jsonwebtoken::{self, Algorithm, Header};
 use serde_derive::{Deserialize, Serialize};
 
+use crate::errors::*;
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
     iat: u64,
@@ -20,3 +23,27 @@ pub fn new_token(app_id: u32, app_key_der: &[u8]) -> String {
 
     return jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, app_key_der).unwrap();
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ServiceAccountClaims {
+    iss: String,
+    scope: String,
+    aud: String,
+    iat: u64,
+    exp: u64,
+}
+
+// A google service account's assertion, exchanged at `aud` for an access token with the scope
+pub fn new_service_account_token(client_email: &str, scope: &str, aud: &str, key_der: &[u8]) -> Result<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let claims = ServiceAccountClaims {
+        iss: client_email.into(),
+        scope: scope.into(),
+        aud: aud.into(),
+        iat: now,
+        exp: now + (60 * 60),
+    };
+
+    jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, key_der)
+        .map_err(|e| format_err!("Error signing token for {}: {}", client_email, e))
+}
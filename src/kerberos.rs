@@ -0,0 +1,272 @@
+use crate::config::KerberosConfig;
+use crate::errors::*;
+
+// What a browser's SPNEGO token proved
+#[derive(Debug, PartialEq)]
+pub struct Accepted {
+    // e.g. "alice@COMPANY.COM"
+    pub principal: String,
+    // for the browser to authenticate octobot in turn, if not empty
+    pub token: Vec<u8>,
+}
+
+// The token of an "Authorization: Negotiate <token>" header
+pub fn negotiate_token(authorization: &str) -> Option<Vec<u8>> {
+    let mut parts = authorization.trim().splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("negotiate") => base64::decode(token.trim()).ok(),
+        _ => None,
+    }
+}
+
+// The user name of a principal ("alice@COMPANY.COM" is "alice"), if it is of the configured realm
+pub fn user_name(config: &KerberosConfig, principal: &str) -> Option<String> {
+    let mut parts = principal.rsplitn(2, '@');
+    let (realm, name) = match (parts.next(), parts.next()) {
+        (Some(realm), Some(name)) => (realm, name),
+        (Some(name), None) => ("", name),
+        _ => return None,
+    };
+    if let Some(expected) = config.realm.as_ref().filter(|r| !r.is_empty()) {
+        if !realm.eq_ignore_ascii_case(expected) {
+            return None;
+        }
+    }
+    // service principals (e.g. "HTTP/host") aren't users
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+// Points kerberos at the configured keytab. The keytab is process-wide state, so this is done once at startup,
+// before any other threads are started.
+pub fn register_keytab(config: &KerberosConfig) -> Result<()> {
+    match config.keytab {
+        Some(ref keytab) => gssapi::register_acceptor_identity(keytab),
+        None => gssapi::check_supported(),
+    }
+}
+
+// Checks the browser's token against octobot's service principal
+pub fn accept(token: &[u8]) -> Result<Accepted> {
+    gssapi::accept(token)
+}
+
+#[cfg(all(unix, feature = "kerberos"))]
+mod gssapi {
+    use std::ffi::CString;
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::ptr;
+    use std::slice;
+
+    use failure::format_err;
+
+    use super::Accepted;
+    use crate::errors::*;
+
+    type OmUint32 = u32;
+
+    #[repr(C)]
+    struct Buffer {
+        length: usize,
+        value: *mut c_void,
+    }
+
+    impl Buffer {
+        fn empty() -> Buffer {
+            Buffer {
+                length: 0,
+                value: ptr::null_mut(),
+            }
+        }
+
+        fn to_vec(&self) -> Vec<u8> {
+            if self.value.is_null() {
+                return vec![];
+            }
+            unsafe { slice::from_raw_parts(self.value as *const u8, self.length).to_vec() }
+        }
+    }
+
+    const GSS_S_COMPLETE: OmUint32 = 0;
+    const GSS_S_CONTINUE_NEEDED: OmUint32 = 1;
+    const GSS_C_GSS_CODE: c_int = 1;
+
+    #[link(name = "gssapi_krb5")]
+    extern "C" {
+        fn gss_accept_sec_context(
+            minor_status: *mut OmUint32,
+            context_handle: *mut *mut c_void,
+            acceptor_cred_handle: *mut c_void,
+            input_token: *mut Buffer,
+            input_chan_bindings: *mut c_void,
+            src_name: *mut *mut c_void,
+            mech_type: *mut *mut c_void,
+            output_token: *mut Buffer,
+            ret_flags: *mut OmUint32,
+            time_rec: *mut OmUint32,
+            delegated_cred_handle: *mut *mut c_void,
+        ) -> OmUint32;
+        fn gss_display_name(
+            minor_status: *mut OmUint32,
+            name: *mut c_void,
+            output_name: *mut Buffer,
+            output_name_type: *mut *mut c_void,
+        ) -> OmUint32;
+        fn gss_display_status(
+            minor_status: *mut OmUint32,
+            status_value: OmUint32,
+            status_type: c_int,
+            mech_type: *mut c_void,
+            message_context: *mut OmUint32,
+            status_string: *mut Buffer,
+        ) -> OmUint32;
+        fn gss_release_buffer(minor_status: *mut OmUint32, buffer: *mut Buffer) -> OmUint32;
+        fn gss_release_name(minor_status: *mut OmUint32, name: *mut *mut c_void) -> OmUint32;
+        fn gss_delete_sec_context(
+            minor_status: *mut OmUint32,
+            context_handle: *mut *mut c_void,
+            output_token: *mut Buffer,
+        ) -> OmUint32;
+        fn krb5_gss_register_acceptor_identity(keytab: *const c_char) -> OmUint32;
+    }
+
+    fn status_message(major: OmUint32) -> String {
+        let mut minor = 0;
+        let mut context = 0;
+        let mut message = Buffer::empty();
+        unsafe {
+            gss_display_status(&mut minor, major, GSS_C_GSS_CODE, ptr::null_mut(), &mut context, &mut message);
+            let text = String::from_utf8_lossy(&message.to_vec()).into_owned();
+            gss_release_buffer(&mut minor, &mut message);
+            text
+        }
+    }
+
+    pub fn check_supported() -> Result<()> {
+        Ok(())
+    }
+
+    // kerberos keeps its own copy of the path
+    pub fn register_acceptor_identity(keytab: &str) -> Result<()> {
+        let path = CString::new(keytab).map_err(|_| format_err!("Invalid keytab path {}", keytab))?;
+        let major = unsafe { krb5_gss_register_acceptor_identity(path.as_ptr()) };
+        if major != GSS_S_COMPLETE {
+            return Err(format_err!("Error registering keytab {}: {}", keytab, status_message(major)));
+        }
+        Ok(())
+    }
+
+    pub fn accept(token: &[u8]) -> Result<Accepted> {
+        let mut minor = 0;
+        let mut context = ptr::null_mut();
+        let mut name = ptr::null_mut();
+        let mut output = Buffer::empty();
+        let mut input = Buffer {
+            length: token.len(),
+            value: token.as_ptr() as *mut c_void,
+        };
+
+        unsafe {
+            let major = gss_accept_sec_context(
+                &mut minor,
+                &mut context,
+                ptr::null_mut(), // the default credentials, from the keytab
+                &mut input,
+                ptr::null_mut(), // no channel bindings
+                &mut name,
+                ptr::null_mut(),
+                &mut output,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            let reply = output.to_vec();
+            gss_release_buffer(&mut minor, &mut output);
+            if !context.is_null() {
+                gss_delete_sec_context(&mut minor, &mut context, ptr::null_mut());
+            }
+
+            let result = match major {
+                GSS_S_COMPLETE => {
+                    let mut display = Buffer::empty();
+                    let major = gss_display_name(&mut minor, name, &mut display, ptr::null_mut());
+                    let principal = String::from_utf8_lossy(&display.to_vec()).into_owned();
+                    gss_release_buffer(&mut minor, &mut display);
+                    if major == GSS_S_COMPLETE {
+                        Ok(Accepted {
+                            principal: principal,
+                            token: reply,
+                        })
+                    } else {
+                        Err(format_err!("Error reading the principal: {}", status_message(major)))
+                    }
+                }
+                // e.g. NTLM, which takes more round trips: only kerberos is supported
+                GSS_S_CONTINUE_NEEDED => Err(format_err!("Negotiation needs more than one round trip")),
+                _ => Err(format_err!("Invalid SPNEGO token: {}", status_message(major))),
+            };
+            if !name.is_null() {
+                gss_release_name(&mut minor, &mut name);
+            }
+            result
+        }
+    }
+}
+
+#[cfg(not(all(unix, feature = "kerberos")))]
+mod gssapi {
+    use failure::{format_err, Error};
+
+    use super::Accepted;
+    use crate::errors::*;
+
+    fn unsupported() -> Error {
+        format_err!("octobot was built without kerberos support (the `kerberos` feature, on unix)")
+    }
+
+    pub fn check_supported() -> Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn register_acceptor_identity(_keytab: &str) -> Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn accept(_token: &[u8]) -> Result<Accepted> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(realm: Option<&str>) -> KerberosConfig {
+        KerberosConfig {
+            keytab: None,
+            realm: realm.map(|r| r.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_negotiate_token() {
+        assert_eq!(Some(b"token".to_vec()), negotiate_token("Negotiate dG9rZW4="));
+        assert_eq!(Some(b"token".to_vec()), negotiate_token("negotiate  dG9rZW4= "));
+        assert_eq!(None, negotiate_token("Basic dG9rZW4="));
+        assert_eq!(None, negotiate_token("Negotiate"));
+        assert_eq!(None, negotiate_token("Negotiate not-base64!"));
+    }
+
+    #[test]
+    fn test_user_name() {
+        assert_eq!(Some("alice".to_string()), user_name(&config(None), "alice@COMPANY.COM"));
+        assert_eq!(Some("alice".to_string()), user_name(&config(Some("company.com")), "alice@COMPANY.COM"));
+        assert_eq!(None, user_name(&config(Some("COMPANY.COM")), "alice@OTHER.COM"));
+        assert_eq!(None, user_name(&config(Some("COMPANY.COM")), "alice"));
+        assert_eq!(Some("alice".to_string()), user_name(&config(None), "alice"));
+        assert_eq!(None, user_name(&config(None), "HTTP/octobot.company.com@COMPANY.COM"));
+        assert_eq!(None, user_name(&config(None), "@COMPANY.COM"));
+    }
+}
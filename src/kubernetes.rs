@@ -0,0 +1,409 @@
+use std::env;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use failure::format_err;
+use log::{error, info};
+use reqwest;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::Config;
+use crate::db;
+use crate::errors::*;
+use crate::scheduler::Leadership;
+use crate::util;
+use crate::worker;
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+// set by SIGTERM, which kubernetes sends to stop the pod
+static TERMINATING: AtomicBool = AtomicBool::new(false);
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+// Whether this replica is stopping: it shouldn't be sent requests or take work from queues
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::SeqCst)
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Readiness {
+    pub ready: bool,
+    pub draining: bool,
+    // jobs started that haven't finished
+    pub in_flight: usize,
+}
+
+pub fn readiness() -> Readiness {
+    Readiness {
+        ready: !is_draining(),
+        draining: is_draining(),
+        in_flight: worker::in_flight(),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaseSpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub holder_identity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lease_duration_seconds: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acquire_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renew_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lease_transitions: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LeaseMetadata {
+    resource_version: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Lease {
+    metadata: LeaseMetadata,
+    #[serde(default)]
+    spec: LeaseSpec,
+}
+
+// A MicroTime, e.g. "2020-01-02T03:04:05.000000Z"
+pub fn micro_time(secs: i64) -> String {
+    let tm = time::at_utc(time::Timespec::new(secs, 0));
+    time::strftime("%Y-%m-%dT%H:%M:%S.000000Z", &tm).unwrap_or_default()
+}
+
+fn parse_micro_time(value: &str) -> Option<i64> {
+    value.get(..19).and_then(|t| util::parse_timestamp(&format!("{}Z", t)))
+}
+
+// What to write to the lease for |identity| to hold it from |now|: renewed if it already does, taken over if no
+// one does or the holder let it expire. None if another replica holds it.
+pub fn claim(current: Option<&LeaseSpec>, identity: &str, duration_secs: i64, now: i64) -> Option<LeaseSpec> {
+    let mut spec = LeaseSpec {
+        holder_identity: Some(identity.to_string()),
+        lease_duration_seconds: Some(duration_secs),
+        acquire_time: Some(micro_time(now)),
+        renew_time: Some(micro_time(now)),
+        lease_transitions: Some(0),
+    };
+
+    let current = match current {
+        Some(c) => c,
+        None => return Some(spec),
+    };
+    let holder = current.holder_identity.as_ref().filter(|h| !h.is_empty());
+    let transitions = current.lease_transitions.unwrap_or(0);
+
+    if holder.map(|h| h == identity).unwrap_or(false) {
+        spec.acquire_time = current.acquire_time.clone().or(spec.acquire_time);
+        spec.lease_transitions = Some(transitions);
+        return Some(spec);
+    }
+
+    let renewed = current.renew_time.as_ref().and_then(|t| parse_micro_time(t)).unwrap_or(0);
+    let expired = renewed + current.lease_duration_seconds.unwrap_or(duration_secs) < now;
+    if holder.is_none() || expired {
+        spec.lease_transitions = Some(transitions + 1);
+        Some(spec)
+    } else {
+        None
+    }
+}
+
+// Talks to the kubernetes API as the pod's service account
+struct ApiClient {
+    client: reqwest::Client,
+    api_url: String,
+}
+
+impl ApiClient {
+    fn new(config: &Config) -> Result<ApiClient> {
+        let api_url = match config.kubernetes.as_ref().and_then(|k| k.api_url.clone()) {
+            Some(url) => url,
+            None => match (env::var("KUBERNETES_SERVICE_HOST"), env::var("KUBERNETES_SERVICE_PORT")) {
+                (Ok(host), Ok(port)) => format!("https://{}:{}", host, port),
+                _ => return Err(format_err!("Not running in kubernetes: set kubernetes.api_url")),
+            },
+        };
+
+        let mut builder = reqwest::Client::builder();
+        if let Ok(ca) = fs::read(format!("{}/ca.crt", SERVICE_ACCOUNT_DIR)) {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&ca)?);
+        }
+
+        Ok(ApiClient {
+            client: builder.build()?,
+            api_url: api_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let req = self.client.request(method, &format!("{}{}", self.api_url, path));
+        // read every time, since kubernetes rotates it
+        match fs::read_to_string(format!("{}/token", SERVICE_ACCOUNT_DIR)) {
+            Ok(token) => req.header(reqwest::header::AUTHORIZATION, format!("Bearer {}", token.trim())),
+            Err(_) => req,
+        }
+    }
+}
+
+// The pod's namespace, unless configured
+fn namespace(config: &Config) -> Result<String> {
+    match config.kubernetes.as_ref().and_then(|k| k.namespace.clone()) {
+        Some(ns) => Ok(ns),
+        None => fs::read_to_string(format!("{}/namespace", SERVICE_ACCOUNT_DIR))
+            .map(|ns| ns.trim().to_string())
+            .map_err(|e| format_err!("Error reading the pod's namespace (set kubernetes.namespace): {}", e)),
+    }
+}
+
+// Holds (or tries to take) the lease that makes a replica the leader
+pub struct LeaderElector {
+    api: ApiClient,
+    // of the lease
+    path: String,
+    name: String,
+    identity: String,
+    duration_secs: i64,
+    // until when the lease is ours, if it is
+    held_until: Mutex<i64>,
+}
+
+impl LeaderElector {
+    pub fn new(config: &Config) -> Result<LeaderElector> {
+        let name = config.kubernetes_lease_name();
+        let identity = env::var("POD_NAME")
+            .or_else(|_| env::var("HOSTNAME"))
+            .map_err(|_| format_err!("Neither POD_NAME nor HOSTNAME is set"))?;
+        Ok(LeaderElector {
+            api: ApiClient::new(config)?,
+            path: format!("/apis/coordination.k8s.io/v1/namespaces/{}/leases", namespace(config)?),
+            name: name,
+            identity: identity,
+            duration_secs: config.kubernetes_lease_duration_secs() as i64,
+            held_until: Mutex::new(0),
+        })
+    }
+
+    fn get(&self) -> Result<Option<Lease>> {
+        let mut resp = self
+            .api
+            .request(reqwest::Method::GET, &format!("{}/{}", self.path, self.name))
+            .send()
+            .map_err(|e| format_err!("Error getting lease {}: {}", self.name, e))?;
+        match resp.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            s if s.is_success() => Ok(Some(resp.json::<Lease>()?)),
+            s => Err(format_err!("Error getting lease {}: {}", self.name, s)),
+        }
+    }
+
+    // Creates or replaces the lease. Returns false if another replica changed it first.
+    fn put(&self, current: Option<&Lease>, spec: &LeaseSpec) -> Result<bool> {
+        let mut body = json!({
+            "apiVersion": "coordination.k8s.io/v1",
+            "kind": "Lease",
+            "metadata": {"name": self.name},
+            "spec": spec,
+        });
+        let req = match current {
+            Some(lease) => {
+                body["metadata"]["resourceVersion"] = json!(lease.metadata.resource_version);
+                self.api.request(reqwest::Method::PUT, &format!("{}/{}", self.path, self.name))
+            }
+            None => self.api.request(reqwest::Method::POST, &self.path),
+        };
+
+        let resp = req.json(&body).send().map_err(|e| format_err!("Error updating lease {}: {}", self.name, e))?;
+        match resp.status() {
+            reqwest::StatusCode::CONFLICT => Ok(false),
+            s if s.is_success() => Ok(true),
+            s => Err(format_err!("Error updating lease {}: {}", self.name, s)),
+        }
+    }
+
+    // Renews the lease if this replica holds it, or takes it if it is free. Returns whether this replica leads.
+    pub fn renew(&self, now: i64) -> Result<bool> {
+        let current = self.get()?;
+        let leads = match claim(current.as_ref().map(|l| &l.spec), &self.identity, self.duration_secs, now) {
+            Some(spec) => self.put(current.as_ref(), &spec)?,
+            None => false,
+        };
+
+        let mut held_until = self.held_until.lock().unwrap();
+        if leads && *held_until <= now {
+            info!("Became the leader ({} holds lease {})", self.identity, self.name);
+        } else if !leads && *held_until > now {
+            info!("No longer the leader: lease {} was taken", self.name);
+        }
+        *held_until = if leads { now + self.duration_secs } else { 0 };
+        Ok(leads)
+    }
+
+    // Gives up the lease, so that another replica can take over without waiting for it to expire
+    pub fn release(&self) -> Result<()> {
+        if !self.is_leader() {
+            return Ok(());
+        }
+        *self.held_until.lock().unwrap() = 0;
+
+        if let Some(lease) = self.get()? {
+            if lease.spec.holder_identity.as_ref() == Some(&self.identity) {
+                let spec = LeaseSpec {
+                    holder_identity: None,
+                    lease_duration_seconds: Some(1),
+                    renew_time: Some(micro_time(db::now())),
+                    ..lease.spec.clone()
+                };
+                self.put(Some(&lease), &spec)?;
+                info!("Released lease {}", self.name);
+            }
+        }
+        Ok(())
+    }
+
+    // Renews a third of the way into the lease, so that a couple of failures don't lose it
+    fn renew_interval(&self) -> Duration {
+        Duration::from_secs(std::cmp::max(1, self.duration_secs / 3) as u64)
+    }
+}
+
+impl Leadership for LeaderElector {
+    // Only while the lease is ours: if renewing fails for long enough, another replica may have taken it
+    fn is_leader(&self) -> bool {
+        *self.held_until.lock().unwrap() > db::now()
+    }
+}
+
+#[cfg(unix)]
+fn handle_sigterm() {
+    extern "C" fn on_sigterm(_signum: i32) {
+        TERMINATING.store(true, Ordering::SeqCst);
+    }
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    const SIGTERM: i32 = 15;
+
+    // only stores to an atomic, which is safe in a signal handler
+    unsafe {
+        signal(SIGTERM, on_sigterm);
+    }
+}
+
+#[cfg(not(unix))]
+fn handle_sigterm() {}
+
+// Keeps renewing the lease, if there is an elector, and on SIGTERM stops being ready, waits (up to the drain
+// timeout) for the work in flight to finish, gives up the lease, and exits.
+pub fn start(config: &Config, elector: Option<Arc<LeaderElector>>) {
+    if let Some(ref elector) = elector {
+        let elector = elector.clone();
+        thread::spawn(move || {
+            while !is_draining() {
+                if let Err(e) = elector.renew(db::now()) {
+                    error!("Error renewing lease {}: {}", elector.name, e);
+                }
+                thread::sleep(elector.renew_interval());
+            }
+        });
+    }
+
+    handle_sigterm();
+    let drain_timeout = config.kubernetes_drain_timeout_secs() as i64;
+    thread::spawn(move || {
+        while !TERMINATING.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        info!("Received SIGTERM: draining {} jobs in flight", worker::in_flight());
+        DRAINING.store(true, Ordering::SeqCst);
+        let deadline = db::now() + drain_timeout;
+        while worker::in_flight() > 0 && db::now() < deadline {
+            thread::sleep(Duration::from_secs(1));
+        }
+        if worker::in_flight() > 0 {
+            error!("Stopping with {} jobs still in flight", worker::in_flight());
+        }
+
+        if let Some(elector) = elector {
+            if let Err(e) = elector.release() {
+                error!("Error releasing lease {}: {}", elector.name, e);
+            }
+        }
+        info!("Drained: exiting");
+        std::process::exit(0);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2020-01-02T03:04:05Z
+    const NOW: i64 = 1577934245;
+
+    #[test]
+    fn test_micro_time() {
+        assert_eq!("2020-01-02T03:04:05.000000Z", micro_time(NOW));
+        assert_eq!(Some(NOW), parse_micro_time("2020-01-02T03:04:05.123456Z"));
+        assert_eq!(None, parse_micro_time("2020-01-02"));
+    }
+
+    #[test]
+    fn test_claim_new() {
+        let spec = claim(None, "octobot-0", 15, NOW).unwrap();
+        assert_eq!(Some("octobot-0".to_string()), spec.holder_identity);
+        assert_eq!(Some(15), spec.lease_duration_seconds);
+        assert_eq!(Some(micro_time(NOW)), spec.renew_time);
+        assert_eq!(Some(0), spec.lease_transitions);
+    }
+
+    #[test]
+    fn test_claim() {
+        let held = LeaseSpec {
+            holder_identity: Some("octobot-0".into()),
+            lease_duration_seconds: Some(15),
+            acquire_time: Some(micro_time(NOW - 100)),
+            renew_time: Some(micro_time(NOW - 10)),
+            lease_transitions: Some(2),
+        };
+
+        // renewed by its holder
+        let spec = claim(Some(&held), "octobot-0", 15, NOW).unwrap();
+        assert_eq!(held.acquire_time, spec.acquire_time);
+        assert_eq!(Some(micro_time(NOW)), spec.renew_time);
+        assert_eq!(Some(2), spec.lease_transitions);
+
+        // still held
+        assert_eq!(None, claim(Some(&held), "octobot-1", 15, NOW));
+
+        // expired
+        let spec = claim(Some(&held), "octobot-1", 15, NOW + 10).unwrap();
+        assert_eq!(Some("octobot-1".to_string()), spec.holder_identity);
+        assert_eq!(Some(micro_time(NOW + 10)), spec.acquire_time);
+        assert_eq!(Some(3), spec.lease_transitions);
+
+        // released
+        let released = LeaseSpec { holder_identity: None, ..held.clone() };
+        let spec = claim(Some(&released), "octobot-1", 15, NOW).unwrap();
+        assert_eq!(Some("octobot-1".to_string()), spec.holder_identity);
+    }
+
+    #[test]
+    fn test_lease_spec_json() {
+        let spec = LeaseSpec {
+            holder_identity: Some("octobot-0".into()),
+            lease_duration_seconds: Some(15),
+            ..LeaseSpec::default()
+        };
+        assert_eq!(json!({"holderIdentity": "octobot-0", "leaseDurationSeconds": 15}), json!(spec));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::repos::RepoLabelRule;
+
+// What the rule does, for the event history
+pub fn actions(rule: &RepoLabelRule) -> Vec<String> {
+    let mut actions = vec![];
+    if !rule.review_team.trim().is_empty() {
+        actions.push(format!("request review from team '{}'", rule.review_team.trim()));
+    }
+    if !rule.channel_list().is_empty() {
+        actions.push(format!("post to {}", rule.channel_list().join(", ")));
+    }
+    if !rule.jira_transitions().is_empty() {
+        actions.push(format!("move JIRA issues to {}", rule.jira_transitions().join(" or ")));
+    }
+    if rule.merge {
+        actions.push("merge once all checks pass".into());
+    }
+    actions
+}
+
+// The rules that adding |label| fires. Github labels aren't case sensitive.
+pub fn fired<'a>(rules: &'a Vec<RepoLabelRule>, label: &str) -> Vec<&'a RepoLabelRule> {
+    rules.iter().filter(|r| r.label.eq_ignore_ascii_case(label.trim())).collect()
+}
+
+// Whether a PR's labels need looking up to keep its merge going after a push
+pub fn has_merge_rules(rules: &Vec<RepoLabelRule>) -> bool {
+    rules.iter().any(|r| r.merge)
+}
+
+// The merge rule of one of the PR's labels, if any
+pub fn merge_rule<'a>(rules: &'a Vec<RepoLabelRule>, labels: &Vec<String>) -> Option<&'a RepoLabelRule> {
+    rules.iter().find(|r| r.merge && labels.iter().any(|l| r.label.eq_ignore_ascii_case(l)))
+}
+
+// What's wrong with the rule, if anything
+pub fn check(rule: &RepoLabelRule) -> Option<String> {
+    if rule.label.trim().is_empty() {
+        return Some("label rules need a `label`".into());
+    }
+    if actions(rule).is_empty() {
+        return Some(format!(
+            "label rule '{}' needs a `review_team`, `channels`, `jira_transition` or `merge`",
+            rule.label
+        ));
+    }
+    if rule.review_team.contains('/') {
+        return Some(format!("label rule '{}': `review_team` is a team slug of the repo's org", rule.label));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Vec<RepoLabelRule> {
+        vec![
+            RepoLabelRule::new("security").with_review_team("appsec").with_channels("sec-reviews, @joe"),
+            RepoLabelRule::new("Security").with_jira_transition("Security Review"),
+            RepoLabelRule::new("automerge").merging(),
+        ]
+    }
+
+    #[test]
+    fn test_fired() {
+        let rules = rules();
+        assert_eq!(vec![&rules[0], &rules[1]], fired(&rules, "SECURITY"));
+        assert_eq!(vec![&rules[2]], fired(&rules, "automerge"));
+        assert_eq!(Vec::<&RepoLabelRule>::new(), fired(&rules, "docs"));
+    }
+
+    #[test]
+    fn test_merge_rule() {
+        let rules = rules();
+        assert!(has_merge_rules(&rules));
+        assert!(!has_merge_rules(&rules[..2].to_vec()));
+
+        assert_eq!(Some(&rules[2]), merge_rule(&rules, &vec!["docs".into(), "AutoMerge".into()]));
+        assert_eq!(None, merge_rule(&rules, &vec!["security".into()]));
+    }
+
+    #[test]
+    fn test_actions() {
+        let rules = rules();
+        assert_eq!(vec!["request review from team 'appsec'", "post to sec-reviews, @joe"], actions(&rules[0]));
+        assert_eq!(vec!["move JIRA issues to Security Review"], actions(&rules[1]));
+        assert_eq!(vec!["merge once all checks pass"], actions(&rules[2]));
+    }
+
+    #[test]
+    fn test_check() {
+        for rule in rules() {
+            assert_eq!(None, check(&rule));
+        }
+        assert!(check(&RepoLabelRule::new(" ").merging()).is_some());
+        assert!(check(&RepoLabelRule::new("security")).is_some());
+        assert!(check(&RepoLabelRule::new("security").with_review_team("some-org/appsec")).is_some());
+    }
+}
@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use log::{error, info};
+
+use crate::config::{Config, TaxonomyLabel};
+use crate::errors::*;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::scheduler;
+use crate::slack::{self, SlackAttachment, SlackAttachmentBuilder, SlackRequest};
+use crate::worker::Worker;
+
+pub const SYNC_INTERVAL_SECS: u64 = 6 * 60 * 60;
+
+// How a repo's labels differ from the taxonomy
+#[derive(Clone, Debug, PartialEq)]
+pub enum Drift {
+    Missing(TaxonomyLabel),
+    // the repo's label differs in its color, description or the case of its name
+    Changed(String, TaxonomyLabel),
+    // the repo has the label by an old name
+    OldName(String, TaxonomyLabel),
+    // the repo has both an old name and the current one. Merging them would drop one of them from issues and PRs,
+    // so it is left to people.
+    Duplicate(String, TaxonomyLabel),
+}
+
+impl Drift {
+    pub fn describe(&self) -> String {
+        match *self {
+            Drift::Missing(ref label) => format!("`{}` is missing", label.name),
+            Drift::Changed(ref name, ref label) => {
+                format!("`{}` doesn't match the color or description of `{}`", name, label.name)
+            }
+            Drift::OldName(ref name, ref label) => format!("`{}` is now named `{}`", name, label.name),
+            Drift::Duplicate(ref name, ref label) => {
+                format!("both `{}` and `{}` exist: merge them by hand", name, label.name)
+            }
+        }
+    }
+
+    pub fn is_fixable(&self) -> bool {
+        match *self {
+            Drift::Duplicate(_, _) => false,
+            _ => true,
+        }
+    }
+
+    fn fix(&self, github: &dyn Session, repo: &github::Repo) -> Result<()> {
+        let owner = repo.owner.login();
+        match *self {
+            Drift::Missing(ref label) => github.create_label(owner, &repo.name, &to_label(label)),
+            // renaming a label keeps it on the issues and PRs that have it
+            Drift::Changed(ref name, ref label) | Drift::OldName(ref name, ref label) => {
+                github.update_label(owner, &repo.name, name, &to_label(label))
+            }
+            Drift::Duplicate(_, _) => Ok(()),
+        }
+    }
+}
+
+pub fn is_valid_color(color: &str) -> bool {
+    let color = color.trim_start_matches('#');
+    color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn to_label(label: &TaxonomyLabel) -> github::Label {
+    let mut result = github::Label::new(&label.name);
+    result.color = Some(label.color.trim_start_matches('#').to_lowercase());
+    result.description = Some(label.description.clone());
+    result
+}
+
+fn is_up_to_date(existing: &github::Label, label: &TaxonomyLabel) -> bool {
+    existing.name == label.name
+        && existing.color.as_ref().map_or(false, |c| c.eq_ignore_ascii_case(label.color.trim_start_matches('#')))
+        && existing.description.as_ref().map(|d| d.as_str()).unwrap_or("") == label.description
+}
+
+// Label names are case-insensitive on github
+pub fn find_drift(taxonomy: &Vec<TaxonomyLabel>, existing: &Vec<github::Label>) -> Vec<Drift> {
+    let mut drift = vec![];
+    for label in taxonomy {
+        let current = existing.iter().find(|l| l.name.eq_ignore_ascii_case(&label.name));
+        let old = existing.iter().find(|l| label.renamed_from.iter().any(|o| l.name.eq_ignore_ascii_case(o)));
+        match (current, old) {
+            (Some(_), Some(old)) => drift.push(Drift::Duplicate(old.name.clone(), label.clone())),
+            (Some(current), None) => {
+                if !is_up_to_date(current, label) {
+                    drift.push(Drift::Changed(current.name.clone(), label.clone()));
+                }
+            }
+            (None, Some(old)) => drift.push(Drift::OldName(old.name.clone(), label.clone())),
+            (None, None) => drift.push(Drift::Missing(label.clone())),
+        };
+    }
+    drift
+}
+
+// Fixes what it can of the repo's drift, unless only reporting it. Returns all of the drift found.
+pub fn sync_repo(
+    github: &dyn Session,
+    repo: &github::Repo,
+    taxonomy: &Vec<TaxonomyLabel>,
+    report_only: bool,
+) -> Result<Vec<Drift>> {
+    let existing = github.get_repo_labels(repo.owner.login(), &repo.name)?;
+    let drift = find_drift(taxonomy, &existing);
+    if !report_only {
+        for d in drift.iter().filter(|d| d.is_fixable()) {
+            if let Err(e) = d.fix(github, repo) {
+                error!("Error fixing label drift in {} ({}): {}", repo.full_name, d.describe(), e);
+            }
+        }
+    }
+    Ok(drift)
+}
+
+pub fn report(drift: &Vec<(String, Vec<Drift>)>, report_only: bool) -> (String, Vec<SlackAttachment>) {
+    let repos = drift.iter().filter(|&&(_, ref d)| !d.is_empty()).collect::<Vec<_>>();
+    let msg = if report_only {
+        format!("Label taxonomy: found drift in {} repo(s)", repos.len())
+    } else {
+        format!("Label taxonomy: fixed drift in {} repo(s)", repos.len())
+    };
+
+    let attachments = repos
+        .iter()
+        .map(|&&(ref repo, ref d)| {
+            let lines = d.iter().map(|d| d.describe()).collect::<Vec<_>>();
+            let mut attachment = SlackAttachmentBuilder::new(&lines.join("\n"));
+            attachment.title(repo.clone());
+            if d.iter().any(|d| !d.is_fixable()) {
+                attachment.color("warning");
+            }
+            attachment.build()
+        })
+        .collect();
+
+    (msg, attachments)
+}
+
+// Keeps the labels of all configured repos in line with the taxonomy
+pub struct LabelSyncer {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    slack: Arc<dyn Worker<SlackRequest>>,
+}
+
+impl LabelSyncer {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        slack: Arc<dyn Worker<SlackRequest>>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(LabelSyncer {
+            config: config,
+            github_app: github_app,
+            slack: slack,
+        })
+    }
+
+    fn sync(&self, repo: &str, taxonomy: &Vec<TaxonomyLabel>) -> Result<Vec<Drift>> {
+        let repo = github::Repo::parse(&format!("https://{}/{}", self.config.github_host(repo), repo))?;
+        let github = self.github_app.new_session(&repo.owner.login(), &repo.name)?;
+
+        sync_repo(&github, &repo, taxonomy, self.config.label_taxonomy_report_only())
+    }
+}
+
+impl scheduler::Task for LabelSyncer {
+    fn run(&self, _now: i64) -> Result<()> {
+        let taxonomy = match self.config.label_taxonomy {
+            Some(ref t) => t,
+            None => return Ok(()),
+        };
+
+        let mut drift = vec![];
+        // org-wide entries don't say which repos to sync
+        for info in self.config.repos().get_all()?.into_iter().filter(|r| r.repo.contains('/')) {
+            match self.sync(&info.repo, &taxonomy.labels) {
+                Ok(d) => drift.push((info.repo, d)),
+                Err(e) => error!("Error syncing labels of {}: {}", info.repo, e),
+            };
+        }
+
+        let count = drift.iter().map(|&(_, ref d)| d.len()).sum::<usize>();
+        info!("Found {} label(s) out of line with the taxonomy", count);
+        if let (true, Some(channel)) = (count > 0, taxonomy.channel.as_ref()) {
+            let (msg, attachments) = report(&drift, self.config.label_taxonomy_report_only());
+            self.slack.send(slack::req(channel, &msg, attachments));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(name: &str, renamed_from: Vec<&str>) -> TaxonomyLabel {
+        TaxonomyLabel {
+            name: name.into(),
+            color: "#D73A4A".into(),
+            description: "Something isn't working".into(),
+            renamed_from: renamed_from.into_iter().map(|n| n.to_string()).collect(),
+        }
+    }
+
+    fn existing(name: &str, color: &str, description: &str) -> github::Label {
+        let mut label = github::Label::new(name);
+        label.color = Some(color.into());
+        label.description = Some(description.into());
+        label
+    }
+
+    #[test]
+    fn test_is_valid_color() {
+        assert!(is_valid_color("d73a4a"));
+        assert!(is_valid_color("#D73A4A"));
+        assert!(!is_valid_color("red"));
+        assert!(!is_valid_color("d73a4"));
+    }
+
+    #[test]
+    fn test_find_drift() {
+        let taxonomy = vec![
+            label("bug", vec!["defect"]),
+            label("security", vec![]),
+            label("docs", vec!["documentation"]),
+            label("breaking", vec!["breaking-change"]),
+            label("ok", vec![]),
+        ];
+        let labels = vec![
+            existing("Security", "d73a4a", "Something isn't working"),
+            existing("documentation", "0000ff", ""),
+            existing("breaking", "d73a4a", "Something isn't working"),
+            existing("Breaking-Change", "d73a4a", ""),
+            existing("ok", "D73A4A", "Something isn't working"),
+            existing("not-in-the-taxonomy", "ffffff", ""),
+        ];
+
+        let drift = find_drift(&taxonomy, &labels);
+        assert_eq!(
+            vec![
+                Drift::Missing(taxonomy[0].clone()),
+                Drift::Changed("Security".into(), taxonomy[1].clone()),
+                Drift::OldName("documentation".into(), taxonomy[2].clone()),
+                Drift::Duplicate("Breaking-Change".into(), taxonomy[3].clone()),
+            ],
+            drift
+        );
+        assert_eq!(
+            vec![true, true, true, false],
+            drift.iter().map(|d| d.is_fixable()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let drift = vec![
+            ("some-user/some-repo".to_string(), vec![Drift::Missing(label("bug", vec![]))]),
+            ("some-user/other-repo".to_string(), vec![]),
+            (
+                "some-user/third-repo".to_string(),
+                vec![Drift::Duplicate("defect".into(), label("bug", vec!["defect"]))],
+            ),
+        ];
+
+        let (msg, attachments) = report(&drift, false);
+        assert_eq!("Label taxonomy: fixed drift in 2 repo(s)", msg);
+        assert_eq!(2, attachments.len());
+        assert_eq!(Some("some-user/some-repo".to_string()), attachments[0].title);
+        assert_eq!("`bug` is missing", attachments[0].text);
+        assert_eq!(None, attachments[0].color);
+        assert_eq!("both `defect` and `bug` exist: merge them by hand", attachments[1].text);
+        assert_eq!(Some("warning".to_string()), attachments[1].color);
+
+        let (msg, _) = report(&drift, true);
+        assert_eq!("Label taxonomy: found drift in 2 repo(s)", msg);
+    }
+}
@@ -1,22 +1,25 @@
 use std::io::{self, Write};
 
 use octobot::config;
+use octobot::db;
 use octobot::ldap_auth;
+use octobot::network;
 
 // A test utility to connect to LDAP to verify the configuration
 fn main() {
     if std::env::args().count() < 3 {
-        panic!("Usage: ldap-check <config file> <command: auth | search>");
+        panic!("Usage: ldap-check <config file> <command: auth | search | health>");
     }
 
     let config_file = std::env::args().nth(1).unwrap();
     let command = std::env::args().nth(2).unwrap();
 
     let config = config::new(config_file.into()).expect("Error parsing config");
+    network::configure(&config.network).expect("Error in [network] config");
     let ldap_config = config.ldap.expect("No LDAP config");
 
-    if command != "auth" && command != "search" {
-        panic!("Invalid command: {}. Must specify auth or search", command);
+    if command != "auth" && command != "search" && command != "health" {
+        panic!("Invalid command: {}. Must specify auth, search or health", command);
     }
 
     if command == "auth" {
@@ -41,6 +44,14 @@ fn main() {
                 }
             }
         }
+    } else if command == "health" {
+        let client = ldap_auth::LdapClient::new(&ldap_config);
+        for server in client.check_health(db::now()) {
+            match server.last_error {
+                Some(e) => println!(" - {}: down ({})", server.url, e),
+                None => println!(" - {}: up", server.url),
+            }
+        }
     }
 }
 
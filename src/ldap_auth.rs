@@ -1,18 +1,53 @@
 use std::ptr;
+use std::sync::{Arc, Mutex};
 
 use log::{debug, info, warn};
 use failure::format_err;
-use openldap::{self, LDAPResponse, RustLDAP};
+use openldap::{self, RustLDAP};
 use regex::Regex;
+use serde_derive::Serialize;
 
-use crate::config::LdapConfig;
+use crate::config::{LdapConfig, LdapGroupRole};
+use crate::config_reload::LiveConfig;
+use crate::db;
 use crate::errors::*;
+use crate::scheduler;
+
+pub const DEFAULT_GROUP_ATTRIBUTE: &str = "memberOf";
+pub const DEFAULT_POOL_SIZE: usize = 4;
+pub const HEALTH_CHECK_INTERVAL_SECS: u64 = 60;
+// how long a server that failed is passed over for the others
+const DOWN_SECS: i64 = 30;
+// libldap's options for the CA certificates to trust, and for making the TLS context again once they are set
+const LDAP_OPT_X_TLS_CACERTFILE: i32 = 0x6002;
+const LDAP_OPT_X_TLS_NEWCTX: i32 = 0x600f;
 
 pub struct LDAPEntry {
     pub dn: String,
+    // DNs of the groups the entry is a member of, from the group attribute
+    pub groups: Vec<String>,
+}
+
+// What members of an LDAP group may do in octobot
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    // may log in
+    User,
+    // also gets admin rights
+    Admin,
+}
+
+impl Role {
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "user" => Some(Role::User),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
 }
 
-fn new_ldap(url: &str) -> Result<RustLDAP> {
+fn new_ldap(url: &str, ca_file: Option<&str>) -> Result<RustLDAP> {
     let ldap = RustLDAP::new(url)?;
 
     ldap.set_option(
@@ -25,117 +60,602 @@ fn new_ldap(url: &str) -> Result<RustLDAP> {
         &openldap::codes::options::LDAP_OPT_X_TLS_DEMAND,
     );
 
+    if let Some(ca_file) = ca_file {
+        if !ldap.set_option(LDAP_OPT_X_TLS_CACERTFILE, ca_file)
+            || !ldap.set_option(LDAP_OPT_X_TLS_NEWCTX, &0)
+        {
+            return Err(format_err!("Could not use {:?} as the LDAP CA file", ca_file));
+        }
+    }
+
     Ok(ldap)
 }
 
+// For one-off checks: logins go through the config's pooled client instead
 pub fn auth(user: &str, pass: &str, config: &LdapConfig) -> Result<bool> {
-    if user.is_empty() {
-        info!("Cannot authenticate without username");
-        return Ok(false);
+    Ok(LdapClient::new(config).authenticate(user, pass)?.is_some())
+}
+
+pub fn search(config: &LdapConfig, extra_filter: Option<&str>, max_results: i32) -> Result<Vec<LDAPEntry>> {
+    LdapClient::new(config).search(extra_filter, max_results)
+}
+
+// The configured servers, in the order they are tried
+pub fn server_urls(config: &LdapConfig) -> Vec<String> {
+    let mut urls = vec![config.url.clone()];
+    urls.extend(config.failover_urls.iter().flatten().cloned());
+    urls
+}
+
+// A connection bound as the service account
+struct Connection {
+    url: String,
+    ldap: RustLDAP,
+}
+
+// RustLDAP holds a raw libldap handle, which may move between threads as long as only one uses it at a time: a
+// connection is either in the pool or used by the one request that took it.
+unsafe impl Send for Connection {}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ServerHealth {
+    pub url: String,
+    pub healthy: bool,
+    // failures in a row
+    pub failures: u32,
+    pub last_error: Option<String>,
+    pub last_checked: i64,
+    // until when it is only tried if no other server is up
+    #[serde(skip)]
+    down_until: i64,
+}
+
+// Keeps connections to the LDAP servers open between logins, and fails over to the next server when one is down
+pub struct LdapClient {
+    config: LdapConfig,
+    idle: Mutex<Vec<Connection>>,
+    health: Mutex<Vec<ServerHealth>>,
+}
+
+impl LdapClient {
+    pub fn new(config: &LdapConfig) -> LdapClient {
+        let health = server_urls(config)
+            .into_iter()
+            .map(|url| ServerHealth {
+                url: url,
+                healthy: true,
+                failures: 0,
+                last_error: None,
+                last_checked: 0,
+                down_until: 0,
+            })
+            .collect();
+        LdapClient {
+            config: config.clone(),
+            idle: Mutex::new(vec![]),
+            health: Mutex::new(health),
+        }
     }
 
-    // in the absence of `ldap_escape` from ldap3, just whitelist acceptable characters
-    let re = Regex::new(r"([^A-Za-z0-9\.\-_@])").unwrap();
-    for cap in re.captures_iter(user) {
-        info!("Invalid username character in username: '{}', '{}'", &cap[1], user);
-        return Ok(false);
+    pub fn health(&self) -> Vec<ServerHealth> {
+        self.health.lock().unwrap().clone()
     }
 
-    let user_filters = config.userid_attributes.iter().map(|a| format!("({}={})", a, user)).collect::<Vec<_>>();
+    pub fn idle_connections(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
 
-    let user_filter;
-    if user_filters.len() == 0 {
-        info!("Cannot authenticate without userid attributes");
-        return Ok(false);
-    } else if user_filters.len() == 1 {
-        user_filter = user_filters[0].clone();
-    } else {
-        user_filter = format!("(|{})", user_filters.join(""));
+    // Servers that are up first, in the configured order: the others are still tried after them
+    fn servers(&self, now: i64) -> Vec<String> {
+        let health = self.health.lock().unwrap();
+        let (up, down): (Vec<_>, Vec<_>) = health.iter().partition(|h| h.down_until <= now);
+        up.into_iter().chain(down).map(|h| h.url.clone()).collect()
     }
 
-    // search for the user's DN
-    let results = search(config, Some(&user_filter), 1)?;
+    fn mark_up(&self, url: &str, now: i64) {
+        let mut health = self.health.lock().unwrap();
+        if let Some(h) = health.iter_mut().find(|h| h.url == url) {
+            if !h.healthy {
+                info!("LDAP server {} is back up", url);
+            }
+            h.healthy = true;
+            h.failures = 0;
+            h.last_error = None;
+            h.last_checked = now;
+            h.down_until = 0;
+        }
+    }
 
-    if results.is_empty() {
-        debug!("No users found matching {}", user);
-        return Ok(false);
+    fn mark_down(&self, url: &str, error: &str, now: i64) {
+        let mut health = self.health.lock().unwrap();
+        if let Some(h) = health.iter_mut().find(|h| h.url == url) {
+            if h.healthy {
+                warn!("LDAP server {} is down: {}", url, error);
+            }
+            h.healthy = false;
+            h.failures += 1;
+            h.last_error = Some(error.to_string());
+            h.last_checked = now;
+            h.down_until = now + DOWN_SECS;
+        }
     }
-    if results.len() > 1 {
-        info!("Too many users found matching {}", user);
-        return Ok(false);
+
+    fn connect(&self, url: &str) -> Result<Connection> {
+        let ldap = new_ldap(url, self.config.tls_ca_file.as_ref().map(|f| f.as_str()))?;
+        if self.config.starttls.unwrap_or(false) {
+            let res = ldap.start_tls(None, None)?;
+            if res != 0 {
+                return Err(format_err!("StartTLS failed with error code {}", res));
+            }
+        }
+
+        let conn = Connection {
+            url: url.to_string(),
+            ldap: ldap,
+        };
+        self.bind_service_account(&conn)?;
+        Ok(conn)
     }
 
-    let user_dn = &results[0].dn;
-    if user_dn.is_empty() {
-        info!("User found but with empty DN!");
-        return Ok(false);
+    // With an empty bind_user, this is an anonymous bind
+    fn bind_service_account(&self, conn: &Connection) -> Result<()> {
+        let bind_res = conn.ldap.simple_bind(&self.config.bind_user, &self.config.bind_pass)?;
+        if bind_res != 0 {
+            return Err(format_err!("LDAP service account bind failed with error code {}", bind_res));
+        }
+        Ok(())
     }
 
-    // now try to bind as the user
-    let ldap = new_ldap(&config.url)?;
-    let res = ldap.simple_bind(&user_dn, &pass)?;
-    if res == 0 {
-        Ok(true)
-    } else if res == 49 {
+    // A new connection to the first server that answers
+    fn open(&self) -> Result<Connection> {
+        let now = db::now();
+        let mut last_error = None;
+        for url in self.servers(now) {
+            match self.connect(&url) {
+                Ok(conn) => {
+                    self.mark_up(&url, now);
+                    return Ok(conn);
+                }
+                Err(e) => {
+                    self.mark_down(&url, &e.to_string(), now);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| format_err!("No LDAP servers configured")))
+    }
+
+    fn release(&self, conn: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.config.pool_size.unwrap_or(DEFAULT_POOL_SIZE) {
+            idle.push(conn);
+        }
+    }
+
+    // Runs `f` with a pooled connection, or with a new one if there is none or the server closed it since
+    fn with_connection<T, F: Fn(&Connection) -> Result<T>>(&self, f: F) -> Result<T> {
+        let pooled = self.idle.lock().unwrap().pop();
+        if let Some(conn) = pooled {
+            match f(&conn) {
+                Ok(result) => {
+                    self.release(conn);
+                    return Ok(result);
+                }
+                Err(e) => debug!("Dropping pooled connection to {}: {}", conn.url, e),
+            }
+        }
+
+        let conn = self.open()?;
+        let result = f(&conn)?;
+        self.release(conn);
+        Ok(result)
+    }
+
+    // Connects to each server, to tell which are up without waiting for a login to fail over
+    pub fn check_health(&self, now: i64) -> Vec<ServerHealth> {
+        for url in server_urls(&self.config) {
+            match self.connect(&url) {
+                Ok(conn) => {
+                    self.mark_up(&url, now);
+                    self.release(conn);
+                }
+                Err(e) => self.mark_down(&url, &e.to_string(), now),
+            }
+        }
+        self.health()
+    }
+
+    // The user's entry, matched on the userid attributes
+    pub fn find_user(&self, user: &str) -> Result<Option<LDAPEntry>> {
+        if user.is_empty() {
+            info!("Cannot authenticate without username");
+            return Ok(None);
+        }
+
+        if !valid_username(user) {
+            return Ok(None);
+        }
+
+        let user_filters =
+            self.config.userid_attributes.iter().map(|a| format!("({}={})", a, user)).collect::<Vec<_>>();
+
+        let user_filter;
+        if user_filters.len() == 0 {
+            info!("Cannot authenticate without userid attributes");
+            return Ok(None);
+        } else if user_filters.len() == 1 {
+            user_filter = user_filters[0].clone();
+        } else {
+            user_filter = format!("(|{})", user_filters.join(""));
+        }
+
+        // search for the user's DN
+        let results = self.search(Some(&user_filter), 1)?;
+
+        if results.is_empty() {
+            debug!("No users found matching {}", user);
+            return Ok(None);
+        }
+        if results.len() > 1 {
+            info!("Too many users found matching {}", user);
+            return Ok(None);
+        }
+
+        let entry = results.into_iter().next().unwrap();
+        if entry.dn.is_empty() {
+            info!("User found but with empty DN!");
+            return Ok(None);
+        }
+        Ok(Some(entry))
+    }
+
+    // The user's entry, if the password is theirs
+    pub fn authenticate(&self, user: &str, pass: &str) -> Result<Option<LDAPEntry>> {
+        // a bind with a DN but no password is an unauthenticated bind, which servers let succeed
+        if pass.is_empty() {
+            info!("Cannot authenticate without password");
+            return Ok(None);
+        }
+        if self.config.user_dn_template.is_some() {
+            return self.bind_as_user(user, pass);
+        }
+
+        let entry = match self.find_user(user)? {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+
+        // now try to bind as the user, then as the service account again before the connection goes back to the pool
+        let res = self.with_connection(|conn| {
+            let res = conn.ldap.simple_bind(&entry.dn, &pass)?;
+            self.bind_service_account(conn)?;
+            Ok(res)
+        })?;
+        if bind_succeeded(res) {
+            Ok(Some(entry))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Binds as the DN from user_dn_template without searching for it first, then reads the user's entry while bound
+    // as them, as the service account may not be allowed to
+    fn bind_as_user(&self, user: &str, pass: &str) -> Result<Option<LDAPEntry>> {
+        let dn = match user_dn(&self.config, user) {
+            Some(dn) => dn,
+            None => return Ok(None),
+        };
+        let filter = search_filter(&self.config, None).unwrap_or_else(|| "(objectClass=*)".into());
+
+        let (res, entries) = self.with_connection(|conn| {
+            let res = conn.ldap.simple_bind(&dn, &pass)?;
+            let entries = if bind_succeeded(res) {
+                self.search_with(conn, &dn, openldap::codes::scopes::LDAP_SCOPE_BASE, &filter, 1)
+            } else {
+                Ok(vec![])
+            };
+            self.bind_service_account(conn)?;
+            Ok((res, entries?))
+        })?;
+        if !bind_succeeded(res) {
+            return Ok(None);
+        }
+
+        match entries.into_iter().next() {
+            Some(entry) => Ok(Some(entry)),
+            None => {
+                info!("{} is not matched by the LDAP search filter", dn);
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn search(&self, extra_filter: Option<&str>, max_results: i32) -> Result<Vec<LDAPEntry>> {
+        let search_filter = search_filter(&self.config, extra_filter).unwrap_or_else(|| {
+            warn!("No LDAP search filter configured. There may be lots of results");
+            "(objectClass=*)".to_string()
+        });
+
+        let scope = openldap::codes::scopes::LDAP_SCOPE_SUB;
+        self.with_connection(|conn| self.search_with(conn, &self.config.base_dn, scope, &search_filter, max_results))
+    }
+
+    fn search_with(
+        &self,
+        conn: &Connection,
+        base: &str,
+        scope: i32,
+        search_filter: &str,
+        max_results: i32,
+    ) -> Result<Vec<LDAPEntry>> {
+        // operational attributes like memberOf are only returned when asked for
+        let group_attribute = group_attribute(&self.config);
+        let resp = conn
+            .ldap
+            .ldap_search(
+                base,
+                scope,
+                Some(search_filter),
+                Some(vec!["*", group_attribute.as_str()]), // attrs
+                false,                                     // attrsonly
+                None,                                      // server controls
+                None,                                      // client controls
+                ptr::null_mut(),                           // timeout
+                max_results,
+            )
+            .map_err(|e| format_err!("Error on LDAP search: {}", e))?;
+
+        let entries = resp
+            .into_iter()
+            .filter_map(|attrs| {
+                let dn = attrs.get("dn").unwrap_or(&vec![]).iter().next().map(|s| s.to_string()).unwrap_or(
+                    String::new(),
+                );
+                if dn.is_empty() {
+                    warn!("Found entry with empty DN! Skipping.");
+                    None
+                } else {
+                    let groups = attrs
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(&group_attribute))
+                        .map(|(_, values)| values.clone())
+                        .unwrap_or_default();
+                    Some(LDAPEntry { dn: dn, groups: groups })
+                }
+            })
+            .collect::<Vec<LDAPEntry>>();
+
+        Ok(entries)
+    }
+}
+
+// Keeps track of which LDAP servers are up, on every replica since each has its own connections
+pub struct HealthCheck {
+    live_config: Arc<LiveConfig>,
+}
+
+impl HealthCheck {
+    pub fn new(live_config: Arc<LiveConfig>) -> Arc<dyn scheduler::Task> {
+        Arc::new(HealthCheck { live_config: live_config })
+    }
+}
+
+impl scheduler::Task for HealthCheck {
+    fn run(&self, now: i64) -> Result<()> {
+        if let Some(ref client) = self.live_config.get().ldap_client {
+            client.check_health(now);
+        }
+        Ok(())
+    }
+}
+
+// The configured filter, and the extra one if any, combined
+fn search_filter(config: &LdapConfig, extra_filter: Option<&str>) -> Option<String> {
+    let filters = extra_filter.into_iter().chain(config.search_filter.as_ref().map(|f| f.as_str())).collect::<Vec<_>>();
+    match filters.len() {
+        0 => None,
+        1 => Some(filters[0].to_string()),
+        _ => Some(format!("(&{})", filters.join(""))),
+    }
+}
+
+// in the absence of `ldap_escape` from ldap3, just whitelist acceptable characters
+fn valid_username(user: &str) -> bool {
+    let re = Regex::new(r"([^A-Za-z0-9\.\-_@])").unwrap();
+    match re.captures(user) {
+        Some(cap) => {
+            info!("Invalid username character in username: '{}', '{}'", &cap[1], user);
+            false
+        }
+        None => true,
+    }
+}
+
+// The DN to bind as from user_dn_template, e.g. "uid={username},ou=people,dc=company,dc=com"
+pub fn user_dn(config: &LdapConfig, user: &str) -> Option<String> {
+    let template = config.user_dn_template.as_ref()?;
+    if user.is_empty() || !valid_username(user) {
+        return None;
+    }
+    Some(template.replace("{username}", user))
+}
+
+fn bind_succeeded(res: i32) -> bool {
+    match res {
+        0 => true,
         // Avoid error messages for invalid creds
-        Ok(false)
+        49 => false,
+        _ => {
+            info!("LDAP auth failed with error code {}", res);
+            false
+        }
+    }
+}
+
+fn group_attribute(config: &LdapConfig) -> String {
+    config.group_attribute.clone().filter(|a| !a.is_empty()).unwrap_or(DEFAULT_GROUP_ATTRIBUTE.into())
+}
+
+fn normalize_dn(dn: &str) -> String {
+    dn.split(',').map(|rdn| rdn.trim()).collect::<Vec<_>>().join(",").to_lowercase()
+}
+
+// Whether the group DN is the configured group: its whole DN, its first RDN (e.g. "cn=octobot-admins"), or just
+// that RDN's value. Groups that aren't DNs (e.g. asserted by a SAML IdP) are compared by name.
+pub fn is_group(dn: &str, group: &str) -> bool {
+    let dn = normalize_dn(dn);
+    let group = normalize_dn(group);
+    if !dn.contains('=') {
+        return dn == group;
+    }
+    if group.contains(',') {
+        return dn == group;
+    }
+    let rdn = dn.split(',').next().unwrap_or("");
+    if group.contains('=') {
+        rdn == group
     } else {
-        info!("LDAP auth failed with error code {}", res);
-        Ok(false)
+        rdn.splitn(2, '=').nth(1) == Some(group.as_str())
     }
 }
 
-pub fn search(config: &LdapConfig, extra_filter: Option<&str>, max_results: i32) -> Result<Vec<LDAPEntry>> {
-    let ldap = new_ldap(&config.url)?;
-    let bind_res = ldap.simple_bind(&config.bind_user, &config.bind_pass)?;
-    if bind_res != 0 {
-        return Err(format_err!("LDAP service account bind failed with error code {}", bind_res));
+// The highest role the groups are mapped to, if any
+pub fn role(config: &LdapConfig, groups: &[String]) -> Option<Role> {
+    mapped_role(&config.group_roles, groups)
+}
+
+// The highest role the mappings give the groups, if any
+pub fn mapped_role(mappings: &Option<Vec<LdapGroupRole>>, groups: &[String]) -> Option<Role> {
+    mappings
+        .iter()
+        .flatten()
+        .filter(|m| groups.iter().any(|g| is_group(g, &m.group)))
+        .filter_map(|m| Role::parse(&m.role))
+        .max()
+}
+
+// Once a group is mapped to the user role, only members of mapped groups may log in. Otherwise all LDAP users may,
+// and the mapping only grants admin rights.
+pub fn requires_group(config: &LdapConfig) -> bool {
+    maps_user_role(&config.group_roles)
+}
+
+pub fn maps_user_role(mappings: &Option<Vec<LdapGroupRole>>) -> bool {
+    mappings.iter().flatten().any(|m| Role::parse(&m.role) == Some(Role::User))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(group_roles: Vec<(&str, &str)>) -> LdapConfig {
+        LdapConfig {
+            url: "ldaps://ldap.company.com".into(),
+            failover_urls: Some(vec!["ldaps://ldap2.company.com".into()]),
+            starttls: None,
+            tls_ca_file: None,
+            pool_size: None,
+            bind_user: "octobot".into(),
+            bind_pass: "the-pass".into(),
+            base_dn: "dc=company,dc=com".into(),
+            userid_attributes: vec!["uid".into()],
+            user_dn_template: None,
+            search_filter: None,
+            group_attribute: None,
+            group_roles: Some(
+                group_roles
+                    .into_iter()
+                    .map(|(group, role)| LdapGroupRole {
+                        group: group.into(),
+                        role: role.into(),
+                    })
+                    .collect(),
+            ),
+        }
     }
 
-    let mut search_filters: Vec<String> = vec![];
-    if let Some(f) = extra_filter {
-        search_filters.push(f.to_string());
+    #[test]
+    fn test_is_group() {
+        let dn = "CN=octobot-admins, OU=Groups,DC=company,DC=com";
+        assert!(is_group(dn, "cn=octobot-admins,ou=groups,dc=company,dc=com"));
+        assert!(is_group(dn, "cn=octobot-admins"));
+        assert!(is_group(dn, "octobot-admins"));
+        assert!(!is_group(dn, "cn=octobot-admins,ou=other,dc=company,dc=com"));
+        assert!(!is_group(dn, "octobot"));
+        assert!(!is_group(dn, "ou=groups"));
+
+        assert!(is_group("Octobot-Admins", "octobot-admins"));
+        assert!(!is_group("octobot", "octobot-admins"));
     }
-    if let Some(ref f) = config.search_filter {
-        search_filters.push(f.to_string());
+
+    #[test]
+    fn test_role() {
+        let config = config(vec![("cn=octobot-admins", "admin"), ("cn=developers", "user")]);
+        let groups = |dns: &[&str]| dns.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(None, role(&config, &groups(&["cn=sales,dc=company,dc=com"])));
+        assert_eq!(Some(Role::User), role(&config, &groups(&["cn=developers,dc=company,dc=com"])));
+        assert_eq!(
+            Some(Role::Admin),
+            role(&config, &groups(&["cn=developers,dc=company,dc=com", "cn=octobot-admins,dc=company,dc=com"]))
+        );
+        assert!(requires_group(&config));
     }
 
-    let search_filter;
-    if search_filters.is_empty() {
-        warn!("No LDAP search filter configured. There may be lots of results");
-        search_filter = "(objectClass=*)".to_string();
-    } else if search_filters.len() == 1 {
-        search_filter = search_filters[0].clone();
-    } else {
-        search_filter = format!("(&{})", search_filters.join(""));
-    }
-
-
-    let resp: Result<LDAPResponse> = ldap.ldap_search(
-        &config.base_dn,
-        openldap::codes::scopes::LDAP_SCOPE_SUB,
-        Some(&search_filter),
-        None, // attrs
-        false, // attrsonly
-        None, // server controls
-        None, // client controls
-        ptr::null_mut(), // timeout
-        max_results,
-    ).map_err(|e| format_err!("Error on LDAP search: {}", e));
-
-    let entries = resp?.into_iter()
-        .filter_map(|attrs| {
-            let dn = attrs.get("dn").unwrap_or(&vec![]).iter().next().map(|s| s.to_string()).unwrap_or(
-                String::new(),
-            );
-            if dn.is_empty() {
-                warn!("Found entry with empty DN! Skipping.");
-                None
-            } else {
-                Some(LDAPEntry { dn: dn })
-            }
-        })
-        .collect::<Vec<LDAPEntry>>();
+    #[test]
+    fn test_failover() {
+        let client = LdapClient::new(&config(vec![]));
+        let first = "ldaps://ldap.company.com";
+        let second = "ldaps://ldap2.company.com";
+        assert_eq!(vec![first, second], client.servers(1000));
+
+        client.mark_down(first, "Can't contact LDAP server", 1000);
+        assert_eq!(vec![second, first], client.servers(1000));
+        assert_eq!(vec![first, second], client.servers(1000 + DOWN_SECS));
+
+        let health = client.health();
+        assert_eq!(false, health[0].healthy);
+        assert_eq!(1, health[0].failures);
+        assert_eq!(Some("Can't contact LDAP server".to_string()), health[0].last_error);
+        assert_eq!(true, health[1].healthy);
 
-    Ok(entries)
+        client.mark_up(first, 1010);
+        assert_eq!(true, client.health()[0].healthy);
+        assert_eq!(vec![first, second], client.servers(1010));
+    }
+
+    #[test]
+    fn test_user_dn() {
+        let mut config = config(vec![]);
+        assert_eq!(None, user_dn(&config, "bob"));
+
+        config.user_dn_template = Some("uid={username},ou=people,dc=company,dc=com".into());
+        assert_eq!(Some("uid=bob,ou=people,dc=company,dc=com".into()), user_dn(&config, "bob"));
+        assert_eq!(None, user_dn(&config, ""));
+        assert_eq!(None, user_dn(&config, "bob,ou=admins"));
+        assert_eq!(None, user_dn(&config, "*"));
+    }
+
+    #[test]
+    fn test_search_filter() {
+        let mut config = config(vec![]);
+        assert_eq!(None, search_filter(&config, None));
+        assert_eq!(Some("(uid=bob)".into()), search_filter(&config, Some("(uid=bob)")));
+
+        config.search_filter = Some("(objectClass=person)".into());
+        assert_eq!(Some("(objectClass=person)".into()), search_filter(&config, None));
+        assert_eq!(Some("(&(uid=bob)(objectClass=person))".into()), search_filter(&config, Some("(uid=bob)")));
+    }
+
+    #[test]
+    fn test_authenticate_without_password() {
+        let client = LdapClient::new(&config(vec![]));
+        assert!(client.authenticate("bob", "").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_requires_group() {
+        assert!(!requires_group(&config(vec![("cn=octobot-admins", "admin")])));
+        assert!(!requires_group(&config(vec![])));
+    }
 }
@@ -1,26 +1,130 @@
+pub mod access_review;
+pub mod alerts;
+pub mod audit;
+pub mod auto_merge;
+pub mod aws;
+pub mod blame;
+pub mod azure_devops;
+pub mod bitbucket_server;
+pub mod ci_logs;
+pub mod clone_cache;
+pub mod codeowners;
+pub mod command_permissions;
+pub mod commit_index;
+pub mod commit_lint;
+pub mod compliance;
+pub mod components;
 pub mod config;
+pub mod config_check;
+pub mod config_export;
+pub mod config_reload;
+pub mod container_images;
+pub mod credentials;
 pub mod db;
+pub mod deliveries;
+pub mod dependencies;
+pub mod diagnostics;
+pub mod digests;
+pub mod discord;
+pub mod diff_preview;
 pub mod diffs;
+pub mod ecosystem;
+pub mod email;
+pub mod email_gateway;
 pub mod dir_pool;
+pub mod error_reports;
+pub mod event_bus;
+pub mod events;
+pub mod faults;
+pub mod federation;
 pub mod force_push;
+pub mod freeze;
 pub mod git;
 pub mod git_clone_manager;
 pub mod github;
+pub mod gitlab;
+pub mod grafana;
+pub mod ha;
 pub mod http_client;
+pub mod huddles;
+pub mod imap;
+pub mod inbound_queue;
+pub mod irc;
+pub mod jobs;
 pub mod ldap_auth;
 pub mod jira;
+pub mod jsm;
 pub mod jwt;
+pub mod kerberos;
+pub mod kubernetes;
+pub mod label_rules;
+pub mod label_taxonomy;
+pub mod load_test;
+pub mod matrix;
+pub mod mentions;
 pub mod messenger;
+pub mod merge_gate;
+pub mod merge_strategy;
+pub mod network;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
+pub mod onboarding;
+pub mod opsgenie;
+pub mod outbound_webhooks;
+pub mod outbox;
+pub mod passwords;
+pub mod path_labels;
+pub mod pr_conflicts;
+pub mod pr_images;
 pub mod pr_merge;
+pub mod pr_revert;
+pub mod provenance;
+pub mod redis;
+pub mod release_notes;
+pub mod release_versions;
+pub mod repo_files;
+pub mod repo_mutes;
 pub mod repos;
+pub mod request_traces;
+pub mod resilience;
 pub mod repo_version;
+pub mod routing;
 pub mod runtime;
+pub mod saml;
+pub mod sbom;
+pub mod scheduler;
+pub mod secrets;
 pub mod server;
+pub mod servicenow;
+pub mod size_labels;
 pub mod slack;
+pub mod slack_batch;
+pub mod slack_blocks;
+pub mod slack_bridge;
+pub mod slack_login;
+pub mod slack_reactions;
+pub mod slack_threads;
+pub mod slack_workflows;
+pub mod stale_prs;
+pub mod statuspage;
+pub mod storage;
+pub mod submodules;
+pub mod tag_protection;
+pub mod templates;
+pub mod teams;
+pub mod terraform;
+pub mod user_discovery;
 pub mod users;
 pub mod util;
+pub mod vcs;
 pub mod version;
+pub mod warehouse;
+pub mod webauthn;
+pub mod webhook_forwards;
+pub mod webhook_retries;
+pub mod webex;
 pub mod worker;
+pub mod worktree_pool;
 
 pub mod errors {
     pub type Error = failure::Error;
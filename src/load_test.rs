@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::format_err;
+use log::{error, info};
+use ring::{digest, hmac};
+use rustc_serialize::hex::ToHex;
+
+use crate::errors::*;
+use crate::events::{self, Event};
+
+// How often /metrics is polled for queue depths while replaying
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct Options {
+    // the staging octobot, e.g. "https://octobot-staging.company.com"
+    pub url: String,
+    // its github webhook secret
+    pub secret: String,
+    // 2.0 replays the trace twice as fast as it was recorded
+    pub speed: f64,
+    // deliveries in flight at once
+    pub concurrency: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub sent: usize,
+    // responses by status code
+    pub statuses: HashMap<u16, usize>,
+    // deliveries that got no response at all
+    pub errors: usize,
+    pub elapsed: Duration,
+    pub latencies: Vec<Duration>,
+    // by series, e.g. `octobot_queue_processed_total{queue="webhooks"}`: counters are the change during the run
+    pub processed: HashMap<String, f64>,
+    pub dropped: HashMap<String, f64>,
+    pub client_requests: HashMap<String, f64>,
+    pub max_depth: HashMap<String, f64>,
+}
+
+// Offsets from the start of the replay for each event: the recorded gaps, divided by `speed`.
+// Events recorded without a time go out back to back.
+pub fn schedule(events: &[Event], speed: f64) -> Vec<Duration> {
+    let speed = if speed > 0.0 { speed } else { 1.0 };
+    let start = events.iter().map(|e| e.received_at).filter(|t| *t > 0).min().unwrap_or(0);
+    events
+        .iter()
+        .map(|e| {
+            let gap_ms = if e.received_at > 0 { e.received_at - start } else { 0 };
+            Duration::from_micros((gap_ms as f64 * 1000.0 / speed) as u64)
+        })
+        .collect()
+}
+
+// The github signature of a body, as github would send it
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::SigningKey::new(&digest::SHA1, secret.as_bytes());
+    "sha1=".to_string() + hmac::sign(&key, body).as_ref().to_hex().as_str()
+}
+
+// Values of the series in prometheus' text format, ignoring comments
+pub fn parse_metrics(text: &str) -> HashMap<String, f64> {
+    text.lines()
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(|l| {
+            let mut parts = l.rsplitn(2, ' ');
+            let value = parts.next()?.parse::<f64>().ok()?;
+            let series = parts.next()?;
+            Some((series.to_string(), value))
+        })
+        .collect()
+}
+
+// How much each series starting with `name` changed between two scrapes
+fn deltas(name: &str, before: &HashMap<String, f64>, after: &HashMap<String, f64>) -> HashMap<String, f64> {
+    after
+        .iter()
+        .filter(|(series, _)| series.starts_with(name))
+        .map(|(series, value)| (series.clone(), value - before.get(series).cloned().unwrap_or(0.0)))
+        .collect()
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::from_millis(0);
+    }
+    let i = ((sorted.len() as f64 * p).ceil() as usize).max(1) - 1;
+    sorted[i.min(sorted.len() - 1)]
+}
+
+fn ms(d: Duration) -> u64 {
+    d.as_secs() * 1000 + d.subsec_millis() as u64
+}
+
+fn write_series(out: &mut String, title: &str, values: &HashMap<String, f64>) {
+    let mut values = values.iter().collect::<Vec<_>>();
+    values.sort_by(|a, b| a.0.cmp(b.0));
+    let _ = writeln!(out, "{}:", title);
+    for (series, value) in values {
+        let _ = writeln!(out, "    {} {}", series, value);
+    }
+}
+
+impl Report {
+    // deliveries per second
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs() as f64 + self.elapsed.subsec_nanos() as f64 / 1e9;
+        if secs > 0.0 {
+            self.sent as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        let mut statuses = self.statuses.iter().collect::<Vec<_>>();
+        statuses.sort();
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "Sent {} deliveries in {:.1}s ({:.1}/s)",
+            self.sent,
+            ms(self.elapsed) as f64 / 1000.0,
+            self.throughput()
+        );
+        let statuses = statuses.iter().map(|(s, n)| format!("{}: {}", s, n)).collect::<Vec<_>>();
+        let _ = writeln!(out, "Responses: {}, no response: {}", statuses.join(", "), self.errors);
+        let _ = writeln!(
+            out,
+            "Response time: p50 {}ms, p95 {}ms, max {}ms",
+            ms(percentile(&latencies, 0.5)),
+            ms(percentile(&latencies, 0.95)),
+            ms(latencies.last().cloned().unwrap_or_default())
+        );
+        write_series(&mut out, "Max queue depth", &self.max_depth);
+        write_series(&mut out, "Jobs processed", &self.processed);
+        write_series(&mut out, "Jobs dropped", &self.dropped);
+        write_series(&mut out, "Integration requests", &self.client_requests);
+        out
+    }
+}
+
+fn scrape(client: &reqwest::Client, url: &str) -> Result<HashMap<String, f64>> {
+    let mut resp = client.get(&format!("{}/metrics", url)).send()?;
+    if !resp.status().is_success() {
+        return Err(format_err!("Error getting {}/metrics: {}", url, resp.status()));
+    }
+    Ok(parse_metrics(&resp.text()?))
+}
+
+fn deliver(client: &reqwest::Client, opts: &Options, run_id: &str, event: &Event) -> Result<u16> {
+    let body = serde_json::to_vec(&event.payload)?;
+    let resp = client
+        .post(&format!("{}/hooks/github", opts.url))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-GitHub-Event", event.event.as_str())
+        // octobot skips deliveries it has already seen, so every run uses new ids
+        .header("X-GitHub-Delivery", format!("load-{}-{}", run_id, event.delivery_id))
+        .header("X-Hub-Signature", sign(&opts.secret, &body))
+        .body(body)
+        .send()?;
+    Ok(resp.status().as_u16())
+}
+
+// Replays the recorded events in `fixtures_dir` against a (staging!) octobot, keeping their timing.
+// Point that octobot's slack, github and JIRA at octobot-mock-server, or it will really act on them.
+pub fn run(fixtures_dir: &Path, opts: &Options) -> Result<Report> {
+    let mut events = events::load_fixtures(fixtures_dir)?.into_iter().map(|f| f.1).collect::<Vec<_>>();
+    if events.is_empty() {
+        return Err(format_err!("No recorded events in {}", fixtures_dir.display()));
+    }
+    events.sort_by_key(|e| e.received_at);
+    let offsets = schedule(&events, opts.speed);
+
+    let client = reqwest::Client::new();
+    let before = scrape(&client, &opts.url)?;
+    let run_id = time::get_time().sec.to_string();
+    info!("Replaying {} events against {} at {}x", events.len(), opts.url, opts.speed);
+
+    // the deepest each queue got
+    let stop = Arc::new(AtomicBool::new(false));
+    let sampler = {
+        let (client, url, stop) = (client.clone(), opts.url.clone(), stop.clone());
+        thread::spawn(move || {
+            let mut max_depth = HashMap::<String, f64>::new();
+            while !stop.load(Ordering::SeqCst) {
+                match scrape(&client, &url) {
+                    Ok(metrics) => {
+                        for (series, value) in deltas("octobot_queue_depth", &HashMap::new(), &metrics) {
+                            let max = max_depth.entry(series).or_insert(0.0);
+                            *max = max.max(value);
+                        }
+                    }
+                    Err(e) => error!("{}", e),
+                };
+                thread::sleep(SAMPLE_INTERVAL);
+            }
+            max_depth
+        })
+    };
+
+    let (job_tx, job_rx) = mpsc::channel::<Event>();
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let (result_tx, result_rx) = mpsc::channel::<(Result<u16>, Duration)>();
+    let opts = Arc::new(opts.clone());
+    let workers = (0..opts.concurrency.max(1))
+        .map(|_| {
+            let (client, opts, run_id) = (client.clone(), opts.clone(), run_id.clone());
+            let (job_rx, result_tx) = (job_rx.clone(), result_tx.clone());
+            thread::spawn(move || loop {
+                let event = match job_rx.lock().unwrap().recv() {
+                    Ok(e) => e,
+                    Err(_) => return,
+                };
+                let sent_at = Instant::now();
+                let result = deliver(&client, &opts, &run_id, &event);
+                let _ = result_tx.send((result, sent_at.elapsed()));
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(result_tx);
+
+    let start = Instant::now();
+    for (event, offset) in events.into_iter().zip(offsets) {
+        let elapsed = start.elapsed();
+        if offset > elapsed {
+            thread::sleep(offset - elapsed);
+        }
+        job_tx.send(event)?;
+    }
+    drop(job_tx);
+
+    let mut report = Report::default();
+    for (result, latency) in result_rx {
+        report.sent += 1;
+        report.latencies.push(latency);
+        match result {
+            Ok(status) => *report.statuses.entry(status).or_insert(0) += 1,
+            Err(e) => {
+                error!("Error delivering event: {}", e);
+                report.errors += 1;
+            }
+        };
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+    report.elapsed = start.elapsed();
+
+    // let the queues drain before the final counts
+    thread::sleep(SAMPLE_INTERVAL * 2);
+    stop.store(true, Ordering::SeqCst);
+    report.max_depth = sampler.join().map_err(|_| format_err!("Metrics sampler panicked"))?;
+
+    let after = scrape(&client, &opts.url)?;
+    report.processed = deltas("octobot_queue_processed_total", &before, &after);
+    report.dropped = deltas("octobot_queue_dropped_total", &before, &after);
+    report.client_requests = deltas("octobot_client_requests_total", &before, &after);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(received_at: i64) -> Event {
+        let mut event = Event::from_webhook("1234", "ping", b"{}").unwrap();
+        event.received_at = received_at;
+        event
+    }
+
+    #[test]
+    fn test_schedule() {
+        let events = vec![event(10_000), event(10_500), event(0), event(14_000)];
+        let millis = |ms| Duration::from_millis(ms);
+
+        assert_eq!(vec![millis(0), millis(500), millis(0), millis(4000)], schedule(&events, 1.0));
+        assert_eq!(vec![millis(0), millis(125), millis(0), millis(1000)], schedule(&events, 4.0));
+        assert_eq!(vec![millis(0), millis(500), millis(0), millis(4000)], schedule(&events, 0.0));
+        assert_eq!(vec![millis(0)], schedule(&[event(0)], 2.0));
+    }
+
+    #[test]
+    fn test_sign() {
+        let body = serde_json::to_vec(&json!({"zen": "Keep it logically awesome."})).unwrap();
+        let signature = sign("the-secret", &body);
+        assert!(signature.starts_with("sha1="));
+        assert_eq!(45, signature.len());
+        assert_ne!(signature, sign("other-secret", &body));
+    }
+
+    #[test]
+    fn test_parse_metrics() {
+        let metrics = parse_metrics(
+            "# HELP octobot_queue_depth Jobs waiting or running.\n\
+             # TYPE octobot_queue_depth gauge\n\
+             octobot_queue_depth{queue=\"webhooks\"} 3\n\
+             octobot_queue_oldest_age_seconds{queue=\"webhooks\"} 0.25\n\
+             octobot_jobs_in_flight 1\n\
+             not a metric\n",
+        );
+
+        assert_eq!(3, metrics.len());
+        assert_eq!(Some(&3.0), metrics.get("octobot_queue_depth{queue=\"webhooks\"}"));
+        assert_eq!(Some(&0.25), metrics.get("octobot_queue_oldest_age_seconds{queue=\"webhooks\"}"));
+        assert_eq!(Some(&1.0), metrics.get("octobot_jobs_in_flight"));
+    }
+
+    #[test]
+    fn test_report() {
+        let before = parse_metrics("octobot_queue_processed_total{queue=\"webhooks\"} 10\n");
+        let after = parse_metrics(
+            "octobot_queue_processed_total{queue=\"webhooks\"} 25\n\
+             octobot_queue_processed_total{queue=\"repos\"} 4\n\
+             octobot_client_requests_total{service=\"slack\"} 30\n",
+        );
+
+        let mut report = Report::default();
+        report.sent = 4;
+        report.statuses.insert(200, 3);
+        report.errors = 1;
+        report.elapsed = Duration::from_secs(2);
+        report.latencies = vec![40, 10, 20, 30].into_iter().map(Duration::from_millis).collect();
+        report.processed = deltas("octobot_queue_processed_total", &before, &after);
+        report.client_requests = deltas("octobot_client_requests_total", &before, &after);
+
+        assert_eq!(Some(&15.0), report.processed.get("octobot_queue_processed_total{queue=\"webhooks\"}"));
+        assert_eq!(Some(&4.0), report.processed.get("octobot_queue_processed_total{queue=\"repos\"}"));
+        assert_eq!(2.0, report.throughput());
+
+        let summary = report.summary();
+        assert!(summary.starts_with("Sent 4 deliveries in 2.0s (2.0/s)\n"), "{}", summary);
+        assert!(summary.contains("Responses: 200: 3, no response: 1\n"), "{}", summary);
+        assert!(summary.contains("Response time: p50 20ms, p95 40ms, max 40ms\n"), "{}", summary);
+        assert!(summary.contains("    octobot_client_requests_total{service=\"slack\"} 30\n"), "{}", summary);
+    }
+}
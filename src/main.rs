@@ -1,9 +1,21 @@
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use failure::format_err;
 
 use octobot::config;
+use octobot::config_check;
+use octobot::db;
+use octobot::error_reports;
+use octobot::load_test;
+use octobot::network;
+use octobot::passwords;
+use octobot::request_traces;
+use octobot::resilience;
 use octobot::server;
+use octobot::slack;
+use octobot::webhook_retries;
 use octobot::errors::*;
 
 fn main() {
@@ -25,13 +37,73 @@ fn main() {
     }
 }
 
+const USAGE: &str = "Usage: octobot <command> [<args>]
+
+Commands:
+    serve <config-file>                         run octobot (also `octobot <config-file>`)
+    check-config <config-file>                  check a config without starting octobot
+    hash-password [<config-file> <admin>]       hash a password for [admin], or set the admin's in the config
+    send-test-message <config-file> --channel <channel> [--message <text>]
+                                                send a slack message
+    replay-event <config-file> <delivery-id>    have octobot handle a failed delivery again
+    load-test <fixtures-dir> <octobot-url> --secret <webhook-secret> [--speed <n>] [--concurrency <n>]
+                                                replay recorded webhooks against a staging octobot
+    migrate <config-file> [<version>]           migrate the database, by default to the latest version
+    migrations <config-file> [status | migrate [<version>] | rollback <version>]";
+
 fn run() -> Result<()> {
-    if std::env::args().len() < 2 {
-        return Err(format_err!("Usage: octobot <config-file>"));
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => return Err(format_err!("{}", USAGE)),
+    };
+    if command == "help" || command == "--help" || command == "-h" {
+        println!("{}", USAGE);
+        return Ok(());
     }
 
-    setup_logging();
+    let (reporter, tracer) = setup_logging();
 
+    match command {
+        "serve" => match args.first() {
+            Some(config_file) => serve(PathBuf::from(config_file), reporter, tracer),
+            None => Err(format_err!("Usage: octobot serve <config-file>")),
+        },
+        "check-config" => match args.first() {
+            Some(config_file) => check_config(PathBuf::from(config_file)),
+            None => Err(format_err!("Usage: octobot check-config <config-file>")),
+        },
+        "hash-password" => hash_password(args),
+        "send-test-message" => send_test_message(args),
+        "replay-event" => match args {
+            [config_file, delivery_id] => replay_event(PathBuf::from(config_file), delivery_id),
+            _ => Err(format_err!("Usage: octobot replay-event <config-file> <delivery-id>")),
+        },
+        "load-test" => run_load_test(args),
+        "migrate" => match args.split_first() {
+            Some((config_file, version)) => {
+                let command = std::iter::once("migrate".to_string()).chain(version.iter().cloned()).collect::<Vec<_>>();
+                migrations(PathBuf::from(config_file), &command)
+            }
+            None => Err(format_err!("Usage: octobot migrate <config-file> [<version>]")),
+        },
+        "migrations" => match args.split_first() {
+            Some((config_file, command)) => migrations(PathBuf::from(config_file), command),
+            None => Err(format_err!(
+                "Usage: octobot migrations <config-file> [status | migrate [<version>] | rollback <version>]"
+            )),
+        },
+        // as it was started before there were commands
+        _ if args.is_empty() && Path::new(command).is_file() => serve(PathBuf::from(command), reporter, tracer),
+        _ => Err(format_err!("Unknown command: '{}'\n\n{}", command, USAGE)),
+    }
+}
+
+fn serve(
+    config_file: PathBuf,
+    reporter: Arc<error_reports::Reporter>,
+    tracer: Arc<request_traces::Tracer>,
+) -> Result<()> {
     if let Ok(mut path) = std::env::current_exe() {
         path.pop();
         path.push("version");
@@ -41,25 +113,184 @@ fn run() -> Result<()> {
         }
     }
 
-    let config_file = std::env::args().nth(1).unwrap();
+    let config = config::new(config_file.clone()).map_err(|e| format_err!("Error parsing config: {}", e))?;
+    network::configure(&config.network)?;
+    resilience::configure(&config.clients);
+
+    if let Some(ref sentry) = config.sentry {
+        reporter.configure(sentry, config.secret_values())?;
+    }
+    tracer.configure(config.request_traces.clone(), config.secret_values())?;
+    error_reports::install_panic_hook(reporter);
+
+    server::main::start(config, config_file);
+
+    Ok(())
+}
+
+// The value following `--name`
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1))
+}
+
+// Prints the hash to put in the config's [admin] section, or saves it there itself
+fn hash_password(args: &[String]) -> Result<()> {
+    let target = match args {
+        [] => None,
+        [config_file, admin_name] => Some((config_file, admin_name)),
+        _ => return Err(format_err!("Usage: octobot hash-password [<config-file> <admin username>]")),
+    };
+
+    let pass1 = rpassword::prompt_password_stdout("Enter new password: ")?;
+    let pass2 = rpassword::prompt_password_stdout("Retype new password: ")?;
+    if pass1 != pass2 {
+        return Err(format_err!("Passwords do not match!"));
+    }
+
+    match target {
+        Some((config_file, admin_name)) => {
+            let mut config = config::new(config_file.into()).map_err(|e| format_err!("Error parsing config: {}", e))?;
+            config.admin = Some(config::AdminConfig {
+                name: admin_name.clone(),
+                salt: None,
+                pass_hash: passwords::hash(&pass1, &config.password_params()),
+            });
+            config.save(config_file)?;
+            println!("Successfully changed password!");
+        }
+        None => {
+            println!("pass_hash = \"{}\"", passwords::hash(&pass1, &passwords::Params::new(&None)));
+        }
+    };
+    Ok(())
+}
+
+// Checks that octobot can post to a channel, with the config's slack credentials
+fn send_test_message(args: &[String]) -> Result<()> {
+    let usage = || format_err!("Usage: octobot send-test-message <config-file> --channel <channel> [--message <text>]");
+    let config_file = args.first().filter(|a| !a.starts_with("--")).ok_or_else(usage)?;
+    let channel = flag_value(args, "--channel").ok_or_else(usage)?;
+    let msg = flag_value(args, "--message").map(|m| m.as_str()).unwrap_or("Test message from octobot");
 
     let config = config::new(config_file.into()).map_err(|e| format_err!("Error parsing config: {}", e))?;
+    network::configure(&config.network)?;
+    resilience::configure(&config.clients);
+    slack::send_now(
+        config.main.slack_webhook_url.as_ref().map(|u| u.as_str()),
+        config.slack_bot_token().as_ref().map(|t| t.as_str()),
+        channel,
+        msg,
+    )?;
+    println!("Sent test message to {}", channel);
+    Ok(())
+}
 
-    server::main::start(config);
+// Has the running octobot retry a failed delivery on its next check. Only failed deliveries are kept.
+fn replay_event(config_file: PathBuf, delivery_id: &str) -> Result<()> {
+    let config = config::new(config_file).map_err(|e| format_err!("Error parsing config: {}", e))?;
+    if !config.webhook_retries.retry_now(delivery_id, db::now())? {
+        return Err(format_err!("No failed delivery {}: see /api/webhook-retries", delivery_id));
+    }
+    println!(
+        "Delivery {} will be handled again within {} seconds",
+        delivery_id,
+        webhook_retries::CHECK_INTERVAL_SECS
+    );
+    Ok(())
+}
+
+// Replays recorded webhooks against a staging octobot, and reports how it kept up
+fn run_load_test(args: &[String]) -> Result<()> {
+    let usage = || {
+        format_err!(
+            "Usage: octobot load-test <fixtures-dir> <octobot-url> --secret <webhook-secret> [--speed <n>] \
+             [--concurrency <n>]"
+        )
+    };
+    let (fixtures_dir, url) = match args {
+        [fixtures_dir, url, ..] if !fixtures_dir.starts_with("--") && !url.starts_with("--") => (fixtures_dir, url),
+        _ => return Err(usage()),
+    };
+    let opts = load_test::Options {
+        url: url.trim_end_matches('/').to_string(),
+        secret: flag_value(args, "--secret").ok_or_else(usage)?.clone(),
+        speed: match flag_value(args, "--speed") {
+            Some(speed) => speed.parse().map_err(|_| format_err!("Invalid speed: '{}'", speed))?,
+            None => 1.0,
+        },
+        concurrency: match flag_value(args, "--concurrency") {
+            Some(n) => n.parse().map_err(|_| format_err!("Invalid concurrency: '{}'", n))?,
+            None => 4,
+        },
+    };
+
+    let report = load_test::run(Path::new(fixtures_dir), &opts)?;
+    print!("{}", report.summary());
+    Ok(())
+}
+
+// Checks the config and the credentials, channels and JIRA projects it refers to, without starting octobot
+fn check_config(config_file: PathBuf) -> Result<()> {
+    let config = config::new(config_file).map_err(|e| format_err!("Error parsing config: {}", e))?;
+    network::configure(&config.network)?;
+    resilience::configure(&config.clients);
+    let report = config_check::check(&config);
+
+    for problem in &report.problems {
+        println!("[{}] {}", problem.section, problem.problem);
+    }
+    for skipped in &report.skipped {
+        println!("skipped: {}", skipped);
+    }
+
+    if !report.ok {
+        return Err(format_err!("Found {} problem(s) in the config", report.problems.len()));
+    }
+    println!("Config OK");
+    Ok(())
+}
+
+// Shows the database's schema version, or migrates it forward or back, without starting octobot
+fn migrations(config_file: PathBuf, command: &[String]) -> Result<()> {
+    let db = config::open_db(&config_file)?;
+    let version = |arg: Option<&String>| -> Result<i32> {
+        let arg = arg.ok_or_else(|| format_err!("Expected a schema version"))?;
+        arg.parse::<i32>().map_err(|_| format_err!("Invalid schema version: '{}'", arg))
+    };
+
+    match command.first().map(|c| c.as_str()) {
+        None | Some("status") => (),
+        Some("migrate") => match command.get(1) {
+            Some(_) => db.migrate_to(version(command.get(1))?)?,
+            None => db.migrate_to(db.schema_status()?.latest)?,
+        },
+        Some("rollback") => db.rollback_to(version(command.get(1))?)?,
+        Some(other) => return Err(format_err!("Unknown migrations command: '{}'", other)),
+    };
 
+    let status = db.schema_status()?;
+    let show = |v: Option<i32>| v.map(|v| v.to_string()).unwrap_or("none".into());
+    println!("Schema version: {}", show(status.current));
+    println!("Latest version: {}", status.latest);
+    println!("Can roll back to: {}", show(status.rollback_limit));
     Ok(())
 }
 
-fn setup_logging() {
+// Errors are only reported, and requests traced, once `serve` configures the reporter and the tracer
+fn setup_logging() -> (Arc<error_reports::Reporter>, Arc<request_traces::Tracer>) {
     let formatter = |buf: &mut env_logger::fmt::Formatter, record: &log::Record| {
         let t = time::now();
+        // the request or delivery being handled, to look up in /api/trace
+        let context = error_reports::current_context();
+        let trace_id = if context.trace_id().is_empty() { String::new() } else { format!("[{}]", context.trace_id()) };
         write!(
             buf,
-            "[{},{:03}][{}:{}] - {} - {}\n",
+            "[{},{:03}][{}:{}]{} - {} - {}\n",
             time::strftime("%Y-%m-%d %H:%M:%S", &t).unwrap(),
             t.tm_nsec / 1000_000,
             thread_id::get(),
             std::thread::current().name().unwrap_or(""),
+            trace_id,
             record.level(),
             record.args()
         )
@@ -79,5 +310,10 @@ fn setup_logging() {
         builder.filter(Some("rustls"), log::LevelFilter::Warn);
     }
 
-    builder.init();
+    let reporter = error_reports::Reporter::new();
+    let tracer = request_traces::Tracer::new();
+    let logger = error_reports::Logger::new(builder.build(), reporter.clone(), tracer.clone());
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(logger)).expect("set logger");
+    (reporter, tracer)
 }
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use log::{error, info};
+use regex::Regex;
+use reqwest;
+use serde_derive::Serialize;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+use crate::config::MatrixConfig;
+use crate::db;
+use crate::deliveries::{self, Deliveries};
+use crate::errors::*;
+use crate::http_client::HTTPClient;
+use crate::slack::{SlackAttachment, SlackRequest};
+use crate::util;
+use crate::worker;
+
+// An "m.room.message" event, with both plain text and HTML bodies
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct MatrixMessage {
+    pub msgtype: String,
+    pub body: String,
+    pub format: String,
+    pub formatted_body: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Segment {
+    Text(String),
+    // (url, text)
+    Link(String, String),
+}
+
+// Splits slack markup into text and "<url|text>" links, unescaping what slack needed escaped
+pub fn segments(text: &str) -> Vec<Segment> {
+    let link = Regex::new(r"<([^<>|]+)(?:\|([^<>]*))?>").unwrap();
+
+    let mut result = vec![];
+    let mut last = 0;
+    for caps in link.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        if whole.start() > last {
+            result.push(Segment::Text(unescape(&text[last..whole.start()])));
+        }
+        let url = unescape(&caps[1]);
+        let label = caps.get(2).map(|l| unescape(l.as_str())).unwrap_or(url.clone());
+        result.push(Segment::Link(url, label));
+        last = whole.end();
+    }
+    if last < text.len() {
+        result.push(Segment::Text(unescape(&text[last..])));
+    }
+    result
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+pub fn to_plain(text: &str) -> String {
+    segments(text)
+        .into_iter()
+        .map(|s| match s {
+            Segment::Text(t) => t,
+            Segment::Link(url, label) => {
+                if url == label {
+                    url
+                } else {
+                    format!("{} ({})", label, url)
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn to_html(text: &str) -> String {
+    segments(text)
+        .into_iter()
+        .map(|s| match s {
+            Segment::Text(t) => escape_html(&t).replace("\n", "<br>"),
+            Segment::Link(url, label) => format!("<a href=\"{}\">{}</a>", escape_html(&url), escape_html(&label)),
+        })
+        .collect()
+}
+
+fn attachment_plain(attachment: &SlackAttachment) -> String {
+    let mut lines = vec![];
+    if let Some(ref title) = attachment.title {
+        match attachment.title_link {
+            Some(ref url) => lines.push(format!("{} ({})", to_plain(title), url)),
+            None => lines.push(to_plain(title)),
+        };
+    }
+    if !attachment.text.is_empty() {
+        lines.push(to_plain(&attachment.text));
+    }
+    for field in &attachment.fields {
+        lines.push(format!("{}: {}", field.title, to_plain(&field.value)));
+    }
+    lines.join("\n")
+}
+
+fn attachment_html(attachment: &SlackAttachment) -> String {
+    let mut lines = vec![];
+    if let Some(ref title) = attachment.title {
+        match attachment.title_link {
+            Some(ref url) => lines.push(format!("<b><a href=\"{}\">{}</a></b>", escape_html(url), to_html(title))),
+            None => lines.push(format!("<b>{}</b>", to_html(title))),
+        };
+    }
+    if !attachment.text.is_empty() {
+        lines.push(to_html(&attachment.text));
+    }
+    for field in &attachment.fields {
+        lines.push(format!("<b>{}</b>: {}", escape_html(&field.title), to_html(&field.value)));
+    }
+    format!("<blockquote>{}</blockquote>", lines.join("<br>"))
+}
+
+pub fn to_message(req: &SlackRequest) -> MatrixMessage {
+    let mut body = vec![to_plain(&req.msg)];
+    body.extend(req.attachments.iter().map(attachment_plain));
+
+    let mut formatted_body = to_html(&req.msg);
+    for attachment in &req.attachments {
+        formatted_body += &attachment_html(attachment);
+    }
+
+    MatrixMessage {
+        msgtype: "m.text".into(),
+        body: body.join("\n\n"),
+        format: "org.matrix.custom.html".into(),
+        formatted_body: formatted_body,
+    }
+}
+
+// the main object for sending messages to matrix
+struct Matrix {
+    client: HTTPClient,
+    rooms: HashMap<String, String>,
+    // transaction IDs must be unique per access token, even across restarts
+    txn_prefix: i64,
+    next_txn: Mutex<u64>,
+    recent_messages: Mutex<Vec<(String, MatrixMessage)>>,
+}
+
+const TRIM_MESSAGES_AT: usize = 200;
+const TRIM_MESSAGES_TO: usize = 20;
+
+impl Matrix {
+    fn new(config: &MatrixConfig) -> Result<Matrix> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {}", config.access_token).parse().unwrap(),
+        );
+
+        let api_url = format!("{}/_matrix/client/v3", config.homeserver_url.trim_end_matches('/'));
+        Ok(Matrix {
+            client: HTTPClient::new_with_headers(&api_url, headers)?,
+            rooms: config.rooms.clone(),
+            txn_prefix: db::now(),
+            next_txn: Mutex::new(0),
+            recent_messages: Mutex::new(Vec::new()),
+        })
+    }
+
+    // The room a channel's messages go to, if it is mapped to one
+    fn room(&self, channel: &str) -> Option<&String> {
+        self.rooms.get(channel).or_else(|| self.rooms.get(channel.trim_start_matches('#')))
+    }
+
+    fn send(&self, room_id: &str, req: &SlackRequest) -> Result<()> {
+        let message = to_message(req);
+        if !self.is_unique(room_id, &message) {
+            info!("Skipping duplicate message to {}", room_id);
+            return Ok(());
+        }
+
+        let txn_id = {
+            let mut next_txn = self.next_txn.lock().unwrap();
+            *next_txn += 1;
+            format!("octobot-{}-{}", self.txn_prefix, next_txn)
+        };
+
+        info!("Sending matrix message to {} for {}", room_id, req.channel);
+        let path = format!(
+            "/rooms/{}/send/m.room.message/{}",
+            utf8_percent_encode(room_id, PATH_SEGMENT_ENCODE_SET),
+            txn_id
+        );
+        self.client.put_void(&path, &message)
+    }
+
+    fn is_unique(&self, room_id: &str, message: &MatrixMessage) -> bool {
+        let mut recent_messages = self.recent_messages.lock().unwrap();
+        util::check_unique_event(
+            (room_id.to_string(), message.clone()),
+            &mut *recent_messages,
+            TRIM_MESSAGES_AT,
+            TRIM_MESSAGES_TO,
+        )
+    }
+}
+
+struct Runner {
+    matrix: Arc<Matrix>,
+    fallback: Arc<dyn worker::Runner<SlackRequest>>,
+    deliveries: Deliveries,
+}
+
+// Sends messenger requests for channels mapped to matrix rooms to matrix, and all others to |fallback|
+pub fn new_runner(
+    config: &MatrixConfig,
+    fallback: Arc<dyn worker::Runner<SlackRequest>>,
+    deliveries: Deliveries,
+) -> Result<Arc<dyn worker::Runner<SlackRequest>>> {
+    Ok(Arc::new(Runner {
+        matrix: Arc::new(Matrix::new(config)?),
+        fallback: fallback,
+        deliveries: deliveries,
+    }))
+}
+
+impl worker::Runner<SlackRequest> for Runner {
+    fn handle(&self, req: SlackRequest) {
+        let room_id = match self.matrix.room(&req.channel) {
+            Some(r) => r.clone(),
+            None => return self.fallback.handle(req),
+        };
+
+        if let Err(e) = self.deliveries.track(deliveries::MATRIX, &req.channel, || self.matrix.send(&room_id, &req)) {
+            error!("Error sending matrix message: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    use crate::db::Database;
+    use crate::slack::{self, SlackAttachmentBuilder};
+
+    #[test]
+    fn test_segments() {
+        assert_eq!(
+            vec![
+                Segment::Text("Pull Request merged (".into()),
+                Segment::Link("http://the-github-host/some-user/some-repo".into(), "some-user/some-repo".into()),
+                Segment::Text(")".into()),
+            ],
+            segments("Pull Request merged (<http://the-github-host/some-user/some-repo|some-user/some-repo>)")
+        );
+        assert_eq!(
+            vec![Segment::Link("http://the-pr".into(), "http://the-pr".into())],
+            segments("<http://the-pr>")
+        );
+        assert_eq!(vec![Segment::Text("a <b> & c".into())], segments("a &lt;b&gt; &amp; c"));
+    }
+
+    #[test]
+    fn test_to_plain_and_html() {
+        let text = "Fixed <http://the-pr?a=1&amp;b=2|#32> for &lt;script&gt;\nand more";
+        assert_eq!("Fixed #32 (http://the-pr?a=1&b=2) for <script>\nand more", to_plain(text));
+        assert_eq!(
+            "Fixed <a href=\"http://the-pr?a=1&amp;b=2\">#32</a> for &lt;script&gt;<br>and more",
+            to_html(text)
+        );
+    }
+
+    #[test]
+    fn test_to_message() {
+        let attach = SlackAttachmentBuilder::new("some <http://the-commit|commit>")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .color("good")
+            .field("Reviewers", "joe.reviewer")
+            .build();
+        let message = to_message(&slack::req("security-reviews", "Pull Request opened", vec![attach]));
+
+        assert_eq!(
+            MatrixMessage {
+                msgtype: "m.text".into(),
+                body: "Pull Request opened\n\n\
+                       Pull Request #32: \"The PR\" (http://the-pr)\n\
+                       some commit (http://the-commit)\n\
+                       Reviewers: joe.reviewer"
+                    .into(),
+                format: "org.matrix.custom.html".into(),
+                formatted_body: "Pull Request opened<blockquote>\
+                                 <b><a href=\"http://the-pr\">Pull Request #32: &quot;The PR&quot;</a></b><br>\
+                                 some <a href=\"http://the-commit\">commit</a><br>\
+                                 <b>Reviewers</b>: joe.reviewer</blockquote>"
+                    .into(),
+            },
+            message
+        );
+    }
+
+    struct CountingRunner {
+        handled: Mutex<Vec<String>>,
+    }
+
+    impl worker::Runner<SlackRequest> for CountingRunner {
+        fn handle(&self, req: SlackRequest) {
+            self.handled.lock().unwrap().push(req.channel);
+        }
+    }
+
+    #[test]
+    fn test_unmapped_channels_fall_back() {
+        let mut rooms = HashMap::new();
+        rooms.insert("security-reviews".to_string(), "!abcdef:company.com".to_string());
+        let config = MatrixConfig {
+            homeserver_url: "https://matrix.company.com/".into(),
+            access_token: "the-token".into(),
+            rooms: rooms,
+        };
+
+        let matrix = Matrix::new(&config).unwrap();
+        assert_eq!("https://matrix.company.com/_matrix/client/v3", matrix.client.api_base);
+        assert_eq!(Some(&"!abcdef:company.com".to_string()), matrix.room("#security-reviews"));
+        assert_eq!(None, matrix.room("reviews"));
+
+        let fallback = Arc::new(CountingRunner {
+            handled: Mutex::new(vec![]),
+        });
+        let temp_dir = TempDir::new("matrix.rs").unwrap();
+        let db = Database::new(&temp_dir.path().join("db.sqlite3").to_string_lossy()).expect("create temp database");
+        let runner = new_runner(&config, fallback.clone(), Deliveries::new(db)).unwrap();
+        runner.handle(slack::req("reviews", "Pull Request opened", vec![]));
+        runner.handle(slack::req("@joe", "Pull Request merged", vec![]));
+        assert_eq!(vec!["reviews", "@joe"], *fallback.handled.lock().unwrap());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::util;
+
+// At most this much of the text around a mention goes in its direct message
+const MAX_CONTEXT_LEN: usize = 300;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mention {
+    User(String),
+    // e.g. "@some-org/backend"
+    Team { org: String, slug: String },
+}
+
+impl Mention {
+    // As it was written, without the "@"
+    pub fn name(&self) -> String {
+        match *self {
+            Mention::User(ref login) => login.clone(),
+            Mention::Team { ref org, ref slug } => format!("{}/{}", org, slug),
+        }
+    }
+}
+
+fn is_quote(line: &str) -> bool {
+    line.trim_start().starts_with('>')
+}
+
+fn team_slug(slug: &str) -> Option<&str> {
+    let end = slug.find(|c: char| !c.is_alphanumeric() && c != '-' && c != '_').unwrap_or(slug.len());
+    if end == 0 {
+        None
+    } else {
+        Some(&slug[..end])
+    }
+}
+
+// The users and teams mentioned in a PR description, review or comment, once each.
+// Quoted lines are left out: they are mentions from someone else's comment.
+pub fn parse(body: &str) -> Vec<Mention> {
+    let mut mentions = vec![];
+    for line in body.lines().filter(|l| !is_quote(l)) {
+        for token in line.split_whitespace() {
+            if !token.starts_with('@') || token.len() < 2 {
+                continue;
+            }
+            let login = match util::find_github_username(&token[1..]) {
+                Some(login) if !login.is_empty() => login,
+                _ => continue,
+            };
+            let rest = &token[1 + login.len()..];
+            let mention = match team_slug(rest.trim_start_matches('/')) {
+                Some(slug) if rest.starts_with('/') => Mention::Team {
+                    org: login.to_string(),
+                    slug: slug.to_string(),
+                },
+                _ => Mention::User(login.to_string()),
+            };
+            if !mentions.contains(&mention) {
+                mentions.push(mention);
+            }
+        }
+    }
+    mentions
+}
+
+// The paragraph with the (first) mention, to show what it is about
+pub fn context(body: &str, mention: &Mention) -> String {
+    let name = format!("@{}", mention.name());
+    let paragraph = body
+        .split("\n\n")
+        .filter(|p| !p.lines().all(is_quote))
+        .find(|p| p.contains(&name))
+        .unwrap_or(body)
+        .trim();
+
+    if paragraph.chars().count() <= MAX_CONTEXT_LEN {
+        return paragraph.to_string();
+    }
+    // keep the mention in view when the paragraph is long
+    let start = paragraph.find(&name).unwrap_or(0);
+    let before = paragraph[..start].chars().rev().take(MAX_CONTEXT_LEN / 3).count();
+    let skip = paragraph[..start].chars().count() - before;
+    let excerpt = paragraph.chars().skip(skip).take(MAX_CONTEXT_LEN).collect::<String>();
+    let mut context = String::new();
+    if skip > 0 {
+        context += "…";
+    }
+    context += excerpt.trim();
+    if skip + MAX_CONTEXT_LEN < paragraph.chars().count() {
+        context += "…";
+    }
+    context
+}
+
+// The mentions that weren't in the previous version of an edited body
+pub fn added(body: &str, previous: &str) -> Vec<Mention> {
+    let previous = parse(previous);
+    parse(body).into_iter().filter(|m| !previous.contains(m)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(login: &str) -> Mention {
+        Mention::User(login.into())
+    }
+
+    fn team(org: &str, slug: &str) -> Mention {
+        Mention::Team {
+            org: org.into(),
+            slug: slug.into(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            vec![user("joe"), team("some-org", "backend_team"), user("mary")],
+            parse("@joe can you or @some-org/backend_team, look? cc @mary, @joe")
+        );
+        assert_eq!(vec![user("some-org")], parse("@some-org/ and a@b.com"));
+        assert_eq!(Vec::<Mention>::new(), parse("> @joe said this\nfair enough @ all"));
+    }
+
+    #[test]
+    fn test_context() {
+        let body = "First part.\n\n> @joe wrote this\n\nWhat do you think, @joe?\nIt's urgent.\n\nThanks";
+        assert_eq!("What do you think, @joe?\nIt's urgent.", context(body, &user("joe")));
+        assert_eq!(body, context(body, &user("mary")));
+
+        let long = format!("{} @some-org/backend please look {}", "a".repeat(400), "b".repeat(400));
+        let context = context(&long, &team("some-org", "backend"));
+        assert!(context.starts_with("…aaa"), "{}", context);
+        assert!(context.contains("@some-org/backend please look bbb"), "{}", context);
+        assert!(context.ends_with("b…"), "{}", context);
+        assert_eq!(MAX_CONTEXT_LEN + 2, context.chars().count());
+    }
+
+    #[test]
+    fn test_added() {
+        assert_eq!(vec![user("mary")], added("@joe and @mary", "@joe"));
+        assert_eq!(Vec::<Mention>::new(), added("@joe", "@joe and @mary"));
+    }
+}
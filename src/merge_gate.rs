@@ -0,0 +1,249 @@
+use failure::format_err;
+
+use crate::errors::*;
+use crate::github;
+use crate::github::api::Session;
+use crate::provenance;
+use crate::teams::TeamMembers;
+
+// The commit status that branch protection can require
+pub const GATE_CONTEXT: &str = "octobot/gate";
+
+// github truncates longer status descriptions
+const MAX_DESCRIPTION_LEN: usize = 140;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TeamApprovals {
+    // slug of a team in the repo's org
+    pub team: String,
+    pub count: u32,
+}
+
+// What a PR needs before it may merge
+#[derive(Clone, Debug, PartialEq)]
+pub struct GateRules {
+    pub required_labels: Vec<String>,
+    pub team_approvals: Vec<TeamApprovals>,
+    pub resolved_threads: bool,
+}
+
+impl GateRules {
+    // |required_labels| is comma-separated, |team_approvals| is like "backend:2,qa" (one approval unless a count
+    // is given). Returns None if no rules are set.
+    pub fn new(required_labels: &str, team_approvals: &str, resolved_threads: bool) -> Result<Option<GateRules>> {
+        let required_labels = split(required_labels);
+
+        let mut approvals = vec![];
+        for entry in split(team_approvals) {
+            let mut parts = entry.splitn(2, ':');
+            let team = parts.next().unwrap_or("").trim().to_string();
+            let count = match parts.next() {
+                Some(count) => count
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|_| format_err!("Invalid approval count for team {}: {}", team, count))?,
+                None => 1,
+            };
+            if team.is_empty() || team.contains('/') || count == 0 {
+                return Err(format_err!("Invalid team approvals: {}", entry));
+            }
+            approvals.push(TeamApprovals { team: team, count: count });
+        }
+
+        if required_labels.is_empty() && approvals.is_empty() && !resolved_threads {
+            return Ok(None);
+        }
+
+        Ok(Some(GateRules {
+            required_labels: required_labels,
+            team_approvals: approvals,
+            resolved_threads: resolved_threads,
+        }))
+    }
+}
+
+fn split(value: &str) -> Vec<String> {
+    value.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()
+}
+
+// Lists what the PR still needs. |team_members| looks up the logins of a team of the repo's org.
+pub fn failures<F>(
+    rules: &GateRules,
+    labels: &[github::Label],
+    reviews: &[github::Review],
+    unresolved_threads: u32,
+    team_members: F,
+) -> Result<Vec<String>>
+where
+    F: Fn(&str) -> Result<Vec<String>>,
+{
+    let mut result = vec![];
+
+    for required in &rules.required_labels {
+        if !labels.iter().any(|l| l.name.eq_ignore_ascii_case(required)) {
+            result.push(format!("needs the {} label", required));
+        }
+    }
+
+    let approvers = provenance::approvals(reviews).into_iter().map(|a| a.user).collect::<Vec<_>>();
+    for required in &rules.team_approvals {
+        let members = team_members(&required.team)?;
+        let count = approvers.iter().filter(|a| members.contains(a)).count() as u32;
+        if count < required.count {
+            result.push(format!("needs {} approval(s) from {}, has {}", required.count, required.team, count));
+        }
+    }
+
+    if rules.resolved_threads && unresolved_threads > 0 {
+        result.push(format!("has {} unresolved review thread(s)", unresolved_threads));
+    }
+
+    Ok(result)
+}
+
+pub fn status(failures: &[String]) -> github::Status {
+    if failures.is_empty() {
+        return github::Status::new(GATE_CONTEXT, "success", "All merge requirements are met");
+    }
+
+    let mut description = format!("This PR {}", failures.join("; "));
+    if description.len() > MAX_DESCRIPTION_LEN {
+        let mut end = MAX_DESCRIPTION_LEN - 3;
+        while !description.is_char_boundary(end) {
+            end -= 1;
+        }
+        description.truncate(end);
+        description.push_str("...");
+    }
+    github::Status::new(GATE_CONTEXT, "failure", &description)
+}
+
+// Sets the gate status of the PR's head commit
+pub fn check_pull_request(
+    github: &dyn Session,
+    team_members: &TeamMembers,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    rules: &GateRules,
+) -> Result<()> {
+    let owner = repo.owner.login();
+    let labels = if rules.required_labels.is_empty() {
+        vec![]
+    } else {
+        github.get_pull_request_labels(owner, &repo.name, pull_request.number)?
+    };
+    let reviews = if rules.team_approvals.is_empty() {
+        vec![]
+    } else {
+        github.get_pull_request_reviews(owner, &repo.name, pull_request.number)?
+    };
+    let unresolved_threads = if rules.resolved_threads {
+        github.get_unresolved_review_threads(owner, &repo.name, pull_request.number)?
+    } else {
+        0
+    };
+
+    let failures = failures(rules, &labels, &reviews, unresolved_threads, |team| {
+        Ok(team_members.get(github, owner, team)?.iter().map(|u| u.login().to_string()).collect())
+    })?;
+    github.create_commit_status(owner, &repo.name, &pull_request.head.sha, &status(&failures))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(name: &str) -> github::Label {
+        github::Label::new(name)
+    }
+
+    fn review(state: &str, login: &str) -> github::Review {
+        let mut review = github::Review::new("", github::User::new(login));
+        review.state = state.into();
+        review
+    }
+
+    fn members(team: &str) -> Result<Vec<String>> {
+        match team {
+            "backend" => Ok(vec!["joe".into(), "jane".into()]),
+            "qa" => Ok(vec!["sue".into()]),
+            _ => Err(format_err!("No such team: {}", team)),
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        assert_eq!(None, GateRules::new(" ", "", false).unwrap());
+        assert_eq!(
+            Some(GateRules {
+                required_labels: vec!["qa-approved".into(), "docs".into()],
+                team_approvals: vec![
+                    TeamApprovals {
+                        team: "backend".into(),
+                        count: 2,
+                    },
+                    TeamApprovals {
+                        team: "qa".into(),
+                        count: 1,
+                    },
+                ],
+                resolved_threads: true,
+            }),
+            GateRules::new("qa-approved, docs", "backend:2, qa", true).unwrap()
+        );
+        assert!(GateRules::new("", "backend:none", false).is_err());
+        assert!(GateRules::new("", "backend:0", false).is_err());
+        assert!(GateRules::new("", "some-org/backend", false).is_err());
+    }
+
+    #[test]
+    fn test_failures() {
+        let rules = GateRules::new("qa-approved", "backend:2,qa", true).unwrap().unwrap();
+
+        assert_eq!(
+            vec![
+                "needs the qa-approved label",
+                "needs 2 approval(s) from backend, has 1",
+                "needs 1 approval(s) from qa, has 0",
+                "has 3 unresolved review thread(s)",
+            ],
+            failures(&rules, &[label("bug")], &[review("APPROVED", "joe"), review("APPROVED", "bob")], 3, members)
+                .unwrap()
+        );
+
+        // a reviewer who later requests changes no longer counts
+        let reviews = vec![
+            review("APPROVED", "joe"),
+            review("APPROVED", "jane"),
+            review("APPROVED", "sue"),
+            review("CHANGES_REQUESTED", "jane"),
+        ];
+        assert_eq!(
+            vec!["needs 2 approval(s) from backend, has 1"],
+            failures(&rules, &[label("QA-Approved")], &reviews, 0, members).unwrap()
+        );
+
+        let reviews = vec![review("APPROVED", "joe"), review("APPROVED", "jane"), review("APPROVED", "sue")];
+        assert_eq!(Vec::<String>::new(), failures(&rules, &[label("qa-approved")], &reviews, 0, members).unwrap());
+
+        let rules = GateRules::new("", "design", false).unwrap().unwrap();
+        assert!(failures(&rules, &[], &[], 0, members).is_err());
+    }
+
+    #[test]
+    fn test_status() {
+        let passed = status(&[]);
+        assert_eq!("success", passed.state);
+        assert_eq!(Some(GATE_CONTEXT.to_string()), passed.context);
+
+        let failed = status(&["needs the qa-approved label".into(), "has 1 unresolved review thread(s)".into()]);
+        assert_eq!("failure", failed.state);
+        assert_eq!(
+            Some("This PR needs the qa-approved label; has 1 unresolved review thread(s)".to_string()),
+            failed.description
+        );
+
+        let truncated = status(&["x".repeat(200)]);
+        assert_eq!(MAX_DESCRIPTION_LEN, truncated.description.unwrap().len());
+    }
+}
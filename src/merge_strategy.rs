@@ -0,0 +1,142 @@
+use log::error;
+use serde_json::{json, Value};
+
+use crate::github;
+use crate::repos::RepoMergeStrategy;
+use crate::routing;
+use crate::templates;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MergeMethod {
+    // a single commit with all of the PR's changes
+    Squash,
+    // each of the PR's commits, replayed onto the branch
+    Rebase,
+    // a merge commit joining the PR's commits to the branch
+    Merge,
+}
+
+impl MergeMethod {
+    pub fn parse(value: &str) -> Option<MergeMethod> {
+        match value.trim().to_lowercase().as_str() {
+            "squash" => Some(MergeMethod::Squash),
+            "rebase" => Some(MergeMethod::Rebase),
+            "merge" | "merge-commit" => Some(MergeMethod::Merge),
+            _ => None,
+        }
+    }
+
+    // The `merge_method` of github's merge API
+    pub fn api_name(&self) -> &'static str {
+        match *self {
+            MergeMethod::Squash => "squash",
+            MergeMethod::Rebase => "rebase",
+            MergeMethod::Merge => "merge",
+        }
+    }
+}
+
+// The first strategy whose branch glob matches |branch|. Strategies with an unknown method are skipped.
+pub fn for_branch<'a>(strategies: &'a Vec<RepoMergeStrategy>, branch: &str) -> Option<&'a RepoMergeStrategy> {
+    strategies
+        .iter()
+        .filter(|s| s.branch.is_empty() || routing::branch_matches(&s.branch, branch))
+        .find(|s| match s.method() {
+            Some(_) => true,
+            None => {
+                error!("Unknown merge strategy '{}' for branch '{}'", s.strategy, s.branch);
+                false
+            }
+        })
+}
+
+// Variables for commit templates: those of the other PR templates, plus the PR's description and its base branch.
+// |branch| is the branch being merged into.
+pub fn commit_vars(pr: &github::PullRequest, branch: &str, repo: &str, commits: &Vec<github::Commit>) -> Value {
+    templates::with_vars(
+        templates::pr_vars(&pr, branch, repo, commits),
+        json!({
+            "pr_body": pr.body.clone().unwrap_or_default(),
+            "base_branch": pr.base.ref_name,
+        }),
+    )
+}
+
+// The title and body rendered from the strategy's commit template, if it has one
+pub fn commit_message(strategy: &RepoMergeStrategy, vars: &Value) -> Option<(String, String)> {
+    if strategy.commit_template.trim().is_empty() {
+        return None;
+    }
+
+    let msg = match templates::render(&strategy.commit_template, vars) {
+        Ok(m) => m,
+        Err(e) => {
+            error!("Error rendering commit template '{}': {}", strategy.commit_template, e);
+            return None;
+        }
+    };
+
+    let mut lines = msg.trim().splitn(2, '\n');
+    let title = lines.next().unwrap_or("").trim().to_string();
+    let body = lines.next().unwrap_or("").trim().to_string();
+    if title.is_empty() {
+        return None;
+    }
+
+    Some((title, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some(MergeMethod::Squash), MergeMethod::parse("squash"));
+        assert_eq!(Some(MergeMethod::Rebase), MergeMethod::parse(" Rebase "));
+        assert_eq!(Some(MergeMethod::Merge), MergeMethod::parse("merge-commit"));
+        assert_eq!(None, MergeMethod::parse("octopus"));
+        assert_eq!("merge", MergeMethod::Merge.api_name());
+    }
+
+    #[test]
+    fn test_for_branch() {
+        let strategies = vec![
+            RepoMergeStrategy::new("release/*", "rebase", ""),
+            RepoMergeStrategy::new("hotfix/*", "octopus", ""),
+            RepoMergeStrategy::new("", "squash", "{{pr_title}} (#{{pr_number}})"),
+        ];
+
+        assert_eq!(Some(MergeMethod::Rebase), for_branch(&strategies, "release/1.0").and_then(|s| s.method()));
+        assert_eq!(Some(MergeMethod::Squash), for_branch(&strategies, "hotfix/urgent").and_then(|s| s.method()));
+        assert_eq!(Some(MergeMethod::Squash), for_branch(&strategies, "main").and_then(|s| s.method()));
+        assert_eq!(None, for_branch(&strategies[0..1].to_vec(), "main"));
+    }
+
+    #[test]
+    fn test_commit_message() {
+        let mut pr = github::PullRequest::new();
+        pr.title = "Fix the thing".into();
+        pr.number = 12;
+        pr.body = Some("It was broken.".into());
+        pr.user = github::User::new("joe");
+        pr.base = github::BranchRef::new("main");
+        let vars = commit_vars(&pr, "release/1.0", "some-user/some-repo", &vec![]);
+
+        let template = "{{pr_title}} (#{{pr_number}})\n\n{{pr_body}}\n\nBy @{{author}}";
+        let strategy = RepoMergeStrategy::new("", "squash", template);
+        assert_eq!(
+            Some(("Fix the thing (#12)".to_string(), "It was broken.\n\nBy @joe".to_string())),
+            commit_message(&strategy, &vars)
+        );
+
+        let strategy = RepoMergeStrategy::new("", "squash", "{{base_branch}}->{{branch}}: {{pr_title}}");
+        assert_eq!(
+            Some(("main->release/1.0: Fix the thing".to_string(), String::new())),
+            commit_message(&strategy, &vars)
+        );
+
+        assert_eq!(None, commit_message(&RepoMergeStrategy::new("", "squash", ""), &vars));
+        assert_eq!(None, commit_message(&RepoMergeStrategy::new("", "squash", "{{#if pr_title}}"), &vars));
+    }
+}
@@ -1,24 +1,154 @@
 use std::sync::Arc;
 
+use log::{error, info};
+
 use crate::config::Config;
+use crate::diagnostics::Trace;
+use crate::email::{self, EmailRequest};
+use crate::error_reports;
 use crate::github;
+use crate::routing::RouteContext;
 use crate::slack::{self, SlackAttachment, SlackRequest};
+use crate::db;
+use crate::slack_threads;
+use crate::statuspage;
+use crate::users;
 use crate::util;
 use crate::worker::Worker;
 
 pub struct Messenger {
     config: Arc<Config>,
     slack: Arc<dyn Worker<SlackRequest>>,
+    trace: Option<Arc<Trace>>,
+    thread_key: Option<String>,
+    // The kind of direct messages sent, for users' notification preferences
+    dm_event: Option<String>,
+    batch_key: Option<String>,
+    email: Option<Arc<dyn Worker<EmailRequest>>>,
+    // github users to email instead when they have no slack user
+    email_fallback: Vec<String>,
+    // what channel messages are about, for the repo's routing rules
+    route: RouteContext,
+    // log messages instead of sending them, whatever the repo's setting
+    dry_run: bool,
 }
 
 pub fn new(config: Arc<Config>, slack: Arc<dyn Worker<SlackRequest>>) -> Messenger {
     Messenger {
         slack: slack.clone(),
         config: config.clone(),
+        trace: None,
+        thread_key: None,
+        dm_event: None,
+        batch_key: None,
+        email: None,
+        email_fallback: vec![],
+        route: RouteContext::default(),
+        dry_run: false,
+    }
+}
+
+// Failures mention the request or delivery they happened in, to look up in /api/trace
+fn with_trace_id(msg: &str) -> String {
+    let context = error_reports::current_context();
+    if context.trace_id().is_empty() {
+        msg.to_string()
+    } else {
+        format!("{} (trace {})", msg, context.trace_id())
     }
 }
 
 impl Messenger {
+    // Records why messages were (or weren't) sent, for diagnosing missing notifications
+    pub fn with_trace(mut self, trace: Arc<Trace>) -> Messenger {
+        self.trace = Some(trace);
+        self
+    }
+
+    pub fn for_dm_event(mut self, event: &str) -> Messenger {
+        self.dm_event = Some(event.into());
+        self
+    }
+
+    pub fn with_email(mut self, email: Arc<dyn Worker<EmailRequest>>) -> Messenger {
+        self.email = Some(email);
+        self
+    }
+
+    // These users are emailed direct messages if they have no slack user, e.g. requested reviewers
+    pub fn with_email_fallback(mut self, github_logins: Vec<String>) -> Messenger {
+        self.email_fallback = github_logins;
+        self
+    }
+
+    pub fn with_route_context(mut self, route: RouteContext) -> Messenger {
+        self.route = route;
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Messenger {
+        self.dry_run = dry_run;
+        self
+    }
+
+    // Whether messages about the repo are only logged
+    fn is_dry_run(&self, repo: &github::Repo) -> bool {
+        self.dry_run || self.config.dry_run(repo)
+    }
+
+    fn is_global_dry_run(&self) -> bool {
+        self.dry_run || self.config.global_dry_run()
+    }
+
+    // Channel messages from the returned messenger are posted in the PR's thread if the repo uses threads.
+    // Messages about the PR may also be batched, if enabled.
+    pub fn in_pr_thread(&self, repo: &github::Repo, number: u32) -> Messenger {
+        let pr_key = slack_threads::pr_thread_key(&repo.full_name, number);
+        let thread_key = if self.config.repos().slack_threads(repo) { Some(pr_key.clone()) } else { None };
+        let batch_key = if self.config.slack_batch_window() > 0 { Some(pr_key) } else { None };
+
+        Messenger {
+            config: self.config.clone(),
+            slack: self.slack.clone(),
+            trace: self.trace.clone(),
+            thread_key: thread_key,
+            dm_event: self.dm_event.clone(),
+            batch_key: batch_key,
+            email: self.email.clone(),
+            email_fallback: self.email_fallback.clone(),
+            route: self.route.clone(),
+            dry_run: self.dry_run,
+        }
+    }
+
+    // Threads channel messages under `thread_key` regardless of the repo's thread settings
+    pub fn in_thread(&self, thread_key: &str) -> Messenger {
+        Messenger {
+            config: self.config.clone(),
+            slack: self.slack.clone(),
+            trace: self.trace.clone(),
+            thread_key: Some(thread_key.to_string()),
+            dm_event: self.dm_event.clone(),
+            batch_key: self.batch_key.clone(),
+            email: self.email.clone(),
+            email_fallback: self.email_fallback.clone(),
+            route: self.route.clone(),
+            dry_run: self.dry_run,
+        }
+    }
+
+    pub fn note<S: Into<String>>(&self, step: S) {
+        if let Some(ref trace) = self.trace {
+            trace.note(step);
+        }
+    }
+
+    fn note_sent(&self, step: String) {
+        if let Some(ref trace) = self.trace {
+            trace.sent(step);
+        }
+    }
+
     pub fn send_to_all<T: github::CommitLike>(
         &self,
         msg: &str,
@@ -30,7 +160,14 @@ impl Messenger {
         branch: &str,
         commits: &Vec<T>,
     ) {
-        self.send_to_channel(msg, attachments, repo, branch, commits);
+        if self.is_muted(repo, msg) {
+            return;
+        }
+        let msg = match self.during_incident(msg, attachments) {
+            Some(m) => m,
+            None => return,
+        };
+        self.post_to_channel(&msg, attachments, repo, branch, commits);
 
         let mut slackbots: Vec<github::User> = vec![item_owner.clone()];
 
@@ -42,9 +179,19 @@ impl Messenger {
         );
 
         // make sure we do not send private message to author of that message
-        slackbots.retain(|u| u.login != sender.login && u.login() != "octobot");
+        slackbots.retain(|u| {
+            if u.login == sender.login {
+                self.note(format!("Not messaging '{}': they triggered the event", u.login()));
+                false
+            } else if u.login() == "octobot" {
+                self.note("Not messaging 'octobot'");
+                false
+            } else {
+                true
+            }
+        });
 
-        self.send_to_slackbots(slackbots, msg, attachments);
+        self.send_to_slackbots(slackbots, &msg, attachments, self.is_dry_run(repo));
     }
 
     pub fn send_to_owner<T: github::CommitLike>(
@@ -56,8 +203,21 @@ impl Messenger {
         branch: &str,
         commits: &Vec<T>,
     ) {
-        self.send_to_channel(msg, attachments, repo, branch, commits);
-        self.send_to_slackbots(vec![item_owner.clone()], msg, attachments);
+        if self.is_muted(repo, msg) {
+            return;
+        }
+        let msg = match self.during_incident(msg, attachments) {
+            Some(m) => m,
+            None => return,
+        };
+        self.post_to_channel(&msg, attachments, repo, branch, commits);
+        self.send_to_slackbots(vec![item_owner.clone()], &msg, attachments, self.is_dry_run(repo));
+    }
+
+    pub fn send_to_user(&self, user: &github::User, msg: &str, attachments: &Vec<SlackAttachment>) {
+        if let Some(msg) = self.during_incident(msg, attachments) {
+            self.send_to_slackbots(vec![user.clone()], &msg, attachments, self.is_global_dry_run());
+        }
     }
 
     pub fn send_to_channel<T: github::CommitLike>(
@@ -68,21 +228,224 @@ impl Messenger {
         branch: &str,
         commits: &Vec<T>,
     ) {
-        for channel in self.config.repos().lookup_channels(repo, branch, commits) {
+        if self.is_muted(repo, msg) {
+            return;
+        }
+        let msg = match self.during_incident(msg, attachments) {
+            Some(m) => m,
+            None => return,
+        };
+        self.post_to_channel(&msg, attachments, repo, branch, commits);
+    }
+
+    fn post_to_channel<T: github::CommitLike>(
+        &self,
+        msg: &str,
+        attachments: &Vec<SlackAttachment>,
+        repo: &github::Repo,
+        branch: &str,
+        commits: &Vec<T>,
+    ) {
+        let dry_run = self.is_dry_run(repo);
+        let channels = self.channels(repo, branch, commits);
+        if channels.is_empty() {
+            self.note(format!(
+                "No channel configured for repo '{}' on branch '{}'",
+                repo.full_name, branch
+            ));
+        }
+        for channel in channels {
             let channel_msg = format!("{} ({})", msg, util::make_link(&repo.html_url, &repo.full_name));
-            self.send_to_slack(channel.as_str(), &channel_msg, attachments);
+            self.note_sent(format!("Sent to channel '{}'", channel));
+            match self.thread_key {
+                Some(ref key) => {
+                    self.send_req(slack::threaded_req(&channel, &channel_msg, attachments.clone(), key), dry_run)
+                }
+                None => self.send_to_slack(channel.as_str(), &channel_msg, attachments, dry_run),
+            };
         }
     }
 
-    fn send_to_slack(&self, channel: &str, msg: &str, attachments: &Vec<SlackAttachment>) {
-        self.slack.send(slack::req(channel, msg, attachments.clone()));
+    // The channels that send_to_channel sends to
+    pub fn channels<T: github::CommitLike>(&self, repo: &github::Repo, branch: &str, commits: &Vec<T>) -> Vec<String> {
+        self.config.repos().lookup_routed_channels(repo, branch, commits, &self.route)
     }
 
-    fn send_to_slackbots(&self, users: Vec<github::User>, msg: &str, attachments: &Vec<SlackAttachment>) {
-        for user in users {
-            if let Some(slack_ref) = self.config.users().slack_user_mention(&user.login()) {
-                self.send_to_slack(&slack_ref, msg, attachments);
+    // Security alerts go to the security channel if one is configured, otherwise to the repo's channel.
+    // They are sent even while the repo is muted.
+    pub fn send_to_security_channel(&self, msg: &str, attachments: &Vec<SlackAttachment>, repo: &github::Repo) {
+        match self.config.security_channel() {
+            Some(channel) => {
+                let channel_msg = format!("{} ({})", msg, util::make_link(&repo.html_url, &repo.full_name));
+                self.note_sent(format!("Sent to security channel '{}'", channel));
+                self.send_to_slack(&channel, &channel_msg, attachments, self.is_dry_run(repo));
+            }
+            None => self.post_to_channel(msg, attachments, repo, "", &Vec::<github::Commit>::new()),
+        };
+    }
+
+    pub fn send_to_team_channel(&self, channel: &str, msg: &str, attachments: &Vec<SlackAttachment>, repo: &github::Repo) {
+        self.send_about_repo("team channel", channel, msg, attachments, repo);
+    }
+
+    // For the channels of a label rule: not the repo's, nor routed
+    pub fn send_to_rule_channel(
+        &self,
+        channel: &str,
+        msg: &str,
+        attachments: &Vec<SlackAttachment>,
+        repo: &github::Repo,
+    ) {
+        self.send_about_repo("label rule channel", channel, msg, attachments, repo);
+    }
+
+    fn send_about_repo(
+        &self,
+        kind: &str,
+        channel: &str,
+        msg: &str,
+        attachments: &Vec<SlackAttachment>,
+        repo: &github::Repo,
+    ) {
+        if self.is_muted(repo, msg) {
+            return;
+        }
+        let msg = match self.during_incident(msg, attachments) {
+            Some(m) => m,
+            None => return,
+        };
+        let channel_msg = format!("{} ({})", msg, util::make_link(&repo.html_url, &repo.full_name));
+        self.note_sent(format!("Sent to {} '{}'", kind, channel));
+        self.send_to_slack(channel, &channel_msg, attachments, self.is_dry_run(repo));
+    }
+
+    // For news that isn't about a repo, e.g. from JIRA
+    pub fn send_to_channels(&self, channels: &Vec<String>, msg: &str, attachments: &Vec<SlackAttachment>) {
+        for channel in channels {
+            self.note_sent(format!("Sent to channel '{}'", channel));
+            self.send_to_slack(channel, msg, attachments, self.is_global_dry_run());
+        }
+    }
+
+    // Messages about a muted repo are held back for the summary posted when the mute ends
+    fn is_muted(&self, repo: &github::Repo, msg: &str) -> bool {
+        let now = db::now();
+        match self.config.repo_mutes.is_muted(&repo.full_name, now) {
+            Ok(false) => false,
+            Ok(true) => {
+                self.note(format!("Not sending: repo '{}' is muted", repo.full_name));
+                if let Err(e) = self.config.repo_mutes.suppress(&repo.full_name, msg, now) {
+                    error!("Error recording muted message for {}: {}", repo.full_name, e);
+                }
+                true
             }
+            Err(e) => {
+                error!("Error checking whether {} is muted: {}", repo.full_name, e);
+                false
+            }
+        }
+    }
+
+    // While the provider is having an incident, failures mention it, and other messages are held back until it is
+    // resolved. Failures also mention what to look up in /api/trace. Returns the message to send, if any.
+    fn during_incident(&self, msg: &str, attachments: &Vec<SlackAttachment>) -> Option<String> {
+        let failure = statuspage::is_failure(attachments);
+        let msg = if failure { with_trace_id(msg) } else { msg.to_string() };
+        let incident = match statuspage::current_incident(&self.config) {
+            Some(i) => i,
+            None => return Some(msg),
+        };
+        if failure {
+            return Some(statuspage::annotate(&msg, &incident));
+        }
+
+        self.note(format!("Not sending: {} is having an incident", incident.provider));
+        if let Err(e) = self.config.provider_incidents.pause(incident.id, &msg, db::now()) {
+            error!("Error recording paused message: {}", e);
         }
+        None
+    }
+
+    fn send_to_slack(&self, channel: &str, msg: &str, attachments: &Vec<SlackAttachment>, dry_run: bool) {
+        self.send_req(slack::req(channel, msg, attachments.clone()), dry_run);
+    }
+
+    fn send_req(&self, mut req: SlackRequest, dry_run: bool) {
+        if dry_run {
+            info!("Dry run: not sending to {}: {}", req.channel, req.msg);
+            self.note(format!("Dry run: the message to '{}' was only logged", req.channel));
+            return;
+        }
+        req.batch_key = self.batch_key.clone();
+        self.slack.send(req);
+    }
+
+    fn wants_dm_event(&self, user: &users::UserInfo) -> bool {
+        match self.dm_event {
+            Some(ref event) => user.wants_dm_event(event),
+            None => true,
+        }
+    }
+
+    fn send_to_slackbots(
+        &self,
+        users: Vec<github::User>,
+        msg: &str,
+        attachments: &Vec<SlackAttachment>,
+        dry_run: bool,
+    ) {
+        let now = db::now();
+        for user in users {
+            match self.config.users().lookup_info(&user.login()) {
+                None => self.send_email(&user, None, msg, attachments, dry_run),
+                Some(ref u) if u.direct_messages_muted() => {
+                    self.note(format!("Not messaging '{}': direct messages are muted", user.login()))
+                }
+                Some(ref u) if !u.digest.is_empty() => {
+                    self.note(format!("Not messaging '{}': they get a {} digest instead", user.login(), u.digest))
+                }
+                Some(ref u) if !self.wants_dm_event(u) => self.note(format!(
+                    "Not messaging '{}': they turned off '{}' direct messages",
+                    user.login(),
+                    self.dm_event.clone().unwrap_or_default()
+                )),
+                Some(ref u) if u.in_quiet_hours(now) => {
+                    self.note(format!("Not messaging '{}': it is their quiet hours", user.login()))
+                }
+                Some(ref u) if u.slack.is_empty() => self.send_email(&user, Some(u), msg, attachments, dry_run),
+                Some(u) => {
+                    self.note_sent(format!("Sent direct message to '{}'", user.login()));
+                    self.send_to_slack(&users::mention(&u.slack), msg, attachments, dry_run);
+                }
+            };
+        }
+    }
+
+    fn send_email(
+        &self,
+        user: &github::User,
+        info: Option<&users::UserInfo>,
+        msg: &str,
+        attachments: &Vec<SlackAttachment>,
+        dry_run: bool,
+    ) {
+        let address = match (&self.email, &self.config.email) {
+            (Some(_), Some(ref email_config)) if self.email_fallback.iter().any(|l| l == user.login()) => {
+                email::address(email_config, user.login(), info)
+            }
+            _ => None,
+        };
+
+        match (address, &self.email) {
+            (Some(_), Some(_)) if dry_run => {
+                info!("Dry run: not emailing {}: {}", user.login(), msg);
+                self.note(format!("Dry run: the email to '{}' was only logged", user.login()));
+            }
+            (Some(address), Some(ref email)) => {
+                self.note_sent(format!("Emailed '{}': no slack user is mapped", user.login()));
+                email.send(email::req(&address, msg, attachments));
+            }
+            _ => self.note(format!("Not messaging '{}': no slack user is mapped", user.login())),
+        };
     }
 }
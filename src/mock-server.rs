@@ -0,0 +1,18 @@
+use futures::Future;
+use hyper::server::Server;
+use log::{error, info};
+
+use octobot::mock_server::MockService;
+
+// Serves fake GitHub, Slack and JIRA APIs for local development and integration tests
+fn main() {
+    env_logger::init();
+
+    let addr = std::env::args().nth(1).unwrap_or("127.0.0.1:9000".into());
+    let addr = addr.parse().expect("Usage: octobot-mock-server [listen address]");
+
+    let server = Server::bind(&addr).serve(MockService::new()).map_err(|e| error!("server error: {}", e));
+    info!("Mock server listening on {}", addr);
+
+    tokio::run(server);
+}
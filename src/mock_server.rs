@@ -0,0 +1,339 @@
+use std::sync::{Arc, Mutex};
+
+use futures::future::{self, Future};
+use futures::Stream;
+use hyper::service::{NewService, Service};
+use hyper::{self, Body, Method, Request, Response, StatusCode};
+use log::info;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{self, json, Value};
+
+use crate::github;
+use crate::server::http::FutureResponse;
+use crate::util;
+
+// Prefix used by octobot when the github host is a URL (e.g. `http://localhost:9000`)
+pub const GITHUB_PREFIX: &str = "/api/v3";
+pub const SLACK_WEBHOOK_PATH: &str = "/slack/webhook";
+pub const MOCK_BOT_LOGIN: &str = "octobot";
+
+// A request received by the mock server, kept so tests can assert on what octobot sent.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub body: Value,
+}
+
+// A response to return instead of the default one for a given method and path.
+// Paths may end with `*` to match any path with that prefix.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CannedResponse {
+    pub method: String,
+    pub path: String,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub body: Value,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+impl CannedResponse {
+    fn matches(&self, method: &str, path: &str) -> bool {
+        if !self.method.eq_ignore_ascii_case(method) {
+            return false;
+        }
+        if self.path.ends_with('*') {
+            path.starts_with(self.path.trim_end_matches('*'))
+        } else {
+            self.path == path
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MockResponse {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl MockResponse {
+    fn new(status: u16, body: Value) -> MockResponse {
+        MockResponse {
+            status: status,
+            body: body,
+        }
+    }
+
+    fn ok(body: Value) -> MockResponse {
+        MockResponse::new(200, body)
+    }
+}
+
+pub struct MockState {
+    requests: Vec<RecordedRequest>,
+    responses: Vec<CannedResponse>,
+}
+
+impl MockState {
+    pub fn new() -> MockState {
+        MockState {
+            requests: vec![],
+            responses: vec![],
+        }
+    }
+
+    pub fn requests(&self) -> &Vec<RecordedRequest> {
+        &self.requests
+    }
+
+    // Later responses take precedence so tests can override earlier ones.
+    pub fn add_response(&mut self, resp: CannedResponse) {
+        self.responses.insert(0, resp);
+    }
+
+    pub fn clear_requests(&mut self) {
+        self.requests.clear();
+    }
+
+    pub fn clear_responses(&mut self) {
+        self.responses.clear();
+    }
+
+    pub fn handle(&mut self, method: &str, path: &str, query: Option<&str>, body: &str) -> MockResponse {
+        let body: Value = if body.trim().is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(body).unwrap_or(Value::String(body.into()))
+        };
+
+        if path.starts_with("/_mock/") {
+            return self.handle_control(method, path, body);
+        }
+
+        self.requests.push(RecordedRequest {
+            method: method.into(),
+            path: path.into(),
+            query: query.map(|q| q.to_string()),
+            body: body,
+        });
+
+        if let Some(canned) = self.responses.iter().find(|r| r.matches(method, path)) {
+            return MockResponse::new(canned.status, canned.body.clone());
+        }
+
+        if path == SLACK_WEBHOOK_PATH {
+            MockResponse::ok(json!("ok"))
+        } else if path.starts_with(GITHUB_PREFIX) {
+            github_response(method, &path[GITHUB_PREFIX.len()..])
+        } else if path.starts_with("/rest/") {
+            jira_response(method, path)
+        } else {
+            MockResponse::new(404, json!({ "message": format!("No mock for {} {}", method, path) }))
+        }
+    }
+
+    fn handle_control(&mut self, method: &str, path: &str, body: Value) -> MockResponse {
+        match (method, path) {
+            ("GET", "/_mock/requests") => MockResponse::ok(json!(self.requests)),
+            ("DELETE", "/_mock/requests") => {
+                self.clear_requests();
+                MockResponse::new(204, Value::Null)
+            }
+            ("DELETE", "/_mock/responses") => {
+                self.clear_responses();
+                MockResponse::new(204, Value::Null)
+            }
+            ("POST", "/_mock/responses") => match serde_json::from_value::<CannedResponse>(body) {
+                Ok(r) => {
+                    self.add_response(r);
+                    MockResponse::new(201, Value::Null)
+                }
+                Err(e) => MockResponse::new(400, json!({ "message": format!("Invalid canned response: {}", e) })),
+            },
+            _ => MockResponse::new(404, Value::Null),
+        }
+    }
+}
+
+fn github_response(method: &str, path: &str) -> MockResponse {
+    let pull_re = Regex::new(r"^/repos/([^/]+)/([^/]+)/pulls/(\d+)$").unwrap();
+
+    match method {
+        "GET" => {
+            if path == "/user" {
+                return MockResponse::ok(json!({ "login": MOCK_BOT_LOGIN, "name": MOCK_BOT_LOGIN }));
+            }
+            if let Some(c) = pull_re.captures(path) {
+                return MockResponse::ok(mock_pull_request(&c[1], &c[2], c[3].parse().unwrap_or(0)));
+            }
+            if path.ends_with("/contents/CODEOWNERS") || path.ends_with("/contents/.github/CODEOWNERS") {
+                return MockResponse::new(404, json!({ "message": "Not Found" }));
+            }
+            // list endpoints (reviews, commits, comments, files, statuses...) start out empty
+            MockResponse::ok(json!([]))
+        }
+        "PUT" if path.ends_with("/merge") => MockResponse::ok(json!({ "merged": true, "message": "Merged" })),
+        "POST" if path.ends_with("/pulls") => {
+            let repo = path.trim_start_matches("/repos/").trim_end_matches("/pulls");
+            let (owner, name) = match repo.find('/') {
+                Some(i) => (&repo[..i], &repo[i + 1..]),
+                None => (repo, ""),
+            };
+            MockResponse::new(201, mock_pull_request(owner, name, 1))
+        }
+        "DELETE" => MockResponse::new(204, Value::Null),
+        _ => MockResponse::ok(json!({ "id": 1 })),
+    }
+}
+
+fn mock_pull_request(owner: &str, repo: &str, number: u32) -> Value {
+    let mut pr = github::PullRequest::new();
+    pr.number = number;
+    pr.title = format!("Mock pull request #{}", number);
+    pr.html_url = format!("http://localhost/{}/{}/pull/{}", owner, repo, number);
+    pr.user = github::User::new("mock-user");
+    pr.head = github::BranchRef::new("mock-branch");
+    pr.head.sha = "1111111111111111111111111111111111111111".into();
+    pr.base = github::BranchRef::new("master");
+    pr.base.sha = "0000000000000000000000000000000000000000".into();
+    pr.base.repo.name = repo.into();
+    pr.base.repo.full_name = format!("{}/{}", owner, repo);
+    pr.base.repo.owner = github::User::new(owner);
+    pr.mergeable = Some(true);
+    pr.mergeable_state = Some("clean".into());
+
+    serde_json::to_value(&pr).unwrap_or(Value::Null)
+}
+
+fn jira_response(method: &str, path: &str) -> MockResponse {
+    match method {
+        "GET" if path == "/rest/auth/1/session" => MockResponse::ok(json!({ "name": MOCK_BOT_LOGIN })),
+        "GET" if path == "/rest/api/2/field" => MockResponse::ok(json!([
+            { "id": "fixVersions", "name": "Fix Version/s" },
+            { "id": "customfield_10000", "name": "Pending Versions" },
+        ])),
+        "GET" if path.ends_with("/transitions") => MockResponse::ok(json!({ "transitions": [] })),
+        "GET" if path.ends_with("/versions") => MockResponse::ok(json!([])),
+        "GET" if path.starts_with("/rest/api/2/issue/") => {
+            let key = path.trim_start_matches("/rest/api/2/issue/");
+            MockResponse::ok(json!({ "key": key, "fields": {} }))
+        }
+        "GET" => MockResponse::ok(json!([])),
+        "POST" if path.ends_with("/version") => MockResponse::new(201, json!({ "id": "1" })),
+        _ => MockResponse::new(204, Value::Null),
+    }
+}
+
+// Serves the subset of the GitHub, Slack and JIRA APIs that octobot uses, so that it can be run
+// and tested locally without real credentials.
+#[derive(Clone)]
+pub struct MockService {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockService {
+    pub fn new() -> MockService {
+        MockService { state: Arc::new(Mutex::new(MockState::new())) }
+    }
+}
+
+impl NewService for MockService {
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = hyper::Error;
+    type Service = MockService;
+    type Future = future::FutureResult<MockService, hyper::Error>;
+    type InitError = hyper::Error;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+impl Service for MockService {
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = hyper::Error;
+    type Future = FutureResponse;
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let method: Method = req.method().clone();
+        let path = req.uri().path().to_string();
+        let query = req.uri().query().map(|q| q.to_string());
+        let state = self.state.clone();
+
+        Box::new(req.into_body().concat2().map(move |data| {
+            let body = String::from_utf8_lossy(&data);
+            let resp = state.lock().unwrap().handle(method.as_str(), &path, query.as_ref().map(|q| q.as_str()), &body);
+            info!("{} {} {}", method, path, resp.status);
+
+            let status = StatusCode::from_u16(resp.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            if resp.body.is_null() {
+                util::new_empty_resp(status)
+            } else {
+                let mut http_resp: Response<Body> = util::new_json_resp(resp.body.to_string());
+                *http_resp.status_mut() = status;
+                http_resp
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_requests() {
+        let mut state = MockState::new();
+        state.handle("POST", SLACK_WEBHOOK_PATH, None, r#"{"text": "hi"}"#);
+        state.handle("GET", "/api/v3/repos/some-user/some-repo/pulls/3/reviews", Some("page=1"), "");
+
+        assert_eq!(2, state.requests().len());
+        assert_eq!(json!({ "text": "hi" }), state.requests()[0].body);
+        assert_eq!(Some("page=1".to_string()), state.requests()[1].query);
+
+        let resp = state.handle("GET", "/_mock/requests", None, "");
+        assert_eq!(2, resp.body.as_array().unwrap().len());
+
+        state.handle("DELETE", "/_mock/requests", None, "");
+        assert_eq!(0, state.requests().len());
+    }
+
+    #[test]
+    fn test_default_responses() {
+        let mut state = MockState::new();
+
+        let resp = state.handle("GET", "/api/v3/user", None, "");
+        assert_eq!(json!(MOCK_BOT_LOGIN), resp.body["login"]);
+
+        let resp = state.handle("GET", "/api/v3/repos/some-user/some-repo/pulls/32", None, "");
+        let pr: github::PullRequest = serde_json::from_value(resp.body).unwrap();
+        assert_eq!(32, pr.number);
+        assert_eq!("some-user/some-repo", pr.base.repo.full_name);
+
+        let resp = state.handle("GET", "/rest/api/2/field", None, "");
+        assert!(resp.body.as_array().unwrap().iter().any(|f| f["id"] == json!("fixVersions")));
+
+        assert_eq!(404, state.handle("GET", "/unknown", None, "").status);
+    }
+
+    #[test]
+    fn test_canned_responses() {
+        let mut state = MockState::new();
+        let canned = r#"{"method": "GET", "path": "/api/v3/repos/some-user/some-repo/*", "status": 500}"#;
+        assert_eq!(201, state.handle("POST", "/_mock/responses", None, canned).status);
+
+        assert_eq!(500, state.handle("GET", "/api/v3/repos/some-user/some-repo/pulls/1", None, "").status);
+        assert_eq!(200, state.handle("GET", "/api/v3/repos/some-user/other-repo/pulls/1", None, "").status);
+        assert_eq!(200, state.handle("POST", "/api/v3/repos/some-user/some-repo/pulls/1", None, "").status);
+
+        assert_eq!(400, state.handle("POST", "/_mock/responses", None, "{}").status);
+    }
+}
@@ -1,9 +1,5 @@
-use ring::rand::SecureRandom;
-use ring::rand::SystemRandom;
-use rustc_serialize::hex::ToHex;
-
 use octobot::config;
-use octobot::server::login;
+use octobot::passwords;
 
 fn main() {
     if std::env::args().count() < 3 {
@@ -23,15 +19,11 @@ fn main() {
         std::process::exit(1);
     }
 
-    let mut salt_bytes: [u8; 32] = [0; 32];
-    SystemRandom::new().fill(&mut salt_bytes).expect("get random");
-    let salt: String = salt_bytes.to_hex().to_string();
-
-    let pass_hash = login::store_password(&pass1, &salt);
+    let pass_hash = passwords::hash(&pass1, &config.password_params());
 
     config.admin = Some(config::AdminConfig {
         name: admin_name,
-        salt: salt,
+        salt: None,
         pass_hash: pass_hash,
     });
 
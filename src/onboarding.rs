@@ -0,0 +1,442 @@
+use std::collections::HashSet;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github::api::Session;
+use crate::repos::RepoInfo;
+use crate::slack::SlackWebApi;
+use crate::teams::{self, TeamChannels};
+
+pub const PROPOSED: &str = "proposed";
+pub const APPLIED: &str = "applied";
+
+// channels less similar than this to a repo or team aren't proposed for it
+const MIN_SIMILARITY: f64 = 0.6;
+
+// page size of slack's conversations.list
+const SLACK_PAGE_SIZE: &str = "1000";
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RepoProposal {
+    // "org/repo"
+    pub repo: String,
+    // empty if no channel was similar enough, in which case the repo is left out unless one is set
+    pub channel: String,
+    // how similar the channel's name is to the repo's, from 0 to 1
+    pub score: f64,
+    // whether to create the repo's webhook
+    pub webhook: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TeamProposal {
+    // "org/team-slug"
+    pub team: String,
+    pub channel: String,
+    pub score: f64,
+}
+
+// Repos and teams of an org, with the channels they could go to, for an admin to review and apply
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct OnboardingPlan {
+    pub id: i32,
+    pub org: String,
+    // where the created webhooks send events
+    pub webhook_url: String,
+    pub repos: Vec<RepoProposal>,
+    pub teams: Vec<TeamProposal>,
+    // proposed or applied
+    pub state: String,
+    pub created_by: String,
+    pub created_at: i64,
+    pub applied_by: String,
+    pub applied_at: i64,
+    // what applying did, one line per repo or team
+    pub results: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct OnboardingPlans {
+    db: Database,
+}
+
+impl OnboardingPlans {
+    pub fn new(db: Database) -> OnboardingPlans {
+        OnboardingPlans { db: db }
+    }
+
+    pub fn create(&self, plan: &OnboardingPlan) -> Result<OnboardingPlan> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO onboarding_plans
+               (org, webhook_url, repos, teams, state, created_by, created_at, applied_by, applied_at, results)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, '', 0, '[]')"#,
+            &[
+                &plan.org as &dyn ToSql,
+                &plan.webhook_url,
+                &serde_json::to_string(&plan.repos)?,
+                &serde_json::to_string(&plan.teams)?,
+                &PROPOSED,
+                &plan.created_by,
+                &plan.created_at,
+            ],
+        )
+        .map_err(|e| format_err!("Error creating onboarding plan for {}: {}", plan.org, e))?;
+
+        self.get(conn.last_insert_rowid()? as i32)?
+            .ok_or_else(|| format_err!("Onboarding plan for {} was not created", plan.org))
+    }
+
+    pub fn get(&self, id: i32) -> Result<Option<OnboardingPlan>> {
+        Ok(self.select("id = ?1", &[&id as &dyn ToSql])?.into_iter().next())
+    }
+
+    // Most recent first
+    pub fn list(&self) -> Result<Vec<OnboardingPlan>> {
+        let mut plans = self.select("1 = 1", &[])?;
+        plans.reverse();
+        Ok(plans)
+    }
+
+    // Replaces the plan's repos and teams. Returns false if it was already applied.
+    pub fn update(&self, id: i32, repos: &[RepoProposal], teams: &[TeamProposal]) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let changed = conn
+            .execute(
+                "UPDATE onboarding_plans SET repos = ?1, teams = ?2 WHERE id = ?3 AND state = ?4",
+                &[&serde_json::to_string(repos)? as &dyn ToSql, &serde_json::to_string(teams)?, &id, &PROPOSED],
+            )
+            .map_err(|e| format_err!("Error updating onboarding plan {}: {}", id, e))?;
+
+        Ok(changed > 0)
+    }
+
+    // Returns false if it was already applied
+    pub fn mark_applied(&self, id: i32, applied_by: &str, results: &[String], now: i64) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let changed = conn
+            .execute(
+                r#"UPDATE onboarding_plans SET state = ?1, applied_by = ?2, applied_at = ?3, results = ?4
+                   WHERE id = ?5 AND state = ?6"#,
+                &[&APPLIED as &dyn ToSql, &applied_by, &now, &serde_json::to_string(results)?, &id, &PROPOSED],
+            )
+            .map_err(|e| format_err!("Error marking onboarding plan {} applied: {}", id, e))?;
+
+        Ok(changed > 0)
+    }
+
+    fn select(&self, filter: &str, params: &[&dyn ToSql]) -> Result<Vec<OnboardingPlan>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(&format!("SELECT * FROM onboarding_plans WHERE {} ORDER BY id", filter))?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(params)?;
+
+        let mut plans = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            let repos: String = cols.get(row, "repos")?;
+            let teams: String = cols.get(row, "teams")?;
+            let results: String = cols.get(row, "results")?;
+            plans.push(OnboardingPlan {
+                id: cols.get(row, "id")?,
+                org: cols.get(row, "org")?,
+                webhook_url: cols.get(row, "webhook_url")?,
+                repos: serde_json::from_str(&repos)?,
+                teams: serde_json::from_str(&teams)?,
+                state: cols.get(row, "state")?,
+                created_by: cols.get(row, "created_by")?,
+                created_at: cols.get(row, "created_at")?,
+                applied_by: cols.get(row, "applied_by")?,
+                applied_at: cols.get(row, "applied_at")?,
+                results: serde_json::from_str(&results)?,
+            });
+        }
+
+        Ok(plans)
+    }
+}
+
+// Lowercase letters and digits only, so that "Web_App" and "web-app" are the same
+fn normalize(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(|c| c.to_lowercase()).collect()
+}
+
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+// How similar two names are, from 0 to 1. A name contained in the other, like "api" in "api-reviews",
+// counts as similar.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = normalize(a).chars().collect();
+    let b: Vec<char> = normalize(b).chars().collect();
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    let longest = a.len().max(b.len());
+    let ratio = 1.0 - edit_distance(&a, &b) as f64 / longest as f64;
+
+    let (shorter, longer) = if a.len() <= b.len() { (&a, &b) } else { (&b, &a) };
+    let contained = shorter.len() >= 3 && longer.windows(shorter.len()).any(|w| w == &shorter[..]);
+    if contained {
+        ratio.max(0.8)
+    } else {
+        ratio
+    }
+}
+
+// The channel most similar to any of `names`, if it is similar enough. Ties go to the first channel.
+pub fn best_channel(names: &[&str], channels: &[String]) -> Option<(String, f64)> {
+    let mut best: Option<(String, f64)> = None;
+    for channel in channels {
+        let score = names.iter().map(|n| similarity(n, channel)).fold(0.0, f64::max);
+        if score >= MIN_SIMILARITY && best.as_ref().map(|b| score > b.1).unwrap_or(true) {
+            best = Some((channel.clone(), score));
+        }
+    }
+    best
+}
+
+// Names of the workspace's public channels, sorted
+pub fn slack_channels(api: &SlackWebApi) -> Result<Vec<String>> {
+    let mut channels = vec![];
+    let mut cursor = String::new();
+    loop {
+        let resp = api.get(
+            "conversations.list",
+            &[
+                ("types", "public_channel"),
+                ("exclude_archived", "true"),
+                ("limit", SLACK_PAGE_SIZE),
+                ("cursor", &cursor),
+            ],
+        )?;
+        for channel in resp["channels"].as_array().into_iter().flatten() {
+            if let Some(name) = channel["name"].as_str() {
+                channels.push(name.to_string());
+            }
+        }
+
+        cursor = resp["response_metadata"]["next_cursor"].as_str().unwrap_or("").to_string();
+        if cursor.is_empty() {
+            break;
+        }
+    }
+
+    channels.sort();
+    Ok(channels)
+}
+
+// Proposes channels for the org's repos and teams that aren't configured yet, and saves the plan
+pub fn propose(
+    config: &Config,
+    github: &dyn Session,
+    org: &str,
+    webhook_url: &str,
+    channels: &[String],
+    created_by: &str,
+    now: i64,
+) -> Result<OnboardingPlan> {
+    let configured: HashSet<String> = config.repos().get_all()?.into_iter().map(|r| r.repo).collect();
+    let mut repos = vec![];
+    for repo in github.get_org_repos(org)? {
+        if repo.archived == Some(true) || configured.contains(&repo.full_name) {
+            continue;
+        }
+        let (channel, score) = best_channel(&[repo.name.as_str()], channels).unwrap_or((String::new(), 0.0));
+        repos.push(RepoProposal {
+            repo: repo.full_name,
+            channel: channel,
+            score: score,
+            webhook: true,
+        });
+    }
+
+    let configured: HashSet<String> = config.team_channels.get_all()?.into_iter().map(|t| t.team).collect();
+    let mut teams = vec![];
+    for team in github.get_org_teams(org)? {
+        let key = teams::team_key(org, &team.slug);
+        if configured.contains(&key) {
+            continue;
+        }
+        if let Some((channel, score)) = best_channel(&[team.slug.as_str(), team.name.as_str()], channels) {
+            teams.push(TeamProposal {
+                team: key,
+                channel: channel,
+                score: score,
+            });
+        }
+    }
+
+    config.onboarding_plans.create(&OnboardingPlan {
+        id: 0,
+        org: org.to_string(),
+        webhook_url: webhook_url.to_string(),
+        repos: repos,
+        teams: teams,
+        state: PROPOSED.to_string(),
+        created_by: created_by.to_string(),
+        created_at: now,
+        applied_by: String::new(),
+        applied_at: 0,
+        results: vec![],
+    })
+}
+
+// Configures the plan's repos and teams that have a channel in one transaction, then creates the webhooks.
+// Returns what was done, which is also saved with the plan.
+pub fn apply(
+    config: &Config,
+    github: &dyn Session,
+    plan: &OnboardingPlan,
+    applied_by: &str,
+    now: i64,
+) -> Result<Vec<String>> {
+    if plan.state != PROPOSED {
+        return Err(format_err!("Onboarding plan {} was already {}", plan.id, plan.state));
+    }
+
+    let mut results = vec![];
+    {
+        let mut repos = config.repos_write();
+        let configured: HashSet<String> = repos.get_all()?.into_iter().map(|r| r.repo).collect();
+
+        let mut conn = config.database().connect()?;
+        let tx = conn.transaction()?;
+        for repo in &plan.repos {
+            if repo.channel.is_empty() {
+                results.push(format!("{}: skipped, no channel", repo.repo));
+            } else if configured.contains(&repo.repo) {
+                results.push(format!("{}: skipped, already configured", repo.repo));
+            } else {
+                repos.insert_in(&tx, &RepoInfo::new(&repo.repo, &repo.channel))?;
+                results.push(format!("{}: configured for #{}", repo.repo, repo.channel));
+            }
+        }
+        for team in plan.teams.iter().filter(|t| !t.channel.is_empty()) {
+            TeamChannels::set_in(&tx, &team.team, &team.channel)?;
+            results.push(format!("{}: review requests go to #{}", team.team, team.channel));
+        }
+        tx.commit()?;
+    }
+
+    let secret = config.github_for_owner(&plan.org).webhook_secret.clone();
+    for repo in plan.repos.iter().filter(|r| r.webhook) {
+        let name = repo.repo.splitn(2, '/').nth(1).unwrap_or("");
+        match github.create_webhook(&plan.org, name, &plan.webhook_url, &secret) {
+            Ok(()) => results.push(format!("{}: created webhook", repo.repo)),
+            Err(e) => {
+                error!("{}", e);
+                results.push(format!("{}: error creating webhook: {}", repo.repo, e));
+            }
+        }
+    }
+
+    if !config.onboarding_plans.mark_applied(plan.id, applied_by, &results, now)? {
+        return Err(format_err!("Onboarding plan {} was applied concurrently", plan.id));
+    }
+    info!("{} applied the onboarding plan {} for {}", applied_by, plan.id, plan.org);
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (Config, TempDir) {
+        let temp_dir = TempDir::new("onboarding.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        (Config::new(db), temp_dir)
+    }
+
+    fn plan(org: &str) -> OnboardingPlan {
+        OnboardingPlan {
+            id: 0,
+            org: org.into(),
+            webhook_url: "https://octobot.company.com/hooks/github".into(),
+            repos: vec![RepoProposal {
+                repo: format!("{}/web-app", org),
+                channel: "web-app".into(),
+                score: 1.0,
+                webhook: true,
+            }],
+            teams: vec![],
+            state: PROPOSED.into(),
+            created_by: "admin".into(),
+            created_at: 100,
+            applied_by: String::new(),
+            applied_at: 0,
+            results: vec![],
+        }
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(1.0, similarity("Web_App", "web-app"));
+        assert_eq!(0.8, similarity("api", "api-reviews"));
+        assert_eq!(0.0, similarity("", "web-app"));
+        assert!(similarity("web-app", "webapp-dev") >= MIN_SIMILARITY);
+        assert!(similarity("web-app", "payments") < MIN_SIMILARITY);
+        // too short to count as contained
+        assert!(similarity("ui", "builds") < MIN_SIMILARITY);
+    }
+
+    #[test]
+    fn test_best_channel() {
+        let channels = vec!["api-reviews".to_string(), "general".to_string(), "web-app".to_string()];
+
+        assert_eq!(Some(("web-app".to_string(), 1.0)), best_channel(&["WebApp"], &channels));
+        assert_eq!(Some(("api-reviews".to_string(), 0.8)), best_channel(&["backend", "api"], &channels));
+        assert_eq!(None, best_channel(&["payments"], &channels));
+    }
+
+    #[test]
+    fn test_plans() {
+        let (config, _temp) = new_test();
+        let plans = &config.onboarding_plans;
+
+        let first = plans.create(&plan("some-org")).unwrap();
+        let second = plans.create(&plan("other-org")).unwrap();
+        assert_eq!(PROPOSED, first.state);
+        assert_eq!(plan("some-org").repos, first.repos);
+        assert_eq!(vec![second.id, first.id], plans.list().unwrap().iter().map(|p| p.id).collect::<Vec<_>>());
+
+        let teams = vec![TeamProposal {
+            team: "some-org/backend".into(),
+            channel: "backend".into(),
+            score: 0.9,
+        }];
+        assert!(plans.update(first.id, &[], &teams).unwrap());
+        let first = plans.get(first.id).unwrap().unwrap();
+        assert_eq!(0, first.repos.len());
+        assert_eq!(teams, first.teams);
+
+        let results = vec!["some-org/backend: review requests go to #backend".to_string()];
+        assert!(plans.mark_applied(first.id, "other-admin", &results, 200).unwrap());
+        assert!(!plans.mark_applied(first.id, "other-admin", &results, 300).unwrap());
+        assert!(!plans.update(first.id, &[], &[]).unwrap());
+
+        let first = plans.get(first.id).unwrap().unwrap();
+        assert_eq!((APPLIED, "other-admin", 200), (first.state.as_str(), first.applied_by.as_str(), first.applied_at));
+        assert_eq!(results, first.results);
+        assert_eq!(None, plans.get(first.id + 10).unwrap());
+    }
+}
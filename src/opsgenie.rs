@@ -0,0 +1,243 @@
+use failure::format_err;
+use log::{error, info};
+use reqwest;
+use serde_derive::{Deserialize, Serialize};
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+use crate::alerts::{self, CriticalAlert};
+use crate::config::{Config, OpsgenieConfig};
+use crate::errors::*;
+use crate::github;
+use crate::http_client::HTTPClient;
+
+const DEFAULT_API_URL: &str = "https://api.opsgenie.com";
+const DEFAULT_PRIORITY: &str = "P1";
+
+// responders like this are escalation policies rather than teams
+const ESCALATION_PREFIX: &str = "escalation:";
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Responder {
+    pub name: String,
+    // "team" or "escalation"
+    #[serde(rename = "type")]
+    pub responder_type: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct NewAlert {
+    pub message: String,
+    // lets octobot acknowledge the alert later on without keeping opsgenie's id
+    pub alias: String,
+    pub description: String,
+    pub responders: Vec<Responder>,
+    pub tags: Vec<String>,
+    pub source: String,
+    pub priority: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct AckReq {
+    user: String,
+    source: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct OnCalls {
+    #[serde(rename = "onCallRecipients", default)]
+    on_call_recipients: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct OnCallsResp {
+    data: OnCalls,
+}
+
+pub trait Session: Send + Sync {
+    fn create_alert(&self, alert: &NewAlert) -> Result<()>;
+    fn acknowledge_alert(&self, alias: &str, user: &str) -> Result<()>;
+    // who is on call for the schedule right now, as opsgenie usernames (emails)
+    fn get_on_calls(&self, schedule: &str) -> Result<Vec<String>>;
+}
+
+pub struct OpsgenieSession {
+    client: HTTPClient,
+}
+
+impl OpsgenieSession {
+    pub fn new(config: &OpsgenieConfig) -> Result<OpsgenieSession> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::ACCEPT, "application/json".parse().unwrap());
+        headers.insert(reqwest::header::AUTHORIZATION, format!("GenieKey {}", config.api_key).parse()?);
+
+        let api_url = config.api_url.clone().unwrap_or(DEFAULT_API_URL.into());
+        Ok(OpsgenieSession {
+            client: HTTPClient::new_with_headers(&format!("{}/v2", api_url.trim_end_matches('/')), headers)?,
+        })
+    }
+}
+
+// Opsgenie handles alert requests asynchronously: these only fail if the request itself is bad
+impl Session for OpsgenieSession {
+    fn create_alert(&self, alert: &NewAlert) -> Result<()> {
+        self.client
+            .post_void("/alerts", alert)
+            .map_err(|e| format_err!("Error creating opsgenie alert {}: {}", alert.alias, e))
+    }
+
+    fn acknowledge_alert(&self, alias: &str, user: &str) -> Result<()> {
+        let req = AckReq {
+            user: user.into(),
+            source: "octobot".into(),
+        };
+        self.client
+            .post_void(&format!("/alerts/{}/acknowledge?identifierType=alias", alias), &req)
+            .map_err(|e| format_err!("Error acknowledging opsgenie alert {}: {}", alias, e))
+    }
+
+    fn get_on_calls(&self, schedule: &str) -> Result<Vec<String>> {
+        let path = format!(
+            "/schedules/{}/on-calls?scheduleIdentifierType=name&flat=true",
+            utf8_percent_encode(schedule, PATH_SEGMENT_ENCODE_SET)
+        );
+        self.client
+            .get::<OnCallsResp>(&path)
+            .map(|r| r.data.on_call_recipients)
+            .map_err(|e| format_err!("Error getting on-calls of opsgenie schedule {}: {}", schedule, e))
+    }
+}
+
+pub fn is_priority(priority: &str) -> bool {
+    ["P1", "P2", "P3", "P4", "P5"].contains(&priority)
+}
+
+pub fn alert_alias(id: i32) -> String {
+    format!("octobot-alert-{}", id)
+}
+
+// "escalation:<policy>" is an escalation policy, anything else a team
+pub fn responder(value: &str) -> Responder {
+    let value = value.trim();
+    if value.starts_with(ESCALATION_PREFIX) {
+        Responder {
+            name: value[ESCALATION_PREFIX.len()..].trim().to_string(),
+            responder_type: "escalation".into(),
+        }
+    } else {
+        Responder {
+            name: value.to_string(),
+            responder_type: "team".into(),
+        }
+    }
+}
+
+// Opsgenie creates a schedule named like this for each team
+pub fn team_schedule(team: &str) -> String {
+    format!("{}_schedule", team)
+}
+
+pub fn new_alert(config: &OpsgenieConfig, alert: &CriticalAlert, responders: &Vec<String>) -> NewAlert {
+    NewAlert {
+        message: format!("{}: {} ({})", alerts::title(&alert.kind), alert.branch, alert.repo),
+        alias: alert_alias(alert.id),
+        description: alert.message.clone(),
+        responders: responders.iter().map(|r| responder(r)).collect(),
+        tags: vec![alert.kind.clone(), alert.repo.clone()],
+        source: "octobot".into(),
+        priority: config.priority.clone().unwrap_or(DEFAULT_PRIORITY.into()),
+    }
+}
+
+// Pages the responders that the repo's routing rules pick for the alert's branch. Returns who is on call for the
+// paged teams, so that the alert can name them.
+pub fn page(session: &dyn Session, config: &Config, repo: &github::Repo, alert: &CriticalAlert) -> Result<Vec<String>> {
+    let opsgenie = match config.opsgenie {
+        Some(ref o) => o,
+        None => return Ok(vec![]),
+    };
+    let responders = config.opsgenie_responders(repo, &alert.branch);
+    if responders.is_empty() {
+        return Ok(vec![]);
+    }
+
+    session.create_alert(&new_alert(opsgenie, alert, &responders))?;
+    info!("Paged {} for alert {}", responders.join(", "), alert.id);
+
+    let mut on_call = vec![];
+    for team in responders.iter().map(|r| responder(r)).filter(|r| r.responder_type == "team") {
+        let people = match session.get_on_calls(&team_schedule(&team.name)) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("{}", e);
+                continue;
+            }
+        };
+        for person in people {
+            if !on_call.contains(&person) {
+                on_call.push(person);
+            }
+        }
+    }
+    Ok(on_call)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_responder() {
+        assert_eq!(
+            Responder {
+                name: "backend".into(),
+                responder_type: "team".into(),
+            },
+            responder(" backend ")
+        );
+        assert_eq!(
+            Responder {
+                name: "Backend Escalation".into(),
+                responder_type: "escalation".into(),
+            },
+            responder("escalation: Backend Escalation")
+        );
+        assert_eq!("backend_schedule", team_schedule("backend"));
+    }
+
+    #[test]
+    fn test_new_alert() {
+        let config = OpsgenieConfig {
+            api_key: "the-key".into(),
+            api_url: None,
+            default_responder: None,
+            priority: None,
+        };
+        let alert = CriticalAlert {
+            id: 12,
+            repo: "some-org/some-repo".into(),
+            branch: "master".into(),
+            kind: alerts::MAIN_BROKEN.into(),
+            message: "ci/build: Tests failed".into(),
+            tier: 0,
+            escalate_at: 1900,
+            acked_by: None,
+            acked_at: 0,
+            created_at: 1000,
+        };
+
+        let new = new_alert(&config, &alert, &vec!["backend".into(), "escalation:Release".into()]);
+        assert_eq!("Main branch is broken: master (some-org/some-repo)", new.message);
+        assert_eq!("octobot-alert-12", new.alias);
+        assert_eq!("ci/build: Tests failed", new.description);
+        assert_eq!(vec![responder("backend"), responder("escalation:Release")], new.responders);
+        assert_eq!("P1", new.priority);
+    }
+
+    #[test]
+    fn test_is_priority() {
+        assert!(is_priority("P1"));
+        assert!(is_priority("P5"));
+        assert!(!is_priority("P6"));
+        assert!(!is_priority("high"));
+    }
+}
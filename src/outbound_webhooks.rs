@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use reqwest;
+use ring::{digest, hmac};
+use rustc_serialize::hex::ToHex;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+use crate::config::WebhookConfig;
+use crate::config_reload::LiveConfig;
+use crate::db;
+use crate::errors::*;
+use crate::event_bus::Publisher;
+use crate::github;
+use crate::network;
+use crate::warehouse::Exporter;
+use crate::worker;
+
+pub const PULL_REQUEST_OPENED: &str = "pull_request.opened";
+pub const PULL_REQUEST_REVIEW_REQUESTED: &str = "pull_request.review_requested";
+pub const PULL_REQUEST_MERGED: &str = "pull_request.merged";
+pub const CI_FAILED: &str = "ci.failed";
+pub const BACKPORT_FAILED: &str = "backport.failed";
+
+pub const EVENT_HEADER: &str = "X-Octobot-Event";
+pub const SIGNATURE_HEADER: &str = "X-Octobot-Signature";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PullRequestInfo {
+    pub number: u32,
+    pub title: String,
+    pub html_url: String,
+    pub author: String,
+    pub base_branch: String,
+    pub head_branch: String,
+}
+
+// An event as octobot understands it, decoupled from the shape of github's webhooks
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OutboundEvent {
+    pub event: String,
+    pub repo: String,
+    pub sender: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pull_request: Option<PullRequestInfo>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub reviewers: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+// What is actually POSTed: the event plus when it was sent
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Delivery {
+    #[serde(flatten)]
+    pub event: OutboundEvent,
+    pub timestamp: i64,
+}
+
+impl OutboundEvent {
+    pub fn new(event: &str, repo: &github::Repo, sender: &str) -> OutboundEvent {
+        OutboundEvent {
+            event: event.into(),
+            repo: repo.full_name.clone(),
+            sender: sender.into(),
+            pull_request: None,
+            reviewers: vec![],
+            commit: None,
+            branch: None,
+            context: None,
+            url: None,
+            message: None,
+        }
+    }
+}
+
+pub fn pull_request_info(pull_request: &github::PullRequest) -> PullRequestInfo {
+    PullRequestInfo {
+        number: pull_request.number,
+        title: pull_request.title.clone(),
+        html_url: pull_request.html_url.clone(),
+        author: pull_request.user.login().into(),
+        base_branch: pull_request.base.ref_name.clone(),
+        head_branch: pull_request.head.ref_name.clone(),
+    }
+}
+
+pub fn pull_request_event(
+    event: &str,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    sender: &github::User,
+) -> OutboundEvent {
+    let mut e = OutboundEvent::new(event, repo, sender.login());
+    e.pull_request = Some(pull_request_info(pull_request));
+    e.commit = Some(pull_request.head.sha.clone()).filter(|s| !s.is_empty());
+    e.branch = Some(pull_request.base.ref_name.clone());
+    if event == PULL_REQUEST_REVIEW_REQUESTED {
+        if let Some(ref reviewers) = pull_request.requested_reviewers {
+            e.reviewers = reviewers.iter().map(|r| r.login().to_string()).collect();
+        }
+    }
+    e
+}
+
+pub fn ci_failed(data: &github::HookBody) -> OutboundEvent {
+    let mut e = OutboundEvent::new(CI_FAILED, &data.repository, data.sender.login());
+    e.commit = data.sha.clone();
+    e.branch = data.branches.as_ref().and_then(|b| b.first()).map(|b| b.name.clone());
+    e.context = data.context.clone();
+    e.url = data.target_url.clone().filter(|u| !u.is_empty());
+    e.message = data.description.clone().filter(|d| !d.is_empty());
+    e
+}
+
+pub fn backport_failed(
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    target_branch: &str,
+    error: &str,
+) -> OutboundEvent {
+    let mut e = OutboundEvent::new(BACKPORT_FAILED, repo, pull_request.user.login());
+    e.pull_request = Some(pull_request_info(pull_request));
+    e.branch = Some(target_branch.into());
+    e.url = Some(pull_request.html_url.clone());
+    e.message = Some(error.into());
+    e
+}
+
+// "sha256=" followed by the hex HMAC-SHA256 of the body, like github's X-Hub-Signature-256
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+    format!("sha256={}", hmac::sign(&key, body).as_ref().to_hex())
+}
+
+pub fn wants(webhook: &WebhookConfig, event: &str) -> bool {
+    match webhook.events {
+        Some(ref events) if !events.is_empty() => events.iter().any(|e| e == event),
+        _ => true,
+    }
+}
+
+struct Runner {
+    live_config: Arc<LiveConfig>,
+    client: reqwest::Client,
+    exporter: Option<Arc<Exporter>>,
+    publisher: Option<Arc<dyn Publisher>>,
+}
+
+pub fn new_runner(
+    live_config: Arc<LiveConfig>,
+    exporter: Option<Arc<Exporter>>,
+    publisher: Option<Arc<dyn Publisher>>,
+) -> Arc<dyn worker::Runner<OutboundEvent>> {
+    Arc::new(Runner {
+        live_config: live_config,
+        client: network::client(),
+        exporter: exporter,
+        publisher: publisher,
+    })
+}
+
+impl Runner {
+    fn deliver(&self, webhook: &WebhookConfig, event: &str, body: &[u8]) -> Result<()> {
+        let mut req = self
+            .client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event);
+        if let Some(ref secret) = webhook.secret {
+            req = req.header(SIGNATURE_HEADER, signature(secret, body));
+        }
+        req.body(body.to_vec())
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format_err!("{}", e))
+    }
+}
+
+impl worker::Runner<OutboundEvent> for Runner {
+    fn handle(&self, event: OutboundEvent) {
+        let name = event.event.clone();
+        let delivery = Delivery {
+            event: event,
+            timestamp: db::now(),
+        };
+        let body = match serde_json::to_vec(&delivery) {
+            Ok(b) => b,
+            Err(e) => {
+                error!("Error serializing {} event: {}", name, e);
+                return;
+            }
+        };
+
+        if let Some(ref publisher) = self.publisher {
+            match publisher.publish(&delivery) {
+                Ok(()) => info!("Published {} event", name),
+                Err(e) => error!("Error publishing {} event: {}", name, e),
+            };
+        }
+        if let Some(ref exporter) = self.exporter {
+            exporter.add(delivery);
+        }
+
+        for webhook in self.live_config.get().webhooks().iter().filter(|w| wants(w, &name)) {
+            match self.deliver(webhook, &name, &body) {
+                Ok(()) => info!("Sent {} event to {}", name, webhook.url),
+                Err(e) => error!("Error sending {} event to {}: {}", name, webhook.url, e),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhook(events: Option<Vec<&str>>) -> WebhookConfig {
+        WebhookConfig {
+            url: "http://the-consumer/hook".into(),
+            secret: Some("the-secret".into()),
+            events: events.map(|e| e.into_iter().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        let body = br#"{"event":"ci.failed"}"#;
+        let sig = signature("the-secret", body);
+
+        assert!(sig.starts_with("sha256="));
+        assert_eq!(7 + 64, sig.len());
+        assert_eq!(sig, signature("the-secret", body));
+        assert_ne!(sig, signature("another-secret", body));
+
+        let key = hmac::VerificationKey::new(&digest::SHA256, b"the-secret");
+        let sig_bytes = (0..64).step_by(2).map(|i| u8::from_str_radix(&sig[7 + i..9 + i], 16).unwrap()).collect::<Vec<_>>();
+        assert!(hmac::verify(&key, body, &sig_bytes).is_ok());
+    }
+
+    #[test]
+    fn test_wants() {
+        assert!(wants(&webhook(None), CI_FAILED));
+        assert!(wants(&webhook(Some(vec![])), PULL_REQUEST_MERGED));
+        assert!(wants(&webhook(Some(vec![CI_FAILED, BACKPORT_FAILED])), BACKPORT_FAILED));
+        assert!(!wants(&webhook(Some(vec![CI_FAILED])), PULL_REQUEST_OPENED));
+    }
+
+    #[test]
+    fn test_pull_request_event() {
+        let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
+        let mut pr = github::PullRequest::new();
+        pr.number = 32;
+        pr.title = "The PR".into();
+        pr.html_url = "http://the-pr".into();
+        pr.user = github::User::new("the-pr-owner");
+        pr.base.ref_name = "master".into();
+        pr.head.ref_name = "the-feature".into();
+        pr.head.sha = "ffff0000".into();
+        pr.requested_reviewers = Some(vec![github::User::new("joe-reviewer")]);
+
+        let e = pull_request_event(PULL_REQUEST_REVIEW_REQUESTED, &repo, &pr, &github::User::new("the-sender"));
+        assert_eq!("the-owner/the-repo", e.repo);
+        assert_eq!("the-sender", e.sender);
+        assert_eq!(vec!["joe-reviewer".to_string()], e.reviewers);
+        assert_eq!(Some("ffff0000".to_string()), e.commit);
+        assert_eq!("the-pr-owner", e.pull_request.unwrap().author);
+
+        let e = pull_request_event(PULL_REQUEST_OPENED, &repo, &pr, &github::User::new("the-sender"));
+        assert!(e.reviewers.is_empty());
+    }
+
+    #[test]
+    fn test_delivery_json() {
+        let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
+        let mut e = OutboundEvent::new(CI_FAILED, &repo, "the-sender");
+        e.commit = Some("abcdef".into());
+        let json = serde_json::to_value(&Delivery { event: e, timestamp: 1234 }).unwrap();
+
+        assert_eq!(
+            serde_json::json!({
+                "event": "ci.failed",
+                "repo": "the-owner/the-repo",
+                "sender": "the-sender",
+                "commit": "abcdef",
+                "timestamp": 1234,
+            }),
+            json
+        );
+    }
+}
@@ -0,0 +1,545 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use ring::digest;
+use rustc_serialize::hex::ToHex;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+use crate::config::Config;
+use crate::db::{self, Connection, Database, ToSql};
+use crate::error_reports;
+use crate::errors::*;
+use crate::redis;
+use crate::scheduler;
+use crate::slack::SlackRequest;
+use crate::worker;
+
+pub const DB: &str = "db";
+pub const REDIS: &str = "redis";
+
+// how often to look for messages that were recorded but never delivered
+pub const CHECK_INTERVAL_SECS: u64 = 60;
+
+// A message that still isn't delivered by then was lost, e.g. because octobot stopped while it was queued
+const REDELIVER_AFTER_SECS: i64 = 300;
+
+// including the first, so that a message slack keeps refusing isn't tried forever
+pub const MAX_ATTEMPTS: u32 = 3;
+
+static NEXT_LOCAL_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OutboxMessage {
+    // the same for every handling of the delivery that sent it. see `receipt`
+    pub receipt: String,
+    pub request: SlackRequest,
+    pub attempts: u32,
+    pub attempted_at: i64,
+}
+
+impl OutboxMessage {
+    pub fn new(receipt: &str, request: SlackRequest, now: i64) -> OutboxMessage {
+        OutboxMessage {
+            receipt: receipt.into(),
+            request: request,
+            attempts: 1,
+            attempted_at: now,
+        }
+    }
+
+    // The request that sends it again
+    fn redelivery(self) -> SlackRequest {
+        let mut req = self.request;
+        req.receipts = vec![self.receipt];
+        req
+    }
+}
+
+// Identifies a message by the github delivery that sent it and what it says, so that handling the delivery again
+// (after a crash, by another instance, or from `webhook_retries`) finds the receipt of the message it sent before
+pub fn receipt(context: &error_reports::Context, req: &SlackRequest) -> String {
+    if context.delivery_id.is_empty() {
+        // e.g. scheduled reminders: nothing sends them again, so they only need to be unique
+        let id = NEXT_LOCAL_ID.fetch_add(1, Ordering::SeqCst);
+        return format!("local-{}-{}-{}", std::process::id(), db::now(), id);
+    }
+    let json = serde_json::to_vec(req).unwrap_or_default();
+    format!("{}-{}", context.delivery_id, digest::digest(&digest::SHA256, &json).as_ref()[..8].to_hex())
+}
+
+// Where messages are recorded before they are sent, and marked once they are
+pub trait ReceiptStore: Send + Sync {
+    // Records a message to send. False if its receipt was recorded before, i.e. it was (or is being) sent already.
+    fn record(&self, msg: &OutboxMessage) -> Result<bool>;
+
+    fn delivered(&self, receipt: &str, now: i64) -> Result<()>;
+
+    // Takes the undelivered messages last attempted before |before| for another attempt, leaving the ones that were
+    // attempted MAX_ATTEMPTS times
+    fn take_stale(&self, before: i64, now: i64) -> Result<Vec<OutboxMessage>>;
+
+    // Forgets the messages recorded before |before|, returning how many
+    fn purge(&self, before: i64) -> Result<usize>;
+}
+
+pub fn new_store(config: &Config) -> Result<Arc<dyn ReceiptStore>> {
+    match config.outbox_store().as_str() {
+        REDIS => {
+            let url = config
+                .outbox_redis_url()
+                .ok_or_else(|| format_err!("outbox.redis_url (or ha.redis_url) is required for the redis store"))?;
+            let store = RedisReceiptStore::new(&url, &config.ha_key_prefix(), config.outbox_retention_secs())?;
+            Ok(Arc::new(store))
+        }
+        _ => Ok(Arc::new(config.message_receipts.clone())),
+    }
+}
+
+// Records a message in the outbox table of |conn|, e.g. in the transaction of the state change that sends it
+pub fn record_in(conn: &Connection, msg: &OutboxMessage) -> Result<bool> {
+    let request = serde_json::to_string(&msg.request)?;
+    let inserted = conn
+        .execute(
+            r#"INSERT INTO outbox (receipt, request, attempts, attempted_at, delivered_at, created_at)
+               VALUES (?1, ?2, ?3, ?4, NULL, ?4)
+               ON CONFLICT DO NOTHING"#,
+            &[&msg.receipt as &dyn ToSql, &request, &msg.attempts, &msg.attempted_at],
+        )
+        .map_err(|e| format_err!("Error recording message {}: {}", msg.receipt, e))?;
+    Ok(inserted > 0)
+}
+
+#[derive(Clone)]
+pub struct DbReceiptStore {
+    db: Database,
+}
+
+impl DbReceiptStore {
+    pub fn new(db: Database) -> DbReceiptStore {
+        DbReceiptStore { db: db }
+    }
+
+    // Records |msg| in the same transaction as the state change |change| makes, so that the message is sent if and
+    // only if the change is made. Neither is if the message was recorded before.
+    pub fn record_with<F>(&self, msg: &OutboxMessage, change: F) -> Result<bool>
+    where
+        F: FnOnce(&Connection) -> Result<()>,
+    {
+        let mut conn = self.db.connect()?;
+        let tx = conn.transaction()?;
+        if !record_in(&*tx, msg)? {
+            return Ok(false);
+        }
+        change(&*tx)?;
+        tx.commit()?;
+        Ok(true)
+    }
+}
+
+impl ReceiptStore for DbReceiptStore {
+    fn record(&self, msg: &OutboxMessage) -> Result<bool> {
+        record_in(&self.db.connect()?, msg)
+    }
+
+    fn delivered(&self, receipt: &str, now: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute("UPDATE outbox SET delivered_at = ?1 WHERE receipt = ?2", &[&now as &dyn ToSql, &receipt])
+            .map_err(|e| format_err!("Error marking message {} delivered: {}", receipt, e))?;
+        Ok(())
+    }
+
+    fn take_stale(&self, before: i64, now: i64) -> Result<Vec<OutboxMessage>> {
+        let mut conn = self.db.connect()?;
+        let tx = conn.transaction()?;
+
+        let mut msgs = vec![];
+        {
+            let mut stmt = tx.prepare(
+                "SELECT * FROM outbox WHERE delivered_at IS NULL AND attempted_at < :before AND attempts < :max \
+                 ORDER BY attempted_at",
+            )?;
+            let cols = db::Columns::from_stmt(&stmt)?;
+            let mut rows = stmt.query_named(&[(":before", &before as &dyn ToSql), (":max", &MAX_ATTEMPTS)])?;
+            while let Ok(Some(row)) = rows.next() {
+                let request: String = cols.get(row, "request")?;
+                let attempts: u32 = cols.get(row, "attempts")?;
+                msgs.push(OutboxMessage {
+                    receipt: cols.get(row, "receipt")?,
+                    request: serde_json::from_str(&request)?,
+                    attempts: attempts + 1,
+                    attempted_at: now,
+                });
+            }
+        }
+
+        for msg in &msgs {
+            tx.execute(
+                "UPDATE outbox SET attempts = ?1, attempted_at = ?2 WHERE receipt = ?3",
+                &[&msg.attempts as &dyn ToSql, &now, &msg.receipt],
+            )
+            .map_err(|e| format_err!("Error taking message {}: {}", msg.receipt, e))?;
+        }
+        tx.commit()?;
+
+        Ok(msgs)
+    }
+
+    fn purge(&self, before: i64) -> Result<usize> {
+        let conn = self.db.connect()?;
+        let purged = conn
+            .execute("DELETE FROM outbox WHERE created_at < ?1", &[&before])
+            .map_err(|e| format_err!("Error purging the outbox: {}", e))?;
+        Ok(purged)
+    }
+}
+
+// Takes an undelivered message for another attempt unless another instance just did
+const TAKE_SCRIPT: &str = r#"
+local score = redis.call('ZSCORE', KEYS[1], ARGV[1])
+if score and tonumber(score) < tonumber(ARGV[2]) then
+    redis.call('ZADD', KEYS[1], ARGV[3], ARGV[1])
+    return 1
+end
+return 0
+"#;
+
+// For instances that share a redis, so that whichever one handles a delivery again knows what the others sent. Each
+// message is kept under its receipt until it expires, and the undelivered ones are listed by when they were attempted.
+pub struct RedisReceiptStore {
+    client: redis::Client,
+    prefix: String,
+    retention_secs: u64,
+}
+
+impl RedisReceiptStore {
+    pub fn new(url: &str, prefix: &str, retention_secs: u64) -> Result<RedisReceiptStore> {
+        Ok(RedisReceiptStore {
+            client: redis::Client::new(url)?,
+            prefix: prefix.to_string(),
+            retention_secs: retention_secs,
+        })
+    }
+
+    fn message_key(&self, receipt: &str) -> String {
+        format!("{}:outbox:{}", self.prefix, receipt)
+    }
+
+    fn undelivered_key(&self) -> String {
+        format!("{}:outbox-undelivered", self.prefix)
+    }
+
+    fn save(&self, msg: &OutboxMessage, only_new: bool) -> Result<bool> {
+        let data = serde_json::to_string(msg)?;
+        let ttl = self.retention_secs.to_string();
+        let condition = if only_new { "NX" } else { "XX" };
+        let saved = self.client.cmd(&["SET", &self.message_key(&msg.receipt), &data, condition, "EX", &ttl])?;
+        Ok(!saved.is_nil())
+    }
+}
+
+impl ReceiptStore for RedisReceiptStore {
+    fn record(&self, msg: &OutboxMessage) -> Result<bool> {
+        if !self.save(msg, true)? {
+            return Ok(false);
+        }
+        let score = msg.attempted_at.to_string();
+        self.client.cmd(&["ZADD", &self.undelivered_key(), &score, &msg.receipt])?;
+        Ok(true)
+    }
+
+    fn delivered(&self, receipt: &str, _now: i64) -> Result<()> {
+        // the message itself stays until it expires: it is the receipt
+        self.client.cmd(&["ZREM", &self.undelivered_key(), receipt])?;
+        Ok(())
+    }
+
+    fn take_stale(&self, before: i64, now: i64) -> Result<Vec<OutboxMessage>> {
+        let undelivered = self.undelivered_key();
+        let max = format!("({}", before);
+        let receipts = self.client.cmd(&["ZRANGEBYSCORE", &undelivered, "-inf", &max])?.into_strings();
+
+        let mut msgs = vec![];
+        let (before, attempted_at) = (before.to_string(), now.to_string());
+        for receipt in receipts {
+            let taken = self.client.cmd(&["EVAL", TAKE_SCRIPT, "1", &undelivered, &receipt, &before, &attempted_at])?;
+            if taken.as_int() != 1 {
+                continue;
+            }
+
+            let data = self.client.cmd(&["GET", &self.message_key(&receipt)])?.into_string();
+            let mut msg: OutboxMessage = match data {
+                Some(data) => serde_json::from_str(&data)?,
+                None => {
+                    // expired
+                    self.client.cmd(&["ZREM", &undelivered, &receipt])?;
+                    continue;
+                }
+            };
+            if msg.attempts >= MAX_ATTEMPTS {
+                self.client.cmd(&["ZREM", &undelivered, &receipt])?;
+                continue;
+            }
+            msg.attempts += 1;
+            msg.attempted_at = now;
+            self.save(&msg, false)?;
+            msgs.push(msg);
+        }
+        Ok(msgs)
+    }
+
+    fn purge(&self, before: i64) -> Result<usize> {
+        // the messages expire by themselves
+        let max = format!("({}", before);
+        let purged = self.client.cmd(&["ZREMRANGEBYSCORE", &self.undelivered_key(), "-inf", &max])?.as_int();
+        Ok(purged as usize)
+    }
+}
+
+// Records each message before it is sent, and drops the ones that were recorded before, e.g. when a delivery that
+// was handled before a crash is handled again
+pub struct Outbox {
+    store: Arc<dyn ReceiptStore>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+impl Outbox {
+    pub fn wrap(
+        store: Arc<dyn ReceiptStore>,
+        slack: Arc<dyn worker::Worker<SlackRequest>>,
+    ) -> Arc<dyn worker::Worker<SlackRequest>> {
+        Arc::new(Outbox {
+            store: store,
+            slack: slack,
+        })
+    }
+}
+
+impl worker::Worker<SlackRequest> for Outbox {
+    fn send(&self, req: SlackRequest) {
+        // redeliveries were recorded already
+        if !req.receipts.is_empty() {
+            return self.slack.send(req);
+        }
+
+        let receipt = receipt(&error_reports::current_context(), &req);
+        match self.store.record(&OutboxMessage::new(&receipt, req.clone(), db::now())) {
+            Ok(true) => (),
+            Ok(false) => {
+                info!("Skipping message to {} that was already sent ({})", req.channel, receipt);
+                return;
+            }
+            // better to risk sending it twice than not at all
+            Err(e) => error!("Error recording message {}: {}", receipt, e),
+        };
+
+        let mut req = req;
+        req.receipts = vec![receipt];
+        self.slack.send(req);
+    }
+}
+
+// Marks the messages a request delivers once |notifier| has sent it
+struct Runner {
+    store: Arc<dyn ReceiptStore>,
+    notifier: Arc<dyn worker::Runner<SlackRequest>>,
+}
+
+pub fn new_runner(
+    store: Arc<dyn ReceiptStore>,
+    notifier: Arc<dyn worker::Runner<SlackRequest>>,
+) -> Arc<dyn worker::Runner<SlackRequest>> {
+    Arc::new(Runner {
+        store: store,
+        notifier: notifier,
+    })
+}
+
+impl worker::Runner<SlackRequest> for Runner {
+    fn handle(&self, req: SlackRequest) {
+        let receipts = req.receipts.clone();
+        self.notifier.handle(req);
+
+        for receipt in receipts {
+            if let Err(e) = self.store.delivered(&receipt, db::now()) {
+                error!("Error marking message {} delivered: {}", receipt, e);
+            }
+        }
+    }
+
+    fn repo(&self, req: &SlackRequest) -> Option<String> {
+        self.notifier.repo(req)
+    }
+
+    fn priority(&self, req: &SlackRequest) -> worker::Priority {
+        self.notifier.priority(req)
+    }
+}
+
+// Sends the messages that were recorded but never delivered again, and forgets old receipts
+pub struct Redeliverer {
+    store: Arc<dyn ReceiptStore>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+    retention_secs: u64,
+}
+
+impl Redeliverer {
+    pub fn new(
+        config: &Config,
+        store: Arc<dyn ReceiptStore>,
+        slack: Arc<dyn worker::Worker<SlackRequest>>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(Redeliverer {
+            store: store,
+            slack: slack,
+            retention_secs: config.outbox_retention_secs(),
+        })
+    }
+}
+
+impl scheduler::Task for Redeliverer {
+    fn run(&self, now: i64) -> Result<()> {
+        for msg in self.store.take_stale(now - REDELIVER_AFTER_SECS, now)? {
+            info!(
+                "Sending undelivered message {} to {} again (attempt {})",
+                msg.receipt, msg.request.channel, msg.attempts
+            );
+            self.slack.send(msg.redelivery());
+        }
+
+        let purged = self.store.purge(now - self.retention_secs as i64)?;
+        if purged > 0 {
+            info!("Purged {} message receipt(s) from the outbox", purged);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::Mutex;
+    use tempdir::TempDir;
+
+    use crate::slack;
+
+    fn new_test() -> (DbReceiptStore, TempDir) {
+        let temp_dir = TempDir::new("outbox.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (DbReceiptStore::new(db), temp_dir)
+    }
+
+    fn delivery(id: &str) -> error_reports::Context {
+        error_reports::Context {
+            delivery_id: id.into(),
+            ..error_reports::Context::default()
+        }
+    }
+
+    struct TestWorker {
+        tx: Mutex<Sender<SlackRequest>>,
+    }
+
+    impl worker::Worker<SlackRequest> for TestWorker {
+        fn send(&self, req: SlackRequest) {
+            self.tx.lock().unwrap().send(req).unwrap();
+        }
+    }
+
+    struct TestNotifier;
+
+    impl worker::Runner<SlackRequest> for TestNotifier {
+        fn handle(&self, _req: SlackRequest) {}
+    }
+
+    #[test]
+    fn test_receipt() {
+        let req = slack::req("the-channel", "hello", vec![]);
+        let first = receipt(&delivery("delivery-1"), &req);
+        assert!(first.starts_with("delivery-1-"));
+        assert_eq!(first, receipt(&delivery("delivery-1"), &req));
+        assert!(first != receipt(&delivery("delivery-2"), &req));
+        assert!(first != receipt(&delivery("delivery-1"), &slack::req("other-channel", "hello", vec![])));
+
+        let context = error_reports::Context::default();
+        assert!(receipt(&context, &req) != receipt(&context, &req));
+    }
+
+    #[test]
+    fn test_record_once() {
+        let (store, _temp) = new_test();
+        let msg = OutboxMessage::new("delivery-1-abc", slack::req("the-channel", "hello", vec![]), 100);
+
+        assert!(store.record(&msg).unwrap());
+        assert!(!store.record(&msg).unwrap());
+        // still not sent again once it's delivered
+        store.delivered("delivery-1-abc", 110).unwrap();
+        assert!(!store.record(&msg).unwrap());
+    }
+
+    #[test]
+    fn test_record_with() {
+        let (store, _temp) = new_test();
+        let msg = OutboxMessage::new("delivery-1-abc", slack::req("the-channel", "hello", vec![]), 100);
+
+        assert!(store.record_with(&msg, |_conn| Err(format_err!("nope"))).is_err());
+        // the failed change took the message with it
+        assert!(store.record_with(&msg, |_conn| Ok(())).unwrap());
+        assert!(!store.record_with(&msg, |_conn| panic!("changed twice")).unwrap());
+    }
+
+    #[test]
+    fn test_take_stale() {
+        let (store, _temp) = new_test();
+        let req = slack::req("the-channel", "hello", vec![]);
+        store.record(&OutboxMessage::new("lost", req.clone(), 100)).unwrap();
+        store.record(&OutboxMessage::new("sent", req.clone(), 100)).unwrap();
+        store.record(&OutboxMessage::new("recent", req.clone(), 500)).unwrap();
+        store.delivered("sent", 105).unwrap();
+
+        let taken = store.take_stale(400, 600).unwrap();
+        assert_eq!(vec![OutboxMessage { attempts: 2, attempted_at: 600, ..OutboxMessage::new("lost", req, 0) }], taken);
+        // it was just attempted
+        assert!(store.take_stale(400, 700).unwrap().is_empty());
+
+        assert_eq!(2, store.take_stale(650, 800).unwrap().len());
+        // given up on after MAX_ATTEMPTS
+        let taken = store.take_stale(900, 1000).unwrap();
+        assert_eq!(vec!["recent"], taken.iter().map(|m| m.receipt.as_str()).collect::<Vec<_>>());
+
+        assert_eq!(2, store.purge(400).unwrap());
+        assert!(store.record(&OutboxMessage::new("lost", slack::req("the-channel", "hi", vec![]), 2000)).unwrap());
+    }
+
+    #[test]
+    fn test_outbox() {
+        let (store, _temp) = new_test();
+        let store: Arc<dyn ReceiptStore> = Arc::new(store);
+        let (tx, rx) = channel();
+        let outbox = Outbox::wrap(store.clone(), Arc::new(TestWorker { tx: Mutex::new(tx) }));
+        let runner = new_runner(store.clone(), Arc::new(TestNotifier));
+
+        let req = slack::req("the-channel", "hello", vec![]);
+        for _ in 0..2 {
+            error_reports::with_context(delivery("delivery-1"), || outbox.send(req.clone()));
+        }
+        error_reports::with_context(delivery("delivery-2"), || outbox.send(req.clone()));
+
+        let sent = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(2, sent.len());
+        assert_eq!(vec![receipt(&delivery("delivery-1"), &req)], sent[0].receipts);
+
+        runner.handle(sent[0].clone());
+        let stale = store.take_stale(db::now() + 1000, db::now()).unwrap();
+        assert_eq!(sent[1].receipts, stale.iter().map(|m| m.receipt.clone()).collect::<Vec<_>>());
+
+        // redeliveries go straight through
+        outbox.send(stale[0].clone().redelivery());
+        assert_eq!(sent[1].receipts, rx.try_recv().unwrap().receipts);
+    }
+}
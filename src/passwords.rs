@@ -0,0 +1,488 @@
+use crypto::blake2b::Blake2b;
+use crypto::digest::Digest;
+use failure::format_err;
+use log::error;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{constant_time, digest, pbkdf2};
+use rustc_serialize::hex::FromHex;
+
+use crate::config::PasswordsConfig;
+use crate::errors::*;
+
+// Argon2id (RFC 9106), stored with its costs and salt in the usual "$argon2id$v=19$m=..,t=..,p=..$salt$hash" form,
+// so the costs can be raised without invalidating existing hashes
+
+pub const DEFAULT_MEMORY_KIB: u32 = 19 * 1024;
+pub const DEFAULT_ITERATIONS: u32 = 2;
+pub const DEFAULT_PARALLELISM: u32 = 1;
+
+const MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+const MAX_PARALLELISM: u32 = 64;
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+const PREFIX: &str = "$argon2id$";
+const VERSION: u32 = 0x13;
+const ARGON2ID: u32 = 2;
+const SYNC_POINTS: u32 = 4;
+const BLOCK_WORDS: usize = 128;
+const BLOCK_BYTES: usize = BLOCK_WORDS * 8;
+
+type Block = [u64; BLOCK_WORDS];
+
+// The cost of a hash: memory in KiB, passes over it, and lanes (which are filled one after another here)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Params {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Params {
+    pub fn new(config: &Option<PasswordsConfig>) -> Params {
+        let config = config.clone().unwrap_or_default();
+        Params {
+            memory_kib: config.memory_kib.unwrap_or(DEFAULT_MEMORY_KIB),
+            iterations: config.iterations.unwrap_or(DEFAULT_ITERATIONS),
+            parallelism: config.parallelism.unwrap_or(DEFAULT_PARALLELISM),
+        }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.parallelism == 0 || self.parallelism > MAX_PARALLELISM {
+            return Err(format_err!("parallelism must be from 1 to {}", MAX_PARALLELISM));
+        }
+        if self.iterations == 0 {
+            return Err(format_err!("iterations must be at least 1"));
+        }
+        if self.memory_kib < 8 * self.parallelism || self.memory_kib > MAX_MEMORY_KIB {
+            return Err(format_err!("memory_kib must be from 8 x parallelism to {}", MAX_MEMORY_KIB));
+        }
+        Ok(())
+    }
+}
+
+// A new hash of the password, with a random salt
+pub fn hash(pass: &str, params: &Params) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new().fill(&mut salt).expect("get random");
+    let hash = argon2id(params, pass.as_bytes(), &salt, &[], &[], HASH_LEN);
+
+    format!(
+        "{}v={}$m={},t={},p={}${}${}",
+        PREFIX,
+        VERSION,
+        params.memory_kib,
+        params.iterations,
+        params.parallelism,
+        base64::encode_config(&salt, base64::STANDARD_NO_PAD),
+        base64::encode_config(&hash, base64::STANDARD_NO_PAD)
+    )
+}
+
+// Checks a password against an argon2id hash, or a hex PBKDF2 one with its separate salt
+pub fn verify(pass: &str, salt: Option<&str>, pass_hash: &str) -> bool {
+    if !pass_hash.starts_with(PREFIX) {
+        return match salt {
+            Some(salt) => verify_pbkdf2(pass, salt, pass_hash),
+            None => {
+                error!("Invalid password hash stored: a PBKDF2 hash needs its salt");
+                false
+            }
+        };
+    }
+
+    let (params, salt, expected) = match parse(pass_hash) {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Invalid password hash stored: {} -- {}", pass_hash, e);
+            return false;
+        }
+    };
+    let hash = argon2id(&params, pass.as_bytes(), &salt, &[], &[], expected.len());
+    constant_time::verify_slices_are_equal(&hash, &expected).is_ok()
+}
+
+// Whether a hash should be replaced, as it is a PBKDF2 one or its costs aren't the configured ones
+pub fn needs_rehash(pass_hash: &str, params: &Params) -> bool {
+    match parse(pass_hash) {
+        Ok((current, _, _)) => current != *params,
+        Err(_) => true,
+    }
+}
+
+fn parse(pass_hash: &str) -> Result<(Params, Vec<u8>, Vec<u8>)> {
+    if !pass_hash.starts_with(PREFIX) {
+        return Err(format_err!("Not an argon2id hash"));
+    }
+    let parts = pass_hash[PREFIX.len()..].split('$').collect::<Vec<_>>();
+    if parts.len() != 4 || parts[0] != format!("v={}", VERSION) {
+        return Err(format_err!("Expected v={}$<costs>$<salt>$<hash>", VERSION));
+    }
+
+    let mut costs = (None, None, None);
+    for cost in parts[1].split(',') {
+        let mut kv = cost.splitn(2, '=');
+        let (key, value) = (kv.next(), kv.next().and_then(|v| v.parse::<u32>().ok()));
+        match key {
+            Some("m") => costs.0 = value,
+            Some("t") => costs.1 = value,
+            Some("p") => costs.2 = value,
+            _ => return Err(format_err!("Unknown cost: {}", cost)),
+        };
+    }
+    let params = match costs {
+        (Some(m), Some(t), Some(p)) => Params {
+            memory_kib: m,
+            iterations: t,
+            parallelism: p,
+        },
+        _ => return Err(format_err!("Expected m=<memory>,t=<iterations>,p=<parallelism>")),
+    };
+    params.check()?;
+
+    let decode = |v: &str| base64::decode_config(v.trim_end_matches('='), base64::STANDARD_NO_PAD);
+    let salt = decode(parts[2]).map_err(|e| format_err!("Invalid salt: {}", e))?;
+    let hash = decode(parts[3]).map_err(|e| format_err!("Invalid hash: {}", e))?;
+    if salt.len() < 8 || hash.len() < 4 {
+        return Err(format_err!("The salt or hash is too short"));
+    }
+    Ok((params, salt, hash))
+}
+
+// Hashes made before argon2id: PBKDF2-SHA256 in hex, with the salt stored separately
+fn verify_pbkdf2(pass: &str, salt: &str, pass_hash: &str) -> bool {
+    let pass_hash = match pass_hash.from_hex() {
+        Ok(h) => h,
+        Err(e) => {
+            error!("Invalid password hash stored: {} -- {}", pass_hash, e);
+            return false;
+        }
+    };
+    pbkdf2::verify(
+        &digest::SHA256,
+        std::num::NonZeroU32::new(100_000).unwrap(),
+        salt.as_bytes(),
+        pass.as_bytes(),
+        &pass_hash,
+    )
+    .is_ok()
+}
+
+fn argon2id(params: &Params, pass: &[u8], salt: &[u8], secret: &[u8], data: &[u8], hash_len: usize) -> Vec<u8> {
+    let lanes = params.parallelism as usize;
+    let segment_len = (params.memory_kib / (SYNC_POINTS * params.parallelism)) as usize;
+    let lane_len = segment_len * SYNC_POINTS as usize;
+
+    let mut h0 = [0u8; 64];
+    let mut hasher = Blake2b::new(64);
+    for n in &[params.parallelism, hash_len as u32, params.memory_kib, params.iterations, VERSION, ARGON2ID] {
+        hasher.input(&n.to_le_bytes());
+    }
+    for input in &[pass, salt, secret, data] {
+        hasher.input(&(input.len() as u32).to_le_bytes());
+        hasher.input(input);
+    }
+    hasher.result(&mut h0);
+
+    let mut memory = vec![[0u64; BLOCK_WORDS]; lane_len * lanes];
+    let mut bytes = [0u8; BLOCK_BYTES];
+    for lane in 0..lanes {
+        for column in 0..2 {
+            hash_long(&mut bytes, &[&h0, &(column as u32).to_le_bytes(), &(lane as u32).to_le_bytes()]);
+            memory[lane * lane_len + column] = block_from_bytes(&bytes);
+        }
+    }
+
+    for pass in 0..params.iterations {
+        for slice in 0..SYNC_POINTS {
+            for lane in 0..lanes {
+                fill_segment(&mut memory, params, pass, slice, lane);
+            }
+        }
+    }
+
+    let mut last = memory[lane_len - 1];
+    for lane in 1..lanes {
+        xor(&mut last, &memory[lane * lane_len + lane_len - 1]);
+    }
+    for (chunk, word) in bytes.chunks_mut(8).zip(last.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    let mut hash = vec![0u8; hash_len];
+    hash_long(&mut hash, &[&bytes]);
+    hash
+}
+
+fn fill_segment(memory: &mut [Block], params: &Params, pass: u32, slice: u32, lane: usize) {
+    let lanes = params.parallelism as usize;
+    let lane_len = memory.len() / lanes;
+    let segment_len = lane_len / SYNC_POINTS as usize;
+
+    // the first half of the first pass picks blocks independently of the password, against side channels
+    let independent = pass == 0 && slice < SYNC_POINTS / 2;
+    let zero = [0u64; BLOCK_WORDS];
+    let mut input = [0u64; BLOCK_WORDS];
+    let mut addresses = [0u64; BLOCK_WORDS];
+    input[0] = pass as u64;
+    input[1] = lane as u64;
+    input[2] = slice as u64;
+    input[3] = memory.len() as u64;
+    input[4] = params.iterations as u64;
+    input[5] = ARGON2ID as u64;
+
+    // the first two blocks of each lane were filled from the password
+    let start = if pass == 0 && slice == 0 { 2 } else { 0 };
+    for index in start..segment_len {
+        if independent && (index == start || index % BLOCK_WORDS == 0) {
+            input[6] += 1;
+            let mut next = [0u64; BLOCK_WORDS];
+            compress(&zero, &input, &mut next, false);
+            compress(&zero, &next, &mut addresses, false);
+        }
+
+        let offset = lane * lane_len + slice as usize * segment_len + index;
+        let prev = if offset % lane_len == 0 { offset + lane_len - 1 } else { offset - 1 };
+        let rand = if independent { addresses[index % BLOCK_WORDS] } else { memory[prev][0] };
+
+        let ref_lane = if pass == 0 && slice == 0 { lane } else { ((rand >> 32) % lanes as u64) as usize };
+        let position = Position {
+            pass: pass,
+            slice: slice,
+            index: index,
+            same_lane: ref_lane == lane,
+        };
+        let ref_index = reference_index(&position, rand & 0xffff_ffff, lane_len, segment_len);
+
+        let previous = memory[prev];
+        let reference = memory[ref_lane * lane_len + ref_index];
+        compress(&previous, &reference, &mut memory[offset], pass > 0);
+    }
+}
+
+struct Position {
+    pass: u32,
+    slice: u32,
+    index: usize,
+    same_lane: bool,
+}
+
+// The column of the block to mix in, from the blocks that are done and not in another lane's current segment
+fn reference_index(position: &Position, rand: u64, lane_len: usize, segment_len: usize) -> usize {
+    let finished = if position.pass == 0 { position.slice as usize * segment_len } else { lane_len - segment_len };
+    let area = if position.pass == 0 && position.slice == 0 {
+        position.index - 1
+    } else if position.same_lane {
+        finished + position.index - 1
+    } else if position.index == 0 {
+        finished - 1
+    } else {
+        finished
+    };
+    let area = area as u64;
+
+    let x = (rand * rand) >> 32;
+    let relative = area - 1 - ((area * x) >> 32);
+    let start = if position.pass == 0 || position.slice == SYNC_POINTS - 1 {
+        0
+    } else {
+        (position.slice as usize + 1) * segment_len
+    };
+    ((start as u64 + relative) % lane_len as u64) as usize
+}
+
+// H' of the spec: blake2b with outputs of any length
+fn hash_long(out: &mut [u8], inputs: &[&[u8]]) {
+    let len = (out.len() as u32).to_le_bytes();
+    let mut hasher = Blake2b::new(out.len().min(64));
+    hasher.input(&len);
+    for input in inputs {
+        hasher.input(input);
+    }
+    if out.len() <= 64 {
+        hasher.result(out);
+        return;
+    }
+
+    let mut v = [0u8; 64];
+    hasher.result(&mut v);
+    out[..32].copy_from_slice(&v[..32]);
+    let mut pos = 32;
+    while out.len() - pos > 64 {
+        let mut hasher = Blake2b::new(64);
+        hasher.input(&v);
+        hasher.result(&mut v);
+        out[pos..pos + 32].copy_from_slice(&v[..32]);
+        pos += 32;
+    }
+    let mut hasher = Blake2b::new(out.len() - pos);
+    hasher.input(&v);
+    hasher.result(&mut out[pos..]);
+}
+
+fn block_from_bytes(bytes: &[u8]) -> Block {
+    let mut block = [0u64; BLOCK_WORDS];
+    for (word, chunk) in block.iter_mut().zip(bytes.chunks(8)) {
+        let mut le = [0u8; 8];
+        le.copy_from_slice(chunk);
+        *word = u64::from_le_bytes(le);
+    }
+    block
+}
+
+fn xor(block: &mut Block, other: &Block) {
+    for (a, b) in block.iter_mut().zip(other.iter()) {
+        *a ^= b;
+    }
+}
+
+// G of the spec: mixes two blocks into `next`, xor'ed with what was there on later passes
+fn compress(prev: &Block, reference: &Block, next: &mut Block, with_xor: bool) {
+    let mut r = *prev;
+    xor(&mut r, reference);
+    let mut result = r;
+    if with_xor {
+        xor(&mut result, next);
+    }
+
+    let mut indexes = [0usize; 16];
+    for row in 0..8 {
+        for (k, i) in indexes.iter_mut().enumerate() {
+            *i = row * 16 + k;
+        }
+        permute(&mut r, &indexes);
+    }
+    for column in 0..8 {
+        for (k, i) in indexes.iter_mut().enumerate() {
+            *i = (k / 2) * 16 + column * 2 + k % 2;
+        }
+        permute(&mut r, &indexes);
+    }
+
+    xor(&mut result, &r);
+    *next = result;
+}
+
+// blake2b's round, on 16 words of the block
+fn permute(v: &mut Block, i: &[usize; 16]) {
+    mix(v, i[0], i[4], i[8], i[12]);
+    mix(v, i[1], i[5], i[9], i[13]);
+    mix(v, i[2], i[6], i[10], i[14]);
+    mix(v, i[3], i[7], i[11], i[15]);
+    mix(v, i[0], i[5], i[10], i[15]);
+    mix(v, i[1], i[6], i[11], i[12]);
+    mix(v, i[2], i[7], i[8], i[13]);
+    mix(v, i[3], i[4], i[9], i[14]);
+}
+
+fn mix(v: &mut Block, a: usize, b: usize, c: usize, d: usize) {
+    v[a] = blamka(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = blamka(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = blamka(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = blamka(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+// blake2b's addition, with a multiplication of the low halves against shortcuts in hardware
+fn blamka(x: u64, y: u64) -> u64 {
+    let low = (x & 0xffff_ffff) * (y & 0xffff_ffff);
+    x.wrapping_add(y).wrapping_add(low.wrapping_mul(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_serialize::hex::ToHex;
+
+    fn cheap() -> Params {
+        Params {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn test_rfc_9106_vector() {
+        let params = Params {
+            memory_kib: 32,
+            iterations: 3,
+            parallelism: 4,
+        };
+        let hash = argon2id(&params, &[1; 32], &[2; 16], &[3; 8], &[4; 12], 32);
+        assert_eq!("0d640df58d78766c08c037a34a8b53c9d01ef0452d75b65eb52520e96b01e659", hash.to_hex());
+    }
+
+    #[test]
+    fn test_hash_and_verify() {
+        let pass_hash = hash("the-pass", &cheap());
+        assert!(pass_hash.starts_with("$argon2id$v=19$m=64,t=1,p=1$"));
+        assert_eq!(true, verify("the-pass", None, &pass_hash));
+        assert_eq!(false, verify("wrong-pass", None, &pass_hash));
+        // the salt is random
+        assert!(pass_hash != hash("the-pass", &cheap()));
+    }
+
+    #[test]
+    fn test_verify_pbkdf2() {
+        let mut pass_hash = [0u8; 32];
+        let iterations = std::num::NonZeroU32::new(100_000).unwrap();
+        pbkdf2::derive(&digest::SHA256, iterations, b"some-salt", b"the-pass", &mut pass_hash);
+        let pass_hash = pass_hash.to_hex();
+
+        assert_eq!(true, verify("the-pass", Some("some-salt"), &pass_hash));
+        assert_eq!(false, verify("wrong-pass", Some("some-salt"), &pass_hash));
+        assert_eq!(false, verify("the-pass", Some("wrong-salt"), &pass_hash));
+        assert_eq!(false, verify("the-pass", None, &pass_hash));
+    }
+
+    #[test]
+    fn test_needs_rehash() {
+        let pass_hash = hash("the-pass", &cheap());
+        assert_eq!(false, needs_rehash(&pass_hash, &cheap()));
+        assert_eq!(true, needs_rehash(&pass_hash, &Params { iterations: 2, ..cheap() }));
+        assert_eq!(true, needs_rehash("abcdef0123", &cheap()));
+    }
+
+    #[test]
+    fn test_parse() {
+        let (params, salt, hash) = parse("$argon2id$v=19$m=64,t=1,p=1$c29tZXNhbHQ$aGFzaGhhc2g").unwrap();
+        assert_eq!(cheap(), params);
+        assert_eq!(b"somesalt".to_vec(), salt);
+        assert_eq!(b"hashhash".to_vec(), hash);
+
+        assert!(parse("$argon2i$v=19$m=64,t=1,p=1$c29tZXNhbHQ$aGFzaGhhc2g").is_err());
+        assert!(parse("$argon2id$v=16$m=64,t=1,p=1$c29tZXNhbHQ$aGFzaGhhc2g").is_err());
+        assert!(parse("$argon2id$v=19$m=64,t=1$c29tZXNhbHQ$aGFzaGhhc2g").is_err());
+        assert!(parse("$argon2id$v=19$m=4,t=1,p=1$c29tZXNhbHQ$aGFzaGhhc2g").is_err());
+        assert!(parse("$argon2id$v=19$m=64,t=1,p=1$not base64$aGFzaGhhc2g").is_err());
+    }
+
+    #[test]
+    fn test_params() {
+        assert_eq!(
+            Params {
+                memory_kib: 19456,
+                iterations: 2,
+                parallelism: 1
+            },
+            Params::new(&None)
+        );
+        let config = PasswordsConfig {
+            memory_kib: Some(65536),
+            iterations: None,
+            parallelism: Some(4),
+        };
+        assert_eq!(65536, Params::new(&Some(config.clone())).memory_kib);
+        assert_eq!(4, Params::new(&Some(config)).parallelism);
+
+        assert!(cheap().check().is_ok());
+        assert!(Params { parallelism: 0, ..cheap() }.check().is_err());
+        assert!(Params { iterations: 0, ..cheap() }.check().is_err());
+        assert!(Params { memory_kib: 8, parallelism: 2, ..cheap() }.check().is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use failure::format_err;
+use log::error;
+use regex::Regex;
+
+use crate::errors::*;
+use crate::github;
+use crate::github::api::Session;
+use crate::repos::RepoPathLabel;
+use crate::util;
+
+pub fn path_regex(glob: &str) -> Result<Regex> {
+    // a trailing slash means everything in that directory
+    let glob = if glob.ends_with("/") {
+        format!("{}**", glob)
+    } else {
+        glob.to_string()
+    };
+    let glob = glob.trim_start_matches('/');
+
+    Regex::new(&format!("^{}$", util::glob_to_regex(glob)))
+        .map_err(|e| format_err!("Invalid path glob '{}': {}", glob, e))
+}
+
+// Returns the (sorted, unique) labels whose globs match any of the given paths.
+pub fn matching_labels(rules: &Vec<RepoPathLabel>, paths: &Vec<String>) -> Vec<String> {
+    let mut labels = vec![];
+    for rule in rules {
+        let regex = match path_regex(&rule.path) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("{}", e);
+                continue;
+            }
+        };
+        if paths.iter().any(|p| regex.is_match(p)) {
+            labels.push(rule.label.clone());
+        }
+    }
+
+    labels.sort();
+    labels.dedup();
+    labels
+}
+
+// Adds labels for matching rules and removes rule-managed labels that no longer match.
+// Labels not mentioned by any rule are left alone.
+pub fn apply_path_labels(
+    github: &dyn Session,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    rules: &Vec<RepoPathLabel>,
+) -> Result<()> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    let owner = repo.owner.login();
+    let files = github.get_pull_request_files(owner, &repo.name, pull_request.number)?;
+    let paths = files.into_iter().map(|f| f.filename).collect::<Vec<_>>();
+
+    let wanted = matching_labels(rules, &paths);
+    let current = github
+        .get_pull_request_labels(owner, &repo.name, pull_request.number)?
+        .into_iter()
+        .map(|l| l.name)
+        .collect::<Vec<_>>();
+
+    let to_add = wanted
+        .iter()
+        .filter(|l| !current.contains(l))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !to_add.is_empty() {
+        github.add_pull_request_labels(owner, &repo.name, pull_request.number, to_add)?;
+    }
+
+    for label in &current {
+        let managed = rules.iter().any(|r| &r.label == label);
+        if managed && !wanted.contains(label) {
+            github.remove_pull_request_label(owner, &repo.name, pull_request.number, label)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: Vec<&str>) -> Vec<String> {
+        paths.into_iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_matching_labels() {
+        let rules = vec![
+            RepoPathLabel::new("docs/", "documentation"),
+            RepoPathLabel::new("**/*.sql", "database"),
+            RepoPathLabel::new("/ui/**", "frontend"),
+            RepoPathLabel::new("*.md", "documentation"),
+        ];
+
+        assert_eq!(
+            vec!["documentation"],
+            matching_labels(&rules, &paths(vec!["docs/guide/intro.txt"]))
+        );
+        assert_eq!(
+            vec!["database", "frontend"],
+            matching_labels(&rules, &paths(vec!["ui/app.js", "server/schema/users.sql"]))
+        );
+        assert_eq!(
+            vec!["documentation"],
+            matching_labels(&rules, &paths(vec!["README.md"]))
+        );
+        // "*" doesn't cross directories
+        assert_eq!(
+            Vec::<String>::new(),
+            matching_labels(&rules, &paths(vec!["server/README.md", "src/docs/other.txt"]))
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::messenger::{self, Messenger};
+use crate::repos::RepoInfo;
+use crate::scheduler;
+use crate::slack::{SlackAttachmentBuilder, SlackRequest};
+use crate::users;
+use crate::util;
+use crate::worker::Worker;
+
+pub const CHECK_INTERVAL_SECS: u64 = 15 * 60;
+
+// Remembers which PRs are known to conflict so that authors are only told once.
+#[derive(Clone)]
+pub struct ConflictTracker {
+    db: Database,
+}
+
+impl ConflictTracker {
+    pub fn new(db: Database) -> ConflictTracker {
+        ConflictTracker { db: db }
+    }
+
+    pub fn is_conflicted(&self, repo: &str, number: u32) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare("SELECT number FROM pr_conflicts WHERE repo = :repo AND number = :number")?;
+        let mut rows = stmt.query_named(&[(":repo", &repo), (":number", &number)])?;
+
+        Ok(rows.next()?.is_some())
+    }
+
+    pub fn mark_conflicted(&self, repo: &str, number: u32, base_sha: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO pr_conflicts (repo, number, base_sha, notified_at)
+               VALUES (?1, ?2, ?3, ?4)
+               ON CONFLICT (repo, number) DO UPDATE SET base_sha = excluded.base_sha,
+                   notified_at = excluded.notified_at"#,
+            &[&repo as &dyn ToSql, &number, &base_sha, &db::now()],
+        )
+        .map_err(|e| format_err!("Error marking PR {}#{} as conflicted: {}", repo, number, e))?;
+
+        Ok(())
+    }
+
+    pub fn clear(&self, repo: &str, number: u32) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "DELETE FROM pr_conflicts WHERE repo = ?1 AND number = ?2",
+            &[&repo as &dyn ToSql, &number],
+        )
+        .map_err(|e| format_err!("Error clearing conflict for PR {}#{}: {}", repo, number, e))?;
+
+        Ok(())
+    }
+
+    // Forget about PRs that are no longer open
+    pub fn retain_open(&self, repo: &str, open: &Vec<u32>) -> Result<()> {
+        let conn = self.db.connect()?;
+        let mut closed = vec![];
+        {
+            let mut stmt = conn.prepare("SELECT number FROM pr_conflicts WHERE repo = :repo")?;
+            let mut rows = stmt.query_named(&[(":repo", &repo)])?;
+            while let Ok(Some(row)) = rows.next() {
+                let number: u32 = row.get(0)?;
+                if !open.contains(&number) {
+                    closed.push(number);
+                }
+            }
+        }
+
+        for number in closed {
+            conn.execute(
+                "DELETE FROM pr_conflicts WHERE repo = ?1 AND number = ?2",
+                &[&repo as &dyn ToSql, &number],
+            )
+            .map_err(|e| format_err!("Error clearing conflict for PR {}#{}: {}", repo, number, e))?;
+        }
+
+        Ok(())
+    }
+}
+
+// Checks the repo's open PRs and tells authors about PRs that have newly become unmergeable.
+// Returns the numbers of the PRs whose authors were notified.
+pub fn check_conflicts(
+    github: &dyn Session,
+    messenger: &Messenger,
+    tracker: &ConflictTracker,
+    repo: &github::Repo,
+) -> Result<Vec<u32>> {
+    let owner = repo.owner.login();
+    let pull_requests = github.get_pull_requests(owner, &repo.name, Some("open"), None)?;
+    let open = pull_requests.iter().map(|pr| pr.number).collect::<Vec<_>>();
+    tracker.retain_open(&repo.full_name, &open)?;
+
+    let mut notified = vec![];
+    for pull_request in pull_requests.iter().filter(|pr| !pr.is_draft()) {
+        // mergeability is only included when fetching PRs one at a time
+        let pull_request = github.get_pull_request(owner, &repo.name, pull_request.number)?;
+
+        match pull_request.mergeable {
+            Some(false) => {
+                if tracker.is_conflicted(&repo.full_name, pull_request.number)? {
+                    continue;
+                }
+                notify_author(messenger, repo, &pull_request);
+                tracker.mark_conflicted(&repo.full_name, pull_request.number, &pull_request.base.sha)?;
+                notified.push(pull_request.number);
+            }
+            Some(true) => tracker.clear(&repo.full_name, pull_request.number)?,
+            // github hasn't figured it out yet: check again next time.
+            None => (),
+        };
+    }
+
+    Ok(notified)
+}
+
+fn notify_author(messenger: &Messenger, repo: &github::Repo, pull_request: &github::PullRequest) {
+    let base_commit = util::make_link(
+        &format!("{}/commit/{}", repo.html_url, pull_request.base.sha),
+        github::Commit::short_hash_str(&pull_request.base.sha),
+    );
+
+    let msg = format!("Pull Request has merge conflicts with {}", pull_request.base.ref_name);
+    let attachments = vec![
+        SlackAttachmentBuilder::new("")
+            .title(format!("Pull Request #{}: \"{}\"", pull_request.number, pull_request.title))
+            .title_link(pull_request.html_url.as_str())
+            .build(),
+        SlackAttachmentBuilder::new(&format!("Conflicting base commit: {}", base_commit)).build(),
+    ];
+
+    messenger.send_to_user(&pull_request.user, &msg, &attachments);
+}
+
+pub struct ConflictNotifier {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    messenger: Messenger,
+}
+
+impl ConflictNotifier {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        slack: Arc<dyn Worker<SlackRequest>>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(ConflictNotifier {
+            config: config.clone(),
+            github_app: github_app,
+            messenger: messenger::new(config, slack).for_dm_event(users::DM_CONFLICT),
+        })
+    }
+
+    fn check_repo(&self, info: &RepoInfo) -> Result<()> {
+        // checks need a specific repo: org-wide entries don't say which repos to scan.
+        if !info.repo.contains('/') {
+            info!("Skipping conflict checks for org-level config: {}", info.repo);
+            return Ok(());
+        }
+
+        let repo = github::Repo::parse(&format!("https://{}/{}", self.config.github_host(&info.repo), info.repo))?;
+        let github = self.github_app.new_session(&repo.owner.login(), &repo.name)?;
+
+        check_conflicts(&github, &self.messenger, &self.config.conflicts, &repo)?;
+        Ok(())
+    }
+}
+
+impl scheduler::Task for ConflictNotifier {
+    fn run(&self, _now: i64) -> Result<()> {
+        let repos = self.config.repos().get_all()?;
+
+        for info in repos.iter().filter(|r| r.conflict_notify) {
+            if let Err(e) = self.check_repo(info) {
+                error!("Error checking PR conflicts for {}: {}", info.repo, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (ConflictTracker, TempDir) {
+        let temp_dir = TempDir::new("pr_conflicts.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (ConflictTracker::new(db), temp_dir)
+    }
+
+    #[test]
+    fn test_conflict_tracker() {
+        let (tracker, _temp) = new_test();
+
+        assert_eq!(false, tracker.is_conflicted("some-user/some-repo", 1).unwrap());
+
+        tracker.mark_conflicted("some-user/some-repo", 1, "abcdef").unwrap();
+        tracker.mark_conflicted("some-user/some-repo", 2, "abcdef").unwrap();
+        assert_eq!(true, tracker.is_conflicted("some-user/some-repo", 1).unwrap());
+        assert_eq!(false, tracker.is_conflicted("some-user/other-repo", 1).unwrap());
+
+        tracker.clear("some-user/some-repo", 1).unwrap();
+        assert_eq!(false, tracker.is_conflicted("some-user/some-repo", 1).unwrap());
+
+        tracker.retain_open("some-user/some-repo", &vec![1, 3]).unwrap();
+        assert_eq!(false, tracker.is_conflicted("some-user/some-repo", 2).unwrap());
+    }
+}
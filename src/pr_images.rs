@@ -0,0 +1,246 @@
+use std::io::Read;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use failure::format_err;
+use log::{error, info};
+use regex::Regex;
+use url::Url;
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::network;
+use crate::slack::SlackWebApi;
+use crate::slack_threads;
+use crate::worker;
+
+// a PR description can be a whole gallery: only the first few are forwarded
+const MAX_IMAGES: usize = 5;
+// slack takes much bigger files, but a screenshot shouldn't be
+const MAX_IMAGE_BYTES: u64 = 10 * 1024 * 1024;
+// how long to wait for the PR's slack thread to be started, since the message starting it is sent separately
+const THREAD_WAIT_SECS: u64 = 10;
+
+#[derive(Debug, PartialEq)]
+pub struct PrImagesRequest {
+    pub repo: github::Repo,
+    pub number: u32,
+    pub title: String,
+    pub channels: Vec<String>,
+    pub urls: Vec<String>,
+}
+
+pub fn req(repo: &github::Repo, pull_request: &github::PullRequest, channels: Vec<String>) -> PrImagesRequest {
+    PrImagesRequest {
+        repo: repo.clone(),
+        number: pull_request.number,
+        title: pull_request.title.clone(),
+        channels: channels,
+        urls: image_urls(pull_request.body.as_ref().map(|b| b.as_str()).unwrap_or("")),
+    }
+}
+
+// Images in markdown (`![alt](url)`) or html (`<img src="url">`), in order
+pub fn image_urls(body: &str) -> Vec<String> {
+    let re = Regex::new(concat!(
+        r#"(?i)!\[[^\]]*\]\(\s*<?(https?://[^\s)>]+)>?(?:\s+"[^"]*")?\s*\)"#,
+        r#"|<img\s[^>]*?src\s*=\s*["'](https?://[^"']+)["']"#,
+    ))
+    .unwrap();
+
+    let mut urls: Vec<String> = vec![];
+    for c in re.captures_iter(body) {
+        if let Some(url) = c.get(1).or(c.get(2)) {
+            if !urls.iter().any(|u| u == url.as_str()) {
+                urls.push(url.as_str().to_string());
+            }
+        }
+        if urls.len() == MAX_IMAGES {
+            break;
+        }
+    }
+    urls
+}
+
+// Images uploaded to github (e.g. pasted into the description) need the github token when the repo is private.
+// It is never sent anywhere else.
+fn is_github_url(url: &str, github_host: &str) -> bool {
+    match Url::parse(url).ok().as_ref().and_then(|u| u.host_str()) {
+        Some(host) => host == github_host || host.ends_with(".githubusercontent.com"),
+        None => false,
+    }
+}
+
+fn file_name(url: &str, index: usize) -> String {
+    let name = Url::parse(url)
+        .ok()
+        .and_then(|u| u.path_segments().and_then(|s| s.last().map(|l| l.to_string())))
+        .unwrap_or_default();
+    if name.is_empty() {
+        format!("image-{}", index + 1)
+    } else {
+        name
+    }
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+) -> Arc<dyn worker::Runner<PrImagesRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+    })
+}
+
+impl Runner {
+    fn download(&self, client: &reqwest::Client, url: &str, repo: &str, github_token: Option<&str>) -> Result<Vec<u8>> {
+        let mut request = client.get(url);
+        if let Some(token) = github_token {
+            if is_github_url(url, &self.config.github_host(repo)) {
+                request = request.header(reqwest::header::AUTHORIZATION, format!("token {}", token));
+            }
+        }
+        let mut res = request.send()?;
+        if !res.status().is_success() {
+            return Err(format_err!("{} returned {}", url, res.status()));
+        }
+
+        let content_type =
+            res.headers().get(reqwest::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+        if !content_type.starts_with("image/") {
+            return Err(format_err!("{} is not an image: {}", url, content_type));
+        }
+
+        let mut data = vec![];
+        res.by_ref().take(MAX_IMAGE_BYTES + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > MAX_IMAGE_BYTES {
+            return Err(format_err!("{} is bigger than {} bytes", url, MAX_IMAGE_BYTES));
+        }
+        Ok(data)
+    }
+
+    fn thread_ts(&self, req: &PrImagesRequest, channel: &str) -> Option<String> {
+        if !self.config.repos().slack_threads(&req.repo) {
+            return None;
+        }
+        let key = slack_threads::pr_thread_key(&req.repo.full_name, req.number);
+        for _ in 0..THREAD_WAIT_SECS {
+            match self.config.slack_threads.get_ts(&key, channel) {
+                Ok(Some(ts)) => return Some(ts),
+                Ok(None) => thread::sleep(Duration::from_secs(1)),
+                Err(e) => {
+                    error!("Error looking up slack thread for {}: {}", key, e);
+                    return None;
+                }
+            };
+        }
+        None
+    }
+}
+
+impl worker::Runner<PrImagesRequest> for Runner {
+    fn handle(&self, req: PrImagesRequest) {
+        let api = match self.config.slack_bot_token() {
+            Some(token) => SlackWebApi::new(&token),
+            None => return,
+        };
+        if req.urls.is_empty() || req.channels.is_empty() {
+            return;
+        }
+
+        let github_token = match self.github_app.new_session(&req.repo.owner.login(), &req.repo.name) {
+            Ok(session) => Some(session.github_token().to_string()),
+            Err(e) => {
+                error!("Error getting github session for {}: {}", req.repo.full_name, e);
+                None
+            }
+        };
+
+        let client = network::client();
+        let title = format!("Image from PR #{}: {}", req.number, req.title);
+        let thread_ts: Vec<Option<String>> = req.channels.iter().map(|c| self.thread_ts(&req, c)).collect();
+
+        let github_token = github_token.as_ref().map(|t| t.as_str());
+        for (i, url) in req.urls.iter().enumerate() {
+            let data = match self.download(&client, url, &req.repo.full_name, github_token) {
+                Ok(d) => d,
+                Err(e) => {
+                    error!("Error downloading image from PR #{} of {}: {}", req.number, req.repo.full_name, e);
+                    continue;
+                }
+            };
+
+            let name = file_name(url, i);
+            for (channel, ts) in req.channels.iter().zip(thread_ts.iter()) {
+                match api.upload(channel, &name, &title, data.clone(), ts.as_ref().map(|t| t.as_str())) {
+                    Ok(_) => info!("Forwarded image {} from PR #{} to {}", name, req.number, channel),
+                    Err(e) => error!("Error uploading image from PR #{} to {}: {}", req.number, channel, e),
+                };
+            }
+        }
+    }
+
+    fn repo(&self, req: &PrImagesRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &PrImagesRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_urls() {
+        let body = "Before:\n![before](https://github.com/user-attachments/assets/1234)\n\
+                    After: ![after](<https://example.com/after.png> \"The new look\")\n\
+                    <IMG width=\"200\" src=\"https://user-images.githubusercontent.com/1/shot.png\" alt=\"x\">\n\
+                    Again ![before again](https://github.com/user-attachments/assets/1234)\n\
+                    [not an image](https://example.com/link.png) ![local](images/shot.png)";
+
+        assert_eq!(
+            vec![
+                "https://github.com/user-attachments/assets/1234",
+                "https://example.com/after.png",
+                "https://user-images.githubusercontent.com/1/shot.png",
+            ],
+            image_urls(body)
+        );
+        assert_eq!(Vec::<String>::new(), image_urls("No pictures"));
+    }
+
+    #[test]
+    fn test_image_urls_max() {
+        let body = (0..10).map(|i| format!("![{}](https://example.com/{}.png)", i, i)).collect::<Vec<_>>().join("\n");
+        assert_eq!(MAX_IMAGES, image_urls(&body).len());
+    }
+
+    #[test]
+    fn test_is_github_url() {
+        assert!(is_github_url("https://github.com/user-attachments/assets/1234", "github.com"));
+        assert!(is_github_url("https://user-images.githubusercontent.com/1/shot.png", "git.company.com"));
+        assert!(is_github_url("https://git.company.com/org/repo/assets/1/shot.png", "git.company.com"));
+        assert!(!is_github_url("https://example.com/github.com/shot.png", "github.com"));
+        assert!(!is_github_url("https://github.com.example.com/shot.png", "github.com"));
+        assert!(!is_github_url("not a url", "github.com"));
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!("shot.png", file_name("https://example.com/images/shot.png?raw=true", 0));
+        assert_eq!("1234", file_name("https://github.com/user-attachments/assets/1234", 0));
+        assert_eq!("image-2", file_name("https://example.com/", 1));
+    }
+}
@@ -12,8 +12,12 @@ use crate::git::Git;
 use crate::git_clone_manager::GitCloneManager;
 use crate::github;
 use crate::github::api::{GithubSessionFactory, Session};
+use crate::merge_strategy::{self, MergeMethod};
 use crate::messenger;
+use crate::outbound_webhooks::{self, OutboundEvent};
+use crate::repos::RepoMergeStrategy;
 use crate::slack::{SlackAttachmentBuilder, SlackRequest};
+use crate::users;
 use crate::worker;
 
 fn clone_and_merge_pull_request(
@@ -22,6 +26,7 @@ fn clone_and_merge_pull_request(
     req: &PRMergeRequest,
     config: Arc<Config>,
     slack: Arc<dyn worker::Worker<SlackRequest>>,
+    webhooks: Arc<dyn worker::Worker<OutboundEvent>>,
 ) {
     let owner = &req.repo.owner.login();
     let repo = &req.repo.name;
@@ -33,17 +38,31 @@ fn clone_and_merge_pull_request(
             return;
         }
     };
-    let held_clone_dir = match clone_mgr.clone(owner, repo) {
+    // merges into other branches of the repo go ahead concurrently, in their own worktrees
+    let _branch_lock = clone_mgr.lock_branch(session.github_host(), owner, repo, &req.target_branch);
+
+    // only the target branch and the merged commit are needed to cherry-pick it
+    let mut refs = vec![req.target_branch.as_str()];
+    if let Some(ref sha) = req.pull_request.merge_commit_sha {
+        refs.push(sha);
+    }
+    // ...or the PR's own commits, to pick them one by one
+    if backport_method(req) != MergeMethod::Squash {
+        refs.push(&req.pull_request.head.sha);
+    }
+    let held_worktree = match clone_mgr.worktree(owner, repo, &refs) {
         Ok(h) => h,
         Err(e) => {
             error!("Error getting new session: {}", e);
             return;
         }
     };
-    let clone_dir = held_clone_dir.dir();
-    let git = Git::new(session.github_host(), session.github_token(), clone_dir);
+    let clone_dir = held_worktree.dir();
+    let git = Git::new(session.github_host(), session.github_token(), clone_dir)
+        .with_signing(config.signing.as_ref())
+        .with_dry_run(config.dry_run(&req.repo));
 
-    merge_pull_request(&git, &session, &req, config, slack)
+    merge_pull_request(&git, &session, &req, config, slack, webhooks)
 }
 
 pub fn merge_pull_request(
@@ -52,8 +71,26 @@ pub fn merge_pull_request(
     req: &PRMergeRequest,
     config: Arc<Config>,
     slack: Arc<dyn worker::Worker<SlackRequest>>,
+    webhooks: Arc<dyn worker::Worker<OutboundEvent>>,
 ) {
-    if let Err(e) = try_merge_pull_request(git, session, req) {
+    let result = try_merge_pull_request(git, session, req);
+
+    // conflicting backports to release branches still get a (draft) PR for the author to resolve
+    if let Err(ref e) = result {
+        if is_release_branch(req) && !conflict_files(&format!("{}", e)).is_empty() {
+            match create_conflict_backport(git, session, req) {
+                Ok((draft_pr, files)) => {
+                    report_conflict_backport(session, req, &draft_pr, &files, config, slack);
+                    return;
+                }
+                Err(e) => error!("Error creating backport PR with conflicts: {}", e),
+            };
+        }
+    }
+
+    report_backport_check(session, req, &result);
+
+    if let Err(e) = result {
         let attach = SlackAttachmentBuilder::new(&format!("{}", e))
             .title(
                 format!("Source PR: #{}: \"{}\"", req.pull_request.number, req.pull_request.title)
@@ -68,7 +105,7 @@ pub fn merge_pull_request(
             req.pull_request.head.ref_name,
             req.target_branch
         );
-        let messenger = messenger::new(config.clone(), slack.clone());
+        let messenger = messenger::new(config.clone(), slack.clone()).for_dm_event(users::DM_BACKPORT);
         messenger.send_to_owner(
             &msg,
             &vec![attach],
@@ -85,6 +122,187 @@ pub fn merge_pull_request(
         if let Err(e) = session.add_pull_request_labels(req.repo.owner.login(), &req.repo.name, req.pull_request.number, vec!["failed-backport".to_string()]) {
             error!("Error adding failed-backport label on pull request: {}", e);
         }
+
+        if config.events_enabled() {
+            webhooks.send(outbound_webhooks::backport_failed(
+                &req.repo,
+                &req.pull_request,
+                &req.target_branch,
+                &format!("{}", e),
+            ));
+        }
+    }
+}
+
+fn report_conflict_backport(
+    session: &dyn Session,
+    req: &PRMergeRequest,
+    draft_pr: &github::PullRequest,
+    files: &Vec<String>,
+    config: Arc<Config>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+) {
+    let name = backport_check_name(req);
+    let url = if draft_pr.html_url.is_empty() { None } else { Some(draft_pr.html_url.clone()) };
+    let mut run =
+        github::CheckRun::new(&name, &req.pull_request, url).completed(github::Conclusion::ActionRequired);
+    let summary = format!(
+        "Created draft backport PR #{} to {}: it needs its conflicts resolved in {}",
+        draft_pr.number,
+        req.target_branch,
+        files.join(", ")
+    );
+    run.output = Some(github::CheckOutput::new(&format!("Conflicts in draft PR #{}", draft_pr.number), &summary));
+    if let Err(e) = session.create_check_run(&req.pull_request, &run) {
+        error!("Error creating backport check run on pull request: {}", e);
+    }
+
+    let attach = SlackAttachmentBuilder::new(&format!("Conflicts in {}", files.join(", ")))
+        .title(format!("Draft PR #{}: \"{}\"", draft_pr.number, draft_pr.title).as_str())
+        .title_link(draft_pr.html_url.clone())
+        .color("warning")
+        .build();
+    let msg = format!(
+        "Backport of PR #{} to {} has conflicts: please resolve them in the draft PR",
+        req.pull_request.number,
+        req.target_branch
+    );
+    let messenger = messenger::new(config, slack).for_dm_event(users::DM_BACKPORT);
+    messenger.send_to_owner(&msg, &vec![attach], &req.pull_request.user, &req.repo, &req.target_branch, &req.commits);
+
+    let comment = format!("Backport to {} has conflicts: see draft PR #{}", req.target_branch, draft_pr.number);
+    let owner = req.repo.owner.login();
+    if let Err(e) = session.comment_pull_request(owner, &req.repo.name, req.pull_request.number, &comment) {
+        error!("Error making backport conflict comment on pull request: {}", e);
+    }
+}
+
+fn is_release_branch(req: &PRMergeRequest) -> bool {
+    !req.release_branch_prefix.is_empty() && req.target_branch.starts_with(&req.release_branch_prefix)
+}
+
+// e.g. "backport/PR-123-release-1.2"
+pub fn conflict_branch_name(pr_number: u32, target_branch: &str) -> String {
+    format!("backport/PR-{}-{}", pr_number, target_branch.replace('/', "-"))
+}
+
+// Commits the cherry-pick with its conflicts unresolved to a branch of its own and opens a draft PR from it, assigned
+// to the original PR's author. Returns the draft PR and the conflicting files.
+pub fn create_conflict_backport(
+    git: &Git,
+    session: &dyn Session,
+    req: &PRMergeRequest,
+) -> Result<(github::PullRequest, Vec<String>)> {
+    let pull_request = &req.pull_request;
+    let merge_commit_sha = match pull_request.merge_commit_sha {
+        Some(ref sha) => sha,
+        None => return Err(format_err!("Pull Request #{} has no merge commit.", pull_request.number)),
+    };
+
+    let branch_name = conflict_branch_name(pull_request.number, &req.target_branch);
+    let current_remotes = git.run(&["ls-remote", "--heads"])?;
+    if current_remotes.contains(&format!("refs/heads/{}", branch_name)) {
+        return Err(format_err!("PR branch already exists on origin: '{}'", branch_name));
+    }
+
+    // clear out the failed cherry-pick
+    git.run(&["reset", "--hard"])?;
+    git.checkout_branch(&branch_name, &format!("origin/{}", req.target_branch))?;
+
+    let (user, email) = git.get_commit_author(merge_commit_sha)?;
+    let email = format!("user.email={}", email);
+    let user = format!("user.name={}", user);
+    let user_opts = ["-c", &email, "-c", &user];
+
+    // a plain cherry-pick, so the conflict markers show everything that needs resolving
+    if let Err(e) = do_cherry_pick(git, merge_commit_sha, &[], &user_opts) {
+        info!("Committing cherry-pick of {} with conflicts: {}", merge_commit_sha, e);
+    }
+    let files = git
+        .run(&["diff", "--name-only", "--diff-filter=U"])?
+        .lines()
+        .map(|f| f.to_string())
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Err(format_err!("Cherry-pick of {} has no conflicts to resolve", merge_commit_sha));
+    }
+
+    let (title, body) = backport_desc(git, req, merge_commit_sha)?;
+
+    git.run(&["add", "-A"])?;
+    let mut commit_args = vec![];
+    commit_args.extend(user_opts.iter());
+    commit_args.extend(["commit", "--no-verify", "-F", "-"].iter());
+    git.run_with_stdin(&commit_args, &format!("{}\n\n{}", &title, &body))?;
+
+    git.run(&["push", "origin", &format!("HEAD:{}", branch_name)])?;
+
+    let body = format!(
+        "Cherry-picking this onto {} conflicted in:\n\n{}\n\n\
+         The conflict markers are committed as-is: resolve them on this branch, \
+         then mark this PR ready for review.\n\n{}",
+        req.target_branch,
+        files.iter().map(|f| format!("* `{}`", f)).collect::<Vec<_>>().join("\n"),
+        body
+    );
+
+    let owner = req.repo.owner.login();
+    let repo = &req.repo.name;
+    let draft_pr = session.create_draft_pull_request(owner, repo, &title, &body, &branch_name, &req.target_branch)?;
+    session.assign_pull_request(owner, repo, draft_pr.number, vec![pull_request.user.login().to_string()])?;
+
+    Ok((draft_pr, files))
+}
+
+fn backport_check_name(req: &PRMergeRequest) -> String {
+    let mut target_branch = req.target_branch.as_str();
+    if !req.release_branch_prefix.is_empty() && target_branch.starts_with(&req.release_branch_prefix) {
+        target_branch = &target_branch[req.release_branch_prefix.len()..];
+    }
+    format!("octobot/backport-{}", target_branch)
+}
+
+// Files that git reported as conflicting in the given error output
+pub fn conflict_files(error: &str) -> Vec<String> {
+    let regex = Regex::new(r"Merge conflict in (\S+)").unwrap();
+    let mut files = regex
+        .captures_iter(error)
+        .map(|c| c[1].to_string())
+        .collect::<Vec<_>>();
+    files.dedup();
+    files
+}
+
+fn backport_failure_title(error: &str) -> String {
+    let files = conflict_files(error);
+    if files.is_empty() {
+        "failed".into()
+    } else {
+        format!("failed — conflict in {}", files.join(", "))
+    }
+}
+
+// Marks the original PR's head commit with the result of the backport.
+fn report_backport_check(session: &dyn Session, req: &PRMergeRequest, result: &Result<github::PullRequest>) {
+    let name = backport_check_name(req);
+    let run = match *result {
+        Ok(ref new_pr) => {
+            let url = if new_pr.html_url.is_empty() { None } else { Some(new_pr.html_url.clone()) };
+            let mut run = github::CheckRun::new(&name, &req.pull_request, url).completed(github::Conclusion::Success);
+            let summary = format!("Created backport PR #{} to {}", new_pr.number, req.target_branch);
+            run.output = Some(github::CheckOutput::new(&format!("Created PR #{}", new_pr.number), &summary));
+            run
+        }
+        Err(ref e) => {
+            let error = format!("{}", e);
+            let mut run = github::CheckRun::new(&name, &req.pull_request, None).completed(github::Conclusion::Failure);
+            run.output = Some(github::CheckOutput::new(&backport_failure_title(&error), &error));
+            run
+        }
+    };
+
+    if let Err(e) = session.create_check_run(&req.pull_request, &run) {
+        error!("Error creating backport check run on pull request: {}", e);
     }
 }
 
@@ -116,15 +334,16 @@ pub fn try_merge_pull_request(
         return Err(format_err!("PR branch already exists on origin: '{}'", pr_branch_name));
     }
 
-    let (title, body, whitespace_mode) = cherry_pick(
-        &git,
-        &merge_commit_sha,
-        &pr_branch_name,
-        pull_request.number,
-        &req.target_branch,
-        &pull_request.base.ref_name,
-        &req.release_branch_prefix,
-    )?;
+    let (title, body) = backport_desc(git, req, merge_commit_sha)?;
+    let whitespace_mode = match backport_method(req) {
+        MergeMethod::Squash => {
+            cherry_pick(&git, &merge_commit_sha, &pr_branch_name, &req.target_branch, &title, &body)?
+        }
+        MergeMethod::Rebase => cherry_pick_commits(&git, &req.commits, &pr_branch_name, &req.target_branch)?,
+        MergeMethod::Merge => {
+            merge_commits(&git, &req.commits, &merge_commit_sha, &pr_branch_name, &req.target_branch, &title, &body)?
+        }
+    };
 
     git.run(&["push", "origin", &format!("HEAD:{}", pr_branch_name)])?;
 
@@ -154,16 +373,60 @@ pub fn try_merge_pull_request(
     Ok(new_pr)
 }
 
+// How the backport is done: squashed into a single commit unless the repo's merge strategy says otherwise.
+// Without the PR's commits to pick one by one, it is squashed either way.
+fn backport_method(req: &PRMergeRequest) -> MergeMethod {
+    let method = req.merge_strategy.as_ref().and_then(|s| s.method()).unwrap_or(MergeMethod::Squash);
+    if method != MergeMethod::Squash && req.commits.is_empty() {
+        info!("No commits of PR #{} to {}: squashing instead", req.pull_request.number, method.api_name());
+        return MergeMethod::Squash;
+    }
+    method
+}
+
+// The title and body of the backport, from the merge strategy's commit template if it has one
+fn backport_desc(git: &Git, req: &PRMergeRequest, merge_commit_sha: &str) -> Result<(String, String)> {
+    let pull_request = &req.pull_request;
+    let templated = req.merge_strategy.as_ref().and_then(|s| {
+        let vars = merge_strategy::commit_vars(pull_request, &req.target_branch, &req.repo.full_name, &req.commits);
+        merge_strategy::commit_message(s, &vars)
+    });
+
+    let (title, mut body) = match templated {
+        Some(desc) => desc,
+        None => {
+            let desc = git.get_commit_desc(merge_commit_sha)?;
+            return Ok(make_merge_desc(
+                desc,
+                merge_commit_sha,
+                pull_request.number,
+                &req.target_branch,
+                &pull_request.base.ref_name,
+                &req.release_branch_prefix,
+            ));
+        }
+    };
+
+    if body.len() != 0 {
+        body += "\n\n";
+    }
+    body += format!("(cherry-picked from {}, PR #{})", merge_commit_sha, pull_request.number).as_str();
+
+    Ok((title, body))
+}
+
+// Cherry-picks |commit_hash| onto a new branch as a single commit with the given message.
+// Returns the whitespace option the cherry-pick needed, if any.
 pub fn cherry_pick(
     git: &Git,
     commit_hash: &str,
     pr_branch_name: &str,
-    pr_number: u32,
     target_branch: &str,
-    orig_base_branch: &str,
-    release_branch_prefix: &str,
-) -> Result<(String, String, String)> {
+    title: &str,
+    body: &str,
+) -> Result<String> {
     git.checkout_branch(pr_branch_name, &format!("origin/{}", target_branch))?;
+    git.ensure_parents(commit_hash)?;
 
     let (user, email) = git.get_commit_author(commit_hash)?;
     let email = format!("user.email={}", email);
@@ -171,33 +434,113 @@ pub fn cherry_pick(
     let user_opts = ["-c", &email, "-c", &user];
 
     // cherry-pick!
+    let whitespace_mode = cherry_pick_ignoring_whitespace(git, commit_hash, &[], &user_opts)?;
+
+    // change commit message
+    let mut amend_args = vec![];
+    amend_args.extend(user_opts.iter());
+    amend_args.extend(["commit", "--amend", "-F", "-"].iter());
+    git.run_with_stdin(&amend_args, &format!("{}\n\n{}", title, body))?;
+
+    Ok(whitespace_mode.into())
+}
+
+// Cherry-picks each of the PR's commits onto a new branch, keeping their messages.
+// Returns the whitespace option the cherry-picks needed, if any.
+fn cherry_pick_commits(
+    git: &Git,
+    commits: &Vec<github::Commit>,
+    pr_branch_name: &str,
+    target_branch: &str,
+) -> Result<String> {
+    git.checkout_branch(pr_branch_name, &format!("origin/{}", target_branch))?;
+    if let Some(first) = commits.first() {
+        git.ensure_parents(&first.sha)?;
+    }
 
     let mut whitespace_mode = "";
-    if let Err(e) = do_cherry_pick(git, &commit_hash, &[], &user_opts) {
+    for commit in commits {
+        let (user, email) = git.get_commit_author(&commit.sha)?;
+        let email = format!("user.email={}", email);
+        let user = format!("user.name={}", user);
+        let user_opts = ["-c", &email, "-c", &user];
+
+        // -x notes where each one was picked from
+        let mode = cherry_pick_ignoring_whitespace(git, &commit.sha, &["-x"], &user_opts)?;
+        if whitespace_mode.is_empty() || mode == "ignore-all-space" {
+            whitespace_mode = mode;
+        }
+    }
+
+    Ok(whitespace_mode.into())
+}
+
+// Cherry-picks each of the PR's commits and joins them to the new branch with a merge commit.
+// Returns the whitespace option the cherry-picks needed, if any.
+fn merge_commits(
+    git: &Git,
+    commits: &Vec<github::Commit>,
+    merge_commit_sha: &str,
+    pr_branch_name: &str,
+    target_branch: &str,
+    title: &str,
+    body: &str,
+) -> Result<String> {
+    let picks_branch = format!("{}-picks", pr_branch_name);
+    let whitespace_mode = cherry_pick_commits(git, commits, &picks_branch, target_branch)?;
+
+    git.checkout_branch(pr_branch_name, &format!("origin/{}", target_branch))?;
+
+    let (user, email) = git.get_commit_author(merge_commit_sha)?;
+    let email = format!("user.email={}", email);
+    let user = format!("user.name={}", user);
+    let user_opts = ["-c", &email, "-c", &user];
+
+    let mut merge_args = vec![];
+    merge_args.extend(user_opts.iter());
+    merge_args.extend(["merge", "--no-ff", "--no-commit", &picks_branch].iter());
+    git.run(&merge_args)?;
+
+    let mut commit_args = vec![];
+    commit_args.extend(user_opts.iter());
+    commit_args.extend(["commit", "--no-verify", "-F", "-"].iter());
+    git.run_with_stdin(&commit_args, &format!("{}\n\n{}", title, body))?;
+    git.run(&["branch", "-D", &picks_branch])?;
+
+    Ok(whitespace_mode)
+}
+
+// Cherry-picks |commit_hash| onto HEAD, ignoring more and more whitespace changes until it applies.
+// Returns the whitespace option it needed, if any.
+fn cherry_pick_ignoring_whitespace(
+    git: &Git,
+    commit_hash: &str,
+    opts: &[&str],
+    user_opts: &[&str],
+) -> Result<&'static str> {
+    if let Err(e) = do_cherry_pick(git, commit_hash, opts, user_opts) {
         info!("Could not cherry-pick normally. Ignoring changed whitespace. {}", e);
 
-        whitespace_mode = "ignore-space-change";
-        if let Err(e) = do_cherry_pick(git, &commit_hash, &["-X", whitespace_mode], &user_opts) {
+        let mut whitespace_mode = "ignore-space-change";
+        if let Err(e) = do_cherry_pick(git, commit_hash, &with_strategy_opt(opts, whitespace_mode), user_opts) {
             info!("Could not cherry-pick with `-X {}`. Ignoring all whitespace. {}", whitespace_mode, e);
 
             whitespace_mode = "ignore-all-space";
-            if let Err(e) = do_cherry_pick(git, &commit_hash, &["-X", whitespace_mode], &user_opts) {
+            if let Err(e) = do_cherry_pick(git, commit_hash, &with_strategy_opt(opts, whitespace_mode), user_opts) {
                 info!("Could not cherry-pick with `-X {}`: {}", whitespace_mode, e);
                 return Err(e);
             }
         }
+        return Ok(whitespace_mode);
     }
 
-    let desc = git.get_commit_desc(commit_hash)?;
-    let (title, body) = make_merge_desc(desc, commit_hash, pr_number, target_branch, orig_base_branch, release_branch_prefix);
-
-    // change commit message
-    let mut amend_args = vec![];
-    amend_args.extend(user_opts.iter());
-    amend_args.extend(["commit", "--amend", "-F", "-"].iter());
-    git.run_with_stdin(&amend_args, &format!("{}\n\n{}", &title, &body))?;
+    Ok("")
+}
 
-    Ok((title, body, whitespace_mode.into()))
+fn with_strategy_opt<'a>(opts: &[&'a str], strategy_opt: &'a str) -> Vec<&'a str> {
+    let mut opts = opts.to_vec();
+    opts.extend(["-X", strategy_opt].iter());
+    opts
 }
 
 fn do_cherry_pick(git: &Git, commit_hash: &str, opts: &[&str], user_opts: &[&str]) -> Result<String> {
@@ -277,6 +620,8 @@ pub struct PRMergeRequest {
     pub target_branch: String,
     pub release_branch_prefix: String,
     pub commits: Vec<github::Commit>,
+    // How to backport it: squashed into a single commit if not set
+    pub merge_strategy: Option<RepoMergeStrategy>,
 }
 
 struct Runner {
@@ -284,6 +629,7 @@ struct Runner {
     github_app: Arc<dyn GithubSessionFactory>,
     clone_mgr: Arc<GitCloneManager>,
     slack: Arc<dyn worker::Worker<SlackRequest>>,
+    webhooks: Arc<dyn worker::Worker<OutboundEvent>>,
 }
 
 pub fn req(repo: &github::Repo, pull_request: &github::PullRequest, target_branch: &str, release_branch_prefix: &str, commits: Vec<github::Commit>) -> PRMergeRequest {
@@ -293,6 +639,7 @@ pub fn req(repo: &github::Repo, pull_request: &github::PullRequest, target_branc
         target_branch: target_branch.to_string(),
         release_branch_prefix: release_branch_prefix.to_string(),
         commits: commits,
+        merge_strategy: None,
     }
 }
 
@@ -301,12 +648,14 @@ pub fn new_runner(
     github_app: Arc<dyn GithubSessionFactory>,
     clone_mgr: Arc<GitCloneManager>,
     slack: Arc<dyn worker::Worker<SlackRequest>>,
+    webhooks: Arc<dyn worker::Worker<OutboundEvent>>,
 ) -> Arc<dyn worker::Runner<PRMergeRequest>> {
     Arc::new(Runner {
         config: config,
         github_app: github_app,
         clone_mgr: clone_mgr.clone(),
         slack: slack,
+        webhooks: webhooks,
     })
 }
 
@@ -318,14 +667,54 @@ impl worker::Runner<PRMergeRequest> for Runner {
             &req,
             self.config.clone(),
             self.slack.clone(),
+            self.webhooks.clone(),
         );
     }
+
+    fn repo(&self, req: &PRMergeRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &PRMergeRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_conflict_files() {
+        let output = "error: could not apply abc123... change\n\
+                      CONFLICT (content): Merge conflict in src/foo.rs\n\
+                      CONFLICT (content): Merge conflict in README.md\n";
+        assert_eq!(vec!["src/foo.rs", "README.md"], conflict_files(output));
+        assert_eq!("failed — conflict in src/foo.rs, README.md", backport_failure_title(output));
+
+        assert_eq!(Vec::<String>::new(), conflict_files("bad stuff"));
+        assert_eq!("failed", backport_failure_title("bad stuff"));
+    }
+
+    #[test]
+    fn test_conflict_branch_name() {
+        assert_eq!("backport/PR-123-release-1.2", conflict_branch_name(123, "release/1.2"));
+        assert_eq!("backport/PR-7-maint", conflict_branch_name(7, "maint"));
+    }
+
+    #[test]
+    fn test_backport_check_name() {
+        let mut pr = github::PullRequest::new();
+        pr.number = 1;
+        let repo = github::Repo::new();
+
+        assert_eq!("octobot/backport-1.2", backport_check_name(&req(&repo, &pr, "release/1.2", "release/", vec![])));
+        assert_eq!("octobot/backport-other", backport_check_name(&req(&repo, &pr, "other", "release/", vec![])));
+        assert!(is_release_branch(&req(&repo, &pr, "release/1.2", "release/", vec![])));
+        assert!(!is_release_branch(&req(&repo, &pr, "other", "release/", vec![])));
+        assert!(!is_release_branch(&req(&repo, &pr, "release/1.2", "", vec![])));
+    }
+
     #[test]
     fn test_make_merge_desc() {
         let desc = make_merge_desc(
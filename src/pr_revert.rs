@@ -0,0 +1,292 @@
+use std::borrow::Borrow;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::git::Git;
+use crate::git_clone_manager::GitCloneManager;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::jira;
+use crate::messenger;
+use crate::slack::{SlackAttachmentBuilder, SlackRequest};
+use crate::users;
+use crate::util;
+use crate::worker;
+
+// Labeling a merged PR with this has octobot open a PR reverting it
+pub const REVERT_LABEL: &str = "revert-me";
+
+pub fn is_revert_label(label: &str) -> bool {
+    label.eq_ignore_ascii_case(REVERT_LABEL)
+}
+
+// e.g. "revert/PR-123"
+pub fn revert_branch_name(pr_number: u32) -> String {
+    format!("revert/PR-{}", pr_number)
+}
+
+// The title and body of the revert. The title is the one github gives reverts, which JIRA and blame notifications
+// recognize.
+pub fn revert_desc(
+    pull_request: &github::PullRequest,
+    merge_commit_sha: &str,
+    requested_by: &str,
+) -> (String, String) {
+    let title = format!("Revert \"{}\"", pull_request.title);
+    let body = format!(
+        "This reverts PR #{} (commit {}), as requested by @{}.",
+        pull_request.number, merge_commit_sha, requested_by
+    );
+    (title, body)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PRRevertRequest {
+    pub repo: github::Repo,
+    pub pull_request: github::PullRequest,
+    // github login of who asked for it
+    pub requested_by: String,
+    pub commits: Vec<github::Commit>,
+}
+
+pub fn req(
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    requested_by: &str,
+    commits: Vec<github::Commit>,
+) -> PRRevertRequest {
+    PRRevertRequest {
+        repo: repo.clone(),
+        pull_request: pull_request.clone(),
+        requested_by: requested_by.to_string(),
+        commits: commits,
+    }
+}
+
+// Reverts the PR's merge commit on a branch of its own and opens a PR from it, assigned to the original PR's author.
+pub fn try_revert_pull_request(git: &Git, session: &dyn Session, req: &PRRevertRequest) -> Result<github::PullRequest> {
+    let pull_request = &req.pull_request;
+    if !pull_request.is_merged() {
+        return Err(format_err!("Pull Request #{} is not merged.", pull_request.number));
+    }
+    let merge_commit_sha = match pull_request.merge_commit_sha {
+        Some(ref sha) => sha,
+        None => return Err(format_err!("Pull Request #{} has no merge commit.", pull_request.number)),
+    };
+
+    let branch_name = revert_branch_name(pull_request.number);
+    let current_remotes = git.run(&["ls-remote", "--heads"])?;
+    if current_remotes.contains(&format!("refs/heads/{}", branch_name)) {
+        return Err(format_err!("Revert branch already exists on origin: '{}'", branch_name));
+    }
+
+    let target_branch = &pull_request.base.ref_name;
+    git.checkout_branch(&branch_name, &format!("origin/{}", target_branch))?;
+    git.ensure_parents(merge_commit_sha)?;
+
+    let (user, email) = git.get_commit_author(merge_commit_sha)?;
+    let email = format!("user.email={}", email);
+    let user = format!("user.name={}", user);
+    let user_opts = ["-c", &email, "-c", &user];
+
+    // a merge commit (the commit and its two parents) is reverted relative to the branch it was merged into
+    let parents = git.run(&["rev-list", "--parents", "-n", "1", merge_commit_sha])?.split_whitespace().count();
+    let mut revert_args: Vec<&str> = vec![];
+    revert_args.extend(user_opts.iter());
+    revert_args.extend(["revert", "--no-commit"].iter());
+    if parents > 2 {
+        revert_args.extend(["-m", "1"].iter());
+    }
+    revert_args.push(merge_commit_sha.as_str());
+    git.run(&revert_args)?;
+
+    let (title, body) = revert_desc(pull_request, merge_commit_sha, &req.requested_by);
+    let mut commit_args = vec![];
+    commit_args.extend(user_opts.iter());
+    commit_args.extend(["commit", "--no-verify", "-F", "-"].iter());
+    git.run_with_stdin(&commit_args, &format!("{}\n\n{}", title, body))?;
+
+    git.run(&["push", "origin", &format!("HEAD:{}", branch_name)])?;
+
+    let owner = req.repo.owner.login();
+    let repo = &req.repo.name;
+    let revert_pr = session.create_pull_request(owner, repo, &title, &body, &branch_name, target_branch)?;
+    session.assign_pull_request(owner, repo, revert_pr.number, vec![pull_request.user.login().to_string()])?;
+
+    let comment = format!("Reverted in #{}, as requested by @{}", revert_pr.number, req.requested_by);
+    if let Err(e) = session.comment_pull_request(owner, repo, pull_request.number, &comment) {
+        error!("Error commenting on reverted pull request: {}", e);
+    }
+
+    Ok(revert_pr)
+}
+
+pub fn revert_pull_request(
+    git: &Git,
+    session: &dyn Session,
+    jira_session: Option<&dyn jira::api::Session>,
+    req: &PRRevertRequest,
+    config: Arc<Config>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+) {
+    let pull_request = &req.pull_request;
+    let target_branch = &pull_request.base.ref_name;
+    let messenger = messenger::new(config.clone(), slack).for_dm_event(users::DM_REVERT);
+    let source_attach = |text: &str, color: &str| {
+        SlackAttachmentBuilder::new(text)
+            .title(format!("Pull Request #{}: \"{}\"", pull_request.number, pull_request.title).as_str())
+            .title_link(pull_request.html_url.clone())
+            .color(color)
+            .build()
+    };
+
+    let revert_pr = match try_revert_pull_request(git, session, req) {
+        Ok(pr) => pr,
+        Err(e) => {
+            let msg = format!("Error reverting PR #{}", pull_request.number);
+            let attach = source_attach(&format!("{}", e), "danger");
+            messenger.send_to_owner(&msg, &vec![attach], &pull_request.user, &req.repo, target_branch, &req.commits);
+
+            let owner = req.repo.owner.login();
+            if let Err(e) = session.comment_pull_request(owner, &req.repo.name, pull_request.number, &msg) {
+                error!("Error making revert failure comment on pull request: {}", e);
+            }
+            return;
+        }
+    };
+
+    if let (Some(jira_session), Some(ref jira_config)) = (jira_session, &config.jira) {
+        let projects = config.repos().jira_projects(&req.repo, target_branch);
+        jira::workflow::reopen_reverted(&revert_pr, &req.commits, &projects, jira_session, jira_config);
+    }
+
+    let msg = format!(
+        "PR #{} was reverted at the request of {}: please review {}",
+        pull_request.number,
+        req.requested_by,
+        util::make_link(&revert_pr.html_url, &format!("revert PR #{}", revert_pr.number))
+    );
+    let attach = source_attach("", "warning");
+    messenger.send_to_owner(&msg, &vec![attach], &pull_request.user, &req.repo, target_branch, &req.commits);
+}
+
+fn clone_and_revert_pull_request(
+    github_app: &dyn GithubSessionFactory,
+    jira_session: Option<&dyn jira::api::Session>,
+    clone_mgr: &GitCloneManager,
+    req: &PRRevertRequest,
+    config: Arc<Config>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+) {
+    let owner = req.repo.owner.login();
+    let repo = &req.repo.name;
+    let target_branch = &req.pull_request.base.ref_name;
+
+    let session = match github_app.new_session(owner, repo) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Error getting new session: {}", e);
+            return;
+        }
+    };
+    let _branch_lock = clone_mgr.lock_branch(session.github_host(), owner, repo, target_branch);
+
+    let mut refs = vec![target_branch.as_str()];
+    if let Some(ref sha) = req.pull_request.merge_commit_sha {
+        refs.push(sha);
+    }
+    let held_worktree = match clone_mgr.worktree(owner, repo, &refs) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("Error getting worktree to revert PR #{}: {}", req.pull_request.number, e);
+            return;
+        }
+    };
+    let git = Git::new(session.github_host(), session.github_token(), held_worktree.dir())
+        .with_signing(config.signing.as_ref())
+        .with_dry_run(config.dry_run(&req.repo));
+
+    info!("Reverting PR #{} of {} for {}", req.pull_request.number, req.repo.full_name, req.requested_by);
+    revert_pull_request(&git, &session, jira_session, req, config, slack);
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    jira_session: Option<Arc<dyn jira::api::Session>>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    jira_session: Option<Arc<dyn jira::api::Session>>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+) -> Arc<dyn worker::Runner<PRRevertRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+        jira_session: jira_session,
+        clone_mgr: clone_mgr,
+        slack: slack,
+    })
+}
+
+impl worker::Runner<PRRevertRequest> for Runner {
+    fn handle(&self, req: PRRevertRequest) {
+        // Only logs JIRA changes if the repo is in dry run mode
+        let jira_session = jira::dry_run::for_repo(self.jira_session.clone(), self.config.dry_run(&req.repo));
+        clone_and_revert_pull_request(
+            self.github_app.borrow(),
+            jira_session.as_ref().map(|j| j.deref()),
+            self.clone_mgr.borrow(),
+            &req,
+            self.config.clone(),
+            self.slack.clone(),
+        );
+    }
+
+    fn repo(&self, req: &PRRevertRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &PRRevertRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_revert_label() {
+        assert!(is_revert_label("revert-me"));
+        assert!(is_revert_label("Revert-Me"));
+        assert!(!is_revert_label("revert"));
+        assert!(!is_revert_label("backport-1.2"));
+    }
+
+    #[test]
+    fn test_revert_desc() {
+        let mut pr = github::PullRequest::new();
+        pr.number = 123;
+        pr.title = "SER-1 Fix the thing".into();
+
+        assert_eq!(
+            (
+                "Revert \"SER-1 Fix the thing\"".to_string(),
+                "This reverts PR #123 (commit abcdef), as requested by @joe.".to_string()
+            ),
+            revert_desc(&pr, "abcdef", "joe")
+        );
+        assert_eq!("revert/PR-123", revert_branch_name(123));
+    }
+}
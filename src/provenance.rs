@@ -0,0 +1,394 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::git::Git;
+use crate::git_clone_manager::GitCloneManager;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::messenger;
+use crate::sbom;
+use crate::slack::{SlackAttachmentBuilder, SlackRequest};
+use crate::worker;
+
+// first releases include the whole history: don't walk further back than this
+const MAX_COMMITS: usize = 1000;
+
+// What went into a release: every PR merged since the previous release, who approved it, how its checks ended up,
+// and who signed off on its commits.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Attestation {
+    pub repo: String,
+    pub tag: String,
+    pub commit_hash: String,
+    pub previous_tag: Option<String>,
+    pub pull_requests: Vec<PullRequestProvenance>,
+    pub generated_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PullRequestProvenance {
+    pub number: u32,
+    pub title: String,
+    pub html_url: String,
+    pub author: String,
+    pub merge_commit_sha: Option<String>,
+    pub approvals: Vec<Approval>,
+    pub checks: Vec<CheckResult>,
+    pub sign_offs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Approval {
+    pub user: String,
+    pub submitted_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: github::CheckStatus,
+    pub conclusion: Option<github::Conclusion>,
+}
+
+// An attestation as recorded. The document is stored (and served) exactly as hashed so auditors can verify it.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RecordedAttestation {
+    pub repo: String,
+    pub tag: String,
+    pub document: String,
+    pub sha256: String,
+    pub recorded_at: i64,
+}
+
+// Attestations are written once per release and never updated
+#[derive(Clone)]
+pub struct AttestationLedger {
+    db: Database,
+}
+
+impl AttestationLedger {
+    pub fn new(db: Database) -> AttestationLedger {
+        AttestationLedger { db: db }
+    }
+
+    pub fn record(&self, attestation: &Attestation) -> Result<RecordedAttestation> {
+        let document = serde_json::to_string_pretty(attestation)?;
+        let recorded = RecordedAttestation {
+            repo: attestation.repo.clone(),
+            tag: attestation.tag.clone(),
+            sha256: sbom::sha256(document.as_bytes()),
+            document: document,
+            recorded_at: db::now(),
+        };
+
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO release_attestations (repo, tag, document, sha256, recorded_at)
+               VALUES (?1, ?2, ?3, ?4, ?5)"#,
+            &[
+                &recorded.repo as &dyn ToSql,
+                &recorded.tag,
+                &recorded.document,
+                &recorded.sha256,
+                &recorded.recorded_at,
+            ],
+        )
+        .map_err(|e| format_err!("Error recording attestation of {} {}: {}", recorded.repo, recorded.tag, e))?;
+
+        Ok(recorded)
+    }
+
+    pub fn get(&self, repo: &str, tag: &str) -> Result<Option<RecordedAttestation>> {
+        let attestations = self.query("WHERE repo = :repo AND tag = :tag", &[(":repo", &repo), (":tag", &tag)])?;
+        Ok(attestations.into_iter().next())
+    }
+
+    pub fn get_all(&self, repo: &str) -> Result<Vec<RecordedAttestation>> {
+        self.query("WHERE repo = :repo ORDER BY recorded_at DESC", &[(":repo", &repo)])
+    }
+
+    fn query(&self, filter: &str, params: &[(&str, &dyn ToSql)]) -> Result<Vec<RecordedAttestation>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare(&format!("SELECT * FROM release_attestations {}", filter))?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(params)?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(RecordedAttestation {
+                repo: cols.get(row, "repo")?,
+                tag: cols.get(row, "tag")?,
+                document: cols.get(row, "document")?,
+                sha256: cols.get(row, "sha256")?,
+                recorded_at: cols.get(row, "recorded_at")?,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AttestationRequest {
+    pub repo: github::Repo,
+    pub release: github::Release,
+}
+
+pub fn req(repo: &github::Repo, release: &github::Release) -> AttestationRequest {
+    AttestationRequest {
+        repo: repo.clone(),
+        release: release.clone(),
+    }
+}
+
+// PRs merged by the given commits, from merge commit ("Merge pull request #12 from ...") or squash ("Title (#12)")
+// subjects, oldest first.
+pub fn pull_request_numbers(commit_messages: &[String]) -> Vec<u32> {
+    let merge = Regex::new(r"^Merge pull request #(\d+)").unwrap();
+    let squash = Regex::new(r"\(#(\d+)\)\s*$").unwrap();
+
+    let mut numbers = vec![];
+    for message in commit_messages.iter().rev() {
+        let subject = message.lines().next().unwrap_or("");
+        let caps = merge.captures(subject).or_else(|| squash.captures(subject));
+        if let Some(number) = caps.and_then(|c| c[1].parse::<u32>().ok()) {
+            if !numbers.contains(&number) {
+                numbers.push(number);
+            }
+        }
+    }
+    numbers
+}
+
+pub fn sign_offs(commit_messages: &[String]) -> Vec<String> {
+    let mut result: Vec<String> = vec![];
+    for message in commit_messages {
+        for line in message.lines() {
+            if line.trim().starts_with("Signed-off-by:") {
+                let sign_off = line.trim()["Signed-off-by:".len()..].trim().to_string();
+                if !sign_off.is_empty() && !result.contains(&sign_off) {
+                    result.push(sign_off);
+                }
+            }
+        }
+    }
+    result
+}
+
+// Latest approval by each reviewer that still stands
+pub fn approvals(reviews: &[github::Review]) -> Vec<Approval> {
+    let mut result: Vec<Approval> = vec![];
+    for review in reviews {
+        if review.state != "APPROVED" && review.state != "CHANGES_REQUESTED" && review.state != "DISMISSED" {
+            continue;
+        }
+        result.retain(|a| a.user != review.user.login());
+        if review.state == "APPROVED" {
+            result.push(Approval {
+                user: review.user.login().to_string(),
+                submitted_at: review.submitted_at.clone(),
+            });
+        }
+    }
+    result
+}
+
+fn pull_request_provenance(session: &dyn Session, repo: &github::Repo, number: u32) -> Result<PullRequestProvenance> {
+    let owner = repo.owner.login();
+    let pull_request = session.get_pull_request(owner, &repo.name, number)?;
+    let reviews = session.get_pull_request_reviews(owner, &repo.name, number)?;
+    let check_runs = session.get_check_runs(owner, &repo.name, &pull_request.head.sha)?;
+    let commits = session.get_pull_request_commits(owner, &repo.name, number)?;
+    let messages = commits.into_iter().map(|c| c.commit.message).collect::<Vec<_>>();
+
+    Ok(PullRequestProvenance {
+        number: number,
+        title: pull_request.title.clone(),
+        html_url: pull_request.html_url.clone(),
+        author: pull_request.user.login().to_string(),
+        merge_commit_sha: pull_request.merge_commit_sha.clone(),
+        approvals: approvals(&reviews),
+        checks: check_runs
+            .into_iter()
+            .map(|r| CheckResult {
+                name: r.name,
+                status: r.status,
+                conclusion: r.conclusion,
+            })
+            .collect(),
+        sign_offs: sign_offs(&messages),
+    })
+}
+
+// Gathers what went into the release from github. |commit_messages| are those since |previous_tag|.
+pub fn attest(
+    session: &dyn Session,
+    req: &AttestationRequest,
+    commit_hash: &str,
+    previous_tag: Option<String>,
+    commit_messages: &[String],
+) -> Result<Attestation> {
+    let mut pull_requests = vec![];
+    for number in pull_request_numbers(commit_messages) {
+        pull_requests.push(pull_request_provenance(session, &req.repo, number)?);
+    }
+
+    Ok(Attestation {
+        repo: req.repo.full_name.clone(),
+        tag: req.release.tag_name.clone(),
+        commit_hash: commit_hash.into(),
+        previous_tag: previous_tag,
+        pull_requests: pull_requests,
+        generated_at: db::now(),
+    })
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+) -> Arc<dyn worker::Runner<AttestationRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+        clone_mgr: clone_mgr,
+        slack: slack,
+    })
+}
+
+impl Runner {
+    fn clone_and_attest(&self, req: &AttestationRequest) -> Result<RecordedAttestation> {
+        let owner = req.repo.owner.login();
+        let session = self.github_app.new_session(owner, &req.repo.name)?;
+        let held_clone_dir = GitCloneManager::clone(&self.clone_mgr, owner, &req.repo.name)?;
+        let git = Git::new(session.github_host(), session.github_token(), held_clone_dir.dir());
+
+        let tag = &req.release.tag_name;
+        let commit_hash = git.run(&["rev-parse", &format!("{}^{{commit}}", tag)])?.trim().to_string();
+        // fails if this is the first tag
+        let previous_tag = git
+            .run(&["describe", "--tags", "--abbrev=0", &format!("{}^", commit_hash)])
+            .ok()
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty());
+
+        let range = match previous_tag {
+            Some(ref previous) => format!("{}..{}", previous, commit_hash),
+            None => commit_hash.clone(),
+        };
+        let log = git.run(&["log", "--format=%B%x00", &format!("--max-count={}", MAX_COMMITS), &range])?;
+        let messages = log
+            .split('\0')
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty())
+            .collect::<Vec<_>>();
+
+        let attestation = attest(&session, req, &commit_hash, previous_tag, &messages)?;
+        self.config.attestations.record(&attestation)
+    }
+}
+
+impl worker::Runner<AttestationRequest> for Runner {
+    fn handle(&self, req: AttestationRequest) {
+        match self.config.attestations.get(&req.repo.full_name, &req.release.tag_name) {
+            Ok(Some(_)) => {
+                info!("Already attested {} {}", req.repo.full_name, req.release.tag_name);
+                return;
+            }
+            Ok(None) => (),
+            Err(e) => error!("Error looking up attestation of {} {}: {}", req.repo.full_name, req.release.tag_name, e),
+        };
+
+        match self.clone_and_attest(&req) {
+            Ok(a) => info!("Recorded attestation of {} {} ({})", a.repo, a.tag, a.sha256),
+            Err(e) => {
+                error!("Error attesting {} {}: {}", req.repo.full_name, req.release.tag_name, e);
+
+                let attach = SlackAttachmentBuilder::new(&format!("{}", e))
+                    .title(req.release.tag_name.clone())
+                    .title_link(req.release.html_url.clone())
+                    .color("danger")
+                    .build();
+                let msg = format!("Error recording provenance of release {}", req.release.tag_name);
+
+                let messenger = messenger::new(self.config.clone(), self.slack.clone());
+                messenger.send_to_channel(&msg, &vec![attach], &req.repo, "", &Vec::<github::Commit>::new());
+            }
+        };
+    }
+
+    fn repo(&self, req: &AttestationRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &AttestationRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(m: &[&str]) -> Vec<String> {
+        m.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_pull_request_numbers() {
+        // newest first, as git log prints them
+        let log = messages(&[
+            "Fix the thing (#14)\n\nSigned-off-by: Joe <joe@company.com>",
+            "Merge pull request #12 from some-user/branch\n\nAdd the thing",
+            "Bump version",
+            "Revert \"Add the thing (#12)\" (#13)",
+            "Merge pull request #12 from some-user/branch",
+        ]);
+        assert_eq!(vec![12, 13, 14], pull_request_numbers(&log));
+    }
+
+    #[test]
+    fn test_sign_offs() {
+        let log = messages(&[
+            "Fix the thing\n\nSigned-off-by: Joe <joe@company.com>\nSigned-off-by: Jane <jane@company.com>",
+            "Fix the other thing\n\n  Signed-off-by: Joe <joe@company.com>",
+            "Signed-off-by:",
+        ]);
+        assert_eq!(vec!["Joe <joe@company.com>", "Jane <jane@company.com>"], sign_offs(&log));
+    }
+
+    #[test]
+    fn test_approvals() {
+        let review = |state: &str, user: &str| {
+            let mut review = github::Review::new("", github::User::new(user));
+            review.state = state.into();
+            review
+        };
+
+        let reviews = vec![
+            review("APPROVED", "joe"),
+            review("APPROVED", "jane"),
+            review("COMMENTED", "jane"),
+            review("CHANGES_REQUESTED", "joe"),
+            review("APPROVED", "bob"),
+        ];
+        let users = approvals(&reviews).into_iter().map(|a| a.user).collect::<Vec<_>>();
+        assert_eq!(vec!["jane", "bob"], users);
+    }
+}
@@ -0,0 +1,342 @@
+use log::error;
+use regex::Regex;
+use serde_derive::Serialize;
+
+use crate::compliance::MergeLog;
+use crate::config::JiraConfig;
+use crate::errors::*;
+use crate::github;
+use crate::github::api::Session;
+use crate::github::CommitLike;
+use crate::jira;
+
+// The group of PRs without labels
+pub const UNLABELED: &str = "Other";
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ReleasePullRequest {
+    pub number: u32,
+    pub title: String,
+    pub html_url: String,
+    pub author: String,
+    pub labels: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ReleaseGroup {
+    pub label: String,
+    pub pull_requests: Vec<ReleasePullRequest>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ReleaseIssue {
+    pub key: String,
+    pub summary: String,
+    pub url: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ReleaseNotes {
+    pub repo: String,
+    pub from: String,
+    pub to: String,
+    pub groups: Vec<ReleaseGroup>,
+    pub issues: Vec<ReleaseIssue>,
+    pub contributors: Vec<String>,
+}
+
+// The PRs merged by |commits|: github's merge commits ("Merge pull request #12 from ...") and squash merges
+// ("Fix it (#12)")
+pub fn merged_pr_numbers<T: CommitLike>(commits: &Vec<T>) -> Vec<u32> {
+    let merge_re = Regex::new(r"^Merge pull request #([0-9]+)\b").unwrap();
+    let squash_re = Regex::new(r"\(#([0-9]+)\)\s*$").unwrap();
+
+    let mut numbers: Vec<u32> = vec![];
+    for commit in commits {
+        let title = github::Commit::title(commit);
+        let number = merge_re
+            .captures(&title)
+            .or_else(|| squash_re.captures(&title))
+            .and_then(|c| c[1].parse::<u32>().ok());
+        if let Some(n) = number {
+            if !numbers.contains(&n) {
+                numbers.push(n);
+            }
+        }
+    }
+    numbers
+}
+
+// Each PR goes in the group of its first label. Groups are sorted by label, with unlabeled PRs last.
+pub fn group_by_label(pull_requests: Vec<ReleasePullRequest>) -> Vec<ReleaseGroup> {
+    let mut groups: Vec<ReleaseGroup> = vec![];
+    for pr in pull_requests {
+        let label = pr.labels.first().cloned().unwrap_or(UNLABELED.to_string());
+        match groups.iter_mut().find(|g| g.label == label) {
+            Some(group) => group.pull_requests.push(pr),
+            None => groups.push(ReleaseGroup {
+                label: label,
+                pull_requests: vec![pr],
+            }),
+        };
+    }
+
+    for group in groups.iter_mut() {
+        group.pull_requests.sort_by_key(|pr| pr.number);
+    }
+    groups.sort_by_key(|g| (g.label == UNLABELED, g.label.to_lowercase()));
+    groups
+}
+
+impl ReleaseNotes {
+    // For the body of the github release
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        for group in &self.groups {
+            md += &format!("### {}\n\n", group.label);
+            for pr in &group.pull_requests {
+                md += &format!("* {} (#{}) by @{}\n", pr.title, pr.number, pr.author);
+            }
+            md += "\n";
+        }
+
+        if !self.issues.is_empty() {
+            md += "### JIRA issues\n\n";
+            for issue in &self.issues {
+                if issue.summary.is_empty() {
+                    md += &format!("* [{}]({})\n", issue.key, issue.url);
+                } else {
+                    md += &format!("* [{}]({}): {}\n", issue.key, issue.url, issue.summary);
+                }
+            }
+            md += "\n";
+        }
+
+        if !self.contributors.is_empty() {
+            md += "### Contributors\n\n";
+            md += &self.contributors.iter().map(|c| format!("@{}", c)).collect::<Vec<_>>().join(", ");
+            md += "\n";
+        }
+
+        if md.is_empty() {
+            md = format!("No changes between {} and {}\n", self.from, self.to);
+        }
+        md
+    }
+}
+
+fn lookup_pull_request(
+    github: &dyn Session,
+    merges: &MergeLog,
+    repo: &github::Repo,
+    number: u32,
+) -> Result<(ReleasePullRequest, Vec<String>)> {
+    let owner = repo.owner.login();
+    // merges octobot recorded already know the PR's tickets
+    let (mut pr, tickets) = match merges.get(&repo.full_name, number)? {
+        Some(merge) => (
+            ReleasePullRequest {
+                number: number,
+                title: merge.title,
+                html_url: merge.html_url,
+                author: merge.author,
+                labels: vec![],
+            },
+            merge.tickets,
+        ),
+        None => {
+            let pull_request = github.get_pull_request(owner, &repo.name, number)?;
+            (
+                ReleasePullRequest {
+                    number: number,
+                    title: pull_request.title,
+                    html_url: pull_request.html_url,
+                    author: pull_request.user.login().to_string(),
+                    labels: vec![],
+                },
+                vec![],
+            )
+        }
+    };
+
+    pr.labels = github.get_pull_request_labels(owner, &repo.name, number)?.into_iter().map(|l| l.name).collect();
+    Ok((pr, tickets))
+}
+
+// Release notes for the changes in |to| since |from| (refs or tags)
+pub fn generate(
+    github: &dyn Session,
+    merges: &MergeLog,
+    jira: Option<(&dyn jira::api::Session, &JiraConfig)>,
+    repo: &github::Repo,
+    projects: &Vec<String>,
+    from: &str,
+    to: &str,
+) -> Result<ReleaseNotes> {
+    let commits = github.compare_commits(repo.owner.login(), &repo.name, from, to)?;
+
+    let mut pull_requests = vec![];
+    let mut keys = jira::workflow::get_all_jira_keys(&commits, projects);
+    let mut contributors = vec![];
+    for number in merged_pr_numbers(&commits) {
+        match lookup_pull_request(github, merges, repo, number) {
+            Ok((pr, tickets)) => {
+                contributors.push(pr.author.clone());
+                keys.extend(tickets);
+                pull_requests.push(pr);
+            }
+            Err(e) => error!("Error looking up PR #{} for release notes: {}", number, e),
+        };
+    }
+    keys.sort();
+    keys.dedup();
+
+    // people who pushed commits directly count too
+    contributors.extend(
+        commits.iter().filter_map(|c| c.author.as_ref()).filter(|a| !a.is_bot()).map(|a| a.login().to_string()),
+    );
+    let mut contributors = contributors
+        .into_iter()
+        .filter(|c| !c.is_empty() && !github::User::new(c).is_bot())
+        .collect::<Vec<_>>();
+    contributors.sort_by_key(|c| c.to_lowercase());
+    contributors.dedup();
+
+    let issues = keys
+        .into_iter()
+        .map(|key| match jira {
+            Some((session, config)) => {
+                let summary = match session.get_issue(&key) {
+                    Ok(issue) => issue.fields.summary.unwrap_or_default(),
+                    Err(e) => {
+                        error!("Error looking up {} for release notes: {}", key, e);
+                        String::new()
+                    }
+                };
+                ReleaseIssue {
+                    url: format!("{}/browse/{}", config.base_url(), key),
+                    key: key,
+                    summary: summary,
+                }
+            }
+            None => ReleaseIssue {
+                key: key,
+                summary: String::new(),
+                url: String::new(),
+            },
+        })
+        .collect();
+
+    Ok(ReleaseNotes {
+        repo: repo.full_name.clone(),
+        from: from.to_string(),
+        to: to.to_string(),
+        groups: group_by_label(pull_requests),
+        issues: issues,
+        contributors: contributors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(message: &str) -> github::PushCommit {
+        github::PushCommit {
+            message: message.into(),
+            ..github::PushCommit::new()
+        }
+    }
+
+    fn pr(number: u32, labels: Vec<&str>) -> ReleasePullRequest {
+        ReleasePullRequest {
+            number: number,
+            title: format!("PR {}", number),
+            html_url: format!("http://the-pr/{}", number),
+            author: "joe".into(),
+            labels: labels.into_iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_merged_pr_numbers() {
+        let commits = vec![
+            commit("Merge pull request #12 from some-user/some-branch\n\nThe title"),
+            commit("Fix the thing (#15)\n\n* SER-1 commit one\n* commit two"),
+            commit("Direct push mentioning (#99) in passing"),
+            commit("Merge pull request #12 from some-user/some-branch"),
+            commit("Bump version"),
+        ];
+        assert_eq!(vec![12, 15], merged_pr_numbers(&commits));
+    }
+
+    #[test]
+    fn test_group_by_label() {
+        let groups = group_by_label(vec![
+            pr(3, vec![]),
+            pr(2, vec!["feature", "bug"]),
+            pr(1, vec!["bug"]),
+            pr(4, vec!["Feature"]),
+        ]);
+        assert_eq!(
+            vec![
+                ReleaseGroup {
+                    label: "bug".into(),
+                    pull_requests: vec![pr(1, vec!["bug"])],
+                },
+                ReleaseGroup {
+                    label: "feature".into(),
+                    pull_requests: vec![pr(2, vec!["feature", "bug"])],
+                },
+                ReleaseGroup {
+                    label: "Feature".into(),
+                    pull_requests: vec![pr(4, vec!["Feature"])],
+                },
+                ReleaseGroup {
+                    label: UNLABELED.into(),
+                    pull_requests: vec![pr(3, vec![])],
+                },
+            ],
+            groups
+        );
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let notes = ReleaseNotes {
+            repo: "some-user/some-repo".into(),
+            from: "v1.0".into(),
+            to: "v1.1".into(),
+            groups: group_by_label(vec![pr(2, vec![]), pr(1, vec!["bug"])]),
+            issues: vec![
+                ReleaseIssue {
+                    key: "SER-1".into(),
+                    summary: "The bug".into(),
+                    url: "https://the-jira/browse/SER-1".into(),
+                },
+                ReleaseIssue {
+                    key: "SER-2".into(),
+                    summary: String::new(),
+                    url: "https://the-jira/browse/SER-2".into(),
+                },
+            ],
+            contributors: vec!["joe".into(), "sue".into()],
+        };
+
+        assert_eq!(
+            "### bug\n\n* PR 1 (#1) by @joe\n\n### Other\n\n* PR 2 (#2) by @joe\n\n\
+             ### JIRA issues\n\n* [SER-1](https://the-jira/browse/SER-1): The bug\n\
+             * [SER-2](https://the-jira/browse/SER-2)\n\n\
+             ### Contributors\n\n@joe, @sue\n",
+            notes.to_markdown()
+        );
+
+        let empty = ReleaseNotes {
+            groups: vec![],
+            issues: vec![],
+            contributors: vec![],
+            ..notes
+        };
+        assert_eq!("No changes between v1.0 and v1.1\n", empty.to_markdown());
+    }
+}
@@ -0,0 +1,339 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use log::{error, info};
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::git::Git;
+use crate::git_clone_manager::GitCloneManager;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::jira;
+use crate::messenger;
+use crate::slack::{SlackAttachment, SlackAttachmentBuilder, SlackRequest};
+use crate::tag_protection;
+use crate::util;
+use crate::version::Version;
+use crate::worker;
+
+const BRANCH_PREFIX: &str = "refs/heads/";
+const REMOTE_BRANCH_PREFIX: &str = "refs/remotes/origin/";
+
+// The version a release branch (e.g. "release/1.2" -> 1.2.0) or version tag (e.g. "v1.2.3") releases
+pub fn release_version(ref_name: &str, release_branch_prefix: &str) -> Option<Version> {
+    let name = match tag_protection::tag_name(ref_name) {
+        Some(tag) => tag.trim_start_matches('v'),
+        None => {
+            if !ref_name.starts_with(BRANCH_PREFIX) {
+                return None;
+            }
+            let branch = &ref_name[BRANCH_PREFIX.len()..];
+            if release_branch_prefix.is_empty() || !branch.starts_with(release_branch_prefix) {
+                return None;
+            }
+            &branch[release_branch_prefix.len()..]
+        }
+    };
+    Version::parse(name)
+}
+
+// "refs/tags/v1.2.0" -> "v1.2.0", "refs/remotes/origin/release/1.2" -> "release/1.2"
+pub fn short_ref_name(ref_name: &str) -> &str {
+    if let Some(tag) = tag_protection::tag_name(ref_name) {
+        tag
+    } else if ref_name.starts_with(REMOTE_BRANCH_PREFIX) {
+        &ref_name[REMOTE_BRANCH_PREFIX.len()..]
+    } else if ref_name.starts_with(BRANCH_PREFIX) {
+        &ref_name[BRANCH_PREFIX.len()..]
+    } else {
+        ref_name
+    }
+}
+
+// Of a clone's tags and origin's branches (full ref names), the release before |version|.
+// A tag wins over a release branch for the same version since it marks what actually shipped.
+pub fn previous_release(version: &Version, refs: &Vec<String>, release_branch_prefix: &str) -> Option<String> {
+    let mut releases = refs
+        .iter()
+        .filter_map(|r| {
+            let local = if r.starts_with(REMOTE_BRANCH_PREFIX) {
+                format!("{}{}", BRANCH_PREFIX, &r[REMOTE_BRANCH_PREFIX.len()..])
+            } else {
+                r.clone()
+            };
+            let is_tag = tag_protection::tag_name(&local).is_some();
+            release_version(&local, release_branch_prefix)
+                .filter(|v| v < version)
+                .map(|v| (v, is_tag, r.clone()))
+        })
+        .collect::<Vec<_>>();
+
+    releases.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+    releases.pop().map(|(_, _, r)| r)
+}
+
+// The slack message and per-project attachments summarizing what was versioned
+pub fn summary(
+    version: &str,
+    ref_name: &str,
+    previous: Option<&str>,
+    results: &Vec<(String, Result<Vec<String>>)>,
+    jira_base_url: &str,
+) -> (String, Vec<SlackAttachment>) {
+    let msg = match previous {
+        Some(p) => format!(
+            "JIRA version {} for {} (changes since {})",
+            version,
+            short_ref_name(ref_name),
+            short_ref_name(p)
+        ),
+        None => format!(
+            "JIRA version {} for {}: no previous release was found, so no issues were assigned to it",
+            version,
+            short_ref_name(ref_name)
+        ),
+    };
+
+    let attachments = results
+        .iter()
+        .map(|(project, result)| match result {
+            Ok(keys) if keys.is_empty() => {
+                SlackAttachmentBuilder::new("No issues").title(format!("{} {}", project, version)).build()
+            }
+            Ok(keys) => {
+                let links = keys
+                    .iter()
+                    .map(|k| util::make_link(&format!("{}/browse/{}", jira_base_url, k), k))
+                    .collect::<Vec<_>>();
+                SlackAttachmentBuilder::new(&links.join(", "))
+                    .title(format!("{} {}", project, version))
+                    .color("good")
+                    .build()
+            }
+            Err(e) => SlackAttachmentBuilder::new(&format!("{}", e))
+                .title(format!("{} {}", project, version))
+                .color("danger")
+                .build(),
+        })
+        .collect();
+
+    (msg, attachments)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ReleaseVersionRequest {
+    pub repo: github::Repo,
+    pub ref_name: String,
+    pub commit_hash: String,
+}
+
+pub fn req(repo: &github::Repo, ref_name: &str, commit_hash: &str) -> ReleaseVersionRequest {
+    ReleaseVersionRequest {
+        repo: repo.clone(),
+        ref_name: ref_name.to_string(),
+        commit_hash: commit_hash.to_string(),
+    }
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    jira_session: Option<Arc<dyn jira::api::Session>>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    jira_session: Option<Arc<dyn jira::api::Session>>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+) -> Arc<dyn worker::Runner<ReleaseVersionRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+        jira_session: jira_session,
+        clone_mgr: clone_mgr,
+        slack: slack,
+    })
+}
+
+impl Runner {
+    // Only logs JIRA changes if the repo is in dry run mode
+    fn jira_session(&self, repo: &github::Repo) -> Option<Arc<dyn jira::api::Session>> {
+        jira::dry_run::for_repo(self.jira_session.clone(), self.config.dry_run(repo))
+    }
+
+    // The previous release, if any, and the commits made since it
+    fn released_commits(
+        &self,
+        req: &ReleaseVersionRequest,
+        version: &Version,
+        release_branch_prefix: &str,
+    ) -> Result<(Option<String>, Vec<github::PushCommit>)> {
+        let owner = req.repo.owner.login();
+        let session = self.github_app.new_session(owner, &req.repo.name)?;
+        let held_clone_dir = GitCloneManager::clone(&self.clone_mgr, owner, &req.repo.name)?;
+        let git = Git::new(session.github_host(), session.github_token(), held_clone_dir.dir());
+
+        let refs = git.run(&["for-each-ref", "--format=%(refname)", "refs/tags", "refs/remotes/origin"])?;
+        let refs = refs.lines().map(|r| r.to_string()).collect::<Vec<_>>();
+        let previous = match previous_release(version, &refs, release_branch_prefix) {
+            Some(p) => p,
+            None => return Ok((None, vec![])),
+        };
+
+        let commits = git
+            .get_commit_messages(&previous, &req.commit_hash)?
+            .into_iter()
+            .map(|(hash, message)| github::PushCommit {
+                url: format!("{}/commit/{}", req.repo.html_url, hash),
+                id: hash,
+                message: message,
+                ..github::PushCommit::new()
+            })
+            .collect();
+
+        Ok((Some(previous), commits))
+    }
+}
+
+impl worker::Runner<ReleaseVersionRequest> for Runner {
+    fn handle(&self, req: ReleaseVersionRequest) {
+        let (jira_session, jira_config) = match (self.jira_session(&req.repo), &self.config.jira) {
+            (Some(s), Some(c)) => (s, c),
+            _ => return,
+        };
+
+        let release_branch_prefix = self.config.repos().release_branch_prefix(&req.repo);
+        let version = match release_version(&req.ref_name, &release_branch_prefix) {
+            Some(v) => v,
+            None => return,
+        };
+        let projects = self.config.repos().all_jira_projects(&req.repo);
+        if projects.is_empty() {
+            return;
+        }
+
+        // tags aren't on any one branch: they go to the repo's main channel
+        let branch = match tag_protection::tag_name(&req.ref_name) {
+            Some(_) => "",
+            None => short_ref_name(&req.ref_name),
+        };
+        let messenger = messenger::new(self.config.clone(), self.slack.clone());
+
+        let (previous, commits) = match self.released_commits(&req, &version, &release_branch_prefix) {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Error finding the commits released by {} {}: {}", req.repo.full_name, req.ref_name, e);
+
+                let attach = SlackAttachmentBuilder::new(&format!("{}", e)).color("danger").build();
+                let msg = format!("Error creating JIRA version {} for {}", version, short_ref_name(&req.ref_name));
+                messenger.send_to_channel(&msg, &vec![attach], &req.repo, branch, &Vec::<github::PushCommit>::new());
+                return;
+            }
+        };
+
+        let version = version.to_string();
+        info!("Versioning {} commits of {} {} as {}", commits.len(), req.repo.full_name, req.ref_name, version);
+        let shas = commits.iter().map(|c| c.id.clone()).collect::<Vec<_>>();
+        if let Err(e) = self.config.commit_index.set_version(&req.repo.full_name, &shas, &version) {
+            error!("{}", e);
+        }
+        let results = jira::workflow::assign_release_version(&version, &commits, &projects, jira_session.deref());
+
+        let (msg, attachments) =
+            summary(&version, &req.ref_name, previous.as_ref().map(|p| p.as_str()), &results, &jira_config.base_url());
+        messenger.send_to_channel(&msg, &attachments, &req.repo, branch, &commits);
+    }
+
+    fn repo(&self, req: &ReleaseVersionRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &ReleaseVersionRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::format_err;
+
+    #[test]
+    fn test_release_version() {
+        assert_eq!(Version::parse("1.2.0"), release_version("refs/heads/release/1.2", "release/"));
+        assert_eq!(Version::parse("1.2.3"), release_version("refs/tags/v1.2.3", "release/"));
+        assert_eq!(Version::parse("1.2.3"), release_version("refs/tags/1.2.3", "release/"));
+        assert_eq!(Version::parse("2.0.0"), release_version("refs/heads/rel-2.0", "rel-"));
+
+        assert_eq!(None, release_version("refs/heads/release/next", "release/"));
+        assert_eq!(None, release_version("refs/heads/master", "release/"));
+        assert_eq!(None, release_version("refs/heads/1.2", ""));
+        assert_eq!(None, release_version("refs/tags/latest", "release/"));
+    }
+
+    #[test]
+    fn test_previous_release() {
+        let refs = vec![
+            "refs/remotes/origin/HEAD".to_string(),
+            "refs/remotes/origin/master".to_string(),
+            "refs/remotes/origin/release/1.1".to_string(),
+            "refs/remotes/origin/release/1.2".to_string(),
+            "refs/remotes/origin/release/1.3".to_string(),
+            "refs/tags/v1.1.0".to_string(),
+            "refs/tags/v1.1.4".to_string(),
+            "refs/tags/v1.2.0".to_string(),
+            "refs/tags/nightly".to_string(),
+        ];
+
+        let previous = |v: &str| previous_release(&Version::parse(v).unwrap(), &refs, "release/");
+
+        // the tag is what shipped for 1.2.0
+        assert_eq!(Some("refs/tags/v1.2.0".to_string()), previous("1.3"));
+        assert_eq!(Some("refs/tags/v1.2.0".to_string()), previous("1.2.1"));
+        assert_eq!(Some("refs/tags/v1.1.4".to_string()), previous("1.2.0"));
+        assert_eq!(Some("refs/remotes/origin/release/1.3".to_string()), previous("1.4"));
+        assert_eq!(None, previous("1.1"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let results = vec![
+            ("SER".to_string(), Ok(vec!["SER-1".to_string(), "SER-2".to_string()])),
+            ("CLI".to_string(), Ok(vec![])),
+            ("OPS".to_string(), Err(format_err!("No such project"))),
+        ];
+
+        let (msg, attachments) = summary(
+            "1.3.0",
+            "refs/heads/release/1.3",
+            Some("refs/tags/v1.2.0"),
+            &results,
+            "https://the-jira",
+        );
+        assert_eq!("JIRA version 1.3.0 for release/1.3 (changes since v1.2.0)", msg);
+        assert_eq!(
+            vec![
+                SlackAttachmentBuilder::new(
+                    "<https://the-jira/browse/SER-1|SER-1>, <https://the-jira/browse/SER-2|SER-2>"
+                )
+                .title("SER 1.3.0")
+                .color("good")
+                .build(),
+                SlackAttachmentBuilder::new("No issues").title("CLI 1.3.0").build(),
+                SlackAttachmentBuilder::new("No such project").title("OPS 1.3.0").color("danger").build(),
+            ],
+            attachments
+        );
+
+        let (msg, _) = summary("1.0.0", "refs/tags/v1.0.0", None, &vec![], "https://the-jira");
+        assert_eq!(
+            "JIRA version 1.0.0 for v1.0.0: no previous release was found, so no issues were assigned to it",
+            msg
+        );
+    }
+}
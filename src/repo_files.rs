@@ -0,0 +1,302 @@
+use failure::format_err;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use toml;
+
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::github::api::Session;
+use crate::label_rules;
+use crate::repos::{RepoInfo, RepoJiraConfig, RepoLabelRule, RepoPathLabel, RepoRoutingRule};
+
+// Repos can carry some of their own settings in this file at their root, on their main branch
+pub const FILE_NAME: &str = ".octobot.toml";
+
+// The settings a repo's file can change. Settings left out keep the repo's (or its org's) config in octobot:
+// lists replace octobot's whole list, so an empty one clears it.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RepoFileConfig {
+    pub channel: Option<String>,
+    // branches that backports go to start with this. e.g. "release/"
+    pub release_branch_prefix: Option<String>,
+    pub jira: Option<Vec<RepoFileJira>>,
+    pub routing_rules: Option<Vec<RepoRoutingRule>>,
+    pub path_labels: Option<Vec<RepoPathLabel>>,
+    pub label_rules: Option<Vec<RepoLabelRule>>,
+}
+
+// A JIRA project of the repo. Version scripts run on octobot's hosts, so they can only be set in octobot itself.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RepoFileJira {
+    pub project: String,
+    #[serde(default)]
+    pub channel: String,
+    #[serde(default)]
+    pub release_branch_regex: String,
+}
+
+impl RepoFileConfig {
+    pub fn parse(contents: &str) -> Result<RepoFileConfig> {
+        let config = toml::from_str::<RepoFileConfig>(contents)
+            .map_err(|e| format_err!("Error parsing {}: {}", FILE_NAME, e))?;
+
+        for jira in config.jira.iter().flatten() {
+            if jira.project.is_empty() {
+                return Err(format_err!("Error in {}: jira projects need a `project`", FILE_NAME));
+            }
+            if !jira.release_branch_regex.is_empty() {
+                Regex::new(&jira.release_branch_regex).map_err(|e| {
+                    format_err!("Error in {}: invalid release_branch_regex for {}: {}", FILE_NAME, jira.project, e)
+                })?;
+            }
+        }
+        let unrouted = |r: &RepoRoutingRule| r.channel_list().is_empty() && r.opsgenie.trim().is_empty() && !r.silent;
+        if config.routing_rules.iter().flatten().any(unrouted) {
+            return Err(format_err!("Error in {}: routing rules need `channels`, `opsgenie` or `silent`", FILE_NAME));
+        }
+        if config.path_labels.iter().flatten().any(|l| l.path.is_empty() || l.label.is_empty()) {
+            return Err(format_err!("Error in {}: path labels need a `path` and a `label`", FILE_NAME));
+        }
+        if let Some(problem) = config.label_rules.iter().flatten().filter_map(label_rules::check).next() {
+            return Err(format_err!("Error in {}: {}", FILE_NAME, problem));
+        }
+
+        Ok(config)
+    }
+
+    pub fn apply(&self, info: RepoInfo) -> RepoInfo {
+        let mut info = info;
+        if let Some(ref channel) = self.channel {
+            info.channel = channel.clone();
+        }
+        if let Some(ref prefix) = self.release_branch_prefix {
+            info.release_branch_prefix = prefix.clone();
+        }
+        if let Some(ref jira) = self.jira {
+            let jira_config = jira
+                .iter()
+                .map(|j| RepoJiraConfig {
+                    jira_project: j.project.clone(),
+                    version_script: info
+                        .jira_config
+                        .iter()
+                        .find(|c| c.jira_project == j.project)
+                        .map(|c| c.version_script.clone())
+                        .unwrap_or_default(),
+                    channel: j.channel.clone(),
+                    release_branch_regex: j.release_branch_regex.clone(),
+                })
+                .collect();
+            info.jira_config = jira_config;
+        }
+        if let Some(ref rules) = self.routing_rules {
+            info.routing_rules = rules.clone();
+        }
+        if let Some(ref labels) = self.path_labels {
+            info.path_labels = labels.clone();
+        }
+        if let Some(ref rules) = self.label_rules {
+            info.label_rules = rules.clone();
+        }
+        info
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RepoFile {
+    // "owner/name"
+    pub repo: String,
+    // empty if the repo doesn't have one
+    pub contents: String,
+    // why the contents aren't used, if they aren't
+    pub error: String,
+    pub fetched_at: i64,
+}
+
+// The last fetched file of each repo, so that it isn't fetched for every event
+pub struct RepoFiles {
+    db: Database,
+}
+
+impl RepoFiles {
+    pub fn new(db: Database) -> RepoFiles {
+        RepoFiles { db: db }
+    }
+
+    pub fn get(&self, repo: &str) -> Result<Option<RepoFile>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare("SELECT repo, contents, error, fetched_at FROM repo_files WHERE repo = ?1")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(&[&repo])?;
+
+        if let Ok(Some(row)) = rows.next() {
+            return Ok(Some(RepoFile {
+                repo: cols.get(row, "repo")?,
+                contents: cols.get(row, "contents")?,
+                error: cols.get(row, "error")?,
+                fetched_at: cols.get(row, "fetched_at")?,
+            }));
+        }
+        Ok(None)
+    }
+
+    pub fn set(&self, repo: &str, contents: &str, now: i64) -> Result<RepoFile> {
+        let error = match RepoFileConfig::parse(contents) {
+            Ok(_) => String::new(),
+            Err(e) => format!("{}", e),
+        };
+
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO repo_files (repo, contents, error, fetched_at)
+               VALUES (?1, ?2, ?3, ?4)
+               ON CONFLICT (repo) DO UPDATE SET contents = excluded.contents, error = excluded.error,
+                   fetched_at = excluded.fetched_at"#,
+            &[&repo as &dyn ToSql, &contents, &error, &now],
+        )
+        .map_err(|e| format_err!("Error saving {} of {}: {}", FILE_NAME, repo, e))?;
+
+        Ok(RepoFile {
+            repo: repo.to_string(),
+            contents: contents.to_string(),
+            error: error,
+            fetched_at: now,
+        })
+    }
+
+    // The repo's settings, if it has a valid file
+    pub fn lookup(&self, repo: &str) -> Result<Option<RepoFileConfig>> {
+        match self.get(repo)? {
+            Some(ref file) if file.error.is_empty() => Ok(Some(RepoFileConfig::parse(&file.contents)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn is_not_found(e: &Error) -> bool {
+    format!("{}", e).contains("404 Not Found")
+}
+
+// Fetches the repo's file from |branch|. Repos without one are saved without contents, so that they aren't
+// fetched again until a push touches the file.
+pub fn refresh(
+    github: &dyn Session,
+    files: &RepoFiles,
+    repo: &github::Repo,
+    branch: &str,
+    now: i64,
+) -> Result<RepoFile> {
+    let contents = match github.get_file_contents(&repo.owner.login(), &repo.name, FILE_NAME, branch) {
+        Ok(c) => c,
+        Err(ref e) if is_not_found(e) => String::new(),
+        Err(e) => return Err(e),
+    };
+    files.set(&repo.full_name, &contents, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (RepoFiles, TempDir) {
+        let temp_dir = TempDir::new("repo_files.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        (RepoFiles::new(db), temp_dir)
+    }
+
+    const CONTENTS: &str = r#"
+channel = "the-reviews"
+release_branch_prefix = "rel/"
+path_labels = []
+
+[[jira]]
+project = "SER"
+channel = "ser-reviews"
+release_branch_regex = "rel/2.*"
+
+[[routing_rules]]
+path = "docs/**"
+channels = "docs"
+
+[[label_rules]]
+label = "automerge"
+merge = true
+"#;
+
+    #[test]
+    fn test_parse() {
+        let config = RepoFileConfig::parse(CONTENTS).unwrap();
+        assert_eq!(Some("the-reviews".to_string()), config.channel);
+        assert_eq!(Some(vec![]), config.path_labels);
+        assert_eq!(Some(vec![RepoRoutingRule::new("", "docs/**", "", "docs")]), config.routing_rules);
+
+        assert_eq!(RepoFileConfig::default(), RepoFileConfig::parse("").unwrap());
+
+        let err = RepoFileConfig::parse("version_script = \"rm -rf /\"").unwrap_err();
+        assert!(format!("{}", err).contains("unknown field `version_script`"), "{}", err);
+        let err = RepoFileConfig::parse("[[jira]]\nproject = \"SER\"\nversion_script = \"./version.sh\"").unwrap_err();
+        assert!(format!("{}", err).contains("unknown field `version_script`"), "{}", err);
+        assert!(RepoFileConfig::parse("[[jira]]\nproject = \"SER\"\nrelease_branch_regex = \"(\"").is_err());
+        assert!(RepoFileConfig::parse("[[routing_rules]]\npath = \"docs/**\"").is_err());
+        let silent = RepoFileConfig::parse("[[routing_rules]]\nhead = \"feature/*\"\nsilent = true").unwrap();
+        let rule = RepoRoutingRule::new("", "", "", "").with_head("feature/*").silenced();
+        assert_eq!(Some(vec![rule]), silent.routing_rules);
+        assert!(RepoFileConfig::parse("[[path_labels]]\npath = \"docs/**\"").is_err());
+        assert_eq!(Some(vec![RepoLabelRule::new("automerge").merging()]), config.label_rules);
+        let err = RepoFileConfig::parse("[[label_rules]]\nlabel = \"security\"").unwrap_err();
+        assert!(format!("{}", err).contains("label rule 'security' needs"), "{}", err);
+    }
+
+    #[test]
+    fn test_apply() {
+        let info = RepoInfo::new("some-org", "org-reviews")
+            .with_jira_config(RepoJiraConfig::new("SER").with_version_script("./version.sh"))
+            .with_path_label("**/*.sql", "database")
+            .with_force_push(true);
+
+        let info = RepoFileConfig::parse(CONTENTS).unwrap().apply(info);
+        assert_eq!("some-org", info.repo);
+        assert_eq!("the-reviews", info.channel);
+        assert_eq!("rel/", info.release_branch_prefix);
+        assert!(info.path_labels.is_empty());
+        assert_eq!(1, info.routing_rules.len());
+        assert_eq!(1, info.label_rules.len());
+        assert!(info.force_push_notify);
+
+        assert_eq!(1, info.jira_config.len());
+        assert_eq!("SER", info.jira_config[0].jira_project);
+        assert_eq!("ser-reviews", info.jira_config[0].channel);
+        assert_eq!("rel/2.*", info.jira_config[0].release_branch_regex);
+        // kept from octobot's config
+        assert_eq!("./version.sh", info.jira_config[0].version_script);
+
+        let unchanged = RepoFileConfig::default().apply(RepoInfo::new("some-org", "org-reviews"));
+        assert_eq!("org-reviews", unchanged.channel);
+    }
+
+    #[test]
+    fn test_repo_files() {
+        let (files, _temp) = new_test();
+        assert_eq!(None, files.get("some-org/some-repo").unwrap());
+
+        let file = files.set("some-org/some-repo", "channel = \"the-reviews\"", 10).unwrap();
+        assert_eq!("", file.error);
+        assert_eq!(Some(file), files.get("some-org/some-repo").unwrap());
+        assert_eq!(
+            Some("the-reviews".to_string()),
+            files.lookup("some-org/some-repo").unwrap().unwrap().channel
+        );
+
+        let file = files.set("some-org/some-repo", "channel = ", 20).unwrap();
+        assert!(file.error.starts_with("Error parsing .octobot.toml"), "{}", file.error);
+        assert_eq!(None, files.lookup("some-org/some-repo").unwrap());
+
+        files.set("some-org/some-repo", "", 30).unwrap();
+        assert_eq!(Some(RepoFileConfig::default()), files.lookup("some-org/some-repo").unwrap());
+    }
+}
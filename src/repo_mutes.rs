@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::Serialize;
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::messenger;
+use crate::scheduler;
+use crate::slack::{SlackAttachment, SlackAttachmentBuilder, SlackRequest};
+use crate::util;
+use crate::worker;
+
+// how often to look for mutes that have ended
+pub const CHECK_INTERVAL_SECS: u64 = 60;
+
+// Only list the first suppressed messages in the summary, to keep it readable
+const MAX_SUMMARY_MESSAGES: usize = 20;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RepoMute {
+    // "owner/name"
+    pub repo: String,
+    pub start_at: i64,
+    pub until: i64,
+    pub muted_by: String,
+    // how many messages have been held back so far
+    pub suppressed: u32,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SuppressedMessage {
+    pub msg: String,
+    pub created_at: i64,
+}
+
+// Repos whose notifications are held back for a window, e.g. during a planned mass refactoring
+pub struct RepoMutes {
+    db: Database,
+}
+
+impl RepoMutes {
+    pub fn new(db: Database) -> RepoMutes {
+        RepoMutes { db: db }
+    }
+
+    // Replaces any existing window for the repo, keeping the messages it already held back
+    pub fn mute(&self, repo: &str, start_at: i64, until: i64, muted_by: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO repo_mutes (repo, start_at, until, muted_by, created_at)
+               VALUES (?1, ?2, ?3, ?4, ?5)
+               ON CONFLICT (repo) DO UPDATE SET start_at = excluded.start_at, until = excluded.until,
+                   muted_by = excluded.muted_by, created_at = excluded.created_at"#,
+            &[&repo as &dyn ToSql, &start_at, &until, &muted_by, &db::now()],
+        )
+        .map_err(|e| format_err!("Error muting {}: {}", repo, e))?;
+
+        Ok(())
+    }
+
+    // Ends the repo's window now, so that the next check posts its summary. Returns false if it wasn't muted.
+    pub fn end(&self, repo: &str, now: i64) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let changed = conn
+            .execute(
+                r#"UPDATE repo_mutes SET until = ?1, start_at = CASE WHEN start_at < ?1 THEN start_at ELSE ?1 END
+                   WHERE repo = ?2 AND until > ?1"#,
+                &[&now as &dyn ToSql, &repo],
+            )
+            .map_err(|e| format_err!("Error unmuting {}: {}", repo, e))?;
+
+        Ok(changed > 0)
+    }
+
+    pub fn get_all(&self) -> Result<Vec<RepoMute>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT m.repo, m.start_at, m.until, m.muted_by,
+                      (SELECT count(*) FROM repo_muted_messages s WHERE s.repo = m.repo) AS suppressed
+               FROM repo_mutes m ORDER BY m.repo"#,
+        )?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(db::NO_PARAMS)?;
+
+        let mut mutes = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            mutes.push(RepoMute {
+                repo: cols.get(row, "repo")?,
+                start_at: cols.get(row, "start_at")?,
+                until: cols.get(row, "until")?,
+                muted_by: cols.get(row, "muted_by")?,
+                suppressed: cols.get(row, "suppressed")?,
+            });
+        }
+
+        Ok(mutes)
+    }
+
+    pub fn get(&self, repo: &str) -> Result<Option<RepoMute>> {
+        Ok(self.get_all()?.into_iter().find(|m| m.repo == repo))
+    }
+
+    pub fn is_muted(&self, repo: &str, now: i64) -> Result<bool> {
+        Ok(self.get(repo)?.map(|m| m.start_at <= now && now < m.until).unwrap_or(false))
+    }
+
+    // Mutes whose window is over, and whose summary is due
+    pub fn ended(&self, now: i64) -> Result<Vec<RepoMute>> {
+        Ok(self.get_all()?.into_iter().filter(|m| m.until <= now).collect())
+    }
+
+    pub fn suppress(&self, repo: &str, msg: &str, now: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "INSERT INTO repo_muted_messages (repo, msg, created_at) VALUES (?1, ?2, ?3)",
+            &[&repo as &dyn ToSql, &msg, &now],
+        )
+        .map_err(|e| format_err!("Error recording muted message for {}: {}", repo, e))?;
+
+        Ok(())
+    }
+
+    pub fn suppressed(&self, repo: &str) -> Result<Vec<SuppressedMessage>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare("SELECT msg, created_at FROM repo_muted_messages WHERE repo = ?1 ORDER BY id")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(&[&repo])?;
+
+        let mut messages = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            messages.push(SuppressedMessage {
+                msg: cols.get(row, "msg")?,
+                created_at: cols.get(row, "created_at")?,
+            });
+        }
+
+        Ok(messages)
+    }
+
+    // Forgets the mute and the messages it held back
+    pub fn remove(&self, repo: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute("DELETE FROM repo_mutes WHERE repo = ?1", &[&repo])
+            .map_err(|e| format_err!("Error removing mute of {}: {}", repo, e))?;
+        conn.execute("DELETE FROM repo_muted_messages WHERE repo = ?1", &[&repo])
+            .map_err(|e| format_err!("Error removing mute of {}: {}", repo, e))?;
+
+        Ok(())
+    }
+}
+
+// The message posted once a mute ends, listing what it held back
+pub fn summary(mute: &RepoMute, suppressed: &Vec<SuppressedMessage>) -> (String, Vec<SlackAttachment>) {
+    let msg = format!(
+        "Notifications were muted from {} until {}: {} message(s) were not sent",
+        util::format_timestamp(mute.start_at),
+        util::format_timestamp(mute.until),
+        suppressed.len()
+    );
+    if suppressed.is_empty() {
+        return (msg, vec![]);
+    }
+
+    let mut lines = suppressed
+        .iter()
+        .take(MAX_SUMMARY_MESSAGES)
+        .map(|s| format!("• {}: {}", util::format_timestamp(s.created_at), s.msg))
+        .collect::<Vec<_>>();
+    if suppressed.len() > MAX_SUMMARY_MESSAGES {
+        lines.push(format!("…and {} more", suppressed.len() - MAX_SUMMARY_MESSAGES));
+    }
+
+    (msg, vec![SlackAttachmentBuilder::new(&lines.join("\n")).title(format!("Muted by {}", mute.muted_by)).build()])
+}
+
+// Posts the summaries of mutes that have ended, then forgets them
+pub struct MuteExpirer {
+    config: Arc<Config>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+impl MuteExpirer {
+    pub fn new(config: Arc<Config>, slack: Arc<dyn worker::Worker<SlackRequest>>) -> Arc<dyn scheduler::Task> {
+        Arc::new(MuteExpirer {
+            config: config,
+            slack: slack,
+        })
+    }
+
+    fn unmute(&self, mute: &RepoMute) -> Result<()> {
+        let suppressed = self.config.repo_mutes.suppressed(&mute.repo)?;
+        // the summary itself mustn't be held back
+        self.config.repo_mutes.remove(&mute.repo)?;
+        info!("Unmuted {}: {} message(s) were suppressed", mute.repo, suppressed.len());
+
+        let repo = github::Repo::parse(&format!("https://{}/{}", self.config.github_host(&mute.repo), mute.repo))?;
+        let (msg, attachments) = summary(mute, &suppressed);
+        messenger::new(self.config.clone(), self.slack.clone()).send_to_channel(
+            &msg,
+            &attachments,
+            &repo,
+            "",
+            &Vec::<github::PushCommit>::new(),
+        );
+        Ok(())
+    }
+}
+
+impl scheduler::Task for MuteExpirer {
+    fn run(&self, now: i64) -> Result<()> {
+        for mute in self.config.repo_mutes.ended(now)? {
+            if let Err(e) = self.unmute(&mute) {
+                error!("Error unmuting {}: {}", mute.repo, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (RepoMutes, TempDir) {
+        let temp_dir = TempDir::new("repo_mutes.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        (RepoMutes::new(db), temp_dir)
+    }
+
+    #[test]
+    fn test_mute_and_unmute() {
+        let (mutes, _temp_dir) = new_test();
+
+        mutes.mute("some-org/some-repo", 1000, 2000, "joe").unwrap();
+        assert!(!mutes.is_muted("some-org/some-repo", 999).unwrap());
+        assert!(mutes.is_muted("some-org/some-repo", 1000).unwrap());
+        assert!(!mutes.is_muted("some-org/some-repo", 2000).unwrap());
+        assert!(!mutes.is_muted("some-org/other-repo", 1500).unwrap());
+
+        mutes.suppress("some-org/some-repo", "first", 1100).unwrap();
+        mutes.suppress("some-org/some-repo", "second", 1200).unwrap();
+        assert_eq!(2, mutes.get("some-org/some-repo").unwrap().unwrap().suppressed);
+        assert_eq!(
+            vec!["first".to_string(), "second".to_string()],
+            mutes.suppressed("some-org/some-repo").unwrap().into_iter().map(|s| s.msg).collect::<Vec<_>>()
+        );
+
+        assert!(mutes.ended(1999).unwrap().is_empty());
+        assert!(mutes.end("some-org/some-repo", 1500).unwrap());
+        assert!(!mutes.end("some-org/some-repo", 1600).unwrap());
+        assert!(!mutes.end("some-org/other-repo", 1600).unwrap());
+        assert_eq!(1, mutes.ended(1500).unwrap().len());
+
+        mutes.remove("some-org/some-repo").unwrap();
+        assert!(mutes.get_all().unwrap().is_empty());
+        assert!(mutes.suppressed("some-org/some-repo").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_summary() {
+        let mute = RepoMute {
+            repo: "some-org/some-repo".into(),
+            start_at: 0,
+            until: 3600,
+            muted_by: "joe".into(),
+            suppressed: 0,
+        };
+
+        let (msg, attachments) = summary(&mute, &vec![]);
+        assert_eq!(
+            "Notifications were muted from 1970-01-01 00:00 UTC until 1970-01-01 01:00 UTC: 0 message(s) were not sent",
+            msg
+        );
+        assert!(attachments.is_empty());
+
+        let suppressed = (0..22)
+            .map(|i| SuppressedMessage {
+                msg: format!("message {}", i),
+                created_at: 60,
+            })
+            .collect::<Vec<_>>();
+        let (_, attachments) = summary(&mute, &suppressed);
+        let text = attachments[0].text.clone();
+        assert!(text.starts_with("• 1970-01-01 00:01 UTC: message 0\n"));
+        assert!(text.ends_with("• 1970-01-01 00:01 UTC: message 19\n…and 2 more"));
+        assert_eq!(Some("Muted by joe".to_string()), attachments[0].title);
+    }
+}
@@ -9,8 +9,9 @@ use failure::format_err;
 use log;
 #[cfg(target_os = "linux")]
 use log::debug;
-use log::error;
+use log::{error, info};
 
+use crate::components;
 use crate::config::{Config, JiraConfig};
 use crate::errors::*;
 use crate::git::Git;
@@ -18,7 +19,9 @@ use crate::git_clone_manager::GitCloneManager;
 use crate::github;
 use crate::github::api::{GithubSessionFactory, Session};
 use crate::jira;
+use crate::jobs;
 use crate::messenger;
+use crate::repos::RepoComponent;
 use crate::slack::{SlackAttachmentBuilder, SlackRequest};
 use crate::worker;
 
@@ -56,22 +59,59 @@ pub fn comment_repo_version(
     };
 
     // resolve with version
-    jira::workflow::resolve_issue(branch_name, maybe_version, commits, jira_projects, jira, jira_config);
+    jira::workflow::resolve_issue(
+        &format!("{}/{}", owner, repo),
+        branch_name,
+        maybe_version,
+        commits,
+        jira_projects,
+        jira,
+        jira_config,
+    );
 
     jira::workflow::add_pending_version(maybe_version, commits, jira_projects, jira);
 
     Ok(())
 }
 
+// Runs the version scripts of several monorepo components from a single clone.
+// Each script runs from its component's directory.
+pub fn component_versions(
+    github_app: &dyn GithubSessionFactory,
+    clone_mgr: &GitCloneManager,
+    owner: &str,
+    repo: &str,
+    branch_name: &str,
+    commit_hash: &str,
+    components: &Vec<&RepoComponent>,
+) -> Result<Vec<Result<String>>> {
+    let github = github_app.new_session(owner, repo)?;
+    let held_clone_dir = clone_mgr.clone(owner, repo)?;
+    let clone_dir = held_clone_dir.dir();
+
+    let git = Git::new(github.github_host(), github.github_token(), &clone_dir);
+    git.checkout_branch(branch_name, commit_hash)?;
+
+    Ok(components
+        .iter()
+        .map(|c| {
+            if c.path.split('/').any(|p| p == "..") {
+                return Err(format_err!("Component path must be inside the repo: {}", c.path));
+            }
+            run_script(&c.version_script, &clone_dir.join(&c.path))
+        })
+        .collect())
+}
+
 // Only run version scripts on Linux since firejail is only for Linux and it doesn't
 // seem like a good idea to allow generic code execution without any containerization.
 #[cfg(not(target_os = "linux"))]
-fn run_script(_: &str, _: &Path) -> Result<String> {
+pub fn run_script(_: &str, _: &Path) -> Result<String> {
     return Err(format_err!("Version scripts only supported when running Linux."));
 }
 
 #[cfg(target_os = "linux")]
-fn run_script(version_script: &str, clone_dir: &Path) -> Result<String> {
+pub fn run_script(version_script: &str, clone_dir: &Path) -> Result<String> {
     debug!("Running version script: {}", version_script);
     let mut cmd = Command::new("firejail");
     cmd.arg("--quiet")
@@ -198,20 +238,43 @@ pub fn new_runner(
 impl worker::Runner<RepoVersionRequest> for Runner {
     fn handle(&self, req: RepoVersionRequest) {
         let configs;
+        let repo_components;
         {
             let repos_lock = self.config.repos();
             configs = repos_lock.jira_configs(&req.repo, &req.branch);
+            repo_components = repos_lock.components(&req.repo);
+        }
+
+        // monorepos version each component on its own instead of the whole repo
+        if !repo_components.is_empty() {
+            self.handle_components(&req, &repo_components);
+            return;
         }
 
-        if let Some(ref jira_session) = self.jira_session {
+        if let Some(ref jira_session) = self.jira_session(&req.repo) {
             if let Some(ref jira_config) = self.config.jira {
+                // Don't run version scripts for jiras not mentioned
+                let configs = configs
+                    .into_iter()
+                    .filter(|c| jira::workflow::references_jira(&req.commits, &c.jira_project))
+                    .collect::<Vec<_>>();
+                if configs.is_empty() {
+                    return;
+                }
+
+                let job_id = self.start_job(&req, configs.len());
+
                 for config in &configs {
-                    // Don't run version scripts for jiras not mentioned
-                    if !jira::workflow::references_jira(&req.commits, &config.jira_project) {
-                        continue;
+                    if let Some(id) = job_id {
+                        if self.config.jobs.is_cancelled(id) {
+                            info!("Job {} cancelled", id);
+                            self.finish_job(job_id, jobs::STATUS_CANCELLED);
+                            return;
+                        }
                     }
 
                     let mut resolved = false;
+                    let mut message = String::new();
                     let jira = jira_session.borrow();
                     let jira_projects = vec![config.jira_project.clone()];
 
@@ -230,6 +293,7 @@ impl worker::Runner<RepoVersionRequest> for Runner {
                             &jira_projects,
                         ) {
                             error!("Error running version script {}: {}", config.version_script, e);
+                            message = format!("{}", e);
                             let messenger = messenger::new(self.config.clone(), self.slack.clone());
 
                             let attach = SlackAttachmentBuilder::new(&format!("{}", e))
@@ -252,6 +316,7 @@ impl worker::Runner<RepoVersionRequest> for Runner {
                     // resolve the issue with no version if version script is missing or failed
                     if !resolved {
                         jira::workflow::resolve_issue(
+                            &req.repo.full_name,
                             &req.branch,
                             None,
                             &req.commits,
@@ -260,7 +325,195 @@ impl worker::Runner<RepoVersionRequest> for Runner {
                             jira_config,
                         );
                     }
+
+                    if let Some(id) = job_id {
+                        if let Err(e) = self.config.jobs.item_done(id, &config.jira_project, message.is_empty(), &message) {
+                            error!("Error updating job {}: {}", id, e);
+                        }
+                    }
                 }
+
+                self.finish_job(job_id, jobs::STATUS_COMPLETED);
+            }
+        }
+    }
+
+    fn repo(&self, req: &RepoVersionRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &RepoVersionRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
+}
+
+impl Runner {
+    // Only logs JIRA changes if the repo is in dry run mode
+    fn jira_session(&self, repo: &github::Repo) -> Option<Arc<dyn jira::api::Session>> {
+        jira::dry_run::for_repo(self.jira_session.clone(), self.config.dry_run(repo))
+    }
+
+    fn handle_components(&self, req: &RepoVersionRequest, repo_components: &Vec<RepoComponent>) {
+        let touched = repo_components
+            .iter()
+            .filter(|c| !c.version_script.is_empty())
+            .map(|c| (c, components::commits_touching(c, &req.commits)))
+            .filter(|(_, commits)| !commits.is_empty())
+            .collect::<Vec<_>>();
+        if touched.is_empty() {
+            return;
+        }
+
+        let job_id = self.start_job(req, touched.len());
+        let messenger = messenger::new(self.config.clone(), self.slack.clone());
+
+        let versions = match component_versions(
+            self.github_app.borrow(),
+            self.clone_mgr.borrow(),
+            &req.repo.owner.login(),
+            &req.repo.name,
+            &req.branch,
+            &req.commit_hash,
+            &touched.iter().map(|(c, _)| *c).collect(),
+        ) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Error getting component versions for {}: {}", req.repo.full_name, e);
+                self.finish_job(job_id, jobs::STATUS_FAILED);
+                return;
+            }
+        };
+
+        for ((component, commits), version) in touched.into_iter().zip(versions) {
+            if let Some(id) = job_id {
+                if self.config.jobs.is_cancelled(id) {
+                    info!("Job {} cancelled", id);
+                    self.finish_job(job_id, jobs::STATUS_CANCELLED);
+                    return;
+                }
+            }
+
+            let mut message = String::new();
+            let version = match version {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    error!("Error running version script for component {}: {}", component.name, e);
+                    message = format!("{}", e);
+
+                    let attach = SlackAttachmentBuilder::new(&message)
+                        .title(component.version_script.clone())
+                        .color("danger")
+                        .build();
+                    messenger.send_to_channel(
+                        &format!("Error running version script for component [{}]", component.name),
+                        &vec![attach],
+                        &req.repo,
+                        &req.branch,
+                        &commits,
+                    );
+                    None
+                }
+            };
+
+            if let Some(ref version) = version {
+                self.record_component_version(&messenger, req, component, version, &commits);
+            }
+            self.resolve_component_jiras(req, component, version.as_ref().map(|v| v.as_str()), &commits);
+
+            if let Some(id) = job_id {
+                if let Err(e) = self.config.jobs.item_done(id, &component.name, message.is_empty(), &message) {
+                    error!("Error updating job {}: {}", id, e);
+                }
+            }
+        }
+
+        self.finish_job(job_id, jobs::STATUS_COMPLETED);
+    }
+
+    // Posts release notes to the repo's channel whenever a component's version changes
+    fn record_component_version(
+        &self,
+        messenger: &messenger::Messenger,
+        req: &RepoVersionRequest,
+        component: &RepoComponent,
+        version: &str,
+        commits: &Vec<github::PushCommit>,
+    ) {
+        let previous = match self.config.component_versions.record(
+            &req.repo.full_name,
+            &component.name,
+            &req.branch,
+            version,
+            &req.commit_hash,
+        ) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+
+        if previous.as_ref().map(|p| p.as_str()) == Some(version) {
+            return;
+        }
+
+        let notes = components::release_notes(component, version, commits);
+        messenger.send_to_channel(
+            &format!("New version of {} on {}", component.name, req.branch),
+            &vec![SlackAttachmentBuilder::new(&notes).build()],
+            &req.repo,
+            &req.branch,
+            commits,
+        );
+    }
+
+    // Only the commits that touched the component get its version as their fix version
+    fn resolve_component_jiras(
+        &self,
+        req: &RepoVersionRequest,
+        component: &RepoComponent,
+        version: Option<&str>,
+        commits: &Vec<github::PushCommit>,
+    ) {
+        let (jira, jira_config) = match (self.jira_session(&req.repo), &self.config.jira) {
+            (Some(s), Some(c)) => (s, c),
+            _ => return,
+        };
+
+        let jira_projects = if component.jira_project.is_empty() {
+            self.config.repos().jira_projects(&req.repo, &req.branch)
+        } else {
+            vec![component.jira_project.clone()]
+        };
+        if jira_projects.is_empty() {
+            return;
+        }
+
+        let jira = jira.borrow();
+        jira::workflow::resolve_issue(&req.repo.full_name, &req.branch, version, commits, &jira_projects, jira, jira_config);
+        jira::workflow::add_pending_version(version, commits, &jira_projects, jira);
+    }
+
+    fn start_job(&self, req: &RepoVersionRequest, num_items: usize) -> Option<i32> {
+        let desc = format!(
+            "Resolve JIRAs for {} {} ({})",
+            req.repo.full_name,
+            req.branch,
+            github::Commit::short_hash_str(&req.commit_hash)
+        );
+        match self.config.jobs.start("repo-version", &desc, num_items as u32) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                error!("Error recording job: {}", e);
+                None
+            }
+        }
+    }
+
+    fn finish_job(&self, job_id: Option<i32>, status: &str) {
+        if let Some(id) = job_id {
+            if let Err(e) = self.config.jobs.finish(id, status) {
+                error!("Error finishing job {}: {}", id, e);
             }
         }
     }
@@ -1,13 +1,18 @@
 use failure::format_err;
 use log::error;
-use rusqlite::types::ToSql;
-use rusqlite::{Connection, Row, Transaction};
 use serde_derive::{Deserialize, Serialize};
 
-use crate::db::{self, Database};
+use crate::db::{self, Connection, Database, Row, ToSql, Transaction};
 use crate::errors::*;
 use crate::github;
+use crate::commit_lint::LintRules;
+use crate::ecosystem::Ecosystem;
 use crate::jira;
+use crate::merge_gate::GateRules;
+use crate::merge_strategy::{self, MergeMethod};
+use crate::repo_files::{self, RepoFiles};
+use crate::routing::{self, RouteContext};
+use crate::size_labels::SizeLabelConfig;
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct RepoInfo {
@@ -23,6 +28,251 @@ pub struct RepoInfo {
     // Used for backporting. Defaults to "release/"
     #[serde(default)]
     pub release_branch_prefix: String,
+    // Request reviews from the owners listed in the repo's CODEOWNERS file when PRs are opened
+    #[serde(default)]
+    pub codeowners_reviews: bool,
+    // Skip CODEOWNERS review requests for PRs opened by bots
+    #[serde(default)]
+    pub codeowners_ignore_bots: bool,
+    // Labels to apply to PRs based on the paths they change
+    #[serde(default)]
+    pub path_labels: Vec<RepoPathLabel>,
+    // Send messages matching these rules to their channels instead of the repo's channel
+    #[serde(default)]
+    pub routing_rules: Vec<RepoRoutingRule>,
+    // How automatic merges and backports are done, by target branch. The first matching one wins.
+    #[serde(default)]
+    pub merge_strategies: Vec<RepoMergeStrategy>,
+    // What to do when a label is added to an open PR
+    #[serde(default)]
+    pub label_rules: Vec<RepoLabelRule>,
+    // Label PRs by size: "size/XS" through "size/XL"
+    #[serde(default)]
+    pub size_labels: bool,
+    // Comma-separated upper bounds (in changed lines) for XS, S, M, and L. Defaults to "10,30,100,500"
+    #[serde(default)]
+    pub size_label_thresholds: String,
+    // Comma-separated globs of generated files that don't count towards PR size. e.g. "**/*.lock"
+    #[serde(default)]
+    pub size_label_excludes: String,
+    // Remind reviewers about PRs without review activity for this many days. 0 disables reminders.
+    #[serde(default)]
+    pub stale_pr_days: u32,
+    // Also post a digest of stale PRs to the repo's channel
+    #[serde(default)]
+    pub stale_pr_digest: bool,
+    // Comma-separated days to skip reminders on. e.g. "Sat,Sun"
+    #[serde(default)]
+    pub stale_pr_quiet_days: String,
+    // Require PR titles and commit messages to follow conventional commit format
+    #[serde(default)]
+    pub lint_conventional: bool,
+    // A regex that PR titles must match
+    #[serde(default)]
+    pub lint_title_regex: String,
+    // A regex that the first line of each commit message must match
+    #[serde(default)]
+    pub lint_commit_regex: String,
+    // Also mark lint violations with a failing check run
+    #[serde(default)]
+    pub lint_check_run: bool,
+    // Webhook secret for deliveries from this repo/org. Falls back to the global secret if empty. It is write-only:
+    // it is serialized masked, and updates that leave it empty or masked keep the stored one.
+    #[serde(default, serialize_with = "mask_secret")]
+    pub webhook_secret: String,
+    // DM PR authors when their PR starts to conflict with its base branch
+    #[serde(default)]
+    pub conflict_notify: bool,
+    // Comma-separated extra channels that subscribed to this repo with `/octobot subscribe`
+    #[serde(default)]
+    pub subscribed_channels: String,
+    // Post updates about a PR as replies in a thread under its first channel message
+    #[serde(default)]
+    pub slack_threads: bool,
+    // cargo, npm or go: detected when the repo is added, and picks the defaults for the settings below
+    #[serde(default)]
+    pub ecosystem: String,
+    // Comma-separated files that hold the version. Empty means the ecosystem default
+    #[serde(default)]
+    pub version_files: String,
+    // Comma-separated lockfile names: PRs only changing these are dependency updates.
+    // Empty means the ecosystem default
+    #[serde(default)]
+    pub lockfiles: String,
+    // Empty means the ecosystem default
+    #[serde(default)]
+    pub changelog_file: String,
+    // Independently-versioned parts of a monorepo
+    #[serde(default)]
+    pub components: Vec<RepoComponent>,
+    // Post a "daily" or "weekly" summary of open PRs and CI failures to the channel. Empty disables it
+    #[serde(default)]
+    pub channel_digest: String,
+    // Submodules pinned to upstream repos: octobot opens a PR bumping the pin when upstream tags a release
+    #[serde(default)]
+    pub submodules: Vec<RepoSubmodule>,
+    // Comma-separated upstream repos this repo depends on besides its submodules, e.g. from its manifest
+    #[serde(default)]
+    pub depends_on: String,
+    // attach an SBOM to each release and record it in the ledger
+    #[serde(default)]
+    pub sbom: bool,
+    // script that prints the SBOM of a release. not needed if CI already attaches one
+    #[serde(default)]
+    pub sbom_script: String,
+    // record a provenance attestation (merged PRs, approvals, checks, sign-offs) for each release
+    #[serde(default)]
+    pub provenance: bool,
+    // apply JIRA smart commit commands (`SER-1 #time 2h #comment ...`) in pushed commits
+    #[serde(default)]
+    pub smart_commits: bool,
+    // Comma-separated smart commit commands to allow, e.g. "comment,time,resolve". Empty means "comment,time"
+    #[serde(default)]
+    pub smart_commit_commands: String,
+    // Mirror replies in the PR's slack thread to the PR as comments. Needs slack_threads
+    #[serde(default)]
+    pub slack_pr_bridge: bool,
+    // Create a JIRA version for each release branch or version tag and assign it to the issues it releases
+    #[serde(default)]
+    pub jira_release_versions: bool,
+    // Suggest a slack huddle in the PR's thread after this much back and forth in review comments (0: never)
+    #[serde(default)]
+    pub huddle_comments: u32,
+    // DM the authors of the lines that reverts and hotfixes change
+    #[serde(default)]
+    pub blame_notify: bool,
+    // Comma-separated labels a PR needs before the `octobot/gate` status passes, e.g. "qa-approved"
+    #[serde(default)]
+    pub gate_required_labels: String,
+    // Comma-separated approvals the gate needs from teams of the repo's org, e.g. "backend:2,qa" (one by default)
+    #[serde(default)]
+    pub gate_team_approvals: String,
+    // The gate also needs every review thread to be resolved
+    #[serde(default)]
+    pub gate_resolved_threads: bool,
+    // "on" only logs the slack messages, JIRA changes and git pushes octobot would make for the repo, "off" makes
+    // them even when main.dry_run is set. Empty follows main.dry_run
+    #[serde(default)]
+    pub dry_run: String,
+    // When the repo was (soft) deleted. Deleted repos can be restored until they are purged.
+    #[serde(default)]
+    pub deleted_at: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RepoPathLabel {
+    // A glob matched against changed file paths. e.g. "docs/**" or "**/*.sql"
+    #[serde(default)]
+    pub path: String,
+
+    // The label to apply when any changed file matches
+    #[serde(default)]
+    pub label: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RepoRoutingRule {
+    // A glob matched against the base branch. e.g. "release/*"
+    #[serde(default)]
+    pub branch: String,
+
+    // A glob matched against the PR's head branch. e.g. "feature/*"
+    #[serde(default)]
+    pub head: String,
+
+    // A glob matched against the PR's changed file paths. e.g. "docs/**"
+    #[serde(default)]
+    pub path: String,
+
+    // A label the PR must have
+    #[serde(default)]
+    pub label: String,
+
+    // Comma-separated channels to send matching messages to
+    #[serde(default)]
+    pub channels: String,
+
+    // The Opsgenie team to page for critical alerts on matching branches, or "escalation:<policy>"
+    #[serde(default)]
+    pub opsgenie: String,
+
+    // Matching messages go to no routed or repo channel. Subscribed channels still get them.
+    #[serde(default)]
+    pub silent: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RepoMergeStrategy {
+    // A glob matched against the branch merged into. Empty matches any branch.
+    #[serde(default)]
+    pub branch: String,
+
+    // "squash", "rebase", or "merge"
+    #[serde(default)]
+    pub strategy: String,
+
+    // Handlebars template for the squash or merge commit message: its first line is the title.
+    // Empty keeps the default message.
+    #[serde(default)]
+    pub commit_template: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RepoLabelRule {
+    // The label that fires the rule when it is added to an open PR, e.g. "needs-security-review"
+    #[serde(default)]
+    pub label: String,
+
+    // Request a review from this team of the repo's org, by its slug
+    #[serde(default)]
+    pub review_team: String,
+
+    // Comma-separated channels to post the PR to
+    #[serde(default)]
+    pub channels: String,
+
+    // Comma-separated JIRA transitions (names, target statuses or IDs) to move the PR's issues with: the first
+    // available one is used
+    #[serde(default)]
+    pub jira_transition: String,
+
+    // Merge the PR with the merge strategy for its base branch once all its checks pass, as long as it keeps the label
+    #[serde(default)]
+    pub merge: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RepoComponent {
+    #[serde(default)]
+    pub name: String,
+
+    // The directory the component lives in, relative to the repo root. e.g. "services/api"
+    #[serde(default)]
+    pub path: String,
+
+    // Run from the component's directory to get its version
+    #[serde(default)]
+    pub version_script: String,
+
+    // The jira project whose fix versions track this component.
+    // If left blank, the repo's jira projects are used.
+    #[serde(default)]
+    pub jira_project: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RepoSubmodule {
+    // The upstream repo, e.g. "some-org/some-lib"
+    #[serde(default)]
+    pub upstream: String,
+
+    // Where the submodule is checked out, relative to the repo root
+    #[serde(default)]
+    pub path: String,
+
+    // Merge the bump PR once its checks pass
+    #[serde(default)]
+    pub auto_merge: bool,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -53,6 +303,22 @@ pub struct RepoConfig {
     db: Database,
 }
 
+// What repos show instead of their webhook secret
+pub const MASKED_SECRET: &str = "********";
+
+fn mask_secret<S: serde::Serializer>(secret: &str, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(if secret.is_empty() { "" } else { MASKED_SECRET })
+}
+
+// The secret to store for one that was sent back: the mask means it wasn't changed
+fn unmasked(secret: &str) -> &str {
+    if secret == MASKED_SECRET {
+        ""
+    } else {
+        secret
+    }
+}
+
 impl RepoInfo {
     pub fn new(repo: &str, channel: &str) -> RepoInfo {
         RepoInfo {
@@ -62,6 +328,48 @@ impl RepoInfo {
             force_push_notify: false,
             jira_config: vec![],
             release_branch_prefix: String::new(),
+            codeowners_reviews: false,
+            codeowners_ignore_bots: false,
+            path_labels: vec![],
+            routing_rules: vec![],
+            merge_strategies: vec![],
+            label_rules: vec![],
+            size_labels: false,
+            size_label_thresholds: String::new(),
+            size_label_excludes: String::new(),
+            stale_pr_days: 0,
+            stale_pr_digest: false,
+            stale_pr_quiet_days: String::new(),
+            lint_conventional: false,
+            lint_title_regex: String::new(),
+            lint_commit_regex: String::new(),
+            lint_check_run: false,
+            webhook_secret: String::new(),
+            conflict_notify: false,
+            subscribed_channels: String::new(),
+            slack_threads: false,
+            ecosystem: String::new(),
+            version_files: String::new(),
+            lockfiles: String::new(),
+            changelog_file: String::new(),
+            components: vec![],
+            channel_digest: String::new(),
+            submodules: vec![],
+            depends_on: String::new(),
+            sbom: false,
+            sbom_script: String::new(),
+            provenance: false,
+            smart_commits: false,
+            smart_commit_commands: String::new(),
+            slack_pr_bridge: false,
+            jira_release_versions: false,
+            huddle_comments: 0,
+            blame_notify: false,
+            gate_required_labels: String::new(),
+            gate_team_approvals: String::new(),
+            gate_resolved_threads: false,
+            dry_run: String::new(),
+            deleted_at: None,
         }
     }
 
@@ -71,6 +379,54 @@ impl RepoInfo {
         info
     }
 
+    pub fn with_slack_threads(self, value: bool) -> RepoInfo {
+        let mut info = self;
+        info.slack_threads = value;
+        info
+    }
+
+    pub fn with_slack_pr_bridge(self, value: bool) -> RepoInfo {
+        let mut info = self;
+        info.slack_pr_bridge = value;
+        info
+    }
+
+    pub fn with_jira_release_versions(self, value: bool) -> RepoInfo {
+        let mut info = self;
+        info.jira_release_versions = value;
+        info
+    }
+
+    pub fn with_huddle_comments(self, value: u32) -> RepoInfo {
+        let mut info = self;
+        info.huddle_comments = value;
+        info
+    }
+
+    pub fn with_blame_notify(self, value: bool) -> RepoInfo {
+        let mut info = self;
+        info.blame_notify = value;
+        info
+    }
+
+    pub fn with_merge_gate(self, required_labels: &str, team_approvals: &str, resolved_threads: bool) -> RepoInfo {
+        let mut info = self;
+        info.gate_required_labels = required_labels.into();
+        info.gate_team_approvals = team_approvals.into();
+        info.gate_resolved_threads = resolved_threads;
+        info
+    }
+
+    pub fn with_dry_run(self, value: Option<bool>) -> RepoInfo {
+        let mut info = self;
+        info.dry_run = match value {
+            Some(true) => "on".into(),
+            Some(false) => "off".into(),
+            None => String::new(),
+        };
+        info
+    }
+
     pub fn with_jira(self, jira_project: &str) -> RepoInfo {
         self.with_jira_config(RepoJiraConfig::new(jira_project))
     }
@@ -87,6 +443,153 @@ impl RepoInfo {
         info.release_branch_prefix = value;
         info
     }
+
+    pub fn with_path_label(self, path: &str, label: &str) -> RepoInfo {
+        let mut info = self;
+        info.path_labels.push(RepoPathLabel::new(path, label));
+        info
+    }
+
+    pub fn with_routing_rule(self, rule: RepoRoutingRule) -> RepoInfo {
+        let mut info = self;
+        info.routing_rules.push(rule);
+        info
+    }
+
+    pub fn with_merge_strategy(self, strategy: RepoMergeStrategy) -> RepoInfo {
+        let mut info = self;
+        info.merge_strategies.push(strategy);
+        info
+    }
+
+    pub fn with_label_rule(self, rule: RepoLabelRule) -> RepoInfo {
+        let mut info = self;
+        info.label_rules.push(rule);
+        info
+    }
+
+    pub fn with_component(self, component: RepoComponent) -> RepoInfo {
+        let mut info = self;
+        info.components.push(component);
+        info
+    }
+
+    pub fn with_submodule(self, submodule: RepoSubmodule) -> RepoInfo {
+        let mut info = self;
+        info.submodules.push(submodule);
+        info
+    }
+
+    pub fn with_size_labels(self, thresholds: &str, excludes: &str) -> RepoInfo {
+        let mut info = self;
+        info.size_labels = true;
+        info.size_label_thresholds = thresholds.into();
+        info.size_label_excludes = excludes.into();
+        info
+    }
+
+    pub fn with_stale_pr_reminders(self, days: u32, digest: bool, quiet_days: &str) -> RepoInfo {
+        let mut info = self;
+        info.stale_pr_days = days;
+        info.stale_pr_digest = digest;
+        info.stale_pr_quiet_days = quiet_days.into();
+        info
+    }
+
+    pub fn with_lint(self, conventional: bool, title_regex: &str, commit_regex: &str, check_run: bool) -> RepoInfo {
+        let mut info = self;
+        info.lint_conventional = conventional;
+        info.lint_title_regex = title_regex.into();
+        info.lint_commit_regex = commit_regex.into();
+        info.lint_check_run = check_run;
+        info
+    }
+
+    pub fn with_webhook_secret(self, secret: &str) -> RepoInfo {
+        let mut info = self;
+        info.webhook_secret = secret.into();
+        info
+    }
+
+    pub fn subscribed_channels(&self) -> Vec<String> {
+        split_list(&self.subscribed_channels)
+    }
+
+    pub fn with_depends_on(self, value: &str) -> RepoInfo {
+        let mut info = self;
+        info.depends_on = value.into();
+        info
+    }
+
+    pub fn with_provenance(self) -> RepoInfo {
+        let mut info = self;
+        info.provenance = true;
+        info
+    }
+
+    pub fn with_sbom(self, script: &str) -> RepoInfo {
+        let mut info = self;
+        info.sbom = true;
+        info.sbom_script = script.into();
+        info
+    }
+
+    pub fn with_smart_commits(self, commands: &str) -> RepoInfo {
+        let mut info = self;
+        info.smart_commits = true;
+        info.smart_commit_commands = commands.into();
+        info
+    }
+
+    // Every repo this one depends on: its submodules' upstreams and any declared ones
+    pub fn upstream_repos(&self) -> Vec<String> {
+        let mut upstreams = self.submodules.iter().map(|s| s.upstream.clone()).collect::<Vec<_>>();
+        upstreams.extend(split_list(&self.depends_on));
+        upstreams.sort();
+        upstreams.dedup();
+        upstreams
+    }
+
+    pub fn with_ecosystem(self, ecosystem: Ecosystem) -> RepoInfo {
+        let mut info = self;
+        info.ecosystem = ecosystem.as_str().into();
+        info
+    }
+
+    pub fn ecosystem(&self) -> Option<Ecosystem> {
+        Ecosystem::parse(&self.ecosystem)
+    }
+
+    pub fn version_files(&self) -> Vec<String> {
+        match self.ecosystem() {
+            Some(e) if self.version_files.trim().is_empty() => {
+                e.defaults().version_files.iter().map(|f| f.to_string()).collect()
+            }
+            _ => split_list(&self.version_files),
+        }
+    }
+
+    pub fn lockfiles(&self) -> Vec<String> {
+        match self.ecosystem() {
+            Some(e) if self.lockfiles.trim().is_empty() => e.defaults().lockfiles.iter().map(|f| f.to_string()).collect(),
+            _ => split_list(&self.lockfiles),
+        }
+    }
+
+    pub fn changelog_file(&self) -> Option<String> {
+        match self.ecosystem() {
+            Some(e) if self.changelog_file.trim().is_empty() => Some(e.defaults().changelog_file.into()),
+            _ if self.changelog_file.trim().is_empty() => None,
+            _ => Some(self.changelog_file.trim().into()),
+        }
+    }
+
+    pub fn with_codeowners(self, value: bool, ignore_bots: bool) -> RepoInfo {
+        let mut info = self;
+        info.codeowners_reviews = value;
+        info.codeowners_ignore_bots = ignore_bots;
+        info
+    }
 }
 
 impl RepoJiraConfig {
@@ -118,6 +621,157 @@ impl RepoJiraConfig {
     }
 }
 
+impl RepoPathLabel {
+    pub fn new(path: &str, label: &str) -> RepoPathLabel {
+        RepoPathLabel {
+            path: path.into(),
+            label: label.into(),
+        }
+    }
+}
+
+impl RepoRoutingRule {
+    pub fn new(branch: &str, path: &str, label: &str, channels: &str) -> RepoRoutingRule {
+        RepoRoutingRule {
+            branch: branch.into(),
+            head: String::new(),
+            path: path.into(),
+            label: label.into(),
+            channels: channels.into(),
+            opsgenie: String::new(),
+            silent: false,
+        }
+    }
+
+    pub fn with_head(self, head: &str) -> RepoRoutingRule {
+        let mut rule = self;
+        rule.head = head.into();
+        rule
+    }
+
+    pub fn with_opsgenie(self, responder: &str) -> RepoRoutingRule {
+        let mut rule = self;
+        rule.opsgenie = responder.into();
+        rule
+    }
+
+    pub fn silenced(self) -> RepoRoutingRule {
+        let mut rule = self;
+        rule.silent = true;
+        rule
+    }
+
+    pub fn channel_list(&self) -> Vec<String> {
+        self.channels.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect()
+    }
+}
+
+impl RepoMergeStrategy {
+    pub fn new(branch: &str, strategy: &str, commit_template: &str) -> RepoMergeStrategy {
+        RepoMergeStrategy {
+            branch: branch.into(),
+            strategy: strategy.into(),
+            commit_template: commit_template.into(),
+        }
+    }
+
+    pub fn method(&self) -> Option<MergeMethod> {
+        MergeMethod::parse(&self.strategy)
+    }
+}
+
+impl RepoLabelRule {
+    pub fn new(label: &str) -> RepoLabelRule {
+        RepoLabelRule {
+            label: label.into(),
+            review_team: String::new(),
+            channels: String::new(),
+            jira_transition: String::new(),
+            merge: false,
+        }
+    }
+
+    pub fn with_review_team(self, team: &str) -> RepoLabelRule {
+        let mut rule = self;
+        rule.review_team = team.into();
+        rule
+    }
+
+    pub fn with_channels(self, channels: &str) -> RepoLabelRule {
+        let mut rule = self;
+        rule.channels = channels.into();
+        rule
+    }
+
+    pub fn with_jira_transition(self, transition: &str) -> RepoLabelRule {
+        let mut rule = self;
+        rule.jira_transition = transition.into();
+        rule
+    }
+
+    pub fn merging(self) -> RepoLabelRule {
+        let mut rule = self;
+        rule.merge = true;
+        rule
+    }
+
+    pub fn channel_list(&self) -> Vec<String> {
+        self.channels.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect()
+    }
+
+    pub fn jira_transitions(&self) -> Vec<String> {
+        self.jira_transition.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect()
+    }
+}
+
+impl RepoSubmodule {
+    pub fn new(upstream: &str, path: &str) -> RepoSubmodule {
+        RepoSubmodule {
+            upstream: upstream.trim().into(),
+            path: path.trim_matches('/').into(),
+            auto_merge: false,
+        }
+    }
+
+    pub fn with_auto_merge(self, value: bool) -> RepoSubmodule {
+        let mut s = self;
+        s.auto_merge = value;
+        s
+    }
+}
+
+impl RepoComponent {
+    pub fn new(name: &str, path: &str) -> RepoComponent {
+        RepoComponent {
+            name: name.into(),
+            path: path.trim_matches('/').into(),
+            version_script: String::new(),
+            jira_project: String::new(),
+        }
+    }
+
+    pub fn with_version_script(self, value: &str) -> RepoComponent {
+        let mut c = self;
+        c.version_script = value.into();
+        c
+    }
+
+    pub fn with_jira_project(self, value: &str) -> RepoComponent {
+        let mut c = self;
+        c.jira_project = value.into();
+        c
+    }
+
+    // True if the file (relative to the repo root) belongs to this component
+    pub fn contains(&self, file: &str) -> bool {
+        let path = self.path.trim_matches('/');
+        if path.is_empty() {
+            return true;
+        }
+        file == path || (file.starts_with(path) && file[path.len()..].starts_with('/'))
+    }
+}
+
 impl RepoConfig {
     pub fn new(db: Database) -> RepoConfig {
         RepoConfig { db: db }
@@ -130,86 +784,414 @@ impl RepoConfig {
     pub fn insert_info(&mut self, repo: &RepoInfo) -> Result<()> {
         let mut conn = self.db.connect()?;
         let tx = conn.transaction()?;
+        self.insert_in(&tx, repo)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    // Inserts the repo as part of `tx`
+    pub fn insert_in(&mut self, tx: &Transaction, repo: &RepoInfo) -> Result<()> {
+        // a new repo replaces a deleted one with the same name
+        tx.execute(r#"DELETE from repos where repo = ?1 and deleted_at != 0"#, &[&repo.repo])
+            .map_err(|e| format_err!("Error replacing deleted repo {}: {}", repo.repo, e))?;
+        Self::delete_orphans(tx)?;
 
         tx.execute(
-            r#"INSERT INTO repos (repo, channel, force_push_notify, release_branch_prefix)
-               VALUES (?1, ?2, ?3, ?4)"#,
+            r#"INSERT INTO repos (repo, channel, force_push_notify, release_branch_prefix,
+                                  codeowners_reviews, codeowners_ignore_bots,
+                                  size_labels, size_label_thresholds, size_label_excludes,
+                                  stale_pr_days, stale_pr_digest, stale_pr_quiet_days,
+                                  lint_conventional, lint_title_regex, lint_commit_regex, lint_check_run,
+                                  webhook_secret,
+                                  conflict_notify,
+                                  subscribed_channels,
+                                  slack_threads,
+                                  ecosystem, version_files, lockfiles, changelog_file,
+                                  channel_digest,
+                                  depends_on,
+                                  sbom, sbom_script,
+                                  provenance,
+                                  smart_commits, smart_commit_commands,
+                                  slack_pr_bridge,
+                                  jira_release_versions,
+                                  huddle_comments,
+                                  dry_run,
+                                  blame_notify,
+                                  gate_required_labels, gate_team_approvals, gate_resolved_threads)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20,
+                       ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31, ?32, ?33, ?34, ?35, ?36, ?37, ?38, ?39)"#,
             &[
                 &repo.repo,
                 &repo.channel,
                 &db::to_tinyint(repo.force_push_notify) as &dyn ToSql,
                 &repo.release_branch_prefix,
+                &db::to_tinyint(repo.codeowners_reviews),
+                &db::to_tinyint(repo.codeowners_ignore_bots),
+                &db::to_tinyint(repo.size_labels),
+                &repo.size_label_thresholds,
+                &repo.size_label_excludes,
+                &repo.stale_pr_days,
+                &db::to_tinyint(repo.stale_pr_digest),
+                &repo.stale_pr_quiet_days,
+                &db::to_tinyint(repo.lint_conventional),
+                &repo.lint_title_regex,
+                &repo.lint_commit_regex,
+                &db::to_tinyint(repo.lint_check_run),
+                &unmasked(&repo.webhook_secret),
+                &db::to_tinyint(repo.conflict_notify),
+                &repo.subscribed_channels,
+                &db::to_tinyint(repo.slack_threads),
+                &repo.ecosystem,
+                &repo.version_files,
+                &repo.lockfiles,
+                &repo.changelog_file,
+                &repo.channel_digest,
+                &repo.depends_on,
+                &db::to_tinyint(repo.sbom),
+                &repo.sbom_script,
+                &db::to_tinyint(repo.provenance),
+                &db::to_tinyint(repo.smart_commits),
+                &repo.smart_commit_commands,
+                &db::to_tinyint(repo.slack_pr_bridge),
+                &db::to_tinyint(repo.jira_release_versions),
+                &repo.huddle_comments,
+                &repo.dry_run,
+                &db::to_tinyint(repo.blame_notify),
+                &repo.gate_required_labels,
+                &repo.gate_team_approvals,
+                &db::to_tinyint(repo.gate_resolved_threads),
             ],
         )
         .map_err(|e| format_err!("Error inserting repo {}: {}", repo.repo, e))?;
 
-        let id = tx.last_insert_rowid();
-        self.insert_jiras(&tx, id, &repo.jira_config)?;
+        let id = tx.last_insert_rowid()?;
+        self.insert_jiras(tx, id, &repo.jira_config)?;
+        self.insert_path_labels(tx, id, &repo.path_labels)?;
+        self.insert_routing_rules(tx, id, &repo.routing_rules)?;
+        self.insert_merge_strategies(tx, id, &repo.merge_strategies)?;
+        self.insert_label_rules(tx, id, &repo.label_rules)?;
+        self.insert_components(tx, id, &repo.components)?;
+        self.insert_submodules(tx, id, &repo.submodules)?;
+
+        Ok(())
+    }
 
+    pub fn update(&mut self, repo: &RepoInfo) -> Result<()> {
+        let mut conn = self.db.connect()?;
+        let tx = conn.transaction()?;
+        self.update_in(&tx, repo)?;
         tx.commit()?;
 
         Ok(())
     }
 
-    pub fn update(&mut self, repo: &RepoInfo) -> Result<()> {
+    pub fn update_in(&mut self, tx: &Transaction, repo: &RepoInfo) -> Result<()> {
         if repo.id.is_none() {
             return Err(format_err!("Repo does not have an id: cannot update."));
         }
-        let id = repo.id.unwrap();
-
-        let mut conn = self.db.connect()?;
-        let tx = conn.transaction()?;
+        let id = repo.id.unwrap();
+
+        tx.execute(
+            r#"UPDATE repos
+                SET repo = ?1,
+                    channel = ?2,
+                    force_push_notify = ?3,
+                    release_branch_prefix = ?4,
+                    codeowners_reviews = ?5,
+                    codeowners_ignore_bots = ?6,
+                    size_labels = ?7,
+                    size_label_thresholds = ?8,
+                    size_label_excludes = ?9,
+                    stale_pr_days = ?10,
+                    stale_pr_digest = ?11,
+                    stale_pr_quiet_days = ?12,
+                    lint_conventional = ?13,
+                    lint_title_regex = ?14,
+                    lint_commit_regex = ?15,
+                    lint_check_run = ?16,
+                    webhook_secret = CASE WHEN ?17 = '' THEN webhook_secret ELSE ?17 END,
+                    conflict_notify = ?18,
+                    subscribed_channels = ?19,
+                    slack_threads = ?20,
+                    ecosystem = ?21,
+                    version_files = ?22,
+                    lockfiles = ?23,
+                    changelog_file = ?24,
+                    channel_digest = ?25,
+                    depends_on = ?26,
+                    sbom = ?27,
+                    sbom_script = ?28,
+                    provenance = ?29,
+                    smart_commits = ?30,
+                    smart_commit_commands = ?31,
+                    slack_pr_bridge = ?32,
+                    jira_release_versions = ?33,
+                    huddle_comments = ?34,
+                    dry_run = ?35,
+                    blame_notify = ?36,
+                    gate_required_labels = ?37,
+                    gate_team_approvals = ?38,
+                    gate_resolved_threads = ?39
+               WHERE id = ?40"#,
+            &[
+                &repo.repo,
+                &repo.channel,
+                &db::to_tinyint(repo.force_push_notify) as &dyn ToSql,
+                &repo.release_branch_prefix,
+                &db::to_tinyint(repo.codeowners_reviews),
+                &db::to_tinyint(repo.codeowners_ignore_bots),
+                &db::to_tinyint(repo.size_labels),
+                &repo.size_label_thresholds,
+                &repo.size_label_excludes,
+                &repo.stale_pr_days,
+                &db::to_tinyint(repo.stale_pr_digest),
+                &repo.stale_pr_quiet_days,
+                &db::to_tinyint(repo.lint_conventional),
+                &repo.lint_title_regex,
+                &repo.lint_commit_regex,
+                &db::to_tinyint(repo.lint_check_run),
+                &unmasked(&repo.webhook_secret),
+                &db::to_tinyint(repo.conflict_notify),
+                &repo.subscribed_channels,
+                &db::to_tinyint(repo.slack_threads),
+                &repo.ecosystem,
+                &repo.version_files,
+                &repo.lockfiles,
+                &repo.changelog_file,
+                &repo.channel_digest,
+                &repo.depends_on,
+                &db::to_tinyint(repo.sbom),
+                &repo.sbom_script,
+                &db::to_tinyint(repo.provenance),
+                &db::to_tinyint(repo.smart_commits),
+                &repo.smart_commit_commands,
+                &db::to_tinyint(repo.slack_pr_bridge),
+                &db::to_tinyint(repo.jira_release_versions),
+                &repo.huddle_comments,
+                &repo.dry_run,
+                &db::to_tinyint(repo.blame_notify),
+                &repo.gate_required_labels,
+                &repo.gate_team_approvals,
+                &db::to_tinyint(repo.gate_resolved_threads),
+                &id,
+            ],
+        )
+        .map_err(|e| format_err!("Error updating repo {}: {}", repo.repo, e))?;
+
+        tx.execute(r#"DELETE from repos_jiras where repo_id = ?1"#, &[&id])
+            .map_err(|e| format_err!("Error clearing repo jira entries {}: {}", repo.repo, e))?;
+
+        self.insert_jiras(tx, id as i64, &repo.jira_config)?;
+
+        tx.execute(r#"DELETE from repos_path_labels where repo_id = ?1"#, &[&id])
+            .map_err(|e| format_err!("Error clearing repo path labels {}: {}", repo.repo, e))?;
+
+        self.insert_path_labels(tx, id as i64, &repo.path_labels)?;
+
+        tx.execute(r#"DELETE from repos_routing_rules where repo_id = ?1"#, &[&id])
+            .map_err(|e| format_err!("Error clearing repo routing rules {}: {}", repo.repo, e))?;
+
+        self.insert_routing_rules(tx, id as i64, &repo.routing_rules)?;
+
+        tx.execute(r#"DELETE from repos_merge_strategies where repo_id = ?1"#, &[&id])
+            .map_err(|e| format_err!("Error clearing repo merge strategies {}: {}", repo.repo, e))?;
+
+        self.insert_merge_strategies(tx, id as i64, &repo.merge_strategies)?;
+
+        tx.execute(r#"DELETE from repos_label_rules where repo_id = ?1"#, &[&id])
+            .map_err(|e| format_err!("Error clearing repo label rules {}: {}", repo.repo, e))?;
+
+        self.insert_label_rules(tx, id as i64, &repo.label_rules)?;
+
+        tx.execute(r#"DELETE from repos_components where repo_id = ?1"#, &[&id])
+            .map_err(|e| format_err!("Error clearing repo components {}: {}", repo.repo, e))?;
+
+        self.insert_components(tx, id as i64, &repo.components)?;
+
+        tx.execute(r#"DELETE from repos_submodules where repo_id = ?1"#, &[&id])
+            .map_err(|e| format_err!("Error clearing repo submodules {}: {}", repo.repo, e))?;
+
+        self.insert_submodules(tx, id as i64, &repo.submodules)?;
+
+        Ok(())
+    }
+
+    fn insert_jiras(&mut self, tx: &Transaction, id: i64, jira_config: &Vec<RepoJiraConfig>) -> Result<()> {
+        for config in jira_config {
+            tx.execute(
+                r#"INSERT INTO repos_jiras (repo_id, jira, channel, version_script, release_branch_regex)
+               VALUES (?1, ?2, ?3, ?4, ?5)"#,
+                &[
+                    &id,
+                    &config.jira_project as &dyn ToSql,
+                    &config.channel,
+                    &config.version_script,
+                    &config.release_branch_regex,
+                ],
+            )
+            .map_err(|e| format_err!("Error inserting jira {} for repo {}: {}", config.jira_project, id, e))?;
+        }
+
+        Ok(())
+    }
+
+    fn insert_path_labels(&mut self, tx: &Transaction, id: i64, path_labels: &Vec<RepoPathLabel>) -> Result<()> {
+        for rule in path_labels {
+            tx.execute(
+                r#"INSERT INTO repos_path_labels (repo_id, path, label) VALUES (?1, ?2, ?3)"#,
+                &[&id, &rule.path as &dyn ToSql, &rule.label],
+            )
+            .map_err(|e| format_err!("Error inserting path label {} for repo {}: {}", rule.label, id, e))?;
+        }
+
+        Ok(())
+    }
+
+    fn insert_routing_rules(&mut self, tx: &Transaction, id: i64, rules: &Vec<RepoRoutingRule>) -> Result<()> {
+        for rule in rules {
+            tx.execute(
+                r#"INSERT INTO repos_routing_rules (repo_id, branch, head, path, label, channels, opsgenie, silent)
+                   VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"#,
+                &[
+                    &id,
+                    &rule.branch as &dyn ToSql,
+                    &rule.head,
+                    &rule.path,
+                    &rule.label,
+                    &rule.channels,
+                    &rule.opsgenie,
+                    &db::to_tinyint(rule.silent),
+                ],
+            )
+            .map_err(|e| format_err!("Error inserting routing rule to {} for repo {}: {}", rule.channels, id, e))?;
+        }
 
-        tx.execute(
-            r#"UPDATE repos
-                SET repo = ?1,
-                    channel = ?2,
-                    force_push_notify = ?3,
-                    release_branch_prefix = ?4
-               WHERE id = ?5"#,
-            &[
-                &repo.repo,
-                &repo.channel,
-                &db::to_tinyint(repo.force_push_notify) as &dyn ToSql,
-                &repo.release_branch_prefix,
-                &id,
-            ],
-        )
-        .map_err(|e| format_err!("Error updating repo {}: {}", repo.repo, e))?;
+        Ok(())
+    }
 
-        tx.execute(r#"DELETE from repos_jiras where repo_id = ?1"#, &[&id])
-            .map_err(|e| format_err!("Error clearing repo jira entries {}: {}", repo.repo, e))?;
+    fn insert_merge_strategies(
+        &mut self,
+        tx: &Transaction,
+        id: i64,
+        strategies: &Vec<RepoMergeStrategy>,
+    ) -> Result<()> {
+        for strategy in strategies {
+            tx.execute(
+                r#"INSERT INTO repos_merge_strategies (repo_id, branch, strategy, commit_template)
+               VALUES (?1, ?2, ?3, ?4)"#,
+                &[&id, &strategy.branch as &dyn ToSql, &strategy.strategy, &strategy.commit_template],
+            )
+            .map_err(|e| format_err!("Error inserting merge strategy {} for repo {}: {}", strategy.strategy, id, e))?;
+        }
 
-        self.insert_jiras(&tx, id as i64, &repo.jira_config)?;
+        Ok(())
+    }
 
-        tx.commit()?;
+    fn insert_label_rules(&mut self, tx: &Transaction, id: i64, rules: &Vec<RepoLabelRule>) -> Result<()> {
+        for rule in rules {
+            tx.execute(
+                r#"INSERT INTO repos_label_rules (repo_id, label, review_team, channels, jira_transition, merge)
+                   VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#,
+                &[
+                    &id,
+                    &rule.label as &dyn ToSql,
+                    &rule.review_team,
+                    &rule.channels,
+                    &rule.jira_transition,
+                    &db::to_tinyint(rule.merge),
+                ],
+            )
+            .map_err(|e| format_err!("Error inserting label rule {} for repo {}: {}", rule.label, id, e))?;
+        }
 
         Ok(())
     }
 
-    fn insert_jiras(&mut self, tx: &Transaction, id: i64, jira_config: &Vec<RepoJiraConfig>) -> Result<()> {
-        for config in jira_config {
+    fn insert_components(&mut self, tx: &Transaction, id: i64, components: &Vec<RepoComponent>) -> Result<()> {
+        for component in components {
             tx.execute(
-                r#"INSERT INTO repos_jiras (repo_id, jira, channel, version_script, release_branch_regex)
+                r#"INSERT INTO repos_components (repo_id, name, path, version_script, jira)
                VALUES (?1, ?2, ?3, ?4, ?5)"#,
                 &[
                     &id,
-                    &config.jira_project as &dyn ToSql,
-                    &config.channel,
-                    &config.version_script,
-                    &config.release_branch_regex,
+                    &component.name as &dyn ToSql,
+                    &component.path,
+                    &component.version_script,
+                    &component.jira_project,
                 ],
             )
-            .map_err(|e| format_err!("Error inserting jira {} for repo {}: {}", config.jira_project, id, e))?;
+            .map_err(|e| format_err!("Error inserting component {} for repo {}: {}", component.name, id, e))?;
+        }
+
+        Ok(())
+    }
+
+    fn insert_submodules(&mut self, tx: &Transaction, id: i64, submodules: &Vec<RepoSubmodule>) -> Result<()> {
+        for submodule in submodules {
+            tx.execute(
+                r#"INSERT INTO repos_submodules (repo_id, upstream, path, auto_merge) VALUES (?1, ?2, ?3, ?4)"#,
+                &[&id, &submodule.upstream as &dyn ToSql, &submodule.path, &db::to_tinyint(submodule.auto_merge)],
+            )
+            .map_err(|e| format_err!("Error inserting submodule {} for repo {}: {}", submodule.path, id, e))?;
         }
 
         Ok(())
     }
 
+    // Soft-deletes the repo: it can be restored until the retention window passes.
     pub fn delete(&mut self, id: i32) -> Result<()> {
         let conn = self.db.connect()?;
-        conn.execute("DELETE from repos where id = ?1", &[&id])
-            .map_err(|e| format_err!("Error deleting repo {}: {}", id, e))?;
+        Self::delete_in(&conn, id, db::now())?;
+
+        self.purge_deleted(&conn)
+    }
+
+    pub fn delete_in(conn: &Connection, id: i32, now: i64) -> Result<()> {
+        conn.execute(
+            "UPDATE repos set deleted_at = ?1 where id = ?2 and deleted_at = 0",
+            &[&now, &(id as i64)],
+        )
+        .map_err(|e| format_err!("Error deleting repo {}: {}", id, e))?;
+
+        Ok(())
+    }
+
+    pub fn restore(&mut self, id: i32) -> Result<()> {
+        let conn = self.db.connect()?;
+        self.purge_deleted(&conn)?;
+
+        let count = conn
+            .execute("UPDATE repos set deleted_at = 0 where id = ?1 and deleted_at != 0", &[&id])
+            .map_err(|e| format_err!("Error restoring repo {}: {}", id, e))?;
+
+        if count == 0 {
+            return Err(format_err!("No deleted repo with id {}", id));
+        }
+        Ok(())
+    }
+
+    fn purge_deleted(&self, conn: &Connection) -> Result<()> {
+        conn.execute(
+            "DELETE from repos where deleted_at != 0 and deleted_at < ?1",
+            &[&(db::now() - db::DELETED_RETENTION_SECS)],
+        )
+        .map_err(|e| format_err!("Error purging deleted repos: {}", e))?;
+
+        Self::delete_orphans(conn)
+    }
+
+    fn delete_orphans(conn: &Connection) -> Result<()> {
+        conn.execute_batch(
+            r#"DELETE from repos_jiras where repo_id not in (SELECT id from repos);
+               DELETE from repos_path_labels where repo_id not in (SELECT id from repos);
+               DELETE from repos_routing_rules where repo_id not in (SELECT id from repos);
+               DELETE from repos_merge_strategies where repo_id not in (SELECT id from repos);
+               DELETE from repos_label_rules where repo_id not in (SELECT id from repos);
+               DELETE from repos_components where repo_id not in (SELECT id from repos);
+               DELETE from repos_submodules where repo_id not in (SELECT id from repos);"#,
+        )
+        .map_err(|e| format_err!("Error cleaning up deleted repo settings: {}", e))?;
 
         Ok(())
     }
@@ -219,13 +1201,26 @@ impl RepoConfig {
         repo: &github::Repo,
         branch: &str,
         commits: &Vec<T>,
+    ) -> Vec<String> {
+        self.lookup_routed_channels(repo, branch, commits, &RouteContext::default())
+    }
+
+    // Routing rules that match take precedence over JIRA project channels and the repo's channel, and a matching
+    // silent rule sends to none of them. Subscribed channels get everything either way.
+    pub fn lookup_routed_channels<T: github::CommitLike>(
+        &self,
+        repo: &github::Repo,
+        branch: &str,
+        commits: &Vec<T>,
+        context: &RouteContext,
     ) -> Vec<String> {
         let info = match self.lookup_info(repo) {
             None => return vec![],
             Some(i) => i,
         };
 
-        let configs = self.filter_configs(info.jira_config, branch);
+        let routed = routing::route(&info.routing_rules, branch, context);
+        let configs = self.filter_configs(info.jira_config.clone(), branch);
 
         let channels = configs
             .into_iter()
@@ -233,19 +1228,240 @@ impl RepoConfig {
             .map(|c| c.channel)
             .collect::<Vec<_>>();
 
-        if channels.is_empty() && info.channel.is_empty() {
+        let mut channels = if routing::is_silenced(&info.routing_rules, branch, context) {
+            vec![]
+        } else if !routed.is_empty() {
+            routed
+        } else if channels.is_empty() && info.channel.is_empty() {
             vec![]
         } else if channels.is_empty() {
-            vec![info.channel]
+            vec![info.channel.clone()]
         } else {
             channels
+        };
+
+        for channel in info.subscribed_channels() {
+            if !channels.contains(&channel) {
+                channels.push(channel);
+            }
+        }
+        channels
+    }
+
+    // Adds a channel to the repo's routing, creating the repo entry if needed.
+    // Returns false if the channel already gets the repo's messages.
+    pub fn subscribe(&mut self, repo: &str, channel: &str) -> Result<bool> {
+        let existing = self.get_all()?.into_iter().find(|r| r.repo == repo);
+
+        match existing {
+            None => {
+                self.insert(repo, channel)?;
+                Ok(true)
+            }
+            Some(mut info) => {
+                if info.channel == channel || info.subscribed_channels().iter().any(|c| c == channel) {
+                    return Ok(false);
+                }
+                if info.channel.is_empty() {
+                    info.channel = channel.into();
+                } else {
+                    let mut subscribed = info.subscribed_channels();
+                    subscribed.push(channel.into());
+                    info.subscribed_channels = subscribed.join(",");
+                }
+                self.update(&info)?;
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn lockfiles(&self, repo: &github::Repo) -> Vec<String> {
+        self.lookup_info(repo).map(|r| r.lockfiles()).unwrap_or(vec![])
+    }
+
+    pub fn provenance_enabled(&self, repo: &github::Repo) -> bool {
+        self.lookup_info(repo).map(|r| r.provenance).unwrap_or(false)
+    }
+
+    // The smart commit commands allowed for the repo, or None if it doesn't use smart commits
+    pub fn smart_commit_commands(&self, repo: &github::Repo) -> Option<Vec<String>> {
+        let info = self.lookup_info(repo).filter(|r| r.smart_commits)?;
+        let commands: Vec<String> = info
+            .smart_commit_commands
+            .split(',')
+            .map(|c| c.trim().trim_start_matches('#').to_lowercase())
+            .filter(|c| !c.is_empty())
+            .collect();
+        if commands.is_empty() {
+            Some(jira::smart_commits::DEFAULT_COMMANDS.iter().map(|c| c.to_string()).collect())
+        } else {
+            Some(commands)
         }
     }
 
+    // All of the repo's JIRA projects, whatever their release branches
+    pub fn all_jira_projects(&self, repo: &github::Repo) -> Vec<String> {
+        self.lookup_info(repo).map(|r| r.jira_config.into_iter().map(|c| c.jira_project).collect()).unwrap_or(vec![])
+    }
+
+    pub fn sbom_enabled(&self, repo: &github::Repo) -> bool {
+        self.lookup_info(repo).map(|r| r.sbom).unwrap_or(false)
+    }
+
+    // Returns None if CI is expected to attach the SBOM instead
+    pub fn sbom_script(&self, repo: &github::Repo) -> Option<String> {
+        self.lookup_info(repo).map(|r| r.sbom_script.trim().to_string()).filter(|s| !s.is_empty())
+    }
+
+    // Whether the repo or its org is set up in octobot
+    pub fn is_configured(&self, repo: &github::Repo) -> bool {
+        self.lookup_info(repo).is_some()
+    }
+
     pub fn notify_force_push(&self, repo: &github::Repo) -> bool {
         self.lookup_info(repo).map(|r| r.force_push_notify).unwrap_or(false)
     }
 
+    pub fn notify_blame(&self, repo: &github::Repo) -> bool {
+        self.lookup_info(repo).map(|r| r.blame_notify).unwrap_or(false)
+    }
+
+    pub fn slack_threads(&self, repo: &github::Repo) -> bool {
+        self.lookup_info(repo).map(|r| r.slack_threads).unwrap_or(false)
+    }
+
+    pub fn slack_pr_bridge(&self, repo: &github::Repo) -> bool {
+        self.lookup_info(repo).map(|r| r.slack_threads && r.slack_pr_bridge).unwrap_or(false)
+    }
+
+    pub fn jira_release_versions(&self, repo: &github::Repo) -> bool {
+        self.lookup_info(repo).map(|r| r.jira_release_versions).unwrap_or(false)
+    }
+
+    // Suggestions are made in the PR's slack thread, so the repo needs threads
+    pub fn huddle_comments(&self, repo: &github::Repo) -> Option<u32> {
+        self.lookup_info(repo).filter(|r| r.slack_threads && r.huddle_comments > 0).map(|r| r.huddle_comments)
+    }
+
+    // None follows main.dry_run
+    pub fn dry_run(&self, repo: &github::Repo) -> Option<bool> {
+        match self.lookup_info(repo).map(|r| r.dry_run) {
+            Some(ref d) if d == "on" => Some(true),
+            Some(ref d) if d == "off" => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn codeowners_reviews(&self, repo: &github::Repo, author: &github::User) -> bool {
+        match self.lookup_info(repo) {
+            None => false,
+            Some(r) => r.codeowners_reviews && !(r.codeowners_ignore_bots && author.is_bot()),
+        }
+    }
+
+    pub fn components(&self, repo: &github::Repo) -> Vec<RepoComponent> {
+        self.lookup_info(repo).map(|r| r.components).unwrap_or(vec![])
+    }
+
+    // The repos that pin the upstream repo as a submodule, along with each submodule
+    pub fn submodule_dependents(&self, upstream: &str) -> Result<Vec<(RepoInfo, RepoSubmodule)>> {
+        let mut result = vec![];
+        for info in self.get_all()? {
+            for submodule in &info.submodules {
+                if submodule.upstream == upstream {
+                    result.push((info.clone(), submodule.clone()));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    // The repos that depend on the upstream repo, through submodules or declared dependencies
+    pub fn dependents(&self, upstream: &str) -> Result<Vec<RepoInfo>> {
+        Ok(self.get_all()?.into_iter().filter(|r| r.upstream_repos().iter().any(|u| u == upstream)).collect())
+    }
+
+    // Where news about a JIRA project goes: each repo using the project, in the project's channel override if it has
+    // one, or else in the repo's channel
+    pub fn jira_project_channels(&self, project: &str) -> Result<Vec<String>> {
+        let mut channels: Vec<String> = vec![];
+        for info in self.get_all()? {
+            for config in info.jira_config.iter().filter(|c| c.jira_project == project) {
+                let channel = if config.channel.is_empty() { &info.channel } else { &config.channel };
+                if !channel.is_empty() && !channels.contains(channel) {
+                    channels.push(channel.clone());
+                }
+            }
+        }
+        Ok(channels)
+    }
+
+    pub fn path_labels(&self, repo: &github::Repo) -> Vec<RepoPathLabel> {
+        self.lookup_info(repo).map(|r| r.path_labels).unwrap_or(vec![])
+    }
+
+    pub fn routing_rules(&self, repo: &github::Repo) -> Vec<RepoRoutingRule> {
+        self.lookup_info(repo).map(|r| r.routing_rules).unwrap_or(vec![])
+    }
+
+    pub fn merge_strategies(&self, repo: &github::Repo) -> Vec<RepoMergeStrategy> {
+        self.lookup_info(repo).map(|r| r.merge_strategies).unwrap_or(vec![])
+    }
+
+    pub fn label_rules(&self, repo: &github::Repo) -> Vec<RepoLabelRule> {
+        self.lookup_info(repo).map(|r| r.label_rules).unwrap_or(vec![])
+    }
+
+    // The merge strategy for merges into |branch|, if one is configured
+    pub fn merge_strategy(&self, repo: &github::Repo, branch: &str) -> Option<RepoMergeStrategy> {
+        let info = self.lookup_info(repo)?;
+        merge_strategy::for_branch(&info.merge_strategies, branch).cloned()
+    }
+
+    pub fn size_label_config(&self, repo: &github::Repo) -> Option<SizeLabelConfig> {
+        let info = self.lookup_info(repo)?;
+        if !info.size_labels {
+            return None;
+        }
+
+        match SizeLabelConfig::parse(&info.size_label_thresholds, &info.size_label_excludes) {
+            Ok(c) => Some(c),
+            Err(e) => {
+                error!("Invalid size label config for repo {}: {}", info.repo, e);
+                None
+            }
+        }
+    }
+
+    pub fn merge_gate_rules(&self, repo: &github::Repo) -> Option<GateRules> {
+        let info = self.lookup_info(repo)?;
+
+        match GateRules::new(&info.gate_required_labels, &info.gate_team_approvals, info.gate_resolved_threads) {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("Invalid merge gate config for repo {}: {}", info.repo, e);
+                None
+            }
+        }
+    }
+
+    pub fn lint_rules(&self, repo: &github::Repo) -> Option<LintRules> {
+        let info = self.lookup_info(repo)?;
+
+        match LintRules::new(
+            info.lint_conventional,
+            &info.lint_title_regex,
+            &info.lint_commit_regex,
+            info.lint_check_run,
+        ) {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("Invalid lint config for repo {}: {}", info.repo, e);
+                None
+            }
+        }
+    }
+
     pub fn jira_configs(&self, repo: &github::Repo, branch: &str) -> Vec<RepoJiraConfig> {
         let configs = self.lookup_info(repo).map(|r| r.jira_config.clone()).unwrap_or(vec![]);
 
@@ -285,10 +1501,19 @@ impl RepoConfig {
     }
 
     pub fn get_all(&self) -> Result<Vec<RepoInfo>> {
-        let conn = self.db.connect()?;
-        let mut stmt = conn.prepare("SELECT * FROM repos ORDER BY repo")?;
+        self.query_repos("deleted_at = 0")
+    }
+
+    // Repos that were deleted but may still be restored
+    pub fn get_deleted(&self) -> Result<Vec<RepoInfo>> {
+        self.query_repos("deleted_at != 0")
+    }
+
+    fn query_repos(&self, filter: &str) -> Result<Vec<RepoInfo>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(&format!("SELECT * FROM repos WHERE {} ORDER BY repo", filter))?;
         let cols = db::Columns::from_stmt(&stmt)?;
-        let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+        let mut rows = stmt.query(db::NO_PARAMS)?;
 
         let mut repos = vec![];
         while let Ok(Some(row)) = rows.next() {
@@ -298,9 +1523,41 @@ impl RepoConfig {
         Ok(repos)
     }
 
+    // The webhook secret configured for the repo, or else for its org.
+    pub fn webhook_secret(&self, repo: &github::Repo) -> Option<String> {
+        match self.do_webhook_secret(repo) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Error looking up webhook secret: {}", e);
+                None
+            }
+        }
+    }
+
+    fn do_webhook_secret(&self, repo: &github::Repo) -> Result<Option<String>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT repo, webhook_secret FROM repos
+               WHERE (repo = :full OR repo = :org) AND deleted_at = 0 AND webhook_secret != ''"#,
+        )?;
+        let mut rows = stmt.query_named(&[(":full", &repo.full_name), (":org", &repo.owner.login())])?;
+
+        let mut secrets = Vec::new();
+        while let Ok(Some(row)) = rows.next() {
+            let name: String = row.get(0)?;
+            let secret: String = row.get(1)?;
+            secrets.push((name, secret));
+        }
+
+        let by_repo = secrets.iter().find(|s| s.0 == repo.full_name);
+        let by_org = secrets.iter().find(|s| s.0 == repo.owner.login());
+
+        Ok(by_repo.or(by_org).map(|s| s.1.clone()))
+    }
+
     fn lookup_info(&self, repo: &github::Repo) -> Option<RepoInfo> {
         match self.do_lookup_info(repo) {
-            Ok(u) => u,
+            Ok(u) => u.map(|info| self.with_repo_file(repo, info)),
             Err(e) => {
                 error!("Error looking up repo: {}", e);
                 None
@@ -308,9 +1565,22 @@ impl RepoConfig {
         }
     }
 
+    // Settings from the repo's own .octobot.toml win over the ones in octobot
+    fn with_repo_file(&self, repo: &github::Repo, info: RepoInfo) -> RepoInfo {
+        match RepoFiles::new(self.db.clone()).lookup(&repo.full_name) {
+            Ok(Some(file)) => file.apply(info),
+            Ok(None) => info,
+            Err(e) => {
+                error!("Error looking up {} of {}: {}", repo_files::FILE_NAME, repo.full_name, e);
+                info
+            }
+        }
+    }
+
     fn do_lookup_info(&self, repo: &github::Repo) -> Result<Option<RepoInfo>> {
         let conn = self.db.connect()?;
-        let mut stmt = conn.prepare(r#"SELECT * FROM repos where repo = :full OR repo = :org"#)?;
+        let mut stmt =
+            conn.prepare(r#"SELECT * FROM repos where (repo = :full OR repo = :org) AND deleted_at = 0"#)?;
         let cols = db::Columns::from_stmt(&stmt)?;
         let mut rows = stmt.query_named(&[(":full", &repo.full_name), (":org", &repo.owner.login())])?;
 
@@ -338,19 +1608,178 @@ impl RepoConfig {
     fn map_row(&self, conn: &Connection, row: &Row, cols: &db::Columns) -> Result<RepoInfo> {
         let id = cols.get(row, "id")?;
         let jira_config = self.load_jira_config(&conn, id)?;
+        let path_labels = self.load_path_labels(&conn, id)?;
+        let routing_rules = self.load_routing_rules(&conn, id)?;
+        let merge_strategies = self.load_merge_strategies(&conn, id)?;
+        let label_rules = self.load_label_rules(&conn, id)?;
+        let components = self.load_components(&conn, id)?;
+        let submodules = self.load_submodules(&conn, id)?;
+
+        Ok(RepoInfo {
+            id: Some(id),
+            repo: cols.get(row, "repo")?,
+            channel: cols.get(row, "channel")?,
+            force_push_notify: db::to_bool(cols.get(row, "force_push_notify")?),
+            jira_config: jira_config,
+            release_branch_prefix: cols.get(row, "release_branch_prefix")?,
+            codeowners_reviews: db::to_bool(cols.get(row, "codeowners_reviews")?),
+            codeowners_ignore_bots: db::to_bool(cols.get(row, "codeowners_ignore_bots")?),
+            path_labels: path_labels,
+            routing_rules: routing_rules,
+            merge_strategies: merge_strategies,
+            label_rules: label_rules,
+            size_labels: db::to_bool(cols.get(row, "size_labels")?),
+            size_label_thresholds: cols.get(row, "size_label_thresholds")?,
+            size_label_excludes: cols.get(row, "size_label_excludes")?,
+            stale_pr_days: cols.get(row, "stale_pr_days")?,
+            stale_pr_digest: db::to_bool(cols.get(row, "stale_pr_digest")?),
+            stale_pr_quiet_days: cols.get(row, "stale_pr_quiet_days")?,
+            lint_conventional: db::to_bool(cols.get(row, "lint_conventional")?),
+            lint_title_regex: cols.get(row, "lint_title_regex")?,
+            lint_commit_regex: cols.get(row, "lint_commit_regex")?,
+            lint_check_run: db::to_bool(cols.get(row, "lint_check_run")?),
+            webhook_secret: cols.get(row, "webhook_secret")?,
+            conflict_notify: db::to_bool(cols.get(row, "conflict_notify")?),
+            subscribed_channels: cols.get(row, "subscribed_channels")?,
+            slack_threads: db::to_bool(cols.get(row, "slack_threads")?),
+            ecosystem: cols.get(row, "ecosystem")?,
+            version_files: cols.get(row, "version_files")?,
+            lockfiles: cols.get(row, "lockfiles")?,
+            changelog_file: cols.get(row, "changelog_file")?,
+            components: components,
+            channel_digest: cols.get(row, "channel_digest")?,
+            submodules: submodules,
+            depends_on: cols.get(row, "depends_on")?,
+            sbom: db::to_bool(cols.get(row, "sbom")?),
+            sbom_script: cols.get(row, "sbom_script")?,
+            provenance: db::to_bool(cols.get(row, "provenance")?),
+            smart_commits: db::to_bool(cols.get(row, "smart_commits")?),
+            smart_commit_commands: cols.get(row, "smart_commit_commands")?,
+            slack_pr_bridge: db::to_bool(cols.get(row, "slack_pr_bridge")?),
+            jira_release_versions: db::to_bool(cols.get(row, "jira_release_versions")?),
+            huddle_comments: cols.get(row, "huddle_comments")?,
+            blame_notify: db::to_bool(cols.get(row, "blame_notify")?),
+            gate_required_labels: cols.get(row, "gate_required_labels")?,
+            gate_team_approvals: cols.get(row, "gate_team_approvals")?,
+            gate_resolved_threads: db::to_bool(cols.get(row, "gate_resolved_threads")?),
+            dry_run: cols.get(row, "dry_run")?,
+            deleted_at: match cols.get(row, "deleted_at")? {
+                0 => None,
+                t => Some(t),
+            },
+        })
+    }
+
+    fn load_path_labels(&self, conn: &Connection, id: i32) -> Result<Vec<RepoPathLabel>> {
+        let mut stmt = conn.prepare(r#"SELECT * FROM repos_path_labels where repo_id = :id ORDER BY rowid"#)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &id)])?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(RepoPathLabel {
+                path: cols.get(row, "path")?,
+                label: cols.get(row, "label")?,
+            });
+        }
+
+        Ok(result)
+    }
+
+    fn load_routing_rules(&self, conn: &Connection, id: i32) -> Result<Vec<RepoRoutingRule>> {
+        let mut stmt = conn.prepare(r#"SELECT * FROM repos_routing_rules where repo_id = :id ORDER BY rowid"#)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &id)])?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(RepoRoutingRule {
+                branch: cols.get(row, "branch")?,
+                head: cols.get(row, "head")?,
+                path: cols.get(row, "path")?,
+                label: cols.get(row, "label")?,
+                channels: cols.get(row, "channels")?,
+                opsgenie: cols.get(row, "opsgenie")?,
+                silent: db::to_bool(cols.get(row, "silent")?),
+            });
+        }
+
+        Ok(result)
+    }
+
+    fn load_merge_strategies(&self, conn: &Connection, id: i32) -> Result<Vec<RepoMergeStrategy>> {
+        let mut stmt = conn.prepare(r#"SELECT * FROM repos_merge_strategies where repo_id = :id ORDER BY rowid"#)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &id)])?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(RepoMergeStrategy {
+                branch: cols.get(row, "branch")?,
+                strategy: cols.get(row, "strategy")?,
+                commit_template: cols.get(row, "commit_template")?,
+            });
+        }
+
+        Ok(result)
+    }
+
+    fn load_label_rules(&self, conn: &Connection, id: i32) -> Result<Vec<RepoLabelRule>> {
+        let mut stmt = conn.prepare(r#"SELECT * FROM repos_label_rules where repo_id = :id ORDER BY rowid"#)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &id)])?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(RepoLabelRule {
+                label: cols.get(row, "label")?,
+                review_team: cols.get(row, "review_team")?,
+                channels: cols.get(row, "channels")?,
+                jira_transition: cols.get(row, "jira_transition")?,
+                merge: db::to_bool(cols.get(row, "merge")?),
+            });
+        }
+
+        Ok(result)
+    }
+
+    fn load_components(&self, conn: &Connection, id: i32) -> Result<Vec<RepoComponent>> {
+        let mut stmt = conn.prepare(r#"SELECT * FROM repos_components where repo_id = :id ORDER BY rowid"#)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &id)])?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(RepoComponent {
+                name: cols.get(row, "name")?,
+                path: cols.get(row, "path")?,
+                version_script: cols.get(row, "version_script")?,
+                jira_project: cols.get(row, "jira")?,
+            });
+        }
+
+        Ok(result)
+    }
+
+    fn load_submodules(&self, conn: &Connection, id: i32) -> Result<Vec<RepoSubmodule>> {
+        let mut stmt = conn.prepare(r#"SELECT * FROM repos_submodules where repo_id = :id ORDER BY rowid"#)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &id)])?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(RepoSubmodule {
+                upstream: cols.get(row, "upstream")?,
+                path: cols.get(row, "path")?,
+                auto_merge: db::to_bool(cols.get(row, "auto_merge")?),
+            });
+        }
 
-        Ok(RepoInfo {
-            id: Some(id),
-            repo: cols.get(row, "repo")?,
-            channel: cols.get(row, "channel")?,
-            force_push_notify: db::to_bool(cols.get(row, "force_push_notify")?),
-            jira_config: jira_config,
-            release_branch_prefix: cols.get(row, "release_branch_prefix")?,
-        })
+        Ok(result)
     }
 
     fn load_jira_config(&self, conn: &Connection, id: i32) -> Result<Vec<RepoJiraConfig>> {
-        let mut stmt = conn.prepare(r#"SELECT * FROM repos_jiras where repo_id = :id"#)?;
+        let mut stmt = conn.prepare(r#"SELECT * FROM repos_jiras where repo_id = :id ORDER BY jira"#)?;
         let cols = db::Columns::from_stmt(&stmt)?;
         let mut rows = stmt.query_named(&[(":id", &id)])?;
 
@@ -370,6 +1799,10 @@ impl RepoConfig {
     }
 }
 
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(|c| c.trim()).filter(|c| !c.is_empty()).map(|c| c.to_string()).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -410,6 +1843,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lookup_channel_from_repo_file() {
+        let (mut repos, _temp) = new_test();
+        repos.insert("some-user", "org-reviews").unwrap();
+
+        let files = RepoFiles::new(repos.db.clone());
+        files.set("some-user/the-repo", "channel = \"the-repo-reviews\"", 10).unwrap();
+        files.set("some-user/broken-repo", "channel = ", 10).unwrap();
+
+        let commits = Vec::<github::Commit>::new();
+        let repo = github::Repo::parse("http://git.company.com/some-user/the-repo").unwrap();
+        assert_eq!(vec!["the-repo-reviews"], repos.lookup_channels(&repo, "", &commits));
+        let repo = github::Repo::parse("http://git.company.com/some-user/broken-repo").unwrap();
+        assert_eq!(vec!["org-reviews"], repos.lookup_channels(&repo, "", &commits));
+
+        // the file can't add repos that aren't configured in octobot
+        files.set("other-user/the-repo", "channel = \"the-repo-reviews\"", 10).unwrap();
+        let repo = github::Repo::parse("http://git.company.com/other-user/the-repo").unwrap();
+        assert!(repos.lookup_channels(&repo, "", &commits).is_empty());
+    }
+
+    #[test]
+    fn lookup_channel_subscribed() {
+        let (mut repos, _temp) = new_test();
+        let repo = github::Repo::parse("http://git.company.com/some-user/the-repo").unwrap();
+
+        assert_eq!(true, repos.subscribe("some-user/the-repo", "the-repo-reviews").unwrap());
+        assert_eq!(true, repos.subscribe("some-user/the-repo", "the-team").unwrap());
+        assert_eq!(false, repos.subscribe("some-user/the-repo", "the-team").unwrap());
+        assert_eq!(false, repos.subscribe("some-user/the-repo", "the-repo-reviews").unwrap());
+
+        assert_eq!(
+            vec!["the-repo-reviews", "the-team"],
+            repos.lookup_channels(&repo, "", &Vec::<github::Commit>::new())
+        );
+        assert_eq!(1, repos.get_all().unwrap().len());
+    }
+
     #[test]
     fn lookup_channel_none() {
         let (mut repos, _temp) = new_test();
@@ -513,6 +1984,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_codeowners_reviews() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(&RepoInfo::new("some-user/the-default", "reviews"))
+            .unwrap();
+        repos
+            .insert_info(&RepoInfo::new("some-user/everyone", "reviews").with_codeowners(true, false))
+            .unwrap();
+        repos
+            .insert_info(&RepoInfo::new("some-user/no-bots", "reviews").with_codeowners(true, true))
+            .unwrap();
+
+        let human = github::User::new("joe");
+        let bot = github::User::new("dependabot[bot]");
+
+        {
+            let repo = github::Repo::parse("http://git.company.com/some-user/the-default").unwrap();
+            assert_eq!(false, repos.codeowners_reviews(&repo, &human));
+            assert_eq!(false, repos.codeowners_reviews(&repo, &bot));
+        }
+
+        {
+            let repo = github::Repo::parse("http://git.company.com/some-user/everyone").unwrap();
+            assert_eq!(true, repos.codeowners_reviews(&repo, &human));
+            assert_eq!(true, repos.codeowners_reviews(&repo, &bot));
+        }
+
+        {
+            let repo = github::Repo::parse("http://git.company.com/some-user/no-bots").unwrap();
+            assert_eq!(true, repos.codeowners_reviews(&repo, &human));
+            assert_eq!(false, repos.codeowners_reviews(&repo, &bot));
+        }
+    }
+
     #[test]
     fn test_jira_enabled() {
         let (mut repos, _temp) = new_test();
@@ -570,6 +2076,403 @@ mod tests {
         assert_eq!(Vec::<String>::new(), repos.jira_projects(&repo, "release/other"));
     }
 
+    #[test]
+    fn test_path_labels() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(
+                &RepoInfo::new("some-user/the-repo", "reviews")
+                    .with_path_label("docs/**", "documentation")
+                    .with_path_label("**/*.sql", "database"),
+            )
+            .unwrap();
+
+        let repo = github::Repo::parse("http://git.company.com/some-user/the-repo").unwrap();
+        assert_eq!(
+            vec![
+                RepoPathLabel::new("docs/**", "documentation"),
+                RepoPathLabel::new("**/*.sql", "database"),
+            ],
+            repos.path_labels(&repo)
+        );
+
+        let mut all = repos.get_all().unwrap();
+        all[0].path_labels = vec![RepoPathLabel::new("ui/**", "frontend")];
+        repos.update(&all[0]).unwrap();
+        assert_eq!(vec![RepoPathLabel::new("ui/**", "frontend")], repos.path_labels(&repo));
+
+        let other = github::Repo::parse("http://git.company.com/some-user/other-repo").unwrap();
+        assert_eq!(Vec::<RepoPathLabel>::new(), repos.path_labels(&other));
+    }
+
+    #[test]
+    fn test_routing_rules() {
+        let (mut repos, _temp) = new_test();
+        let mut info = RepoInfo::new("some-user/the-repo", "reviews")
+            .with_routing_rule(RepoRoutingRule::new("release/*", "", "", "releases"))
+            .with_routing_rule(RepoRoutingRule::new("", "docs/**", "", "docs"));
+        info.subscribed_channels = "firehose".into();
+        repos.insert_info(&info).unwrap();
+
+        let repo = github::Repo::parse("http://git.company.com/some-user/the-repo").unwrap();
+        let commits = Vec::<github::Commit>::new();
+        assert_eq!(2, repos.routing_rules(&repo).len());
+
+        assert_eq!(vec!["reviews", "firehose"], repos.lookup_channels(&repo, "main", &commits));
+        assert_eq!(vec!["releases", "firehose"], repos.lookup_channels(&repo, "release/1.0", &commits));
+
+        let docs = RouteContext::new(vec!["docs/guide.md".into()], vec![]);
+        assert_eq!(vec!["docs", "firehose"], repos.lookup_routed_channels(&repo, "main", &commits, &docs));
+        assert_eq!(
+            vec!["releases", "docs", "firehose"],
+            repos.lookup_routed_channels(&repo, "release/1.0", &commits, &docs)
+        );
+
+        let mut all = repos.get_all().unwrap();
+        all[0].routing_rules = vec![RepoRoutingRule::new("", "", "hotfix", "oncall, releases")];
+        repos.update(&all[0]).unwrap();
+        assert_eq!(vec![RepoRoutingRule::new("", "", "hotfix", "oncall, releases")], repos.routing_rules(&repo));
+
+        let hotfix = RouteContext::new(vec![], vec!["hotfix".into()]);
+        assert_eq!(
+            vec!["oncall", "releases", "firehose"],
+            repos.lookup_routed_channels(&repo, "main", &commits, &hotfix)
+        );
+
+        all[0].routing_rules = vec![
+            RepoRoutingRule::new("main", "", "", "team"),
+            RepoRoutingRule::new("feature/*", "", "", "").with_head("feature/*").silenced(),
+        ];
+        repos.update(&all[0]).unwrap();
+        assert_eq!(all[0].routing_rules, repos.routing_rules(&repo));
+
+        let feature = RouteContext::default().with_head("feature/b");
+        assert_eq!(vec!["team", "firehose"], repos.lookup_routed_channels(&repo, "main", &commits, &feature));
+        assert_eq!(vec!["firehose"], repos.lookup_routed_channels(&repo, "feature/a", &commits, &feature));
+        assert_eq!(vec!["reviews", "firehose"], repos.lookup_channels(&repo, "feature/a", &commits));
+    }
+
+    #[test]
+    fn test_merge_strategies() {
+        let (mut repos, _temp) = new_test();
+        let info = RepoInfo::new("some-user/the-repo", "reviews")
+            .with_merge_strategy(RepoMergeStrategy::new("release/*", "rebase", ""))
+            .with_merge_strategy(RepoMergeStrategy::new("", "squash", "{{pr_title}} (#{{pr_number}})"));
+        repos.insert_info(&info).unwrap();
+
+        let repo = github::Repo::parse("http://git.company.com/some-user/the-repo").unwrap();
+        assert_eq!(2, repos.merge_strategies(&repo).len());
+        assert_eq!(
+            Some(RepoMergeStrategy::new("release/*", "rebase", "")),
+            repos.merge_strategy(&repo, "release/1.0")
+        );
+        assert_eq!(Some(MergeMethod::Squash), repos.merge_strategy(&repo, "main").and_then(|s| s.method()));
+
+        let mut all = repos.get_all().unwrap();
+        all[0].merge_strategies = vec![RepoMergeStrategy::new("main", "merge", "")];
+        repos.update(&all[0]).unwrap();
+        assert_eq!(vec![RepoMergeStrategy::new("main", "merge", "")], repos.merge_strategies(&repo));
+        assert_eq!(None, repos.merge_strategy(&repo, "release/1.0"));
+
+        let other = github::Repo::parse("http://git.company.com/other-user/the-repo").unwrap();
+        assert_eq!(None, repos.merge_strategy(&other, "main"));
+    }
+
+    #[test]
+    fn test_label_rules() {
+        let (mut repos, _temp) = new_test();
+        let info = RepoInfo::new("some-user/the-repo", "reviews")
+            .with_label_rule(RepoLabelRule::new("security").with_review_team("appsec").with_channels("sec, @joe"))
+            .with_label_rule(RepoLabelRule::new("automerge").merging());
+        repos.insert_info(&info).unwrap();
+
+        let repo = github::Repo::parse("http://git.company.com/some-user/the-repo").unwrap();
+        assert_eq!(info.label_rules, repos.label_rules(&repo));
+        assert_eq!(vec!["sec", "@joe"], repos.label_rules(&repo)[0].channel_list());
+
+        let mut all = repos.get_all().unwrap();
+        all[0].label_rules = vec![RepoLabelRule::new("qa").with_jira_transition("In QA, QA")];
+        repos.update(&all[0]).unwrap();
+        let rules = repos.label_rules(&repo);
+        assert_eq!(1, rules.len());
+        assert_eq!(vec!["In QA", "QA"], rules[0].jira_transitions());
+
+        let other = github::Repo::parse("http://git.company.com/other-user/the-repo").unwrap();
+        assert_eq!(Vec::<RepoLabelRule>::new(), repos.label_rules(&other));
+    }
+
+    #[test]
+    fn test_components() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(
+                &RepoInfo::new("some-user/the-repo", "reviews")
+                    .with_component(RepoComponent::new("api", "services/api/").with_version_script("cat VERSION"))
+                    .with_component(RepoComponent::new("web", "web").with_jira_project("WEB")),
+            )
+            .unwrap();
+
+        let repo = github::Repo::parse("http://git.company.com/some-user/the-repo").unwrap();
+        let components = repos.components(&repo);
+        assert_eq!(2, components.len());
+        assert_eq!("services/api", components[0].path);
+        assert_eq!("cat VERSION", components[0].version_script);
+        assert_eq!("WEB", components[1].jira_project);
+
+        let mut all = repos.get_all().unwrap();
+        all[0].components.remove(0);
+        repos.update(&all[0]).unwrap();
+        assert_eq!(vec![RepoComponent::new("web", "web").with_jira_project("WEB")], repos.components(&repo));
+    }
+
+    #[test]
+    fn test_submodule_dependents() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(
+                &RepoInfo::new("some-user/app", "reviews")
+                    .with_submodule(RepoSubmodule::new("some-user/lib", "vendor/lib/").with_auto_merge(true))
+                    .with_submodule(RepoSubmodule::new("some-user/other-lib", "vendor/other")),
+            )
+            .unwrap();
+        repos
+            .insert_info(&RepoInfo::new("some-user/tool", "reviews").with_submodule(RepoSubmodule::new("some-user/lib", "lib")))
+            .unwrap();
+
+        let dependents = repos.submodule_dependents("some-user/lib").unwrap();
+        assert_eq!(2, dependents.len());
+        assert_eq!("some-user/app", dependents[0].0.repo);
+        assert_eq!(RepoSubmodule::new("some-user/lib", "vendor/lib").with_auto_merge(true), dependents[0].1);
+        assert_eq!("some-user/tool", dependents[1].0.repo);
+        assert_eq!(0, repos.submodule_dependents("some-user/unknown").unwrap().len());
+
+        let mut all = repos.get_all().unwrap();
+        all[0].submodules.clear();
+        repos.update(&all[0]).unwrap();
+        assert_eq!(1, repos.submodule_dependents("some-user/lib").unwrap().len());
+    }
+
+    #[test]
+    fn test_dependents() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(
+                &RepoInfo::new("some-user/app", "reviews")
+                    .with_submodule(RepoSubmodule::new("some-user/lib", "vendor/lib"))
+                    .with_depends_on("some-user/lib, some-user/sdk"),
+            )
+            .unwrap();
+        repos.insert_info(&RepoInfo::new("some-user/tool", "reviews").with_depends_on("some-user/sdk")).unwrap();
+
+        assert_eq!(vec!["some-user/lib", "some-user/sdk"], repos.get_all().unwrap()[0].upstream_repos());
+
+        let names = |upstream| repos.dependents(upstream).unwrap().into_iter().map(|r| r.repo).collect::<Vec<_>>();
+        assert_eq!(vec!["some-user/app"], names("some-user/lib"));
+        assert_eq!(vec!["some-user/app", "some-user/tool"], names("some-user/sdk"));
+        assert_eq!(Vec::<String>::new(), names("some-user/app"));
+    }
+
+    #[test]
+    fn test_jira_project_channels() {
+        let (mut repos, _temp) = new_test();
+        repos.insert_info(&RepoInfo::new("some-user/app", "app-reviews").with_jira("APP").with_jira("LIB")).unwrap();
+        repos
+            .insert_info(
+                &RepoInfo::new("some-user/lib", "lib-reviews")
+                    .with_jira_config(RepoJiraConfig::new("LIB").with_channel("lib-releases")),
+            )
+            .unwrap();
+        repos.insert_info(&RepoInfo::new("some-user/tool", "app-reviews").with_jira("APP")).unwrap();
+
+        assert_eq!(vec!["app-reviews"], repos.jira_project_channels("APP").unwrap());
+        assert_eq!(vec!["app-reviews", "lib-releases"], repos.jira_project_channels("LIB").unwrap());
+        assert!(repos.jira_project_channels("OTHER").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_component_contains() {
+        let component = RepoComponent::new("api", "services/api");
+        assert!(component.contains("services/api/src/main.rs"));
+        assert!(component.contains("services/api"));
+        assert!(!component.contains("services/api-client/src/lib.rs"));
+        assert!(!component.contains("web/index.html"));
+        assert!(RepoComponent::new("root", "").contains("anything"));
+    }
+
+    #[test]
+    fn test_size_label_config() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(&RepoInfo::new("some-user/the-default", "reviews"))
+            .unwrap();
+        repos
+            .insert_info(&RepoInfo::new("some-user/sized", "reviews").with_size_labels("5,10,20,40", "**/*.lock"))
+            .unwrap();
+        repos
+            .insert_info(&RepoInfo::new("some-user/invalid", "reviews").with_size_labels("5,4", ""))
+            .unwrap();
+
+        {
+            let repo = github::Repo::parse("http://git.company.com/some-user/the-default").unwrap();
+            assert_eq!(None, repos.size_label_config(&repo));
+        }
+
+        {
+            let repo = github::Repo::parse("http://git.company.com/some-user/sized").unwrap();
+            assert_eq!(
+                Some(SizeLabelConfig::parse("5,10,20,40", "**/*.lock").unwrap()),
+                repos.size_label_config(&repo)
+            );
+        }
+
+        {
+            let repo = github::Repo::parse("http://git.company.com/some-user/invalid").unwrap();
+            assert_eq!(None, repos.size_label_config(&repo));
+        }
+    }
+
+    #[test]
+    fn test_webhook_secret() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(&RepoInfo::new("some-user", "reviews").with_webhook_secret("org-secret"))
+            .unwrap();
+        repos
+            .insert_info(&RepoInfo::new("some-user/special", "reviews").with_webhook_secret("repo-secret"))
+            .unwrap();
+        repos.insert_info(&RepoInfo::new("some-user/plain", "reviews")).unwrap();
+        repos.insert_info(&RepoInfo::new("other-user/repo", "reviews")).unwrap();
+
+        let secret = |url: &str| repos.webhook_secret(&github::Repo::parse(url).unwrap());
+
+        assert_eq!(Some("repo-secret".into()), secret("http://git.company.com/some-user/special"));
+        // repos without their own secret use the org's
+        assert_eq!(Some("org-secret".into()), secret("http://git.company.com/some-user/plain"));
+        assert_eq!(Some("org-secret".into()), secret("http://git.company.com/some-user/unknown"));
+        assert_eq!(None, secret("http://git.company.com/other-user/repo"));
+    }
+
+    #[test]
+    fn test_webhook_secret_write_only() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(&RepoInfo::new("some-user/special", "reviews").with_webhook_secret("repo-secret"))
+            .unwrap();
+        repos.insert_info(&RepoInfo::new("some-user/plain", "reviews")).unwrap();
+        let special = github::Repo::parse("http://git.company.com/some-user/special").unwrap();
+        let secret = |repos: &RepoConfig| repos.webhook_secret(&special);
+
+        let json = serde_json::to_value(&repos.get_all().unwrap()).unwrap();
+        assert_eq!("", json[0]["webhook_secret"]);
+        assert_eq!(MASKED_SECRET, json[1]["webhook_secret"]);
+
+        // sending back the mask or nothing keeps it
+        let mut info: RepoInfo = serde_json::from_value(json[1].clone()).unwrap();
+        info.channel = "other-reviews".into();
+        repos.update(&info).unwrap();
+        assert_eq!(Some("repo-secret".into()), secret(&repos));
+        info.webhook_secret = String::new();
+        repos.update(&info).unwrap();
+        assert_eq!(Some("repo-secret".into()), secret(&repos));
+
+        info.webhook_secret = "new-secret".into();
+        repos.update(&info).unwrap();
+        assert_eq!(Some("new-secret".into()), secret(&repos));
+        assert_eq!("other-reviews", repos.get_all().unwrap()[1].channel);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let (mut repos, _temp) = new_test();
+        repos.insert_info(&RepoInfo::new("some-user", "reviews").with_dry_run(Some(true))).unwrap();
+        repos.insert_info(&RepoInfo::new("some-user/live", "reviews").with_dry_run(Some(false))).unwrap();
+        repos.insert_info(&RepoInfo::new("other-user/repo", "reviews")).unwrap();
+
+        let dry_run = |url: &str| repos.dry_run(&github::Repo::parse(url).unwrap());
+
+        assert_eq!(Some(true), dry_run("http://git.company.com/some-user/some-repo"));
+        assert_eq!(Some(false), dry_run("http://git.company.com/some-user/live"));
+        // follows main.dry_run
+        assert_eq!(None, dry_run("http://git.company.com/other-user/repo"));
+        assert_eq!(None, dry_run("http://git.company.com/unknown-user/repo"));
+    }
+
+    #[test]
+    fn test_lint_rules() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(&RepoInfo::new("some-user/the-default", "reviews"))
+            .unwrap();
+        repos
+            .insert_info(&RepoInfo::new("some-user/linted", "reviews").with_lint(true, "", "^[A-Z]", true))
+            .unwrap();
+        repos
+            .insert_info(&RepoInfo::new("some-user/invalid", "reviews").with_lint(false, "(", "", false))
+            .unwrap();
+
+        {
+            let repo = github::Repo::parse("http://git.company.com/some-user/the-default").unwrap();
+            assert!(repos.lint_rules(&repo).is_none());
+        }
+
+        {
+            let repo = github::Repo::parse("http://git.company.com/some-user/linted").unwrap();
+            let rules = repos.lint_rules(&repo).unwrap();
+            assert_eq!(true, rules.conventional);
+            assert!(rules.title_regex.is_none());
+            assert_eq!("^[A-Z]", rules.commit_regex.unwrap().as_str());
+            assert_eq!(true, rules.check_run);
+        }
+
+        {
+            let repo = github::Repo::parse("http://git.company.com/some-user/invalid").unwrap();
+            assert!(repos.lint_rules(&repo).is_none());
+        }
+    }
+
+    #[test]
+    fn test_soft_delete() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(&RepoInfo::new("some-user/the-repo", "reviews").with_path_label("docs/**", "documentation"))
+            .unwrap();
+        let id = repos.get_all().unwrap()[0].id.unwrap();
+
+        let repo = github::Repo::parse("http://git.company.com/some-user/the-repo").unwrap();
+
+        repos.delete(id).unwrap();
+        assert_eq!(0, repos.get_all().unwrap().len());
+        assert_eq!(Vec::<String>::new(), repos.lookup_channels(&repo, "", &Vec::<github::Commit>::new()));
+
+        let deleted = repos.get_deleted().unwrap();
+        assert_eq!(1, deleted.len());
+        assert!(deleted[0].deleted_at.is_some());
+
+        // settings come back on restore
+        repos.restore(id).unwrap();
+        assert_eq!(vec!["reviews"], repos.lookup_channels(&repo, "", &Vec::<github::Commit>::new()));
+        assert_eq!(vec![RepoPathLabel::new("docs/**", "documentation")], repos.path_labels(&repo));
+
+        assert!(repos.restore(id).is_err());
+    }
+
+    #[test]
+    fn test_soft_delete_reinsert() {
+        let (mut repos, _temp) = new_test();
+        repos
+            .insert_info(&RepoInfo::new("some-user/the-repo", "reviews").with_path_label("docs/**", "documentation"))
+            .unwrap();
+        let id = repos.get_all().unwrap()[0].id.unwrap();
+        repos.delete(id).unwrap();
+
+        repos.insert("some-user/the-repo", "new-reviews").unwrap();
+
+        let repo = github::Repo::parse("http://git.company.com/some-user/the-repo").unwrap();
+        assert_eq!(vec!["new-reviews"], repos.lookup_channels(&repo, "", &Vec::<github::Commit>::new()));
+        assert_eq!(Vec::<RepoPathLabel>::new(), repos.path_labels(&repo));
+        assert_eq!(0, repos.get_deleted().unwrap().len());
+    }
+
     #[test]
     fn test_repos_update() {
         let (mut repos, _temp) = new_test();
@@ -585,4 +2488,29 @@ mod tests {
         assert_eq!(1, all.len());
         assert_eq!("new-channel", all[0].channel);
     }
+
+    #[test]
+    fn test_ecosystem_defaults() {
+        let (mut repos, _temp) = new_test();
+        repos.insert_info(&RepoInfo::new("some-user/the-repo", "reviews").with_ecosystem(Ecosystem::Npm)).unwrap();
+
+        let mut info = repos.get_all().unwrap().remove(0);
+        assert_eq!(Some(Ecosystem::Npm), info.ecosystem());
+        assert_eq!(vec!["package.json"], info.version_files());
+        assert_eq!(vec!["package-lock.json", "yarn.lock", "npm-shrinkwrap.json"], info.lockfiles());
+        assert_eq!(Some("CHANGELOG.md".to_string()), info.changelog_file());
+
+        info.lockfiles = "yarn.lock".into();
+        info.changelog_file = "docs/HISTORY.md".into();
+        repos.update(&info).unwrap();
+
+        let repo = github::Repo::parse("http://git.company.com/some-user/the-repo").unwrap();
+        assert_eq!(vec!["yarn.lock"], repos.lockfiles(&repo));
+        assert_eq!(Some("docs/HISTORY.md".to_string()), repos.get_all().unwrap()[0].changelog_file());
+
+        let plain = RepoInfo::new("some-user/other-repo", "reviews");
+        assert_eq!(None, plain.ecosystem());
+        assert_eq!(Vec::<String>::new(), plain.lockfiles());
+        assert_eq!(None, plain.changelog_file());
+    }
 }
@@ -0,0 +1,262 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use failure::format_err;
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::db::{self, Database, ToSql};
+use crate::error_reports::{self, Scrubber};
+use crate::errors::*;
+
+// By default, only keep this many recent log lines
+pub const MAX_TRACE_ENTRIES: u32 = 50000;
+// Lines waiting to be written: beyond that, e.g. while the database is locked, new ones are dropped
+const MAX_PENDING: usize = 1000;
+const WRITER_THREAD: &str = "request-traces";
+
+// One log line of a request or delivery, as part of its timeline
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TraceEntry {
+    pub id: i32,
+    // empty for the jobs of a delivery that is retried or consumed from a queue, which have no request
+    pub request_id: String,
+    pub delivery_id: String,
+    pub event: String,
+    pub repo: String,
+    // the PR or issue it was about, 0 if none
+    pub number: u32,
+    // the worker queue, empty while the request itself is handled
+    pub job: String,
+    // milliseconds since the epoch
+    pub at: i64,
+    // i.e. "INFO" or "ERROR"
+    pub level: String,
+    pub thread: String,
+    pub message: String,
+}
+
+impl TraceEntry {
+    pub fn new(context: &error_reports::Context, at: i64, level: &str, thread: &str, message: &str) -> TraceEntry {
+        TraceEntry {
+            id: 0,
+            request_id: context.request_id.clone(),
+            delivery_id: context.delivery_id.clone(),
+            event: context.event.clone(),
+            repo: context.repo.clone(),
+            number: context.number,
+            job: context.job.clone(),
+            at: at,
+            level: level.into(),
+            thread: thread.into(),
+            message: message.into(),
+        }
+    }
+}
+
+// What was logged while handling each request, for /api/trace
+#[derive(Clone)]
+pub struct RequestTraces {
+    db: Database,
+    max_entries: u32,
+}
+
+impl RequestTraces {
+    pub fn new(db: Database) -> RequestTraces {
+        RequestTraces {
+            db: db,
+            max_entries: MAX_TRACE_ENTRIES,
+        }
+    }
+
+    pub fn with_max_entries(self, max_entries: u32) -> RequestTraces {
+        RequestTraces {
+            max_entries: max_entries,
+            ..self
+        }
+    }
+
+    pub fn record(&self, entry: &TraceEntry) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO trace_entries
+                 (request_id, delivery_id, event, repo, number, job, at, level, thread, message)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"#,
+            &[
+                &entry.request_id as &dyn ToSql,
+                &entry.delivery_id,
+                &entry.event,
+                &entry.repo,
+                &entry.number,
+                &entry.job,
+                &entry.at,
+                &entry.level,
+                &entry.thread,
+                &entry.message,
+            ],
+        )
+        .map_err(|e| format_err!("Error recording trace of request {}: {}", entry.request_id, e))?;
+
+        conn.execute(
+            "DELETE FROM trace_entries WHERE id <= (SELECT MAX(id) FROM trace_entries) - ?1",
+            &[&self.max_entries],
+        )
+        .map_err(|e| format_err!("Error pruning trace entries: {}", e))?;
+
+        Ok(())
+    }
+
+    // What was logged for a request id or a github delivery id, including the jobs it queued, oldest first
+    pub fn timeline(&self, id: &str) -> Result<Vec<TraceEntry>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt =
+            conn.prepare("SELECT * FROM trace_entries WHERE request_id = :id OR delivery_id = :id ORDER BY id")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &id)])?;
+
+        let mut entries = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            entries.push(TraceEntry {
+                id: cols.get(row, "id")?,
+                request_id: cols.get(row, "request_id")?,
+                delivery_id: cols.get(row, "delivery_id")?,
+                event: cols.get(row, "event")?,
+                repo: cols.get(row, "repo")?,
+                number: cols.get(row, "number")?,
+                job: cols.get(row, "job")?,
+                at: cols.get(row, "at")?,
+                level: cols.get(row, "level")?,
+                thread: cols.get(row, "thread")?,
+                message: cols.get(row, "message")?,
+            });
+        }
+
+        Ok(entries)
+    }
+}
+
+// Errors are only logged as warnings from the writer thread, which has no request to trace them for
+fn write(traces: RequestTraces, entries: Receiver<TraceEntry>) {
+    for entry in entries {
+        if let Err(e) = traces.record(&entry) {
+            warn!("{}", e);
+        }
+    }
+}
+
+// Stores what is logged for requests in the background, once configured: until then (e.g. for commands other than
+// serve) it is only logged
+pub struct Tracer {
+    sender: Mutex<Option<SyncSender<TraceEntry>>>,
+    scrubber: Mutex<Scrubber>,
+}
+
+impl Tracer {
+    pub fn new() -> Arc<Tracer> {
+        Arc::new(Tracer {
+            sender: Mutex::new(None),
+            scrubber: Mutex::new(Scrubber::new(vec![])),
+        })
+    }
+
+    pub fn configure(&self, traces: RequestTraces, secrets: Vec<String>) -> Result<()> {
+        let (tx, rx) = mpsc::sync_channel(MAX_PENDING);
+        thread::Builder::new().name(WRITER_THREAD.into()).spawn(move || write(traces, rx))?;
+
+        *self.scrubber.lock().unwrap() = Scrubber::new(secrets);
+        *self.sender.lock().unwrap() = Some(tx);
+        Ok(())
+    }
+
+    // Lines logged outside of a request or delivery aren't part of any timeline
+    pub fn trace(&self, level: log::Level, message: &str) {
+        let context = error_reports::current_context();
+        if context.trace_id().is_empty() {
+            return;
+        }
+        let sender = match *self.sender.lock().unwrap() {
+            Some(ref sender) => sender.clone(),
+            None => return,
+        };
+
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64 * 1000 + d.subsec_millis() as i64)
+            .unwrap_or(0);
+        let entry = {
+            let scrubber = self.scrubber.lock().unwrap();
+            let thread = thread::current();
+            TraceEntry::new(&context, at, &level.to_string(), thread.name().unwrap_or(""), &scrubber.scrub(message))
+        };
+        match sender.try_send(entry) {
+            Ok(()) => (),
+            // warning about it would trace another line
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (RequestTraces, TempDir) {
+        let temp_dir = TempDir::new("request_traces.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (RequestTraces::new(db), temp_dir)
+    }
+
+    fn context(request_id: &str, delivery_id: &str, job: &str) -> error_reports::Context {
+        error_reports::Context {
+            request_id: request_id.into(),
+            delivery_id: delivery_id.into(),
+            event: "pull_request".into(),
+            repo: "some-org/some-repo".into(),
+            number: 32,
+            job: job.into(),
+        }
+    }
+
+    #[test]
+    fn test_timeline() {
+        let (traces, _temp_dir) = new_test();
+        let entries = vec![
+            TraceEntry::new(&context("req-1", "", ""), 1000, "INFO", "hyper", "POST /hooks/github 200"),
+            TraceEntry::new(&context("req-2", "delivery-2", ""), 2000, "INFO", "hyper", "Received event: push"),
+            TraceEntry::new(&context("req-1", "delivery-1", ""), 3000, "INFO", "hyper", "Received event: pull_request"),
+            TraceEntry::new(&context("req-1", "delivery-1", "slack"), 4000, "ERROR", "slack-1", "Error sending"),
+            TraceEntry::new(&context("", "delivery-1", "slack"), 5000, "INFO", "slack-2", "Retried delivery"),
+        ];
+        for entry in &entries {
+            traces.record(entry).unwrap();
+        }
+
+        let timeline = traces.timeline("req-1").unwrap();
+        assert_eq!(vec![1, 3, 4], timeline.iter().map(|e| e.id).collect::<Vec<_>>());
+        assert_eq!("ERROR", timeline[2].level);
+        assert_eq!("slack", timeline[2].job);
+        assert_eq!(32, timeline[2].number);
+
+        let timeline = traces.timeline("delivery-1").unwrap();
+        assert_eq!(vec![3, 4, 5], timeline.iter().map(|e| e.id).collect::<Vec<_>>());
+
+        assert_eq!(Vec::<TraceEntry>::new(), traces.timeline("req-3").unwrap());
+    }
+
+    #[test]
+    fn test_prune() {
+        let (traces, _temp_dir) = new_test();
+        let traces = traces.with_max_entries(2);
+        for i in 0..4 {
+            traces.record(&TraceEntry::new(&context("req-1", "", ""), i, "INFO", "hyper", "line")).unwrap();
+        }
+
+        let timeline = traces.timeline("req-1").unwrap();
+        assert_eq!(vec![2, 3], timeline.iter().map(|e| e.at).collect::<Vec<_>>());
+    }
+}
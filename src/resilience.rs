@@ -0,0 +1,336 @@
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::format_err;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use reqwest::{self, StatusCode};
+
+use crate::config::{ClientPolicyConfig, ClientsConfig};
+use crate::errors::*;
+use crate::faults::{self, Service};
+use crate::network;
+
+// How long to wait for GitHub, Slack and JIRA, how often to try again, and when to stop trying for a while: a service
+// that keeps failing has its circuit opened, and its requests fail right away until `open_secs` have passed. Then the
+// next request is let through, and closes the circuit again if it succeeds.
+
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_RETRIES: u32 = 2;
+pub const DEFAULT_BACKOFF_MS: u64 = 500;
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+pub const DEFAULT_OPEN_SECS: u64 = 5 * 60;
+
+// each retry waits twice as long as the one before, up to this
+const MAX_BACKOFF_MS: u64 = 30 * 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Policy {
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: Duration,
+    // consecutive failures that open the circuit
+    pub failure_threshold: u32,
+    pub open_for: Duration,
+}
+
+impl Policy {
+    pub fn new(config: &Option<ClientPolicyConfig>) -> Policy {
+        let config = config.clone().unwrap_or_default();
+        Policy {
+            connect_timeout: Duration::from_secs(config.connect_timeout_secs.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS)),
+            timeout: Duration::from_secs(config.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+            retries: config.retries.unwrap_or(DEFAULT_RETRIES),
+            backoff: Duration::from_millis(config.backoff_ms.unwrap_or(DEFAULT_BACKOFF_MS)),
+            failure_threshold: config.failure_threshold.unwrap_or(DEFAULT_FAILURE_THRESHOLD),
+            open_for: Duration::from_secs(config.open_secs.unwrap_or(DEFAULT_OPEN_SECS)),
+        }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.connect_timeout == Duration::from_secs(0) || self.timeout == Duration::from_secs(0) {
+            return Err(format_err!("connect_timeout_secs and timeout_secs must be at least 1"));
+        }
+        if self.failure_threshold == 0 {
+            return Err(format_err!("failure_threshold must be at least 1"));
+        }
+        Ok(())
+    }
+
+    // The wait before the given retry (the first is 1)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let doublings = retry.saturating_sub(1).min(16);
+        let ms = (self.backoff.as_millis() as u64).saturating_mul(1 << doublings);
+        Duration::from_millis(ms.min(MAX_BACKOFF_MS))
+    }
+}
+
+pub fn policies(config: &Option<ClientsConfig>) -> Vec<(Service, Policy)> {
+    let config = config.clone().unwrap_or_default();
+    vec![
+        (Service::Github, Policy::new(&config.github)),
+        (Service::Slack, Policy::new(&config.slack)),
+        (Service::Jira, Policy::new(&config.jira)),
+    ]
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl Breaker {
+    fn is_open(&self, now: Instant) -> bool {
+        self.open_until.map(|t| now < t).unwrap_or(false)
+    }
+
+    // Whether this opened the circuit
+    fn failed(&mut self, policy: &Policy, now: Instant) -> bool {
+        self.failures += 1;
+        if self.failures < policy.failure_threshold || self.is_open(now) {
+            return false;
+        }
+        self.open_until = Some(now + policy.open_for);
+        true
+    }
+
+    // Whether this closed the circuit
+    fn succeeded(&mut self) -> bool {
+        let was_open = self.open_until.is_some();
+        *self = Breaker::default();
+        was_open
+    }
+}
+
+// Requests made to a service since octobot started, for /metrics
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CallStats {
+    pub requests: u64,
+    // the ones that found it down or overloaded
+    pub unavailable: u64,
+}
+
+lazy_static! {
+    static ref POLICIES: RwLock<Vec<(Service, Policy)>> = RwLock::new(policies(&None));
+    static ref BREAKERS: Mutex<Vec<(Service, Breaker)>> =
+        Mutex::new(Service::all().into_iter().map(|s| (s, Breaker::default())).collect());
+    static ref CALLS: Mutex<Vec<(Service, CallStats)>> =
+        Mutex::new(Service::all().into_iter().map(|s| (s, CallStats::default())).collect());
+}
+
+// Applies the config's [clients] section to the clients created from now on: called on startup
+pub fn configure(config: &Option<ClientsConfig>) {
+    *POLICIES.write().unwrap() = policies(config);
+}
+
+pub fn policy(service: Service) -> Policy {
+    let policies = POLICIES.read().unwrap();
+    policies.iter().find(|p| p.0 == service).map(|p| p.1).unwrap_or_else(|| Policy::new(&None))
+}
+
+fn with_breaker<T>(service: Service, f: impl FnOnce(&mut Breaker) -> T) -> T {
+    let mut breakers = BREAKERS.lock().unwrap();
+    let breaker = breakers.iter_mut().find(|b| b.0 == service).map(|b| &mut b.1).expect("breaker for service");
+    f(breaker)
+}
+
+pub fn is_open(service: Service) -> bool {
+    with_breaker(service, |b| b.is_open(Instant::now()))
+}
+
+// Fails right away while the service's circuit is open
+pub fn check(service: Service) -> Result<()> {
+    let open_until = with_breaker(service, |b| b.open_until.filter(|_| b.is_open(Instant::now())));
+    match open_until {
+        Some(t) => Err(format_err!(
+            "{:?} is unavailable: not trying again for {}s",
+            service,
+            t.duration_since(Instant::now()).as_secs() + 1
+        )),
+        None => Ok(()),
+    }
+}
+
+pub fn call_stats() -> Vec<(Service, CallStats)> {
+    CALLS.lock().unwrap().clone()
+}
+
+pub fn record(service: Service, ok: bool) {
+    if let Some(calls) = CALLS.lock().unwrap().iter_mut().find(|c| c.0 == service) {
+        calls.1.requests += 1;
+        if !ok {
+            calls.1.unavailable += 1;
+        }
+    }
+
+    let policy = policy(service);
+    if ok {
+        if with_breaker(service, |b| b.succeeded()) {
+            info!("{:?} is available again: closed its circuit", service);
+        }
+    } else if with_breaker(service, |b| b.failed(&policy, Instant::now())) {
+        warn!(
+            "{:?} failed {} times in a row: not trying it again for {}s",
+            service,
+            policy.failure_threshold,
+            policy.open_for.as_secs()
+        );
+    }
+}
+
+// Whether a response means the service is down or overloaded, rather than that the request was wrong
+pub fn unavailable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+pub fn unavailable(e: &reqwest::Error) -> bool {
+    match e.status() {
+        Some(status) => unavailable_status(status),
+        // connecting or reading failed, or timed out
+        None => e.is_timeout() || e.is_http(),
+    }
+}
+
+// Makes a request to the service, retrying it after a backoff if the service was unavailable. Requests that may have
+// had an effect are only retried when the service refused them outright (429).
+pub fn run<T, F>(service: Service, idempotent: bool, mut attempt: F) -> Result<T>
+where
+    F: FnMut() -> std::result::Result<T, reqwest::Error>,
+{
+    let policy = policy(service);
+    let mut retry = 0;
+    loop {
+        check(service)?;
+        if let Err(e) = faults::check(service) {
+            record(service, false);
+            return Err(e);
+        }
+
+        let e = match attempt() {
+            Ok(t) => {
+                record(service, true);
+                return Ok(t);
+            }
+            Err(e) => e,
+        };
+        let is_unavailable = unavailable(&e);
+        record(service, !is_unavailable);
+
+        let refused = e.status() == Some(StatusCode::TOO_MANY_REQUESTS);
+        if !is_unavailable || !(idempotent || refused) || retry >= policy.retries {
+            return Err(format_err!("{}", e));
+        }
+        retry += 1;
+        warn!("{:?} request failed ({}): retry {} of {}", service, e, retry, policy.retries);
+        thread::sleep(policy.backoff(retry));
+    }
+}
+
+// A client with the service's timeouts
+pub fn client_builder(service: Service) -> reqwest::ClientBuilder {
+    let policy = policy(service);
+    network::client_builder().connect_timeout(policy.connect_timeout).timeout(policy.timeout)
+}
+
+pub fn client(service: Service) -> reqwest::Client {
+    client_builder(service).build().expect("build HTTP client")
+}
+
+pub fn async_client(service: Service) -> reqwest::r#async::Client {
+    let policy = policy(service);
+    network::async_client_builder()
+        .connect_timeout(policy.connect_timeout)
+        .timeout(policy.timeout)
+        .build()
+        .expect("build HTTP client")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_policy() -> Policy {
+        Policy::new(&Some(ClientPolicyConfig {
+            connect_timeout_secs: None,
+            timeout_secs: Some(5),
+            retries: Some(3),
+            backoff_ms: Some(100),
+            failure_threshold: Some(3),
+            open_secs: Some(60),
+        }))
+    }
+
+    #[test]
+    fn test_policy_new() {
+        let policy = Policy::new(&None);
+        assert_eq!(Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS), policy.connect_timeout);
+        assert_eq!(Duration::from_secs(DEFAULT_TIMEOUT_SECS), policy.timeout);
+        assert_eq!(DEFAULT_RETRIES, policy.retries);
+        assert!(policy.check().is_ok());
+
+        let policy = test_policy();
+        assert_eq!(Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS), policy.connect_timeout);
+        assert_eq!(Duration::from_secs(5), policy.timeout);
+        assert_eq!(3, policy.retries);
+
+        let policy = Policy {
+            failure_threshold: 0,
+            ..test_policy()
+        };
+        assert!(policy.check().is_err());
+        let policy = Policy {
+            timeout: Duration::from_secs(0),
+            ..test_policy()
+        };
+        assert!(policy.check().is_err());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = test_policy();
+        assert_eq!(Duration::from_millis(100), policy.backoff(1));
+        assert_eq!(Duration::from_millis(200), policy.backoff(2));
+        assert_eq!(Duration::from_millis(400), policy.backoff(3));
+        assert_eq!(Duration::from_millis(MAX_BACKOFF_MS), policy.backoff(100));
+    }
+
+    #[test]
+    fn test_breaker() {
+        let policy = test_policy();
+        let now = Instant::now();
+        let mut breaker = Breaker::default();
+
+        assert!(!breaker.failed(&policy, now));
+        assert!(!breaker.failed(&policy, now));
+        assert!(!breaker.is_open(now));
+        assert!(breaker.failed(&policy, now));
+        assert!(breaker.is_open(now));
+        assert!(breaker.is_open(now + Duration::from_secs(59)));
+
+        // still failing while open doesn't keep it open longer
+        assert!(!breaker.failed(&policy, now + Duration::from_secs(30)));
+        assert!(!breaker.is_open(now + Duration::from_secs(60)));
+
+        // let through after, and opened again by another failure
+        assert!(breaker.failed(&policy, now + Duration::from_secs(61)));
+        assert!(breaker.is_open(now + Duration::from_secs(62)));
+
+        assert!(breaker.succeeded());
+        assert!(!breaker.is_open(now + Duration::from_secs(62)));
+        assert!(!breaker.succeeded());
+        assert!(!breaker.failed(&policy, now));
+    }
+
+    #[test]
+    fn test_unavailable_status() {
+        assert!(unavailable_status(StatusCode::BAD_GATEWAY));
+        assert!(unavailable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(unavailable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!unavailable_status(StatusCode::NOT_FOUND));
+        assert!(!unavailable_status(StatusCode::UNAUTHORIZED));
+    }
+}
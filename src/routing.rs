@@ -0,0 +1,182 @@
+use log::error;
+use regex::Regex;
+
+use crate::path_labels;
+use crate::repos::RepoRoutingRule;
+use crate::util;
+
+// What a message is about, beyond its repo and branch. Empty if not about a pull request.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RouteContext {
+    pub paths: Vec<String>,
+    pub labels: Vec<String>,
+    // The PR's head branch
+    pub head: String,
+}
+
+impl RouteContext {
+    pub fn new(paths: Vec<String>, labels: Vec<String>) -> RouteContext {
+        RouteContext {
+            paths: paths,
+            labels: labels,
+            head: String::new(),
+        }
+    }
+
+    pub fn with_head(mut self, head: &str) -> RouteContext {
+        self.head = head.into();
+        self
+    }
+}
+
+pub fn branch_matches(glob: &str, branch: &str) -> bool {
+    match Regex::new(&format!("^{}$", util::glob_to_regex(glob))) {
+        Ok(r) => r.is_match(branch),
+        Err(e) => {
+            error!("Invalid branch glob '{}': {}", glob, e);
+            false
+        }
+    }
+}
+
+fn path_matches(glob: &str, paths: &Vec<String>) -> bool {
+    match path_labels::path_regex(glob) {
+        Ok(r) => paths.iter().any(|p| r.is_match(p)),
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    }
+}
+
+// A rule matches if all of the conditions it has match. Rules without any conditions never match.
+pub fn rule_matches(rule: &RepoRoutingRule, branch: &str, context: &RouteContext) -> bool {
+    if rule.branch.is_empty() && rule.head.is_empty() && rule.path.is_empty() && rule.label.is_empty() {
+        return false;
+    }
+
+    (rule.branch.is_empty() || branch_matches(&rule.branch, branch))
+        && (rule.head.is_empty() || branch_matches(&rule.head, &context.head))
+        && (rule.path.is_empty() || path_matches(&rule.path, &context.paths))
+        && (rule.label.is_empty() || context.labels.iter().any(|l| l.eq_ignore_ascii_case(&rule.label)))
+}
+
+// The channels of all matching rules, in rule order
+pub fn route(rules: &Vec<RepoRoutingRule>, branch: &str, context: &RouteContext) -> Vec<String> {
+    let mut channels: Vec<String> = vec![];
+    for rule in rules.iter().filter(|r| !r.silent && rule_matches(r, branch, context)) {
+        for channel in rule.channel_list() {
+            if !channels.contains(&channel) {
+                channels.push(channel);
+            }
+        }
+    }
+    channels
+}
+
+// The Opsgenie responders of the rules matching the branch alone, in rule order. Alerts aren't about a pull request,
+// so rules with a path or label never match them.
+pub fn opsgenie_responders(rules: &Vec<RepoRoutingRule>, branch: &str) -> Vec<String> {
+    let mut responders: Vec<String> = vec![];
+    for rule in rules.iter().filter(|r| rule_matches(r, branch, &RouteContext::default())) {
+        let responder = rule.opsgenie.trim();
+        if !responder.is_empty() && !responders.iter().any(|r| r == responder) {
+            responders.push(responder.to_string());
+        }
+    }
+    responders
+}
+
+// Whether a matching silent rule keeps the message out of the repo's channels
+pub fn is_silenced(rules: &Vec<RepoRoutingRule>, branch: &str, context: &RouteContext) -> bool {
+    rules.iter().any(|r| r.silent && rule_matches(r, branch, context))
+}
+
+pub fn needs_paths(rules: &Vec<RepoRoutingRule>) -> bool {
+    rules.iter().any(|r| !r.path.is_empty())
+}
+
+pub fn needs_head(rules: &Vec<RepoRoutingRule>) -> bool {
+    rules.iter().any(|r| !r.head.is_empty())
+}
+
+pub fn needs_labels(rules: &Vec<RepoRoutingRule>) -> bool {
+    rules.iter().any(|r| !r.label.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Vec<RepoRoutingRule> {
+        vec![
+            RepoRoutingRule::new("release/*", "", "", "releases"),
+            RepoRoutingRule::new("", "docs/**", "", "docs, writers"),
+            RepoRoutingRule::new("", "", "security", "security"),
+            RepoRoutingRule::new("main", "**/*.sql", "", "dba"),
+            RepoRoutingRule::new("", "", "", "everything"),
+        ]
+    }
+
+    #[test]
+    fn test_route_by_branch() {
+        let none = RouteContext::default();
+        assert_eq!(vec!["releases"], route(&rules(), "release/1.0", &none));
+        assert_eq!(Vec::<String>::new(), route(&rules(), "release/1.0/hotfix", &none));
+        assert_eq!(Vec::<String>::new(), route(&rules(), "main", &none));
+    }
+
+    #[test]
+    fn test_route_by_path_and_label() {
+        let context = RouteContext::new(vec!["docs/README.md".into(), "db/schema.sql".into()], vec!["Security".into()]);
+        assert_eq!(vec!["docs", "writers", "security"], route(&rules(), "feature", &context));
+        assert_eq!(vec!["docs", "writers", "security", "dba"], route(&rules(), "main", &context));
+        assert_eq!(
+            vec!["releases", "docs", "writers", "security"],
+            route(&rules(), "release/2.0", &context)
+        );
+    }
+
+    #[test]
+    fn test_route_by_head_branch() {
+        let rules = vec![
+            RepoRoutingRule::new("release/*", "", "", "release-captains"),
+            RepoRoutingRule::new("main", "", "", "team"),
+            RepoRoutingRule::new("feature/*", "", "", "").with_head("feature/*").silenced(),
+        ];
+        let feature = RouteContext::default().with_head("feature/b");
+        assert_eq!(vec!["release-captains"], route(&rules, "release/1.0", &feature));
+        assert_eq!(Vec::<String>::new(), route(&rules, "feature/a", &feature));
+        assert_eq!(true, is_silenced(&rules, "feature/a", &feature));
+        assert_eq!(false, is_silenced(&rules, "main", &feature));
+
+        // pushes and other events that aren't about a pull request have no head branch
+        assert_eq!(false, is_silenced(&rules, "feature/a", &RouteContext::default()));
+    }
+
+    #[test]
+    fn test_opsgenie_responders() {
+        let paged = vec![
+            RepoRoutingRule::new("release/*", "", "", "releases").with_opsgenie("release-eng"),
+            RepoRoutingRule::new("main", "", "", "").with_opsgenie("escalation:Backend Escalation"),
+            RepoRoutingRule::new("main", "**/*.sql", "", "dba").with_opsgenie("dba"),
+            RepoRoutingRule::new("release/2.*", "", "", "").with_opsgenie(" release-eng "),
+        ];
+        assert_eq!(vec!["release-eng"], opsgenie_responders(&paged, "release/2.0"));
+        assert_eq!(vec!["escalation:Backend Escalation"], opsgenie_responders(&paged, "main"));
+        assert_eq!(Vec::<String>::new(), opsgenie_responders(&paged, "feature"));
+        assert_eq!(Vec::<String>::new(), opsgenie_responders(&rules(), "release/1.0"));
+    }
+
+    #[test]
+    fn test_needs() {
+        assert_eq!(true, needs_paths(&rules()));
+        assert_eq!(true, needs_labels(&rules()));
+        assert_eq!(false, needs_head(&rules()));
+        assert_eq!(true, needs_head(&vec![RepoRoutingRule::new("", "", "", "").with_head("feature/*").silenced()]));
+
+        let branch_only = vec![RepoRoutingRule::new("release/*", "", "", "releases")];
+        assert_eq!(false, needs_paths(&branch_only));
+        assert_eq!(false, needs_labels(&branch_only));
+    }
+}
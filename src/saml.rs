@@ -0,0 +1,1091 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::mem;
+use std::ptr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use failure::format_err;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, signature};
+use url::Url;
+
+use crate::config::SamlConfig;
+use crate::errors::*;
+use crate::util;
+
+pub const SAML_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+pub const SAMLP_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const METADATA_NS: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+
+// the only algorithms octobot accepts in signatures
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+const SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+
+// how long users have to log in at the IdP
+const REQUEST_EXPIRY_SECS: u64 = 10 * 60;
+// pending logins: beyond that, the oldest are dropped
+const MAX_REQUESTS: usize = 1000;
+// how far the IdP's clock may be off from ours
+const CLOCK_SKEW_SECS: i64 = 2 * 60;
+const MAX_XML_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Element {
+    // the qualified name, e.g. "saml:Assertion"
+    pub name: String,
+    // attributes other than namespace declarations, in document order
+    pub attrs: Vec<(String, String)>,
+    // the namespaces in scope, by prefix ("" for the default namespace): the innermost declarations last
+    namespaces: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+fn split_name(name: &str) -> (&str, &str) {
+    match name.find(':') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => ("", name),
+    }
+}
+
+impl Element {
+    pub fn local_name(&self) -> &str {
+        split_name(&self.name).1
+    }
+
+    fn namespace_of(&self, prefix: &str) -> Option<&str> {
+        if prefix == "xml" {
+            return Some(XML_NS);
+        }
+        self.namespaces.iter().rev().find(|n| n.0 == prefix).map(|n| n.1.as_str()).filter(|uri| !uri.is_empty())
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace_of(split_name(&self.name).0)
+    }
+
+    pub fn is(&self, namespace: &str, local_name: &str) -> bool {
+        self.local_name() == local_name && self.namespace() == Some(namespace)
+    }
+
+    // An attribute without a namespace
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|a| a.0 == name).map(|a| a.1.as_str())
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|c| match *c {
+            Node::Element(ref e) => Some(e),
+            Node::Text(_) => None,
+        })
+    }
+
+    pub fn children_named<'a>(&'a self, namespace: &'a str, local_name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.elements().filter(move |e| e.is(namespace, local_name))
+    }
+
+    pub fn child(&self, namespace: &str, local_name: &str) -> Option<&Element> {
+        self.elements().find(|e| e.is(namespace, local_name))
+    }
+
+    // The text directly in the element
+    pub fn text(&self) -> String {
+        self.children
+            .iter()
+            .filter_map(|c| match *c {
+                Node::Text(ref t) => Some(t.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect()
+    }
+}
+
+fn is_xml_space(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n' || c == '\r'
+}
+
+// Replaces character and entity references, and whitespace characters with spaces in attribute values
+fn decode_text(raw: &str, attribute: bool) -> Result<String> {
+    let normalize = |text: &str| -> String {
+        if attribute {
+            text.replace(|c: char| c == '\t' || c == '\n', " ")
+        } else {
+            text.to_string()
+        }
+    };
+    if attribute && raw.contains('<') {
+        return Err(format_err!("Invalid '<' in XML attribute value"));
+    }
+
+    let mut text = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find('&') {
+        text.push_str(&normalize(&rest[..start]));
+        let end = rest[start..].find(';').ok_or_else(|| format_err!("Unterminated XML reference"))? + start;
+        let reference = &rest[start + 1..end];
+        let c = match reference {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if reference.starts_with("#x") => {
+                u32::from_str_radix(&reference[2..], 16).ok().and_then(std::char::from_u32)
+            }
+            _ if reference.starts_with('#') => reference[1..].parse::<u32>().ok().and_then(std::char::from_u32),
+            _ => None,
+        };
+        text.push(c.ok_or_else(|| format_err!("Unsupported XML reference: &{};", reference))?);
+        rest = &rest[end + 1..];
+    }
+    text.push_str(&normalize(rest));
+    Ok(text)
+}
+
+struct XmlReader<'a> {
+    xml: &'a str,
+    pos: usize,
+}
+
+impl<'a> XmlReader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.xml[self.pos..]
+    }
+
+    fn skip(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<()> {
+        if self.skip(s) {
+            Ok(())
+        } else {
+            Err(format_err!("Invalid XML: expected '{}' at {}", s, self.pos))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches(is_xml_space).len();
+    }
+
+    // Everything up to `end`, which is skipped too
+    fn take_until(&mut self, end: &str) -> Result<&'a str> {
+        let rest = self.rest();
+        let len = rest.find(end).ok_or_else(|| format_err!("Invalid XML: no '{}' after {}", end, self.pos))?;
+        self.pos += len + end.len();
+        Ok(&rest[..len])
+    }
+
+    fn name(&mut self) -> Result<&'a str> {
+        let rest = self.rest();
+        let len = rest.find(|c: char| is_xml_space(c) || c == '/' || c == '>' || c == '=').unwrap_or(rest.len());
+        if len == 0 {
+            return Err(format_err!("Invalid XML: expected a name at {}", self.pos));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    // Comments and processing instructions (like the XML declaration) around the document element
+    fn skip_misc(&mut self) -> Result<()> {
+        loop {
+            self.skip_whitespace();
+            if self.skip("<!--") {
+                self.take_until("-->")?;
+            } else if self.skip("<?") {
+                self.take_until("?>")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn element(&mut self, namespaces: &[(String, String)], depth: usize) -> Result<Element> {
+        if depth > MAX_XML_DEPTH {
+            return Err(format_err!("XML nested too deeply"));
+        }
+        self.expect("<")?;
+        let mut element = Element {
+            name: self.name()?.to_string(),
+            attrs: vec![],
+            namespaces: namespaces.to_vec(),
+            children: vec![],
+        };
+
+        let empty = loop {
+            self.skip_whitespace();
+            if self.skip("/>") {
+                break true;
+            }
+            if self.skip(">") {
+                break false;
+            }
+            let name = self.name()?.to_string();
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = if self.skip("'") { "'" } else { self.expect("\"").map(|_| "\"")? };
+            let value = decode_text(self.take_until(quote)?, true)?;
+
+            if name == "xmlns" {
+                element.namespaces.push((String::new(), value));
+            } else if name.starts_with("xmlns:") {
+                element.namespaces.push((name["xmlns:".len()..].to_string(), value));
+            } else if element.attrs.iter().any(|a| a.0 == name) {
+                return Err(format_err!("Duplicate XML attribute: {}", name));
+            } else {
+                element.attrs.push((name, value));
+            }
+        };
+
+        let names = Some(&element.name).into_iter().chain(element.attrs.iter().map(|a| &a.0));
+        for prefix in names.map(|n| split_name(n).0).filter(|p| !p.is_empty()) {
+            if element.namespace_of(prefix).is_none() {
+                return Err(format_err!("Undeclared XML namespace prefix: {}", prefix));
+            }
+        }
+
+        if !empty {
+            element.children = self.content(&element, depth)?;
+        }
+        Ok(element)
+    }
+
+    fn content(&mut self, parent: &Element, depth: usize) -> Result<Vec<Node>> {
+        let mut children = vec![];
+        // text around comments is one text node, as it is once they are left out
+        let mut text = String::new();
+        loop {
+            let rest = self.rest();
+            let len = rest.find('<').ok_or_else(|| format_err!("Invalid XML: {} is not closed", parent.name))?;
+            text.push_str(&decode_text(&rest[..len], false)?);
+            self.pos += len;
+
+            if self.skip("</") {
+                if self.name()? != parent.name {
+                    return Err(format_err!("Invalid XML: {} is not closed", parent.name));
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                if !text.is_empty() {
+                    children.push(Node::Text(text));
+                }
+                return Ok(children);
+            } else if self.skip("<!--") {
+                self.take_until("-->")?;
+            } else if self.skip("<![CDATA[") {
+                text.push_str(self.take_until("]]>")?);
+            } else if self.rest().starts_with("<!") || self.rest().starts_with("<?") {
+                return Err(format_err!("Unsupported XML at {}", self.pos));
+            } else {
+                if !text.is_empty() {
+                    children.push(Node::Text(mem::replace(&mut text, String::new())));
+                }
+                children.push(Node::Element(self.element(&parent.namespaces, depth + 1)?));
+            }
+        }
+    }
+}
+
+// Parses an XML document into its document element. Comments are left out. DTDs, and so entities other than the
+// predefined ones, are refused.
+pub fn parse_xml(xml: &str) -> Result<Element> {
+    let xml = xml.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+    let mut reader = XmlReader { xml: &xml, pos: 0 };
+    reader.skip_misc()?;
+    if reader.rest().starts_with("<!") {
+        return Err(format_err!("XML DTDs are not supported"));
+    }
+    let root = reader.element(&[], 0)?;
+    reader.skip_misc()?;
+    if !reader.rest().is_empty() {
+        return Err(format_err!("Invalid XML: content after the document element"));
+    }
+    Ok(root)
+}
+
+fn escape_text(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+fn escape_attr(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    escape_attr(value, &mut escaped);
+    escaped
+}
+
+// Exclusive XML canonicalization (without comments) of the element, leaving out the `excluded` child (an enveloped
+// signature). The `inclusive` prefixes ("#default" for the default namespace) are declared wherever they are in
+// scope, rather than only where they are used.
+pub fn canonicalize(element: &Element, inclusive: &[String], excluded: Option<&Element>) -> String {
+    let mut out = String::new();
+    write_canonical(element, inclusive, excluded, &[], &mut out);
+    out
+}
+
+// `rendered` are the namespace declarations already written by the element's ancestors
+fn write_canonical(
+    element: &Element,
+    inclusive: &[String],
+    excluded: Option<&Element>,
+    rendered: &[(String, String)],
+    out: &mut String,
+) {
+    let mut prefixes = vec![split_name(&element.name).0];
+    prefixes.extend(element.attrs.iter().map(|a| split_name(&a.0).0).filter(|p| !p.is_empty()));
+    let included = inclusive.iter().map(|p| if p == "#default" { "" } else { p.as_str() });
+    prefixes.extend(included.filter(|p| p.is_empty() || element.namespace_of(p).is_some()));
+    prefixes.sort();
+    prefixes.dedup();
+
+    let mut declarations = vec![];
+    for prefix in prefixes.into_iter().filter(|p| *p != "xml") {
+        let uri = element.namespace_of(prefix).unwrap_or("");
+        let current = rendered.iter().rev().find(|n| n.0 == prefix).map(|n| n.1.as_str()).unwrap_or("");
+        if uri != current {
+            declarations.push((prefix.to_string(), uri.to_string()));
+        }
+    }
+
+    let mut attrs = element
+        .attrs
+        .iter()
+        .map(|a| {
+            let (prefix, local_name) = split_name(&a.0);
+            let namespace = if prefix.is_empty() { "" } else { element.namespace_of(prefix).unwrap_or("") };
+            (namespace, local_name, a)
+        })
+        .collect::<Vec<_>>();
+    attrs.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    out.push('<');
+    out.push_str(&element.name);
+    for &(ref prefix, ref uri) in &declarations {
+        out.push_str(if prefix.is_empty() { " xmlns" } else { " xmlns:" });
+        out.push_str(prefix);
+        out.push_str("=\"");
+        escape_attr(uri, out);
+        out.push('"');
+    }
+    for &(_, _, &(ref name, ref value)) in &attrs {
+        out.push(' ');
+        out.push_str(name);
+        out.push_str("=\"");
+        escape_attr(value, out);
+        out.push('"');
+    }
+    out.push('>');
+
+    let rendered = [rendered, &declarations[..]].concat();
+    for child in &element.children {
+        match *child {
+            Node::Text(ref text) => escape_text(text, out),
+            Node::Element(ref e) if excluded.map(|x| ptr::eq(x, e)).unwrap_or(false) => (),
+            Node::Element(ref e) => write_canonical(e, inclusive, excluded, &rendered, out),
+        }
+    }
+
+    out.push_str("</");
+    out.push_str(&element.name);
+    out.push('>');
+}
+
+fn check_algorithm(parent: &Element, name: &str, expected: &str) -> Result<()> {
+    match parent.child(DSIG_NS, name).and_then(|e| e.attr("Algorithm")) {
+        Some(a) if a == expected => Ok(()),
+        a => Err(format_err!("Unsupported {}: {}", name, a.unwrap_or("none"))),
+    }
+}
+
+// The InclusiveNamespaces PrefixList of an exclusive canonicalization
+fn inclusive_prefixes(method: &Element) -> Vec<String> {
+    method
+        .child(EXC_C14N, "InclusiveNamespaces")
+        .and_then(|i| i.attr("PrefixList"))
+        .map(|list| list.split_whitespace().map(|p| p.to_string()).collect())
+        .unwrap_or_default()
+}
+
+fn decode_base64(value: &str) -> Result<Vec<u8>> {
+    let value = value.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    base64::decode(&value).map_err(|e| format_err!("Invalid base64: {}", e))
+}
+
+// Checks the enveloped signature of the element against the IdP's key, and says whether the element is signed at all
+fn verify_signature(element: &Element, idp_key: &[u8]) -> Result<bool> {
+    let signatures = element.children_named(DSIG_NS, "Signature").collect::<Vec<_>>();
+    let enveloped = match signatures.len() {
+        0 => return Ok(false),
+        1 => signatures[0],
+        _ => return Err(format_err!("The {} has more than one signature", element.local_name())),
+    };
+    let signed_info =
+        enveloped.child(DSIG_NS, "SignedInfo").ok_or_else(|| format_err!("No SignedInfo in the signature"))?;
+    check_algorithm(signed_info, "CanonicalizationMethod", EXC_C14N)?;
+    check_algorithm(signed_info, "SignatureMethod", RSA_SHA256)?;
+
+    // it must reference the element it's in, and nothing else
+    let id = element.attr("ID").unwrap_or("");
+    let references = signed_info.children_named(DSIG_NS, "Reference").collect::<Vec<_>>();
+    let reference = match references.as_slice() {
+        [r] if !id.is_empty() && r.attr("URI") == Some(format!("#{}", id).as_str()) => *r,
+        _ => return Err(format_err!("The signature is not of the {}", element.local_name())),
+    };
+    let mut inclusive = vec![];
+    let transforms = reference.child(DSIG_NS, "Transforms");
+    for transform in transforms.into_iter().flat_map(|t| t.children_named(DSIG_NS, "Transform")) {
+        match transform.attr("Algorithm") {
+            Some(ENVELOPED_SIGNATURE) => (),
+            Some(EXC_C14N) => inclusive = inclusive_prefixes(transform),
+            a => return Err(format_err!("Unsupported transform: {}", a.unwrap_or("none"))),
+        }
+    }
+    check_algorithm(reference, "DigestMethod", SHA256)?;
+
+    let digest_value = decode_base64(&reference.child(DSIG_NS, "DigestValue").map(|d| d.text()).unwrap_or_default())?;
+    let canonical = canonicalize(element, &inclusive, Some(enveloped));
+    if digest::digest(&digest::SHA256, canonical.as_bytes()).as_ref() != digest_value.as_slice() {
+        return Err(format_err!("The {} does not match its signature", element.local_name()));
+    }
+
+    let method = signed_info.child(DSIG_NS, "CanonicalizationMethod").map(inclusive_prefixes).unwrap_or_default();
+    let signature_value =
+        decode_base64(&enveloped.child(DSIG_NS, "SignatureValue").map(|v| v.text()).unwrap_or_default())?;
+    signature::verify(
+        &signature::RSA_PKCS1_2048_8192_SHA256,
+        untrusted::Input::from(idp_key),
+        untrusted::Input::from(canonicalize(signed_info, &method, None).as_bytes()),
+        untrusted::Input::from(&signature_value),
+    )
+    .map_err(|_| format_err!("Invalid signature of the {}", element.local_name()))?;
+    Ok(true)
+}
+
+const SEQUENCE: u8 = 0x30;
+const BIT_STRING: u8 = 0x03;
+const OBJECT_ID: u8 = 0x06;
+const VERSION_TAG: u8 = 0xa0;
+// 1.2.840.113549.1.1.1
+const RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+// The DER value at the start of `data`: its tag, its contents, and what follows it
+fn der_value(data: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    if data.len() < 2 {
+        return Err(format_err!("Truncated certificate"));
+    }
+    let (len, start) = match data[1] as usize {
+        len if len < 0x80 => (len, 2),
+        n => {
+            let count = n & 0x7f;
+            if count == 0 || count > 4 || data.len() < 2 + count {
+                return Err(format_err!("Invalid certificate"));
+            }
+            (data[2..2 + count].iter().fold(0, |len, b| (len << 8) | *b as usize), 2 + count)
+        }
+    };
+    if data.len() - start < len {
+        return Err(format_err!("Truncated certificate"));
+    }
+    Ok((data[0], &data[start..start + len], &data[start + len..]))
+}
+
+fn der_expect(data: &[u8], tag: u8) -> Result<(&[u8], &[u8])> {
+    match der_value(data)? {
+        (t, contents, rest) if t == tag => Ok((contents, rest)),
+        (t, _, _) => Err(format_err!("Invalid certificate: expected tag {}, got {}", tag, t)),
+    }
+}
+
+// The RSA public key of a PEM certificate, as ring takes it
+fn public_key(pem: &str) -> Result<Vec<u8>> {
+    let begin = "-----BEGIN CERTIFICATE-----";
+    let start = pem.find(begin).ok_or_else(|| format_err!("No PEM certificate"))? + begin.len();
+    let end = pem[start..].find("-----END CERTIFICATE-----").ok_or_else(|| format_err!("No PEM certificate"))?;
+    let der = decode_base64(&pem[start..start + end])?;
+
+    let (certificate, _) = der_expect(&der, SEQUENCE)?;
+    let (mut tbs, _) = der_expect(certificate, SEQUENCE)?;
+    // the version (if not v1), serial number, signature algorithm, issuer, validity and subject come first
+    if tbs.first() == Some(&VERSION_TAG) {
+        tbs = der_value(tbs)?.2;
+    }
+    for _ in 0..5 {
+        tbs = der_value(tbs)?.2;
+    }
+    let (key_info, _) = der_expect(tbs, SEQUENCE)?;
+    let (algorithm, key) = der_expect(key_info, SEQUENCE)?;
+    if der_expect(algorithm, OBJECT_ID)?.0 != RSA_ENCRYPTION {
+        return Err(format_err!("The certificate's key is not an RSA key"));
+    }
+    match der_expect(key, BIT_STRING)?.0.split_first() {
+        Some((&0, key)) => Ok(key.to_vec()),
+        _ => Err(format_err!("Invalid certificate key")),
+    }
+}
+
+// The key the IdP signs with, from its certificate
+pub fn idp_key(config: &SamlConfig) -> Result<Vec<u8>> {
+    let pem = fs::read_to_string(&config.idp_cert_file)
+        .map_err(|e| format_err!("Error reading {}: {}", config.idp_cert_file, e))?;
+    public_key(&pem)
+}
+
+// What the IdP asserted about a user who logged in
+#[derive(Clone, Debug, PartialEq)]
+pub struct SamlLogin {
+    pub user: String,
+    pub groups: Vec<String>,
+    // the id of octobot's request the IdP answered
+    pub request_id: String,
+}
+
+// The times may be in fractions of seconds, but must be in UTC
+fn parse_time(value: &str) -> Result<i64> {
+    let seconds = match value.find('.') {
+        Some(i) if value.ends_with('Z') => format!("{}Z", &value[..i]),
+        _ => value.to_string(),
+    };
+    util::parse_timestamp(&seconds).ok_or_else(|| format_err!("Invalid time: {}", value))
+}
+
+fn check_time_window(element: &Element, now: i64) -> Result<()> {
+    if let Some(not_before) = element.attr("NotBefore") {
+        if now + CLOCK_SKEW_SECS < parse_time(not_before)? {
+            return Err(format_err!("The assertion is not valid until {}", not_before));
+        }
+    }
+    if let Some(not_on_or_after) = element.attr("NotOnOrAfter") {
+        if now - CLOCK_SKEW_SECS >= parse_time(not_on_or_after)? {
+            return Err(format_err!("The assertion expired at {}", not_on_or_after));
+        }
+    }
+    Ok(())
+}
+
+fn check_issuer(config: &SamlConfig, issuer: Option<&Element>) -> Result<()> {
+    match issuer.map(|i| i.text()) {
+        Some(ref i) if i.trim() == config.idp_entity_id => Ok(()),
+        i => Err(format_err!("Issued by {}, not by the IdP", i.unwrap_or_else(|| "no one".into()).trim())),
+    }
+}
+
+// Signatures reference what they sign by ID: with duplicates, one element's signature could pass for another's
+fn check_unique_ids(root: &Element) -> Result<()> {
+    let mut ids = HashSet::new();
+    let mut elements = vec![root];
+    while let Some(element) = elements.pop() {
+        if let Some(id) = element.attr("ID") {
+            if !ids.insert(id) {
+                return Err(format_err!("Duplicate ID in the response: {}", id));
+            }
+        }
+        elements.extend(element.elements());
+    }
+    Ok(())
+}
+
+// The user must be confirmed as the bearer of the assertion, which was posted to octobot in answer to its request
+fn check_confirmation(config: &SamlConfig, subject: &Element, request_id: &str, now: i64) -> Result<()> {
+    let confirmed = subject
+        .children_named(SAML_NS, "SubjectConfirmation")
+        .filter(|c| c.attr("Method") == Some(BEARER))
+        .filter_map(|c| c.child(SAML_NS, "SubjectConfirmationData"))
+        .any(|data| {
+            data.attr("Recipient") == Some(config.acs_url.as_str())
+                && data.attr("InResponseTo").map(|id| id == request_id).unwrap_or(true)
+                && data.attr("NotOnOrAfter").is_some()
+                && check_time_window(data, now).is_ok()
+        });
+    if !confirmed {
+        return Err(format_err!("The assertion has no valid bearer confirmation for {}", config.acs_url));
+    }
+    Ok(())
+}
+
+// Checks the IdP's response to a login (that it signed it, for octobot, and that it's still valid), and says who
+// logged in. The response or its assertion must be signed; encrypted assertions aren't supported.
+pub fn verify_response(config: &SamlConfig, idp_key: &[u8], xml: &str, now: i64) -> Result<SamlLogin> {
+    let response = parse_xml(xml)?;
+    if !response.is(SAMLP_NS, "Response") {
+        return Err(format_err!("Not a SAML response: {}", response.name));
+    }
+    check_unique_ids(&response)?;
+
+    if let Some(destination) = response.attr("Destination") {
+        if destination != config.acs_url {
+            return Err(format_err!("The response is for {}", destination));
+        }
+    }
+    if response.child(SAML_NS, "Issuer").is_some() {
+        check_issuer(config, response.child(SAML_NS, "Issuer"))?;
+    }
+    let status = response.child(SAMLP_NS, "Status").and_then(|s| s.child(SAMLP_NS, "StatusCode"));
+    match status.and_then(|s| s.attr("Value")) {
+        Some(SUCCESS) => (),
+        s => return Err(format_err!("The IdP refused the login: {}", s.unwrap_or("no status"))),
+    }
+    // logins started at the IdP aren't accepted
+    let request_id = response.attr("InResponseTo").ok_or_else(|| format_err!("The response is not for a request"))?;
+
+    if response.child(SAML_NS, "EncryptedAssertion").is_some() {
+        return Err(format_err!("Encrypted assertions are not supported"));
+    }
+    let assertions = response.children_named(SAML_NS, "Assertion").collect::<Vec<_>>();
+    if assertions.len() != 1 {
+        return Err(format_err!("Expected one assertion, got {}", assertions.len()));
+    }
+    let assertion = assertions[0];
+
+    let response_signed = verify_signature(&response, idp_key)?;
+    if !verify_signature(assertion, idp_key)? && !response_signed {
+        return Err(format_err!("Neither the response nor its assertion are signed"));
+    }
+
+    check_issuer(config, assertion.child(SAML_NS, "Issuer"))?;
+    if let Some(conditions) = assertion.child(SAML_NS, "Conditions") {
+        check_time_window(conditions, now)?;
+        for restriction in conditions.children_named(SAML_NS, "AudienceRestriction") {
+            if !restriction.children_named(SAML_NS, "Audience").any(|a| a.text().trim() == config.entity_id) {
+                return Err(format_err!("The assertion is not for {}", config.entity_id));
+            }
+        }
+    }
+    let subject = assertion.child(SAML_NS, "Subject").ok_or_else(|| format_err!("The assertion has no subject"))?;
+    check_confirmation(config, subject, request_id, now)?;
+
+    let values = |name: &str| -> Vec<String> {
+        assertion
+            .children_named(SAML_NS, "AttributeStatement")
+            .flat_map(|s| s.children_named(SAML_NS, "Attribute"))
+            .filter(|a| a.attr("Name") == Some(name))
+            .flat_map(|a| a.children_named(SAML_NS, "AttributeValue"))
+            .map(|v| v.text().trim().to_string())
+            .collect()
+    };
+    let user = match config.username_attribute {
+        Some(ref attribute) => values(attribute).into_iter().next(),
+        None => subject.child(SAML_NS, "NameID").map(|n| n.text().trim().to_string()),
+    };
+    let user = user.filter(|u| !u.is_empty()).ok_or_else(|| format_err!("The assertion has no user name"))?;
+
+    Ok(SamlLogin {
+        user: user,
+        groups: config.groups_attribute.as_ref().map(|a| values(a)).unwrap_or_default(),
+        request_id: request_id.into(),
+    })
+}
+
+fn format_time(now: i64) -> String {
+    time::strftime("%Y-%m-%dT%H:%M:%SZ", &time::at_utc(time::Timespec::new(now, 0))).unwrap_or_default()
+}
+
+pub fn authn_request(config: &SamlConfig, request_id: &str, now: i64) -> String {
+    format!(
+        "<samlp:AuthnRequest xmlns:samlp=\"{}\" xmlns:saml=\"{}\" ID=\"{}\" Version=\"2.0\" IssueInstant=\"{}\" \
+         Destination=\"{}\" AssertionConsumerServiceURL=\"{}\" ProtocolBinding=\"{}\">\
+         <saml:Issuer>{}</saml:Issuer></samlp:AuthnRequest>",
+        SAMLP_NS,
+        SAML_NS,
+        escape(request_id),
+        format_time(now),
+        escape(&config.idp_sso_url),
+        escape(&config.acs_url),
+        POST_BINDING,
+        escape(&config.entity_id)
+    )
+}
+
+// Where to send the user to log in: the IdP, with the request deflated into the URL (the HTTP-Redirect binding)
+pub fn login_url(config: &SamlConfig, request_id: &str, now: i64) -> Result<String> {
+    let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+    encoder.write_all(authn_request(config, request_id, now).as_bytes())?;
+    let deflated = encoder.finish()?;
+
+    let mut url = Url::parse(&config.idp_sso_url)?;
+    url.query_pairs_mut().append_pair("SAMLRequest", &base64::encode(&deflated));
+    Ok(url.into_string())
+}
+
+// octobot's service provider metadata, to register it with the IdP
+pub fn metadata(config: &SamlConfig) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <md:EntityDescriptor xmlns:md=\"{}\" entityID=\"{}\">\n  \
+         <md:SPSSODescriptor protocolSupportEnumeration=\"{}\">\n    \
+         <md:AssertionConsumerService Binding=\"{}\" Location=\"{}\" index=\"0\" isDefault=\"true\"/>\n  \
+         </md:SPSSODescriptor>\n\
+         </md:EntityDescriptor>\n",
+        METADATA_NS,
+        escape(&config.entity_id),
+        SAMLP_NS,
+        POST_BINDING,
+        escape(&config.acs_url)
+    )
+}
+
+// What's wrong with the [saml] section
+pub fn check_config(config: &SamlConfig) -> Vec<String> {
+    let mut errors = vec![];
+    for &(name, url) in &[("acs_url", &config.acs_url), ("idp_sso_url", &config.idp_sso_url)] {
+        match Url::parse(url) {
+            Ok(ref u) if u.scheme() == "https" || u.scheme() == "http" => (),
+            Ok(_) => errors.push(format!("{} must be an http(s) URL", name)),
+            Err(e) => errors.push(format!("{}: {}", name, e)),
+        }
+    }
+    if config.entity_id.trim().is_empty() {
+        errors.push("entity_id is required".into());
+    }
+    if config.idp_entity_id.trim().is_empty() {
+        errors.push("idp_entity_id is required".into());
+    }
+    if let Err(e) = idp_key(config) {
+        errors.push(format!("idp_cert_file: {}", e));
+    }
+    if config.group_roles.is_some() && config.groups_attribute.is_none() {
+        errors.push("group_roles needs groups_attribute".into());
+    }
+    errors
+}
+
+// Logins sent to the IdP, until it answers them or they expire
+pub struct Requests {
+    requests: Mutex<Vec<(String, Instant)>>,
+}
+
+impl Requests {
+    pub fn new() -> Requests {
+        Requests {
+            requests: Mutex::new(vec![]),
+        }
+    }
+
+    // The id of a new request. XML IDs may not start with a digit.
+    pub fn start(&self) -> String {
+        let mut bytes = [0u8; 20];
+        SystemRandom::new().fill(&mut bytes).expect("get random");
+        let id = format!("_{}", bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+
+        let mut requests = self.requests.lock().unwrap();
+        requests.retain(|r| r.1.elapsed() < Duration::from_secs(REQUEST_EXPIRY_SECS));
+        if requests.len() >= MAX_REQUESTS {
+            requests.remove(0);
+        }
+        requests.push((id.clone(), Instant::now()));
+        id
+    }
+
+    // Whether the request is pending. Each can only be answered once.
+    pub fn take(&self, id: &str) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        match requests.iter().position(|r| r.0 == id) {
+            Some(index) => requests.remove(index).1.elapsed() < Duration::from_secs(REQUEST_EXPIRY_SECS),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    const IDP_CERT: &str = "-----BEGIN CERTIFICATE-----\n\
+MIIDFzCCAf+gAwIBAgIUSd4BrdwVmScB+S0XzG897LsIAJcwDQYJKoZIhvcNAQEL\n\
+BQAwGjEYMBYGA1UEAwwPaWRwLmNvbXBhbnkuY29tMCAXDTI2MTAxNTE1MTEwMVoY\n\
+DzIxMjYwOTIxMTUxMTAxWjAaMRgwFgYDVQQDDA9pZHAuY29tcGFueS5jb20wggEi\n\
+MA0GCSqGSIb3DQEBAQUAA4IBDwAwggEKAoIBAQCORlTjcDWGCjSLr/QfsNXfzAm5\n\
+jJ/aGYf+alTuopFas+mav56jl6yHdVcuFtpQAkxwX+XXxIcqzUwAjEYJdZ5ZxCaf\n\
+vjfxl5NUZkXBDaCkCoNptTCKuGb2F0U62anl8IUK8NpnEGAVbIgWW57QENkBr2/Q\n\
+gdTzVbkJn890VXR/OuhVS+yzikX4EyfUf41GlKeuSXSQeCMYqkpDykwp9XCnn0Sh\n\
+bI0ou06XAb1taEJAQRFm1HKJ4Y1g0NfDnUH98Rel8fhUraGPDBP0gL+jF5zs6Lpl\n\
+7qsAYne3DoLHex8D0Q+7/pON/cZTsIRQPNo0vJRXaD0VjefZyq0bOUakjQaxAgMB\n\
+AAGjUzBRMB0GA1UdDgQWBBSK0DWinPEAIIFzGTJuZ+WGf7mrSjAfBgNVHSMEGDAW\n\
+gBSK0DWinPEAIIFzGTJuZ+WGf7mrSjAPBgNVHRMBAf8EBTADAQH/MA0GCSqGSIb3\n\
+DQEBCwUAA4IBAQBQI7y9e5IBxoaRh0qCX+TrKRDIZ0xaiwaCIvU0Mqi2hrB/5iD9\n\
+eIPercCRy1gsLDZWak9ndURAclchRsJ/Zo5r70mm1j/Szm5ZmII+dI36mI3M04wb\n\
+beQip+6SwJK6cRgLbXzEUIDo8l/kYeOe07Wc4bmc3qOex4xjhubMBGFYvQvHQI5w\n\
+oEP//k8WEE76+F4iHfe1/ntZ9m5OAwwGoRa6sofXhHevWijLZxgG9S2qROwKcfM5\n\
+8W8kq8Lo+XBOsSgLU1LAOjDPEd9Z0aRFyiR5FAmOQjXMofuc800/71lh+Jk6aeaB\n\
+QQv4+0it33ybm0/DR7r8+e/3oZarZFrDky6g\n\
+-----END CERTIFICATE-----";
+
+    const RESPONSE: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"
+    xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" xmlns:xs="http://www.w3.org/2001/XMLSchema"
+    xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
+    Destination="https://octobot.company.com/auth/saml/acs" ID="_r1" InResponseTo="_req1"
+    IssueInstant="2026-10-15T12:00:00Z" Version="2.0">
+  <saml:Issuer>https://idp.company.com</saml:Issuer>
+  <samlp:Status>
+    <samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/>
+  </samlp:Status>
+  <saml:Assertion ID="_a1" IssueInstant="2026-10-15T12:00:00Z" Version="2.0">
+    <saml:Issuer>https://idp.company.com</saml:Issuer>
+    <ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#">
+        <ds:SignedInfo>
+          <ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/>
+          <ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"/>
+          <ds:Reference URI="#_a1">
+            <ds:Transforms>
+              <ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/>
+              <ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/>
+            </ds:Transforms>
+            <ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/>
+            <ds:DigestValue>6/NniyN2Va4rj/X1N9qpQNanAQA/M6xksnHeJyE71nQ=</ds:DigestValue>
+          </ds:Reference>
+        </ds:SignedInfo>
+        <ds:SignatureValue>D2SqzLD8a8twOhnFHjVA2a3Ms47afyrDqody7g5PBHlhZoF4pzkJ56Lkv1wJcz938pUbRoQz3uq/
+6Z0E1hJGq1N5FA7Hrz0EgOAJcZ2SBZLFbhdOkDq0/kZqpQZ1ef6B/8/0jlNQkENMH1YvLyz+Wmrh
+yLPLo270OyBjuRrB+kms91dD9HCauYYV7pdTMVkgpF+sh1fXTv/eOI3cgGlxi26s7UkcOm7Mk3LA
+XJX/AaBHlM/IqCqOepk3By8/GLB9+hMDFltGtl2d7MFEKIFSithZcvdWDyiwxbTQN/LXB6vvYHLC
+T8J9Dd5AMxr8pjr2dZ9+QiJkyE/5uONOq0mtzA==</ds:SignatureValue>
+    </ds:Signature>
+    
+    <saml:Subject>
+      <saml:NameID
+        Format="urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress">alice@company.com<!---->.evil.com</saml:NameID>
+      <saml:SubjectConfirmation Method="urn:oasis:names:tc:SAML:2.0:cm:bearer">
+        <saml:SubjectConfirmationData InResponseTo="_req1" NotOnOrAfter="2026-10-15T12:05:00Z"
+            Recipient="https://octobot.company.com/auth/saml/acs"/>
+      </saml:SubjectConfirmation>
+    </saml:Subject>
+    <saml:Conditions NotBefore="2026-10-15T11:59:00Z" NotOnOrAfter="2026-10-15T12:05:00.123Z">
+      <saml:AudienceRestriction>
+        <saml:Audience>https://octobot.company.com/auth/saml/metadata</saml:Audience>
+      </saml:AudienceRestriction>
+    </saml:Conditions>
+    <saml:AttributeStatement>
+      <saml:Attribute Name="uid">
+        <saml:AttributeValue xsi:type="xs:string">alice</saml:AttributeValue>
+      </saml:Attribute>
+      <saml:Attribute Name="groups">
+        <saml:AttributeValue>engineering &amp; ops</saml:AttributeValue>
+        <saml:AttributeValue>octobot-admins</saml:AttributeValue>
+      </saml:Attribute>
+    </saml:AttributeStatement>
+  </saml:Assertion>
+</samlp:Response>"##;
+
+    // 2026-10-15T12:01:00Z
+    const NOW: i64 = 1792065660;
+
+    fn config() -> SamlConfig {
+        SamlConfig {
+            entity_id: "https://octobot.company.com/auth/saml/metadata".into(),
+            acs_url: "https://octobot.company.com/auth/saml/acs".into(),
+            idp_entity_id: "https://idp.company.com".into(),
+            idp_sso_url: "https://idp.company.com/sso?app=octobot".into(),
+            idp_cert_file: "/data/idp.pem".into(),
+            username_attribute: Some("uid".into()),
+            groups_attribute: Some("groups".into()),
+            group_roles: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_xml() {
+        let root = parse_xml(
+            "<?xml version=\"1.0\"?>\n<!-- hi -->\n<a:root xmlns:a=\"urn:a\" x='1 &amp;\n2'>\
+             one<!-- two --> &#x41;<![CDATA[<b>]]><a:child/><child xmlns=\"urn:b\"></child></a:root>",
+        )
+        .unwrap();
+        assert!(root.is("urn:a", "root"));
+        assert_eq!(Some("1 & 2"), root.attr("x"));
+        assert_eq!("one A<b>", root.text());
+        let children = root.elements().collect::<Vec<_>>();
+        assert!(children[0].is("urn:a", "child"));
+        assert_eq!(Some("urn:b"), children[1].namespace());
+        assert_eq!(Some(children[0]), root.child("urn:a", "child"));
+
+        assert!(parse_xml("<!DOCTYPE a [<!ENTITY x \"y\">]><a>&x;</a>").is_err());
+        assert!(parse_xml("<a>&x;</a>").is_err());
+        assert!(parse_xml("<b:a></b:a>").is_err());
+        assert!(parse_xml("<a><b></a></b>").is_err());
+        assert!(parse_xml("<a x=\"1\" x=\"2\"/>").is_err());
+        assert!(parse_xml("<a/><b/>").is_err());
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        // as `xmllint --exc-c14n` has it, but without the comment
+        let root = parse_xml(
+            r#"<a:root xmlns:a="urn:a" xmlns="urn:default" xmlns:unused="urn:unused" z="1" a:y='2 &amp; "3"'>
+  <!-- dropped -->
+  <child b="x&#9;y
+z" a="&lt;&gt;"/>
+  <x:other xmlns:x="urn:x"><plain xmlns="">text &amp; &lt;more&gt; <![CDATA[<raw>]]>&#xD;</plain></x:other>
+</a:root>"#,
+        )
+        .unwrap();
+        let canonical = r#"<a:root xmlns:a="urn:a" z="1" a:y="2 &amp; &quot;3&quot;">
+  
+  <child xmlns="urn:default" a="&lt;>" b="x&#x9;y z"></child>
+  <x:other xmlns:x="urn:x"><plain>text &amp; &lt;more&gt; &lt;raw&gt;&#xD;</plain></x:other>
+</a:root>"#;
+        assert_eq!(canonical, canonicalize(&root, &[], None));
+
+        let root = parse_xml("<a xmlns:x=\"urn:x\" xmlns:y=\"urn:y\"><b><x:c/></b></a>").unwrap();
+        assert_eq!("<a><b><x:c xmlns:x=\"urn:x\"></x:c></b></a>", canonicalize(&root, &[], None));
+        assert_eq!(
+            "<a xmlns:y=\"urn:y\"><b><x:c xmlns:x=\"urn:x\"></x:c></b></a>",
+            canonicalize(&root, &["y".into(), "z".into()], None)
+        );
+    }
+
+    #[test]
+    fn test_public_key() {
+        let key = concat!(
+            "MIIBCgKCAQEAjkZU43A1hgo0i6/0H7DV38wJuYyf2hmH/mpU7qKRWrPpmr+eo5esh3VXLhbaUAJMcF/l18SHKs1MAIxGCXWe",
+            "WcQmn7438ZeTVGZFwQ2gpAqDabUwirhm9hdFOtmp5fCFCvDaZxBgFWyIFlue0BDZAa9v0IHU81W5CZ/PdFV0fzroVUvss4pF",
+            "+BMn1H+NRpSnrkl0kHgjGKpKQ8pMKfVwp59EoWyNKLtOlwG9bWhCQEERZtRyieGNYNDXw51B/fEXpfH4VK2hjwwT9IC/oxec",
+            "7Oi6Ze6rAGJ3tw6Cx3sfA9EPu/6Tjf3GU7CEUDzaNLyUV2g9FY3n2cqtGzlGpI0GsQIDAQAB",
+        );
+        assert_eq!(base64::decode(key).unwrap(), public_key(IDP_CERT).unwrap());
+        assert!(public_key("-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----").is_err());
+        assert!(public_key("").is_err());
+    }
+
+    #[test]
+    fn test_verify_response() {
+        let key = public_key(IDP_CERT).unwrap();
+        let login = SamlLogin {
+            user: "alice".into(),
+            groups: vec!["engineering & ops".into(), "octobot-admins".into()],
+            request_id: "_req1".into(),
+        };
+        assert_eq!(login, verify_response(&config(), &key, RESPONSE, NOW).unwrap());
+
+        // the comment doesn't cut the name short
+        let mut name_id = config();
+        name_id.username_attribute = None;
+        assert_eq!("alice@company.com.evil.com", verify_response(&name_id, &key, RESPONSE, NOW).unwrap().user);
+
+        // changed after it was signed, not signed, or signed by someone else
+        let changed = RESPONSE.replace(">alice<", ">mallory<");
+        assert!(verify_response(&config(), &key, &changed, NOW).is_err());
+        let unsigned = RESPONSE.replace("ds:Signature>", "ds:Unsigned>").replace("<ds:Signature ", "<ds:Unsigned ");
+        assert!(verify_response(&config(), &key, &unsigned, NOW).is_err());
+        let mut other_key = key.clone();
+        other_key[20] ^= 1;
+        assert!(verify_response(&config(), &other_key, RESPONSE, NOW).is_err());
+
+        // another assertion next to the signed one, with the same ID
+        let start = RESPONSE.find("<saml:Assertion").unwrap();
+        let end = RESPONSE.find("</samlp:Response>").unwrap();
+        let wrapped = format!("{}{}{}", &RESPONSE[..end], &RESPONSE[start..end], &RESPONSE[end..]);
+        assert!(verify_response(&config(), &key, &wrapped, NOW).is_err());
+
+        // expired, not valid yet, or for someone else
+        assert!(verify_response(&config(), &key, RESPONSE, NOW + 10 * 60).is_err());
+        assert!(verify_response(&config(), &key, RESPONSE, NOW - 10 * 60).is_err());
+        let mut other = config();
+        other.entity_id = "https://other.company.com".into();
+        assert!(verify_response(&other, &key, RESPONSE, NOW).is_err());
+        let mut other = config();
+        other.acs_url = "https://other.company.com/auth/saml/acs".into();
+        assert!(verify_response(&other, &key, RESPONSE, NOW).is_err());
+        let mut other = config();
+        other.idp_entity_id = "https://other-idp.company.com".into();
+        assert!(verify_response(&other, &key, RESPONSE, NOW).is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(NOW, parse_time("2026-10-15T12:01:00Z").unwrap());
+        assert_eq!(NOW, parse_time("2026-10-15T12:01:00.999Z").unwrap());
+        assert!(parse_time("2026-10-15T12:01:00.999+02:00").is_err());
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_login_url() {
+        let url = Url::parse(&login_url(&config(), "_abc", NOW).unwrap()).unwrap();
+        assert_eq!("idp.company.com", url.host_str().unwrap());
+        assert_eq!(Some("octobot".into()), url.query_pairs().find(|p| p.0 == "app").map(|p| p.1.into_owned()));
+
+        let request = url.query_pairs().find(|p| p.0 == "SAMLRequest").unwrap().1.into_owned();
+        let mut xml = String::new();
+        DeflateDecoder::new(&base64::decode(&request).unwrap()[..]).read_to_string(&mut xml).unwrap();
+        assert_eq!(authn_request(&config(), "_abc", NOW), xml);
+
+        let request = parse_xml(&xml).unwrap();
+        assert!(request.is(SAMLP_NS, "AuthnRequest"));
+        assert_eq!(Some("_abc"), request.attr("ID"));
+        assert_eq!(Some("2026-10-15T12:01:00Z"), request.attr("IssueInstant"));
+        assert_eq!(Some("https://octobot.company.com/auth/saml/acs"), request.attr("AssertionConsumerServiceURL"));
+        let issuer = request.child(SAML_NS, "Issuer").unwrap();
+        assert_eq!("https://octobot.company.com/auth/saml/metadata", issuer.text());
+    }
+
+    #[test]
+    fn test_metadata() {
+        let metadata = parse_xml(&metadata(&config())).unwrap();
+        assert_eq!(Some("https://octobot.company.com/auth/saml/metadata"), metadata.attr("entityID"));
+        let acs = metadata
+            .child(METADATA_NS, "SPSSODescriptor")
+            .and_then(|d| d.child(METADATA_NS, "AssertionConsumerService"))
+            .unwrap();
+        assert_eq!(Some(POST_BINDING), acs.attr("Binding"));
+        assert_eq!(Some("https://octobot.company.com/auth/saml/acs"), acs.attr("Location"));
+    }
+
+    #[test]
+    fn test_requests() {
+        let requests = Requests::new();
+        let id = requests.start();
+        assert!(id.starts_with('_'));
+        assert_ne!(id, requests.start());
+
+        assert!(!requests.take("_other"));
+        assert!(requests.take(&id));
+        assert!(!requests.take(&id));
+    }
+}
@@ -0,0 +1,268 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use ring::digest;
+use rustc_serialize::hex::ToHex;
+use serde_derive::Serialize;
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::git::Git;
+use crate::git_clone_manager::GitCloneManager;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::messenger;
+use crate::repo_version;
+use crate::slack::{SlackAttachmentBuilder, SlackRequest};
+use crate::worker;
+
+pub const SOURCE_CI: &str = "ci";
+pub const SOURCE_GENERATED: &str = "generated";
+
+const SBOM_CONTENT_TYPE: &str = "application/json";
+
+// The SBOM of a release, as recorded in the ledger
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ReleaseSbom {
+    pub repo: String,
+    pub tag: String,
+    pub commit_hash: String,
+    pub asset_name: String,
+    pub asset_url: String,
+    // empty for SBOMs attached by CI: octobot never downloads them
+    pub sha256: String,
+    // SOURCE_CI or SOURCE_GENERATED
+    pub source: String,
+    pub recorded_at: i64,
+}
+
+// Keeps a record of the SBOM attached to each release
+#[derive(Clone)]
+pub struct SbomLedger {
+    db: Database,
+}
+
+impl SbomLedger {
+    pub fn new(db: Database) -> SbomLedger {
+        SbomLedger { db: db }
+    }
+
+    pub fn record(&self, sbom: &ReleaseSbom) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO release_sboms
+               (repo, tag, commit_hash, asset_name, asset_url, sha256, source, recorded_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+               ON CONFLICT (repo, tag) DO UPDATE SET commit_hash = excluded.commit_hash,
+                   asset_name = excluded.asset_name, asset_url = excluded.asset_url, sha256 = excluded.sha256,
+                   source = excluded.source, recorded_at = excluded.recorded_at"#,
+            &[
+                &sbom.repo as &dyn ToSql,
+                &sbom.tag,
+                &sbom.commit_hash,
+                &sbom.asset_name,
+                &sbom.asset_url,
+                &sbom.sha256,
+                &sbom.source,
+                &sbom.recorded_at,
+            ],
+        )
+        .map_err(|e| format_err!("Error recording SBOM of {} {}: {}", sbom.repo, sbom.tag, e))?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, repo: &str, tag: &str) -> Result<Option<ReleaseSbom>> {
+        let sboms = self.query("WHERE repo = :repo AND tag = :tag", &[(":repo", &repo), (":tag", &tag)])?;
+        Ok(sboms.into_iter().next())
+    }
+
+    pub fn get_all(&self, repo: &str) -> Result<Vec<ReleaseSbom>> {
+        self.query("WHERE repo = :repo ORDER BY recorded_at DESC", &[(":repo", &repo)])
+    }
+
+    fn query(&self, filter: &str, params: &[(&str, &dyn ToSql)]) -> Result<Vec<ReleaseSbom>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare(&format!("SELECT * FROM release_sboms {}", filter))?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(params)?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(ReleaseSbom {
+                repo: cols.get(row, "repo")?,
+                tag: cols.get(row, "tag")?,
+                commit_hash: cols.get(row, "commit_hash")?,
+                asset_name: cols.get(row, "asset_name")?,
+                asset_url: cols.get(row, "asset_url")?,
+                sha256: cols.get(row, "sha256")?,
+                source: cols.get(row, "source")?,
+                recorded_at: cols.get(row, "recorded_at")?,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SbomRequest {
+    pub repo: github::Repo,
+    pub release: github::Release,
+}
+
+pub fn req(repo: &github::Repo, release: &github::Release) -> SbomRequest {
+    SbomRequest {
+        repo: repo.clone(),
+        release: release.clone(),
+    }
+}
+
+// Names CI tools give SBOMs, e.g. "app.spdx.json", "bom.cdx.json" or "sbom.json"
+pub fn is_sbom_asset(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.contains("sbom") || name.ends_with(".spdx") || name.ends_with(".spdx.json") || name.ends_with(".cdx.json")
+}
+
+pub fn sbom_asset_name(repo: &github::Repo, tag: &str) -> String {
+    format!("{}-{}.sbom.json", repo.name, tag)
+}
+
+pub fn sha256(contents: &[u8]) -> String {
+    digest::digest(&digest::SHA256, contents).as_ref().to_hex()
+}
+
+// Records the SBOM CI attached to the release, or else uploads the one |generate| returns
+pub fn attach_sbom<F>(
+    session: &dyn Session,
+    ledger: &SbomLedger,
+    req: &SbomRequest,
+    commit_hash: &str,
+    generate: F,
+) -> Result<ReleaseSbom>
+where
+    F: FnOnce() -> Result<String>,
+{
+    let tag = &req.release.tag_name;
+    // the webhook's copy may predate CI's uploads
+    let release = session.get_release_by_tag(req.repo.owner.login(), &req.repo.name, tag)?;
+
+    let (asset, sha, source) = match release.assets.iter().find(|a| is_sbom_asset(&a.name)) {
+        Some(asset) => (asset.clone(), String::new(), SOURCE_CI),
+        None => {
+            let contents = generate()?;
+            let name = sbom_asset_name(&req.repo, tag);
+            let asset = session.upload_release_asset(&release, &name, SBOM_CONTENT_TYPE, contents.as_bytes())?;
+            (asset, sha256(contents.as_bytes()), SOURCE_GENERATED)
+        }
+    };
+
+    let sbom = ReleaseSbom {
+        repo: req.repo.full_name.clone(),
+        tag: tag.clone(),
+        commit_hash: commit_hash.into(),
+        asset_name: asset.name,
+        asset_url: asset.browser_download_url,
+        sha256: sha,
+        source: source.into(),
+        recorded_at: db::now(),
+    };
+    ledger.record(&sbom)?;
+
+    Ok(sbom)
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+) -> Arc<dyn worker::Runner<SbomRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+        clone_mgr: clone_mgr,
+        slack: slack,
+    })
+}
+
+impl Runner {
+    fn clone_and_attach(&self, req: &SbomRequest) -> Result<ReleaseSbom> {
+        let owner = req.repo.owner.login();
+        let session = self.github_app.new_session(owner, &req.repo.name)?;
+        let held_clone_dir = GitCloneManager::clone(&self.clone_mgr, owner, &req.repo.name)?;
+        let clone_dir = held_clone_dir.dir();
+        let git = Git::new(session.github_host(), session.github_token(), clone_dir);
+
+        let tag = &req.release.tag_name;
+        let commit_hash = git.run(&["rev-parse", &format!("{}^{{commit}}", tag)])?.trim().to_string();
+        let script = self.config.repos().sbom_script(&req.repo);
+
+        attach_sbom(&session, &self.config.sboms, req, &commit_hash, || {
+            let script = script.ok_or_else(|| format_err!("No SBOM attached by CI and no SBOM script configured"))?;
+            git.run(&["checkout", "--detach", &commit_hash])?;
+            repo_version::run_script(&script, clone_dir)
+        })
+    }
+}
+
+impl worker::Runner<SbomRequest> for Runner {
+    fn handle(&self, req: SbomRequest) {
+        match self.clone_and_attach(&req) {
+            Ok(sbom) => info!("Recorded {} SBOM {} for {} {}", sbom.source, sbom.asset_name, sbom.repo, sbom.tag),
+            Err(e) => {
+                error!("Error attaching SBOM to {} {}: {}", req.repo.full_name, req.release.tag_name, e);
+
+                let attach = SlackAttachmentBuilder::new(&format!("{}", e))
+                    .title(req.release.tag_name.clone())
+                    .title_link(req.release.html_url.clone())
+                    .color("danger")
+                    .build();
+                let msg = format!("Error attaching SBOM to release {}", req.release.tag_name);
+
+                let messenger = messenger::new(self.config.clone(), self.slack.clone());
+                messenger.send_to_channel(&msg, &vec![attach], &req.repo, "", &Vec::<github::Commit>::new());
+            }
+        };
+    }
+
+    fn repo(&self, req: &SbomRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &SbomRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_sbom_asset() {
+        assert!(is_sbom_asset("app-v1.0.sbom.json"));
+        assert!(is_sbom_asset("SBOM.txt"));
+        assert!(is_sbom_asset("app.spdx.json"));
+        assert!(is_sbom_asset("bom.cdx.json"));
+        assert!(!is_sbom_asset("app-v1.0.tar.gz"));
+        assert!(!is_sbom_asset("checksums.json"));
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            sha256(b"")
+        );
+    }
+}
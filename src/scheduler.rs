@@ -0,0 +1,713 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use failure::format_err;
+use futures::{future, Future, Stream};
+use log::{debug, error, info};
+use serde_derive::Serialize;
+use tokio;
+use tokio::timer::Interval;
+
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::runtime;
+
+const TICK_SECS: u64 = 30;
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+// how far ahead to look for a time a cron expression matches, which covers leap days
+const CRON_SEARCH_DAYS: i64 = 5 * 366;
+
+pub trait Task: Send + Sync {
+    fn run(&self, now: i64) -> Result<()>;
+}
+
+// Whether this replica is the one that runs the tasks that must only run once (e.g. reminders), when there are
+// several of them
+pub trait Leadership: Send + Sync {
+    fn is_leader(&self) -> bool;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schedule {
+    // Run every N seconds
+    Every(u64),
+    // Run once a day at the given time (UTC)
+    Daily { hour: u32, minute: u32 },
+    // Run at the times a cron expression matches (UTC)
+    Cron(Cron),
+}
+
+// A cron expression: "minute hour day-of-month month day-of-week", each field a `*`, a number or name, a range
+// (`1-5`), a step (`*/15`, `0-30/10`) or a comma-separated list of those. Also "@hourly", "@daily", "@weekly",
+// "@monthly" and "@yearly".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cron {
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // when both days and weekdays are restricted, either one matching is enough (as in cron)
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+fn parse_value(value: &str, min: u32, names: &[&str]) -> Option<u32> {
+    let lower = value.to_lowercase();
+    match names.iter().position(|n| *n == lower) {
+        Some(i) => Some(min + i as u32),
+        None => value.parse::<u32>().ok(),
+    }
+}
+
+// Parses a cron field into a bitmask of the values it matches, and whether it is `*`
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<(u64, bool)> {
+    let invalid = || format_err!("Invalid cron field '{}'", field);
+    let mut mask = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => (&part[..i], part[i + 1..].parse::<u32>().ok().filter(|s| *s > 0).ok_or_else(invalid)?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else {
+            match range.find('-') {
+                Some(i) => (
+                    parse_value(&range[..i], min, names).ok_or_else(invalid)?,
+                    parse_value(&range[i + 1..], min, names).ok_or_else(invalid)?,
+                ),
+                None => {
+                    let value = parse_value(range, min, names).ok_or_else(invalid)?;
+                    // `5/15` means from 5 on
+                    (value, if step > 1 { max } else { value })
+                }
+            }
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid());
+        }
+
+        let mut value = start;
+        while value <= end {
+            mask |= 1 << value;
+            value += step;
+        }
+    }
+
+    Ok((mask, field == "*"))
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Cron> {
+        let expr = expr.trim();
+        let expanded = match expr {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            _ => expr,
+        };
+
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(format_err!("Invalid cron expression (expected 5 fields): '{}'", expr));
+        }
+        let (minutes, _) = parse_field(fields[0], 0, 59, &[])?;
+        let (hours, _) = parse_field(fields[1], 0, 23, &[])?;
+        let (days, any_day) = parse_field(fields[2], 1, 31, &[])?;
+        let (months, _) = parse_field(fields[3], 1, 12, &MONTHS)?;
+        // 7 is sunday too
+        let (mut weekdays, any_weekday) = parse_field(fields[4], 0, 7, &WEEKDAYS)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        let cron = Cron {
+            expr: expr.to_string(),
+            minutes: minutes,
+            hours: hours,
+            days: days,
+            months: months,
+            weekdays: weekdays,
+            any_day: any_day,
+            any_weekday: any_weekday,
+        };
+        if cron.next_run(0).is_none() {
+            return Err(format_err!("Cron expression never matches: '{}'", expr));
+        }
+        Ok(cron)
+    }
+
+    pub fn expr(&self) -> &str {
+        &self.expr
+    }
+
+    fn matches_day(&self, tm: &time::Tm) -> bool {
+        let day = self.days & (1 << tm.tm_mday) != 0;
+        let weekday = self.weekdays & (1 << tm.tm_wday) != 0;
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+
+    // The first whole minute strictly after `after` that matches, skipping ahead by days and hours that don't
+    pub fn next_run(&self, after: i64) -> Option<i64> {
+        let mut at = after - after.rem_euclid(60) + 60;
+        let limit = after + CRON_SEARCH_DAYS * SECS_PER_DAY;
+
+        while at <= limit {
+            let tm = time::at_utc(time::Timespec::new(at, 0));
+            if self.months & (1 << (tm.tm_mon + 1)) == 0 || !self.matches_day(&tm) {
+                at = at - at.rem_euclid(SECS_PER_DAY) + SECS_PER_DAY;
+            } else if self.hours & (1 << tm.tm_hour) == 0 {
+                at = at - at.rem_euclid(60 * 60) + 60 * 60;
+            } else if self.minutes & (1 << tm.tm_min) == 0 {
+                at += 60;
+            } else {
+                return Some(at);
+            }
+        }
+        None
+    }
+}
+
+impl Schedule {
+    // Parses a daily "HH:MM" (UTC) time
+    pub fn parse_daily(value: &str) -> Result<Schedule> {
+        let parts = value.trim().split(':').collect::<Vec<_>>();
+        if parts.len() != 2 {
+            return Err(format_err!("Invalid time (expected HH:MM): '{}'", value));
+        }
+
+        let hour = parts[0].parse::<u32>().ok().filter(|h| *h < 24);
+        let minute = parts[1].parse::<u32>().ok().filter(|m| *m < 60);
+        match (hour, minute) {
+            (Some(hour), Some(minute)) => Ok(Schedule::Daily { hour: hour, minute: minute }),
+            _ => Err(format_err!("Invalid time (expected HH:MM): '{}'", value)),
+        }
+    }
+
+    pub fn parse_cron(expr: &str) -> Result<Schedule> {
+        Ok(Schedule::Cron(Cron::parse(expr)?))
+    }
+
+    // The next time (in seconds since the epoch) this schedule should run, strictly after `after`
+    pub fn next_run(&self, after: i64) -> i64 {
+        match *self {
+            Schedule::Every(secs) => after + secs as i64,
+            Schedule::Daily { hour, minute } => {
+                let start_of_day = after - after.rem_euclid(SECS_PER_DAY);
+                let at = start_of_day + (hour as i64) * 60 * 60 + (minute as i64) * 60;
+                if at > after {
+                    at
+                } else {
+                    at + SECS_PER_DAY
+                }
+            }
+            // parsing made sure that it matches some time
+            Schedule::Cron(ref cron) => cron.next_run(after).unwrap_or(after + CRON_SEARCH_DAYS * SECS_PER_DAY),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match *self {
+            Schedule::Every(secs) => format!("every {}s", secs),
+            Schedule::Daily { hour, minute } => format!("daily at {:02}:{:02} UTC", hour, minute),
+            Schedule::Cron(ref cron) => format!("cron {}", cron.expr()),
+        }
+    }
+}
+
+// A random delay of up to `max_secs`, so that jobs on the same schedule don't all start at once
+fn jitter(name: &str, at: i64, max_secs: u64) -> i64 {
+    if max_secs == 0 {
+        return 0;
+    }
+    let mut hasher = RandomState::new().build_hasher();
+    (name, at).hash(&mut hasher);
+    (hasher.finish() % (max_secs + 1)) as i64
+}
+
+// The last run of a scheduled job
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct JobRun {
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub error: Option<String>,
+}
+
+// Keeps the last run of each job across restarts, so that a job whose time came while octobot was down runs when it
+// is back
+pub struct JobRuns {
+    db: Database,
+}
+
+impl JobRuns {
+    pub fn new(db: Database) -> JobRuns {
+        JobRuns { db: db }
+    }
+
+    pub fn record(&self, name: &str, run: &JobRun) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO scheduled_job_runs (name, started_at, finished_at, error)
+               VALUES (?1, ?2, ?3, ?4)
+               ON CONFLICT (name) DO UPDATE SET started_at = excluded.started_at,
+                   finished_at = excluded.finished_at, error = excluded.error"#,
+            &[&name as &dyn ToSql, &run.started_at, &run.finished_at, &run.error.clone().unwrap_or_default()],
+        )
+        .map_err(|e| format_err!("Error recording run of job {}: {}", name, e))?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<JobRun>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare("SELECT * FROM scheduled_job_runs WHERE name = :name")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":name", &name)])?;
+
+        if let Ok(Some(row)) = rows.next() {
+            let error: String = cols.get(row, "error")?;
+            Ok(Some(JobRun {
+                started_at: cols.get(row, "started_at")?,
+                finished_at: cols.get(row, "finished_at")?,
+                error: Some(error).filter(|e| !e.is_empty()),
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct JobInfo {
+    pub name: String,
+    pub schedule: String,
+    pub jitter_secs: u64,
+    pub leader_only: bool,
+    pub next_run: i64,
+    pub running: bool,
+    pub last_run: Option<JobRun>,
+}
+
+struct ScheduledTask {
+    name: String,
+    schedule: Schedule,
+    task: Arc<dyn Task>,
+    next_run: i64,
+    running: Arc<AtomicBool>,
+    // otherwise every replica runs it, e.g. for maintaining its own clones
+    leader_only: bool,
+    jitter_secs: u64,
+    last_run: Arc<Mutex<Option<JobRun>>>,
+}
+
+impl ScheduledTask {
+    fn next_run_after(&self, after: i64) -> i64 {
+        let next_run = self.schedule.next_run(after);
+        next_run + jitter(&self.name, next_run, self.jitter_secs)
+    }
+}
+
+pub struct Scheduler {
+    tasks: Mutex<Vec<ScheduledTask>>,
+    runtime: Mutex<tokio::runtime::Runtime>,
+    // without one, this is the only replica
+    leadership: Mutex<Option<Arc<dyn Leadership>>>,
+    // without it, last runs are forgotten on restart
+    runs: Mutex<Option<Arc<JobRuns>>>,
+}
+
+fn now() -> i64 {
+    time::get_time().sec
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            tasks: Mutex::new(vec![]),
+            runtime: Mutex::new(runtime::new(4, "scheduler")),
+            leadership: Mutex::new(None),
+            runs: Mutex::new(None),
+        }
+    }
+
+    // Must be called before tasks are added, for them to pick up where they left off
+    pub fn set_runs(&self, runs: Arc<JobRuns>) {
+        *self.runs.lock().unwrap() = Some(runs);
+    }
+
+    pub fn set_leadership(&self, leadership: Arc<dyn Leadership>) {
+        *self.leadership.lock().unwrap() = Some(leadership);
+    }
+
+    // Only the leader runs the task
+    pub fn add(&self, name: &str, schedule: Schedule, task: Arc<dyn Task>) {
+        self.push(name, schedule, task, true);
+    }
+
+    pub fn add_on_every_replica(&self, name: &str, schedule: Schedule, task: Arc<dyn Task>) {
+        self.push(name, schedule, task, false);
+    }
+
+    fn push(&self, name: &str, schedule: Schedule, task: Arc<dyn Task>, leader_only: bool) {
+        info!("Scheduling task {}: {}", name, schedule.describe());
+        let last_run = match *self.runs.lock().unwrap() {
+            Some(ref runs) => runs.get(name).unwrap_or_else(|e| {
+                error!("Error looking up last run of task {}: {}", name, e);
+                None
+            }),
+            None => None,
+        };
+
+        let mut scheduled = ScheduledTask {
+            name: name.into(),
+            schedule: schedule,
+            task: task,
+            next_run: 0,
+            running: Arc::new(AtomicBool::new(false)),
+            leader_only: leader_only,
+            jitter_secs: 0,
+            last_run: Arc::new(Mutex::new(last_run.clone())),
+        };
+        // a run missed while octobot was down is made up for right away
+        scheduled.next_run = scheduled.next_run_after(last_run.map(|r| r.started_at).unwrap_or(now()));
+        self.tasks.lock().unwrap().push(scheduled);
+    }
+
+    // Changes when a task runs (or only its jitter, without a new schedule), e.g. as configured under
+    // `[[scheduler.jobs]]`. Returns whether there is such a task.
+    pub fn reschedule(&self, name: &str, schedule: Option<Schedule>, jitter_secs: u64, now: i64) -> bool {
+        let mut tasks = self.tasks.lock().unwrap();
+        let scheduled = match tasks.iter_mut().find(|t| t.name == name) {
+            Some(t) => t,
+            None => return false,
+        };
+
+        if let Some(schedule) = schedule {
+            scheduled.schedule = schedule;
+        }
+        scheduled.jitter_secs = jitter_secs;
+        let last_run = scheduled.last_run.lock().unwrap().as_ref().map(|r| r.started_at);
+        scheduled.next_run = scheduled.next_run_after(last_run.unwrap_or(now));
+        info!("Rescheduled task {}: {} (jitter {}s)", name, scheduled.schedule.describe(), jitter_secs);
+        true
+    }
+
+    // Stops running the task. Returns whether there was such a task.
+    pub fn remove(&self, name: &str) -> bool {
+        let mut tasks = self.tasks.lock().unwrap();
+        let count = tasks.len();
+        tasks.retain(|t| t.name != name);
+        tasks.len() < count
+    }
+
+    // Starts checking for due tasks. Must be called from within a tokio runtime.
+    pub fn start(scheduler: Arc<Scheduler>) {
+        let ticks = Interval::new_interval(Duration::from_secs(TICK_SECS))
+            .for_each(move |_| {
+                scheduler.run_due(now());
+                Ok(())
+            })
+            .map_err(|e| error!("Scheduler timer error: {}", e));
+
+        tokio::spawn(ticks);
+    }
+
+    // Runs all tasks that are due at `now`. Tasks still running from a previous run are skipped, and so are
+    // leader-only tasks if this replica isn't the leader.
+    pub fn run_due(&self, now: i64) -> Vec<String> {
+        let mut started = vec![];
+        let leader = self.leadership.lock().unwrap().as_ref().map(|l| l.is_leader()).unwrap_or(true);
+        let mut tasks = self.tasks.lock().unwrap();
+
+        for scheduled in tasks.iter_mut().filter(|t| t.next_run <= now) {
+            scheduled.next_run = scheduled.next_run_after(now);
+
+            if scheduled.leader_only && !leader {
+                debug!("Skipping task {}: not the leader", scheduled.name);
+                continue;
+            }
+
+            if self.spawn(scheduled, now) {
+                started.push(scheduled.name.clone());
+            }
+        }
+
+        started
+    }
+
+    // Runs the task now, whatever its schedule (and on this replica, even if it isn't the leader). Returns false if
+    // it is still running.
+    pub fn trigger(&self, name: &str, now: i64) -> Result<bool> {
+        let tasks = self.tasks.lock().unwrap();
+        match tasks.iter().find(|t| t.name == name) {
+            Some(scheduled) => {
+                info!("Triggering task {}", name);
+                Ok(self.spawn(scheduled, now))
+            }
+            None => Err(format_err!("No such task: {}", name)),
+        }
+    }
+
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|t| JobInfo {
+                name: t.name.clone(),
+                schedule: t.schedule.describe(),
+                jitter_secs: t.jitter_secs,
+                leader_only: t.leader_only,
+                next_run: t.next_run,
+                running: t.running.load(Ordering::SeqCst),
+                last_run: t.last_run.lock().unwrap().clone(),
+            })
+            .collect()
+    }
+
+    // Returns false if the task is still running from before
+    fn spawn(&self, scheduled: &ScheduledTask, now: i64) -> bool {
+        if scheduled.running.swap(true, Ordering::SeqCst) {
+            info!("Skipping task {}: previous run still in progress", scheduled.name);
+            return false;
+        }
+
+        let name = scheduled.name.clone();
+        let task = scheduled.task.clone();
+        let running = scheduled.running.clone();
+        let last_run = scheduled.last_run.clone();
+        let runs = self.runs.lock().unwrap().clone();
+
+        self.runtime.lock().unwrap().spawn(future::lazy(move || {
+            info!("Running scheduled task {}", name);
+            let started_at = time::get_time().sec;
+            let result = task.run(now);
+            if let Err(ref e) = result {
+                error!("Error running scheduled task {}: {}", name, e);
+            }
+
+            let run = JobRun {
+                started_at: started_at,
+                finished_at: time::get_time().sec,
+                error: result.err().map(|e| format!("{}", e)),
+            };
+            if let Some(runs) = runs {
+                if let Err(e) = runs.record(&name, &run) {
+                    error!("{}", e);
+                }
+            }
+            *last_run.lock().unwrap() = Some(run);
+            running.store(false, Ordering::SeqCst);
+            future::ok(())
+        }));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Sender};
+    use tempdir::TempDir;
+
+    use crate::util;
+
+    struct TestTask {
+        tx: Mutex<Sender<i64>>,
+    }
+
+    impl Task for TestTask {
+        fn run(&self, now: i64) -> Result<()> {
+            self.tx.lock().unwrap().send(now)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_parse_daily() {
+        assert_eq!(Schedule::Daily { hour: 9, minute: 30 }, Schedule::parse_daily("09:30").unwrap());
+        assert_eq!(Schedule::Daily { hour: 23, minute: 0 }, Schedule::parse_daily(" 23:00 ").unwrap());
+        assert!(Schedule::parse_daily("24:00").is_err());
+        assert!(Schedule::parse_daily("9").is_err());
+        assert!(Schedule::parse_daily("nine:thirty").is_err());
+    }
+
+    #[test]
+    fn test_next_run() {
+        // 2019-05-01T12:00:00Z
+        let noon = 1556712000;
+
+        assert_eq!(noon + 60, Schedule::Every(60).next_run(noon));
+
+        let daily = Schedule::Daily { hour: 14, minute: 30 };
+        assert_eq!(noon + 2 * 60 * 60 + 30 * 60, daily.next_run(noon));
+
+        let daily = Schedule::Daily { hour: 12, minute: 0 };
+        assert_eq!(noon + SECS_PER_DAY, daily.next_run(noon));
+
+        let daily = Schedule::Daily { hour: 9, minute: 0 };
+        assert_eq!(noon + 21 * 60 * 60, daily.next_run(noon));
+    }
+
+    #[test]
+    fn test_parse_cron() {
+        let cron = Cron::parse("*/15 9-17 * * mon-fri").unwrap();
+        assert_eq!(1 | 1 << 15 | 1 << 30 | 1 << 45, cron.minutes);
+        assert_eq!(0b111111111 << 9, cron.hours);
+        assert_eq!(0b111110, cron.weekdays);
+        assert!(cron.any_day);
+        assert!(!cron.any_weekday);
+
+        assert_eq!(Cron::parse("0 0 * * 0").unwrap().weekdays, Cron::parse("0 0 * * 7").unwrap().weekdays);
+        assert_eq!(Cron::parse("0 0 1 1 *").unwrap().months, Cron::parse("@yearly").unwrap().months);
+        assert_eq!(1 << 5 | 1 << 20 | 1 << 35 | 1 << 50, Cron::parse("5/15 * * * *").unwrap().minutes);
+        assert_eq!(1 << 1 | 1 << 6, Cron::parse("0 0 * jan,jun *").unwrap().months);
+
+        assert!(Cron::parse("* * * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert!(Cron::parse("0 5-3 * * *").is_err());
+        assert!(Cron::parse("0 0 * foo *").is_err());
+        assert!(Cron::parse("0 0 31 2 *").is_err());
+    }
+
+    #[test]
+    fn test_cron_next_run() {
+        // 2019-05-01T12:00:00Z, a wednesday
+        let noon = 1556712000;
+        let hour = 60 * 60;
+
+        assert_eq!(Some(noon + 60), Cron::parse("* * * * *").unwrap().next_run(noon));
+        assert_eq!(Some(noon + 60), Cron::parse("* * * * *").unwrap().next_run(noon + 30));
+        assert_eq!(Some(noon + 15 * 60), Cron::parse("*/15 * * * *").unwrap().next_run(noon));
+        assert_eq!(Some(noon + 2 * hour + 30 * 60), Cron::parse("30 14 * * *").unwrap().next_run(noon));
+        assert_eq!(Some(noon + SECS_PER_DAY), Cron::parse("0 12 * * *").unwrap().next_run(noon));
+        // the next monday
+        assert_eq!(Some(noon + 5 * SECS_PER_DAY - 3 * hour), Cron::parse("0 9 * * mon").unwrap().next_run(noon));
+        // the 1st of june
+        assert_eq!(Some(noon + 31 * SECS_PER_DAY - 12 * hour), Cron::parse("@monthly").unwrap().next_run(noon));
+        // either the 3rd or a monday, whichever comes first
+        assert_eq!(Some(noon + 2 * SECS_PER_DAY - 12 * hour), Cron::parse("0 0 3 * mon").unwrap().next_run(noon));
+        // 2020-02-29
+        assert_eq!(Some(1582934400), Cron::parse("0 0 29 2 *").unwrap().next_run(noon));
+    }
+
+    #[test]
+    fn test_reschedule_and_trigger() {
+        let scheduler = Scheduler::new();
+        let (tx, rx) = channel();
+
+        scheduler.add("test-task", Schedule::Every(60), Arc::new(TestTask { tx: Mutex::new(tx) }));
+        assert!(!scheduler.reschedule("other-task", None, 0, now()));
+        assert!(!scheduler.remove("other-task"));
+
+        let start = now();
+        assert!(scheduler.reschedule("test-task", Some(Schedule::Every(3600)), 300, start));
+        let jobs = scheduler.jobs();
+        assert_eq!(1, jobs.len());
+        assert_eq!("every 3600s", jobs[0].schedule);
+        assert_eq!(300, jobs[0].jitter_secs);
+        assert!(jobs[0].next_run >= start + 3600 && jobs[0].next_run <= start + 3900);
+        assert_eq!(Vec::<String>::new(), scheduler.run_due(start + 61));
+
+        // only the jitter
+        assert!(scheduler.reschedule("test-task", None, 0, start));
+        assert_eq!("every 3600s", scheduler.jobs()[0].schedule);
+        assert_eq!(start + 3600, scheduler.jobs()[0].next_run);
+
+        assert!(scheduler.trigger("test-task", start).unwrap());
+        assert_eq!(start, util::recv_timeout(&rx, Duration::from_secs(5)).unwrap());
+        assert!(scheduler.trigger("other-task", start).is_err());
+
+        assert!(scheduler.remove("test-task"));
+        assert!(scheduler.jobs().is_empty());
+    }
+
+    #[test]
+    fn test_job_runs() {
+        let temp_dir = TempDir::new("scheduler.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        let runs = Arc::new(JobRuns::new(db));
+
+        assert_eq!(None, runs.get("test-task").unwrap());
+        let run = JobRun {
+            started_at: 1000,
+            finished_at: 1010,
+            error: Some("oops".into()),
+        };
+        runs.record("test-task", &run).unwrap();
+        assert_eq!(Some(run), runs.get("test-task").unwrap());
+
+        // it ran long ago, so it runs again right away
+        let scheduler = Scheduler::new();
+        scheduler.set_runs(runs.clone());
+        let (tx, rx) = channel();
+        scheduler.add("test-task", Schedule::Every(60), Arc::new(TestTask { tx: Mutex::new(tx) }));
+        assert_eq!(1060, scheduler.jobs()[0].next_run);
+
+        let later = now();
+        assert_eq!(vec!["test-task"], scheduler.run_due(later));
+        assert_eq!(later, util::recv_timeout(&rx, Duration::from_secs(5)).unwrap());
+    }
+
+    #[test]
+    fn test_run_due() {
+        let scheduler = Scheduler::new();
+        let (tx, rx) = channel();
+
+        scheduler.add("test-task", Schedule::Every(60), Arc::new(TestTask { tx: Mutex::new(tx) }));
+
+        // not due yet
+        assert_eq!(Vec::<String>::new(), scheduler.run_due(now()));
+
+        let later = now() + 61;
+        assert_eq!(vec!["test-task"], scheduler.run_due(later));
+        assert_eq!(later, util::recv_timeout(&rx, Duration::from_secs(5)).unwrap());
+
+        // already ran
+        assert_eq!(Vec::<String>::new(), scheduler.run_due(later));
+    }
+
+    struct Follower;
+
+    impl Leadership for Follower {
+        fn is_leader(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_run_due_not_leader() {
+        let scheduler = Scheduler::new();
+        scheduler.set_leadership(Arc::new(Follower));
+        let (tx, rx) = channel();
+        let (replica_tx, replica_rx) = channel();
+
+        scheduler.add("leader-task", Schedule::Every(60), Arc::new(TestTask { tx: Mutex::new(tx) }));
+        scheduler.add_on_every_replica(
+            "replica-task",
+            Schedule::Every(60),
+            Arc::new(TestTask { tx: Mutex::new(replica_tx) }),
+        );
+
+        let later = now() + 61;
+        assert_eq!(vec!["replica-task"], scheduler.run_due(later));
+        assert_eq!(later, util::recv_timeout(&replica_rx, Duration::from_secs(5)).unwrap());
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::env;
+
+use failure::format_err;
+use reqwest;
+use serde_json;
+use toml;
+
+use crate::errors::*;
+use crate::http_client::HTTPClient;
+
+// Config values like these are looked up when the config is loaded, so that secrets needn't be in the file
+pub const ENV_PREFIX: &str = "env:";
+pub const VAULT_PREFIX: &str = "vault:";
+
+// A config value that was looked up, so that saving the config writes back the reference instead of the secret
+#[derive(Clone, Debug, PartialEq)]
+pub struct SecretRef {
+    // table keys and array indices leading to the value, e.g. ["jira", "password"]
+    pub path: Vec<String>,
+    // e.g. "env:JIRA_PASSWORD"
+    pub reference: String,
+    pub value: String,
+}
+
+pub fn is_reference(value: &str) -> bool {
+    value.starts_with(ENV_PREFIX) || value.starts_with(VAULT_PREFIX)
+}
+
+pub trait Vault {
+    // The secret's fields, i.e. the `data` of a KV v1 secret, or `data.data` of a KV v2 one
+    fn read(&self, path: &str) -> Result<serde_json::Map<String, serde_json::Value>>;
+}
+
+// Talks to the vault at VAULT_ADDR with VAULT_TOKEN, like the vault CLI does
+pub struct VaultSession {
+    client: HTTPClient,
+}
+
+impl VaultSession {
+    pub fn from_env() -> Result<VaultSession> {
+        let addr = env::var("VAULT_ADDR").map_err(|_| format_err!("VAULT_ADDR is not set"))?;
+        let token = env::var("VAULT_TOKEN").map_err(|_| format_err!("VAULT_TOKEN is not set"))?;
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("X-Vault-Token", token.parse()?);
+        if let Ok(namespace) = env::var("VAULT_NAMESPACE") {
+            headers.insert("X-Vault-Namespace", namespace.parse()?);
+        }
+
+        Ok(VaultSession {
+            client: HTTPClient::new_with_headers(&format!("{}/v1", addr.trim_end_matches('/')), headers)?,
+        })
+    }
+}
+
+impl Vault for VaultSession {
+    fn read(&self, path: &str) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let resp = self
+            .client
+            .get::<serde_json::Value>(path.trim_start_matches('/'))
+            .map_err(|e| format_err!("Error reading {} from vault: {}", path, e))?;
+
+        let data = match resp.get("data") {
+            Some(serde_json::Value::Object(data)) => data.clone(),
+            _ => return Err(format_err!("Error reading {} from vault: no data", path)),
+        };
+        // KV v2 nests the fields, next to the secret's metadata
+        match (data.get("data"), data.get("metadata")) {
+            (Some(serde_json::Value::Object(fields)), Some(_)) => Ok(fields.clone()),
+            _ => Ok(data),
+        }
+    }
+}
+
+// Looks up references, reading each vault secret only once
+pub struct Resolver {
+    vault: Option<Box<dyn Vault>>,
+    secrets: HashMap<String, serde_json::Map<String, serde_json::Value>>,
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            vault: None,
+            secrets: HashMap::new(),
+        }
+    }
+
+    pub fn with_vault(vault: Box<dyn Vault>) -> Resolver {
+        Resolver {
+            vault: Some(vault),
+            secrets: HashMap::new(),
+        }
+    }
+
+    pub fn lookup(&mut self, reference: &str) -> Result<String> {
+        if reference.starts_with(ENV_PREFIX) {
+            let name = &reference[ENV_PREFIX.len()..];
+            return env::var(name).map_err(|_| format_err!("Environment variable {} is not set", name));
+        }
+        if !reference.starts_with(VAULT_PREFIX) {
+            return Ok(reference.to_string());
+        }
+
+        let parts = reference[VAULT_PREFIX.len()..].splitn(2, '#').collect::<Vec<_>>();
+        if parts.len() != 2 || parts[0].is_empty() || parts[1].is_empty() {
+            return Err(format_err!("Invalid vault reference (expected vault:path#key): '{}'", reference));
+        }
+        let (path, key) = (parts[0], parts[1]);
+
+        if !self.secrets.contains_key(path) {
+            if self.vault.is_none() {
+                self.vault = Some(Box::new(VaultSession::from_env()?));
+            }
+            let secret = self.vault.as_ref().unwrap().read(path)?;
+            self.secrets.insert(path.to_string(), secret);
+        }
+
+        match self.secrets[path].get(key) {
+            Some(serde_json::Value::String(value)) => Ok(value.clone()),
+            Some(value) => Ok(value.to_string()),
+            None => Err(format_err!("Vault secret {} has no key {}", path, key)),
+        }
+    }
+
+    // Replaces each reference in the config with what it refers to
+    pub fn resolve(&mut self, config: &mut toml::Value) -> Result<Vec<SecretRef>> {
+        let mut refs = vec![];
+        self.resolve_at(config, &mut vec![], &mut refs)?;
+        Ok(refs)
+    }
+
+    fn resolve_at(&mut self, value: &mut toml::Value, path: &mut Vec<String>, refs: &mut Vec<SecretRef>) -> Result<()> {
+        if let Some(reference) = value.as_str().filter(|v| is_reference(v)).map(|v| v.to_string()) {
+            let secret = self.lookup(&reference).map_err(|e| format_err!("{}: {}", path.join("."), e))?;
+            *value = toml::Value::String(secret.clone());
+            refs.push(SecretRef {
+                path: path.clone(),
+                reference: reference,
+                value: secret,
+            });
+            return Ok(());
+        }
+
+        match value {
+            toml::Value::Table(table) => {
+                for (key, value) in table.iter_mut() {
+                    path.push(key.clone());
+                    self.resolve_at(value, path, refs)?;
+                    path.pop();
+                }
+            }
+            toml::Value::Array(values) => {
+                for (i, value) in values.iter_mut().enumerate() {
+                    path.push(i.to_string());
+                    self.resolve_at(value, path, refs)?;
+                    path.pop();
+                }
+            }
+            _ => (),
+        };
+        Ok(())
+    }
+}
+
+// Puts references back in place of the secrets they were resolved to. Values that were changed since are kept.
+pub fn restore(config: &mut toml::Value, refs: &Vec<SecretRef>) {
+    for secret in refs {
+        let mut value = Some(&mut *config);
+        for key in &secret.path {
+            value = match value {
+                Some(toml::Value::Table(table)) => table.get_mut(key),
+                Some(toml::Value::Array(values)) => match key.parse::<usize>() {
+                    Ok(i) => values.get_mut(i),
+                    Err(_) => None,
+                },
+                _ => None,
+            };
+        }
+        if let Some(value) = value {
+            if value.as_str() == Some(secret.value.as_str()) {
+                *value = toml::Value::String(secret.reference.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct FakeVault {
+        reads: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Vault for FakeVault {
+        fn read(&self, path: &str) -> Result<serde_json::Map<String, serde_json::Value>> {
+            self.reads.borrow_mut().push(path.to_string());
+            match path {
+                "secret/octobot" => match serde_json::json!({"jira": "jira-pass", "port": 389}) {
+                    serde_json::Value::Object(fields) => Ok(fields),
+                    _ => unreachable!(),
+                },
+                _ => Err(format_err!("404 Not Found")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        env::set_var("OCTOBOT_SECRETS_TEST_TOKEN", "the-token");
+        let reads = Rc::new(RefCell::new(vec![]));
+        let mut resolver = Resolver::with_vault(Box::new(FakeVault { reads: reads.clone() }));
+
+        let mut config = toml::from_str::<toml::Value>(
+            r#"
+[github]
+api_token = "env:OCTOBOT_SECRETS_TEST_TOKEN"
+host = "git.company.com"
+
+[jira]
+password = "vault:secret/octobot#jira"
+
+[[webhooks]]
+url = "https://the-consumer/hook"
+secret = "vault:secret/octobot#port"
+"#,
+        )
+        .unwrap();
+
+        let refs = resolver.resolve(&mut config).unwrap();
+        assert_eq!("the-token", config["github"]["api_token"].as_str().unwrap());
+        assert_eq!("git.company.com", config["github"]["host"].as_str().unwrap());
+        assert_eq!("jira-pass", config["jira"]["password"].as_str().unwrap());
+        assert_eq!("389", config["webhooks"][0]["secret"].as_str().unwrap());
+        assert_eq!(vec!["secret/octobot".to_string()], *reads.borrow());
+
+        assert_eq!(3, refs.len());
+        assert_eq!(vec!["webhooks", "0", "secret"], refs[2].path);
+        assert_eq!("vault:secret/octobot#port", refs[2].reference);
+
+        // changed values aren't replaced with their old reference
+        config["jira"]["password"] = toml::Value::String("new-pass".into());
+        restore(&mut config, &refs);
+        assert_eq!("env:OCTOBOT_SECRETS_TEST_TOKEN", config["github"]["api_token"].as_str().unwrap());
+        assert_eq!("new-pass", config["jira"]["password"].as_str().unwrap());
+        assert_eq!("vault:secret/octobot#port", config["webhooks"][0]["secret"].as_str().unwrap());
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let reads = Rc::new(RefCell::new(vec![]));
+        let mut resolver = Resolver::with_vault(Box::new(FakeVault { reads: reads }));
+
+        let mut config = toml::from_str::<toml::Value>("[ldap]\nbind_pass = \"env:OCTOBOT_SECRETS_TEST_UNSET\"")
+            .unwrap();
+        let err = resolver.resolve(&mut config).unwrap_err();
+        assert_eq!("ldap.bind_pass: Environment variable OCTOBOT_SECRETS_TEST_UNSET is not set", format!("{}", err));
+
+        assert!(resolver.lookup("vault:secret/octobot").is_err());
+        assert!(resolver.lookup("vault:#jira").is_err());
+        assert!(resolver.lookup("vault:secret/other#jira").is_err());
+        assert!(resolver.lookup("vault:secret/octobot#missing").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use log::{error, info};
+use serde_derive::Deserialize;
+use serde_json;
+
+use crate::access_review::{self, AccessReview};
+use crate::config::Config;
+use crate::db;
+use crate::server::http::{parse_json, FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::Sessions;
+use crate::util;
+
+pub enum AccessReviewOp {
+    Report,
+    RevokeSession,
+    RevokeAccount,
+    ReinstateAccount,
+}
+
+// Super-admin only: who has access to octobot, and revoking it
+pub struct AccessReviewHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    op: AccessReviewOp,
+}
+
+#[derive(Deserialize)]
+struct RevokeSessionReq {
+    handle: String,
+}
+
+#[derive(Deserialize)]
+struct AccountReq {
+    user: String,
+}
+
+impl AccessReviewHandler {
+    pub fn new(config: Arc<Config>, sessions: Arc<Sessions>, op: AccessReviewOp) -> Box<AccessReviewHandler> {
+        Box::new(AccessReviewHandler {
+            config: config,
+            sessions: sessions,
+            op: op,
+        })
+    }
+}
+
+impl Handler for AccessReviewHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let session = match login::get_admin_session(&self.sessions, &req) {
+            Some(s) => s,
+            None => return self.respond_with(StatusCode::FORBIDDEN, "Only the admin may do this"),
+        };
+
+        match &self.op {
+            &AccessReviewOp::Report => self.report(),
+            &AccessReviewOp::RevokeSession => self.revoke_session(req, session.user),
+            &AccessReviewOp::RevokeAccount => self.revoke_account(req, session.user),
+            &AccessReviewOp::ReinstateAccount => self.reinstate_account(req, session.user),
+        }
+    }
+}
+
+impl AccessReviewHandler {
+    fn report(&self) -> FutureResponse {
+        let review = match AccessReview::generate(&self.config, &self.sessions, db::now()) {
+            Ok(r) => r,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&review) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing access review: {}", e)),
+        }
+    }
+
+    fn revoke_session(&self, req: Request<Body>, admin: String) -> FutureResponse {
+        let config = self.config.clone();
+        let sessions = self.sessions.clone();
+
+        parse_json(req, move |revoke: RevokeSessionReq| {
+            let user = match sessions.revoke(&revoke.handle) {
+                Some(u) => u,
+                None => return util::new_msg_resp(StatusCode::NOT_FOUND, "No such session"),
+            };
+
+            if let Err(e) = config.audit.record(&admin, access_review::REVOKE_SESSION_ACTION, &user, &revoke.handle) {
+                error!("{}", e);
+            }
+            info!("{} revoked a session of {}", admin, user);
+            util::new_empty_resp(StatusCode::OK)
+        })
+    }
+
+    fn revoke_account(&self, req: Request<Body>, admin: String) -> FutureResponse {
+        let config = self.config.clone();
+        let sessions = self.sessions.clone();
+
+        parse_json(req, move |account: AccountReq| {
+            if account.user.is_empty() {
+                return util::new_bad_req_resp("No `user` specified");
+            }
+            // the configured admin can only be removed from the config file
+            if config.admin.as_ref().map(|a| a.name == account.user).unwrap_or(false) {
+                return util::new_bad_req_resp("The configured admin account cannot be revoked");
+            }
+
+            if let Err(e) = config.audit.record(&admin, access_review::REVOKE_ACCOUNT_ACTION, &account.user, "") {
+                error!("{}", e);
+                return util::new_empty_error_resp();
+            }
+            if let Err(e) = config.account_logins.revoke(&account.user, &admin) {
+                error!("{}", e);
+                return util::new_empty_error_resp();
+            }
+
+            let count = sessions.remove_user_sessions(&account.user);
+            info!("{} revoked access of {} ({} sessions ended)", admin, account.user, count);
+            util::new_empty_resp(StatusCode::OK)
+        })
+    }
+
+    fn reinstate_account(&self, req: Request<Body>, admin: String) -> FutureResponse {
+        let config = self.config.clone();
+
+        parse_json(req, move |account: AccountReq| {
+            if let Err(e) = config.audit.record(&admin, access_review::REINSTATE_ACCOUNT_ACTION, &account.user, "") {
+                error!("{}", e);
+                return util::new_empty_error_resp();
+            }
+            if let Err(e) = config.account_logins.reinstate(&account.user) {
+                error!("{}", e);
+                return util::new_empty_error_resp();
+            }
+
+            info!("{} reinstated access of {}", admin, account.user);
+            util::new_empty_resp(StatusCode::OK)
+        })
+    }
+}
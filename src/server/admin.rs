@@ -9,8 +9,12 @@ use serde_derive::{Deserialize, Serialize};
 use log::error;
 
 use crate::config::{Config, JiraConfig};
+use crate::ecosystem;
+use crate::errors::*;
+use crate::github::api::GithubSessionFactory;
 use crate::jira;
 use crate::repos::RepoInfo;
+use crate::templates;
 use crate::server::http::{FutureResponse, Handler, parse_json};
 use crate::users::UserInfo;
 use crate::util;
@@ -21,6 +25,8 @@ pub enum Op {
     Create,
     Update,
     Delete,
+    ListDeleted,
+    Restore,
 }
 
 pub struct UserAdmin {
@@ -30,6 +36,7 @@ pub struct UserAdmin {
 
 pub struct RepoAdmin {
     config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
     op: Op,
 }
 
@@ -44,9 +51,10 @@ impl UserAdmin {
 }
 
 impl RepoAdmin {
-    pub fn new(config: Arc<Config>, op: Op) -> Box<RepoAdmin> {
+    pub fn new(config: Arc<Config>, github_app: Arc<dyn GithubSessionFactory>, op: Op) -> Box<RepoAdmin> {
         Box::new(RepoAdmin {
             config: config,
+            github_app: github_app,
             op: op,
         })
     }
@@ -60,18 +68,38 @@ impl Handler for UserAdmin {
             &Op::Create => self.create(req),
             &Op::Update => self.update(req),
             &Op::Delete => self.delete(req),
+            &Op::ListDeleted => self.get_deleted(req),
+            &Op::Restore => self.restore(req),
         }
     }
 }
 
+#[derive(Serialize)]
+struct UsersResp {
+    users: Vec<UserInfo>,
+}
+
+#[derive(Serialize)]
+struct ReposResp {
+    repos: Vec<RepoInfo>,
+}
+
+fn id_param(req: &Request<Body>) -> Option<i32> {
+    let query = util::parse_query(req.uri().query());
+    query.get("id").and_then(|id| id.parse::<i32>().ok())
+}
+
 impl UserAdmin {
     fn get_all(&self, _: Request<Body>) -> FutureResponse {
-        #[derive(Serialize)]
-        struct UsersResp {
-            users: Vec<UserInfo>,
-        }
+        self.respond_users(self.config.users().get_all())
+    }
+
+    fn get_deleted(&self, _: Request<Body>) -> FutureResponse {
+        self.respond_users(self.config.users().get_deleted())
+    }
 
-        let users = match self.config.users().get_all() {
+    fn respond_users(&self, users: Result<Vec<UserInfo>>) -> FutureResponse {
+        let users = match users {
             Ok(u) => u,
             Err(e) => {
                 return self.respond_error(&format!("{}", e));
@@ -92,6 +120,9 @@ impl UserAdmin {
     fn create(&self, req: Request<Body>) -> FutureResponse {
         let config = self.config.clone();
         parse_json(req, move |user: UserInfo| {
+            if let Err(e) = user.validate() {
+                return util::new_bad_req_resp(format!("{}", e));
+            }
             if let Err(e) = config.users_write().insert_info(&user) {
                 error!("{}", e);
                 return util::new_empty_error_resp();
@@ -104,6 +135,9 @@ impl UserAdmin {
         let config = self.config.clone();
 
         parse_json(req, move |user: UserInfo| {
+            if let Err(e) = user.validate() {
+                return util::new_bad_req_resp(format!("{}", e));
+            }
             if let Err(e) = config.users_write().update(&user) {
                 error!("{}", e);
                 return util::new_empty_error_resp();
@@ -127,6 +161,18 @@ impl UserAdmin {
         }
         self.respond_with(StatusCode::OK, "")
     }
+
+    fn restore(&self, req: Request<Body>) -> FutureResponse {
+        let user_id = match id_param(&req) {
+            None => return self.respond(util::new_bad_req_resp("No `id` param specified")),
+            Some(id) => id,
+        };
+
+        if let Err(e) = self.config.users_write().restore(user_id) {
+            return self.respond(util::new_bad_req_resp(format!("{}", e)));
+        }
+        self.respond_with(StatusCode::OK, "")
+    }
 }
 
 impl Handler for RepoAdmin {
@@ -136,18 +182,23 @@ impl Handler for RepoAdmin {
             &Op::Create => self.create(req),
             &Op::Update => self.update(req),
             &Op::Delete => self.delete(req),
+            &Op::ListDeleted => self.get_deleted(req),
+            &Op::Restore => self.restore(req),
         }
     }
 }
 
 impl RepoAdmin {
     fn get_all(&self, _: Request<Body>) -> FutureResponse {
-        #[derive(Serialize)]
-        struct ReposResp {
-            repos: Vec<RepoInfo>,
-        }
+        self.respond_repos(self.config.repos().get_all())
+    }
 
-        let repos = match self.config.repos().get_all() {
+    fn get_deleted(&self, _: Request<Body>) -> FutureResponse {
+        self.respond_repos(self.config.repos().get_deleted())
+    }
+
+    fn respond_repos(&self, repos: Result<Vec<RepoInfo>>) -> FutureResponse {
+        let repos = match repos {
             Ok(u) => u,
             Err(e) => {
                 return self.respond_error(&format!("{}", e));
@@ -167,7 +218,22 @@ impl RepoAdmin {
 
     fn create(&self, req: Request<Body>) -> FutureResponse {
         let config = self.config.clone();
+        let github_app = self.github_app.clone();
         parse_json(req, move |repo: RepoInfo| {
+            // org-level entries don't have any files to look at
+            let repo = match repo.repo.find('/') {
+                Some(i) if repo.ecosystem.is_empty() => {
+                    match github_app.new_session(&repo.repo[..i], &repo.repo[i + 1..]) {
+                        Ok(github) => ecosystem::onboard(&github, repo),
+                        Err(e) => {
+                            error!("Not detecting ecosystem of {}: {}", repo.repo, e);
+                            repo
+                        }
+                    }
+                }
+                _ => repo,
+            };
+
             if let Err(e) = config.repos_write().insert_info(&repo) {
                 error!("{}", e);
                 return util::new_empty_error_resp();
@@ -203,6 +269,18 @@ impl RepoAdmin {
         }
         self.respond_with(StatusCode::OK, "")
     }
+
+    fn restore(&self, req: Request<Body>) -> FutureResponse {
+        let repo_id = match id_param(&req) {
+            None => return self.respond(util::new_bad_req_resp("No `id` param specified")),
+            Some(id) => id,
+        };
+
+        if let Err(e) = self.config.repos_write().restore(repo_id) {
+            return self.respond(util::new_bad_req_resp(format!("{}", e)));
+        }
+        self.respond_with(StatusCode::OK, "")
+    }
 }
 
 pub struct MergeVersions {
@@ -236,8 +314,8 @@ impl Handler for MergeVersions {
         let config = self.config.clone();
         parse_json(req, move |merge_req: MergeVersionsReq| {
             // make a copy of the jira config so we can modify the auth
-            let mut jira_config: JiraConfig = match config.jira {
-                Some(ref j) => j.clone(),
+            let mut jira_config: JiraConfig = match config.jira_for_project(&merge_req.project) {
+                Some(j) => j.clone(),
                 None => return util::new_bad_req_resp("No JIRA config"),
             };
 
@@ -248,6 +326,8 @@ impl Handler for MergeVersions {
                 if jira_config.username.is_empty() || jira_config.password.is_empty() {
                     return util::new_bad_req_resp("JIRA auth required for non dry-run");
                 }
+                // act as the admin rather than as octobot's oauth app
+                jira_config.oauth = None;
             }
 
             let jira_sess = match jira::api::JiraSession::new(&jira_config) {
@@ -298,3 +378,151 @@ impl Handler for MergeVersions {
         })
     }
 }
+
+pub struct ValidateTransitions {
+    config: Arc<Config>,
+}
+
+impl ValidateTransitions {
+    pub fn new(config: Arc<Config>) -> Box<ValidateTransitions> {
+        Box::new(ValidateTransitions { config: config })
+    }
+}
+
+#[derive(Deserialize, Clone)]
+struct ValidateTransitionsReq {
+    project: String,
+    // "owner/name", for repo overrides
+    repo: Option<String>,
+    // an issue to also check transition names and IDs against
+    issue: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+struct ValidateTransitionsResp {
+    valid: bool,
+    transitions: Vec<jira::workflow::TransitionCheck>,
+}
+
+impl Handler for ValidateTransitions {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let config = self.config.clone();
+        parse_json(req, move |validate_req: ValidateTransitionsReq| {
+            let jira_config = match config.jira {
+                Some(ref j) => j,
+                None => return util::new_bad_req_resp("No JIRA config"),
+            };
+
+            // transitions are configured in [jira], even for projects on other instances
+            let instance_config = match config.jira_for_project(&validate_req.project) {
+                Some(j) => j,
+                None => return util::new_bad_req_resp("No JIRA config"),
+            };
+            let jira_sess = match jira::api::JiraSession::new(instance_config) {
+                Ok(j) => j,
+                Err(e) => return util::new_bad_req_resp(format!("Error creating JIRA session: {}", e)),
+            };
+
+            let transitions = match jira::workflow::validate_transitions(
+                &validate_req.project,
+                validate_req.repo.as_ref().map(|r| r.as_str()).unwrap_or(""),
+                validate_req.issue.as_ref().map(|i| i.as_str()),
+                &jira_sess,
+                jira_config,
+            ) {
+                Ok(t) => t,
+                Err(e) => {
+                    error!("Error validating JIRA transitions: {}", e);
+                    return util::new_error_resp(format!("Error validating JIRA transitions: {}", e));
+                }
+            };
+
+            let resp = ValidateTransitionsResp {
+                valid: transitions.iter().all(|t| t.found.is_some()),
+                transitions: transitions,
+            };
+
+            match serde_json::to_string(&resp) {
+                Ok(j) => util::new_json_resp(j),
+                Err(e) => util::new_error_resp(format!("Error serializing transitions: {}", e)),
+            }
+        })
+    }
+}
+
+pub struct TestTemplate;
+
+impl TestTemplate {
+    pub fn new() -> Box<TestTemplate> {
+        Box::new(TestTemplate)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+struct TestTemplateReq {
+    template: String,
+    // the kind of template, whose sample variables it is rendered with (defaults to "pull_request")
+    sample: Option<String>,
+    // more variables, or other values for the sample's
+    vars: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Clone)]
+struct TestTemplateResp {
+    output: Option<String>,
+    error: Option<templates::TemplateError>,
+    vars: serde_json::Value,
+}
+
+// Renders a template against a sample event, to try it out before it is configured
+impl Handler for TestTemplate {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        parse_json(req, move |test_req: TestTemplateReq| {
+            let sample = test_req.sample.unwrap_or("pull_request".into());
+            let vars = match templates::sample_vars(&sample) {
+                Some(v) => templates::with_vars(v, test_req.vars.unwrap_or_default()),
+                None => {
+                    let samples = templates::SAMPLES.join(", ");
+                    return util::new_bad_req_resp(format!("Unknown sample '{}' (expected one of {})", sample, samples));
+                }
+            };
+
+            let (output, error) = match templates::render_checked(&test_req.template, &vars) {
+                Ok(o) => (Some(o), None),
+                Err(e) => (None, Some(e)),
+            };
+            let resp = TestTemplateResp {
+                output: output,
+                error: error,
+                vars: vars,
+            };
+            match serde_json::to_string(&resp) {
+                Ok(j) => util::new_json_resp(j),
+                Err(e) => util::new_error_resp(format!("Error serializing template test: {}", e)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_repos_resp_has_no_secret() {
+        let temp_dir = TempDir::new("admin.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let config = Config::new(Database::new(&db_file.to_string_lossy()).expect("create temp database"));
+        config
+            .repos_write()
+            .insert_info(&RepoInfo::new("some-org/some-repo", "reviews").with_webhook_secret("the-secret"))
+            .unwrap();
+
+        let resp = ReposResp { repos: config.repos().get_all().unwrap() };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert!(json.contains("some-org/some-repo"));
+        assert!(!json.contains("the-secret"));
+    }
+}
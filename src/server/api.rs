@@ -0,0 +1,515 @@
+use hyper::{Body, Method, Request};
+use serde_json::{self, json};
+
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// The admin API is described once here: requests are routed with these descriptions, and the OpenAPI document is
+// generated from them, so the two can't drift apart
+
+pub const PREFIX: &str = "/api/v1";
+pub const OPENAPI_PATH: &str = "/api/v1/openapi.json";
+
+// Before it was versioned, the API was served under /api. Those paths still work, for existing scripts
+const UNVERSIONED_PREFIX: &str = "/api";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiOp {
+    GetMe,
+    UpdateMyMapping,
+    UpdateMyPreferences,
+    ListUsers,
+    UpdateUser,
+    CreateUser,
+    DeleteUser,
+    ListDeletedUsers,
+    RestoreUser,
+    ListUserMappings,
+    ConfirmUserMapping,
+    RejectUserMapping,
+    ListRepos,
+    UpdateRepo,
+    CreateRepo,
+    DeleteRepo,
+    ListDeletedRepos,
+    RestoreRepo,
+    ListTeamChannels,
+    SetTeamChannel,
+    RemoveTeamChannel,
+    ListOnboardingPlans,
+    ProposeOnboarding,
+    UpdateOnboardingPlan,
+    ApplyOnboardingPlan,
+    GetFreezes,
+    ListRepoMutes,
+    MuteRepo,
+    UnmuteRepo,
+    ViewAs,
+    GetAuditLog,
+    GetAccessReview,
+    RevokeSession,
+    RevokeAccount,
+    ReinstateAccount,
+    ListSessions,
+    EndSession,
+    EndUserSessions,
+    EndOtherSessions,
+    BeginSecurityKeyRegistration,
+    FinishSecurityKeyRegistration,
+    ListSecurityKeys,
+    RemoveSecurityKey,
+    ListFaults,
+    InjectFault,
+    ClearFaults,
+    DiagnoseEvent,
+    ListEvents,
+    GetComponentVersions,
+    GetDependencyGraph,
+    ListSboms,
+    ListAttestations,
+    GetReleaseNotes,
+    GetComplianceReport,
+    GetArtifact,
+    GetCommit,
+    MergeVersions,
+    ValidateJiraTransitions,
+    TestTemplate,
+    ListWorktreePools,
+    GetLdapHealth,
+    GetCloneCache,
+    GetConfigReloadStatus,
+    ReloadConfig,
+    ValidateConfig,
+    ExportConfig,
+    ImportConfig,
+    ListWebhookRetries,
+    RetryWebhook,
+    RemoveWebhookRetry,
+    ListWebhookForwards,
+    ListDeliveries,
+    GetDeliveryStats,
+    GetTrace,
+    ListQueues,
+    ListScheduledJobs,
+    RunScheduledJob,
+    ListJobs,
+    GetJob,
+    CancelJob,
+    StreamJobEvents,
+}
+
+pub struct Param {
+    pub name: &'static str,
+    pub required: bool,
+}
+
+const fn required(name: &'static str) -> Param {
+    Param {
+        name: name,
+        required: true,
+    }
+}
+
+const fn optional(name: &'static str) -> Param {
+    Param {
+        name: name,
+        required: false,
+    }
+}
+
+pub struct Route {
+    pub method: Method,
+    // under PREFIX, e.g. "/users". A "{name}" segment matches any one segment, e.g. "/commits/{sha}"
+    pub path: &'static str,
+    pub op: ApiOp,
+    // groups routes in the document, e.g. "users"
+    pub tag: &'static str,
+    pub summary: &'static str,
+    pub query: &'static [Param],
+    // the content type of the request body, if it takes one
+    pub body: Option<&'static str>,
+}
+
+// Routes that self-service sessions may use too: the logged in user's own settings
+const SELF_SERVICE_TAG: &str = "me";
+
+impl Route {
+    pub fn self_service(&self) -> bool {
+        self.tag == SELF_SERVICE_TAG
+    }
+}
+
+const JSON: Option<&str> = Some("application/json");
+const TOML: Option<&str> = Some("application/toml");
+
+macro_rules! route {
+    ($method:ident $path:expr, $op:ident, $tag:expr, $summary:expr) => {
+        route!($method $path, $op, $tag, $summary, &[], None)
+    };
+    ($method:ident $path:expr, $op:ident, $tag:expr, $summary:expr, $query:expr) => {
+        route!($method $path, $op, $tag, $summary, $query, None)
+    };
+    ($method:ident $path:expr, $op:ident, $tag:expr, $summary:expr, $query:expr, $body:expr) => {
+        Route {
+            method: Method::$method,
+            path: $path,
+            op: ApiOp::$op,
+            tag: $tag,
+            summary: $summary,
+            query: $query,
+            body: $body,
+        }
+    };
+}
+
+pub const ROUTES: &[Route] = &[
+    route!(GET "/me", GetMe, SELF_SERVICE_TAG, "Show your user mapping and notification preferences"),
+    route!(PUT "/me/mapping", UpdateMyMapping, SELF_SERVICE_TAG, "Change your slack user or email address", &[], JSON),
+    route!(
+        PUT "/me/preferences",
+        UpdateMyPreferences,
+        SELF_SERVICE_TAG,
+        "Change your notification preferences",
+        &[],
+        JSON
+    ),
+    route!(GET "/users", ListUsers, "users", "List users"),
+    route!(PUT "/user", UpdateUser, "users", "Update a user", &[], JSON),
+    route!(POST "/users", CreateUser, "users", "Add a user", &[], JSON),
+    route!(DELETE "/user", DeleteUser, "users", "Delete a user", &[required("id")]),
+    route!(GET "/users/deleted", ListDeletedUsers, "users", "List deleted users"),
+    route!(POST "/user/restore", RestoreUser, "users", "Restore a deleted user", &[required("id")]),
+    route!(GET "/user-mappings", ListUserMappings, "users", "List proposed user mappings", &[optional("state")]),
+    route!(POST "/user-mappings/confirm", ConfirmUserMapping, "users", "Map the proposed users", &[required("id")]),
+    route!(POST "/user-mappings/reject", RejectUserMapping, "users", "Reject a proposed mapping", &[required("id")]),
+    route!(GET "/repos", ListRepos, "repos", "List repos"),
+    route!(PUT "/repo", UpdateRepo, "repos", "Update a repo", &[], JSON),
+    route!(POST "/repos", CreateRepo, "repos", "Add a repo", &[], JSON),
+    route!(DELETE "/repo", DeleteRepo, "repos", "Delete a repo", &[required("id")]),
+    route!(GET "/repos/deleted", ListDeletedRepos, "repos", "List deleted repos"),
+    route!(POST "/repo/restore", RestoreRepo, "repos", "Restore a deleted repo", &[required("id")]),
+    route!(GET "/teams", ListTeamChannels, "teams", "List teams whose review requests go to a channel"),
+    route!(PUT "/teams", SetTeamChannel, "teams", "Send a team's review requests to a channel", &[], JSON),
+    route!(DELETE "/teams", RemoveTeamChannel, "teams", "Send a team's review requests to its members", &[], JSON),
+    route!(GET "/onboarding", ListOnboardingPlans, "repos", "List org onboarding plans", &[optional("id")]),
+    route!(POST "/onboarding", ProposeOnboarding, "repos", "Propose channels for an org's repos and teams", &[], JSON),
+    route!(PUT "/onboarding", UpdateOnboardingPlan, "repos", "Edit an org onboarding plan", &[], JSON),
+    route!(
+        POST "/onboarding/apply",
+        ApplyOnboardingPlan,
+        "repos",
+        "Configure an onboarding plan's repos and teams and create their webhooks",
+        &[],
+        JSON
+    ),
+    route!(GET "/freeze", GetFreezes, "repos", "List code freezes and the PRs they hold back"),
+    route!(GET "/repo-mutes", ListRepoMutes, "repos", "List muted repos"),
+    route!(POST "/repo-mutes", MuteRepo, "repos", "Mute a repo's notifications", &[], JSON),
+    route!(DELETE "/repo-mutes", UnmuteRepo, "repos", "Unmute a repo's notifications", &[], JSON),
+    route!(GET "/view-as", ViewAs, "access", "Preview what a user is notified about", &[required("user")]),
+    route!(GET "/audit", GetAuditLog, "access", "List audited admin actions"),
+    route!(GET "/access-review", GetAccessReview, "access", "Review who has access"),
+    route!(POST "/access-review/revoke-session", RevokeSession, "access", "End a session", &[], JSON),
+    route!(POST "/access-review/revoke", RevokeAccount, "access", "Revoke a user's access", &[], JSON),
+    route!(POST "/access-review/reinstate", ReinstateAccount, "access", "Reinstate a user's access", &[], JSON),
+    route!(GET "/sessions", ListSessions, "access", "List live sessions", &[optional("user")]),
+    route!(DELETE "/session", EndSession, "access", "End a live session", &[required("handle")]),
+    route!(DELETE "/sessions", EndUserSessions, "access", "End all of a user's sessions", &[required("user")]),
+    route!(DELETE "/sessions/others", EndOtherSessions, "access", "End all sessions but the caller's"),
+    route!(POST "/webauthn/register/begin", BeginSecurityKeyRegistration, "access", "Start registering a security key"),
+    route!(
+        POST "/webauthn/register/finish",
+        FinishSecurityKeyRegistration,
+        "access",
+        "Finish registering a security key",
+        &[],
+        JSON
+    ),
+    route!(GET "/webauthn/credentials", ListSecurityKeys, "access", "List security keys", &[optional("user")]),
+    route!(DELETE "/webauthn/credentials", RemoveSecurityKey, "access", "Remove a security key", &[required("id")]),
+    route!(GET "/faults", ListFaults, "testing", "List injected faults"),
+    route!(POST "/faults", InjectFault, "testing", "Simulate an outage of a service", &[], JSON),
+    route!(DELETE "/faults", ClearFaults, "testing", "Clear injected faults"),
+    route!(
+        GET "/event-diagnosis",
+        DiagnoseEvent,
+        "events",
+        "Explain how a webhook was handled",
+        &[required("delivery")]
+    ),
+    route!(
+        GET "/events",
+        ListEvents,
+        "events",
+        "List handled webhooks",
+        &[
+            optional("repo"),
+            optional("event"),
+            optional("action"),
+            optional("sender"),
+            optional("number"),
+            optional("failed"),
+            optional("since"),
+            optional("until"),
+            optional("page"),
+            optional("per_page"),
+        ]
+    ),
+    route!(GET "/component-versions", GetComponentVersions, "releases", "List component versions", &[required("repo")]),
+    route!(GET "/dependencies", GetDependencyGraph, "releases", "Show repo dependencies", &[optional("upstream")]),
+    route!(GET "/sboms", ListSboms, "releases", "List release SBOMs", &[required("repo")]),
+    route!(
+        GET "/attestations",
+        ListAttestations,
+        "releases",
+        "List release attestations",
+        &[required("repo"), optional("tag")]
+    ),
+    route!(
+        GET "/release-notes",
+        GetReleaseNotes,
+        "releases",
+        "Generate release notes",
+        &[required("repo"), required("from"), required("to"), optional("format"), optional("save")]
+    ),
+    route!(
+        GET "/compliance-report",
+        GetComplianceReport,
+        "releases",
+        "Report on merges and their reviews",
+        &[optional("start"), optional("end"), optional("format")]
+    ),
+    route!(GET "/artifacts", GetArtifact, "releases", "Download a stored artifact", &[required("key")]),
+    route!(GET "/commits/{sha}", GetCommit, "releases", "Find the PR, tickets and release of a commit"),
+    route!(POST "/merge-versions", MergeVersions, "jira", "Merge pending JIRA versions", &[], JSON),
+    route!(POST "/jira/validate-transitions", ValidateJiraTransitions, "jira", "Check JIRA transitions", &[], JSON),
+    route!(POST "/templates/test", TestTemplate, "config", "Render a template against a sample event", &[], JSON),
+    route!(GET "/worktree-pools", ListWorktreePools, "operations", "List worktree pools"),
+    route!(GET "/ldap", GetLdapHealth, "operations", "Check the LDAP servers"),
+    route!(GET "/clone-cache", GetCloneCache, "operations", "Show the clone cache"),
+    route!(GET "/config/reload", GetConfigReloadStatus, "config", "Show when the config was last reloaded"),
+    route!(POST "/config/reload", ReloadConfig, "config", "Reload the config file"),
+    route!(POST "/config/validate", ValidateConfig, "config", "Validate a config, or the config file", &[], TOML),
+    route!(
+        GET "/config/export",
+        ExportConfig,
+        "config",
+        "Export users and repos",
+        &[optional("format"), optional("save")]
+    ),
+    route!(POST "/config/import", ImportConfig, "config", "Import users and repos", &[optional("dry_run")], JSON),
+    route!(GET "/webhook-retries", ListWebhookRetries, "events", "List failed webhooks", &[optional("state")]),
+    route!(POST "/webhook-retries/retry", RetryWebhook, "events", "Retry a failed webhook", &[required("delivery_id")]),
+    route!(
+        DELETE "/webhook-retries",
+        RemoveWebhookRetry,
+        "events",
+        "Give up on a failed webhook",
+        &[required("delivery_id")]
+    ),
+    route!(GET "/webhook-forwards", ListWebhookForwards, "events", "List failed forwards", &[optional("state")]),
+    route!(
+        GET "/deliveries",
+        ListDeliveries,
+        "events",
+        "List attempts to send notifications",
+        &[
+            optional("provider"),
+            optional("target"),
+            optional("user"),
+            optional("delivery"),
+            optional("repo"),
+            optional("number"),
+            optional("failed"),
+            optional("since"),
+            optional("until"),
+            optional("page"),
+            optional("per_page"),
+        ]
+    ),
+    route!(
+        GET "/deliveries/stats",
+        GetDeliveryStats,
+        "events",
+        "Summarize notification attempts by provider",
+        &[optional("since")]
+    ),
+    route!(GET "/trace/{id}", GetTrace, "events", "Show what was logged for a request or delivery, in order"),
+    route!(GET "/queues", ListQueues, "operations", "Show the work queues"),
+    route!(GET "/scheduled-jobs", ListScheduledJobs, "operations", "List scheduled jobs"),
+    route!(POST "/scheduled-jobs/run", RunScheduledJob, "operations", "Run a scheduled job now", &[required("name")]),
+    route!(GET "/jobs", ListJobs, "jobs", "List jobs"),
+    route!(GET "/job", GetJob, "jobs", "Show a job", &[required("id")]),
+    route!(POST "/job/cancel", CancelJob, "jobs", "Cancel a job", &[required("id")]),
+    route!(
+        GET "/jobs/events",
+        StreamJobEvents,
+        "jobs",
+        "Stream job updates (server-sent events)",
+        &[optional("session")]
+    ),
+];
+
+// The route of an API request, under /api/v1 or still under /api
+pub fn find(method: &Method, path: &str) -> Option<&'static Route> {
+    let path = match path.strip_prefix(PREFIX) {
+        Some(p) => p,
+        None => path.strip_prefix(UNVERSIONED_PREFIX)?,
+    };
+    ROUTES.iter().find(|r| &r.method == method && matches(r.path, path))
+}
+
+fn is_path_param(segment: &str) -> bool {
+    segment.starts_with('{') && segment.ends_with('}')
+}
+
+fn matches(route_path: &str, path: &str) -> bool {
+    let segments = path.split('/').collect::<Vec<_>>();
+    let route_segments = route_path.split('/').collect::<Vec<_>>();
+    segments.len() == route_segments.len()
+        && route_segments
+            .iter()
+            .zip(segments.iter())
+            .all(|(r, s)| r == s || (is_path_param(r) && !s.is_empty()))
+}
+
+// The value of a route's "{name}" segment in a request path
+pub fn path_param(route: &Route, path: &str, name: &str) -> Option<String> {
+    let path = path.strip_prefix(PREFIX).or_else(|| path.strip_prefix(UNVERSIONED_PREFIX))?;
+    route
+        .path
+        .split('/')
+        .zip(path.split('/'))
+        .find(|(r, _)| is_path_param(r) && &r[1..r.len() - 1] == name)
+        .map(|(_, s)| s.to_string())
+}
+
+// "ListUsers" is "listUsers", as code generators expect
+fn operation_id(op: ApiOp) -> String {
+    let name = format!("{:?}", op);
+    name[..1].to_lowercase() + &name[1..]
+}
+
+fn param(name: &str, location: &str, required: bool) -> serde_json::Value {
+    json!({"name": name, "in": location, "required": required, "schema": {"type": "string"}})
+}
+
+pub fn openapi() -> serde_json::Value {
+    let mut paths = serde_json::Map::new();
+    for route in ROUTES {
+        let parameters = route
+            .path
+            .split('/')
+            .filter(|s| is_path_param(s))
+            .map(|s| param(&s[1..s.len() - 1], "path", true))
+            .chain(route.query.iter().map(|p| param(p.name, "query", p.required)))
+            .collect::<Vec<_>>();
+        let mut operation = json!({
+            "operationId": operation_id(route.op),
+            "summary": route.summary,
+            "tags": [route.tag],
+            "parameters": parameters,
+            "responses": {
+                "200": {"description": "Success"},
+                "400": {"description": "Invalid request"},
+                "401": {"description": "Not logged in"},
+                "403": {"description": "Not allowed"},
+            },
+        });
+        if let Some(content_type) = route.body {
+            operation["requestBody"] = json!({"required": true, "content": {content_type: {}}});
+        }
+
+        let methods = paths.entry(route.path).or_insert_with(|| json!({}));
+        methods[route.method.as_str().to_lowercase()] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {"title": "octobot", "version": env!("CARGO_PKG_VERSION")},
+        "servers": [{"url": PREFIX}],
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "session": {"type": "apiKey", "in": "header", "name": "session"},
+            },
+        },
+        "security": [{"session": []}],
+    })
+}
+
+// The OpenAPI document, which anyone may fetch: it only describes the API
+pub struct OpenApiHandler;
+
+impl OpenApiHandler {
+    pub fn new() -> Box<OpenApiHandler> {
+        Box::new(OpenApiHandler)
+    }
+}
+
+impl Handler for OpenApiHandler {
+    fn handle(&self, _req: Request<Body>) -> FutureResponse {
+        self.respond(util::new_json_resp(openapi().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_find() {
+        assert_eq!(ApiOp::ListUsers, find(&Method::GET, "/api/v1/users").unwrap().op);
+        assert_eq!(ApiOp::ListUsers, find(&Method::GET, "/api/users").unwrap().op);
+        assert_eq!(ApiOp::CreateUser, find(&Method::POST, "/api/v1/users").unwrap().op);
+        assert_eq!(ApiOp::StreamJobEvents, find(&Method::GET, "/api/v1/jobs/events").unwrap().op);
+        assert!(find(&Method::PATCH, "/api/v1/users").is_none());
+        assert!(find(&Method::GET, "/api/v1/nope").is_none());
+        assert!(find(&Method::GET, "/users").is_none());
+
+        let route = find(&Method::GET, "/api/v1/commits/abcdef0").unwrap();
+        assert_eq!(ApiOp::GetCommit, route.op);
+        assert_eq!(Some("abcdef0".to_string()), path_param(route, "/api/v1/commits/abcdef0", "sha"));
+        assert_eq!(Some("abcdef0".to_string()), path_param(route, "/api/commits/abcdef0", "sha"));
+        assert_eq!(None, path_param(route, "/api/commits/abcdef0", "id"));
+        assert!(find(&Method::GET, "/api/v1/commits/").is_none());
+        assert!(find(&Method::GET, "/api/v1/commits/abcdef0/more").is_none());
+
+        assert!(find(&Method::GET, "/api/v1/me").unwrap().self_service());
+        assert!(find(&Method::PUT, "/api/me/preferences").unwrap().self_service());
+        assert!(!find(&Method::PUT, "/api/v1/user").unwrap().self_service());
+    }
+
+    #[test]
+    fn test_routes_are_unique() {
+        let mut seen = HashSet::new();
+        for route in ROUTES {
+            assert!(seen.insert((route.method.clone(), route.path)), "{} {}", route.method, route.path);
+        }
+        let ops = ROUTES.iter().map(|r| operation_id(r.op)).collect::<HashSet<_>>();
+        assert_eq!(ROUTES.len(), ops.len());
+    }
+
+    #[test]
+    fn test_openapi() {
+        let doc = openapi();
+        assert_eq!("/api/v1", doc["servers"][0]["url"]);
+
+        let users = &doc["paths"]["/users"];
+        assert_eq!("listUsers", users["get"]["operationId"]);
+        assert_eq!("createUser", users["post"]["operationId"]);
+        assert!(users["post"]["requestBody"]["content"]["application/json"].is_object());
+        assert!(users["get"]["requestBody"].is_null());
+
+        let delete = &doc["paths"]["/user"]["delete"];
+        assert_eq!(
+            json!([{"name": "id", "in": "query", "required": true, "schema": {"type": "string"}}]),
+            delete["parameters"]
+        );
+        assert_eq!(
+            json!([{"name": "sha", "in": "path", "required": true, "schema": {"type": "string"}}]),
+            doc["paths"]["/commits/{sha}"]["get"]["parameters"]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use hyper::{header, Body, Request, Response, StatusCode};
+
+use crate::config::Config;
+use crate::server::http::{FutureResponse, Handler};
+use crate::storage;
+use crate::util;
+
+// A saved release notes or config export, by `key` (as in the Content-Location of the response that saved it)
+pub struct ArtifactsHandler {
+    config: Arc<Config>,
+}
+
+impl ArtifactsHandler {
+    pub fn new(config: Arc<Config>) -> Box<ArtifactsHandler> {
+        Box::new(ArtifactsHandler { config: config })
+    }
+}
+
+pub fn content_type(key: &str) -> &'static str {
+    match key.rsplit('.').next() {
+        Some("json") => "application/json",
+        Some("md") => "text/markdown",
+        _ => "text/plain",
+    }
+}
+
+impl Handler for ArtifactsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        let key = match query.get("key").filter(|k| !k.is_empty()) {
+            Some(k) => k,
+            None => return self.respond(util::new_bad_req_resp("Expected a `key` param")),
+        };
+
+        match storage::load_artifact(&self.config, key) {
+            Ok(Some(data)) => {
+                let mut resp = Response::new(Body::from(data));
+                resp.headers_mut().insert(header::CONTENT_TYPE, content_type(key).parse().unwrap());
+                self.respond(resp)
+            }
+            Ok(None) => self.respond(util::new_msg_resp(StatusCode::NOT_FOUND, format!("No artifact {}", key))),
+            Err(e) => self.respond_error(&format!("Error loading artifact {}: {}", key, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!("text/markdown", content_type("release-notes/owner/repo/v1...v2.md"));
+        assert_eq!("application/json", content_type("config-exports/20200102T030405Z.json"));
+        assert_eq!("text/plain", content_type("config-exports/20200102T030405Z.toml"));
+    }
+}
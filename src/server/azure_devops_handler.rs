@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::{Body, HeaderMap, Request, StatusCode};
+use log::{error, info};
+use ring::constant_time;
+
+use crate::azure_devops::api::AzureDevOpsSession;
+use crate::azure_devops::hooks;
+use crate::config::Config;
+use crate::jira;
+use crate::messenger;
+use crate::server::http::{FutureResponse, Handler};
+use crate::slack::SlackRequest;
+use crate::util;
+use crate::worker::Worker;
+
+// Receives Azure DevOps service hooks (pull request and build events) and handles them like github's
+pub struct AzureDevOpsHandler {
+    config: Arc<Config>,
+    slack: Arc<dyn Worker<SlackRequest>>,
+    jira: Option<Arc<dyn jira::api::Session>>,
+}
+
+impl AzureDevOpsHandler {
+    pub fn new(
+        config: Arc<Config>,
+        slack: Arc<dyn Worker<SlackRequest>>,
+        jira: Option<Arc<dyn jira::api::Session>>,
+    ) -> Box<AzureDevOpsHandler> {
+        Box::new(AzureDevOpsHandler {
+            config: config,
+            slack: slack,
+            jira: jira,
+        })
+    }
+}
+
+// Service hooks can't sign their deliveries: they send basic auth instead, with the secret as its password
+fn is_authorized(secret: &str, headers: &HeaderMap) -> bool {
+    let encoded = match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+        Some(a) if a.starts_with("Basic ") => a[6..].trim().to_string(),
+        _ => return false,
+    };
+    let decoded = match base64::decode(&encoded) {
+        Ok(d) => String::from_utf8_lossy(&d).into_owned(),
+        Err(_) => return false,
+    };
+
+    match decoded.splitn(2, ':').nth(1) {
+        Some(password) => constant_time::verify_slices_are_equal(password.as_bytes(), secret.as_bytes()).is_ok(),
+        None => false,
+    }
+}
+
+impl Handler for AzureDevOpsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let azure_config = match self.config.azure_devops {
+            Some(ref c) => c.clone(),
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Azure DevOps is not configured"),
+        };
+        if !is_authorized(&azure_config.webhook_secret, req.headers()) {
+            return self.respond_with(StatusCode::FORBIDDEN, "Invalid credentials");
+        }
+
+        let config = self.config.clone();
+        let slack = self.slack.clone();
+        let jira = self.jira.clone();
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            let event = match hooks::parse(&body) {
+                Ok(e) => e,
+                Err(e) => return util::new_bad_req_resp(format!("Error parsing Azure DevOps event: {}", e)),
+            };
+            let session = match AzureDevOpsSession::new(&azure_config) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Error creating Azure DevOps session: {}", e);
+                    return util::new_msg_resp(StatusCode::INTERNAL_SERVER_ERROR, "Error creating Azure DevOps session");
+                }
+            };
+            info!("Received Azure DevOps event from {}", azure_config.organization_url);
+
+            let messenger = messenger::new(config.clone(), slack);
+            hooks::handle(&event, &config, &session, jira.as_ref().map(|j| j.as_ref()), &messenger);
+            util::new_empty_resp(StatusCode::OK)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn basic_auth(user_pass: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = format!("Basic {}", base64::encode(user_pass.as_bytes()));
+        headers.insert("authorization", HeaderValue::from_str(&value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        assert!(is_authorized("the-secret", &basic_auth("octobot:the-secret")));
+        assert!(is_authorized("the:secret", &basic_auth(":the:secret")));
+        assert!(!is_authorized("the-secret", &basic_auth("octobot:other-secret")));
+        assert!(!is_authorized("the-secret", &basic_auth("the-secret")));
+        assert!(!is_authorized("the-secret", &HeaderMap::new()));
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::{Body, HeaderMap, Request, StatusCode};
+use log::{error, info};
+use ring::{digest, hmac};
+use rustc_serialize::hex::FromHex;
+
+use crate::bitbucket_server::api::BitbucketServerSession;
+use crate::bitbucket_server::hooks;
+use crate::config::Config;
+use crate::jira;
+use crate::messenger;
+use crate::server::http::{FutureResponse, Handler};
+use crate::slack::SlackRequest;
+use crate::util;
+use crate::worker::Worker;
+
+// Receives Bitbucket Server webhooks (pull request and comment events) and handles them like github's
+pub struct BitbucketServerHandler {
+    config: Arc<Config>,
+    slack: Arc<dyn Worker<SlackRequest>>,
+    jira: Option<Arc<dyn jira::api::Session>>,
+}
+
+impl BitbucketServerHandler {
+    pub fn new(
+        config: Arc<Config>,
+        slack: Arc<dyn Worker<SlackRequest>>,
+        jira: Option<Arc<dyn jira::api::Session>>,
+    ) -> Box<BitbucketServerHandler> {
+        Box::new(BitbucketServerHandler {
+            config: config,
+            slack: slack,
+            jira: jira,
+        })
+    }
+}
+
+// Bitbucket Server signs deliveries like github does, but with sha256: `X-Hub-Signature: sha256=<hex hmac of the body>`
+fn is_signed_with(secret: &str, headers: &HeaderMap, data: &[u8]) -> bool {
+    let signature = match headers.get("x-hub-signature").and_then(|v| v.to_str().ok()) {
+        Some(s) if s.starts_with("sha256=") => s[7..].to_string(),
+        _ => return false,
+    };
+    let sig_bytes: Vec<u8> = match signature.from_hex() {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    let key = hmac::VerificationKey::new(&digest::SHA256, secret.as_bytes());
+    hmac::verify(&key, data, &sig_bytes).is_ok()
+}
+
+impl Handler for BitbucketServerHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let bitbucket_config = match self.config.bitbucket_server {
+            Some(ref c) => c.clone(),
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Bitbucket Server is not configured"),
+        };
+
+        let headers = req.headers().clone();
+        let config = self.config.clone();
+        let slack = self.slack.clone();
+        let jira = self.jira.clone();
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            if !is_signed_with(&bitbucket_config.webhook_secret, &headers, &body) {
+                return util::new_msg_resp(StatusCode::FORBIDDEN, "Invalid signature");
+            }
+
+            let event = match hooks::parse(&body) {
+                Ok(e) => e,
+                Err(e) => return util::new_bad_req_resp(format!("Error parsing Bitbucket Server event: {}", e)),
+            };
+            let session = match BitbucketServerSession::new(&bitbucket_config) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Error creating Bitbucket Server session: {}", e);
+                    return util::new_msg_resp(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Error creating Bitbucket Server session",
+                    );
+                }
+            };
+            info!("Received Bitbucket Server event from {}", bitbucket_config.url);
+
+            let messenger = messenger::new(config.clone(), slack);
+            hooks::handle(&event, &config, &session, jira.as_ref().map(|j| j.as_ref()), &messenger);
+            util::new_empty_resp(StatusCode::OK)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+    use rustc_serialize::hex::ToHex;
+
+    fn sign(secret: &str, data: &[u8]) -> HeaderMap {
+        let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+        let mut headers = HeaderMap::new();
+        let value = format!("sha256={}", hmac::sign(&key, data).as_ref().to_hex());
+        headers.insert("x-hub-signature", HeaderValue::from_str(&value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_is_signed_with() {
+        let data = b"{\"eventKey\": \"pr:opened\"}";
+        assert!(is_signed_with("the-secret", &sign("the-secret", data), data));
+        assert!(!is_signed_with("other-secret", &sign("the-secret", data), data));
+        assert!(!is_signed_with("the-secret", &sign("the-secret", b"something else"), data));
+        assert!(!is_signed_with("the-secret", &HeaderMap::new(), data));
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request};
+use serde_json;
+
+use crate::git_clone_manager::GitCloneManager;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// How much disk the cached clones use, per repo
+pub struct CloneCacheHandler {
+    clone_mgr: Arc<GitCloneManager>,
+}
+
+impl CloneCacheHandler {
+    pub fn new(clone_mgr: Arc<GitCloneManager>) -> Box<CloneCacheHandler> {
+        Box::new(CloneCacheHandler { clone_mgr: clone_mgr })
+    }
+}
+
+impl Handler for CloneCacheHandler {
+    fn handle(&self, _req: Request<Body>) -> FutureResponse {
+        match serde_json::to_string(&self.clone_mgr.cache().stats()) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing clone cache stats: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use serde_json;
+
+use crate::commit_index;
+use crate::config::Config;
+use crate::server::api::{self, Route};
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// Where a commit of a main or release branch came from: its PR, tickets and the first release with it
+pub struct CommitsHandler {
+    config: Arc<Config>,
+    route: &'static Route,
+}
+
+impl CommitsHandler {
+    pub fn new(config: Arc<Config>, route: &'static Route) -> Box<CommitsHandler> {
+        Box::new(CommitsHandler {
+            config: config,
+            route: route,
+        })
+    }
+}
+
+impl Handler for CommitsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let sha = match api::path_param(self.route, req.uri().path(), "sha") {
+            Some(s) if commit_index::is_sha(&s) => s,
+            _ => return self.respond(util::new_bad_req_resp("Expected a commit sha of at least 7 characters")),
+        };
+
+        let commits = match self.config.commit_index.find(&sha) {
+            Ok(c) => c,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+        if commits.is_empty() {
+            return self.respond(util::new_msg_resp(StatusCode::NOT_FOUND, format!("No indexed commit {}", sha)));
+        }
+
+        match serde_json::to_string(&commits) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing commits: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use hyper::{header, Body, Request, Response};
+use serde_json;
+
+use crate::compliance::{self, ComplianceReport};
+use crate::config::Config;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+// Change management report of merges to protected branches between `start` and `end` (inclusive, YYYY-MM-DD).
+// `format` is json (the default), csv or pdf.
+pub struct ComplianceReportHandler {
+    config: Arc<Config>,
+}
+
+impl ComplianceReportHandler {
+    pub fn new(config: Arc<Config>) -> Box<ComplianceReportHandler> {
+        Box::new(ComplianceReportHandler { config: config })
+    }
+}
+
+fn new_file_resp(content_type: &str, file_name: &str, contents: Vec<u8>) -> Response<Body> {
+    let mut resp = Response::new(Body::from(contents));
+    resp.headers_mut().insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+    resp.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{}\"", file_name).parse().unwrap(),
+    );
+    resp
+}
+
+impl Handler for ComplianceReportHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        let start = query.get("start").and_then(|d| compliance::parse_date(d));
+        let end = query.get("end").and_then(|d| compliance::parse_date(d));
+        let (start, end) = match (start, end) {
+            (Some(s), Some(e)) if s <= e => (s, e + SECS_PER_DAY),
+            _ => return self.respond(util::new_bad_req_resp("Expected `start` and `end` dates (YYYY-MM-DD)")),
+        };
+
+        let report = match ComplianceReport::generate(&self.config, start, end) {
+            Ok(r) => r,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match query.get("format").map(|f| f.as_str()).unwrap_or("json") {
+            "csv" => self.respond(new_file_resp("text/csv", &report.file_name("csv"), report.to_csv().into_bytes())),
+            "pdf" => self.respond(new_file_resp("application/pdf", &report.file_name("pdf"), report.to_pdf())),
+            "json" => match serde_json::to_string(&report) {
+                Ok(j) => self.respond(util::new_json_resp(j)),
+                Err(e) => self.respond_error(&format!("Error serializing report: {}", e)),
+            },
+            other => self.respond(util::new_bad_req_resp(format!("Unknown format (expected json, csv or pdf): {}", other))),
+        }
+    }
+}
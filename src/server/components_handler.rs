@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request};
+use serde_json;
+
+use crate::config::Config;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// The version matrix of a monorepo: the latest version of each component on each branch.
+pub struct ComponentVersionsHandler {
+    config: Arc<Config>,
+}
+
+impl ComponentVersionsHandler {
+    pub fn new(config: Arc<Config>) -> Box<ComponentVersionsHandler> {
+        Box::new(ComponentVersionsHandler { config: config })
+    }
+}
+
+impl Handler for ComponentVersionsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        let repo = match query.get("repo") {
+            Some(r) if !r.is_empty() => r.clone(),
+            _ => return self.respond(util::new_bad_req_resp("No `repo` param specified")),
+        };
+
+        let versions = match self.config.component_versions.get_all(&repo) {
+            Ok(v) => v,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&versions) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing component versions: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::{header, Body, Request, StatusCode};
+use log::error;
+use serde_json;
+
+use crate::config::Config;
+use crate::config_export;
+use crate::server::http::{FutureResponse, Handler};
+use crate::storage;
+use crate::util;
+
+pub enum ConfigExportOp {
+    Export,
+    // with `dry_run=true`, only shows what the import would change
+    Import,
+}
+
+// Users, repos and team channels as one document, for keeping them in version control
+pub struct ConfigExportHandler {
+    config: Arc<Config>,
+    op: ConfigExportOp,
+}
+
+impl ConfigExportHandler {
+    pub fn new(config: Arc<Config>, op: ConfigExportOp) -> Box<ConfigExportHandler> {
+        Box::new(ConfigExportHandler { config: config, op: op })
+    }
+}
+
+impl Handler for ConfigExportHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        match self.op {
+            ConfigExportOp::Export => self.export(req),
+            ConfigExportOp::Import => self.import(req),
+        }
+    }
+}
+
+impl ConfigExportHandler {
+    fn export(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        let doc = match config_export::export(&self.config) {
+            Ok(d) => d,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        let (body, ext) = match query.get("format").map(|f| f.as_str()) {
+            Some("toml") => match config_export::to_toml(&doc) {
+                Ok(t) => (t, "toml"),
+                Err(e) => return self.respond_error(&format!("{}", e)),
+            },
+            None | Some("json") => match serde_json::to_string_pretty(&doc) {
+                Ok(j) => (j, "json"),
+                Err(e) => return self.respond_error(&format!("Error serializing config: {}", e)),
+            },
+            Some(other) => return self.respond(util::new_bad_req_resp(format!("Unknown format: {}", other))),
+        };
+
+        // with `save=true`, the export is also kept in the configured storage
+        let mut location = None;
+        if query.get("save").map(|s| s == "true").unwrap_or(false) {
+            let stamp = time::strftime("%Y%m%dT%H%M%SZ", &time::now_utc()).unwrap();
+            let key = format!("config-exports/{}.{}", stamp, ext);
+            if let Err(e) = storage::save_artifact(&self.config, &key, body.as_bytes()) {
+                return self.respond_error(&format!("Error saving config export: {}", e));
+            }
+            location = Some(storage::artifact_location(&key));
+        }
+
+        let mut resp = match ext {
+            "json" => util::new_json_resp(body),
+            _ => util::new_msg_resp(StatusCode::OK, body),
+        };
+        if let Some(location) = location {
+            resp.headers_mut().insert(header::CONTENT_LOCATION, location.parse().unwrap());
+        }
+        self.respond(resp)
+    }
+
+    fn import(&self, req: Request<Body>) -> FutureResponse {
+        let config = self.config.clone();
+        let query = util::parse_query(req.uri().query());
+        let dry_run = query.get("dry_run").map(|d| d == "true").unwrap_or(false);
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            let doc = match config_export::parse(&String::from_utf8_lossy(&body)) {
+                Ok(d) => d,
+                Err(e) => return util::new_bad_req_resp(format!("{}", e)),
+            };
+            let errors = config_export::validate(&doc);
+            if !errors.is_empty() {
+                return util::new_bad_req_resp(errors.join("\n"));
+            }
+
+            let diff = match config_export::import(&config, &doc, dry_run) {
+                Ok(d) => d,
+                Err(e) => {
+                    error!("Error importing config: {}", e);
+                    return util::new_error_resp(format!("{}", e));
+                }
+            };
+            match serde_json::to_string(&diff) {
+                Ok(j) => util::new_json_resp(j),
+                Err(e) => {
+                    error!("Error serializing config diff: {}", e);
+                    util::new_empty_error_resp()
+                }
+            }
+        }))
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::{Body, Request, Response, StatusCode};
+use log::error;
+use serde_json;
+
+use crate::config_check;
+use crate::config_reload::LiveConfig;
+use crate::db;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+pub enum ConfigReloadOp {
+    Status,
+    Reload,
+    // checks a proposed config (the request body), or the config file if there is none, without applying it
+    Validate,
+}
+
+// Reloads the config file on demand, reporting why it wasn't applied if it has errors
+pub struct ConfigReloadHandler {
+    live_config: Arc<LiveConfig>,
+    op: ConfigReloadOp,
+}
+
+impl ConfigReloadHandler {
+    pub fn new(live_config: Arc<LiveConfig>, op: ConfigReloadOp) -> Box<ConfigReloadHandler> {
+        Box::new(ConfigReloadHandler {
+            live_config: live_config,
+            op: op,
+        })
+    }
+}
+
+fn report_resp<T: serde::Serialize>(report: &T, failed: bool) -> Response<Body> {
+    match serde_json::to_string(report) {
+        Ok(j) => {
+            let mut resp = util::new_json_resp(j);
+            if failed {
+                *resp.status_mut() = StatusCode::BAD_REQUEST;
+            }
+            resp
+        }
+        Err(e) => {
+            error!("Error serializing config report: {}", e);
+            util::new_empty_resp(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+impl Handler for ConfigReloadHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let (status, failed) = match self.op {
+            ConfigReloadOp::Status => (self.live_config.status(), false),
+            ConfigReloadOp::Reload => {
+                let status = self.live_config.reload(db::now());
+                let failed = !status.errors.is_empty();
+                (status, failed)
+            }
+            ConfigReloadOp::Validate => {
+                let live_config = self.live_config.clone();
+                return Box::new(req.into_body().concat2().map(move |body| {
+                    let contents = String::from_utf8_lossy(&body);
+                    let current = live_config.get();
+                    let proposed = if contents.trim().is_empty() {
+                        current.reload(live_config.config_file())
+                    } else {
+                        current.parse(&contents)
+                    };
+                    match proposed {
+                        Ok(config) => {
+                            let report = config_check::check(&config);
+                            report_resp(&report, !report.ok)
+                        }
+                        Err(e) => util::new_bad_req_resp(format!("{}", e)),
+                    }
+                }));
+            }
+        };
+
+        self.respond(report_resp(&status, failed))
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::config::Config;
+use crate::db;
+use crate::deliveries::{DeliveryFilter, DeliveryStats};
+use crate::email;
+use crate::server::diagnostics_handler::{page_params, param, parse_param};
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::Sessions;
+use crate::users;
+use crate::util;
+
+// Stats cover the last day unless a `since` is given
+const DEFAULT_STATS_SECS: i64 = 24 * 60 * 60;
+
+pub enum DeliveriesOp {
+    List,
+    Stats,
+}
+
+// Each attempt to send a notification, e.g. to tell whether a user was actually messaged about a PR
+pub struct DeliveriesHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    op: DeliveriesOp,
+}
+
+#[derive(Serialize)]
+struct StatsResp {
+    since: i64,
+    providers: Vec<DeliveryStats>,
+}
+
+impl DeliveriesHandler {
+    pub fn new(config: Arc<Config>, sessions: Arc<Sessions>, op: DeliveriesOp) -> Box<DeliveriesHandler> {
+        Box::new(DeliveriesHandler {
+            config: config,
+            sessions: sessions,
+            op: op,
+        })
+    }
+}
+
+// Where a github user's direct messages go: their slack user, or else their email address
+fn user_target(config: &Config, login: &str) -> Option<String> {
+    let info = config.users().lookup_info(login);
+    match info {
+        Some(ref u) if !u.slack.is_empty() => Some(users::mention(&u.slack)),
+        _ => config.email.as_ref().and_then(|e| email::address(e, login, info.as_ref())),
+    }
+}
+
+// e.g. "?user=joe&repo=some-org/some-repo&number=456" or "?provider=slack&failed=true"
+fn parse_filter(config: &Config, query: &HashMap<String, String>) -> Result<DeliveryFilter, String> {
+    let target = match (param(query, "target"), param(query, "user")) {
+        (Some(target), _) => Some(target),
+        (None, Some(user)) => match user_target(config, &user) {
+            Some(t) => Some(t),
+            None => return Err(format!("No slack user or email address is mapped to {}", user)),
+        },
+        (None, None) => None,
+    };
+
+    Ok(DeliveryFilter {
+        provider: param(query, "provider"),
+        target: target,
+        delivery_id: param(query, "delivery"),
+        repo: param(query, "repo"),
+        number: parse_param(query, "number")?,
+        failed: parse_param(query, "failed")?,
+        since: parse_param(query, "since")?,
+        until: parse_param(query, "until")?,
+    })
+}
+
+impl Handler for DeliveriesHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        if login::get_admin_session(&self.sessions, &req).is_none() {
+            return self.respond_with(StatusCode::FORBIDDEN, "Only admins may do this");
+        }
+
+        let query = util::parse_query(req.uri().query());
+        match &self.op {
+            &DeliveriesOp::List => self.list(&query),
+            &DeliveriesOp::Stats => match parse_param::<i64>(&query, "since") {
+                Ok(since) => self.stats(since.unwrap_or(db::now() - DEFAULT_STATS_SECS)),
+                Err(e) => self.respond(util::new_bad_req_resp(e)),
+            },
+        }
+    }
+}
+
+impl DeliveriesHandler {
+    fn list(&self, query: &HashMap<String, String>) -> FutureResponse {
+        let filter = match parse_filter(&self.config, query) {
+            Ok(f) => f,
+            Err(e) => return self.respond(util::new_bad_req_resp(e)),
+        };
+        let (page, per_page) = page_params(query);
+
+        let deliveries = match self.config.deliveries.search(&filter, page, per_page) {
+            Ok(d) => d,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&deliveries) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing deliveries: {}", e)),
+        }
+    }
+
+    fn stats(&self, since: i64) -> FutureResponse {
+        let providers = match self.config.deliveries.stats(since) {
+            Ok(s) => s,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&StatsResp {
+            since: since,
+            providers: providers,
+        }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing delivery stats: {}", e)),
+        }
+    }
+}
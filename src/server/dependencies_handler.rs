@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request};
+use serde_json;
+
+use crate::config::Config;
+use crate::dependencies;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// The dependency graph between configured repos. Filter to one repo's dependents with `?upstream=`
+pub struct DependencyGraphHandler {
+    config: Arc<Config>,
+}
+
+impl DependencyGraphHandler {
+    pub fn new(config: Arc<Config>) -> Box<DependencyGraphHandler> {
+        Box::new(DependencyGraphHandler { config: config })
+    }
+}
+
+impl Handler for DependencyGraphHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+
+        let repos = match self.config.repos().get_all() {
+            Ok(r) => r,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        let mut graph = dependencies::dependency_graph(&repos);
+        if let Some(upstream) = query.get("upstream") {
+            graph.retain(|d| &d.upstream == upstream);
+        }
+
+        match serde_json::to_string(&graph) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing dependency graph: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use serde_json;
+
+use crate::config::Config;
+use crate::diagnostics::EventFilter;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 500;
+
+// Explains what happened to a webhook delivery, e.g. why nobody was notified about it.
+pub struct EventDiagnosisHandler {
+    config: Arc<Config>,
+}
+
+impl EventDiagnosisHandler {
+    pub fn new(config: Arc<Config>) -> Box<EventDiagnosisHandler> {
+        Box::new(EventDiagnosisHandler { config: config })
+    }
+}
+
+impl Handler for EventDiagnosisHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        let delivery_id = match query.get("delivery") {
+            Some(d) if !d.is_empty() => d.clone(),
+            _ => return self.respond(util::new_bad_req_resp("No `delivery` param specified")),
+        };
+
+        let diagnosis = match self.config.event_diagnostics.get(&delivery_id) {
+            Ok(Some(d)) => d,
+            Ok(None) => {
+                return self.respond_with(
+                    StatusCode::NOT_FOUND,
+                    &format!("No record of delivery {}: it may be too old or never have been received", delivery_id),
+                )
+            }
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&diagnosis) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing event diagnosis: {}", e)),
+        }
+    }
+}
+
+// Lists the webhook deliveries octobot received, newest first, one page at a time
+pub struct EventHistoryHandler {
+    config: Arc<Config>,
+}
+
+impl EventHistoryHandler {
+    pub fn new(config: Arc<Config>) -> Box<EventHistoryHandler> {
+        Box::new(EventHistoryHandler { config: config })
+    }
+}
+
+pub fn param(query: &HashMap<String, String>, name: &str) -> Option<String> {
+    query.get(name).filter(|v| !v.is_empty()).cloned()
+}
+
+pub fn parse_param<T: FromStr>(query: &HashMap<String, String>, name: &str) -> Result<Option<T>, String> {
+    match param(query, name) {
+        Some(v) => v.parse::<T>().map(Some).map_err(|_| format!("Invalid `{}`: '{}'", name, v)),
+        None => Ok(None),
+    }
+}
+
+// (page, per_page) from e.g. "?page=2&per_page=100"
+pub fn page_params(query: &HashMap<String, String>) -> (u32, u32) {
+    let page = query.get("page").and_then(|p| p.parse::<u32>().ok()).unwrap_or(1);
+    let per_page = query
+        .get("per_page")
+        .and_then(|p| p.parse::<u32>().ok())
+        .filter(|p| *p > 0)
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .min(MAX_PER_PAGE);
+    (page, per_page)
+}
+
+// e.g. "?repo=some-org/some-repo&event=pull_request&failed=true&page=2"
+fn parse_filter(query: &HashMap<String, String>) -> Result<EventFilter, String> {
+    Ok(EventFilter {
+        repo: param(query, "repo"),
+        event: param(query, "event"),
+        action: param(query, "action"),
+        sender: param(query, "sender"),
+        number: parse_param(query, "number")?,
+        failed: parse_param(query, "failed")?,
+        since: parse_param(query, "since")?,
+        until: parse_param(query, "until")?,
+    })
+}
+
+impl Handler for EventHistoryHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        let filter = match parse_filter(&query) {
+            Ok(f) => f,
+            Err(e) => return self.respond(util::new_bad_req_resp(e)),
+        };
+        let (page, per_page) = page_params(&query);
+
+        let events = match self.config.event_diagnostics.search(&filter, page, per_page) {
+            Ok(e) => e,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&events) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing events: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(params: &[(&str, &str)]) -> HashMap<String, String> {
+        params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let filter = parse_filter(&query(&[
+            ("repo", "some-org/some-repo"),
+            ("event", "pull_request"),
+            ("sender", ""),
+            ("number", "32"),
+            ("failed", "true"),
+            ("since", "1500000000"),
+        ]))
+        .unwrap();
+        assert_eq!(Some("some-org/some-repo".to_string()), filter.repo);
+        assert_eq!(Some("pull_request".to_string()), filter.event);
+        assert_eq!(None, filter.sender);
+        assert_eq!(Some(32), filter.number);
+        assert_eq!(Some(true), filter.failed);
+        assert_eq!(Some(1500000000), filter.since);
+        assert_eq!(None, filter.until);
+
+        assert_eq!(Err("Invalid `number`: 'abc'".to_string()), parse_filter(&query(&[("number", "abc")])).map(|_| ()));
+        assert_eq!(Err("Invalid `failed`: 'yes'".to_string()), parse_filter(&query(&[("failed", "yes")])).map(|_| ()));
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use log::{error, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+use crate::config::Config;
+use crate::faults::{self, Fault, Service};
+use crate::server::http::{parse_json, FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::Sessions;
+use crate::util;
+
+pub const INJECT_FAULT_ACTION: &str = "inject-fault";
+pub const CLEAR_FAULTS_ACTION: &str = "clear-faults";
+
+pub enum FaultsOp {
+    List,
+    Inject,
+    Clear,
+}
+
+// Admin-only, and only when `[testing] fault_injection` is enabled: simulates outages of
+// the services octobot depends on.
+pub struct FaultsHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    op: FaultsOp,
+}
+
+#[derive(Deserialize)]
+struct InjectReq {
+    service: Service,
+    // how many requests to fail (defaults to failing all of them until cleared)
+    count: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct FaultsResp {
+    faults: Vec<Fault>,
+}
+
+impl FaultsHandler {
+    pub fn new(config: Arc<Config>, sessions: Arc<Sessions>, op: FaultsOp) -> Box<FaultsHandler> {
+        Box::new(FaultsHandler {
+            config: config,
+            sessions: sessions,
+            op: op,
+        })
+    }
+}
+
+impl Handler for FaultsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        if !faults::is_enabled() {
+            return self.respond_with(StatusCode::NOT_FOUND, "Fault injection is not enabled");
+        }
+
+        let session = match login::get_admin_session(&self.sessions, &req) {
+            Some(s) => s,
+            None => return self.respond_with(StatusCode::FORBIDDEN, "Only the admin may do this"),
+        };
+
+        match &self.op {
+            &FaultsOp::List => self.list(),
+            &FaultsOp::Inject => self.inject(req, session.user),
+            &FaultsOp::Clear => self.clear(&session.user),
+        }
+    }
+}
+
+impl FaultsHandler {
+    fn list(&self) -> FutureResponse {
+        match serde_json::to_string(&FaultsResp { faults: faults::active() }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing faults: {}", e)),
+        }
+    }
+
+    fn inject(&self, req: Request<Body>, admin: String) -> FutureResponse {
+        let config = self.config.clone();
+
+        parse_json(req, move |inject: InjectReq| {
+            let count = inject.count.unwrap_or(faults::UNTIL_CLEARED);
+            let details = format!("{} failure(s)", count);
+            if let Err(e) = config.audit.record(&admin, INJECT_FAULT_ACTION, &format!("{:?}", inject.service), &details) {
+                error!("{}", e);
+                return util::new_empty_error_resp();
+            }
+
+            warn!("{} injected {} {:?} fault(s)", admin, count, inject.service);
+            if let Err(e) = faults::inject(inject.service, count) {
+                return util::new_bad_req_resp(format!("{}", e));
+            }
+            util::new_empty_resp(StatusCode::OK)
+        })
+    }
+
+    fn clear(&self, admin: &str) -> FutureResponse {
+        if let Err(e) = self.config.audit.record(admin, CLEAR_FAULTS_ACTION, "", "") {
+            return self.respond_error(&format!("{}", e));
+        }
+
+        faults::clear_all();
+        self.respond_with(StatusCode::OK, "")
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use hyper::{Body, HeaderMap, Request, StatusCode};
+use ring::constant_time;
+
+use crate::config::Config;
+use crate::federation::FederatedUser;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// Other octobot instances look up users mapped here. Only users mapped here are returned (never ones this instance
+// got from its own peers), so that instances which are each other's peers don't ask each other in circles.
+pub struct FederationUsersHandler {
+    config: Arc<Config>,
+}
+
+impl FederationUsersHandler {
+    pub fn new(config: Arc<Config>) -> Box<FederationUsersHandler> {
+        Box::new(FederationUsersHandler { config: config })
+    }
+}
+
+fn is_authorized(token: &str, headers: &HeaderMap) -> bool {
+    match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+        Some(a) if a.starts_with("Bearer ") => {
+            constant_time::verify_slices_are_equal(a[7..].trim().as_bytes(), token.as_bytes()).is_ok()
+        }
+        _ => false,
+    }
+}
+
+impl Handler for FederationUsersHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let token = match self.config.federation.as_ref().and_then(|f| f.token.clone()).filter(|t| !t.is_empty()) {
+            Some(t) => t,
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Federation is not configured"),
+        };
+        if !is_authorized(&token, req.headers()) {
+            return self.respond_with(StatusCode::FORBIDDEN, "Invalid credentials");
+        }
+
+        let query = util::parse_query(req.uri().query());
+        let github = match query.get("github").filter(|g| !g.is_empty()) {
+            Some(g) => g.clone(),
+            None => return self.respond(util::new_bad_req_resp("Expected `github` param")),
+        };
+
+        // null for users who aren't mapped here
+        let user = self.config.users().lookup_local_info(&github).map(|u| FederatedUser::from_info(&u));
+        self.respond(util::new_json_resp(serde_json::to_string(&user).unwrap_or_default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_is_authorized() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer the-token"));
+        assert!(is_authorized("the-token", &headers));
+        assert!(!is_authorized("other-token", &headers));
+        assert!(!is_authorized("the-token", &HeaderMap::new()));
+
+        headers.insert("authorization", HeaderValue::from_static("Basic the-token"));
+        assert!(!is_authorized("the-token", &headers));
+    }
+}
@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::config::Config;
+use crate::db;
+use crate::freeze::FreezeStatus;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// Orgs that are currently frozen, and the PRs held back (or excepted) by each freeze.
+pub struct FreezeStatusHandler {
+    config: Arc<Config>,
+}
+
+#[derive(Serialize)]
+struct FreezeStatusResp {
+    freezes: Vec<FreezeStatus>,
+}
+
+impl FreezeStatusHandler {
+    pub fn new(config: Arc<Config>) -> Box<FreezeStatusHandler> {
+        Box::new(FreezeStatusHandler { config: config })
+    }
+}
+
+impl Handler for FreezeStatusHandler {
+    fn handle(&self, _req: Request<Body>) -> FutureResponse {
+        let freezes = match self.config.code_freezes.status(db::now()) {
+            Ok(f) => f,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&FreezeStatusResp { freezes: freezes }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing code freezes: {}", e)),
+        }
+    }
+}
@@ -1,45 +1,138 @@
+use std::collections::HashMap;
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
 
 use futures::Future;
 use futures::Stream;
-use hyper::{Body, Request, StatusCode};
+use hyper::{Body, HeaderMap, Request, StatusCode};
 use log::{info, error};
 use regex::Regex;
-use serde_json;
+use serde_json::{self, json};
 use tokio;
 
+use crate::alerts;
+use crate::auto_merge::AutoMerge;
+use crate::blame::{self, BlameRequest};
+use crate::ci_logs;
+use crate::codeowners::{self, CodeOwnersRequest};
+use crate::commit_index::{self, CommitRecord};
+use crate::commit_lint;
+use crate::compliance;
 use crate::config::Config;
+use crate::config_reload::{self, LiveConfig};
+use crate::db;
+use crate::dependencies;
+use crate::diagnostics;
+use crate::diff_preview;
+use crate::digests;
+use crate::discord;
+use crate::ecosystem;
+use crate::error_reports;
+use crate::email::{self, EmailRequest};
+use crate::event_bus;
+use crate::events::{Event, FixtureRecorder};
+use crate::faults;
 use crate::force_push::{self, ForcePushRequest};
+use crate::freeze;
 use crate::git_clone_manager::GitCloneManager;
 use crate::github;
+use crate::ha::Cluster;
 use crate::github::api::Session;
 use crate::github::CommentLike;
+use crate::grafana::{self, AnnotationRequest};
+use crate::huddles;
+use crate::inbound_queue::Envelope;
+use crate::irc;
 use crate::jira;
+use crate::label_rules;
+use crate::matrix;
+use crate::mentions::{self, Mention};
+use crate::merge_gate;
 use crate::messenger::{self, Messenger};
+use crate::opsgenie;
+use crate::outbound_webhooks::{self, OutboundEvent};
+use crate::outbox::{self, Outbox, ReceiptStore};
+use crate::path_labels;
+use crate::pr_images::{self, PrImagesRequest};
+use crate::size_labels;
 use crate::pr_merge::{self, PRMergeRequest};
+use crate::pr_revert::{self, PRRevertRequest};
+use crate::provenance::{self, AttestationRequest};
+use crate::release_versions::{self, ReleaseVersionRequest};
+use crate::repo_files;
 use crate::repo_version::{self, RepoVersionRequest};
+use crate::resilience;
+use crate::routing::{self, RouteContext};
 use crate::runtime;
+use crate::sbom::{self, SbomRequest};
 use crate::server::github_verify::GithubWebhookVerifier;
 use crate::server::http::{FutureResponse, Handler};
+use crate::server::slack_actions;
+use crate::jsm;
+use crate::servicenow;
 use crate::slack::{self, SlackAttachmentBuilder, SlackRequest};
+use crate::slack_batch::SlackBatcher;
+use crate::slack_bridge::{self, BridgeRequest};
+use crate::slack_reactions::{self, ReactionRequest};
+use crate::slack_workflows::{self, WorkflowStepRequest};
+use crate::submodules::{self, SubmoduleBumpRequest};
+use crate::tag_protection::{self, TagRestoreRequest};
+use crate::templates;
+use crate::teams::{self, TeamMembers};
+use crate::users;
 use crate::util;
-use crate::worker::{Worker, TokioWorker};
+use crate::warehouse;
+use crate::webex;
+use crate::webhook_forwards::{self, ForwardRequest};
+use crate::webhook_retries;
+use crate::worker::{self, Dispatcher, TokioWorker, WorkQueues, Worker};
 
 pub struct GithubHandlerState {
     pub config: Arc<Config>,
     pub github_app: Arc<dyn github::api::GithubSessionFactory>,
     pub jira_session: Option<Arc<dyn jira::api::Session>>,
+    pub servicenow_session: Option<Arc<dyn servicenow::Session>>,
+    pub jsm_session: Option<Arc<dyn jsm::Session>>,
+    pub opsgenie_session: Option<Arc<dyn opsgenie::Session>>,
+    pub event_exporter: Option<Arc<warehouse::Exporter>>,
+    // shared with the other instances, if there are
+    pub cluster: Option<Arc<Cluster>>,
+    pub clone_mgr: Arc<GitCloneManager>,
+    pub queues: Arc<WorkQueues>,
     _runtime: Arc<Mutex<tokio::runtime::Runtime>>,
     pr_merge_worker: Arc<dyn Worker<PRMergeRequest>>,
+    pr_revert_worker: Arc<dyn Worker<PRRevertRequest>>,
     repo_version_worker: Arc<dyn Worker<RepoVersionRequest>>,
+    release_versions_worker: Arc<dyn Worker<ReleaseVersionRequest>>,
     force_push_worker: Arc<dyn Worker<ForcePushRequest>>,
-    slack_worker: Arc<dyn Worker<SlackRequest>>,
+    codeowners_worker: Arc<dyn Worker<CodeOwnersRequest>>,
+    blame_worker: Arc<dyn Worker<BlameRequest>>,
+    submodule_worker: Arc<dyn Worker<SubmoduleBumpRequest>>,
+    tag_restore_worker: Arc<dyn Worker<TagRestoreRequest>>,
+    pr_images_worker: Arc<dyn Worker<PrImagesRequest>>,
+    sbom_worker: Arc<dyn Worker<SbomRequest>>,
+    provenance_worker: Arc<dyn Worker<AttestationRequest>>,
+    webhooks_worker: Arc<dyn Worker<OutboundEvent>>,
+    forwards_worker: Arc<dyn Worker<ForwardRequest>>,
+    annotations_worker: Arc<dyn Worker<AnnotationRequest>>,
+    pub email_worker: Option<Arc<dyn Worker<EmailRequest>>>,
+    pub team_members: Arc<TeamMembers>,
+    pub slack_worker: Arc<dyn Worker<SlackRequest>>,
+    pub outbox: Arc<dyn ReceiptStore>,
+    pub workflow_step_worker: Arc<dyn Worker<WorkflowStepRequest>>,
+    pub reaction_worker: Arc<dyn Worker<ReactionRequest>>,
+    pub slack_bridge_worker: Arc<dyn Worker<BridgeRequest>>,
     recent_events: Mutex<Vec<String>>,
+    fixture_recorder: Option<Arc<FixtureRecorder>>,
 }
 
 pub struct GithubHandler {
     state: Arc<GithubHandlerState>,
+    config: Arc<Config>,
+    // handling a delivery again from `webhook_retries`, rather than as github sent it
+    retry: bool,
+    // handling a delivery from the queue shared with other instances, which was already deduplicated
+    queued: bool,
 }
 
 pub struct GithubEventHandler {
@@ -50,70 +143,356 @@ pub struct GithubEventHandler {
     pub action: String,
     pub github_session: Arc<dyn github::api::Session>,
     pub jira_session: Option<Arc<dyn jira::api::Session>>,
+    pub servicenow_session: Option<Arc<dyn servicenow::Session>>,
+    pub jsm_session: Option<Arc<dyn jsm::Session>>,
+    pub opsgenie_session: Option<Arc<dyn opsgenie::Session>>,
     pub pr_merge: Arc<dyn Worker<PRMergeRequest>>,
+    pub pr_revert: Arc<dyn Worker<PRRevertRequest>>,
     pub repo_version: Arc<dyn Worker<RepoVersionRequest>>,
+    pub release_versions: Arc<dyn Worker<ReleaseVersionRequest>>,
     pub force_push: Arc<dyn Worker<ForcePushRequest>>,
+    pub codeowners: Arc<dyn Worker<CodeOwnersRequest>>,
+    pub blame: Arc<dyn Worker<BlameRequest>>,
+    pub submodules: Arc<dyn Worker<SubmoduleBumpRequest>>,
+    pub tag_restore: Arc<dyn Worker<TagRestoreRequest>>,
+    pub pr_images: Arc<dyn Worker<PrImagesRequest>>,
+    pub sbom: Arc<dyn Worker<SbomRequest>>,
+    pub provenance: Arc<dyn Worker<AttestationRequest>>,
+    pub webhooks: Arc<dyn Worker<OutboundEvent>>,
+    pub annotations: Arc<dyn Worker<AnnotationRequest>>,
+    pub team_members: Arc<TeamMembers>,
 }
 
-const MAX_CONCURRENT_JOBS: usize = 20;
 const MAX_COMMITS_FOR_JIRA_CONSIDERATION: usize = 20;
 
 impl GithubHandlerState {
     pub fn new(
-        config: Arc<Config>,
+        live_config: Arc<LiveConfig>,
         github_app: Arc<dyn github::api::GithubSessionFactory>,
         jira_session: Option<Arc<dyn jira::api::Session>>,
     ) -> GithubHandlerState {
+        // for setting up the workers: their jobs get the current config
+        let config = live_config.get();
 
         let git_clone_manager = Arc::new(GitCloneManager::new(github_app.clone(), config.clone()));
 
-        let runtime = Arc::new(Mutex::new(runtime::new(MAX_CONCURRENT_JOBS, "jobs")));
-
-        let slack_worker = TokioWorker::new(runtime.clone(), slack::new_runner(config.main.slack_webhook_url.clone()));
-        let pr_merge_worker = TokioWorker::new(runtime.clone(), pr_merge::new_runner(
-            config.clone(),
-            github_app.clone(),
-            git_clone_manager.clone(),
-            slack_worker.clone(),
-        ));
-        let repo_version_worker = TokioWorker::new(runtime.clone(), repo_version::new_runner(
-            config.clone(),
-            github_app.clone(),
-            jira_session.clone(),
-            git_clone_manager.clone(),
-            slack_worker.clone(),
-        ));
-        let force_push_worker = TokioWorker::new(runtime.clone(), force_push::new_runner(
+        let runtime = Arc::new(Mutex::new(runtime::new(worker::MAX_CONCURRENT_JOBS, "jobs")));
+        let dispatcher =
+            Dispatcher::new(runtime.clone(), worker::MAX_CONCURRENT_JOBS, config.worker_repo_concurrency());
+        let queues = Arc::new(WorkQueues::new(config.worker_queue_capacity(), config.worker_queue_capacities()));
+
+        let notifier = match config.discord {
+            Some(ref discord_config) => discord::new_runner(discord_config, config.deliveries.clone())
+                .expect("Error creating discord client"),
+            None => slack::new_runner(
+                config.main.slack_webhook_url.clone(),
+                config.slack_legacy_format(),
+                config.slack_bot_token(),
+                Some(config.slack_threads.clone()),
+                config.deliveries.clone(),
+            ),
+        };
+        let notifier = match config.matrix {
+            Some(ref matrix_config) => matrix::new_runner(matrix_config, notifier, config.deliveries.clone())
+                .expect("Error creating matrix client"),
+            None => notifier,
+        };
+        let notifier = match config.webex {
+            Some(ref webex_config) => webex::new_runner(webex_config, notifier, config.deliveries.clone())
+                .expect("Error creating webex client"),
+            None => notifier,
+        };
+        let notifier = match config.irc {
+            Some(ref irc_config) => irc::new_runner(irc_config, notifier, config.deliveries.clone()),
+            None => notifier,
+        };
+        let outbox = outbox::new_store(&config).expect("Error setting up the outbox");
+        let notifier = outbox::new_runner(outbox.clone(), notifier);
+        let slack_worker = TokioWorker::new(dispatcher.clone(), queues.queue("slack"), notifier);
+        let slack_worker = SlackBatcher::wrap(slack_worker, config.slack_batch_window(), runtime.clone());
+        // messages are recorded as they are sent, in the context of the delivery that sends them
+        let slack_worker = Outbox::wrap(outbox.clone(), slack_worker);
+        let event_exporter = warehouse::new_exporter(&config).expect("Error creating warehouse exporter");
+        let event_publisher = event_bus::new_publisher(&config).expect("Error creating event bus publisher");
+        let webhooks_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("webhooks"),
+            outbound_webhooks::new_runner(live_config.clone(), event_exporter.clone(), event_publisher),
+        );
+        let forwards_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("forwards"),
+            webhook_forwards::new_runner(live_config.clone()),
+        );
+        let annotations_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("annotations"),
+            grafana::new_runner(config.clone()).expect("Error creating Grafana client"),
+        );
+        let pr_merge_worker = TokioWorker::new(dispatcher.clone(), queues.queue("pr-merge"), {
+            let (github_app, clone_mgr, slack, webhooks) =
+                (github_app.clone(), git_clone_manager.clone(), slack_worker.clone(), webhooks_worker.clone());
+            config_reload::live_runner(live_config.clone(), move |config| {
+                pr_merge::new_runner(config, github_app.clone(), clone_mgr.clone(), slack.clone(), webhooks.clone())
+            })
+        });
+        let pr_revert_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("pr-revert"),
+            {
+                let (github_app, jira_session, clone_mgr, slack) =
+                    (github_app.clone(), jira_session.clone(), git_clone_manager.clone(), slack_worker.clone());
+                config_reload::live_runner(live_config.clone(), move |config| {
+                    pr_revert::new_runner(
+                        config,
+                        github_app.clone(),
+                        jira_session.clone(),
+                        clone_mgr.clone(),
+                        slack.clone(),
+                    )
+                })
+            },
+        );
+        let repo_version_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("repo-version"),
+            {
+                let (github_app, jira_session, clone_mgr, slack) =
+                    (github_app.clone(), jira_session.clone(), git_clone_manager.clone(), slack_worker.clone());
+                config_reload::live_runner(live_config.clone(), move |config| {
+                    repo_version::new_runner(
+                        config,
+                        github_app.clone(),
+                        jira_session.clone(),
+                        clone_mgr.clone(),
+                        slack.clone(),
+                    )
+                })
+            },
+        );
+        let release_versions_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("release-versions"),
+            {
+                let (github_app, jira_session, clone_mgr, slack) =
+                    (github_app.clone(), jira_session.clone(), git_clone_manager.clone(), slack_worker.clone());
+                config_reload::live_runner(live_config.clone(), move |config| {
+                    release_versions::new_runner(
+                        config,
+                        github_app.clone(),
+                        jira_session.clone(),
+                        clone_mgr.clone(),
+                        slack.clone(),
+                    )
+                })
+            },
+        );
+        let force_push_worker = TokioWorker::new(dispatcher.clone(), queues.queue("force-push"), force_push::new_runner(
             github_app.clone(),
             git_clone_manager.clone(),
         ));
+        let codeowners_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("codeowners"),
+            codeowners::new_runner(github_app.clone()),
+        );
+        let blame_worker = TokioWorker::new(dispatcher.clone(), queues.queue("blame"), {
+            let (github_app, clone_mgr, slack) = (github_app.clone(), git_clone_manager.clone(), slack_worker.clone());
+            config_reload::live_runner(live_config.clone(), move |config| {
+                blame::new_runner(config, github_app.clone(), clone_mgr.clone(), slack.clone())
+            })
+        });
+        let submodule_worker = TokioWorker::new(dispatcher.clone(), queues.queue("submodules"), {
+            let (github_app, clone_mgr, slack) = (github_app.clone(), git_clone_manager.clone(), slack_worker.clone());
+            config_reload::live_runner(live_config.clone(), move |config| {
+                submodules::new_runner(config, github_app.clone(), clone_mgr.clone(), slack.clone())
+            })
+        });
+        let tag_restore_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("tag-restore"),
+            {
+                let (github_app, clone_mgr, slack) =
+                    (github_app.clone(), git_clone_manager.clone(), slack_worker.clone());
+                config_reload::live_runner(live_config.clone(), move |config| {
+                    tag_protection::new_runner(config, github_app.clone(), clone_mgr.clone(), slack.clone())
+                })
+            },
+        );
+        let pr_images_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("pr-images"),
+            {
+                let github_app = github_app.clone();
+                config_reload::live_runner(live_config.clone(), move |config| {
+                    pr_images::new_runner(config, github_app.clone())
+                })
+            },
+        );
+        let sbom_worker = TokioWorker::new(dispatcher.clone(), queues.queue("sbom"), {
+            let (github_app, clone_mgr, slack) = (github_app.clone(), git_clone_manager.clone(), slack_worker.clone());
+            config_reload::live_runner(live_config.clone(), move |config| {
+                sbom::new_runner(config, github_app.clone(), clone_mgr.clone(), slack.clone())
+            })
+        });
+        let provenance_worker = TokioWorker::new(dispatcher.clone(), queues.queue("provenance"), {
+            let (github_app, clone_mgr, slack) = (github_app.clone(), git_clone_manager.clone(), slack_worker.clone());
+            config_reload::live_runner(live_config.clone(), move |config| {
+                provenance::new_runner(config, github_app.clone(), clone_mgr.clone(), slack.clone())
+            })
+        });
+        let email_worker = config
+            .email
+            .as_ref()
+            .map(|_| TokioWorker::new(
+                dispatcher.clone(),
+                queues.queue("email"),
+                config_reload::live_runner(live_config.clone(), email::new_runner),
+            ));
+        let servicenow_session = config.servicenow.as_ref().map(|servicenow_config| {
+            let session =
+                servicenow::ServiceNowSession::new(servicenow_config).expect("Error creating ServiceNow client");
+            Arc::new(session) as Arc<dyn servicenow::Session>
+        });
+        let cluster = match Cluster::new(&config) {
+            Ok(c) => c.map(Arc::new),
+            Err(e) => panic!("Error setting up high availability: {}", e),
+        };
+        let jsm_session = config.jsm.as_ref().map(|jsm_config| {
+            let session = jsm::JsmSession::new(jsm_config).expect("Error creating Jira Service Management client");
+            Arc::new(session) as Arc<dyn jsm::Session>
+        });
+        let opsgenie_session = config.opsgenie.as_ref().map(|opsgenie_config| {
+            let session = opsgenie::OpsgenieSession::new(opsgenie_config).expect("Error creating Opsgenie client");
+            Arc::new(session) as Arc<dyn opsgenie::Session>
+        });
+        let team_members = Arc::new(TeamMembers::new());
+        let workflow_step_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("workflow-steps"),
+            {
+                let github_app = github_app.clone();
+                config_reload::live_runner(live_config.clone(), move |config| {
+                    slack_workflows::new_runner(config, github_app.clone())
+                })
+            },
+        );
+        let reaction_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("reactions"),
+            {
+                let (github_app, team_members, opsgenie) =
+                    (github_app.clone(), team_members.clone(), opsgenie_session.clone());
+                config_reload::live_runner(live_config.clone(), move |config| {
+                    slack_reactions::new_runner(config, github_app.clone(), team_members.clone(), opsgenie.clone())
+                })
+            },
+        );
+        let slack_bridge_worker = TokioWorker::new(
+            dispatcher.clone(),
+            queues.queue("slack-bridge"),
+            {
+                let github_app = github_app.clone();
+                config_reload::live_runner(live_config.clone(), move |config| {
+                    slack_bridge::new_runner(config, github_app.clone())
+                })
+            },
+        );
 
         GithubHandlerState {
             config: config.clone(),
             github_app: github_app.clone(),
             jira_session: jira_session.clone(),
+            servicenow_session: servicenow_session,
+            jsm_session: jsm_session,
+            opsgenie_session: opsgenie_session.clone(),
+            event_exporter: event_exporter,
+            cluster: cluster,
+            clone_mgr: git_clone_manager,
             _runtime: runtime,
+            queues: queues,
             pr_merge_worker: pr_merge_worker,
+            pr_revert_worker: pr_revert_worker,
             repo_version_worker: repo_version_worker,
+            release_versions_worker: release_versions_worker,
             force_push_worker: force_push_worker,
+            codeowners_worker: codeowners_worker,
+            blame_worker: blame_worker,
+            submodule_worker: submodule_worker,
+            tag_restore_worker: tag_restore_worker,
+            pr_images_worker: pr_images_worker,
+            sbom_worker: sbom_worker,
+            provenance_worker: provenance_worker,
+            webhooks_worker: webhooks_worker,
+            forwards_worker: forwards_worker,
+            annotations_worker: annotations_worker,
+            email_worker: email_worker,
+            team_members: team_members,
             slack_worker: slack_worker,
+            outbox: outbox,
+            workflow_step_worker: workflow_step_worker,
+            reaction_worker: reaction_worker,
+            slack_bridge_worker: slack_bridge_worker,
             recent_events: Mutex::new(Vec::new()),
+            fixture_recorder: config.fixture_recorder().map(Arc::new),
         }
     }
 }
 
 impl GithubHandler {
     pub fn new(
-        config: Arc<Config>,
+        live_config: Arc<LiveConfig>,
         github_app: Arc<dyn github::api::GithubSessionFactory>,
         jira_session: Option<Arc<dyn jira::api::Session>>,
     ) -> Box<GithubHandler> {
-        let state = GithubHandlerState::new(config, github_app, jira_session);
-        GithubHandler::from_state(Arc::new(state))
+        let state = GithubHandlerState::new(live_config.clone(), github_app, jira_session);
+        GithubHandler::from_state(Arc::new(state), live_config.get())
     }
 
-    pub fn from_state(state: Arc<GithubHandlerState>) -> Box<GithubHandler> {
-        Box::new(GithubHandler { state: state })
+    // |config| may be newer than the one |state| was set up with, if it has been reloaded since
+    pub fn from_state(state: Arc<GithubHandlerState>, config: Arc<Config>) -> Box<GithubHandler> {
+        Box::new(GithubHandler {
+            state: state,
+            config: config,
+            retry: false,
+            queued: false,
+        })
+    }
+
+    // For deliveries from `webhook_retries`: their delivery ids were seen before, and they're forgotten once handled
+    pub fn for_retry(state: Arc<GithubHandlerState>, config: Arc<Config>) -> Box<GithubHandler> {
+        Box::new(GithubHandler {
+            state: state,
+            config: config,
+            retry: true,
+            queued: false,
+        })
+    }
+
+    // For deliveries from the queue shared with other instances
+    pub fn for_queue(state: Arc<GithubHandlerState>, config: Arc<Config>) -> Box<GithubHandler> {
+        Box::new(GithubHandler {
+            state: state,
+            config: config,
+            retry: false,
+            queued: true,
+        })
+    }
+}
+
+// The headers needed to handle a delivery again
+const RETRY_HEADERS: &[&str] = &["x-github-event", "x-github-delivery", "x-hub-signature", "content-type"];
+
+fn retry_envelope(headers: &HeaderMap, body: &[u8]) -> Envelope {
+    let mut values = HashMap::new();
+    for name in RETRY_HEADERS {
+        if let Some(value) = headers.get(*name) {
+            values.insert(name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned());
+        }
+    }
+    Envelope {
+        headers: values,
+        body: String::from_utf8_lossy(body).into_owned(),
     }
 }
 
@@ -131,10 +510,33 @@ impl Handler for GithubHandler {
             event_id = String::from_utf8_lossy(values[0].as_bytes()).into_owned();
         }
 
-        // make sure event id is valid
-        {
-            let mut recent_events = self.state.recent_events.lock().unwrap();
-            if !util::check_unique_event(event_id.clone(), &mut *recent_events, 1000, 100) {
+        // github shows the delivery as failed so that it can be redelivered, and queued deliveries are tried again.
+        // before the duplicate check, which would refuse it then
+        let full = self.state.queues.full();
+        if !full.is_empty() {
+            let msg = format!("Too busy: the {} queue is full", full.join(", "));
+            error!("Refusing delivery {}: {}", event_id, msg);
+            return self.respond(util::new_msg_resp(StatusCode::SERVICE_UNAVAILABLE, msg));
+        }
+
+        // make sure event id is valid: seen by no instance, when there are several
+        if !self.retry && !self.queued {
+            let unique = match self.state.cluster {
+                Some(ref cluster) => match cluster.claim_delivery(&event_id) {
+                    Ok(claimed) => claimed,
+                    Err(e) => {
+                        // github can deliver it again once the other instances can be asked
+                        error!("Error claiming delivery {}: {}", event_id, e);
+                        let msg = "Could not check for duplicate deliveries";
+                        return self.respond(util::new_msg_resp(StatusCode::SERVICE_UNAVAILABLE, msg));
+                    }
+                },
+                None => {
+                    let mut recent_events = self.state.recent_events.lock().unwrap();
+                    util::check_unique_event(event_id.clone(), &mut *recent_events, 1000, 100)
+                }
+            };
+            if !unique {
                 let msg = format!("Duplicate X-Github-Delivery header: {}", event_id);
                 error!("{}", msg);
                 return self.respond(util::new_bad_req_resp(msg));
@@ -154,27 +556,118 @@ impl Handler for GithubHandler {
 
         let headers = req.headers().clone();
         let github_app = self.state.github_app.clone();
-        let config = self.state.config.clone();
+        let config = self.config.clone();
         let jira_session = self.state.jira_session.clone();
+        let servicenow_session = self.state.servicenow_session.clone();
+        let jsm_session = self.state.jsm_session.clone();
+        let opsgenie_session = self.state.opsgenie_session.clone();
         let pr_merge = self.state.pr_merge_worker.clone();
+        let pr_revert = self.state.pr_revert_worker.clone();
         let repo_version = self.state.repo_version_worker.clone();
+        let release_versions = self.state.release_versions_worker.clone();
         let force_push = self.state.force_push_worker.clone();
+        let codeowners = self.state.codeowners_worker.clone();
+        let blame = self.state.blame_worker.clone();
+        let submodules = self.state.submodule_worker.clone();
+        let tag_restore = self.state.tag_restore_worker.clone();
+        let pr_images = self.state.pr_images_worker.clone();
+        let sbom = self.state.sbom_worker.clone();
+        let provenance = self.state.provenance_worker.clone();
+        let webhooks = self.state.webhooks_worker.clone();
+        let forwards = self.state.forwards_worker.clone();
+        let annotations = self.state.annotations_worker.clone();
+        let email = self.state.email_worker.clone();
+        let team_members = self.state.team_members.clone();
         let slack = self.state.slack_worker.clone();
+        let fixture_recorder = self.state.fixture_recorder.clone();
+        let cluster = if self.retry || self.queued { None } else { self.state.cluster.clone() };
+        let retry = self.retry;
 
         Box::new(req.into_body().concat2().map(move |body| {
-            let verifier = GithubWebhookVerifier { secret: config.github.webhook_secret.clone() };
+            // |error| is recorded, |resp| is what github is told
+            let reject = |status: StatusCode, resp: &str, error: &str| {
+                let diagnosis =
+                    diagnostics::EventDiagnosis::rejected(&event_id, &event, &body, status.as_u16(), error);
+                if let Err(e) = config.event_diagnostics.record_rejected(&diagnosis) {
+                    error!("Error recording event diagnosis: {}", e);
+                }
+                // it would be refused the same way next time
+                if retry {
+                    if let Err(e) = config.webhook_retries.give_up(&event_id, error, db::now()) {
+                        error!("Error dead-lettering delivery {}: {}", event_id, e);
+                    }
+                }
+                util::new_msg_resp(status, resp.to_string())
+            };
+
+            // For failures before the event is handled, e.g. a github outage: nothing was sent yet, so it can all be
+            // done again later.
+            let retry_later = |repo: &str, error: &str| {
+                let envelope = retry_envelope(&headers, &body);
+                let failed = match config.webhook_retries.failed(&event_id, &event, repo, &envelope, error, db::now()) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        error!("Error queueing delivery {} for retry: {}", event_id, e);
+                        return reject(StatusCode::BAD_REQUEST, error, error);
+                    }
+                };
+                let (status, resp) = if failed.state == webhook_retries::DEAD {
+                    (StatusCode::BAD_REQUEST, format!("Gave up after {} attempts: {}", failed.attempts, error))
+                } else {
+                    (StatusCode::ACCEPTED, format!("Will retry: {}", error))
+                };
+                let diagnosis = diagnostics::EventDiagnosis::rejected(&event_id, &event, &body, status.as_u16(), &resp);
+                if let Err(e) = config.event_diagnostics.record_rejected(&diagnosis) {
+                    error!("Error recording event diagnosis: {}", e);
+                }
+                util::new_msg_resp(status, resp)
+            };
+
+            let verifier = GithubWebhookVerifier::for_delivery(&config, &body);
             if !verifier.is_req_valid(&headers, &body) {
-                return util::new_msg_resp(StatusCode::FORBIDDEN, "Invalid signature");
+                return reject(StatusCode::FORBIDDEN, "Invalid signature", "Invalid signature");
+            }
+
+            // whichever instance is free handles it
+            if let Some(ref cluster) = cluster {
+                return match cluster.push(&retry_envelope(&headers, &body)) {
+                    Ok(()) => util::new_msg_resp(StatusCode::ACCEPTED, "Queued"),
+                    Err(e) => {
+                        error!("Error queueing delivery {}: {}", event_id, e);
+                        if let Err(e) = cluster.forget_delivery(&event_id) {
+                            error!("Error forgetting delivery {}: {}", event_id, e);
+                        }
+                        util::new_msg_resp(StatusCode::SERVICE_UNAVAILABLE, "Could not queue delivery")
+                    }
+                };
+            }
+
+            if let Some(ref recorder) = fixture_recorder {
+                match Event::from_webhook(&event_id, &event, &body) {
+                    Ok(e) => recorder.maybe_record(&e),
+                    Err(e) => error!("Error normalizing {} event: {}", event, e),
+                };
             }
 
             let mut data: github::HookBody = match serde_json::from_slice(&body) {
                 Ok(h) => h,
                 Err(e) => {
                     error!("Error parsing json: {}\n---\n{}\n---\n", e, String::from_utf8_lossy(&body));
-                    return util::new_bad_req_resp(format!("Error parsing JSON: {}", e));
+                    let msg = format!("Error parsing JSON: {}", e);
+                    return reject(StatusCode::BAD_REQUEST, &msg, &msg);
                 }
             };
 
+            // rather than handle a pull request without updating its JIRA tickets, try it again once JIRA is back
+            if jira_session.is_some() && resilience::is_open(faults::Service::Jira) {
+                let uses_jira = data.pull_request.as_ref().map(|pr| {
+                    !config.repos().jira_projects(&data.repository, &pr.base.ref_name).is_empty()
+                });
+                if uses_jira == Some(true) {
+                    return retry_later(&data.repository.full_name, "JIRA is unavailable");
+                }
+            }
+
             let github_session = match github_app.new_session(&data.repository.owner.login(), &data.repository.name) {
                 // Note: this doesn't really need to be an Arc anymore...
                 Ok(g) => Arc::new(g),
@@ -185,7 +678,8 @@ impl Handler for GithubHandler {
                         &data.repository.name,
                         e
                     );
-                    return util::new_bad_req_resp("Could not create github session");
+                    let msg = format!("Could not create github session: {}", e);
+                    return retry_later(&data.repository.full_name, &msg);
                 }
             };
 
@@ -206,7 +700,8 @@ impl Handler for GithubHandler {
                         Ok(pr) => Some(pr),
                         Err(e) => {
                             error!("Error refetching issue #{} as pull request: {}", issue.number, e);
-                            None
+                            let msg = format!("Could not fetch pull request #{}: {}", issue.number, e);
+                            return retry_later(&data.repository.full_name, &msg);
                         }
                     };
                 }
@@ -222,7 +717,11 @@ impl Handler for GithubHandler {
                         pull_request.number,
                     ) {
                         Ok(pr) => changed_pr = Some(pr),
-                        Err(e) => error!("Error refetching pull request to get reviewers: {}", e),
+                        Err(e) => {
+                            error!("Error refetching pull request to get reviewers: {}", e);
+                            let msg = format!("Could not fetch pull request #{}: {}", pull_request.number, e);
+                            return retry_later(&data.repository.full_name, &msg);
+                        }
                     };
                 }
             }
@@ -230,23 +729,90 @@ impl Handler for GithubHandler {
                 data.pull_request = Some(changed_pr);
             }
 
+            let trace = Arc::new(diagnostics::Trace::new());
+            let sender = data.sender.login().to_string();
+            let summary = diagnostics::summarize(&event, &data);
+            let repo_name = data.repository.full_name.clone();
+            let number = match (&data.pull_request, &data.issue) {
+                (Some(pr), _) => pr.number,
+                (None, Some(issue)) => issue.number,
+                (None, None) => 0,
+            };
+            let dry_run = config.dry_run(&data.repository);
+            let mut messenger =
+                messenger::new(config.clone(), slack).with_trace(trace.clone()).with_dry_run(dry_run);
+            if let Some(dm_event) = users::dm_event_type(&event) {
+                messenger = messenger.for_dm_event(dm_event);
+            }
+            if let Some(email) = email {
+                messenger = messenger.with_email(email);
+            }
             let handler = GithubEventHandler {
                 event: event.clone(),
                 data: data,
-                action: action,
+                action: action.clone(),
                 config: config.clone(),
-                messenger: messenger::new(config.clone(), slack),
+                messenger: messenger,
                 github_session: github_session,
-                jira_session: jira_session,
+                jira_session: jira::dry_run::for_repo(jira_session, dry_run),
+                servicenow_session: servicenow_session,
+                jsm_session: jsm_session,
+                opsgenie_session: opsgenie_session,
                 pr_merge: pr_merge,
+                pr_revert: pr_revert,
                 repo_version: repo_version,
+                release_versions: release_versions,
                 force_push: force_push,
+                codeowners: codeowners,
+                blame: blame,
+                submodules: submodules,
+                tag_restore: tag_restore,
+                pr_images: pr_images,
+                sbom: sbom,
+                provenance: provenance,
+                webhooks: webhooks,
+                annotations: annotations,
+                team_members: team_members,
             };
 
-            match handler.handle_event() {
-                Some((status, resp)) => util::new_msg_resp(status, resp),
-                None => util::new_msg_resp(StatusCode::OK, format!("Unhandled event: {}", event)),
+            // keeping the request's id, so that the timeline of the request includes its delivery's jobs
+            let context = error_reports::Context {
+                request_id: error_reports::current_context().request_id,
+                delivery_id: event_id.clone(),
+                event: event.clone(),
+                repo: repo_name.clone(),
+                number: number,
+                job: String::new(),
+            };
+            let (status, resp) = match error_reports::with_context(context, || handler.handle_event()) {
+                Some((status, resp)) => (status, resp),
+                None => (StatusCode::OK, format!("Unhandled event: {}", event)),
+            };
+
+            let diagnosis =
+                diagnostics::EventDiagnosis::new(&event_id, &event, &action, &repo_name, number, &resp, &trace)
+                    .with_payload(&sender, &summary)
+                    .with_status(status.as_u16());
+            if let Err(e) = config.event_diagnostics.record(&diagnosis) {
+                error!("Error recording event diagnosis: {}", e);
+            }
+            if retry {
+                if let Err(e) = config.webhook_retries.remove(&event_id) {
+                    error!("Error removing retried delivery {}: {}", event_id, e);
+                }
             }
+            // downstream services get it after octobot has handled it, whatever came of that
+            if !config.forwards().is_empty() {
+                forwards.send(ForwardRequest {
+                    delivery_id: event_id.clone(),
+                    event: event.clone(),
+                    action: action,
+                    repo: repo_name,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                });
+            }
+
+            util::new_msg_resp(status, resp)
         }))
     }
 }
@@ -256,6 +822,8 @@ type EventResponse = (StatusCode, String);
 impl GithubEventHandler {
     pub fn handle_event(&self) -> Option<EventResponse> {
         info!("Received event: {}", self.event);
+        self.record_digest_items();
+
         if self.event == "ping" {
             Some(self.handle_ping())
         } else if self.event == "pull_request" {
@@ -264,12 +832,20 @@ impl GithubEventHandler {
             Some(self.handle_pr_review_comment())
         } else if self.event == "pull_request_review" {
             Some(self.handle_pr_review())
+        } else if self.event == "pull_request_review_thread" {
+            Some(self.handle_pr_review_thread())
         } else if self.event == "commit_comment" {
             Some(self.handle_commit_comment())
         } else if self.event == "issue_comment" {
             Some(self.handle_issue_comment())
         } else if self.event == "push" {
             Some(self.handle_push())
+        } else if self.event == "status" {
+            Some(self.handle_status())
+        } else if self.event == "release" {
+            Some(self.handle_release())
+        } else if self.event == "deployment_status" {
+            Some(self.handle_deployment_status())
         } else {
             None
         }
@@ -288,6 +864,10 @@ impl GithubEventHandler {
         users.iter().map(|u| self.slack_user_name(u)).collect()
     }
 
+    fn pr_link(&self, pull_request: &dyn github::PullRequestLike) -> String {
+        util::make_link(pull_request.html_url(), pull_request.title())
+    }
+
     fn pull_request_commits(&self, pull_request: &dyn github::PullRequestLike) -> Vec<github::Commit> {
         if !pull_request.has_commits() {
             return vec![];
@@ -331,6 +911,426 @@ impl GithubEventHandler {
         (StatusCode::OK, "ping".into())
     }
 
+    // Statuses are only recorded for digests
+    fn handle_status(&self) -> EventResponse {
+        let state = self.data.state.as_ref().map(|s| s.as_str()).unwrap_or("");
+        if (state == "failure" || state == "error") && self.config.events_enabled() {
+            self.webhooks.send(outbound_webhooks::ci_failed(&self.data));
+        }
+        if (state == "failure" || state == "error") && self.config.alerts_enabled() {
+            self.raise_critical_alerts();
+        }
+
+        (StatusCode::OK, "status".into())
+    }
+
+    // A failing main or release branch is escalated until someone acknowledges it
+    fn raise_critical_alerts(&self) {
+        let repo = &self.data.repository;
+        let release_branch_prefix = self.config.repos().release_branch_prefix(repo);
+        let context = self.data.context.clone().unwrap_or_default();
+        let mut message = match self.data.target_url {
+            Some(ref url) if !url.is_empty() => util::make_link(url, &context),
+            _ => context,
+        };
+        if let Some(ref description) = self.data.description {
+            message += &format!(": {}", description);
+        }
+
+        let mut with_excerpt = false;
+        for branch in self.data.branches.iter().flatten() {
+            let kind = match alerts::failed_status_kind(&branch.name, &release_branch_prefix) {
+                Some(k) => k,
+                None => continue,
+            };
+            if !with_excerpt {
+                if let Some(excerpt) = self.ci_log_excerpt() {
+                    message += &format!("\n{}", excerpt);
+                }
+                with_excerpt = true;
+            }
+            let now = db::now();
+            if let Err(e) = alerts::raise(
+                &self.config,
+                &self.messenger,
+                self.opsgenie_session.as_ref().map(|s| s.deref()),
+                repo,
+                &branch.name,
+                kind,
+                &message,
+                now,
+            ) {
+                error!("Error raising alert for {} {}: {}", repo.full_name, branch.name, e);
+            }
+        }
+    }
+
+    // What the failed status's check or job logged, if github has it
+    fn ci_log_excerpt(&self) -> Option<String> {
+        let max_lines = self.config.alert_log_lines();
+        let sha = self.data.sha.as_ref().map(|s| s.as_str()).unwrap_or("");
+        if max_lines == 0 || sha.is_empty() {
+            return None;
+        }
+
+        let context = self.data.context.as_ref().map(|c| c.as_str()).unwrap_or("");
+        let target_url = self.data.target_url.as_ref().map(|u| u.as_str()).unwrap_or("");
+        let repo = &self.data.repository;
+        match ci_logs::excerpt(self.github_session.deref(), repo, sha, context, target_url, max_lines) {
+            Ok(e) => e,
+            Err(e) => {
+                error!("Error looking up the log of {} for {}: {}", context, repo.full_name, e);
+                None
+            }
+        }
+    }
+
+    // Opens PRs bumping the submodule pin in repos that depend on this one
+    fn handle_release(&self) -> EventResponse {
+        if self.action != "published" {
+            return (StatusCode::OK, "release [ignored]".into());
+        }
+
+        if let Some(ref release) = self.data.release {
+            if submodules::is_bumpable(release) {
+                self.submodules.send(submodules::req(&self.data.repository, release));
+            }
+            if !release.draft && self.config.repos().sbom_enabled(&self.data.repository) {
+                self.sbom.send(sbom::req(&self.data.repository, release));
+            }
+            if !release.draft && self.config.repos().provenance_enabled(&self.data.repository) {
+                self.provenance.send(provenance::req(&self.data.repository, release));
+            }
+            if !release.draft {
+                self.annotate(grafana::released(&self.data.repository, release));
+
+                let msg = format!("Upstream repo {} released {}", self.data.repository.full_name, release.tag_name);
+                let attachments = vec![dependencies::release_attachment(&self.data.repository, release)];
+                self.notify_downstream(&msg, &attachments);
+            }
+        }
+
+        (StatusCode::OK, "release".into())
+    }
+
+    // Successful deployments are annotated on the repo's grafana dashboards
+    fn handle_deployment_status(&self) -> EventResponse {
+        if let (Some(deployment), Some(status)) = (&self.data.deployment, &self.data.deployment_status) {
+            if status.state == "success" {
+                self.annotate(grafana::deployed(&self.data.repository, deployment, status));
+            }
+        }
+
+        (StatusCode::OK, "deployment_status".into())
+    }
+
+    fn notify_downstream(&self, msg: &str, attachments: &Vec<slack::SlackAttachment>) {
+        match self.config.repos().dependents(&self.data.repository.full_name) {
+            Ok(dependents) => {
+                dependencies::notify_downstream(&self.messenger, &self.data.repository, &dependents, msg, attachments)
+            }
+            Err(e) => error!("Error looking up repos depending on {}: {}", self.data.repository.full_name, e),
+        };
+    }
+
+    // Keeps what digests report on up to date, whether or not the event sends any messages
+    fn record_digest_items(&self) {
+        let items = &self.config.digest_items;
+        let result = match self.event.as_str() {
+            "pull_request" | "pull_request_review" => match self.data.pull_request {
+                Some(ref pull_request) => {
+                    let review = if self.action == "submitted" { self.data.review.as_ref() } else { None };
+                    digests::record_pull_request(items, &self.data.repository, pull_request, review)
+                }
+                None => Ok(()),
+            },
+            "status" => digests::record_status(items, &self.data),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            error!("Error recording digest items: {}", e);
+        }
+    }
+
+    // Keeps a record of merges to protected branches for compliance reports
+    // Messages about a PR go to its thread, and to whichever channels the repo's routing rules pick for it
+    fn pr_messenger(&self, pull_request: &dyn github::PullRequestLike) -> Messenger {
+        let rules = self.config.repos().routing_rules(&self.data.repository);
+        let owner = self.data.repository.owner.login();
+        let repo = &self.data.repository.name;
+        let number = pull_request.number();
+
+        let mut route = RouteContext::default().with_head(pull_request.head_branch());
+        if route.head.is_empty() && routing::needs_head(&rules) {
+            match self.github_session.get_pull_request(owner, repo, number) {
+                Ok(pr) => route.head = pr.head.ref_name,
+                Err(e) => error!("Error getting PR #{} for routing: {}", number, e),
+            };
+        }
+        if routing::needs_paths(&rules) {
+            match self.github_session.get_pull_request_files(owner, repo, number) {
+                Ok(files) => route.paths = files.into_iter().map(|f| f.filename).collect(),
+                Err(e) => error!("Error getting files of PR #{} for routing: {}", number, e),
+            };
+        }
+        if routing::needs_labels(&rules) {
+            match self.github_session.get_pull_request_labels(owner, repo, number) {
+                Ok(labels) => route.labels = labels.into_iter().map(|l| l.name).collect(),
+                Err(e) => error!("Error getting labels of PR #{} for routing: {}", number, e),
+            };
+        }
+
+        self.messenger.in_pr_thread(&self.data.repository, number).with_route_context(route)
+    }
+
+    // Force-pushes to repos with force-push notifications say what changed, instead of just listing the pushed commits
+    fn force_push_summary(&self, pull_request: &github::PullRequest) -> Option<force_push::ForcePushSummary> {
+        if !self.data.forced() || !self.config.repos().notify_force_push(&self.data.repository) {
+            return None;
+        }
+
+        let repo = &self.data.repository;
+        let compare_url = match self.data.compare {
+            Some(ref url) => url.clone(),
+            None => format!("{}/compare/{}...{}", repo.html_url, self.data.before(), self.data.after()),
+        };
+        let summary = force_push::summarize(
+            self.github_session.deref(),
+            repo,
+            pull_request,
+            self.data.before(),
+            self.data.after(),
+            &compare_url,
+        );
+        match summary {
+            Ok(s) => Some(s),
+            Err(e) => {
+                error!("Error summarizing force-push to PR #{}: {}", pull_request.number, e);
+                None
+            }
+        }
+    }
+
+    // Team review requests go to the team's channel if it has one, otherwise to each of its members
+    fn notify_team(
+        &self,
+        team: &github::Team,
+        msg: &str,
+        attachments: &Vec<slack::SlackAttachment>,
+        number: u32,
+    ) {
+        let org = self.data.repository.owner.login();
+        let key = teams::team_key(org, &team.slug);
+        match self.config.team_channels.get(&key) {
+            Ok(Some(channel)) => {
+                self.messenger.send_to_team_channel(&channel, msg, attachments, &self.data.repository);
+                return;
+            }
+            Ok(None) => (),
+            Err(e) => error!("{}", e),
+        };
+
+        let members = match self.team_members.get(self.github_session.deref(), org, &team.slug) {
+            Ok(m) => m,
+            Err(e) => {
+                error!("{}", e);
+                self.messenger.note(format!("Could not look up members of team '{}'", key));
+                return;
+            }
+        };
+        let members: Vec<github::User> = members.into_iter().filter(|m| m.login != self.data.sender.login).collect();
+        let logins = members.iter().map(|m| m.login().to_string()).collect();
+
+        // direct messages aren't routed, so there's no need for pr_messenger's lookups
+        let messenger = self.messenger.in_pr_thread(&self.data.repository, number).with_email_fallback(logins);
+        for member in members.iter() {
+            messenger.send_to_user(member, msg, attachments);
+        }
+    }
+
+    // Mentions in an edited description are only new if the previous one didn't have them
+    fn notify_description_mentions(&self, pull_request: &github::PullRequest) {
+        let body = pull_request.body.clone().unwrap_or_default();
+        let previous = self.data.changes.as_ref().and_then(|c| c.body.as_ref()).map(|b| b.from.as_str());
+        let mentions = match previous {
+            Some(previous) if self.action == "edited" => mentions::added(&body, previous),
+            _ if self.action == "edited" => vec![],
+            _ => mentions::parse(&body),
+        };
+        self.notify_mentions(&pull_request, &mentions, &body, &pull_request.html_url, "the description of", &[]);
+    }
+
+    // Mentioned users, and the members of mentioned teams, get a direct message with the text around the mention.
+    // Users in `notified` already got a message with all of the text.
+    fn notify_mentions(
+        &self,
+        pull_request: &dyn github::PullRequestLike,
+        mentions: &[Mention],
+        body: &str,
+        url: &str,
+        place: &str,
+        notified: &[github::User],
+    ) {
+        let mut notified = notified.iter().map(|u| u.login().to_string()).collect::<Vec<_>>();
+        notified.push(pull_request.user().login().to_string());
+        notified.push(self.data.sender.login().to_string());
+        notified.push(self.github_session.bot_name().to_string());
+
+        for mention in mentions {
+            let (users, whom) = match *mention {
+                Mention::User(ref login) => (vec![github::User::new(login)], "you".to_string()),
+                Mention::Team { ref org, ref slug } => {
+                    match self.team_members.get(self.github_session.deref(), org, slug) {
+                        Ok(members) => (members, format!("team {}", mention.name())),
+                        Err(e) => {
+                            error!("{}", e);
+                            self.messenger.note(format!("Could not look up members of team '{}'", mention.name()));
+                            continue;
+                        }
+                    }
+                }
+            };
+            let users = users.into_iter().filter(|u| !notified.iter().any(|n| n == u.login())).collect::<Vec<_>>();
+            if users.is_empty() {
+                continue;
+            }
+            let logins = users.iter().map(|u| u.login().to_string()).collect::<Vec<_>>();
+
+            let msg = format!(
+                "{} mentioned {} in {} \"{}\"",
+                self.slack_user_name(&self.data.sender),
+                whom,
+                place,
+                self.pr_link(pull_request)
+            );
+            let attachments = vec![
+                SlackAttachmentBuilder::new(&mentions::context(body, mention))
+                    .title(format!("{} said:", self.slack_user_name(&self.data.sender)))
+                    .title_link(url)
+                    .build(),
+            ];
+            let messenger = self
+                .messenger
+                .in_pr_thread(&self.data.repository, pull_request.number())
+                .for_dm_event(users::DM_MENTION)
+                .with_email_fallback(logins.clone());
+            for user in users.iter() {
+                messenger.send_to_user(user, &msg, &attachments);
+            }
+            notified.extend(logins);
+        }
+    }
+
+    fn send_webhook(&self, event: &str, pull_request: &github::PullRequest) {
+        if self.config.events_enabled() {
+            self.webhooks.send(outbound_webhooks::pull_request_event(
+                event,
+                &self.data.repository,
+                pull_request,
+                &self.data.sender,
+            ));
+        }
+    }
+
+    fn annotate(&self, req: grafana::AnnotationRequest) {
+        if !self.config.grafana_dashboards(&req.repo, &req.event).is_empty() {
+            self.annotations.send(req);
+        }
+    }
+
+    fn record_merge(&self, pull_request: &github::PullRequest, commits: &Vec<github::Commit>) {
+        let branch = &pull_request.base.ref_name;
+        let release_branch_prefix = self.config.repos().release_branch_prefix(&self.data.repository);
+        if !self.config.compliance_enabled() || !compliance::is_protected_branch(branch, &release_branch_prefix) {
+            return;
+        }
+
+        let reviews = match pull_request.reviews {
+            Some(ref reviews) => reviews.clone(),
+            None => match self.github_session.get_pull_request_reviews(
+                self.data.repository.owner.login(),
+                &self.data.repository.name,
+                pull_request.number,
+            ) {
+                Ok(reviews) => reviews,
+                Err(e) => {
+                    error!("Error getting reviews of merged PR #{}: {}", pull_request.number, e);
+                    vec![]
+                }
+            },
+        };
+
+        let jira_projects = self.config.repos().jira_projects(&self.data.repository, branch);
+        let tickets = jira::workflow::get_all_jira_keys(commits, &jira_projects);
+        let merge = compliance::merge_record(
+            &self.data.repository,
+            pull_request,
+            &self.data.sender,
+            &reviews,
+            tickets,
+            db::now(),
+        );
+        if let Err(e) = self.config.merges.record(&merge) {
+            error!("{}", e);
+        }
+    }
+
+    // So support can find which PR and release a commit of a main or release branch came from
+    fn index_merge(&self, pull_request: &github::PullRequest, commits: &Vec<github::Commit>) {
+        let branch = &pull_request.base.ref_name;
+        let release_branch_prefix = self.config.repos().release_branch_prefix(&self.data.repository);
+        if !compliance::is_protected_branch(branch, &release_branch_prefix) {
+            return;
+        }
+
+        let jira_projects = self.config.repos().jira_projects(&self.data.repository, branch);
+        let tickets = jira::workflow::get_all_jira_keys(commits, &jira_projects);
+        let mut shas = commits.iter().map(|c| c.sha.as_str()).collect::<Vec<_>>();
+        if let Some(ref merge_commit_sha) = pull_request.merge_commit_sha {
+            shas.push(merge_commit_sha);
+        }
+        for sha in shas {
+            let mut record = CommitRecord::new(&self.data.repository.full_name, sha, branch);
+            record.pr_number = Some(pull_request.number);
+            record.pr_title = pull_request.title.clone();
+            record.pr_url = pull_request.html_url.clone();
+            record.tickets = tickets.clone();
+            if let Err(e) = self.config.commit_index.record(&record) {
+                error!("{}", e);
+            }
+        }
+    }
+
+    fn index_pushed_commits(&self, branch: &str) {
+        let commits = match self.data.commits {
+            Some(ref c) => c,
+            None => return,
+        };
+        let repo = &self.data.repository.full_name;
+        let jira_projects = self.config.repos().jira_projects(&self.data.repository, branch);
+        for commit in commits {
+            let mut record = CommitRecord::new(repo, &commit.id, branch);
+            record.tickets = jira::workflow::get_all_jira_keys(&vec![commit.clone()], &jira_projects);
+            // backports keep the PR of the commit they were picked from
+            if let Some(picked_from) = commit_index::cherry_picked_from(&commit.message) {
+                match self.config.commit_index.get(repo, &picked_from) {
+                    Ok(Some(original)) => {
+                        record.pr_number = original.pr_number;
+                        record.pr_title = original.pr_title;
+                        record.pr_url = original.pr_url;
+                    }
+                    Ok(None) => (),
+                    Err(e) => error!("{}", e),
+                };
+                record.picked_from = picked_from;
+            }
+            if let Err(e) = self.config.commit_index.record(&record) {
+                error!("{}", e);
+            }
+        }
+    }
+
     fn handle_pr(&self) -> EventResponse {
         enum NotifyMode {
             NotifyAll,
@@ -368,117 +1368,528 @@ impl GithubEventHandler {
                 verb = Some("unassigned".to_string());
                 notify_mode = NotifyMode::NotifyChannel;
             } else if self.action == "review_requested" {
-                if let Some(ref reviewers) = pull_request.requested_reviewers {
-                    let assignees_str = self.slack_user_names(reviewers).join(", ");
-                    verb = Some(format!("submitted for review to {}", assignees_str));
+                if let Some(ref team) = self.data.requested_team {
+                    // members are notified separately: the whole team is too many to treat as participants
+                    verb = Some(format!("submitted for review to team {}", team.name));
+                    notify_mode = NotifyMode::NotifyChannel;
                 } else {
-                    verb = None;
+                    if let Some(ref reviewers) = pull_request.requested_reviewers {
+                        let assignees_str = self.slack_user_names(reviewers).join(", ");
+                        verb = Some(format!("submitted for review to {}", assignees_str));
+                    } else {
+                        verb = None;
+                    }
+                    notify_mode = NotifyMode::NotifyAll;
                 }
-                notify_mode = NotifyMode::NotifyAll;
             } else {
                 verb = None;
                 notify_mode = NotifyMode::NotifyNone;
             }
 
+            if self.action == "opened" || self.action == "synchronize" {
+                self.apply_path_labels(pull_request);
+                self.apply_size_label(pull_request);
+            }
+
+            if self.action == "opened" || self.action == "synchronize" || self.action == "edited" {
+                self.lint_pull_request(pull_request);
+            }
+
+            if self.action == "opened" || self.action == "edited" {
+                self.notify_description_mentions(pull_request);
+            }
+
+            if self.action == "opened" || self.action == "reopened" || self.action == "synchronize" {
+                self.check_code_freeze(pull_request);
+                self.check_change_request(pull_request);
+                self.check_jsm_approval(pull_request);
+            } else if self.action == "closed" && pull_request.merged != Some(true) {
+                self.forget_change_request(pull_request);
+                self.forget_jsm_approval(pull_request);
+            }
+
+            if self.action == "opened"
+                || self.action == "reopened"
+                || self.action == "synchronize"
+                || self.action == "labeled"
+                || self.action == "unlabeled"
+            {
+                self.check_merge_gate(pull_request);
+            }
+
+            if self.action == "synchronize" || self.action == "unlabeled" {
+                self.update_label_merge(pull_request);
+            }
+
             // early exit if we have nothing to do here.
             if verb.is_none() && self.action != "labeled" {
+                self.messenger.note(format!("Pull request action '{}' does not send notifications", self.action));
                 return (StatusCode::OK, "pr".into())
             }
 
             let commits = self.pull_request_commits(&pull_request);
 
-            if let Some(ref verb) = verb {
-                let branch_name = &pull_request.base.ref_name;
+            if self.action == "closed" && pull_request.merged == Some(true) {
+                self.record_merge(pull_request, &commits);
+                self.index_merge(pull_request, &commits);
+                self.send_webhook(outbound_webhooks::PULL_REQUEST_MERGED, pull_request);
+                self.annotate(grafana::merged(&self.data.repository, pull_request, &self.data.sender));
+            } else if self.action == "opened" {
+                self.send_webhook(outbound_webhooks::PULL_REQUEST_OPENED, pull_request);
+            } else if self.action == "review_requested" {
+                self.send_webhook(outbound_webhooks::PULL_REQUEST_REVIEW_REQUESTED, pull_request);
+            }
+
+            if let Some(ref verb) = verb {
+                let branch_name = &pull_request.base.ref_name;
+
+                let mut attachment = SlackAttachmentBuilder::new("");
+                attachment
+                    .title(format!("Pull Request #{}: \"{}\"", pull_request.number, pull_request.title.as_str()))
+                    .title_link(pull_request.html_url.as_str());
+                if self.action == "review_requested" {
+                    if let Some(ref team) = self.data.requested_team {
+                        attachment.field("Team", team.name.as_str());
+                    } else if let Some(ref reviewers) = pull_request.requested_reviewers {
+                        attachment.field("Reviewers", self.slack_user_names(reviewers).join(", "));
+                    }
+                }
+                if self.config.slack_signing_secret().is_some() {
+                    if pull_request.state == "open" {
+                        slack_actions::add_pr_actions(&mut attachment, &self.data.repository, pull_request);
+                    } else if pull_request.is_merged() {
+                        slack_actions::add_merged_pr_actions(&mut attachment, &self.data.repository, pull_request);
+                    }
+                }
+                let mut attachments = vec![attachment.build()];
+                if !pull_request.is_draft() && (self.action == "opened" || self.action == "ready_for_review") {
+                    if let Some(preview) = self.diff_preview(pull_request) {
+                        attachments.push(SlackAttachmentBuilder::new(&preview).title("Diff").build());
+                    }
+                }
+
+                if !pull_request.is_draft() {
+                    let msg = templates::render_or(
+                        &self.config.slack_templates().pull_request,
+                        &templates::with_vars(
+                            templates::pr_vars(&pull_request, branch_name, &self.data.repository.full_name, &commits),
+                            json!({ "verb": verb, "pr_link": self.pr_link(&pull_request) }),
+                        ),
+                        format!("Pull Request {}", verb),
+                    );
+                    let mut messenger = self.pr_messenger(&pull_request);
+                    if self.action == "review_requested" && self.data.requested_team.is_none() {
+                        if let Some(ref reviewers) = pull_request.requested_reviewers {
+                            let logins = reviewers.iter().map(|r| r.login().to_string()).collect();
+                            messenger = messenger.with_email_fallback(logins);
+                        }
+                    }
+
+                    match notify_mode {
+                    NotifyMode::NotifyChannel =>
+                        messenger.send_to_channel(&msg, &attachments, &self.data.repository, &branch_name, &commits),
+
+                    NotifyMode::NotifyAll =>
+                        messenger.send_to_all(
+                            &msg,
+                            &attachments,
+                            &pull_request.user,
+                            &self.data.sender,
+                            &self.data.repository,
+                            &self.all_participants(&pull_request, &commits),
+                            &branch_name,
+                            &commits,
+                        ),
+
+                    NotifyMode::NotifyNone =>
+                        self.messenger.note(format!("Pull request action '{}' does not send notifications", self.action)),
+                    };
+
+                    if self.action == "opened" || self.action == "ready_for_review" {
+                        let channels = messenger.channels(&self.data.repository, branch_name, &commits);
+                        self.forward_images(pull_request, &channels);
+                    }
+
+                    if self.action == "review_requested" {
+                        if let Some(ref team) = self.data.requested_team {
+                            self.notify_team(team, &msg, &attachments, pull_request.number);
+                        }
+                    }
+                } else {
+                    self.messenger.note(format!("Pull request #{} is a draft", pull_request.number));
+                }
+
+                let jira_projects = self.config.repos().jira_projects(&self.data.repository, branch_name);
+
+                let is_pull_request_ready = self.action == "opened" || self.action == "ready_for_review";
+
+                // Mark JIRAs in review for PR open
+                if is_pull_request_ready {
+                    if let Some(ref jira_config) = self.config.jira {
+                        if let Some(ref jira_session) = self.jira_session {
+                            if commits.len() > MAX_COMMITS_FOR_JIRA_CONSIDERATION {
+                                let msg = format!(
+                                    "Too many commits on Pull Request #{}. Ignoring JIRAs.",
+                                    pull_request.number
+                                );
+                                self.pr_messenger(&pull_request).send_to_owner(
+                                    &msg,
+                                    &attachments,
+                                    &pull_request.user,
+                                    &self.data.repository,
+                                    &branch_name,
+                                    &commits,
+                                );
+
+                            } else {
+                                jira::workflow::submit_for_review(
+                                    &pull_request,
+                                    &commits,
+                                    &jira_projects,
+                                    jira_session.deref(),
+                                    jira_config,
+                                );
+                            }
+                        }
+                    }
+                }
+
+                // Request reviews from code owners once the PR is ready
+                if is_pull_request_ready && !pull_request.is_draft() {
+                    if self.config.repos().codeowners_reviews(&self.data.repository, &pull_request.user) {
+                        self.codeowners.send(codeowners::req(&self.data.repository, pull_request));
+                    }
+                }
+
+                // Let the authors of the lines a revert or hotfix changes know about it
+                if is_pull_request_ready && !pull_request.is_draft() && blame::kind(pull_request).is_some() {
+                    if self.config.repos().notify_blame(&self.data.repository) {
+                        self.blame.send(blame::req(&self.data.repository, pull_request));
+                    }
+                }
+
+                // Check for jira reference on ready for review and PR title rename
+                // (since JIRA check ignore is based on PR title)
+                if is_pull_request_ready || self.action == "edited" {
+                    // Mark if no JIRA references
+                    self.check_jira_refs(&pull_request, &commits, &jira_projects);
+                }
+
+                if verb == "merged" {
+                    self.mark_jiras_merged(&pull_request, &commits, &jira_projects);
+                }
+            }
+
+            let release_branch_prefix = self.config.repos().release_branch_prefix(&self.data.repository);
+            if self.action == "labeled" {
+                if let Some(ref label) = self.data.label {
+                    self.apply_label_rules(pull_request, label, &commits);
+                    self.merge_pull_request(pull_request, label, &release_branch_prefix, &commits);
+                    self.revert_pull_request(pull_request, label, &commits);
+                }
+            } else if verb == Some("merged".to_string()) {
+                self.merge_pull_request_all_labels(pull_request, &release_branch_prefix, &commits);
+            }
+        }
+
+        (StatusCode::OK, "pr".into())
+    }
+
+    fn apply_path_labels(&self, pull_request: &github::PullRequest) {
+        let rules = self.config.repos().path_labels(&self.data.repository);
+        if let Err(e) =
+            path_labels::apply_path_labels(self.github_session.deref(), &self.data.repository, pull_request, &rules)
+        {
+            error!("Error applying path labels to PR #{}: {}", pull_request.number, e);
+        }
+    }
+
+    fn apply_size_label(&self, pull_request: &github::PullRequest) {
+        if let Some(config) = self.config.repos().size_label_config(&self.data.repository) {
+            if let Err(e) =
+                size_labels::apply_size_label(self.github_session.deref(), &self.data.repository, pull_request, &config)
+            {
+                error!("Error applying size label to PR #{}: {}", pull_request.number, e);
+            }
+        }
+    }
+
+    fn forward_images(&self, pull_request: &github::PullRequest, channels: &Vec<String>) {
+        if !self.config.slack_forward_images() || channels.is_empty() {
+            return;
+        }
+        let req = pr_images::req(&self.data.repository, pull_request, channels.clone());
+        if !req.urls.is_empty() {
+            self.messenger.note(format!("Forwarding {} images to slack", req.urls.len()));
+            self.pr_images.send(req);
+        }
+    }
+
+    fn diff_preview(&self, pull_request: &github::PullRequest) -> Option<String> {
+        let max_lines = self.config.slack_diff_preview_lines();
+        if max_lines == 0 {
+            return None;
+        }
+        match self.github_session.get_pull_request_files(
+            &self.data.repository.owner.login(),
+            &self.data.repository.name,
+            pull_request.number,
+        ) {
+            Ok(files) => diff_preview::preview(&files, max_lines),
+            Err(e) => {
+                error!("Error getting files of PR #{} for a diff preview: {}", pull_request.number, e);
+                None
+            }
+        }
+    }
+
+    fn check_code_freeze(&self, pull_request: &github::PullRequest) {
+        if let Err(e) = freeze::check_pull_request(
+            self.github_session.deref(),
+            &self.config.code_freezes,
+            &self.data.repository,
+            pull_request,
+            db::now(),
+        ) {
+            error!("Error checking PR #{} for a code freeze: {}", pull_request.number, e);
+        }
+    }
+
+    // Release PRs of regulated repos need an approved change request before they merge
+    fn check_change_request(&self, pull_request: &github::PullRequest) {
+        let servicenow_session = match self.servicenow_session {
+            Some(ref s) => s,
+            None => return,
+        };
+        let repo = &self.data.repository;
+        let release_branch_prefix = self.config.repos().release_branch_prefix(repo);
+        if !self.config.requires_change_request(&repo.full_name)
+            || !servicenow::is_release_pull_request(pull_request, &release_branch_prefix)
+        {
+            return;
+        }
+
+        let projects = self.config.repos().jira_projects(repo, &pull_request.base.ref_name);
+        let jira_keys = jira::workflow::get_all_jira_keys(&self.pull_request_commits(&pull_request), &projects);
+        if let Err(e) = servicenow::open_change_request(
+            servicenow_session.deref(),
+            &self.config,
+            self.github_session.deref(),
+            self.jira_session.as_ref().map(|j| j.deref()),
+            repo,
+            pull_request,
+            &jira_keys,
+            db::now(),
+        ) {
+            error!("Error opening a change request for PR #{}: {}", pull_request.number, e);
+        }
+    }
+
+    // A release PR closed without merging no longer holds back tags
+    fn forget_change_request(&self, pull_request: &github::PullRequest) {
+        if self.servicenow_session.is_none() {
+            return;
+        }
+        if let Err(e) = self.config.change_requests.remove(&self.data.repository.full_name, pull_request.number) {
+            error!("Error forgetting the change request of PR #{}: {}", pull_request.number, e);
+        }
+    }
+
+    // Release PRs of repos whose changes need ITSM approval wait for their service desk request to be approved
+    fn check_jsm_approval(&self, pull_request: &github::PullRequest) {
+        let jsm_session = match self.jsm_session {
+            Some(ref s) => s,
+            None => return,
+        };
+        let repo = &self.data.repository;
+        let release_branch_prefix = self.config.repos().release_branch_prefix(repo);
+        if !self.config.requires_jsm_approval(&repo.full_name)
+            || !servicenow::is_release_pull_request(pull_request, &release_branch_prefix)
+        {
+            return;
+        }
 
-                let attachments = vec![
-                    SlackAttachmentBuilder::new("")
-                        .title(format!("Pull Request #{}: \"{}\"", pull_request.number, pull_request.title.as_str()))
-                        .title_link(pull_request.html_url.as_str())
-                        .build(),
-                ];
+        let projects = self.config.repos().jira_projects(repo, &pull_request.base.ref_name);
+        let jira_keys = jira::workflow::get_all_jira_keys(&self.pull_request_commits(&pull_request), &projects);
+        if let Err(e) = jsm::open_approval_request(
+            jsm_session.deref(),
+            &self.config,
+            self.github_session.deref(),
+            self.jira_session.as_ref().map(|j| j.deref()),
+            repo,
+            pull_request,
+            &jira_keys,
+            db::now(),
+        ) {
+            error!("Error opening an approval request for PR #{}: {}", pull_request.number, e);
+        }
+    }
 
-                if !pull_request.is_draft() {
-                    let msg = format!("Pull Request {}", verb);
+    fn forget_jsm_approval(&self, pull_request: &github::PullRequest) {
+        if self.jsm_session.is_none() {
+            return;
+        }
+        if let Err(e) = self.config.jsm_approvals.remove(&self.data.repository.full_name, pull_request.number) {
+            error!("Error forgetting the approval request of PR #{}: {}", pull_request.number, e);
+        }
+    }
 
-                    match notify_mode {
-                    NotifyMode::NotifyChannel =>
-                        self.messenger.send_to_channel(&msg, &attachments, &self.data.repository, &branch_name, &commits),
+    // Sets the `octobot/gate` status of repos with merge gating rules
+    fn check_merge_gate(&self, pull_request: &github::PullRequest) {
+        if pull_request.state != "open" {
+            return;
+        }
+        if let Some(rules) = self.config.repos().merge_gate_rules(&self.data.repository) {
+            if let Err(e) = merge_gate::check_pull_request(
+                self.github_session.deref(),
+                &self.team_members,
+                &self.data.repository,
+                pull_request,
+                &rules,
+            ) {
+                error!("Error checking the merge gate of PR #{}: {}", pull_request.number, e);
+            }
+        }
+    }
 
-                    NotifyMode::NotifyAll =>
-                        self.messenger.send_to_all(
-                            &msg,
-                            &attachments,
-                            &pull_request.user,
-                            &self.data.sender,
-                            &self.data.repository,
-                            &self.all_participants(&pull_request, &commits),
-                            &branch_name,
-                            &commits,
-                        ),
+    // Fires the repo's label rules for the label just added to an open PR. Firings are recorded in the event history.
+    fn apply_label_rules(
+        &self,
+        pull_request: &github::PullRequest,
+        label: &github::Label,
+        commits: &Vec<github::Commit>,
+    ) {
+        if pull_request.state != "open" {
+            return;
+        }
+        let rules = self.config.repos().label_rules(&self.data.repository);
+        let owner = self.data.repository.owner.login();
+        let repo = &self.data.repository.name;
+        let number = pull_request.number;
+
+        for rule in label_rules::fired(&rules, &label.name) {
+            let actions = label_rules::actions(rule).join(", ");
+            self.messenger.note(format!("Label rule '{}' fired: {}", rule.label, actions));
+
+            let team = rule.review_team.trim();
+            if !team.is_empty() {
+                if let Err(e) = self.github_session.request_team_review(owner, repo, number, vec![team.to_string()]) {
+                    error!("Error requesting review of PR #{} from team {}: {}", number, team, e);
+                    self.messenger.note(format!("Could not request review from team '{}'", team));
+                }
+            }
 
-                    NotifyMode::NotifyNone => (),
-                    };
+            let channels = rule.channel_list();
+            if !channels.is_empty() {
+                let msg = format!("Pull Request labeled {} by {}", label.name, self.slack_user_name(&self.data.sender));
+                let attachments = vec![SlackAttachmentBuilder::new("")
+                    .title(format!("Pull Request #{}: \"{}\"", number, pull_request.title.as_str()))
+                    .title_link(pull_request.html_url.as_str())
+                    .build()];
+                for channel in &channels {
+                    self.messenger.send_to_rule_channel(channel, &msg, &attachments, &self.data.repository);
                 }
+            }
 
-                let jira_projects = self.config.repos().jira_projects(&self.data.repository, branch_name);
+            let transitions = rule.jira_transitions();
+            if !transitions.is_empty() {
+                match self.jira_session {
+                    Some(ref jira_session) => {
+                        let branch = &pull_request.base.ref_name;
+                        let projects = self.config.repos().jira_projects(&self.data.repository, branch);
+                        let moved = jira::workflow::transition_referenced(
+                            pull_request,
+                            commits,
+                            &projects,
+                            &transitions,
+                            jira_session.deref(),
+                        );
+                        if moved.is_empty() {
+                            self.messenger.note("No JIRA issues were moved");
+                        } else {
+                            self.messenger.note(format!("Moved JIRA issues {}", moved.join(", ")));
+                        }
+                    }
+                    None => self.messenger.note("Not moving JIRA issues: JIRA is not configured"),
+                };
+            }
 
-                let is_pull_request_ready = self.action == "opened" || self.action == "ready_for_review";
+            if rule.merge {
+                self.merge_when_green(pull_request, &rule.label);
+            }
+        }
+    }
 
-                // Mark JIRAs in review for PR open
-                if is_pull_request_ready {
-                    if let Some(ref jira_config) = self.config.jira {
-                        if let Some(ref jira_session) = self.jira_session {
-                            if commits.len() > MAX_COMMITS_FOR_JIRA_CONSIDERATION {
-                                let msg = format!(
-                                    "Too many commits on Pull Request #{}. Ignoring JIRAs.",
-                                    pull_request.number
-                                );
-                                self.messenger.send_to_owner(
-                                    &msg,
-                                    &attachments,
-                                    &pull_request.user,
-                                    &self.data.repository,
-                                    &branch_name,
-                                    &commits,
-                                );
+    // Pushing to a PR that keeps a merge rule's label merges the new commits instead, and removing the label stops
+    // the merge
+    fn update_label_merge(&self, pull_request: &github::PullRequest) {
+        if pull_request.state != "open" {
+            return;
+        }
+        let rules = self.config.repos().label_rules(&self.data.repository);
+        if !label_rules::has_merge_rules(&rules) {
+            return;
+        }
+        let removed = match self.data.label {
+            Some(ref label) => label.name.clone(),
+            None => String::new(),
+        };
+        if self.action == "unlabeled" && label_rules::merge_rule(&rules, &vec![removed.clone()]).is_none() {
+            return;
+        }
 
-                            } else {
-                                jira::workflow::submit_for_review(
-                                    &pull_request,
-                                    &commits,
-                                    &jira_projects,
-                                    jira_session.deref(),
-                                    jira_config,
-                                );
-                            }
-                        }
-                    }
+        let owner = self.data.repository.owner.login();
+        let repo = &self.data.repository.name;
+        let labels: Vec<String> = match self.github_session.get_pull_request_labels(owner, repo, pull_request.number) {
+            Ok(labels) => labels.into_iter().map(|l| l.name).collect(),
+            Err(e) => {
+                error!("Error getting labels of PR #{} for label rules: {}", pull_request.number, e);
+                return;
+            }
+        };
+        match label_rules::merge_rule(&rules, &labels) {
+            Some(rule) => {
+                if self.action == "synchronize" {
+                    self.merge_when_green(pull_request, &rule.label);
                 }
-
-                // Check for jira reference on ready for review and PR title rename
-                // (since JIRA check ignore is based on PR title)
-                if is_pull_request_ready || self.action == "edited" {
-                    // Mark if no JIRA references
-                    jira::check_jira_refs(
-                        &pull_request,
-                        &commits,
-                        &jira_projects,
-                        self.github_session.deref(),
-                    );
+            }
+            None => {
+                if self.action == "unlabeled" {
+                    match self.config.auto_merges.remove(&self.data.repository.full_name, pull_request.number) {
+                        Ok(()) => self.messenger.note(format!("Not merging: label '{}' was removed", removed)),
+                        Err(e) => error!("{}", e),
+                    };
                 }
             }
+        };
+    }
 
-            let release_branch_prefix = self.config.repos().release_branch_prefix(&self.data.repository);
-            if self.action == "labeled" {
-                if let Some(ref label) = self.data.label {
-                    self.merge_pull_request(pull_request, label, &release_branch_prefix, &commits);
-                }
-            } else if verb == Some("merged".to_string()) {
-                self.merge_pull_request_all_labels(pull_request, &release_branch_prefix, &commits);
+    // The auto-merger merges the PR once github says it is ready: all its required checks passed and it is approved
+    fn merge_when_green(&self, pull_request: &github::PullRequest, label: &str) {
+        let merge = AutoMerge {
+            repo: self.data.repository.full_name.clone(),
+            number: pull_request.number,
+            head_sha: pull_request.head.sha.clone(),
+            requested_by: self.data.sender.login().to_string(),
+        };
+        match self.config.auto_merges.add(&merge) {
+            Ok(()) => self.messenger.note(format!(
+                "Merging {} once all checks pass, for label '{}'",
+                github::Commit::short_hash_str(&merge.head_sha),
+                label
+            )),
+            Err(e) => error!("{}", e),
+        };
+    }
+
+    fn lint_pull_request(&self, pull_request: &github::PullRequest) {
+        if let Some(rules) = self.config.repos().lint_rules(&self.data.repository) {
+            if let Err(e) =
+                commit_lint::lint_pull_request(self.github_session.deref(), &self.data.repository, pull_request, &rules)
+            {
+                error!("Error linting PR #{}: {}", pull_request.number, e);
             }
         }
-
-        (StatusCode::OK, "pr".into())
     }
 
     fn handle_pr_review_comment(&self) -> EventResponse {
@@ -500,6 +1911,9 @@ impl GithubEventHandler {
 
     fn handle_pr_review(&self) -> EventResponse {
         if let Some(ref pull_request) = self.data.pull_request {
+            if self.action == "submitted" || self.action == "dismissed" {
+                self.check_merge_gate(pull_request);
+            }
             if let Some(ref review) = self.data.review {
                 if self.action == "submitted" {
 
@@ -526,14 +1940,27 @@ impl GithubEventHandler {
                         color = "good";
 
                     } else {
+                        self.messenger.note(format!("Reviews in state '{}' do not send notifications", review.state));
                         return (StatusCode::OK, "pr_review [ignored]".into());
                     }
 
-                    let msg = format!(
-                        "{} {} PR \"{}\"",
-                        self.slack_user_name(&review.user),
-                        action_msg,
-                        util::make_link(pull_request.html_url.as_str(), pull_request.title.as_str())
+                    let msg = templates::render_or(
+                        &self.config.slack_templates().review,
+                        &templates::with_vars(
+                            templates::pr_vars(&pull_request, branch_name, &self.data.repository.full_name, &commits),
+                            json!({
+                                "pr_link": self.pr_link(&pull_request),
+                                "reviewer": self.slack_user_name(&review.user),
+                                "action": action_msg,
+                                "state": state_msg,
+                            }),
+                        ),
+                        format!(
+                            "{} {} PR \"{}\"",
+                            self.slack_user_name(&review.user),
+                            action_msg,
+                            util::make_link(pull_request.html_url.as_str(), pull_request.title.as_str())
+                        ),
                     );
 
                     let attachments = vec![
@@ -544,12 +1971,8 @@ impl GithubEventHandler {
                             .build(),
                     ];
 
-                    let mut participants = self.all_participants(&pull_request, &commits);
-                    for username in util::get_mentioned_usernames(review.body()) {
-                        participants.push(github::User::new(username))
-                    }
-
-                    self.messenger.send_to_all(
+                    let participants = self.all_participants(&pull_request, &commits);
+                    self.pr_messenger(&pull_request).send_to_all(
                         &msg,
                         &attachments,
                         &pull_request.user,
@@ -559,6 +1982,15 @@ impl GithubEventHandler {
                         branch_name,
                         &commits,
                     );
+
+                    self.notify_mentions(
+                        &pull_request,
+                        &mentions::parse(review.body()),
+                        review.body(),
+                        &review.html_url,
+                        "a review of",
+                        &participants,
+                    );
                 }
             }
         }
@@ -566,17 +1998,41 @@ impl GithubEventHandler {
         (StatusCode::OK, "pr_review".into())
     }
 
+    // Resolving a review thread only matters to the merge gate
+    fn handle_pr_review_thread(&self) -> EventResponse {
+        if let Some(ref pull_request) = self.data.pull_request {
+            if self.action == "resolved" || self.action == "unresolved" {
+                self.check_merge_gate(pull_request);
+            }
+        }
+
+        (StatusCode::OK, "pr_review_thread".into())
+    }
+
     fn do_pull_request_comment(&self, pull_request: &dyn github::PullRequestLike, comment: &dyn github::CommentLike, branch_name: &str, commits: &Vec<github::Commit>) {
         if comment.body().trim().len() == 0 {
+            self.messenger.note("Comment is empty");
             return;
         }
 
         if comment.user().login() == self.github_session.bot_name() {
             info!("Ignoring message from octobot ({}): {}", self.github_session.bot_name(), comment.body());
+            self.messenger.note("Comment was made by octobot");
             return;
         }
 
-        let msg = format!("Comment on \"{}\"", util::make_link(pull_request.html_url(), pull_request.title()));
+        let msg = templates::render_or(
+            &self.config.slack_templates().comment,
+            &templates::with_vars(
+                templates::pr_vars(pull_request, branch_name, &self.data.repository.full_name, commits),
+                json!({
+                    "pr_link": self.pr_link(pull_request),
+                    "commenter": self.slack_user_name(comment.user()),
+                    "comment": comment.body().trim(),
+                }),
+            ),
+            format!("Comment on \"{}\"", util::make_link(pull_request.html_url(), pull_request.title())),
+        );
 
         let attachments = vec![
             SlackAttachmentBuilder::new(comment.body().trim())
@@ -585,12 +2041,8 @@ impl GithubEventHandler {
                 .build(),
         ];
 
-        let mut participants = self.all_participants(pull_request, &commits);
-        for username in util::get_mentioned_usernames(comment.body()) {
-            participants.push(github::User::new(username))
-        }
-
-        self.messenger.send_to_all(
+        let participants = self.all_participants(pull_request, &commits);
+        self.pr_messenger(pull_request).send_to_all(
             &msg,
             &attachments,
             pull_request.user(),
@@ -601,6 +2053,69 @@ impl GithubEventHandler {
             &commits,
         );
 
+        self.notify_mentions(
+            pull_request,
+            &mentions::parse(comment.body()),
+            comment.body(),
+            comment.html_url(),
+            "a comment on",
+            &participants,
+        );
+
+        if self.data.pull_request.is_some() {
+            self.suggest_huddle(pull_request, comment, branch_name, commits);
+        }
+    }
+
+    // A review that keeps going back and forth is often quicker to settle by talking it through
+    fn suggest_huddle(
+        &self,
+        pull_request: &dyn github::PullRequestLike,
+        comment: &dyn github::CommentLike,
+        branch_name: &str,
+        commits: &Vec<github::Commit>,
+    ) {
+        let threshold = match self.config.repos().huddle_comments(&self.data.repository) {
+            Some(t) => t,
+            None => return,
+        };
+
+        let repo = &self.data.repository.full_name;
+        let number = pull_request.number();
+        let commenters = match self.config.review_discussions.add_comment(repo, number, comment.user().login()) {
+            Ok(c) => c,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+        let back_and_forth = huddles::back_and_forth(&commenters);
+        if back_and_forth < threshold {
+            return;
+        }
+        match self.config.review_discussions.mark_suggested(repo, number) {
+            Ok(true) => (),
+            Ok(false) => return,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+
+        let mut logins = vec![pull_request.user().login().to_string()];
+        logins.extend(commenters);
+        let mentions = huddles::participants(&logins)
+            .into_iter()
+            .map(|l| self.config.users().slack_user_name(&l).map(|s| users::mention(&s)).unwrap_or(l))
+            .collect();
+
+        self.pr_messenger(pull_request).send_to_channel(
+            &huddles::suggestion(back_and_forth, &mentions),
+            &vec![],
+            &self.data.repository,
+            branch_name,
+            commits,
+        );
     }
 
     fn handle_commit_comment(&self) -> EventResponse {
@@ -670,14 +2185,25 @@ impl GithubEventHandler {
     }
 
     fn handle_push(&self) -> EventResponse {
+        if tag_protection::tag_name(self.data.ref_name()).is_some() {
+            return self.handle_tag_push();
+        }
+        if self.data.created() {
+            self.version_release();
+        }
         if self.data.deleted() || self.data.created() {
             // ignore
+            self.messenger.note("Branch creation and deletion pushes do not send notifications");
             return (StatusCode::OK, "push [ignored]".into());
         }
         if self.data.ref_name().len() > 0 && self.data.after().len() > 0 && self.data.before().len() > 0 {
 
             let branch_name = self.data.ref_name().replace("refs/heads/", "");
 
+            if github::is_main_branch(&branch_name) {
+                self.refresh_repo_file(&branch_name);
+            }
+
             let release_branch_prefix = self.config.repos().release_branch_prefix(&self.data.repository);
             let is_versioned_branch = github::is_main_branch(&branch_name) || branch_name.starts_with(&release_branch_prefix);
 
@@ -730,10 +2256,17 @@ impl GithubEventHandler {
                     for pull_request in &prs {
                         if pull_request.is_draft() {
                             info!("Skipping WIP PR #{}", pull_request.number);
+                            self.messenger.note(format!("Pull request #{} is a draft", pull_request.number));
                             continue;
                         }
 
-                        let mut attachments = attachments.clone();
+                        let (message, mut attachments) = match self.force_push_summary(pull_request) {
+                            Some(summary) => (
+                                force_push::message(&summary, &self.slack_user_name(&self.data.sender), &branch_name),
+                                force_push::attachments(&summary),
+                            ),
+                            None => (message.clone(), attachments.clone()),
+                        };
                         attachments
                             .insert(
                                 0,
@@ -749,14 +2282,14 @@ impl GithubEventHandler {
 
                         let commits = self.pull_request_commits(&pull_request);
 
-                        self.messenger.send_to_all(
+                        self.pr_messenger(&pull_request).send_to_all(
                             &message,
                             &attachments,
                             &pull_request.user,
                             &self.data.sender,
                             &self.data.repository,
                             &self.all_participants(&pull_request, &commits),
-                            &branch_name,
+                            &pull_request.base.ref_name,
                             &commits,
                         );
 
@@ -774,21 +2307,23 @@ impl GithubEventHandler {
                         let jira_projects = self.config.repos().jira_projects(&self.data.repository, &pull_request.base.ref_name);
 
                         // Mark if no JIRA references
-                        jira::check_jira_refs(
-                            &pull_request,
-                            &commits,
-                            &jira_projects,
-                            self.github_session.deref(),
-                        );
+                        self.check_jira_refs(&pull_request, &commits, &jira_projects);
                     }
                 }
             }
 
+            self.apply_smart_commits();
+
+            if compliance::is_protected_branch(&branch_name, &release_branch_prefix) {
+                self.index_pushed_commits(&branch_name);
+            }
+
             // Note: check for jira projects on the branch being pushed to
             let has_jira_projects = !self.config.repos().jira_projects(&self.data.repository, &branch_name).is_empty();
+            let has_components = !self.config.repos().components(&self.data.repository).is_empty();
 
-            // Mark JIRAs as merged
-            if is_versioned_branch && has_jira_projects {
+            // Mark JIRAs as merged, and version monorepo components
+            if is_versioned_branch && (has_jira_projects || has_components) {
                 if let Some(ref commits) = self.data.commits {
                     let msg = repo_version::req(
                         &self.data.repository,
@@ -804,6 +2339,195 @@ impl GithubEventHandler {
         (StatusCode::OK, "push".into())
     }
 
+    // Picks up changes to the repo's .octobot.toml, or fetches it the first time the repo is pushed to
+    fn refresh_repo_file(&self, branch: &str) {
+        let repo = &self.data.repository;
+        if !self.config.repos().is_configured(repo) {
+            return;
+        }
+        let touched = self
+            .data
+            .commits
+            .iter()
+            .flatten()
+            .any(|c| c.changed_files().any(|f| f == repo_files::FILE_NAME));
+        match self.config.repo_files.get(&repo.full_name) {
+            Ok(Some(_)) if !touched => return,
+            Ok(_) => (),
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+
+        let file = match repo_files::refresh(
+            self.github_session.as_ref(),
+            &self.config.repo_files,
+            repo,
+            branch,
+            db::now(),
+        ) {
+            Ok(f) => f,
+            Err(e) => {
+                error!("Error refreshing {} of {}: {}", repo_files::FILE_NAME, repo.full_name, e);
+                return;
+            }
+        };
+
+        if touched && !file.error.is_empty() {
+            let msg = format!("Ignoring {} of {}: {}", repo_files::FILE_NAME, repo.full_name, file.error);
+            let commits = self.data.commits.clone().unwrap_or(vec![]);
+            self.messenger.send_to_channel(&msg, &vec![], repo, branch, &commits);
+        }
+    }
+
+    fn apply_smart_commits(&self) {
+        let jira_session = match self.jira_session {
+            Some(ref j) => j,
+            None => return,
+        };
+        let allowed = match self.config.repos().smart_commit_commands(&self.data.repository) {
+            Some(a) => a,
+            None => return,
+        };
+        let commits = match self.data.commits {
+            Some(ref c) => c,
+            None => return,
+        };
+
+        let projects = self.config.repos().all_jira_projects(&self.data.repository);
+        for commit in commits {
+            match self.config.smart_commits.mark_applied(&self.data.repository.full_name, &commit.id) {
+                Ok(true) => jira::smart_commits::apply(
+                    commit,
+                    self.data.sender.login(),
+                    &projects,
+                    &allowed,
+                    jira_session.deref(),
+                ),
+                Ok(false) => info!("Smart commit commands of {} were already applied", commit.id),
+                Err(e) => error!("{}", e),
+            };
+        }
+    }
+
+    // New release branches and version tags get a JIRA version for the issues they release
+    fn version_release(&self) {
+        if self.jira_session.is_none() || !self.config.repos().jira_release_versions(&self.data.repository) {
+            return;
+        }
+        let release_branch_prefix = self.config.repos().release_branch_prefix(&self.data.repository);
+        if release_versions::release_version(self.data.ref_name(), &release_branch_prefix).is_some() {
+            let msg = release_versions::req(&self.data.repository, self.data.ref_name(), self.data.after());
+            self.release_versions.send(msg);
+        }
+    }
+
+    // Regulated repos can't be tagged while one of their change requests isn't approved: the tag is deleted
+    fn block_unapproved_tag(&self) -> bool {
+        let repo = &self.data.repository;
+        if self.servicenow_session.is_none() || !self.config.requires_change_request(&repo.full_name) {
+            return false;
+        }
+        let change = match self.config.change_requests.blocking_tags(&repo.full_name) {
+            Ok(Some(c)) => c,
+            Ok(None) => return false,
+            Err(e) => {
+                error!("Error looking up change requests of {}: {}", repo.full_name, e);
+                return false;
+            }
+        };
+
+        let tag = tag_protection::tag_name(self.data.ref_name()).unwrap_or("");
+        if let Err(e) = self.config.change_requests.block_tag(&repo.full_name, tag, &change.change_number, db::now()) {
+            error!("Error blocking tag {} of {}: {}", tag, repo.full_name, e);
+        }
+
+        let msg = format!(
+            "Deleting tag {} pushed by {}: change request {} of PR #{} is not approved",
+            tag,
+            self.data.sender.login(),
+            change.change_number,
+            change.number
+        );
+        let commits: Vec<github::PushCommit> = vec![];
+        self.messenger.send_to_channel(&msg, &vec![], repo, &change.branch, &commits);
+        self.tag_restore.send(tag_protection::delete_req(repo, tag));
+        true
+    }
+
+    fn was_blocked_tag(&self) -> bool {
+        let tag = tag_protection::tag_name(self.data.ref_name()).unwrap_or("");
+        match self.config.change_requests.take_blocked_tag(&self.data.repository.full_name, tag) {
+            Ok(blocked) => blocked,
+            Err(e) => {
+                error!("Error looking up blocked tag {}: {}", tag, e);
+                false
+            }
+        }
+    }
+
+    // A deleted or moved release tag is a supply-chain red flag: alert security and optionally put it back
+    fn handle_tag_push(&self) -> EventResponse {
+        if !tag_protection::is_tag_tampered(&self.data) {
+            if self.data.created() {
+                if self.block_unapproved_tag() {
+                    return (StatusCode::OK, "push [tag blocked]".into());
+                }
+                self.version_release();
+            }
+            self.messenger.note("Tag creation pushes do not send notifications");
+            return (StatusCode::OK, "push [ignored]".into());
+        }
+
+        if self.data.deleted() && self.was_blocked_tag() {
+            self.messenger.note("Tag was deleted by octobot: its change request is not approved");
+            return (StatusCode::OK, "push [ignored]".into());
+        }
+
+        let msg = tag_protection::alert_message(&self.data);
+        let attachments = vec![tag_protection::alert_attachment(&self.data)];
+        self.messenger.send_to_security_channel(&msg, &attachments, &self.data.repository);
+
+        if self.config.restore_tags() {
+            let tag = tag_protection::tag_name(self.data.ref_name()).unwrap_or("");
+            self.tag_restore.send(tag_protection::req(&self.data.repository, tag, self.data.before()));
+        }
+
+        (StatusCode::OK, "push [tag]".into())
+    }
+
+    fn mark_jiras_merged(&self, pull_request: &github::PullRequest, commits: &Vec<github::Commit>, projects: &Vec<String>) {
+        if commits.len() > MAX_COMMITS_FOR_JIRA_CONSIDERATION {
+            return;
+        }
+        if let (Some(ref jira_config), Some(ref jira_session)) = (&self.config.jira, &self.jira_session) {
+            jira::workflow::mark_merged(pull_request, commits, projects, jira_session.deref(), jira_config);
+        }
+    }
+
+    // Dependency updates that only change lockfiles don't need a JIRA reference
+    fn check_jira_refs(&self, pull_request: &github::PullRequest, commits: &Vec<github::Commit>, projects: &Vec<String>) {
+        let lockfiles = self.config.repos().lockfiles(&self.data.repository);
+        if !projects.is_empty() && !lockfiles.is_empty() {
+            match self.github_session.get_pull_request_files(
+                &self.data.repository.owner.login(),
+                &self.data.repository.name,
+                pull_request.number,
+            ) {
+                Ok(ref files) if ecosystem::is_lockfile_only(files, &lockfiles) => {
+                    let reason = "Skipped JIRA check for dependency update: only lockfiles changed";
+                    jira::skip_jira_refs(pull_request, projects, reason, self.github_session.deref());
+                    return;
+                }
+                Ok(_) => (),
+                Err(e) => error!("Error getting pull request files: {}", e),
+            };
+        }
+
+        jira::check_jira_refs(pull_request, commits, projects, self.github_session.deref());
+    }
+
     fn merge_pull_request_all_labels(&self, pull_request: &github::PullRequest, release_branch_prefix: &str, commits: &Vec<github::Commit>) {
         if !pull_request.is_merged() {
             return;
@@ -818,7 +2542,7 @@ impl GithubEventHandler {
         ) {
             Ok(l) => l,
             Err(e) => {
-                self.messenger.send_to_owner(
+                self.pr_messenger(&pull_request).send_to_owner(
                     "Error getting Pull Request labels",
                     &vec![SlackAttachmentBuilder::new(&format!("{}", e)).color("danger").build()],
                     &pull_request.user,
@@ -830,6 +2554,14 @@ impl GithubEventHandler {
             }
         };
 
+        if dependencies::is_breaking(&labels) {
+            let msg = format!(
+                "Breaking change merged into upstream repo {}",
+                self.data.repository.full_name
+            );
+            self.notify_downstream(&msg, &vec![dependencies::breaking_change_attachment(pull_request)]);
+        }
+
         for label in &labels {
             self.merge_pull_request(pull_request, label, release_branch_prefix, &commits);
         }
@@ -857,7 +2589,23 @@ impl GithubEventHandler {
             release_branch_prefix.to_string() + &backport
         };
 
-        let req = pr_merge::req(&self.data.repository, pull_request, &target_branch, release_branch_prefix, commits.clone());
+        let mut req = pr_merge::req(&self.data.repository, pull_request, &target_branch, release_branch_prefix, commits.clone());
+        req.merge_strategy = self.config.repos().merge_strategy(&self.data.repository, &target_branch);
         self.pr_merge.send(req);
     }
+
+    // Only labeling an already merged PR reverts it: a label added before the merge is a mistake, not a request
+    fn revert_pull_request(
+        &self,
+        pull_request: &github::PullRequest,
+        label: &github::Label,
+        commits: &Vec<github::Commit>,
+    ) {
+        if !pull_request.is_merged() || !pr_revert::is_revert_label(&label.name) {
+            return;
+        }
+
+        let req = pr_revert::req(&self.data.repository, pull_request, self.data.sender.login(), commits.clone());
+        self.pr_revert.send(req);
+    }
 }
@@ -1,13 +1,51 @@
 use hyper::HeaderMap;
-use log::{debug, error};
+use log::{debug, error, warn};
 use ring::{digest, hmac};
 use rustc_serialize::hex::FromHex;
+use serde_derive::Deserialize;
+
+use crate::config::Config;
+use crate::github;
 
 pub struct GithubWebhookVerifier {
     pub secret: String,
+    // also accepted while the secret is being rotated
+    pub previous_secret: Option<String>,
+}
+
+// Just enough of a delivery to know which repo sent it.
+#[derive(Deserialize)]
+struct DeliveryRepo {
+    repository: Option<github::Repo>,
 }
 
 impl GithubWebhookVerifier {
+    pub fn new(secret: &str) -> GithubWebhookVerifier {
+        GithubWebhookVerifier {
+            secret: secret.into(),
+            previous_secret: None,
+        }
+    }
+
+    // Picks the secret configured for the repo (or org) the delivery is for, falling back to the secret of the
+    // github host that has the repo's owner.
+    // Note: the body isn't trusted yet: it's only used to decide which secret to verify it with. Going by the owner,
+    // like API calls do, means one host's secret can't sign deliveries for another host's repos.
+    pub fn for_delivery(config: &Config, data: &[u8]) -> GithubWebhookVerifier {
+        let repo = serde_json::from_slice::<DeliveryRepo>(data).ok().and_then(|d| d.repository);
+        if let Some(secret) = repo.as_ref().and_then(|r| config.repos().webhook_secret(r)) {
+            return GithubWebhookVerifier::new(&secret);
+        }
+        let github = match repo {
+            Some(ref r) => config.github_for_owner(r.owner.login()),
+            None => &config.github,
+        };
+        GithubWebhookVerifier {
+            secret: github.webhook_secret.clone(),
+            previous_secret: github.previous_webhook_secret.clone().filter(|s| !s.is_empty()),
+        }
+    }
+
     pub fn is_req_valid(&self, headers: &HeaderMap, data: &[u8]) -> bool {
         let values = headers.get_all("x-hub-signature").iter().collect::<Vec<_>>();
 
@@ -46,19 +84,34 @@ impl GithubWebhookVerifier {
                 debug!("Signature verified!");
                 true
             }
-            Err(e) => {
-                error!("Signature verify failed: {}", e);
-                false
-            }
+            Err(e) => match self.previous_secret {
+                Some(ref previous) if is_signed_with(previous, data, &sig_bytes) => {
+                    warn!("Signature verified with the previous webhook secret");
+                    true
+                }
+                _ => {
+                    error!("Signature verify failed: {}", e);
+                    false
+                }
+            },
         }
     }
 }
 
+fn is_signed_with(secret: &str, data: &[u8], sig_bytes: &[u8]) -> bool {
+    let key = hmac::VerificationKey::new(&digest::SHA1, secret.as_bytes());
+    hmac::verify(&key, data, sig_bytes).is_ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use ring::{digest, hmac};
     use rustc_serialize::hex::ToHex;
+    use tempdir::TempDir;
+
+    use crate::db::Database;
+    use crate::repos::RepoInfo;
 
     #[test]
     fn verify_sig_valid() {
@@ -69,7 +122,7 @@ mod tests {
         let signature = hmac::sign(&key, msg.as_bytes());
         let signature_hex = "sha1=".to_string() + signature.as_ref().to_hex().as_str();
 
-        let verifier = GithubWebhookVerifier { secret: key_value.clone() };
+        let verifier = GithubWebhookVerifier::new(&key_value);
 
         assert!(verifier.is_valid(msg.as_bytes(), &signature_hex));
     }
@@ -83,7 +136,7 @@ mod tests {
         let signature = hmac::sign(&key, msg.as_bytes());
         let signature_hex = "sha9=".to_string() + signature.as_ref().to_hex().as_str();
 
-        let verifier = GithubWebhookVerifier { secret: key_value.clone() };
+        let verifier = GithubWebhookVerifier::new(&key_value);
 
         assert!(!verifier.is_valid(msg.as_bytes(), &signature_hex));
     }
@@ -97,8 +150,75 @@ mod tests {
         let signature = hmac::sign(&key, msg.as_bytes());
         let signature_hex = signature.as_ref().to_hex();
 
-        let verifier = GithubWebhookVerifier { secret: key_value.clone() };
+        let verifier = GithubWebhookVerifier::new(&key_value);
 
         assert!(!verifier.is_valid(msg.as_bytes(), &signature_hex));
     }
+
+    #[test]
+    fn verify_sig_previous_secret() {
+        let sign = |secret: &str, msg: &str| {
+            let key = hmac::SigningKey::new(&digest::SHA1, secret.as_bytes());
+            "sha1=".to_string() + hmac::sign(&key, msg.as_bytes()).as_ref().to_hex().as_str()
+        };
+        let msg = "a message from the githubs.";
+
+        let mut verifier = GithubWebhookVerifier::new("the new secret");
+        assert!(verifier.is_valid(msg.as_bytes(), &sign("the new secret", msg)));
+        assert!(!verifier.is_valid(msg.as_bytes(), &sign("the old secret", msg)));
+
+        verifier.previous_secret = Some("the old secret".into());
+        assert!(verifier.is_valid(msg.as_bytes(), &sign("the new secret", msg)));
+        assert!(verifier.is_valid(msg.as_bytes(), &sign("the old secret", msg)));
+        assert!(!verifier.is_valid(msg.as_bytes(), &sign("some other secret", msg)));
+    }
+
+    #[test]
+    fn verifier_for_delivery() {
+        let temp_dir = TempDir::new("github_verify.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        let mut config = Config::new(db);
+        config.github.webhook_secret = "global-secret".into();
+        config
+            .repos_write()
+            .insert_info(&RepoInfo::new("some-org", "reviews").with_webhook_secret("org-secret"))
+            .unwrap();
+
+        let delivery = |full_name: &str| {
+            let parts = full_name.split('/').collect::<Vec<_>>();
+            format!(
+                r#"{{"repository": {{"html_url": "http://git.company.com/{0}", "full_name": "{0}", "name": "{1}", "owner": {{"login": "{2}"}}}}}}"#,
+                full_name, parts[1], parts[0]
+            )
+        };
+
+        config.github.previous_webhook_secret = Some("old-global-secret".into());
+
+        let verifier = GithubWebhookVerifier::for_delivery(&config, delivery("some-org/repo").as_bytes());
+        assert_eq!("org-secret", verifier.secret);
+        assert_eq!(None, verifier.previous_secret);
+
+        let verifier = GithubWebhookVerifier::for_delivery(&config, delivery("other-org/repo").as_bytes());
+        assert_eq!("global-secret", verifier.secret);
+        assert_eq!(Some("old-global-secret".to_string()), verifier.previous_secret);
+
+        let verifier = GithubWebhookVerifier::for_delivery(&config, b"not json");
+        assert_eq!("global-secret", verifier.secret);
+
+        let mut instance = config.github.clone();
+        instance.host = "ghe.company.com".into();
+        instance.webhook_secret = "ghe-secret".into();
+        instance.previous_webhook_secret = None;
+        instance.orgs = Some(vec!["platform".into()]);
+        config.github_instances = Some(vec![instance]);
+
+        let verifier = GithubWebhookVerifier::for_delivery(&config, delivery("Platform/repo").as_bytes());
+        assert_eq!("ghe-secret", verifier.secret);
+        assert_eq!(None, verifier.previous_secret);
+
+        let verifier = GithubWebhookVerifier::for_delivery(&config, delivery("other-org/repo").as_bytes());
+        assert_eq!("global-secret", verifier.secret);
+    }
 }
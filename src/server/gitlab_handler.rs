@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::{Body, HeaderMap, Request, StatusCode};
+use log::{error, info};
+use ring::constant_time;
+
+use crate::config::Config;
+use crate::gitlab::api::GitlabSession;
+use crate::gitlab::hooks;
+use crate::jira;
+use crate::messenger;
+use crate::server::http::{FutureResponse, Handler};
+use crate::slack::SlackRequest;
+use crate::util;
+use crate::worker::Worker;
+
+// Receives GitLab webhooks (merge request, comment and pipeline events) and handles them like github's
+pub struct GitlabHandler {
+    config: Arc<Config>,
+    slack: Arc<dyn Worker<SlackRequest>>,
+    jira: Option<Arc<dyn jira::api::Session>>,
+}
+
+impl GitlabHandler {
+    pub fn new(
+        config: Arc<Config>,
+        slack: Arc<dyn Worker<SlackRequest>>,
+        jira: Option<Arc<dyn jira::api::Session>>,
+    ) -> Box<GitlabHandler> {
+        Box::new(GitlabHandler {
+            config: config,
+            slack: slack,
+            jira: jira,
+        })
+    }
+}
+
+// GitLab doesn't sign its deliveries: it sends the webhook's secret token as is
+fn is_authorized(secret: &str, headers: &HeaderMap) -> bool {
+    match headers.get("x-gitlab-token").and_then(|v| v.to_str().ok()) {
+        Some(token) => constant_time::verify_slices_are_equal(token.as_bytes(), secret.as_bytes()).is_ok(),
+        None => false,
+    }
+}
+
+impl Handler for GitlabHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let gitlab_config = match self.config.gitlab {
+            Some(ref c) => c.clone(),
+            None => return self.respond_with(StatusCode::NOT_FOUND, "GitLab is not configured"),
+        };
+        if !is_authorized(&gitlab_config.webhook_secret, req.headers()) {
+            return self.respond_with(StatusCode::FORBIDDEN, "Invalid credentials");
+        }
+
+        let config = self.config.clone();
+        let slack = self.slack.clone();
+        let jira = self.jira.clone();
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            let event = match hooks::parse(&body) {
+                Ok(e) => e,
+                Err(e) => return util::new_bad_req_resp(format!("Error parsing GitLab event: {}", e)),
+            };
+            let session = match GitlabSession::new(&gitlab_config) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Error creating GitLab session: {}", e);
+                    return util::new_msg_resp(StatusCode::INTERNAL_SERVER_ERROR, "Error creating GitLab session");
+                }
+            };
+            info!("Received GitLab event from {}", gitlab_config.url);
+
+            let messenger = messenger::new(config.clone(), slack);
+            hooks::handle(&event, &config, &session, jira.as_ref().map(|j| j.as_ref()), &messenger);
+            util::new_empty_resp(StatusCode::OK)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_is_authorized() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Gitlab-Token", HeaderValue::from_static("the-secret"));
+        assert!(is_authorized("the-secret", &headers));
+        assert!(!is_authorized("other-secret", &headers));
+        assert!(!is_authorized("the-secret", &HeaderMap::new()));
+    }
+}
@@ -0,0 +1,43 @@
+use hyper::{Body, Request, StatusCode};
+use serde_json;
+
+use crate::kubernetes;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+pub enum HealthOp {
+    // up at all: for liveness probes
+    Live,
+    // should be sent requests: for readiness probes. not once it is draining to stop.
+    Ready,
+}
+
+pub struct HealthHandler {
+    op: HealthOp,
+}
+
+impl HealthHandler {
+    pub fn new(op: HealthOp) -> Box<HealthHandler> {
+        Box::new(HealthHandler { op: op })
+    }
+}
+
+impl Handler for HealthHandler {
+    fn handle(&self, _req: Request<Body>) -> FutureResponse {
+        match self.op {
+            HealthOp::Live => self.respond(util::new_msg_resp(StatusCode::OK, "ok")),
+            HealthOp::Ready => {
+                let readiness = kubernetes::readiness();
+                let json = match serde_json::to_string(&readiness) {
+                    Ok(j) => j,
+                    Err(e) => return self.respond_error(&format!("Error serializing readiness: {}", e)),
+                };
+                let mut resp = util::new_json_resp(json);
+                if !readiness.ready {
+                    *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                }
+                self.respond(resp)
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use futures::Stream;
 use futures::future::{self, Future};
 use hyper::{self, Body, Request, Response, StatusCode};
@@ -9,6 +11,10 @@ use crate::util;
 
 pub type FutureResponse = Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send>;
 
+// The address a request's connection came from, in the request's extensions
+#[derive(Clone, Copy, Debug)]
+pub struct RemoteAddr(pub SocketAddr);
+
 pub trait Handler {
     fn handle(&self, req: Request<Body>) -> FutureResponse;
 
@@ -22,7 +28,7 @@ pub trait Handler {
 
     fn respond_error(&self, err: &str) -> FutureResponse {
         error!("InternalServerError: {}", err);
-        self.respond(util::new_empty_resp(StatusCode::INTERNAL_SERVER_ERROR))
+        self.respond(util::new_msg_resp(StatusCode::INTERNAL_SERVER_ERROR, ""))
     }
 }
 
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Future};
+use futures::Stream;
+use hyper::{self, Body, Method, Request, Response, StatusCode};
+use ring::digest;
+
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::Sessions;
+use crate::util;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+pub const IDEMPOTENT_REPLAY_HEADER: &str = "idempotent-replayed";
+
+static KEY_EXPIRY_SECS: u64 = 24 * 60 * 60;
+static PRUNE_SECS: u64 = 60;
+static MAX_KEY_LEN: usize = 255;
+// the oldest keys are forgotten first past this many
+static MAX_KEYS: usize = 10_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum KeyState {
+    // First time this key has been seen: the request should be processed
+    New,
+    // A request with this key is still being processed
+    InProgress,
+    // A request with this key has finished: replay its response
+    Done(StoredResponse),
+    // The key was used for a request with a different body
+    Mismatch,
+}
+
+struct Entry {
+    body_hash: Vec<u8>,
+    response: Option<StoredResponse>,
+    created_at: Instant,
+}
+
+impl Entry {
+    fn is_expired(&self) -> bool {
+        self.created_at.elapsed() >= Duration::from_secs(KEY_EXPIRY_SECS)
+    }
+}
+
+pub fn body_hash(body: &[u8]) -> Vec<u8> {
+    digest::digest(&digest::SHA256, body).as_ref().to_vec()
+}
+
+// Remembers the responses to mutating requests by their `Idempotency-Key` so that
+// retried requests get the original response instead of being applied twice.
+pub struct IdempotencyKeys {
+    entries: RwLock<HashMap<String, Entry>>,
+    last_pruned: RwLock<Instant>,
+}
+
+impl IdempotencyKeys {
+    pub fn new() -> IdempotencyKeys {
+        IdempotencyKeys {
+            entries: RwLock::new(HashMap::new()),
+            last_pruned: RwLock::new(Instant::now()),
+        }
+    }
+
+    pub fn begin(&self, key: &str, body_hash: &[u8]) -> KeyState {
+        self.prune();
+
+        let mut entries = self.entries.write().unwrap();
+        if let Some(entry) = entries.get(key).filter(|e| !e.is_expired()) {
+            if entry.body_hash != body_hash {
+                return KeyState::Mismatch;
+            }
+            return match entry.response {
+                Some(ref resp) => KeyState::Done(resp.clone()),
+                None => KeyState::InProgress,
+            };
+        }
+
+        if entries.len() >= MAX_KEYS && !entries.contains_key(key) {
+            entries.retain(|_, e| !e.is_expired());
+            let oldest = entries.iter().min_by_key(|(_, e)| e.created_at).map(|(k, _)| k.clone());
+            if let (true, Some(oldest)) = (entries.len() >= MAX_KEYS, oldest) {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            key.to_string(),
+            Entry {
+                body_hash: body_hash.to_vec(),
+                response: None,
+                created_at: Instant::now(),
+            },
+        );
+        KeyState::New
+    }
+
+    pub fn complete(&self, key: &str, response: StoredResponse) {
+        let mut entries = self.entries.write().unwrap();
+        if let Some(entry) = entries.get_mut(key) {
+            entry.response = Some(response);
+        }
+    }
+
+    // Forget about a key so that the request may be retried, i.e. after a server error.
+    pub fn abort(&self, key: &str) {
+        self.entries.write().unwrap().remove(key);
+    }
+
+    fn needs_prune(&self) -> bool {
+        let last_pruned = self.last_pruned.read().unwrap();
+        last_pruned.elapsed() >= Duration::from_secs(PRUNE_SECS)
+    }
+
+    fn prune(&self) {
+        if self.needs_prune() {
+            let mut last_pruned = self.last_pruned.write().unwrap();
+
+            let mut entries = self.entries.write().unwrap();
+            entries.retain(|_, e| !e.is_expired());
+
+            *last_pruned = Instant::now();
+        }
+    }
+}
+
+pub struct IdempotentHandler {
+    keys: Arc<IdempotencyKeys>,
+    sessions: Arc<Sessions>,
+    // called once the request's body is read
+    handler: Arc<dyn Handler + Send + Sync>,
+}
+
+impl IdempotentHandler {
+    pub fn new(
+        keys: Arc<IdempotencyKeys>,
+        sessions: Arc<Sessions>,
+        handler: Box<dyn Handler + Send + Sync>,
+    ) -> Box<IdempotentHandler> {
+        Box::new(IdempotentHandler {
+            keys: keys,
+            sessions: sessions,
+            handler: handler.into(),
+        })
+    }
+}
+
+fn is_mutating(method: &Method) -> bool {
+    *method == Method::POST || *method == Method::PUT || *method == Method::DELETE || *method == Method::PATCH
+}
+
+fn replay(stored: StoredResponse) -> Response<Body> {
+    let mut resp = Response::new(Body::from(stored.body));
+    *resp.status_mut() = stored.status;
+    if let Some(content_type) = stored.content_type {
+        if let Ok(value) = content_type.parse() {
+            resp.headers_mut().insert(hyper::header::CONTENT_TYPE, value);
+        }
+    }
+    resp.headers_mut().insert(IDEMPOTENT_REPLAY_HEADER, "true".parse().unwrap());
+    resp
+}
+
+impl Handler for IdempotentHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        if !is_mutating(req.method()) {
+            return self.handler.handle(req);
+        }
+
+        let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER).map(|k| k.to_str()) {
+            None => return self.handler.handle(req),
+            Some(Ok(k)) if !k.trim().is_empty() && k.len() <= MAX_KEY_LEN => k.trim().to_string(),
+            Some(_) => return self.respond(util::new_bad_req_resp("Invalid Idempotency-Key header")),
+        };
+
+        // Keys are only unique per user and endpoint
+        let user = login::get_session(&req).and_then(|s| self.sessions.get_session(&s)).map(|s| s.user);
+        let key = format!("{} {} {} {}", user.unwrap_or_default(), req.method(), req.uri(), key);
+
+        let keys = self.keys.clone();
+        let handler = self.handler.clone();
+        let (parts, body) = req.into_parts();
+
+        Box::new(body.concat2().and_then(move |data| -> FutureResponse {
+            match keys.begin(&key, &body_hash(&data)) {
+                KeyState::Done(stored) => return handler.respond(replay(stored)),
+                KeyState::InProgress => {
+                    return handler
+                        .respond_with(StatusCode::CONFLICT, "A request with this Idempotency-Key is in progress")
+                }
+                KeyState::Mismatch => {
+                    return handler.respond_with(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "This Idempotency-Key was used for a request with a different body",
+                    )
+                }
+                KeyState::New => (),
+            };
+
+            let abort_keys = keys.clone();
+            let abort_key = key.clone();
+            let req = Request::from_parts(parts, Body::from(data));
+
+            Box::new(
+                handler
+                    .handle(req)
+                    .and_then(move |resp| {
+                        let (parts, body) = resp.into_parts();
+                        body.concat2().map(move |data| {
+                            if parts.status.is_server_error() {
+                                keys.abort(&key);
+                            } else {
+                                let content_type = parts
+                                    .headers
+                                    .get(hyper::header::CONTENT_TYPE)
+                                    .and_then(|v| v.to_str().ok())
+                                    .map(|v| v.to_string());
+                                keys.complete(
+                                    &key,
+                                    StoredResponse {
+                                        status: parts.status,
+                                        content_type: content_type,
+                                        body: data.to_vec(),
+                                    },
+                                );
+                            }
+                            Response::from_parts(parts, Body::from(data))
+                        })
+                    })
+                    .or_else(move |e| {
+                        abort_keys.abort(&abort_key);
+                        future::err(e)
+                    }),
+            )
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored(body: &str) -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::OK,
+            content_type: Some("application/json".into()),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_idempotency_keys() {
+        let keys = IdempotencyKeys::new();
+
+        assert_eq!(KeyState::New, keys.begin("key1", b""));
+        assert_eq!(KeyState::InProgress, keys.begin("key1", b""));
+
+        keys.complete("key1", stored("{}"));
+        assert_eq!(KeyState::Done(stored("{}")), keys.begin("key1", b""));
+
+        // other keys are unaffected
+        assert_eq!(KeyState::New, keys.begin("key2", b""));
+    }
+
+    #[test]
+    fn test_idempotency_keys_abort() {
+        let keys = IdempotencyKeys::new();
+
+        assert_eq!(KeyState::New, keys.begin("key1", b""));
+        keys.abort("key1");
+        assert_eq!(KeyState::New, keys.begin("key1", b""));
+    }
+
+    #[test]
+    fn test_idempotency_keys_expire() {
+        let keys = IdempotencyKeys::new();
+
+        assert_eq!(KeyState::New, keys.begin("key1", b""));
+        keys.complete("key1", stored("{}"));
+
+        *keys.last_pruned.write().unwrap() -= Duration::from_secs(PRUNE_SECS + 1);
+        keys.entries.write().unwrap().get_mut("key1").unwrap().created_at -= Duration::from_secs(KEY_EXPIRY_SECS + 1);
+
+        assert_eq!(KeyState::New, keys.begin("key1", b""));
+    }
+
+    #[test]
+    fn test_idempotency_keys_body_mismatch() {
+        let keys = IdempotencyKeys::new();
+
+        assert_eq!(KeyState::New, keys.begin("key1", &body_hash(b"{\"a\": 1}")));
+        assert_eq!(KeyState::Mismatch, keys.begin("key1", &body_hash(b"{\"a\": 2}")));
+        keys.complete("key1", stored("{}"));
+        assert_eq!(KeyState::Mismatch, keys.begin("key1", &body_hash(b"{\"a\": 2}")));
+        assert_eq!(KeyState::Done(stored("{}")), keys.begin("key1", &body_hash(b"{\"a\": 1}")));
+    }
+
+    #[test]
+    fn test_idempotency_keys_expire_without_prune() {
+        let keys = IdempotencyKeys::new();
+
+        assert_eq!(KeyState::New, keys.begin("key1", b""));
+        keys.complete("key1", stored("{}"));
+        keys.entries.write().unwrap().get_mut("key1").unwrap().created_at -= Duration::from_secs(KEY_EXPIRY_SECS + 1);
+
+        assert_eq!(KeyState::New, keys.begin("key1", b""));
+    }
+
+    #[test]
+    fn test_idempotency_keys_cap() {
+        let keys = IdempotencyKeys::new();
+
+        for i in 0..MAX_KEYS {
+            assert_eq!(KeyState::New, keys.begin(&format!("key{}", i), b""));
+            keys.entries.write().unwrap().get_mut(&format!("key{}", i)).unwrap().created_at -=
+                Duration::from_millis((MAX_KEYS - i) as u64);
+        }
+        assert_eq!(KeyState::New, keys.begin("another", b""));
+
+        // the oldest one made room
+        let entries = keys.entries.read().unwrap();
+        assert_eq!(MAX_KEYS, entries.len());
+        assert!(!entries.contains_key("key0"));
+        assert!(entries.contains_key("key1"));
+    }
+}
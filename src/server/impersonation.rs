@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use log::{error, info};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::audit::AuditEntry;
+use crate::config::Config;
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::Sessions;
+use crate::users::UserInfo;
+use crate::util;
+
+const AUDIT_LIMIT: u32 = 200;
+
+pub const VIEW_AS_ACTION: &str = "view-as";
+
+// What octobot would do for a given github user
+#[derive(Serialize)]
+pub struct UserView {
+    pub github: String,
+    pub user: Option<UserInfo>,
+    // the slack user name that channel messages refer to this user by
+    pub slack_name: Option<String>,
+    // where direct messages are sent, if anywhere
+    pub direct_messages_to: Option<String>,
+    // explanations of anything that would stop this user from being notified
+    pub notes: Vec<String>,
+}
+
+pub fn user_view(config: &Config, github_name: &str) -> UserView {
+    let users = config.users();
+    let user = users.lookup_info(github_name);
+
+    let mut notes = vec![];
+    match user {
+        None => {
+            notes.push(format!(
+                "No slack user is mapped to github user '{}': they will not receive direct messages",
+                github_name
+            ));
+            let deleted = users.get_deleted().unwrap_or(vec![]);
+            if deleted.iter().any(|u| u.github == github_name) {
+                notes.push("This user was deleted and may be restored".into());
+            }
+        }
+        Some(ref u) if u.mute_direct_messages => {
+            notes.push("Direct messages are muted".into());
+        }
+        Some(ref u) if u.direct_messages_muted() => {
+            notes.push(format!("Direct messages are muted until {}", util::format_timestamp(u.muted_until)));
+        }
+        Some(_) => (),
+    };
+
+    UserView {
+        github: github_name.into(),
+        slack_name: users.slack_user_name(github_name),
+        direct_messages_to: users.slack_user_mention(github_name),
+        user: user,
+        notes: notes,
+    }
+}
+
+pub enum ImpersonationOp {
+    ViewAs,
+    AuditLog,
+}
+
+// Super-admin only: lets support see what a user would see without logging in as them.
+pub struct ImpersonationHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    op: ImpersonationOp,
+}
+
+#[derive(Serialize)]
+struct AuditResp {
+    entries: Vec<AuditEntry>,
+}
+
+impl ImpersonationHandler {
+    pub fn new(config: Arc<Config>, sessions: Arc<Sessions>, op: ImpersonationOp) -> Box<ImpersonationHandler> {
+        Box::new(ImpersonationHandler {
+            config: config,
+            sessions: sessions,
+            op: op,
+        })
+    }
+}
+
+impl Handler for ImpersonationHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let session = match login::get_admin_session(&self.sessions, &req) {
+            Some(s) => s,
+            None => return self.respond_with(StatusCode::FORBIDDEN, "Only the admin may do this"),
+        };
+
+        match &self.op {
+            &ImpersonationOp::ViewAs => self.view_as(req, &session.user),
+            &ImpersonationOp::AuditLog => self.audit_log(req),
+        }
+    }
+}
+
+impl ImpersonationHandler {
+    fn view_as(&self, req: Request<Body>, admin: &str) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        let github_name = match query.get("user") {
+            Some(u) if !u.is_empty() => u.clone(),
+            _ => return self.respond(util::new_bad_req_resp("No `user` param specified")),
+        };
+
+        // don't show anything that wasn't audited
+        if let Err(e) = self.config.audit.record(admin, VIEW_AS_ACTION, &github_name, "") {
+            return self.respond_error(&format!("{}", e));
+        }
+        info!("{} is viewing octobot as {}", admin, github_name);
+
+        let view = user_view(&self.config, &github_name);
+        match serde_json::to_string(&view) {
+            Ok(v) => self.respond(util::new_json_resp(v)),
+            Err(e) => {
+                error!("Error serializing user view: {}", e);
+                self.respond_error(&format!("Error serializing user view: {}", e))
+            }
+        }
+    }
+
+    fn audit_log(&self, _: Request<Body>) -> FutureResponse {
+        let entries = match self.config.audit.get_recent(AUDIT_LIMIT) {
+            Ok(e) => e,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&AuditResp { entries: entries }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing audit log: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    use crate::db::Database;
+    use crate::users;
+
+    fn new_test() -> (Config, TempDir) {
+        let temp_dir = TempDir::new("impersonation.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (Config::new(db), temp_dir)
+    }
+
+    #[test]
+    fn test_user_view() {
+        let (config, _temp) = new_test();
+        config.users_write().insert("joe-gh", "joe.slack").unwrap();
+        let mut muted = UserInfo::new("bob-gh", "bob.slack");
+        muted.mute_direct_messages = true;
+        config.users_write().insert_info(&muted).unwrap();
+
+        let view = user_view(&config, "joe-gh");
+        assert_eq!(Some("joe.slack".to_string()), view.slack_name);
+        assert_eq!(Some(users::mention("joe.slack")), view.direct_messages_to);
+        assert_eq!(Vec::<String>::new(), view.notes);
+
+        let view = user_view(&config, "bob-gh");
+        assert_eq!(Some("bob.slack".to_string()), view.slack_name);
+        assert_eq!(None, view.direct_messages_to);
+        assert_eq!(vec!["Direct messages are muted"], view.notes);
+
+        let view = user_view(&config, "unknown");
+        assert!(view.user.is_none());
+        assert_eq!(None, view.slack_name);
+        assert_eq!(1, view.notes.len());
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::{Body, HeaderMap, Request, StatusCode};
+use log::info;
+use ring::{digest, hmac};
+use rustc_serialize::hex::FromHex;
+use serde_json;
+
+use crate::config::{Config, JiraConfig};
+use crate::jira::webhooks::{self, WebhookEvent};
+use crate::messenger;
+use crate::server::http::{FutureResponse, Handler};
+use crate::slack::SlackRequest;
+use crate::users;
+use crate::util;
+use crate::worker::Worker;
+
+// Receives JIRA webhooks (issue updates, comments and version releases) and passes them on to slack
+pub struct JiraHandler {
+    config: Arc<Config>,
+    slack: Arc<dyn Worker<SlackRequest>>,
+}
+
+impl JiraHandler {
+    pub fn new(config: Arc<Config>, slack: Arc<dyn Worker<SlackRequest>>) -> Box<JiraHandler> {
+        Box::new(JiraHandler {
+            config: config,
+            slack: slack,
+        })
+    }
+}
+
+// JIRA signs deliveries like github does, but with sha256: `X-Hub-Signature: sha256=<hex hmac of the body>`
+fn is_signed_with(secret: &str, headers: &HeaderMap, data: &[u8]) -> bool {
+    let signature = match headers.get("x-hub-signature").and_then(|v| v.to_str().ok()) {
+        Some(s) if s.starts_with("sha256=") => s[7..].to_string(),
+        _ => return false,
+    };
+    let sig_bytes: Vec<u8> = match signature.from_hex() {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    let key = hmac::VerificationKey::new(&digest::SHA256, secret.as_bytes());
+    hmac::verify(&key, data, &sig_bytes).is_ok()
+}
+
+// The instance whose secret signed the delivery, if any
+fn signed_by(instances: Vec<JiraConfig>, headers: &HeaderMap, data: &[u8]) -> Option<JiraConfig> {
+    instances.into_iter().find(|j| is_signed_with(j.webhook_secret.as_ref().unwrap(), headers, data))
+}
+
+impl Handler for JiraHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let instances = self.config.jira_webhook_instances();
+        if instances.is_empty() {
+            return self.respond_with(StatusCode::NOT_FOUND, "JIRA webhooks are not configured");
+        }
+
+        let query = util::parse_query(req.uri().query());
+        let headers = req.headers().clone();
+        let config = self.config.clone();
+        let slack = self.slack.clone();
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            let jira = match signed_by(instances, &headers, &body) {
+                Some(j) => j,
+                None => return util::new_msg_resp(StatusCode::FORBIDDEN, "Invalid signature"),
+            };
+
+            let event: WebhookEvent = match serde_json::from_slice(&body) {
+                Ok(e) => e,
+                Err(e) => return util::new_bad_req_resp(format!("Error parsing JIRA event: {}", e)),
+            };
+            info!("Received JIRA event '{}' from {}", event.event, jira.host);
+
+            let messenger = messenger::new(config.clone(), slack).for_dm_event(users::DM_JIRA);
+            webhooks::notify(&event, query.get("project").map(|p| p.as_str()), &jira, &config, &messenger);
+            util::new_empty_resp(StatusCode::OK)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+    use rustc_serialize::hex::ToHex;
+
+    fn sign(secret: &str, data: &[u8]) -> HeaderMap {
+        let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+        let mut headers = HeaderMap::new();
+        let value = format!("sha256={}", hmac::sign(&key, data).as_ref().to_hex());
+        headers.insert("x-hub-signature", HeaderValue::from_str(&value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_is_signed_with() {
+        let data = b"{\"webhookEvent\": \"comment_created\"}";
+        assert!(is_signed_with("the-secret", &sign("the-secret", data), data));
+        assert!(!is_signed_with("other-secret", &sign("the-secret", data), data));
+        assert!(!is_signed_with("the-secret", &sign("the-secret", b"something else"), data));
+        assert!(!is_signed_with("the-secret", &HeaderMap::new(), data));
+
+        let mut headers = sign("the-secret", data);
+        let sha1 = headers.get("x-hub-signature").unwrap().to_str().unwrap().replace("sha256=", "sha1=");
+        headers.insert("x-hub-signature", HeaderValue::from_str(&sha1).unwrap());
+        assert!(!is_signed_with("the-secret", &headers, data));
+    }
+}
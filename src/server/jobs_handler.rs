@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Stream;
+use hyper::{Body, Request, Response, StatusCode};
+use log::error;
+use serde_derive::Serialize;
+use serde_json;
+use tokio::timer::Interval;
+
+use crate::config::Config;
+use crate::jobs::JobInfo;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// Clients (i.e. EventSource) reconnect automatically once a stream ends,
+// so cap how long any one stream is held open.
+const EVENTS_INTERVAL_SECS: u64 = 2;
+const EVENTS_MAX_UPDATES: u64 = 150;
+
+pub enum JobOp {
+    List,
+    Get,
+    Cancel,
+    Events,
+}
+
+pub struct JobsHandler {
+    config: Arc<Config>,
+    op: JobOp,
+}
+
+#[derive(Serialize)]
+struct JobsResp {
+    jobs: Vec<JobInfo>,
+}
+
+impl JobsHandler {
+    pub fn new(config: Arc<Config>, op: JobOp) -> Box<JobsHandler> {
+        Box::new(JobsHandler {
+            config: config,
+            op: op,
+        })
+    }
+}
+
+impl Handler for JobsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        match &self.op {
+            &JobOp::List => self.get_all(req),
+            &JobOp::Get => self.get(req),
+            &JobOp::Cancel => self.cancel(req),
+            &JobOp::Events => self.events(req),
+        }
+    }
+}
+
+fn job_id(req: &Request<Body>) -> Option<i32> {
+    let query = util::parse_query(req.uri().query());
+    query.get("id").and_then(|id| id.parse::<i32>().ok())
+}
+
+impl JobsHandler {
+    fn get_all(&self, _: Request<Body>) -> FutureResponse {
+        let jobs = match self.config.jobs.get_all() {
+            Ok(j) => j,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&JobsResp { jobs: jobs }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing jobs: {}", e)),
+        }
+    }
+
+    fn get(&self, req: Request<Body>) -> FutureResponse {
+        let id = match job_id(&req) {
+            Some(id) => id,
+            None => return self.respond(util::new_bad_req_resp("No `id` param specified")),
+        };
+
+        let job = match self.config.jobs.get(id) {
+            Ok(Some(j)) => j,
+            Ok(None) => return self.respond_with(StatusCode::NOT_FOUND, "No such job"),
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&job) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing job: {}", e)),
+        }
+    }
+
+    fn cancel(&self, req: Request<Body>) -> FutureResponse {
+        let id = match job_id(&req) {
+            Some(id) => id,
+            None => return self.respond(util::new_bad_req_resp("No `id` param specified")),
+        };
+
+        match self.config.jobs.cancel(id) {
+            Ok(true) => self.respond_with(StatusCode::OK, ""),
+            Ok(false) => self.respond(util::new_bad_req_resp("Job is not running")),
+            Err(e) => self.respond_error(&format!("{}", e)),
+        }
+    }
+
+    // Server-sent events stream of the currently running jobs.
+    fn events(&self, _: Request<Body>) -> FutureResponse {
+        let config = self.config.clone();
+
+        let stream = Interval::new_interval(Duration::from_secs(EVENTS_INTERVAL_SECS))
+            .take(EVENTS_MAX_UPDATES)
+            .map(move |_| {
+                let jobs = match config.jobs.get_running() {
+                    Ok(j) => j,
+                    Err(e) => {
+                        error!("Error looking up running jobs: {}", e);
+                        vec![]
+                    }
+                };
+                match serde_json::to_string(&JobsResp { jobs: jobs }) {
+                    Ok(j) => format!("event: jobs\ndata: {}\n\n", j),
+                    Err(e) => {
+                        error!("Error serializing jobs: {}", e);
+                        String::new()
+                    }
+                }
+            });
+
+        let mut resp = Response::new(Body::wrap_stream(stream));
+        resp.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            "text/event-stream".parse().unwrap(),
+        );
+        resp.headers_mut().insert(
+            hyper::header::CACHE_CONTROL,
+            "no-cache".parse().unwrap(),
+        );
+        self.respond(resp)
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use hyper::{Body, HeaderMap, Request, StatusCode};
+use log::error;
+use ring::constant_time;
+
+use crate::config::Config;
+use crate::github::api::GithubSessionFactory;
+use crate::jsm;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// Jira automation tells octobot that an approval request was decided: the decision itself is looked up from the
+// service desk, so the webhook only needs the issue key
+pub struct JsmDecisionHandler {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    jsm: Option<Arc<dyn jsm::Session>>,
+}
+
+impl JsmDecisionHandler {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        jsm: Option<Arc<dyn jsm::Session>>,
+    ) -> Box<JsmDecisionHandler> {
+        Box::new(JsmDecisionHandler {
+            config: config,
+            github_app: github_app,
+            jsm: jsm,
+        })
+    }
+}
+
+fn is_authorized(token: &str, headers: &HeaderMap) -> bool {
+    match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+        Some(a) if a.starts_with("Bearer ") => {
+            constant_time::verify_slices_are_equal(a[7..].trim().as_bytes(), token.as_bytes()).is_ok()
+        }
+        _ => false,
+    }
+}
+
+impl Handler for JsmDecisionHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let token = match self.config.jsm.as_ref().and_then(|c| c.webhook_token.clone()) {
+            Some(t) => t,
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Approval webhook is not configured"),
+        };
+        let jsm = match self.jsm {
+            Some(ref s) => s.clone(),
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Jira Service Management is not configured"),
+        };
+        if !is_authorized(&token, req.headers()) {
+            return self.respond_with(StatusCode::FORBIDDEN, "Invalid credentials");
+        }
+
+        let query = util::parse_query(req.uri().query());
+        let key = match query.get("key").filter(|k| !k.is_empty()) {
+            Some(k) => k.clone(),
+            None => return self.respond(util::new_bad_req_resp("Expected `key` param")),
+        };
+
+        let request = match self.config.jsm_approvals.get_by_key(&key) {
+            Ok(Some(r)) => r,
+            Ok(None) => return self.respond_with(StatusCode::NOT_FOUND, &format!("Unknown approval request {}", key)),
+            Err(e) => {
+                error!("Error looking up approval request {}: {}", key, e);
+                return self.respond_error("Error looking up approval request");
+            }
+        };
+
+        if let Err(e) = jsm::refresh_decision(&*jsm, &self.config, &*self.github_app, &request) {
+            error!("Error checking decision on approval request {}: {}", key, e);
+            return self.respond_error("Error checking decision");
+        }
+
+        match self.config.jsm_approvals.get_by_key(&key) {
+            Ok(Some(r)) => self.respond(util::new_json_resp(serde_json::to_string(&r).unwrap_or_default())),
+            _ => self.respond_with(StatusCode::NOT_FOUND, &format!("Unknown approval request {}", key)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_is_authorized() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer the-token"));
+        assert!(is_authorized("the-token", &headers));
+        assert!(!is_authorized("other-token", &headers));
+        assert!(!is_authorized("the-token", &HeaderMap::new()));
+    }
+}
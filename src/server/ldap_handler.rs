@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::config::Config;
+use crate::ldap_auth::ServerHealth;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// Which LDAP servers are up, as of the last login or health check, and how many connections are kept open
+pub struct LdapHealthHandler {
+    config: Arc<Config>,
+}
+
+#[derive(Serialize)]
+struct LdapHealthResp {
+    servers: Vec<ServerHealth>,
+    idle_connections: usize,
+}
+
+impl LdapHealthHandler {
+    pub fn new(config: Arc<Config>) -> Box<LdapHealthHandler> {
+        Box::new(LdapHealthHandler { config: config })
+    }
+}
+
+impl Handler for LdapHealthHandler {
+    fn handle(&self, _req: Request<Body>) -> FutureResponse {
+        let client = match self.config.ldap_client {
+            Some(ref c) => c,
+            None => return self.respond_with(StatusCode::NOT_FOUND, "LDAP is not configured"),
+        };
+        let resp = LdapHealthResp {
+            servers: client.health(),
+            idle_connections: client.idle_connections(),
+        };
+        match serde_json::to_string(&resp) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing LDAP health: {}", e)),
+        }
+    }
+}
@@ -1,57 +1,37 @@
 use std::sync::Arc;
 
+use hyper::header::{AUTHORIZATION, WWW_AUTHENTICATE};
 use hyper::{Body, Request, Response, StatusCode};
 use log::{error, info, warn};
-use ring::{digest, pbkdf2};
-use rustc_serialize::hex::{FromHex, ToHex};
 use serde_derive::Deserialize;
 use serde_json::json;
 
-use crate::config::Config;
+use crate::config::{AdminConfig, Config, LdapConfig};
+use crate::config_reload::LiveConfig;
+use crate::db;
+use crate::kerberos;
 use crate::ldap_auth;
+use crate::passwords;
+use crate::server::api;
 use crate::server::http::{parse_json, Filter, FilterResult, FutureResponse, Handler};
-use crate::server::sessions::Sessions;
+use crate::server::sessions::{Client, SessionInfo, Sessions};
+use crate::users::UserInfo;
 use crate::util;
+use crate::webauthn;
 
-static DIGEST_ALG: &'static digest::Algorithm = &digest::SHA256;
-const CREDENTIAL_LEN: usize = digest::SHA256_OUTPUT_LEN;
-
-fn pbdkf2_iterations() -> std::num::NonZeroU32 {
-    std::num::NonZeroU32::new(100_000).unwrap()
-}
-
-pub fn store_password(pass: &str, salt: &str) -> String {
-    let mut pass_hash = [0u8; CREDENTIAL_LEN];
-    pbkdf2::derive(
-        DIGEST_ALG,
-        pbdkf2_iterations(),
-        salt.as_bytes(),
-        pass.as_bytes(),
-        &mut pass_hash,
-    );
-
-    pass_hash.to_hex()
+pub struct LoginHandler {
+    sessions: Arc<Sessions>,
+    live_config: Arc<LiveConfig>,
 }
 
-pub fn verify_password(pass: &str, salt: &str, pass_hash: &str) -> bool {
-    let pass_hash = match pass_hash.from_hex() {
-        Ok(h) => h,
-        Err(e) => {
-            error!("Invalid password hash stored: {} -- {}", pass_hash, e);
-            return false;
-        }
-    };
-    pbkdf2::verify(
-        DIGEST_ALG,
-        pbdkf2_iterations(),
-        salt.as_bytes(),
-        pass.as_bytes(),
-        &pass_hash,
-    )
-    .is_ok()
+// Logs in with the browser's kerberos ticket (SPNEGO)
+pub struct NegotiateHandler {
+    sessions: Arc<Sessions>,
+    config: Arc<Config>,
 }
 
-pub struct LoginHandler {
+// Logs in with LDAP to a session that may only manage the user's own settings, under /api/me
+pub struct SelfServiceLoginHandler {
     sessions: Arc<Sessions>,
     config: Arc<Config>,
 }
@@ -69,8 +49,26 @@ pub struct LoginSessionFilter {
 }
 
 impl LoginHandler {
-    pub fn new(sessions: Arc<Sessions>, config: Arc<Config>) -> Box<LoginHandler> {
+    pub fn new(sessions: Arc<Sessions>, live_config: Arc<LiveConfig>) -> Box<LoginHandler> {
         Box::new(LoginHandler {
+            sessions: sessions,
+            live_config: live_config,
+        })
+    }
+}
+
+impl NegotiateHandler {
+    pub fn new(sessions: Arc<Sessions>, config: Arc<Config>) -> Box<NegotiateHandler> {
+        Box::new(NegotiateHandler {
+            sessions: sessions,
+            config: config,
+        })
+    }
+}
+
+impl SelfServiceLoginHandler {
+    pub fn new(sessions: Arc<Sessions>, config: Arc<Config>) -> Box<SelfServiceLoginHandler> {
+        Box::new(SelfServiceLoginHandler {
             sessions: sessions,
             config: config,
         })
@@ -101,24 +99,43 @@ struct LoginRequest {
     password: String,
 }
 
-fn get_session(req: &Request<Body>) -> Option<String> {
+pub fn get_session(req: &Request<Body>) -> Option<String> {
     req.headers()
         .get("session")
         .map(|h| String::from_utf8_lossy(h.as_bytes()).into_owned())
 }
 
+// EventSource can't set headers, so allow event streams to pass the session as a query param.
+fn get_session_or_query(req: &Request<Body>) -> Option<String> {
+    get_session(req).or_else(|| {
+        if req.uri().path().ends_with("/events") {
+            util::parse_query(req.uri().query()).remove("session")
+        } else {
+            None
+        }
+    })
+}
+
 impl Handler for LoginHandler {
     fn handle(&self, req: Request<Body>) -> FutureResponse {
-        let config = self.config.clone();
+        let config = self.live_config.get();
+        let live_config = self.live_config.clone();
         let sessions = self.sessions.clone();
+        let client = Client::from_request(&req);
 
         parse_json(req, move |login_req: LoginRequest| {
             let mut success = None;
+            let mut is_admin = false;
+            let mut is_super_admin = false;
             if let Some(ref admin) = config.admin {
                 if admin.name == login_req.username {
-                    if verify_password(&login_req.password, &admin.salt, &admin.pass_hash) {
+                    let salt = admin.salt.as_ref().map(|s| s.as_str());
+                    if passwords::verify(&login_req.password, salt, &admin.pass_hash) {
                         info!("Admin auth success");
+                        rehash_admin_password(&live_config, &config, admin, &login_req.password);
                         success = Some(true);
+                        is_admin = true;
+                        is_super_admin = true;
                     } else {
                         warn!("Admin auth failure");
                         success = Some(false);
@@ -127,25 +144,28 @@ impl Handler for LoginHandler {
             }
 
             if success.is_none() {
-                if let Some(ref ldap) = config.ldap {
-                    match ldap_auth::auth(&login_req.username, &login_req.password, ldap) {
-                        Ok(true) => {
+                if let (Some(ldap), Some(client)) = (&config.ldap, &config.ldap_client) {
+                    match client.authenticate(&login_req.username, &login_req.password) {
+                        Ok(Some(entry)) => {
                             info!("LDAP auth successfor user: {}", login_req.username);
-                            success = Some(true)
+                            match ldap_access(ldap, &login_req.username, &entry) {
+                                Some(admin) => {
+                                    success = Some(true);
+                                    is_admin = admin;
+                                }
+                                None => success = Some(false),
+                            }
                         }
-                        Ok(false) => warn!("LDAP auth failure for user: {}", login_req.username),
+                        Ok(None) => warn!("LDAP auth failure for user: {}", login_req.username),
                         Err(e) => error!("Error authenticating to LDAP: {}", e),
                     };
                 }
             }
 
-            if success == Some(true) {
-                let sess_id = sessions.new_session();
-                let json = json!({
-                    "session": sess_id,
-                });
-
-                util::new_json_resp(json.to_string())
+            if success == Some(true) && is_admin && security_key_required(&config, &login_req.username) {
+                util::new_msg_resp(StatusCode::FORBIDDEN, SECURITY_KEY_REQUIRED)
+            } else if success == Some(true) {
+                new_session(&config, &sessions, &login_req.username, is_admin, is_super_admin, client)
             } else {
                 util::new_empty_resp(StatusCode::UNAUTHORIZED)
             }
@@ -153,6 +173,244 @@ impl Handler for LoginHandler {
     }
 }
 
+impl Handler for SelfServiceLoginHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let config = self.config.clone();
+        let sessions = self.sessions.clone();
+        let client = Client::from_request(&req);
+
+        parse_json(req, move |login_req: LoginRequest| {
+            let (ldap, ldap_client) = match (&config.ldap, &config.ldap_client) {
+                (Some(l), Some(c)) => (l, c),
+                _ => return util::new_msg_resp(StatusCode::NOT_FOUND, "LDAP is not configured"),
+            };
+            match ldap_client.authenticate(&login_req.username, &login_req.password) {
+                Ok(Some(ref entry)) if ldap_access(ldap, &login_req.username, entry).is_some() => (),
+                Ok(Some(_)) => return util::new_empty_resp(StatusCode::FORBIDDEN),
+                Ok(None) => {
+                    warn!("LDAP auth failure for user: {}", login_req.username);
+                    return util::new_empty_resp(StatusCode::UNAUTHORIZED);
+                }
+                Err(e) => {
+                    error!("Error authenticating to LDAP: {}", e);
+                    return util::new_empty_resp(StatusCode::UNAUTHORIZED);
+                }
+            };
+
+            if is_revoked(&config, &login_req.username) {
+                return util::new_empty_resp(StatusCode::UNAUTHORIZED);
+            }
+            let user = match user_for_login(&config, &login_req.username) {
+                Some(u) => u,
+                None => {
+                    warn!("Login refused: no octobot user matches {}", login_req.username);
+                    let msg = format!("No octobot user has the github login or email address {}", login_req.username);
+                    return util::new_msg_resp(StatusCode::FORBIDDEN, msg);
+                }
+            };
+            info!("Self-service LDAP auth success for user: {} ({})", login_req.username, user.github);
+            match start_self_service_session(&config, &sessions, &user.github, client) {
+                Some(session) => util::new_json_resp(json!({"session": session, "username": user.github}).to_string()),
+                None => util::new_empty_resp(StatusCode::UNAUTHORIZED),
+            }
+        })
+    }
+}
+
+// Replaces the admin's password hash when it isn't argon2id with the configured costs, as the password is only known
+// while logging in
+fn rehash_admin_password(live_config: &LiveConfig, config: &Config, admin: &AdminConfig, pass: &str) {
+    let params = config.password_params();
+    if params.check().is_err() || !passwords::needs_rehash(&admin.pass_hash, &params) {
+        return;
+    }
+
+    let config_file = live_config.config_file().to_string_lossy().into_owned();
+    match config.save_admin_hash(&config_file, &passwords::hash(pass, &params)) {
+        Ok(()) => {
+            info!("Rehashed the admin password with the configured costs");
+            live_config.reload(db::now());
+        }
+        Err(e) => warn!("Error saving the rehashed admin password: {}", e),
+    };
+}
+
+// Whether a directory user may log in, and if so as an admin or not. Groups are looked up on every login, so leaving
+// a group takes its role away.
+pub fn ldap_access(ldap: &LdapConfig, user: &str, entry: &ldap_auth::LDAPEntry) -> Option<bool> {
+    match ldap_auth::role(ldap, &entry.groups) {
+        Some(role) => Some(role == ldap_auth::Role::Admin),
+        None if ldap_auth::requires_group(ldap) => {
+            warn!("Login refused: {} is in none of the LDAP groups", user);
+            None
+        }
+        None => Some(false),
+    }
+}
+
+const SECURITY_KEY_REQUIRED: &str = "Log in with a security key";
+
+// Whether the admin may only log in with a security key
+fn security_key_required(config: &Config, user: &str) -> bool {
+    match webauthn::fallback_allowed(config, user) {
+        Ok(true) => false,
+        Ok(false) => {
+            warn!("Login refused: {} must log in with a security key", user);
+            true
+        }
+        Err(e) => {
+            error!("{}", e);
+            true
+        }
+    }
+}
+
+// Starts the session of a user who proved who they are, unless their access was revoked
+pub fn new_session(
+    config: &Config,
+    sessions: &Sessions,
+    user: &str,
+    is_admin: bool,
+    is_super_admin: bool,
+    client: Client,
+) -> Response<Body> {
+    let sess_id = match start_session(config, sessions, user, is_admin, is_super_admin, client) {
+        Some(s) => s,
+        None => return util::new_empty_resp(StatusCode::UNAUTHORIZED),
+    };
+    let json = json!({
+        "session": sess_id,
+        "username": user,
+    });
+
+    util::new_json_resp(json.to_string())
+}
+
+// The id of the user's new session, or None if their access was revoked
+pub fn start_session(
+    config: &Config,
+    sessions: &Sessions,
+    user: &str,
+    is_admin: bool,
+    is_super_admin: bool,
+    client: Client,
+) -> Option<String> {
+    if !is_super_admin && is_revoked(config, user) {
+        return None;
+    }
+
+    if let Err(e) = config.account_logins.record_login(user, is_admin) {
+        error!("{}", e);
+    }
+    Some(sessions.new_session(user, is_admin, client))
+}
+
+// The same for a session limited to /api/me, as the github user who logged in
+pub fn start_self_service_session(config: &Config, sessions: &Sessions, user: &str, client: Client) -> Option<String> {
+    if is_revoked(config, user) {
+        return None;
+    }
+
+    if let Err(e) = config.account_logins.record_login(user, false) {
+        error!("{}", e);
+    }
+    Some(sessions.new_self_service_session(user, client))
+}
+
+fn is_revoked(config: &Config, user: &str) -> bool {
+    match config.account_logins.is_revoked(user) {
+        Ok(true) => {
+            warn!("Login refused: access of {} has been revoked", user);
+            true
+        }
+        Ok(false) => false,
+        Err(e) => {
+            error!("{}", e);
+            true
+        }
+    }
+}
+
+// The octobot user a login (e.g. a directory user) is: the one with that github login, or with that email address
+pub fn user_for_login(config: &Config, login: &str) -> Option<UserInfo> {
+    let users = config.users();
+    users.lookup_local_info(login).or_else(|| users.lookup_by_email(login))
+}
+
+impl Handler for NegotiateHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let (kerberos, ldap, client) = match (&self.config.kerberos, &self.config.ldap, &self.config.ldap_client) {
+            (Some(k), Some(l), Some(c)) => (k, l, c),
+            _ => return self.respond_with(StatusCode::NOT_FOUND, "Kerberos is not configured"),
+        };
+
+        let token = match req.headers().get(AUTHORIZATION).and_then(|h| h.to_str().ok()) {
+            Some(a) => kerberos::negotiate_token(a),
+            None => None,
+        };
+        let token = match token {
+            Some(t) => t,
+            // asks the browser for a ticket
+            None => return self.respond(negotiate_resp(util::new_empty_resp(StatusCode::UNAUTHORIZED), "Negotiate")),
+        };
+
+        let accepted = match kerberos::accept(&token) {
+            Ok(a) => a,
+            Err(e) => {
+                warn!("Kerberos auth failure: {}", e);
+                return self.respond(util::new_empty_resp(StatusCode::UNAUTHORIZED));
+            }
+        };
+        let user = match kerberos::user_name(kerberos, &accepted.principal) {
+            Some(u) => u,
+            None => {
+                warn!("Login refused: principal {} is not a user of the realm", accepted.principal);
+                return self.respond(util::new_empty_resp(StatusCode::FORBIDDEN));
+            }
+        };
+
+        let is_admin = match client.find_user(&user) {
+            Ok(Some(entry)) => match ldap_access(ldap, &user, &entry) {
+                Some(admin) => admin,
+                None => return self.respond(util::new_empty_resp(StatusCode::FORBIDDEN)),
+            },
+            Ok(None) => {
+                warn!("Login refused: no LDAP user matches principal {}", accepted.principal);
+                return self.respond(util::new_empty_resp(StatusCode::FORBIDDEN));
+            }
+            Err(e) => {
+                error!("Error looking up {} in LDAP: {}", user, e);
+                return self.respond_error("Error looking up user");
+            }
+        };
+
+        if is_admin && security_key_required(&self.config, &user) {
+            return self.respond_with(StatusCode::FORBIDDEN, SECURITY_KEY_REQUIRED);
+        }
+
+        info!("Kerberos auth success for principal: {}", accepted.principal);
+        let resp = new_session(&self.config, &self.sessions, &user, is_admin, false, Client::from_request(&req));
+        if accepted.token.is_empty() {
+            self.respond(resp)
+        } else {
+            self.respond(negotiate_resp(resp, &format!("Negotiate {}", base64::encode(&accepted.token))))
+        }
+    }
+}
+
+fn negotiate_resp(mut resp: Response<Body>, challenge: &str) -> Response<Body> {
+    if let Ok(value) = challenge.parse() {
+        resp.headers_mut().insert(WWW_AUTHENTICATE, value);
+    }
+    resp
+}
+
+// The session for the request, but only if it belongs to an admin: the configured (super) admin, or an LDAP user
+// whose groups grant the admin role.
+pub fn get_admin_session(sessions: &Sessions, req: &Request<Body>) -> Option<SessionInfo> {
+    get_session(req).and_then(|s| sessions.get_session(&s)).filter(|s| s.admin)
+}
+
 fn invalid_session() -> Response<Body> {
     util::new_msg_resp(StatusCode::FORBIDDEN, "Invalid session")
 }
@@ -186,28 +444,82 @@ impl Handler for SessionCheckHandler {
 
 impl Filter for LoginSessionFilter {
     fn filter(&self, req: &Request<Body>) -> FilterResult {
-        let sess: String = match get_session(&req) {
+        let sess: String = match get_session_or_query(&req) {
             Some(s) => s.to_string(),
             None => return FilterResult::Halt(invalid_session()),
         };
 
-        if self.sessions.is_valid_session(&sess) {
-            FilterResult::Continue
-        } else {
-            FilterResult::Halt(invalid_session())
+        // Only admins may use the rest of the API. Admin rights come with the session, so an LDAP user who left the
+        // admin group keeps just their own settings.
+        match self.sessions.get_session(&sess) {
+            Some(ref s) if s.admin || is_self_service_request(req) => FilterResult::Continue,
+            Some(_) => FilterResult::Halt(util::new_msg_resp(
+                StatusCode::FORBIDDEN,
+                "This session may only use /api/v1/me",
+            )),
+            None => FilterResult::Halt(invalid_session()),
         }
     }
 }
 
+fn is_self_service_request(req: &Request<Body>) -> bool {
+    api::find(req.method(), req.uri().path()).map(|r| r.self_service()).unwrap_or(false)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use hyper::Method;
 
     #[test]
-    fn test_password() {
-        let pw_hash = store_password("the-pass", "some-salt");
-        assert_eq!(true, verify_password("the-pass", "some-salt", &pw_hash));
-        assert_eq!(false, verify_password("wrong-pass", "some-salt", &pw_hash));
-        assert_eq!(false, verify_password("the-pass", "wrong-salt", &pw_hash));
+    fn test_session_query_only_for_events() {
+        let req = Request::builder().uri("/api/jobs/events?session=abc").body(Body::empty()).unwrap();
+        assert_eq!(Some("abc".to_string()), get_session_or_query(&req));
+
+        let req = Request::builder().uri("/api/jobs?session=abc").body(Body::empty()).unwrap();
+        assert_eq!(None, get_session_or_query(&req));
+
+        let req = Request::builder()
+            .uri("/api/jobs")
+            .header("session", "def")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(Some("def".to_string()), get_session_or_query(&req));
+    }
+
+    fn filter_status(filter: &LoginSessionFilter, sess: &str, method: Method, path: &str) -> Option<StatusCode> {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("session", sess)
+            .body(Body::empty())
+            .unwrap();
+        match filter.filter(&req) {
+            FilterResult::Halt(resp) => Some(resp.status()),
+            FilterResult::Continue => None,
+        }
+    }
+
+    #[test]
+    fn test_filter_requires_admin() {
+        let sessions = Arc::new(Sessions::new());
+        let filter = LoginSessionFilter::new(sessions.clone());
+
+        // what an LDAP user whose groups only map to Role::User gets
+        let user = sessions.new_session("dev", false, Client::default());
+        assert_eq!(Some(StatusCode::FORBIDDEN), filter_status(&filter, &user, Method::GET, "/api/users"));
+        assert_eq!(Some(StatusCode::FORBIDDEN), filter_status(&filter, &user, Method::GET, "/api/repos"));
+        assert_eq!(Some(StatusCode::FORBIDDEN), filter_status(&filter, &user, Method::GET, "/api/v1/repos"));
+        assert_eq!(None, filter_status(&filter, &user, Method::GET, "/api/v1/me"));
+
+        let self_service = sessions.new_self_service_session("dev", Client::default());
+        assert_eq!(Some(StatusCode::FORBIDDEN), filter_status(&filter, &self_service, Method::GET, "/api/users"));
+        assert_eq!(None, filter_status(&filter, &self_service, Method::GET, "/api/v1/me"));
+
+        let admin = sessions.new_session("admin", true, Client::default());
+        assert_eq!(None, filter_status(&filter, &admin, Method::GET, "/api/users"));
+        assert_eq!(None, filter_status(&filter, &admin, Method::GET, "/api/repos"));
+
+        assert_eq!(Some(StatusCode::FORBIDDEN), filter_status(&filter, "nope", Method::GET, "/api/users"));
     }
 }
@@ -2,66 +2,93 @@ use std::fs;
 use std::io;
 use std::io::Seek;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use futures::{Future, Stream};
+use futures::{future, Future, Stream};
 use log::{error, info, warn};
+use hyper::server::conn::AddrStream;
 use hyper::server::Server;
+use hyper::service::make_service_fn;
 use rustls;
 use rustls::internal::pemfile;
 use tokio;
-use tokio_rustls::TlsAcceptor;
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, TlsStream};
 
+use crate::access_review::AccessReviewer;
+use crate::alerts::{self, AlertEscalator};
+use crate::auto_merge::{self, AutoMerger};
+use crate::clone_cache::{self, CloneCacheMaintainer};
+use crate::compliance::ComplianceReporter;
 use crate::config::Config;
+use crate::config_reload::{self, ConfigWatcher, LiveConfig};
+use crate::container_images::{ImageWatcher, RegistryClient};
+use crate::credentials::CredentialExpiryChecker;
+use crate::db;
+use crate::digests::DigestSender;
+use crate::email_gateway::{self, EmailGateway};
+use crate::faults;
+use crate::freeze::{self, FreezeThawer};
 use crate::github;
+use crate::ha;
+use crate::inbound_queue;
 use crate::jira;
-use crate::jira::api::JiraSession;
+use crate::jira::maintenance::{TokenRefresher, VersionSorter};
+use crate::jsm::{self, DecisionPoller};
+use crate::kerberos;
+use crate::kubernetes::{self, LeaderElector};
+use crate::label_taxonomy::{self, LabelSyncer};
+use crate::ldap_auth;
+use crate::outbox::{self, Redeliverer};
 use crate::runtime;
+use crate::pr_conflicts::{self, ConflictNotifier};
+use crate::repo_mutes::{self, MuteExpirer};
+use crate::scheduler::{Schedule, Scheduler};
 use crate::server::github_handler::GithubHandlerState;
 use crate::server::octobot_service::OctobotService;
+use crate::server::queue_consumer::QueueConsumer;
 use crate::server::redirect_service::RedirectService;
+use crate::server::retry_runner::RetryRunner;
 use crate::server::sessions::Sessions;
+use crate::servicenow::{self, ApprovalPoller};
+use crate::stale_prs::StalePRReminders;
+use crate::statuspage::{self, IncidentWatcher, StatuspageSession};
+use crate::user_discovery::{self, UserDiscoverer};
+use crate::warehouse::ExportFlusher;
+use crate::webhook_forwards::{self, ForwardRetrier};
+use crate::webhook_retries;
 
-pub fn start(config: Config) {
+pub fn start(config: Config, config_file: PathBuf) {
     let num_http_threads = config.main.num_http_threads.unwrap_or(20);
 
-    runtime::run(num_http_threads, move || run_server(config));
+    if let Some(ref kerberos) = config.kerberos {
+        if let Err(e) = kerberos::register_keytab(kerberos) {
+            panic!("Error initiating kerberos: {}", e);
+        }
+    }
+
+    runtime::run(num_http_threads, move || run_server(config, config_file));
 }
 
-fn run_server(config: Config) {
+fn run_server(config: Config, config_file: PathBuf) {
     let config = Arc::new(config);
+    let live_config = Arc::new(LiveConfig::new(config.clone(), &config_file, db::now()));
 
-
-    let github: Arc<dyn github::api::GithubSessionFactory>;
-
-    if config.github.app_id.is_some() {
-        github = match github::api::GithubApp::new(
-            &config.github.host,
-            config.github.app_id.expect("expected an app_id"),
-            &config.github.app_key().expect("expected an app_key"),
-        ) {
-            Ok(s) => Arc::new(s),
-            Err(e) => panic!("Error initiating github session: {}", e),
-        };
-    } else {
-        github = match github::api::GithubOauthApp::new(
-            &config.github.host,
-            &config.github.api_token.as_ref().expect("expected an api_token"),
-        ) {
-            Ok(s) => Arc::new(s),
-            Err(e) => panic!("Error initiating github session: {}", e),
-        };
+    if config.fault_injection_enabled() {
+        warn!("Fault injection is enabled: admins may simulate slack/github/jira failures");
+        faults::set_enabled(true);
     }
 
-    let jira: Option<Arc<dyn jira::api::Session>>;
-    if let Some(ref jira_config) = config.jira {
-        jira = match JiraSession::new(&jira_config) {
-            Ok(s) => Some(Arc::new(s)),
-            Err(e) => panic!("Error initiating jira session: {}", e),
-        };
-    } else {
-        jira = None;
-    }
+    let github: Arc<dyn github::api::GithubSessionFactory> = match github::multi::new_session_factory(&config) {
+        Ok(s) => s,
+        Err(e) => panic!("Error initiating github session: {}", e),
+    };
+
+    let jira: Option<Arc<dyn jira::api::Session>> = match jira::multi::new_session(&config) {
+        Ok(s) => s,
+        Err(e) => panic!("Error initiating jira session: {}", e),
+    };
 
     let http_addr: SocketAddr = match config.main.listen_addr {
         Some(ref addr_and_port) => addr_and_port.parse().unwrap(),
@@ -93,10 +120,321 @@ fn run_server(config: Config) {
     }
 
     let ui_sessions = Arc::new(Sessions::new());
-    let github_handler_state = Arc::new(GithubHandlerState::new(config.clone(), github.clone(), jira.clone()));
+    let github_handler_state = Arc::new(GithubHandlerState::new(live_config.clone(), github.clone(), jira.clone()));
 
-    let main_service = OctobotService::new(config.clone(), ui_sessions.clone(), github_handler_state.clone());
-    let redirect_service = RedirectService::new(https_addr.port());
+    let scheduler = Arc::new(Scheduler::new());
+    scheduler.set_runs(config.job_runs.clone());
+    // an instance sharing a redis with others is elected there instead
+    let leader_elector = if config.kubernetes_leader_election() && github_handler_state.cluster.is_none() {
+        match LeaderElector::new(&config) {
+            Ok(e) => Some(Arc::new(e)),
+            Err(e) => panic!("Error initiating kubernetes leader election: {}", e),
+        }
+    } else {
+        None
+    };
+    if let Some(ref elector) = leader_elector {
+        scheduler.set_leadership(elector.clone());
+    }
+    if let Some(ref cluster) = github_handler_state.cluster {
+        scheduler.set_leadership(cluster.clone());
+    }
+    match Schedule::parse_daily(&config.stale_pr_reminder_time()) {
+        Ok(schedule) => scheduler.add(
+            "stale-pr-reminders",
+            schedule,
+            {
+                let (github, slack) = (github.clone(), github_handler_state.slack_worker.clone());
+                config_reload::live_task(live_config.clone(), move |config| {
+                    StalePRReminders::new(config, github.clone(), slack.clone())
+                })
+            },
+        ),
+        Err(e) => error!("Not scheduling stale PR reminders: {}", e),
+    };
+    match Schedule::parse_daily(&config.digest_time()) {
+        Ok(schedule) => scheduler.add(
+            "digests",
+            schedule,
+            {
+                let slack = github_handler_state.slack_worker.clone();
+                config_reload::live_task(live_config.clone(), move |config| DigestSender::new(config, slack.clone()))
+            },
+        ),
+        Err(e) => error!("Not scheduling digests: {}", e),
+    };
+    match Schedule::parse_daily(&config.digest_time()) {
+        Ok(schedule) => scheduler.add(
+            "compliance-reports",
+            schedule,
+            {
+                let slack = github_handler_state.slack_worker.clone();
+                config_reload::live_task(live_config.clone(), move |config| {
+                    ComplianceReporter::new(config, slack.clone())
+                })
+            },
+        ),
+        Err(e) => error!("Not scheduling compliance reports: {}", e),
+    };
+    match Schedule::parse_daily(&config.digest_time()) {
+        Ok(schedule) => scheduler.add(
+            "access-reviews",
+            schedule,
+            {
+                let (sessions, slack) = (ui_sessions.clone(), github_handler_state.slack_worker.clone());
+                config_reload::live_task(live_config.clone(), move |config| {
+                    AccessReviewer::new(config, sessions.clone(), slack.clone())
+                })
+            },
+        ),
+        Err(e) => error!("Not scheduling access reviews: {}", e),
+    };
+    match Schedule::parse_daily(&config.digest_time()) {
+        Ok(schedule) => scheduler.add(
+            "credential-expiry",
+            schedule,
+            {
+                let slack = github_handler_state.slack_worker.clone();
+                config_reload::live_task(live_config.clone(), move |config| {
+                    CredentialExpiryChecker::new(config, slack.clone())
+                })
+            },
+        ),
+        Err(e) => error!("Not scheduling credential expiry reminders: {}", e),
+    };
+    scheduler.add(
+        "pr-conflict-checks",
+        Schedule::Every(pr_conflicts::CHECK_INTERVAL_SECS),
+        {
+            let (github, slack) = (github.clone(), github_handler_state.slack_worker.clone());
+            config_reload::live_task(live_config.clone(), move |config| {
+                ConflictNotifier::new(config, github.clone(), slack.clone())
+            })
+        },
+    );
+    scheduler.add(
+        "auto-merges",
+        Schedule::Every(auto_merge::CHECK_INTERVAL_SECS),
+        {
+            let github = github.clone();
+            config_reload::live_task(live_config.clone(), move |config| AutoMerger::new(config, github.clone()))
+        },
+    );
+    scheduler.add_on_every_replica(
+        "clone-cache",
+        Schedule::Every(clone_cache::CHECK_INTERVAL_SECS),
+        CloneCacheMaintainer::new(github_handler_state.clone_mgr.clone()),
+    );
+    scheduler.add(
+        "code-freeze-thaws",
+        Schedule::Every(freeze::CHECK_INTERVAL_SECS),
+        {
+            let github = github.clone();
+            config_reload::live_task(live_config.clone(), move |config| FreezeThawer::new(config, github.clone()))
+        },
+    );
+    scheduler.add(
+        "alert-escalations",
+        Schedule::Every(alerts::CHECK_INTERVAL_SECS),
+        {
+            let slack = github_handler_state.slack_worker.clone();
+            config_reload::live_task(live_config.clone(), move |config| AlertEscalator::new(config, slack.clone()))
+        },
+    );
+    scheduler.add(
+        "repo-unmutes",
+        Schedule::Every(repo_mutes::CHECK_INTERVAL_SECS),
+        {
+            let slack = github_handler_state.slack_worker.clone();
+            config_reload::live_task(live_config.clone(), move |config| MuteExpirer::new(config, slack.clone()))
+        },
+    );
+    let redeliverer =
+        Redeliverer::new(&config, github_handler_state.outbox.clone(), github_handler_state.slack_worker.clone());
+    // a shared redis only needs one instance to look at it, while each instance has its own database
+    if config.outbox_store() == outbox::REDIS {
+        scheduler.add("outbox", Schedule::Every(outbox::CHECK_INTERVAL_SECS), redeliverer);
+    } else {
+        scheduler.add_on_every_replica("outbox", Schedule::Every(outbox::CHECK_INTERVAL_SECS), redeliverer);
+    }
+    if let Some(ref servicenow_session) = github_handler_state.servicenow_session {
+        scheduler.add(
+            "change-approvals",
+            Schedule::Every(servicenow::CHECK_INTERVAL_SECS),
+            {
+                let (github, servicenow) = (github.clone(), servicenow_session.clone());
+                config_reload::live_task(live_config.clone(), move |config| {
+                    ApprovalPoller::new(config, github.clone(), servicenow.clone())
+                })
+            },
+        );
+    }
+    if let Some(ref jsm_session) = github_handler_state.jsm_session {
+        scheduler.add(
+            "jsm-approvals",
+            Schedule::Every(jsm::CHECK_INTERVAL_SECS),
+            {
+                let (github, jsm) = (github.clone(), jsm_session.clone());
+                config_reload::live_task(live_config.clone(), move |config| {
+                    DecisionPoller::new(config, github.clone(), jsm.clone())
+                })
+            },
+        );
+    }
+    if let (true, Some(email)) = (config.email_gateway_enabled(), github_handler_state.email_worker.clone()) {
+        scheduler.add(
+            "email-gateway",
+            Schedule::Every(email_gateway::CHECK_INTERVAL_SECS),
+            {
+                let (github, team_members) = (github.clone(), github_handler_state.team_members.clone());
+                config_reload::live_task(live_config.clone(), move |config| {
+                    EmailGateway::new(config, github.clone(), team_members.clone(), email.clone())
+                })
+            },
+        );
+    }
+    if let Some(ref statuspage) = config.statuspage {
+        let session = StatuspageSession::new(statuspage).expect("Error creating Statuspage client");
+        scheduler.add(
+            "provider-incidents",
+            Schedule::Every(statuspage::CHECK_INTERVAL_SECS),
+            {
+                let (session, slack) = (Arc::new(session), github_handler_state.slack_worker.clone());
+                config_reload::live_task(live_config.clone(), move |config| {
+                    IncidentWatcher::new(config, session.clone(), slack.clone())
+                })
+            },
+        );
+    }
+    if let Some(ref container_images) = config.container_images {
+        let registry = RegistryClient::new(container_images).expect("Error creating container registry client");
+        scheduler.add(
+            "container-images",
+            Schedule::Every(config.container_images_check_secs()),
+            {
+                let (github, clone_mgr, registry) =
+                    (github.clone(), github_handler_state.clone_mgr.clone(), Arc::new(registry));
+                config_reload::live_task(live_config.clone(), move |config| {
+                    ImageWatcher::new(config, github.clone(), clone_mgr.clone(), registry.clone())
+                })
+            },
+        );
+    }
+    if let Some(ref jira) = jira {
+        scheduler.add(
+            "jira-version-sync",
+            Schedule::Every(jira::maintenance::VERSION_SYNC_INTERVAL_SECS),
+            {
+                let jira = jira.clone();
+                config_reload::live_task(live_config.clone(), move |config| VersionSorter::new(config, jira.clone()))
+            },
+        );
+        scheduler.add(
+            "jira-token-refresh",
+            Schedule::Every(jira::maintenance::TOKEN_REFRESH_INTERVAL_SECS),
+            TokenRefresher::new(jira.clone()),
+        );
+    }
+    if config.user_discovery.is_some() {
+        scheduler.add(
+            "user-discovery",
+            Schedule::Every(user_discovery::SYNC_INTERVAL_SECS),
+            {
+                let (github, jira) = (github.clone(), jira.clone());
+                config_reload::live_task(live_config.clone(), move |config| {
+                    UserDiscoverer::new(config, github.clone(), jira.clone())
+                })
+            },
+        );
+    }
+    if config.label_taxonomy.is_some() {
+        scheduler.add(
+            "label-taxonomy",
+            Schedule::Every(label_taxonomy::SYNC_INTERVAL_SECS),
+            {
+                let (github, slack) = (github.clone(), github_handler_state.slack_worker.clone());
+                config_reload::live_task(live_config.clone(), move |config| {
+                    LabelSyncer::new(config, github.clone(), slack.clone())
+                })
+            },
+        );
+    }
+    if let Some(ref exporter) = github_handler_state.event_exporter {
+        scheduler.add_on_every_replica(
+            "warehouse-export",
+            Schedule::Every(config.warehouse_flush_secs()),
+            ExportFlusher::new(exporter.clone()),
+        );
+    }
+    match inbound_queue::new_consumer(&config) {
+        Ok(Some(consumer)) => scheduler.add(
+            "inbound-queue",
+            Schedule::Every(config.inbound_queue_poll_secs()),
+            QueueConsumer::new(live_config.clone(), github_handler_state.clone(), consumer),
+        ),
+        Ok(None) => (),
+        Err(e) => panic!("Error initiating inbound queue consumer: {}", e),
+    };
+    scheduler.add(
+        "webhook-retries",
+        Schedule::Every(webhook_retries::CHECK_INTERVAL_SECS),
+        RetryRunner::new(live_config.clone(), github_handler_state.clone()),
+    );
+    scheduler.add(
+        "webhook-forwards",
+        Schedule::Every(webhook_forwards::CHECK_INTERVAL_SECS),
+        ForwardRetrier::new(live_config.clone()),
+    );
+    scheduler.add_on_every_replica(
+        "ldap-health",
+        Schedule::Every(ldap_auth::HEALTH_CHECK_INTERVAL_SECS),
+        ldap_auth::HealthCheck::new(live_config.clone()),
+    );
+    scheduler.add_on_every_replica(
+        "config-reload",
+        Schedule::Every(config_reload::CHECK_INTERVAL_SECS),
+        ConfigWatcher::new(live_config.clone()),
+    );
+    for job in config.scheduled_jobs() {
+        if !job.enabled.unwrap_or(true) {
+            if scheduler.remove(&job.name) {
+                info!("Not running scheduled job {}: disabled", job.name);
+            }
+            continue;
+        }
+        let schedule = match job.cron {
+            Some(ref cron) => match Schedule::parse_cron(cron) {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    error!("Not rescheduling job {}: {}", job.name, e);
+                    continue;
+                }
+            },
+            None => None,
+        };
+        if !scheduler.reschedule(&job.name, schedule, job.jitter_secs.unwrap_or(0), db::now()) {
+            warn!("Not rescheduling job {}: there is no such job", job.name);
+        }
+    }
+    Scheduler::start(scheduler.clone());
+    if let Some(ref cluster) = github_handler_state.cluster {
+        info!("Sharing deliveries and scheduled tasks with other instances as {}", cluster.identity());
+        let consumer = QueueConsumer::shared(live_config.clone(), github_handler_state.clone(), cluster.clone());
+        ha::start(cluster.clone(), consumer);
+    }
+    if config.kubernetes.is_some() {
+        kubernetes::start(&config, leader_elector);
+    }
+
+    let main_service = OctobotService::new(
+        live_config.clone(),
+        ui_sessions.clone(),
+        github_handler_state.clone(),
+        scheduler.clone(),
+    );
+    let redirect_service = RedirectService::new(https_addr.port())
+        .with_host(config.redirect_host())
+        .with_exclusions(config.redirect_exclusions(), main_service.clone());
 
     if let Some(tls_cfg) = tls_cfg {
         // setup main service on https
@@ -112,22 +450,29 @@ fn run_server(config: Config) {
                     }
                 })
                 .filter_map(|x| x);
-            let server = Server::builder(tls).serve(main_service).map_err(|e| error!("server error: {}", e));
+            let service = make_service_fn(move |conn: &TlsStream<TcpStream, rustls::ServerSession>| {
+                future::ok::<_, hyper::Error>(main_service.for_connection(conn.get_ref().0.peer_addr().ok()))
+            });
+            let server = Server::builder(tls).serve(service).map_err(|e| error!("server error: {}", e));
             info!("Listening (HTTPS) on {}", https_addr);
             tokio::spawn(server);
         }
         // setup http redirect
         {
-            let server = Server::bind(&http_addr).serve(redirect_service).map_err(
-                |e| error!("server error: {}", e),
-            );
+            let service = make_service_fn(move |conn: &AddrStream| {
+                future::ok::<_, hyper::Error>(redirect_service.for_connection(Some(conn.remote_addr())))
+            });
+            let server = Server::bind(&http_addr).serve(service).map_err(|e| error!("server error: {}", e));
             info!("Listening (HTTP Redirect) on {}", http_addr);
             tokio::spawn(server);
         }
     } else {
         // setup main service on http
         {
-            let server = Server::bind(&http_addr).serve(main_service).map(|_| ()).map_err(
+            let service = make_service_fn(move |conn: &AddrStream| {
+                future::ok::<_, hyper::Error>(main_service.for_connection(Some(conn.remote_addr())))
+            });
+            let server = Server::bind(&http_addr).serve(service).map(|_| ()).map_err(
                 |e| error!("server error: {}", e),
             );
             info!("Listening (HTTP) on {}", http_addr);
@@ -0,0 +1,281 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+use log::error;
+use serde_derive::Deserialize;
+use serde_json;
+
+use crate::config::Config;
+use crate::server::http::{parse_json, FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::Sessions;
+use crate::users::UserInfo;
+use crate::util;
+
+pub enum MeOp {
+    Get,
+    UpdateMapping,
+    UpdatePreferences,
+}
+
+// The logged in user's own mapping and notification preferences, which they may change without asking an admin
+pub struct MeHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    op: MeOp,
+}
+
+// Fields left out stay as they are
+#[derive(Deserialize, Debug, Default, PartialEq)]
+pub struct MappingUpdate {
+    pub slack: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+pub struct PreferencesUpdate {
+    pub mute_direct_messages: Option<bool>,
+    pub muted_until: Option<i64>,
+    pub dm_events: Option<String>,
+    pub quiet_hours_start: Option<String>,
+    pub quiet_hours_end: Option<String>,
+    pub timezone: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl MeHandler {
+    pub fn new(config: Arc<Config>, sessions: Arc<Sessions>, op: MeOp) -> Box<MeHandler> {
+        Box::new(MeHandler {
+            config: config,
+            sessions: sessions,
+            op: op,
+        })
+    }
+}
+
+impl MappingUpdate {
+    // What the user would become, or why they can't: a slack user or email address can only be someone's
+    fn apply(&self, config: &Config, user: &UserInfo) -> Result<UserInfo, String> {
+        let mut updated = user.clone();
+        if let Some(ref slack) = self.slack {
+            let slack = slack.trim().trim_start_matches('@');
+            match config.users().lookup_by_slack(slack) {
+                Some(ref other) if !slack.is_empty() && other.github != user.github => {
+                    return Err(format!("Slack user {} is mapped to another github user", slack));
+                }
+                _ => updated.slack = slack.to_string(),
+            };
+        }
+        if let Some(ref email) = self.email {
+            let email = email.trim();
+            match config.users().lookup_by_email(email) {
+                Some(ref other) if other.github != user.github => {
+                    return Err(format!("{} is the email address of another github user", email));
+                }
+                _ => updated.email = email.to_string(),
+            };
+        }
+        Ok(updated)
+    }
+}
+
+impl PreferencesUpdate {
+    fn apply(&self, user: &UserInfo) -> UserInfo {
+        let mut updated = user.clone();
+        let set = |field: &mut String, value: &Option<String>| {
+            if let Some(ref v) = value {
+                *field = v.trim().to_string();
+            }
+        };
+        if let Some(mute) = self.mute_direct_messages {
+            updated.mute_direct_messages = mute;
+        }
+        if let Some(until) = self.muted_until {
+            updated.muted_until = until;
+        }
+        set(&mut updated.dm_events, &self.dm_events);
+        set(&mut updated.quiet_hours_start, &self.quiet_hours_start);
+        set(&mut updated.quiet_hours_end, &self.quiet_hours_end);
+        set(&mut updated.timezone, &self.timezone);
+        set(&mut updated.digest, &self.digest);
+        updated
+    }
+}
+
+impl Handler for MeHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let session = match login::get_session(&req).and_then(|s| self.sessions.get_session(&s)) {
+            Some(s) => s,
+            None => return self.respond_with(StatusCode::FORBIDDEN, "Invalid session"),
+        };
+        let user = match login::user_for_login(&self.config, &session.user) {
+            Some(u) => u,
+            None => {
+                let msg = format!("No octobot user is mapped to {}: ask an admin to add you", session.user);
+                return self.respond_with(StatusCode::NOT_FOUND, &msg);
+            }
+        };
+
+        match self.op {
+            MeOp::Get => self.get(&user),
+            MeOp::UpdateMapping => self.update_mapping(req, user),
+            MeOp::UpdatePreferences => self.update_preferences(req, user),
+        }
+    }
+}
+
+impl MeHandler {
+    fn get(&self, user: &UserInfo) -> FutureResponse {
+        match serde_json::to_string(user) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing user: {}", e)),
+        }
+    }
+
+    fn update_mapping(&self, req: Request<Body>, user: UserInfo) -> FutureResponse {
+        let config = self.config.clone();
+        parse_json(req, move |update: MappingUpdate| {
+            let updated = match update.apply(&config, &user) {
+                Ok(u) => u,
+                Err(e) => return util::new_msg_resp(StatusCode::CONFLICT, e),
+            };
+            if let Err(e) = updated.validate() {
+                return util::new_bad_req_resp(format!("{}", e));
+            }
+            if let Err(e) = config.users_write().update(&updated) {
+                error!("{}", e);
+                return util::new_empty_error_resp();
+            }
+
+            let details = format!(
+                "slack: '{}' -> '{}', email: '{}' -> '{}'",
+                user.slack, updated.slack, user.email, updated.email
+            );
+            if let Err(e) = config.audit.record(&user.github, "update-own-mapping", &user.github, &details) {
+                error!("{}", e);
+            }
+            respond_user(&updated)
+        })
+    }
+
+    fn update_preferences(&self, req: Request<Body>, user: UserInfo) -> FutureResponse {
+        let config = self.config.clone();
+        parse_json(req, move |update: PreferencesUpdate| {
+            let updated = update.apply(&user);
+            if let Err(e) = updated.validate() {
+                return util::new_bad_req_resp(format!("{}", e));
+            }
+
+            let mut users = config.users_write();
+            if let Err(e) = users.update(&updated) {
+                error!("{}", e);
+                return util::new_empty_error_resp();
+            }
+            if updated.muted_until != user.muted_until {
+                if let Err(e) = users.mute_until(&updated.github, updated.muted_until) {
+                    error!("{}", e);
+                    return util::new_empty_error_resp();
+                }
+            }
+            respond_user(&updated)
+        })
+    }
+}
+
+fn respond_user(user: &UserInfo) -> Response<Body> {
+    match serde_json::to_string(user) {
+        Ok(j) => util::new_json_resp(j),
+        Err(e) => {
+            error!("Error serializing user: {}", e);
+            util::new_empty_error_resp()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use tempdir::TempDir;
+
+    fn new_test() -> (Config, TempDir) {
+        let temp_dir = TempDir::new("me_handler.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        let config = Config::new(db);
+        config.users_write().insert("joe", "joe.slacker").unwrap();
+        let mut jane = UserInfo::new("jane", "jane.slacker");
+        jane.email = "jane@company.com".into();
+        config.users_write().insert_info(&jane).unwrap();
+
+        (config, temp_dir)
+    }
+
+    #[test]
+    fn test_mapping_update() {
+        let (config, _temp_dir) = new_test();
+        let joe = config.users().lookup_info("joe").unwrap();
+
+        let update = MappingUpdate {
+            slack: Some("@joe.elsewhere".into()),
+            email: Some(" joe@company.com ".into()),
+        };
+        let updated = update.apply(&config, &joe).unwrap();
+        assert_eq!("joe.elsewhere", updated.slack);
+        assert_eq!("joe@company.com", updated.email);
+        assert_eq!(joe.id, updated.id);
+
+        // the user's own are fine, someone else's aren't
+        let update = MappingUpdate {
+            slack: Some("joe.slacker".into()),
+            email: None,
+        };
+        assert_eq!("joe.slacker", update.apply(&config, &joe).unwrap().slack);
+        let update = MappingUpdate {
+            slack: Some("jane.slacker".into()),
+            email: None,
+        };
+        assert!(update.apply(&config, &joe).is_err());
+        let update = MappingUpdate {
+            slack: None,
+            email: Some("JANE@company.com".into()),
+        };
+        assert!(update.apply(&config, &joe).is_err());
+
+        // clearing them
+        let update = MappingUpdate {
+            slack: Some(String::new()),
+            email: Some(String::new()),
+        };
+        let updated = update.apply(&config, &joe).unwrap();
+        assert_eq!("", updated.slack);
+        assert_eq!("", updated.email);
+    }
+
+    #[test]
+    fn test_preferences_update() {
+        let (config, _temp_dir) = new_test();
+        let joe = config.users().lookup_info("joe").unwrap();
+
+        let update: PreferencesUpdate =
+            serde_json::from_str(r#"{"quiet_hours_start": "22:00", "quiet_hours_end": " 08:00", "digest": "daily"}"#)
+                .unwrap();
+        let updated = update.apply(&joe);
+        assert_eq!("22:00", updated.quiet_hours_start);
+        assert_eq!("08:00", updated.quiet_hours_end);
+        assert_eq!("daily", updated.digest);
+        assert_eq!(joe.slack, updated.slack);
+        assert_eq!(joe.dm_events, updated.dm_events);
+        assert!(updated.validate().is_ok());
+
+        let update = PreferencesUpdate {
+            mute_direct_messages: Some(true),
+            timezone: Some("Mars/Olympus".into()),
+            ..PreferencesUpdate::default()
+        };
+        let updated = update.apply(&joe);
+        assert_eq!(true, updated.mute_direct_messages);
+        assert!(updated.validate().is_err());
+    }
+}
@@ -1,10 +1,59 @@
+mod access_review_handler;
 mod admin;
+mod api;
+mod artifacts_handler;
+mod azure_devops_handler;
+mod bitbucket_server_handler;
+mod clone_cache_handler;
+mod commits_handler;
+mod compliance_handler;
+mod components_handler;
+mod config_export_handler;
+mod config_reload_handler;
+mod deliveries_handler;
+mod dependencies_handler;
+mod diagnostics_handler;
+mod faults_handler;
+mod federation_handler;
+mod freeze_handler;
 pub mod github_handler;
 mod github_verify;
+mod gitlab_handler;
+mod health_handler;
 mod html_handler;
-mod http;
+pub(crate) mod http;
+mod idempotency;
+mod impersonation;
+mod jira_handler;
+mod jobs_handler;
+mod jsm_handler;
+mod ldap_handler;
+mod me_handler;
 mod octobot_service;
+mod onboarding_handler;
+mod provenance_handler;
+mod queue_consumer;
+mod queues_handler;
 mod redirect_service;
+mod release_notes_handler;
+mod repo_mutes_handler;
+mod retry_runner;
+mod saml_handler;
+mod sbom_handler;
+mod scheduled_jobs_handler;
 pub mod login;
-mod sessions;
+pub mod sessions;
+mod sessions_handler;
+pub mod slack_actions;
+pub mod slack_command;
+pub mod slack_events;
+mod slack_login_handler;
+mod slack_verify;
+mod teams_handler;
+mod terraform_handler;
+mod trace_handler;
+mod user_mappings_handler;
+mod webauthn_handler;
+mod webhook_retries_handler;
+mod worktree_pools_handler;
 pub mod main;
@@ -1,38 +1,117 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use futures::future::{self, Future};
+use hyper::header::HeaderValue;
 use hyper::{self, Body, Method, Request};
 use hyper::service::{NewService, Service};
 use time;
 use log::{debug, error, info};
 
 use crate::config::Config;
+use crate::config_reload::LiveConfig;
+use crate::error_reports;
+use crate::scheduler::Scheduler;
+use crate::server::access_review_handler::{AccessReviewHandler, AccessReviewOp};
 use crate::server::admin;
 use crate::server::admin::{Op, RepoAdmin, UserAdmin};
+use crate::server::api::{self, ApiOp, OpenApiHandler};
+use crate::server::artifacts_handler::ArtifactsHandler;
+use crate::server::azure_devops_handler::AzureDevOpsHandler;
+use crate::server::bitbucket_server_handler::BitbucketServerHandler;
+use crate::server::clone_cache_handler::CloneCacheHandler;
+use crate::server::commits_handler::CommitsHandler;
+use crate::server::compliance_handler::ComplianceReportHandler;
+use crate::server::components_handler::ComponentVersionsHandler;
+use crate::server::config_export_handler::{ConfigExportHandler, ConfigExportOp};
+use crate::server::config_reload_handler::{ConfigReloadHandler, ConfigReloadOp};
+use crate::server::deliveries_handler::{DeliveriesHandler, DeliveriesOp};
+use crate::server::dependencies_handler::DependencyGraphHandler;
+use crate::server::diagnostics_handler::{EventDiagnosisHandler, EventHistoryHandler};
+use crate::server::faults_handler::{FaultsHandler, FaultsOp};
+use crate::server::federation_handler::FederationUsersHandler;
+use crate::server::freeze_handler::FreezeStatusHandler;
 use crate::server::github_handler::{GithubHandler, GithubHandlerState};
+use crate::server::gitlab_handler::GitlabHandler;
+use crate::server::health_handler::{HealthHandler, HealthOp};
 use crate::server::html_handler::HtmlHandler;
-use crate::server::http::{FilteredHandler, FutureResponse, Handler, NotFoundHandler};
-use crate::server::login::{LoginHandler, LoginSessionFilter, LogoutHandler, SessionCheckHandler};
+use crate::server::http::{FilteredHandler, FutureResponse, Handler, NotFoundHandler, RemoteAddr};
+use crate::server::idempotency::{IdempotencyKeys, IdempotentHandler};
+use crate::server::impersonation::{ImpersonationHandler, ImpersonationOp};
+use crate::server::jira_handler::JiraHandler;
+use crate::server::jobs_handler::{JobOp, JobsHandler};
+use crate::server::jsm_handler::JsmDecisionHandler;
+use crate::server::ldap_handler::LdapHealthHandler;
+use crate::server::login::{
+    LoginHandler, LoginSessionFilter, LogoutHandler, NegotiateHandler, SelfServiceLoginHandler, SessionCheckHandler,
+};
+use crate::server::me_handler::{MeHandler, MeOp};
+use crate::server::onboarding_handler::{OnboardingHandler, OnboardingOp};
+use crate::server::provenance_handler::AttestationsHandler;
+use crate::server::queues_handler::{QueuesHandler, QueuesOp};
+use crate::server::release_notes_handler::ReleaseNotesHandler;
+use crate::server::repo_mutes_handler::{RepoMutesHandler, RepoMutesOp};
+use crate::server::saml_handler::{SamlHandler, SamlOp};
+use crate::server::sbom_handler::ReleaseSbomsHandler;
+use crate::server::scheduled_jobs_handler::{ScheduledJobsHandler, ScheduledJobsOp};
 use crate::server::sessions::Sessions;
+use crate::server::sessions_handler::{SessionsHandler, SessionsOp};
+use crate::server::slack_actions::SlackActionsHandler;
+use crate::server::slack_command::SlackCommandHandler;
+use crate::server::slack_events::SlackEventsHandler;
+use crate::server::slack_login_handler::{SlackLoginHandler, SlackLoginOp};
+use crate::server::teams_handler::{TeamsHandler, TeamsOp};
+use crate::server::terraform_handler::TerraformPlanHandler;
+use crate::server::trace_handler::TraceHandler;
+use crate::server::user_mappings_handler::{UserMappingsHandler, UserMappingsOp};
+use crate::server::webauthn_handler::{WebauthnHandler, WebauthnOp};
+use crate::server::webhook_retries_handler::{WebhookRetriesHandler, WebhookRetriesOp};
+use crate::server::worktree_pools_handler::WorktreePoolsHandler;
+use crate::saml;
 use crate::util;
+use crate::webauthn::Challenges;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
 
 #[derive(Clone)]
 pub struct OctobotService {
-    config: Arc<Config>,
+    live_config: Arc<LiveConfig>,
     ui_sessions: Arc<Sessions>,
     github_handler_state: Arc<GithubHandlerState>,
+    scheduler: Arc<Scheduler>,
+    idempotency_keys: Arc<IdempotencyKeys>,
+    webauthn_challenges: Arc<Challenges>,
+    saml_requests: Arc<saml::Requests>,
+    slack_login_requests: Arc<saml::Requests>,
+    // of the connection this service was made for
+    remote_addr: Option<SocketAddr>,
 }
 
 impl OctobotService {
     pub fn new(
-        config: Arc<Config>,
+        live_config: Arc<LiveConfig>,
         ui_sessions: Arc<Sessions>,
         github_handler_state: Arc<GithubHandlerState>,
+        scheduler: Arc<Scheduler>,
     ) -> OctobotService {
         OctobotService {
-            config: config,
+            live_config: live_config,
             ui_sessions: ui_sessions,
             github_handler_state: github_handler_state,
+            scheduler: scheduler,
+            idempotency_keys: Arc::new(IdempotencyKeys::new()),
+            webauthn_challenges: Arc::new(Challenges::new()),
+            saml_requests: Arc::new(saml::Requests::new()),
+            slack_login_requests: Arc::new(saml::Requests::new()),
+            remote_addr: None,
+        }
+    }
+
+    // The service for one connection, which knows where its requests come from
+    pub fn for_connection(&self, remote_addr: Option<SocketAddr>) -> OctobotService {
+        OctobotService {
+            remote_addr: remote_addr,
+            ..self.clone()
         }
     }
 }
@@ -57,52 +136,222 @@ impl Service for OctobotService {
     type Error = hyper::Error;
     type Future = FutureResponse;
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
         let start = time::now();
+        if let Some(addr) = self.remote_addr {
+            req.extensions_mut().insert(RemoteAddr(addr));
+        }
 
         let method = req.method().clone();
         let path = req.uri().path().to_string();
-        debug!("Received request: {} {}", method, path);
 
-        Box::new(
-            self.route(&req)
-                .handle(req)
-                .map(move |res| {
+        // what is logged while handling it, including by the jobs it queues, can be looked up by the id
+        let context = error_reports::Context {
+            request_id: error_reports::new_request_id(),
+            ..error_reports::Context::default()
+        };
+        let request_id = context.request_id.clone();
+        let response = error_reports::with_context(context.clone(), || {
+            debug!("Received request: {} {}", method, path);
+            self.route(&req).handle(req)
+        });
+
+        Box::new(error_reports::in_context(
+            context,
+            response
+                .map(move |mut res| {
                     info!("{} {} {} ({})", method, path, res.status(), util::format_duration(time::now() - start));
+                    if let Ok(value) = HeaderValue::from_str(&request_id) {
+                        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+                    }
                     res
                 })
                 .or_else(move |e| {
                     error!("Error processing request: {}", e);
                     future::err(e)
                 }),
-        )
+        ))
     }
 }
 
 impl OctobotService {
     fn route(&self, req: &Request<Body>) -> Box<dyn Handler> {
+        // requests keep the config they started with, even if it is reloaded while they are handled
+        let config = self.live_config.get();
+
+        // the API's description, which needs no login
+        if req.method() == &Method::GET && req.uri().path() == api::OPENAPI_PATH {
+            return OpenApiHandler::new();
+        }
+
         // API routes
         if req.uri().path().starts_with("/api") {
             let filter = LoginSessionFilter::new(self.ui_sessions.clone());
+            let github_app = &self.github_handler_state.github_app;
+
+            let route = match api::find(req.method(), req.uri().path()) {
+                Some(r) => r,
+                None => return FilteredHandler::new(filter, Box::new(NotFoundHandler)),
+            };
+            let handler: Box<dyn Handler + Send + Sync> = match route.op {
+                ApiOp::GetMe => MeHandler::new(config.clone(), self.ui_sessions.clone(), MeOp::Get),
+                ApiOp::UpdateMyMapping => MeHandler::new(config.clone(), self.ui_sessions.clone(), MeOp::UpdateMapping),
+                ApiOp::UpdateMyPreferences => {
+                    MeHandler::new(config.clone(), self.ui_sessions.clone(), MeOp::UpdatePreferences)
+                }
+
+                ApiOp::ListUsers => UserAdmin::new(config.clone(), Op::List),
+                ApiOp::UpdateUser => UserAdmin::new(config.clone(), Op::Update),
+                ApiOp::CreateUser => UserAdmin::new(config.clone(), Op::Create),
+                ApiOp::DeleteUser => UserAdmin::new(config.clone(), Op::Delete),
+                ApiOp::ListDeletedUsers => UserAdmin::new(config.clone(), Op::ListDeleted),
+                ApiOp::RestoreUser => UserAdmin::new(config.clone(), Op::Restore),
+                ApiOp::ListUserMappings => {
+                    UserMappingsHandler::new(config.clone(), self.ui_sessions.clone(), UserMappingsOp::List)
+                }
+                ApiOp::ConfirmUserMapping => {
+                    UserMappingsHandler::new(config.clone(), self.ui_sessions.clone(), UserMappingsOp::Confirm)
+                }
+                ApiOp::RejectUserMapping => {
+                    UserMappingsHandler::new(config.clone(), self.ui_sessions.clone(), UserMappingsOp::Reject)
+                }
+
+                ApiOp::ListRepos => RepoAdmin::new(config.clone(), github_app.clone(), Op::List),
+                ApiOp::UpdateRepo => RepoAdmin::new(config.clone(), github_app.clone(), Op::Update),
+                ApiOp::CreateRepo => RepoAdmin::new(config.clone(), github_app.clone(), Op::Create),
+                ApiOp::DeleteRepo => RepoAdmin::new(config.clone(), github_app.clone(), Op::Delete),
+                ApiOp::ListDeletedRepos => RepoAdmin::new(config.clone(), github_app.clone(), Op::ListDeleted),
+                ApiOp::RestoreRepo => RepoAdmin::new(config.clone(), github_app.clone(), Op::Restore),
+
+                ApiOp::ListTeamChannels => TeamsHandler::new(config.clone(), TeamsOp::List),
+                ApiOp::SetTeamChannel => TeamsHandler::new(config.clone(), TeamsOp::Set),
+                ApiOp::RemoveTeamChannel => TeamsHandler::new(config.clone(), TeamsOp::Remove),
+
+                ApiOp::ListOnboardingPlans => {
+                    OnboardingHandler::new(config.clone(), self.ui_sessions.clone(), OnboardingOp::List)
+                }
+                ApiOp::ProposeOnboarding => {
+                    OnboardingHandler::new(config.clone(), self.ui_sessions.clone(), OnboardingOp::Propose)
+                }
+                ApiOp::UpdateOnboardingPlan => {
+                    OnboardingHandler::new(config.clone(), self.ui_sessions.clone(), OnboardingOp::Update)
+                }
+                ApiOp::ApplyOnboardingPlan => {
+                    OnboardingHandler::new(config.clone(), self.ui_sessions.clone(), OnboardingOp::Apply)
+                }
+
+                ApiOp::GetFreezes => FreezeStatusHandler::new(config.clone()),
+
+                ApiOp::ListRepoMutes => RepoMutesHandler::new(config.clone(), RepoMutesOp::List),
+                ApiOp::MuteRepo => RepoMutesHandler::new(config.clone(), RepoMutesOp::Mute),
+                ApiOp::UnmuteRepo => RepoMutesHandler::new(config.clone(), RepoMutesOp::Unmute),
 
-            return FilteredHandler::new(
-                filter,
-                match (req.method(), req.uri().path()) {
-                    (&Method::GET, "/api/users") => UserAdmin::new(self.config.clone(), Op::List),
-                    (&Method::PUT, "/api/user") => UserAdmin::new(self.config.clone(), Op::Update),
-                    (&Method::POST, "/api/users") => UserAdmin::new(self.config.clone(), Op::Create),
-                    (&Method::DELETE, "/api/user") => UserAdmin::new(self.config.clone(), Op::Delete),
+                ApiOp::ViewAs => {
+                    ImpersonationHandler::new(config.clone(), self.ui_sessions.clone(), ImpersonationOp::ViewAs)
+                }
+                ApiOp::GetAuditLog => {
+                    ImpersonationHandler::new(config.clone(), self.ui_sessions.clone(), ImpersonationOp::AuditLog)
+                }
 
-                    (&Method::GET, "/api/repos") => RepoAdmin::new(self.config.clone(), Op::List),
-                    (&Method::PUT, "/api/repo") => RepoAdmin::new(self.config.clone(), Op::Update),
-                    (&Method::POST, "/api/repos") => RepoAdmin::new(self.config.clone(), Op::Create),
-                    (&Method::DELETE, "/api/repo") => RepoAdmin::new(self.config.clone(), Op::Delete),
+                ApiOp::GetAccessReview => {
+                    AccessReviewHandler::new(config.clone(), self.ui_sessions.clone(), AccessReviewOp::Report)
+                }
+                ApiOp::RevokeSession => {
+                    AccessReviewHandler::new(config.clone(), self.ui_sessions.clone(), AccessReviewOp::RevokeSession)
+                }
+                ApiOp::RevokeAccount => {
+                    AccessReviewHandler::new(config.clone(), self.ui_sessions.clone(), AccessReviewOp::RevokeAccount)
+                }
+                ApiOp::ReinstateAccount => {
+                    AccessReviewHandler::new(config.clone(), self.ui_sessions.clone(), AccessReviewOp::ReinstateAccount)
+                }
 
-                    (&Method::POST, "/api/merge-versions") => admin::MergeVersions::new(self.config.clone()),
+                ApiOp::ListSessions => SessionsHandler::new(config.clone(), self.ui_sessions.clone(), SessionsOp::List),
+                ApiOp::EndSession => SessionsHandler::new(config.clone(), self.ui_sessions.clone(), SessionsOp::End),
+                ApiOp::EndUserSessions => {
+                    SessionsHandler::new(config.clone(), self.ui_sessions.clone(), SessionsOp::EndForUser)
+                }
+                ApiOp::EndOtherSessions => {
+                    SessionsHandler::new(config.clone(), self.ui_sessions.clone(), SessionsOp::EndOthers)
+                }
 
-                    _ => Box::new(NotFoundHandler),
-                },
-            );
+                ApiOp::BeginSecurityKeyRegistration => self.webauthn(config.clone(), WebauthnOp::RegisterBegin),
+                ApiOp::FinishSecurityKeyRegistration => self.webauthn(config.clone(), WebauthnOp::RegisterFinish),
+                ApiOp::ListSecurityKeys => self.webauthn(config.clone(), WebauthnOp::List),
+                ApiOp::RemoveSecurityKey => self.webauthn(config.clone(), WebauthnOp::Remove),
+
+                ApiOp::ListFaults => FaultsHandler::new(config.clone(), self.ui_sessions.clone(), FaultsOp::List),
+                ApiOp::InjectFault => FaultsHandler::new(config.clone(), self.ui_sessions.clone(), FaultsOp::Inject),
+                ApiOp::ClearFaults => FaultsHandler::new(config.clone(), self.ui_sessions.clone(), FaultsOp::Clear),
+
+                ApiOp::DiagnoseEvent => EventDiagnosisHandler::new(config.clone()),
+                ApiOp::ListEvents => EventHistoryHandler::new(config.clone()),
+                ApiOp::GetComponentVersions => ComponentVersionsHandler::new(config.clone()),
+                ApiOp::GetDependencyGraph => DependencyGraphHandler::new(config.clone()),
+                ApiOp::ListSboms => ReleaseSbomsHandler::new(config.clone()),
+                ApiOp::ListAttestations => AttestationsHandler::new(config.clone()),
+                ApiOp::GetReleaseNotes => ReleaseNotesHandler::new(
+                    config.clone(),
+                    github_app.clone(),
+                    self.github_handler_state.jira_session.clone(),
+                ),
+                ApiOp::GetComplianceReport => ComplianceReportHandler::new(config.clone()),
+                ApiOp::GetArtifact => ArtifactsHandler::new(config.clone()),
+                ApiOp::GetCommit => CommitsHandler::new(config.clone(), route),
+
+                ApiOp::MergeVersions => admin::MergeVersions::new(config.clone()),
+                ApiOp::ValidateJiraTransitions => admin::ValidateTransitions::new(config.clone()),
+                ApiOp::TestTemplate => admin::TestTemplate::new(),
+
+                ApiOp::ListWorktreePools => WorktreePoolsHandler::new(self.github_handler_state.clone_mgr.clone()),
+                ApiOp::GetLdapHealth => LdapHealthHandler::new(config.clone()),
+                ApiOp::GetCloneCache => CloneCacheHandler::new(self.github_handler_state.clone_mgr.clone()),
+                ApiOp::GetConfigReloadStatus => {
+                    ConfigReloadHandler::new(self.live_config.clone(), ConfigReloadOp::Status)
+                }
+                ApiOp::ReloadConfig => ConfigReloadHandler::new(self.live_config.clone(), ConfigReloadOp::Reload),
+                ApiOp::ValidateConfig => ConfigReloadHandler::new(self.live_config.clone(), ConfigReloadOp::Validate),
+                ApiOp::ExportConfig => ConfigExportHandler::new(config.clone(), ConfigExportOp::Export),
+                ApiOp::ImportConfig => ConfigExportHandler::new(config.clone(), ConfigExportOp::Import),
+                ApiOp::ListWebhookRetries => WebhookRetriesHandler::new(
+                    self.live_config.clone(),
+                    self.github_handler_state.clone(),
+                    WebhookRetriesOp::List,
+                ),
+                ApiOp::RetryWebhook => WebhookRetriesHandler::new(
+                    self.live_config.clone(),
+                    self.github_handler_state.clone(),
+                    WebhookRetriesOp::Retry,
+                ),
+                ApiOp::RemoveWebhookRetry => WebhookRetriesHandler::new(
+                    self.live_config.clone(),
+                    self.github_handler_state.clone(),
+                    WebhookRetriesOp::Remove,
+                ),
+                ApiOp::ListWebhookForwards => WebhookRetriesHandler::new(
+                    self.live_config.clone(),
+                    self.github_handler_state.clone(),
+                    WebhookRetriesOp::ListForwards,
+                ),
+                ApiOp::ListDeliveries => {
+                    DeliveriesHandler::new(config.clone(), self.ui_sessions.clone(), DeliveriesOp::List)
+                }
+                ApiOp::GetDeliveryStats => {
+                    DeliveriesHandler::new(config.clone(), self.ui_sessions.clone(), DeliveriesOp::Stats)
+                }
+                ApiOp::GetTrace => TraceHandler::new(config.clone(), self.ui_sessions.clone(), route),
+                ApiOp::ListQueues => QueuesHandler::new(self.github_handler_state.queues.clone(), QueuesOp::List),
+                ApiOp::ListScheduledJobs => ScheduledJobsHandler::new(self.scheduler.clone(), ScheduledJobsOp::List),
+                ApiOp::RunScheduledJob => ScheduledJobsHandler::new(self.scheduler.clone(), ScheduledJobsOp::Run),
+                ApiOp::ListJobs => JobsHandler::new(config.clone(), JobOp::List),
+                ApiOp::GetJob => JobsHandler::new(config.clone(), JobOp::Get),
+                ApiOp::CancelJob => JobsHandler::new(config.clone(), JobOp::Cancel),
+                ApiOp::StreamJobEvents => JobsHandler::new(config.clone(), JobOp::Events),
+            };
+
+            // retried mutations with the same Idempotency-Key get the original response
+            let handler = IdempotentHandler::new(self.idempotency_keys.clone(), self.ui_sessions.clone(), handler);
+            return FilteredHandler::new(filter, handler);
         }
 
         // static routes
@@ -124,15 +373,93 @@ impl OctobotService {
             }
             (&Method::GET, "/app.js") => HtmlHandler::new("app.js", include_str!("../../src/assets/app.js")),
 
+            // probes
+            (&Method::GET, "/healthz") => HealthHandler::new(HealthOp::Live),
+            (&Method::GET, "/readyz") => HealthHandler::new(HealthOp::Ready),
+            (&Method::GET, "/metrics") => {
+                QueuesHandler::new(self.github_handler_state.queues.clone(), QueuesOp::Metrics)
+            }
+
             // auth
-            (&Method::POST, "/auth/login") => LoginHandler::new(self.ui_sessions.clone(), self.config.clone()),
+            (&Method::POST, "/auth/login") => LoginHandler::new(self.ui_sessions.clone(), self.live_config.clone()),
+            (&Method::GET, "/auth/negotiate") => NegotiateHandler::new(self.ui_sessions.clone(), config.clone()),
+            (&Method::POST, "/auth/webauthn/login/begin") => self.webauthn(config.clone(), WebauthnOp::LoginBegin),
+            (&Method::POST, "/auth/webauthn/login/finish") => self.webauthn(config.clone(), WebauthnOp::LoginFinish),
+            (&Method::GET, "/auth/saml/metadata") => self.saml(config.clone(), SamlOp::Metadata),
+            (&Method::GET, "/auth/saml/login") => self.saml(config.clone(), SamlOp::Login),
+            (&Method::POST, "/auth/saml/acs") => self.saml(config.clone(), SamlOp::Acs),
+            (&Method::POST, "/auth/me/login") => SelfServiceLoginHandler::new(self.ui_sessions.clone(), config.clone()),
+            (&Method::GET, "/auth/slack/login") => self.slack_login(config.clone(), SlackLoginOp::Login),
+            (&Method::GET, "/auth/slack/callback") => self.slack_login(config.clone(), SlackLoginOp::Callback),
             (&Method::POST, "/auth/check") => SessionCheckHandler::new(self.ui_sessions.clone()),
             (&Method::POST, "/auth/logout") => LogoutHandler::new(self.ui_sessions.clone()),
 
             // hooks
-            (&Method::POST, "/hooks/github") => GithubHandler::from_state(self.github_handler_state.clone()),
+            (&Method::POST, "/hooks/github") => {
+                GithubHandler::from_state(self.github_handler_state.clone(), config.clone())
+            }
+            (&Method::POST, "/hooks/azure-devops") => AzureDevOpsHandler::new(
+                config.clone(),
+                self.github_handler_state.slack_worker.clone(),
+                self.github_handler_state.jira_session.clone(),
+            ),
+            (&Method::POST, "/hooks/bitbucket-server") => BitbucketServerHandler::new(
+                config.clone(),
+                self.github_handler_state.slack_worker.clone(),
+                self.github_handler_state.jira_session.clone(),
+            ),
+            (&Method::POST, "/hooks/gitlab") => GitlabHandler::new(
+                config.clone(),
+                self.github_handler_state.slack_worker.clone(),
+                self.github_handler_state.jira_session.clone(),
+            ),
+            (&Method::POST, "/hooks/jira") => {
+                JiraHandler::new(config.clone(), self.github_handler_state.slack_worker.clone())
+            }
+            (&Method::POST, "/hooks/terraform") => TerraformPlanHandler::new(
+                config.clone(),
+                self.github_handler_state.github_app.clone(),
+                self.github_handler_state.slack_worker.clone(),
+            ),
+            (&Method::POST, "/hooks/jsm") => JsmDecisionHandler::new(
+                config.clone(),
+                self.github_handler_state.github_app.clone(),
+                self.github_handler_state.jsm_session.clone(),
+            ),
+            (&Method::POST, "/hooks/slack/actions") => SlackActionsHandler::new(
+                config.clone(),
+                self.github_handler_state.github_app.clone(),
+                self.github_handler_state.team_members.clone(),
+                self.github_handler_state.opsgenie_session.clone(),
+            ),
+            (&Method::POST, "/hooks/slack/command") => SlackCommandHandler::new(
+                config.clone(),
+                self.github_handler_state.github_app.clone(),
+                self.github_handler_state.team_members.clone(),
+            ),
+            (&Method::POST, "/hooks/slack/events") => SlackEventsHandler::new(
+                config.clone(),
+                self.github_handler_state.workflow_step_worker.clone(),
+                self.github_handler_state.reaction_worker.clone(),
+                self.github_handler_state.slack_bridge_worker.clone(),
+            ),
+
+            // other octobot instances
+            (&Method::GET, "/federation/users") => FederationUsersHandler::new(config.clone()),
 
             _ => Box::new(NotFoundHandler),
         }
     }
+
+    fn webauthn(&self, config: Arc<Config>, op: WebauthnOp) -> Box<dyn Handler + Send + Sync> {
+        WebauthnHandler::new(config, self.ui_sessions.clone(), self.webauthn_challenges.clone(), op)
+    }
+
+    fn saml(&self, config: Arc<Config>, op: SamlOp) -> Box<dyn Handler> {
+        SamlHandler::new(config, self.ui_sessions.clone(), self.saml_requests.clone(), op)
+    }
+
+    fn slack_login(&self, config: Arc<Config>, op: SlackLoginOp) -> Box<dyn Handler> {
+        SlackLoginHandler::new(config, self.ui_sessions.clone(), self.slack_login_requests.clone(), op)
+    }
 }
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+use crate::config::Config;
+use crate::db;
+use crate::github::api::GithubSession;
+use crate::onboarding::{self, OnboardingPlan, RepoProposal, TeamProposal};
+use crate::server::http::{parse_json, FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::Sessions;
+use crate::slack::SlackWebApi;
+use crate::util;
+
+pub const APPLY_ONBOARDING_ACTION: &str = "apply-onboarding-plan";
+
+pub enum OnboardingOp {
+    List,
+    Propose,
+    Update,
+    Apply,
+}
+
+// Onboards a whole github org: proposes channels for its repos and teams, for admins to edit and apply
+pub struct OnboardingHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    op: OnboardingOp,
+}
+
+#[derive(Serialize)]
+struct PlansResp {
+    plans: Vec<OnboardingPlan>,
+}
+
+// The org token is only used for this request, never stored
+#[derive(Deserialize)]
+struct ProposeReq {
+    org: String,
+    token: String,
+    webhook_url: String,
+}
+
+#[derive(Deserialize)]
+struct UpdateReq {
+    id: i32,
+    repos: Vec<RepoProposal>,
+    teams: Vec<TeamProposal>,
+}
+
+#[derive(Deserialize)]
+struct ApplyReq {
+    id: i32,
+    token: String,
+}
+
+#[derive(Serialize)]
+struct ApplyResp {
+    results: Vec<String>,
+}
+
+impl OnboardingHandler {
+    pub fn new(config: Arc<Config>, sessions: Arc<Sessions>, op: OnboardingOp) -> Box<OnboardingHandler> {
+        Box::new(OnboardingHandler {
+            config: config,
+            sessions: sessions,
+            op: op,
+        })
+    }
+}
+
+impl Handler for OnboardingHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let session = match login::get_admin_session(&self.sessions, &req) {
+            Some(s) => s,
+            None => return self.respond_with(StatusCode::FORBIDDEN, "Only admins may do this"),
+        };
+
+        let query = util::parse_query(req.uri().query());
+        match &self.op {
+            &OnboardingOp::List => self.list(query.get("id")),
+            &OnboardingOp::Propose => self.propose(req, session.user),
+            &OnboardingOp::Update => self.update(req),
+            &OnboardingOp::Apply => self.apply(req, session.user),
+        }
+    }
+}
+
+impl OnboardingHandler {
+    fn list(&self, id: Option<&String>) -> FutureResponse {
+        let plans = match id {
+            Some(id) => match id.parse::<i32>() {
+                Ok(id) => self.config.onboarding_plans.get(id).map(|p| p.into_iter().collect()),
+                Err(_) => return self.respond(util::new_bad_req_resp(format!("Invalid id: {}", id))),
+            },
+            None => self.config.onboarding_plans.list(),
+        };
+        let plans = match plans {
+            Ok(p) => p,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&PlansResp { plans: plans }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing onboarding plans: {}", e)),
+        }
+    }
+
+    fn propose(&self, req: Request<Body>, admin: String) -> FutureResponse {
+        let config = self.config.clone();
+
+        parse_json(req, move |body: ProposeReq| {
+            if body.org.is_empty() || body.org.contains('/') || body.token.is_empty() || body.webhook_url.is_empty() {
+                return util::new_bad_req_resp("Expected an `org`, a `token` and a `webhook_url`");
+            }
+            let slack_token = match config.slack_bot_token() {
+                Some(t) => t,
+                None => return util::new_bad_req_resp("Onboarding needs main.slack_bot_token to list channels"),
+            };
+
+            let plan = onboarding::slack_channels(&SlackWebApi::new(&slack_token)).and_then(|channels| {
+                let github = GithubSession::new(&config.github_host(&body.org), "", &body.token, None)?;
+                onboarding::propose(&config, &github, &body.org, &body.webhook_url, &channels, &admin, db::now())
+            });
+            let plan = match plan {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("Error proposing onboarding plan for {}: {}", body.org, e);
+                    return util::new_error_resp(format!("{}", e));
+                }
+            };
+
+            info!("{} proposed onboarding plan {} for {}", admin, plan.id, plan.org);
+            match serde_json::to_string(&plan) {
+                Ok(j) => util::new_json_resp(j),
+                Err(e) => util::new_error_resp(format!("Error serializing onboarding plan: {}", e)),
+            }
+        })
+    }
+
+    fn update(&self, req: Request<Body>) -> FutureResponse {
+        let config = self.config.clone();
+
+        parse_json(req, move |body: UpdateReq| {
+            match config.onboarding_plans.update(body.id, &body.repos, &body.teams) {
+                Ok(true) => util::new_empty_resp(StatusCode::OK),
+                Ok(false) => util::new_bad_req_resp("No such plan, or it was already applied"),
+                Err(e) => {
+                    error!("{}", e);
+                    util::new_empty_error_resp()
+                }
+            }
+        })
+    }
+
+    fn apply(&self, req: Request<Body>, admin: String) -> FutureResponse {
+        let config = self.config.clone();
+
+        parse_json(req, move |body: ApplyReq| {
+            let plan = match config.onboarding_plans.get(body.id) {
+                Ok(Some(p)) => p,
+                Ok(None) => return util::new_msg_resp(StatusCode::NOT_FOUND, "No such plan"),
+                Err(e) => return util::new_error_resp(format!("{}", e)),
+            };
+            if plan.state != onboarding::PROPOSED {
+                return util::new_bad_req_resp(format!("The plan was already {}", plan.state));
+            }
+
+            let results = GithubSession::new(&config.github_host(&plan.org), "", &body.token, None)
+                .and_then(|github| onboarding::apply(&config, &github, &plan, &admin, db::now()));
+            let results = match results {
+                Ok(r) => r,
+                Err(e) => {
+                    error!("Error applying onboarding plan {}: {}", plan.id, e);
+                    return util::new_error_resp(format!("{}", e));
+                }
+            };
+
+            let details = format!("{} repos, {} teams", plan.repos.len(), plan.teams.len());
+            if let Err(e) = config.audit.record(&admin, APPLY_ONBOARDING_ACTION, &plan.org, &details) {
+                error!("{}", e);
+            }
+            match serde_json::to_string(&ApplyResp { results: results }) {
+                Ok(j) => util::new_json_resp(j),
+                Err(e) => util::new_error_resp(format!("Error serializing onboarding results: {}", e)),
+            }
+        })
+    }
+}
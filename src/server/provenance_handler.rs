@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use serde_json;
+
+use crate::config::Config;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// Provenance attestations of a repo's releases, newest first, or of a single release if `tag` is given.
+pub struct AttestationsHandler {
+    config: Arc<Config>,
+}
+
+impl AttestationsHandler {
+    pub fn new(config: Arc<Config>) -> Box<AttestationsHandler> {
+        Box::new(AttestationsHandler { config: config })
+    }
+}
+
+impl Handler for AttestationsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        let repo = match query.get("repo") {
+            Some(r) if !r.is_empty() => r.clone(),
+            _ => return self.respond(util::new_bad_req_resp("No `repo` param specified")),
+        };
+
+        let json = match query.get("tag") {
+            Some(tag) => match self.config.attestations.get(&repo, tag) {
+                Ok(Some(a)) => serde_json::to_string(&a),
+                Ok(None) => {
+                    let msg = format!("No attestation of {} {}", repo, tag);
+                    return self.respond(util::new_msg_resp(StatusCode::NOT_FOUND, msg));
+                }
+                Err(e) => return self.respond_error(&format!("{}", e)),
+            },
+            None => match self.config.attestations.get_all(&repo) {
+                Ok(v) => serde_json::to_string(&v),
+                Err(e) => return self.respond_error(&format!("{}", e)),
+            },
+        };
+
+        match json {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing attestations: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use futures::Future;
+use hyper::StatusCode;
+use log::{error, info};
+
+use crate::config_reload::LiveConfig;
+use crate::errors::*;
+use crate::inbound_queue::{Consumer, Envelope, Message};
+use crate::kubernetes;
+use crate::scheduler::Task;
+use crate::server::github_handler::{GithubHandler, GithubHandlerState};
+use crate::server::http::Handler;
+use crate::worker::InFlight;
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Ack,
+    Retry,
+}
+
+// Handled deliveries are acked, and so are those that would be refused again (bad signature, invalid JSON, or
+// already seen). Anything else is delivered again.
+pub fn outcome(status: StatusCode) -> Outcome {
+    if status.is_success() || status.is_client_error() {
+        Outcome::Ack
+    } else {
+        Outcome::Retry
+    }
+}
+
+// Hands github deliveries from the inbound queue to the github handler, as if github had sent them
+pub struct QueueConsumer {
+    live_config: Arc<LiveConfig>,
+    state: Arc<GithubHandlerState>,
+    consumer: Arc<dyn Consumer>,
+    // the queue shared with other instances, whose deliveries were deduplicated before they were queued
+    shared: bool,
+}
+
+impl QueueConsumer {
+    pub fn new(
+        live_config: Arc<LiveConfig>,
+        state: Arc<GithubHandlerState>,
+        consumer: Arc<dyn Consumer>,
+    ) -> Arc<dyn Task> {
+        Arc::new(QueueConsumer {
+            live_config: live_config,
+            state: state,
+            consumer: consumer,
+            shared: false,
+        })
+    }
+
+    pub fn shared(
+        live_config: Arc<LiveConfig>,
+        state: Arc<GithubHandlerState>,
+        consumer: Arc<dyn Consumer>,
+    ) -> Arc<dyn Task> {
+        Arc::new(QueueConsumer {
+            live_config: live_config,
+            state: state,
+            consumer: consumer,
+            shared: true,
+        })
+    }
+
+    fn deliver(&self, message: &Message) -> StatusCode {
+        let _in_flight = InFlight::start();
+        let req = match Envelope::parse(&message.data).and_then(|e| e.into_request()) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("{}", e);
+                return StatusCode::BAD_REQUEST;
+            }
+        };
+        let handler = if self.shared {
+            GithubHandler::for_queue(self.state.clone(), self.live_config.get())
+        } else {
+            GithubHandler::from_state(self.state.clone(), self.live_config.get())
+        };
+        match handler.handle(req).wait() {
+            Ok(resp) => resp.status(),
+            Err(e) => {
+                error!("Error handling queued delivery {}: {}", message.id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+impl Task for QueueConsumer {
+    // Keeps going until the queue is empty, or a delivery has to be retried, or this replica is stopping
+    fn run(&self, _now: i64) -> Result<()> {
+        let mut count = 0;
+        'receiving: loop {
+            if kubernetes::is_draining() {
+                break;
+            }
+            let messages = self.consumer.receive()?;
+            if messages.is_empty() {
+                break;
+            }
+
+            for message in &messages {
+                let status = self.deliver(message);
+                match outcome(status) {
+                    Outcome::Ack => {
+                        if !status.is_success() {
+                            error!("Dropping queued delivery {}: {}", message.id, status);
+                        }
+                        self.consumer.ack(message)?;
+                        count += 1;
+                    }
+                    Outcome::Retry => {
+                        error!("Queued delivery {} failed ({}): will retry", message.id, status);
+                        self.consumer.retry(message)?;
+                        break 'receiving;
+                    }
+                }
+            }
+        }
+
+        if count > 0 {
+            info!("Consumed {} queued deliveries", count);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(Outcome::Ack, outcome(StatusCode::OK));
+        assert_eq!(Outcome::Ack, outcome(StatusCode::FORBIDDEN));
+        assert_eq!(Outcome::Ack, outcome(StatusCode::BAD_REQUEST));
+        assert_eq!(Outcome::Retry, outcome(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(Outcome::Retry, outcome(StatusCode::SERVICE_UNAVAILABLE));
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::faults::Service;
+use crate::resilience::{self, CallStats};
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+use crate::worker::{self, QueueStats, WorkQueues};
+
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+pub enum QueuesOp {
+    // JSON, for /api/queues
+    List,
+    // prometheus text format, for /metrics
+    Metrics,
+}
+
+// How backed up the worker queues are
+pub struct QueuesHandler {
+    queues: Arc<WorkQueues>,
+    op: QueuesOp,
+}
+
+#[derive(Serialize)]
+struct QueuesResp {
+    queues: Vec<QueueStats>,
+    in_flight: usize,
+}
+
+impl QueuesHandler {
+    pub fn new(queues: Arc<WorkQueues>, op: QueuesOp) -> Box<QueuesHandler> {
+        Box::new(QueuesHandler {
+            queues: queues,
+            op: op,
+        })
+    }
+}
+
+impl Handler for QueuesHandler {
+    fn handle(&self, _req: Request<Body>) -> FutureResponse {
+        let resp = QueuesResp {
+            queues: self.queues.stats(),
+            in_flight: worker::in_flight(),
+        };
+        match self.op {
+            QueuesOp::List => match serde_json::to_string(&resp) {
+                Ok(j) => self.respond(util::new_json_resp(j)),
+                Err(e) => self.respond_error(&format!("Error serializing queues: {}", e)),
+            },
+            QueuesOp::Metrics => {
+                let out = metrics(&resp.queues, resp.in_flight) + &client_metrics(&resilience::call_stats());
+                let mut resp = util::new_msg_resp(StatusCode::OK, out);
+                resp.headers_mut().insert(hyper::header::CONTENT_TYPE, METRICS_CONTENT_TYPE.parse().unwrap());
+                self.respond(resp)
+            }
+        }
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, queues: &[QueueStats], value: fn(&QueueStats) -> f64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for queue in queues {
+        let _ = writeln!(out, "{}{{queue=\"{}\"}} {}", name, queue.name, value(queue));
+    }
+}
+
+fn metrics(queues: &[QueueStats], in_flight: usize) -> String {
+    let mut out = String::new();
+    metric(&mut out, "octobot_queue_capacity", "gauge", "Jobs the queue holds.", queues, |q| q.capacity as f64);
+    metric(&mut out, "octobot_queue_depth", "gauge", "Jobs waiting or running.", queues, |q| q.depth as f64);
+    metric(
+        &mut out,
+        "octobot_queue_oldest_age_seconds",
+        "gauge",
+        "How long the oldest job has been waiting or running.",
+        queues,
+        |q| q.oldest_age_ms as f64 / 1000.0,
+    );
+    metric(&mut out, "octobot_queue_processed_total", "counter", "Jobs done.", queues, |q| q.processed as f64);
+    metric(
+        &mut out,
+        "octobot_queue_dropped_total",
+        "counter",
+        "Jobs refused because the queue was full.",
+        queues,
+        |q| q.dropped as f64,
+    );
+
+    let latency = "octobot_queue_latency_seconds";
+    let _ = writeln!(out, "# HELP {} Time from being queued to being done.", latency);
+    let _ = writeln!(out, "# TYPE {} summary", latency);
+    for queue in queues {
+        let _ = writeln!(out, "{}_sum{{queue=\"{}\"}} {}", latency, queue.name, queue.total_latency_ms as f64 / 1000.0);
+        let _ = writeln!(out, "{}_count{{queue=\"{}\"}} {}", latency, queue.name, queue.processed);
+    }
+
+    let _ = writeln!(out, "# HELP octobot_jobs_in_flight Jobs started but not done, across all queues.");
+    let _ = writeln!(out, "# TYPE octobot_jobs_in_flight gauge");
+    let _ = writeln!(out, "octobot_jobs_in_flight {}", in_flight);
+    out
+}
+
+// How many requests went to slack, github and JIRA, e.g. to compare load tests by
+fn client_metrics(calls: &[(Service, CallStats)]) -> String {
+    let mut out = String::new();
+    let requests = "octobot_client_requests_total";
+    let _ = writeln!(out, "# HELP {} Requests made to the service.", requests);
+    let _ = writeln!(out, "# TYPE {} counter", requests);
+    for (service, stats) in calls {
+        let _ = writeln!(out, "{}{{service=\"{}\"}} {}", requests, service_name(*service), stats.requests);
+    }
+
+    let unavailable = "octobot_client_unavailable_total";
+    let _ = writeln!(out, "# HELP {} Requests that found the service down or overloaded.", unavailable);
+    let _ = writeln!(out, "# TYPE {} counter", unavailable);
+    for (service, stats) in calls {
+        let _ = writeln!(out, "{}{{service=\"{}\"}} {}", unavailable, service_name(*service), stats.unavailable);
+    }
+    out
+}
+
+fn service_name(service: Service) -> String {
+    format!("{:?}", service).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let queues = vec![QueueStats {
+            name: "slack".into(),
+            capacity: 1000,
+            depth: 2,
+            oldest_age_ms: 1500,
+            processed: 10,
+            dropped: 0,
+            total_latency_ms: 2500,
+            last_latency_ms: 200,
+        }];
+
+        let out = metrics(&queues, 3);
+        assert!(out.contains("# TYPE octobot_queue_depth gauge\noctobot_queue_depth{queue=\"slack\"} 2\n"));
+        assert!(out.contains("octobot_queue_oldest_age_seconds{queue=\"slack\"} 1.5\n"));
+        assert!(out.contains("octobot_queue_latency_seconds_sum{queue=\"slack\"} 2.5\n"));
+        assert!(out.contains("octobot_queue_latency_seconds_count{queue=\"slack\"} 10\n"));
+        assert!(out.ends_with("octobot_jobs_in_flight 3\n"));
+    }
+
+    #[test]
+    fn test_client_metrics() {
+        let calls = vec![
+            (Service::Slack, CallStats { requests: 12, unavailable: 2 }),
+            (Service::Github, CallStats { requests: 30, unavailable: 0 }),
+        ];
+
+        let out = client_metrics(&calls);
+        assert!(out.contains("# TYPE octobot_client_requests_total counter\n"));
+        assert!(out.contains("octobot_client_requests_total{service=\"slack\"} 12\n"));
+        assert!(out.contains("octobot_client_requests_total{service=\"github\"} 30\n"));
+        assert!(out.ends_with("octobot_client_unavailable_total{service=\"github\"} 0\n"));
+    }
+}
@@ -1,26 +1,63 @@
-use futures::future::{self, FutureResult};
+use std::net::SocketAddr;
+
+use futures::future::{self, Future};
 use http::header::{HeaderMap, HeaderValue};
-use hyper::{self, Body, Request, Response};
+use hyper::{self, Body, Client, Request, Response};
 use hyper::{StatusCode, Uri};
 use hyper::header::{HOST, LOCATION};
 use hyper::service::{NewService, Service};
 use log::{debug, error};
 
+use crate::config::RedirectExclusion;
+use crate::server::http::FutureResponse;
+use crate::server::octobot_service::OctobotService;
 use crate::util;
 
+// Redirects plain HTTP requests to https, except for the excluded paths
 #[derive(Clone)]
 pub struct RedirectService {
     https_port: u16,
+    // redirect to this host (and port) rather than the one that was requested
+    host: Option<String>,
+    exclusions: Vec<RedirectExclusion>,
+    // answers the excluded paths that aren't proxied
+    main: Option<OctobotService>,
 }
 
 impl RedirectService {
     pub fn new(https_port: u16) -> RedirectService {
-        RedirectService { https_port: https_port }
+        RedirectService {
+            https_port: https_port,
+            host: None,
+            exclusions: vec![],
+            main: None,
+        }
+    }
+
+    pub fn with_host(mut self, host: Option<String>) -> RedirectService {
+        self.host = host;
+        self
+    }
+
+    pub fn with_exclusions(mut self, exclusions: Vec<RedirectExclusion>, main: OctobotService) -> RedirectService {
+        self.exclusions = exclusions;
+        self.main = Some(main);
+        self
+    }
+
+    // The service for one connection, which knows where its requests come from
+    pub fn for_connection(&self, remote_addr: Option<SocketAddr>) -> RedirectService {
+        RedirectService {
+            main: self.main.as_ref().map(|m| m.for_connection(remote_addr)),
+            ..self.clone()
+        }
     }
 
     fn rewrite_uri(&self, uri: Uri, host_header: Option<Uri>) -> String {
         let mut new_url = String::from("https://");
-        if let Some(host) = uri.host() {
+        if let Some(ref host) = self.host {
+            new_url += host;
+        } else if let Some(host) = uri.host() {
             new_url += host;
             self.maybe_add_port(&mut new_url, uri.port_part())
         } else if let Some(host_header) = host_header {
@@ -43,6 +80,50 @@ impl RedirectService {
             new_url.push_str(&format!(":{}", self.https_port));
         }
     }
+
+    fn exclusion(&self, path: &str) -> Option<&RedirectExclusion> {
+        self.exclusions.iter().find(|e| path.starts_with(&e.path))
+    }
+
+    fn redirect(&self, req: &Request<Body>) -> Response<Body> {
+        let host_header = get_host_header(&req.headers());
+
+        let new_uri_str = self.rewrite_uri(req.uri().clone(), host_header);
+        let new_uri = match HeaderValue::from_str(&new_uri_str) {
+            Err(e) => {
+                error!("Invalid Location header '{}': {}", new_uri_str, e);
+                return util::new_empty_resp(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+            Ok(uri) => uri,
+        };
+
+        debug!("Redirecting request to {}", new_uri_str);
+        let mut resp = util::new_empty_resp(StatusCode::MOVED_PERMANENTLY);
+        resp.headers_mut().insert(LOCATION, new_uri);
+        resp
+    }
+}
+
+// Sends the request on to |upstream| as it is, with the host that was requested
+fn proxy(upstream: &str, mut req: Request<Body>) -> FutureResponse {
+    let url = format!("{}{}", upstream.trim_end_matches('/'), proxied_path(req.uri()));
+    match url.parse::<Uri>() {
+        Ok(uri) => *req.uri_mut() = uri,
+        Err(e) => {
+            error!("Invalid proxy url '{}': {}", url, e);
+            return Box::new(future::ok(util::new_empty_resp(StatusCode::BAD_GATEWAY)));
+        }
+    };
+
+    debug!("Proxying request to {}", url);
+    Box::new(Client::new().request(req).or_else(move |e| {
+        error!("Error proxying request to {}: {}", url, e);
+        future::ok::<_, hyper::Error>(util::new_empty_resp(StatusCode::BAD_GATEWAY))
+    }))
+}
+
+fn proxied_path(uri: &Uri) -> &str {
+    uri.path_and_query().map(|p| p.as_str()).unwrap_or("/")
 }
 
 impl NewService for RedirectService {
@@ -62,25 +143,21 @@ impl Service for RedirectService {
     type ReqBody = Body;
     type ResBody = Body;
     type Error = hyper::Error;
-    type Future = FutureResult<Response<Body>, hyper::Error>;
+    type Future = FutureResponse;
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
-        let host_header = get_host_header(&req.headers());
-
-        let new_uri_str = self.rewrite_uri(req.uri().clone(), host_header);
-        let new_uri = match HeaderValue::from_str(&new_uri_str) {
-            Err(e) => {
-                error!("Invalid Location header '{}': {}", new_uri_str, e);
-                return future::ok(util::new_empty_resp(StatusCode::INTERNAL_SERVER_ERROR));
-            }
-            Ok(uri) => uri,
+        let upstream = match self.exclusion(req.uri().path()) {
+            None => return Box::new(future::ok(self.redirect(&req))),
+            Some(exclusion) => exclusion.proxy.clone(),
         };
 
-        debug!("Redirecting request to {}", new_uri_str);
-        let mut resp = util::new_empty_resp(StatusCode::MOVED_PERMANENTLY);
-        resp.headers_mut().insert(LOCATION, new_uri);
-
-        future::ok(resp)
+        if let Some(upstream) = upstream {
+            return proxy(&upstream, req);
+        }
+        match self.main {
+            Some(ref mut main) => main.call(req),
+            None => Box::new(future::ok(util::new_empty_resp(StatusCode::NOT_FOUND))),
+        }
     }
 }
 
@@ -144,4 +221,53 @@ mod tests {
             service.rewrite_uri(uri, get_host_header(&headers))
         );
     }
+
+    #[test]
+    fn test_rewrite_uri_configured_host() {
+        let service = RedirectService::new(99).with_host(Some("octobot.company.com:8443".into()));
+        let uri = Uri::from_str("http://host.foo.com:20/path/to/thing?param=value").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, "other.com:20".parse().unwrap());
+
+        assert_eq!(
+            "https://octobot.company.com:8443/path/to/thing?param=value",
+            service.rewrite_uri(uri, get_host_header(&headers))
+        );
+    }
+
+    #[test]
+    fn test_exclusions() {
+        let mut service = RedirectService {
+            exclusions: vec![
+                RedirectExclusion {
+                    path: "/.well-known/acme-challenge/".into(),
+                    proxy: Some("http://localhost:8402".into()),
+                },
+                RedirectExclusion {
+                    path: "/healthz".into(),
+                    proxy: None,
+                },
+            ],
+            ..RedirectService::new(99)
+        };
+
+        assert_eq!(
+            Some("http://localhost:8402".to_string()),
+            service.exclusion("/.well-known/acme-challenge/abcd").and_then(|e| e.proxy.clone())
+        );
+        assert_eq!(None, service.exclusion("/.well-known/other"));
+        assert_eq!(
+            "/.well-known/acme-challenge/abcd?x=y",
+            proxied_path(&Uri::from_str("http://other.com/.well-known/acme-challenge/abcd?x=y").unwrap())
+        );
+
+        let req = |path: &str| Request::get(path).header(HOST, "other.com").body(Body::empty()).unwrap();
+        let resp = service.call(req("/login?next=/repos")).wait().unwrap();
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, resp.status());
+        assert_eq!("https://other.com/login?next=/repos", resp.headers()[LOCATION]);
+
+        // without octobot to answer them
+        let resp = service.call(req("/healthz")).wait().unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
 }
@@ -0,0 +1,105 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use hyper::{header, Body, Request, Response};
+use serde_json;
+
+use crate::config::Config;
+use crate::github;
+use crate::github::api::GithubSessionFactory;
+use crate::jira;
+use crate::release_notes;
+use crate::server::http::{FutureResponse, Handler};
+use crate::storage;
+use crate::util;
+
+// Release notes for `repo` (owner/name) covering the changes in `to` since `from` (refs or tags).
+// `format` is json (the default) or markdown, ready for the body of the github release.
+pub struct ReleaseNotesHandler {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    jira_session: Option<Arc<dyn jira::api::Session>>,
+}
+
+impl ReleaseNotesHandler {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        jira_session: Option<Arc<dyn jira::api::Session>>,
+    ) -> Box<ReleaseNotesHandler> {
+        Box::new(ReleaseNotesHandler {
+            config: config,
+            github_app: github_app,
+            jira_session: jira_session,
+        })
+    }
+}
+
+impl Handler for ReleaseNotesHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        let param = |name: &str| query.get(name).filter(|v| !v.is_empty()).cloned();
+        let (repo, from, to) = match (param("repo"), param("from"), param("to")) {
+            (Some(r), Some(f), Some(t)) => (r, f, t),
+            _ => return self.respond(util::new_bad_req_resp("Expected `repo`, `from` and `to` params")),
+        };
+
+        let repo = match github::Repo::parse(&format!("https://{}/{}", self.config.github_host(&repo), repo)) {
+            Ok(r) => r,
+            Err(e) => return self.respond(util::new_bad_req_resp(format!("Invalid repo {}: {}", repo, e))),
+        };
+        let session = match self.github_app.new_session(repo.owner.login(), &repo.name) {
+            Ok(s) => s,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+        let jira = match (&self.jira_session, &self.config.jira) {
+            (Some(s), Some(c)) => Some((s.deref(), c)),
+            _ => None,
+        };
+        let projects = self.config.repos().all_jira_projects(&repo);
+
+        let notes =
+            match release_notes::generate(&session, &self.config.merges, jira, &repo, &projects, &from, &to) {
+                Ok(n) => n,
+                Err(e) => return self.respond_error(&format!("{}", e)),
+            };
+
+        let (body, content_type, ext) = match query.get("format").map(|f| f.as_str()).unwrap_or("json") {
+            "markdown" => (notes.to_markdown(), "text/markdown", "md"),
+            "json" => match serde_json::to_string(&notes) {
+                Ok(j) => (j, "application/json", "json"),
+                Err(e) => return self.respond_error(&format!("Error serializing release notes: {}", e)),
+            },
+            other => {
+                return self.respond(util::new_bad_req_resp(format!(
+                    "Unknown format (expected json or markdown): {}",
+                    other
+                )))
+            }
+        };
+
+        // with `save=true`, the notes are also kept in the configured storage
+        let mut location = None;
+        if query.get("save").map(|s| s == "true").unwrap_or(false) {
+            let key = format!(
+                "release-notes/{}/{}/{}...{}.{}",
+                repo.owner.login(),
+                repo.name,
+                storage::key_part(&from),
+                storage::key_part(&to),
+                ext
+            );
+            if let Err(e) = storage::save_artifact(&self.config, &key, body.as_bytes()) {
+                return self.respond_error(&format!("Error saving release notes: {}", e));
+            }
+            location = Some(storage::artifact_location(&key));
+        }
+
+        let mut resp = Response::new(Body::from(body));
+        resp.headers_mut().insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+        if let Some(location) = location {
+            resp.headers_mut().insert(header::CONTENT_LOCATION, location.parse().unwrap());
+        }
+        self.respond(resp)
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+use crate::config::Config;
+use crate::db;
+use crate::repo_mutes::RepoMute;
+use crate::server::http::{parse_json, FutureResponse, Handler};
+use crate::util;
+
+pub enum RepoMutesOp {
+    List,
+    Mute,
+    Unmute,
+}
+
+// Repos whose notifications are held back, e.g. while a planned mass refactoring is pushed
+pub struct RepoMutesHandler {
+    config: Arc<Config>,
+    op: RepoMutesOp,
+}
+
+#[derive(Serialize)]
+struct RepoMutesResp {
+    mutes: Vec<RepoMute>,
+}
+
+// `start` defaults to now. Both are unix timestamps.
+#[derive(Deserialize)]
+struct MuteReq {
+    repo: String,
+    start: Option<i64>,
+    until: i64,
+}
+
+#[derive(Deserialize)]
+struct UnmuteReq {
+    repo: String,
+}
+
+impl RepoMutesHandler {
+    pub fn new(config: Arc<Config>, op: RepoMutesOp) -> Box<RepoMutesHandler> {
+        Box::new(RepoMutesHandler { config: config, op: op })
+    }
+}
+
+impl Handler for RepoMutesHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        match &self.op {
+            &RepoMutesOp::List => self.list(),
+            &RepoMutesOp::Mute => self.mute(req),
+            &RepoMutesOp::Unmute => self.unmute(req),
+        }
+    }
+}
+
+impl RepoMutesHandler {
+    fn list(&self) -> FutureResponse {
+        let mutes = match self.config.repo_mutes.get_all() {
+            Ok(m) => m,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&RepoMutesResp { mutes: mutes }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing repo mutes: {}", e)),
+        }
+    }
+
+    fn mute(&self, req: Request<Body>) -> FutureResponse {
+        let config = self.config.clone();
+
+        parse_json(req, move |mute: MuteReq| {
+            let now = db::now();
+            let start = mute.start.unwrap_or(now).max(now);
+            if !mute.repo.contains('/') || mute.until <= start {
+                return util::new_bad_req_resp("Expected a `repo` like \"org/repo\" and an `until` after `start`");
+            }
+            if let Err(e) = config.repo_mutes.mute(&mute.repo, start, mute.until, "api") {
+                error!("{}", e);
+                return util::new_empty_error_resp();
+            }
+
+            info!(
+                "Muted {} from {} until {}",
+                mute.repo,
+                util::format_timestamp(start),
+                util::format_timestamp(mute.until)
+            );
+            util::new_empty_resp(StatusCode::OK)
+        })
+    }
+
+    fn unmute(&self, req: Request<Body>) -> FutureResponse {
+        let config = self.config.clone();
+
+        parse_json(req, move |unmute: UnmuteReq| match config.repo_mutes.end(&unmute.repo, db::now()) {
+            Ok(true) => {
+                info!("Unmuted {}", unmute.repo);
+                util::new_empty_resp(StatusCode::OK)
+            }
+            Ok(false) => util::new_bad_req_resp(format!("{} is not muted", unmute.repo)),
+            Err(e) => {
+                error!("{}", e);
+                util::new_empty_error_resp()
+            }
+        })
+    }
+}
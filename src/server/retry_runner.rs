@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use futures::Future;
+use hyper::StatusCode;
+use log::{error, info};
+
+use crate::config_reload::LiveConfig;
+use crate::errors::*;
+use crate::scheduler::Task;
+use crate::server::github_handler::{GithubHandler, GithubHandlerState};
+use crate::server::http::Handler;
+use crate::webhook_retries::FailedDelivery;
+use crate::worker::InFlight;
+
+// Hands a failed delivery to the github handler again. It's forgotten if it's handled this time, and queued for
+// another attempt (or dead-lettered) if it fails the same way.
+pub fn redeliver(state: Arc<GithubHandlerState>, live_config: &LiveConfig, delivery: &FailedDelivery) -> StatusCode {
+    let _in_flight = InFlight::start();
+    let req = match delivery.envelope.clone().into_request() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Error retrying delivery {}: {}", delivery.delivery_id, e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    let handler = GithubHandler::for_retry(state, live_config.get());
+    match handler.handle(req).wait() {
+        Ok(resp) => resp.status(),
+        Err(e) => {
+            error!("Error retrying delivery {}: {}", delivery.delivery_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Retries the failed webhook deliveries that are due
+pub struct RetryRunner {
+    live_config: Arc<LiveConfig>,
+    state: Arc<GithubHandlerState>,
+}
+
+impl RetryRunner {
+    pub fn new(live_config: Arc<LiveConfig>, state: Arc<GithubHandlerState>) -> Arc<dyn Task> {
+        Arc::new(RetryRunner {
+            live_config: live_config,
+            state: state,
+        })
+    }
+}
+
+impl Task for RetryRunner {
+    fn run(&self, now: i64) -> Result<()> {
+        let due = self.live_config.get().webhook_retries.due(now)?;
+        for delivery in &due {
+            let status = redeliver(self.state.clone(), &self.live_config, delivery);
+            info!("Retried delivery {} (attempt {}): {}", delivery.delivery_id, delivery.attempts + 1, status);
+        }
+        Ok(())
+    }
+}
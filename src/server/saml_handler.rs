@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE, LOCATION};
+use hyper::{Body, Request, Response, StatusCode};
+use log::{error, info, warn};
+use serde_json::json;
+use url::form_urlencoded;
+
+use crate::config::{Config, SamlConfig};
+use crate::db;
+use crate::ldap_auth::{self, Role};
+use crate::saml::{self, Requests};
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::{Client, Sessions};
+use crate::util;
+
+pub enum SamlOp {
+    Metadata,
+    Login,
+    Acs,
+}
+
+// Single sign-on through a SAML identity provider: octobot's metadata, sending users to the IdP to log in, and the
+// assertion consumer service (ACS) the IdP sends them back to
+pub struct SamlHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    requests: Arc<Requests>,
+    op: SamlOp,
+}
+
+impl SamlHandler {
+    pub fn new(config: Arc<Config>, sessions: Arc<Sessions>, requests: Arc<Requests>, op: SamlOp) -> Box<SamlHandler> {
+        Box::new(SamlHandler {
+            config: config,
+            sessions: sessions,
+            requests: requests,
+            op: op,
+        })
+    }
+}
+
+impl Handler for SamlHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let saml = match self.config.saml {
+            Some(ref s) => s.clone(),
+            None => return self.respond_with(StatusCode::NOT_FOUND, "SAML is not configured"),
+        };
+
+        match self.op {
+            SamlOp::Metadata => {
+                let mut resp = Response::new(Body::from(saml::metadata(&saml)));
+                resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/samlmetadata+xml"));
+                self.respond(resp)
+            }
+            SamlOp::Login => self.login(&saml),
+            SamlOp::Acs => self.acs(req, saml),
+        }
+    }
+}
+
+// The page the IdP's form post ends on: it keeps the session, like the login page does, and goes on to the UI
+pub fn session_page(session: &str, user: &str) -> Response<Body> {
+    // JSON is javascript, but "</script>" in it would end the script early
+    let value = |v: &str| json!(v).to_string().replace('<', "\\u003c");
+    let page = format!(
+        "<!DOCTYPE html>\n<html><head><script>\nsessionStorage['session'] = {};\nsessionStorage['username'] = {};\n\
+         window.location.replace('/');\n</script></head><body></body></html>\n",
+        value(session),
+        value(user)
+    );
+
+    let mut resp = Response::new(Body::from(page));
+    resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+    resp.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    resp
+}
+
+impl SamlHandler {
+    fn login(&self, saml: &SamlConfig) -> FutureResponse {
+        let request_id = self.requests.start();
+        let url = match saml::login_url(saml, &request_id, db::now()) {
+            Ok(u) => u,
+            Err(e) => return self.respond_error(&format!("Error starting SAML login: {}", e)),
+        };
+        let location = match HeaderValue::from_str(&url) {
+            Ok(l) => l,
+            Err(e) => return self.respond_error(&format!("Invalid SAML login URL: {}", e)),
+        };
+
+        let mut resp = util::new_empty_resp(StatusCode::FOUND);
+        resp.headers_mut().insert(LOCATION, location);
+        self.respond(resp)
+    }
+
+    fn acs(&self, req: Request<Body>, saml: SamlConfig) -> FutureResponse {
+        let config = self.config.clone();
+        let sessions = self.sessions.clone();
+        let requests = self.requests.clone();
+        let client = Client::from_request(&req);
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            let encoded = match form_urlencoded::parse(&body).find(|&(ref k, _)| k == "SAMLResponse") {
+                Some((_, v)) => v.into_owned(),
+                None => return util::new_bad_req_resp("No SAMLResponse"),
+            };
+            let xml = match base64::decode(&encoded.replace(char::is_whitespace, "")).map(String::from_utf8) {
+                Ok(Ok(x)) => x,
+                _ => return util::new_bad_req_resp("Invalid SAMLResponse"),
+            };
+            let idp_key = match saml::idp_key(&saml) {
+                Ok(k) => k,
+                Err(e) => {
+                    error!("{}", e);
+                    return util::new_empty_error_resp();
+                }
+            };
+
+            let login = match saml::verify_response(&saml, &idp_key, &xml, db::now()) {
+                Ok(l) => l,
+                Err(e) => {
+                    warn!("SAML auth failure: {}", e);
+                    return util::new_empty_resp(StatusCode::UNAUTHORIZED);
+                }
+            };
+            if !requests.take(&login.request_id) {
+                warn!("SAML auth failure for {}: unknown or expired request {}", login.user, login.request_id);
+                return util::new_msg_resp(StatusCode::UNAUTHORIZED, "The login expired: try again");
+            }
+
+            let is_admin = match ldap_auth::mapped_role(&saml.group_roles, &login.groups) {
+                Some(role) => role == Role::Admin,
+                None if ldap_auth::maps_user_role(&saml.group_roles) => {
+                    warn!("Login refused: {} is in none of the SAML groups", login.user);
+                    return util::new_empty_resp(StatusCode::FORBIDDEN);
+                }
+                None => false,
+            };
+            match login::start_session(&config, &sessions, &login.user, is_admin, false, client) {
+                Some(session) => {
+                    info!("SAML auth success for user: {}", login.user);
+                    session_page(&session, &login.user)
+                }
+                None => util::new_empty_resp(StatusCode::UNAUTHORIZED),
+            }
+        }))
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request};
+use serde_json;
+
+use crate::config::Config;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+// The ledger of SBOMs attached to a repo's releases, newest first.
+pub struct ReleaseSbomsHandler {
+    config: Arc<Config>,
+}
+
+impl ReleaseSbomsHandler {
+    pub fn new(config: Arc<Config>) -> Box<ReleaseSbomsHandler> {
+        Box::new(ReleaseSbomsHandler { config: config })
+    }
+}
+
+impl Handler for ReleaseSbomsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        let repo = match query.get("repo") {
+            Some(r) if !r.is_empty() => r.clone(),
+            _ => return self.respond(util::new_bad_req_resp("No `repo` param specified")),
+        };
+
+        let sboms = match self.config.sboms.get_all(&repo) {
+            Ok(v) => v,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&sboms) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing SBOMs: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::db;
+use crate::scheduler::{JobInfo, Scheduler};
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+
+pub enum ScheduledJobsOp {
+    List,
+    Run,
+}
+
+// The scheduled jobs, when they run next and how their last run went, and running them by hand
+pub struct ScheduledJobsHandler {
+    scheduler: Arc<Scheduler>,
+    op: ScheduledJobsOp,
+}
+
+#[derive(Serialize)]
+struct ScheduledJobsResp {
+    jobs: Vec<JobInfo>,
+}
+
+impl ScheduledJobsHandler {
+    pub fn new(scheduler: Arc<Scheduler>, op: ScheduledJobsOp) -> Box<ScheduledJobsHandler> {
+        Box::new(ScheduledJobsHandler {
+            scheduler: scheduler,
+            op: op,
+        })
+    }
+}
+
+impl Handler for ScheduledJobsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        match self.op {
+            ScheduledJobsOp::List => {
+                match serde_json::to_string(&ScheduledJobsResp { jobs: self.scheduler.jobs() }) {
+                    Ok(j) => self.respond(util::new_json_resp(j)),
+                    Err(e) => self.respond_error(&format!("Error serializing scheduled jobs: {}", e)),
+                }
+            }
+            ScheduledJobsOp::Run => {
+                let query = util::parse_query(req.uri().query());
+                let name = match query.get("name") {
+                    Some(n) => n,
+                    None => return self.respond(util::new_bad_req_resp("No `name` param specified")),
+                };
+                match self.scheduler.trigger(name, db::now()) {
+                    Ok(true) => self.respond_with(StatusCode::ACCEPTED, "Started"),
+                    Ok(false) => self.respond_with(StatusCode::CONFLICT, "Still running from before"),
+                    Err(e) => self.respond_with(StatusCode::NOT_FOUND, &format!("{}", e)),
+                }
+            }
+        }
+    }
+}
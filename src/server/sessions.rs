@@ -1,9 +1,16 @@
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::RwLock;
 use std::time::{Duration, Instant};
 
+use hyper::{Body, Request};
+use ring::digest;
 use ring::rand::SecureRandom;
 use ring::rand::SystemRandom;
 use rustc_serialize::hex::ToHex;
+use serde_derive::Serialize;
+
+use crate::db;
+use crate::server::http::RemoteAddr;
 
 static SESSION_EXPIRY_SECS: u64 = 15 * 60;
 static PRUNE_SECS: u64 = 30;
@@ -13,11 +20,72 @@ pub struct Sessions {
     last_pruned: RwLock<Instant>,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionInfo {
+    pub user: String,
+    pub admin: bool,
+    // may only see and change the user's own settings, under /api/me
+    pub self_service: bool,
+}
+
+// Where a session was started from, so that admins can tell a stolen session from its user's
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Client {
+    // the address of the connection, which is a load balancer's if there is one
+    pub ip: Option<String>,
+    // the X-Forwarded-For header, as the client or load balancer sent it
+    pub forwarded_for: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+// A live session as shown to the admin: identified by a handle, since the id itself is a credential
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SessionRecord {
+    pub handle: String,
+    pub user: String,
+    pub admin: bool,
+    pub self_service: bool,
+    pub started_at: i64,
+    pub last_used: i64,
+    #[serde(flatten)]
+    pub client: Client,
+}
+
 struct Session {
     id: String,
+    user: String,
+    // Logged in as the configured (super) admin, or as an LDAP user in a group with the admin role
+    admin: bool,
+    // Logged in through /auth/me/login or with slack, as the github user to show and change the settings of
+    self_service: bool,
     // Note: could change this to last_accessed, but then we'd have to worry about max
     // session time too. Keep it simple for now.
     created_at: Instant,
+    // wall clock times, for access reviews
+    started_at: i64,
+    last_used: AtomicI64,
+    client: Client,
+}
+
+// Safe to show or log: a session can't be recovered from its handle
+pub fn handle(sess_id: &str) -> String {
+    digest::digest(&digest::SHA256, sess_id.as_bytes()).as_ref()[..8].to_hex()
+}
+
+impl Client {
+    pub fn from_request(req: &Request<Body>) -> Client {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .map(|h| String::from_utf8_lossy(h.as_bytes()).into_owned())
+                .filter(|h| !h.is_empty())
+        };
+        Client {
+            ip: req.extensions().get::<RemoteAddr>().map(|a| a.0.ip().to_string()),
+            forwarded_for: header("x-forwarded-for"),
+            user_agent: header("user-agent"),
+        }
+    }
 }
 
 impl Sessions {
@@ -28,7 +96,16 @@ impl Sessions {
         }
     }
 
-    pub fn new_session(&self) -> String {
+    pub fn new_session(&self, user: &str, admin: bool, client: Client) -> String {
+        self.add_session(user, admin, false, client)
+    }
+
+    // A session for a user to manage their own settings, and nothing else
+    pub fn new_self_service_session(&self, user: &str, client: Client) -> String {
+        self.add_session(user, false, true, client)
+    }
+
+    fn add_session(&self, user: &str, admin: bool, self_service: bool, client: Client) -> String {
         let mut bytes: [u8; 32] = [0; 32];
         // Doesn't look like SecureRandom, but docs claim it is.
         SystemRandom::new().fill(&mut bytes).expect("get random");
@@ -36,7 +113,13 @@ impl Sessions {
         let sess_id = bytes.to_hex();
         let session = Session {
             id: sess_id.clone(),
+            user: user.into(),
+            admin: admin,
+            self_service: self_service,
             created_at: Instant::now(),
+            started_at: db::now(),
+            last_used: AtomicI64::new(db::now()),
+            client: client,
         };
 
         self.sessions.write().unwrap().push(session);
@@ -53,7 +136,69 @@ impl Sessions {
         self.prune(); // maybe prune out old sessions first
 
         let sessions = self.sessions.read().unwrap();
-        sessions.iter().find(|s| s.id == sess_id).is_some()
+        match sessions.iter().find(|s| s.id == sess_id) {
+            Some(s) => {
+                s.last_used.store(db::now(), Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_session(&self, sess_id: &str) -> Option<SessionInfo> {
+        self.prune();
+
+        let sessions = self.sessions.read().unwrap();
+        sessions.iter().find(|s| s.id == sess_id).map(|s| {
+            s.last_used.store(db::now(), Ordering::Relaxed);
+            SessionInfo {
+                user: s.user.clone(),
+                admin: s.admin,
+                self_service: s.self_service,
+            }
+        })
+    }
+
+    pub fn list(&self) -> Vec<SessionRecord> {
+        self.prune();
+
+        let sessions = self.sessions.read().unwrap();
+        sessions
+            .iter()
+            .map(|s| SessionRecord {
+                handle: handle(&s.id),
+                user: s.user.clone(),
+                admin: s.admin,
+                self_service: s.self_service,
+                started_at: s.started_at,
+                last_used: s.last_used.load(Ordering::Relaxed),
+                client: s.client.clone(),
+            })
+            .collect()
+    }
+
+    // Ends the session with the given handle, returning who it belonged to
+    pub fn revoke(&self, sess_handle: &str) -> Option<String> {
+        let mut sessions = self.sessions.write().unwrap();
+        let user = sessions.iter().find(|s| handle(&s.id) == sess_handle).map(|s| s.user.clone());
+        sessions.retain(|s| handle(&s.id) != sess_handle);
+        user
+    }
+
+    // Ends all of a user's sessions, returning how many there were
+    pub fn remove_user_sessions(&self, user: &str) -> usize {
+        let mut sessions = self.sessions.write().unwrap();
+        let count = sessions.len();
+        sessions.retain(|s| s.user != user);
+        count - sessions.len()
+    }
+
+    // Ends every session but the given one, e.g. after a credential leaked, returning how many there were
+    pub fn remove_other_sessions(&self, sess_id: &str) -> usize {
+        let mut sessions = self.sessions.write().unwrap();
+        let count = sessions.len();
+        sessions.retain(|s| s.id == sess_id);
+        count - sessions.len()
     }
 
     fn needs_prune(&self) -> bool {
@@ -80,15 +225,26 @@ mod tests {
     #[test]
     fn test_sessions() {
         let sessions = Sessions::new();
-        let sess1 = sessions.new_session();
-        let sess2 = sessions.new_session();
+        let sess1 = sessions.new_session("admin", true, Client::default());
+        let sess2 = sessions.new_session("joe", false, Client::default());
 
         assert_eq!(true, sessions.is_valid_session(&sess1));
         assert_eq!(true, sessions.is_valid_session(&sess2));
 
+        assert_eq!(
+            Some(SessionInfo {
+                user: "admin".into(),
+                admin: true,
+                self_service: false,
+            }),
+            sessions.get_session(&sess1)
+        );
+        assert_eq!(false, sessions.get_session(&sess2).unwrap().admin);
+
         sessions.remove_session(&sess1);
 
         assert_eq!(false, sessions.is_valid_session(&sess1));
+        assert_eq!(None, sessions.get_session(&sess1));
         assert_eq!(true, sessions.is_valid_session(&sess2));
 
         sessions.remove_session(&sess2);
@@ -96,11 +252,91 @@ mod tests {
         assert_eq!(false, sessions.is_valid_session(&sess2));
     }
 
+    #[test]
+    fn test_sessions_list_and_revoke() {
+        let sessions = Sessions::new();
+        let sess1 = sessions.new_session("admin", true, Client::default());
+        let sess2 = sessions.new_session("joe", false, Client::default());
+        let sess3 = sessions.new_session("joe", false, Client::default());
+
+        let list = sessions.list();
+        assert_eq!(3, list.len());
+        assert_eq!(handle(&sess1), list[0].handle);
+        assert_eq!(16, list[0].handle.len());
+        assert!(!list.iter().any(|s| s.handle == sess1));
+        assert_eq!(vec!["admin", "joe", "joe"], list.iter().map(|s| s.user.as_str()).collect::<Vec<_>>());
+
+        assert_eq!(Some("admin".to_string()), sessions.revoke(&handle(&sess1)));
+        assert_eq!(None, sessions.revoke(&handle(&sess1)));
+        assert_eq!(false, sessions.is_valid_session(&sess1));
+
+        assert_eq!(2, sessions.remove_user_sessions("joe"));
+        assert_eq!(false, sessions.is_valid_session(&sess2));
+        assert_eq!(false, sessions.is_valid_session(&sess3));
+        assert!(sessions.list().is_empty());
+    }
+
+    #[test]
+    fn test_self_service_sessions() {
+        let sessions = Sessions::new();
+        let sess = sessions.new_self_service_session("joe", Client::default());
+        assert_eq!(true, sessions.is_valid_session(&sess));
+        assert_eq!(
+            Some(SessionInfo {
+                user: "joe".into(),
+                admin: false,
+                self_service: true,
+            }),
+            sessions.get_session(&sess)
+        );
+        assert_eq!(true, sessions.list()[0].self_service);
+    }
+
+    #[test]
+    fn test_remove_other_sessions() {
+        let sessions = Sessions::new();
+        let sess1 = sessions.new_session("admin", true, Client::default());
+        let sess2 = sessions.new_session("joe", false, Client::default());
+        let sess3 = sessions.new_session("admin", true, Client::default());
+
+        assert_eq!(2, sessions.remove_other_sessions(&sess3));
+        assert_eq!(false, sessions.is_valid_session(&sess1));
+        assert_eq!(false, sessions.is_valid_session(&sess2));
+        assert_eq!(true, sessions.is_valid_session(&sess3));
+        assert_eq!(0, sessions.remove_other_sessions(&sess3));
+        assert_eq!(vec![handle(&sess3)], sessions.list().into_iter().map(|s| s.handle).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_session_client() {
+        let mut req = Request::builder()
+            .header("User-Agent", "Mozilla/5.0")
+            .header("X-Forwarded-For", "203.0.113.7, 10.0.0.2")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(RemoteAddr("10.0.0.1:54321".parse().unwrap()));
+
+        let client = Client::from_request(&req);
+        assert_eq!(
+            Client {
+                ip: Some("10.0.0.1".into()),
+                forwarded_for: Some("203.0.113.7, 10.0.0.2".into()),
+                user_agent: Some("Mozilla/5.0".into()),
+            },
+            client
+        );
+        assert_eq!(Client::default(), Client::from_request(&Request::new(Body::empty())));
+
+        let sessions = Sessions::new();
+        sessions.new_session("joe", false, client.clone());
+        assert_eq!(client, sessions.list()[0].client);
+    }
+
     #[test]
     fn test_sessions_timeout() {
         let sessions = Sessions::new();
 
-        let sess = sessions.new_session();
+        let sess = sessions.new_session("admin", true, Client::default());
         assert_eq!(true, sessions.is_valid_session(&sess));
 
         // reset only last prune time. not enough.
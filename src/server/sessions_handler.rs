@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use log::{error, info};
+use serde_json::json;
+
+use crate::access_review;
+use crate::config::Config;
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::{self, Sessions};
+use crate::util;
+
+pub enum SessionsOp {
+    List,
+    End,
+    EndForUser,
+    EndOthers,
+}
+
+// Admin only: the live sessions, and ending them, e.g. when one was stolen
+pub struct SessionsHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    op: SessionsOp,
+}
+
+impl SessionsHandler {
+    pub fn new(config: Arc<Config>, sessions: Arc<Sessions>, op: SessionsOp) -> Box<SessionsHandler> {
+        Box::new(SessionsHandler {
+            config: config,
+            sessions: sessions,
+            op: op,
+        })
+    }
+}
+
+impl Handler for SessionsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let session = match login::get_admin_session(&self.sessions, &req) {
+            Some(s) => s,
+            None => return self.respond_with(StatusCode::FORBIDDEN, "Only admins may do this"),
+        };
+        let session_id = login::get_session(&req).unwrap_or_default();
+
+        let mut query = util::parse_query(req.uri().query());
+        match &self.op {
+            &SessionsOp::List => self.list(query.remove("user"), &session_id),
+            &SessionsOp::End => match query.remove("handle") {
+                Some(handle) => self.end(&handle, &session.user),
+                None => self.respond(util::new_bad_req_resp("No `handle` specified")),
+            },
+            &SessionsOp::EndForUser => match query.remove("user") {
+                Some(user) => self.end_for_user(&user, &session.user),
+                None => self.respond(util::new_bad_req_resp("No `user` specified")),
+            },
+            &SessionsOp::EndOthers => self.end_others(&session_id, &session.user),
+        }
+    }
+}
+
+impl SessionsHandler {
+    // `current` is the caller's own session
+    fn list(&self, user: Option<String>, session_id: &str) -> FutureResponse {
+        let mut sessions = self.sessions.list();
+        if let Some(user) = user {
+            sessions.retain(|s| s.user == user);
+        }
+        let current = sessions::handle(session_id);
+        self.respond(util::new_json_resp(json!({ "sessions": sessions, "current": current }).to_string()))
+    }
+
+    fn end(&self, handle: &str, admin: &str) -> FutureResponse {
+        let user = match self.sessions.revoke(handle) {
+            Some(u) => u,
+            None => return self.respond_with(StatusCode::NOT_FOUND, "No such session"),
+        };
+
+        if let Err(e) = self.config.audit.record(admin, access_review::REVOKE_SESSION_ACTION, &user, handle) {
+            error!("{}", e);
+        }
+        info!("{} ended a session of {}", admin, user);
+        self.respond(util::new_empty_resp(StatusCode::OK))
+    }
+
+    fn end_for_user(&self, user: &str, admin: &str) -> FutureResponse {
+        let count = self.sessions.remove_user_sessions(user);
+        if count == 0 {
+            return self.respond_with(StatusCode::NOT_FOUND, "No sessions for this user");
+        }
+
+        let audit = &self.config.audit;
+        if let Err(e) = audit.record(admin, access_review::REVOKE_USER_SESSIONS_ACTION, user, &count.to_string()) {
+            error!("{}", e);
+        }
+        info!("{} ended {} sessions of {}", admin, count, user);
+        self.respond(util::new_json_resp(json!({ "ended": count }).to_string()))
+    }
+
+    // Everyone has to log in again, except the admin doing this
+    fn end_others(&self, session_id: &str, admin: &str) -> FutureResponse {
+        let count = self.sessions.remove_other_sessions(session_id);
+
+        // all sessions, and which one was kept
+        let details = format!("{} ended, kept {}", count, sessions::handle(session_id));
+        if let Err(e) = self.config.audit.record(admin, access_review::REVOKE_OTHER_SESSIONS_ACTION, "*", &details) {
+            error!("{}", e);
+        }
+        info!("{} ended all {} other sessions", admin, count);
+        self.respond(util::new_json_resp(json!({ "ended": count }).to_string()))
+    }
+}
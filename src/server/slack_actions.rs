@@ -0,0 +1,569 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::{Body, Request, Response, StatusCode};
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use url::form_urlencoded;
+
+use crate::alerts;
+use crate::auto_merge::AutoMerge;
+use crate::command_permissions;
+use crate::config::Config;
+use crate::db;
+use crate::errors::*;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::opsgenie;
+use crate::pr_revert;
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::slack_verify::SlackRequestVerifier;
+use crate::slack::{SlackAction, SlackAttachmentBuilder};
+use crate::slack_workflows::{self, SlackWorkflowApi, StepInput};
+use crate::teams::TeamMembers;
+use crate::users::UserInfo;
+use crate::util;
+
+pub const APPROVE_ACTION: &str = "approve";
+pub const MERGE_ACTION: &str = "merge";
+pub const REVERT_ACTION: &str = "revert";
+
+const PR_CALLBACK_PREFIX: &str = "pr:";
+
+// Adds "Approve", "Merge when green" and "Open in GitHub" buttons to a PR attachment.
+pub fn add_pr_actions(builder: &mut SlackAttachmentBuilder, repo: &github::Repo, pull_request: &github::PullRequest) {
+    builder
+        .callback_id(pr_callback_id(&repo.full_name, pull_request.number))
+        .action(SlackAction::button(APPROVE_ACTION, "Approve"))
+        .action(SlackAction::button(MERGE_ACTION, "Merge when green"))
+        .action(SlackAction::link("Open in GitHub", &pull_request.html_url));
+}
+
+// Adds "Revert" and "Open in GitHub" buttons to a merged PR's attachment.
+pub fn add_merged_pr_actions(
+    builder: &mut SlackAttachmentBuilder,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+) {
+    builder
+        .callback_id(pr_callback_id(&repo.full_name, pull_request.number))
+        .action(SlackAction::button(REVERT_ACTION, "Revert"))
+        .action(SlackAction::link("Open in GitHub", &pull_request.html_url));
+}
+
+pub fn pr_callback_id(repo: &str, number: u32) -> String {
+    format!("{}{}#{}", PR_CALLBACK_PREFIX, repo, number)
+}
+
+// Returns ((owner, repo), number)
+pub fn parse_pr_callback_id(callback_id: &str) -> Option<((String, String), u32)> {
+    if !callback_id.starts_with(PR_CALLBACK_PREFIX) {
+        return None;
+    }
+    let mut parts = callback_id[PR_CALLBACK_PREFIX.len()..].splitn(2, '#');
+    let repo = parts.next()?;
+    let number = parts.next()?.parse::<u32>().ok()?;
+
+    let mut repo_parts = repo.splitn(2, '/');
+    let owner = repo_parts.next()?;
+    let name = repo_parts.next()?;
+    if owner.is_empty() || name.is_empty() {
+        return None;
+    }
+
+    Some(((owner.into(), name.into()), number))
+}
+
+// Either a legacy attachment action or a Block Kit "block_actions" payload
+#[derive(Deserialize, Debug)]
+pub struct ActionPayload {
+    #[serde(default)]
+    pub callback_id: String,
+    pub actions: Vec<ActionValue>,
+    pub user: SlackUser,
+    pub channel: Option<SlackChannel>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SlackChannel {
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ActionValue {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub action_id: String,
+    #[serde(default)]
+    pub block_id: String,
+    pub value: Option<String>,
+}
+
+impl ActionValue {
+    pub fn action_name(&self) -> &str {
+        if self.action_id.is_empty() {
+            &self.name
+        } else {
+            &self.action_id
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SlackUser {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    pub username: Option<String>,
+}
+
+impl SlackUser {
+    pub fn user_name(&self) -> &str {
+        match self.username {
+            Some(ref u) if !u.is_empty() => u,
+            _ => &self.name,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PayloadType {
+    #[serde(rename = "type", default)]
+    payload_type: String,
+}
+
+// Adding/editing one of octobot's steps in Workflow Builder ("workflow_step_edit"), or saving it ("view_submission")
+#[derive(Deserialize, Debug)]
+pub struct WorkflowStepPayload {
+    #[serde(rename = "type")]
+    pub payload_type: String,
+    #[serde(default)]
+    pub callback_id: String,
+    pub trigger_id: Option<String>,
+    pub user: SlackUser,
+    pub view: Option<WorkflowStepView>,
+    pub workflow_step: WorkflowStepEdit,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WorkflowStepView {
+    pub callback_id: String,
+    #[serde(default)]
+    pub state: serde_json::Value,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WorkflowStepEdit {
+    pub workflow_step_edit_id: String,
+    #[serde(default)]
+    pub inputs: HashMap<String, StepInput>,
+}
+
+impl WorkflowStepPayload {
+    pub fn step_callback_id(&self) -> &str {
+        match self.view {
+            Some(ref v) => &v.callback_id,
+            None => &self.callback_id,
+        }
+    }
+}
+
+pub fn is_workflow_step_payload(payload: &str) -> bool {
+    match serde_json::from_str::<PayloadType>(payload) {
+        Ok(p) => p.payload_type == "workflow_step_edit" || p.payload_type == "view_submission",
+        Err(_) => false,
+    }
+}
+
+// Keeps the view open, showing each error under its input block
+#[derive(Serialize)]
+struct ViewErrorsResp {
+    response_action: String,
+    errors: HashMap<String, String>,
+}
+
+fn view_errors_resp(errors: HashMap<String, String>) -> Response<Body> {
+    let resp = ViewErrorsResp {
+        response_action: "errors".into(),
+        errors: errors,
+    };
+    match serde_json::to_string(&resp) {
+        Ok(j) => util::new_json_resp(j),
+        Err(e) => {
+            error!("Error serializing slack view response: {}", e);
+            util::new_empty_error_resp()
+        }
+    }
+}
+
+// Opens the step's configuration modal, or saves its inputs. Permissions are checked when saving, since
+// workflow steps run without a user.
+fn workflow_step_resp(
+    config: &Config,
+    github_app: &dyn GithubSessionFactory,
+    team_members: &TeamMembers,
+    payload: &str,
+) -> Response<Body> {
+    let payload: WorkflowStepPayload = match serde_json::from_str(payload) {
+        Ok(p) => p,
+        Err(e) => return util::new_bad_req_resp(format!("Error parsing payload: {}", e)),
+    };
+    let step = match slack_workflows::find_step(payload.step_callback_id()) {
+        Some(s) => s,
+        None => return util::new_bad_req_resp(format!("Unknown workflow step: {}", payload.step_callback_id())),
+    };
+    let api = match SlackWorkflowApi::new(config) {
+        Ok(a) => a,
+        Err(e) => {
+            error!("{}", e);
+            return util::new_empty_error_resp();
+        }
+    };
+
+    let res = if payload.payload_type == "workflow_step_edit" {
+        let view = slack_workflows::edit_view(step, &payload.workflow_step.inputs);
+        api.open_view(payload.trigger_id.as_ref().map(|t| t.as_str()).unwrap_or(""), view)
+    } else {
+        let state = payload.view.as_ref().map(|v| v.state.clone()).unwrap_or(serde_json::Value::Null);
+        let inputs = match slack_workflows::submitted_inputs(step, &state) {
+            Ok(i) => i,
+            Err(errors) => return view_errors_resp(errors),
+        };
+
+        let user = payload.user.user_name();
+        let github_login = config.users().lookup_by_slack(user).map(|u| u.github);
+        let allowed = match command_permissions::authorize(
+            config,
+            github_app,
+            team_members,
+            step.command,
+            "",
+            user,
+            github_login.as_ref().map(|l| l.as_str()),
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                error!("Error checking workflow step permissions: {}", e);
+                false
+            }
+        };
+        if !allowed {
+            let mut errors = HashMap::new();
+            errors.insert(
+                step.inputs[0].name.to_string(),
+                format!("You are not allowed to use `{}` in workflows", step.command),
+            );
+            return view_errors_resp(errors);
+        }
+
+        info!("{} saved workflow step {}", user, step.callback_id);
+        api.update_step(&payload.workflow_step.workflow_step_edit_id, inputs, slack_workflows::step_outputs(step))
+    };
+
+    match res {
+        Ok(()) => util::new_empty_resp(StatusCode::OK),
+        Err(e) => {
+            error!("Error handling workflow step {}: {}", step.callback_id, e);
+            util::new_empty_error_resp()
+        }
+    }
+}
+
+// Only shown to the user who clicked
+#[derive(Serialize)]
+struct EphemeralResp {
+    response_type: String,
+    replace_original: bool,
+    text: String,
+}
+
+pub fn ephemeral_resp(text: &str) -> Response<Body> {
+    let resp = EphemeralResp {
+        response_type: "ephemeral".into(),
+        replace_original: false,
+        text: text.into(),
+    };
+    match serde_json::to_string(&resp) {
+        Ok(j) => util::new_json_resp(j),
+        Err(e) => {
+            error!("Error serializing slack action response: {}", e);
+            util::new_empty_error_resp()
+        }
+    }
+}
+
+// Performs the clicked action on the PR as the given (mapped) user, returning what to tell them.
+pub fn perform_action(
+    config: &Config,
+    github: &dyn Session,
+    user: &UserInfo,
+    action: &str,
+    owner: &str,
+    repo: &str,
+    number: u32,
+) -> Result<String> {
+    perform_action_from(config, github, user, action, owner, repo, number, "Slack")
+}
+
+// Like `perform_action`, for actions coming from elsewhere, e.g. "email"
+pub fn perform_action_from(
+    config: &Config,
+    github: &dyn Session,
+    user: &UserInfo,
+    action: &str,
+    owner: &str,
+    repo: &str,
+    number: u32,
+    source: &str,
+) -> Result<String> {
+    let pull_request = github.get_pull_request(owner, repo, number)?;
+    if action == REVERT_ACTION {
+        return request_revert(github, user, &pull_request, owner, repo, source);
+    }
+    if pull_request.state != "open" {
+        return Ok(format!("Pull Request #{} is no longer open", number));
+    }
+
+    if action == APPROVE_ACTION {
+        if pull_request.user.login() == user.github {
+            return Ok("You cannot approve your own pull request".into());
+        }
+        let comment = format!("Approved by @{} from {}", user.github, source);
+        github.approve_pull_request(owner, repo, number, &pull_request.head.sha, Some(&comment))?;
+        Ok(format!("Approved Pull Request #{}", number))
+    } else if action == MERGE_ACTION {
+        config.auto_merges.add(&AutoMerge {
+            repo: format!("{}/{}", owner, repo),
+            number: number,
+            head_sha: pull_request.head.sha.clone(),
+            requested_by: user.github.clone(),
+        })?;
+        let comment = format!(
+            "@{} requested from {} that this be merged once all checks pass ({})",
+            user.github,
+            source,
+            github::Commit::short_hash_str(&pull_request.head.sha)
+        );
+        github.comment_pull_request(owner, repo, number, &comment)?;
+        Ok(format!("Pull Request #{} will be merged once all checks pass", number))
+    } else {
+        Ok(format!("Unknown action: {}", action))
+    }
+}
+
+// Labels the PR for octobot to revert, the same as someone labeling it in github would
+fn request_revert(
+    github: &dyn Session,
+    user: &UserInfo,
+    pull_request: &github::PullRequest,
+    owner: &str,
+    repo: &str,
+    source: &str,
+) -> Result<String> {
+    let number = pull_request.number;
+    if !pull_request.is_merged() {
+        return Ok(format!("Pull Request #{} is not merged", number));
+    }
+    github.add_pull_request_labels(owner, repo, number, vec![pr_revert::REVERT_LABEL.to_string()])?;
+    let comment = format!("@{} requested from {} that this be reverted", user.github, source);
+    github.comment_pull_request(owner, repo, number, &comment)?;
+    Ok(format!("Pull Request #{} will be reverted: octobot will open a revert PR and let its author know", number))
+}
+
+pub struct SlackActionsHandler {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    team_members: Arc<TeamMembers>,
+    opsgenie: Option<Arc<dyn opsgenie::Session>>,
+}
+
+impl SlackActionsHandler {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        team_members: Arc<TeamMembers>,
+        opsgenie: Option<Arc<dyn opsgenie::Session>>,
+    ) -> Box<SlackActionsHandler> {
+        Box::new(SlackActionsHandler {
+            config: config,
+            github_app: github_app,
+            team_members: team_members,
+            opsgenie: opsgenie,
+        })
+    }
+}
+
+impl Handler for SlackActionsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let verifier = match self.config.slack_signing_secret() {
+            Some(secret) => SlackRequestVerifier::new(&secret).with_previous(self.config.previous_slack_signing_secret()),
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Slack interactivity is not configured"),
+        };
+
+        let headers = req.headers().clone();
+        let config = self.config.clone();
+        let github_app = self.github_app.clone();
+        let team_members = self.team_members.clone();
+        let opsgenie = self.opsgenie.clone();
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            if !verifier.is_req_valid(&headers, &body, db::now()) {
+                return util::new_msg_resp(StatusCode::FORBIDDEN, "Invalid signature");
+            }
+
+            let payload = match form_urlencoded::parse(&body).find(|&(ref k, _)| k == "payload") {
+                Some((_, v)) => v.into_owned(),
+                None => return util::new_bad_req_resp("No payload"),
+            };
+            if is_workflow_step_payload(&payload) {
+                return workflow_step_resp(&config, &*github_app, &team_members, &payload);
+            }
+
+            let payload: ActionPayload = match serde_json::from_str(&payload) {
+                Ok(p) => p,
+                Err(e) => return util::new_bad_req_resp(format!("Error parsing payload: {}", e)),
+            };
+
+            // url buttons ("Open in GitHub") are handled by slack itself
+            let (action, callback_id) = match payload.actions.iter().find(|a| a.value.is_some()) {
+                Some(a) if payload.callback_id.is_empty() => (a.action_name().to_string(), a.block_id.clone()),
+                Some(a) => (a.action_name().to_string(), payload.callback_id.clone()),
+                None => return util::new_empty_resp(StatusCode::OK),
+            };
+
+            // anyone who sees an alert may acknowledge it
+            if let Some(id) = alerts::parse_alert_key(&callback_id) {
+                let opsgenie = opsgenie.as_ref().map(|o| o.deref());
+                return match alerts::acknowledge(&config, opsgenie, id, payload.user.user_name(), db::now()) {
+                    Ok(msg) => ephemeral_resp(&msg),
+                    Err(e) => {
+                        error!("Error acknowledging alert {}: {}", id, e);
+                        ephemeral_resp(&format!("Error: {}", e))
+                    }
+                };
+            }
+
+            let user = match config.users().lookup_by_slack(payload.user.user_name()) {
+                Some(u) => u,
+                None => return ephemeral_resp("Your slack user is not mapped to a github user in octobot"),
+            };
+
+            let ((owner, repo), number) = match parse_pr_callback_id(&callback_id) {
+                Some(pr) => pr,
+                None => return util::new_bad_req_resp(format!("Unknown callback: {}", callback_id)),
+            };
+
+            info!("{} clicked '{}' on {}/{} #{}", user.github, action, owner, repo, number);
+
+            let channel = payload.channel.as_ref().map(|c| c.name.as_str()).unwrap_or("");
+            match command_permissions::authorize(
+                &config,
+                &*github_app,
+                &team_members,
+                &action,
+                channel,
+                payload.user.user_name(),
+                Some(&user.github),
+            ) {
+                Ok(true) => (),
+                Ok(false) => return ephemeral_resp(&format!("You are not allowed to use `{}` here", action)),
+                Err(e) => {
+                    error!("Error checking slack action permissions: {}", e);
+                    return ephemeral_resp(&format!("Error: {}", e));
+                }
+            };
+
+            let github = match github_app.new_session(&owner, &repo) {
+                Ok(g) => g,
+                Err(e) => {
+                    error!("Error creating a new github session for {}/{}: {}", owner, repo, e);
+                    return ephemeral_resp("Could not connect to github");
+                }
+            };
+
+            match perform_action(&config, &github, &user, &action, &owner, &repo, number) {
+                Ok(msg) => ephemeral_resp(&msg),
+                Err(e) => {
+                    error!("Error performing slack action: {}", e);
+                    ephemeral_resp(&format!("Error: {}", e))
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pr_callback_id() {
+        assert_eq!("pr:some-user/some-repo#32", pr_callback_id("some-user/some-repo", 32));
+        assert_eq!(
+            Some((("some-user".to_string(), "some-repo".to_string()), 32)),
+            parse_pr_callback_id("pr:some-user/some-repo#32")
+        );
+        assert_eq!(None, parse_pr_callback_id("pr:some-user/some-repo#x"));
+        assert_eq!(None, parse_pr_callback_id("pr:some-repo#32"));
+        assert_eq!(None, parse_pr_callback_id("issue:some-user/some-repo#32"));
+    }
+
+    #[test]
+    fn test_parse_payload() {
+        let payload: ActionPayload = serde_json::from_str(
+            r#"{"type": "interactive_message", "callback_id": "pr:a/b#1",
+                "actions": [{"name": "approve", "type": "button", "value": "approve"}],
+                "user": {"id": "U123", "name": "joe"}, "channel": {"id": "C123", "name": "releases"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!("approve", payload.actions[0].action_name());
+        assert_eq!("joe", payload.user.user_name());
+        assert_eq!("releases", payload.channel.unwrap().name);
+    }
+
+    #[test]
+    fn test_parse_block_actions_payload() {
+        let payload: ActionPayload = serde_json::from_str(
+            r#"{"type": "block_actions",
+                "actions": [{"action_id": "merge", "block_id": "pr:a/b#1", "type": "button", "value": "merge"}],
+                "user": {"id": "U123", "username": "joe", "team_id": "T1"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!("", payload.callback_id);
+        assert_eq!("merge", payload.actions[0].action_name());
+        assert_eq!("pr:a/b#1", payload.actions[0].block_id);
+        assert_eq!("joe", payload.user.user_name());
+        assert!(payload.channel.is_none());
+    }
+
+    #[test]
+    fn test_parse_workflow_step_payloads() {
+        let edit = r#"{"type": "workflow_step_edit", "callback_id": "create_backport", "trigger_id": "t1",
+                       "user": {"id": "U123", "username": "joe", "team_id": "T1"},
+                       "workflow_step": {"workflow_step_edit_id": "edit-1", "workflow_id": "W1", "step_id": "S1",
+                                         "inputs": {"repo": {"value": "a/b"}}, "outputs": []}}"#;
+        assert!(is_workflow_step_payload(edit));
+        let payload: WorkflowStepPayload = serde_json::from_str(edit).unwrap();
+        assert_eq!("create_backport", payload.step_callback_id());
+        assert_eq!("edit-1", payload.workflow_step.workflow_step_edit_id);
+        assert_eq!(Some("a/b".to_string()), payload.workflow_step.inputs["repo"].as_string());
+
+        let submission = r#"{"type": "view_submission", "user": {"id": "U123", "username": "joe"},
+                             "view": {"type": "workflow_step", "callback_id": "release_state",
+                                      "state": {"values": {"repo": {"value": {"value": "a/b"}}}}},
+                             "workflow_step": {"workflow_step_edit_id": "edit-2"}}"#;
+        assert!(is_workflow_step_payload(submission));
+        let payload: WorkflowStepPayload = serde_json::from_str(submission).unwrap();
+        assert_eq!("release_state", payload.step_callback_id());
+
+        assert!(!is_workflow_step_payload(r#"{"type": "block_actions", "actions": []}"#));
+        assert!(!is_workflow_step_payload("not json"));
+    }
+}
@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::{Body, Request, StatusCode};
+use log::{error, info};
+use url::form_urlencoded;
+
+use crate::command_permissions;
+use crate::commit_index;
+use crate::compliance;
+use crate::config::Config;
+use crate::db;
+use crate::diagnostics::EventDiagnostics;
+use crate::errors::*;
+use crate::freeze;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::slack_actions::ephemeral_resp;
+use crate::server::slack_verify::SlackRequestVerifier;
+use crate::teams::TeamMembers;
+use crate::users::UserInfo;
+use crate::util;
+
+const USAGE: &str = "Usage:
+`/octobot status <owner/repo>`: list open PRs awaiting review
+`/octobot mute <duration>`: mute direct messages, e.g. `mute 2h`, `mute 30m`, `mute 1d`
+`/octobot unmute`: unmute direct messages
+`/octobot mute <owner/repo> <until>`: hold back a repo's notifications until then, e.g. `mute some-org/some-repo 4h`
+`/octobot unmute <owner/repo>`: end a repo's mute early
+`/octobot subscribe <owner/repo>`: send the repo's messages to this channel
+`/octobot freeze <org> <until>`: block merges across the org, e.g. `freeze some-org 2d`, `freeze some-org 2019-03-01`
+`/octobot thaw <org>`: end the org's code freeze
+`/octobot freeze-exception <owner/repo>#<number>`: allow a PR to merge during a code freeze
+`/octobot history <owner/repo>#<number>`: recent events and notifications for a PR
+`/octobot what happened to <PR url>`: the same, given the PR's URL
+`/octobot where is <sha>`: the PR, tickets and release of a commit on a main or release branch";
+
+// Only show a PR's most recent events, to keep the response readable
+const MAX_HISTORY_EVENTS: usize = 20;
+
+#[derive(Debug, PartialEq)]
+pub enum SlackCommand {
+    Status(String, String),
+    Mute(i64),
+    Unmute,
+    MuteRepo(String, String),
+    UnmuteRepo(String),
+    Subscribe(String),
+    Freeze(String, String),
+    Thaw(String),
+    FreezeException(String, String, u32),
+    History(String, String, u32),
+    WhereIs(String),
+    Help,
+}
+
+impl SlackCommand {
+    // as used in [[command_permissions]]
+    pub fn name(&self) -> &str {
+        match *self {
+            SlackCommand::Status(..) => "status",
+            SlackCommand::Mute(..) => "mute",
+            SlackCommand::Unmute => "unmute",
+            SlackCommand::MuteRepo(..) => "mute-repo",
+            SlackCommand::UnmuteRepo(..) => "unmute-repo",
+            SlackCommand::Subscribe(..) => "subscribe",
+            SlackCommand::Freeze(..) => "freeze",
+            SlackCommand::Thaw(..) => "thaw",
+            SlackCommand::FreezeException(..) => "freeze-exception",
+            SlackCommand::History(..) => "history",
+            SlackCommand::WhereIs(..) => "where-is",
+            SlackCommand::Help => "help",
+        }
+    }
+}
+
+// Parses durations like "2h", "30m" or "1d" into seconds
+pub fn parse_duration(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if value.len() < 2 {
+        return None;
+    }
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount = amount.parse::<i64>().ok().filter(|a| *a > 0)?;
+    let multiplier = match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+
+    Some(amount * multiplier)
+}
+
+fn parse_repo(value: Option<&str>) -> std::result::Result<(String, String), String> {
+    let repo = value.ok_or_else(|| "Please specify a repo, e.g. `some-org/some-repo`".to_string())?;
+    let mut parts = repo.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(owner), Some(name)) if !owner.is_empty() && !name.is_empty() => Ok((owner.into(), name.into())),
+        _ => Err(format!("Invalid repo '{}': expected `owner/repo`", repo)),
+    }
+}
+
+// When a freeze or mute given as a duration (e.g. "2d") or a date (e.g. "2019-03-01") ends
+pub fn freeze_until(value: &str, now: i64) -> Option<i64> {
+    parse_duration(value).map(|secs| now + secs).or_else(|| compliance::parse_date(value))
+}
+
+fn parse_pull_request(value: Option<&str>) -> std::result::Result<(String, String, u32), String> {
+    let value = value.ok_or_else(|| "Please specify a pull request, e.g. `some-org/some-repo#123`".to_string())?;
+    let mut parts = value.splitn(2, '#');
+    let (owner, name) = parse_repo(parts.next())?;
+    match parts.next().and_then(|n| n.parse::<u32>().ok()) {
+        Some(number) => Ok((owner, name, number)),
+        None => Err(format!("Invalid pull request '{}': expected `owner/repo#number`", value)),
+    }
+}
+
+// e.g. "https://github.com/some-org/some-repo/pull/123", which slack sends wrapped as "<https://...>"
+fn parse_pull_request_url(value: Option<&str>) -> std::result::Result<(String, String, u32), String> {
+    let value = value.ok_or_else(|| "Please specify a pull request URL".to_string())?;
+    let url = value.trim_start_matches('<').trim_end_matches('>').split('|').next().unwrap_or("");
+    let parts = url.split('/').collect::<Vec<_>>();
+    let pull = parts.iter().position(|p| *p == "pull").filter(|i| *i >= 3);
+    let number = pull.and_then(|i| parts.get(i + 1)).and_then(|n| n.parse::<u32>().ok());
+    match (pull, number) {
+        (Some(i), Some(number)) if !parts[i - 2].is_empty() && !parts[i - 1].is_empty() => {
+            Ok((parts[i - 2].into(), parts[i - 1].into(), number))
+        }
+        _ => Err(format!("Invalid pull request URL '{}'", url)),
+    }
+}
+
+pub fn parse_command(text: &str) -> std::result::Result<SlackCommand, String> {
+    let words = text.split_whitespace().collect::<Vec<_>>();
+
+    match words.get(0).map(|w| w.to_lowercase()).as_ref().map(|w| w.as_str()) {
+        None | Some("help") => Ok(SlackCommand::Help),
+        Some("status") => parse_repo(words.get(1).cloned()).map(|(owner, name)| SlackCommand::Status(owner, name)),
+        Some("mute") if words.get(1).map_or(false, |w| w.contains('/')) => {
+            let (owner, name) = parse_repo(words.get(1).cloned())?;
+            match words.get(2) {
+                Some(until) if freeze_until(until, 0).is_some() => {
+                    Ok(SlackCommand::MuteRepo(format!("{}/{}", owner, name), until.to_string()))
+                }
+                _ => Err("Please specify when the mute ends, e.g. `mute some-org/some-repo 4h`".into()),
+            }
+        }
+        Some("mute") => match words.get(1).and_then(|d| parse_duration(d)) {
+            Some(secs) => Ok(SlackCommand::Mute(secs)),
+            None => Err("Please specify how long to mute for, e.g. `mute 2h`".into()),
+        },
+        Some("unmute") if words.len() > 1 => parse_repo(words.get(1).cloned())
+            .map(|(owner, name)| SlackCommand::UnmuteRepo(format!("{}/{}", owner, name))),
+        Some("unmute") => Ok(SlackCommand::Unmute),
+        Some("subscribe") => {
+            parse_repo(words.get(1).cloned()).map(|(owner, name)| SlackCommand::Subscribe(format!("{}/{}", owner, name)))
+        }
+        Some("freeze") => match (words.get(1), words.get(2)) {
+            (Some(org), Some(until)) if freeze_until(until, 0).is_some() => {
+                Ok(SlackCommand::Freeze(org.to_string(), until.to_string()))
+            }
+            _ => Err("Please specify an org and when the freeze ends, e.g. `freeze some-org 2d`".into()),
+        },
+        Some("thaw") => match words.get(1) {
+            Some(org) => Ok(SlackCommand::Thaw(org.to_string())),
+            None => Err("Please specify an org, e.g. `thaw some-org`".into()),
+        },
+        Some("freeze-exception") => parse_pull_request(words.get(1).cloned())
+            .map(|(owner, name, number)| SlackCommand::FreezeException(owner, name, number)),
+        Some("history") => parse_pull_request(words.get(1).cloned())
+            .map(|(owner, name, number)| SlackCommand::History(owner, name, number)),
+        Some("what") if words.get(1..3).map_or(false, |w| w.join(" ").eq_ignore_ascii_case("happened to")) => {
+            parse_pull_request_url(words.get(3).cloned())
+                .map(|(owner, name, number)| SlackCommand::History(owner, name, number))
+        }
+        Some("where") if words.get(1).map_or(false, |w| w.eq_ignore_ascii_case("is")) => match words.get(2) {
+            Some(sha) if commit_index::is_sha(sha) => Ok(SlackCommand::WhereIs(sha.to_lowercase())),
+            _ => Err("Please specify a commit sha of at least 7 characters, e.g. `where is 1a2b3c4`".into()),
+        },
+        Some(other) => Err(format!("Unknown command: `{}`\n{}", other, USAGE)),
+    }
+}
+
+// Open, non-draft PRs that are waiting on requested reviewers
+pub fn status_message(github: &dyn Session, owner: &str, name: &str) -> Result<String> {
+    let pull_requests = github.get_pull_requests(owner, name, Some("open"), None)?;
+    let awaiting = pull_requests
+        .iter()
+        .filter(|pr| !pr.is_draft())
+        .filter(|pr| pr.requested_reviewers.as_ref().map(|r| !r.is_empty()).unwrap_or(false))
+        .collect::<Vec<_>>();
+
+    if awaiting.is_empty() {
+        return Ok(format!("No open pull requests in {}/{} are awaiting review", owner, name));
+    }
+
+    let lines = awaiting
+        .iter()
+        .map(|pr| {
+            let reviewers = pr
+                .requested_reviewers
+                .as_ref()
+                .map(|r| r.iter().map(|u| u.login().to_string()).collect::<Vec<_>>().join(", "))
+                .unwrap_or(String::new());
+            format!(
+                "• {} by {}, waiting on {}",
+                util::make_link(&pr.html_url, &format!("#{}: {}", pr.number, pr.title)),
+                pr.user.login(),
+                reviewers
+            )
+        })
+        .collect::<Vec<_>>();
+
+    Ok(format!(
+        "{} pull request(s) in {}/{} awaiting review:\n{}",
+        awaiting.len(),
+        owner,
+        name,
+        lines.join("\n")
+    ))
+}
+
+// What octobot did about a PR: the events it received and who it notified (or why not)
+pub fn history_message(diagnostics: &EventDiagnostics, owner: &str, name: &str, number: u32) -> Result<String> {
+    let timeline = diagnostics.get_timeline(&format!("{}/{}", owner, name), number)?;
+    if timeline.is_empty() {
+        return Ok(format!(
+            "No recent events for {}/{}#{}: octobot has not received any, or they are too old to remember",
+            owner, name, number
+        ));
+    }
+
+    let mut msg = format!("History of {}/{}#{}", owner, name, number);
+    if timeline.len() > MAX_HISTORY_EVENTS {
+        msg += &format!(" (last {} of {} events)", MAX_HISTORY_EVENTS, timeline.len());
+    }
+    msg += ":";
+
+    for diagnosis in timeline.iter().skip(timeline.len().saturating_sub(MAX_HISTORY_EVENTS)) {
+        let event = if diagnosis.action.is_empty() {
+            diagnosis.event.clone()
+        } else {
+            format!("{} {}", diagnosis.event, diagnosis.action)
+        };
+        msg += &format!(
+            "\n• {}: `{}`, {} notification(s)",
+            util::format_timestamp(diagnosis.created_at),
+            event,
+            diagnosis.notifications
+        );
+        for step in &diagnosis.steps {
+            msg += &format!("\n    ◦ {}", step);
+        }
+    }
+
+    Ok(msg)
+}
+
+pub struct SlackCommandHandler {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    team_members: Arc<TeamMembers>,
+}
+
+impl SlackCommandHandler {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        team_members: Arc<TeamMembers>,
+    ) -> Box<SlackCommandHandler> {
+        Box::new(SlackCommandHandler {
+            config: config,
+            github_app: github_app,
+            team_members: team_members,
+        })
+    }
+}
+
+// Runs the command for the given slack user in the given channel, returning what to tell them.
+fn run_command(
+    config: &Config,
+    github_app: &dyn GithubSessionFactory,
+    team_members: &TeamMembers,
+    command: SlackCommand,
+    slack_user: &str,
+    channel: &str,
+) -> Result<String> {
+    let user = config.users().lookup_by_slack(slack_user);
+    let github_login = user.as_ref().map(|u| u.github.as_str());
+    let allowed = command_permissions::authorize(
+        config,
+        github_app,
+        team_members,
+        command.name(),
+        channel,
+        slack_user,
+        github_login,
+    )?;
+    if !allowed {
+        return Ok(format!("You are not allowed to use `{}` here", command.name()));
+    }
+
+    match command {
+        SlackCommand::Help => Ok(USAGE.into()),
+        SlackCommand::Status(owner, name) => {
+            let github = github_app.new_session(&owner, &name)?;
+            status_message(&github, &owner, &name)
+        }
+        SlackCommand::History(owner, name, number) => history_message(&config.event_diagnostics, &owner, &name, number),
+        SlackCommand::WhereIs(sha) => {
+            let commits = config.commit_index.find(&sha)?;
+            Ok(commit_index::where_is_message(&sha, &commits))
+        }
+        command => {
+            // everything else needs to know who is asking
+            match user {
+                Some(ref user) => run_user_command(config, github_app, command, user, channel),
+                None => Ok("Your slack user is not mapped to a github user in octobot".into()),
+            }
+        }
+    }
+}
+
+fn run_user_command(
+    config: &Config,
+    github_app: &dyn GithubSessionFactory,
+    command: SlackCommand,
+    user: &UserInfo,
+    channel: &str,
+) -> Result<String> {
+    match command {
+        SlackCommand::Mute(secs) => {
+            let until = db::now() + secs;
+            config.users_write().mute_until(&user.github, until)?;
+            Ok(format!("Direct messages are muted until {}", util::format_timestamp(until)))
+        }
+        SlackCommand::Unmute => {
+            config.users_write().mute_until(&user.github, 0)?;
+            Ok("Direct messages are unmuted".into())
+        }
+        SlackCommand::MuteRepo(repo, until) => {
+            let now = db::now();
+            let until = freeze_until(&until, now).unwrap_or(now);
+            if until <= now {
+                return Ok("The mute must end in the future".into());
+            }
+            config.repo_mutes.mute(&repo, now, until, &user.github)?;
+            info!("{} muted {} until {}", user.github, repo, util::format_timestamp(until));
+            Ok(format!(
+                "{}'s notifications are muted until {}, when a summary of them will be posted",
+                repo,
+                util::format_timestamp(until)
+            ))
+        }
+        SlackCommand::UnmuteRepo(repo) => {
+            if !config.repo_mutes.end(&repo, db::now())? {
+                return Ok(format!("{} is not muted", repo));
+            }
+            info!("{} unmuted {}", user.github, repo);
+            Ok(format!("{} is unmuted: a summary of its muted notifications will be posted shortly", repo))
+        }
+        SlackCommand::Subscribe(repo) => {
+            if channel.is_empty() || channel == "directmessage" || channel == "privategroup" {
+                return Ok("Only channels can subscribe to repos".into());
+            }
+            if config.repos_write().subscribe(&repo, channel)? {
+                info!("{} subscribed #{} to {}", user.github, channel, repo);
+                Ok(format!("This channel will now receive messages for {}", repo))
+            } else {
+                Ok(format!("This channel already receives messages for {}", repo))
+            }
+        }
+        SlackCommand::Freeze(..) | SlackCommand::Thaw(..) | SlackCommand::FreezeException(..)
+            if !config.is_freeze_approver(&user.github) =>
+        {
+            Ok("Only freeze approvers can manage code freezes".into())
+        }
+        SlackCommand::Freeze(org, until) => {
+            let now = db::now();
+            let until = freeze_until(&until, now).unwrap_or(now);
+            if until <= now {
+                return Ok("The freeze must end in the future".into());
+            }
+            freeze::freeze_org(config, github_app, &org, until, &user.github, now)?;
+            Ok(format!("{} is frozen until {}", org, util::format_timestamp(until)))
+        }
+        SlackCommand::Thaw(org) => {
+            if config.code_freezes.active(&org, db::now())?.is_none() {
+                return Ok(format!("{} is not frozen", org));
+            }
+            freeze::thaw_org(config, github_app, &org)?;
+            Ok(format!("{} is no longer frozen", org))
+        }
+        SlackCommand::FreezeException(owner, name, number) => {
+            freeze::grant_exception(config, github_app, &owner, &name, number, &user.github, db::now())?;
+            Ok(format!("{}/{}#{} may merge during the code freeze", owner, name, number))
+        }
+        SlackCommand::Status(..) | SlackCommand::History(..) | SlackCommand::WhereIs(..) | SlackCommand::Help => {
+            Ok(USAGE.into())
+        }
+    }
+}
+
+impl Handler for SlackCommandHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let verifier = match self.config.slack_signing_secret() {
+            Some(secret) => SlackRequestVerifier::new(&secret).with_previous(self.config.previous_slack_signing_secret()),
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Slack commands are not configured"),
+        };
+
+        let headers = req.headers().clone();
+        let config = self.config.clone();
+        let github_app = self.github_app.clone();
+        let team_members = self.team_members.clone();
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            if !verifier.is_req_valid(&headers, &body, db::now()) {
+                return util::new_msg_resp(StatusCode::FORBIDDEN, "Invalid signature");
+            }
+
+            let params = form_urlencoded::parse(&body).into_owned().collect::<HashMap<String, String>>();
+            let param = |name: &str| params.get(name).cloned().unwrap_or(String::new());
+
+            let command = match parse_command(&param("text")) {
+                Ok(c) => c,
+                Err(msg) => return ephemeral_resp(&msg),
+            };
+
+            let (user_name, channel_name) = (param("user_name"), param("channel_name"));
+            match run_command(&config, &*github_app, &team_members, command, &user_name, &channel_name) {
+                Ok(msg) => ephemeral_resp(&msg),
+                Err(e) => {
+                    error!("Error running slack command: {}", e);
+                    ephemeral_resp(&format!("Error: {}", e))
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Some(2 * 60 * 60), parse_duration("2h"));
+        assert_eq!(Some(30 * 60), parse_duration("30m"));
+        assert_eq!(Some(24 * 60 * 60), parse_duration("1d"));
+        assert_eq!(None, parse_duration("0h"));
+        assert_eq!(None, parse_duration("2w"));
+        assert_eq!(None, parse_duration("h"));
+        assert_eq!(None, parse_duration("soon"));
+    }
+
+    #[test]
+    fn test_freeze_until() {
+        assert_eq!(Some(1000 + 2 * 24 * 60 * 60), freeze_until("2d", 1000));
+        assert_eq!(compliance::parse_date("2019-03-01"), freeze_until("2019-03-01", 1000));
+        assert_eq!(None, freeze_until("later", 1000));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Ok(SlackCommand::Help), parse_command(""));
+        assert_eq!(Ok(SlackCommand::Help), parse_command("help"));
+        assert_eq!(
+            Ok(SlackCommand::Status("some-org".into(), "some-repo".into())),
+            parse_command("status some-org/some-repo")
+        );
+        assert_eq!(Ok(SlackCommand::Mute(7200)), parse_command("Mute 2h"));
+        assert_eq!(Ok(SlackCommand::Unmute), parse_command("unmute"));
+        assert_eq!(
+            Ok(SlackCommand::MuteRepo("some-org/some-repo".into(), "4h".into())),
+            parse_command("mute some-org/some-repo 4h")
+        );
+        assert_eq!(
+            Ok(SlackCommand::UnmuteRepo("some-org/some-repo".into())),
+            parse_command("unmute some-org/some-repo")
+        );
+        assert_eq!(
+            Ok(SlackCommand::Subscribe("some-org/some-repo".into())),
+            parse_command("subscribe  some-org/some-repo")
+        );
+
+        assert_eq!(
+            Ok(SlackCommand::Freeze("some-org".into(), "2d".into())),
+            parse_command("freeze some-org 2d")
+        );
+        assert_eq!(
+            Ok(SlackCommand::Freeze("some-org".into(), "2019-03-01".into())),
+            parse_command("freeze some-org 2019-03-01")
+        );
+        assert_eq!(Ok(SlackCommand::Thaw("some-org".into())), parse_command("thaw some-org"));
+        assert_eq!(
+            Ok(SlackCommand::FreezeException("some-org".into(), "some-repo".into(), 123)),
+            parse_command("freeze-exception some-org/some-repo#123")
+        );
+
+        assert_eq!(
+            Ok(SlackCommand::History("some-org".into(), "some-repo".into(), 123)),
+            parse_command("history some-org/some-repo#123")
+        );
+        assert_eq!(
+            Ok(SlackCommand::History("some-org".into(), "some-repo".into(), 123)),
+            parse_command("what happened to <https://github.com/some-org/some-repo/pull/123>")
+        );
+        assert_eq!(
+            Ok(SlackCommand::History("some-org".into(), "some-repo".into(), 123)),
+            parse_command("What happened to https://github.com/some-org/some-repo/pull/123/files")
+        );
+
+        assert_eq!(Ok(SlackCommand::WhereIs("abcdef0".into())), parse_command("where is ABCDEF0"));
+
+        assert!(parse_command("status").is_err());
+        assert!(parse_command("freeze some-org").is_err());
+        assert!(parse_command("freeze some-org someday").is_err());
+        assert!(parse_command("thaw").is_err());
+        assert!(parse_command("freeze-exception some-org/some-repo").is_err());
+        assert!(parse_command("freeze-exception some-org/some-repo#abc").is_err());
+        assert!(parse_command("status some-repo").is_err());
+        assert!(parse_command("history some-org/some-repo").is_err());
+        assert!(parse_command("what happened to").is_err());
+        assert!(parse_command("what happened to https://github.com/some-org/some-repo/issues/123").is_err());
+        assert!(parse_command("what happened to https://github.com/some-org/some-repo/pull/abc").is_err());
+        assert!(parse_command("what now").is_err());
+        assert!(parse_command("where is").is_err());
+        assert!(parse_command("where is abc").is_err());
+        assert!(parse_command("where is some-branch").is_err());
+        assert!(parse_command("mute forever").is_err());
+        assert!(parse_command("mute some-org/some-repo").is_err());
+        assert!(parse_command("mute some-org/some-repo forever").is_err());
+        assert!(parse_command("mute some-org/ 4h").is_err());
+        assert!(parse_command("unmute some-repo").is_err());
+        assert!(parse_command("dance").unwrap_err().contains("Usage"));
+    }
+}
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::{Body, Request, StatusCode};
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+use crate::config::Config;
+use crate::db;
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::slack_verify::SlackRequestVerifier;
+use crate::slack_bridge::{self, BridgeRequest, MessageEvent};
+use crate::slack_reactions::{self, ReactionEvent, ReactionRequest};
+use crate::slack_workflows::{self, StepExecuteEvent, WorkflowStepRequest};
+use crate::util;
+use crate::worker::Worker;
+
+#[derive(Deserialize, Debug)]
+pub struct EventsPayload {
+    #[serde(rename = "type")]
+    pub payload_type: String,
+    pub challenge: Option<String>,
+    pub event: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct ChallengeResp {
+    challenge: String,
+}
+
+// Receives the slack Events API subscription: workflow steps, and reactions and thread replies to octobot's messages
+pub struct SlackEventsHandler {
+    config: Arc<Config>,
+    workflow_steps: Arc<dyn Worker<WorkflowStepRequest>>,
+    reactions: Arc<dyn Worker<ReactionRequest>>,
+    replies: Arc<dyn Worker<BridgeRequest>>,
+}
+
+impl SlackEventsHandler {
+    pub fn new(
+        config: Arc<Config>,
+        workflow_steps: Arc<dyn Worker<WorkflowStepRequest>>,
+        reactions: Arc<dyn Worker<ReactionRequest>>,
+        replies: Arc<dyn Worker<BridgeRequest>>,
+    ) -> Box<SlackEventsHandler> {
+        Box::new(SlackEventsHandler {
+            config: config,
+            workflow_steps: workflow_steps,
+            reactions: reactions,
+            replies: replies,
+        })
+    }
+}
+
+impl Handler for SlackEventsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let verifier = match self.config.slack_signing_secret() {
+            Some(secret) => SlackRequestVerifier::new(&secret).with_previous(self.config.previous_slack_signing_secret()),
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Slack events are not configured"),
+        };
+
+        let headers = req.headers().clone();
+        let workflow_steps = self.workflow_steps.clone();
+        let reactions = self.reactions.clone();
+        let replies = self.replies.clone();
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            if !verifier.is_req_valid(&headers, &body, db::now()) {
+                return util::new_msg_resp(StatusCode::FORBIDDEN, "Invalid signature");
+            }
+
+            let payload: EventsPayload = match serde_json::from_slice(&body) {
+                Ok(p) => p,
+                Err(e) => return util::new_bad_req_resp(format!("Error parsing payload: {}", e)),
+            };
+
+            match payload.payload_type.as_str() {
+                "url_verification" => {
+                    let resp = ChallengeResp {
+                        challenge: payload.challenge.unwrap_or(String::new()),
+                    };
+                    match serde_json::to_string(&resp) {
+                        Ok(j) => util::new_json_resp(j),
+                        Err(e) => {
+                            error!("Error serializing slack challenge response: {}", e);
+                            util::new_empty_error_resp()
+                        }
+                    }
+                }
+                "event_callback" => {
+                    // slack wants an answer within 3 seconds: events are handled (and replied to) separately
+                    let event = payload.event.unwrap_or(Value::Null);
+                    if event["type"] == "workflow_step_execute" {
+                        match serde_json::from_value::<StepExecuteEvent>(event) {
+                            Ok(e) => {
+                                info!("Running workflow step {}", e.callback_id);
+                                workflow_steps.send(slack_workflows::req(e));
+                            }
+                            Err(e) => return util::new_bad_req_resp(format!("Error parsing event: {}", e)),
+                        }
+                    } else if event["type"] == "reaction_added" {
+                        match serde_json::from_value::<ReactionEvent>(event) {
+                            Ok(e) => reactions.send(slack_reactions::req(e)),
+                            Err(e) => return util::new_bad_req_resp(format!("Error parsing event: {}", e)),
+                        }
+                    } else if event["type"] == "message" {
+                        match serde_json::from_value::<MessageEvent>(event) {
+                            Ok(ref e) if !e.is_human_reply() => (),
+                            Ok(e) => replies.send(slack_bridge::req(e)),
+                            Err(e) => return util::new_bad_req_resp(format!("Error parsing event: {}", e)),
+                        }
+                    }
+                    util::new_empty_resp(StatusCode::OK)
+                }
+                _ => util::new_empty_resp(StatusCode::OK),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_payload() {
+        let payload: EventsPayload = serde_json::from_str(
+            r#"{"token": "x", "challenge": "the-challenge", "type": "url_verification"}"#,
+        )
+        .unwrap();
+        assert_eq!("url_verification", payload.payload_type);
+        assert_eq!(Some("the-challenge".to_string()), payload.challenge);
+
+        let payload: EventsPayload = serde_json::from_str(
+            r#"{"type": "event_callback", "team_id": "T1",
+                "event": {"type": "workflow_step_execute", "callback_id": "release_state",
+                          "workflow_step": {"workflow_step_execute_id": "exec-1", "inputs": {}}}}"#,
+        )
+        .unwrap();
+        let event: StepExecuteEvent = serde_json::from_value(payload.event.unwrap()).unwrap();
+        assert_eq!("release_state", event.callback_id);
+    }
+}
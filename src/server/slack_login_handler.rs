@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use hyper::header::{HeaderValue, LOCATION};
+use hyper::{Body, Request, StatusCode};
+use log::{error, info, warn};
+
+use crate::config::{Config, SlackLoginConfig};
+use crate::saml::Requests;
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::login;
+use crate::server::saml_handler::session_page;
+use crate::server::sessions::{Client, Sessions};
+use crate::slack_login;
+use crate::util;
+
+pub enum SlackLoginOp {
+    Login,
+    Callback,
+}
+
+// "Sign in with Slack", for users to manage their own settings: sending them to slack, and the redirect URL slack
+// sends them back to. Sessions started this way may only use /api/me.
+pub struct SlackLoginHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    // pending logins, by the state slack passes back
+    requests: Arc<Requests>,
+    op: SlackLoginOp,
+}
+
+impl SlackLoginHandler {
+    pub fn new(
+        config: Arc<Config>,
+        sessions: Arc<Sessions>,
+        requests: Arc<Requests>,
+        op: SlackLoginOp,
+    ) -> Box<SlackLoginHandler> {
+        Box::new(SlackLoginHandler {
+            config: config,
+            sessions: sessions,
+            requests: requests,
+            op: op,
+        })
+    }
+}
+
+impl Handler for SlackLoginHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let slack_login = match self.config.slack_login {
+            Some(ref s) => s,
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Slack login is not configured"),
+        };
+
+        match self.op {
+            SlackLoginOp::Login => self.login(slack_login),
+            SlackLoginOp::Callback => self.callback(slack_login, req),
+        }
+    }
+}
+
+impl SlackLoginHandler {
+    fn login(&self, slack_login: &SlackLoginConfig) -> FutureResponse {
+        let state = self.requests.start();
+        let url = match slack_login::login_url(slack_login, &state) {
+            Ok(u) => u,
+            Err(e) => return self.respond_error(&format!("Error starting slack login: {}", e)),
+        };
+        let location = match HeaderValue::from_str(&url) {
+            Ok(l) => l,
+            Err(e) => return self.respond_error(&format!("Invalid slack login URL: {}", e)),
+        };
+
+        let mut resp = util::new_empty_resp(StatusCode::FOUND);
+        resp.headers_mut().insert(LOCATION, location);
+        self.respond(resp)
+    }
+
+    fn callback(&self, slack_login: &SlackLoginConfig, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        if !query.get("state").map(|s| self.requests.take(s)).unwrap_or(false) {
+            warn!("Slack auth failure: unknown or expired state");
+            return self.respond_with(StatusCode::UNAUTHORIZED, "The login expired: try again");
+        }
+        let code = match query.get("code") {
+            Some(c) => c,
+            None => {
+                let error = query.get("error").map(|e| e.as_str()).unwrap_or("no code");
+                warn!("Slack auth failure: {}", error);
+                return self.respond(util::new_empty_resp(StatusCode::UNAUTHORIZED));
+            }
+        };
+
+        let identity = match slack_login::identify(slack_login, code) {
+            Ok(i) => i,
+            Err(e) => {
+                error!("{}", e);
+                return self.respond(util::new_empty_resp(StatusCode::UNAUTHORIZED));
+            }
+        };
+        if !slack_login::allowed(slack_login, &identity) {
+            warn!("Login refused: slack user {} is of another workspace ({})", identity.user_id, identity.team_id);
+            return self.respond(util::new_empty_resp(StatusCode::FORBIDDEN));
+        }
+        let user = match slack_login::find_user(&self.config.users(), &identity) {
+            Some(u) => u,
+            None => {
+                warn!("Login refused: no octobot user matches slack user {}", identity.user_id);
+                let msg = "No octobot user is mapped to your slack user or email address: ask an admin to add you";
+                return self.respond_with(StatusCode::FORBIDDEN, msg);
+            }
+        };
+
+        let client = Client::from_request(&req);
+        match login::start_self_service_session(&self.config, &self.sessions, &user.github, client) {
+            Some(session) => {
+                info!("Slack auth success for slack user {} ({})", identity.user_id, user.github);
+                self.respond(session_page(&session, &user.github))
+            }
+            None => self.respond(util::new_empty_resp(StatusCode::UNAUTHORIZED)),
+        }
+    }
+}
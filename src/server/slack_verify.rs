@@ -0,0 +1,149 @@
+use hyper::HeaderMap;
+use log::{debug, error, warn};
+use ring::{digest, hmac};
+use rustc_serialize::hex::FromHex;
+
+// Slack recommends rejecting requests older than this to prevent replays
+const MAX_REQUEST_AGE_SECS: i64 = 5 * 60;
+
+pub struct SlackRequestVerifier {
+    pub secret: String,
+    // also accepted while the signing secret is being rotated
+    pub previous_secret: Option<String>,
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values = headers.get_all(name).iter().collect::<Vec<_>>();
+    if values.len() != 1 {
+        error!("Expected to find exactly one {} header", name);
+        return None;
+    }
+    Some(String::from_utf8_lossy(values[0].as_bytes()).into_owned())
+}
+
+impl SlackRequestVerifier {
+    pub fn new(secret: &str) -> SlackRequestVerifier {
+        SlackRequestVerifier {
+            secret: secret.into(),
+            previous_secret: None,
+        }
+    }
+
+    pub fn with_previous(mut self, previous_secret: Option<String>) -> SlackRequestVerifier {
+        self.previous_secret = previous_secret;
+        self
+    }
+
+    pub fn is_req_valid(&self, headers: &HeaderMap, data: &[u8], now: i64) -> bool {
+        let timestamp = match header_value(headers, "x-slack-request-timestamp") {
+            Some(t) => t,
+            None => return false,
+        };
+        let signature = match header_value(headers, "x-slack-signature") {
+            Some(s) => s,
+            None => return false,
+        };
+
+        self.is_valid(&timestamp, data, &signature, now)
+    }
+
+    pub fn is_valid(&self, timestamp: &str, data: &[u8], signature: &str, now: i64) -> bool {
+        let request_time = match timestamp.parse::<i64>() {
+            Ok(t) => t,
+            Err(_) => {
+                error!("Invalid slack request timestamp: {}", timestamp);
+                return false;
+            }
+        };
+        if (now - request_time).abs() > MAX_REQUEST_AGE_SECS {
+            error!("Slack request timestamp is too old: {}", timestamp);
+            return false;
+        }
+
+        if !signature.starts_with("v0=") {
+            error!("Invalid signature value. Expected v0: {}", signature);
+            return false;
+        }
+
+        let sig_bytes: Vec<u8> = match signature[3..].from_hex() {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Invalid hex value. {}", e);
+                return false;
+            }
+        };
+
+        let mut base = format!("v0:{}:", timestamp).into_bytes();
+        base.extend_from_slice(data);
+
+        let key = hmac::VerificationKey::new(&digest::SHA256, self.secret.as_bytes());
+        match hmac::verify(&key, &base, &sig_bytes) {
+            Ok(_) => {
+                debug!("Slack signature verified!");
+                true
+            }
+            Err(e) => match self.previous_secret {
+                Some(ref previous) if is_signed_with(previous, &base, &sig_bytes) => {
+                    warn!("Slack signature verified with the previous signing secret");
+                    true
+                }
+                _ => {
+                    error!("Slack signature verify failed: {}", e);
+                    false
+                }
+            },
+        }
+    }
+}
+
+fn is_signed_with(secret: &str, base: &[u8], sig_bytes: &[u8]) -> bool {
+    let key = hmac::VerificationKey::new(&digest::SHA256, secret.as_bytes());
+    hmac::verify(&key, base, sig_bytes).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_serialize::hex::ToHex;
+
+    fn sign(secret: &str, timestamp: &str, msg: &str) -> String {
+        let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+        let signature = hmac::sign(&key, format!("v0:{}:{}", timestamp, msg).as_bytes());
+        "v0=".to_string() + signature.as_ref().to_hex().as_str()
+    }
+
+    #[test]
+    fn verify_sig_valid() {
+        let verifier = SlackRequestVerifier::new("this is my secret key!");
+        let signature = sign("this is my secret key!", "1000", "payload=stuff");
+
+        assert!(verifier.is_valid("1000", b"payload=stuff", &signature, 1010));
+    }
+
+    #[test]
+    fn verify_sig_invalid() {
+        let verifier = SlackRequestVerifier::new("this is my secret key!");
+        let signature = sign("some other key", "1000", "payload=stuff");
+
+        assert!(!verifier.is_valid("1000", b"payload=stuff", &signature, 1010));
+        assert!(!verifier.is_valid("1000", b"payload=stuff", &signature[3..], 1010));
+    }
+
+    #[test]
+    fn verify_sig_previous_secret() {
+        let verifier = SlackRequestVerifier::new("the new key").with_previous(Some("the old key".into()));
+
+        assert!(verifier.is_valid("1000", b"payload=stuff", &sign("the new key", "1000", "payload=stuff"), 1010));
+        assert!(verifier.is_valid("1000", b"payload=stuff", &sign("the old key", "1000", "payload=stuff"), 1010));
+        assert!(!verifier.is_valid("1000", b"payload=stuff", &sign("another key", "1000", "payload=stuff"), 1010));
+    }
+
+    #[test]
+    fn verify_sig_too_old() {
+        let verifier = SlackRequestVerifier::new("this is my secret key!");
+        let signature = sign("this is my secret key!", "1000", "payload=stuff");
+
+        assert!(!verifier.is_valid("1000", b"payload=stuff", &signature, 1000 + 6 * 60));
+        assert!(!verifier.is_valid("not-a-time", b"payload=stuff", &signature, 1000));
+    }
+}
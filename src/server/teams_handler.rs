@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+use crate::config::Config;
+use crate::server::http::{parse_json, FutureResponse, Handler};
+use crate::teams::TeamChannel;
+use crate::util;
+
+pub enum TeamsOp {
+    List,
+    Set,
+    Remove,
+}
+
+// Which channels team review requests go to
+pub struct TeamsHandler {
+    config: Arc<Config>,
+    op: TeamsOp,
+}
+
+#[derive(Serialize)]
+struct TeamsResp {
+    teams: Vec<TeamChannel>,
+}
+
+#[derive(Deserialize)]
+struct RemoveReq {
+    team: String,
+}
+
+impl TeamsHandler {
+    pub fn new(config: Arc<Config>, op: TeamsOp) -> Box<TeamsHandler> {
+        Box::new(TeamsHandler { config: config, op: op })
+    }
+}
+
+impl Handler for TeamsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        match &self.op {
+            &TeamsOp::List => self.list(),
+            &TeamsOp::Set => self.set(req),
+            &TeamsOp::Remove => self.remove(req),
+        }
+    }
+}
+
+impl TeamsHandler {
+    fn list(&self) -> FutureResponse {
+        let teams = match self.config.team_channels.get_all() {
+            Ok(t) => t,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&TeamsResp { teams: teams }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing team channels: {}", e)),
+        }
+    }
+
+    fn set(&self, req: Request<Body>) -> FutureResponse {
+        let config = self.config.clone();
+
+        parse_json(req, move |team: TeamChannel| {
+            if !team.team.contains('/') || team.channel.trim().is_empty() {
+                return util::new_bad_req_resp("Expected a `team` like \"org/team-slug\" and a `channel`");
+            }
+            if let Err(e) = config.team_channels.set(&team.team, team.channel.trim()) {
+                error!("{}", e);
+                return util::new_empty_error_resp();
+            }
+
+            info!("Team {} review requests now go to {}", team.team, team.channel);
+            util::new_empty_resp(StatusCode::OK)
+        })
+    }
+
+    fn remove(&self, req: Request<Body>) -> FutureResponse {
+        let config = self.config.clone();
+
+        parse_json(req, move |team: RemoveReq| {
+            if let Err(e) = config.team_channels.remove(&team.team) {
+                error!("{}", e);
+                return util::new_empty_error_resp();
+            }
+
+            info!("Team {} review requests now go to its members", team.team);
+            util::new_empty_resp(StatusCode::OK)
+        })
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::{Body, HeaderMap, Request, StatusCode};
+use log::{error, info};
+use ring::constant_time;
+
+use crate::config::Config;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::server::http::{FutureResponse, Handler};
+use crate::slack::SlackRequest;
+use crate::terraform::{self, PlanSummary};
+use crate::util;
+use crate::worker::Worker;
+
+// Receives the `terraform show -json` plans that CI posts for a PR, to summarize them on the PR
+pub struct TerraformPlanHandler {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    slack: Arc<dyn Worker<SlackRequest>>,
+}
+
+impl TerraformPlanHandler {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        slack: Arc<dyn Worker<SlackRequest>>,
+    ) -> Box<TerraformPlanHandler> {
+        Box::new(TerraformPlanHandler {
+            config: config,
+            github_app: github_app,
+            slack: slack,
+        })
+    }
+}
+
+fn is_authorized(token: &str, headers: &HeaderMap) -> bool {
+    match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+        Some(a) if a.starts_with("Bearer ") => {
+            constant_time::verify_slices_are_equal(a[7..].trim().as_bytes(), token.as_bytes()).is_ok()
+        }
+        _ => false,
+    }
+}
+
+impl Handler for TerraformPlanHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let terraform_config = match self.config.terraform {
+            Some(ref c) => c.clone(),
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Terraform plans are not configured"),
+        };
+        if !is_authorized(&terraform_config.token, req.headers()) {
+            return self.respond_with(StatusCode::FORBIDDEN, "Invalid credentials");
+        }
+
+        let query = util::parse_query(req.uri().query());
+        let param = |name: &str| query.get(name).filter(|v| !v.is_empty()).cloned();
+        let (repo, number) = match (param("repo"), param("pr").and_then(|n| n.parse::<u32>().ok())) {
+            (Some(r), Some(n)) => (r, n),
+            _ => return self.respond(util::new_bad_req_resp("Expected `repo` and `pr` params")),
+        };
+        let workspace = param("workspace");
+
+        let repo = match github::Repo::parse(&format!("https://{}/{}", self.config.github_host(&repo), repo)) {
+            Ok(r) => r,
+            Err(e) => return self.respond(util::new_bad_req_resp(format!("Invalid repo {}: {}", repo, e))),
+        };
+        let github_app = self.github_app.clone();
+        let slack = self.slack.clone();
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            let summary = match PlanSummary::parse(&body, workspace.as_ref().map(|w| w.as_str())) {
+                Ok(s) => s,
+                Err(e) => return util::new_bad_req_resp(format!("{}", e)),
+            };
+            let session = match github_app.new_session(repo.owner.login(), &repo.name) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Error creating github session for {}: {}", repo.full_name, e);
+                    return util::new_msg_resp(StatusCode::INTERNAL_SERVER_ERROR, "Error creating github session");
+                }
+            };
+            let pull_request = match session.get_pull_request(repo.owner.login(), &repo.name, number) {
+                Ok(p) => p,
+                Err(e) => return util::new_msg_resp(StatusCode::NOT_FOUND, format!("Error fetching PR: {}", e)),
+            };
+
+            info!("Received terraform plan for {} #{}: {}", repo.full_name, number, summary.title());
+            let check_run = terraform_config.check_run();
+            if let Err(e) = terraform::publish(&session, &repo, &pull_request, &summary, check_run) {
+                error!("Error publishing terraform plan for {} #{}: {}", repo.full_name, number, e);
+                return util::new_msg_resp(StatusCode::INTERNAL_SERVER_ERROR, "Error commenting on PR");
+            }
+
+            if let Some(ref channel) = terraform_config.infra_channel {
+                if let Some(msg) = terraform::escalation(channel, &repo, &pull_request, &summary) {
+                    slack.send(msg);
+                }
+            }
+
+            util::new_json_resp(serde_json::to_string(&summary).unwrap_or_default())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_str(&format!("Bearer {}", token)).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        assert!(is_authorized("the-token", &bearer("the-token")));
+        assert!(!is_authorized("the-token", &bearer("other-token")));
+        assert!(!is_authorized("the-token", &HeaderMap::new()));
+    }
+}
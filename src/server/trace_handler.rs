@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::config::Config;
+use crate::request_traces::TraceEntry;
+use crate::server::api::{self, Route};
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::Sessions;
+use crate::util;
+
+// What was logged while handling a request, by the id given in its X-Request-Id header or error messages, or while
+// handling a github delivery
+pub struct TraceHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    route: &'static Route,
+}
+
+#[derive(Serialize)]
+struct TraceResp {
+    id: String,
+    entries: Vec<TraceEntry>,
+}
+
+impl TraceHandler {
+    pub fn new(config: Arc<Config>, sessions: Arc<Sessions>, route: &'static Route) -> Box<TraceHandler> {
+        Box::new(TraceHandler {
+            config: config,
+            sessions: sessions,
+            route: route,
+        })
+    }
+}
+
+impl Handler for TraceHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        if login::get_admin_session(&self.sessions, &req).is_none() {
+            return self.respond_with(StatusCode::FORBIDDEN, "Only admins may do this");
+        }
+
+        let id = match api::path_param(self.route, req.uri().path(), "id") {
+            Some(id) => id,
+            None => return self.respond(util::new_bad_req_resp("Expected a request or delivery id")),
+        };
+
+        let entries = match self.config.request_traces.timeline(&id) {
+            Ok(e) => e,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+        if entries.is_empty() {
+            return self.respond(util::new_msg_resp(StatusCode::NOT_FOUND, format!("Nothing was traced for {}", id)));
+        }
+
+        match serde_json::to_string(&TraceResp {
+            id: id,
+            entries: entries,
+        }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing trace: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use log::{error, info};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::config::Config;
+use crate::db;
+use crate::server::http::{FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::Sessions;
+use crate::user_discovery::{self, MappingProposal};
+use crate::util;
+
+pub const CONFIRM_MAPPING_ACTION: &str = "confirm-user-mapping";
+pub const REJECT_MAPPING_ACTION: &str = "reject-user-mapping";
+
+pub enum UserMappingsOp {
+    List,
+    Confirm,
+    Reject,
+}
+
+// User mappings proposed by user discovery, for admins to confirm or reject
+pub struct UserMappingsHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    op: UserMappingsOp,
+}
+
+#[derive(Serialize)]
+struct ProposalsResp {
+    proposals: Vec<MappingProposal>,
+}
+
+impl UserMappingsHandler {
+    pub fn new(config: Arc<Config>, sessions: Arc<Sessions>, op: UserMappingsOp) -> Box<UserMappingsHandler> {
+        Box::new(UserMappingsHandler {
+            config: config,
+            sessions: sessions,
+            op: op,
+        })
+    }
+}
+
+impl Handler for UserMappingsHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let session = match login::get_admin_session(&self.sessions, &req) {
+            Some(s) => s,
+            None => return self.respond_with(StatusCode::FORBIDDEN, "Only admins may do this"),
+        };
+
+        let mut query = util::parse_query(req.uri().query());
+        let id = query.get("id").and_then(|id| id.parse::<i32>().ok());
+        match &self.op {
+            &UserMappingsOp::List => self.list(query.remove("state")),
+            &UserMappingsOp::Confirm => self.decide(id, user_discovery::CONFIRMED, &session.user),
+            &UserMappingsOp::Reject => self.decide(id, user_discovery::REJECTED, &session.user),
+        }
+    }
+}
+
+impl UserMappingsHandler {
+    fn list(&self, state: Option<String>) -> FutureResponse {
+        let states = [user_discovery::PENDING, user_discovery::CONFIRMED, user_discovery::REJECTED];
+        if let Some(ref state) = state {
+            if !states.contains(&state.as_str()) {
+                return self.respond(util::new_bad_req_resp(format!("Unknown state: {}", state)));
+            }
+        }
+
+        let proposals = match self.config.user_mappings.list(state.as_ref().map(|s| s.as_str())) {
+            Ok(p) => p,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        match serde_json::to_string(&ProposalsResp { proposals: proposals }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing user mapping proposals: {}", e)),
+        }
+    }
+
+    fn decide(&self, id: Option<i32>, state: &str, admin: &str) -> FutureResponse {
+        let id = match id {
+            Some(id) => id,
+            None => return self.respond(util::new_bad_req_resp("No `id` param specified")),
+        };
+        let proposal = match self.config.user_mappings.get(id) {
+            Ok(Some(p)) => p,
+            Ok(None) => return self.respond(util::new_msg_resp(StatusCode::NOT_FOUND, "No such proposal")),
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+        if proposal.state != user_discovery::PENDING {
+            return self.respond(util::new_bad_req_resp(format!("The proposal was already {}", proposal.state)));
+        }
+
+        let action = if state == user_discovery::CONFIRMED {
+            if let Err(e) = user_discovery::apply(&self.config, &proposal) {
+                return self.respond_error(&format!("{}", e));
+            }
+            CONFIRM_MAPPING_ACTION
+        } else {
+            REJECT_MAPPING_ACTION
+        };
+        if let Err(e) = self.config.user_mappings.decide(id, state, admin, db::now()) {
+            return self.respond_error(&format!("{}", e));
+        }
+
+        let details = format!("slack: {}, email: {}", proposal.slack, proposal.email);
+        if let Err(e) = self.config.audit.record(admin, action, &proposal.github, &details) {
+            error!("{}", e);
+        }
+        info!("{} {} the mapping of {} to {}", admin, state, proposal.github, proposal.slack);
+        self.respond_with(StatusCode::OK, "")
+    }
+}
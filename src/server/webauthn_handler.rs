@@ -0,0 +1,307 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+use log::{error, info, warn};
+use serde_derive::Deserialize;
+use serde_json::{self, json};
+
+use crate::config::{Config, WebauthnConfig};
+use crate::server::http::{parse_json, FutureResponse, Handler};
+use crate::server::login;
+use crate::server::sessions::{Client, SessionInfo, Sessions};
+use crate::util;
+use crate::webauthn::{self, Ceremony, Challenges};
+
+pub enum WebauthnOp {
+    LoginBegin,
+    LoginFinish,
+    RegisterBegin,
+    RegisterFinish,
+    List,
+    Remove,
+}
+
+// Logging in with security keys, and (for admins) registering and removing them
+pub struct WebauthnHandler {
+    config: Arc<Config>,
+    sessions: Arc<Sessions>,
+    challenges: Arc<Challenges>,
+    op: WebauthnOp,
+}
+
+#[derive(Deserialize)]
+struct LoginBeginReq {
+    username: String,
+}
+
+// binary values in base64url, as the browser gave them
+#[derive(Deserialize)]
+struct LoginFinishReq {
+    challenge_id: String,
+    id: String,
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct RegisterFinishReq {
+    challenge_id: String,
+    name: Option<String>,
+    client_data_json: String,
+    attestation_object: String,
+}
+
+impl WebauthnHandler {
+    pub fn new(
+        config: Arc<Config>,
+        sessions: Arc<Sessions>,
+        challenges: Arc<Challenges>,
+        op: WebauthnOp,
+    ) -> Box<WebauthnHandler> {
+        Box::new(WebauthnHandler {
+            config: config,
+            sessions: sessions,
+            challenges: challenges,
+            op: op,
+        })
+    }
+}
+
+impl Handler for WebauthnHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let webauthn = match self.config.webauthn {
+            Some(ref w) => w.clone(),
+            None => return self.respond_with(StatusCode::NOT_FOUND, "Security keys are not configured"),
+        };
+
+        // logging in is for anyone, the rest for admins
+        let session = match &self.op {
+            &WebauthnOp::LoginBegin => return self.login_begin(req, webauthn),
+            &WebauthnOp::LoginFinish => return self.login_finish(req, webauthn),
+            _ => match login::get_admin_session(&self.sessions, &req) {
+                Some(s) => s,
+                None => return self.respond_with(StatusCode::FORBIDDEN, "Only admins may do this"),
+            },
+        };
+
+        match &self.op {
+            &WebauthnOp::RegisterBegin => self.register_begin(&session, &webauthn),
+            &WebauthnOp::RegisterFinish => self.register_finish(req, session, webauthn),
+            &WebauthnOp::List => self.list(&req, &session),
+            &WebauthnOp::Remove => self.remove(&req, &session),
+            &WebauthnOp::LoginBegin | &WebauthnOp::LoginFinish => unreachable!(),
+        }
+    }
+}
+
+// The configured admin manages everyone's keys, other admins only their own
+fn may_manage(config: &Config, session: &SessionInfo, user: &str) -> bool {
+    session.user == user || config.admin.as_ref().map(|a| a.name == session.user).unwrap_or(false)
+}
+
+fn decode_all(values: &[&str]) -> Option<Vec<Vec<u8>>> {
+    values.iter().map(|v| webauthn::decode(v).ok()).collect()
+}
+
+// Whether the user may log in, and as an admin or not: their role is looked up again, as for any other login
+fn login_role(config: &Config, user: &str) -> std::result::Result<(bool, bool), Response<Body>> {
+    if config.admin.as_ref().map(|a| a.name == user).unwrap_or(false) {
+        return Ok((true, true));
+    }
+    let (ldap, client) = match (&config.ldap, &config.ldap_client) {
+        (Some(l), Some(c)) => (l, c),
+        _ => {
+            warn!("Login refused: {} is neither the admin nor an LDAP user", user);
+            return Err(util::new_empty_resp(StatusCode::FORBIDDEN));
+        }
+    };
+    match client.find_user(user) {
+        Ok(Some(entry)) => match login::ldap_access(ldap, user, &entry) {
+            Some(admin) => Ok((admin, false)),
+            None => Err(util::new_empty_resp(StatusCode::FORBIDDEN)),
+        },
+        Ok(None) => {
+            warn!("Login refused: {} is not in LDAP", user);
+            Err(util::new_empty_resp(StatusCode::FORBIDDEN))
+        }
+        Err(e) => {
+            error!("Error looking up {} in LDAP: {}", user, e);
+            Err(util::new_empty_error_resp())
+        }
+    }
+}
+
+impl WebauthnHandler {
+    fn login_begin(&self, req: Request<Body>, webauthn: WebauthnConfig) -> FutureResponse {
+        let config = self.config.clone();
+        let challenges = self.challenges.clone();
+
+        parse_json(req, move |begin: LoginBeginReq| {
+            let credentials = match config.webauthn_credentials.get_for_user(&begin.username) {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("{}", e);
+                    return util::new_empty_error_resp();
+                }
+            };
+            if credentials.is_empty() {
+                return util::new_msg_resp(StatusCode::NOT_FOUND, "No security keys are registered for this user");
+            }
+
+            let (id, challenge) = challenges.start(&begin.username, Ceremony::Login);
+            let options = webauthn::login_options(&webauthn, &challenge, &credentials);
+            util::new_json_resp(json!({"challenge_id": id, "public_key": options}).to_string())
+        })
+    }
+
+    fn login_finish(&self, req: Request<Body>, webauthn: WebauthnConfig) -> FutureResponse {
+        let config = self.config.clone();
+        let sessions = self.sessions.clone();
+        let challenges = self.challenges.clone();
+        let client = Client::from_request(&req);
+
+        parse_json(req, move |finish: LoginFinishReq| {
+            let (user, challenge) = match challenges.take(&finish.challenge_id, Ceremony::Login) {
+                Some(c) => c,
+                None => return util::new_msg_resp(StatusCode::UNAUTHORIZED, "The login expired: try again"),
+            };
+            let credential = match config.webauthn_credentials.get(&finish.id) {
+                Ok(Some(c)) if c.user == user => c,
+                Ok(_) => {
+                    warn!("Security key auth failure for {}: unknown key {}", user, finish.id);
+                    return util::new_empty_resp(StatusCode::UNAUTHORIZED);
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    return util::new_empty_error_resp();
+                }
+            };
+            let decoded = match decode_all(&[&finish.client_data_json, &finish.authenticator_data, &finish.signature]) {
+                Some(d) => d,
+                None => return util::new_bad_req_resp("Invalid base64url"),
+            };
+
+            let verified =
+                webauthn::verify_assertion(&webauthn, &challenge, &credential, &decoded[0], &decoded[1], &decoded[2]);
+            let sign_count = match verified {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("Security key auth failure for {}: {}", user, e);
+                    return util::new_empty_resp(StatusCode::UNAUTHORIZED);
+                }
+            };
+            if let Err(e) = config.webauthn_credentials.record_use(&credential.id, sign_count) {
+                error!("{}", e);
+            }
+
+            let (is_admin, is_super_admin) = match login_role(&config, &user) {
+                Ok(r) => r,
+                Err(resp) => return resp,
+            };
+            info!("Security key auth success for user: {}", user);
+            login::new_session(&config, &sessions, &user, is_admin, is_super_admin, client)
+        })
+    }
+
+    fn register_begin(&self, session: &SessionInfo, webauthn: &WebauthnConfig) -> FutureResponse {
+        let existing = match self.config.webauthn_credentials.get_for_user(&session.user) {
+            Ok(c) => c,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        let (id, challenge) = self.challenges.start(&session.user, Ceremony::Register);
+        let options = webauthn::registration_options(webauthn, &session.user, &challenge, &existing);
+        self.respond(util::new_json_resp(json!({"challenge_id": id, "public_key": options}).to_string()))
+    }
+
+    fn register_finish(&self, req: Request<Body>, session: SessionInfo, webauthn: WebauthnConfig) -> FutureResponse {
+        let config = self.config.clone();
+        let challenges = self.challenges.clone();
+
+        parse_json(req, move |finish: RegisterFinishReq| {
+            let challenge = match challenges.take(&finish.challenge_id, Ceremony::Register) {
+                Some((user, challenge)) if user == session.user => challenge,
+                _ => return util::new_msg_resp(StatusCode::BAD_REQUEST, "The registration expired: try again"),
+            };
+            let decoded = match decode_all(&[&finish.client_data_json, &finish.attestation_object]) {
+                Some(d) => d,
+                None => return util::new_bad_req_resp("Invalid base64url"),
+            };
+
+            let attested = match webauthn::verify_registration(&webauthn, &challenge, &decoded[0], &decoded[1]) {
+                Ok(a) => a,
+                Err(e) => {
+                    warn!("Security key registration failure for {}: {}", session.user, e);
+                    return util::new_bad_req_resp(format!("Invalid registration: {}", e));
+                }
+            };
+            match config.webauthn_credentials.get(&webauthn::encode(&attested.id)) {
+                Ok(None) => (),
+                Ok(Some(_)) => return util::new_msg_resp(StatusCode::CONFLICT, "This key is already registered"),
+                Err(e) => {
+                    error!("{}", e);
+                    return util::new_empty_error_resp();
+                }
+            };
+
+            let name = finish.name.filter(|n| !n.trim().is_empty()).unwrap_or_else(|| "Security key".into());
+            let credential = match config.webauthn_credentials.add(&session.user, name.trim(), &attested) {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("{}", e);
+                    return util::new_empty_error_resp();
+                }
+            };
+            let audit = config.audit.record(&session.user, webauthn::REGISTER_ACTION, &session.user, &credential.name);
+            if let Err(e) = audit {
+                error!("{}", e);
+            }
+
+            info!("{} registered security key {}", session.user, credential.id);
+            match serde_json::to_string(&credential) {
+                Ok(j) => util::new_json_resp(j),
+                Err(e) => util::new_error_resp(format!("Error serializing security key: {}", e)),
+            }
+        })
+    }
+
+    fn list(&self, req: &Request<Body>, session: &SessionInfo) -> FutureResponse {
+        let user = util::parse_query(req.uri().query()).remove("user").unwrap_or_else(|| session.user.clone());
+        if !may_manage(&self.config, session, &user) {
+            return self.respond_with(StatusCode::FORBIDDEN, "Only the admin may see other users' security keys");
+        }
+
+        let credentials = match self.config.webauthn_credentials.get_for_user(&user) {
+            Ok(c) => c,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+        self.respond(util::new_json_resp(json!({ "credentials": credentials }).to_string()))
+    }
+
+    fn remove(&self, req: &Request<Body>, session: &SessionInfo) -> FutureResponse {
+        let id = match util::parse_query(req.uri().query()).remove("id") {
+            Some(id) => id,
+            None => return self.respond(util::new_bad_req_resp("No `id` specified")),
+        };
+        let credential = match self.config.webauthn_credentials.get(&id) {
+            Ok(Some(c)) => c,
+            Ok(None) => return self.respond_with(StatusCode::NOT_FOUND, "No such security key"),
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+        if !may_manage(&self.config, session, &credential.user) {
+            return self.respond_with(StatusCode::FORBIDDEN, "Only the admin may remove other users' security keys");
+        }
+
+        if let Err(e) = self.config.webauthn_credentials.remove(&id) {
+            return self.respond_error(&format!("{}", e));
+        }
+        let audit = &self.config.audit;
+        if let Err(e) = audit.record(&session.user, webauthn::REMOVE_ACTION, &credential.user, &credential.name) {
+            error!("{}", e);
+        }
+
+        info!("{} removed security key {} of {}", session.user, id, credential.user);
+        self.respond(util::new_empty_resp(StatusCode::OK))
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::config_reload::LiveConfig;
+use crate::server::github_handler::{GithubHandler, GithubHandlerState};
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+use crate::webhook_forwards::FailedForward;
+use crate::webhook_retries::{self, FailedDelivery};
+
+pub enum WebhookRetriesOp {
+    List,
+    Retry,
+    Remove,
+    ListForwards,
+}
+
+// Webhook deliveries that failed before they could be handled, waiting to be retried or dead-lettered, and
+// deliveries that could not be forwarded downstream
+pub struct WebhookRetriesHandler {
+    live_config: Arc<LiveConfig>,
+    state: Arc<GithubHandlerState>,
+    op: WebhookRetriesOp,
+}
+
+#[derive(Serialize)]
+struct WebhookRetriesResp {
+    deliveries: Vec<FailedDelivery>,
+}
+
+#[derive(Serialize)]
+struct WebhookForwardsResp {
+    forwards: Vec<FailedForward>,
+}
+
+impl WebhookRetriesHandler {
+    pub fn new(
+        live_config: Arc<LiveConfig>,
+        state: Arc<GithubHandlerState>,
+        op: WebhookRetriesOp,
+    ) -> Box<WebhookRetriesHandler> {
+        Box::new(WebhookRetriesHandler {
+            live_config: live_config,
+            state: state,
+            op: op,
+        })
+    }
+}
+
+impl Handler for WebhookRetriesHandler {
+    fn handle(&self, req: Request<Body>) -> FutureResponse {
+        let query = util::parse_query(req.uri().query());
+        match &self.op {
+            &WebhookRetriesOp::List | &WebhookRetriesOp::ListForwards => match query.get("state") {
+                Some(s) if s != webhook_retries::PENDING && s != webhook_retries::DEAD => {
+                    self.respond(util::new_bad_req_resp("Expected a `state` of \"pending\" or \"dead\""))
+                }
+                state => self.list(state.map(|s| s.as_str())),
+            },
+            &WebhookRetriesOp::Retry => match query.get("delivery_id") {
+                Some(id) => self.retry(id),
+                None => self.respond(util::new_bad_req_resp("No `delivery_id` param specified")),
+            },
+            &WebhookRetriesOp::Remove => match query.get("delivery_id") {
+                Some(id) => self.remove(id),
+                None => self.respond(util::new_bad_req_resp("No `delivery_id` param specified")),
+            },
+        }
+    }
+}
+
+impl WebhookRetriesHandler {
+    fn list(&self, state: Option<&str>) -> FutureResponse {
+        let config = self.live_config.get();
+        let json = if let WebhookRetriesOp::ListForwards = self.op {
+            config
+                .webhook_forwards
+                .list(state)
+                .and_then(|f| Ok(serde_json::to_string(&WebhookForwardsResp { forwards: f })?))
+        } else {
+            config
+                .webhook_retries
+                .list(state)
+                .and_then(|d| Ok(serde_json::to_string(&WebhookRetriesResp { deliveries: d })?))
+        };
+
+        match json {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("{}", e)),
+        }
+    }
+
+    // Handles the delivery now, whether it is pending or dead, and responds as the github handler did
+    fn retry(&self, delivery_id: &str) -> FutureResponse {
+        let config = self.live_config.get();
+        let delivery = match config.webhook_retries.get(delivery_id) {
+            Ok(Some(d)) => d,
+            Ok(None) => return self.respond_with(StatusCode::NOT_FOUND, "No such failed delivery"),
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+
+        let req = match delivery.envelope.into_request() {
+            Ok(r) => r,
+            Err(e) => return self.respond_error(&format!("{}", e)),
+        };
+        GithubHandler::for_retry(self.state.clone(), config).handle(req)
+    }
+
+    fn remove(&self, delivery_id: &str) -> FutureResponse {
+        match self.live_config.get().webhook_retries.remove(delivery_id) {
+            Ok(true) => self.respond_with(StatusCode::OK, ""),
+            Ok(false) => self.respond_with(StatusCode::NOT_FOUND, "No such failed delivery"),
+            Err(e) => self.respond_error(&format!("{}", e)),
+        }
+    }
+}
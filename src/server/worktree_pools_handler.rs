@@ -0,0 +1,35 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::git_clone_manager::GitCloneManager;
+use crate::server::http::{FutureResponse, Handler};
+use crate::util;
+use crate::worktree_pool::WorktreePoolStats;
+
+// How busy each repo's pool of merge worktrees is
+pub struct WorktreePoolsHandler {
+    clone_mgr: Arc<GitCloneManager>,
+}
+
+#[derive(Serialize)]
+struct WorktreePoolsResp {
+    pools: Vec<WorktreePoolStats>,
+}
+
+impl WorktreePoolsHandler {
+    pub fn new(clone_mgr: Arc<GitCloneManager>) -> Box<WorktreePoolsHandler> {
+        Box::new(WorktreePoolsHandler { clone_mgr: clone_mgr })
+    }
+}
+
+impl Handler for WorktreePoolsHandler {
+    fn handle(&self, _req: Request<Body>) -> FutureResponse {
+        match serde_json::to_string(&WorktreePoolsResp { pools: self.clone_mgr.worktree_stats() }) {
+            Ok(j) => self.respond(util::new_json_resp(j)),
+            Err(e) => self.respond_error(&format!("Error serializing worktree pools: {}", e)),
+        }
+    }
+}
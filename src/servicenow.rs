@@ -0,0 +1,551 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use reqwest;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::{Config, ServiceNowConfig};
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session as GithubSession};
+use crate::http_client::HTTPClient;
+use crate::jira;
+use crate::scheduler;
+
+pub const CHANGE_CONTEXT: &str = "change-request";
+
+// how often to look for approvals of pending change requests
+pub const CHECK_INTERVAL_SECS: u64 = 5 * 60;
+
+const DEFAULT_CATEGORY: &str = "Software";
+
+// values of a change request's `approval` field
+pub const APPROVED: &str = "approved";
+pub const REJECTED: &str = "rejected";
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct NewChange {
+    pub short_description: String,
+    pub description: String,
+    pub category: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assignment_group: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ChangeRecord {
+    pub number: String,
+    pub sys_id: String,
+    // "not requested", "requested", "approved" or "rejected"
+    pub approval: String,
+}
+
+// the table API wraps every record like this
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct ChangeResult {
+    result: ChangeRecord,
+}
+
+pub trait Session: Send + Sync {
+    fn create_change_request(&self, change: &NewChange) -> Result<ChangeRecord>;
+    fn get_change_request(&self, sys_id: &str) -> Result<ChangeRecord>;
+    // where people can look at (and approve) the change request
+    fn change_request_url(&self, sys_id: &str) -> String;
+}
+
+pub struct ServiceNowSession {
+    client: HTTPClient,
+    instance_url: String,
+}
+
+const FIELDS: &str = "sysparm_fields=number,sys_id,approval";
+
+impl ServiceNowSession {
+    pub fn new(config: &ServiceNowConfig) -> Result<ServiceNowSession> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::ACCEPT, "application/json".parse().unwrap());
+        let auth = base64::encode(format!("{}:{}", config.username, config.password).as_bytes());
+        headers.insert(reqwest::header::AUTHORIZATION, format!("Basic {}", auth).parse()?);
+
+        let instance_url = config.instance_url.trim_end_matches('/').to_string();
+        Ok(ServiceNowSession {
+            client: HTTPClient::new_with_headers(&format!("{}/api/now/table", instance_url), headers)?,
+            instance_url: instance_url,
+        })
+    }
+}
+
+impl Session for ServiceNowSession {
+    fn create_change_request(&self, change: &NewChange) -> Result<ChangeRecord> {
+        self.client
+            .post::<ChangeResult, NewChange>(&format!("/change_request?{}", FIELDS), change)
+            .map(|r| r.result)
+            .map_err(|e| format_err!("Error creating change request: {}", e))
+    }
+
+    fn get_change_request(&self, sys_id: &str) -> Result<ChangeRecord> {
+        self.client
+            .get::<ChangeResult>(&format!("/change_request/{}?{}", sys_id, FIELDS))
+            .map(|r| r.result)
+            .map_err(|e| format_err!("Error getting change request {}: {}", sys_id, e))
+    }
+
+    fn change_request_url(&self, sys_id: &str) -> String {
+        format!("{}/nav_to.do?uri=change_request.do?sys_id={}", self.instance_url, sys_id)
+    }
+}
+
+// The change request opened for a release PR
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ChangeRequest {
+    // "owner/name"
+    pub repo: String,
+    pub number: u32,
+    // the release branch the PR merges into
+    pub branch: String,
+    // e.g. "CHG0030001"
+    pub change_number: String,
+    pub sys_id: String,
+    pub approval: String,
+    pub created_at: i64,
+}
+
+impl ChangeRequest {
+    pub fn is_approved(&self) -> bool {
+        self.approval == APPROVED
+    }
+
+    pub fn is_rejected(&self) -> bool {
+        self.approval == REJECTED
+    }
+
+    pub fn owner_and_name(&self) -> Result<(&str, &str)> {
+        let mut parts = self.repo.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(owner), Some(name)) => Ok((owner, name)),
+            _ => Err(format_err!("Invalid repo: {}", self.repo)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ChangeRequests {
+    db: Database,
+}
+
+impl ChangeRequests {
+    pub fn new(db: Database) -> ChangeRequests {
+        ChangeRequests { db: db }
+    }
+
+    pub fn add(&self, change: &ChangeRequest) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO change_requests
+               (repo, number, branch, change_number, sys_id, approval, created_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+               ON CONFLICT (repo, number) DO UPDATE SET branch = excluded.branch,
+                   change_number = excluded.change_number, sys_id = excluded.sys_id, approval = excluded.approval,
+                   created_at = excluded.created_at"#,
+            &[
+                &change.repo as &dyn ToSql,
+                &change.number,
+                &change.branch,
+                &change.change_number,
+                &change.sys_id,
+                &change.approval,
+                &change.created_at,
+            ],
+        )
+        .map_err(|e| format_err!("Error saving change request {}: {}", change.change_number, e))?;
+
+        Ok(())
+    }
+
+    pub fn set_approval(&self, change_number: &str, approval: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "UPDATE change_requests SET approval = ?1 WHERE change_number = ?2",
+            &[&approval as &dyn ToSql, &change_number],
+        )
+        .map_err(|e| format_err!("Error updating change request {}: {}", change_number, e))?;
+
+        Ok(())
+    }
+
+    // Forgets the change requests of closed PRs
+    pub fn remove(&self, repo: &str, number: u32) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "DELETE FROM change_requests WHERE repo = ?1 AND number = ?2",
+            &[&repo as &dyn ToSql, &number],
+        )
+        .map_err(|e| format_err!("Error removing change request of {}#{}: {}", repo, number, e))?;
+
+        Ok(())
+    }
+
+    fn query(&self, sql: &str, params: &[&dyn ToSql]) -> Result<Vec<ChangeRequest>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(sql)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(params)?;
+
+        let mut changes = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            changes.push(ChangeRequest {
+                repo: cols.get(row, "repo")?,
+                number: cols.get(row, "number")?,
+                branch: cols.get(row, "branch")?,
+                change_number: cols.get(row, "change_number")?,
+                sys_id: cols.get(row, "sys_id")?,
+                approval: cols.get(row, "approval")?,
+                created_at: cols.get(row, "created_at")?,
+            });
+        }
+
+        Ok(changes)
+    }
+
+    pub fn get(&self, repo: &str, number: u32) -> Result<Option<ChangeRequest>> {
+        let changes = self.query(
+            "SELECT * FROM change_requests WHERE repo = ?1 AND number = ?2",
+            &[&repo as &dyn ToSql, &number],
+        )?;
+        Ok(changes.into_iter().next())
+    }
+
+    pub fn get_all(&self) -> Result<Vec<ChangeRequest>> {
+        self.query("SELECT * FROM change_requests ORDER BY repo, number", &[])
+    }
+
+    // Change requests still waiting for a decision
+    pub fn pending(&self) -> Result<Vec<ChangeRequest>> {
+        Ok(self.get_all()?.into_iter().filter(|c| !c.is_approved() && !c.is_rejected()).collect())
+    }
+
+    // The oldest of the repo's ("owner/name") change requests that isn't approved yet, which keeps it from
+    // being tagged
+    pub fn blocking_tags(&self, repo: &str) -> Result<Option<ChangeRequest>> {
+        let changes = self.query(
+            "SELECT * FROM change_requests WHERE repo = ?1 ORDER BY created_at",
+            &[&repo as &dyn ToSql],
+        )?;
+        Ok(changes.into_iter().find(|c| !c.is_approved()))
+    }
+
+    // Remembers a tag that octobot is deleting, so its deletion isn't taken for tampering
+    pub fn block_tag(&self, repo: &str, tag: &str, change_number: &str, now: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO blocked_tags (repo, tag, change_number, blocked_at)
+               VALUES (?1, ?2, ?3, ?4)
+               ON CONFLICT (repo, tag) DO UPDATE SET change_number = excluded.change_number,
+                   blocked_at = excluded.blocked_at"#,
+            &[&repo as &dyn ToSql, &tag, &change_number, &now],
+        )
+        .map_err(|e| format_err!("Error blocking tag {} of {}: {}", tag, repo, e))?;
+
+        Ok(())
+    }
+
+    // Whether the tag was deleted by octobot: forgets it, so that later deletions are alerted on again
+    pub fn take_blocked_tag(&self, repo: &str, tag: &str) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let count = conn
+            .execute("DELETE FROM blocked_tags WHERE repo = ?1 AND tag = ?2", &[&repo as &dyn ToSql, &tag])
+            .map_err(|e| format_err!("Error unblocking tag {} of {}: {}", tag, repo, e))?;
+
+        Ok(count > 0)
+    }
+}
+
+// Release PRs merge into a release branch
+pub fn is_release_pull_request(pull_request: &github::PullRequest, release_branch_prefix: &str) -> bool {
+    !release_branch_prefix.is_empty() && pull_request.base.ref_name.starts_with(release_branch_prefix)
+}
+
+pub fn new_change(
+    config: &ServiceNowConfig,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    jira_keys: &Vec<String>,
+) -> NewChange {
+    let mut description = format!(
+        "Release of {} to {}: {}\n\nPull request: {}",
+        repo.full_name, pull_request.base.ref_name, pull_request.title, pull_request.html_url
+    );
+    if !jira_keys.is_empty() {
+        description += &format!("\nJIRAs: {}", jira_keys.join(", "));
+    }
+
+    NewChange {
+        short_description: format!("Release {} {}", repo.full_name, pull_request.base.ref_name),
+        description: description,
+        category: config.category.clone().unwrap_or(DEFAULT_CATEGORY.into()),
+        assignment_group: config.assignment_group.clone(),
+    }
+}
+
+// Passes once the change request is approved: pending while it waits, and failed if it was rejected
+pub fn check_run(pull_request: &github::PullRequest, change: &ChangeRequest, url: &str) -> github::CheckRun {
+    let run = github::CheckRun::new(CHANGE_CONTEXT, pull_request, Some(url.to_string()));
+    let (mut run, title, summary) = if change.is_approved() {
+        (
+            run.completed(github::Conclusion::Success),
+            "Change approved",
+            format!("Change request {} was approved", change.change_number),
+        )
+    } else if change.is_rejected() {
+        (
+            run.completed(github::Conclusion::Failure),
+            "Change rejected",
+            format!("Change request {} was rejected: this release can't go out", change.change_number),
+        )
+    } else {
+        (
+            run,
+            "Waiting for change approval",
+            format!("Change request {} must be approved before this release merges", change.change_number),
+        )
+    };
+
+    run.output = Some(github::CheckOutput::new(title, &summary));
+    run
+}
+
+// Opens a change request for the release PR (unless it already has one), notes its number on the PR and on the
+// JIRAs it references, and holds the PR's change check until it is approved
+pub fn open_change_request(
+    servicenow: &dyn Session,
+    config: &Config,
+    github: &dyn GithubSession,
+    jira: Option<&dyn jira::api::Session>,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    jira_keys: &Vec<String>,
+    now: i64,
+) -> Result<ChangeRequest> {
+    let servicenow_config = match config.servicenow {
+        Some(ref c) => c,
+        None => return Err(format_err!("ServiceNow is not configured")),
+    };
+
+    if let Some(change) = config.change_requests.get(&repo.full_name, pull_request.number)? {
+        let url = servicenow.change_request_url(&change.sys_id);
+        github.create_check_run(pull_request, &check_run(pull_request, &change, &url))?;
+        return Ok(change);
+    }
+
+    let record = servicenow.create_change_request(&new_change(servicenow_config, repo, pull_request, jira_keys))?;
+    let change = ChangeRequest {
+        repo: repo.full_name.clone(),
+        number: pull_request.number,
+        branch: pull_request.base.ref_name.clone(),
+        change_number: record.number,
+        sys_id: record.sys_id,
+        approval: record.approval,
+        created_at: now,
+    };
+    config.change_requests.add(&change)?;
+    info!("Opened change request {} for {}#{}", change.change_number, repo.full_name, pull_request.number);
+
+    let url = servicenow.change_request_url(&change.sys_id);
+    let comment = format!("Change request [{}]({}) was opened for this release.", change.change_number, url);
+    if let Err(e) = github.comment_pull_request(repo.owner.login(), &repo.name, pull_request.number, &comment) {
+        error!("Error commenting change request on {}#{}: {}", repo.full_name, pull_request.number, e);
+    }
+
+    if let Some(jira) = jira {
+        let comment = format!(
+            "Change request {} was opened for release {}: {}",
+            change.change_number, pull_request.html_url, url
+        );
+        for key in jira_keys {
+            if let Err(e) = jira.comment_issue(key, &comment) {
+                error!("Error commenting change request on {}: {}", key, e);
+            }
+        }
+    }
+
+    github.create_check_run(pull_request, &check_run(pull_request, &change, &url))?;
+    Ok(change)
+}
+
+// Looks up the approval of a pending change request, and updates its PR's change check when it was decided
+pub fn refresh_approval(
+    servicenow: &dyn Session,
+    config: &Config,
+    github_app: &dyn GithubSessionFactory,
+    change: &ChangeRequest,
+) -> Result<()> {
+    let record = servicenow.get_change_request(&change.sys_id)?;
+    if record.approval == change.approval {
+        return Ok(());
+    }
+
+    config.change_requests.set_approval(&change.change_number, &record.approval)?;
+    info!("Change request {} of {}#{} is now {}", change.change_number, change.repo, change.number, record.approval);
+
+    let mut change = change.clone();
+    change.approval = record.approval;
+
+    let (owner, name) = change.owner_and_name()?;
+    let github = github_app.new_session(owner, name)?;
+    let pull_request = github.get_pull_request(owner, name, change.number)?;
+    if pull_request.state == "open" {
+        let url = servicenow.change_request_url(&change.sys_id);
+        github.create_check_run(&pull_request, &check_run(&pull_request, &change, &url))?;
+    }
+    Ok(())
+}
+
+pub struct ApprovalPoller {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    servicenow: Arc<dyn Session>,
+}
+
+impl ApprovalPoller {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        servicenow: Arc<dyn Session>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(ApprovalPoller {
+            config: config,
+            github_app: github_app,
+            servicenow: servicenow,
+        })
+    }
+}
+
+impl scheduler::Task for ApprovalPoller {
+    fn run(&self, _now: i64) -> Result<()> {
+        for change in self.config.change_requests.pending()? {
+            if let Err(e) = refresh_approval(&*self.servicenow, &self.config, &*self.github_app, &change) {
+                error!("Error checking approval of change request {}: {}", change.change_number, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (ChangeRequests, TempDir) {
+        let temp_dir = TempDir::new("servicenow.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        (ChangeRequests::new(db), temp_dir)
+    }
+
+    fn change(number: u32, change_number: &str, approval: &str, created_at: i64) -> ChangeRequest {
+        ChangeRequest {
+            repo: "some-org/some-repo".into(),
+            number: number,
+            branch: "release/1.0".into(),
+            change_number: change_number.into(),
+            sys_id: format!("sys-{}", change_number),
+            approval: approval.into(),
+            created_at: created_at,
+        }
+    }
+
+    #[test]
+    fn test_change_requests() {
+        let (changes, _temp_dir) = new_test();
+
+        changes.add(&change(1, "CHG001", "requested", 10)).unwrap();
+        changes.add(&change(2, "CHG002", "requested", 20)).unwrap();
+        assert_eq!("CHG001", changes.get("some-org/some-repo", 1).unwrap().unwrap().change_number);
+        assert_eq!(None, changes.get("some-org/some-repo", 3).unwrap());
+        assert_eq!(2, changes.pending().unwrap().len());
+        assert_eq!("CHG001", changes.blocking_tags("some-org/some-repo").unwrap().unwrap().change_number);
+        assert_eq!(None, changes.blocking_tags("some-org/other-repo").unwrap());
+
+        changes.set_approval("CHG001", APPROVED).unwrap();
+        changes.set_approval("CHG002", REJECTED).unwrap();
+        assert!(changes.pending().unwrap().is_empty());
+        // rejected changes still keep the repo from being tagged
+        assert_eq!("CHG002", changes.blocking_tags("some-org/some-repo").unwrap().unwrap().change_number);
+
+        changes.remove("some-org/some-repo", 2).unwrap();
+        assert_eq!(None, changes.blocking_tags("some-org/some-repo").unwrap());
+        assert_eq!(1, changes.get_all().unwrap().len());
+    }
+
+    #[test]
+    fn test_blocked_tags() {
+        let (changes, _temp_dir) = new_test();
+
+        assert!(!changes.take_blocked_tag("some-org/some-repo", "v1.0").unwrap());
+        changes.block_tag("some-org/some-repo", "v1.0", "CHG001", 10).unwrap();
+        assert!(!changes.take_blocked_tag("some-org/some-repo", "v1.1").unwrap());
+        assert!(changes.take_blocked_tag("some-org/some-repo", "v1.0").unwrap());
+        assert!(!changes.take_blocked_tag("some-org/some-repo", "v1.0").unwrap());
+    }
+
+    #[test]
+    fn test_is_release_pull_request() {
+        let mut pr = github::PullRequest::new();
+        pr.base.ref_name = "release/1.0".into();
+        assert!(is_release_pull_request(&pr, "release/"));
+        assert!(!is_release_pull_request(&pr, ""));
+
+        pr.base.ref_name = "master".into();
+        assert!(!is_release_pull_request(&pr, "release/"));
+    }
+
+    #[test]
+    fn test_check_run() {
+        let mut pr = github::PullRequest::new();
+        pr.head.sha = "abcdef".into();
+
+        let run = check_run(&pr, &change(1, "CHG001", "requested", 10), "http://the-change");
+        assert_eq!(None, run.conclusion);
+        assert_eq!("abcdef", run.head_sha);
+        assert_eq!(Some("http://the-change".to_string()), run.details_url);
+        assert_eq!(Some("Waiting for change approval".to_string()), run.output.unwrap().title);
+
+        let run = check_run(&pr, &change(1, "CHG001", APPROVED, 10), "http://the-change");
+        assert_eq!(Some(github::Conclusion::Success), run.conclusion);
+
+        let run = check_run(&pr, &change(1, "CHG001", REJECTED, 10), "http://the-change");
+        assert_eq!(Some(github::Conclusion::Failure), run.conclusion);
+    }
+
+    #[test]
+    fn test_new_change() {
+        let config = ServiceNowConfig {
+            instance_url: "https://company.service-now.com".into(),
+            username: "octobot".into(),
+            password: "the-password".into(),
+            assignment_group: Some("Release Managers".into()),
+            category: None,
+            repos: vec!["some-org".into()],
+        };
+        let repo = github::Repo::parse("http://the-github-host/some-org/some-repo").unwrap();
+        let mut pr = github::PullRequest::new();
+        pr.base.ref_name = "release/1.0".into();
+        pr.title = "Release 1.0".into();
+        pr.html_url = "http://the-pr".into();
+
+        let change = new_change(&config, &repo, &pr, &vec!["SER-1".into(), "SER-2".into()]);
+        assert_eq!("Release some-org/some-repo release/1.0", change.short_description);
+        assert_eq!(
+            "Release of some-org/some-repo to release/1.0: Release 1.0\n\n\
+             Pull request: http://the-pr\n\
+             JIRAs: SER-1, SER-2",
+            change.description
+        );
+        assert_eq!("Software", change.category);
+        assert_eq!(Some("Release Managers".to_string()), change.assignment_group);
+    }
+}
@@ -0,0 +1,168 @@
+use failure::format_err;
+use regex::Regex;
+
+use crate::errors::*;
+use crate::github;
+use crate::github::api::Session;
+use crate::path_labels;
+
+pub const SIZE_LABELS: [&str; 5] = ["size/XS", "size/S", "size/M", "size/L", "size/XL"];
+
+const DEFAULT_THRESHOLDS: [u32; 4] = [10, 30, 100, 500];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SizeLabelConfig {
+    // upper bounds (inclusive) of changed lines for XS, S, M, and L. Anything bigger is XL.
+    pub thresholds: Vec<u32>,
+    // globs of generated files to leave out of the count
+    pub excludes: Vec<String>,
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(|c| c == ',' || c == '\n')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+impl SizeLabelConfig {
+    pub fn new() -> SizeLabelConfig {
+        SizeLabelConfig {
+            thresholds: DEFAULT_THRESHOLDS.to_vec(),
+            excludes: vec![],
+        }
+    }
+
+    pub fn parse(thresholds: &str, excludes: &str) -> Result<SizeLabelConfig> {
+        let mut config = SizeLabelConfig::new();
+
+        let values = split_list(thresholds);
+        if !values.is_empty() {
+            let values = values
+                .iter()
+                .map(|v| v.parse::<u32>().map_err(|_| format_err!("Invalid size threshold: '{}'", v)))
+                .collect::<Result<Vec<_>>>()?;
+
+            if values.len() != DEFAULT_THRESHOLDS.len() {
+                return Err(format_err!(
+                    "Expected {} size thresholds, got {}",
+                    DEFAULT_THRESHOLDS.len(),
+                    values.len()
+                ));
+            }
+            if values.windows(2).any(|w| w[0] >= w[1]) {
+                return Err(format_err!("Size thresholds must be increasing: {}", thresholds));
+            }
+            config.thresholds = values;
+        }
+
+        config.excludes = split_list(excludes);
+        for glob in &config.excludes {
+            path_labels::path_regex(glob)?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn label_for(&self, changed_lines: u32) -> &'static str {
+        for (i, max) in self.thresholds.iter().enumerate() {
+            if changed_lines <= *max {
+                return SIZE_LABELS[i];
+            }
+        }
+        SIZE_LABELS[SIZE_LABELS.len() - 1]
+    }
+
+    pub fn changed_lines(&self, files: &Vec<github::PullRequestFile>) -> u32 {
+        let excludes = self
+            .excludes
+            .iter()
+            .filter_map(|g| path_labels::path_regex(g).ok())
+            .collect::<Vec<Regex>>();
+
+        files
+            .iter()
+            .filter(|f| !excludes.iter().any(|r| r.is_match(&f.filename)))
+            .map(|f| f.additions + f.deletions)
+            .sum()
+    }
+}
+
+// Applies the size label for the PR and removes any other (stale) size labels.
+pub fn apply_size_label(
+    github: &dyn Session,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    config: &SizeLabelConfig,
+) -> Result<()> {
+    let owner = repo.owner.login();
+    let files = github.get_pull_request_files(owner, &repo.name, pull_request.number)?;
+    let label = config.label_for(config.changed_lines(&files));
+
+    let current = github
+        .get_pull_request_labels(owner, &repo.name, pull_request.number)?
+        .into_iter()
+        .map(|l| l.name)
+        .collect::<Vec<_>>();
+
+    for stale in current.iter().filter(|l| SIZE_LABELS.contains(&l.as_str()) && *l != label) {
+        github.remove_pull_request_label(owner, &repo.name, pull_request.number, stale)?;
+    }
+
+    if !current.iter().any(|l| l == label) {
+        github.add_pull_request_labels(owner, &repo.name, pull_request.number, vec![label.to_string()])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, additions: u32, deletions: u32) -> github::PullRequestFile {
+        let mut f = github::PullRequestFile::new(name);
+        f.additions = additions;
+        f.deletions = deletions;
+        f
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(SizeLabelConfig::new(), SizeLabelConfig::parse("", "").unwrap());
+
+        let config = SizeLabelConfig::parse("1, 2, 3, 4", "Cargo.lock,\n**/*.pb.go").unwrap();
+        assert_eq!(vec![1, 2, 3, 4], config.thresholds);
+        assert_eq!(vec!["Cargo.lock", "**/*.pb.go"], config.excludes);
+
+        assert!(SizeLabelConfig::parse("1,2,3", "").is_err());
+        assert!(SizeLabelConfig::parse("1,2,3,x", "").is_err());
+        assert!(SizeLabelConfig::parse("1,2,2,4", "").is_err());
+    }
+
+    #[test]
+    fn test_label_for() {
+        let config = SizeLabelConfig::new();
+        assert_eq!("size/XS", config.label_for(0));
+        assert_eq!("size/XS", config.label_for(10));
+        assert_eq!("size/S", config.label_for(11));
+        assert_eq!("size/M", config.label_for(100));
+        assert_eq!("size/L", config.label_for(500));
+        assert_eq!("size/XL", config.label_for(501));
+    }
+
+    #[test]
+    fn test_changed_lines() {
+        let config = SizeLabelConfig::parse("", "Cargo.lock, **/generated/**").unwrap();
+        let files = vec![
+            file("src/main.rs", 10, 5),
+            file("Cargo.lock", 300, 200),
+            file("api/generated/client.rs", 1000, 0),
+            file("README.md", 1, 1),
+        ];
+
+        assert_eq!(17, config.changed_lines(&files));
+    }
+}
@@ -1,20 +1,83 @@
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
+use failure::format_err;
 use futures::{future, Future};
 use reqwest;
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
 use tokio;
 use log::{error, info};
 
+use crate::deliveries::{self, Deliveries};
+use crate::error_reports;
+use crate::errors::*;
+use crate::faults;
+use crate::resilience;
+use crate::slack_blocks::{self, Block};
+use crate::slack_threads::SlackThreads;
 use crate::util;
 use crate::worker;
 
-#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 pub struct SlackAttachment {
     pub text: String,
     pub title: Option<String>,
     pub title_link: Option<String>,
     pub color: Option<String>,
+    // identifies what interactive actions on this attachment apply to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<SlackAction>,
+    // short labelled values, e.g. reviewers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<SlackField>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SlackField {
+    pub title: String,
+    pub value: String,
+    pub short: bool,
+}
+
+// A button on an attachment. Buttons with a url just open it; others are posted back to octobot.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SlackAction {
+    pub name: String,
+    pub text: String,
+    #[serde(rename = "type")]
+    pub action_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<String>,
+}
+
+impl SlackAction {
+    pub fn button(name: &str, text: &str) -> SlackAction {
+        SlackAction {
+            name: name.into(),
+            text: text.into(),
+            action_type: "button".into(),
+            value: Some(name.into()),
+            url: None,
+            style: None,
+        }
+    }
+
+    pub fn link(text: &str, url: &str) -> SlackAction {
+        SlackAction {
+            name: "link".into(),
+            text: text.into(),
+            action_type: "button".into(),
+            value: None,
+            url: Some(url.into()),
+            style: None,
+        }
+    }
 }
 
 impl SlackAttachment {
@@ -24,6 +87,9 @@ impl SlackAttachment {
             title: None,
             title_link: None,
             color: None,
+            callback_id: None,
+            actions: vec![],
+            fields: vec![],
         }
     }
 }
@@ -58,6 +124,25 @@ impl SlackAttachmentBuilder {
         self
     }
 
+    pub fn callback_id<S: Into<String>>(&mut self, value: S) -> &mut SlackAttachmentBuilder {
+        self.attachment.callback_id = Some(value.into());
+        self
+    }
+
+    pub fn action(&mut self, action: SlackAction) -> &mut SlackAttachmentBuilder {
+        self.attachment.actions.push(action);
+        self
+    }
+
+    pub fn field<T: Into<String>, V: Into<String>>(&mut self, title: T, value: V) -> &mut SlackAttachmentBuilder {
+        self.attachment.fields.push(SlackField {
+            title: title.into(),
+            value: value.into(),
+            short: true,
+        });
+        self
+    }
+
     pub fn build(&self) -> SlackAttachment {
         self.attachment.clone()
     }
@@ -65,15 +150,135 @@ impl SlackAttachmentBuilder {
 
 #[derive(Serialize, Clone, PartialEq)]
 struct SlackMessage {
+    // with blocks, this is only used for notifications
     text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     attachments: Vec<SlackAttachment>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    blocks: Vec<Block>,
     channel: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_ts: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PostMessageResp {
+    ok: bool,
+    // the channel's id, rather than the name it was sent to
+    channel: Option<String>,
+    ts: Option<String>,
+    error: Option<String>,
+}
+
+// Threads need the web API: webhooks don't say which message they created.
+const POST_MESSAGE_URL: &str = "https://slack.com/api/chat.postMessage";
+
+const API_URL: &str = "https://slack.com/api/";
+
+// Other slack Web API methods, authorized with the bot token
+pub struct SlackWebApi {
+    token: String,
+    client: reqwest::Client,
+}
+
+impl SlackWebApi {
+    pub fn new(token: &str) -> SlackWebApi {
+        SlackWebApi {
+            token: token.into(),
+            client: resilience::client(faults::Service::Slack),
+        }
+    }
+
+    // Sends the request made by `req`, again if slack is rate limiting
+    fn send<F>(&self, req: F) -> Result<reqwest::Response>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        resilience::run(faults::Service::Slack, false, || {
+            req().header(reqwest::header::AUTHORIZATION, format!("Bearer {}", self.token)).send()?.error_for_status()
+        })
+    }
+
+    fn check(method: &str, mut res: reqwest::Response) -> Result<serde_json::Value> {
+        let resp: serde_json::Value = res.json()?;
+        if resp["ok"] != true {
+            return Err(format_err!("{} failed: {}", method, resp["error"].as_str().unwrap_or("unknown error")));
+        }
+        Ok(resp)
+    }
+
+    // For methods that take JSON
+    pub fn post(&self, method: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let res = self.send(|| self.client.post(&format!("{}{}", API_URL, method)).json(&body))?;
+        SlackWebApi::check(method, res)
+    }
+
+    // For methods that only take query parameters, like users.info
+    pub fn get(&self, method: &str, params: &[(&str, &str)]) -> Result<serde_json::Value> {
+        let res = self.send(|| self.client.get(&format!("{}{}", API_URL, method)).query(params))?;
+        SlackWebApi::check(method, res)
+    }
+
+    // files.upload, which takes a multipart form instead of JSON
+    pub fn upload(
+        &self,
+        channel: &str,
+        filename: &str,
+        title: &str,
+        data: Vec<u8>,
+        thread_ts: Option<&str>,
+    ) -> Result<serde_json::Value> {
+        // a form can only be sent once
+        let form = || {
+            let form = reqwest::multipart::Form::new()
+                .text("channels", channel.to_string())
+                .text("filename", filename.to_string())
+                .text("title", title.to_string())
+                .part("file", reqwest::multipart::Part::bytes(data.clone()).file_name(filename.to_string()));
+            match thread_ts {
+                Some(ts) => form.text("thread_ts", ts.to_string()),
+                None => form,
+            }
+        };
+
+        let res = self.send(|| self.client.post(&format!("{}files.upload", API_URL)).multipart(form()))?;
+        SlackWebApi::check("files.upload", res)
+    }
+}
+
+// Sends a message right away rather than through the worker, e.g. to check that octobot can post to a channel.
+// Uses the bot token if there is one, and the incoming webhook otherwise.
+pub fn send_now(webhook_url: Option<&str>, bot_token: Option<&str>, channel: &str, msg: &str) -> Result<()> {
+    let body = serde_json::json!({
+        "channel": channel,
+        "text": msg,
+    });
+    if let Some(token) = bot_token {
+        SlackWebApi::new(token).post("chat.postMessage", body)?;
+        return Ok(());
+    }
+
+    let url = webhook_url
+        .filter(|u| !u.is_empty())
+        .ok_or_else(|| format_err!("Neither main.slack_webhook_url nor main.slack_bot_token is configured"))?;
+    let res = resilience::client(faults::Service::Slack).post(url).json(&body).send()?;
+    if !res.status().is_success() {
+        return Err(format_err!("Slack responded with {}", res.status()));
+    }
+    Ok(())
 }
 
 // the main object for sending messages to slack
 struct Slack {
     client: reqwest::r#async::Client,
     webhook_url: String,
+    // send attachments instead of blocks
+    legacy_format: bool,
+    bot_token: Option<String>,
+    threads: Option<SlackThreads>,
+    deliveries: Deliveries,
+    // held while starting threads so that two quick messages about a PR don't both start one
+    thread_lock: Mutex<()>,
     recent_messages: Mutex<Vec<SlackMessage>>,
 }
 
@@ -81,23 +286,46 @@ const TRIM_MESSAGES_AT: usize = 200;
 const TRIM_MESSAGES_TO: usize = 20;
 
 impl Slack {
-    pub fn new(webhook_url: Option<String>) -> Slack {
+    pub fn new(
+        webhook_url: Option<String>,
+        legacy_format: bool,
+        bot_token: Option<String>,
+        threads: Option<SlackThreads>,
+        deliveries: Deliveries,
+    ) -> Slack {
         Slack {
-            client: reqwest::r#async::Client::new(),
+            client: resilience::async_client(faults::Service::Slack),
             webhook_url: webhook_url.unwrap_or(String::new()),
+            legacy_format: legacy_format,
+            bot_token: bot_token,
+            threads: threads,
+            deliveries: deliveries,
+            thread_lock: Mutex::new(()),
             recent_messages: Mutex::new(Vec::new()),
         }
     }
 
-    fn send(&self, channel: &str, msg: &str, attachments: Vec<SlackAttachment>) {
-        if self.webhook_url.is_empty() {
+    fn send(&self, channel: &str, msg: &str, attachments: Vec<SlackAttachment>, thread_key: Option<String>) {
+        if self.webhook_url.is_empty() && self.bot_token.is_none() {
             return
         }
 
-        let slack_msg = SlackMessage {
-            text: msg.to_string(),
-            attachments: attachments,
-            channel: channel.to_string(),
+        let slack_msg = if self.legacy_format {
+            SlackMessage {
+                text: msg.to_string(),
+                attachments: attachments,
+                blocks: vec![],
+                channel: channel.to_string(),
+                thread_ts: None,
+            }
+        } else {
+            SlackMessage {
+                text: msg.to_string(),
+                attachments: vec![],
+                blocks: slack_blocks::from_attachments(msg, &attachments),
+                channel: channel.to_string(),
+                thread_ts: None,
+            }
         };
 
         if !self.is_unique(&slack_msg) {
@@ -105,27 +333,118 @@ impl Slack {
             return;
         }
 
+        if let (Some(thread_key), Some(token), Some(threads)) = (thread_key, &self.bot_token, &self.threads) {
+            info!("Sending message to #{} in thread {}", channel, thread_key);
+            let sent = self
+                .deliveries
+                .track(deliveries::SLACK, channel, || self.send_threaded(slack_msg, &thread_key, token, threads));
+            if let Err(e) = sent {
+                error!("Error sending slack message: {}", e);
+            }
+            return;
+        }
+
+        if self.webhook_url.is_empty() {
+            return
+        }
+
+        let started = Instant::now();
+        if let Err(e) = resilience::check(faults::Service::Slack) {
+            error!("Error sending slack message: {}", e);
+            self.deliveries.attempted(deliveries::SLACK, channel, started, Some(format!("{}", e)));
+            return;
+        }
+        if let Err(e) = faults::check(faults::Service::Slack) {
+            resilience::record(faults::Service::Slack, false);
+            error!("Error sending slack message: {}", e);
+            self.deliveries.attempted(deliveries::SLACK, channel, started, Some(format!("{}", e)));
+            return;
+        }
+
         info!("Sending message to #{}", channel);
-        tokio::spawn(self.client.post(&self.webhook_url).json(&slack_msg).send().then(|res| {
-            match res {
-                Ok(_) => info!("Successfully sent slack message"),
-                Err(e) => error!("Error sending slack message: {}", e),
+        // the response comes back on another thread, outside the context of the delivery that sent the message
+        let context = error_reports::current_context();
+        let store = self.deliveries.clone();
+        let channel = channel.to_string();
+        tokio::spawn(self.client.post(&self.webhook_url).json(&slack_msg).send().then(move |res| {
+            let error = match res {
+                Ok(ref r) if resilience::unavailable_status(r.status()) => {
+                    resilience::record(faults::Service::Slack, false);
+                    error!("Error sending slack message: {}", r.status());
+                    Some(format!("{}", r.status()))
+                }
+                Ok(ref r) if !r.status().is_success() => {
+                    resilience::record(faults::Service::Slack, true);
+                    error!("Error sending slack message: {}", r.status());
+                    Some(format!("{}", r.status()))
+                }
+                Ok(_) => {
+                    resilience::record(faults::Service::Slack, true);
+                    info!("Successfully sent slack message");
+                    None
+                }
+                Err(e) => {
+                    resilience::record(faults::Service::Slack, !resilience::unavailable(&e));
+                    error!("Error sending slack message: {}", e);
+                    Some(format!("{}", e))
+                }
             };
+            store.attempted_in(&context, deliveries::SLACK, &channel, started, error);
             future::ok::<(), ()>(())
         }));
     }
 
+    fn send_threaded(&self, slack_msg: SlackMessage, thread_key: &str, token: &str, threads: &SlackThreads) -> Result<()> {
+        let _lock = self.thread_lock.lock().unwrap();
+
+        let mut slack_msg = slack_msg;
+        slack_msg.thread_ts = threads.get_ts(thread_key, &slack_msg.channel)?;
+
+        let client = resilience::client(faults::Service::Slack);
+        let mut res = resilience::run(faults::Service::Slack, false, || {
+            client
+                .post(POST_MESSAGE_URL)
+                .header(reqwest::header::AUTHORIZATION, format!("Bearer {}", token))
+                .json(&slack_msg)
+                .send()?
+                .error_for_status()
+        })?;
+        let resp: PostMessageResp = res.json()?;
+        if !resp.ok {
+            return Err(format_err!("{}", resp.error.unwrap_or("unknown error".into())));
+        }
+
+        if let (Some(channel_id), Some(ts)) = (&resp.channel, &resp.ts) {
+            threads.set_message(channel_id, ts, thread_key)?;
+        }
+        if slack_msg.thread_ts.is_none() {
+            if let Some(ts) = resp.ts {
+                threads.set_ts(thread_key, &slack_msg.channel, &ts)?;
+            }
+        }
+
+        info!("Successfully sent slack message");
+        Ok(())
+    }
+
     fn is_unique(&self, req: &SlackMessage) -> bool {
         let mut recent_messages = self.recent_messages.lock().unwrap();
         util::check_unique_event(req.clone(), &mut *recent_messages, TRIM_MESSAGES_AT, TRIM_MESSAGES_TO)
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct SlackRequest {
     pub channel: String,
     pub msg: String,
     pub attachments: Vec<SlackAttachment>,
+    // messages with the same key are posted as replies in one thread
+    pub thread_key: Option<String>,
+    // messages with the same key (and channel) may be merged into one. see slack_batch
+    pub batch_key: Option<String>,
+    // the outbox receipts of the messages this delivers, marked delivered once it is sent. see outbox
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub receipts: Vec<String>,
 }
 
 struct Runner {
@@ -137,17 +456,33 @@ pub fn req(channel: &str, msg: &str, attachments: Vec<SlackAttachment>) -> Slack
         channel: channel.into(),
         msg: msg.into(),
         attachments: attachments,
+        thread_key: None,
+        batch_key: None,
+        receipts: vec![],
+    }
+}
+
+pub fn threaded_req(channel: &str, msg: &str, attachments: Vec<SlackAttachment>, thread_key: &str) -> SlackRequest {
+    SlackRequest {
+        thread_key: Some(thread_key.into()),
+        ..req(channel, msg, attachments)
     }
 }
 
-pub fn new_runner(webhook_url: Option<String>) -> Arc<dyn worker::Runner<SlackRequest>> {
+pub fn new_runner(
+    webhook_url: Option<String>,
+    legacy_format: bool,
+    bot_token: Option<String>,
+    threads: Option<SlackThreads>,
+    deliveries: Deliveries,
+) -> Arc<dyn worker::Runner<SlackRequest>> {
     Arc::new(Runner {
-        slack: Arc::new(Slack::new(webhook_url)),
+        slack: Arc::new(Slack::new(webhook_url, legacy_format, bot_token, threads, deliveries)),
     })
 }
 
 impl worker::Runner<SlackRequest> for Runner {
     fn handle(&self, req: SlackRequest) {
-        self.slack.send(&req.channel, &req.msg, req.attachments);
+        self.slack.send(&req.channel, &req.msg, req.attachments, req.thread_key);
     }
 }
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use log::error;
+use tokio;
+use tokio::timer::Delay;
+
+use crate::slack::{SlackAttachment, SlackRequest};
+use crate::worker::Worker;
+
+// Requests for the same channel and batch key (i.e. the same PR) that arrive within the window are
+// merged into one message, so that a burst of events doesn't post a burst of messages.
+pub struct SlackBatcher {
+    slack: Arc<dyn Worker<SlackRequest>>,
+    window: Duration,
+    runtime: Arc<Mutex<tokio::runtime::Runtime>>,
+    // (channel, batch key) => requests waiting for the window to pass
+    pending: Arc<Mutex<HashMap<(String, String), Vec<SlackRequest>>>>,
+}
+
+impl SlackBatcher {
+    // Batches requests sent to |slack|. A zero window disables batching.
+    pub fn wrap(
+        slack: Arc<dyn Worker<SlackRequest>>,
+        window_secs: u64,
+        runtime: Arc<Mutex<tokio::runtime::Runtime>>,
+    ) -> Arc<dyn Worker<SlackRequest>> {
+        if window_secs == 0 {
+            return slack;
+        }
+
+        Arc::new(SlackBatcher {
+            slack: slack,
+            window: Duration::from_secs(window_secs),
+            runtime: runtime,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    fn flush_later(&self, key: (String, String)) {
+        let slack = self.slack.clone();
+        let pending = self.pending.clone();
+
+        let timer = Delay::new(Instant::now() + self.window).then(move |result| -> Result<(), ()> {
+            if let Err(e) = result {
+                error!("Slack batch timer error: {}", e);
+            }
+            let merged = pending.lock().unwrap().remove(&key).and_then(merge);
+            if let Some(req) = merged {
+                slack.send(req);
+            }
+            Ok(())
+        });
+
+        self.runtime.lock().unwrap().spawn(timer);
+    }
+}
+
+impl Worker<SlackRequest> for SlackBatcher {
+    fn send(&self, req: SlackRequest) {
+        let key = match req.batch_key {
+            Some(ref k) => (req.channel.clone(), k.clone()),
+            None => return self.slack.send(req),
+        };
+
+        let is_new = {
+            let mut pending = self.pending.lock().unwrap();
+            let batch = pending.entry(key.clone()).or_insert(vec![]);
+            batch.push(req);
+            batch.len() == 1
+        };
+
+        // the first request for a target starts its timer
+        if is_new {
+            self.flush_later(key);
+        }
+    }
+}
+
+// Merges a batch of requests to one target into a single request. Repeated messages and
+// attachments are only included once.
+pub fn merge(batch: Vec<SlackRequest>) -> Option<SlackRequest> {
+    let mut requests = batch.into_iter();
+    let mut merged = requests.next()?;
+
+    let mut msgs = vec![merged.msg.clone()];
+    let mut attachments: Vec<SlackAttachment> = vec![];
+    for attachment in merged.attachments.drain(..) {
+        if !attachments.contains(&attachment) {
+            attachments.push(attachment);
+        }
+    }
+
+    for req in requests {
+        if !msgs.contains(&req.msg) {
+            msgs.push(req.msg);
+        }
+        // each merged message is delivered by this one
+        merged.receipts.extend(req.receipts);
+        for attachment in req.attachments {
+            if !attachments.contains(&attachment) {
+                attachments.push(attachment);
+            }
+        }
+    }
+
+    merged.msg = msgs.join("\n");
+    merged.attachments = attachments;
+    Some(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Sender};
+
+    use crate::runtime;
+    use crate::slack::{self, SlackAttachmentBuilder};
+    use crate::util;
+
+    struct TestWorker {
+        tx: Mutex<Sender<SlackRequest>>,
+    }
+
+    impl Worker<SlackRequest> for TestWorker {
+        fn send(&self, req: SlackRequest) {
+            self.tx.lock().unwrap().send(req).unwrap();
+        }
+    }
+
+    fn batched(channel: &str, msg: &str, attachments: Vec<SlackAttachment>) -> SlackRequest {
+        let mut req = slack::req(channel, msg, attachments);
+        req.batch_key = Some("some-user/some-repo#32".into());
+        req
+    }
+
+    #[test]
+    fn test_merge() {
+        let pr = SlackAttachmentBuilder::new("").title("Pull Request #32").build();
+        let comment = SlackAttachmentBuilder::new("nice").title("joe said:").build();
+
+        let merged = merge(vec![
+            batched("the-channel", "Pull Request synchronized", vec![pr.clone()]),
+            batched("the-channel", "Comment on PR #32", vec![pr.clone(), comment.clone()]),
+            batched("the-channel", "Pull Request synchronized", vec![pr.clone()]),
+        ])
+        .unwrap();
+
+        assert_eq!("Pull Request synchronized\nComment on PR #32", merged.msg);
+        assert_eq!(vec![pr, comment], merged.attachments);
+        assert_eq!("the-channel", merged.channel);
+
+        let mut first = batched("the-channel", "first", vec![]);
+        first.receipts = vec!["delivery-1-abc".into()];
+        let mut second = batched("the-channel", "second", vec![]);
+        second.receipts = vec!["delivery-2-def".into()];
+        assert_eq!(vec!["delivery-1-abc", "delivery-2-def"], merge(vec![first, second]).unwrap().receipts);
+
+        assert_eq!(None, merge(vec![]));
+    }
+
+    #[test]
+    fn test_batches_within_window() {
+        let (tx, rx) = channel();
+        let worker = Arc::new(TestWorker { tx: Mutex::new(tx) });
+        let runtime = Arc::new(Mutex::new(runtime::new(1, "test")));
+        let batcher = SlackBatcher::wrap(worker, 1, runtime);
+
+        batcher.send(batched("the-channel", "first", vec![]));
+        batcher.send(batched("the-channel", "second", vec![]));
+        batcher.send(batched("other-channel", "first", vec![]));
+        // not batched: sent right away
+        batcher.send(slack::req("the-channel", "unbatched", vec![]));
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!("unbatched", util::recv_timeout(&rx, timeout).unwrap().msg);
+
+        let mut sent = vec![
+            util::recv_timeout(&rx, timeout).unwrap(),
+            util::recv_timeout(&rx, timeout).unwrap(),
+        ];
+        sent.sort_by(|a, b| a.channel.cmp(&b.channel));
+        assert_eq!(("other-channel", "first"), (sent[0].channel.as_str(), sent[0].msg.as_str()));
+        assert_eq!(("the-channel", "first\nsecond"), (sent[1].channel.as_str(), sent[1].msg.as_str()));
+    }
+}
@@ -0,0 +1,248 @@
+use serde_derive::Serialize;
+
+use crate::slack::{SlackAction, SlackAttachment};
+
+// Slack rejects section text longer than this
+const MAX_TEXT_LEN: usize = 3000;
+// ...and context blocks with more elements than this
+const MAX_CONTEXT_ELEMENTS: usize = 10;
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Text {
+    #[serde(rename = "type")]
+    pub text_type: String,
+    pub text: String,
+}
+
+impl Text {
+    pub fn mrkdwn(text: &str) -> Text {
+        Text {
+            text_type: "mrkdwn".into(),
+            text: truncate(text),
+        }
+    }
+
+    pub fn plain(text: &str) -> Text {
+        Text {
+            text_type: "plain_text".into(),
+            text: truncate(text),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Button {
+    #[serde(rename = "type")]
+    pub element_type: String,
+    pub text: Text,
+    pub action_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<String>,
+}
+
+impl Button {
+    fn from_action(action: &SlackAction) -> Button {
+        Button {
+            element_type: "button".into(),
+            text: Text::plain(&action.text),
+            action_id: action.name.clone(),
+            value: action.value.clone(),
+            url: action.url.clone(),
+            style: action.style.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    Section {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        text: Option<Text>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<Text>,
+    },
+    Context {
+        elements: Vec<Text>,
+    },
+    // The block_id is what the legacy callback_id was: it says what the buttons apply to.
+    Actions {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        block_id: Option<String>,
+        elements: Vec<Button>,
+    },
+}
+
+fn truncate(text: &str) -> String {
+    if text.len() <= MAX_TEXT_LEN {
+        return text.into();
+    }
+    let mut end = MAX_TEXT_LEN - 1;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
+
+fn color_emoji(color: &Option<String>) -> Option<&'static str> {
+    match color.as_ref().map(|c| c.as_str()) {
+        Some("good") => Some(":white_check_mark:"),
+        Some("warning") => Some(":warning:"),
+        Some("danger") => Some(":x:"),
+        _ => None,
+    }
+}
+
+fn section_text(attachment: &SlackAttachment) -> String {
+    let mut lines = vec![];
+    if let Some(ref title) = attachment.title {
+        let title = match attachment.title_link {
+            Some(ref link) => format!("*<{}|{}>*", link, title),
+            None => format!("*{}*", title),
+        };
+        lines.push(match color_emoji(&attachment.color) {
+            Some(emoji) => format!("{} {}", emoji, title),
+            None => title,
+        });
+    }
+    if !attachment.text.trim().is_empty() {
+        lines.push(attachment.text.trim().to_string());
+    }
+    lines.join("\n")
+}
+
+// Builds the Block Kit equivalent of a message with legacy attachments.
+// Attachments that are just a line of text (e.g. pushed commits) become context; the rest become sections.
+pub fn from_attachments(msg: &str, attachments: &Vec<SlackAttachment>) -> Vec<Block> {
+    let mut blocks = vec![Block::Section {
+        text: Some(Text::mrkdwn(msg)),
+        fields: vec![],
+    }];
+
+    let mut context = vec![];
+    for attachment in attachments {
+        let is_context = attachment.title.is_none() && attachment.fields.is_empty() && attachment.actions.is_empty();
+        if is_context {
+            if !attachment.text.trim().is_empty() {
+                context.push(Text::mrkdwn(attachment.text.trim()));
+            }
+            continue;
+        }
+        push_context(&mut blocks, &mut context);
+
+        let text = section_text(attachment);
+        let fields = attachment
+            .fields
+            .iter()
+            .map(|f| Text::mrkdwn(&format!("*{}*\n{}", f.title, f.value)))
+            .collect::<Vec<_>>();
+        if !text.is_empty() || !fields.is_empty() {
+            blocks.push(Block::Section {
+                text: if text.is_empty() { None } else { Some(Text::mrkdwn(&text)) },
+                fields: fields,
+            });
+        }
+
+        if !attachment.actions.is_empty() {
+            blocks.push(Block::Actions {
+                block_id: attachment.callback_id.clone(),
+                elements: attachment.actions.iter().map(Button::from_action).collect(),
+            });
+        }
+    }
+    push_context(&mut blocks, &mut context);
+
+    blocks
+}
+
+fn push_context(blocks: &mut Vec<Block>, context: &mut Vec<Text>) {
+    for chunk in context.chunks(MAX_CONTEXT_ELEMENTS) {
+        blocks.push(Block::Context { elements: chunk.to_vec() });
+    }
+    context.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{self, json};
+
+    use crate::slack::SlackAttachmentBuilder;
+
+    #[test]
+    fn test_from_attachments() {
+        let attachments = vec![
+            SlackAttachmentBuilder::new("")
+                .title("Pull Request #32: \"The PR\"")
+                .title_link("http://the-pr")
+                .field("Reviewers", "joe.reviewer")
+                .callback_id("pr:some-user/some-repo#32")
+                .action(SlackAction::button("approve", "Approve"))
+                .build(),
+            SlackAttachmentBuilder::new("<http://commit|1111111>: first").build(),
+            SlackAttachmentBuilder::new("<http://commit|2222222>: second").build(),
+        ];
+
+        let blocks = from_attachments("joe pushed 2 commit(s)", &attachments);
+        assert_eq!(
+            json!([
+                {"type": "section", "text": {"type": "mrkdwn", "text": "joe pushed 2 commit(s)"}},
+                {
+                    "type": "section",
+                    "text": {"type": "mrkdwn", "text": "*<http://the-pr|Pull Request #32: \"The PR\">*"},
+                    "fields": [{"type": "mrkdwn", "text": "*Reviewers*\njoe.reviewer"}],
+                },
+                {
+                    "type": "actions",
+                    "block_id": "pr:some-user/some-repo#32",
+                    "elements": [{
+                        "type": "button",
+                        "text": {"type": "plain_text", "text": "Approve"},
+                        "action_id": "approve",
+                        "value": "approve",
+                    }],
+                },
+                {
+                    "type": "context",
+                    "elements": [
+                        {"type": "mrkdwn", "text": "<http://commit|1111111>: first"},
+                        {"type": "mrkdwn", "text": "<http://commit|2222222>: second"},
+                    ],
+                },
+            ]),
+            serde_json::to_value(&blocks).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_from_attachments_color() {
+        let attachments = vec![
+            SlackAttachmentBuilder::new("Looks good")
+                .title("Review: Approved")
+                .color("good")
+                .build(),
+        ];
+
+        let blocks = from_attachments("joe approved PR", &attachments);
+        assert_eq!(
+            Block::Section {
+                text: Some(Text::mrkdwn(":white_check_mark: *Review: Approved*\nLooks good")),
+                fields: vec![],
+            },
+            blocks[1]
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let long = "é".repeat(MAX_TEXT_LEN);
+        let text = Text::mrkdwn(&long).text;
+        assert!(text.len() <= MAX_TEXT_LEN + "…".len());
+        assert!(text.ends_with("…"));
+        assert_eq!("short", Text::mrkdwn("short").text);
+    }
+}
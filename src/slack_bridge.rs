@@ -0,0 +1,232 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use regex::{Captures, Regex};
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::slack::SlackWebApi;
+use crate::slack_threads;
+use crate::worker;
+
+// A `message` event. Users and channels are given by id.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MessageEvent {
+    #[serde(default)]
+    pub channel: String,
+    pub user: Option<String>,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub ts: String,
+    // the ts of the message starting the thread, for replies (and for the message starting it)
+    pub thread_ts: Option<String>,
+    // set for messages from bots, including octobot's own
+    pub bot_id: Option<String>,
+    // set for edits, deletions, joins and the like
+    pub subtype: Option<String>,
+}
+
+impl MessageEvent {
+    // Only people's new replies are mirrored: octobot's own messages (e.g. the PR comments it posts in the thread)
+    // would otherwise go round in circles
+    pub fn is_human_reply(&self) -> bool {
+        self.bot_id.is_none() &&
+            self.subtype.is_none() &&
+            self.user.is_some() &&
+            self.thread_ts.as_ref().map(|t| *t != self.ts).unwrap_or(false)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BridgeRequest {
+    pub event: MessageEvent,
+}
+
+pub fn req(event: MessageEvent) -> BridgeRequest {
+    BridgeRequest { event: event }
+}
+
+// Slack's markup to github markdown: links, mentions and escaping
+pub fn slack_to_markdown(text: &str) -> String {
+    let re = Regex::new(r"<([^<>|]+)(?:\|([^<>]*))?>").unwrap();
+    let text = re.replace_all(text, |c: &Captures| {
+        let target = &c[1];
+        let label = c.get(2).map(|l| l.as_str());
+        if target.starts_with('@') || target.starts_with('#') {
+            // user or channel: only the label is meaningful outside of slack
+            match label {
+                Some(l) => format!("{}{}", &target[0..1], l),
+                None => target.to_string(),
+            }
+        } else if target.starts_with('!') {
+            // <!here>, <!channel>
+            format!("@{}", label.unwrap_or(&target[1..]))
+        } else {
+            match label {
+                Some(l) => format!("[{}]({})", l, target),
+                None => target.to_string(),
+            }
+        }
+    });
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+pub fn comment_body(github_login: &str, text: &str) -> String {
+    format!("**{}** replied in slack:\n\n{}", github_login, slack_to_markdown(text))
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+) -> Arc<dyn worker::Runner<BridgeRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+    })
+}
+
+impl Runner {
+    // Comments on the PR for the user, returning what to tell them if it couldn't
+    fn mirror(
+        &self,
+        api: &SlackWebApi,
+        event: &MessageEvent,
+        owner: &str,
+        repo: &str,
+        number: u32,
+    ) -> Result<Option<String>> {
+        let slack_user = event.user.as_ref().map(|u| u.as_str()).unwrap_or("");
+        let user_info = api.get("users.info", &[("user", slack_user)])?;
+        let slack_name = user_info["user"]["name"].as_str().ok_or_else(|| format_err!("Unknown slack user"))?;
+
+        let user = match self.config.users().lookup_by_slack(slack_name) {
+            Some(u) => u,
+            None => {
+                return Ok(Some(
+                    "Your slack user is not mapped to a github user in octobot, so your reply was not added to the PR"
+                        .into(),
+                ))
+            }
+        };
+
+        let github = self.github_app.new_session(owner, repo)?;
+        github.comment_pull_request(owner, repo, number, &comment_body(&user.github, &event.text))?;
+        info!("Mirrored slack reply from {} to {}/{} #{}", user.github, owner, repo, number);
+        Ok(None)
+    }
+}
+
+impl worker::Runner<BridgeRequest> for Runner {
+    fn handle(&self, req: BridgeRequest) {
+        let event = req.event;
+        if !event.is_human_reply() || event.text.trim().is_empty() {
+            return;
+        }
+
+        // only threads octobot started about a PR are tracked
+        let thread_ts = event.thread_ts.clone().unwrap_or_default();
+        let thread_key = match self.config.slack_threads.get_message_thread_key(&event.channel, &thread_ts) {
+            Ok(Some(k)) => k,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Error looking up slack thread: {}", e);
+                return;
+            }
+        };
+        let ((owner, repo), number) = match slack_threads::parse_pr_thread_key(&thread_key) {
+            Some(pr) => pr,
+            None => return,
+        };
+
+        let repo_url = format!("https://{}/{}/{}", self.config.github_host(&owner), owner, repo);
+        match github::Repo::parse(&repo_url) {
+            Ok(ref r) if self.config.repos().slack_pr_bridge(r) => (),
+            _ => return,
+        };
+
+        let api = match self.config.slack_bot_token() {
+            Some(token) => SlackWebApi::new(&token),
+            None => return,
+        };
+
+        let msg = match self.mirror(&api, &event, &owner, &repo, number) {
+            Ok(Some(m)) => m,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Error mirroring slack reply to {} #{}: {}", thread_key, number, e);
+                format!("Error adding your reply to the PR: {}", e)
+            }
+        };
+
+        // only shown to the user who replied
+        let ephemeral = json!({"channel": event.channel, "user": event.user, "text": msg, "thread_ts": thread_ts});
+        if let Err(e) = api.post("chat.postEphemeral", ephemeral) {
+            error!("Error replying to slack message: {}", e);
+        }
+    }
+
+    fn priority(&self, _req: &BridgeRequest) -> worker::Priority {
+        worker::Priority::Interactive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn event(json: &str) -> MessageEvent {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_is_human_reply() {
+        assert!(event(
+            r#"{"type": "message", "channel": "C123", "user": "U123", "text": "Sounds good",
+                "ts": "1355517523.000005", "thread_ts": "1355517500.000001"}"#
+        )
+        .is_human_reply());
+
+        // not in a thread, or starting it
+        assert!(!event(r#"{"type": "message", "channel": "C123", "user": "U123", "text": "hi", "ts": "1.0"}"#)
+            .is_human_reply());
+        assert!(!event(r#"{"type": "message", "user": "U123", "text": "hi", "ts": "1.0", "thread_ts": "1.0"}"#)
+            .is_human_reply());
+
+        // octobot's own messages in the thread
+        assert!(!event(r#"{"type": "message", "bot_id": "B123", "text": "Comment", "ts": "2.0", "thread_ts": "1.0"}"#)
+            .is_human_reply());
+        assert!(!event(
+            r#"{"type": "message", "subtype": "message_changed", "user": "U123", "ts": "2.0", "thread_ts": "1.0"}"#
+        )
+        .is_human_reply());
+    }
+
+    #[test]
+    fn test_slack_to_markdown() {
+        assert_eq!(
+            "See [the docs](https://example.com/a?b=1&c=2) and https://example.com, @joe @here #general",
+            slack_to_markdown(
+                "See <https://example.com/a?b=1&amp;c=2|the docs> and <https://example.com>, <@U123|joe> <!here> \
+                 <#C123|general>"
+            )
+        );
+        assert_eq!("if a < b && c > d", slack_to_markdown("if a &lt; b &amp;&amp; c &gt; d"));
+    }
+
+    #[test]
+    fn test_comment_body() {
+        assert_eq!("**joe** replied in slack:\n\nLGTM", comment_body("joe", "LGTM"));
+    }
+}
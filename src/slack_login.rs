@@ -0,0 +1,200 @@
+use failure::format_err;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use url::Url;
+
+use crate::config::SlackLoginConfig;
+use crate::errors::*;
+use crate::faults;
+use crate::resilience;
+use crate::users::{UserConfig, UserInfo};
+
+const AUTHORIZE_URL: &str = "https://slack.com/openid/connect/authorize";
+const TOKEN_URL: &str = "https://slack.com/api/openid.connect.token";
+const USER_INFO_URL: &str = "https://slack.com/api/openid.connect.userInfo";
+const SCOPES: &str = "openid email";
+
+// Who logged in, as slack's user info says
+#[derive(Debug, PartialEq)]
+pub struct SlackIdentity {
+    pub user_id: String,
+    pub team_id: String,
+    // empty without the email scope
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResp {
+    ok: bool,
+    access_token: Option<String>,
+    error: Option<String>,
+}
+
+// Where to send users to log in with slack. |state| comes back with them, to match the callback to the login.
+pub fn login_url(config: &SlackLoginConfig, state: &str) -> Result<String> {
+    let mut url = Url::parse(AUTHORIZE_URL)?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("scope", SCOPES)
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_url)
+        .append_pair("state", state);
+    Ok(url.into_string())
+}
+
+// Trades the code slack sent the user back with for who they are
+pub fn identify(config: &SlackLoginConfig, code: &str) -> Result<SlackIdentity> {
+    let client = resilience::client(faults::Service::Slack);
+    let token: TokenResp = client
+        .post(TOKEN_URL)
+        .form(&[
+            ("client_id", config.client_id.as_str()),
+            ("client_secret", config.client_secret.as_str()),
+            ("code", code),
+            ("redirect_uri", config.redirect_url.as_str()),
+        ])
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|mut r| r.json())
+        .map_err(|e| format_err!("Error getting slack access token: {}", e))?;
+    let access_token = match token.access_token {
+        Some(ref t) if token.ok => t.clone(),
+        _ => return Err(format_err!("Slack refused the login: {}", token.error.unwrap_or_default())),
+    };
+
+    let info: Value = client
+        .get(USER_INFO_URL)
+        .bearer_auth(&access_token)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|mut r| r.json())
+        .map_err(|e| format_err!("Error getting slack user info: {}", e))?;
+    parse_identity(&info)
+}
+
+fn parse_identity(info: &Value) -> Result<SlackIdentity> {
+    if info["ok"] != true {
+        return Err(format_err!("Error getting slack user info: {}", info["error"].as_str().unwrap_or("")));
+    }
+    let claim = |name: &str| info[name].as_str().unwrap_or("").to_string();
+    let identity = SlackIdentity {
+        user_id: claim("https://slack.com/user_id"),
+        team_id: claim("https://slack.com/team_id"),
+        email: if info["email_verified"] == true { claim("email") } else { String::new() },
+    };
+    if identity.user_id.is_empty() {
+        return Err(format_err!("Slack user info has no user id"));
+    }
+    Ok(identity)
+}
+
+// Whether the user belongs to the configured workspace, if any
+pub fn allowed(config: &SlackLoginConfig, identity: &SlackIdentity) -> bool {
+    match config.team_id {
+        Some(ref team_id) if !team_id.is_empty() => *team_id == identity.team_id,
+        _ => true,
+    }
+}
+
+// The octobot user who logged in: the one mapped to the slack user, or else the one with their (verified) email address
+pub fn find_user(users: &UserConfig, identity: &SlackIdentity) -> Option<UserInfo> {
+    users.lookup_by_slack(&identity.user_id).or_else(|| users.lookup_by_email(&identity.email))
+}
+
+// What's wrong with the [slack_login] section
+pub fn check_config(config: &SlackLoginConfig) -> Vec<String> {
+    let mut errors = vec![];
+    if config.client_id.trim().is_empty() {
+        errors.push("client_id is required".into());
+    }
+    if config.client_secret.trim().is_empty() {
+        errors.push("client_secret is required".into());
+    }
+    match Url::parse(&config.redirect_url) {
+        Ok(ref u) if u.scheme() == "https" || u.scheme() == "http" => (),
+        Ok(_) => errors.push("redirect_url must be an http(s) URL".into()),
+        Err(e) => errors.push(format!("redirect_url: {}", e)),
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use serde_json::json;
+    use tempdir::TempDir;
+
+    fn slack_login(team_id: Option<&str>) -> SlackLoginConfig {
+        SlackLoginConfig {
+            client_id: "1234.5678".into(),
+            client_secret: "the-secret".into(),
+            redirect_url: "https://octobot.company.com/auth/slack/callback".into(),
+            team_id: team_id.map(|t| t.into()),
+        }
+    }
+
+    #[test]
+    fn test_login_url() {
+        let url = Url::parse(&login_url(&slack_login(None), "_abc123").unwrap()).unwrap();
+        assert_eq!("slack.com", url.host_str().unwrap());
+        let query = url.query_pairs().into_owned().collect::<Vec<_>>();
+        assert!(query.contains(&("scope".into(), "openid email".into())));
+        assert!(query.contains(&("client_id".into(), "1234.5678".into())));
+        assert!(query.contains(&("redirect_uri".into(), "https://octobot.company.com/auth/slack/callback".into())));
+        assert!(query.contains(&("state".into(), "_abc123".into())));
+    }
+
+    #[test]
+    fn test_parse_identity() {
+        let info = json!({
+            "ok": true,
+            "sub": "U0123",
+            "https://slack.com/user_id": "U0123",
+            "https://slack.com/team_id": "T0456",
+            "email": "joe@company.com",
+            "email_verified": true,
+        });
+        let identity = parse_identity(&info).unwrap();
+        assert_eq!(
+            SlackIdentity {
+                user_id: "U0123".into(),
+                team_id: "T0456".into(),
+                email: "joe@company.com".into(),
+            },
+            identity
+        );
+        assert!(allowed(&slack_login(None), &identity));
+        assert!(allowed(&slack_login(Some("T0456")), &identity));
+        assert!(!allowed(&slack_login(Some("T0789")), &identity));
+
+        let unverified = json!({"ok": true, "https://slack.com/user_id": "U0123", "email": "joe@company.com"});
+        assert_eq!("", parse_identity(&unverified).unwrap().email);
+
+        assert!(parse_identity(&json!({"ok": false, "error": "invalid_auth"})).is_err());
+        assert!(parse_identity(&json!({"ok": true})).is_err());
+    }
+
+    #[test]
+    fn test_find_user() {
+        let temp_dir = TempDir::new("slack_login.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        let mut users = UserConfig::new(db);
+
+        users.insert("joe-mapped", "U0123").unwrap();
+        let mut jane = UserInfo::new("jane-emailed", "jane.slacker");
+        jane.email = "jane@company.com".into();
+        users.insert_info(&jane).unwrap();
+
+        let identity = |user_id: &str, email: &str| SlackIdentity {
+            user_id: user_id.into(),
+            team_id: "T0456".into(),
+            email: email.into(),
+        };
+        let found = |identity: SlackIdentity| find_user(&users, &identity).map(|u| u.github);
+        assert_eq!(Some("joe-mapped".to_string()), found(identity("U0123", "joe@company.com")));
+        assert_eq!(Some("jane-emailed".to_string()), found(identity("U0789", "Jane@company.com")));
+        assert_eq!(None, found(identity("U0789", "")));
+    }
+}
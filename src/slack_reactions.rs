@@ -0,0 +1,207 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use crate::alerts;
+use crate::command_permissions;
+use crate::config::Config;
+use crate::db;
+use crate::errors::*;
+use crate::github::api::GithubSessionFactory;
+use crate::opsgenie;
+use crate::server::slack_actions;
+use crate::slack::SlackWebApi;
+use crate::slack_threads;
+use crate::teams::TeamMembers;
+use crate::worker;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ReactionItem {
+    #[serde(rename = "type")]
+    pub item_type: String,
+    // channel id
+    #[serde(default)]
+    pub channel: String,
+    #[serde(default)]
+    pub ts: String,
+}
+
+// A `reaction_added` event. Users and channels are given by id.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ReactionEvent {
+    pub user: String,
+    pub reaction: String,
+    pub item: ReactionItem,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ReactionRequest {
+    pub event: ReactionEvent,
+}
+
+pub fn req(event: ReactionEvent) -> ReactionRequest {
+    ReactionRequest { event: event }
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    team_members: Arc<TeamMembers>,
+    opsgenie: Option<Arc<dyn opsgenie::Session>>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    team_members: Arc<TeamMembers>,
+    opsgenie: Option<Arc<dyn opsgenie::Session>>,
+) -> Arc<dyn worker::Runner<ReactionRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+        team_members: team_members,
+        opsgenie: opsgenie,
+    })
+}
+
+impl Runner {
+    // Performs `action` on the PR like the button of the same name would, returning what to tell the user
+    fn perform(
+        &self,
+        api: &SlackWebApi,
+        event: &ReactionEvent,
+        action: &str,
+        owner: &str,
+        repo: &str,
+        number: u32,
+    ) -> Result<String> {
+        let user_info = api.get("users.info", &[("user", event.user.as_str())])?;
+        let slack_name = user_info["user"]["name"].as_str().ok_or_else(|| format_err!("Unknown slack user"))?;
+        let channel_info = api.get("conversations.info", &[("channel", event.item.channel.as_str())])?;
+        let channel = channel_info["channel"]["name"].as_str().unwrap_or("");
+
+        let user = match self.config.users().lookup_by_slack(slack_name) {
+            Some(u) => u,
+            None => return Ok("Your slack user is not mapped to a github user in octobot".into()),
+        };
+
+        info!("{} reacted with :{}: ('{}') on {}/{} #{}", user.github, event.reaction, action, owner, repo, number);
+
+        if !command_permissions::authorize(
+            &self.config,
+            &*self.github_app,
+            &self.team_members,
+            action,
+            channel,
+            slack_name,
+            Some(&user.github),
+        )? {
+            return Ok(format!("You are not allowed to use `{}` here", action));
+        }
+
+        let github = self.github_app.new_session(owner, repo)?;
+        slack_actions::perform_action(&self.config, &github, &user, action, owner, repo, number)
+    }
+
+    fn acknowledge(&self, api: &SlackWebApi, event: &ReactionEvent, id: i32) -> Result<String> {
+        let user_info = api.get("users.info", &[("user", event.user.as_str())])?;
+        let slack_name = user_info["user"]["name"].as_str().ok_or_else(|| format_err!("Unknown slack user"))?;
+        alerts::acknowledge(&self.config, self.opsgenie.as_ref().map(|o| o.deref()), id, slack_name, db::now())
+    }
+}
+
+impl worker::Runner<ReactionRequest> for Runner {
+    fn handle(&self, req: ReactionRequest) {
+        let event = req.event;
+        if event.item.item_type != "message" {
+            return;
+        }
+
+        // only messages octobot sent about a PR or an alert are tracked
+        let thread_key = match self.config.slack_threads.get_message_thread_key(&event.item.channel, &event.item.ts) {
+            Ok(Some(k)) => k,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Error looking up slack message: {}", e);
+                return;
+            }
+        };
+
+        let api = match self.config.slack_bot_token() {
+            Some(token) => SlackWebApi::new(&token),
+            None => return,
+        };
+
+        let msg = if let Some(id) = alerts::parse_alert_key(&thread_key) {
+            // any reaction acknowledges an alert
+            match self.acknowledge(&api, &event, id) {
+                Ok(m) => m,
+                Err(e) => {
+                    error!("Error acknowledging alert {} for reaction :{}:: {}", id, event.reaction, e);
+                    format!("Error: {}", e)
+                }
+            }
+        } else {
+            let action = match self.config.reaction_action(&event.reaction) {
+                Some(a) => a,
+                None => return,
+            };
+            let ((owner, repo), number) = match slack_threads::parse_pr_thread_key(&thread_key) {
+                Some(pr) => pr,
+                None => return,
+            };
+
+            match self.perform(&api, &event, &action, &owner, &repo, number) {
+                Ok(m) => m,
+                Err(e) => {
+                    error!("Error performing '{}' for reaction :{}:: {}", action, event.reaction, e);
+                    format!("Error: {}", e)
+                }
+            }
+        };
+
+        // only shown to the user who reacted
+        let ephemeral = json!({"channel": event.item.channel, "user": event.user, "text": msg});
+        if let Err(e) = api.post("chat.postEphemeral", ephemeral) {
+            error!("Error replying to slack reaction: {}", e);
+        }
+    }
+
+    fn priority(&self, _req: &ReactionRequest) -> worker::Priority {
+        worker::Priority::Interactive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_parse_event() {
+        let event: ReactionEvent = serde_json::from_str(
+            r#"{"type": "reaction_added", "user": "U123", "reaction": "rocket", "item_user": "U456",
+                "item": {"type": "message", "channel": "C123", "ts": "1360782400.498405"},
+                "event_ts": "1360782804.083113"}"#,
+        )
+        .unwrap();
+
+        assert_eq!("U123", event.user);
+        assert_eq!("rocket", event.reaction);
+        assert_eq!("message", event.item.item_type);
+        assert_eq!("C123", event.item.channel);
+        assert_eq!("1360782400.498405", event.item.ts);
+
+        let event: ReactionEvent = serde_json::from_str(
+            r#"{"type": "reaction_added", "user": "U123", "reaction": "eyes",
+                "item": {"type": "file", "file": "F123"}}"#,
+        )
+        .unwrap();
+        assert_eq!("file", event.item.item_type);
+        assert_eq!("", event.item.channel);
+    }
+}
@@ -0,0 +1,141 @@
+use failure::format_err;
+
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+
+// Identifies the thread that all channel messages about a PR are posted in
+pub fn pr_thread_key(repo: &str, number: u32) -> String {
+    format!("{}#{}", repo, number)
+}
+
+// Returns ((owner, repo), number)
+pub fn parse_pr_thread_key(key: &str) -> Option<((String, String), u32)> {
+    let mut parts = key.rsplitn(2, '#');
+    let number = parts.next()?.parse::<u32>().ok()?;
+    let mut repo_parts = parts.next()?.splitn(2, '/');
+    let owner = repo_parts.next()?;
+    let name = repo_parts.next()?;
+    if owner.is_empty() || name.is_empty() {
+        return None;
+    }
+
+    Some(((owner.into(), name.into()), number))
+}
+
+// Remembers the slack message that started each thread so that later messages can reply to it.
+#[derive(Clone)]
+pub struct SlackThreads {
+    db: Database,
+}
+
+impl SlackThreads {
+    pub fn new(db: Database) -> SlackThreads {
+        SlackThreads { db: db }
+    }
+
+    pub fn get_ts(&self, thread_key: &str, channel: &str) -> Result<Option<String>> {
+        let conn = self.db.connect()?;
+        let mut stmt =
+            conn.prepare("SELECT ts FROM slack_threads WHERE thread_key = :thread_key AND channel = :channel")?;
+        let mut rows = stmt.query_named(&[(":thread_key", &thread_key), (":channel", &channel)])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_ts(&self, thread_key: &str, channel: &str, ts: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO slack_threads (thread_key, channel, ts, created_at)
+               VALUES (?1, ?2, ?3, ?4)
+               ON CONFLICT (thread_key, channel) DO UPDATE SET ts = excluded.ts, created_at = excluded.created_at"#,
+            &[&thread_key as &dyn ToSql, &channel, &ts, &db::now()],
+        )
+        .map_err(|e| format_err!("Error saving slack thread for {}: {}", thread_key, e))?;
+
+        Ok(())
+    }
+
+    // Remembers which thread each sent message belongs to, so that reactions to it can be traced back to the PR.
+    // Slack events identify channels by id rather than name.
+    pub fn set_message(&self, channel_id: &str, ts: &str, thread_key: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO slack_messages (channel_id, ts, thread_key, created_at)
+               VALUES (?1, ?2, ?3, ?4)
+               ON CONFLICT (channel_id, ts) DO UPDATE SET thread_key = excluded.thread_key,
+                   created_at = excluded.created_at"#,
+            &[&channel_id as &dyn ToSql, &ts, &thread_key, &db::now()],
+        )
+        .map_err(|e| format_err!("Error saving slack message for {}: {}", thread_key, e))?;
+
+        Ok(())
+    }
+
+    pub fn get_message_thread_key(&self, channel_id: &str, ts: &str) -> Result<Option<String>> {
+        let conn = self.db.connect()?;
+        let mut stmt =
+            conn.prepare("SELECT thread_key FROM slack_messages WHERE channel_id = :channel_id AND ts = :ts")?;
+        let mut rows = stmt.query_named(&[(":channel_id", &channel_id), (":ts", &ts)])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (SlackThreads, TempDir) {
+        let temp_dir = TempDir::new("slack_threads.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (SlackThreads::new(db), temp_dir)
+    }
+
+    #[test]
+    fn test_slack_threads() {
+        let (threads, _temp) = new_test();
+        let key = pr_thread_key("some-user/some-repo", 32);
+        assert_eq!("some-user/some-repo#32", key);
+
+        assert_eq!(None, threads.get_ts(&key, "the-reviews").unwrap());
+
+        threads.set_ts(&key, "the-reviews", "1234.5678").unwrap();
+        assert_eq!(Some("1234.5678".to_string()), threads.get_ts(&key, "the-reviews").unwrap());
+        assert_eq!(None, threads.get_ts(&key, "other-channel").unwrap());
+        assert_eq!(None, threads.get_ts("some-user/some-repo#33", "the-reviews").unwrap());
+    }
+
+    #[test]
+    fn test_slack_messages() {
+        let (threads, _temp) = new_test();
+        let key = pr_thread_key("some-user/some-repo", 32);
+
+        assert_eq!(None, threads.get_message_thread_key("C123", "1234.5678").unwrap());
+
+        threads.set_message("C123", "1234.5678", &key).unwrap();
+        threads.set_message("C123", "1234.9999", &key).unwrap();
+        assert_eq!(Some(key.clone()), threads.get_message_thread_key("C123", "1234.5678").unwrap());
+        assert_eq!(Some(key), threads.get_message_thread_key("C123", "1234.9999").unwrap());
+        assert_eq!(None, threads.get_message_thread_key("C456", "1234.5678").unwrap());
+    }
+
+    #[test]
+    fn test_parse_pr_thread_key() {
+        assert_eq!(
+            Some((("some-user".to_string(), "some-repo".to_string()), 32)),
+            parse_pr_thread_key("some-user/some-repo#32")
+        );
+        assert_eq!(None, parse_pr_thread_key("some-user/some-repo#x"));
+        assert_eq!(None, parse_pr_thread_key("some-repo#32"));
+        assert_eq!(None, parse_pr_thread_key("some-user/some-repo"));
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::Deserialize;
+use serde_json::{self, json, Value};
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::slack::SlackWebApi;
+use crate::worker;
+
+pub const CREATE_BACKPORT: &str = "create_backport";
+pub const RELEASE_STATE: &str = "release_state";
+
+// Every step's input blocks use this action id
+const INPUT_ACTION_ID: &str = "value";
+
+pub struct StepField {
+    pub name: &'static str,
+    pub label: &'static str,
+}
+
+// A step offered to Slack Workflow Builder, identified by the callback id it was registered with in the slack app
+pub struct StepDefinition {
+    pub callback_id: &'static str,
+    // as used in [[command_permissions]]
+    pub command: &'static str,
+    pub inputs: &'static [StepField],
+    pub outputs: &'static [StepField],
+}
+
+pub const STEPS: &[StepDefinition] = &[
+    StepDefinition {
+        callback_id: CREATE_BACKPORT,
+        command: "backport",
+        inputs: &[
+            StepField {
+                name: "repo",
+                label: "Repository (owner/repo)",
+            },
+            StepField {
+                name: "pull_request",
+                label: "Pull request number",
+            },
+            StepField {
+                name: "branch",
+                label: "Release branch, e.g. 1.2 or master",
+            },
+        ],
+        outputs: &[
+            StepField {
+                name: "label",
+                label: "Backport label",
+            },
+            StepField {
+                name: "pull_request_url",
+                label: "Pull request URL",
+            },
+        ],
+    },
+    StepDefinition {
+        callback_id: RELEASE_STATE,
+        command: "release-state",
+        inputs: &[StepField {
+            name: "repo",
+            label: "Repository (owner/repo)",
+        }],
+        outputs: &[
+            StepField {
+                name: "tag_name",
+                label: "Latest release tag",
+            },
+            StepField {
+                name: "name",
+                label: "Latest release name",
+            },
+            StepField {
+                name: "url",
+                label: "Latest release URL",
+            },
+            StepField {
+                name: "prerelease",
+                label: "Latest release is a prerelease",
+            },
+        ],
+    },
+];
+
+pub fn find_step(callback_id: &str) -> Option<&'static StepDefinition> {
+    STEPS.iter().find(|s| s.callback_id == callback_id)
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct StepInput {
+    // slack allows any json here, but octobot only ever saves strings
+    pub value: Value,
+}
+
+impl StepInput {
+    pub fn as_string(&self) -> Option<String> {
+        match self.value {
+            Value::String(ref s) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+            Value::Number(ref n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+}
+
+// The `workflow_step` of a `workflow_step_execute` event
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ExecutedStep {
+    pub workflow_step_execute_id: String,
+    #[serde(default)]
+    pub inputs: HashMap<String, StepInput>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct StepExecuteEvent {
+    pub callback_id: String,
+    pub workflow_step: ExecutedStep,
+}
+
+fn input(step: &ExecutedStep, name: &str) -> Result<String> {
+    step.inputs
+        .get(name)
+        .and_then(|i| i.as_string())
+        .ok_or_else(|| format_err!("Missing input '{}'", name))
+}
+
+fn parse_repo(value: &str) -> Result<(String, String)> {
+    let mut parts = value.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(owner), Some(name)) if !owner.is_empty() && !name.is_empty() => Ok((owner.into(), name.into())),
+        _ => Err(format_err!("Invalid repo '{}': expected owner/repo", value)),
+    }
+}
+
+// The label that makes octobot backport a PR to `branch` once it is merged
+pub fn backport_label(branch: &str) -> String {
+    format!("backport-{}", branch)
+}
+
+fn create_backport(
+    github: &dyn Session,
+    owner: &str,
+    repo: &str,
+    step: &ExecutedStep,
+) -> Result<HashMap<String, String>> {
+    let number = input(step, "pull_request")?;
+    let number = number
+        .trim_start_matches('#')
+        .parse::<u32>()
+        .map_err(|_| format_err!("Invalid pull request number '{}'", number))?;
+    let branch = input(step, "branch")?;
+
+    let pull_request = github.get_pull_request(owner, repo, number)?;
+    let label = backport_label(&branch);
+    github.add_pull_request_labels(owner, repo, number, vec![label.clone()])?;
+
+    let mut outputs = HashMap::new();
+    outputs.insert("label".to_string(), label);
+    outputs.insert("pull_request_url".to_string(), pull_request.html_url);
+    Ok(outputs)
+}
+
+fn release_state(github: &dyn Session, owner: &str, repo: &str) -> Result<HashMap<String, String>> {
+    let release = github.get_latest_release(owner, repo)?;
+
+    let mut outputs = HashMap::new();
+    outputs.insert("name".to_string(), release.name.clone().unwrap_or(release.tag_name.clone()));
+    outputs.insert("tag_name".to_string(), release.tag_name);
+    outputs.insert("url".to_string(), release.html_url);
+    outputs.insert("prerelease".to_string(), release.prerelease.to_string());
+    Ok(outputs)
+}
+
+// Performs the step, returning its outputs
+pub fn run_step(
+    github_app: &dyn GithubSessionFactory,
+    callback_id: &str,
+    step: &ExecutedStep,
+) -> Result<HashMap<String, String>> {
+    let (owner, repo) = parse_repo(&input(step, "repo")?)?;
+    let github = github_app.new_session(&owner, &repo)?;
+    perform_step(&github, callback_id, step)
+}
+
+pub fn perform_step(github: &dyn Session, callback_id: &str, step: &ExecutedStep) -> Result<HashMap<String, String>> {
+    let (owner, repo) = parse_repo(&input(step, "repo")?)?;
+
+    match callback_id {
+        CREATE_BACKPORT => create_backport(github, &owner, &repo, step),
+        RELEASE_STATE => release_state(github, &owner, &repo),
+        _ => Err(format_err!("Unknown workflow step: {}", callback_id)),
+    }
+}
+
+// The modal shown when someone adds or edits the step in Workflow Builder
+pub fn edit_view(step: &StepDefinition, current: &HashMap<String, StepInput>) -> Value {
+    let blocks = step
+        .inputs
+        .iter()
+        .map(|field| {
+            let mut element = json!({
+                "type": "plain_text_input",
+                "action_id": INPUT_ACTION_ID,
+            });
+            if let Some(value) = current.get(field.name).and_then(|i| i.as_string()) {
+                element["initial_value"] = json!(value);
+            }
+            json!({
+                "type": "input",
+                "block_id": field.name,
+                "label": {"type": "plain_text", "text": field.label},
+                "element": element,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "type": "workflow_step",
+        "callback_id": step.callback_id,
+        "blocks": blocks,
+    })
+}
+
+// The inputs to save from a submitted edit view's `state`, or what is wrong with each input block. Values may
+// contain workflow variables like "{{step.output}}", which slack replaces before the step runs.
+pub fn submitted_inputs(step: &StepDefinition, state: &Value) -> std::result::Result<Value, HashMap<String, String>> {
+    let mut inputs = serde_json::Map::new();
+    let mut errors = HashMap::new();
+    for field in step.inputs {
+        match state["values"][field.name][INPUT_ACTION_ID]["value"].as_str().map(|v| v.trim()) {
+            Some(value) if !value.is_empty() => {
+                inputs.insert(field.name.to_string(), json!({ "value": value }));
+            }
+            _ => {
+                errors.insert(field.name.to_string(), "Required".to_string());
+            }
+        };
+    }
+
+    if errors.is_empty() {
+        Ok(Value::Object(inputs))
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn step_outputs(step: &StepDefinition) -> Value {
+    Value::Array(
+        step.outputs
+            .iter()
+            .map(|o| json!({"name": o.name, "type": "text", "label": o.label}))
+            .collect(),
+    )
+}
+
+// The slack Web API calls used to implement workflow steps
+pub struct SlackWorkflowApi {
+    api: SlackWebApi,
+}
+
+impl SlackWorkflowApi {
+    pub fn new(config: &Config) -> Result<SlackWorkflowApi> {
+        let token = config
+            .slack_bot_token()
+            .ok_or_else(|| format_err!("Slack workflow steps need a slack_bot_token"))?;
+        Ok(SlackWorkflowApi {
+            api: SlackWebApi::new(&token),
+        })
+    }
+
+    fn call(&self, method: &str, body: Value) -> Result<()> {
+        self.api.post(method, body).map(|_| ())
+    }
+
+    pub fn open_view(&self, trigger_id: &str, view: Value) -> Result<()> {
+        self.call("views.open", json!({"trigger_id": trigger_id, "view": view}))
+    }
+
+    pub fn update_step(&self, workflow_step_edit_id: &str, inputs: Value, outputs: Value) -> Result<()> {
+        self.call(
+            "workflows.updateStep",
+            json!({"workflow_step_edit_id": workflow_step_edit_id, "inputs": inputs, "outputs": outputs}),
+        )
+    }
+
+    pub fn step_completed(&self, workflow_step_execute_id: &str, outputs: &HashMap<String, String>) -> Result<()> {
+        self.call(
+            "workflows.stepCompleted",
+            json!({"workflow_step_execute_id": workflow_step_execute_id, "outputs": outputs}),
+        )
+    }
+
+    pub fn step_failed(&self, workflow_step_execute_id: &str, message: &str) -> Result<()> {
+        self.call(
+            "workflows.stepFailed",
+            json!({"workflow_step_execute_id": workflow_step_execute_id, "error": {"message": message}}),
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct WorkflowStepRequest {
+    pub event: StepExecuteEvent,
+}
+
+pub fn req(event: StepExecuteEvent) -> WorkflowStepRequest {
+    WorkflowStepRequest { event: event }
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+) -> Arc<dyn worker::Runner<WorkflowStepRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+    })
+}
+
+impl worker::Runner<WorkflowStepRequest> for Runner {
+    fn handle(&self, req: WorkflowStepRequest) {
+        let api = match SlackWorkflowApi::new(&self.config) {
+            Ok(a) => a,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+
+        let event = req.event;
+        let execute_id = &event.workflow_step.workflow_step_execute_id;
+        let res = match run_step(self.github_app.as_ref(), &event.callback_id, &event.workflow_step) {
+            Ok(outputs) => {
+                info!("Completed workflow step {}", event.callback_id);
+                api.step_completed(execute_id, &outputs)
+            }
+            Err(e) => {
+                info!("Workflow step {} failed: {}", event.callback_id, e);
+                api.step_failed(execute_id, &format!("{}", e))
+            }
+        };
+        if let Err(e) = res {
+            error!("Error reporting workflow step result to slack: {}", e);
+        }
+    }
+
+    fn priority(&self, _req: &WorkflowStepRequest) -> worker::Priority {
+        worker::Priority::Interactive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_execute_event() {
+        let event: StepExecuteEvent = serde_json::from_str(
+            r#"{"type": "workflow_step_execute", "callback_id": "create_backport",
+                "workflow_step": {"workflow_step_execute_id": "exec-1", "workflow_id": "W1",
+                    "inputs": {"repo": {"value": "some-org/some-repo"}, "pull_request": {"value": 32},
+                               "branch": {"value": " 1.2 "}},
+                    "outputs": []}}"#,
+        )
+        .unwrap();
+
+        assert_eq!("create_backport", event.callback_id);
+        assert_eq!("exec-1", event.workflow_step.workflow_step_execute_id);
+        assert_eq!("some-org/some-repo", input(&event.workflow_step, "repo").unwrap());
+        assert_eq!("32", input(&event.workflow_step, "pull_request").unwrap());
+        assert_eq!("1.2", input(&event.workflow_step, "branch").unwrap());
+        assert!(input(&event.workflow_step, "other").is_err());
+    }
+
+    #[test]
+    fn test_edit_view() {
+        let step = find_step(CREATE_BACKPORT).unwrap();
+        let mut current = HashMap::new();
+        current.insert("repo".to_string(), StepInput { value: json!("some-org/some-repo") });
+
+        let view = edit_view(step, &current);
+        assert_eq!("workflow_step", view["type"]);
+        assert_eq!("create_backport", view["callback_id"]);
+        assert_eq!(3, view["blocks"].as_array().unwrap().len());
+        assert_eq!("repo", view["blocks"][0]["block_id"]);
+        assert_eq!("some-org/some-repo", view["blocks"][0]["element"]["initial_value"]);
+        assert!(view["blocks"][1]["element"].get("initial_value").is_none());
+    }
+
+    #[test]
+    fn test_submitted_inputs() {
+        let step = find_step(RELEASE_STATE).unwrap();
+        let state = json!({"values": {"repo": {"value": {"type": "plain_text_input", "value": "some-org/some-repo"}}}});
+        assert_eq!(
+            json!({"repo": {"value": "some-org/some-repo"}}),
+            submitted_inputs(step, &state).unwrap()
+        );
+
+        let state = json!({"values": {"repo": {"value": {"type": "plain_text_input", "value": " "}}}});
+        assert_eq!(Some("Required"), submitted_inputs(step, &state).unwrap_err().get("repo").map(|e| e.as_str()));
+
+        assert_eq!(4, step_outputs(step).as_array().unwrap().len());
+        assert_eq!("tag_name", step_outputs(step)[0]["name"]);
+    }
+
+    #[test]
+    fn test_parse_repo() {
+        assert_eq!(("a".to_string(), "b".to_string()), parse_repo("a/b").unwrap());
+        assert!(parse_repo("a").is_err());
+        assert!(parse_repo("/b").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use log::{error, info};
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::messenger::{self, Messenger};
+use crate::repos::RepoInfo;
+use crate::scheduler;
+use crate::slack::{SlackAttachment, SlackAttachmentBuilder, SlackRequest};
+use crate::users;
+use crate::util;
+use crate::worker::Worker;
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// Whether `now` falls on one of the given comma-separated days (e.g. "Sat,Sun"), in UTC.
+pub fn is_quiet_day(quiet_days: &str, now: i64) -> bool {
+    let weekday = time::at_utc(time::Timespec::new(now, 0)).tm_wday as usize;
+    let today = DAY_NAMES[weekday % 7];
+
+    quiet_days
+        .split(',')
+        .map(|d| d.trim().to_lowercase())
+        .any(|d| !d.is_empty() && today.starts_with(&d[..std::cmp::min(3, d.len())]))
+}
+
+// The last time anything happened review-wise on the PR: its latest review, or when it was opened.
+fn last_review_activity(pull_request: &github::PullRequest) -> Option<i64> {
+    let created_at = pull_request.created_at.as_ref().and_then(|t| util::parse_timestamp(t));
+    let last_review = pull_request
+        .reviews
+        .as_ref()
+        .and_then(|reviews| {
+            reviews
+                .iter()
+                .filter_map(|r| r.submitted_at.as_ref().and_then(|t| util::parse_timestamp(t)))
+                .max()
+        });
+
+    match (created_at, last_review) {
+        (Some(c), Some(r)) => Some(std::cmp::max(c, r)),
+        (c, r) => c.or(r),
+    }
+}
+
+// Open, non-draft PRs with outstanding review requests and no review activity for `days` days.
+pub fn find_stale_prs(pull_requests: &Vec<github::PullRequest>, days: u32, now: i64) -> Vec<&github::PullRequest> {
+    pull_requests
+        .iter()
+        .filter(|pr| !pr.is_draft())
+        .filter(|pr| pr.requested_reviewers.as_ref().map(|r| !r.is_empty()).unwrap_or(false))
+        .filter(|pr| match last_review_activity(pr) {
+            Some(t) => now - t >= (days as i64) * SECS_PER_DAY,
+            None => false,
+        })
+        .collect()
+}
+
+fn days_waiting(pull_request: &github::PullRequest, now: i64) -> i64 {
+    last_review_activity(pull_request).map(|t| (now - t) / SECS_PER_DAY).unwrap_or(0)
+}
+
+fn pr_attachment(pull_request: &github::PullRequest) -> SlackAttachment {
+    SlackAttachmentBuilder::new("")
+        .title(format!("Pull Request #{}: \"{}\"", pull_request.number, pull_request.title))
+        .title_link(pull_request.html_url.as_str())
+        .build()
+}
+
+// Reminds requested reviewers of stale PRs in the given repo and optionally posts a digest to its channel.
+pub fn send_reminders(
+    github: &dyn Session,
+    messenger: &Messenger,
+    repo: &github::Repo,
+    info: &RepoInfo,
+    now: i64,
+) -> Result<()> {
+    let pull_requests = github.get_pull_requests(&repo.owner.login(), &repo.name, Some("open"), None)?;
+    let stale = find_stale_prs(&pull_requests, info.stale_pr_days, now);
+
+    for pull_request in &stale {
+        let msg = format!(
+            "Pull Request has been waiting for your review for {} days",
+            days_waiting(pull_request, now)
+        );
+        let attachments = vec![pr_attachment(pull_request)];
+        for reviewer in pull_request.requested_reviewers.as_ref().unwrap_or(&vec![]) {
+            messenger.send_to_user(reviewer, &msg, &attachments);
+        }
+    }
+
+    if info.stale_pr_digest && !stale.is_empty() {
+        let msg = format!(
+            "{} pull request(s) waiting more than {} days for review",
+            stale.len(),
+            info.stale_pr_days
+        );
+        let attachments = stale.iter().map(|pr| pr_attachment(pr)).collect::<Vec<_>>();
+        messenger.send_to_channel(&msg, &attachments, repo, "", &Vec::<github::Commit>::new());
+    }
+
+    Ok(())
+}
+
+pub struct StalePRReminders {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    messenger: Messenger,
+}
+
+impl StalePRReminders {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        slack: Arc<dyn Worker<SlackRequest>>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(StalePRReminders {
+            config: config.clone(),
+            github_app: github_app,
+            messenger: messenger::new(config, slack).for_dm_event(users::DM_REMINDER),
+        })
+    }
+
+    fn remind_repo(&self, info: &RepoInfo, now: i64) -> Result<()> {
+        // reminders need a specific repo: org-wide entries don't say which repos to scan.
+        if !info.repo.contains('/') {
+            info!("Skipping stale PR reminders for org-level config: {}", info.repo);
+            return Ok(());
+        }
+
+        let repo = github::Repo::parse(&format!("https://{}/{}", self.config.github_host(&info.repo), info.repo))?;
+        let github = self.github_app.new_session(&repo.owner.login(), &repo.name)?;
+
+        send_reminders(&github, &self.messenger, &repo, info, now)
+    }
+}
+
+impl scheduler::Task for StalePRReminders {
+    fn run(&self, now: i64) -> Result<()> {
+        let repos = self.config.repos().get_all()?;
+
+        for info in repos.iter().filter(|r| r.stale_pr_days > 0) {
+            if is_quiet_day(&info.stale_pr_quiet_days, now) {
+                continue;
+            }
+            if let Err(e) = self.remind_repo(info, now) {
+                error!("Error sending stale PR reminders for {}: {}", info.repo, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2019-05-01T12:00:00Z (a Wednesday)
+    const NOW: i64 = 1556712000;
+
+    fn pr(number: u32, created_at: &str, reviewers: Vec<&str>) -> github::PullRequest {
+        let mut pr = github::PullRequest::new();
+        pr.number = number;
+        pr.created_at = Some(created_at.into());
+        pr.requested_reviewers = Some(reviewers.into_iter().map(|r| github::User::new(r)).collect());
+        pr
+    }
+
+    #[test]
+    fn test_is_quiet_day() {
+        assert_eq!(false, is_quiet_day("", NOW));
+        assert_eq!(false, is_quiet_day("Sat,Sun", NOW));
+        assert_eq!(true, is_quiet_day("Wed", NOW));
+        assert_eq!(true, is_quiet_day("sat, wednesday", NOW));
+        assert_eq!(false, is_quiet_day("Wed", NOW + SECS_PER_DAY));
+    }
+
+    #[test]
+    fn test_find_stale_prs() {
+        let old = pr(1, "2019-04-25T12:00:00Z", vec!["joe"]);
+        let new = pr(2, "2019-04-30T12:00:00Z", vec!["joe"]);
+        let no_reviewers = pr(3, "2019-04-25T12:00:00Z", vec![]);
+        let mut draft = pr(4, "2019-04-25T12:00:00Z", vec!["joe"]);
+        draft.draft = Some(true);
+        let mut recently_reviewed = pr(5, "2019-04-25T12:00:00Z", vec!["joe"]);
+        let mut review = github::Review::new("looks ok", github::User::new("joe"));
+        review.submitted_at = Some("2019-04-30T00:00:00Z".into());
+        recently_reviewed.reviews = Some(vec![review]);
+        let mut unknown_age = pr(6, "", vec!["joe"]);
+        unknown_age.created_at = None;
+
+        let prs = vec![old, new, no_reviewers, draft, recently_reviewed, unknown_age];
+
+        let stale = find_stale_prs(&prs, 3, NOW).into_iter().map(|pr| pr.number).collect::<Vec<_>>();
+        assert_eq!(vec![1], stale);
+
+        let stale = find_stale_prs(&prs, 1, NOW).into_iter().map(|pr| pr.number).collect::<Vec<_>>();
+        assert_eq!(vec![1, 2, 5], stale);
+    }
+}
@@ -0,0 +1,429 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::{Config, StatuspageConfig};
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::http_client::HTTPClient;
+use crate::repo_mutes::SuppressedMessage;
+use crate::scheduler;
+use crate::slack::{self, SlackAttachment, SlackAttachmentBuilder, SlackRequest};
+use crate::util;
+use crate::worker;
+
+// how often to poll the status feed
+pub const CHECK_INTERVAL_SECS: u64 = 60;
+
+const DEFAULT_PROVIDER: &str = "GitHub";
+const DEFAULT_MIN_INDICATOR: &str = "major";
+
+// Statuspage's indicators, from least to most severe
+const INDICATORS: [&str; 4] = ["none", "minor", "major", "critical"];
+
+// Only list the first paused messages in the summary, to keep it readable
+const MAX_SUMMARY_MESSAGES: usize = 20;
+
+// The `status` of a Statuspage `/api/v2/status.json` feed
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Status {
+    // "none", "minor", "major" or "critical"
+    pub indicator: String,
+    pub description: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct StatusResp {
+    status: Status,
+}
+
+pub trait Session: Send + Sync {
+    fn get_status(&self) -> Result<Status>;
+}
+
+pub struct StatuspageSession {
+    client: HTTPClient,
+}
+
+impl StatuspageSession {
+    pub fn new(config: &StatuspageConfig) -> Result<StatuspageSession> {
+        Ok(StatuspageSession {
+            client: HTTPClient::new(&config.url)?,
+        })
+    }
+}
+
+impl Session for StatuspageSession {
+    fn get_status(&self) -> Result<Status> {
+        self.client
+            .get::<StatusResp>("")
+            .map(|r| r.status)
+            .map_err(|e| format_err!("Error getting status feed: {}", e))
+    }
+}
+
+pub fn is_indicator(indicator: &str) -> bool {
+    INDICATORS.contains(&indicator)
+}
+
+fn severity(indicator: &str) -> usize {
+    INDICATORS.iter().position(|i| *i == indicator).unwrap_or(0)
+}
+
+// Whether the status is at least as severe as `min_indicator`. Unknown indicators don't count.
+pub fn is_incident(status: &Status, min_indicator: &str) -> bool {
+    severity(&status.indicator) > 0 && severity(&status.indicator) >= severity(min_indicator)
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Incident {
+    pub id: i32,
+    pub provider: String,
+    pub indicator: String,
+    pub description: String,
+    pub started_at: i64,
+    // 0 until the status feed recovers
+    pub resolved_at: i64,
+}
+
+// Tells people that a failure may be the provider's fault, rather than something to retry
+pub fn annotate(msg: &str, incident: &Incident) -> String {
+    format!("{} ({} is having an incident: {})", msg, incident.provider, incident.description)
+}
+
+// Failures are still sent during an incident
+pub fn is_failure(attachments: &Vec<SlackAttachment>) -> bool {
+    attachments.iter().any(|a| a.color.as_ref().map(|c| c == "danger").unwrap_or(false))
+}
+
+// Incidents of the provider octobot's events come from, during which notifications are paused
+pub struct ProviderIncidents {
+    db: Database,
+}
+
+impl ProviderIncidents {
+    pub fn new(db: Database) -> ProviderIncidents {
+        ProviderIncidents { db: db }
+    }
+
+    pub fn start(&self, provider: &str, status: &Status, now: i64) -> Result<Incident> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO provider_incidents (provider, indicator, description, started_at, resolved_at)
+               VALUES (?1, ?2, ?3, ?4, 0)"#,
+            &[&provider as &dyn ToSql, &status.indicator, &status.description, &now],
+        )
+        .map_err(|e| format_err!("Error starting {} incident: {}", provider, e))?;
+
+        self.get(conn.last_insert_rowid()? as i32)?
+            .ok_or_else(|| format_err!("Error starting {} incident", provider))
+    }
+
+    // Keeps the incident's status up to date as the provider posts updates
+    pub fn update(&self, id: i32, status: &Status) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "UPDATE provider_incidents SET indicator = ?1, description = ?2 WHERE id = ?3",
+            &[&status.indicator as &dyn ToSql, &status.description, &id],
+        )
+        .map_err(|e| format_err!("Error updating incident {}: {}", id, e))?;
+
+        Ok(())
+    }
+
+    // Resolves the incident, forgetting the messages it paused
+    pub fn resolve(&self, id: i32, now: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "UPDATE provider_incidents SET resolved_at = ?1 WHERE id = ?2",
+            &[&now as &dyn ToSql, &id],
+        )
+        .map_err(|e| format_err!("Error resolving incident {}: {}", id, e))?;
+        conn.execute("DELETE FROM provider_paused_messages WHERE incident_id = ?1", &[&id])
+            .map_err(|e| format_err!("Error resolving incident {}: {}", id, e))?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, id: i32) -> Result<Option<Incident>> {
+        Ok(self.select("id = ?1", &[&id as &dyn ToSql])?.into_iter().next())
+    }
+
+    // The incident that is still going on, if any
+    pub fn current(&self) -> Result<Option<Incident>> {
+        Ok(self.select("resolved_at = 0", &[])?.into_iter().next())
+    }
+
+    pub fn get_all(&self) -> Result<Vec<Incident>> {
+        self.select("1 = 1", &[])
+    }
+
+    fn select(&self, filter: &str, params: &[&dyn ToSql]) -> Result<Vec<Incident>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT * FROM provider_incidents WHERE {} ORDER BY started_at DESC, id DESC",
+            filter
+        ))?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(params)?;
+
+        let mut incidents = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            incidents.push(Incident {
+                id: cols.get(row, "id")?,
+                provider: cols.get(row, "provider")?,
+                indicator: cols.get(row, "indicator")?,
+                description: cols.get(row, "description")?,
+                started_at: cols.get(row, "started_at")?,
+                resolved_at: cols.get(row, "resolved_at")?,
+            });
+        }
+
+        Ok(incidents)
+    }
+
+    pub fn pause(&self, id: i32, msg: &str, now: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "INSERT INTO provider_paused_messages (incident_id, msg, created_at) VALUES (?1, ?2, ?3)",
+            &[&id as &dyn ToSql, &msg, &now],
+        )
+        .map_err(|e| format_err!("Error recording paused message for incident {}: {}", id, e))?;
+
+        Ok(())
+    }
+
+    pub fn paused(&self, id: i32) -> Result<Vec<SuppressedMessage>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt =
+            conn.prepare("SELECT msg, created_at FROM provider_paused_messages WHERE incident_id = ?1 ORDER BY id")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(&[&id])?;
+
+        let mut messages = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            messages.push(SuppressedMessage {
+                msg: cols.get(row, "msg")?,
+                created_at: cols.get(row, "created_at")?,
+            });
+        }
+
+        Ok(messages)
+    }
+}
+
+pub fn started_message(incident: &Incident) -> String {
+    format!(
+        "{} is having an incident: {}. Notifications other than failures are paused until it is resolved.",
+        incident.provider, incident.description
+    )
+}
+
+// The message posted once an incident is resolved, listing what it paused
+pub fn summary(incident: &Incident, paused: &Vec<SuppressedMessage>) -> (String, Vec<SlackAttachment>) {
+    let msg = format!(
+        "{}'s incident is resolved: notifications were paused from {} until {}, and {} message(s) were not sent",
+        incident.provider,
+        util::format_timestamp(incident.started_at),
+        util::format_timestamp(incident.resolved_at),
+        paused.len()
+    );
+    if paused.is_empty() {
+        return (msg, vec![]);
+    }
+
+    let mut lines = paused
+        .iter()
+        .take(MAX_SUMMARY_MESSAGES)
+        .map(|s| format!("• {}: {}", util::format_timestamp(s.created_at), s.msg))
+        .collect::<Vec<_>>();
+    if paused.len() > MAX_SUMMARY_MESSAGES {
+        lines.push(format!("…and {} more", paused.len() - MAX_SUMMARY_MESSAGES));
+    }
+
+    (msg, vec![SlackAttachmentBuilder::new(&lines.join("\n")).title(incident.description.clone()).build()])
+}
+
+// Starts and resolves incidents as the provider's status feed changes
+pub struct IncidentWatcher {
+    config: Arc<Config>,
+    session: Arc<dyn Session>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+impl IncidentWatcher {
+    pub fn new(
+        config: Arc<Config>,
+        session: Arc<dyn Session>,
+        slack: Arc<dyn worker::Worker<SlackRequest>>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(IncidentWatcher {
+            config: config,
+            session: session,
+            slack: slack,
+        })
+    }
+
+    fn announce(&self, msg: &str, attachments: Vec<SlackAttachment>) {
+        if let Some(channel) = self.config.statuspage.as_ref().and_then(|s| s.channel.clone()) {
+            self.slack.send(slack::req(&channel, msg, attachments));
+        }
+    }
+}
+
+impl scheduler::Task for IncidentWatcher {
+    fn run(&self, now: i64) -> Result<()> {
+        let statuspage = match self.config.statuspage {
+            Some(ref s) => s,
+            None => return Ok(()),
+        };
+        let provider = statuspage.provider.clone().unwrap_or(DEFAULT_PROVIDER.into());
+        let min_indicator = statuspage.min_indicator.clone().unwrap_or(DEFAULT_MIN_INDICATOR.into());
+
+        // an unreachable status page says nothing about the provider: keep things as they are
+        let status = self.session.get_status()?;
+        let incidents = &self.config.provider_incidents;
+
+        match (incidents.current()?, is_incident(&status, &min_indicator)) {
+            (None, true) => {
+                let incident = incidents.start(&provider, &status, now)?;
+                info!("{} is having an incident: {}", provider, status.description);
+                self.announce(&started_message(&incident), vec![]);
+            }
+            (Some(incident), true) => {
+                if incident.indicator != status.indicator || incident.description != status.description {
+                    incidents.update(incident.id, &status)?;
+                }
+            }
+            (Some(mut incident), false) => {
+                let paused = incidents.paused(incident.id)?;
+                incidents.resolve(incident.id, now)?;
+                info!("{}'s incident is resolved: {} message(s) were paused", provider, paused.len());
+
+                incident.resolved_at = now;
+                let (msg, attachments) = summary(&incident, &paused);
+                self.announce(&msg, attachments);
+            }
+            (None, false) => (),
+        };
+
+        Ok(())
+    }
+}
+
+// Looks up the incident notifications are paused for, if the status feed is configured
+pub fn current_incident(config: &Config) -> Option<Incident> {
+    if config.statuspage.is_none() {
+        return None;
+    }
+    match config.provider_incidents.current() {
+        Ok(i) => i,
+        Err(e) => {
+            error!("Error looking up provider incident: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn new_test() -> (ProviderIncidents, TempDir) {
+        let temp_dir = TempDir::new("statuspage.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        (ProviderIncidents::new(db), temp_dir)
+    }
+
+    fn status(indicator: &str, description: &str) -> Status {
+        Status {
+            indicator: indicator.into(),
+            description: description.into(),
+        }
+    }
+
+    #[test]
+    fn test_is_incident() {
+        assert!(!is_incident(&status("none", "All Systems Operational"), "major"));
+        assert!(!is_incident(&status("minor", "Minor Service Outage"), "major"));
+        assert!(is_incident(&status("major", "Partial System Outage"), "major"));
+        assert!(is_incident(&status("critical", "Major System Outage"), "major"));
+        assert!(is_incident(&status("minor", "Minor Service Outage"), "minor"));
+        assert!(!is_incident(&status("none", "All Systems Operational"), "none"));
+        assert!(!is_incident(&status("maintenance", "Scheduled Maintenance"), "minor"));
+    }
+
+    #[test]
+    fn test_incidents() {
+        let (incidents, _temp_dir) = new_test();
+        assert_eq!(None, incidents.current().unwrap());
+
+        let incident = incidents.start("GitHub", &status("major", "Partial System Outage"), 1000).unwrap();
+        assert_eq!("GitHub", incident.provider);
+        assert_eq!(0, incident.resolved_at);
+        assert_eq!(Some(incident.clone()), incidents.current().unwrap());
+
+        incidents.update(incident.id, &status("critical", "Major System Outage")).unwrap();
+        assert_eq!("Major System Outage", incidents.current().unwrap().unwrap().description);
+
+        incidents.pause(incident.id, "first", 1100).unwrap();
+        incidents.pause(incident.id, "second", 1200).unwrap();
+        assert_eq!(
+            vec!["first".to_string(), "second".to_string()],
+            incidents.paused(incident.id).unwrap().into_iter().map(|s| s.msg).collect::<Vec<_>>()
+        );
+
+        incidents.resolve(incident.id, 2000).unwrap();
+        assert_eq!(None, incidents.current().unwrap());
+        assert!(incidents.paused(incident.id).unwrap().is_empty());
+        assert_eq!(2000, incidents.get(incident.id).unwrap().unwrap().resolved_at);
+        assert_eq!(1, incidents.get_all().unwrap().len());
+    }
+
+    #[test]
+    fn test_messages() {
+        let incident = Incident {
+            id: 1,
+            provider: "GitHub".into(),
+            indicator: "major".into(),
+            description: "Partial System Outage".into(),
+            started_at: 0,
+            resolved_at: 3600,
+        };
+
+        assert_eq!(
+            "Error creating merge PR (GitHub is having an incident: Partial System Outage)",
+            annotate("Error creating merge PR", &incident)
+        );
+
+        let (msg, attachments) = summary(&incident, &vec![]);
+        assert_eq!(
+            "GitHub's incident is resolved: notifications were paused from 1970-01-01 00:00 UTC until \
+             1970-01-01 01:00 UTC, and 0 message(s) were not sent",
+            msg
+        );
+        assert!(attachments.is_empty());
+
+        let paused = (0..22)
+            .map(|i| SuppressedMessage {
+                msg: format!("message {}", i),
+                created_at: 60,
+            })
+            .collect::<Vec<_>>();
+        let (_, attachments) = summary(&incident, &paused);
+        let text = attachments[0].text.clone();
+        assert!(text.starts_with("• 1970-01-01 00:01 UTC: message 0\n"));
+        assert!(text.ends_with("• 1970-01-01 00:01 UTC: message 19\n…and 2 more"));
+    }
+
+    #[test]
+    fn test_is_failure() {
+        assert!(!is_failure(&vec![]));
+        assert!(!is_failure(&vec![SlackAttachmentBuilder::new("fine").color("good").build()]));
+        assert!(is_failure(&vec![SlackAttachmentBuilder::new("broken").color("danger").build()]));
+    }
+}
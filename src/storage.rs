@@ -0,0 +1,363 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use failure::format_err;
+use reqwest;
+use url::{form_urlencoded, Url};
+
+use crate::aws;
+use crate::config::{Config, StorageConfig};
+use crate::errors::*;
+use crate::network;
+
+pub const LOCAL: &str = "local";
+pub const S3: &str = "s3";
+
+// Where generated artifacts are kept, as opposed to the git cache, which isn't served
+pub const ARTIFACT_PREFIXES: &[&str] = &["release-notes/", "config-exports/"];
+
+// For uploads streamed from a file, which would otherwise have to be read twice to sign them
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+// Where the git cache bundles and generated artifacts are kept, by key (e.g. "release-notes/owner/repo/v1...v2.md")
+pub trait Store: Send + Sync {
+    // None if there is nothing at |key|
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()>;
+
+    // Like `get`, but into a file, for objects too big to hold in memory. Returns whether there was one.
+    fn download(&self, key: &str, path: &Path) -> Result<bool>;
+
+    fn upload(&self, key: &str, path: &Path) -> Result<()>;
+}
+
+// Objects are files under a directory
+pub struct LocalStore {
+    dir: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(dir: &Path) -> LocalStore {
+        LocalStore { dir: dir.to_path_buf() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if key.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+            return Err(format_err!("Invalid storage key: {}", key));
+        }
+        Ok(self.dir.join(key))
+    }
+
+    fn parent_dir(path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(())
+    }
+}
+
+impl Store for LocalStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)?) {
+            Ok(data) => Ok(Some(data)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+        LocalStore::parent_dir(&path)?;
+        fs::write(&path, data)?;
+        Ok(())
+    }
+
+    fn download(&self, key: &str, path: &Path) -> Result<bool> {
+        match fs::copy(self.path(key)?, path) {
+            Ok(_) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn upload(&self, key: &str, path: &Path) -> Result<()> {
+        let dest = self.path(key)?;
+        LocalStore::parent_dir(&dest)?;
+        fs::copy(path, &dest)?;
+        Ok(())
+    }
+}
+
+// Percent-encodes everything but unreserved characters and the slashes between path segments, as S3 expects
+pub fn uri_encode(key: &str) -> String {
+    let mut encoded = String::new();
+    for b in key.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+// Objects are in an S3 bucket, or one of an S3-compatible service (e.g. MinIO)
+pub struct S3Store {
+    client: reqwest::Client,
+    credentials: aws::Credentials,
+    region: String,
+    // scheme and host (with the port, if it isn't the default)
+    base_url: String,
+    host: String,
+    // path of the bucket under |base_url|: empty for virtual-hosted buckets
+    bucket_path: String,
+    prefix: String,
+}
+
+impl S3Store {
+    pub fn new(config: &StorageConfig) -> Result<S3Store> {
+        let bucket = config.bucket.clone().ok_or_else(|| format_err!("storage.bucket is required"))?;
+        let region = config.region.clone().ok_or_else(|| format_err!("storage.region is required"))?;
+
+        // AWS buckets are addressed by host, others (which mostly don't have wildcard DNS) by path
+        let (endpoint, bucket_path) = match config.endpoint {
+            Some(ref endpoint) => (Url::parse(endpoint)?, format!("/{}", uri_encode(&bucket))),
+            None => (Url::parse(&format!("https://{}.s3.{}.amazonaws.com", bucket, region))?, String::new()),
+        };
+        let host = match (endpoint.host_str(), endpoint.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(format_err!("storage: invalid endpoint '{}'", endpoint)),
+        };
+
+        Ok(S3Store {
+            client: network::client(),
+            credentials: aws::Credentials::from_config_or_env(
+                config.access_key_id.as_ref(),
+                config.secret_access_key.as_ref(),
+            )?,
+            region: region,
+            base_url: format!("{}://{}", endpoint.scheme(), host),
+            host: host,
+            bucket_path: bucket_path,
+            prefix: config.prefix.clone().unwrap_or_default().trim_matches('/').to_string(),
+        })
+    }
+
+    // The encoded path of the object, e.g. "/bucket/prefix/release-notes/..."
+    pub fn object_path(&self, key: &str) -> String {
+        if self.prefix.is_empty() {
+            format!("{}/{}", self.bucket_path, uri_encode(key))
+        } else {
+            format!("{}/{}/{}", self.bucket_path, uri_encode(&self.prefix), uri_encode(key))
+        }
+    }
+
+    pub fn object_url(&self, key: &str) -> String {
+        format!("{}{}", self.base_url, self.object_path(key))
+    }
+
+    fn request(&self, method: reqwest::Method, key: &str, payload_hash: &str) -> reqwest::RequestBuilder {
+        let path = self.object_path(key);
+        let signed = aws::sign_v4(
+            &self.credentials,
+            &self.region,
+            "s3",
+            &aws::Request {
+                method: method.as_str(),
+                host: &self.host,
+                path: &path,
+                query: "",
+                headers: vec![("x-amz-content-sha256", payload_hash)],
+                body: b"",
+            },
+            &aws::amz_date(&time::now_utc()),
+        );
+
+        let mut req = self.client.request(method, &self.object_url(key)).header("x-amz-content-sha256", payload_hash);
+        for (name, value) in &signed {
+            req = req.header(name.as_str(), value.as_str());
+        }
+        req
+    }
+
+    // None if there is no such object
+    fn fetch(&self, key: &str) -> Result<Option<reqwest::Response>> {
+        let resp = self
+            .request(reqwest::Method::GET, key, &aws::sha256_hex(b""))
+            .send()
+            .map_err(|e| format_err!("Error getting {} from S3: {}", key, e))?;
+        match resp.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            s if s.is_success() => Ok(Some(resp)),
+            s => Err(format_err!("Error getting {} from S3: {}", key, s)),
+        }
+    }
+
+    fn store(&self, key: &str, req: reqwest::RequestBuilder) -> Result<()> {
+        req.send()
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format_err!("Error putting {} in S3: {}", key, e))
+    }
+}
+
+impl Store for S3Store {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.fetch(key)? {
+            Some(mut resp) => {
+                let mut data = vec![];
+                resp.copy_to(&mut data).map_err(|e| format_err!("Error getting {} from S3: {}", key, e))?;
+                Ok(Some(data))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        let req = self.request(reqwest::Method::PUT, key, &aws::sha256_hex(data));
+        self.store(key, req.body(data.to_vec()))
+    }
+
+    fn download(&self, key: &str, path: &Path) -> Result<bool> {
+        match self.fetch(key)? {
+            Some(mut resp) => {
+                let mut file = fs::File::create(path)?;
+                resp.copy_to(&mut file).map_err(|e| format_err!("Error getting {} from S3: {}", key, e))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn upload(&self, key: &str, path: &Path) -> Result<()> {
+        let file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        let req = self.request(reqwest::Method::PUT, key, UNSIGNED_PAYLOAD);
+        self.store(key, req.body(reqwest::Body::sized(file, len)))
+    }
+}
+
+pub fn new_store(config: &Config) -> Result<Option<Arc<dyn Store>>> {
+    let storage = match config.storage {
+        Some(ref s) => s,
+        None => return Ok(None),
+    };
+
+    match storage.kind.as_str() {
+        LOCAL => {
+            let dir = storage.dir.as_ref().ok_or_else(|| format_err!("storage.dir is required"))?;
+            Ok(Some(Arc::new(LocalStore::new(Path::new(dir)))))
+        }
+        S3 => Ok(Some(Arc::new(S3Store::new(storage)?))),
+        kind => Err(format_err!("Unknown storage kind: {}", kind)),
+    }
+}
+
+// Artifact keys are made of names that come from requests (e.g. refs), which mustn't add path segments
+pub fn key_part(name: &str) -> String {
+    name.chars().map(|c| if c == '/' || c == '\\' { '_' } else { c }).collect()
+}
+
+pub fn is_artifact_key(key: &str) -> bool {
+    ARTIFACT_PREFIXES.iter().any(|p| key.starts_with(p))
+}
+
+// Where the API serves the artifact
+pub fn artifact_location(key: &str) -> String {
+    format!("/api/v1/artifacts?{}", form_urlencoded::Serializer::new(String::new()).append_pair("key", key).finish())
+}
+
+pub fn save_artifact(config: &Config, key: &str, data: &[u8]) -> Result<()> {
+    match new_store(config)? {
+        Some(store) => store.put(key, data),
+        None => Err(format_err!("Artifacts can't be saved without a [storage] section in the config")),
+    }
+}
+
+pub fn load_artifact(config: &Config, key: &str) -> Result<Option<Vec<u8>>> {
+    match new_store(config)? {
+        Some(ref store) if is_artifact_key(key) => store.get(key),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn s3_config(endpoint: Option<&str>, prefix: Option<&str>) -> StorageConfig {
+        StorageConfig {
+            kind: S3.into(),
+            dir: None,
+            bucket: Some("octobot-cache".into()),
+            region: Some("us-east-1".into()),
+            endpoint: endpoint.map(|e| e.into()),
+            prefix: prefix.map(|p| p.into()),
+            access_key_id: Some("AKIDEXAMPLE".into()),
+            secret_access_key: Some("secret".into()),
+            git_cache: None,
+        }
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!("git-cache/host/owner/repo.bundle", uri_encode("git-cache/host/owner/repo.bundle"));
+        assert_eq!("release-notes/v1...v2%2B1%20%28rc%29.md", uri_encode("release-notes/v1...v2+1 (rc).md"));
+    }
+
+    #[test]
+    fn test_s3_object_url() {
+        let store = S3Store::new(&s3_config(None, None)).unwrap();
+        assert_eq!("https://octobot-cache.s3.us-east-1.amazonaws.com/a/b%20c", store.object_url("a/b c"));
+        assert_eq!("/a/b%20c", store.object_path("a/b c"));
+
+        let store = S3Store::new(&s3_config(Some("http://minio.company.com:9000"), Some("/octobot/"))).unwrap();
+        assert_eq!("http://minio.company.com:9000/octobot-cache/octobot/a/b", store.object_url("a/b"));
+        assert_eq!("minio.company.com:9000", store.host);
+    }
+
+    #[test]
+    fn test_local_store() {
+        let temp_dir = TempDir::new("storage.rs").unwrap();
+        let store = LocalStore::new(&temp_dir.path().join("store"));
+
+        assert_eq!(None, store.get("a/b.txt").unwrap());
+        store.put("a/b.txt", b"hello").unwrap();
+        assert_eq!(Some(b"hello".to_vec()), store.get("a/b.txt").unwrap());
+
+        let file = temp_dir.path().join("file.txt");
+        assert!(!store.download("a/c.txt", &file).unwrap());
+        assert!(store.download("a/b.txt", &file).unwrap());
+        assert_eq!("hello", fs::read_to_string(&file).unwrap());
+
+        store.upload("d/e.txt", &file).unwrap();
+        assert_eq!(Some(b"hello".to_vec()), store.get("d/e.txt").unwrap());
+
+        assert!(store.get("../secrets").is_err());
+        assert!(store.put("a//b", b"").is_err());
+    }
+
+    #[test]
+    fn test_key_part() {
+        assert_eq!("feature_thing..v1.2", key_part("feature/thing..v1.2"));
+    }
+
+    #[test]
+    fn test_is_artifact_key() {
+        assert!(is_artifact_key("release-notes/owner/repo/v1...v2.md"));
+        assert!(is_artifact_key("config-exports/20200102T030405Z.toml"));
+        assert!(!is_artifact_key("git-cache/github.com/owner/repo.bundle"));
+    }
+
+    #[test]
+    fn test_artifact_location() {
+        assert_eq!(
+            "/api/v1/artifacts?key=release-notes%2Fowner%2Frepo%2Fv1...v2%2B1.md",
+            artifact_location("release-notes/owner/repo/v1...v2+1.md")
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+
+use crate::auto_merge::AutoMerge;
+use crate::config::Config;
+use crate::dependencies;
+use crate::errors::*;
+use crate::git::Git;
+use crate::git_clone_manager::GitCloneManager;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::messenger;
+use crate::repos::{RepoInfo, RepoSubmodule};
+use crate::slack::{SlackAttachmentBuilder, SlackRequest};
+use crate::worker;
+
+// Git's file mode for a submodule (gitlink) entry
+const GITLINK_MODE: &str = "160000";
+
+#[derive(Debug, PartialEq)]
+pub struct SubmoduleBumpRequest {
+    pub upstream: github::Repo,
+    pub release: github::Release,
+}
+
+pub fn req(upstream: &github::Repo, release: &github::Release) -> SubmoduleBumpRequest {
+    SubmoduleBumpRequest {
+        upstream: upstream.clone(),
+        release: release.clone(),
+    }
+}
+
+// Only published, final releases are worth pinning
+pub fn is_bumpable(release: &github::Release) -> bool {
+    !release.draft && !release.prerelease && !release.tag_name.trim().is_empty()
+}
+
+pub fn bump_branch_name(submodule: &RepoSubmodule, tag: &str) -> String {
+    format!("octobot/bump-{}-{}", submodule.path.replace('/', "-"), tag)
+}
+
+pub fn bump_title(submodule: &RepoSubmodule, tag: &str) -> String {
+    format!("Bump {} to {}", submodule.path, tag)
+}
+
+pub fn release_notes_url(upstream: &github::Repo, release: &github::Release) -> String {
+    if release.html_url.is_empty() {
+        format!("{}/releases/tag/{}", upstream.html_url, release.tag_name)
+    } else {
+        release.html_url.clone()
+    }
+}
+
+pub fn bump_body(upstream: &github::Repo, release: &github::Release) -> String {
+    format!(
+        "Updates the {} submodule to {}.\n\nRelease notes: {}",
+        upstream.full_name,
+        release.tag_name,
+        release_notes_url(upstream, release)
+    )
+}
+
+// The commit a tag points to on the remote, peeling annotated tags
+pub fn remote_tag_commit(git: &Git, url: &str, tag: &str) -> Result<String> {
+    let tag_ref = format!("refs/tags/{}", tag);
+    let peeled_ref = format!("{}^{{}}", tag_ref);
+    let output = git.run(&["ls-remote", url, &tag_ref, &peeled_ref])?;
+
+    let mut found = None;
+    for line in output.lines() {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some(sha), Some(name)) if name == peeled_ref => return Ok(sha.to_string()),
+            (Some(sha), Some(name)) if name == tag_ref => found = Some(sha.to_string()),
+            _ => (),
+        }
+    }
+
+    found.ok_or_else(|| format_err!("Tag {} not found in {}", tag, url))
+}
+
+// The commit the submodule is currently pinned to at HEAD
+pub fn pinned_commit(git: &Git, path: &str) -> Result<String> {
+    let output = git.run(&["ls-tree", "HEAD", path])?;
+    let mut parts = output.split_whitespace();
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(GITLINK_MODE), Some("commit"), Some(sha)) => Ok(sha.to_string()),
+        _ => Err(format_err!("{} is not a submodule", path)),
+    }
+}
+
+// Commits a new pin for the submodule on |branch|, created from |base|.
+// Returns false if the submodule is already pinned to the commit.
+pub fn commit_bump(
+    git: &Git,
+    submodule: &RepoSubmodule,
+    commit_hash: &str,
+    branch: &str,
+    base: &str,
+    message: &str,
+    user_opts: &[&str],
+) -> Result<bool> {
+    git.checkout_branch(branch, base)?;
+
+    if pinned_commit(git, &submodule.path)? == commit_hash {
+        return Ok(false);
+    }
+
+    // update the gitlink directly: there's no need to fetch the submodule itself
+    let cacheinfo = format!("{},{},{}", GITLINK_MODE, commit_hash, submodule.path);
+    git.run(&["update-index", "--cacheinfo", &cacheinfo])?;
+
+    let mut args = vec![];
+    args.extend(user_opts.iter());
+    args.extend(["commit", "-F", "-"].iter());
+    git.run_with_stdin(&args, message)?;
+
+    Ok(true)
+}
+
+// Opens the bump PR in the downstream repo. Returns None if there was nothing to bump.
+pub fn bump_submodule(
+    git: &Git,
+    session: &dyn Session,
+    config: &Config,
+    downstream: &RepoInfo,
+    submodule: &RepoSubmodule,
+    req: &SubmoduleBumpRequest,
+    commit_hash: &str,
+) -> Result<Option<github::PullRequest>> {
+    let tag = &req.release.tag_name;
+    let branch = bump_branch_name(submodule, tag);
+
+    let current_remotes = git.run(&["ls-remote", "--heads"])?;
+    if current_remotes.contains(&format!("refs/heads/{}", branch)) {
+        info!("Submodule bump branch already exists on origin: '{}'", branch);
+        return Ok(None);
+    }
+
+    let base_branch = git.default_branch()?;
+
+    let title = bump_title(submodule, tag);
+    let body = bump_body(&req.upstream, &req.release);
+
+    let user = format!("user.name={}", session.bot_name());
+    let email = format!("user.email={}@users.noreply.{}", session.bot_name(), session.github_host());
+    let user_opts = ["-c", &user, "-c", &email];
+
+    let base = format!("origin/{}", base_branch);
+    if !commit_bump(git, submodule, commit_hash, &branch, &base, &format!("{}\n\n{}", title, body), &user_opts)? {
+        info!("{} in {} is already at {}", submodule.path, downstream.repo, tag);
+        return Ok(None);
+    }
+
+    git.run(&["push", "origin", &format!("HEAD:{}", branch)])?;
+
+    let (owner, repo) = owner_and_name(&downstream.repo)?;
+    let new_pr = session.create_pull_request(owner, repo, &title, &body, &branch, &base_branch)?;
+
+    if submodule.auto_merge {
+        let head_sha = if new_pr.head.sha.is_empty() { git.current_commit()? } else { new_pr.head.sha.clone() };
+        config.auto_merges.add(&AutoMerge {
+            repo: downstream.repo.clone(),
+            number: new_pr.number,
+            head_sha: head_sha,
+            requested_by: session.bot_name().into(),
+        })?;
+    }
+
+    Ok(Some(new_pr))
+}
+
+fn owner_and_name(repo: &str) -> Result<(&str, &str)> {
+    let mut parts = repo.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(owner), Some(name)) => Ok((owner, name)),
+        _ => Err(format_err!("Invalid repo: {}", repo)),
+    }
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+) -> Arc<dyn worker::Runner<SubmoduleBumpRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+        clone_mgr: clone_mgr,
+        slack: slack,
+    })
+}
+
+impl Runner {
+    fn clone_and_bump(&self, downstream: &RepoInfo, submodule: &RepoSubmodule, req: &SubmoduleBumpRequest) -> Result<()> {
+        let (owner, repo) = owner_and_name(&downstream.repo)?;
+        let session = self.github_app.new_session(owner, repo)?;
+        let held_clone_dir = GitCloneManager::clone(&self.clone_mgr, owner, repo)?;
+        let downstream_repo = github::Repo::parse(&format!("https://{}/{}", session.github_host(), downstream.repo))?;
+        let git = Git::new(session.github_host(), session.github_token(), held_clone_dir.dir())
+            .with_signing(self.config.signing.as_ref())
+            .with_dry_run(self.config.dry_run(&downstream_repo));
+
+        // the downstream repo's token may not be able to read upstream, so look up the tag with upstream's
+        let upstream_session = self.github_app.new_session(req.upstream.owner.login(), &req.upstream.name)?;
+        let upstream_git = Git::new(upstream_session.github_host(), upstream_session.github_token(), held_clone_dir.dir());
+        let upstream_url = format!("https://x-access-token@{}/{}", upstream_session.github_host(), req.upstream.full_name);
+        let commit_hash = remote_tag_commit(&upstream_git, &upstream_url, &req.release.tag_name)?;
+
+        if let Some(pr) = bump_submodule(&git, &session, &self.config, downstream, submodule, req, &commit_hash)? {
+            info!("Opened {}#{} to bump {} to {}", downstream.repo, pr.number, submodule.path, req.release.tag_name);
+        }
+        Ok(())
+    }
+
+    fn report_failure(&self, downstream: &RepoInfo, submodule: &RepoSubmodule, req: &SubmoduleBumpRequest, e: &Error) {
+        let repo = match dependencies::downstream_repo(&req.upstream, &downstream.repo) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Error parsing downstream repo {}: {}", downstream.repo, e);
+                return;
+            }
+        };
+
+        let attach = SlackAttachmentBuilder::new(&format!("{}", e))
+            .title(format!("{} {}", req.upstream.full_name, req.release.tag_name).as_str())
+            .title_link(release_notes_url(&req.upstream, &req.release))
+            .color("danger")
+            .build();
+        let msg = format!("Error bumping submodule {} in {}", submodule.path, downstream.repo);
+
+        let messenger = messenger::new(self.config.clone(), self.slack.clone());
+        messenger.send_to_channel(&msg, &vec![attach], &repo, "", &Vec::<github::Commit>::new());
+    }
+}
+
+impl worker::Runner<SubmoduleBumpRequest> for Runner {
+    fn handle(&self, req: SubmoduleBumpRequest) {
+        let dependents = match self.config.repos().submodule_dependents(&req.upstream.full_name) {
+            Ok(d) => d,
+            Err(e) => {
+                error!("Error looking up repos depending on {}: {}", req.upstream.full_name, e);
+                return;
+            }
+        };
+
+        for (downstream, submodule) in dependents {
+            if let Err(e) = self.clone_and_bump(&downstream, &submodule, &req) {
+                error!("Error bumping {} in {}: {}", submodule.path, downstream.repo, e);
+                self.report_failure(&downstream, &submodule, &req, &e);
+            }
+        }
+    }
+
+    fn repo(&self, req: &SubmoduleBumpRequest) -> Option<String> {
+        Some(req.upstream.full_name.clone())
+    }
+
+    fn priority(&self, _req: &SubmoduleBumpRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bump_description() {
+        let upstream = github::Repo::parse("https://git.company.com/some-user/some-lib").unwrap();
+        let mut release = github::Release::new("v1.2.0");
+        let submodule = RepoSubmodule::new("some-user/some-lib", "vendor/some-lib");
+
+        assert_eq!("octobot/bump-vendor-some-lib-v1.2.0", bump_branch_name(&submodule, "v1.2.0"));
+        assert_eq!("Bump vendor/some-lib to v1.2.0", bump_title(&submodule, "v1.2.0"));
+        assert_eq!(
+            "Updates the some-user/some-lib submodule to v1.2.0.\n\n\
+             Release notes: https://git.company.com/some-user/some-lib/releases/tag/v1.2.0",
+            bump_body(&upstream, &release)
+        );
+
+        release.html_url = "https://git.company.com/some-user/some-lib/releases/1".into();
+        assert!(bump_body(&upstream, &release).ends_with("Release notes: https://git.company.com/some-user/some-lib/releases/1"));
+    }
+
+    #[test]
+    fn test_is_bumpable() {
+        let mut release = github::Release::new("v1.0");
+        assert!(is_bumpable(&release));
+
+        release.prerelease = true;
+        assert!(!is_bumpable(&release));
+
+        release.prerelease = false;
+        release.draft = true;
+        assert!(!is_bumpable(&release));
+
+        assert!(!is_bumpable(&github::Release::new("")));
+    }
+}
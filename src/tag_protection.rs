@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::git::Git;
+use crate::git_clone_manager::GitCloneManager;
+use crate::github;
+use crate::github::api::{GithubSessionFactory, Session};
+use crate::messenger;
+use crate::slack::{SlackAttachment, SlackAttachmentBuilder, SlackRequest};
+use crate::worker;
+
+const TAG_PREFIX: &str = "refs/tags/";
+
+#[derive(Debug, PartialEq)]
+pub struct TagRestoreRequest {
+    pub repo: github::Repo,
+    pub tag: String,
+    // the commit (or tag object) the tag pointed to before it was deleted or moved. empty to delete the tag
+    pub before: String,
+}
+
+pub fn req(repo: &github::Repo, tag: &str, before: &str) -> TagRestoreRequest {
+    TagRestoreRequest {
+        repo: repo.clone(),
+        tag: tag.into(),
+        before: before.into(),
+    }
+}
+
+// Deletes the tag instead of restoring it
+pub fn delete_req(repo: &github::Repo, tag: &str) -> TagRestoreRequest {
+    req(repo, tag, "")
+}
+
+pub fn tag_name(ref_name: &str) -> Option<&str> {
+    if ref_name.starts_with(TAG_PREFIX) {
+        Some(&ref_name[TAG_PREFIX.len()..])
+    } else {
+        None
+    }
+}
+
+// github sends an all-zero hash for the missing side of a created or deleted ref
+pub fn is_null_sha(sha: &str) -> bool {
+    sha.is_empty() || sha.chars().all(|c| c == '0')
+}
+
+// A tag push that changed what an existing tag points to: the tag was deleted or moved
+pub fn is_tag_tampered(data: &github::HookBody) -> bool {
+    if tag_name(data.ref_name()).is_none() || data.created() || is_null_sha(data.before()) {
+        return false;
+    }
+    data.deleted() || data.before() != data.after()
+}
+
+pub fn alert_message(data: &github::HookBody) -> String {
+    let tag = tag_name(data.ref_name()).unwrap_or(data.ref_name());
+    if data.deleted() || is_null_sha(data.after()) {
+        format!("Tag {} was deleted by {}", tag, data.sender.login())
+    } else {
+        format!("Tag {} was moved by {}", tag, data.sender.login())
+    }
+}
+
+pub fn alert_attachment(data: &github::HookBody) -> SlackAttachment {
+    let mut text = format!("Was: {}", short_sha(data.before()));
+    if !is_null_sha(data.after()) {
+        text += &format!("\nNow: {}", short_sha(data.after()));
+    }
+    SlackAttachmentBuilder::new(&text)
+        .title(tag_name(data.ref_name()).unwrap_or(data.ref_name()))
+        .title_link(format!("{}/commit/{}", data.repository.html_url, data.before()))
+        .color("danger")
+        .build()
+}
+
+fn short_sha(sha: &str) -> &str {
+    &sha[0..std::cmp::min(sha.len(), 7)]
+}
+
+// Pushes the tag back to |before| if the clone still has it (or deletes it if |before| is null).
+// Returns false if it doesn't.
+pub fn restore_tag(git: &Git, tag: &str, before: &str) -> Result<bool> {
+    if is_null_sha(before) {
+        git.run(&["push", "origin", &format!(":{}{}", TAG_PREFIX, tag)])?;
+        return Ok(true);
+    }
+    if git.run(&["cat-file", "-e", before]).is_err() {
+        return Ok(false);
+    }
+
+    git.run(&["push", "origin", &format!("+{}:{}{}", before, TAG_PREFIX, tag)])?;
+    Ok(true)
+}
+
+struct Runner {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+}
+
+pub fn new_runner(
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    clone_mgr: Arc<GitCloneManager>,
+    slack: Arc<dyn worker::Worker<SlackRequest>>,
+) -> Arc<dyn worker::Runner<TagRestoreRequest>> {
+    Arc::new(Runner {
+        config: config,
+        github_app: github_app,
+        clone_mgr: clone_mgr,
+        slack: slack,
+    })
+}
+
+impl Runner {
+    fn restore(&self, req: &TagRestoreRequest) -> Result<()> {
+        let owner = req.repo.owner.login();
+        let session = self.github_app.new_session(owner, &req.repo.name)?;
+
+        // the usual clone fetches tags, which would pick up the moved tag: use the cache as-is
+        let held_clone_dir = match self.clone_mgr.cached(owner, &req.repo.name)? {
+            Some(d) => d,
+            None => return Err(format_err!("No cached clone of {} to restore from", req.repo.full_name)),
+        };
+        let git = Git::new(session.github_host(), session.github_token(), held_clone_dir.dir())
+            .with_dry_run(self.config.dry_run(&req.repo));
+
+        if !restore_tag(&git, &req.tag, &req.before)? {
+            return Err(format_err!("Cached clone of {} does not have {}", req.repo.full_name, req.before));
+        }
+        Ok(())
+    }
+}
+
+impl worker::Runner<TagRestoreRequest> for Runner {
+    fn handle(&self, req: TagRestoreRequest) {
+        let messenger = messenger::new(self.config.clone(), self.slack.clone());
+
+        let deleting = is_null_sha(&req.before);
+        match self.restore(&req) {
+            Ok(()) if deleting => {
+                info!("Deleted tag {} in {}", req.tag, req.repo.full_name);
+                let msg = format!("Deleted tag {}", req.tag);
+                messenger.send_to_security_channel(&msg, &vec![], &req.repo);
+            }
+            Ok(()) => {
+                info!("Restored tag {} in {} to {}", req.tag, req.repo.full_name, req.before);
+                let msg = format!("Restored tag {} to {}", req.tag, short_sha(&req.before));
+                messenger.send_to_security_channel(&msg, &vec![], &req.repo);
+            }
+            Err(e) => {
+                let action = if deleting { "deleting" } else { "restoring" };
+                error!("Error {} tag {} in {}: {}", action, req.tag, req.repo.full_name, e);
+                let attach = SlackAttachmentBuilder::new(&format!("{}", e)).color("danger").build();
+                let msg = format!("Error {} tag {}", action, req.tag);
+                messenger.send_to_security_channel(&msg, &vec![attach], &req.repo);
+            }
+        };
+    }
+
+    fn repo(&self, req: &TagRestoreRequest) -> Option<String> {
+        Some(req.repo.full_name.clone())
+    }
+
+    fn priority(&self, _req: &TagRestoreRequest) -> worker::Priority {
+        worker::Priority::Background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag_push(ref_name: &str, before: &str, after: &str) -> github::HookBody {
+        let mut data = github::HookBody::new();
+        data.ref_name = Some(ref_name.into());
+        data.before = Some(before.into());
+        data.after = Some(after.into());
+        data.sender = github::User::new("joe-sender");
+        data
+    }
+
+    #[test]
+    fn test_tag_name() {
+        assert_eq!(Some("v1.0"), tag_name("refs/tags/v1.0"));
+        assert_eq!(None, tag_name("refs/heads/master"));
+    }
+
+    #[test]
+    fn test_is_tag_tampered() {
+        let zero = "0000000000000000000000000000000000000000";
+
+        let mut deleted = tag_push("refs/tags/v1.0", "abcdef1", zero);
+        deleted.deleted = Some(true);
+        assert!(is_tag_tampered(&deleted));
+        assert_eq!("Tag v1.0 was deleted by joe-sender", alert_message(&deleted));
+
+        let mut moved = tag_push("refs/tags/v1.0", "abcdef1", "1234567");
+        moved.forced = Some(true);
+        assert!(is_tag_tampered(&moved));
+        assert_eq!("Tag v1.0 was moved by joe-sender", alert_message(&moved));
+
+        let mut created = tag_push("refs/tags/v1.0", zero, "1234567");
+        created.created = Some(true);
+        assert!(!is_tag_tampered(&created));
+
+        let mut branch = tag_push("refs/heads/master", "abcdef1", "1234567");
+        branch.forced = Some(true);
+        assert!(!is_tag_tampered(&branch));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use failure::format_err;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::db::{self, Connection, Database, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::github::api::Session;
+
+// Team membership rarely changes, so only look it up again every so often
+const MEMBERS_TTL_SECS: u64 = 15 * 60;
+
+// "org/team-slug"
+pub fn team_key(org: &str, team_slug: &str) -> String {
+    format!("{}/{}", org, team_slug)
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TeamChannel {
+    // "org/team-slug"
+    pub team: String,
+    pub channel: String,
+}
+
+// Teams whose review requests go to a channel instead of to each member
+#[derive(Clone)]
+pub struct TeamChannels {
+    db: Database,
+}
+
+impl TeamChannels {
+    pub fn new(db: Database) -> TeamChannels {
+        TeamChannels { db: db }
+    }
+
+    pub fn set(&self, team: &str, channel: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        Self::set_in(&conn, team, channel)
+    }
+
+    pub fn set_in(conn: &Connection, team: &str, channel: &str) -> Result<()> {
+        conn.execute(
+            r#"INSERT INTO team_channels (team, channel)
+               VALUES (?1, ?2)
+               ON CONFLICT (team) DO UPDATE SET channel = excluded.channel"#,
+            &[&team as &dyn ToSql, &channel],
+        )
+        .map_err(|e| format_err!("Error setting channel of team {}: {}", team, e))?;
+
+        Ok(())
+    }
+
+    pub fn remove(&self, team: &str) -> Result<()> {
+        let conn = self.db.connect()?;
+        Self::remove_in(&conn, team)
+    }
+
+    pub fn remove_in(conn: &Connection, team: &str) -> Result<()> {
+        conn.execute("DELETE FROM team_channels WHERE team = ?1", &[&team])
+            .map_err(|e| format_err!("Error removing channel of team {}: {}", team, e))?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, team: &str) -> Result<Option<String>> {
+        Ok(self.get_all()?.into_iter().find(|t| t.team == team).map(|t| t.channel))
+    }
+
+    pub fn get_all(&self) -> Result<Vec<TeamChannel>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare("SELECT * FROM team_channels ORDER BY team")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(db::NO_PARAMS)?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(TeamChannel {
+                team: cols.get(row, "team")?,
+                channel: cols.get(row, "channel")?,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+// Caches team members looked up from github
+pub struct TeamMembers {
+    ttl: Duration,
+    members: Mutex<HashMap<String, (Instant, Vec<github::User>)>>,
+}
+
+impl TeamMembers {
+    pub fn new() -> TeamMembers {
+        TeamMembers::with_ttl(Duration::from_secs(MEMBERS_TTL_SECS))
+    }
+
+    pub fn with_ttl(ttl: Duration) -> TeamMembers {
+        TeamMembers {
+            ttl: ttl,
+            members: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, github: &dyn Session, org: &str, team_slug: &str) -> Result<Vec<github::User>> {
+        let key = team_key(org, team_slug);
+        if let Some(&(ref fetched_at, ref members)) = self.members.lock().unwrap().get(&key) {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(members.clone());
+            }
+        }
+
+        let members = github.get_team_members(org, team_slug)?;
+        self.members.lock().unwrap().insert(key, (Instant::now(), members.clone()));
+        Ok(members)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_team_channels() {
+        let temp_dir = TempDir::new("teams.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        let teams = TeamChannels::new(db);
+
+        assert_eq!(None, teams.get("some-org/backend").unwrap());
+
+        teams.set("some-org/backend", "backend-reviews").unwrap();
+        teams.set("some-org/frontend", "frontend").unwrap();
+        teams.set("some-org/frontend", "frontend-reviews").unwrap();
+
+        assert_eq!(Some("backend-reviews".to_string()), teams.get("some-org/backend").unwrap());
+        assert_eq!(
+            vec![
+                TeamChannel {
+                    team: "some-org/backend".into(),
+                    channel: "backend-reviews".into(),
+                },
+                TeamChannel {
+                    team: "some-org/frontend".into(),
+                    channel: "frontend-reviews".into(),
+                },
+            ],
+            teams.get_all().unwrap()
+        );
+
+        teams.remove("some-org/backend").unwrap();
+        assert_eq!(None, teams.get("some-org/backend").unwrap());
+    }
+}
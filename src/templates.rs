@@ -0,0 +1,310 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use failure::format_err;
+use handlebars::{self, Handlebars, TemplateRenderError};
+use lazy_static::lazy_static;
+use log::error;
+use regex::Regex;
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+
+use crate::errors::*;
+use crate::github::{self, Commit, CommitLike, PullRequestLike};
+
+// Templates are edited by users, so they are kept from using much memory: longer ones are refused, and rendering
+// stops once the output is this long. Loops only go over the variables, so this bounds the time they take too.
+pub const MAX_TEMPLATE_LEN: usize = 16 * 1024;
+pub const MAX_OUTPUT_LEN: usize = 64 * 1024;
+
+lazy_static! {
+    // partials and decorators: there are none to use, and inline partials can call themselves forever
+    static ref PARTIAL_RE: Regex = Regex::new(r"\{\{~?\s*#?\s*[>*]").unwrap();
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TemplateError {
+    pub message: String,
+    // where in the template, from 1, when handlebars can tell
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{} (line {}, column {})", self.message, line, column),
+            (Some(line), None) => write!(f, "{} (line {})", self.message, line),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl TemplateError {
+    fn new(message: &str) -> TemplateError {
+        TemplateError {
+            message: message.into(),
+            line: None,
+            column: None,
+        }
+    }
+
+    fn from_handlebars(e: TemplateRenderError) -> TemplateError {
+        let (message, line, column) = match e {
+            TemplateRenderError::TemplateError(e) => (e.to_string(), e.line_no, e.column_no),
+            TemplateRenderError::RenderError(e) => (e.desc.clone(), e.line_no, e.column_no),
+            TemplateRenderError::IOError(e, _) => (e.to_string(), None, None),
+        };
+        // handlebars adds the template's lines after the first
+        TemplateError {
+            message: message.lines().next().unwrap_or("").trim().to_string(),
+            line: line,
+            column: column,
+        }
+    }
+}
+
+// Output that refuses to grow past MAX_OUTPUT_LEN
+struct LimitedOutput {
+    buf: Vec<u8>,
+    overflowed: bool,
+}
+
+impl Write for LimitedOutput {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + data.len() > MAX_OUTPUT_LEN {
+            self.overflowed = true;
+            return Err(io::Error::new(io::ErrorKind::Other, "output too long"));
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// The sandbox's rules, which templates are checked against before they are rendered
+pub fn check(template: &str) -> std::result::Result<(), TemplateError> {
+    if template.len() > MAX_TEMPLATE_LEN {
+        return Err(TemplateError::new(&format!("Templates may be at most {} bytes", MAX_TEMPLATE_LEN)));
+    }
+    if let Some(m) = PARTIAL_RE.find(template) {
+        let before = &template[..m.start()];
+        return Err(TemplateError {
+            message: "Partials and decorators are not supported".into(),
+            line: Some(before.matches('\n').count() + 1),
+            column: Some(before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1),
+        });
+    }
+    Ok(())
+}
+
+// Renders a handlebars template in the sandbox, with the line and column of what went wrong if it can't be
+pub fn render_checked(template: &str, vars: &Value) -> std::result::Result<String, TemplateError> {
+    check(template)?;
+
+    let mut hb = Handlebars::new();
+    hb.register_escape_fn(handlebars::no_escape);
+    let mut output = LimitedOutput {
+        buf: vec![],
+        overflowed: false,
+    };
+    match hb.render_template_to_write(template, vars, &mut output) {
+        Ok(()) => String::from_utf8(output.buf).map_err(|e| TemplateError::new(&e.to_string())),
+        Err(_) if output.overflowed => {
+            Err(TemplateError::new(&format!("The output is longer than {} bytes", MAX_OUTPUT_LEN)))
+        }
+        Err(e) => Err(TemplateError::from_handlebars(e)),
+    }
+}
+
+// Renders a handlebars template. Nothing is escaped: the output goes to JIRA or slack markup, not to HTML.
+pub fn render(template: &str, vars: &Value) -> Result<String> {
+    render_checked(template, vars).map_err(|e| format_err!("Invalid template: {}", e))
+}
+
+// Renders the configured template if there is one, or else uses octobot's own wording
+pub fn render_or(template: &Option<String>, vars: &Value, default: String) -> String {
+    match *template {
+        Some(ref t) => match render(t, vars) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Error rendering template '{}': {}", t, e);
+                default
+            }
+        },
+        None => default,
+    }
+}
+
+pub fn commit_vars(commit: &dyn CommitLike) -> Value {
+    json!({
+        "hash": Commit::short_hash(commit),
+        "sha": commit.sha(),
+        "url": commit.html_url(),
+        "title": Commit::title(commit),
+        "message": commit.message(),
+    })
+}
+
+// Variables for templates about a pull request. See the README for the full list.
+pub fn pr_vars<T: CommitLike>(pr: &dyn PullRequestLike, branch: &str, repo: &str, commits: &Vec<T>) -> Value {
+    let commits: Vec<Value> = commits.iter().map(|c| commit_vars(c)).collect();
+    json!({
+        "pr_title": pr.title(),
+        "pr_url": pr.html_url(),
+        "pr_number": pr.number(),
+        "author": pr.user().login(),
+        "branch": branch,
+        "repo": repo,
+        "commits": commits,
+    })
+}
+
+// Merges `extra` into `vars`, which must both be objects
+pub fn with_vars(mut vars: Value, extra: Value) -> Value {
+    if let (Some(v), Value::Object(extra)) = (vars.as_object_mut(), extra) {
+        v.extend(extra);
+    }
+    vars
+}
+
+// The kinds of templates, which get different variables
+pub const SAMPLES: &[&str] = &["pull_request", "review", "comment", "jira_pull_request", "jira_commit", "merge_commit"];
+
+// Made-up variables like those a template of the kind gets, to try it out with
+pub fn sample_vars(kind: &str) -> Option<Value> {
+    let mut pr = github::PullRequest::new();
+    pr.title = "Add retries to the deploy script".into();
+    pr.number = 42;
+    pr.html_url = "https://github.company.com/some-org/some-repo/pull/42".into();
+    pr.user = github::User::new("joe");
+    pr.body = Some("So that flaky hosts don't fail the deploy.\n\nFixes DEPLOY-7".into());
+    pr.base.ref_name = "master".into();
+
+    let mut commit = github::Commit::new();
+    commit.sha = "9e1f0c2a7b3d4e5f60718293a4b5c6d7e8f90a1b".into();
+    commit.html_url = "https://github.company.com/some-org/some-repo/commit/9e1f0c2".into();
+    commit.commit.message = "Retry deploys to flaky hosts\n\n[DEPLOY-7]".into();
+
+    let repo = "some-org/some-repo";
+    let pr_link = "<https://github.company.com/some-org/some-repo/pull/42|Add retries to the deploy script>";
+    let pr = pr_vars(&&pr, "master", repo, &vec![commit.clone()]);
+    let vars = match kind {
+        "pull_request" => with_vars(pr, json!({ "verb": "submitted for review to mary", "pr_link": pr_link })),
+        "review" => with_vars(
+            pr,
+            json!({ "pr_link": pr_link, "reviewer": "mary", "action": "approved", "state": "approved" }),
+        ),
+        "comment" => with_vars(
+            pr,
+            json!({ "pr_link": pr_link, "commenter": "mary", "comment": "Should this retry on timeouts too?" }),
+        ),
+        "jira_pull_request" => with_vars(pr, json!({ "key": "DEPLOY-7" })),
+        "jira_commit" => json!({
+            "key": "DEPLOY-7",
+            "branch": "master",
+            "repo": repo,
+            "version": "1.4.0",
+            "commit": commit_vars(&commit),
+        }),
+        "merge_commit" => with_vars(
+            pr,
+            json!({
+                "pr_body": "So that flaky hosts don't fail the deploy.\n\nFixes DEPLOY-7",
+                "base_branch": "master",
+            }),
+        ),
+        _ => return None,
+    };
+    Some(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let vars = json!({"pr_title": "Fix <things>", "branch": "master", "version": null});
+        assert_eq!(
+            "Review of Fix <things> for master",
+            render("Review of {{pr_title}} for {{branch}}", &vars).unwrap()
+        );
+        assert_eq!("no version", render("{{#if version}}v{{version}}{{else}}no version{{/if}}", &vars).unwrap());
+        assert!(render("{{#if version}}", &vars).is_err());
+    }
+
+    #[test]
+    fn test_render_checked() {
+        let vars = json!({"branch": "master", "commits": [1, 2, 3]});
+        assert_eq!(Ok("on master".to_string()), render_checked("on {{branch}}", &vars));
+
+        let e = render_checked("on {{branch}}\n{{#if branch}}", &vars).unwrap_err();
+        assert_eq!(Some(2), e.line);
+        assert!(!e.message.contains('\n'));
+
+        let e = render_checked("ok\n  {{#*inline \"loop\"}}{{> loop}}{{/inline}}{{> loop}}", &vars).unwrap_err();
+        assert_eq!("Partials and decorators are not supported", e.message);
+        assert_eq!((Some(2), Some(3)), (e.line, e.column));
+        assert!(render_checked("{{~> other}}", &vars).is_err());
+        assert!(render_checked("{{#> layout}}x{{/layout}}", &vars).is_err());
+
+        let e = render_checked(&"x".repeat(MAX_TEMPLATE_LEN + 1), &vars).unwrap_err();
+        assert!(e.message.starts_with("Templates may be at most"));
+
+        let vars = json!({ "big": "x".repeat(MAX_OUTPUT_LEN / 2) });
+        assert!(render_checked("{{big}}", &vars).is_ok());
+        let e = render_checked("{{big}}{{big}}{{big}}", &vars).unwrap_err();
+        assert_eq!(format!("The output is longer than {} bytes", MAX_OUTPUT_LEN), e.message);
+    }
+
+    #[test]
+    fn test_sample_vars() {
+        for kind in SAMPLES {
+            assert!(sample_vars(kind).is_some(), "{}", kind);
+        }
+        assert_eq!(None, sample_vars("nope"));
+
+        let vars = sample_vars("review").unwrap();
+        let review = render_checked("{{reviewer}} {{action}} #{{pr_number}}", &vars);
+        assert_eq!(Ok("mary approved #42".to_string()), review);
+        let vars = sample_vars("jira_commit").unwrap();
+        assert_eq!(Ok("9e1f0c2 on master".to_string()), render_checked("{{commit.hash}} on {{branch}}", &vars));
+    }
+
+    #[test]
+    fn test_render_or() {
+        let vars = json!({"branch": "master"});
+        assert_eq!("default", render_or(&None, &vars, "default".into()));
+        assert_eq!("on master", render_or(&Some("on {{branch}}".into()), &vars, "default".into()));
+        assert_eq!("default", render_or(&Some("{{#each}}".into()), &vars, "default".into()));
+    }
+
+    #[test]
+    fn test_pr_vars() {
+        let mut pr = github::PullRequest::new();
+        pr.title = "The PR".into();
+        pr.number = 5;
+        pr.html_url = "http://the-pr".into();
+        pr.user = github::User::new("joe");
+
+        let mut commit = github::Commit::new();
+        commit.sha = "abcdef0123".into();
+        commit.html_url = "http://the-commit".into();
+        commit.commit.message = "First line\n\nmore".into();
+
+        let vars = with_vars(pr_vars(&&pr, "master", "some-org/some-repo", &vec![commit]), json!({"verb": "opened"}));
+        assert_eq!(
+            "joe opened #5 The PR (http://the-pr) on some-org/some-repo master: abcdef0 First line",
+            render(
+                "{{author}} {{verb}} #{{pr_number}} {{pr_title}} ({{pr_url}}) on {{repo}} {{branch}}: \
+                 {{#each commits}}{{this.hash}} {{this.title}}{{/each}}",
+                &vars
+            )
+            .unwrap()
+        );
+    }
+}
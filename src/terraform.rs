@@ -0,0 +1,337 @@
+use failure::format_err;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+use crate::errors::*;
+use crate::github;
+use crate::github::api::Session;
+use crate::slack::{self, SlackAttachmentBuilder, SlackRequest};
+use crate::util;
+
+pub const CHECK_NAME: &str = "terraform-plan";
+const COMMENT_MARKER: &str = "<!-- octobot:terraform-plan";
+// resources listed in the comment before the rest are only counted
+const MAX_LISTED: usize = 50;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    // deleted and created again, in either order
+    Replace,
+    Delete,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ResourceChange {
+    pub address: String,
+    pub action: Action,
+}
+
+// The changes of a `terraform show -json` plan, leaving out reads and no-ops
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PlanSummary {
+    pub workspace: Option<String>,
+    pub changes: Vec<ResourceChange>,
+}
+
+#[derive(Deserialize)]
+struct PlanJson {
+    #[serde(default)]
+    resource_changes: Vec<ResourceChangeJson>,
+}
+
+#[derive(Deserialize)]
+struct ResourceChangeJson {
+    address: String,
+    change: ChangeJson,
+}
+
+#[derive(Deserialize)]
+struct ChangeJson {
+    actions: Vec<String>,
+}
+
+fn action(actions: &[String]) -> Option<Action> {
+    let actions = actions.iter().map(|a| a.as_str()).collect::<Vec<_>>();
+    match actions.as_slice() {
+        ["create"] => Some(Action::Create),
+        ["update"] => Some(Action::Update),
+        ["delete"] => Some(Action::Delete),
+        ["delete", "create"] | ["create", "delete"] => Some(Action::Replace),
+        _ => None,
+    }
+}
+
+impl PlanSummary {
+    pub fn parse(plan: &[u8], workspace: Option<&str>) -> Result<PlanSummary> {
+        let plan: PlanJson =
+            serde_json::from_slice(plan).map_err(|e| format_err!("Error parsing terraform plan: {}", e))?;
+
+        let changes = plan
+            .resource_changes
+            .into_iter()
+            .filter_map(|c| {
+                action(&c.change.actions).map(|action| ResourceChange {
+                    address: c.address,
+                    action: action,
+                })
+            })
+            .collect();
+
+        Ok(PlanSummary {
+            workspace: workspace.map(|w| w.to_string()),
+            changes: changes,
+        })
+    }
+
+    pub fn count(&self, action: Action) -> usize {
+        self.changes.iter().filter(|c| c.action == action).count()
+    }
+
+    // Resources that are deleted, including those that are replaced
+    pub fn destroyed(&self) -> Vec<&ResourceChange> {
+        self.changes.iter().filter(|c| c.action == Action::Delete || c.action == Action::Replace).collect()
+    }
+
+    pub fn is_destructive(&self) -> bool {
+        !self.destroyed().is_empty()
+    }
+
+    // e.g. "Plan: 2 to add, 1 to change, 0 to replace, 1 to destroy"
+    pub fn title(&self) -> String {
+        if self.changes.is_empty() {
+            return "No changes".into();
+        }
+        format!(
+            "Plan: {} to add, {} to change, {} to replace, {} to destroy",
+            self.count(Action::Create),
+            self.count(Action::Update),
+            self.count(Action::Replace),
+            self.count(Action::Delete)
+        )
+    }
+
+    pub fn check_name(&self) -> String {
+        match self.workspace {
+            Some(ref w) => format!("{} ({})", CHECK_NAME, w),
+            None => CHECK_NAME.into(),
+        }
+    }
+
+    fn marker(&self) -> String {
+        match self.workspace {
+            Some(ref w) => format!("{}:{} -->", COMMENT_MARKER, w),
+            None => format!("{} -->", COMMENT_MARKER),
+        }
+    }
+
+    pub fn comment(&self) -> String {
+        let mut comment = format!("{}\n", self.marker());
+        comment += &match self.workspace {
+            Some(ref w) => format!("#### Terraform plan for `{}`\n\n", w),
+            None => "#### Terraform plan\n\n".into(),
+        };
+        comment += &format!("**{}**\n", self.title());
+
+        if self.is_destructive() {
+            comment += &format!(
+                "\n:warning: This plan destroys {} resource(s). Make sure that is intended.\n",
+                self.destroyed().len()
+            );
+        }
+
+        if !self.changes.is_empty() {
+            comment += "\n| Action | Resource |\n| --- | --- |\n";
+            for change in self.changes.iter().take(MAX_LISTED) {
+                comment += &format!("| {} | `{}` |\n", action_label(change.action), change.address);
+            }
+            if self.changes.len() > MAX_LISTED {
+                comment += &format!("\n... and {} more\n", self.changes.len() - MAX_LISTED);
+            }
+        }
+
+        comment
+    }
+}
+
+fn action_label(action: Action) -> &'static str {
+    match action {
+        Action::Create => ":heavy_plus_sign: create",
+        Action::Update => ":pencil2: update",
+        Action::Replace => ":recycle: replace",
+        Action::Delete => ":x: delete",
+    }
+}
+
+// Keeps one comment per workspace up to date with the plan, and sets its check run: neutral when it destroys
+// anything, so that it stands out without blocking the merge
+pub fn publish(
+    github: &dyn Session,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    summary: &PlanSummary,
+    check_run: bool,
+) -> Result<()> {
+    let owner = repo.owner.login();
+    let comment = summary.comment();
+    let marker = summary.marker();
+
+    let existing = github
+        .get_pull_request_comments(owner, &repo.name, pull_request.number)?
+        .into_iter()
+        .find(|c| c.body().starts_with(&marker));
+
+    match existing {
+        Some(existing) => {
+            if existing.body() != comment {
+                github.edit_comment(owner, &repo.name, existing.id, &comment)?;
+            }
+        }
+        None => github.comment_pull_request(owner, &repo.name, pull_request.number, &comment)?,
+    };
+
+    if check_run {
+        let conclusion = if summary.is_destructive() {
+            github::Conclusion::Neutral
+        } else {
+            github::Conclusion::Success
+        };
+        let mut run = github::CheckRun::new(&summary.check_name(), pull_request, None).completed(conclusion);
+        run.output = Some(github::CheckOutput::new(&summary.title(), &comment));
+        github.create_check_run(pull_request, &run)?;
+    }
+
+    Ok(())
+}
+
+// The message to the infra channel about a plan that destroys resources, if it does
+pub fn escalation(
+    channel: &str,
+    repo: &github::Repo,
+    pull_request: &github::PullRequest,
+    summary: &PlanSummary,
+) -> Option<SlackRequest> {
+    let destroyed = summary.destroyed();
+    if destroyed.is_empty() {
+        return None;
+    }
+
+    let mut text = destroyed
+        .iter()
+        .take(MAX_LISTED)
+        .map(|c| format!("{} `{}`", if c.action == Action::Delete { "delete" } else { "replace" }, c.address))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if destroyed.len() > MAX_LISTED {
+        text += &format!("\n... and {} more", destroyed.len() - MAX_LISTED);
+    }
+
+    let attach = SlackAttachmentBuilder::new(&text)
+        .title(format!("{} #{}: {}", repo.full_name, pull_request.number, pull_request.title))
+        .title_link(pull_request.html_url.clone())
+        .color("danger")
+        .build();
+    let workspace = match summary.workspace {
+        Some(ref w) => format!(" to `{}`", w),
+        None => String::new(),
+    };
+    let msg = format!(
+        "Terraform plan{} destroys {} resource(s) in {}",
+        workspace,
+        destroyed.len(),
+        util::make_link(&pull_request.html_url, &format!("{} #{}", repo.full_name, pull_request.number))
+    );
+
+    Some(slack::req(channel, &msg, vec![attach]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAN: &str = r#"{
+        "format_version": "1.1",
+        "resource_changes": [
+            {"address": "aws_s3_bucket.logs", "type": "aws_s3_bucket", "change": {"actions": ["create"]}},
+            {"address": "aws_iam_role.ci", "type": "aws_iam_role", "change": {"actions": ["update"]}},
+            {"address": "aws_db_instance.main", "type": "aws_db_instance", "change": {"actions": ["delete", "create"]}},
+            {"address": "aws_instance.old", "type": "aws_instance", "change": {"actions": ["delete"]}},
+            {"address": "data.aws_ami.ubuntu", "type": "aws_ami", "change": {"actions": ["read"]}},
+            {"address": "aws_vpc.main", "type": "aws_vpc", "change": {"actions": ["no-op"]}}
+        ]
+    }"#;
+
+    #[test]
+    fn test_parse() {
+        let summary = PlanSummary::parse(PLAN.as_bytes(), Some("prod")).unwrap();
+        assert_eq!(
+            vec![
+                ResourceChange {
+                    address: "aws_s3_bucket.logs".into(),
+                    action: Action::Create,
+                },
+                ResourceChange {
+                    address: "aws_iam_role.ci".into(),
+                    action: Action::Update,
+                },
+                ResourceChange {
+                    address: "aws_db_instance.main".into(),
+                    action: Action::Replace,
+                },
+                ResourceChange {
+                    address: "aws_instance.old".into(),
+                    action: Action::Delete,
+                },
+            ],
+            summary.changes
+        );
+        assert_eq!("Plan: 1 to add, 1 to change, 1 to replace, 1 to destroy", summary.title());
+        assert_eq!(2, summary.destroyed().len());
+        assert!(summary.is_destructive());
+        assert_eq!("terraform-plan (prod)", summary.check_name());
+
+        assert!(PlanSummary::parse(b"not json", None).is_err());
+    }
+
+    #[test]
+    fn test_no_changes() {
+        let summary = PlanSummary::parse(br#"{"format_version": "1.1"}"#, None).unwrap();
+        assert_eq!("No changes", summary.title());
+        assert!(!summary.is_destructive());
+        assert_eq!("<!-- octobot:terraform-plan -->\n#### Terraform plan\n\n**No changes**\n", summary.comment());
+    }
+
+    #[test]
+    fn test_comment() {
+        let summary = PlanSummary::parse(PLAN.as_bytes(), Some("prod")).unwrap();
+        let comment = summary.comment();
+        assert!(comment.starts_with("<!-- octobot:terraform-plan:prod -->\n#### Terraform plan for `prod`\n"));
+        assert!(comment.contains(":warning: This plan destroys 2 resource(s)"));
+        assert!(comment.contains("| :recycle: replace | `aws_db_instance.main` |\n"));
+        assert!(!comment.contains("data.aws_ami.ubuntu"));
+    }
+
+    #[test]
+    fn test_escalation() {
+        let repo = github::Repo::parse("http://git.company.com/some-org/infra").unwrap();
+        let mut pull_request = github::PullRequest::new();
+        pull_request.number = 32;
+        pull_request.title = "Move the database".into();
+        pull_request.html_url = "http://git.company.com/some-org/infra/pull/32".into();
+
+        let summary = PlanSummary::parse(PLAN.as_bytes(), Some("prod")).unwrap();
+        let req = escalation("infra", &repo, &pull_request, &summary).unwrap();
+        assert_eq!("infra", req.channel);
+        assert_eq!(
+            "Terraform plan to `prod` destroys 2 resource(s) in \
+             <http://git.company.com/some-org/infra/pull/32|some-org/infra #32>",
+            req.msg
+        );
+        assert_eq!("replace `aws_db_instance.main`\ndelete `aws_instance.old`", req.attachments[0].text);
+
+        let summary = PlanSummary::parse(br#"{"resource_changes": []}"#, Some("prod")).unwrap();
+        assert_eq!(None, escalation("infra", &repo, &pull_request, &summary));
+    }
+}
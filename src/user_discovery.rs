@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info};
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::config::Config;
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+use crate::github;
+use crate::github::api::{GithubSession, GithubSessionFactory, Session};
+use crate::jira;
+use crate::scheduler;
+use crate::slack::SlackWebApi;
+use crate::users::UserInfo;
+
+// how often to look for users who aren't mapped yet
+pub const SYNC_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+pub const PENDING: &str = "pending";
+pub const CONFIRMED: &str = "confirmed";
+pub const REJECTED: &str = "rejected";
+
+// page size of slack's users.list
+const SLACK_PAGE_SIZE: &str = "200";
+
+// A github user, and the slack user who has the same verified email
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub github: String,
+    pub slack: String,
+    pub email: String,
+    // The JIRA user with the same email, if there is one. JIRA notifications find our users by their email.
+    pub jira: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MappingProposal {
+    pub id: i32,
+    pub github: String,
+    pub slack: String,
+    pub email: String,
+    pub jira: String,
+    // pending, confirmed or rejected
+    pub state: String,
+    pub proposed_at: i64,
+    pub decided_by: String,
+    pub decided_at: i64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlackUser {
+    pub name: String,
+    pub email: String,
+}
+
+// Proposed user mappings, kept once decided so that a rejected one isn't proposed again
+#[derive(Clone)]
+pub struct MappingProposals {
+    db: Database,
+}
+
+impl MappingProposals {
+    pub fn new(db: Database) -> MappingProposals {
+        MappingProposals { db: db }
+    }
+
+    // Returns false if the same mapping was already proposed
+    pub fn propose(&self, candidate: &Candidate, now: i64) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let changed = conn
+            .execute(
+                r#"INSERT INTO user_mapping_proposals
+                       (github_name, slack_name, email, jira_name, state, proposed_at, decided_by, decided_at)
+                   VALUES (?1, ?2, ?3, ?4, ?5, ?6, '', 0)
+                   ON CONFLICT DO NOTHING"#,
+                &[
+                    &candidate.github as &dyn ToSql,
+                    &candidate.slack,
+                    &candidate.email.to_lowercase(),
+                    &candidate.jira,
+                    &PENDING,
+                    &now,
+                ],
+            )
+            .map_err(|e| format_err!("Error proposing user mapping for {}: {}", candidate.github, e))?;
+
+        Ok(changed > 0)
+    }
+
+    // Most recent first
+    pub fn list(&self, state: Option<&str>) -> Result<Vec<MappingProposal>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT id, github_name, slack_name, email, jira_name, state, proposed_at, decided_by, decided_at
+               FROM user_mapping_proposals WHERE state = ?1 OR ?1 IS NULL ORDER BY id DESC"#,
+        )?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(&[&state])?;
+
+        let mut proposals = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            proposals.push(MappingProposal {
+                id: cols.get(row, "id")?,
+                github: cols.get(row, "github_name")?,
+                slack: cols.get(row, "slack_name")?,
+                email: cols.get(row, "email")?,
+                jira: cols.get(row, "jira_name")?,
+                state: cols.get(row, "state")?,
+                proposed_at: cols.get(row, "proposed_at")?,
+                decided_by: cols.get(row, "decided_by")?,
+                decided_at: cols.get(row, "decided_at")?,
+            });
+        }
+
+        Ok(proposals)
+    }
+
+    pub fn get(&self, id: i32) -> Result<Option<MappingProposal>> {
+        Ok(self.list(None)?.into_iter().find(|p| p.id == id))
+    }
+
+    // Returns false if the proposal was already decided
+    pub fn decide(&self, id: i32, state: &str, decided_by: &str, now: i64) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let changed = conn
+            .execute(
+                r#"UPDATE user_mapping_proposals SET state = ?1, decided_by = ?2, decided_at = ?3
+                   WHERE id = ?4 AND state = ?5"#,
+                &[&state as &dyn ToSql, &decided_by, &now, &id, &PENDING],
+            )
+            .map_err(|e| format_err!("Error deciding user mapping proposal {}: {}", id, e))?;
+
+        Ok(changed > 0)
+    }
+}
+
+// Active, human members of users.list that have an email
+pub fn parse_slack_users(resp: &serde_json::Value) -> Vec<SlackUser> {
+    let members = match resp["members"].as_array() {
+        Some(m) => m,
+        None => return vec![],
+    };
+
+    members
+        .iter()
+        .filter(|m| m["deleted"] != true && m["is_bot"] != true && m["is_email_confirmed"] != false)
+        .filter(|m| m["id"] != "USLACKBOT")
+        .filter_map(|m| match (m["name"].as_str(), m["profile"]["email"].as_str()) {
+            (Some(name), Some(email)) if !name.is_empty() && !email.is_empty() => Some(SlackUser {
+                name: name.to_string(),
+                email: email.to_string(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn slack_users(api: &SlackWebApi) -> Result<Vec<SlackUser>> {
+    let mut users = vec![];
+    let mut cursor = String::new();
+    loop {
+        let resp = api.get("users.list", &[("limit", SLACK_PAGE_SIZE), ("cursor", &cursor)])?;
+        users.extend(parse_slack_users(&resp));
+
+        cursor = resp["response_metadata"]["next_cursor"].as_str().unwrap_or("").to_string();
+        if cursor.is_empty() {
+            break;
+        }
+    }
+
+    Ok(users)
+}
+
+// Github users whose email belongs to exactly one slack user, unless either of them is already mapped otherwise.
+// Users who are mapped to that slack user but have no email yet get it.
+pub fn candidates(config: &Config, github_users: &[github::User], slack_users: &[SlackUser]) -> Vec<Candidate> {
+    let mut by_email: HashMap<String, Option<&SlackUser>> = HashMap::new();
+    for user in slack_users {
+        by_email
+            .entry(user.email.to_lowercase())
+            .and_modify(|u| *u = None)
+            .or_insert(Some(user));
+    }
+
+    let users = config.users();
+    let mut candidates = vec![];
+    for github_user in github_users {
+        let email = match github_user.email {
+            Some(ref e) if !e.is_empty() => e.to_lowercase(),
+            _ => continue,
+        };
+        let slack_user = match by_email.get(&email) {
+            Some(Some(u)) => u,
+            _ => continue,
+        };
+
+        if let Some(existing) = users.lookup_local_info(github_user.login()) {
+            if !existing.slack.is_empty() && (existing.slack != slack_user.name || !existing.email.is_empty()) {
+                continue;
+            }
+        }
+        if let Some(existing) = users.lookup_by_slack(&slack_user.name) {
+            if existing.github != github_user.login() {
+                continue;
+            }
+        }
+
+        candidates.push(Candidate {
+            github: github_user.login().to_string(),
+            slack: slack_user.name.clone(),
+            email: email,
+            jira: String::new(),
+        });
+    }
+
+    candidates
+}
+
+// The JIRA user whose email it is. Cloud only has it if the user's profile allows.
+pub fn jira_user(jira: &dyn jira::api::Session, email: &str) -> Result<Option<String>> {
+    let found = jira.search_users(email)?;
+    Ok(found
+        .into_iter()
+        .find(|u| u.email_address.as_ref().map(|e| e.eq_ignore_ascii_case(email)).unwrap_or(false))
+        .map(|u| u.id().to_string()))
+}
+
+// Proposes mappings for the org's members, returning how many are new
+pub fn discover(
+    config: &Config,
+    github: &dyn Session,
+    org: &str,
+    slack_users: &[SlackUser],
+    jira: Option<&dyn jira::api::Session>,
+    now: i64,
+) -> Result<usize> {
+    let mut github_users = vec![];
+    for member in github.get_org_members(org)? {
+        if member.is_bot() {
+            continue;
+        }
+        // only the users API has their email, so skip those who are fully mapped already
+        let mapped = config.users().lookup_local_info(member.login());
+        if mapped.map(|u| !u.slack.is_empty() && !u.email.is_empty()).unwrap_or(false) {
+            continue;
+        }
+        match github.get_user(member.login()) {
+            Ok(u) => github_users.push(u),
+            Err(e) => error!("{}", e),
+        }
+    }
+
+    let mut proposed = 0;
+    for mut candidate in candidates(config, &github_users, slack_users) {
+        if let Some(jira) = jira {
+            match jira_user(jira, &candidate.email) {
+                Ok(Some(name)) => candidate.jira = name,
+                Ok(None) => (),
+                Err(e) => error!("{}", e),
+            }
+        }
+        if config.user_mappings.propose(&candidate, now)? {
+            proposed += 1;
+        }
+    }
+
+    Ok(proposed)
+}
+
+// Maps the proposal's users, without overwriting what was already set for the github user
+pub fn apply(config: &Config, proposal: &MappingProposal) -> Result<()> {
+    let mut users = config.users_write();
+    match users.lookup_local_info(&proposal.github) {
+        Some(mut user) => {
+            if user.slack.is_empty() {
+                user.slack = proposal.slack.clone();
+            }
+            if user.email.is_empty() {
+                user.email = proposal.email.clone();
+            }
+            users.update(&user)
+        }
+        None => {
+            let mut user = UserInfo::new(&proposal.github, &proposal.slack);
+            user.email = proposal.email.clone();
+            users.insert_info(&user)
+        }
+    }
+}
+
+// Looks for users of the configured github orgs who could be mapped, for an admin to confirm
+pub struct UserDiscoverer {
+    config: Arc<Config>,
+    github_app: Arc<dyn GithubSessionFactory>,
+    jira: Option<Arc<dyn jira::api::Session>>,
+}
+
+impl UserDiscoverer {
+    pub fn new(
+        config: Arc<Config>,
+        github_app: Arc<dyn GithubSessionFactory>,
+        jira: Option<Arc<dyn jira::api::Session>>,
+    ) -> Arc<dyn scheduler::Task> {
+        Arc::new(UserDiscoverer {
+            config: config,
+            github_app: github_app,
+            jira: jira,
+        })
+    }
+
+    fn discover_org(&self, org: &str, slack_users: &[SlackUser], now: i64) -> Result<usize> {
+        let token = self.github_app.get_token_org(org)?;
+        let bot_name = self.github_app.owner_bot_name(org);
+        let github = GithubSession::new(&self.config.github_host(org), &bot_name, &token, None)?;
+        discover(&self.config, &github, org, slack_users, self.jira.as_ref().map(|j| j.deref()), now)
+    }
+}
+
+impl scheduler::Task for UserDiscoverer {
+    fn run(&self, now: i64) -> Result<()> {
+        let token = self
+            .config
+            .slack_bot_token()
+            .ok_or_else(|| format_err!("User discovery needs main.slack_bot_token"))?;
+        let slack_users = slack_users(&SlackWebApi::new(&token))?;
+
+        for org in self.config.user_discovery_orgs() {
+            match self.discover_org(&org, &slack_users, now) {
+                Ok(0) => (),
+                Ok(count) => info!("Proposed {} user mapping(s) for members of {}", count, org),
+                Err(e) => error!("Error discovering users of {}: {}", org, e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+    use tempdir::TempDir;
+
+    fn new_test() -> (Config, TempDir) {
+        let temp_dir = TempDir::new("user_discovery.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        (Config::new(db), temp_dir)
+    }
+
+    fn github_user(login: &str, email: Option<&str>) -> github::User {
+        let mut user = github::User::new(login);
+        user.email = email.map(|e| e.to_string());
+        user
+    }
+
+    fn slack_user(name: &str, email: &str) -> SlackUser {
+        SlackUser {
+            name: name.into(),
+            email: email.into(),
+        }
+    }
+
+    fn candidate(github: &str, slack: &str, email: &str) -> Candidate {
+        Candidate {
+            github: github.into(),
+            slack: slack.into(),
+            email: email.into(),
+            jira: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_slack_users() {
+        let resp = json!({
+            "ok": true,
+            "members": [
+                { "id": "U1", "name": "joe", "profile": { "email": "joe@company.com" } },
+                { "id": "U2", "name": "gone", "deleted": true, "profile": { "email": "gone@company.com" } },
+                { "id": "U3", "name": "a-bot", "is_bot": true, "profile": { "email": "bot@company.com" } },
+                { "id": "U4", "name": "unconfirmed", "is_email_confirmed": false, "profile": { "email": "u@x.com" } },
+                { "id": "U5", "name": "no-email", "profile": {} },
+                { "id": "USLACKBOT", "name": "slackbot", "profile": { "email": "slackbot@slack.com" } },
+            ],
+        });
+
+        assert_eq!(vec![slack_user("joe", "joe@company.com")], parse_slack_users(&resp));
+        assert_eq!(Vec::<SlackUser>::new(), parse_slack_users(&json!({ "ok": true })));
+    }
+
+    #[test]
+    fn test_candidates() {
+        let (config, _temp) = new_test();
+        config.users_write().insert("mapped", "mapped.slack").unwrap();
+        config.users_write().insert("no-email", "no.email").unwrap();
+
+        let github_users = vec![
+            github_user("joe", Some("Joe@Company.com")),
+            github_user("private", None),
+            github_user("nobody", Some("nobody@company.com")),
+            github_user("shared", Some("shared@company.com")),
+            github_user("mapped", Some("mapped@company.com")),
+            github_user("no-email", Some("no.email@company.com")),
+            github_user("takes-mapped-slack", Some("other@company.com")),
+        ];
+        let slack_users = vec![
+            slack_user("joe.slack", "joe@company.com"),
+            slack_user("shared1", "shared@company.com"),
+            slack_user("shared2", "shared@company.com"),
+            slack_user("someone.else", "mapped@company.com"),
+            slack_user("no.email", "no.email@company.com"),
+            slack_user("mapped.slack", "other@company.com"),
+        ];
+
+        assert_eq!(
+            vec![
+                candidate("joe", "joe.slack", "joe@company.com"),
+                candidate("no-email", "no.email", "no.email@company.com"),
+            ],
+            candidates(&config, &github_users, &slack_users)
+        );
+    }
+
+    #[test]
+    fn test_proposals() {
+        let (config, _temp) = new_test();
+        let proposals = &config.user_mappings;
+
+        assert!(proposals.propose(&candidate("joe", "joe.slack", "joe@company.com"), 100).unwrap());
+        assert!(!proposals.propose(&candidate("joe", "joe.slack", "joe@company.com"), 200).unwrap());
+        assert!(proposals.propose(&candidate("ann", "ann.slack", "ann@company.com"), 200).unwrap());
+
+        let pending = proposals.list(Some(PENDING)).unwrap();
+        assert_eq!(vec!["ann", "joe"], pending.iter().map(|p| p.github.as_str()).collect::<Vec<_>>());
+        let joe = pending[1].clone();
+        assert_eq!(100, joe.proposed_at);
+
+        assert!(proposals.decide(joe.id, REJECTED, "admin", 300).unwrap());
+        assert!(!proposals.decide(joe.id, CONFIRMED, "admin", 400).unwrap());
+
+        let joe = proposals.get(joe.id).unwrap().unwrap();
+        assert_eq!((REJECTED, "admin", 300), (joe.state.as_str(), joe.decided_by.as_str(), joe.decided_at));
+        assert_eq!(1, proposals.list(Some(PENDING)).unwrap().len());
+        assert_eq!(2, proposals.list(None).unwrap().len());
+
+        // a rejected mapping isn't proposed again
+        assert!(!proposals.propose(&candidate("joe", "joe.slack", "joe@company.com"), 500).unwrap());
+    }
+
+    #[test]
+    fn test_apply() {
+        let (config, _temp) = new_test();
+        let mut existing = UserInfo::new("existing", "");
+        existing.email = "kept@company.com".into();
+        config.users_write().insert_info(&existing).unwrap();
+
+        let proposal = |github: &str, slack: &str, email: &str| MappingProposal {
+            id: 1,
+            github: github.into(),
+            slack: slack.into(),
+            email: email.into(),
+            jira: String::new(),
+            state: PENDING.into(),
+            proposed_at: 0,
+            decided_by: String::new(),
+            decided_at: 0,
+        };
+
+        apply(&config, &proposal("joe", "joe.slack", "joe@company.com")).unwrap();
+        let joe = config.users().lookup_info("joe").unwrap();
+        assert_eq!(("joe.slack", "joe@company.com"), (joe.slack.as_str(), joe.email.as_str()));
+
+        apply(&config, &proposal("existing", "existing.slack", "other@company.com")).unwrap();
+        let existing = config.users().lookup_info("existing").unwrap();
+        assert_eq!(("existing.slack", "kept@company.com"), (existing.slack.as_str(), existing.email.as_str()));
+    }
+}
@@ -1,22 +1,86 @@
+use std::sync::Arc;
+
 use failure::format_err;
 use log::error;
-use rusqlite::types::ToSql;
 use serde_derive::{Deserialize, Serialize};
 
-use crate::db::{self, Database};
+use crate::db::{self, Database, ToSql};
+use crate::digests;
 use crate::errors::*;
+use crate::federation;
+
+// The kinds of direct messages users can choose to receive
+pub const DM_PULL_REQUEST: &str = "pull_request";
+pub const DM_REVIEW: &str = "review";
+pub const DM_COMMENT: &str = "comment";
+pub const DM_PUSH: &str = "push";
+pub const DM_CONFLICT: &str = "conflict";
+pub const DM_REMINDER: &str = "reminder";
+pub const DM_BACKPORT: &str = "backport";
+pub const DM_JIRA: &str = "jira";
+pub const DM_MENTION: &str = "mention";
+pub const DM_REVERT: &str = "revert";
+
+pub const DM_EVENT_TYPES: &[&str] = &[
+    DM_PULL_REQUEST,
+    DM_REVIEW,
+    DM_COMMENT,
+    DM_PUSH,
+    DM_CONFLICT,
+    DM_REMINDER,
+    DM_BACKPORT,
+    DM_JIRA,
+    DM_MENTION,
+    DM_REVERT,
+];
+
+// The kind of direct message sent for a github webhook event
+pub fn dm_event_type(github_event: &str) -> Option<&'static str> {
+    match github_event {
+        "pull_request" => Some(DM_PULL_REQUEST),
+        "pull_request_review" => Some(DM_REVIEW),
+        "pull_request_review_comment" | "issue_comment" | "commit_comment" => Some(DM_COMMENT),
+        "push" => Some(DM_PUSH),
+        _ => None,
+    }
+}
 
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct UserInfo {
     pub id: Option<i32>,
     pub github: String,
     pub slack: String,
     pub mute_direct_messages: bool,
+    // Direct messages are temporarily muted until this time (e.g. with `/octobot mute 2h`)
+    #[serde(default)]
+    pub muted_until: i64,
+    // Comma-separated kinds of direct messages to receive (see `DM_EVENT_TYPES`). Empty means all of them.
+    #[serde(default)]
+    pub dm_events: String,
+    // No direct messages are sent between these local times ("HH:MM"), e.g. from "22:00" to "08:00"
+    #[serde(default)]
+    pub quiet_hours_start: String,
+    #[serde(default)]
+    pub quiet_hours_end: String,
+    // UTC offset for quiet hours, e.g. "-05:00". Defaults to UTC
+    #[serde(default)]
+    pub timezone: String,
+    // "daily" or "weekly": get a summary instead of real-time direct messages. Empty means real-time.
+    #[serde(default)]
+    pub digest: String,
+    // Email address for notifications when the user has no slack mapping. Empty means the default address, if any.
+    #[serde(default)]
+    pub email: String,
+    // When the user was (soft) deleted. Deleted users can be restored until they are purged.
+    #[serde(default)]
+    pub deleted_at: Option<i64>,
 }
 
 #[derive(Clone)]
 pub struct UserConfig {
     db: Database,
+    // other octobot instances to ask about users who aren't mapped here
+    federation: Option<Arc<federation::Peers>>,
 }
 
 impl UserInfo {
@@ -26,13 +90,113 @@ impl UserInfo {
             github: git_user.to_string(),
             slack: slack_user.to_string(),
             mute_direct_messages: false,
+            muted_until: 0,
+            dm_events: String::new(),
+            quiet_hours_start: String::new(),
+            quiet_hours_end: String::new(),
+            timezone: String::new(),
+            digest: String::new(),
+            email: String::new(),
+            deleted_at: None,
         }
     }
+
+    pub fn direct_messages_muted(&self) -> bool {
+        self.mute_direct_messages || self.muted_until > db::now()
+    }
+
+    pub fn wants_dm_event(&self, event: &str) -> bool {
+        self.dm_events.trim().is_empty() || self.dm_events.split(',').any(|e| e.trim() == event)
+    }
+
+    pub fn in_quiet_hours(&self, now: i64) -> bool {
+        let start = parse_time_of_day(&self.quiet_hours_start);
+        let end = parse_time_of_day(&self.quiet_hours_end);
+        let (start, end) = match (start, end) {
+            (Some(s), Some(e)) if s != e => (s, e),
+            _ => return false,
+        };
+        let offset = parse_utc_offset(&self.timezone).unwrap_or(0);
+        let minute = ((now + offset) / 60).rem_euclid(24 * 60);
+
+        if start < end {
+            minute >= start && minute < end
+        } else {
+            // overnight, e.g. 22:00 - 08:00
+            minute >= start || minute < end
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for event in self.dm_events.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            if !DM_EVENT_TYPES.contains(&event) {
+                return Err(format_err!("Unknown direct message type: {}", event));
+            }
+        }
+        for time in &[&self.quiet_hours_start, &self.quiet_hours_end] {
+            if !time.trim().is_empty() && parse_time_of_day(time).is_none() {
+                return Err(format_err!("Invalid quiet hours time (expected HH:MM): {}", time));
+            }
+        }
+        if !self.digest.is_empty() && !digests::is_valid_frequency(&self.digest) {
+            return Err(format_err!("Invalid digest (expected daily or weekly): {}", self.digest));
+        }
+        if !self.email.trim().is_empty() && !self.email.contains('@') {
+            return Err(format_err!("Invalid email: {}", self.email));
+        }
+        if parse_utc_offset(&self.timezone).is_none() {
+            return Err(format_err!("Invalid timezone (expected a UTC offset like -05:00): {}", self.timezone));
+        }
+        Ok(())
+    }
+}
+
+// "HH:MM" to minutes since midnight
+fn parse_time_of_day(value: &str) -> Option<i64> {
+    let mut parts = value.trim().splitn(2, ':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next().unwrap_or("0").parse().ok()?;
+    if hours < 0 || hours > 23 || minutes < 0 || minutes > 59 {
+        return None;
+    }
+    Some(hours * 60 + minutes)
+}
+
+// "+02:00", "-0500", "+5" or "UTC" to seconds east of UTC. Named timezones aren't supported.
+fn parse_utc_offset(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if value.is_empty() || value.eq_ignore_ascii_case("utc") || value == "Z" {
+        return Some(0);
+    }
+
+    let sign = match value.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = value[1..].replace(':', "");
+    let (hours, minutes) = match digits.len() {
+        1 | 2 => (digits.parse::<i64>().ok()?, 0),
+        4 => (digits[..2].parse::<i64>().ok()?, digits[2..].parse::<i64>().ok()?),
+        _ => return None,
+    };
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
 }
 
 impl UserConfig {
     pub fn new(db: Database) -> UserConfig {
-        UserConfig { db: db }
+        UserConfig {
+            db: db,
+            federation: None,
+        }
+    }
+
+    pub fn with_federation(mut self, peers: Option<Arc<federation::Peers>>) -> UserConfig {
+        self.federation = peers;
+        self
     }
 
     pub fn insert(&mut self, git_user: &str, slack_user: &str) -> Result<()> {
@@ -41,9 +205,32 @@ impl UserConfig {
 
     pub fn insert_info(&mut self, user: &UserInfo) -> Result<()> {
         let conn = self.db.connect()?;
+        Self::insert_in(&conn, user)
+    }
+
+    // Inserts the user on `conn`, so that it can be part of a larger transaction
+    pub fn insert_in(conn: &db::Connection, user: &UserInfo) -> Result<()> {
+        // a new user replaces a deleted one with the same name
+        conn.execute(
+            "DELETE from users where github_name = ?1 and deleted_at != 0",
+            &[&user.github],
+        ).map_err(|e| format_err!("Error replacing deleted user {}: {}", user.github, e))?;
+
         conn.execute(
-            "INSERT INTO users (github_name, slack_name, mute_direct_messages) VALUES (?1, ?2, ?3)",
-            &[&user.github, &user.slack, &db::to_tinyint(user.mute_direct_messages) as &dyn ToSql],
+            "INSERT INTO users (github_name, slack_name, mute_direct_messages,
+                                dm_events, quiet_hours_start, quiet_hours_end, timezone, digest, email)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            &[
+                &user.github,
+                &user.slack,
+                &db::to_tinyint(user.mute_direct_messages) as &dyn ToSql,
+                &user.dm_events,
+                &user.quiet_hours_start,
+                &user.quiet_hours_end,
+                &user.timezone,
+                &user.digest,
+                &user.email,
+            ],
         ).map_err(|e| format_err!("Error inserting user {}: {}", user.github, e))?;
 
         Ok(())
@@ -51,46 +238,128 @@ impl UserConfig {
 
     pub fn update(&mut self, user: &UserInfo) -> Result<()> {
         let conn = self.db.connect()?;
+        Self::update_in(&conn, user)
+    }
+
+    pub fn update_in(conn: &db::Connection, user: &UserInfo) -> Result<()> {
         conn.execute(
-            "UPDATE users set github_name = ?1, slack_name = ?2, mute_direct_messages = ?3 where id = ?4",
-            &[&user.github, &user.slack, &db::to_tinyint(user.mute_direct_messages) as &dyn ToSql, &user.id],
+            "UPDATE users set github_name = ?1, slack_name = ?2, mute_direct_messages = ?3,
+                              dm_events = ?4, quiet_hours_start = ?5, quiet_hours_end = ?6, timezone = ?7,
+                              digest = ?8, email = ?9
+             where id = ?10",
+            &[
+                &user.github,
+                &user.slack,
+                &db::to_tinyint(user.mute_direct_messages) as &dyn ToSql,
+                &user.dm_events,
+                &user.quiet_hours_start,
+                &user.quiet_hours_end,
+                &user.timezone,
+                &user.digest,
+                &user.email,
+                &user.id,
+            ],
         ).map_err(|e| format_err!("Error updating user {}: {}", user.github, e))?;
 
         Ok(())
     }
 
+    // Soft-deletes the user: it can be restored until the retention window passes.
     pub fn delete(&mut self, user_id: i32) -> Result<()> {
         let conn = self.db.connect()?;
-        conn.execute("DELETE from users where id = ?1", &[&user_id]).map_err(|e| {
-            format_err!("Error deleting user {}: {}", user_id, e)
-        })?;
+        Self::delete_in(&conn, user_id, db::now())?;
+
+        self.purge_deleted(&conn)
+    }
+
+    pub fn delete_in(conn: &db::Connection, user_id: i32, now: i64) -> Result<()> {
+        conn.execute(
+            "UPDATE users set deleted_at = ?1 where id = ?2 and deleted_at = 0",
+            &[&now, &(user_id as i64)],
+        ).map_err(|e| format_err!("Error deleting user {}: {}", user_id, e))?;
+
+        Ok(())
+    }
+
+    pub fn restore(&mut self, user_id: i32) -> Result<()> {
+        let conn = self.db.connect()?;
+        self.purge_deleted(&conn)?;
+
+        let count = conn.execute(
+            "UPDATE users set deleted_at = 0 where id = ?1 and deleted_at != 0",
+            &[&user_id],
+        ).map_err(|e| format_err!("Error restoring user {}: {}", user_id, e))?;
+
+        if count == 0 {
+            return Err(format_err!("No deleted user with id {}", user_id));
+        }
+        Ok(())
+    }
+
+    fn purge_deleted(&self, conn: &db::Connection) -> Result<()> {
+        conn.execute(
+            "DELETE from users where deleted_at != 0 and deleted_at < ?1",
+            &[&(db::now() - db::DELETED_RETENTION_SECS)],
+        ).map_err(|e| format_err!("Error purging deleted users: {}", e))?;
 
         Ok(())
     }
 
     pub fn slack_user_name(&self, github_name: &str) -> Option<String> {
-        self.lookup_info(github_name).map(|u| u.slack)
+        self.lookup_info(github_name).map(|u| u.slack).filter(|s| !s.is_empty())
     }
 
     pub fn slack_user_mention(&self, github_name: &str) -> Option<String> {
-        self.lookup_info(github_name).and_then(|u| if u.mute_direct_messages {
+        self.lookup_info(github_name).and_then(|u| if u.direct_messages_muted() || u.slack.is_empty() {
             None
         } else {
             Some(mention(&u.slack))
         })
     }
 
-    pub fn get_all(&self) -> Result<Vec<UserInfo>> {
+    // Temporarily mute direct messages. Passing 0 unmutes.
+    pub fn mute_until(&mut self, github_name: &str, until: i64) -> Result<()> {
         let conn = self.db.connect()?;
-        let mut stmt = conn.prepare(
-            "SELECT id, slack_name, github_name, mute_direct_messages FROM users ORDER BY github_name",
-        )?;
-        let found = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+        conn.execute(
+            "UPDATE users set muted_until = ?1 where github_name = ?2 and deleted_at = 0",
+            &[&until as &dyn ToSql, &github_name],
+        ).map_err(|e| format_err!("Error muting user {}: {}", github_name, e))?;
+
+        Ok(())
+    }
+
+    pub fn get_all(&self) -> Result<Vec<UserInfo>> {
+        self.query_users("deleted_at = 0")
+    }
+
+    // Users that were deleted but may still be restored
+    pub fn get_deleted(&self) -> Result<Vec<UserInfo>> {
+        self.query_users("deleted_at != 0")
+    }
+
+    fn query_users(&self, filter: &str) -> Result<Vec<UserInfo>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, slack_name, github_name, mute_direct_messages, deleted_at, muted_until,
+                    dm_events, quiet_hours_start, quiet_hours_end, timezone, digest, email
+             FROM users WHERE {} ORDER BY github_name",
+            filter
+        ))?;
+        let found = stmt.query_map(db::NO_PARAMS, |row| {
+            let deleted_at: i64 = row.get(4)?;
             Ok(UserInfo {
                 id: row.get(0)?,
                 slack: row.get(1)?,
                 github: row.get(2)?,
                 mute_direct_messages: db::to_bool(row.get(3)?),
+                muted_until: row.get(5)?,
+                dm_events: row.get(6)?,
+                quiet_hours_start: row.get(7)?,
+                quiet_hours_end: row.get(8)?,
+                timezone: row.get(9)?,
+                digest: row.get(10)?,
+                email: row.get(11)?,
+                deleted_at: if deleted_at == 0 { None } else { Some(deleted_at) },
             })
         })?;
 
@@ -102,7 +371,13 @@ impl UserConfig {
         Ok(users)
     }
 
+    // Users who aren't mapped here are looked up in the federation's peers
     pub fn lookup_info(&self, github_name: &str) -> Option<UserInfo> {
+        self.lookup_local_info(github_name).or_else(|| self.federation.as_ref().and_then(|f| f.lookup(github_name)))
+    }
+
+    // Only users mapped here, e.g. to answer the federation's peers
+    pub fn lookup_local_info(&self, github_name: &str) -> Option<UserInfo> {
         match self.do_lookup_info(github_name) {
             Ok(u) => u,
             Err(e) => {
@@ -116,7 +391,9 @@ impl UserConfig {
         let github_name = github_name.to_string();
         let conn = self.db.connect()?;
         let mut stmt = conn.prepare(
-            "SELECT id, slack_name, mute_direct_messages FROM users where github_name = ?1",
+            "SELECT id, slack_name, mute_direct_messages, muted_until,
+                    dm_events, quiet_hours_start, quiet_hours_end, timezone, digest, email
+             FROM users where github_name = ?1 and deleted_at = 0",
         )?;
         let found = stmt.query_map(&[&github_name], |row| {
             Ok(UserInfo {
@@ -124,6 +401,14 @@ impl UserConfig {
                 slack: row.get(1)?,
                 github: github_name.clone(),
                 mute_direct_messages: db::to_bool(row.get(2)?),
+                muted_until: row.get(3)?,
+                dm_events: row.get(4)?,
+                quiet_hours_start: row.get(5)?,
+                quiet_hours_end: row.get(6)?,
+                timezone: row.get(7)?,
+                digest: row.get(8)?,
+                email: row.get(9)?,
+                deleted_at: None,
             })
         })?;
 
@@ -137,6 +422,89 @@ impl UserConfig {
         }
         Ok(user)
     }
+
+    // Reverse lookup for requests that come from slack (e.g. button clicks)
+    pub fn lookup_by_slack(&self, slack_name: &str) -> Option<UserInfo> {
+        match self.do_lookup_by_slack(slack_name) {
+            Ok(u) => u,
+            Err(e) => {
+                error!("Error looking up slack user: {}", e);
+                None
+            }
+        }
+    }
+
+    fn do_lookup_by_slack(&self, slack_name: &str) -> Result<Option<UserInfo>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, github_name, mute_direct_messages, muted_until,
+                    dm_events, quiet_hours_start, quiet_hours_end, timezone, digest, email
+             FROM users where slack_name = ?1 and deleted_at = 0",
+        )?;
+        let mut rows = stmt.query(&[&slack_name])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(Some(UserInfo {
+                id: row.get(0)?,
+                slack: slack_name.to_string(),
+                github: row.get(1)?,
+                mute_direct_messages: db::to_bool(row.get(2)?),
+                muted_until: row.get(3)?,
+                dm_events: row.get(4)?,
+                quiet_hours_start: row.get(5)?,
+                quiet_hours_end: row.get(6)?,
+                timezone: row.get(7)?,
+                digest: row.get(8)?,
+                email: row.get(9)?,
+                deleted_at: None,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Reverse lookup for notifications from JIRA, whose users only have an email address in common with ours
+    pub fn lookup_by_email(&self, email: &str) -> Option<UserInfo> {
+        match self.do_lookup_by_email(email) {
+            Ok(u) => u,
+            Err(e) => {
+                error!("Error looking up user by email: {}", e);
+                None
+            }
+        }
+    }
+
+    fn do_lookup_by_email(&self, email: &str) -> Result<Option<UserInfo>> {
+        if email.is_empty() {
+            return Ok(None);
+        }
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, github_name, slack_name, mute_direct_messages, muted_until,
+                    dm_events, quiet_hours_start, quiet_hours_end, timezone, digest, email
+             FROM users where lower(email) = lower(?1) and deleted_at = 0",
+        )?;
+        let mut rows = stmt.query(&[&email])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(Some(UserInfo {
+                id: row.get(0)?,
+                github: row.get(1)?,
+                slack: row.get(2)?,
+                mute_direct_messages: db::to_bool(row.get(3)?),
+                muted_until: row.get(4)?,
+                dm_events: row.get(5)?,
+                quiet_hours_start: row.get(6)?,
+                quiet_hours_end: row.get(7)?,
+                timezone: row.get(8)?,
+                digest: row.get(9)?,
+                email: row.get(10)?,
+                deleted_at: None,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
 }
 
 pub fn mention(username: &str) -> String {
@@ -176,6 +544,167 @@ mod tests {
         assert_eq!(None, users.slack_user_mention("some.other.user"));
     }
 
+    #[test]
+    fn test_lookup_by_slack() {
+        let (mut users, _temp) = new_test();
+
+        users.insert("some-git-user", "the-slacker").unwrap();
+
+        assert_eq!("some-git-user", users.lookup_by_slack("the-slacker").unwrap().github);
+        assert!(users.lookup_by_slack("some-git-user").is_none());
+    }
+
+    #[test]
+    fn test_lookup_by_email() {
+        let (mut users, _temp) = new_test();
+
+        let mut user = UserInfo::new("some-git-user", "the-slacker");
+        user.email = "Some.User@company.com".into();
+        users.insert_info(&user).unwrap();
+        users.insert("other-git-user", "other-slacker").unwrap();
+
+        assert_eq!("some-git-user", users.lookup_by_email("some.user@company.com").unwrap().github);
+        assert!(users.lookup_by_email("other@company.com").is_none());
+        assert!(users.lookup_by_email("").is_none());
+    }
+
+    #[test]
+    fn test_mute_until() {
+        let (mut users, _temp) = new_test();
+
+        users.insert("some-git-user", "the-slacker").unwrap();
+
+        users.mute_until("some-git-user", db::now() + 60).unwrap();
+        assert_eq!(None, users.slack_user_mention("some-git-user"));
+        assert_eq!(Some("the-slacker".into()), users.slack_user_name("some-git-user"));
+
+        users.mute_until("some-git-user", db::now() - 1).unwrap();
+        assert_eq!(Some("@the-slacker".into()), users.slack_user_mention("some-git-user"));
+    }
+
+    #[test]
+    fn test_soft_delete() {
+        let (mut users, _temp) = new_test();
+
+        users.insert("some-git-user", "the-slacker").unwrap();
+        let id = users.get_all().unwrap()[0].id.unwrap();
+
+        users.delete(id).unwrap();
+        assert_eq!(None, users.slack_user_name("some-git-user"));
+        assert_eq!(0, users.get_all().unwrap().len());
+
+        let deleted = users.get_deleted().unwrap();
+        assert_eq!(1, deleted.len());
+        assert!(deleted[0].deleted_at.is_some());
+
+        users.restore(id).unwrap();
+        assert_eq!(Some("the-slacker".into()), users.slack_user_name("some-git-user"));
+        assert_eq!(0, users.get_deleted().unwrap().len());
+
+        // can't restore users that aren't deleted
+        assert!(users.restore(id).is_err());
+    }
+
+    #[test]
+    fn test_soft_delete_reinsert() {
+        let (mut users, _temp) = new_test();
+
+        users.insert("some-git-user", "the-slacker").unwrap();
+        let id = users.get_all().unwrap()[0].id.unwrap();
+        users.delete(id).unwrap();
+
+        users.insert("some-git-user", "the-new-slacker").unwrap();
+        assert_eq!(Some("the-new-slacker".into()), users.slack_user_name("some-git-user"));
+        assert_eq!(0, users.get_deleted().unwrap().len());
+    }
+
+    #[test]
+    fn test_soft_delete_purge() {
+        let (mut users, _temp) = new_test();
+
+        users.insert("some-git-user", "the-slacker").unwrap();
+        users.insert("other-git-user", "other-slacker").unwrap();
+        let all = users.get_all().unwrap();
+        users.delete(all[0].id.unwrap()).unwrap();
+
+        // pretend it was deleted long ago
+        let conn = users.db.connect().unwrap();
+        conn.execute("UPDATE users set deleted_at = 1 where deleted_at != 0", db::NO_PARAMS).unwrap();
+
+        users.delete(all[1].id.unwrap()).unwrap();
+        assert_eq!(vec![all[1].id], users.get_deleted().unwrap().iter().map(|u| u.id).collect::<Vec<_>>());
+        assert!(users.restore(all[0].id.unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_notification_preferences() {
+        let (mut users, _temp) = new_test();
+
+        let mut user = UserInfo::new("some-git-user", "the-slacker");
+        user.dm_events = "review, comment".into();
+        user.quiet_hours_start = "22:00".into();
+        user.quiet_hours_end = "08:00".into();
+        user.timezone = "-05:00".into();
+        users.insert_info(&user).unwrap();
+
+        let user = users.lookup_info("some-git-user").unwrap();
+        assert!(user.wants_dm_event(DM_REVIEW));
+        assert!(user.wants_dm_event(DM_COMMENT));
+        assert!(!user.wants_dm_event(DM_PUSH));
+        assert_eq!("-05:00", user.timezone);
+
+        // 1970-01-02 04:00 UTC is 23:00 the day before in UTC-5
+        assert!(user.in_quiet_hours(28 * 3600));
+        // 14:00 UTC is 09:00 in UTC-5
+        assert!(!user.in_quiet_hours(38 * 3600));
+
+        assert!(UserInfo::new("a", "b").wants_dm_event(DM_PUSH));
+        assert!(!UserInfo::new("a", "b").in_quiet_hours(0));
+    }
+
+    #[test]
+    fn test_quiet_hours_same_day() {
+        let mut user = UserInfo::new("some-git-user", "the-slacker");
+        user.quiet_hours_start = "12:00".into();
+        user.quiet_hours_end = "13:30".into();
+
+        assert!(!user.in_quiet_hours(11 * 3600 + 59 * 60));
+        assert!(user.in_quiet_hours(12 * 3600));
+        assert!(user.in_quiet_hours(13 * 3600 + 29 * 60));
+        assert!(!user.in_quiet_hours(13 * 3600 + 30 * 60));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut user = UserInfo::new("some-git-user", "the-slacker");
+        assert!(user.validate().is_ok());
+
+        user.dm_events = "review,bogus".into();
+        assert!(user.validate().is_err());
+        user.dm_events = "review".into();
+
+        user.quiet_hours_start = "25:00".into();
+        assert!(user.validate().is_err());
+        user.quiet_hours_start = "9:30".into();
+        assert!(user.validate().is_ok());
+
+        user.timezone = "America/New_York".into();
+        assert!(user.validate().is_err());
+        user.timezone = "+0530".into();
+        assert!(user.validate().is_ok());
+    }
+
+    #[test]
+    fn test_parse_utc_offset() {
+        assert_eq!(Some(0), parse_utc_offset(""));
+        assert_eq!(Some(0), parse_utc_offset("UTC"));
+        assert_eq!(Some(-5 * 3600), parse_utc_offset("-05:00"));
+        assert_eq!(Some(5 * 3600 + 30 * 60), parse_utc_offset("+0530"));
+        assert_eq!(Some(2 * 3600), parse_utc_offset("+2"));
+        assert_eq!(None, parse_utc_offset("05:00"));
+        assert_eq!(None, parse_utc_offset("+25"));
+    }
+
     #[test]
     fn test_mention() {
         assert_eq!("@me", mention("me"));
@@ -6,9 +6,10 @@ use failure::format_err;
 use hyper::{self, Body, Response, StatusCode};
 use time;
 
+use crate::error_reports;
 use crate::errors::*;
 
-fn escape_for_slack(str: &str) -> String {
+pub fn escape_for_slack(str: &str) -> String {
     str.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;")
 }
 
@@ -16,7 +17,7 @@ pub fn make_link(url: &str, text: &str) -> String {
     format!("<{}|{}>", escape_for_slack(url), escape_for_slack(text))
 }
 
-fn find_github_username(name: &str) -> Option<&str> {
+pub fn find_github_username(name: &str) -> Option<&str> {
     if name.len() == 0 {
         return None;
     }
@@ -57,6 +58,19 @@ pub fn format_duration(dur: time::Duration) -> String {
     }
 }
 
+// Parses an ISO 8601 UTC timestamp (as returned by the github API) into seconds since the epoch
+pub fn parse_timestamp(value: &str) -> Option<i64> {
+    time::strptime(value, "%Y-%m-%dT%H:%M:%SZ")
+        .ok()
+        .map(|t| t.to_timespec().sec)
+}
+
+// Formats seconds since the epoch for showing to users, e.g. "2019-03-01 14:00 UTC"
+pub fn format_timestamp(value: i64) -> String {
+    let tm = time::at_utc(time::Timespec::new(value, 0));
+    time::strftime("%Y-%m-%d %H:%M UTC", &tm).unwrap_or(String::new())
+}
+
 pub fn check_unique_event<T>(event: T, events: &mut Vec<T>, trim_at: usize, trim_to: usize) -> bool
 where
     T: PartialEq,
@@ -122,12 +136,27 @@ pub fn recv_timeout<T>(rx: &Receiver<T>, timeout: std::time::Duration) -> Result
 }
 
 pub fn new_msg_resp<S: Into<String>>(status: StatusCode, msg: S) -> Response<Body> {
-    let msg: String = msg.into();
+    let mut msg: String = msg.into();
+    if status.is_client_error() || status.is_server_error() {
+        msg = with_request_id(msg);
+    }
     let mut resp = Response::new(Body::from(msg));
     *resp.status_mut() = status;
     resp
 }
 
+// Errors mention the request they happened in, to look up in /api/trace
+pub fn with_request_id(msg: String) -> String {
+    let context = error_reports::current_context();
+    if context.request_id.is_empty() {
+        msg
+    } else if msg.is_empty() {
+        format!("request {}", context.request_id)
+    } else {
+        format!("{} (request {})", msg, context.request_id)
+    }
+}
+
 pub fn new_json_resp(json: String) -> Response<Body> {
     let mut resp = Response::new(Body::from(json));
     resp.headers_mut().insert(
@@ -155,12 +184,71 @@ pub fn new_bad_req_resp<S: Into<String>>(msg: S) -> Response<Body> {
     new_msg_resp(StatusCode::BAD_REQUEST, msg)
 }
 
+// Translates a gitignore-style glob into an (unanchored) regex string.
+// `**` matches across directories, `*` and `?` match within a single path segment.
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let chars = glob.chars().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '*' {
+            if i + 1 < chars.len() && chars[i + 1] == '*' {
+                if i + 2 < chars.len() && chars[i + 2] == '/' {
+                    regex += "(.*/)?";
+                    i += 3;
+                } else {
+                    regex += ".*";
+                    i += 2;
+                }
+                continue;
+            }
+            regex += "[^/]*";
+        } else if c == '?' {
+            regex += "[^/]";
+        } else {
+            regex += &regex::escape(&c.to_string());
+        }
+        i += 1;
+    }
+    regex
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use maplit::hashmap;
 
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(Some(0), parse_timestamp("1970-01-01T00:00:00Z"));
+        assert_eq!(Some(1556712000), parse_timestamp("2019-05-01T12:00:00Z"));
+        assert_eq!(None, parse_timestamp("yesterday"));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!("2019-05-01 12:00 UTC", format_timestamp(1556712000));
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        let matches = |glob: &str, path: &str| {
+            regex::Regex::new(&format!("^{}$", glob_to_regex(glob))).unwrap().is_match(path)
+        };
+
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "src/main.rs"));
+        assert!(matches("**/*.rs", "main.rs"));
+        assert!(matches("**/*.rs", "src/server/main.rs"));
+        assert!(matches("docs/**", "docs/a/b.md"));
+        assert!(matches("file?.txt", "file1.txt"));
+        assert!(!matches("file?.txt", "file/.txt"));
+        assert!(matches("a+b.txt", "a+b.txt"));
+        assert!(!matches("a+b.txt", "aab.txt"));
+    }
+
     #[test]
     fn test_make_link() {
         assert_eq!("<http://the-url|the text>", make_link("http://the-url", "the text"));
@@ -234,4 +322,18 @@ mod tests {
     fn test_parse_query_none() {
         assert_eq!(HashMap::new(), parse_query(None));
     }
+
+    #[test]
+    fn test_with_request_id() {
+        assert_eq!("Not found", with_request_id("Not found".into()));
+
+        let context = error_reports::Context {
+            request_id: "0123456789abcdef".into(),
+            ..error_reports::Context::default()
+        };
+        error_reports::with_context(context, || {
+            assert_eq!("Not found (request 0123456789abcdef)", with_request_id("Not found".into()));
+            assert_eq!("request 0123456789abcdef", with_request_id(String::new()));
+        });
+    }
 }
@@ -0,0 +1,282 @@
+use log::error;
+
+use crate::commit_lint;
+use crate::config::{Config, JiraConfig};
+use crate::errors::*;
+use crate::github;
+use crate::jira;
+use crate::messenger::Messenger;
+use crate::slack::SlackAttachmentBuilder;
+use crate::util;
+
+// Code hosts other than github (Azure DevOps, GitLab) translate their webhooks into github's models: repos named
+// like they are in the repo config, users matched through the user mappings, and pull requests. Routing, messages and
+// JIRA then handle them like github's, and the checks octobot runs on pull requests go through `PullRequestHost`.
+
+const LINT_CONTEXT: &str = "commit-lint";
+
+// More commits than this are probably a merge of some other branch: their JIRAs are left alone
+const MAX_COMMITS_FOR_JIRA_CONSIDERATION: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Success,
+    Failure,
+}
+
+// One pull (or merge) request on the host that sent it
+pub trait PullRequestHost {
+    fn commits(&self) -> Result<Vec<github::Commit>>;
+
+    fn comment(&self, body: &str) -> Result<()>;
+
+    fn set_status(&self, context: &str, status: Status, description: &str) -> Result<()>;
+}
+
+pub fn notify_pull_request(
+    pr: &github::PullRequest,
+    verb: &str,
+    sender: &github::User,
+    commits: &Vec<github::Commit>,
+    messenger: &Messenger,
+) {
+    let attachment = SlackAttachmentBuilder::new("")
+        .title(format!("Pull Request #{}: \"{}\"", pr.number, pr.title))
+        .title_link(pr.html_url.as_str())
+        .build();
+
+    messenger.send_to_all(
+        &format!("Pull Request {}", verb),
+        &vec![attachment],
+        &pr.user,
+        sender,
+        &pr.base.repo,
+        &pr.all_reviewers(),
+        &pr.base.ref_name,
+        commits,
+    );
+}
+
+pub fn notify_comment(
+    pr: &github::PullRequest,
+    commenter: &github::User,
+    body: &str,
+    url: &str,
+    commits: &Vec<github::Commit>,
+    messenger: &Messenger,
+) {
+    if body.trim().is_empty() {
+        return;
+    }
+    let attachment = SlackAttachmentBuilder::new(body.trim())
+        .title(format!("{} said:", commenter.login()))
+        .title_link(url)
+        .build();
+
+    let mut participants = pr.all_reviewers();
+    participants.extend(pr.assignees.iter().cloned());
+    participants.extend(util::get_mentioned_usernames(body).into_iter().map(|u| github::User::new(u)));
+
+    messenger.send_to_all(
+        &format!("Comment on \"{}\"", util::make_link(&pr.html_url, &pr.title)),
+        &vec![attachment],
+        &pr.user,
+        commenter,
+        &pr.base.repo,
+        &participants,
+        &pr.base.ref_name,
+        commits,
+    );
+}
+
+// Approvals go to the PR's author, with how many more the host requires (if it says)
+pub fn notify_approval(pr: &github::PullRequest, approver: &github::User, approvals_left: u32, messenger: &Messenger) {
+    let remaining = if approvals_left > 0 {
+        format!(" ({} more approval(s) required)", approvals_left)
+    } else {
+        String::new()
+    };
+    let msg = format!(
+        "{} approved PR \"{}\"{}",
+        approver.login(),
+        util::make_link(&pr.html_url, &pr.title),
+        remaining
+    );
+    let attachment = SlackAttachmentBuilder::new("")
+        .title("Review: Approved")
+        .title_link(pr.html_url.as_str())
+        .color("good")
+        .build();
+    let commits = Vec::<github::Commit>::new();
+
+    messenger.send_to_owner(&msg, &vec![attachment], &pr.user, &pr.base.repo, &pr.base.ref_name, &commits);
+}
+
+// Checks the PR title and commits against the repo's lint rules, and reports the result as a status.
+// With |comment|, violations are listed in a comment too: only on new PRs, so updates don't repeat it.
+pub fn lint_pull_request(pr: &github::PullRequest, comment: bool, config: &Config, host: &dyn PullRequestHost) {
+    let rules = match config.repos().lint_rules(&pr.base.repo) {
+        Some(r) => r,
+        None => return,
+    };
+
+    if let Err(e) = do_lint_pull_request(pr, &rules, comment, host) {
+        error!("Error linting pull request {}#{}: {}", pr.base.repo.full_name, pr.number, e);
+    }
+}
+
+fn do_lint_pull_request(
+    pr: &github::PullRequest,
+    rules: &commit_lint::LintRules,
+    comment: bool,
+    host: &dyn PullRequestHost,
+) -> Result<()> {
+    let violations = commit_lint::violations(rules, &pr.title, &host.commits()?);
+
+    if violations.is_empty() {
+        host.set_status(LINT_CONTEXT, Status::Success, "No lint violations")?;
+    } else {
+        host.set_status(LINT_CONTEXT, Status::Failure, &format!("{} lint violation(s)", violations.len()))?;
+    }
+
+    if comment && !violations.is_empty() {
+        host.comment(&commit_lint::lint_comment(&violations))?;
+    }
+    Ok(())
+}
+
+// Moves the JIRAs the PR fixes or references along, like github PRs that are opened
+pub fn submit_for_review(
+    pr: &github::PullRequest,
+    config: &Config,
+    jira: Option<&dyn jira::api::Session>,
+    host: &dyn PullRequestHost,
+) {
+    if pr.is_draft() {
+        return;
+    }
+    with_jira(pr, config, jira, host, |commits, projects, jira, jira_config| {
+        jira::workflow::submit_for_review(pr, commits, projects, jira, jira_config)
+    });
+}
+
+pub fn mark_merged(
+    pr: &github::PullRequest,
+    config: &Config,
+    jira: Option<&dyn jira::api::Session>,
+    host: &dyn PullRequestHost,
+) {
+    with_jira(pr, config, jira, host, |commits, projects, jira, jira_config| {
+        jira::workflow::mark_merged(pr, commits, projects, jira, jira_config)
+    });
+}
+
+fn with_jira<F>(
+    pr: &github::PullRequest,
+    config: &Config,
+    jira: Option<&dyn jira::api::Session>,
+    host: &dyn PullRequestHost,
+    f: F,
+) where
+    F: FnOnce(&Vec<github::Commit>, &Vec<String>, &dyn jira::api::Session, &JiraConfig),
+{
+    let (jira, jira_config) = match (jira, config.jira.as_ref()) {
+        (Some(j), Some(c)) => (j, c),
+        _ => return,
+    };
+    let projects = config.repos().jira_projects(&pr.base.repo, &pr.base.ref_name);
+    if projects.is_empty() {
+        return;
+    }
+
+    match host.commits() {
+        Ok(ref commits) if commits.len() <= MAX_COMMITS_FOR_JIRA_CONSIDERATION => {
+            f(commits, &projects, jira, jira_config)
+        }
+        Ok(_) => (),
+        Err(e) => error!("Error getting commits of pull request {}#{}: {}", pr.base.repo.full_name, pr.number, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    struct FakeHost {
+        commits: Vec<github::Commit>,
+        comments: Mutex<Vec<String>>,
+        statuses: Mutex<Vec<(String, Status, String)>>,
+    }
+
+    impl PullRequestHost for FakeHost {
+        fn commits(&self) -> Result<Vec<github::Commit>> {
+            Ok(self.commits.clone())
+        }
+
+        fn comment(&self, body: &str) -> Result<()> {
+            self.comments.lock().unwrap().push(body.into());
+            Ok(())
+        }
+
+        fn set_status(&self, context: &str, status: Status, description: &str) -> Result<()> {
+            self.statuses.lock().unwrap().push((context.into(), status, description.into()));
+            Ok(())
+        }
+    }
+
+    fn fake_host(messages: Vec<&str>) -> FakeHost {
+        FakeHost {
+            commits: messages
+                .into_iter()
+                .map(|m| {
+                    let mut commit = github::Commit::new();
+                    commit.commit.message = m.into();
+                    commit
+                })
+                .collect(),
+            comments: Mutex::new(vec![]),
+            statuses: Mutex::new(vec![]),
+        }
+    }
+
+    fn rules() -> commit_lint::LintRules {
+        commit_lint::LintRules::new(true, "", "", false).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_lint_pull_request() {
+        let host = fake_host(vec!["feat: add the thing"]);
+        do_lint_pull_request(&pr("fix: the thing"), &rules(), true, &host).unwrap();
+
+        assert_eq!(
+            vec![(LINT_CONTEXT.to_string(), Status::Success, "No lint violations".to_string())],
+            *host.statuses.lock().unwrap()
+        );
+        assert!(host.comments.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lint_pull_request_violations() {
+        let host = fake_host(vec!["added the thing"]);
+        do_lint_pull_request(&pr("The thing"), &rules(), true, &host).unwrap();
+
+        let statuses = host.statuses.lock().unwrap();
+        assert_eq!(Status::Failure, statuses[0].1);
+        assert_eq!("2 lint violation(s)", statuses[0].2);
+        assert_eq!(1, host.comments.lock().unwrap().len());
+
+        // updates only set the status
+        let host = fake_host(vec!["added the thing"]);
+        do_lint_pull_request(&pr("The thing"), &rules(), false, &host).unwrap();
+        assert_eq!(Status::Failure, host.statuses.lock().unwrap()[0].1);
+        assert!(host.comments.lock().unwrap().is_empty());
+    }
+
+    fn pr(title: &str) -> github::PullRequest {
+        let mut pr = github::PullRequest::new();
+        pr.title = title.into();
+        pr
+    }
+}
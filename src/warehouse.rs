@@ -0,0 +1,407 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use failure::format_err;
+use log::{error, info};
+use reqwest;
+use ring::digest;
+use rustc_serialize::hex::ToHex;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+use crate::config::{Config, WarehouseConfig};
+use crate::db;
+use crate::errors::*;
+use crate::jwt;
+use crate::network;
+use crate::outbound_webhooks::Delivery;
+use crate::scheduler::Task;
+
+pub const BIGQUERY: &str = "bigquery";
+pub const HTTP: &str = "http";
+
+const BIGQUERY_URL: &str = "https://bigquery.googleapis.com/bigquery/v2";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const SCOPE: &str = "https://www.googleapis.com/auth/bigquery.insertdata";
+
+// Batches that couldn't be written are retried on the next flush, but only this many are kept
+const MAX_PENDING_BATCHES: usize = 10;
+
+// An exported event: a webhook delivery plus an id, so that retried batches don't duplicate rows
+#[derive(Serialize, Debug)]
+pub struct Row<'a> {
+    pub id: String,
+    #[serde(flatten)]
+    pub delivery: &'a Delivery,
+}
+
+// The hex SHA-256 of the delivery, so the same event always gets the same id
+pub fn row_id(delivery: &Delivery) -> String {
+    let json = serde_json::to_vec(delivery).unwrap_or_default();
+    digest::digest(&digest::SHA256, &json).as_ref().to_hex()
+}
+
+pub fn rows(deliveries: &[Delivery]) -> Vec<Row> {
+    deliveries.iter().map(|d| Row { id: row_id(d), delivery: d }).collect()
+}
+
+pub trait Sink: Send + Sync {
+    fn write(&self, deliveries: &[Delivery]) -> Result<()>;
+}
+
+// POSTs each batch to an endpoint as a JSON array of rows
+pub struct HttpSink {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl HttpSink {
+    pub fn new(config: &WarehouseConfig) -> Result<HttpSink> {
+        Ok(HttpSink {
+            client: network::client(),
+            url: config.url.clone().ok_or_else(|| format_err!("warehouse.url is required"))?,
+            token: config.token.clone().filter(|t| !t.is_empty()),
+        })
+    }
+}
+
+impl Sink for HttpSink {
+    fn write(&self, deliveries: &[Delivery]) -> Result<()> {
+        let mut req = self.client.post(&self.url).json(&rows(deliveries));
+        if let Some(ref token) = self.token {
+            req = req.header(reqwest::header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        req.send()
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format_err!("Error writing {} events to {}: {}", deliveries.len(), self.url, e))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct AccessToken {
+    access_token: String,
+    expires_in: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct InsertAllResponse {
+    #[serde(rename = "insertErrors", default)]
+    insert_errors: Vec<serde_json::Value>,
+}
+
+// Streams each batch into a BigQuery table, as the config's service account
+pub struct BigQuerySink {
+    client: reqwest::Client,
+    url: String,
+    client_email: String,
+    key: Vec<u8>,
+    // and when it expires
+    token: Mutex<Option<(String, i64)>>,
+}
+
+impl BigQuerySink {
+    pub fn new(config: &WarehouseConfig) -> Result<BigQuerySink> {
+        let field = |value: &Option<String>, name: &str| {
+            value.clone().ok_or_else(|| format_err!("warehouse.{} is required", name))
+        };
+        Ok(BigQuerySink {
+            client: network::client(),
+            url: format!(
+                "{}/projects/{}/datasets/{}/tables/{}/insertAll",
+                BIGQUERY_URL,
+                field(&config.project, "project")?,
+                field(&config.dataset, "dataset")?,
+                field(&config.table, "table")?
+            ),
+            client_email: field(&config.client_email, "client_email")?,
+            key: config.key()?,
+            token: Mutex::new(None),
+        })
+    }
+
+    fn access_token(&self) -> Result<String> {
+        let mut token = self.token.lock().unwrap();
+        if let Some((ref access_token, expires_at)) = *token {
+            if expires_at > db::now() + 60 {
+                return Ok(access_token.clone());
+            }
+        }
+
+        let assertion = jwt::new_service_account_token(&self.client_email, SCOPE, TOKEN_URL, &self.key)?;
+        let resp = self
+            .client
+            .post(TOKEN_URL)
+            .form(&[("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"), ("assertion", &assertion)])
+            .send()
+            .and_then(|r| r.error_for_status())
+            .and_then(|mut r| r.json::<AccessToken>())
+            .map_err(|e| format_err!("Error getting an access token for {}: {}", self.client_email, e))?;
+
+        *token = Some((resp.access_token.clone(), db::now() + resp.expires_in));
+        Ok(resp.access_token)
+    }
+}
+
+pub fn insert_all_body(deliveries: &[Delivery]) -> serde_json::Value {
+    let rows = rows(deliveries)
+        .into_iter()
+        .map(|row| serde_json::json!({ "insertId": row.id.clone(), "json": row }))
+        .collect::<Vec<_>>();
+    serde_json::json!({ "rows": rows, "ignoreUnknownValues": true })
+}
+
+impl Sink for BigQuerySink {
+    fn write(&self, deliveries: &[Delivery]) -> Result<()> {
+        let resp = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::AUTHORIZATION, format!("Bearer {}", self.access_token()?))
+            .json(&insert_all_body(deliveries))
+            .send()
+            .and_then(|r| r.error_for_status())
+            .and_then(|mut r| r.json::<InsertAllResponse>())
+            .map_err(|e| format_err!("Error inserting {} events into bigquery: {}", deliveries.len(), e))?;
+
+        // rows are inserted all or nothing unless skipInvalidRows is set, so any error means none were
+        match resp.insert_errors.first() {
+            Some(e) => Err(format_err!(
+                "Error inserting {} events into bigquery: {} row(s) failed, e.g. {}",
+                deliveries.len(),
+                resp.insert_errors.len(),
+                e
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+// Buffers events and writes them to the sink in batches
+pub struct Exporter {
+    sink: Arc<dyn Sink>,
+    batch_size: usize,
+    pending: Mutex<Vec<Delivery>>,
+}
+
+impl Exporter {
+    pub fn new(sink: Arc<dyn Sink>, batch_size: usize) -> Exporter {
+        Exporter {
+            sink: sink,
+            batch_size: batch_size,
+            pending: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn add(&self, delivery: Delivery) {
+        let full = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push(delivery);
+            pending.len() >= self.batch_size
+        };
+        if full {
+            if let Err(e) = self.flush() {
+                error!("{}", e);
+            }
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    // Writes the pending events, returning how many were written. Those that weren't are kept for the next flush.
+    pub fn flush(&self) -> Result<usize> {
+        let deliveries = mem::replace(&mut *self.pending.lock().unwrap(), vec![]);
+
+        let mut written = 0;
+        for batch in deliveries.chunks(self.batch_size) {
+            if let Err(e) = self.sink.write(batch) {
+                self.keep(deliveries[written..].to_vec());
+                return Err(e);
+            }
+            written += batch.len();
+        }
+        Ok(written)
+    }
+
+    fn keep(&self, mut deliveries: Vec<Delivery>) {
+        let mut pending = self.pending.lock().unwrap();
+        deliveries.extend(pending.drain(..));
+
+        let max = self.batch_size * MAX_PENDING_BATCHES;
+        if deliveries.len() > max {
+            let dropped = deliveries.len() - max;
+            error!("Dropping {} events that couldn't be exported", dropped);
+            deliveries.drain(..dropped);
+        }
+        *pending = deliveries;
+    }
+}
+
+pub fn new_exporter(config: &Config) -> Result<Option<Arc<Exporter>>> {
+    let warehouse = match config.warehouse {
+        Some(ref w) => w,
+        None => return Ok(None),
+    };
+    let sink: Arc<dyn Sink> = match warehouse.sink.as_str() {
+        BIGQUERY => Arc::new(BigQuerySink::new(warehouse)?),
+        HTTP => Arc::new(HttpSink::new(warehouse)?),
+        sink => return Err(format_err!("Invalid warehouse sink: '{}'", sink)),
+    };
+    Ok(Some(Arc::new(Exporter::new(sink, config.warehouse_batch_size()))))
+}
+
+// Writes events that haven't filled a batch yet, and retries failed ones
+pub struct ExportFlusher {
+    exporter: Arc<Exporter>,
+}
+
+impl ExportFlusher {
+    pub fn new(exporter: Arc<Exporter>) -> Arc<dyn Task> {
+        Arc::new(ExportFlusher { exporter: exporter })
+    }
+}
+
+impl Task for ExportFlusher {
+    fn run(&self, _now: i64) -> Result<()> {
+        let count = self.exporter.flush()?;
+        if count > 0 {
+            info!("Exported {} events", count);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::github;
+    use crate::outbound_webhooks::{OutboundEvent, CI_FAILED, PULL_REQUEST_MERGED};
+
+    struct FakeSink {
+        batches: Mutex<Vec<Vec<Delivery>>>,
+        broken: Mutex<bool>,
+    }
+
+    impl FakeSink {
+        fn new() -> Arc<FakeSink> {
+            Arc::new(FakeSink {
+                batches: Mutex::new(vec![]),
+                broken: Mutex::new(false),
+            })
+        }
+
+        fn sizes(&self) -> Vec<usize> {
+            self.batches.lock().unwrap().iter().map(|b| b.len()).collect()
+        }
+    }
+
+    impl Sink for FakeSink {
+        fn write(&self, deliveries: &[Delivery]) -> Result<()> {
+            if *self.broken.lock().unwrap() {
+                return Err(format_err!("503 Service Unavailable"));
+            }
+            self.batches.lock().unwrap().push(deliveries.to_vec());
+            Ok(())
+        }
+    }
+
+    fn delivery(event: &str, timestamp: i64) -> Delivery {
+        let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
+        Delivery {
+            event: OutboundEvent::new(event, &repo, "the-sender"),
+            timestamp: timestamp,
+        }
+    }
+
+    #[test]
+    fn test_batches() {
+        let sink = FakeSink::new();
+        let exporter = Exporter::new(sink.clone(), 3);
+
+        for i in 0..4 {
+            exporter.add(delivery(CI_FAILED, i));
+        }
+        assert_eq!(vec![3], sink.sizes());
+        assert_eq!(1, exporter.pending());
+
+        assert_eq!(1, exporter.flush().unwrap());
+        assert_eq!(0, exporter.flush().unwrap());
+        assert_eq!(vec![3, 1], sink.sizes());
+        assert_eq!(3, sink.batches.lock().unwrap()[1][0].timestamp);
+    }
+
+    #[test]
+    fn test_failed_batches_are_retried() {
+        let sink = FakeSink::new();
+        let exporter = Exporter::new(sink.clone(), 2);
+
+        *sink.broken.lock().unwrap() = true;
+        for i in 0..5 {
+            exporter.add(delivery(CI_FAILED, i));
+        }
+        assert!(exporter.flush().is_err());
+        assert_eq!(5, exporter.pending());
+
+        *sink.broken.lock().unwrap() = false;
+        assert_eq!(5, exporter.flush().unwrap());
+        assert_eq!(vec![2, 2, 1], sink.sizes());
+        let timestamps = sink.batches.lock().unwrap().iter().flatten().map(|d| d.timestamp).collect::<Vec<_>>();
+        assert_eq!(vec![0, 1, 2, 3, 4], timestamps);
+    }
+
+    #[test]
+    fn test_oldest_events_are_dropped() {
+        let sink = FakeSink::new();
+        let exporter = Exporter::new(sink.clone(), 1);
+
+        *sink.broken.lock().unwrap() = true;
+        for i in 0..(MAX_PENDING_BATCHES as i64 + 5) {
+            exporter.add(delivery(CI_FAILED, i));
+        }
+        assert_eq!(MAX_PENDING_BATCHES, exporter.pending());
+
+        *sink.broken.lock().unwrap() = false;
+        exporter.flush().unwrap();
+        assert_eq!(5, sink.batches.lock().unwrap()[0][0].timestamp);
+    }
+
+    #[test]
+    fn test_insert_all_body() {
+        let deliveries = vec![delivery(PULL_REQUEST_MERGED, 1234), delivery(PULL_REQUEST_MERGED, 1234)];
+        let id = row_id(&deliveries[0]);
+        assert_eq!(64, id.len());
+        assert_ne!(id, row_id(&delivery(PULL_REQUEST_MERGED, 1235)));
+
+        assert_eq!(
+            serde_json::json!({
+                "rows": [
+                    {
+                        "insertId": id,
+                        "json": {
+                            "id": id,
+                            "event": "pull_request.merged",
+                            "repo": "the-owner/the-repo",
+                            "sender": "the-sender",
+                            "timestamp": 1234,
+                        },
+                    },
+                    {
+                        "insertId": id,
+                        "json": {
+                            "id": id,
+                            "event": "pull_request.merged",
+                            "repo": "the-owner/the-repo",
+                            "sender": "the-sender",
+                            "timestamp": 1234,
+                        },
+                    },
+                ],
+                "ignoreUnknownValues": true,
+            }),
+            insert_all_body(&deliveries)
+        );
+    }
+}
@@ -0,0 +1,765 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use failure::format_err;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, signature};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::{Config, WebauthnConfig};
+use crate::db::{self, Database, ToSql};
+use crate::errors::*;
+
+// COSE algorithms of the keys octobot accepts
+pub const ES256: i64 = -7;
+pub const EDDSA: i64 = -8;
+
+const CHALLENGE_EXPIRY_SECS: u64 = 5 * 60;
+// pending registrations and logins: beyond that, the oldest are dropped
+const MAX_CHALLENGES: usize = 1000;
+const TIMEOUT_MILLIS: u64 = 60 * 1000;
+const MAX_CBOR_DEPTH: usize = 8;
+
+// flags of the authenticator data
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL: u8 = 0x40;
+
+pub const REGISTER_ACTION: &str = "register-security-key";
+pub const REMOVE_ACTION: &str = "remove-security-key";
+
+// WebAuthn encodes binary values as unpadded base64url
+pub fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn decode(data: &str) -> Result<Vec<u8>> {
+    base64::decode_config(data.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+        .map_err(|e| format_err!("Invalid base64url: {}", e))
+}
+
+// When admins may still log in without a security key (with a password, or kerberos)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fallback {
+    Always,
+    // only admins who haven't registered a key
+    Unregistered,
+    Never,
+}
+
+impl Fallback {
+    pub fn parse(value: Option<&str>) -> Option<Fallback> {
+        match value {
+            Some("always") => Some(Fallback::Always),
+            None | Some("unregistered") => Some(Fallback::Unregistered),
+            Some("never") => Some(Fallback::Never),
+            _ => None,
+        }
+    }
+}
+
+// Whether an admin may log in some other way than with a security key
+pub fn fallback_allowed(config: &Config, user: &str) -> Result<bool> {
+    let webauthn = match config.webauthn {
+        Some(ref w) => w,
+        None => return Ok(true),
+    };
+    match Fallback::parse(webauthn.password_fallback.as_ref().map(|f| f.as_str())) {
+        Some(Fallback::Always) => Ok(true),
+        Some(Fallback::Never) => Ok(false),
+        _ => Ok(config.webauthn_credentials.get_for_user(user)?.is_empty()),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cbor {
+    Int(i64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    // false, true, null and undefined
+    Simple(u8),
+}
+
+impl Cbor {
+    pub fn get(&self, key: &Cbor) -> Option<&Cbor> {
+        match *self {
+            Cbor::Map(ref entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            Cbor::Int(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Cbor::Bytes(ref b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+struct CborReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CborReader<'a> {
+    fn take(&mut self, len: u64) -> Result<&'a [u8]> {
+        if len > (self.data.len() - self.pos) as u64 {
+            return Err(format_err!("Truncated CBOR"));
+        }
+        let start = self.pos;
+        self.pos += len as usize;
+        Ok(&self.data[start..self.pos])
+    }
+
+    fn argument(&mut self, info: u8) -> Result<u64> {
+        let len = match info {
+            0..=23 => return Ok(info as u64),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return Err(format_err!("Unsupported CBOR length: {}", info)),
+        };
+        Ok(self.take(len)?.iter().fold(0, |n, b| (n << 8) | *b as u64))
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Cbor> {
+        if depth > MAX_CBOR_DEPTH {
+            return Err(format_err!("CBOR nested too deeply"));
+        }
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        if major == 7 {
+            return match info {
+                20..=23 => Ok(Cbor::Simple(info)),
+                _ => Err(format_err!("Unsupported CBOR value: {}", initial)),
+            };
+        }
+
+        let arg = self.argument(info)?;
+        match major {
+            0 if arg <= i64::max_value() as u64 => Ok(Cbor::Int(arg as i64)),
+            1 if arg <= i64::max_value() as u64 => Ok(Cbor::Int(-1 - arg as i64)),
+            2 => Ok(Cbor::Bytes(self.take(arg)?.to_vec())),
+            3 => {
+                let text = String::from_utf8(self.take(arg)?.to_vec()).map_err(|_| format_err!("Invalid CBOR text"))?;
+                Ok(Cbor::Text(text))
+            }
+            4 => {
+                let mut items = vec![];
+                for _ in 0..arg {
+                    items.push(self.value(depth + 1)?);
+                }
+                Ok(Cbor::Array(items))
+            }
+            5 => {
+                let mut entries = vec![];
+                for _ in 0..arg {
+                    let key = self.value(depth + 1)?;
+                    entries.push((key, self.value(depth + 1)?));
+                }
+                Ok(Cbor::Map(entries))
+            }
+            // tags only say how to interpret what follows
+            6 => self.value(depth + 1),
+            _ => Err(format_err!("CBOR integer out of range")),
+        }
+    }
+}
+
+// Decodes the value at the start of `data`, and says how many bytes it took
+pub fn decode_cbor(data: &[u8]) -> Result<(Cbor, usize)> {
+    let mut reader = CborReader { data: data, pos: 0 };
+    let value = reader.value(0)?;
+    Ok((value, reader.pos))
+}
+
+// A public key in COSE format, as the algorithm and the key as ring takes it
+fn cose_key(key: &Cbor) -> Result<(i64, Vec<u8>)> {
+    let int = |label: i64| key.get(&Cbor::Int(label)).and_then(|v| v.as_int());
+    let bytes = |label: i64| key.get(&Cbor::Int(label)).and_then(|v| v.as_bytes()).filter(|b| b.len() == 32);
+
+    match (int(1), int(3), int(-1)) {
+        // EC2 key on P-256: an uncompressed point
+        (Some(2), Some(ES256), Some(1)) => match (bytes(-2), bytes(-3)) {
+            (Some(x), Some(y)) => Ok((ES256, [&[4u8][..], x, y].concat())),
+            _ => Err(format_err!("Invalid P-256 key")),
+        },
+        // OKP key on Ed25519
+        (Some(1), Some(EDDSA), Some(6)) => match bytes(-2) {
+            Some(x) => Ok((EDDSA, x.to_vec())),
+            None => Err(format_err!("Invalid Ed25519 key")),
+        },
+        (_, alg, _) => Err(format_err!("Unsupported key algorithm: {:?}", alg)),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AttestedCredential {
+    pub id: Vec<u8>,
+    pub algorithm: i64,
+    pub public_key: Vec<u8>,
+}
+
+// What the authenticator signs: for which site, whether the user was there, and, when registering, the new key
+#[derive(Debug, PartialEq)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: Vec<u8>,
+    pub flags: u8,
+    pub sign_count: u32,
+    pub credential: Option<AttestedCredential>,
+}
+
+impl AuthenticatorData {
+    pub fn parse(data: &[u8]) -> Result<AuthenticatorData> {
+        if data.len() < 37 {
+            return Err(format_err!("Authenticator data is too short"));
+        }
+        let flags = data[32];
+        let sign_count = data[33..37].iter().fold(0, |n, b| (n << 8) | *b as u32);
+
+        let mut credential = None;
+        if flags & ATTESTED_CREDENTIAL != 0 {
+            // after a 16 byte AAGUID
+            let rest = &data[37..];
+            if rest.len() < 18 {
+                return Err(format_err!("Attested credential data is too short"));
+            }
+            let id_len = ((rest[16] as usize) << 8) | rest[17] as usize;
+            if rest.len() < 18 + id_len {
+                return Err(format_err!("Attested credential data is too short"));
+            }
+            let (key, _) = decode_cbor(&rest[18 + id_len..])?;
+            let (algorithm, public_key) = cose_key(&key)?;
+            credential = Some(AttestedCredential {
+                id: rest[18..18 + id_len].to_vec(),
+                algorithm: algorithm,
+                public_key: public_key,
+            });
+        }
+
+        Ok(AuthenticatorData {
+            rp_id_hash: data[..32].to_vec(),
+            flags: flags,
+            sign_count: sign_count,
+            credential: credential,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+// That the browser answered our challenge, on our site
+fn check_client_data(config: &WebauthnConfig, client_data_json: &[u8], kind: &str, challenge: &[u8]) -> Result<()> {
+    let client_data: ClientData =
+        serde_json::from_slice(client_data_json).map_err(|e| format_err!("Invalid client data: {}", e))?;
+    if client_data.kind != kind {
+        return Err(format_err!("Expected {} client data, not {}", kind, client_data.kind));
+    }
+    if decode(&client_data.challenge)? != challenge {
+        return Err(format_err!("Wrong challenge"));
+    }
+    if client_data.origin != config.origin.trim_end_matches('/') {
+        return Err(format_err!("Wrong origin: {}", client_data.origin));
+    }
+    Ok(())
+}
+
+fn check_auth_data(config: &WebauthnConfig, auth_data: &AuthenticatorData) -> Result<()> {
+    if auth_data.rp_id_hash != digest::digest(&digest::SHA256, config.rp_id.as_bytes()).as_ref() {
+        return Err(format_err!("The key was used for another site"));
+    }
+    if auth_data.flags & USER_PRESENT == 0 {
+        return Err(format_err!("The user wasn't present"));
+    }
+    if config.user_verification.unwrap_or(false) && auth_data.flags & USER_VERIFIED == 0 {
+        return Err(format_err!("The user wasn't verified"));
+    }
+    Ok(())
+}
+
+// The key a browser registered, after checking that it answered the challenge. Attestation statements aren't
+// checked: the key is trusted because an admin registered it.
+pub fn verify_registration(
+    config: &WebauthnConfig,
+    challenge: &[u8],
+    client_data_json: &[u8],
+    attestation_object: &[u8],
+) -> Result<AttestedCredential> {
+    check_client_data(config, client_data_json, "webauthn.create", challenge)?;
+
+    let (attestation, _) = decode_cbor(attestation_object)?;
+    let auth_data = attestation
+        .get(&Cbor::Text("authData".into()))
+        .and_then(|a| a.as_bytes())
+        .ok_or_else(|| format_err!("No authenticator data in attestation"))?;
+    let auth_data = AuthenticatorData::parse(auth_data)?;
+    check_auth_data(config, &auth_data)?;
+
+    auth_data.credential.ok_or_else(|| format_err!("No credential in attestation"))
+}
+
+// Checks a login with a registered key, and returns the key's new signature counter
+pub fn verify_assertion(
+    config: &WebauthnConfig,
+    challenge: &[u8],
+    credential: &Credential,
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+) -> Result<u32> {
+    check_client_data(config, client_data_json, "webauthn.get", challenge)?;
+    let auth_data = AuthenticatorData::parse(authenticator_data)?;
+    check_auth_data(config, &auth_data)?;
+
+    let algorithm: &dyn signature::VerificationAlgorithm = match credential.algorithm {
+        ES256 => &signature::ECDSA_P256_SHA256_ASN1,
+        EDDSA => &signature::ED25519,
+        alg => return Err(format_err!("Unsupported key algorithm: {}", alg)),
+    };
+    let public_key = decode(&credential.public_key)?;
+    let signed = [authenticator_data, digest::digest(&digest::SHA256, client_data_json).as_ref()].concat();
+    signature::verify(
+        algorithm,
+        untrusted::Input::from(&public_key[..]),
+        untrusted::Input::from(&signed[..]),
+        untrusted::Input::from(signature),
+    )
+    .map_err(|_| format_err!("Invalid signature"))?;
+
+    // keys that count their signatures never go back, unless they were cloned
+    if (auth_data.sign_count != 0 || credential.sign_count != 0) && auth_data.sign_count <= credential.sign_count {
+        return Err(format_err!(
+            "Signature counter went from {} to {}: the key may have been cloned",
+            credential.sign_count,
+            auth_data.sign_count
+        ));
+    }
+    Ok(auth_data.sign_count)
+}
+
+// Identifies the user to their key without giving their name away
+fn user_handle(user: &str) -> String {
+    encode(&digest::digest(&digest::SHA256, user.as_bytes()).as_ref()[..16])
+}
+
+fn user_verification(config: &WebauthnConfig) -> &'static str {
+    if config.user_verification.unwrap_or(false) {
+        "required"
+    } else {
+        "discouraged"
+    }
+}
+
+// For navigator.credentials.create(), with binary values in base64url
+pub fn registration_options(config: &WebauthnConfig, user: &str, challenge: &[u8], existing: &[Credential]) -> Value {
+    json!({
+        "challenge": encode(challenge),
+        "rp": {
+            "id": config.rp_id,
+            "name": "Octobot",
+        },
+        "user": {
+            "id": user_handle(user),
+            "name": user,
+            "displayName": user,
+        },
+        "pubKeyCredParams": [
+            {"type": "public-key", "alg": ES256},
+            {"type": "public-key", "alg": EDDSA},
+        ],
+        "excludeCredentials": existing.iter().map(|c| json!({"type": "public-key", "id": c.id})).collect::<Vec<_>>(),
+        "authenticatorSelection": {
+            "userVerification": user_verification(config),
+        },
+        "attestation": "none",
+        "timeout": TIMEOUT_MILLIS,
+    })
+}
+
+// For navigator.credentials.get(), with binary values in base64url
+pub fn login_options(config: &WebauthnConfig, challenge: &[u8], credentials: &[Credential]) -> Value {
+    json!({
+        "challenge": encode(challenge),
+        "rpId": config.rp_id,
+        "allowCredentials": credentials.iter().map(|c| json!({"type": "public-key", "id": c.id})).collect::<Vec<_>>(),
+        "userVerification": user_verification(config),
+        "timeout": TIMEOUT_MILLIS,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ceremony {
+    Register,
+    Login,
+}
+
+struct Challenge {
+    id: String,
+    user: String,
+    ceremony: Ceremony,
+    challenge: Vec<u8>,
+    created_at: Instant,
+}
+
+// Registrations and logins that were started, until they are finished or expire
+pub struct Challenges {
+    challenges: Mutex<Vec<Challenge>>,
+}
+
+fn random_bytes() -> Vec<u8> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes).expect("get random");
+    bytes.to_vec()
+}
+
+impl Challenges {
+    pub fn new() -> Challenges {
+        Challenges {
+            challenges: Mutex::new(vec![]),
+        }
+    }
+
+    // A new challenge for the user to sign, and its id
+    pub fn start(&self, user: &str, ceremony: Ceremony) -> (String, Vec<u8>) {
+        let challenge = Challenge {
+            id: encode(&random_bytes()),
+            user: user.into(),
+            ceremony: ceremony,
+            challenge: random_bytes(),
+            created_at: Instant::now(),
+        };
+        let started = (challenge.id.clone(), challenge.challenge.clone());
+
+        let mut challenges = self.challenges.lock().unwrap();
+        challenges.retain(|c| c.created_at.elapsed() < Duration::from_secs(CHALLENGE_EXPIRY_SECS));
+        if challenges.len() >= MAX_CHALLENGES {
+            challenges.remove(0);
+        }
+        challenges.push(challenge);
+        started
+    }
+
+    // The user and challenge of an id. Each can only be answered once.
+    pub fn take(&self, id: &str, ceremony: Ceremony) -> Option<(String, Vec<u8>)> {
+        let mut challenges = self.challenges.lock().unwrap();
+        let index = challenges.iter().position(|c| c.id == id && c.ceremony == ceremony)?;
+        let challenge = challenges.remove(index);
+        if challenge.created_at.elapsed() >= Duration::from_secs(CHALLENGE_EXPIRY_SECS) {
+            return None;
+        }
+        Some((challenge.user, challenge.challenge))
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Credential {
+    // base64url, as browsers show it
+    pub id: String,
+    pub user: String,
+    pub name: String,
+    #[serde(skip)]
+    pub algorithm: i64,
+    // base64url
+    #[serde(skip)]
+    pub public_key: String,
+    #[serde(skip)]
+    pub sign_count: u32,
+    pub created_at: i64,
+    pub last_used: i64,
+}
+
+// The security keys admins registered
+#[derive(Clone)]
+pub struct WebauthnCredentials {
+    db: Database,
+}
+
+impl WebauthnCredentials {
+    pub fn new(db: Database) -> WebauthnCredentials {
+        WebauthnCredentials { db: db }
+    }
+
+    pub fn add(&self, user: &str, name: &str, credential: &AttestedCredential) -> Result<Credential> {
+        let added = Credential {
+            id: encode(&credential.id),
+            user: user.into(),
+            name: name.into(),
+            algorithm: credential.algorithm,
+            public_key: encode(&credential.public_key),
+            sign_count: 0,
+            created_at: db::now(),
+            last_used: 0,
+        };
+
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO webauthn_credentials
+                 (id, "user", name, algorithm, public_key, sign_count, created_at, last_used)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"#,
+            &[
+                &added.id as &dyn ToSql,
+                &added.user,
+                &added.name,
+                &added.algorithm,
+                &added.public_key,
+                &added.sign_count,
+                &added.created_at,
+                &added.last_used,
+            ],
+        )
+        .map_err(|e| format_err!("Error adding security key of {}: {}", user, e))?;
+
+        Ok(added)
+    }
+
+    pub fn record_use(&self, id: &str, sign_count: u32) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "UPDATE webauthn_credentials SET sign_count = ?2, last_used = ?3 WHERE id = ?1",
+            &[&id as &dyn ToSql, &sign_count, &db::now()],
+        )
+        .map_err(|e| format_err!("Error recording use of security key {}: {}", id, e))?;
+
+        Ok(())
+    }
+
+    // Whether there was such a key
+    pub fn remove(&self, id: &str) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let count = conn
+            .execute("DELETE FROM webauthn_credentials WHERE id = ?1", &[&id])
+            .map_err(|e| format_err!("Error removing security key {}: {}", id, e))?;
+
+        Ok(count > 0)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<Credential>> {
+        Ok(self.get_all()?.into_iter().find(|c| c.id == id))
+    }
+
+    pub fn get_for_user(&self, user: &str) -> Result<Vec<Credential>> {
+        Ok(self.get_all()?.into_iter().filter(|c| c.user == user).collect())
+    }
+
+    pub fn get_all(&self) -> Result<Vec<Credential>> {
+        let conn = self.db.connect_read()?;
+        let mut stmt = conn.prepare(r#"SELECT * FROM webauthn_credentials ORDER BY "user", created_at"#)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query(db::NO_PARAMS)?;
+
+        let mut result = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            result.push(Credential {
+                id: cols.get(row, "id")?,
+                user: cols.get(row, "user")?,
+                name: cols.get(row, "name")?,
+                algorithm: cols.get(row, "algorithm")?,
+                public_key: cols.get(row, "public_key")?,
+                sign_count: cols.get(row, "sign_count")?,
+                created_at: cols.get(row, "created_at")?,
+                last_used: cols.get(row, "last_used")?,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::KeyPair;
+    use tempdir::TempDir;
+
+    fn config() -> WebauthnConfig {
+        WebauthnConfig {
+            rp_id: "octobot.company.com".into(),
+            origin: "https://octobot.company.com".into(),
+            password_fallback: None,
+            user_verification: None,
+        }
+    }
+
+    fn client_data(kind: &str, challenge: &[u8], origin: &str) -> Vec<u8> {
+        json!({"type": kind, "challenge": encode(challenge), "origin": origin}).to_string().into_bytes()
+    }
+
+    fn auth_data(rp_id: &str, flags: u8, sign_count: u32, attested: &[u8]) -> Vec<u8> {
+        let rp_id_hash = digest::digest(&digest::SHA256, rp_id.as_bytes());
+        [rp_id_hash.as_ref(), &[flags], &sign_count.to_be_bytes(), attested].concat()
+    }
+
+    // an Ed25519 COSE key: {1: 1, 3: -8, -1: 6, -2: <x>}
+    fn cose_ed25519(x: &[u8]) -> Vec<u8> {
+        [&[0xa4, 0x01, 0x01, 0x03, 0x27, 0x20, 0x06, 0x21, 0x58, 0x20][..], x].concat()
+    }
+
+    fn key_pair() -> signature::Ed25519KeyPair {
+        signature::Ed25519KeyPair::from_seed_unchecked(untrusted::Input::from(&[7u8; 32][..])).unwrap()
+    }
+
+    #[test]
+    fn test_decode_cbor() {
+        // {"fmt": "none", "n": [1, -2, h'0102'], "t": true}
+        let data = [
+            0xa3, 0x63, b'f', b'm', b't', 0x64, b'n', b'o', b'n', b'e', 0x61, b'n', 0x83, 0x01, 0x21, 0x42, 0x01, 0x02,
+            0x61, b't', 0xf5, 0xff,
+        ];
+        let (value, len) = decode_cbor(&data).unwrap();
+        assert_eq!(21, len);
+        assert_eq!(Some(&Cbor::Text("none".into())), value.get(&Cbor::Text("fmt".into())));
+        assert_eq!(
+            Some(&Cbor::Array(vec![Cbor::Int(1), Cbor::Int(-2), Cbor::Bytes(vec![1, 2])])),
+            value.get(&Cbor::Text("n".into()))
+        );
+        assert_eq!(Some(&Cbor::Simple(21)), value.get(&Cbor::Text("t".into())));
+
+        assert_eq!(Some(Cbor::Int(1000)), decode_cbor(&[0x19, 0x03, 0xe8]).ok().map(|v| v.0));
+        assert!(decode_cbor(&[0x5a, 0xff, 0xff, 0xff, 0xff, 0x00]).is_err());
+        assert!(decode_cbor(&[0x9f]).is_err());
+        assert!(decode_cbor(&[0x81; 20]).is_err());
+    }
+
+    #[test]
+    fn test_verify_registration() {
+        let challenge = b"the-challenge";
+        let key = key_pair();
+        let attested = [&[0u8; 16][..], &[0, 3], b"abc", &cose_ed25519(key.public_key().as_ref())[..]].concat();
+        // {"fmt": "none", "attStmt": {}, "authData": <auth data>}
+        let data = auth_data("octobot.company.com", USER_PRESENT | ATTESTED_CREDENTIAL, 0, &attested);
+        let attestation = [
+            &[0xa3, 0x63, b'f', b'm', b't', 0x64, b'n', b'o', b'n', b'e'][..],
+            &[0x67, b'a', b't', b't', b'S', b't', b'm', b't', 0xa0],
+            &[0x68, b'a', b'u', b't', b'h', b'D', b'a', b't', b'a', 0x58, data.len() as u8],
+            &data[..],
+        ]
+        .concat();
+
+        let client = client_data("webauthn.create", challenge, "https://octobot.company.com");
+        let credential = verify_registration(&config(), challenge, &client, &attestation).unwrap();
+        assert_eq!(
+            AttestedCredential {
+                id: b"abc".to_vec(),
+                algorithm: EDDSA,
+                public_key: key.public_key().as_ref().to_vec(),
+            },
+            credential
+        );
+
+        let client = client_data("webauthn.create", b"other", "https://octobot.company.com");
+        assert!(verify_registration(&config(), challenge, &client, &attestation).is_err());
+        let client = client_data("webauthn.create", challenge, "https://octobot.evil.com");
+        assert!(verify_registration(&config(), challenge, &client, &attestation).is_err());
+        let client = client_data("webauthn.get", challenge, "https://octobot.company.com");
+        assert!(verify_registration(&config(), challenge, &client, &attestation).is_err());
+    }
+
+    #[test]
+    fn test_verify_assertion() {
+        let challenge = b"the-challenge";
+        let key = key_pair();
+        let credential = Credential {
+            id: encode(b"abc"),
+            user: "alice".into(),
+            name: "yubikey".into(),
+            algorithm: EDDSA,
+            public_key: encode(key.public_key().as_ref()),
+            sign_count: 4,
+            created_at: 0,
+            last_used: 0,
+        };
+        let sign = |data: &[u8], client: &[u8]| {
+            let signed = [data, digest::digest(&digest::SHA256, client).as_ref()].concat();
+            key.sign(&signed).as_ref().to_vec()
+        };
+
+        let client = client_data("webauthn.get", challenge, "https://octobot.company.com");
+        let data = auth_data("octobot.company.com", USER_PRESENT, 5, &[]);
+        let sig = sign(&data, &client);
+        assert_eq!(5, verify_assertion(&config(), challenge, &credential, &client, &data, &sig).unwrap());
+
+        // signed by another key
+        let other = signature::Ed25519KeyPair::from_seed_unchecked(untrusted::Input::from(&[8u8; 32][..])).unwrap();
+        let bad_sig = other.sign(&[&data[..], digest::digest(&digest::SHA256, &client).as_ref()].concat());
+        assert!(verify_assertion(&config(), challenge, &credential, &client, &data, bad_sig.as_ref()).is_err());
+
+        // the counter went backwards
+        let data = auth_data("octobot.company.com", USER_PRESENT, 4, &[]);
+        let sig = sign(&data, &client);
+        assert!(verify_assertion(&config(), challenge, &credential, &client, &data, &sig).is_err());
+
+        // for another site
+        let data = auth_data("evil.com", USER_PRESENT, 6, &[]);
+        let sig = sign(&data, &client);
+        assert!(verify_assertion(&config(), challenge, &credential, &client, &data, &sig).is_err());
+
+        // user verification required
+        let mut uv_config = config();
+        uv_config.user_verification = Some(true);
+        let data = auth_data("octobot.company.com", USER_PRESENT, 6, &[]);
+        let sig = sign(&data, &client);
+        assert!(verify_assertion(&uv_config, challenge, &credential, &client, &data, &sig).is_err());
+        let data = auth_data("octobot.company.com", USER_PRESENT | USER_VERIFIED, 6, &[]);
+        let sig = sign(&data, &client);
+        assert_eq!(6, verify_assertion(&uv_config, challenge, &credential, &client, &data, &sig).unwrap());
+    }
+
+    #[test]
+    fn test_challenges() {
+        let challenges = Challenges::new();
+        let (id, challenge) = challenges.start("alice", Ceremony::Login);
+
+        assert_eq!(None, challenges.take(&id, Ceremony::Register));
+        assert_eq!(Some(("alice".to_string(), challenge)), challenges.take(&id, Ceremony::Login));
+        assert_eq!(None, challenges.take(&id, Ceremony::Login));
+    }
+
+    #[test]
+    fn test_fallback_parse() {
+        assert_eq!(Some(Fallback::Unregistered), Fallback::parse(None));
+        assert_eq!(Some(Fallback::Always), Fallback::parse(Some("always")));
+        assert_eq!(Some(Fallback::Never), Fallback::parse(Some("never")));
+        assert_eq!(None, Fallback::parse(Some("sometimes")));
+    }
+
+    #[test]
+    fn test_credentials() {
+        let temp_dir = TempDir::new("webauthn.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let credentials = WebauthnCredentials::new(Database::new(&db_file.to_string_lossy()).unwrap());
+
+        let attested = AttestedCredential {
+            id: b"abc".to_vec(),
+            algorithm: ES256,
+            public_key: vec![4; 65],
+        };
+        let added = credentials.add("alice", "yubikey", &attested).unwrap();
+        assert_eq!("YWJj", added.id);
+        assert_eq!(vec![added.clone()], credentials.get_for_user("alice").unwrap());
+        assert!(credentials.get_for_user("bob").unwrap().is_empty());
+
+        credentials.record_use("YWJj", 12).unwrap();
+        assert_eq!(12, credentials.get("YWJj").unwrap().unwrap().sign_count);
+
+        assert!(credentials.remove("YWJj").unwrap());
+        assert!(!credentials.remove("YWJj").unwrap());
+        assert_eq!(None, credentials.get("YWJj").unwrap());
+    }
+}
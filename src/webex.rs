@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use log::{error, info};
+use reqwest;
+use serde_derive::Serialize;
+
+use crate::config::WebexConfig;
+use crate::deliveries::{self, Deliveries};
+use crate::discord;
+use crate::errors::*;
+use crate::http_client::HTTPClient;
+use crate::slack::{SlackAttachment, SlackRequest};
+use crate::util;
+use crate::worker;
+
+// Messenger channels starting with this go to webex, e.g. "webex:releases" for a room named in the config,
+// or "webex:<room ID>"
+pub const CHANNEL_PREFIX: &str = "webex:";
+
+const DEFAULT_API_URL: &str = "https://webexapis.com/v1";
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebexMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_person_email: Option<String>,
+    pub markdown: String,
+}
+
+// Where a request goes
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    Room(String),
+    // a direct message to the person with this email
+    Person(String),
+}
+
+// The webex room or person of a messenger channel like "webex:releases" or "webex:@joe@company.com".
+// |rooms| maps names to room IDs: other names are taken to be room IDs.
+pub fn target(channel: &str, rooms: &HashMap<String, String>) -> Option<Target> {
+    if !channel.starts_with(CHANNEL_PREFIX) {
+        return None;
+    }
+
+    let name = channel[CHANNEL_PREFIX.len()..].trim();
+    if name.is_empty() {
+        None
+    } else if name.starts_with('@') {
+        Some(Target::Person(name[1..].to_string()))
+    } else {
+        let name = name.trim_start_matches('#');
+        Some(Target::Room(rooms.get(name).cloned().unwrap_or(name.to_string())))
+    }
+}
+
+fn attachment_markdown(attachment: &SlackAttachment) -> String {
+    let mut lines = vec![];
+    if let Some(ref title) = attachment.title {
+        match attachment.title_link {
+            Some(ref url) => lines.push(format!("**[{}]({})**", discord::from_slack_markup(title), url)),
+            None => lines.push(format!("**{}**", discord::from_slack_markup(title))),
+        };
+    }
+    lines.extend(attachment.text.lines().filter(|l| !l.trim().is_empty()).map(discord::from_slack_markup));
+    for field in &attachment.fields {
+        lines.push(format!("**{}**: {}", field.title, discord::from_slack_markup(&field.value)));
+    }
+    lines.iter().map(|l| format!("> {}", l)).collect::<Vec<_>>().join("\n")
+}
+
+pub fn to_message(target: &Target, req: &SlackRequest) -> WebexMessage {
+    let mut markdown = vec![discord::from_slack_markup(&req.msg)];
+    markdown.extend(req.attachments.iter().map(attachment_markdown));
+
+    let (room_id, to_person_email) = match *target {
+        Target::Room(ref id) => (Some(id.clone()), None),
+        Target::Person(ref email) => (None, Some(email.clone())),
+    };
+    WebexMessage {
+        room_id: room_id,
+        to_person_email: to_person_email,
+        markdown: markdown.join("\n\n"),
+    }
+}
+
+// the main object for sending messages to webex
+struct Webex {
+    client: HTTPClient,
+    rooms: HashMap<String, String>,
+    recent_messages: Mutex<Vec<WebexMessage>>,
+}
+
+const TRIM_MESSAGES_AT: usize = 200;
+const TRIM_MESSAGES_TO: usize = 20;
+
+impl Webex {
+    fn new(config: &WebexConfig) -> Result<Webex> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {}", config.bot_token).parse().unwrap(),
+        );
+
+        let api_url = config.api_url.clone().unwrap_or(DEFAULT_API_URL.into());
+        Ok(Webex {
+            client: HTTPClient::new_with_headers(&api_url, headers)?,
+            rooms: config.rooms.clone(),
+            recent_messages: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn send(&self, target: &Target, req: &SlackRequest) -> Result<()> {
+        let message = to_message(target, req);
+        if !self.is_unique(&message) {
+            info!("Skipping duplicate message to {}", req.channel);
+            return Ok(());
+        }
+
+        info!("Sending webex message to {}", req.channel);
+        self.client.post_void("/messages", &message)
+    }
+
+    fn is_unique(&self, message: &WebexMessage) -> bool {
+        let mut recent_messages = self.recent_messages.lock().unwrap();
+        util::check_unique_event(message.clone(), &mut *recent_messages, TRIM_MESSAGES_AT, TRIM_MESSAGES_TO)
+    }
+}
+
+struct Runner {
+    webex: Arc<Webex>,
+    fallback: Arc<dyn worker::Runner<SlackRequest>>,
+    deliveries: Deliveries,
+}
+
+// Sends messenger requests for "webex:" channels to webex, and all others to |fallback|
+pub fn new_runner(
+    config: &WebexConfig,
+    fallback: Arc<dyn worker::Runner<SlackRequest>>,
+    deliveries: Deliveries,
+) -> Result<Arc<dyn worker::Runner<SlackRequest>>> {
+    Ok(Arc::new(Runner {
+        webex: Arc::new(Webex::new(config)?),
+        fallback: fallback,
+        deliveries: deliveries,
+    }))
+}
+
+impl worker::Runner<SlackRequest> for Runner {
+    fn handle(&self, req: SlackRequest) {
+        let target = match target(&req.channel, &self.webex.rooms) {
+            Some(t) => t,
+            None => return self.fallback.handle(req),
+        };
+
+        if let Err(e) = self.deliveries.track(deliveries::WEBEX, &req.channel, || self.webex.send(&target, &req)) {
+            error!("Error sending webex message to {}: {}", req.channel, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    use crate::db::Database;
+    use crate::slack::{self, SlackAttachmentBuilder};
+
+    #[test]
+    fn test_target() {
+        let mut rooms = HashMap::new();
+        rooms.insert("releases".to_string(), "Y2lzY29zcGFyazovL3JlbGVhc2Vz".to_string());
+
+        assert_eq!(Some(Target::Room("Y2lzY29zcGFyazovL3JlbGVhc2Vz".into())), target("webex:releases", &rooms));
+        assert_eq!(Some(Target::Room("Y2lzY29zcGFyazovL3JlbGVhc2Vz".into())), target("webex:#releases", &rooms));
+        assert_eq!(
+            Some(Target::Room("Y2lzY29zcGFyazovL290aGVy".into())),
+            target("webex:Y2lzY29zcGFyazovL290aGVy", &rooms)
+        );
+        assert_eq!(Some(Target::Person("joe@company.com".into())), target("webex:@joe@company.com", &rooms));
+        assert_eq!(None, target("webex:", &rooms));
+        assert_eq!(None, target("releases", &rooms));
+    }
+
+    #[test]
+    fn test_to_message() {
+        let attach = SlackAttachmentBuilder::new("some <http://the-commit|commit>")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .field("Reviewers", "joe.reviewer")
+            .build();
+        let req = slack::req("webex:releases", "Pull Request opened", vec![attach]);
+
+        assert_eq!(
+            WebexMessage {
+                room_id: Some("the-room".into()),
+                to_person_email: None,
+                markdown: "Pull Request opened\n\n\
+                           > **[Pull Request #32: \"The PR\"](http://the-pr)**\n\
+                           > some [commit](http://the-commit)\n\
+                           > **Reviewers**: joe.reviewer"
+                    .into(),
+            },
+            to_message(&Target::Room("the-room".into()), &req)
+        );
+
+        let message = to_message(&Target::Person("joe@company.com".into()), &slack::req("webex:@joe", "Hi", vec![]));
+        assert_eq!(None, message.room_id);
+        assert_eq!(Some("joe@company.com".into()), message.to_person_email);
+        assert_eq!("Hi", message.markdown);
+    }
+
+    struct CountingRunner {
+        handled: Mutex<Vec<String>>,
+    }
+
+    impl worker::Runner<SlackRequest> for CountingRunner {
+        fn handle(&self, req: SlackRequest) {
+            self.handled.lock().unwrap().push(req.channel);
+        }
+    }
+
+    #[test]
+    fn test_other_channels_fall_back() {
+        let config = WebexConfig {
+            bot_token: "the-token".into(),
+            api_url: None,
+            rooms: HashMap::new(),
+        };
+
+        let fallback = Arc::new(CountingRunner {
+            handled: Mutex::new(vec![]),
+        });
+        let temp_dir = TempDir::new("webex.rs").unwrap();
+        let db = Database::new(&temp_dir.path().join("db.sqlite3").to_string_lossy()).expect("create temp database");
+        let runner = new_runner(&config, fallback.clone(), Deliveries::new(db)).unwrap();
+        runner.handle(slack::req("reviews", "Pull Request opened", vec![]));
+        runner.handle(slack::req("@joe", "Pull Request merged", vec![]));
+        assert_eq!(vec!["reviews", "@joe"], *fallback.handled.lock().unwrap());
+    }
+}
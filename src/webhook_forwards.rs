@@ -0,0 +1,451 @@
+use std::sync::Arc;
+
+use failure::format_err;
+use log::{error, info, warn};
+use reqwest;
+use ring::{digest, hmac};
+use rustc_serialize::hex::ToHex;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::ForwardConfig;
+use crate::config_reload::LiveConfig;
+use crate::db::{self, Database, Row, ToSql};
+use crate::errors::*;
+use crate::network;
+use crate::outbound_webhooks;
+use crate::scheduler;
+use crate::webhook_retries::{backoff, DEAD, PENDING};
+use crate::worker;
+
+pub const CHECK_INTERVAL_SECS: u64 = 30;
+pub const MAX_ATTEMPTS: u32 = 8;
+const BACKOFF_SECS: i64 = 30;
+// retried per check, so that a target that was down for a while isn't flooded
+const MAX_DUE: u32 = 50;
+
+// A delivery octobot handled, to forward to the downstream services as github sent it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ForwardRequest {
+    pub delivery_id: String,
+    pub event: String,
+    pub action: String,
+    // "owner/name"
+    pub repo: String,
+    pub body: String,
+}
+
+// A forward to one target that failed, and is either waiting to be retried or dead-lettered
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct FailedForward {
+    pub delivery_id: String,
+    pub url: String,
+    pub event: String,
+    pub repo: String,
+    #[serde(skip)]
+    pub request: ForwardRequest,
+    pub attempts: u32,
+    // when it is retried next, if it is pending
+    pub next_attempt: i64,
+    pub last_error: String,
+    // "pending" or "dead"
+    pub state: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+// Whether the target gets the delivery: `events` are github's event names, or "<event>.<action>" for one action of
+// it, and `repos` are "owner/repo" or "owner" for all of an org's repos. Either defaults to all of them.
+pub fn wants(forward: &ForwardConfig, event: &str, action: &str, repo: &str) -> bool {
+    let org = repo.split('/').next().unwrap_or("");
+    let event_action = format!("{}.{}", event, action);
+    let wants_event = match forward.events {
+        Some(ref events) if !events.is_empty() => events.iter().any(|e| e == event || e == &event_action),
+        _ => true,
+    };
+    let wants_repo = match forward.repos {
+        Some(ref repos) if !repos.is_empty() => repos.iter().any(|r| r == repo || r == org),
+        _ => true,
+    };
+    wants_event && wants_repo
+}
+
+// "sha1=" followed by the hex HMAC-SHA1 of the body, like github's X-Hub-Signature
+pub fn sha1_signature(secret: &str, body: &[u8]) -> String {
+    let key = hmac::SigningKey::new(&digest::SHA1, secret.as_bytes());
+    format!("sha1={}", hmac::sign(&key, body).as_ref().to_hex())
+}
+
+// The headers github would have sent the target, signed with its secret rather than octobot's
+pub fn headers(forward: &ForwardConfig, req: &ForwardRequest) -> Vec<(&'static str, String)> {
+    let mut headers = vec![
+        ("Content-Type", "application/json".to_string()),
+        ("X-GitHub-Event", req.event.clone()),
+        ("X-GitHub-Delivery", req.delivery_id.clone()),
+    ];
+    if let Some(ref secret) = forward.secret {
+        headers.push(("X-Hub-Signature", sha1_signature(secret, req.body.as_bytes())));
+        headers.push(("X-Hub-Signature-256", outbound_webhooks::signature(secret, req.body.as_bytes())));
+    }
+    headers
+}
+
+pub fn deliver(client: &reqwest::Client, forward: &ForwardConfig, req: &ForwardRequest) -> Result<()> {
+    let mut builder = client.post(&forward.url);
+    for (name, value) in headers(forward, req) {
+        builder = builder.header(name, value);
+    }
+    builder
+        .body(req.body.clone())
+        .send()
+        .and_then(|r| r.error_for_status())
+        .map(|_| ())
+        .map_err(|e| format_err!("{}", e))
+}
+
+pub struct WebhookForwards {
+    db: Database,
+    max_attempts: u32,
+    backoff_secs: i64,
+}
+
+impl WebhookForwards {
+    pub fn new(db: Database) -> WebhookForwards {
+        WebhookForwards {
+            db: db,
+            max_attempts: MAX_ATTEMPTS,
+            backoff_secs: BACKOFF_SECS,
+        }
+    }
+
+    pub fn with_policy(mut self, max_attempts: u32, backoff_secs: i64) -> WebhookForwards {
+        self.max_attempts = max_attempts;
+        self.backoff_secs = backoff_secs;
+        self
+    }
+
+    // Records a failed attempt at forwarding the delivery to `url`: it is retried after a backoff, or dead-lettered
+    // once it has failed `max_attempts` times.
+    pub fn failed(&self, req: &ForwardRequest, url: &str, error: &str, now: i64) -> Result<FailedForward> {
+        let mut forward = match self.get(&req.delivery_id, url)? {
+            Some(f) => f,
+            None => FailedForward {
+                delivery_id: req.delivery_id.clone(),
+                url: url.to_string(),
+                event: req.event.clone(),
+                repo: req.repo.clone(),
+                request: req.clone(),
+                attempts: 0,
+                next_attempt: 0,
+                last_error: String::new(),
+                state: PENDING.into(),
+                created_at: now,
+                updated_at: now,
+            },
+        };
+
+        forward.attempts += 1;
+        forward.last_error = error.to_string();
+        forward.updated_at = now;
+        if forward.attempts >= self.max_attempts {
+            forward.state = DEAD.into();
+            forward.next_attempt = 0;
+        } else {
+            forward.state = PENDING.into();
+            forward.next_attempt = now + backoff(forward.attempts, self.backoff_secs);
+        }
+
+        let request = serde_json::to_string(&forward.request)?;
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO webhook_forwards
+                 (delivery_id, url, event, repo, request, attempts, next_attempt, last_error, state, created_at,
+                  updated_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+               ON CONFLICT (delivery_id, url) DO UPDATE SET event = excluded.event, repo = excluded.repo,
+                   request = excluded.request, attempts = excluded.attempts, next_attempt = excluded.next_attempt,
+                   last_error = excluded.last_error, state = excluded.state, created_at = excluded.created_at,
+                   updated_at = excluded.updated_at"#,
+            &[
+                &forward.delivery_id as &dyn ToSql,
+                &forward.url,
+                &forward.event,
+                &forward.repo,
+                &request,
+                &forward.attempts,
+                &forward.next_attempt,
+                &forward.last_error,
+                &forward.state,
+                &forward.created_at,
+                &forward.updated_at,
+            ],
+        )
+        .map_err(|e| format_err!("Error recording failed forward of {} to {}: {}", req.delivery_id, url, e))?;
+
+        Ok(forward)
+    }
+
+    // Dead-letters the forward without retrying it again, e.g. once its target is no longer configured
+    pub fn give_up(&self, delivery_id: &str, url: &str, error: &str, now: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "UPDATE webhook_forwards SET state = ?1, next_attempt = 0, last_error = ?2, updated_at = ?3
+             WHERE delivery_id = ?4 AND url = ?5",
+            &[&DEAD as &dyn ToSql, &error, &now, &delivery_id, &url],
+        )
+        .map_err(|e| format_err!("Error dead-lettering forward of {} to {}: {}", delivery_id, url, e))?;
+        Ok(())
+    }
+
+    // Forgets the forward, once it went through. Returns whether there was one.
+    pub fn remove(&self, delivery_id: &str, url: &str) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let count = conn
+            .execute(
+                "DELETE FROM webhook_forwards WHERE delivery_id = ?1 AND url = ?2",
+                &[&delivery_id as &dyn ToSql, &url],
+            )
+            .map_err(|e| format_err!("Error removing forward of {} to {}: {}", delivery_id, url, e))?;
+        Ok(count > 0)
+    }
+
+    pub fn get(&self, delivery_id: &str, url: &str) -> Result<Option<FailedForward>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare("SELECT * FROM webhook_forwards WHERE delivery_id = :id AND url = :url")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &delivery_id), (":url", &url)])?;
+
+        if let Ok(Some(row)) = rows.next() {
+            Ok(Some(self.map_row(row, &cols)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Pending forwards whose next attempt is due, oldest first
+    pub fn due(&self, now: i64) -> Result<Vec<FailedForward>> {
+        self.query(
+            "SELECT * FROM webhook_forwards WHERE state = :state AND next_attempt <= :now
+             ORDER BY next_attempt, created_at LIMIT :limit",
+            &[(":state", &PENDING), (":now", &now), (":limit", &MAX_DUE)],
+        )
+    }
+
+    // All of them, or those in |state|, newest first
+    pub fn list(&self, state: Option<&str>) -> Result<Vec<FailedForward>> {
+        match state {
+            Some(state) => self.query(
+                "SELECT * FROM webhook_forwards WHERE state = :state ORDER BY created_at DESC",
+                &[(":state", &state)],
+            ),
+            None => self.query("SELECT * FROM webhook_forwards ORDER BY created_at DESC", &[]),
+        }
+    }
+
+    fn query(&self, sql: &str, params: &[(&str, &dyn ToSql)]) -> Result<Vec<FailedForward>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare(sql)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(params)?;
+
+        let mut forwards = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            forwards.push(self.map_row(row, &cols)?);
+        }
+        Ok(forwards)
+    }
+
+    fn map_row(&self, row: &Row, cols: &db::Columns) -> Result<FailedForward> {
+        let request: String = cols.get(row, "request")?;
+        Ok(FailedForward {
+            delivery_id: cols.get(row, "delivery_id")?,
+            url: cols.get(row, "url")?,
+            event: cols.get(row, "event")?,
+            repo: cols.get(row, "repo")?,
+            request: serde_json::from_str(&request).map_err(|e| format_err!("Invalid stored forward: {}", e))?,
+            attempts: cols.get(row, "attempts")?,
+            next_attempt: cols.get(row, "next_attempt")?,
+            last_error: cols.get(row, "last_error")?,
+            state: cols.get(row, "state")?,
+            created_at: cols.get(row, "created_at")?,
+            updated_at: cols.get(row, "updated_at")?,
+        })
+    }
+}
+
+struct Runner {
+    live_config: Arc<LiveConfig>,
+    client: reqwest::Client,
+}
+
+pub fn new_runner(live_config: Arc<LiveConfig>) -> Arc<dyn worker::Runner<ForwardRequest>> {
+    Arc::new(Runner {
+        live_config: live_config,
+        client: network::client(),
+    })
+}
+
+impl worker::Runner<ForwardRequest> for Runner {
+    fn handle(&self, req: ForwardRequest) {
+        let config = self.live_config.get();
+        let targets = config.forwards().into_iter().filter(|f| wants(f, &req.event, &req.action, &req.repo));
+        for forward in targets {
+            match deliver(&self.client, &forward, &req) {
+                Ok(()) => info!("Forwarded delivery {} to {}", req.delivery_id, forward.url),
+                Err(e) => {
+                    warn!("Error forwarding delivery {} to {}: {}", req.delivery_id, forward.url, e);
+                    if let Err(e) = config.webhook_forwards.failed(&req, &forward.url, &e.to_string(), db::now()) {
+                        error!("{}", e);
+                    }
+                }
+            };
+        }
+    }
+
+    fn repo(&self, req: &ForwardRequest) -> Option<String> {
+        Some(req.repo.clone())
+    }
+}
+
+// Retries the failed forwards that are due
+pub struct ForwardRetrier {
+    live_config: Arc<LiveConfig>,
+    client: reqwest::Client,
+}
+
+impl ForwardRetrier {
+    pub fn new(live_config: Arc<LiveConfig>) -> Arc<dyn scheduler::Task> {
+        Arc::new(ForwardRetrier {
+            live_config: live_config,
+            client: network::client(),
+        })
+    }
+}
+
+impl scheduler::Task for ForwardRetrier {
+    fn run(&self, now: i64) -> Result<()> {
+        let config = self.live_config.get();
+        for failed in config.webhook_forwards.due(now)? {
+            let forward = match config.forwards().into_iter().find(|f| f.url == failed.url) {
+                Some(f) => f,
+                None => {
+                    config.webhook_forwards.give_up(&failed.delivery_id, &failed.url, "No longer forwarded", now)?;
+                    continue;
+                }
+            };
+            match deliver(&self.client, &forward, &failed.request) {
+                Ok(()) => {
+                    let attempt = failed.attempts + 1;
+                    info!("Forwarded delivery {} to {} (attempt {})", failed.delivery_id, failed.url, attempt);
+                    config.webhook_forwards.remove(&failed.delivery_id, &failed.url)?;
+                }
+                Err(e) => {
+                    let failed = config.webhook_forwards.failed(&failed.request, &failed.url, &e.to_string(), now)?;
+                    if failed.state == DEAD {
+                        error!("Gave up forwarding delivery {} to {}: {}", failed.delivery_id, failed.url, e);
+                    }
+                }
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn forward(events: Option<Vec<&str>>, repos: Option<Vec<&str>>) -> ForwardConfig {
+        ForwardConfig {
+            url: "http://the-service/hooks/github".into(),
+            secret: Some("the-secret".into()),
+            events: events.map(|e| e.into_iter().map(|s| s.to_string()).collect()),
+            repos: repos.map(|r| r.into_iter().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    fn request() -> ForwardRequest {
+        ForwardRequest {
+            delivery_id: "1234".into(),
+            event: "pull_request".into(),
+            action: "closed".into(),
+            repo: "some-org/some-repo".into(),
+            body: r#"{"action":"closed"}"#.into(),
+        }
+    }
+
+    #[test]
+    fn test_wants() {
+        let repo = "some-org/some-repo";
+        assert!(wants(&forward(None, None), "push", "", repo));
+        assert!(wants(&forward(Some(vec![]), Some(vec![])), "push", "", repo));
+        assert!(wants(&forward(Some(vec!["push", "pull_request"]), None), "pull_request", "opened", repo));
+        assert!(wants(&forward(Some(vec!["pull_request.closed"]), None), "pull_request", "closed", repo));
+        assert!(!wants(&forward(Some(vec!["pull_request.closed"]), None), "pull_request", "opened", repo));
+        assert!(!wants(&forward(Some(vec!["pull_request"]), None), "push", "", repo));
+
+        assert!(wants(&forward(None, Some(vec!["some-org"])), "push", "", repo));
+        assert!(wants(&forward(None, Some(vec!["other-org/x", "some-org/some-repo"])), "push", "", repo));
+        assert!(!wants(&forward(None, Some(vec!["some-org/other-repo"])), "push", "", repo));
+        assert!(!wants(&forward(Some(vec!["pull_request"]), Some(vec!["some-org"])), "push", "", repo));
+    }
+
+    #[test]
+    fn test_headers() {
+        let req = request();
+        let headers = headers(&forward(None, None), &req);
+        let header = |name: &str| headers.iter().find(|h| h.0 == name).map(|h| h.1.clone());
+
+        assert_eq!(Some("pull_request".to_string()), header("X-GitHub-Event"));
+        assert_eq!(Some("1234".to_string()), header("X-GitHub-Delivery"));
+        assert_eq!(Some(sha1_signature("the-secret", req.body.as_bytes())), header("X-Hub-Signature"));
+        assert_eq!(
+            Some(outbound_webhooks::signature("the-secret", req.body.as_bytes())),
+            header("X-Hub-Signature-256")
+        );
+
+        let sig = header("X-Hub-Signature").unwrap();
+        assert!(sig.starts_with("sha1="));
+        assert_eq!(5 + 40, sig.len());
+
+        let unsigned = ForwardConfig {
+            secret: None,
+            ..forward(None, None)
+        };
+        assert_eq!(3, super::headers(&unsigned, &req).len());
+    }
+
+    #[test]
+    fn test_failed_forwards() {
+        let temp_dir = TempDir::new("webhook_forwards.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+        let forwards = WebhookForwards::new(db).with_policy(2, 30);
+        let url = "http://the-service/hooks/github";
+
+        let failed = forwards.failed(&request(), url, "502", 1000).unwrap();
+        assert_eq!(1, failed.attempts);
+        assert_eq!(PENDING, failed.state);
+        assert_eq!(1030, failed.next_attempt);
+        assert_eq!(request(), failed.request);
+        assert_eq!(Some(failed.clone()), forwards.get("1234", url).unwrap());
+        assert!(forwards.due(1029).unwrap().is_empty());
+        assert_eq!(vec![failed], forwards.due(1030).unwrap());
+
+        // to another target, independently
+        forwards.failed(&request(), "http://another-service/hook", "503", 1000).unwrap();
+        assert_eq!(2, forwards.list(Some(PENDING)).unwrap().len());
+
+        let failed = forwards.failed(&request(), url, "504", 1030).unwrap();
+        assert_eq!(DEAD, failed.state);
+        assert_eq!("504", failed.last_error);
+        assert_eq!(vec![failed], forwards.list(Some(DEAD)).unwrap());
+
+        forwards.give_up("1234", "http://another-service/hook", "No longer forwarded", 1040).unwrap();
+        assert!(forwards.due(100000).unwrap().is_empty());
+
+        assert!(forwards.remove("1234", url).unwrap());
+        assert!(!forwards.remove("1234", url).unwrap());
+        assert_eq!(1, forwards.list(None).unwrap().len());
+    }
+}
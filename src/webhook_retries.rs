@@ -0,0 +1,302 @@
+use failure::format_err;
+use serde_derive::Serialize;
+
+use crate::db::{self, Database, Row, ToSql};
+use crate::errors::*;
+use crate::inbound_queue::Envelope;
+
+pub const PENDING: &str = "pending";
+pub const DEAD: &str = "dead";
+
+pub const CHECK_INTERVAL_SECS: u64 = 30;
+pub const MAX_ATTEMPTS: u32 = 5;
+pub const BACKOFF_SECS: i64 = 60;
+// however many attempts it has taken
+const MAX_BACKOFF_SECS: i64 = 6 * 60 * 60;
+// retried per check, so that a backlog after an outage is worked through gradually
+const MAX_DUE: u32 = 50;
+
+// A webhook delivery that couldn't be handled, and is either waiting to be retried or dead-lettered
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct FailedDelivery {
+    pub delivery_id: String,
+    pub event: String,
+    pub repo: String,
+    // the delivery as github sent it, to handle it again
+    #[serde(skip)]
+    pub envelope: Envelope,
+    pub attempts: u32,
+    // when it is retried next, if it is pending
+    pub next_attempt: i64,
+    pub last_error: String,
+    // "pending" or "dead"
+    pub state: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+// The delay before the next attempt, which doubles with each failed one
+pub fn backoff(attempts: u32, base_secs: i64) -> i64 {
+    let doublings = attempts.saturating_sub(1).min(20);
+    std::cmp::min(base_secs.saturating_mul(1 << doublings), MAX_BACKOFF_SECS)
+}
+
+pub struct WebhookRetries {
+    db: Database,
+    max_attempts: u32,
+    backoff_secs: i64,
+}
+
+impl WebhookRetries {
+    pub fn new(db: Database) -> WebhookRetries {
+        WebhookRetries {
+            db: db,
+            max_attempts: MAX_ATTEMPTS,
+            backoff_secs: BACKOFF_SECS,
+        }
+    }
+
+    pub fn with_policy(mut self, max_attempts: u32, backoff_secs: i64) -> WebhookRetries {
+        self.max_attempts = max_attempts;
+        self.backoff_secs = backoff_secs;
+        self
+    }
+
+    // Records a failed attempt at handling the delivery: it is retried after a backoff, or dead-lettered once it
+    // has failed `max_attempts` times.
+    pub fn failed(
+        &self,
+        delivery_id: &str,
+        event: &str,
+        repo: &str,
+        envelope: &Envelope,
+        error: &str,
+        now: i64,
+    ) -> Result<FailedDelivery> {
+        let mut delivery = match self.get(delivery_id)? {
+            Some(d) => d,
+            None => FailedDelivery {
+                delivery_id: delivery_id.to_string(),
+                event: event.to_string(),
+                repo: repo.to_string(),
+                envelope: envelope.clone(),
+                attempts: 0,
+                next_attempt: 0,
+                last_error: String::new(),
+                state: PENDING.into(),
+                created_at: now,
+                updated_at: now,
+            },
+        };
+
+        delivery.attempts += 1;
+        delivery.last_error = error.to_string();
+        delivery.updated_at = now;
+        if delivery.attempts >= self.max_attempts {
+            delivery.state = DEAD.into();
+            delivery.next_attempt = 0;
+        } else {
+            delivery.state = PENDING.into();
+            delivery.next_attempt = now + backoff(delivery.attempts, self.backoff_secs);
+        }
+
+        let envelope = serde_json::to_string(&delivery.envelope)?;
+        let conn = self.db.connect()?;
+        conn.execute(
+            r#"INSERT INTO webhook_retries
+                 (delivery_id, event, repo, envelope, attempts, next_attempt, last_error, state, created_at, updated_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+               ON CONFLICT (delivery_id) DO UPDATE SET event = excluded.event, repo = excluded.repo,
+                   envelope = excluded.envelope, attempts = excluded.attempts,
+                   next_attempt = excluded.next_attempt, last_error = excluded.last_error, state = excluded.state,
+                   created_at = excluded.created_at, updated_at = excluded.updated_at"#,
+            &[
+                &delivery.delivery_id as &dyn ToSql,
+                &delivery.event,
+                &delivery.repo,
+                &envelope,
+                &delivery.attempts,
+                &delivery.next_attempt,
+                &delivery.last_error,
+                &delivery.state,
+                &delivery.created_at,
+                &delivery.updated_at,
+            ],
+        )
+        .map_err(|e| format_err!("Error recording failed delivery {}: {}", delivery_id, e))?;
+
+        Ok(delivery)
+    }
+
+    // Dead-letters the delivery without retrying it again, e.g. after it was refused for a bad signature
+    pub fn give_up(&self, delivery_id: &str, error: &str, now: i64) -> Result<()> {
+        let conn = self.db.connect()?;
+        conn.execute(
+            "UPDATE webhook_retries SET state = ?1, next_attempt = 0, last_error = ?2, updated_at = ?3
+             WHERE delivery_id = ?4",
+            &[&DEAD as &dyn ToSql, &error, &now, &delivery_id],
+        )
+        .map_err(|e| format_err!("Error dead-lettering delivery {}: {}", delivery_id, e))?;
+        Ok(())
+    }
+
+    // Makes the delivery due now, dead-lettered or not, so that the retry runner handles it on its next check.
+    // Returns whether there was one.
+    pub fn retry_now(&self, delivery_id: &str, now: i64) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let count = conn
+            .execute(
+                "UPDATE webhook_retries SET state = ?1, next_attempt = ?2, updated_at = ?2 WHERE delivery_id = ?3",
+                &[&PENDING as &dyn ToSql, &now, &delivery_id],
+            )
+            .map_err(|e| format_err!("Error scheduling delivery {}: {}", delivery_id, e))?;
+        Ok(count > 0)
+    }
+
+    // Forgets the delivery, once it was handled or an admin gave up on it. Returns whether there was one.
+    pub fn remove(&self, delivery_id: &str) -> Result<bool> {
+        let conn = self.db.connect()?;
+        let count = conn
+            .execute("DELETE FROM webhook_retries WHERE delivery_id = ?1", &[&delivery_id])
+            .map_err(|e| format_err!("Error removing failed delivery {}: {}", delivery_id, e))?;
+        Ok(count > 0)
+    }
+
+    pub fn get(&self, delivery_id: &str) -> Result<Option<FailedDelivery>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare("SELECT * FROM webhook_retries WHERE delivery_id = :id")?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(&[(":id", &delivery_id)])?;
+
+        if let Ok(Some(row)) = rows.next() {
+            Ok(Some(self.map_row(row, &cols)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Pending deliveries whose next attempt is due, oldest first
+    pub fn due(&self, now: i64) -> Result<Vec<FailedDelivery>> {
+        self.query(
+            "SELECT * FROM webhook_retries WHERE state = :state AND next_attempt <= :now
+             ORDER BY next_attempt, created_at LIMIT :limit",
+            &[(":state", &PENDING), (":now", &now), (":limit", &MAX_DUE)],
+        )
+    }
+
+    // All of them, or those in |state|, newest first
+    pub fn list(&self, state: Option<&str>) -> Result<Vec<FailedDelivery>> {
+        match state {
+            Some(state) => self.query(
+                "SELECT * FROM webhook_retries WHERE state = :state ORDER BY created_at DESC",
+                &[(":state", &state)],
+            ),
+            None => self.query("SELECT * FROM webhook_retries ORDER BY created_at DESC", &[]),
+        }
+    }
+
+    fn query(&self, sql: &str, params: &[(&str, &dyn ToSql)]) -> Result<Vec<FailedDelivery>> {
+        let conn = self.db.connect()?;
+        let mut stmt = conn.prepare(sql)?;
+        let cols = db::Columns::from_stmt(&stmt)?;
+        let mut rows = stmt.query_named(params)?;
+
+        let mut deliveries = vec![];
+        while let Ok(Some(row)) = rows.next() {
+            deliveries.push(self.map_row(row, &cols)?);
+        }
+        Ok(deliveries)
+    }
+
+    fn map_row(&self, row: &Row, cols: &db::Columns) -> Result<FailedDelivery> {
+        let envelope: String = cols.get(row, "envelope")?;
+        Ok(FailedDelivery {
+            delivery_id: cols.get(row, "delivery_id")?,
+            event: cols.get(row, "event")?,
+            repo: cols.get(row, "repo")?,
+            envelope: Envelope::parse(envelope.as_bytes())?,
+            attempts: cols.get(row, "attempts")?,
+            next_attempt: cols.get(row, "next_attempt")?,
+            last_error: cols.get(row, "last_error")?,
+            state: cols.get(row, "state")?,
+            created_at: cols.get(row, "created_at")?,
+            updated_at: cols.get(row, "updated_at")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashmap;
+    use tempdir::TempDir;
+
+    fn new_test() -> (WebhookRetries, TempDir) {
+        let temp_dir = TempDir::new("webhook_retries.rs").unwrap();
+        let db_file = temp_dir.path().join("db.sqlite3");
+        let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+        (WebhookRetries::new(db).with_policy(3, 60), temp_dir)
+    }
+
+    fn envelope() -> Envelope {
+        Envelope {
+            headers: hashmap! {
+                "X-GitHub-Event".to_string() => "push".to_string(),
+                "X-GitHub-Delivery".to_string() => "1234".to_string(),
+            },
+            body: "{}".into(),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(60, backoff(1, 60));
+        assert_eq!(120, backoff(2, 60));
+        assert_eq!(480, backoff(4, 60));
+        assert_eq!(MAX_BACKOFF_SECS, backoff(100, 60));
+    }
+
+    #[test]
+    fn test_retries() {
+        let (retries, _temp_dir) = new_test();
+
+        let delivery = retries.failed("1234", "push", "some-user/some-repo", &envelope(), "502", 1000).unwrap();
+        assert_eq!(1, delivery.attempts);
+        assert_eq!(PENDING, delivery.state);
+        assert_eq!(1060, delivery.next_attempt);
+        assert_eq!(Some(delivery.clone()), retries.get("1234").unwrap());
+
+        assert!(retries.due(1059).unwrap().is_empty());
+        assert_eq!(vec![delivery], retries.due(1060).unwrap());
+
+        let delivery = retries.failed("1234", "push", "some-user/some-repo", &envelope(), "503", 1060).unwrap();
+        assert_eq!(2, delivery.attempts);
+        assert_eq!(1180, delivery.next_attempt);
+        assert_eq!("503", delivery.last_error);
+        assert_eq!(1000, delivery.created_at);
+
+        // dead-lettered
+        let delivery = retries.failed("1234", "push", "some-user/some-repo", &envelope(), "504", 1180).unwrap();
+        assert_eq!(DEAD, delivery.state);
+        assert!(retries.due(100000).unwrap().is_empty());
+        assert_eq!(vec![delivery], retries.list(Some(DEAD)).unwrap());
+        assert!(retries.list(Some(PENDING)).unwrap().is_empty());
+        assert_eq!(1, retries.list(None).unwrap().len());
+
+        retries.failed("5678", "push", "some-user/some-repo", &envelope(), "502", 1000).unwrap();
+        retries.give_up("5678", "Invalid signature", 1060).unwrap();
+        let delivery = retries.get("5678").unwrap().unwrap();
+        assert_eq!(DEAD, delivery.state);
+        assert_eq!("Invalid signature", delivery.last_error);
+        assert_eq!(1, delivery.attempts);
+        assert!(retries.retry_now("5678", 2000).unwrap());
+        assert_eq!(vec!["5678"], retries.due(2000).unwrap().iter().map(|d| d.delivery_id.as_str()).collect::<Vec<_>>());
+        assert!(!retries.retry_now("unknown", 2000).unwrap());
+        assert!(retries.remove("5678").unwrap());
+
+        assert!(retries.remove("1234").unwrap());
+        assert!(!retries.remove("1234").unwrap());
+        assert_eq!(None, retries.get("1234").unwrap());
+    }
+}
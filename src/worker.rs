@@ -1,37 +1,506 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use futures::future;
+use log::error;
+use serde_derive::Serialize;
 use tokio;
 
+use crate::error_reports;
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+// How many jobs were started (e.g. sent to a worker) but haven't finished, so that a replica that is shutting
+// down can wait for them
+pub fn in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+// Counts as in flight until dropped
+pub struct InFlight;
+
+impl InFlight {
+    pub fn start() -> InFlight {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 pub trait Worker<T: Send + 'static>: Send + Sync {
     fn send(&self, req: T);
 }
 
 pub trait Runner<T: Send + 'static>: Send + Sync {
     fn handle(&self, req: T);
+
+    // The repo the job is about, so that a busy repo can't hold up the jobs of all the others
+    fn repo(&self, _req: &T) -> Option<String> {
+        None
+    }
+
+    fn priority(&self, _req: &T) -> Priority {
+        Priority::Normal
+    }
+}
+
+// Which jobs start first when more are waiting than can run
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    // someone is waiting for it, e.g. a slack action
+    Interactive,
+    // notifications
+    Normal,
+    // e.g. backports and release bookkeeping
+    Background,
+}
+
+pub const MAX_CONCURRENT_JOBS: usize = 20;
+pub const REPO_CONCURRENCY: usize = 4;
+
+struct Lane<J> {
+    // repos with waiting jobs, in the order they get their next turn
+    turns: VecDeque<Option<String>>,
+    jobs: HashMap<Option<String>, VecDeque<J>>,
+}
+
+impl<J> Lane<J> {
+    fn new() -> Lane<J> {
+        Lane {
+            turns: VecDeque::new(),
+            jobs: HashMap::new(),
+        }
+    }
+}
+
+// Waiting jobs, taken by priority and then round-robin across repos, with at most `repo_limit` running per repo.
+// Jobs without a repo are only limited by `max_running`.
+pub struct FairQueue<J> {
+    max_running: usize,
+    repo_limit: usize,
+    lanes: BTreeMap<Priority, Lane<J>>,
+    running: usize,
+    running_by_repo: HashMap<String, usize>,
+}
+
+impl<J> FairQueue<J> {
+    pub fn new(max_running: usize, repo_limit: usize) -> FairQueue<J> {
+        FairQueue {
+            max_running: max_running,
+            repo_limit: repo_limit,
+            lanes: BTreeMap::new(),
+            running: 0,
+            running_by_repo: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, priority: Priority, repo: Option<String>, job: J) {
+        let lane = self.lanes.entry(priority).or_insert_with(Lane::new);
+        let jobs = lane.jobs.entry(repo.clone()).or_insert_with(VecDeque::new);
+        if jobs.is_empty() {
+            lane.turns.push_back(repo);
+        }
+        jobs.push_back(job);
+    }
+
+    // The next job to start, if one can. It counts as running until `done`.
+    pub fn next(&mut self) -> Option<(Option<String>, J)> {
+        if self.running >= self.max_running {
+            return None;
+        }
+
+        for lane in self.lanes.values_mut() {
+            for _ in 0..lane.turns.len() {
+                let repo = lane.turns.pop_front()?;
+                let busy = match repo {
+                    Some(ref r) => self.running_by_repo.get(r).cloned().unwrap_or(0) >= self.repo_limit,
+                    None => false,
+                };
+                if busy {
+                    lane.turns.push_back(repo);
+                    continue;
+                }
+
+                let (job, more) = match lane.jobs.get_mut(&repo) {
+                    Some(jobs) => (jobs.pop_front(), !jobs.is_empty()),
+                    None => (None, false),
+                };
+                if more {
+                    lane.turns.push_back(repo.clone());
+                } else {
+                    lane.jobs.remove(&repo);
+                }
+                if let Some(job) = job {
+                    self.running += 1;
+                    if let Some(ref r) = repo {
+                        *self.running_by_repo.entry(r.clone()).or_insert(0) += 1;
+                    }
+                    return Some((repo, job));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn done(&mut self, repo: &Option<String>) {
+        self.running = self.running.saturating_sub(1);
+        if let Some(ref r) = *repo {
+            let count = self.running_by_repo.get(r).cloned().unwrap_or(0);
+            if count <= 1 {
+                self.running_by_repo.remove(r);
+            } else {
+                self.running_by_repo.insert(r.clone(), count - 1);
+            }
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Runs the jobs of all workers on a runtime, in the order a `FairQueue` gives them
+pub struct Dispatcher {
+    runtime: Arc<Mutex<tokio::runtime::Runtime>>,
+    queue: Mutex<FairQueue<Job>>,
+}
+
+// Frees the job's slot when it's done, even if it panicked
+struct Slot {
+    dispatcher: Arc<Dispatcher>,
+    repo: Option<String>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.dispatcher.queue.lock().unwrap().done(&self.repo);
+        self.dispatcher.pump();
+    }
+}
+
+impl Dispatcher {
+    pub fn new(
+        runtime: Arc<Mutex<tokio::runtime::Runtime>>,
+        max_running: usize,
+        repo_limit: usize,
+    ) -> Arc<Dispatcher> {
+        Arc::new(Dispatcher {
+            runtime: runtime,
+            queue: Mutex::new(FairQueue::new(max_running, repo_limit)),
+        })
+    }
+
+    pub fn submit(self: &Arc<Self>, priority: Priority, repo: Option<String>, job: Job) {
+        self.queue.lock().unwrap().push(priority, repo, job);
+        self.pump();
+    }
+
+    // Starts as many waiting jobs as can run
+    fn pump(self: &Arc<Self>) {
+        loop {
+            let next = self.queue.lock().unwrap().next();
+            let (repo, job) = match next {
+                Some(n) => n,
+                None => return,
+            };
+            let slot = Slot {
+                dispatcher: self.clone(),
+                repo: repo,
+            };
+            self.runtime.lock().unwrap().spawn(future::lazy(move || {
+                job();
+                drop(slot);
+                future::ok(())
+            }));
+        }
+    }
+}
+
+pub const QUEUE_CAPACITY: usize = 1000;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct QueueStats {
+    pub name: String,
+    pub capacity: usize,
+    // jobs waiting or running
+    pub depth: usize,
+    // how long the oldest of them has been waiting or running
+    pub oldest_age_ms: u64,
+    pub processed: u64,
+    // jobs refused because the queue was full
+    pub dropped: u64,
+    // from being queued to being done, in total and for the latest job
+    pub total_latency_ms: u64,
+    pub last_latency_ms: u64,
+}
+
+#[derive(Default)]
+struct QueueState {
+    next_id: u64,
+    // id => when it was queued, so that the first is the oldest
+    pending: BTreeMap<u64, Instant>,
+    processed: u64,
+    dropped: u64,
+    total_latency: Duration,
+    last_latency: Duration,
+}
+
+// The jobs of a worker, of which at most |capacity| can wait or run at a time
+pub struct WorkQueue {
+    name: String,
+    capacity: usize,
+    state: Mutex<QueueState>,
+}
+
+impl WorkQueue {
+    pub fn new(name: &str, capacity: usize) -> WorkQueue {
+        WorkQueue {
+            name: name.to_string(),
+            capacity: capacity,
+            state: Mutex::new(QueueState::default()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.state.lock().unwrap().pending.len() >= self.capacity
+    }
+
+    // Queues a job, unless the queue is full. Returns its id, to pass to `done`.
+    pub fn push(&self, now: Instant) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        if state.pending.len() >= self.capacity {
+            state.dropped += 1;
+            return None;
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.pending.insert(id, now);
+        Some(id)
+    }
+
+    pub fn done(&self, id: u64, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(queued) = state.pending.remove(&id) {
+            let latency = now.duration_since(queued);
+            state.processed += 1;
+            state.total_latency += latency;
+            state.last_latency = latency;
+        }
+    }
+
+    pub fn stats(&self, now: Instant) -> QueueStats {
+        let state = self.state.lock().unwrap();
+        QueueStats {
+            name: self.name.clone(),
+            capacity: self.capacity,
+            depth: state.pending.len(),
+            oldest_age_ms: state.pending.values().next().map(|t| millis(now.duration_since(*t))).unwrap_or(0),
+            processed: state.processed,
+            dropped: state.dropped,
+            total_latency_ms: millis(state.total_latency),
+            last_latency_ms: millis(state.last_latency),
+        }
+    }
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + d.subsec_millis() as u64
+}
+
+// All the worker queues, by name
+pub struct WorkQueues {
+    capacity: usize,
+    capacities: HashMap<String, usize>,
+    queues: Mutex<Vec<Arc<WorkQueue>>>,
+}
+
+impl WorkQueues {
+    // |capacities| overrides |capacity| for some queues
+    pub fn new(capacity: usize, capacities: HashMap<String, usize>) -> WorkQueues {
+        WorkQueues {
+            capacity: capacity,
+            capacities: capacities,
+            queues: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn queue(&self, name: &str) -> Arc<WorkQueue> {
+        let capacity = self.capacities.get(name).cloned().unwrap_or(self.capacity);
+        let queue = Arc::new(WorkQueue::new(name, capacity));
+        self.queues.lock().unwrap().push(queue.clone());
+        queue
+    }
+
+    // Names of the queues that can't take another job
+    pub fn full(&self) -> Vec<String> {
+        let queues = self.queues.lock().unwrap();
+        queues.iter().filter(|q| q.is_full()).map(|q| q.name().to_string()).collect()
+    }
+
+    pub fn stats(&self) -> Vec<QueueStats> {
+        let now = Instant::now();
+        self.queues.lock().unwrap().iter().map(|q| q.stats(now)).collect()
+    }
 }
 
 pub struct TokioWorker<T: Send + Sync + 'static> {
     runner: Arc<dyn Runner<T>>,
-    runtime: Arc<Mutex<tokio::runtime::Runtime>>,
+    dispatcher: Arc<Dispatcher>,
+    queue: Arc<WorkQueue>,
 }
 
 impl<T: Send + Sync + 'static> TokioWorker<T> {
-    pub fn new(runtime: Arc<Mutex<tokio::runtime::Runtime>>, runner: Arc<dyn Runner<T>>) -> Arc<dyn Worker<T>> {
+    pub fn new(
+        dispatcher: Arc<Dispatcher>,
+        queue: Arc<WorkQueue>,
+        runner: Arc<dyn Runner<T>>,
+    ) -> Arc<dyn Worker<T>> {
         Arc::new(TokioWorker {
             runner: runner,
-            runtime: runtime,
+            dispatcher: dispatcher,
+            queue: queue,
         })
     }
 }
 
 impl<T: Send + Sync + 'static> Worker<T> for TokioWorker<T> {
     fn send(&self, req: T) -> () {
+        let id = match self.queue.push(Instant::now()) {
+            Some(id) => id,
+            None => {
+                error!("Dropping {} job: its queue is full", self.queue.name());
+                return;
+            }
+        };
+        let priority = self.runner.priority(&req);
+        let repo = self.runner.repo(&req);
         let runner = self.runner.clone();
-        self.runtime.lock().unwrap().spawn(future::lazy(move || {
-            runner.handle(req);
-            future::ok(())
-        }));
+        let queue = self.queue.clone();
+        let in_flight = InFlight::start();
+        // errors of the job are about the delivery that queued it, if any
+        let mut context = error_reports::current_context();
+        context.job = self.queue.name().to_string();
+        if let Some(ref repo) = repo {
+            context.repo = repo.clone();
+        }
+        self.dispatcher.submit(
+            priority,
+            repo,
+            Box::new(move || {
+                error_reports::with_context(context, || runner.handle(req));
+                queue.done(id, Instant::now());
+                drop(in_flight);
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashmap;
+
+    fn next(queue: &mut FairQueue<&'static str>) -> Option<&'static str> {
+        queue.next().map(|(_, job)| job)
+    }
+
+    #[test]
+    fn test_fair_queue_round_robin() {
+        let mut queue = FairQueue::new(10, 2);
+        let monorepo = Some("some-org/monorepo".to_string());
+        let service = Some("some-org/service".to_string());
+        for job in &["mono-1", "mono-2", "mono-3", "mono-4"] {
+            queue.push(Priority::Background, monorepo.clone(), *job);
+        }
+        queue.push(Priority::Background, service.clone(), "service-1");
+        queue.push(Priority::Background, None, "no-repo");
+
+        // taking turns, and at most 2 at a time for the monorepo
+        assert_eq!(Some("mono-1"), next(&mut queue));
+        assert_eq!(Some("service-1"), next(&mut queue));
+        assert_eq!(Some("no-repo"), next(&mut queue));
+        assert_eq!(Some("mono-2"), next(&mut queue));
+        assert_eq!(None, next(&mut queue));
+
+        queue.done(&monorepo);
+        assert_eq!(Some("mono-3"), next(&mut queue));
+        assert_eq!(None, next(&mut queue));
+        queue.done(&service);
+        queue.done(&monorepo);
+        assert_eq!(Some("mono-4"), next(&mut queue));
+        assert_eq!(None, next(&mut queue));
+    }
+
+    #[test]
+    fn test_fair_queue_priorities() {
+        let mut queue = FairQueue::new(2, 5);
+        queue.push(Priority::Background, Some("some-org/a".to_string()), "backport");
+        queue.push(Priority::Normal, None, "notification");
+        queue.push(Priority::Interactive, Some("some-org/b".to_string()), "slack action");
+
+        assert_eq!(Some("slack action"), next(&mut queue));
+        assert_eq!(Some("notification"), next(&mut queue));
+        // all slots are taken
+        assert_eq!(None, next(&mut queue));
+
+        queue.done(&None);
+        assert_eq!(Some("backport"), next(&mut queue));
+        assert_eq!(None, next(&mut queue));
+    }
+
+    #[test]
+    fn test_work_queue() {
+        let start = Instant::now();
+        let queue = WorkQueue::new("slack", 2);
+
+        let first = queue.push(start).unwrap();
+        let second = queue.push(start + Duration::from_millis(100)).unwrap();
+        assert!(queue.is_full());
+        assert_eq!(None, queue.push(start + Duration::from_millis(200)));
+
+        let stats = queue.stats(start + Duration::from_millis(500));
+        assert_eq!(2, stats.depth);
+        assert_eq!(500, stats.oldest_age_ms);
+        assert_eq!(1, stats.dropped);
+
+        queue.done(first, start + Duration::from_millis(1000));
+        assert!(!queue.is_full());
+        let stats = queue.stats(start + Duration::from_millis(1000));
+        assert_eq!(1, stats.depth);
+        assert_eq!(900, stats.oldest_age_ms);
+        assert_eq!(1, stats.processed);
+        assert_eq!(1000, stats.last_latency_ms);
+
+        queue.done(second, start + Duration::from_millis(1100));
+        let stats = queue.stats(start + Duration::from_millis(2000));
+        assert_eq!(0, stats.depth);
+        assert_eq!(0, stats.oldest_age_ms);
+        assert_eq!(2, stats.processed);
+        assert_eq!(2000, stats.total_latency_ms);
+    }
+
+    #[test]
+    fn test_work_queues() {
+        let queues = WorkQueues::new(5, hashmap! { "slack".to_string() => 1 });
+        let slack = queues.queue("slack");
+        let sbom = queues.queue("sbom");
+
+        slack.push(Instant::now()).unwrap();
+        sbom.push(Instant::now()).unwrap();
+        assert_eq!(vec!["slack".to_string()], queues.full());
+
+        let stats = queues.stats();
+        assert_eq!(vec![1, 5], stats.iter().map(|s| s.capacity).collect::<Vec<_>>());
     }
 }
 
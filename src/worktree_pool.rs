@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+
+use log::error;
+use serde_derive::Serialize;
+
+use crate::dir_pool::AvailableDirs;
+use crate::errors::*;
+use crate::git::Git;
+
+// Utilization of a repo's worktrees
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct WorktreePoolStats {
+    // "host/owner/repo"
+    pub repo: String,
+    // worktrees created so far
+    pub size: u32,
+    pub in_use: u32,
+    pub peak_in_use: u32,
+    // how many times a worktree was handed out
+    pub checkouts: u64,
+    // how many times a merge had to wait for another one to the same branch
+    pub branch_lock_waits: u64,
+}
+
+struct RepoWorktrees {
+    dirs: AvailableDirs,
+    stats: WorktreePoolStats,
+}
+
+// Hands out worktrees of a single shared clone per repo, so that merges into the same repo can run
+// concurrently without a full clone each.
+pub struct WorktreePool {
+    root_dir: PathBuf,
+    repos: Mutex<HashMap<String, RepoWorktrees>>,
+    // serializes fetches into (and worktree changes of) each repo's shared clone
+    clone_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    locked_branches: Mutex<HashSet<String>>,
+    branch_unlocked: Condvar,
+}
+
+pub struct HeldWorktree<'a> {
+    id: u32,
+    repo_key: String,
+    dir: PathBuf,
+    pool: &'a WorktreePool,
+}
+
+// Held while pushing to a branch, so that concurrent merges don't race each other's pushes
+pub struct BranchLock<'a> {
+    key: String,
+    pool: &'a WorktreePool,
+}
+
+impl WorktreePool {
+    pub fn new(root_dir: &str) -> WorktreePool {
+        WorktreePool {
+            root_dir: PathBuf::from(root_dir),
+            repos: Mutex::new(HashMap::new()),
+            clone_locks: Mutex::new(HashMap::new()),
+            locked_branches: Mutex::new(HashSet::new()),
+            branch_unlocked: Condvar::new(),
+        }
+    }
+
+    fn repo_root(&self, host: &str, owner: &str, repo: &str) -> PathBuf {
+        self.root_dir.join(host).join(owner).join(repo)
+    }
+
+    fn repo_key(host: &str, owner: &str, repo: &str) -> String {
+        format!("{}/{}/{}", host, owner, repo)
+    }
+
+    // The clone that the repo's worktrees are added to
+    pub fn main_dir(&self, host: &str, owner: &str, repo: &str) -> PathBuf {
+        self.repo_root(host, owner, repo).join("main")
+    }
+
+    // Must be held while fetching into the main clone or adding worktrees to it
+    pub fn clone_lock(&self, host: &str, owner: &str, repo: &str) -> Arc<Mutex<()>> {
+        let mut locks = self.clone_locks.lock().unwrap();
+        locks.entry(WorktreePool::repo_key(host, owner, repo)).or_insert_with(|| Arc::new(Mutex::new(()))).clone()
+    }
+
+    pub fn take_worktree(&self, host: &str, owner: &str, repo: &str) -> HeldWorktree {
+        let repo_key = WorktreePool::repo_key(host, owner, repo);
+
+        let id;
+        {
+            let mut repos = self.repos.lock().unwrap();
+            let entry = repos.entry(repo_key.clone()).or_insert_with(|| RepoWorktrees::new(&repo_key));
+            id = entry.dirs.get_id();
+            entry.stats.size = entry.stats.size.max(id);
+            entry.stats.in_use += 1;
+            entry.stats.peak_in_use = entry.stats.peak_in_use.max(entry.stats.in_use);
+            entry.stats.checkouts += 1;
+        }
+
+        HeldWorktree {
+            id: id,
+            dir: self.repo_root(host, owner, repo).join("worktrees").join(id.to_string()),
+            repo_key: repo_key,
+            pool: self,
+        }
+    }
+
+    fn return_worktree(&self, id: u32, repo_key: &str) {
+        let mut repos = self.repos.lock().unwrap();
+        if let Some(entry) = repos.get_mut(repo_key) {
+            entry.dirs.return_id(id);
+            entry.stats.in_use -= 1;
+        }
+    }
+
+    // Blocks until no one else holds the lock on |branch|
+    pub fn lock_branch(&self, host: &str, owner: &str, repo: &str, branch: &str) -> BranchLock {
+        let repo_key = WorktreePool::repo_key(host, owner, repo);
+        let key = format!("{}:{}", repo_key, branch);
+
+        let mut locked = self.locked_branches.lock().unwrap();
+        if locked.contains(&key) {
+            let mut repos = self.repos.lock().unwrap();
+            let entry = repos.entry(repo_key.clone()).or_insert_with(|| RepoWorktrees::new(&repo_key));
+            entry.stats.branch_lock_waits += 1;
+        }
+        while locked.contains(&key) {
+            locked = self.branch_unlocked.wait(locked).unwrap();
+        }
+        locked.insert(key.clone());
+
+        BranchLock { key: key, pool: self }
+    }
+
+    fn unlock_branch(&self, key: &str) {
+        self.locked_branches.lock().unwrap().remove(key);
+        self.branch_unlocked.notify_all();
+    }
+
+    // Runs |f| unless one of the repo's worktrees is held, and keeps them from being taken until it is done.
+    // Returns None if they were in use.
+    pub fn with_unused_repo<T, F>(&self, host: &str, owner: &str, repo: &str, f: F) -> Result<Option<T>>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let repos = self.repos.lock().unwrap();
+        if WorktreePool::repo_in_use(&repos, &WorktreePool::repo_key(host, owner, repo)) {
+            return Ok(None);
+        }
+
+        let result = f().map(Some);
+        drop(repos);
+        result
+    }
+
+    pub fn in_use(&self, host: &str, owner: &str, repo: &str) -> bool {
+        let repos = self.repos.lock().unwrap();
+        WorktreePool::repo_in_use(&repos, &WorktreePool::repo_key(host, owner, repo))
+    }
+
+    fn repo_in_use(repos: &HashMap<String, RepoWorktrees>, key: &str) -> bool {
+        repos.get(key).map(|r| r.stats.in_use > 0).unwrap_or(false)
+    }
+
+    pub fn stats(&self) -> Vec<WorktreePoolStats> {
+        let repos = self.repos.lock().unwrap();
+        let mut stats = repos.values().map(|r| r.stats.clone()).collect::<Vec<_>>();
+        stats.sort_by(|a, b| a.repo.cmp(&b.repo));
+        stats
+    }
+}
+
+impl RepoWorktrees {
+    fn new(repo_key: &str) -> RepoWorktrees {
+        RepoWorktrees {
+            dirs: AvailableDirs::new(),
+            stats: WorktreePoolStats {
+                repo: repo_key.to_string(),
+                size: 0,
+                in_use: 0,
+                peak_in_use: 0,
+                checkouts: 0,
+                branch_lock_waits: 0,
+            },
+        }
+    }
+}
+
+impl<'a> HeldWorktree<'a> {
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+}
+
+impl<'a> Drop for HeldWorktree<'a> {
+    fn drop(&mut self) {
+        // a branch can only be checked out in one worktree at a time: don't keep it from the others
+        if self.dir.join(".git").exists() {
+            if let Err(e) = Git::new("", "", &self.dir).run(&["checkout", "--detach"]) {
+                error!("Error detaching worktree {:?}: {}", self.dir, e);
+            }
+        }
+        self.pool.return_worktree(self.id, &self.repo_key)
+    }
+}
+
+impl<'a> Drop for BranchLock<'a> {
+    fn drop(&mut self) {
+        self.pool.unlock_branch(&self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_worktree_pool() {
+        let pool = WorktreePool::new("<root>");
+        assert_eq!("<root>/h1/o1/repo-A/main", pool.main_dir("h1", "o1", "repo-A").to_string_lossy());
+
+        {
+            let tree_a1 = pool.take_worktree("h1", "o1", "repo-A");
+            assert_eq!("<root>/h1/o1/repo-A/worktrees/1", tree_a1.dir().to_string_lossy());
+
+            let tree_a2 = pool.take_worktree("h1", "o1", "repo-A");
+            assert_eq!("<root>/h1/o1/repo-A/worktrees/2", tree_a2.dir().to_string_lossy());
+
+            let tree_b1 = pool.take_worktree("h1", "o1", "repo-B");
+            assert_eq!("<root>/h1/o1/repo-B/worktrees/1", tree_b1.dir().to_string_lossy());
+        }
+
+        // going out of scope should return it to the pool
+        let tree_a1_again = pool.take_worktree("h1", "o1", "repo-A");
+        assert_eq!("<root>/h1/o1/repo-A/worktrees/1", tree_a1_again.dir().to_string_lossy());
+
+        let stats = pool.stats();
+        assert_eq!(2, stats.len());
+        assert_eq!(
+            WorktreePoolStats {
+                repo: "h1/o1/repo-A".into(),
+                size: 2,
+                in_use: 1,
+                peak_in_use: 2,
+                checkouts: 3,
+                branch_lock_waits: 0,
+            },
+            stats[0]
+        );
+        assert_eq!("h1/o1/repo-B", stats[1].repo);
+        assert_eq!(0, stats[1].in_use);
+
+        assert!(pool.in_use("h1", "o1", "repo-A"));
+        assert_eq!(None, pool.with_unused_repo("h1", "o1", "repo-A", || Ok(())).unwrap());
+        assert!(!pool.in_use("h1", "o1", "repo-B"));
+        assert_eq!(Some(()), pool.with_unused_repo("h1", "o1", "repo-B", || Ok(())).unwrap());
+    }
+
+    #[test]
+    fn test_lock_branch() {
+        let pool = Arc::new(WorktreePool::new("<root>"));
+
+        let lock = pool.lock_branch("h1", "o1", "repo-A", "release/1.0");
+        // other branches aren't held up
+        pool.lock_branch("h1", "o1", "repo-A", "release/2.0");
+
+        let waiter = {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                pool.lock_branch("h1", "o1", "repo-A", "release/1.0");
+            })
+        };
+
+        // wait for the other thread to be blocked on the lock
+        while pool.stats().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(lock);
+        waiter.join().unwrap();
+
+        assert_eq!(1, pool.stats()[0].branch_lock_waits);
+    }
+}
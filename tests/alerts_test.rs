@@ -0,0 +1,259 @@
+mod mocks;
+
+use std::sync::{Arc, Mutex};
+
+use tempdir::TempDir;
+
+use octobot::alerts::{self, AlertEscalator, CriticalAlert};
+use octobot::config::{AlertsConfig, Config, OpsgenieConfig};
+use octobot::db::Database;
+use octobot::errors::*;
+use octobot::github;
+use octobot::messenger;
+use octobot::opsgenie::{self, NewAlert};
+use octobot::repos::{RepoInfo, RepoRoutingRule};
+use octobot::slack;
+
+use mocks::mock_slack::MockSlack;
+
+const NOW: i64 = 1556712000;
+
+fn new_test() -> (Arc<Config>, TempDir) {
+    let temp_dir = TempDir::new("alerts_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let mut config = Config::new(db);
+    config.alerts = Some(AlertsConfig {
+        ack_minutes: Some(10),
+        main_broken: Some(vec!["the-oncall-channel".into(), "@the.lead".into()]),
+        release_failed: None,
+        log_lines: None,
+    });
+    config.repos_write().insert_info(&RepoInfo::new("some-user/some-repo", "the-reviews-channel")).unwrap();
+
+    (Arc::new(config), temp_dir)
+}
+
+fn the_repo() -> github::Repo {
+    github::Repo::parse("http://the-github-host/some-user/some-repo").unwrap()
+}
+
+fn the_alert() -> CriticalAlert {
+    CriticalAlert {
+        id: 1,
+        repo: "some-user/some-repo".into(),
+        branch: "master".into(),
+        kind: alerts::MAIN_BROKEN.into(),
+        message: "ci/build: Tests failed".into(),
+        tier: 0,
+        escalate_at: NOW + 600,
+        acked_by: None,
+        acked_at: 0,
+        created_at: NOW,
+    }
+}
+
+fn expect_alert(slack: &mut MockSlack) {
+    slack.expect(vec![slack::threaded_req(
+        "the-reviews-channel",
+        "Main branch is broken: master (<http://the-github-host/some-user/some-repo|some-user/some-repo>)",
+        vec![alerts::attachment(&the_alert())],
+        "alert:1",
+    )]);
+}
+
+fn raise(config: &Arc<Config>, slack: &MockSlack, message: &str, now: i64) -> Option<CriticalAlert> {
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+    alerts::raise(config, &messenger, None, &the_repo(), "master", alerts::MAIN_BROKEN, message, now).unwrap()
+}
+
+#[test]
+fn test_unacknowledged_alert_escalates_through_tiers() {
+    let (config, _temp) = new_test();
+    let mut slack = MockSlack::new(vec![]);
+
+    expect_alert(&mut slack);
+    assert_eq!(Some(the_alert()), raise(&config, &slack, "ci/build: Tests failed", NOW));
+    // the branch is already alerted
+    assert_eq!(None, raise(&config, &slack, "ci/lint: Lint failed", NOW + 60));
+
+    let escalator = AlertEscalator::new(config.clone(), slack.new_sender());
+    escalator.run(NOW + 599).unwrap();
+
+    slack.expect(vec![slack::threaded_req(
+        "the-oncall-channel",
+        "Main branch is broken: master (some-user/some-repo) has not been acknowledged for 10 minutes",
+        vec![alerts::attachment(&the_alert())],
+        "alert:1",
+    )]);
+    escalator.run(NOW + 600).unwrap();
+
+    slack.expect(vec![slack::threaded_req(
+        "@the.lead",
+        "Main branch is broken: master (some-user/some-repo) has not been acknowledged for 20 minutes",
+        vec![alerts::attachment(&the_alert())],
+        "alert:1",
+    )]);
+    escalator.run(NOW + 1200).unwrap();
+
+    // no one is left to escalate to
+    escalator.run(NOW + 1800).unwrap();
+    escalator.run(NOW + 2400).unwrap();
+    assert_eq!(0, config.critical_alerts.get(1).unwrap().unwrap().escalate_at);
+
+    // so the branch can be alerted again
+    let next = CriticalAlert {
+        id: 2,
+        message: "ci/build: Still failing".into(),
+        escalate_at: NOW + 3600,
+        created_at: NOW + 3000,
+        ..the_alert()
+    };
+    slack.expect(vec![slack::threaded_req(
+        "the-reviews-channel",
+        "Main branch is broken: master (<http://the-github-host/some-user/some-repo|some-user/some-repo>)",
+        vec![alerts::attachment(&next)],
+        "alert:2",
+    )]);
+    assert_eq!(Some(next), raise(&config, &slack, "ci/build: Still failing", NOW + 3000));
+}
+
+#[test]
+fn test_acknowledged_alert_stops_escalating() {
+    let (config, _temp) = new_test();
+    let mut slack = MockSlack::new(vec![]);
+
+    expect_alert(&mut slack);
+    raise(&config, &slack, "ci/build: Tests failed", NOW);
+
+    assert_eq!(
+        "Acknowledged: master (some-user/some-repo) will not be escalated any further",
+        alerts::acknowledge(&config, None, 1, "joe.sender", NOW + 300).unwrap()
+    );
+    assert_eq!(
+        "This alert was already acknowledged by joe.sender",
+        alerts::acknowledge(&config, None, 1, "sue.sender", NOW + 400).unwrap()
+    );
+    assert_eq!("Unknown alert: 2", alerts::acknowledge(&config, None, 2, "joe.sender", NOW + 400).unwrap());
+
+    // nothing is escalated
+    let escalator = AlertEscalator::new(config.clone(), slack.new_sender());
+    escalator.run(NOW + 600).unwrap();
+}
+
+// Records what octobot asks of opsgenie
+#[derive(Default)]
+struct FakeOpsgenie {
+    alerts: Mutex<Vec<NewAlert>>,
+    acks: Mutex<Vec<(String, String)>>,
+}
+
+impl opsgenie::Session for FakeOpsgenie {
+    fn create_alert(&self, alert: &NewAlert) -> Result<()> {
+        self.alerts.lock().unwrap().push(alert.clone());
+        Ok(())
+    }
+
+    fn acknowledge_alert(&self, alias: &str, user: &str) -> Result<()> {
+        self.acks.lock().unwrap().push((alias.into(), user.into()));
+        Ok(())
+    }
+
+    fn get_on_calls(&self, schedule: &str) -> Result<Vec<String>> {
+        assert_eq!("backend_schedule", schedule);
+        Ok(vec!["the.oncall@company.com".into()])
+    }
+}
+
+fn new_test_with_opsgenie() -> (Arc<Config>, TempDir) {
+    let temp_dir = TempDir::new("alerts_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let mut config = Config::new(db);
+    config.alerts = Some(AlertsConfig {
+        ack_minutes: Some(10),
+        main_broken: None,
+        release_failed: None,
+        log_lines: None,
+    });
+    config.opsgenie = Some(OpsgenieConfig {
+        api_key: "the-key".into(),
+        api_url: None,
+        default_responder: Some("escalation:Everyone".into()),
+        priority: Some("P2".into()),
+    });
+    let info = RepoInfo::new("some-user/some-repo", "the-reviews-channel")
+        .with_routing_rule(RepoRoutingRule::new("master", "", "", "").with_opsgenie("backend"));
+    config.repos_write().insert_info(&info).unwrap();
+
+    (Arc::new(config), temp_dir)
+}
+
+#[test]
+fn test_alert_pages_opsgenie() {
+    let (config, _temp) = new_test_with_opsgenie();
+    let slack = MockSlack::new(vec![slack::threaded_req(
+        "the-reviews-channel",
+        "Main branch is broken: master. On call: the.oncall@company.com \
+         (<http://the-github-host/some-user/some-repo|some-user/some-repo>)",
+        vec![alerts::attachment(&the_alert())],
+        "alert:1",
+    )]);
+    let fake = FakeOpsgenie::default();
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+
+    let alert = alerts::raise(
+        &config,
+        &messenger,
+        Some(&fake),
+        &the_repo(),
+        "master",
+        alerts::MAIN_BROKEN,
+        "ci/build: Tests failed",
+        NOW,
+    )
+    .unwrap();
+    assert_eq!(Some(the_alert()), alert);
+
+    let paged = fake.alerts.lock().unwrap().clone();
+    assert_eq!(1, paged.len());
+    assert_eq!("octobot-alert-1", paged[0].alias);
+    assert_eq!("P2", paged[0].priority);
+    // the routing rule wins over the default responder
+    assert_eq!(vec![opsgenie::responder("backend")], paged[0].responders);
+
+    alerts::acknowledge(&config, Some(&fake), 1, "joe.sender", NOW + 300).unwrap();
+    assert_eq!(
+        vec![("octobot-alert-1".to_string(), "joe.sender".to_string())],
+        *fake.acks.lock().unwrap()
+    );
+}
+
+#[test]
+fn test_alert_pages_default_opsgenie_responder() {
+    let (config, _temp) = new_test_with_opsgenie();
+    let alert = CriticalAlert {
+        branch: "release/1.0".into(),
+        kind: alerts::RELEASE_FAILED.into(),
+        message: "ci".into(),
+        ..the_alert()
+    };
+    // no one's on call for an escalation policy
+    let slack = MockSlack::new(vec![slack::threaded_req(
+        "the-reviews-channel",
+        "Release branch is failing: release/1.0 (<http://the-github-host/some-user/some-repo|some-user/some-repo>)",
+        vec![alerts::attachment(&alert)],
+        "alert:1",
+    )]);
+    let fake = FakeOpsgenie::default();
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+
+    let kind = alerts::RELEASE_FAILED;
+    alerts::raise(&config, &messenger, Some(&fake), &the_repo(), "release/1.0", kind, "ci", NOW).unwrap();
+
+    let paged = fake.alerts.lock().unwrap().clone();
+    assert_eq!(1, paged.len());
+    assert_eq!(vec![opsgenie::responder("escalation:Everyone")], paged[0].responders);
+}
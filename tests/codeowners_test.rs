@@ -0,0 +1,92 @@
+mod mocks;
+
+use failure::format_err;
+
+use mocks::mock_github::MockGithub;
+
+use octobot::codeowners::{self, CodeOwners};
+use octobot::github;
+
+fn the_pr() -> github::PullRequest {
+    let mut pr = github::PullRequest::new();
+    pr.number = 32;
+    pr.user = github::User::new("the-pr-owner");
+    pr
+}
+
+fn the_repo() -> github::Repo {
+    github::Repo::parse("http://the-github-host/some-user/some-repo").unwrap()
+}
+
+#[test]
+fn test_fetch_codeowners_fallback_locations() {
+    let github = MockGithub::new();
+    github.mock_get_file_contents(
+        "some-user",
+        "some-repo",
+        ".github/CODEOWNERS",
+        "master",
+        Err(format_err!("404")),
+    );
+    github.mock_get_file_contents(
+        "some-user",
+        "some-repo",
+        "CODEOWNERS",
+        "master",
+        Ok("*.rs @rust-owner\n".into()),
+    );
+
+    let codeowners = codeowners::fetch_codeowners(&github, "some-user", "some-repo", "master").unwrap();
+    assert_eq!(vec!["@rust-owner"], codeowners.owners_for("src/lib.rs"));
+}
+
+#[test]
+fn test_fetch_codeowners_none() {
+    let github = MockGithub::new();
+    for path in &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"] {
+        github.mock_get_file_contents("some-user", "some-repo", path, "master", Err(format_err!("404")));
+    }
+
+    assert!(codeowners::fetch_codeowners(&github, "some-user", "some-repo", "master").is_none());
+}
+
+#[test]
+fn test_request_reviews() {
+    let codeowners = CodeOwners::parse(
+        r#"
+*.rs    @rust-owner @the-pr-owner
+/docs/  @some-user/docs-team
+"#,
+    );
+
+    let github = MockGithub::new();
+    github.mock_get_pull_request_files(
+        "some-user",
+        "some-repo",
+        32,
+        Ok(vec![
+            github::PullRequestFile::new("src/lib.rs"),
+            github::PullRequestFile::new("docs/README.md"),
+        ]),
+    );
+    // the PR author should not be requested
+    github.mock_request_review("some-user", "some-repo", 32, vec!["rust-owner".into()], Ok(()));
+    github.mock_request_team_review("some-user", "some-repo", 32, vec!["docs-team".into()], Ok(()));
+
+    codeowners::request_reviews(&github, &codeowners, &the_repo(), &the_pr());
+}
+
+#[test]
+fn test_request_reviews_no_owners() {
+    let codeowners = CodeOwners::parse("/docs/ @docs-owner\n");
+
+    let github = MockGithub::new();
+    github.mock_get_pull_request_files(
+        "some-user",
+        "some-repo",
+        32,
+        Ok(vec![github::PullRequestFile::new("src/lib.rs")]),
+    );
+
+    codeowners::request_reviews(&github, &codeowners, &the_repo(), &the_pr());
+}
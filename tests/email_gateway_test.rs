@@ -0,0 +1,103 @@
+mod mocks;
+
+use tempdir::TempDir;
+
+use octobot::config::{Config, EmailConfig};
+use octobot::db::Database;
+use octobot::email_gateway::{self, EmailCommand};
+use octobot::github;
+use octobot::server::slack_actions;
+use octobot::users::UserInfo;
+
+use mocks::mock_github::MockGithub;
+
+fn new_test() -> (Config, TempDir) {
+    let temp_dir = TempDir::new("email_gateway_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let config = Config::new(db);
+    let mut joe = UserInfo::new("joe-reviewer", "");
+    joe.email = "joe@elsewhere.com".into();
+    config.users_write().insert_info(&joe).unwrap();
+    config.users_write().insert_info(&UserInfo::new("jane-reviewer", "")).unwrap();
+
+    (config, temp_dir)
+}
+
+fn email_config() -> EmailConfig {
+    EmailConfig {
+        smtp_host: "smtp.company.com".into(),
+        smtp_port: None,
+        username: Some("octobot".into()),
+        password: Some("the-password".into()),
+        tls: None,
+        from: "octobot@company.com".into(),
+        default_domain: Some("company.com".into()),
+        imap_host: Some("imap.company.com".into()),
+        imap_port: None,
+        imap_username: None,
+        imap_password: None,
+        imap_mailbox: None,
+    }
+}
+
+#[test]
+fn test_lookup_sender() {
+    let (config, _temp) = new_test();
+    let email_config = email_config();
+
+    let lookup = |from: &str| email_gateway::lookup_sender(&config, &email_config, from).map(|u| u.github);
+    assert_eq!(Some("joe-reviewer".to_string()), lookup("Joe <Joe@Elsewhere.com>"));
+    assert_eq!(Some("jane-reviewer".to_string()), lookup("jane-reviewer@company.com"));
+    // joe has an email of their own
+    assert_eq!(None, lookup("joe-reviewer@company.com"));
+    assert_eq!(None, lookup("jane-reviewer@other.com"));
+    assert_eq!(None, lookup("nobody@company.com"));
+}
+
+#[test]
+fn test_snooze() {
+    let (config, _temp) = new_test();
+    let jane = config.users().lookup_info("jane-reviewer").unwrap();
+
+    let msg = email_gateway::snooze(&config, &jane, 3600, 0).unwrap();
+    assert_eq!("Notifications are snoozed until 1970-01-01 01:00 UTC", msg);
+    assert_eq!(3600, config.users().lookup_info("jane-reviewer").unwrap().muted_until);
+}
+
+#[test]
+fn test_approve_reply() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+
+    let raw = "From: jane-reviewer@company.com\n\
+               Subject: Re: [octobot] Review requested\n\
+               \n\
+               approve\n\
+               \n\
+               > Review requested: Some PR (https://the-github-host/some-user/some-repo/pull/32)\n";
+    let email = email_gateway::parse_email(raw);
+    assert_eq!(Some(EmailCommand::Approve), email_gateway::parse_command(&email.text));
+    let (owner, repo, number) = email_gateway::find_pull_request("the-github-host", &email).unwrap();
+
+    let mut pr = github::PullRequest::new();
+    pr.number = 32;
+    pr.state = "open".into();
+    pr.user = github::User::new("the-pr-owner");
+    pr.head = github::BranchRef::new("feature");
+    pr.head.sha = "abcdef0123456".into();
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(pr));
+    github.mock_approve_pull_request(
+        "some-user",
+        "some-repo",
+        32,
+        "abcdef0123456",
+        Some("Approved by @jane-reviewer from email"),
+        Ok(()),
+    );
+
+    let jane = email_gateway::lookup_sender(&config, &email_config(), &email.from).unwrap();
+    let msg = slack_actions::perform_action_from(&config, &github, &jane, "approve", &owner, &repo, number, "email");
+    assert_eq!("Approved Pull Request #32", msg.unwrap());
+}
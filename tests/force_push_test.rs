@@ -402,3 +402,50 @@ fn test_force_push_identical_wrong_previous_approval() {
     force_push::comment_force_push(diffs, &github, "some-user", "some-repo", &pr, before_hash, after_hash)
         .unwrap();
 }
+
+fn review(login: &str, state: &str, commit: &str) -> github::Review {
+    let mut review = github::Review::new("", github::User::new(login));
+    review.state = state.into();
+    review.commit_id = Some(commit.into());
+    review
+}
+
+#[test]
+fn test_force_push_summary() {
+    let mut pr = github::PullRequest::new();
+    pr.number = 32;
+    let repo = github::Repo::parse("http://the-github-host/some-user/some-repo").unwrap();
+
+    let mut dropped = github::Commit::new();
+    dropped.sha = "abcdef0999999".into();
+    dropped.html_url = "http://commit/abcdef0".into();
+    dropped.commit.message = "Old work\n\nwith details".into();
+
+    let github = MockGithub::new();
+    github.mock_compare_commits("some-user", "some-repo", "1111abc9999999", "abcdef0999999", Ok(vec![dropped]));
+    github.mock_compare_commits("some-user", "some-repo", "abcdef0999999", "1111abc9999999", Ok(vec![]));
+    github.mock_get_pull_request_reviews(
+        "some-user",
+        "some-repo",
+        32,
+        Ok(vec![
+            review("joe", "DISMISSED", "abcdef0999999"),
+            review("jane", "DISMISSED", "abcdef0999999"),
+            review("jane", "APPROVED", "1111abc9999999"),
+            // dismissed by an earlier push
+            review("bob", "DISMISSED", "0000000000000"),
+        ]),
+    );
+
+    let summary =
+        force_push::summarize(&github, &repo, &pr, "abcdef0999999", "1111abc9999999", "http://compare").unwrap();
+    assert_eq!(vec!["joe"], summary.dismissed);
+    assert_eq!(vec!["jane"], summary.approved);
+    assert_eq!(0, summary.added.len());
+
+    assert_eq!("joe force-pushed branch main (<http://compare|compare>)", force_push::message(&summary, "joe", "main"));
+    let attachments = force_push::attachments(&summary);
+    assert_eq!(2, attachments.len());
+    assert_eq!("<http://commit/abcdef0|abcdef0>: Old work", attachments[0].text);
+    assert_eq!("Approvals by joe were dismissed", attachments[1].text);
+}
@@ -74,6 +74,18 @@ impl TempGit {
         self.git.run(&["config", "commit.gpgsign", "false"]).expect("turn off gpg signing");
     }
 
+    // local clones ignore --depth, unless cloned from a file:// url
+    pub fn reclone_shallow(&self, depth: u32) {
+        std::fs::remove_dir_all(&self.repo_dir).expect("remove clone dir");
+        std::fs::create_dir(&self.repo_dir).expect("create clone dir");
+
+        let remote = self.repo_dir.parent().expect("temp dir").join("remote");
+        let url = format!("file://{}", remote.to_string_lossy());
+        let depth = format!("--depth={}", depth);
+        self.git.run(&["clone", &depth, "--no-single-branch", &url, "."]).expect("shallow clone from bare repo");
+        self.git.run(&["config", "commit.gpgsign", "false"]).expect("turn off gpg signing");
+    }
+
     pub fn add_repo_file(&self, path: &str, contents: &str, msg: &str) {
         self.write_file(path, contents);
         self.run_git(&["add", path]);
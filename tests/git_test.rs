@@ -1,5 +1,12 @@
 mod git_helper;
 
+use std::process::Command;
+
+use tempdir::TempDir;
+
+use octobot::config::SigningConfig;
+use octobot::git::Git;
+
 use git_helper::temp_git::TempGit;
 
 #[test]
@@ -11,6 +18,14 @@ fn test_current_branch() {
     assert_eq!("other-branch", git.git.current_branch().unwrap());
 }
 
+#[test]
+fn test_default_branch() {
+    let git = TempGit::new();
+
+    git.run_git(&["checkout", "-b", "other-branch"]);
+    assert_eq!("master", git.git.default_branch().unwrap());
+}
+
 #[test]
 fn test_has_branch() {
     let git = TempGit::new();
@@ -118,6 +133,69 @@ fn test_find_base_commit() {
     assert_eq!(some_commit_1.clone(), git.git.find_base_branch_commit(&new_falcon_commit, "master").unwrap());
 }
 
+#[test]
+fn test_find_base_commit_deepens_shallow_clone() {
+    let git = TempGit::new();
+
+    let base_commit = git.run_git(&["rev-parse", "HEAD"]);
+
+    git.run_git(&["checkout", "-b", "falcon"]);
+    git.add_repo_file("prarie-falcon.txt", "Prarie", "falcons 1");
+    git.add_repo_file("peregrine-falcon.txt", "Peregrine", "falcons 2");
+    git.run_git(&["push", "origin", "falcon"]);
+
+    git.run_git(&["checkout", "master"]);
+    git.add_repo_file("foo1.txt", "", "some other commit 1");
+    git.add_repo_file("foo2.txt", "", "some other commit 2");
+    git.run_git(&["push"]);
+
+    git.reclone_shallow(1);
+    assert!(git.git.is_shallow());
+
+    assert_eq!(base_commit, git.git.find_base_branch_commit("origin/falcon", "master").unwrap());
+}
+
+#[test]
+fn test_fetch_refs_shallow_clone() {
+    let git = TempGit::new();
+
+    git.run_git(&["checkout", "-b", "falcon"]);
+    git.add_repo_file("prarie-falcon.txt", "Prarie", "falcons 1");
+    let falcon_commit = git.run_git(&["rev-parse", "HEAD"]);
+    git.add_repo_file("peregrine-falcon.txt", "Peregrine", "falcons 2");
+    git.run_git(&["push", "origin", "falcon"]);
+    git.run_git(&["checkout", "master"]);
+
+    git.reclone_shallow(1);
+
+    git.run_git(&["checkout", "master"]);
+    git.add_repo_file("horses.txt", "Stallion\n", "Horses and stuff");
+    git.run_git(&["push", "origin", "master:horses"]);
+    let horses_commit = git.run_git(&["rev-parse", "HEAD"]);
+    git.run_git(&["reset", "--hard", "HEAD^"]);
+
+    git.git.fetch_refs(&["horses", &falcon_commit], 1).unwrap();
+    assert_eq!(horses_commit, git.run_git(&["rev-parse", "origin/horses"]));
+
+    git.run_git(&["rev-parse", "--verify", &format!("{}^", falcon_commit)]);
+}
+
+#[test]
+fn test_ensure_parents_shallow_clone() {
+    let git = TempGit::new();
+
+    git.run_git(&["checkout", "-b", "falcon"]);
+    git.add_repo_file("prarie-falcon.txt", "Prarie", "falcons 1");
+    git.run_git(&["push", "origin", "falcon"]);
+    git.run_git(&["checkout", "master"]);
+
+    git.reclone_shallow(1);
+    assert!(git.git.run(&["rev-parse", "--verify", "origin/falcon^"]).is_err());
+
+    git.git.ensure_parents("origin/falcon").unwrap();
+    git.run_git(&["rev-parse", "--verify", "origin/falcon^"]);
+}
+
 #[test]
 fn test_checkout_branch_new_local_branch() {
     let git = TempGit::new();
@@ -206,3 +284,70 @@ fn test_get_commit_desc() {
         git.git.get_commit_desc("HEAD").unwrap()
     );
 }
+
+#[test]
+fn test_get_commit_messages() {
+    let git = TempGit::new();
+    git.run_git(&["tag", "v1.0"]);
+
+    git.add_repo_file("file.txt", "contents1", "SER-1: First\n\nWith a body");
+    git.add_repo_file("file.txt", "contents2", "Second");
+
+    let messages = git.git.get_commit_messages("v1.0", "HEAD").unwrap();
+    let hashes = git.run_git(&["log", "--pretty=%H", "-2"]);
+    let hashes = hashes.lines().collect::<Vec<_>>();
+
+    assert_eq!(
+        vec![
+            (hashes[0].to_string(), "Second".to_string()),
+            (hashes[1].to_string(), "SER-1: First\n\nWith a body".to_string()),
+        ],
+        messages
+    );
+
+    assert!(git.git.get_commit_messages("HEAD", "v1.0").unwrap().is_empty());
+}
+
+fn signing_git(git: &TempGit, key: &str) -> Git {
+    Git::new("the-host", "the-token", &git.repo_dir).with_signing(Some(&SigningConfig {
+        format: Some("ssh".into()),
+        key: key.into(),
+        program: None,
+    }))
+}
+
+#[test]
+fn test_signed_commits_and_tags() {
+    let git = TempGit::new();
+    let key_dir = TempDir::new("signing").expect("create dir for signing key");
+    let key = key_dir.path().join("signing_key").to_string_lossy().into_owned();
+    let keygen = Command::new("ssh-keygen")
+        .args(&["-q", "-t", "ed25519", "-N", "", "-f", &key])
+        .status()
+        .expect("run ssh-keygen");
+    assert!(keygen.success());
+
+    let signing_git = signing_git(&git, &key);
+    let user_opts = ["-c", "user.name=Test User", "-c", "user.email=testy@octobot.com"];
+    let mut args = user_opts.to_vec();
+    args.extend(&["commit", "--allow-empty", "-m", "Signed"]);
+    signing_git.run(&args).unwrap();
+    assert!(git.run_git(&["cat-file", "-p", "HEAD"]).contains("-----BEGIN SSH SIGNATURE-----"));
+
+    signing_git.create_tag("v1.0", "HEAD", "Release 1.0", ("Test User", "testy@octobot.com")).unwrap();
+    assert!(git.run_git(&["cat-file", "-p", "v1.0"]).contains("-----BEGIN SSH SIGNATURE-----"));
+
+    // commands that don't sign anything are unaffected
+    assert_eq!("master", signing_git.current_branch().unwrap());
+}
+
+#[test]
+fn test_signing_failure() {
+    let git = TempGit::new();
+    let signing_git = signing_git(&git, "/no/such/key");
+
+    let err = signing_git
+        .run(&["-c", "user.name=Test User", "-c", "user.email=testy@octobot.com", "commit", "--allow-empty", "-m", "x"])
+        .unwrap_err();
+    assert!(format!("{}", err).starts_with("Error signing with key /no/such/key"), "{}", err);
+}
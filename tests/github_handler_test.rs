@@ -6,18 +6,37 @@ use failure::format_err;
 use hyper::StatusCode;
 use tempdir::TempDir;
 
-use octobot::config::{Config, JiraConfig};
-use octobot::db::Database;
+use octobot::blame::{self, BlameRequest};
+use octobot::codeowners::{self, CodeOwnersRequest};
+use octobot::config::{
+    AlertsConfig, ComplianceConfig, Config, GrafanaConfig, GrafanaDashboard, JiraConfig, SecurityConfig,
+    ServiceNowConfig, WebhookConfig,
+};
+use octobot::db::{self, Database};
+use octobot::errors::*;
 use octobot::force_push::{self, ForcePushRequest};
 use octobot::github::*;
 use octobot::github::api::Session;
+use octobot::grafana::{self, AnnotationRequest};
 use octobot::jira;
+use octobot::merge_gate;
 use octobot::messenger;
+use octobot::pr_images::{self, PrImagesRequest};
 use octobot::pr_merge::{self, PRMergeRequest};
+use octobot::pr_revert::{self, PRRevertRequest};
+use octobot::outbound_webhooks::{self, OutboundEvent};
+use octobot::provenance::{self, AttestationRequest};
+use octobot::release_versions::{self, ReleaseVersionRequest};
+use octobot::repo_files;
 use octobot::repo_version::{self, RepoVersionRequest};
 use octobot::repos;
+use octobot::sbom::{self, SbomRequest};
 use octobot::server::github_handler::GithubEventHandler;
+use octobot::servicenow::{self, ChangeRecord, ChangeRequest, NewChange};
 use octobot::slack::{self, SlackAttachmentBuilder};
+use octobot::submodules::{self, SubmoduleBumpRequest};
+use octobot::tag_protection::{self, TagRestoreRequest};
+use octobot::teams::TeamMembers;
 
 use mocks::mock_github::MockGithub;
 use mocks::mock_jira::MockJira;
@@ -39,8 +58,19 @@ struct GithubHandlerTest {
     _temp_dir: TempDir,
     config: Arc<Config>,
     pr_merge: LockedMockWorker<PRMergeRequest>,
+    pr_revert: LockedMockWorker<PRRevertRequest>,
     repo_version: LockedMockWorker<RepoVersionRequest>,
+    release_versions: LockedMockWorker<ReleaseVersionRequest>,
     force_push: LockedMockWorker<ForcePushRequest>,
+    codeowners: LockedMockWorker<CodeOwnersRequest>,
+    blame: LockedMockWorker<BlameRequest>,
+    submodules: LockedMockWorker<SubmoduleBumpRequest>,
+    tag_restore: LockedMockWorker<TagRestoreRequest>,
+    pr_images: LockedMockWorker<PrImagesRequest>,
+    sbom: LockedMockWorker<SbomRequest>,
+    provenance: LockedMockWorker<AttestationRequest>,
+    webhooks: LockedMockWorker<OutboundEvent>,
+    annotations: LockedMockWorker<AnnotationRequest>,
 }
 
 impl GithubHandlerTest {
@@ -65,6 +95,10 @@ impl GithubHandlerTest {
         self.repo_version.expect_req(repo_version::req(repo, branch, commit_hash, commits));
     }
 
+    fn mock_repo_file(&self, branch: &str, contents: Result<String>) {
+        self.github.mock_get_file_contents("some-user", "some-repo", repo_files::FILE_NAME, branch, contents);
+    }
+
     fn mock_pull_request_commits(&self) -> Vec<Commit> {
         let commits = some_commits();
         self.github.mock_get_pull_request_commits(
@@ -83,11 +117,26 @@ fn new_test() -> GithubHandlerTest {
 }
 
 fn new_test_with(jira: Option<JiraConfig>) -> GithubHandlerTest {
+    new_test_configured(move |config| config.jira = jira)
+}
+
+fn new_test_configured<F: FnOnce(&mut Config)>(configure: F) -> GithubHandlerTest {
     let github = Arc::new(MockGithub::new());
     let slack = MockSlack::new(vec![]);
     let pr_merge = LockedMockWorker::new("pr-merge");
+    let pr_revert = LockedMockWorker::new("pr-revert");
     let repo_version = LockedMockWorker::new("repo-version");
+    let release_versions = LockedMockWorker::new("release-versions");
     let force_push = LockedMockWorker::new("force-push");
+    let codeowners = LockedMockWorker::new("codeowners");
+    let blame = LockedMockWorker::new("blame");
+    let submodules = LockedMockWorker::new("submodules");
+    let tag_restore = LockedMockWorker::new("tag-restore");
+    let pr_images = LockedMockWorker::new("pr-images");
+    let sbom = LockedMockWorker::new("sbom");
+    let provenance = LockedMockWorker::new("provenance");
+    let webhooks = LockedMockWorker::new("webhooks");
+    let annotations = LockedMockWorker::new("annotations");
 
     let temp_dir = TempDir::new("github_handler_test.rs").unwrap();
     let db_file = temp_dir.path().join("db.sqlite3");
@@ -116,13 +165,24 @@ fn new_test_with(jira: Option<JiraConfig>) -> GithubHandlerTest {
             .with_force_push(true))
         .expect("Failed to add some-user/some-repo");
 
-    config.jira = jira;
+    configure(&mut config);
     let config = Arc::new(config);
 
     let slack_sender = slack.new_sender();
     let pr_merge_sender = pr_merge.new_sender();
+    let pr_revert_sender = pr_revert.new_sender();
     let repo_version_sender = repo_version.new_sender();
+    let release_versions_sender = release_versions.new_sender();
     let force_push_sender = force_push.new_sender();
+    let codeowners_sender = codeowners.new_sender();
+    let blame_sender = blame.new_sender();
+    let submodules_sender = submodules.new_sender();
+    let tag_restore_sender = tag_restore.new_sender();
+    let pr_images_sender = pr_images.new_sender();
+    let sbom_sender = sbom.new_sender();
+    let provenance_sender = provenance.new_sender();
+    let webhooks_sender = webhooks.new_sender();
+    let annotations_sender = annotations.new_sender();
 
     GithubHandlerTest {
         github: github.clone(),
@@ -131,8 +191,19 @@ fn new_test_with(jira: Option<JiraConfig>) -> GithubHandlerTest {
         _temp_dir: temp_dir,
         config: config.clone(),
         pr_merge: pr_merge,
+        pr_revert: pr_revert,
         repo_version: repo_version,
+        release_versions: release_versions,
         force_push: force_push,
+        codeowners: codeowners,
+        blame: blame,
+        submodules: submodules,
+        tag_restore: tag_restore,
+        pr_images: pr_images,
+        sbom: sbom,
+        provenance: provenance,
+        webhooks: webhooks,
+        annotations: annotations,
         handler: GithubEventHandler {
             event: "ping".to_string(),
             data: data,
@@ -141,9 +212,24 @@ fn new_test_with(jira: Option<JiraConfig>) -> GithubHandlerTest {
             messenger: messenger::new(config.clone(), slack_sender),
             github_session: github.clone(),
             jira_session: None,
+            servicenow_session: None,
+            jsm_session: None,
+            opsgenie_session: None,
             pr_merge: pr_merge_sender,
+            pr_revert: pr_revert_sender,
             repo_version: repo_version_sender,
+            release_versions: release_versions_sender,
             force_push: force_push_sender,
+            codeowners: codeowners_sender,
+            blame: blame_sender,
+            submodules: submodules_sender,
+            tag_restore: tag_restore_sender,
+            pr_images: pr_images_sender,
+            sbom: sbom_sender,
+            provenance: provenance_sender,
+            webhooks: webhooks_sender,
+            annotations: annotations_sender,
+            team_members: Arc::new(TeamMembers::new()),
         },
     }
 }
@@ -161,6 +247,14 @@ fn new_test_with_jira() -> GithubHandlerTest {
         pending_versions_field: Some("the-pending-versions".into()),
         restrict_comment_visibility_to_role: None,
         login_suffix: None,
+        deployment: None,
+        oauth: None,
+        transitions: None,
+        projects: None,
+        pr_updates: None,
+        project_pr_updates: None,
+        templates: None,
+        webhook_secret: None,
     });
     let mut test = new_test_with(jira);
 
@@ -197,6 +291,9 @@ fn some_pr() -> Option<PullRequest> {
             repo: the_repo(),
         },
         draft: None,
+        created_at: None,
+        mergeable: None,
+        mergeable_state: None,
     })
 }
 
@@ -248,6 +345,293 @@ fn test_ping() {
     assert_eq!((StatusCode::OK, "ping".into()), resp);
 }
 
+#[test]
+fn test_status_recorded_for_digests() {
+    let mut test = new_test();
+    test.handler.event = "status".into();
+    test.handler.data.state = Some("failure".into());
+    test.handler.data.context = Some("ci/build".into());
+    test.handler.data.branches = Some(vec![StatusBranch { name: "some-branch".into() }]);
+    let mut commit = Commit::new();
+    commit.author = Some(User::new("bob-author"));
+    test.handler.data.commit = Some(commit);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "status".into()), resp);
+
+    let items = test.config.digest_items.for_user("bob-author").unwrap();
+    assert_eq!(1, items.len());
+    assert_eq!("some-branch", items[0].item);
+}
+
+#[test]
+fn test_status_failure_sends_webhook() {
+    let mut test = new_test_configured(|config| {
+        config.webhooks = Some(vec![WebhookConfig {
+            url: "http://the-consumer/hook".into(),
+            secret: Some("the-secret".into()),
+            events: None,
+        }]);
+    });
+    test.handler.event = "status".into();
+    test.handler.data.sha = Some("abcdef0123456".into());
+    test.handler.data.state = Some("failure".into());
+    test.handler.data.context = Some("ci/build".into());
+    test.handler.data.target_url = Some("http://the-build".into());
+    test.handler.data.branches = Some(vec![StatusBranch { name: "some-branch".into() }]);
+
+    let mut event = OutboundEvent::new(
+        outbound_webhooks::CI_FAILED,
+        &test.handler.data.repository,
+        test.handler.data.sender.login(),
+    );
+    event.commit = Some("abcdef0123456".into());
+    event.branch = Some("some-branch".into());
+    event.context = Some("ci/build".into());
+    event.url = Some("http://the-build".into());
+    test.webhooks.expect_req(event);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "status".into()), resp);
+}
+
+#[test]
+fn test_status_failure_on_main_raises_alert() {
+    let mut test = new_test_configured(|config| {
+        config.alerts = Some(AlertsConfig {
+            ack_minutes: None,
+            main_broken: Some(vec!["the-oncall-channel".into()]),
+            release_failed: None,
+            log_lines: None,
+        });
+    });
+    test.handler.event = "status".into();
+    test.handler.data.state = Some("failure".into());
+    test.handler.data.context = Some("ci/build".into());
+    test.handler.data.description = Some("Tests failed".into());
+    test.handler.data.target_url = Some("http://the-build".into());
+    test.handler.data.branches =
+        Some(vec![StatusBranch { name: "master".into() }, StatusBranch { name: "some-branch".into() }]);
+
+    test.slack.expect(vec![slack::threaded_req(
+        "the-reviews-channel",
+        &format!("Main branch is broken: master {}", REPO_MSG),
+        vec![SlackAttachmentBuilder::new("<http://the-build|ci/build>: Tests failed")
+            .color("danger")
+            .callback_id("alert:1")
+            .action(slack::SlackAction::button("acknowledge", "Acknowledge"))
+            .build()],
+        "alert:1",
+    )]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "status".into()), resp);
+
+    // the next failing status doesn't alert again
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "status".into()), resp);
+}
+
+#[test]
+fn test_status_failure_alert_has_log_excerpt() {
+    let mut test = new_test_configured(|config| {
+        config.alerts = Some(AlertsConfig {
+            ack_minutes: None,
+            main_broken: Some(vec!["the-oncall-channel".into()]),
+            release_failed: None,
+            log_lines: Some(2),
+        });
+    });
+    test.handler.event = "status".into();
+    test.handler.data.sha = Some("abcdef0123456".into());
+    test.handler.data.state = Some("failure".into());
+    test.handler.data.context = Some("ci/build".into());
+    test.handler.data.target_url = Some("http://the-build".into());
+    test.handler.data.branches = Some(vec![StatusBranch { name: "master".into() }]);
+
+    let job_url = "http://the-github-host/some-user/some-repo/actions/runs/5/job/77";
+    let mut run = CheckRun::new("ci/build", &some_pr().unwrap(), Some(job_url.into())).completed(Conclusion::Failure);
+    run.id = 77;
+    test.github.mock_get_check_runs("some-user", "some-repo", "abcdef0123456", Ok(vec![run]));
+    test.github.mock_get_check_run_annotations("some-user", "some-repo", 77, Ok(vec![]));
+    test.github.mock_get_job_log(
+        "some-user",
+        "some-repo",
+        77,
+        Ok("2021-03-01T12:00:00.1234567Z running 1 test\n\
+            2021-03-01T12:00:01.1234567Z test a ... FAILED\n\
+            2021-03-01T12:00:02.1234567Z ##[error]Process completed with exit code 101.\n\
+            2021-03-01T12:00:03.1234567Z Post job cleanup."
+            .into()),
+    );
+
+    test.slack.expect(vec![slack::threaded_req(
+        "the-reviews-channel",
+        &format!("Main branch is broken: master {}", REPO_MSG),
+        vec![SlackAttachmentBuilder::new(
+            "<http://the-build|ci/build>\n```\ntest a ... FAILED\n##[error]Process completed with exit code 101.\n```",
+        )
+        .color("danger")
+        .callback_id("alert:1")
+        .action(slack::SlackAction::button("acknowledge", "Acknowledge"))
+        .build()],
+        "alert:1",
+    )]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "status".into()), resp);
+}
+
+#[test]
+fn test_release_published_bumps_submodules() {
+    let mut test = new_test();
+    test.handler.event = "release".into();
+    test.handler.action = "published".into();
+    let release = Release::new("v1.2.0");
+    test.handler.data.release = Some(release.clone());
+
+    test.config
+        .repos_write()
+        .insert_info(&repos::RepoInfo::new("other-user/app", "app-channel").with_depends_on("some-user/some-repo"))
+        .unwrap();
+
+    test.submodules.expect_req(submodules::req(&test.handler.data.repository, &release));
+    test.slack.expect(vec![slack::req(
+        "app-channel",
+        "Upstream repo some-user/some-repo released v1.2.0 (<http://the-github-host/other-user/app|other-user/app>)",
+        vec![SlackAttachmentBuilder::new("")
+            .title("v1.2.0")
+            .title_link("http://the-github-host/some-user/some-repo/releases/tag/v1.2.0")
+            .build()],
+    )]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "release".into()), resp);
+}
+
+#[test]
+fn test_prerelease_does_not_bump_submodules() {
+    let mut test = new_test();
+    test.handler.event = "release".into();
+    test.handler.action = "published".into();
+    let mut release = Release::new("v1.2.0-rc1");
+    release.prerelease = true;
+    test.handler.data.release = Some(release);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "release".into()), resp);
+
+    test.handler.action = "created".into();
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "release [ignored]".into()), resp);
+}
+
+#[test]
+fn test_release_published_attaches_sbom() {
+    let mut test = new_test();
+    let info = test.config.repos().get_all().unwrap().remove(0).with_sbom("./make-sbom.sh");
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "release".into();
+    test.handler.action = "published".into();
+    let release = Release::new("v1.2.0");
+    test.handler.data.release = Some(release.clone());
+
+    test.submodules.expect_req(submodules::req(&test.handler.data.repository, &release));
+    test.sbom.expect_req(sbom::req(&test.handler.data.repository, &release));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "release".into()), resp);
+}
+
+#[test]
+fn test_release_published_records_provenance() {
+    let mut test = new_test();
+    let info = test.config.repos().get_all().unwrap().remove(0).with_provenance();
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "release".into();
+    test.handler.action = "published".into();
+    let release = Release::new("v1.2.0");
+    test.handler.data.release = Some(release.clone());
+
+    test.submodules.expect_req(submodules::req(&test.handler.data.repository, &release));
+    test.provenance.expect_req(provenance::req(&test.handler.data.repository, &release));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "release".into()), resp);
+}
+
+fn grafana_config() -> Option<GrafanaConfig> {
+    Some(GrafanaConfig {
+        url: "https://grafana.company.com".into(),
+        api_key: "the-key".into(),
+        dashboards: vec![GrafanaDashboard {
+            repo: "some-user".into(),
+            dashboard_uid: "the-dashboard".into(),
+            panel_id: None,
+            events: None,
+        }],
+    })
+}
+
+#[test]
+fn test_release_published_annotates_grafana() {
+    let mut test = new_test_configured(|config| config.grafana = grafana_config());
+    test.handler.event = "release".into();
+    test.handler.action = "published".into();
+    let release = Release::new("v1.2.0");
+    test.handler.data.release = Some(release.clone());
+
+    test.submodules.expect_req(submodules::req(&test.handler.data.repository, &release));
+    test.annotations.expect_req(grafana::released(&test.handler.data.repository, &release));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "release".into()), resp);
+}
+
+#[test]
+fn test_deployment_status_annotates_grafana() {
+    let mut test = new_test_configured(|config| config.grafana = grafana_config());
+    test.handler.event = "deployment_status".into();
+    test.handler.action = "created".into();
+    let deployment = Deployment::new("master", "abcdef0123456789", "production");
+    let mut status = DeploymentStatus {
+        state: "in_progress".into(),
+        target_url: None,
+        description: None,
+    };
+    test.handler.data.deployment = Some(deployment.clone());
+    test.handler.data.deployment_status = Some(status.clone());
+
+    // only finished deployments are annotated
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "deployment_status".into()), resp);
+
+    status.state = "success".into();
+    test.handler.data.deployment_status = Some(status.clone());
+    test.annotations.expect_req(grafana::deployed(&test.handler.data.repository, &deployment, &status));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "deployment_status".into()), resp);
+}
+
+#[test]
+fn test_deployment_status_without_grafana() {
+    let mut test = new_test();
+    test.handler.event = "deployment_status".into();
+    test.handler.action = "created".into();
+    test.handler.data.deployment = Some(Deployment::new("master", "abcdef0123456789", "production"));
+    test.handler.data.deployment_status = Some(DeploymentStatus {
+        state: "success".into(),
+        target_url: None,
+        description: None,
+    });
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "deployment_status".into()), resp);
+}
+
 #[test]
 fn test_commit_comment_with_path() {
     let mut test = new_test();
@@ -334,12 +718,13 @@ fn test_issue_comment() {
             .build(),
     ];
     let msg = "Comment on \"<http://the-issue|The Issue>\"";
+    let mention_msg = "joe.reviewer mentioned you in a comment on \"<http://the-issue|The Issue>\"";
 
     test.slack.expect(vec![
         slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
         slack::req("@the.pr.owner", msg, attach.clone()),
         slack::req("@assign1", msg, attach.clone()),
-        slack::req("@mentioned.participant", msg, attach.clone()),
+        slack::req("@mentioned.participant", mention_msg, attach.clone()),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
@@ -369,17 +754,67 @@ fn test_pull_request_comment() {
             .build(),
     ];
     let msg = "Comment on \"<http://the-pr|The PR>\"";
+    let mention_msg = "joe.reviewer mentioned you in a comment on \"<http://the-pr|The PR>\"";
 
     test.slack.expect(vec![
         slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
         slack::req("@the.pr.owner", msg, attach.clone()),
         slack::req("@assign1", msg, attach.clone()),
         slack::req("@bob.author", msg, attach.clone()),
-        slack::req("@mentioned.participant", msg, attach.clone()),
+        slack::req("@mentioned.participant", mention_msg, attach.clone()),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr_review_comment".into()), resp);
+}
+
+#[test]
+fn test_pull_request_comment_suggests_huddle() {
+    let mut test = new_test();
+    let info = test.config.repos().get_all().unwrap().remove(0).with_slack_threads(true).with_huddle_comments(2);
+    test.config.repos_write().update(&info).unwrap();
+
+    // the PR's owner already answered joe's first comment
+    test.config.review_discussions.add_comment("some-user/some-repo", 32, "joe-reviewer").unwrap();
+    test.config.review_discussions.add_comment("some-user/some-repo", 32, "the-pr-owner").unwrap();
+
+    test.handler.event = "pull_request_review_comment".into();
+    test.handler.action = "created".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.comment = Some(Comment {
+        commit_id: Some("abcdef00001111".into()),
+        path: Some("src/main.rs".into()),
+        body: Some("I'm still not sure about this".into()),
+        html_url: "http://the-comment".into(),
+        user: User::new("joe-reviewer"),
+    });
+    test.handler.data.sender = User::new("joe-reviewer");
+    test.mock_pull_request_commits();
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("I'm still not sure about this")
+            .title("joe.reviewer said:")
+            .title_link("http://the-comment")
+            .build(),
+    ];
+    let msg = "Comment on \"<http://the-pr|The PR>\"";
+    let huddle_msg = "This review has gone back and forth 2 times. A quick huddle might settle it faster: \
+                      consider inviting @the.pr.owner, @joe.reviewer";
+
+    let thread = "some-user/some-repo#32";
+    test.slack.expect(vec![
+        slack::threaded_req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone(), thread),
+        slack::req("@the.pr.owner", msg, attach.clone()),
+        slack::req("@assign1", msg, attach.clone()),
+        slack::req("@bob.author", msg, attach.clone()),
+        slack::threaded_req("the-reviews-channel", &format!("{} {}", huddle_msg, REPO_MSG), vec![], thread),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
     assert_eq!((StatusCode::OK, "pr_review_comment".into()), resp);
+
+    // only suggested once
+    assert!(!test.config.review_discussions.mark_suggested("some-user/some-repo", 32).unwrap());
 }
 
 #[test]
@@ -393,6 +828,8 @@ fn test_pull_request_review_commented() {
         body: Some("I think this file should change, cc: @mentioned-participant".into()),
         html_url: "http://the-comment".into(),
         user: User::new("joe-reviewer"),
+        submitted_at: None,
+        commit_id: None,
     });
     test.handler.data.sender = User::new("joe-reviewer");
     test.mock_pull_request_commits();
@@ -404,13 +841,14 @@ fn test_pull_request_review_commented() {
             .build(),
     ];
     let msg = "Comment on \"<http://the-pr|The PR>\"";
+    let mention_msg = "joe.reviewer mentioned you in a comment on \"<http://the-pr|The PR>\"";
 
     test.slack.expect(vec![
         slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
         slack::req("@the.pr.owner", msg, attach.clone()),
         slack::req("@assign1", msg, attach.clone()),
         slack::req("@bob.author", msg, attach.clone()),
-        slack::req("@mentioned.participant", msg, attach.clone()),
+        slack::req("@mentioned.participant", mention_msg, attach.clone()),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
@@ -472,6 +910,8 @@ fn test_pull_request_review_approved() {
         body: Some("I like it! cc: @mentioned-participant".into()),
         html_url: "http://the-comment".into(),
         user: User::new("joe-reviewer"),
+        submitted_at: None,
+        commit_id: None,
     });
     test.handler.data.sender = User::new("joe-reviewer");
     test.mock_pull_request_commits();
@@ -484,13 +924,20 @@ fn test_pull_request_review_approved() {
             .build(),
     ];
     let msg = "joe.reviewer approved PR \"<http://the-pr|The PR>\"";
+    let mention_msg = "joe.reviewer mentioned you in a review of \"<http://the-pr|The PR>\"";
+    let mention_attach = vec![
+        SlackAttachmentBuilder::new("I like it! cc: @mentioned-participant")
+            .title("joe.reviewer said:")
+            .title_link("http://the-comment")
+            .build(),
+    ];
 
     test.slack.expect(vec![
         slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
         slack::req("@the.pr.owner", msg, attach.clone()),
         slack::req("@assign1", msg, attach.clone()),
         slack::req("@bob.author", msg, attach.clone()),
-        slack::req("@mentioned.participant", msg, attach.clone()),
+        slack::req("@mentioned.participant", mention_msg, mention_attach.clone()),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
@@ -508,6 +955,8 @@ fn test_pull_request_review_changes_requested() {
         body: Some("It needs some work! cc: @mentioned-participant".into()),
         html_url: "http://the-comment".into(),
         user: User::new("joe-reviewer"),
+        submitted_at: None,
+        commit_id: None,
     });
     test.handler.data.sender = User::new("joe-reviewer");
     test.mock_pull_request_commits();
@@ -520,12 +969,19 @@ fn test_pull_request_review_changes_requested() {
             .build(),
     ];
     let msg = "joe.reviewer requested changes to PR \"<http://the-pr|The PR>\"";
+    let mention_msg = "joe.reviewer mentioned you in a review of \"<http://the-pr|The PR>\"";
+    let mention_attach = vec![
+        SlackAttachmentBuilder::new("It needs some work! cc: @mentioned-participant")
+            .title("joe.reviewer said:")
+            .title_link("http://the-comment")
+            .build(),
+    ];
     test.slack.expect(vec![
         slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
         slack::req("@the.pr.owner", msg, attach.clone()),
         slack::req("@assign1", msg, attach.clone()),
         slack::req("@bob.author", msg, attach.clone()),
-        slack::req("@mentioned.participant", msg, attach.clone()),
+        slack::req("@mentioned.participant", mention_msg, mention_attach.clone()),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
@@ -564,28 +1020,36 @@ fn test_pull_request_opened() {
 }
 
 #[test]
-fn test_pull_request_closed() {
-    let mut test = new_test();
+fn test_pull_request_opened_diff_preview() {
+    let mut test = new_test_configured(|config| config.main.slack_diff_preview_lines = Some(10));
     test.handler.event = "pull_request".into();
-    test.handler.action = "closed".into();
+    test.handler.action = "opened".into();
     test.handler.data.pull_request = some_pr();
-    test.handler.data.sender = User::new("the-pr-closer");
+    test.handler.data.sender = User::new("the-pr-owner");
     test.mock_pull_request_commits();
 
-    let attach = vec![
-        SlackAttachmentBuilder::new("")
-            .title("Pull Request #32: \"The PR\"")
-            .title_link("http://the-pr")
-            .build(),
-    ];
-    let msg = "Pull Request closed";
+    let mut file = PullRequestFile::new("src/main.rs");
+    file.additions = 1;
+    file.deletions = 1;
+    file.patch = Some("@@ -1 +1 @@\n-old\n+new".into());
+    test.github.mock_get_pull_request_files("some-user", "some-repo", 32, Ok(vec![file]));
+
+    expect_jira_ref_fail(&test.github);
 
     test.slack.expect(vec![
-        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
-        slack::req("@the.pr.owner", msg, attach.clone()),
-        slack::req("@assign1", msg, attach.clone()),
-        slack::req("@bob.author", msg, attach.clone()),
-        slack::req("@joe.reviewer", msg, attach.clone()),
+        slack::req(
+            "the-reviews-channel",
+            &format!("Pull Request opened by the.pr.owner {}", REPO_MSG),
+            vec![
+                SlackAttachmentBuilder::new("")
+                    .title("Pull Request #32: \"The PR\"")
+                    .title_link("http://the-pr")
+                    .build(),
+                SlackAttachmentBuilder::new("```\n--- src/main.rs\n@@ -1 +1 @@\n-old\n+new\n```")
+                    .title("Diff")
+                    .build(),
+            ],
+        ),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
@@ -593,90 +1057,876 @@ fn test_pull_request_closed() {
 }
 
 #[test]
-fn test_pull_request_reopened() {
-    let mut test = new_test();
+fn test_pull_request_opened_forwards_images() {
+    let mut test = new_test_configured(|config| {
+        config.main.slack_bot_token = Some("the-bot-token".into());
+        config.main.slack_forward_images = Some(true);
+    });
     test.handler.event = "pull_request".into();
-    test.handler.action = "reopened".into();
-    test.handler.data.pull_request = some_pr();
-    test.handler.data.sender = User::new("the-pr-closer");
+    test.handler.action = "opened".into();
+    let mut pr = some_pr().unwrap();
+    pr.body = Some("New look:\n![screenshot](https://github.com/user-attachments/assets/1234)".into());
+    test.handler.data.pull_request = Some(pr.clone());
+    test.handler.data.sender = User::new("the-pr-owner");
     test.mock_pull_request_commits();
 
-    let attach = vec![
-        SlackAttachmentBuilder::new("")
-            .title("Pull Request #32: \"The PR\"")
-            .title_link("http://the-pr")
-            .build(),
-    ];
-    let msg = "Pull Request reopened";
+    expect_jira_ref_fail(&test.github);
 
     test.slack.expect(vec![
         slack::req(
             "the-reviews-channel",
-            &format!("{} {}", msg, REPO_MSG),
-            attach.clone()
+            &format!("Pull Request opened by the.pr.owner {}", REPO_MSG),
+            vec![
+                SlackAttachmentBuilder::new("")
+                    .title("Pull Request #32: \"The PR\"")
+                    .title_link("http://the-pr")
+                    .build(),
+            ],
         ),
     ]);
+    test.pr_images.expect_req(pr_images::req(
+        &test.handler.data.repository,
+        &pr,
+        vec!["the-reviews-channel".into()],
+    ));
 
     let resp = test.handler.handle_event().unwrap();
     assert_eq!((StatusCode::OK, "pr".into()), resp);
 }
 
 #[test]
-fn test_pull_request_ready_for_review() {
+fn test_pull_request_opened_during_code_freeze() {
     let mut test = new_test();
     test.handler.event = "pull_request".into();
-    test.handler.action = "ready_for_review".into();
+    test.handler.action = "opened".into();
     test.handler.data.pull_request = some_pr();
-    test.handler.data.sender = User::new("the-pr-closer");
+    test.handler.data.sender = User::new("the-pr-owner");
     test.mock_pull_request_commits();
 
-    expect_jira_ref_fail(&test.github);
+    let now = db::now();
+    test.config.code_freezes.freeze("some-user", now + 3600, "the-release-manager", now).unwrap();
 
-    let attach = vec![
-        SlackAttachmentBuilder::new("")
-            .title("Pull Request #32: \"The PR\"")
-            .title_link("http://the-pr")
-            .build(),
-    ];
-    let msg = "Pull Request is ready for review";
+    let pr = some_pr().unwrap();
+    let mut run = CheckRun::new("code-freeze", &pr, None).completed(Conclusion::Failure);
+    run.output = Some(CheckOutput::new("Code freeze", ""));
+    test.github.mock_create_check_run(&pr, &run, Ok(1));
+    expect_jira_ref_fail(&test.github);
 
     test.slack.expect(vec![
-        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
-        slack::req("@the.pr.owner", msg, attach.clone()),
-        slack::req("@assign1", msg, attach.clone()),
-        slack::req("@bob.author", msg, attach.clone()),
-        slack::req("@joe.reviewer", msg, attach.clone()),
+        slack::req(
+            "the-reviews-channel",
+            &format!("Pull Request opened by the.pr.owner {}", REPO_MSG),
+            vec![
+                SlackAttachmentBuilder::new("")
+                    .title("Pull Request #32: \"The PR\"")
+                    .title_link("http://the-pr")
+                    .build(),
+            ],
+        ),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
     assert_eq!((StatusCode::OK, "pr".into()), resp);
+
+    let status = test.config.code_freezes.status(now).unwrap();
+    assert_eq!(1, status[0].pull_requests.len());
+    assert_eq!(32, status[0].pull_requests[0].number);
+}
+
+struct FakeServiceNow;
+
+impl servicenow::Session for FakeServiceNow {
+    fn create_change_request(&self, change: &NewChange) -> Result<ChangeRecord> {
+        assert_eq!("Release some-user/some-repo release/1.0", change.short_description);
+        Ok(ChangeRecord {
+            number: "CHG0030001".into(),
+            sys_id: "the-sys-id".into(),
+            approval: "requested".into(),
+        })
+    }
+
+    fn get_change_request(&self, sys_id: &str) -> Result<ChangeRecord> {
+        Err(format_err!("Unexpected change request lookup: {}", sys_id))
+    }
+
+    fn change_request_url(&self, sys_id: &str) -> String {
+        format!("http://the-servicenow/{}", sys_id)
+    }
+}
+
+fn new_test_with_servicenow() -> GithubHandlerTest {
+    let mut test = new_test_configured(|config| {
+        config.servicenow = Some(ServiceNowConfig {
+            instance_url: "http://the-servicenow".into(),
+            username: "the-servicenow-user".into(),
+            password: "the-servicenow-pass".into(),
+            assignment_group: None,
+            category: None,
+            repos: vec!["some-user".into()],
+        })
+    });
+    test.handler.servicenow_session = Some(Arc::new(FakeServiceNow));
+    test
 }
 
 #[test]
-fn test_pull_request_edited() {
-    let mut test = new_test();
+fn test_release_pull_request_opens_change_request() {
+    let mut test = new_test_with_servicenow();
+    let mut pr = some_pr().unwrap();
+    pr.base.ref_name = "release/1.0".into();
+
     test.handler.event = "pull_request".into();
-    test.handler.action = "edited".into();
-    test.handler.data.pull_request = some_pr();
-    test.handler.data.sender = User::new("the-pr-closer");
+    test.handler.action = "opened".into();
+    test.handler.data.pull_request = Some(pr.clone());
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
     test.mock_pull_request_commits();
 
-    expect_jira_ref_fail(&test.github);
+    test.github.mock_comment_pull_request(
+        "some-user",
+        "some-repo",
+        32,
+        "Change request [CHG0030001](http://the-servicenow/the-sys-id) was opened for this release.",
+        Ok(()),
+    );
+    let mut run = CheckRun::new("change-request", &pr, None);
+    run.output = Some(CheckOutput::new("Waiting for change approval", ""));
+    test.github.mock_create_check_run(&pr, &run, Ok(1));
+    expect_jira_ref_fail_pr(&test.github, &pr);
 
-    // no slack mocks
+    test.slack.expect(vec![
+        slack::req(
+            "the-reviews-channel",
+            &format!("Pull Request opened by the.pr.owner {}", REPO_MSG),
+            vec![
+                SlackAttachmentBuilder::new("")
+                    .title("Pull Request #32: \"The PR\"")
+                    .title_link("http://the-pr")
+                    .build(),
+            ],
+        ),
+    ]);
 
     let resp = test.handler.handle_event().unwrap();
     assert_eq!((StatusCode::OK, "pr".into()), resp);
+
+    let change = test.config.change_requests.get("some-user/some-repo", 32).unwrap().unwrap();
+    assert_eq!("CHG0030001", change.change_number);
+    assert_eq!("release/1.0", change.branch);
 }
 
 #[test]
-fn test_pull_request_assigned() {
-    let mut test = new_test();
+fn test_pull_request_opened_sends_webhook() {
+    let mut test = new_test_configured(|config| {
+        config.webhooks = Some(vec![WebhookConfig {
+            url: "http://the-consumer/hook".into(),
+            secret: None,
+            events: Some(vec![outbound_webhooks::PULL_REQUEST_OPENED.into()]),
+        }]);
+    });
     test.handler.event = "pull_request".into();
-    test.handler.action = "assigned".into();
+    test.handler.action = "opened".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    expect_jira_ref_fail(&test.github);
+
+    test.slack.expect(vec![
+        slack::req(
+            "the-reviews-channel",
+            &format!("Pull Request opened by the.pr.owner {}", REPO_MSG),
+            vec![
+                SlackAttachmentBuilder::new("")
+                    .title("Pull Request #32: \"The PR\"")
+                    .title_link("http://the-pr")
+                    .build(),
+            ],
+        ),
+    ]);
+    test.webhooks.expect_req(outbound_webhooks::pull_request_event(
+        outbound_webhooks::PULL_REQUEST_OPENED,
+        &test.handler.data.repository,
+        test.handler.data.pull_request.as_ref().unwrap(),
+        &User::new("the-pr-owner"),
+    ));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_opened_routing_rules() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.routing_rules = vec![
+        repos::RepoRoutingRule::new("release/*", "", "", "releases"),
+        repos::RepoRoutingRule::new("", "", "security", "security-reviews, the-reviews-channel"),
+    ];
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "opened".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    expect_jira_ref_fail(&test.github);
+    test.github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![Label::new("security")]));
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    let msg = format!("Pull Request opened by the.pr.owner {}", REPO_MSG);
+
+    test.slack.expect(vec![
+        slack::req("security-reviews", &msg, attach.clone()),
+        slack::req("the-reviews-channel", &msg, attach.clone()),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_opened_silent_routing_rule() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.routing_rules = vec![
+        repos::RepoRoutingRule::new("release/*", "", "", "releases"),
+        repos::RepoRoutingRule::new("master", "", "", "").with_head("pr-*").silenced(),
+    ];
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "opened".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    expect_jira_ref_fail(&test.github);
+
+    test.slack.expect(vec![]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_opened_lockfile_only() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.ecosystem = "cargo".into();
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "opened".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    test.github.mock_get_pull_request_files(
+        "some-user",
+        "some-repo",
+        32,
+        Ok(vec![PullRequestFile::new("Cargo.lock"), PullRequestFile::new("tools/Cargo.lock")]),
+    );
+    let pr = some_pr().unwrap();
+    let mut run = CheckRun::new("jira", &pr, None).completed(Conclusion::Neutral);
+    run.output = Some(CheckOutput::new("Skipped JIRA check", ""));
+    test.github.mock_create_check_run(&pr, &run, Ok(1));
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    let msg = "Pull Request opened by the.pr.owner";
+
+    test.slack.expect(vec![
+        slack::req(
+            "the-reviews-channel",
+            &format!("{} {}", msg, REPO_MSG),
+            attach.clone()
+        ),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_opened_codeowners() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.codeowners_reviews = true;
+    info.codeowners_ignore_bots = true;
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "opened".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    expect_jira_ref_fail(&test.github);
+
+    test.codeowners.expect_req(codeowners::req(&test.handler.data.repository, test.handler.data.pull_request.as_ref().unwrap()));
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    let msg = "Pull Request opened by the.pr.owner";
+
+    test.slack.expect(vec![
+        slack::req(
+            "the-reviews-channel",
+            &format!("{} {}", msg, REPO_MSG),
+            attach.clone()
+        ),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_opened_hotfix_blame_notify() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.blame_notify = true;
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "opened".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.pull_request.as_mut().unwrap().head.ref_name = "hotfix/login".into();
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    let pr = test.handler.data.pull_request.clone().unwrap();
+    expect_jira_ref_fail_pr(&test.github, &pr);
+
+    test.blame.expect_req(blame::req(&test.handler.data.repository, &pr));
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    let msg = "Pull Request opened by the.pr.owner";
+
+    test.slack.expect(vec![
+        slack::req(
+            "the-reviews-channel",
+            &format!("{} {}", msg, REPO_MSG),
+            attach.clone()
+        ),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_opened_codeowners_ignore_bots() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.codeowners_reviews = true;
+    info.codeowners_ignore_bots = true;
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "opened".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.pull_request.as_mut().unwrap().user = User::new("dependabot[bot]");
+    test.handler.data.sender = User::new("dependabot[bot]");
+    test.mock_pull_request_commits();
+
+    let mut pr = some_pr().unwrap();
+    pr.user = User::new("dependabot[bot]");
+    expect_jira_ref_fail_pr(&test.github, &pr);
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    let msg = "Pull Request opened by dependabot[bot]";
+
+    test.slack.expect(vec![
+        slack::req(
+            "the-reviews-channel",
+            &format!("{} {}", msg, REPO_MSG),
+            attach.clone()
+        ),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_synchronize_path_labels() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.path_labels = vec![repos::RepoPathLabel::new("docs/**", "documentation")];
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "synchronize".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-owner");
+
+    test.github.mock_get_pull_request_files(
+        "some-user",
+        "some-repo",
+        32,
+        Ok(vec![PullRequestFile::new("docs/README.md")]),
+    );
+    test.github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![]));
+    test.github.mock_add_pull_request_labels("some-user", "some-repo", 32, vec!["documentation".into()], Ok(()));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_synchronize_size_labels() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.size_labels = true;
+    info.size_label_excludes = "*.lock".into();
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "synchronize".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-owner");
+
+    let mut source = PullRequestFile::new("src/main.rs");
+    source.additions = 20;
+    source.deletions = 5;
+    let mut lock = PullRequestFile::new("Cargo.lock");
+    lock.additions = 1000;
+
+    test.github.mock_get_pull_request_files("some-user", "some-repo", 32, Ok(vec![source, lock]));
+    test.github.mock_get_pull_request_labels(
+        "some-user",
+        "some-repo",
+        32,
+        Ok(vec![Label::new("size/XS"), Label::new("bug")]),
+    );
+    test.github.mock_remove_pull_request_label("some-user", "some-repo", 32, "size/XS", Ok(()));
+    test.github.mock_add_pull_request_labels("some-user", "some-repo", 32, vec!["size/S".into()], Ok(()));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_synchronize_lint_violations() {
+    let mut test = new_test();
+    let info = test.config.repos().get_all().unwrap().remove(0).with_lint(false, r"^\[", "", true);
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "synchronize".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    test.github.mock_get_pull_request_comments("some-user", "some-repo", 32, Ok(vec![]));
+    test.github.mock_comment_pull_request(
+        "some-user",
+        "some-repo",
+        32,
+        "<!-- octobot:lint -->\n:warning: Please fix the following before merging:\n\n\
+         * PR title \"The PR\" does not match `^\\[`",
+        Ok(()),
+    );
+
+    let pr = some_pr().unwrap();
+    let mut run = CheckRun::new("commit-lint", &pr, None).completed(Conclusion::Failure);
+    run.output = Some(CheckOutput::new("1 lint violation(s)", ""));
+    test.github.mock_create_check_run(&pr, &run, Ok(1));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_synchronize_lint_resolved() {
+    let mut test = new_test();
+    let info = test.config.repos().get_all().unwrap().remove(0).with_lint(false, "PR$", "", false);
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "synchronize".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    test.github.mock_get_pull_request_comments(
+        "some-user",
+        "some-repo",
+        32,
+        Ok(vec![
+            IssueComment::new(5, "looks good", User::new("joe-reviewer")),
+            IssueComment::new(6, "<!-- octobot:lint -->\n:warning: Please fix...", User::new("octobot[bot]")),
+        ]),
+    );
+    test.github.mock_edit_comment(
+        "some-user",
+        "some-repo",
+        6,
+        "<!-- octobot:lint -->\n:white_check_mark: All lint issues have been resolved.",
+        Ok(()),
+    );
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_closed() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "closed".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-closer");
+    test.mock_pull_request_commits();
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    let msg = "Pull Request closed";
+
+    test.slack.expect(vec![
+        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
+        slack::req("@the.pr.owner", msg, attach.clone()),
+        slack::req("@assign1", msg, attach.clone()),
+        slack::req("@bob.author", msg, attach.clone()),
+        slack::req("@joe.reviewer", msg, attach.clone()),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_reopened() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "reopened".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-closer");
+    test.mock_pull_request_commits();
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    let msg = "Pull Request reopened";
+
+    test.slack.expect(vec![
+        slack::req(
+            "the-reviews-channel",
+            &format!("{} {}", msg, REPO_MSG),
+            attach.clone()
+        ),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_ready_for_review() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "ready_for_review".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-closer");
+    test.mock_pull_request_commits();
+
+    expect_jira_ref_fail(&test.github);
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    let msg = "Pull Request is ready for review";
+
+    test.slack.expect(vec![
+        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
+        slack::req("@the.pr.owner", msg, attach.clone()),
+        slack::req("@assign1", msg, attach.clone()),
+        slack::req("@bob.author", msg, attach.clone()),
+        slack::req("@joe.reviewer", msg, attach.clone()),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_edited() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "edited".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-closer");
+    test.mock_pull_request_commits();
+
+    expect_jira_ref_fail(&test.github);
+
+    // no slack mocks
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_edited_mentions() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "edited".into();
+    test.handler.data.pull_request = some_pr();
+    let body = "Fixes the thing.\n\nNeeds a look from @some-user/backend, cc @mentioned-participant";
+    if let Some(ref mut pr) = test.handler.data.pull_request {
+        pr.body = Some(body.into());
+    }
+    test.handler.data.changes = Some(Changes {
+        title: None,
+        body: Some(ChangedValue {
+            from: "Fixes the thing. cc @mentioned-participant".into(),
+        }),
+    });
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    expect_jira_ref_fail(&test.github);
+
+    // the PR's owner made the edit, and the participant was already mentioned
+    test.github.mock_get_team_members(
+        "some-user",
+        "backend",
+        Ok(vec![User::new("the-pr-owner"), User::new("assign1"), User::new("mentioned-participant")]),
+    );
+
+    let msg = "the.pr.owner mentioned team some-user/backend in the description of \"<http://the-pr|The PR>\"";
+    let attach = vec![
+        SlackAttachmentBuilder::new("Needs a look from @some-user/backend, cc @mentioned-participant")
+            .title("the.pr.owner said:")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    test.slack.expect(vec![
+        slack::req("@assign1", msg, attach.clone()),
+        slack::req("@mentioned.participant", msg, attach.clone()),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_assigned() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "assigned".into();
+    test.handler.data.pull_request = some_pr();
+    if let Some(ref mut pr) = test.handler.data.pull_request {
+        pr.assignees.push(User::new("assign2"));
+    }
+    test.handler.data.sender = User::new("the-pr-closer");
+    test.mock_pull_request_commits();
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    let msg = "Pull Request assigned to assign1, assign2";
+
+    test.slack.expect(vec![
+        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
+        slack::req("@the.pr.owner", msg, attach.clone()),
+        slack::req("@assign1", msg, attach.clone()),
+        slack::req("@assign2", msg, attach.clone()),
+        slack::req("@bob.author", msg, attach.clone()),
+        slack::req("@joe.reviewer", msg, attach.clone()),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_unassigned() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "unassigned".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("the-pr-closer");
+    test.mock_pull_request_commits();
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+    let msg = "Pull Request unassigned";
+
+    test.slack.expect(vec![
+        slack::req(
+            "the-reviews-channel",
+            &format!("{} {}", msg, REPO_MSG),
+            attach.clone()
+        ),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_review_requested() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "review_requested".into();
+    test.handler.data.pull_request = some_pr();
+    if let Some(ref mut pr) = test.handler.data.pull_request {
+        pr.requested_reviewers = Some(vec![User::new("joe-reviewer"), User::new("smith-reviewer")]);
+    }
+    test.handler.data.sender = User::new("the-pr-closer");
+    test.mock_pull_request_commits();
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .field("Reviewers", "joe.reviewer, smith.reviewer")
+            .build(),
+    ];
+    let msg = "Pull Request submitted for review to joe.reviewer, smith.reviewer";
+
+    test.slack.expect(vec![
+        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
+        slack::req("@the.pr.owner", msg, attach.clone()),
+        slack::req("@assign1", msg, attach.clone()),
+        slack::req("@bob.author", msg, attach.clone()),
+        slack::req("@joe.reviewer", msg, attach.clone()),
+        slack::req("@smith.reviewer", msg, attach.clone()),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_team_review_requested() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "review_requested".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.requested_team = Some(Team::new("reviewers", "The Reviewers"));
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    test.github.mock_get_team_members(
+        "some-user",
+        "reviewers",
+        Ok(vec![User::new("joe-reviewer"), User::new("smith-reviewer"), User::new("the-pr-owner")]),
+    );
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .field("Team", "The Reviewers")
+            .build(),
+    ];
+    let msg = "Pull Request submitted for review to team The Reviewers";
+
+    test.slack.expect(vec![
+        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
+        slack::req("@joe.reviewer", msg, attach.clone()),
+        slack::req("@smith.reviewer", msg, attach.clone()),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+
+    // members are cached: the second request doesn't look them up again
+    test.slack.expect(vec![
+        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
+        slack::req("@joe.reviewer", msg, attach.clone()),
+        slack::req("@smith.reviewer", msg, attach.clone()),
+    ]);
+    test.mock_pull_request_commits();
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_team_review_requested_team_channel() {
+    let mut test = new_test();
+    test.config.team_channels.set("some-user/reviewers", "the-reviewers").unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "review_requested".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.requested_team = Some(Team::new("reviewers", "The Reviewers"));
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    let attach = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .field("Team", "The Reviewers")
+            .build(),
+    ];
+    let msg = "Pull Request submitted for review to team The Reviewers";
+
+    test.slack.expect(vec![
+        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
+        slack::req("the-reviewers", &format!("{} {}", msg, REPO_MSG), attach.clone()),
+    ]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_review_no_username() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "review_requested".into();
     test.handler.data.pull_request = some_pr();
     if let Some(ref mut pr) = test.handler.data.pull_request {
-        pr.assignees.push(User::new("assign2"));
+        pr.requested_reviewers = Some(vec![User::new("some-unknown-reviewer")]);
     }
     test.handler.data.sender = User::new("the-pr-closer");
     test.mock_pull_request_commits();
@@ -685,17 +1935,16 @@ fn test_pull_request_assigned() {
         SlackAttachmentBuilder::new("")
             .title("Pull Request #32: \"The PR\"")
             .title_link("http://the-pr")
+            .field("Reviewers", "some-unknown-reviewer")
             .build(),
     ];
-    let msg = "Pull Request assigned to assign1, assign2";
+    let msg = "Pull Request submitted for review to some-unknown-reviewer";
 
     test.slack.expect(vec![
         slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
         slack::req("@the.pr.owner", msg, attach.clone()),
         slack::req("@assign1", msg, attach.clone()),
-        slack::req("@assign2", msg, attach.clone()),
         slack::req("@bob.author", msg, attach.clone()),
-        slack::req("@joe.reviewer", msg, attach.clone()),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
@@ -703,28 +1952,223 @@ fn test_pull_request_assigned() {
 }
 
 #[test]
-fn test_pull_request_unassigned() {
+fn test_pull_request_other() {
     let mut test = new_test();
     test.handler.event = "pull_request".into();
-    test.handler.action = "unassigned".into();
+    test.handler.action = "some-other-action".into();
     test.handler.data.pull_request = some_pr();
     test.handler.data.sender = User::new("the-pr-closer");
+
+    // should not do anything!
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+
+#[test]
+fn test_pull_request_labeled_not_merged() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "labeled".into();
+    test.handler.data.pull_request = some_pr();
+    if let Some(ref mut pr) = test.handler.data.pull_request {
+        pr.merged = Some(false);
+    }
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    // labeled but not merged --> noop
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_labeled_merge_gate() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.gate_required_labels = "qa-approved".into();
+    info.gate_team_approvals = "backend".into();
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "labeled".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.label = Some(Label::new("qa-approved"));
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    test.github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![Label::new("qa-approved")]));
+    let mut approval = Review::new("lgtm", User::new("joe-reviewer"));
+    approval.state = "APPROVED".into();
+    test.github.mock_get_pull_request_reviews("some-user", "some-repo", 32, Ok(vec![approval]));
+    test.github.mock_get_team_members("some-user", "backend", Ok(vec![User::new("sue")]));
+    test.github.mock_create_commit_status(
+        "some-user",
+        "some-repo",
+        "ffff0000",
+        &Status::new(merge_gate::GATE_CONTEXT, "failure", "This PR needs 1 approval(s) from backend, has 0"),
+        Ok(()),
+    );
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_labeled_label_rules() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.label_rules = vec![
+        repos::RepoLabelRule::new("needs-security-review").with_review_team("appsec").with_channels("security"),
+        repos::RepoLabelRule::new("docs").with_channels("docs"),
+    ];
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "labeled".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.label = Some(Label::new("Needs-Security-Review"));
+    test.handler.data.sender = User::new("the-pr-owner");
     test.mock_pull_request_commits();
 
+    test.github.mock_request_team_review("some-user", "some-repo", 32, vec!["appsec".into()], Ok(()));
+
     let attach = vec![
         SlackAttachmentBuilder::new("")
             .title("Pull Request #32: \"The PR\"")
             .title_link("http://the-pr")
             .build(),
     ];
-    let msg = "Pull Request unassigned";
+    let msg = "Pull Request labeled Needs-Security-Review by the.pr.owner";
+    test.slack.expect(vec![slack::req("security", &format!("{} {}", msg, REPO_MSG), attach)]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_labeled_automerge() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.label_rules = vec![repos::RepoLabelRule::new("automerge").merging()];
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "labeled".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.label = Some(Label::new("automerge"));
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+
+    let merges = test.config.auto_merges.get_all().unwrap();
+    assert_eq!(1, merges.len());
+    assert_eq!("some-user/some-repo", merges[0].repo);
+    assert_eq!(32, merges[0].number);
+    assert_eq!("ffff0000", merges[0].head_sha);
+    assert_eq!("the-pr-owner", merges[0].requested_by);
+}
+
+#[test]
+fn test_pull_request_synchronize_automerge() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.label_rules = vec![repos::RepoLabelRule::new("automerge").merging()];
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request".into();
+    test.handler.action = "synchronize".into();
+    test.handler.data.pull_request = some_pr();
+    if let Some(ref mut pr) = test.handler.data.pull_request {
+        pr.head.sha = "ffff1111".into();
+    }
+    test.handler.data.sender = User::new("the-pr-owner");
+
+    test.github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![Label::new("automerge")]));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+    let merges = test.config.auto_merges.get_all().unwrap();
+    assert_eq!(vec!["ffff1111"], merges.iter().map(|m| m.head_sha.as_str()).collect::<Vec<_>>());
+
+    // removing the label stops the merge
+    test.handler.action = "unlabeled".into();
+    test.handler.data.label = Some(Label::new("automerge"));
+    test.github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![]));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+    assert!(test.config.auto_merges.get_all().unwrap().is_empty());
+}
+
+#[test]
+fn test_pull_request_review_thread_resolved_merge_gate() {
+    let mut test = new_test();
+    let mut info = test.config.repos().get_all().unwrap().remove(0);
+    info.gate_resolved_threads = true;
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "pull_request_review_thread".into();
+    test.handler.action = "resolved".into();
+    test.handler.data.pull_request = some_pr();
+    test.handler.data.sender = User::new("joe-reviewer");
+
+    test.github.mock_get_unresolved_review_threads("some-user", "some-repo", 32, Ok(0));
+    test.github.mock_create_commit_status(
+        "some-user",
+        "some-repo",
+        "ffff0000",
+        &Status::new(merge_gate::GATE_CONTEXT, "success", "All merge requirements are met"),
+        Ok(()),
+    );
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr_review_thread".into()), resp);
+}
+
+#[test]
+fn test_pull_request_merged_error_getting_labels() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "closed".into();
+    test.handler.data.pull_request = some_pr();
+    if let Some(ref mut pr) = test.handler.data.pull_request {
+        pr.merged = Some(true);
+    }
+    test.handler.data.sender = User::new("the-pr-merger");
+
+    test.mock_pull_request_commits();
+    test.github.mock_get_pull_request_labels(
+        "some-user",
+        "some-repo",
+        32,
+        Err(format_err!("whooops.")),
+    );
+
+    let msg1 = "Pull Request merged";
+    let attach1 = vec![
+        SlackAttachmentBuilder::new("")
+            .title("Pull Request #32: \"The PR\"")
+            .title_link("http://the-pr")
+            .build(),
+    ];
+
+    let msg2 = "Error getting Pull Request labels";
+    let attach2 = vec![SlackAttachmentBuilder::new("whooops.").color("danger").build()];
 
     test.slack.expect(vec![
-        slack::req(
-            "the-reviews-channel",
-            &format!("{} {}", msg, REPO_MSG),
-            attach.clone()
-        ),
+        slack::req("the-reviews-channel", &format!("{} {}", msg1, REPO_MSG), attach1.clone()),
+        slack::req("@the.pr.owner", msg1, attach1.clone()),
+        slack::req("@assign1", msg1, attach1.clone()),
+        slack::req("@bob.author", msg1, attach1.clone()),
+        slack::req("@joe.reviewer", msg1, attach1.clone()),
+
+        slack::req("the-reviews-channel", &format!("{} {}", msg2, REPO_MSG), attach2.clone()),
+        slack::req("@the.pr.owner", msg2, attach2.clone()),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
@@ -732,16 +2176,18 @@ fn test_pull_request_unassigned() {
 }
 
 #[test]
-fn test_pull_request_review_requested() {
+fn test_pull_request_merged_no_labels() {
     let mut test = new_test();
     test.handler.event = "pull_request".into();
-    test.handler.action = "review_requested".into();
+    test.handler.action = "closed".into();
     test.handler.data.pull_request = some_pr();
     if let Some(ref mut pr) = test.handler.data.pull_request {
-        pr.requested_reviewers = Some(vec![User::new("joe-reviewer"), User::new("smith-reviewer")]);
+        pr.merged = Some(true);
     }
-    test.handler.data.sender = User::new("the-pr-closer");
+    test.handler.data.sender = User::new("the-pr-merger");
+
     test.mock_pull_request_commits();
+    test.github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![]));
 
     let attach = vec![
         SlackAttachmentBuilder::new("")
@@ -749,7 +2195,7 @@ fn test_pull_request_review_requested() {
             .title_link("http://the-pr")
             .build(),
     ];
-    let msg = "Pull Request submitted for review to joe.reviewer, smith.reviewer";
+    let msg = "Pull Request merged";
 
     test.slack.expect(vec![
         slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
@@ -757,25 +2203,33 @@ fn test_pull_request_review_requested() {
         slack::req("@assign1", msg, attach.clone()),
         slack::req("@bob.author", msg, attach.clone()),
         slack::req("@joe.reviewer", msg, attach.clone()),
-        slack::req("@smith.reviewer", msg, attach.clone()),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
     assert_eq!((StatusCode::OK, "pr".into()), resp);
 }
 
-
 #[test]
-fn test_pull_request_review_no_username() {
-    let mut test = new_test();
+fn test_pull_request_merged_records_merge() {
+    let mut test = new_test_configured(|config| {
+        config.compliance = Some(ComplianceConfig {
+            channel: None,
+            frequency: None,
+        });
+    });
     test.handler.event = "pull_request".into();
-    test.handler.action = "review_requested".into();
+    test.handler.action = "closed".into();
     test.handler.data.pull_request = some_pr();
     if let Some(ref mut pr) = test.handler.data.pull_request {
-        pr.requested_reviewers = Some(vec![User::new("some-unknown-reviewer")]);
+        pr.merged = Some(true);
     }
-    test.handler.data.sender = User::new("the-pr-closer");
+    test.handler.data.sender = User::new("the-pr-merger");
+
     test.mock_pull_request_commits();
+    test.github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![]));
+    let mut approval = Review::new("lgtm", User::new("joe-reviewer"));
+    approval.state = "APPROVED".into();
+    test.github.mock_get_pull_request_reviews("some-user", "some-repo", 32, Ok(vec![approval]));
 
     let attach = vec![
         SlackAttachmentBuilder::new("")
@@ -783,55 +2237,31 @@ fn test_pull_request_review_no_username() {
             .title_link("http://the-pr")
             .build(),
     ];
-    let msg = "Pull Request submitted for review to some-unknown-reviewer";
+    let msg = "Pull Request merged";
 
     test.slack.expect(vec![
         slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
         slack::req("@the.pr.owner", msg, attach.clone()),
         slack::req("@assign1", msg, attach.clone()),
         slack::req("@bob.author", msg, attach.clone()),
+        slack::req("@joe.reviewer", msg, attach.clone()),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
     assert_eq!((StatusCode::OK, "pr".into()), resp);
-}
-
-#[test]
-fn test_pull_request_other() {
-    let mut test = new_test();
-    test.handler.event = "pull_request".into();
-    test.handler.action = "some-other-action".into();
-    test.handler.data.pull_request = some_pr();
-    test.handler.data.sender = User::new("the-pr-closer");
-
-    // should not do anything!
-
-    let resp = test.handler.handle_event().unwrap();
-    assert_eq!((StatusCode::OK, "pr".into()), resp);
-}
-
-
-#[test]
-fn test_pull_request_labeled_not_merged() {
-    let mut test = new_test();
-    test.handler.event = "pull_request".into();
-    test.handler.action = "labeled".into();
-    test.handler.data.pull_request = some_pr();
-    if let Some(ref mut pr) = test.handler.data.pull_request {
-        pr.merged = Some(false);
-    }
-    test.handler.data.sender = User::new("the-pr-owner");
-    test.mock_pull_request_commits();
-
-    // labeled but not merged --> noop
 
-    let resp = test.handler.handle_event().unwrap();
-    assert_eq!((StatusCode::OK, "pr".into()), resp);
+    let merges = test.config.merges.get_between(0, std::i64::MAX).unwrap();
+    assert_eq!(1, merges.len());
+    assert_eq!(32, merges[0].number);
+    assert_eq!("master", merges[0].base_branch);
+    assert_eq!("the-pr-merger", merges[0].merged_by);
+    assert_eq!(vec!["joe-reviewer"], merges[0].approvers);
+    assert_eq!(Vec::<String>::new(), merges[0].overrides);
 }
 
 #[test]
-fn test_pull_request_merged_error_getting_labels() {
-    let mut test = new_test();
+fn test_pull_request_merged_annotates_grafana() {
+    let mut test = new_test_configured(|config| config.grafana = grafana_config());
     test.handler.event = "pull_request".into();
     test.handler.action = "closed".into();
     test.handler.data.pull_request = some_pr();
@@ -841,41 +2271,35 @@ fn test_pull_request_merged_error_getting_labels() {
     test.handler.data.sender = User::new("the-pr-merger");
 
     test.mock_pull_request_commits();
-    test.github.mock_get_pull_request_labels(
-        "some-user",
-        "some-repo",
-        32,
-        Err(format_err!("whooops.")),
-    );
+    test.github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![]));
 
-    let msg1 = "Pull Request merged";
-    let attach1 = vec![
+    let attach = vec![
         SlackAttachmentBuilder::new("")
             .title("Pull Request #32: \"The PR\"")
             .title_link("http://the-pr")
             .build(),
     ];
-
-    let msg2 = "Error getting Pull Request labels";
-    let attach2 = vec![SlackAttachmentBuilder::new("whooops.").color("danger").build()];
+    let msg = "Pull Request merged";
 
     test.slack.expect(vec![
-        slack::req("the-reviews-channel", &format!("{} {}", msg1, REPO_MSG), attach1.clone()),
-        slack::req("@the.pr.owner", msg1, attach1.clone()),
-        slack::req("@assign1", msg1, attach1.clone()),
-        slack::req("@bob.author", msg1, attach1.clone()),
-        slack::req("@joe.reviewer", msg1, attach1.clone()),
-
-        slack::req("the-reviews-channel", &format!("{} {}", msg2, REPO_MSG), attach2.clone()),
-        slack::req("@the.pr.owner", msg2, attach2.clone()),
+        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
+        slack::req("@the.pr.owner", msg, attach.clone()),
+        slack::req("@assign1", msg, attach.clone()),
+        slack::req("@bob.author", msg, attach.clone()),
+        slack::req("@joe.reviewer", msg, attach.clone()),
     ]);
+    test.annotations.expect_req(grafana::merged(
+        &test.handler.data.repository,
+        test.handler.data.pull_request.as_ref().unwrap(),
+        &User::new("the-pr-merger"),
+    ));
 
     let resp = test.handler.handle_event().unwrap();
     assert_eq!((StatusCode::OK, "pr".into()), resp);
 }
 
 #[test]
-fn test_pull_request_merged_no_labels() {
+fn test_pull_request_merged_breaking_change() {
     let mut test = new_test();
     test.handler.event = "pull_request".into();
     test.handler.action = "closed".into();
@@ -885,8 +2309,13 @@ fn test_pull_request_merged_no_labels() {
     }
     test.handler.data.sender = User::new("the-pr-merger");
 
+    test.config
+        .repos_write()
+        .insert_info(&repos::RepoInfo::new("other-user/app", "app-channel").with_depends_on("some-user/some-repo"))
+        .unwrap();
+
     test.mock_pull_request_commits();
-    test.github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![]));
+    test.github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![Label::new("breaking-change")]));
 
     let attach = vec![
         SlackAttachmentBuilder::new("")
@@ -902,6 +2331,16 @@ fn test_pull_request_merged_no_labels() {
         slack::req("@assign1", msg, attach.clone()),
         slack::req("@bob.author", msg, attach.clone()),
         slack::req("@joe.reviewer", msg, attach.clone()),
+        slack::req(
+            "app-channel",
+            "Breaking change merged into upstream repo some-user/some-repo \
+             (<http://the-github-host/other-user/app|other-user/app>)",
+            vec![SlackAttachmentBuilder::new("")
+                .title("Pull Request #32: \"The PR\"")
+                .title_link("http://the-pr")
+                .color("warning")
+                .build()],
+        ),
     ]);
 
     let resp = test.handler.handle_event().unwrap();
@@ -1050,6 +2489,46 @@ fn test_pull_request_merged_retroactively_labeled() {
     assert_eq!((StatusCode::OK, "pr".into()), resp);
 }
 
+#[test]
+fn test_pull_request_merged_labeled_revert() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "labeled".into();
+    test.handler.data.pull_request = some_pr();
+    if let Some(ref mut pr) = test.handler.data.pull_request {
+        pr.merged = Some(true);
+    }
+    test.handler.data.label = Some(Label::new("revert-me"));
+    test.handler.data.sender = User::new("the-pr-merger");
+
+    let commits = test.mock_pull_request_commits();
+
+    let pr = test.handler.data.pull_request.clone().unwrap();
+    test.pr_revert.expect_req(pr_revert::req(&test.handler.data.repository, &pr, "the-pr-merger", commits));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
+#[test]
+fn test_pull_request_labeled_revert_not_merged() {
+    let mut test = new_test();
+    test.handler.event = "pull_request".into();
+    test.handler.action = "labeled".into();
+    test.handler.data.pull_request = some_pr();
+    if let Some(ref mut pr) = test.handler.data.pull_request {
+        pr.merged = Some(false);
+    }
+    test.handler.data.label = Some(Label::new("revert-me"));
+    test.handler.data.sender = User::new("the-pr-owner");
+    test.mock_pull_request_commits();
+
+    // nothing to revert yet --> noop
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "pr".into()), resp);
+}
+
 #[test]
 fn test_pull_request_merged_master_branch() {
     let mut test = new_test();
@@ -1144,12 +2623,14 @@ fn test_push_with_pr() {
             tree_id: "".into(),
             message: "add stuff".into(),
             url: "http://commit1".into(),
+            ..PushCommit::new()
         },
         PushCommit {
             id: "1111abcdef".into(),
             tree_id: "".into(),
             message: "fix stuff".into(),
             url: "http://commit2".into(),
+            ..PushCommit::new()
         },
     ]);
 
@@ -1229,6 +2710,100 @@ fn test_push_with_pr() {
     assert_eq!((StatusCode::OK, "push".into()), resp);
 }
 
+#[test]
+fn test_push_smart_commits() {
+    let mut test = new_test_with_jira();
+    let info = test.config.repos().get_all().unwrap().remove(0).with_smart_commits("comment, time");
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "push".into();
+    test.handler.data.ref_name = Some("refs/heads/some-branch".into());
+    test.handler.data.before = Some("abcdef0000".into());
+    test.handler.data.after = Some("1111abcdef".into());
+    test.handler.data.commits = Some(vec![PushCommit {
+        id: "1111abcdef".into(),
+        tree_id: "".into(),
+        message: "Fix stuff\n\nSER-1 #time 2h wrote it #comment fixed #resolve".into(),
+        url: "http://commit1".into(),
+        ..PushCommit::new()
+    }]);
+
+    test.github.mock_get_pull_requests("some-user", "some-repo", Some("open".into()), None, Ok(vec![]));
+
+    let via = "(smart commit by joe-sender in commit [1111abc|http://commit1])";
+    if let Some(ref jira) = test.jira {
+        jira.mock_add_worklog("SER-1", "2h", &format!("wrote it\n\n{}", via), Ok(()));
+        jira.mock_comment_issue("SER-1", &format!("fixed\n\n{}", via), Ok(()));
+    }
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push".into()), resp);
+
+    // already applied: pushing the commit again (e.g. merging it) doesn't log the time again
+    test.github.mock_get_pull_requests("some-user", "some-repo", Some("open".into()), None, Ok(vec![]));
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push".into()), resp);
+}
+
+#[test]
+fn test_push_master_repo_file() {
+    let mut test = new_test_with_jira();
+    test.handler.event = "push".into();
+    test.handler.data.ref_name = Some("refs/heads/master".into());
+    test.handler.data.before = Some("abcdef0000".into());
+    test.handler.data.after = Some("1111abcdef".into());
+    let commits = some_jira_push_commits();
+    test.handler.data.commits = Some(commits.clone());
+
+    // fetched on the first push to the repo's main branch
+    test.mock_repo_file("master", Ok("channel = \"the-file-channel\"\n".into()));
+    test.expect_will_run_version_script("master", "1111abcdef", &commits);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push".into()), resp);
+
+    let repo = test.handler.data.repository.clone();
+    assert_eq!(vec!["the-file-channel"], test.config.repos().lookup_channels(&repo, "master", &commits));
+
+    // and not again until a push changes it
+    test.expect_will_run_version_script("master", "1111abcdef", &commits);
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push".into()), resp);
+}
+
+#[test]
+fn test_push_master_invalid_repo_file() {
+    let mut test = new_test();
+    test.config.repo_files.set("some-user/some-repo", "channel = \"the-file-channel\"", 1).unwrap();
+
+    test.handler.event = "push".into();
+    test.handler.data.ref_name = Some("refs/heads/master".into());
+    test.handler.data.before = Some("abcdef0000".into());
+    test.handler.data.after = Some("1111abcdef".into());
+    let commits = vec![PushCommit {
+        id: "1111abcdef".into(),
+        message: "Route docs".into(),
+        modified: vec![repo_files::FILE_NAME.into()],
+        ..PushCommit::new()
+    }];
+    test.handler.data.commits = Some(commits.clone());
+
+    test.mock_repo_file("master", Ok("[[routing_rules]]\npath = \"docs/**\"\n".into()));
+    test.slack.expect(vec![slack::req(
+        "the-reviews-channel",
+        &format!(
+            "Ignoring .octobot.toml of some-user/some-repo: Error in .octobot.toml: routing rules need `channels`, \
+             `opsgenie` or `silent` {}",
+            REPO_MSG
+        ),
+        vec![],
+    )]);
+    test.expect_will_run_version_script("master", "1111abcdef", &commits);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push".into()), resp);
+}
+
 #[test]
 fn test_push_force_notify() {
     let mut test = new_test();
@@ -1254,12 +2829,28 @@ fn test_push_force_notify() {
 
     expect_jira_ref_fail_pr(&test.github, &pr);
 
-    let msg = "joe.sender pushed 0 commit(s) to branch some-branch";
+    let mut commits = some_commits();
+    let added = commits.pop().unwrap();
+    test.github.mock_compare_commits("some-user", "some-repo", "1111abcdef", "abcdef0000", Ok(commits));
+    test.github.mock_compare_commits("some-user", "some-repo", "abcdef0000", "1111abcdef", Ok(vec![added]));
+    let mut dismissed = Review::new("", User::new("joe-reviewer"));
+    dismissed.state = "DISMISSED".into();
+    dismissed.commit_id = Some("abcdef0000".into());
+    test.github.mock_get_pull_request_reviews("some-user", "some-repo", 32, Ok(vec![dismissed]));
+
+    let msg = "joe.sender force-pushed branch some-branch (<http://compare-url|compare>)";
     let attach = vec![
         SlackAttachmentBuilder::new("")
             .title("Pull Request #32: \"The PR\"")
             .title_link("http://the-pr")
             .build(),
+        SlackAttachmentBuilder::new("<http://commit/ffeedd00110011|ffeedd0>: I made a commit!")
+            .title("Dropped 1 commit(s)")
+            .build(),
+        SlackAttachmentBuilder::new("<http://commit/ffeedd00110022|ffeedd0>: I also made a commit!")
+            .title("Added 1 commit(s)")
+            .build(),
+        SlackAttachmentBuilder::new("Approvals by joe-reviewer were dismissed").color("warning").build(),
     ];
     test.slack.expect(vec![
         slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), attach.clone()),
@@ -1355,10 +2946,148 @@ fn test_push_force_notify_ignored() {
     assert_eq!((StatusCode::OK, "push".into()), resp);
 }
 
+fn tag_alert_attachment(text: &str) -> Vec<slack::SlackAttachment> {
+    vec![SlackAttachmentBuilder::new(text)
+        .title("v1.0")
+        .title_link("http://the-github-host/some-user/some-repo/commit/abcdef0000")
+        .color("danger")
+        .build()]
+}
+
+#[test]
+fn test_push_tag_deleted() {
+    let mut test = new_test_configured(|config| {
+        config.security = Some(SecurityConfig {
+            channel: Some("the-security-channel".into()),
+            restore_tags: Some(true),
+        })
+    });
+
+    test.handler.event = "push".into();
+    test.handler.data.ref_name = Some("refs/tags/v1.0".into());
+    test.handler.data.before = Some("abcdef0000".into());
+    test.handler.data.after = Some("0000000000".into());
+    test.handler.data.deleted = Some(true);
+
+    test.slack.expect(vec![slack::req(
+        "the-security-channel",
+        &format!("Tag v1.0 was deleted by joe-sender {}", REPO_MSG),
+        tag_alert_attachment("Was: abcdef0"),
+    )]);
+    test.tag_restore.expect_req(tag_protection::req(&test.handler.data.repository, "v1.0", "abcdef0000"));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [tag]".into()), resp);
+}
+
+#[test]
+fn test_push_tag_moved() {
+    let mut test = new_test();
+
+    test.handler.event = "push".into();
+    test.handler.data.ref_name = Some("refs/tags/v1.0".into());
+    test.handler.data.before = Some("abcdef0000".into());
+    test.handler.data.after = Some("1111abcdef".into());
+    test.handler.data.forced = Some(true);
+
+    // no security channel: falls back to the repo's channel, and the tag isn't restored
+    test.slack.expect(vec![slack::req(
+        "the-reviews-channel",
+        &format!("Tag v1.0 was moved by joe-sender {}", REPO_MSG),
+        tag_alert_attachment("Was: abcdef0\nNow: 1111abc"),
+    )]);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [tag]".into()), resp);
+}
+
+#[test]
+fn test_push_tag_created() {
+    let mut test = new_test();
+
+    test.handler.event = "push".into();
+    test.handler.data.ref_name = Some("refs/tags/v1.0".into());
+    test.handler.data.before = Some("0000000000".into());
+    test.handler.data.after = Some("1111abcdef".into());
+    test.handler.data.created = Some(true);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [ignored]".into()), resp);
+}
+
+#[test]
+fn test_push_tag_created_release_versions() {
+    let mut test = new_test_with_jira();
+    let info = test.config.repos().get_all().unwrap().remove(0).with_jira_release_versions(true);
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "push".into();
+    test.handler.data.ref_name = Some("refs/tags/v1.2.0".into());
+    test.handler.data.before = Some("0000000000".into());
+    test.handler.data.after = Some("1111abcdef".into());
+    test.handler.data.created = Some(true);
+
+    test.release_versions.expect_req(release_versions::req(
+        &test.handler.data.repository,
+        "refs/tags/v1.2.0",
+        "1111abcdef",
+    ));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [ignored]".into()), resp);
+
+    // not a version
+    test.handler.data.ref_name = Some("refs/tags/nightly".into());
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [ignored]".into()), resp);
+}
+
+#[test]
+fn test_push_release_branch_created() {
+    let mut test = new_test_with_jira();
+    let info = test.config.repos().get_all().unwrap().remove(0).with_jira_release_versions(true);
+    test.config.repos_write().update(&info).unwrap();
+
+    test.handler.event = "push".into();
+    test.handler.data.ref_name = Some("refs/heads/release/1.3".into());
+    test.handler.data.before = Some("0000000000".into());
+    test.handler.data.after = Some("1111abcdef".into());
+    test.handler.data.created = Some(true);
+
+    test.release_versions.expect_req(release_versions::req(
+        &test.handler.data.repository,
+        "refs/heads/release/1.3",
+        "1111abcdef",
+    ));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [ignored]".into()), resp);
+
+    // other new branches aren't releases
+    test.handler.data.ref_name = Some("refs/heads/release-notes".into());
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [ignored]".into()), resp);
+}
+
+#[test]
+fn test_push_release_branch_created_not_enabled() {
+    let mut test = new_test_with_jira();
+
+    test.handler.event = "push".into();
+    test.handler.data.ref_name = Some("refs/heads/release/1.3".into());
+    test.handler.data.before = Some("0000000000".into());
+    test.handler.data.after = Some("1111abcdef".into());
+    test.handler.data.created = Some(true);
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [ignored]".into()), resp);
+}
+
 fn new_issue(key: &str) -> jira::Issue {
     jira::Issue {
         key: key.into(),
         status: None,
+        fields: jira::IssueFields::default(),
     }
 }
 
@@ -1413,6 +3142,7 @@ fn some_jira_push_commits() -> Vec<PushCommit> {
             tree_id: "ffeedd00110011".into(),
             url: "http://commit/ffeedd00110011".into(),
             message: "Fix [SER-1] Add the feature\n\nThe body ([OTHER-123])".into(),
+            ..PushCommit::new()
         },
     ]
 }
@@ -1521,6 +3251,7 @@ fn test_jira_pull_request_opened_too_many_commits() {
 #[test]
 fn test_jira_push_master() {
     let mut test = new_test_with_jira();
+    test.mock_repo_file("master", Err(format_err!("404 Not Found")));
     test.handler.event = "push".into();
     test.handler.data.ref_name = Some("refs/heads/master".into());
     test.handler.data.before = Some("abcdef0000".into());
@@ -1537,6 +3268,7 @@ fn test_jira_push_master() {
 #[test]
 fn test_jira_push_develop() {
     let mut test = new_test_with_jira();
+    test.mock_repo_file("develop", Err(format_err!("404 Not Found")));
     test.handler.event = "push".into();
     test.handler.data.ref_name = Some("refs/heads/develop".into());
     test.handler.data.before = Some("abcdef0000".into());
@@ -1633,8 +3365,70 @@ fn test_jira_push_triggers_version_script() {
     let commits = some_jira_push_commits();
     test.handler.data.commits = Some(commits.clone());
 
+    test.github.mock_get_file_contents(
+        "some-user",
+        "versioning-repo",
+        repo_files::FILE_NAME,
+        "master",
+        Err(format_err!("404 Not Found")),
+    );
     test.expect_will_run_version_script("master", "1111abcdef", &commits);
 
     let resp = test.handler.handle_event().unwrap();
     assert_eq!((StatusCode::OK, "push".into()), resp);
 }
+
+#[test]
+fn test_push_tag_blocked_by_change_request() {
+    let mut test = new_test_with_servicenow();
+    test.config
+        .change_requests
+        .add(&ChangeRequest {
+            repo: "some-user/some-repo".into(),
+            number: 32,
+            branch: "release/1.0".into(),
+            change_number: "CHG0030001".into(),
+            sys_id: "the-sys-id".into(),
+            approval: "requested".into(),
+            created_at: 10,
+        })
+        .unwrap();
+
+    test.handler.event = "push".into();
+    test.handler.data.ref_name = Some("refs/tags/v1.0".into());
+    test.handler.data.before = Some("0000000000".into());
+    test.handler.data.after = Some("1111abcdef".into());
+    test.handler.data.created = Some(true);
+
+    test.slack.expect(vec![slack::req(
+        "the-reviews-channel",
+        &format!(
+            "Deleting tag v1.0 pushed by joe-sender: change request CHG0030001 of PR #32 is not approved {}",
+            REPO_MSG
+        ),
+        vec![],
+    )]);
+    test.tag_restore.expect_req(tag_protection::delete_req(&test.handler.data.repository, "v1.0"));
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [tag blocked]".into()), resp);
+
+    // its deletion isn't taken for tampering
+    test.handler.data.created = Some(false);
+    test.handler.data.deleted = Some(true);
+    test.handler.data.before = Some("1111abcdef".into());
+    test.handler.data.after = Some("0000000000".into());
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [ignored]".into()), resp);
+
+    // approved: tags go through
+    test.config.change_requests.set_approval("CHG0030001", servicenow::APPROVED).unwrap();
+    test.handler.data.created = Some(true);
+    test.handler.data.deleted = Some(false);
+    test.handler.data.before = Some("0000000000".into());
+    test.handler.data.after = Some("1111abcdef".into());
+
+    let resp = test.handler.handle_event().unwrap();
+    assert_eq!((StatusCode::OK, "push [ignored]".into()), resp);
+}
@@ -0,0 +1,60 @@
+mod mocks;
+
+use std::sync::Arc;
+
+use octobot::jira::api::{JiraVersionPosition, Session};
+use octobot::jira::multi::MultiSession;
+use octobot::jira::*;
+
+use mocks::mock_jira::MockJira;
+
+fn version(uri: &str, name: &str) -> Version {
+    Version {
+        uri: uri.into(),
+        id: "1".into(),
+        name: name.into(),
+    }
+}
+
+#[test]
+fn test_routes_by_project() {
+    let on_prem = Arc::new(MockJira::new());
+    let cloud = Arc::new(MockJira::new());
+    let jira = MultiSession::new(on_prem.clone()).with_instance(
+        vec!["CLOUD".into(), "WEB".into()],
+        "https://company.atlassian.net/rest/api/2",
+        cloud.clone(),
+    );
+
+    on_prem.mock_comment_issue("SER-1", "on prem", Ok(()));
+    cloud.mock_comment_issue("CLOUD-1", "in the cloud", Ok(()));
+    cloud.mock_get_transitions("WEB-99", Ok(vec![]));
+    on_prem.mock_get_versions("SER", Ok(vec![]));
+    cloud.mock_add_version("WEB", "1.2.3", Ok(()));
+
+    jira.comment_issue("SER-1", "on prem").unwrap();
+    jira.comment_issue("CLOUD-1", "in the cloud").unwrap();
+    assert!(jira.get_transitions("WEB-99").unwrap().is_empty());
+    assert!(jira.get_versions("SER").unwrap().is_empty());
+    jira.add_version("WEB", "1.2.3").unwrap();
+}
+
+#[test]
+fn test_routes_versions_by_url() {
+    let on_prem = Arc::new(MockJira::new());
+    let cloud = Arc::new(MockJira::new());
+    let jira = MultiSession::new(on_prem.clone()).with_instance(
+        vec!["CLOUD".into()],
+        "https://company.atlassian.net/rest/api/2",
+        cloud.clone(),
+    );
+
+    let cloud_version = version("https://company.atlassian.net/rest/api/2/version/1", "1.0");
+    let on_prem_version = version("https://jira.company.com/rest/api/2/version/1", "1.0");
+
+    cloud.mock_reorder_version(&cloud_version, JiraVersionPosition::First, Ok(()));
+    on_prem.mock_reorder_version(&on_prem_version, JiraVersionPosition::First, Ok(()));
+
+    jira.reorder_version(&cloud_version, JiraVersionPosition::First).unwrap();
+    jira.reorder_version(&on_prem_version, JiraVersionPosition::First).unwrap();
+}
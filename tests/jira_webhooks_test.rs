@@ -0,0 +1,179 @@
+mod mocks;
+
+use std::sync::Arc;
+
+use tempdir::TempDir;
+
+use octobot::config::{Config, JiraConfig};
+use octobot::db::Database;
+use octobot::jira::webhooks::{self, WebhookEvent};
+use octobot::messenger;
+use octobot::repos::{RepoInfo, RepoJiraConfig};
+use octobot::slack::{self, SlackAttachmentBuilder};
+use octobot::users::{self, UserInfo};
+
+use mocks::mock_slack::MockSlack;
+
+struct JiraWebhooksTest {
+    config: Arc<Config>,
+    jira: JiraConfig,
+    _temp_dir: TempDir,
+}
+
+fn new_test() -> JiraWebhooksTest {
+    let temp_dir = TempDir::new("jira_webhooks_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let config = Arc::new(Config::new(db));
+    let mut bob = UserInfo::new("bob-github", "bob.slack");
+    bob.email = "bob@company.com".into();
+    config.users_write().insert_info(&bob).unwrap();
+    config.users_write().insert("joe", "joe.slack").unwrap();
+    config.users_write().insert("sue", "sue.slack").unwrap();
+
+    config.repos_write().insert_info(&RepoInfo::new("some-org/server", "server-reviews").with_jira("SER")).unwrap();
+    config
+        .repos_write()
+        .insert_info(
+            &RepoInfo::new("some-org/client", "client-reviews")
+                .with_jira("CLI")
+                .with_jira_config(RepoJiraConfig::new("SER").with_channel("server-news")),
+        )
+        .unwrap();
+
+    let jira = JiraConfig {
+        host: "https://the-jira".into(),
+        username: "the-jira-user".into(),
+        password: "the-jira-pass".into(),
+        progress_states: None,
+        review_states: None,
+        resolved_states: None,
+        fixed_resolutions: None,
+        fix_versions_field: None,
+        pending_versions_field: None,
+        restrict_comment_visibility_to_role: None,
+        login_suffix: Some("@company.com".into()),
+        deployment: None,
+        oauth: None,
+        transitions: None,
+        projects: None,
+        pr_updates: None,
+        project_pr_updates: None,
+        templates: None,
+        webhook_secret: Some("the-secret".into()),
+    };
+
+    JiraWebhooksTest {
+        config: config,
+        jira: jira,
+        _temp_dir: temp_dir,
+    }
+}
+
+fn notify(test: &JiraWebhooksTest, slack: &MockSlack, event: &str, project: Option<&str>) {
+    let event: WebhookEvent = serde_json::from_str(event).unwrap();
+    let messenger = messenger::new(test.config.clone(), slack.new_sender()).for_dm_event(users::DM_JIRA);
+    webhooks::notify(&event, project, &test.jira, &test.config, &messenger);
+}
+
+#[test]
+fn test_issue_transitioned() {
+    let test = new_test();
+
+    let msg = "Sue Smith moved <https://the-jira/browse/SER-1|SER-1: Fix &lt;it&gt;> from Open to Resolved";
+    let slack = MockSlack::new(vec![
+        slack::req("@bob.slack", msg, vec![]),
+        slack::req("server-news", msg, vec![]),
+        slack::req("server-reviews", msg, vec![]),
+    ]);
+
+    // sue is the reporter, but made the change
+    notify(
+        &test,
+        &slack,
+        r#"{"webhookEvent": "jira:issue_updated", "issue_event_type_name": "issue_generic",
+            "user": {"name": "sue@company.com", "displayName": "Sue Smith"},
+            "issue": {"key": "SER-1", "fields": {"summary": "Fix <it>", "project": {"key": "SER"},
+                      "assignee": {"accountId": "1234", "emailAddress": "Bob@Company.com"},
+                      "reporter": {"name": "sue@company.com"}}},
+            "changelog": {"items": [{"field": "status", "fromString": "Open", "toString": "Resolved"}]}}"#,
+        None,
+    );
+}
+
+#[test]
+fn test_issue_updated_not_transitioned() {
+    let test = new_test();
+
+    let slack = MockSlack::new(vec![]);
+    notify(
+        &test,
+        &slack,
+        r#"{"webhookEvent": "jira:issue_updated", "issue_event_type_name": "issue_updated",
+            "issue": {"key": "SER-1", "fields": {"assignee": {"name": "joe"}}},
+            "changelog": {"items": [{"field": "summary", "fromString": "Old", "toString": "New"}]}}"#,
+        None,
+    );
+}
+
+#[test]
+fn test_comment_added() {
+    let test = new_test();
+
+    let msg = "joe commented on <https://the-jira/browse/CLI-2|CLI-2: Crash>";
+    let attach = vec![SlackAttachmentBuilder::new("What do you think [~sue]? [~unknown]").build()];
+    let slack = MockSlack::new(vec![
+        slack::req("@bob.slack", msg, attach.clone()),
+        slack::req("@sue.slack", msg, attach.clone()),
+    ]);
+
+    // comments don't go to channels, and joe doesn't hear about their own comment
+    notify(
+        &test,
+        &slack,
+        r#"{"webhookEvent": "comment_created",
+            "issue": {"key": "CLI-2", "fields": {"summary": "Crash",
+                      "assignee": {"name": "joe"}, "reporter": {"name": "bob", "emailAddress": "bob@company.com"}}},
+            "comment": {"body": "What do you think [~sue]? [~unknown]", "author": {"name": "joe"}}}"#,
+        None,
+    );
+}
+
+#[test]
+fn test_comment_dm_muted() {
+    let test = new_test();
+    let mut bob = test.config.users().lookup_info("bob-github").unwrap();
+    bob.dm_events = "pull_request, review".into();
+    test.config.users_write().update(&bob).unwrap();
+
+    let slack = MockSlack::new(vec![]);
+    notify(
+        &test,
+        &slack,
+        r#"{"webhookEvent": "comment_created",
+            "issue": {"key": "CLI-2", "fields": {"reporter": {"emailAddress": "bob@company.com"}}},
+            "comment": {"body": "Hello", "author": {"name": "joe"}}}"#,
+        None,
+    );
+}
+
+#[test]
+fn test_version_released() {
+    let test = new_test();
+
+    let msg = "SER version 1.2.0 was released";
+    let attach = vec![SlackAttachmentBuilder::new("The big one").build()];
+    let slack = MockSlack::new(vec![
+        slack::req("server-news", msg, attach.clone()),
+        slack::req("server-reviews", msg, attach.clone()),
+    ]);
+
+    let event = r#"{"webhookEvent": "jira:version_released",
+                    "version": {"id": "10", "name": "1.2.0", "projectId": 100, "description": "The big one"}}"#;
+    notify(&test, &slack, event, Some("SER"));
+
+    // without the project from the webhook URL there's nowhere to send it
+    let slack = MockSlack::new(vec![]);
+    notify(&test, &slack, event, None);
+}
@@ -1,8 +1,9 @@
 mod mocks;
 
+use failure::format_err;
 use maplit::hashmap;
 
-use octobot::config::JiraConfig;
+use octobot::config::{JiraConfig, JiraTemplates, JiraTransitionsConfig};
 use octobot::github;
 use octobot::jira;
 use octobot::jira::*;
@@ -29,6 +30,14 @@ fn new_test() -> JiraWorkflowTest {
         pending_versions_field: Some("the-pending-versions".into()),
         restrict_comment_visibility_to_role: None,
         login_suffix: None,
+        deployment: None,
+        oauth: None,
+        transitions: None,
+        projects: None,
+        pr_updates: None,
+        project_pr_updates: None,
+        templates: None,
+        webhook_secret: None,
     };
 
     JiraWorkflowTest {
@@ -65,6 +74,7 @@ fn new_issue(key: &str, status: Option<&str>) -> Issue {
     Issue {
         key: key.into(),
         status: status.map(|s| Status { name: s.to_string() }),
+        fields: IssueFields::default(),
     }
 }
 
@@ -124,6 +134,218 @@ fn test_submit_for_review() {
     jira::workflow::submit_for_review(&pr, &vec![commit], &projects, &test.jira, &test.config);
 }
 
+#[test]
+fn test_submit_for_review_templates() {
+    let mut test = new_test();
+    test.config.templates = Some(JiraTemplates {
+        review_submitted: Some(
+            "{{key}}: {{author}} wants a review of #{{pr_number}} on {{branch}} \
+             ({{#each commits}}{{this.hash}}{{/each}})"
+                .into(),
+        ),
+        ..JiraTemplates::default()
+    });
+    let mut pr = new_pr();
+    pr.number = 5;
+    pr.user = github::User::new("joe");
+    let projects = vec!["SER".to_string(), "CLI".to_string()];
+    let commit = new_commit("Fix [SER-1] I fixed it. And also relates to [CLI-9999]", "aabbccddee");
+
+    test.jira.mock_comment_issue("SER-1", "SER-1: joe wants a review of #5 on master (aabbccd)", Ok(()));
+    // no template: octobot's own wording
+    test.jira.mock_comment_issue(
+        "CLI-9999",
+        "Referenced by review submitted for branch master: http://the-pr",
+        Ok(()),
+    );
+
+    test.jira.mock_get_issue("SER-1", Ok(new_issue("SER-1", Some("reviewing1"))));
+    test.jira.mock_get_issue("CLI-9999", Ok(new_issue("CLI-9999", Some("progress1"))));
+
+    jira::workflow::submit_for_review(&pr, &vec![commit], &projects, &test.jira, &test.config);
+}
+
+#[test]
+fn test_submit_for_review_project_transitions() {
+    let mut test = new_test();
+    test.config.transitions = Some(vec![JiraTransitionsConfig {
+        project: Some("SER".into()),
+        repo: None,
+        progress: None,
+        review: Some(vec!["ser-review".into()]),
+        resolved: None,
+        reopened: None,
+    }]);
+    let pr = new_pr();
+    let projects = vec!["SER".to_string(), "CLI".to_string()];
+    let commit = new_commit("Fix [SER-1][CLI-1] I fixed it.", "aabbccddee");
+
+    test.jira.mock_comment_issue("CLI-1", "Review submitted for branch master: http://the-pr", Ok(()));
+    test.jira.mock_comment_issue("SER-1", "Review submitted for branch master: http://the-pr", Ok(()));
+
+    test.jira.mock_get_issue("CLI-1", Ok(new_issue("CLI-1", Some("progress1"))));
+    test.jira.mock_get_issue("SER-1", Ok(new_issue("SER-1", Some("progress1"))));
+
+    // CLI uses the global review states, SER its own
+    test.jira.mock_get_transitions("CLI-1", Ok(vec![new_transition("002", "reviewing1")]));
+    test.jira.mock_transition_issue("CLI-1", &new_transition_req("002"), Ok(()));
+    test.jira.mock_get_transitions(
+        "SER-1",
+        Ok(vec![new_transition("002", "reviewing1"), new_transition("012", "ser-review")]),
+    );
+    test.jira.mock_transition_issue("SER-1", &new_transition_req("012"), Ok(()));
+
+    jira::workflow::submit_for_review(&pr, &vec![commit], &projects, &test.jira, &test.config);
+}
+
+#[test]
+fn test_resolve_issue_reverted() {
+    let test = new_test();
+    let projects = vec!["SER".to_string()];
+    let commit = new_push_commit(
+        "Revert \"Fix [SER-1] I fixed it.\"\n\nThis reverts commit aabbccddee.",
+        "ffeeddccbb",
+    );
+
+    let comment = "Reverted in branch master: [ffeeddc|http://the-commit/ffeeddccbb]\n\
+                   {quote}Revert \"Fix [SER-1] I fixed it.\"{quote}";
+    test.jira.mock_comment_issue("SER-1", comment, Ok(()));
+
+    test.jira.mock_get_issue("SER-1", Ok(new_issue("SER-1", Some("resolved1"))));
+    test.jira.mock_get_transitions(
+        "SER-1",
+        Ok(vec![new_transition("003", "resolved1"), new_transition("005", "Reopened")]),
+    );
+    test.jira.mock_transition_issue("SER-1", &new_transition_req("005"), Ok(()));
+
+    jira::workflow::resolve_issue(
+        "some-org/some-repo",
+        "master",
+        None,
+        &vec![commit],
+        &projects,
+        &test.jira,
+        &test.config,
+    );
+}
+
+#[test]
+fn test_submit_for_review_links() {
+    let mut test = new_test();
+    test.config.project_pr_updates = Some(hashmap! { "SER".to_string() => "links".to_string() });
+    let mut pr = new_pr();
+    pr.number = 32;
+    pr.base.repo.full_name = "some-org/some-repo".into();
+    let projects = vec!["SER".to_string()];
+    let commit = new_commit("Fix [SER-1] I fixed it.", "aabbccddee");
+
+    // the summary comment is created the first time, then edited
+    test.jira.mock_add_remote_link("SER-1", "http://the-pr", "Open", false, Ok(()));
+    test.jira.mock_get_comments("SER-1", Ok(vec![Comment::new("100", "Someone else's comment")]));
+    test.jira.mock_comment_issue(
+        "SER-1",
+        "Pull requests and commits (kept up to date by octobot):\n\
+         * Pull request [some-org/some-repo#32|http://the-pr] is in review for branch master",
+        Ok(()),
+    );
+    test.jira.mock_get_issue("SER-1", Ok(new_issue("SER-1", Some("reviewing1"))));
+
+    jira::workflow::submit_for_review(&pr, &vec![commit.clone()], &projects, &test.jira, &test.config);
+
+    test.jira.mock_add_remote_link("SER-1", "http://the-pr", "Merged", true, Ok(()));
+    test.jira.mock_get_comments(
+        "SER-1",
+        Ok(vec![Comment::new(
+            "101",
+            "Pull requests and commits (kept up to date by octobot):\n\
+             * Pull request [some-org/some-repo#32|http://the-pr] is in review for branch master",
+        )]),
+    );
+    test.jira.mock_update_comment(
+        "SER-1",
+        "101",
+        "Pull requests and commits (kept up to date by octobot):\n\
+         * Pull request [some-org/some-repo#32|http://the-pr] was merged into branch master",
+        Ok(()),
+    );
+
+    jira::workflow::mark_merged(&pr, &vec![commit], &projects, &test.jira, &test.config);
+}
+
+#[test]
+fn test_mark_merged_comments_only() {
+    let test = new_test();
+    let projects = vec!["SER".to_string()];
+    let commit = new_commit("Fix [SER-1] I fixed it.", "aabbccddee");
+
+    // nothing to do until the merge is pushed
+    jira::workflow::mark_merged(&new_pr(), &vec![commit], &projects, &test.jira, &test.config);
+}
+
+#[test]
+fn test_resolve_issue_links() {
+    let mut test = new_test();
+    test.config.pr_updates = Some("links".into());
+    let projects = vec!["SER".to_string()];
+    let commit = new_push_commit("Fix [SER-1] I fixed it.", "aabbccddee");
+
+    let summary = "Pull requests and commits (kept up to date by octobot):\n\
+                   * Pull request [some-org/some-repo#32|http://the-pr] was merged into branch master";
+    test.jira.mock_get_comments("SER-1", Ok(vec![Comment::new("101", summary)]));
+    test.jira.mock_update_comment(
+        "SER-1",
+        "101",
+        &format!(
+            "{}\n* Commit [aabbccd|http://the-commit/aabbccddee] merged into branch master (version 1.2.0)",
+            summary
+        ),
+        Ok(()),
+    );
+    test.jira.mock_get_issue("SER-1", Ok(new_issue("SER-1", Some("resolved1"))));
+
+    jira::workflow::resolve_issue(
+        "some-org/some-repo",
+        "master",
+        Some("1.2.0"),
+        &vec![commit],
+        &projects,
+        &test.jira,
+        &test.config,
+    );
+}
+
+#[test]
+fn test_validate_transitions() {
+    let mut test = new_test();
+    test.config.transitions = Some(vec![JiraTransitionsConfig {
+        project: None,
+        repo: Some("some-org/some-repo".into()),
+        progress: None,
+        review: None,
+        resolved: Some(vec!["resolved1".into(), "041".into()]),
+        reopened: Some(vec!["Reopen".into()]),
+    }]);
+
+    test.jira.mock_get_project_statuses(
+        "SER",
+        Ok(vec![Status { name: "progress1".into() }, Status { name: "reviewing1".into() }]),
+    );
+    test.jira.mock_get_transitions("SER-1", Ok(vec![new_transition("041", "Resolve")]));
+
+    let checks =
+        jira::workflow::validate_transitions("SER", "some-org/some-repo", Some("SER-1"), &test.jira, &test.config)
+            .unwrap();
+    assert_eq!(
+        vec![
+            ("progress", Some("progress1".to_string())),
+            ("review", Some("reviewing1".to_string())),
+            ("resolved", Some("041".to_string())),
+            ("reopened", None),
+        ],
+        checks.iter().map(|c| (c.action.as_str(), c.found.clone())).collect::<Vec<_>>()
+    );
+}
+
 #[test]
 fn test_resolve_issue_no_resolution() {
     let test = new_test();
@@ -152,7 +374,41 @@ fn test_resolve_issue_no_resolution() {
     // should only transition if necessary
     test.jira.mock_get_issue("CLI-9999", Ok(new_issue("CLI-9999", Some("resolved2"))));
 
-    jira::workflow::resolve_issue("master", None, &vec![commit1, commit2], &projects, &test.jira, &test.config);
+    jira::workflow::resolve_issue(
+        "some-org/some-repo",
+        "master",
+        None,
+        &vec![commit1, commit2],
+        &projects,
+        &test.jira,
+        &test.config,
+    );
+}
+
+#[test]
+fn test_resolve_issue_templates() {
+    let mut test = new_test();
+    test.config.templates = Some(JiraTemplates {
+        merged: Some(
+            "{{key}} fixed by {{commit.hash}} in {{repo}} {{branch}}{{#if version}} ({{version}}){{/if}}".into(),
+        ),
+        ..JiraTemplates::default()
+    });
+    let projects = vec!["SER".to_string()];
+    let commit = new_push_commit("Fix [SER-1] I fixed it", "aabbccddee");
+
+    test.jira.mock_comment_issue("SER-1", "SER-1 fixed by aabbccd in some-org/some-repo master (1.2.3)", Ok(()));
+    test.jira.mock_get_issue("SER-1", Ok(new_issue("SER-1", Some("resolved1"))));
+
+    jira::workflow::resolve_issue(
+        "some-org/some-repo",
+        "master",
+        Some("1.2.3"),
+        &vec![commit],
+        &projects,
+        &test.jira,
+        &test.config,
+    );
 }
 
 #[test]
@@ -202,7 +458,15 @@ fn test_resolve_issue_with_resolution() {
     test.jira.mock_get_transitions("SER2-1", Ok(vec![trans]));
     test.jira.mock_transition_issue("SER2-1", &req, Ok(()));
 
-    jira::workflow::resolve_issue("release/99", Some("5.6.7"), &vec![commit], &projects, &test.jira, &test.config);
+    jira::workflow::resolve_issue(
+        "some-org/some-repo",
+        "release/99",
+        Some("5.6.7"),
+        &vec![commit],
+        &projects,
+        &test.jira,
+        &test.config,
+    );
 }
 
 #[test]
@@ -275,6 +539,33 @@ fn test_add_pending_version() {
     jira::workflow::add_pending_version(Some("5.6.7"), &vec![commit], &projects, &test.jira);
 }
 
+#[test]
+fn test_assign_release_version() {
+    let test = new_test();
+    let projects = vec!["SER".to_string(), "CLI".to_string(), "OTHER".to_string()];
+    let commits = vec![
+        new_push_commit("Fix [SER-1] I fixed it.\n\nand it is kinda related to [CLI-45]", "aabbccddee"),
+        new_push_commit("SER-2: Another one, and SER-1 again", "bbccddeeff"),
+    ];
+
+    test.jira.mock_get_versions("SER", Ok(vec![Version::new("1.1.0"), Version::new("1.2.0")]));
+    test.jira.mock_assign_fix_version("SER-1", "1.2.0", Ok(()));
+    test.jira.mock_assign_fix_version("SER-2", "1.2.0", Err(format_err!("Issue is closed")));
+
+    test.jira.mock_get_versions("CLI", Ok(vec![Version::new("1.1.0")]));
+    test.jira.mock_add_version("CLI", "1.2.0", Ok(()));
+    test.jira.mock_assign_fix_version("CLI-45", "1.2.0", Ok(()));
+
+    test.jira.mock_get_versions("OTHER", Err(format_err!("No such project")));
+
+    let results = jira::workflow::assign_release_version("1.2.0", &commits, &projects, &test.jira);
+    assert_eq!(3, results.len());
+    assert_eq!(("SER", vec!["SER-1".to_string()]), (results[0].0.as_str(), results[0].1.as_ref().unwrap().clone()));
+    assert_eq!(("CLI", vec!["CLI-45".to_string()]), (results[1].0.as_str(), results[1].1.as_ref().unwrap().clone()));
+    assert_eq!("OTHER", results[2].0);
+    assert!(results[2].1.is_err());
+}
+
 #[test]
 fn test_merge_pending_versions_for_real() {
     let test = new_test();
@@ -0,0 +1,83 @@
+mod mocks;
+
+use octobot::config::TaxonomyLabel;
+use octobot::github;
+use octobot::label_taxonomy::{self, Drift};
+
+use mocks::mock_github::MockGithub;
+
+fn taxonomy() -> Vec<TaxonomyLabel> {
+    vec![
+        TaxonomyLabel {
+            name: "bug".into(),
+            color: "d73a4a".into(),
+            description: "Something isn't working".into(),
+            renamed_from: vec!["defect".into()],
+        },
+        TaxonomyLabel {
+            name: "docs".into(),
+            color: "0075ca".into(),
+            description: String::new(),
+            renamed_from: vec![],
+        },
+        TaxonomyLabel {
+            name: "security".into(),
+            color: "#B60205".into(),
+            description: "Needs a security review".into(),
+            renamed_from: vec!["sec".into()],
+        },
+    ]
+}
+
+fn label(name: &str, color: &str) -> github::Label {
+    let mut label = github::Label::new(name);
+    label.color = Some(color.into());
+    label
+}
+
+fn repo() -> github::Repo {
+    github::Repo::parse("http://the-github-host/some-user/some-repo").unwrap()
+}
+
+#[test]
+fn test_sync_repo() {
+    let github = MockGithub::new();
+    github.mock_get_repo_labels(
+        "some-user",
+        "some-repo",
+        Ok(vec![label("defect", "ff0000"), label("Docs", "0075ca"), label("sec", "b60205"), label("security", "")]),
+    );
+    github.mock_update_label("some-user", "some-repo", "defect", "bug", "d73a4a", "Something isn't working", Ok(()));
+    github.mock_update_label("some-user", "some-repo", "Docs", "docs", "0075ca", "", Ok(()));
+
+    let drift = label_taxonomy::sync_repo(&github, &repo(), &taxonomy(), false).unwrap();
+    assert_eq!(
+        vec![
+            Drift::OldName("defect".into(), taxonomy()[0].clone()),
+            Drift::Changed("Docs".into(), taxonomy()[1].clone()),
+            Drift::Duplicate("sec".into(), taxonomy()[2].clone()),
+        ],
+        drift
+    );
+}
+
+#[test]
+fn test_sync_repo_creates_missing() {
+    let github = MockGithub::new();
+    github.mock_get_repo_labels("some-user", "some-repo", Ok(vec![label("docs", "0075CA")]));
+    github.mock_create_label("some-user", "some-repo", "bug", "d73a4a", "Something isn't working", Ok(()));
+    github.mock_create_label("some-user", "some-repo", "security", "b60205", "Needs a security review", Ok(()));
+
+    let drift = label_taxonomy::sync_repo(&github, &repo(), &taxonomy(), false).unwrap();
+    assert_eq!(2, drift.len());
+}
+
+#[test]
+fn test_sync_repo_report_only() {
+    let github = MockGithub::new();
+    github.mock_get_repo_labels("some-user", "some-repo", Ok(vec![]));
+
+    // Note: nothing is created
+    let drift = label_taxonomy::sync_repo(&github, &repo(), &taxonomy(), true).unwrap();
+    assert_eq!(3, drift.len());
+}
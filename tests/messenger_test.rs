@@ -4,13 +4,19 @@ use std::sync::Arc;
 
 use tempdir::TempDir;
 
-use octobot::config::Config;
-use octobot::db::Database;
+use octobot::config::{Config, EmailConfig};
+use octobot::db::{self, Database};
+use octobot::diagnostics::Trace;
+use octobot::email;
+use octobot::error_reports;
 use octobot::github;
 use octobot::messenger;
-use octobot::slack;
+use octobot::repos::RepoInfo;
+use octobot::slack::{self, SlackAttachmentBuilder};
+use octobot::users;
 
 use mocks::mock_slack::MockSlack;
+use mocks::mock_worker::LockedMockWorker;
 
 fn new_test() -> (Arc<Config>, TempDir) {
     let temp_dir = TempDir::new("repos.rs").unwrap();
@@ -90,6 +96,87 @@ fn test_sends_to_owner_with_channel() {
     );
 }
 
+#[test]
+fn test_dry_run_only_logs() {
+    let (config, _temp) = new_test();
+
+    config
+        .repos_write()
+        .insert_info(&RepoInfo::new("the-owner/the-repo", "the-review-channel").with_dry_run(Some(true)))
+        .unwrap();
+
+    let slack = MockSlack::new(vec![]);
+    let messenger = messenger::new(config, slack.new_sender());
+
+    messenger.send_to_all(
+        "hello there",
+        &vec![],
+        &github::User::new("the-owner"),
+        &github::User::new("the-sender"),
+        &github::Repo::parse("http://git.foo.com/the-owner/the-repo").unwrap(),
+        &vec![],
+        "",
+        &Vec::<github::Commit>::new(),
+    );
+}
+
+#[test]
+fn test_sends_to_channel_in_pr_thread() {
+    let (config, _temp) = new_test();
+
+    config
+        .repos_write()
+        .insert_info(&RepoInfo::new("the-owner/the-repo", "the-review-channel").with_slack_threads(true))
+        .unwrap();
+
+    // Note: only channel messages are threaded
+    let slack = MockSlack::new(vec![
+        slack::threaded_req(
+            "the-review-channel",
+            "hello there (<http://git.foo.com/the-owner/the-repo|the-owner/the-repo>)",
+            vec![],
+            "the-owner/the-repo#32",
+        ),
+        slack::req("@the.owner", "hello there", vec![]),
+    ]);
+    let messenger = messenger::new(config, slack.new_sender());
+    let repo = github::Repo::parse("http://git.foo.com/the-owner/the-repo").unwrap();
+
+    messenger.in_pr_thread(&repo, 32).send_to_all(
+        "hello there",
+        &vec![],
+        &github::User::new("the-owner"),
+        &github::User::new("the-sender"),
+        &repo,
+        &vec![],
+        "",
+        &Vec::<github::Commit>::new(),
+    );
+}
+
+#[test]
+fn test_no_pr_thread_unless_enabled() {
+    let (config, _temp) = new_test();
+
+    config.repos_write().insert("the-owner/the-repo", "the-review-channel").unwrap();
+
+    let slack = MockSlack::new(vec![slack::req(
+        "the-review-channel",
+        "hello there (<http://git.foo.com/the-owner/the-repo|the-owner/the-repo>)",
+        vec![],
+    )]);
+    let messenger = messenger::new(config, slack.new_sender());
+    let repo = github::Repo::parse("http://git.foo.com/the-owner/the-repo").unwrap();
+
+    messenger.in_pr_thread(&repo, 32).send_to_channel(
+        "hello there",
+        &vec![],
+        &repo,
+        "",
+        &Vec::<github::Commit>::new(),
+    );
+}
+
 #[test]
 fn test_sends_to_assignees() {
     let (config, _temp) = new_test();
@@ -187,3 +274,195 @@ fn test_peace_and_quiet() {
         &Vec::<github::Commit>::new(),
     );
 }
+
+#[test]
+fn test_trace_explains_suppressed_messages() {
+    let (config, _temp) = new_test();
+
+    let slack = MockSlack::new(vec![slack::req("@the.owner", "hello there", vec![])]);
+    let trace = Arc::new(Trace::new());
+    let messenger = messenger::new(config, slack.new_sender()).with_trace(trace.clone());
+
+    messenger.send_to_all(
+        "hello there",
+        &vec![],
+        &github::User::new("the-owner"),
+        &github::User::new("the-sender"),
+        &github::Repo::parse("http://git.foo.com/some-org/some-repo").unwrap(),
+        &vec![github::User::new("the-sender"), github::User::new("nobody")],
+        "master",
+        &Vec::<github::Commit>::new(),
+    );
+
+    assert_eq!(
+        vec![
+            "No channel configured for repo 'some-org/some-repo' on branch 'master'",
+            "Not messaging 'the-sender': they triggered the event",
+            "Sent direct message to 'the-owner'",
+            "Not messaging 'nobody': no slack user is mapped",
+        ],
+        trace.steps()
+    );
+    assert_eq!(1, trace.notifications());
+}
+
+#[test]
+fn test_respects_notification_preferences() {
+    let (config, _temp) = new_test();
+
+    config.users_write().insert("assign2", "assign2").unwrap();
+
+    let mut user = config.users().lookup_info("the-owner").unwrap();
+    user.dm_events = users::DM_REVIEW.into();
+    config.users_write().update(&user).unwrap();
+
+    // the owner only wants to hear about reviews
+    let slack = MockSlack::new(vec![slack::req("@assign2", "hello there", vec![])]);
+    let trace = Arc::new(Trace::new());
+    let messenger = messenger::new(config, slack.new_sender())
+        .with_trace(trace.clone())
+        .for_dm_event(users::DM_COMMENT);
+
+    messenger.send_to_all(
+        "hello there",
+        &vec![],
+        &github::User::new("the-owner"),
+        &github::User::new("the-sender"),
+        &github::Repo::parse("http://git.foo.com/some-org/some-repo").unwrap(),
+        &vec![github::User::new("assign2")],
+        "master",
+        &Vec::<github::Commit>::new(),
+    );
+
+    assert!(trace
+        .steps()
+        .contains(&"Not messaging 'the-owner': they turned off 'comment' direct messages".to_string()));
+}
+
+#[test]
+fn test_respects_quiet_hours() {
+    let (config, _temp) = new_test();
+
+    // quiet for the current hour (in UTC)
+    let hour = (db::now() / 3600) % 24;
+    let mut user = config.users().lookup_info("the-owner").unwrap();
+    user.quiet_hours_start = format!("{:02}:00", hour);
+    user.quiet_hours_end = format!("{:02}:00", (hour + 1) % 24);
+    config.users_write().update(&user).unwrap();
+
+    let slack = MockSlack::new(vec![]);
+    let messenger = messenger::new(config, slack.new_sender());
+
+    messenger.send_to_user(&github::User::new("the-owner"), "hello there", &vec![]);
+}
+
+#[test]
+fn test_batch_key_for_pr_messages() {
+    let temp_dir = TempDir::new("messenger_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let mut config = Config::new(db);
+    config.main.slack_batch_window_secs = Some(10);
+    config.users_write().insert("the-owner", "the.owner").unwrap();
+    config.repos_write().insert("some-org/some-repo", "the-channel").unwrap();
+    let config = Arc::new(config);
+
+    let repo = github::Repo::parse("http://git.foo.com/some-org/some-repo").unwrap();
+    let batched = |channel: &str, msg: &str| {
+        let mut req = slack::req(channel, msg, vec![]);
+        req.batch_key = Some("some-org/some-repo#32".into());
+        req
+    };
+
+    let slack = MockSlack::new(vec![
+        batched("the-channel", "hello there (<http://git.foo.com/some-org/some-repo|some-org/some-repo>)"),
+        batched("@the.owner", "hello there"),
+        slack::req("@the.owner", "not about a PR", vec![]),
+    ]);
+    let messenger = messenger::new(config, slack.new_sender());
+
+    messenger.in_pr_thread(&repo, 32).send_to_owner(
+        "hello there",
+        &vec![],
+        &github::User::new("the-owner"),
+        &repo,
+        "master",
+        &Vec::<github::Commit>::new(),
+    );
+    messenger.send_to_user(&github::User::new("the-owner"), "not about a PR", &vec![]);
+}
+
+#[test]
+fn test_emails_users_without_slack() {
+    let temp_dir = TempDir::new("messenger_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let mut config = Config::new(db);
+    config.email = Some(EmailConfig {
+        smtp_host: "smtp.company.com".into(),
+        smtp_port: None,
+        username: None,
+        password: None,
+        tls: None,
+        from: "octobot@company.com".into(),
+        default_domain: Some("company.com".into()),
+        imap_host: None,
+        imap_port: None,
+        imap_username: None,
+        imap_password: None,
+        imap_mailbox: None,
+    });
+    config.users_write().insert("the-owner", "the.owner").unwrap();
+    let mut joe = users::UserInfo::new("joe-reviewer", "");
+    joe.email = "joe@elsewhere.com".into();
+    config.users_write().insert_info(&joe).unwrap();
+    let config = Arc::new(config);
+
+    let slack = MockSlack::new(vec![slack::req("@the.owner", "hello there", vec![])]);
+    let email = LockedMockWorker::new("email");
+    email.expect_req(email::req("joe@elsewhere.com", "hello there", &vec![]));
+    email.expect_req(email::req("jane-reviewer@company.com", "hello there", &vec![]));
+
+    let messenger = messenger::new(config, slack.new_sender())
+        .with_email(email.new_sender())
+        .with_email_fallback(vec!["joe-reviewer".into(), "jane-reviewer".into()]);
+
+    // "someone-else" has no slack user either, but is not one of the users to email
+    messenger.send_to_all(
+        "hello there",
+        &vec![],
+        &github::User::new("the-owner"),
+        &github::User::new("the-sender"),
+        &github::Repo::new(),
+        &vec![
+            github::User::new("joe-reviewer"),
+            github::User::new("jane-reviewer"),
+            github::User::new("someone-else"),
+        ],
+        "",
+        &Vec::<github::Commit>::new(),
+    );
+}
+
+#[test]
+fn test_failures_mention_trace_id() {
+    let (config, _temp) = new_test();
+
+    let attach = vec![SlackAttachmentBuilder::new("merge conflict").color("danger").build()];
+    let slack = MockSlack::new(vec![
+        slack::req("@the.owner", "Error creating merge PR (trace 0123456789abcdef)", attach.clone()),
+        slack::req("@the.owner", "Merge PR created", vec![]),
+    ]);
+    let messenger = messenger::new(config, slack.new_sender());
+
+    let context = error_reports::Context {
+        request_id: "0123456789abcdef".into(),
+        ..error_reports::Context::default()
+    };
+    error_reports::with_context(context, || {
+        messenger.send_to_user(&github::User::new("the-owner"), "Error creating merge PR", &attach);
+        messenger.send_to_user(&github::User::new("the-owner"), "Merge PR created", &vec![]);
+    });
+}
@@ -12,21 +12,47 @@ pub struct MockGithub {
     get_pr_calls: Mutex<Vec<MockCall<PullRequest>>>,
     get_prs_calls: Mutex<Vec<MockCall<Vec<PullRequest>>>>,
     create_pr_calls: Mutex<Vec<MockCall<PullRequest>>>,
+    create_draft_pr_calls: Mutex<Vec<MockCall<PullRequest>>>,
     get_pr_labels_calls: Mutex<Vec<MockCall<Vec<Label>>>>,
     add_pr_labels_calls: Mutex<Vec<MockCall<()>>>,
+    remove_pr_label_calls: Mutex<Vec<MockCall<()>>>,
+    get_repo_labels_calls: Mutex<Vec<MockCall<Vec<Label>>>>,
+    create_label_calls: Mutex<Vec<MockCall<()>>>,
+    update_label_calls: Mutex<Vec<MockCall<()>>>,
     get_pr_commits_calls: Mutex<Vec<MockCall<Vec<Commit>>>>,
     get_pr_reviews_calls: Mutex<Vec<MockCall<Vec<Review>>>>,
+    get_unresolved_threads_calls: Mutex<Vec<MockCall<u32>>>,
+    create_commit_status_calls: Mutex<Vec<MockCall<()>>>,
+    get_pr_files_calls: Mutex<Vec<MockCall<Vec<PullRequestFile>>>>,
+    get_file_contents_calls: Mutex<Vec<MockCall<String>>>,
     assign_pr_calls: Mutex<Vec<MockCall<()>>>,
     request_review_calls: Mutex<Vec<MockCall<()>>>,
+    request_team_review_calls: Mutex<Vec<MockCall<()>>>,
+    get_team_members_calls: Mutex<Vec<MockCall<Vec<User>>>>,
+    get_org_members_calls: Mutex<Vec<MockCall<Vec<User>>>>,
+    get_org_repos_calls: Mutex<Vec<MockCall<Vec<Repo>>>>,
+    get_org_teams_calls: Mutex<Vec<MockCall<Vec<Team>>>>,
+    create_webhook_calls: Mutex<Vec<MockCall<()>>>,
+    get_user_calls: Mutex<Vec<MockCall<User>>>,
     comment_pr_calls: Mutex<Vec<MockCall<()>>>,
+    get_pr_comments_calls: Mutex<Vec<MockCall<Vec<IssueComment>>>>,
+    edit_comment_calls: Mutex<Vec<MockCall<()>>>,
     create_branch_calls: Mutex<Vec<MockCall<()>>>,
     delete_branch_calls: Mutex<Vec<MockCall<()>>>,
     approve_pull_request_calls: Mutex<Vec<MockCall<()>>>,
+    merge_pull_request_calls: Mutex<Vec<MockCall<()>>>,
     get_timeline_calls: Mutex<Vec<MockCall<Vec<TimelineEvent>>>>,
     get_suites_calls: Mutex<Vec<MockCall<Vec<CheckSuite>>>>,
+    get_check_runs_calls: Mutex<Vec<MockCall<Vec<CheckRun>>>>,
     get_check_run_calls: Mutex<Vec<MockCall<CheckRun>>>,
     create_check_run_calls: Mutex<Vec<MockCall<u32>>>,
     update_check_run_calls: Mutex<Vec<MockCall<()>>>,
+    get_check_run_annotations_calls: Mutex<Vec<MockCall<Vec<CheckAnnotation>>>>,
+    get_job_log_calls: Mutex<Vec<MockCall<String>>>,
+    get_release_by_tag_calls: Mutex<Vec<MockCall<Release>>>,
+    get_latest_release_calls: Mutex<Vec<MockCall<Release>>>,
+    compare_commits_calls: Mutex<Vec<MockCall<Vec<Commit>>>>,
+    upload_release_asset_calls: Mutex<Vec<MockCall<ReleaseAsset>>>,
 }
 
 #[derive(Debug)]
@@ -53,21 +79,47 @@ impl MockGithub {
             get_pr_calls: Mutex::new(vec![]),
             get_prs_calls: Mutex::new(vec![]),
             create_pr_calls: Mutex::new(vec![]),
+            create_draft_pr_calls: Mutex::new(vec![]),
             get_pr_labels_calls: Mutex::new(vec![]),
             add_pr_labels_calls: Mutex::new(vec![]),
+            remove_pr_label_calls: Mutex::new(vec![]),
+            get_repo_labels_calls: Mutex::new(vec![]),
+            create_label_calls: Mutex::new(vec![]),
+            update_label_calls: Mutex::new(vec![]),
             get_pr_commits_calls: Mutex::new(vec![]),
             get_pr_reviews_calls: Mutex::new(vec![]),
+            get_unresolved_threads_calls: Mutex::new(vec![]),
+            create_commit_status_calls: Mutex::new(vec![]),
+            get_pr_files_calls: Mutex::new(vec![]),
+            get_file_contents_calls: Mutex::new(vec![]),
             assign_pr_calls: Mutex::new(vec![]),
             request_review_calls: Mutex::new(vec![]),
+            request_team_review_calls: Mutex::new(vec![]),
+            get_team_members_calls: Mutex::new(vec![]),
+            get_org_members_calls: Mutex::new(vec![]),
+            get_org_repos_calls: Mutex::new(vec![]),
+            get_org_teams_calls: Mutex::new(vec![]),
+            create_webhook_calls: Mutex::new(vec![]),
+            get_user_calls: Mutex::new(vec![]),
             comment_pr_calls: Mutex::new(vec![]),
+            get_pr_comments_calls: Mutex::new(vec![]),
+            edit_comment_calls: Mutex::new(vec![]),
             create_branch_calls: Mutex::new(vec![]),
             delete_branch_calls: Mutex::new(vec![]),
             approve_pull_request_calls: Mutex::new(vec![]),
+            merge_pull_request_calls: Mutex::new(vec![]),
             get_timeline_calls: Mutex::new(vec![]),
             get_suites_calls: Mutex::new(vec![]),
+            get_check_runs_calls: Mutex::new(vec![]),
             get_check_run_calls: Mutex::new(vec![]),
             create_check_run_calls: Mutex::new(vec![]),
             update_check_run_calls: Mutex::new(vec![]),
+            get_check_run_annotations_calls: Mutex::new(vec![]),
+            get_job_log_calls: Mutex::new(vec![]),
+            get_release_by_tag_calls: Mutex::new(vec![]),
+            get_latest_release_calls: Mutex::new(vec![]),
+            compare_commits_calls: Mutex::new(vec![]),
+            upload_release_asset_calls: Mutex::new(vec![]),
         }
     }
 }
@@ -90,6 +142,11 @@ impl Drop for MockGithub {
                 "Unmet create_pull_request calls: {:?}",
                 *self.create_pr_calls.lock().unwrap()
             );
+            assert!(
+                self.create_draft_pr_calls.lock().unwrap().len() == 0,
+                "Unmet create_draft_pull_request calls: {:?}",
+                *self.create_draft_pr_calls.lock().unwrap()
+            );
             assert!(
                 self.get_pr_labels_calls.lock().unwrap().len() == 0,
                 "Unmet get_pull_request_labels calls: {:?}",
@@ -100,6 +157,46 @@ impl Drop for MockGithub {
                 "Unmet add_pull_request_labels calls: {:?}",
                 *self.add_pr_labels_calls.lock().unwrap()
             );
+            assert!(
+                self.get_pr_files_calls.lock().unwrap().len() == 0,
+                "Unmet get_pull_request_files calls: {:?}",
+                *self.get_pr_files_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_file_contents_calls.lock().unwrap().len() == 0,
+                "Unmet get_file_contents calls: {:?}",
+                *self.get_file_contents_calls.lock().unwrap()
+            );
+            assert!(
+                self.remove_pr_label_calls.lock().unwrap().len() == 0,
+                "Unmet remove_pull_request_label calls: {:?}",
+                *self.remove_pr_label_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_repo_labels_calls.lock().unwrap().len() == 0,
+                "Unmet get_repo_labels calls: {:?}",
+                *self.get_repo_labels_calls.lock().unwrap()
+            );
+            assert!(
+                self.create_label_calls.lock().unwrap().len() == 0,
+                "Unmet create_label calls: {:?}",
+                *self.create_label_calls.lock().unwrap()
+            );
+            assert!(
+                self.update_label_calls.lock().unwrap().len() == 0,
+                "Unmet update_label calls: {:?}",
+                *self.update_label_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_unresolved_threads_calls.lock().unwrap().len() == 0,
+                "Unmet get_unresolved_review_threads calls: {:?}",
+                *self.get_unresolved_threads_calls.lock().unwrap()
+            );
+            assert!(
+                self.create_commit_status_calls.lock().unwrap().len() == 0,
+                "Unmet create_commit_status calls: {:?}",
+                *self.create_commit_status_calls.lock().unwrap()
+            );
             assert!(
                 self.assign_pr_calls.lock().unwrap().len() == 0,
                 "Unmet assign_pull_request calls: {:?}",
@@ -110,11 +207,56 @@ impl Drop for MockGithub {
                 "Unmet request_review calls: {:?}",
                 *self.request_review_calls.lock().unwrap()
             );
+            assert!(
+                self.request_team_review_calls.lock().unwrap().len() == 0,
+                "Unmet request_team_review calls: {:?}",
+                *self.request_team_review_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_team_members_calls.lock().unwrap().len() == 0,
+                "Unmet get_team_members calls: {:?}",
+                *self.get_team_members_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_org_members_calls.lock().unwrap().len() == 0,
+                "Unmet get_org_members calls: {:?}",
+                *self.get_org_members_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_org_repos_calls.lock().unwrap().len() == 0,
+                "Unmet get_org_repos calls: {:?}",
+                *self.get_org_repos_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_org_teams_calls.lock().unwrap().len() == 0,
+                "Unmet get_org_teams calls: {:?}",
+                *self.get_org_teams_calls.lock().unwrap()
+            );
+            assert!(
+                self.create_webhook_calls.lock().unwrap().len() == 0,
+                "Unmet create_webhook calls: {:?}",
+                *self.create_webhook_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_user_calls.lock().unwrap().len() == 0,
+                "Unmet get_user calls: {:?}",
+                *self.get_user_calls.lock().unwrap()
+            );
             assert!(
                 self.comment_pr_calls.lock().unwrap().len() == 0,
                 "Unmet comment_pull_request calls: {:?}",
                 *self.comment_pr_calls.lock().unwrap()
             );
+            assert!(
+                self.get_pr_comments_calls.lock().unwrap().len() == 0,
+                "Unmet get_pull_request_comments calls: {:?}",
+                *self.get_pr_comments_calls.lock().unwrap()
+            );
+            assert!(
+                self.edit_comment_calls.lock().unwrap().len() == 0,
+                "Unmet edit_comment calls: {:?}",
+                *self.edit_comment_calls.lock().unwrap()
+            );
             assert!(
                 self.create_branch_calls.lock().unwrap().len() == 0,
                 "Unmet create_branch calls: {:?}",
@@ -130,11 +272,51 @@ impl Drop for MockGithub {
                 "Unmet approve_pull_request calls: {:?}",
                 *self.approve_pull_request_calls.lock().unwrap()
             );
+            assert!(
+                self.merge_pull_request_calls.lock().unwrap().len() == 0,
+                "Unmet merge_pull_request calls: {:?}",
+                *self.merge_pull_request_calls.lock().unwrap()
+            );
             assert!(
                 self.get_timeline_calls.lock().unwrap().len() == 0,
                 "Unmet get_timeline calls: {:?}",
                 *self.get_timeline_calls.lock().unwrap()
             );
+            assert!(
+                self.get_release_by_tag_calls.lock().unwrap().len() == 0,
+                "Unmet get_release_by_tag calls: {:?}",
+                *self.get_release_by_tag_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_latest_release_calls.lock().unwrap().len() == 0,
+                "Unmet get_latest_release calls: {:?}",
+                *self.get_latest_release_calls.lock().unwrap()
+            );
+            assert!(
+                self.compare_commits_calls.lock().unwrap().len() == 0,
+                "Unmet compare_commits calls: {:?}",
+                *self.compare_commits_calls.lock().unwrap()
+            );
+            assert!(
+                self.upload_release_asset_calls.lock().unwrap().len() == 0,
+                "Unmet upload_release_asset calls: {:?}",
+                *self.upload_release_asset_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_check_runs_calls.lock().unwrap().len() == 0,
+                "Unmet get_check_runs calls: {:?}",
+                *self.get_check_runs_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_check_run_annotations_calls.lock().unwrap().len() == 0,
+                "Unmet get_check_run_annotations calls: {:?}",
+                *self.get_check_run_annotations_calls.lock().unwrap()
+            );
+            assert!(
+                self.get_job_log_calls.lock().unwrap().len() == 0,
+                "Unmet get_job_log calls: {:?}",
+                *self.get_job_log_calls.lock().unwrap()
+            );
         }
     }
 }
@@ -209,6 +391,28 @@ impl Session for MockGithub {
         call.ret
     }
 
+    fn create_draft_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        title: &str,
+        body: &str,
+        head: &str,
+        base: &str,
+    ) -> Result<PullRequest> {
+        let mut calls = self.create_draft_pr_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to create_draft_pull_request");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], title);
+        assert_eq!(call.args[3], body);
+        assert_eq!(call.args[4], head);
+        assert_eq!(call.args[5], base);
+
+        call.ret
+    }
+
     fn get_pull_request_labels(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<Label>> {
         let mut calls = self.get_pr_labels_calls.lock().unwrap();
         assert!(calls.len() > 0, "Unexpected call to get_pull_request_labels");
@@ -232,6 +436,55 @@ impl Session for MockGithub {
         call.ret
     }
 
+    fn remove_pull_request_label(&self, owner: &str, repo: &str, number: u32, label: &str) -> Result<()> {
+        let mut calls = self.remove_pr_label_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to remove_pull_request_label");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], number.to_string());
+        assert_eq!(call.args[3], label);
+
+        call.ret
+    }
+
+    fn get_repo_labels(&self, owner: &str, repo: &str) -> Result<Vec<Label>> {
+        let mut calls = self.get_repo_labels_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_repo_labels");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+
+        call.ret
+    }
+
+    fn create_label(&self, owner: &str, repo: &str, label: &Label) -> Result<()> {
+        let mut calls = self.create_label_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to create_label");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], label.name);
+        assert_eq!(call.args[3], label.color.clone().unwrap_or(String::new()));
+        assert_eq!(call.args[4], label.description.clone().unwrap_or(String::new()));
+
+        call.ret
+    }
+
+    fn update_label(&self, owner: &str, repo: &str, name: &str, label: &Label) -> Result<()> {
+        let mut calls = self.update_label_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to update_label");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], name);
+        assert_eq!(call.args[3], label.name);
+        assert_eq!(call.args[4], label.color.clone().unwrap_or(String::new()));
+        assert_eq!(call.args[5], label.description.clone().unwrap_or(String::new()));
+
+        call.ret
+    }
+
     fn get_pull_request_commits(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<Commit>> {
         let mut calls = self.get_pr_commits_calls.lock().unwrap();
         assert!(calls.len() > 0, "Unexpected call to get_pull_request_commits");
@@ -254,6 +507,54 @@ impl Session for MockGithub {
         call.ret
     }
 
+    fn get_unresolved_review_threads(&self, owner: &str, repo: &str, number: u32) -> Result<u32> {
+        let mut calls = self.get_unresolved_threads_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_unresolved_review_threads");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], number.to_string());
+
+        call.ret
+    }
+
+    fn create_commit_status(&self, owner: &str, repo: &str, sha: &str, status: &Status) -> Result<()> {
+        let mut calls = self.create_commit_status_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to create_commit_status");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], sha);
+        assert_eq!(call.args[3], status.context.clone().unwrap_or_default());
+        assert_eq!(call.args[4], status.state);
+        assert_eq!(call.args[5], status.description.clone().unwrap_or_default());
+
+        call.ret
+    }
+
+    fn get_pull_request_files(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<PullRequestFile>> {
+        let mut calls = self.get_pr_files_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_pull_request_files");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], number.to_string());
+
+        call.ret
+    }
+
+    fn get_file_contents(&self, owner: &str, repo: &str, path: &str, git_ref: &str) -> Result<String> {
+        let mut calls = self.get_file_contents_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_file_contents");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], path);
+        assert_eq!(call.args[3], git_ref);
+
+        call.ret
+    }
+
     fn assign_pull_request(&self, owner: &str, repo: &str, number: u32, assignees: Vec<String>) -> Result<()> {
         let mut calls = self.assign_pr_calls.lock().unwrap();
         assert!(calls.len() > 0, "Unexpected call to assign_pull_request");
@@ -278,6 +579,76 @@ impl Session for MockGithub {
         call.ret
     }
 
+    fn request_team_review(&self, owner: &str, repo: &str, number: u32, teams: Vec<String>) -> Result<()> {
+        let mut calls = self.request_team_review_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to request_team_review");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], number.to_string());
+        assert_eq!(call.args[3], teams.join(","));
+
+        call.ret
+    }
+
+    fn get_team_members(&self, org: &str, team_slug: &str) -> Result<Vec<User>> {
+        let mut calls = self.get_team_members_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_team_members");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], org);
+        assert_eq!(call.args[1], team_slug);
+
+        call.ret
+    }
+
+    fn get_org_members(&self, org: &str) -> Result<Vec<User>> {
+        let mut calls = self.get_org_members_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_org_members");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], org);
+
+        call.ret
+    }
+
+    fn get_org_repos(&self, org: &str) -> Result<Vec<Repo>> {
+        let mut calls = self.get_org_repos_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_org_repos");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], org);
+
+        call.ret
+    }
+
+    fn get_org_teams(&self, org: &str) -> Result<Vec<Team>> {
+        let mut calls = self.get_org_teams_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_org_teams");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], org);
+
+        call.ret
+    }
+
+    fn create_webhook(&self, owner: &str, repo: &str, url: &str, secret: &str) -> Result<()> {
+        let mut calls = self.create_webhook_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to create_webhook");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], url);
+        assert_eq!(call.args[3], secret);
+
+        call.ret
+    }
+
+    fn get_user(&self, login: &str) -> Result<User> {
+        let mut calls = self.get_user_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_user");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], login);
+
+        call.ret
+    }
+
     fn comment_pull_request(&self, owner: &str, repo: &str, number: u32, comment: &str) -> Result<()> {
         let mut calls = self.comment_pr_calls.lock().unwrap();
         assert!(calls.len() > 0, "Unexpected call to comment_pull_request");
@@ -290,6 +661,29 @@ impl Session for MockGithub {
         call.ret
     }
 
+    fn get_pull_request_comments(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<IssueComment>> {
+        let mut calls = self.get_pr_comments_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_pull_request_comments");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], number.to_string());
+
+        call.ret
+    }
+
+    fn edit_comment(&self, owner: &str, repo: &str, comment_id: u64, comment: &str) -> Result<()> {
+        let mut calls = self.edit_comment_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to edit_comment");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], comment_id.to_string());
+        assert_eq!(call.args[3], comment);
+
+        call.ret
+    }
+
     fn create_branch(&self, owner: &str, repo: &str, branch_name: &str, sha: &str) -> Result<()> {
         let mut calls = self.create_branch_calls.lock().unwrap();
         assert!(calls.len() > 0, "Unexpected call to create_branch");
@@ -333,6 +727,28 @@ impl Session for MockGithub {
         call.ret
     }
 
+    fn merge_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        number: u32,
+        commit_hash: &str,
+        options: &MergeOptions,
+    ) -> Result<()> {
+        let mut calls = self.merge_pull_request_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to merge_pull_request");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], number.to_string());
+        assert_eq!(call.args[3], commit_hash);
+        assert_eq!(call.args[4], options.merge_method.as_deref().unwrap_or(""));
+        assert_eq!(call.args[5], options.commit_title.as_deref().unwrap_or(""));
+        assert_eq!(call.args[6], options.commit_message.as_deref().unwrap_or(""));
+
+        call.ret
+    }
+
     fn get_timeline(&self, owner: &str, repo: &str, number: u32) -> Result<Vec<TimelineEvent>> {
         let mut calls = self.get_timeline_calls.lock().unwrap();
         assert!(calls.len() > 0, "Unexpected call to get_timeline");
@@ -353,6 +769,17 @@ impl Session for MockGithub {
         call.ret
     }
 
+    fn get_check_runs(&self, owner: &str, repo: &str, git_ref: &str) -> Result<Vec<CheckRun>> {
+        let mut calls = self.get_check_runs_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_check_runs");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], git_ref);
+
+        call.ret
+    }
+
     fn get_check_run(&self, pr: &PullRequest, id: u32) -> Result<CheckRun> {
         let mut calls = self.get_check_run_calls.lock().unwrap();
         assert!(calls.len() > 0, "Unexpected call to get_check_run");
@@ -382,10 +809,77 @@ impl Session for MockGithub {
 
         call.ret
     }
+
+    fn get_check_run_annotations(&self, owner: &str, repo: &str, check_run_id: u64) -> Result<Vec<CheckAnnotation>> {
+        let mut calls = self.get_check_run_annotations_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_check_run_annotations");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], check_run_id.to_string());
+
+        call.ret
+    }
+
+    fn get_job_log(&self, owner: &str, repo: &str, job_id: u64) -> Result<String> {
+        let mut calls = self.get_job_log_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_job_log");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], job_id.to_string());
+
+        call.ret
+    }
+
+    fn get_release_by_tag(&self, owner: &str, repo: &str, tag: &str) -> Result<Release> {
+        let mut calls = self.get_release_by_tag_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_release_by_tag");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], tag);
+
+        call.ret
+    }
+
+    fn get_latest_release(&self, owner: &str, repo: &str) -> Result<Release> {
+        let mut calls = self.get_latest_release_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_latest_release");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+
+        call.ret
+    }
+
+    fn compare_commits(&self, owner: &str, repo: &str, base: &str, head: &str) -> Result<Vec<Commit>> {
+        let mut calls = self.compare_commits_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to compare_commits");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], owner);
+        assert_eq!(call.args[1], repo);
+        assert_eq!(call.args[2], base);
+        assert_eq!(call.args[3], head);
+
+        call.ret
+    }
+
+    fn upload_release_asset(&self, release: &Release, name: &str, content_type: &str, contents: &[u8]) -> Result<ReleaseAsset> {
+        let mut calls = self.upload_release_asset_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to upload_release_asset");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], release.tag_name);
+        assert_eq!(call.args[1], name);
+        assert_eq!(call.args[2], content_type);
+        assert_eq!(call.args[3], String::from_utf8_lossy(contents));
+
+        call.ret
+    }
 }
 
 impl MockGithub {
-    pub fn get_pull_request(&self, owner: &str, repo: &str, number: u32, ret: Result<PullRequest>) {
+    pub fn mock_get_pull_request(&self, owner: &str, repo: &str, number: u32, ret: Result<PullRequest>) {
         self.get_pr_calls.lock().unwrap().push(MockCall::new(
             ret,
             vec![owner, repo, &number.to_string()],
@@ -427,6 +921,19 @@ impl MockGithub {
         ));
     }
 
+    pub fn mock_create_draft_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        title: &str,
+        body: &str,
+        head: &str,
+        base: &str,
+        ret: Result<PullRequest>,
+    ) {
+        self.create_draft_pr_calls.lock().unwrap().push(MockCall::new(ret, vec![owner, repo, title, body, head, base]));
+    }
+
     pub fn mock_get_pull_request_labels(&self, owner: &str, repo: &str, number: u32, ret: Result<Vec<Label>>) {
         self.get_pr_labels_calls.lock().unwrap().push(MockCall::new(
             ret,
@@ -441,6 +948,45 @@ impl MockGithub {
         ));
     }
 
+    pub fn mock_remove_pull_request_label(&self, owner: &str, repo: &str, number: u32, label: &str, ret: Result<()>) {
+        self.remove_pr_label_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![owner, repo, &number.to_string(), label],
+        ));
+    }
+
+    pub fn mock_get_repo_labels(&self, owner: &str, repo: &str, ret: Result<Vec<Label>>) {
+        self.get_repo_labels_calls.lock().unwrap().push(MockCall::new(ret, vec![owner, repo]));
+    }
+
+    pub fn mock_create_label(
+        &self,
+        owner: &str,
+        repo: &str,
+        name: &str,
+        color: &str,
+        description: &str,
+        ret: Result<()>,
+    ) {
+        self.create_label_calls.lock().unwrap().push(MockCall::new(ret, vec![owner, repo, name, color, description]));
+    }
+
+    pub fn mock_update_label(
+        &self,
+        owner: &str,
+        repo: &str,
+        name: &str,
+        new_name: &str,
+        color: &str,
+        description: &str,
+        ret: Result<()>,
+    ) {
+        self.update_label_calls
+            .lock()
+            .unwrap()
+            .push(MockCall::new(ret, vec![owner, repo, name, new_name, color, description]));
+    }
+
     pub fn mock_get_pull_request_commits(&self, owner: &str, repo: &str, number: u32, ret: Result<Vec<Commit>>) {
         self.get_pr_commits_calls.lock().unwrap().push(MockCall::new(
             ret,
@@ -455,6 +1001,36 @@ impl MockGithub {
         ));
     }
 
+    pub fn mock_get_unresolved_review_threads(&self, owner: &str, repo: &str, number: u32, ret: Result<u32>) {
+        self.get_unresolved_threads_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![owner, repo, &number.to_string()],
+        ));
+    }
+
+    pub fn mock_create_commit_status(&self, owner: &str, repo: &str, sha: &str, status: &Status, ret: Result<()>) {
+        let context = status.context.clone().unwrap_or_default();
+        let description = status.description.clone().unwrap_or_default();
+        self.create_commit_status_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![owner, repo, sha, &context, &status.state, &description],
+        ));
+    }
+
+    pub fn mock_get_pull_request_files(&self, owner: &str, repo: &str, number: u32, ret: Result<Vec<PullRequestFile>>) {
+        self.get_pr_files_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![owner, repo, &number.to_string()],
+        ));
+    }
+
+    pub fn mock_get_file_contents(&self, owner: &str, repo: &str, path: &str, git_ref: &str, ret: Result<String>) {
+        self.get_file_contents_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![owner, repo, path, git_ref],
+        ));
+    }
+
     pub fn mock_comment_pull_request(&self, owner: &str, repo: &str, number: u32, comment: &str, ret: Result<()>) {
         self.comment_pr_calls.lock().unwrap().push(MockCall::new(
             ret,
@@ -462,6 +1038,20 @@ impl MockGithub {
         ));
     }
 
+    pub fn mock_get_pull_request_comments(&self, owner: &str, repo: &str, number: u32, ret: Result<Vec<IssueComment>>) {
+        self.get_pr_comments_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![owner, repo, &number.to_string()],
+        ));
+    }
+
+    pub fn mock_edit_comment(&self, owner: &str, repo: &str, comment_id: u64, comment: &str, ret: Result<()>) {
+        self.edit_comment_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![owner, repo, &comment_id.to_string(), comment],
+        ));
+    }
+
     pub fn mock_assign_pull_request(
         &self,
         owner: &str,
@@ -493,6 +1083,42 @@ impl MockGithub {
         ));
     }
 
+    pub fn mock_get_team_members(&self, org: &str, team_slug: &str, ret: Result<Vec<User>>) {
+        self.get_team_members_calls.lock().unwrap().push(MockCall::new(ret, vec![org, team_slug]));
+    }
+
+    pub fn mock_get_org_members(&self, org: &str, ret: Result<Vec<User>>) {
+        self.get_org_members_calls.lock().unwrap().push(MockCall::new(ret, vec![org]));
+    }
+
+    pub fn mock_get_org_repos(&self, org: &str, ret: Result<Vec<Repo>>) {
+        self.get_org_repos_calls.lock().unwrap().push(MockCall::new(ret, vec![org]));
+    }
+
+    pub fn mock_get_org_teams(&self, org: &str, ret: Result<Vec<Team>>) {
+        self.get_org_teams_calls.lock().unwrap().push(MockCall::new(ret, vec![org]));
+    }
+
+    pub fn mock_create_webhook(&self, owner: &str, repo: &str, url: &str, secret: &str, ret: Result<()>) {
+        self.create_webhook_calls.lock().unwrap().push(MockCall::new(ret, vec![owner, repo, url, secret]));
+    }
+
+    pub fn mock_get_user(&self, login: &str, ret: Result<User>) {
+        self.get_user_calls.lock().unwrap().push(MockCall::new(ret, vec![login]));
+    }
+
+    pub fn mock_request_team_review(&self, owner: &str, repo: &str, number: u32, teams: Vec<String>, ret: Result<()>) {
+        self.request_team_review_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![
+                owner,
+                repo,
+                &number.to_string(),
+                &teams.join(","),
+            ],
+        ));
+    }
+
     pub fn mock_create_branch(&self, owner: &str, repo: &str, branch_name: &str, sha: &str, ret: Result<()>) {
         self.create_branch_calls.lock().unwrap().push(MockCall::new(
             ret,
@@ -527,6 +1153,29 @@ impl MockGithub {
         ));
     }
 
+    pub fn mock_merge_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        number: u32,
+        commit_hash: &str,
+        options: &MergeOptions,
+        ret: Result<()>,
+    ) {
+        self.merge_pull_request_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![
+                owner,
+                repo,
+                &number.to_string(),
+                commit_hash,
+                options.merge_method.as_deref().unwrap_or(""),
+                options.commit_title.as_deref().unwrap_or(""),
+                options.commit_message.as_deref().unwrap_or(""),
+            ],
+        ));
+    }
+
     pub fn mock_get_timeline(&self, owner: &str, repo: &str, number: u32, ret: Result<Vec<TimelineEvent>>) {
         self.get_timeline_calls.lock().unwrap().push(MockCall::new(
             ret,
@@ -540,6 +1189,53 @@ impl MockGithub {
             vec![&pr.number.to_string(), &format_check_run(run)],
         ));
     }
+
+    pub fn mock_get_release_by_tag(&self, owner: &str, repo: &str, tag: &str, ret: Result<Release>) {
+        self.get_release_by_tag_calls.lock().unwrap().push(MockCall::new(ret, vec![owner, repo, tag]));
+    }
+
+    pub fn mock_get_latest_release(&self, owner: &str, repo: &str, ret: Result<Release>) {
+        self.get_latest_release_calls.lock().unwrap().push(MockCall::new(ret, vec![owner, repo]));
+    }
+
+    pub fn mock_compare_commits(&self, owner: &str, repo: &str, base: &str, head: &str, ret: Result<Vec<Commit>>) {
+        self.compare_commits_calls.lock().unwrap().push(MockCall::new(ret, vec![owner, repo, base, head]));
+    }
+
+    pub fn mock_get_check_runs(&self, owner: &str, repo: &str, git_ref: &str, ret: Result<Vec<CheckRun>>) {
+        self.get_check_runs_calls.lock().unwrap().push(MockCall::new(ret, vec![owner, repo, git_ref]));
+    }
+
+    pub fn mock_get_check_run_annotations(
+        &self,
+        owner: &str,
+        repo: &str,
+        check_run_id: u64,
+        ret: Result<Vec<CheckAnnotation>>,
+    ) {
+        self.get_check_run_annotations_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![owner, repo, &check_run_id.to_string()],
+        ));
+    }
+
+    pub fn mock_get_job_log(&self, owner: &str, repo: &str, job_id: u64, ret: Result<String>) {
+        self.get_job_log_calls.lock().unwrap().push(MockCall::new(ret, vec![owner, repo, &job_id.to_string()]));
+    }
+
+    pub fn mock_upload_release_asset(
+        &self,
+        tag: &str,
+        name: &str,
+        content_type: &str,
+        contents: &str,
+        ret: Result<ReleaseAsset>,
+    ) {
+        self.upload_release_asset_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![tag, name, content_type, contents],
+        ));
+    }
 }
 
 fn format_check_run(run: &CheckRun) -> String {
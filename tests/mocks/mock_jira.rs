@@ -10,8 +10,13 @@ use octobot::version;
 pub struct MockJira {
     get_issue_calls: Mutex<Vec<MockCall<Issue>>>,
     get_transitions_calls: Mutex<Vec<MockCall<Vec<Transition>>>>,
+    get_project_statuses_calls: Mutex<Vec<MockCall<Vec<Status>>>>,
     transition_issue_calls: Mutex<Vec<MockCall<()>>>,
     comment_issue_calls: Mutex<Vec<MockCall<()>>>,
+    get_comments_calls: Mutex<Vec<MockCall<Vec<Comment>>>>,
+    update_comment_calls: Mutex<Vec<MockCall<()>>>,
+    add_remote_link_calls: Mutex<Vec<MockCall<()>>>,
+    add_worklog_calls: Mutex<Vec<MockCall<()>>>,
     add_version_calls: Mutex<Vec<MockCall<()>>>,
     get_versions_calls: Mutex<Vec<MockCall<Vec<Version>>>>,
     assign_fix_version_calls: Mutex<Vec<MockCall<()>>>,
@@ -19,6 +24,7 @@ pub struct MockJira {
     add_pending_version_calls: Mutex<Vec<MockCall<()>>>,
     remove_pending_versions_calls: Mutex<Vec<MockCall<()>>>,
     find_pending_versions_calls: Mutex<Vec<MockCall<HashMap<String, Vec<version::Version>>>>>,
+    search_users_calls: Mutex<Vec<MockCall<Vec<User>>>>,
 }
 
 #[derive(Debug)]
@@ -41,8 +47,13 @@ impl MockJira {
         MockJira {
             get_issue_calls: Mutex::new(vec![]),
             get_transitions_calls: Mutex::new(vec![]),
+            get_project_statuses_calls: Mutex::new(vec![]),
             transition_issue_calls: Mutex::new(vec![]),
             comment_issue_calls: Mutex::new(vec![]),
+            get_comments_calls: Mutex::new(vec![]),
+            update_comment_calls: Mutex::new(vec![]),
+            add_remote_link_calls: Mutex::new(vec![]),
+            add_worklog_calls: Mutex::new(vec![]),
             add_version_calls: Mutex::new(vec![]),
             get_versions_calls: Mutex::new(vec![]),
             assign_fix_version_calls: Mutex::new(vec![]),
@@ -50,6 +61,7 @@ impl MockJira {
             add_pending_version_calls: Mutex::new(vec![]),
             remove_pending_versions_calls: Mutex::new(vec![]),
             find_pending_versions_calls: Mutex::new(vec![]),
+            search_users_calls: Mutex::new(vec![]),
         }
     }
 }
@@ -67,6 +79,11 @@ impl Drop for MockJira {
                 "Unmet get_transitions calls: {:?}",
                 *self.get_transitions_calls.lock().unwrap()
             );
+            assert!(
+                self.get_project_statuses_calls.lock().unwrap().len() == 0,
+                "Unmet get_project_statuses calls: {:?}",
+                *self.get_project_statuses_calls.lock().unwrap()
+            );
             assert!(
                 self.transition_issue_calls.lock().unwrap().len() == 0,
                 "Unmet transition_issue calls: {:?}",
@@ -77,6 +94,26 @@ impl Drop for MockJira {
                 "Unmet comment_issue calls: {:?}",
                 *self.comment_issue_calls.lock().unwrap()
             );
+            assert!(
+                self.get_comments_calls.lock().unwrap().len() == 0,
+                "Unmet get_comments calls: {:?}",
+                *self.get_comments_calls.lock().unwrap()
+            );
+            assert!(
+                self.update_comment_calls.lock().unwrap().len() == 0,
+                "Unmet update_comment calls: {:?}",
+                *self.update_comment_calls.lock().unwrap()
+            );
+            assert!(
+                self.add_remote_link_calls.lock().unwrap().len() == 0,
+                "Unmet add_remote_link calls: {:?}",
+                *self.add_remote_link_calls.lock().unwrap()
+            );
+            assert!(
+                self.add_worklog_calls.lock().unwrap().len() == 0,
+                "Unmet add_worklog calls: {:?}",
+                *self.add_worklog_calls.lock().unwrap()
+            );
             assert!(
                 self.add_version_calls.lock().unwrap().len() == 0,
                 "Unmet add_version calls: {:?}",
@@ -112,6 +149,11 @@ impl Drop for MockJira {
                 "Unmet find_pending_versions calls: {:?}",
                 *self.find_pending_versions_calls.lock().unwrap()
             );
+            assert!(
+                self.search_users_calls.lock().unwrap().len() == 0,
+                "Unmet search_users calls: {:?}",
+                *self.search_users_calls.lock().unwrap()
+            );
         }
     }
 }
@@ -134,6 +176,15 @@ impl Session for MockJira {
         call.ret
     }
 
+    fn get_project_statuses(&self, proj: &str) -> Result<Vec<Status>> {
+        let mut calls = self.get_project_statuses_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_project_statuses");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], proj);
+
+        call.ret
+    }
+
     fn transition_issue(&self, key: &str, req: &TransitionRequest) -> Result<()> {
         let mut calls = self.transition_issue_calls.lock().unwrap();
         assert!(calls.len() > 0, "Unexpected call to transition_issue");
@@ -154,6 +205,49 @@ impl Session for MockJira {
         call.ret
     }
 
+    fn get_comments(&self, key: &str) -> Result<Vec<Comment>> {
+        let mut calls = self.get_comments_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to get_comments");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], key);
+
+        call.ret
+    }
+
+    fn update_comment(&self, key: &str, comment_id: &str, comment: &str) -> Result<()> {
+        let mut calls = self.update_comment_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to update_comment");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], key);
+        assert_eq!(call.args[1], comment_id);
+        assert_eq!(call.args[2], comment);
+
+        call.ret
+    }
+
+    fn add_remote_link(&self, key: &str, link: &RemoteLink) -> Result<()> {
+        let mut calls = self.add_remote_link_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to add_remote_link");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], key);
+        assert_eq!(call.args[1], link.object.url);
+        assert_eq!(call.args[2], link.object.status.icon.title);
+        assert_eq!(call.args[3], link.object.status.resolved.to_string());
+
+        call.ret
+    }
+
+    fn add_worklog(&self, key: &str, time_spent: &str, comment: &str) -> Result<()> {
+        let mut calls = self.add_worklog_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to add_worklog");
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], key);
+        assert_eq!(call.args[1], time_spent);
+        assert_eq!(call.args[2], comment);
+
+        call.ret
+    }
+
     fn add_version(&self, proj: &str, version: &str) -> Result<()> {
         let mut calls = self.add_version_calls.lock().unwrap();
         assert!(calls.len() > 0, "Unexpected call to add_version");
@@ -221,6 +315,15 @@ impl Session for MockJira {
 
         call.ret
     }
+
+    fn search_users(&self, query: &str) -> Result<Vec<User>> {
+        let mut calls = self.search_users_calls.lock().unwrap();
+        assert!(calls.len() > 0, "Unexpected call to search_users {}", query);
+        let call = calls.remove(0);
+        assert_eq!(call.args[0], query);
+
+        call.ret
+    }
 }
 
 impl MockJira {
@@ -232,6 +335,10 @@ impl MockJira {
         self.get_transitions_calls.lock().unwrap().push(MockCall::new(ret, vec![key]));
     }
 
+    pub fn mock_get_project_statuses(&self, proj: &str, ret: Result<Vec<Status>>) {
+        self.get_project_statuses_calls.lock().unwrap().push(MockCall::new(ret, vec![proj]));
+    }
+
     pub fn mock_transition_issue(&self, key: &str, req: &TransitionRequest, ret: Result<()>) {
         self.transition_issue_calls.lock().unwrap().push(MockCall::new(
             ret,
@@ -243,6 +350,26 @@ impl MockJira {
         self.comment_issue_calls.lock().unwrap().push(MockCall::new(ret, vec![key, comment]));
     }
 
+    pub fn mock_get_comments(&self, key: &str, ret: Result<Vec<Comment>>) {
+        self.get_comments_calls.lock().unwrap().push(MockCall::new(ret, vec![key]));
+    }
+
+    pub fn mock_update_comment(&self, key: &str, comment_id: &str, comment: &str, ret: Result<()>) {
+        self.update_comment_calls.lock().unwrap().push(MockCall::new(ret, vec![key, comment_id, comment]));
+    }
+
+    // the link to `url`, with the given status
+    pub fn mock_add_remote_link(&self, key: &str, url: &str, status: &str, resolved: bool, ret: Result<()>) {
+        self.add_remote_link_calls.lock().unwrap().push(MockCall::new(
+            ret,
+            vec![key, url, status, &resolved.to_string()],
+        ));
+    }
+
+    pub fn mock_add_worklog(&self, key: &str, time_spent: &str, comment: &str, ret: Result<()>) {
+        self.add_worklog_calls.lock().unwrap().push(MockCall::new(ret, vec![key, time_spent, comment]));
+    }
+
     pub fn mock_add_version(&self, proj: &str, version: &str, ret: Result<()>) {
         self.add_version_calls.lock().unwrap().push(MockCall::new(ret, vec![proj, version]));
     }
@@ -281,4 +408,8 @@ impl MockJira {
     pub fn mock_find_pending_versions(&self, proj: &str, ret: Result<HashMap<String, Vec<version::Version>>>) {
         self.find_pending_versions_calls.lock().unwrap().push(MockCall::new(ret, vec![proj]));
     }
+
+    pub fn mock_search_users(&self, query: &str, ret: Result<Vec<User>>) {
+        self.search_users_calls.lock().unwrap().push(MockCall::new(ret, vec![query]));
+    }
 }
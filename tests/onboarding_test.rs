@@ -0,0 +1,136 @@
+mod mocks;
+
+use failure::format_err;
+use tempdir::TempDir;
+
+use octobot::config::Config;
+use octobot::db::Database;
+use octobot::github;
+use octobot::onboarding;
+
+use mocks::mock_github::MockGithub;
+
+const NOW: i64 = 1556712000;
+const WEBHOOK_URL: &str = "https://octobot.company.com/hooks/github";
+
+fn new_test() -> (Config, TempDir) {
+    let temp_dir = TempDir::new("onboarding_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let config = Config::new(db);
+    config.repos_write().insert("some-org/configured", "configured-reviews").unwrap();
+    config.team_channels.set("some-org/infra", "infra").unwrap();
+
+    (config, temp_dir)
+}
+
+fn repo(name: &str, archived: bool) -> github::Repo {
+    let mut repo = github::Repo::new();
+    repo.name = name.into();
+    repo.full_name = format!("some-org/{}", name);
+    repo.archived = Some(archived);
+    repo
+}
+
+fn channels() -> Vec<String> {
+    vec!["backend-reviews".into(), "general".into(), "infra".into(), "web-app".into()]
+}
+
+fn mock_org(github: &MockGithub) {
+    github.mock_get_org_repos(
+        "some-org",
+        Ok(vec![repo("web_app", false), repo("old-app", true), repo("configured", false), repo("zzz", false)]),
+    );
+    github.mock_get_org_teams(
+        "some-org",
+        Ok(vec![
+            github::Team::new("backend", "Backend"),
+            github::Team::new("infra", "Infra"),
+            github::Team::new("design", "Design"),
+        ]),
+    );
+}
+
+#[test]
+fn test_propose() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+    mock_org(&github);
+
+    let plan = onboarding::propose(&config, &github, "some-org", WEBHOOK_URL, &channels(), "admin", NOW).unwrap();
+    assert_eq!(onboarding::PROPOSED, plan.state);
+    assert_eq!(
+        vec![("some-org/web_app", "web-app"), ("some-org/zzz", "")],
+        plan.repos.iter().map(|r| (r.repo.as_str(), r.channel.as_str())).collect::<Vec<_>>()
+    );
+    assert!(plan.repos.iter().all(|r| r.webhook));
+    assert_eq!(
+        vec![("some-org/backend", "backend-reviews")],
+        plan.teams.iter().map(|t| (t.team.as_str(), t.channel.as_str())).collect::<Vec<_>>()
+    );
+
+    assert_eq!(Some(plan), config.onboarding_plans.get(1).unwrap());
+}
+
+#[test]
+fn test_apply() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+    mock_org(&github);
+
+    let mut plan = onboarding::propose(&config, &github, "some-org", WEBHOOK_URL, &channels(), "admin", NOW).unwrap();
+    // the admin picks a channel for the repo that had none, and skips its webhook
+    plan.repos[1].channel = "misc".into();
+    plan.repos[1].webhook = false;
+    assert!(config.onboarding_plans.update(plan.id, &plan.repos, &plan.teams).unwrap());
+    let plan = config.onboarding_plans.get(plan.id).unwrap().unwrap();
+
+    github.mock_create_webhook("some-org", "web_app", WEBHOOK_URL, "", Ok(()));
+    let results = onboarding::apply(&config, &github, &plan, "other-admin", NOW + 60).unwrap();
+    assert_eq!(
+        vec![
+            "some-org/web_app: configured for #web-app",
+            "some-org/zzz: configured for #misc",
+            "some-org/backend: review requests go to #backend-reviews",
+            "some-org/web_app: created webhook",
+        ],
+        results
+    );
+
+    let repos = config.repos().get_all().unwrap();
+    assert_eq!(
+        vec![("some-org/configured", "configured-reviews"), ("some-org/web_app", "web-app"), ("some-org/zzz", "misc")],
+        repos.iter().map(|r| (r.repo.as_str(), r.channel.as_str())).collect::<Vec<_>>()
+    );
+    assert_eq!(Some("backend-reviews".to_string()), config.team_channels.get("some-org/backend").unwrap());
+
+    let plan = config.onboarding_plans.get(plan.id).unwrap().unwrap();
+    assert_eq!(onboarding::APPLIED, plan.state);
+    assert_eq!("other-admin", plan.applied_by);
+    assert_eq!(results, plan.results);
+
+    // a plan is only applied once
+    assert!(onboarding::apply(&config, &github, &plan, "other-admin", NOW + 120).is_err());
+}
+
+#[test]
+fn test_apply_webhook_error() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+    mock_org(&github);
+
+    let mut plan = onboarding::propose(&config, &github, "some-org", WEBHOOK_URL, &channels(), "admin", NOW).unwrap();
+    plan.repos.truncate(1);
+    plan.teams.clear();
+
+    github.mock_create_webhook("some-org", "web_app", WEBHOOK_URL, "", Err(format_err!("Not Found")));
+    let results = onboarding::apply(&config, &github, &plan, "admin", NOW + 60).unwrap();
+    assert_eq!(
+        vec![
+            "some-org/web_app: configured for #web-app".to_string(),
+            "some-org/web_app: error creating webhook: Not Found".to_string(),
+        ],
+        results
+    );
+}
@@ -0,0 +1,72 @@
+mod mocks;
+
+use mocks::mock_github::MockGithub;
+
+use octobot::github;
+use octobot::path_labels;
+use octobot::repos::RepoPathLabel;
+
+fn the_pr() -> github::PullRequest {
+    let mut pr = github::PullRequest::new();
+    pr.number = 32;
+    pr
+}
+
+fn the_repo() -> github::Repo {
+    github::Repo::parse("http://the-github-host/some-user/some-repo").unwrap()
+}
+
+fn rules() -> Vec<RepoPathLabel> {
+    vec![
+        RepoPathLabel::new("docs/**", "documentation"),
+        RepoPathLabel::new("**/*.sql", "database"),
+        RepoPathLabel::new("ui/**", "frontend"),
+    ]
+}
+
+#[test]
+fn test_apply_path_labels() {
+    let github = MockGithub::new();
+    github.mock_get_pull_request_files(
+        "some-user",
+        "some-repo",
+        32,
+        Ok(vec![
+            github::PullRequestFile::new("docs/README.md"),
+            github::PullRequestFile::new("server/schema.sql"),
+        ]),
+    );
+    github.mock_get_pull_request_labels(
+        "some-user",
+        "some-repo",
+        32,
+        Ok(vec![github::Label::new("database"), github::Label::new("frontend"), github::Label::new("backport-1.0")]),
+    );
+    // only add what's missing
+    github.mock_add_pull_request_labels("some-user", "some-repo", 32, vec!["documentation".into()], Ok(()));
+    // remove managed labels that no longer apply, but leave others alone
+    github.mock_remove_pull_request_label("some-user", "some-repo", 32, "frontend", Ok(()));
+
+    path_labels::apply_path_labels(&github, &the_repo(), &the_pr(), &rules()).unwrap();
+}
+
+#[test]
+fn test_apply_path_labels_no_changes() {
+    let github = MockGithub::new();
+    github.mock_get_pull_request_files(
+        "some-user",
+        "some-repo",
+        32,
+        Ok(vec![github::PullRequestFile::new("ui/app.js")]),
+    );
+    github.mock_get_pull_request_labels("some-user", "some-repo", 32, Ok(vec![github::Label::new("frontend")]));
+
+    path_labels::apply_path_labels(&github, &the_repo(), &the_pr(), &rules()).unwrap();
+}
+
+#[test]
+fn test_apply_path_labels_no_rules() {
+    let github = MockGithub::new();
+
+    path_labels::apply_path_labels(&github, &the_repo(), &the_pr(), &vec![]).unwrap();
+}
@@ -0,0 +1,142 @@
+// These run against the PostgreSQL server in OCTOBOT_TEST_POSTGRES_URL (e.g. "postgres://postgres@localhost/octobot"),
+// and pass without doing anything when it isn't set. Each test drops and recreates a schema of its own to work in.
+
+use std::env;
+use std::time::Duration;
+
+use postgres::{Client, NoTls};
+
+use octobot::config::Config;
+use octobot::db::{Backend, Database, ToSql, NO_PARAMS};
+use octobot::repos::RepoInfo;
+use octobot::users::UserInfo;
+
+fn test_db_url(name: &str) -> Option<String> {
+    let url = match env::var("OCTOBOT_TEST_POSTGRES_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            println!("OCTOBOT_TEST_POSTGRES_URL is not set: skipping");
+            return None;
+        }
+    };
+
+    let schema = format!("octobot_test_{}", name);
+    let mut client = Client::connect(&url, NoTls).expect("connect to postgres");
+    client
+        .batch_execute(&format!("DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0}", schema))
+        .expect("create test schema");
+
+    let sep = if url.contains('?') { '&' } else { '?' };
+    Some(format!("{}{}options=-c%20search_path%3D{}", url, sep, schema))
+}
+
+#[test]
+fn test_migrations() {
+    let url = match test_db_url("migrations") {
+        Some(url) => url,
+        None => return,
+    };
+
+    let db = Database::new(&url).expect("migrate database");
+    assert_eq!(Backend::Postgres, db.backend());
+    let status = db.schema_status().unwrap();
+    assert_eq!(Some(status.latest), status.current);
+
+    // migrating again is a no-op
+    let db = Database::new(&url).expect("open migrated database");
+    assert_eq!(Some(status.latest), db.schema_status().unwrap().current);
+}
+
+#[test]
+fn test_users() {
+    let url = match test_db_url("users") {
+        Some(url) => url,
+        None => return,
+    };
+    let config = Config::new(Database::new(&url).unwrap());
+
+    config.users_write().insert("joe", "joe.slacker").unwrap();
+    let mut jane = UserInfo::new("jane", "jane.slacker");
+    jane.email = "jane@company.com".into();
+    config.users_write().insert_info(&jane).unwrap();
+
+    assert_eq!(Some("joe.slacker".into()), config.users().slack_user_name("joe"));
+    assert_eq!(Some("jane".into()), config.users().lookup_by_email("jane@company.com").map(|u| u.github));
+
+    let mut joe = config.users().lookup_info("joe").unwrap();
+    joe.slack = "joe.elsewhere".into();
+    config.users_write().update(&joe).unwrap();
+    assert_eq!(Some("joe.elsewhere".into()), config.users().slack_user_name("joe"));
+
+    config.users_write().delete(joe.id.unwrap()).unwrap();
+    assert_eq!(vec!["jane"], config.users().get_all().unwrap().iter().map(|u| u.github.as_str()).collect::<Vec<_>>());
+    config.users_write().restore(joe.id.unwrap()).unwrap();
+    assert_eq!(2, config.users().get_all().unwrap().len());
+}
+
+#[test]
+fn test_repos() {
+    let url = match test_db_url("repos") {
+        Some(url) => url,
+        None => return,
+    };
+    let config = Config::new(Database::new(&url).unwrap());
+
+    config
+        .repos_write()
+        .insert_info(&RepoInfo::new("some-org/some-repo", "reviews").with_webhook_secret("the-secret"))
+        .unwrap();
+    config.repos_write().insert_info(&RepoInfo::new("some-org", "org-reviews")).unwrap();
+
+    let repo = octobot::github::Repo::parse("https://git.company.com/some-org/some-repo").unwrap();
+    let mut info = config.repos().get_all().unwrap().into_iter().find(|r| r.repo == "some-org/some-repo").unwrap();
+    assert_eq!("reviews", info.channel);
+
+    // the secret is kept when an update doesn't send it
+    info.channel = "other-reviews".into();
+    info.webhook_secret = String::new();
+    config.repos_write().update(&info).unwrap();
+    assert_eq!(Some("the-secret".into()), config.repos().webhook_secret(&repo));
+
+    config.repos_write().delete(info.id.unwrap()).unwrap();
+    assert_eq!(1, config.repos().get_all().unwrap().len());
+    config.repos_write().restore(info.id.unwrap()).unwrap();
+    let repos = config.repos().get_all().unwrap();
+    assert_eq!(vec!["org-reviews", "other-reviews"], repos.iter().map(|r| r.channel.as_str()).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_integer_parameters() {
+    let url = match test_db_url("integers") {
+        Some(url) => url,
+        None => return,
+    };
+    let db = Database::new(&url).unwrap();
+    let conn = db.connect().unwrap();
+    conn.execute_batch("CREATE TABLE numbers (small smallint, regular integer)").unwrap();
+
+    conn.execute("INSERT INTO numbers (small, regular) VALUES (?1, ?2)", &[&1, &2]).unwrap();
+    let row: (i64, i64) = conn
+        .query_row("SELECT small, regular FROM numbers", NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap();
+    assert_eq!((1, 2), row);
+
+    // too big for the columns, rather than wrapped around
+    let insert = "INSERT INTO numbers (small, regular) VALUES (?1, ?2)";
+    assert!(conn.execute(insert, &[&40000, &2]).is_err());
+    assert!(conn.execute(insert, &[&1 as &dyn ToSql, &(1i64 << 40)]).is_err());
+}
+
+#[test]
+fn test_read_replica_not_a_standby() {
+    let url = match test_db_url("replica") {
+        Some(url) => url,
+        None => return,
+    };
+
+    // the primary has no replay lag to report, so reads fall back to it
+    let db = Database::new(&url).unwrap().with_read_replica(&url, Duration::from_secs(60));
+    let conn = db.connect_read().unwrap();
+    let one: i64 = conn.query_row("SELECT 1", NO_PARAMS, |row| row.get(0)).unwrap();
+    assert_eq!(1, one);
+}
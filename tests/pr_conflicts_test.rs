@@ -0,0 +1,98 @@
+mod mocks;
+
+use std::sync::Arc;
+
+use tempdir::TempDir;
+
+use octobot::config::Config;
+use octobot::db::Database;
+use octobot::github;
+use octobot::messenger;
+use octobot::pr_conflicts;
+use octobot::slack::{self, SlackAttachmentBuilder};
+
+use mocks::mock_github::MockGithub;
+use mocks::mock_slack::MockSlack;
+
+fn new_test() -> (Arc<Config>, TempDir) {
+    let temp_dir = TempDir::new("pr_conflicts_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let config = Arc::new(Config::new(db));
+    config.users_write().insert("the-pr-owner", "the.pr.owner").unwrap();
+
+    (config, temp_dir)
+}
+
+fn the_repo() -> github::Repo {
+    github::Repo::parse("http://the-github-host/some-user/some-repo").unwrap()
+}
+
+fn the_pr(number: u32, mergeable: Option<bool>) -> github::PullRequest {
+    let mut pr = github::PullRequest::new();
+    pr.number = number;
+    pr.title = "The PR".into();
+    pr.html_url = "http://the-pr".into();
+    pr.user = github::User::new("the-pr-owner");
+    pr.base = github::BranchRef::new("master");
+    pr.base.sha = "1111eeee2222".into();
+    pr.mergeable = mergeable;
+    pr
+}
+
+fn expect_prs(github: &MockGithub, mergeable: Option<bool>) {
+    github.mock_get_pull_requests("some-user", "some-repo", Some("open"), None, Ok(vec![the_pr(32, None)]));
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(the_pr(32, mergeable)));
+}
+
+#[test]
+fn test_check_conflicts_notifies_once() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+
+    let msg = "Pull Request has merge conflicts with master";
+    let slack = MockSlack::new(vec![slack::req(
+        "@the.pr.owner",
+        msg,
+        vec![
+            SlackAttachmentBuilder::new("")
+                .title("Pull Request #32: \"The PR\"")
+                .title_link("http://the-pr")
+                .build(),
+            SlackAttachmentBuilder::new(
+                "Conflicting base commit: <http://the-github-host/some-user/some-repo/commit/1111eeee2222|1111eee>",
+            )
+            .build(),
+        ],
+    )]);
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+
+    expect_prs(&github, Some(false));
+    let notified = pr_conflicts::check_conflicts(&github, &messenger, &config.conflicts, &the_repo()).unwrap();
+    assert_eq!(vec![32], notified);
+
+    // still conflicted: already told them
+    expect_prs(&github, Some(false));
+    let notified = pr_conflicts::check_conflicts(&github, &messenger, &config.conflicts, &the_repo()).unwrap();
+    assert_eq!(Vec::<u32>::new(), notified);
+}
+
+#[test]
+fn test_check_conflicts_renotifies_after_resolved() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+    config.conflicts.mark_conflicted("some-user/some-repo", 32, "abcdef").unwrap();
+
+    let slack = MockSlack::new(vec![]);
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+
+    // not known yet: do nothing
+    expect_prs(&github, None);
+    pr_conflicts::check_conflicts(&github, &messenger, &config.conflicts, &the_repo()).unwrap();
+    assert_eq!(true, config.conflicts.is_conflicted("some-user/some-repo", 32).unwrap());
+
+    expect_prs(&github, Some(true));
+    pr_conflicts::check_conflicts(&github, &messenger, &config.conflicts, &the_repo()).unwrap();
+    assert_eq!(false, config.conflicts.is_conflicted("some-user/some-repo", 32).unwrap());
+}
@@ -5,11 +5,12 @@ use std::sync::Arc;
 
 use tempdir::TempDir;
 
-use octobot::config::Config;
+use octobot::config::{Config, WebhookConfig};
 use octobot::db::Database;
 use git_helper::temp_git::TempGit;
 use mocks::mock_github::MockGithub;
 use octobot::github;
+use octobot::outbound_webhooks::{self, OutboundEvent};
 use octobot::pr_merge;
 use octobot::repos;
 use octobot::slack::{self, SlackAttachmentBuilder};
@@ -17,12 +18,14 @@ use octobot::slack::{self, SlackAttachmentBuilder};
 use failure::format_err;
 
 use mocks::mock_slack::MockSlack;
+use mocks::mock_worker::LockedMockWorker;
 
 struct PRMergeTest {
     git: TempGit,
     github: MockGithub,
     config: Arc<Config>,
     slack: MockSlack,
+    webhooks: LockedMockWorker<OutboundEvent>,
 }
 
 fn new_test() -> (PRMergeTest, TempDir) {
@@ -30,7 +33,13 @@ fn new_test() -> (PRMergeTest, TempDir) {
     let db_file = temp_dir.path().join("db.sqlite3");
     let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
 
-    let config = Arc::new(Config::new(db));
+    let mut config = Config::new(db);
+    config.webhooks = Some(vec![WebhookConfig {
+        url: "http://the-consumer/hook".into(),
+        secret: None,
+        events: None,
+    }]);
+    let config = Arc::new(config);
     config.users_write().insert("the-pr-owner", "the.pr.owner").unwrap();
     config
         .repos_write()
@@ -44,9 +53,16 @@ fn new_test() -> (PRMergeTest, TempDir) {
         github: MockGithub::new(),
         config,
         slack: MockSlack::new(vec![]),
+        webhooks: LockedMockWorker::new("webhooks"),
     }, temp_dir)
 }
 
+fn expect_backport_check(github: &MockGithub, pr: &github::PullRequest, conclusion: github::Conclusion, title: &str) {
+    let mut run = github::CheckRun::new("octobot/backport-1.0", pr, None).completed(conclusion);
+    run.output = Some(github::CheckOutput::new(title, ""));
+    github.mock_create_check_run(pr, &run, Ok(1));
+}
+
 #[test]
 fn test_pr_merge_basic() {
     let (test, _temp_dir) = new_test();
@@ -105,9 +121,11 @@ fn test_pr_merge_basic() {
         Ok(()),
     );
 
+    expect_backport_check(&test.github, &pr, github::Conclusion::Success, "Created PR #456");
+
     let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
     let req = pr_merge::req(&repo, &pr, "release/1.0", "release/", vec![]);
-    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender());
+    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender(), test.webhooks.new_sender());
 
     let (user, email) = test.git.git.get_commit_author("origin/my-feature-branch-1.0").unwrap();
 
@@ -202,9 +220,11 @@ if (true)    {
         Ok(()),
     );
 
+    expect_backport_check(&test.github, &pr, github::Conclusion::Success, "Created PR #456");
+
     let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
     let req = pr_merge::req(&repo, &pr, "release/1.0", "release/", vec![]);
-    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender());
+    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender(), test.webhooks.new_sender());
 
     assert_eq!(contents_10_final, test.git.run_git(&["cat-file", "blob", "my-feature-branch-1.0:file.cpp"]));
 }
@@ -293,9 +313,11 @@ if (true) {
         Ok(()),
     );
 
+    expect_backport_check(&test.github, &pr, github::Conclusion::Success, "Created PR #456");
+
     let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
     let req = pr_merge::req(&repo, &pr, "release/1.0", "release/", vec![]);
-    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender());
+    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender(), test.webhooks.new_sender());
 
     assert_eq!(contents_10_final, test.git.run_git(&["cat-file", "blob", "my-feature-branch-1.0:file.cpp"]));
 }
@@ -358,9 +380,11 @@ fn test_pr_merge_conventional_commit() {
         Ok(()),
     );
 
+    expect_backport_check(&test.github, &pr, github::Conclusion::Success, "Created PR #456");
+
     let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
     let req = pr_merge::req(&repo, &pr, "release/1.0", "release/", vec![]);
-    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender());
+    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender(), test.webhooks.new_sender());
 
     let (user, email) = test.git.git.get_commit_author("origin/my-feature-branch-1.0").unwrap();
 
@@ -437,7 +461,214 @@ fn test_pr_merge_backport_failure() {
         )
     ]);
 
+    expect_backport_check(&test.github, &pr, github::Conclusion::Failure, "failed");
+
     let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
+    test.webhooks.expect_req(outbound_webhooks::backport_failed(&repo, &pr, "release/1.0", "bad stuff"));
+
     let req = pr_merge::req(&repo, &pr, "release/1.0", "release/", vec![]);
-    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender());
+    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender(), test.webhooks.new_sender());
+}
+
+#[test]
+fn test_pr_merge_conflict_backport() {
+    let (mut test, _temp_dir) = new_test();
+
+    // setup a release branch that changed the same file
+    test.git.add_repo_file("file.txt", "contents0", "Add the file");
+    test.git.run_git(&["push", "origin", "master:release/1.0"]);
+    test.git.run_git(&["checkout", "-b", "the-release", "origin/release/1.0"]);
+    test.git.add_repo_file("file.txt", "release contents", "Change the file on the release");
+    test.git.run_git(&["push", "origin", "HEAD:release/1.0"]);
+
+    // make a conflicting commit on master
+    test.git.run_git(&["checkout", "master"]);
+    test.git.add_repo_file("file.txt", "contents1", "I made a change");
+    let commit1 = test.git.git.current_commit().unwrap();
+
+    // pretend this came from a PR
+    let mut pr = github::PullRequest::new();
+    pr.number = 123;
+    pr.title = "The Title".into();
+    pr.merged = Some(true);
+    pr.merge_commit_sha = Some(commit1.clone());
+    pr.head = github::BranchRef::new("my-feature-branch");
+    pr.base = github::BranchRef::new("master");
+    pr.user = github::User::new("the-pr-owner");
+    let pr = pr;
+
+    let mut draft_pr = github::PullRequest::new();
+    draft_pr.number = 456;
+    draft_pr.title = "master->1.0: I made a change".into();
+    draft_pr.html_url = "http://the-github-host/the-owner/the-repo/pull/456".into();
+    let draft_pr = draft_pr;
+
+    test.github.mock_create_draft_pull_request(
+        "the-owner",
+        "the-repo",
+        "master->1.0: I made a change",
+        &format!(
+            "Cherry-picking this onto release/1.0 conflicted in:\n\n* `file.txt`\n\n\
+             The conflict markers are committed as-is: resolve them on this branch, \
+             then mark this PR ready for review.\n\n(cherry-picked from {}, PR #123)",
+            commit1
+        ),
+        "backport/PR-123-release-1.0",
+        "release/1.0",
+        Ok(draft_pr),
+    );
+    test.github.mock_assign_pull_request("the-owner", "the-repo", 456, vec!["the-pr-owner".into()], Ok(()));
+
+    expect_backport_check(&test.github, &pr, github::Conclusion::ActionRequired, "Conflicts in draft PR #456");
+
+    let attach = SlackAttachmentBuilder::new("Conflicts in file.txt")
+        .title("Draft PR #456: \"master->1.0: I made a change\"")
+        .title_link("http://the-github-host/the-owner/the-repo/pull/456")
+        .color("warning")
+        .build();
+    test.slack.expect(vec![
+        slack::req(
+            "the-review-channel",
+            "Backport of PR #123 to release/1.0 has conflicts: please resolve them in the draft PR \
+             (<http://the-github-host/the-owner/the-repo|the-owner/the-repo>)",
+            vec![attach.clone()],
+        ),
+        slack::req(
+            "@the.pr.owner",
+            "Backport of PR #123 to release/1.0 has conflicts: please resolve them in the draft PR",
+            vec![attach],
+        ),
+    ]);
+
+    test.github.mock_comment_pull_request(
+        "the-owner",
+        "the-repo",
+        123,
+        "Backport to release/1.0 has conflicts: see draft PR #456",
+        Ok(()),
+    );
+
+    let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
+    let req = pr_merge::req(&repo, &pr, "release/1.0", "release/", vec![]);
+    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender(), test.webhooks.new_sender());
+
+    let contents = test.git.run_git(&["show", "origin/backport/PR-123-release-1.0:file.txt"]);
+    assert!(contents.contains("<<<<<<<"), "Expected conflict markers: {}", contents);
+    assert!(contents.contains("release contents"));
+    assert!(contents.contains("contents1"));
+
+    let (user, email) = test.git.git.get_commit_author("origin/backport/PR-123-release-1.0").unwrap();
+    assert_eq!(user, test.git.user_name());
+    assert_eq!(email, test.git.user_email());
+}
+
+fn commit(sha: &str) -> github::Commit {
+    let mut commit = github::Commit::new();
+    commit.sha = sha.into();
+    commit
+}
+
+#[test]
+fn test_pr_merge_rebase_strategy() {
+    let (test, _temp_dir) = new_test();
+
+    test.git.run_git(&["push", "origin", "master:release/1.0"]);
+
+    test.git.run_git(&["checkout", "master"]);
+    test.git.add_repo_file("file.txt", "contents1", "First part");
+    let commit1 = test.git.git.current_commit().unwrap();
+    test.git.add_repo_file("file.txt", "contents2", "Second part");
+    let commit2 = test.git.git.current_commit().unwrap();
+
+    let mut pr = github::PullRequest::new();
+    pr.number = 123;
+    pr.merged = Some(true);
+    pr.merge_commit_sha = Some(commit2.clone());
+    pr.head = github::BranchRef::new("my-feature-branch");
+    pr.base = github::BranchRef::new("master");
+    pr.user = github::User::new("the-pr-author");
+
+    let mut new_pr = github::PullRequest::new();
+    new_pr.number = 456;
+
+    test.github.mock_create_pull_request(
+        "the-owner",
+        "the-repo",
+        "master->1.0: Second part",
+        &format!("(cherry-picked from {}, PR #123)", commit2),
+        "my-feature-branch-1.0",
+        "release/1.0",
+        Ok(new_pr),
+    );
+    expect_backport_check(&test.github, &pr, github::Conclusion::Success, "Created PR #456");
+
+    let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
+    let mut req = pr_merge::req(&repo, &pr, "release/1.0", "release/", vec![commit(&commit1), commit(&commit2)]);
+    req.merge_strategy = Some(repos::RepoMergeStrategy::new("release/*", "rebase", ""));
+    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender(), test.webhooks.new_sender());
+
+    // each commit is picked on its own
+    let log = test.git.run_git(&["log", "--format=%s", "origin/release/1.0..origin/my-feature-branch-1.0"]);
+    assert_eq!(vec!["Second part", "First part"], log.lines().collect::<Vec<_>>());
+    let message = test.git.run_git(&["log", "-1", "--format=%B", "origin/my-feature-branch-1.0~1"]);
+    assert!(message.contains(&format!("(cherry picked from commit {})", commit1)), "{}", message);
+
+    assert_eq!("", test.git.run_git(&["diff", "master", "origin/my-feature-branch-1.0"]));
+}
+
+#[test]
+fn test_pr_merge_merge_commit_strategy() {
+    let (test, _temp_dir) = new_test();
+
+    test.git.run_git(&["push", "origin", "master:release/1.0"]);
+
+    test.git.run_git(&["checkout", "master"]);
+    test.git.add_repo_file("file.txt", "contents1", "First part");
+    let commit1 = test.git.git.current_commit().unwrap();
+    test.git.add_repo_file("other.txt", "contents2", "Second part");
+    let commit2 = test.git.git.current_commit().unwrap();
+
+    let mut pr = github::PullRequest::new();
+    pr.number = 123;
+    pr.title = "The feature".into();
+    pr.merged = Some(true);
+    pr.merge_commit_sha = Some(commit2.clone());
+    pr.head = github::BranchRef::new("my-feature-branch");
+    pr.base = github::BranchRef::new("master");
+    pr.user = github::User::new("the-pr-author");
+
+    let mut new_pr = github::PullRequest::new();
+    new_pr.number = 456;
+
+    test.github.mock_create_pull_request(
+        "the-owner",
+        "the-repo",
+        "The feature (#123) into release/1.0",
+        &format!("By @the-pr-author\n\n(cherry-picked from {}, PR #123)", commit2),
+        "my-feature-branch-1.0",
+        "release/1.0",
+        Ok(new_pr),
+    );
+    expect_backport_check(&test.github, &pr, github::Conclusion::Success, "Created PR #456");
+
+    let repo = github::Repo::parse("http://the-github-host/the-owner/the-repo").unwrap();
+    let mut req = pr_merge::req(&repo, &pr, "release/1.0", "release/", vec![commit(&commit1), commit(&commit2)]);
+    req.merge_strategy = Some(repos::RepoMergeStrategy::new(
+        "",
+        "merge",
+        "{{pr_title}} (#{{pr_number}}) into {{branch}}\n\nBy @{{author}}",
+    ));
+    pr_merge::merge_pull_request(&test.git.git, &test.github, &req, test.config, test.slack.new_sender(), test.webhooks.new_sender());
+
+    // a merge commit with the templated message, joining the picked commits
+    let range = "origin/release/1.0..origin/my-feature-branch-1.0";
+    let log = test.git.run_git(&["log", "--topo-order", "--format=%s", range]);
+    assert_eq!(
+        vec!["The feature (#123) into release/1.0", "Second part", "First part"],
+        log.lines().collect::<Vec<_>>()
+    );
+    let parents = test.git.run_git(&["log", "-1", "--format=%P", "origin/my-feature-branch-1.0"]);
+    assert_eq!(2, parents.split_whitespace().count());
+
+    assert_eq!("", test.git.run_git(&["diff", "master", "origin/my-feature-branch-1.0"]));
 }
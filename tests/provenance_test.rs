@@ -0,0 +1,104 @@
+mod mocks;
+
+use tempdir::TempDir;
+
+use octobot::config::Config;
+use octobot::db::Database;
+use octobot::github;
+use octobot::provenance;
+use octobot::sbom;
+
+use mocks::mock_github::MockGithub;
+
+struct ProvenanceTest {
+    github: MockGithub,
+    config: Config,
+    req: provenance::AttestationRequest,
+    _temp_dir: TempDir,
+}
+
+fn new_test() -> ProvenanceTest {
+    let temp_dir = TempDir::new("provenance_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let repo = github::Repo::parse("http://the-github-host/some-user/some-repo").unwrap();
+    ProvenanceTest {
+        github: MockGithub::new(),
+        config: Config::new(db),
+        req: provenance::req(&repo, &github::Release::new("v1.1")),
+        _temp_dir: temp_dir,
+    }
+}
+
+fn commit(message: &str) -> github::Commit {
+    let mut commit = github::Commit::new();
+    commit.commit.message = message.into();
+    commit
+}
+
+#[test]
+fn test_attest_release() {
+    let test = new_test();
+
+    let mut pr = github::PullRequest::new();
+    pr.number = 12;
+    pr.title = "Add the thing".into();
+    pr.html_url = "http://the-github-host/some-user/some-repo/pull/12".into();
+    pr.user = github::User::new("the-author");
+    pr.head.sha = "ffff0000".into();
+    pr.merge_commit_sha = Some("abcd1234".into());
+
+    let mut approval = github::Review::new("lgtm", github::User::new("the-reviewer"));
+    approval.state = "APPROVED".into();
+    let check = github::CheckRun::new("build", &pr, None).completed(github::Conclusion::Success);
+
+    test.github.mock_get_pull_request("some-user", "some-repo", 12, Ok(pr.clone()));
+    test.github.mock_get_pull_request_reviews("some-user", "some-repo", 12, Ok(vec![approval]));
+    test.github.mock_get_check_runs("some-user", "some-repo", "ffff0000", Ok(vec![check]));
+    test.github.mock_get_pull_request_commits(
+        "some-user",
+        "some-repo",
+        12,
+        Ok(vec![commit("Add the thing\n\nSigned-off-by: The Author <author@company.com>")]),
+    );
+
+    let messages = vec!["Bump version".to_string(), "Merge pull request #12 from some-user/thing".to_string()];
+    let attestation =
+        provenance::attest(&test.github, &test.req, "abcdef", Some("v1.0".into()), &messages).unwrap();
+
+    assert_eq!("some-user/some-repo", attestation.repo);
+    assert_eq!("v1.1", attestation.tag);
+    assert_eq!(Some("v1.0".to_string()), attestation.previous_tag);
+    assert_eq!(1, attestation.pull_requests.len());
+
+    let pr = &attestation.pull_requests[0];
+    assert_eq!(12, pr.number);
+    assert_eq!("the-author", pr.author);
+    assert_eq!(Some("abcd1234".to_string()), pr.merge_commit_sha);
+    assert_eq!(vec!["the-reviewer".to_string()], pr.approvals.iter().map(|a| a.user.clone()).collect::<Vec<_>>());
+    assert_eq!("build", pr.checks[0].name);
+    assert_eq!(Some(github::Conclusion::Success), pr.checks[0].conclusion);
+    assert_eq!(vec!["The Author <author@company.com>".to_string()], pr.sign_offs);
+}
+
+#[test]
+fn test_attestations_are_immutable() {
+    let test = new_test();
+
+    let attestation = provenance::attest(&test.github, &test.req, "abcdef", None, &vec![]).unwrap();
+    let recorded = test.config.attestations.record(&attestation).unwrap();
+    assert_eq!(sbom::sha256(recorded.document.as_bytes()), recorded.sha256);
+    assert_eq!(
+        attestation,
+        serde_json::from_str::<provenance::Attestation>(&recorded.document).unwrap()
+    );
+
+    let mut changed = attestation.clone();
+    changed.commit_hash = "123456".into();
+    assert!(test.config.attestations.record(&changed).is_err());
+
+    assert_eq!(Some(recorded.clone()), test.config.attestations.get("some-user/some-repo", "v1.1").unwrap());
+    assert_eq!(vec![recorded], test.config.attestations.get_all("some-user/some-repo").unwrap());
+    assert_eq!(None, test.config.attestations.get("some-user/some-repo", "v1.0").unwrap());
+}
@@ -0,0 +1,207 @@
+mod mocks;
+
+use failure::format_err;
+use tempdir::TempDir;
+
+use octobot::compliance::MergeRecord;
+use octobot::config::{Config, JiraConfig};
+use octobot::db::Database;
+use octobot::github;
+use octobot::jira;
+use octobot::release_notes::{self, ReleaseIssue};
+
+use mocks::mock_github::MockGithub;
+use mocks::mock_jira::MockJira;
+
+struct ReleaseNotesTest {
+    github: MockGithub,
+    jira: MockJira,
+    jira_config: JiraConfig,
+    config: Config,
+    repo: github::Repo,
+    _temp_dir: TempDir,
+}
+
+fn new_test() -> ReleaseNotesTest {
+    let temp_dir = TempDir::new("release_notes_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let jira_config = JiraConfig {
+        host: "the-jira".into(),
+        username: "the-jira-user".into(),
+        password: "the-jira-pass".into(),
+        progress_states: None,
+        review_states: None,
+        resolved_states: None,
+        fixed_resolutions: None,
+        fix_versions_field: None,
+        pending_versions_field: None,
+        restrict_comment_visibility_to_role: None,
+        login_suffix: None,
+        deployment: None,
+        oauth: None,
+        transitions: None,
+        projects: None,
+        pr_updates: None,
+        project_pr_updates: None,
+        templates: None,
+        webhook_secret: None,
+    };
+
+    ReleaseNotesTest {
+        github: MockGithub::new(),
+        jira: MockJira::new(),
+        jira_config: jira_config,
+        config: Config::new(db),
+        repo: github::Repo::parse("http://the-github-host/some-user/some-repo").unwrap(),
+        _temp_dir: temp_dir,
+    }
+}
+
+fn commit(message: &str, author: Option<&str>) -> github::Commit {
+    let mut commit = github::Commit::new();
+    commit.commit.message = message.into();
+    commit.author = author.map(|a| github::User::new(a));
+    commit
+}
+
+fn issue(key: &str, summary: &str) -> jira::Issue {
+    jira::Issue {
+        key: key.into(),
+        status: None,
+        fields: jira::IssueFields {
+            summary: Some(summary.into()),
+        },
+    }
+}
+
+#[test]
+fn test_generate_release_notes() {
+    let test = new_test();
+
+    // octobot recorded the first merge; the second predates it
+    test.config
+        .merges
+        .record(&MergeRecord {
+            repo: "some-user/some-repo".into(),
+            number: 12,
+            title: "Fix the crash".into(),
+            html_url: "http://the-github-host/some-user/some-repo/pull/12".into(),
+            base_branch: "master".into(),
+            author: "joe".into(),
+            merged_by: "sue".into(),
+            merged_at: 0,
+            approvers: vec!["sue".into()],
+            tickets: vec!["SER-2".into()],
+            overrides: vec![],
+        })
+        .unwrap();
+
+    let mut pr = github::PullRequest::new();
+    pr.number = 15;
+    pr.title = "Add the feature".into();
+    pr.html_url = "http://the-github-host/some-user/some-repo/pull/15".into();
+    pr.user = github::User::new("sue");
+
+    test.github.mock_compare_commits(
+        "some-user",
+        "some-repo",
+        "v1.0",
+        "v1.1",
+        Ok(vec![
+            commit("Merge pull request #12 from joe/crash\n\nFix the crash", Some("joe")),
+            commit("Add the feature (#15)\n\n* [SER-1] the feature", Some("sue")),
+            commit("Merge pull request #16 from joe/gone", Some("joe")),
+            commit("Bump dependencies", Some("dependabot[bot]")),
+            commit("Tweak the docs", Some("bob")),
+        ]),
+    );
+    test.github.mock_get_pull_request_labels("some-user", "some-repo", 12, Ok(vec![github::Label::new("bug")]));
+    test.github.mock_get_pull_request("some-user", "some-repo", 15, Ok(pr));
+    test.github.mock_get_pull_request_labels(
+        "some-user",
+        "some-repo",
+        15,
+        Ok(vec![github::Label::new("feature"), github::Label::new("bug")]),
+    );
+    // PRs that can't be looked up are left out rather than failing the notes
+    test.github.mock_get_pull_request("some-user", "some-repo", 16, Err(format_err!("Not found")));
+
+    test.jira.mock_get_issue("SER-1", Ok(issue("SER-1", "The feature")));
+    test.jira.mock_get_issue("SER-2", Err(format_err!("Forbidden")));
+
+    let notes = release_notes::generate(
+        &test.github,
+        &test.config.merges,
+        Some((&test.jira as &dyn jira::api::Session, &test.jira_config)),
+        &test.repo,
+        &vec!["SER".to_string()],
+        "v1.0",
+        "v1.1",
+    )
+    .unwrap();
+
+    assert_eq!("some-user/some-repo", notes.repo);
+    assert_eq!(
+        vec![("bug".to_string(), vec![12]), ("feature".to_string(), vec![15])],
+        notes
+            .groups
+            .iter()
+            .map(|g| (g.label.clone(), g.pull_requests.iter().map(|pr| pr.number).collect::<Vec<_>>()))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!("Fix the crash", notes.groups[0].pull_requests[0].title);
+    assert_eq!("joe", notes.groups[0].pull_requests[0].author);
+    assert_eq!(
+        vec![
+            ReleaseIssue {
+                key: "SER-1".into(),
+                summary: "The feature".into(),
+                url: "https://the-jira/browse/SER-1".into(),
+            },
+            ReleaseIssue {
+                key: "SER-2".into(),
+                summary: String::new(),
+                url: "https://the-jira/browse/SER-2".into(),
+            },
+        ],
+        notes.issues
+    );
+    assert_eq!(vec!["bob".to_string(), "joe".to_string(), "sue".to_string()], notes.contributors);
+}
+
+#[test]
+fn test_generate_release_notes_without_jira() {
+    let test = new_test();
+
+    test.github.mock_compare_commits(
+        "some-user",
+        "some-repo",
+        "v1.0",
+        "master",
+        Ok(vec![commit("[SER-3] Tweak the docs", None)]),
+    );
+
+    let notes = release_notes::generate(
+        &test.github,
+        &test.config.merges,
+        None,
+        &test.repo,
+        &vec!["SER".to_string()],
+        "v1.0",
+        "master",
+    )
+    .unwrap();
+
+    assert!(notes.groups.is_empty());
+    assert!(notes.contributors.is_empty());
+    assert_eq!(
+        vec![ReleaseIssue {
+            key: "SER-3".into(),
+            summary: String::new(),
+            url: String::new(),
+        }],
+        notes.issues
+    );
+}
@@ -0,0 +1,57 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use octobot::events::{self, Event, EVENT_VERSION};
+
+// Recorded events checked in to the repo. Set OCTOBOT_REPLAY_FIXTURES to also replay a
+// directory of events recorded by a running octobot (see `[testing] record_fixtures_dir`).
+fn fixture_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/events")];
+    if let Ok(dir) = env::var("OCTOBOT_REPLAY_FIXTURES") {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs
+}
+
+fn check_event(path: &Path, event: &Event) {
+    assert_eq!(EVENT_VERSION, event.version, "{}", path.display());
+
+    let body = match event.hook_body() {
+        Ok(b) => b,
+        Err(e) => panic!("{}: {}", path.display(), e),
+    };
+    assert_eq!(event.repo, body.repository.full_name, "{}", path.display());
+    assert_eq!(event.action, body.action.clone().unwrap_or(String::new()), "{}", path.display());
+
+    // make sure what each handler relies on is still there
+    match event.event.as_str() {
+        "pull_request" => assert!(body.pull_request.is_some(), "{}", path.display()),
+        "pull_request_review" => assert!(body.review.is_some(), "{}", path.display()),
+        "pull_request_review_comment" | "commit_comment" => assert!(body.comment.is_some(), "{}", path.display()),
+        "issue_comment" => assert!(body.issue.is_some() && body.comment.is_some(), "{}", path.display()),
+        "push" => assert!(body.ref_name.is_some() && body.after.is_some(), "{}", path.display()),
+        _ => (),
+    };
+}
+
+#[test]
+fn test_replay_recorded_events() {
+    let mut count = 0;
+    for dir in fixture_dirs() {
+        let fixtures = events::load_fixtures(&dir).expect("load fixtures");
+        for (path, event) in fixtures {
+            check_event(&path, &event);
+            count += 1;
+        }
+    }
+
+    assert!(count > 0, "No recorded events found");
+}
+
+#[test]
+fn test_recorded_events_are_scrubbed() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/events");
+    for (path, event) in events::load_fixtures(&dir).expect("load fixtures") {
+        assert_eq!(event.scrubbed(), event, "{} has unscrubbed values", path.display());
+    }
+}
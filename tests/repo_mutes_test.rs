@@ -0,0 +1,103 @@
+mod mocks;
+
+use std::sync::Arc;
+
+use tempdir::TempDir;
+
+use octobot::config::Config;
+use octobot::db::{self, Database};
+use octobot::github;
+use octobot::messenger;
+use octobot::repo_mutes::MuteExpirer;
+use octobot::repos::RepoInfo;
+use octobot::slack::{self, SlackAttachmentBuilder};
+use octobot::util;
+
+use mocks::mock_slack::MockSlack;
+
+const REPO_MSG: &'static str = "(<https://the-github-host/some-org/some-repo|some-org/some-repo>)";
+
+fn new_test() -> (Arc<Config>, TempDir) {
+    let temp_dir = TempDir::new("repo_mutes_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let mut config = Config::new(db);
+    config.github.host = "the-github-host".into();
+    config.users_write().insert("the-owner", "the.owner").unwrap();
+    config.repos_write().insert_info(&RepoInfo::new("some-org/some-repo", "the-reviews-channel")).unwrap();
+
+    (Arc::new(config), temp_dir)
+}
+
+fn the_repo() -> github::Repo {
+    github::Repo::parse("https://the-github-host/some-org/some-repo").unwrap()
+}
+
+#[test]
+fn test_muted_repo_messages_are_summarized() {
+    let (config, _temp) = new_test();
+    let now = db::now();
+    config.repo_mutes.mute("some-org/some-repo", now - 60, now + 3600, "the-owner").unwrap();
+
+    let mut slack = MockSlack::new(vec![]);
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+    messenger.send_to_owner(
+        "Pull Request merged",
+        &vec![],
+        &github::User::new("the-owner"),
+        &the_repo(),
+        "master",
+        &Vec::<github::Commit>::new(),
+    );
+    messenger.send_to_channel("Branch pushed", &vec![], &the_repo(), "master", &Vec::<github::Commit>::new());
+
+    let mute = config.repo_mutes.get("some-org/some-repo").unwrap().unwrap();
+    assert_eq!(2, mute.suppressed);
+
+    // nothing to summarize until the mute ends
+    let expirer = MuteExpirer::new(config.clone(), slack.new_sender());
+    expirer.run(now).unwrap();
+
+    assert!(config.repo_mutes.end("some-org/some-repo", now).unwrap());
+    let suppressed = config.repo_mutes.suppressed("some-org/some-repo").unwrap();
+    let lines = suppressed
+        .iter()
+        .map(|s| format!("• {}: {}", util::format_timestamp(s.created_at), s.msg))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec!["Pull Request merged", "Branch pushed"],
+        suppressed.iter().map(|s| s.msg.as_str()).collect::<Vec<_>>()
+    );
+
+    slack.expect(vec![slack::req(
+        "the-reviews-channel",
+        &format!(
+            "Notifications were muted from {} until {}: 2 message(s) were not sent {}",
+            util::format_timestamp(now - 60),
+            util::format_timestamp(now),
+            REPO_MSG
+        ),
+        vec![SlackAttachmentBuilder::new(&lines.join("\n")).title("Muted by the-owner").build()],
+    )]);
+    expirer.run(now).unwrap();
+
+    assert!(config.repo_mutes.get_all().unwrap().is_empty());
+
+    // and messages are sent again
+    slack.expect(vec![slack::req("the-reviews-channel", &format!("Branch pushed {}", REPO_MSG), vec![])]);
+    messenger.send_to_channel("Branch pushed", &vec![], &the_repo(), "master", &Vec::<github::Commit>::new());
+}
+
+#[test]
+fn test_scheduled_mute_not_yet_started() {
+    let (config, _temp) = new_test();
+    let now = db::now();
+    config.repo_mutes.mute("some-org/some-repo", now + 3600, now + 7200, "the-owner").unwrap();
+
+    let slack = MockSlack::new(vec![slack::req("the-reviews-channel", &format!("Branch pushed {}", REPO_MSG), vec![])]);
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+    messenger.send_to_channel("Branch pushed", &vec![], &the_repo(), "master", &Vec::<github::Commit>::new());
+
+    assert_eq!(0, config.repo_mutes.get("some-org/some-repo").unwrap().unwrap().suppressed);
+}
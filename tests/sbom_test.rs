@@ -0,0 +1,100 @@
+mod mocks;
+
+use failure::format_err;
+use tempdir::TempDir;
+
+use octobot::config::Config;
+use octobot::db::Database;
+use octobot::github;
+use octobot::sbom;
+
+use mocks::mock_github::MockGithub;
+
+struct SbomTest {
+    github: MockGithub,
+    config: Config,
+    req: sbom::SbomRequest,
+    _temp_dir: TempDir,
+}
+
+fn new_test() -> SbomTest {
+    let temp_dir = TempDir::new("sbom_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let repo = github::Repo::parse("http://the-github-host/some-user/some-repo").unwrap();
+    SbomTest {
+        github: MockGithub::new(),
+        config: Config::new(db),
+        req: sbom::req(&repo, &github::Release::new("v1.0")),
+        _temp_dir: temp_dir,
+    }
+}
+
+fn asset(name: &str) -> github::ReleaseAsset {
+    let mut asset = github::ReleaseAsset::new(name);
+    asset.browser_download_url = format!("http://the-github-host/some-user/some-repo/releases/download/v1.0/{}", name);
+    asset
+}
+
+#[test]
+fn test_attach_sbom_from_ci() {
+    let test = new_test();
+
+    let mut release = github::Release::new("v1.0");
+    release.assets = vec![asset("some-repo.tar.gz"), asset("some-repo.spdx.json")];
+    test.github.mock_get_release_by_tag("some-user", "some-repo", "v1.0", Ok(release));
+
+    let sbom = sbom::attach_sbom(&test.github, &test.config.sboms, &test.req, "abcdef", || {
+        panic!("should not generate an SBOM when CI attached one")
+    })
+    .unwrap();
+
+    assert_eq!("some-repo.spdx.json", sbom.asset_name);
+    assert_eq!(sbom::SOURCE_CI, sbom.source);
+    assert_eq!("", sbom.sha256);
+    assert_eq!(Some(sbom), test.config.sboms.get("some-user/some-repo", "v1.0").unwrap());
+}
+
+#[test]
+fn test_attach_generated_sbom() {
+    let test = new_test();
+
+    let release = github::Release::new("v1.0");
+    test.github.mock_get_release_by_tag("some-user", "some-repo", "v1.0", Ok(release));
+    test.github.mock_upload_release_asset(
+        "v1.0",
+        "some-repo-v1.0.sbom.json",
+        "application/json",
+        "{\"bomFormat\": \"CycloneDX\"}",
+        Ok(asset("some-repo-v1.0.sbom.json")),
+    );
+
+    let sbom = sbom::attach_sbom(&test.github, &test.config.sboms, &test.req, "abcdef", || {
+        Ok("{\"bomFormat\": \"CycloneDX\"}".into())
+    })
+    .unwrap();
+
+    assert_eq!(sbom::SOURCE_GENERATED, sbom.source);
+    assert_eq!("abcdef", sbom.commit_hash);
+    assert_eq!(sbom::sha256(b"{\"bomFormat\": \"CycloneDX\"}"), sbom.sha256);
+    assert_eq!(
+        "http://the-github-host/some-user/some-repo/releases/download/v1.0/some-repo-v1.0.sbom.json",
+        sbom.asset_url
+    );
+    assert_eq!(vec![sbom], test.config.sboms.get_all("some-user/some-repo").unwrap());
+}
+
+#[test]
+fn test_attach_sbom_generate_failure() {
+    let test = new_test();
+
+    test.github.mock_get_release_by_tag("some-user", "some-repo", "v1.0", Ok(github::Release::new("v1.0")));
+
+    let result = sbom::attach_sbom(&test.github, &test.config.sboms, &test.req, "abcdef", || {
+        Err(format_err!("no SBOM script"))
+    });
+
+    assert!(result.is_err());
+    assert_eq!(None, test.config.sboms.get("some-user/some-repo", "v1.0").unwrap());
+}
@@ -0,0 +1,235 @@
+mod mocks;
+
+use tempdir::TempDir;
+
+use octobot::auto_merge::{self, AutoMerge, MergeOutcome};
+use octobot::config::Config;
+use octobot::db::Database;
+use octobot::github;
+use octobot::repos::RepoMergeStrategy;
+use octobot::server::slack_actions;
+use octobot::users::UserInfo;
+
+use mocks::mock_github::MockGithub;
+
+fn new_test() -> (Config, TempDir) {
+    let temp_dir = TempDir::new("slack_actions_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    (Config::new(db), temp_dir)
+}
+
+fn the_pr(state: &str) -> github::PullRequest {
+    let mut pr = github::PullRequest::new();
+    pr.number = 32;
+    pr.state = state.into();
+    pr.user = github::User::new("the-pr-owner");
+    pr.head = github::BranchRef::new("feature");
+    pr.head.sha = "abcdef0123456".into();
+    pr
+}
+
+fn the_user() -> UserInfo {
+    UserInfo::new("the-reviewer", "the.reviewer")
+}
+
+fn the_merge() -> AutoMerge {
+    AutoMerge {
+        repo: "some-user/some-repo".into(),
+        number: 32,
+        head_sha: "abcdef0123456".into(),
+        requested_by: "the-reviewer".into(),
+    }
+}
+
+#[test]
+fn test_approve() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(the_pr("open")));
+    github.mock_approve_pull_request(
+        "some-user",
+        "some-repo",
+        32,
+        "abcdef0123456",
+        Some("Approved by @the-reviewer from Slack"),
+        Ok(()),
+    );
+
+    let msg = slack_actions::perform_action(&config, &github, &the_user(), "approve", "some-user", "some-repo", 32);
+    assert_eq!("Approved Pull Request #32", msg.unwrap());
+}
+
+#[test]
+fn test_approve_own_pr() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(the_pr("open")));
+
+    let owner = UserInfo::new("the-pr-owner", "the.pr.owner");
+    let msg = slack_actions::perform_action(&config, &github, &owner, "approve", "some-user", "some-repo", 32);
+    assert_eq!("You cannot approve your own pull request", msg.unwrap());
+}
+
+#[test]
+fn test_closed_pr() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(the_pr("closed")));
+
+    let msg = slack_actions::perform_action(&config, &github, &the_user(), "merge", "some-user", "some-repo", 32);
+    assert_eq!("Pull Request #32 is no longer open", msg.unwrap());
+    assert_eq!(0, config.auto_merges.get_all().unwrap().len());
+}
+
+#[test]
+fn test_revert() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+
+    let mut pr = the_pr("closed");
+    pr.merged = Some(true);
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(pr));
+    github.mock_add_pull_request_labels("some-user", "some-repo", 32, vec!["revert-me".into()], Ok(()));
+    github.mock_comment_pull_request(
+        "some-user",
+        "some-repo",
+        32,
+        "@the-reviewer requested from Slack that this be reverted",
+        Ok(()),
+    );
+
+    let msg = slack_actions::perform_action(&config, &github, &the_user(), "revert", "some-user", "some-repo", 32);
+    assert_eq!(
+        "Pull Request #32 will be reverted: octobot will open a revert PR and let its author know",
+        msg.unwrap()
+    );
+}
+
+#[test]
+fn test_revert_not_merged() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(the_pr("closed")));
+
+    let msg = slack_actions::perform_action(&config, &github, &the_user(), "revert", "some-user", "some-repo", 32);
+    assert_eq!("Pull Request #32 is not merged", msg.unwrap());
+}
+
+#[test]
+fn test_merge_when_green() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(the_pr("open")));
+    github.mock_comment_pull_request(
+        "some-user",
+        "some-repo",
+        32,
+        "@the-reviewer requested from Slack that this be merged once all checks pass (abcdef0)",
+        Ok(()),
+    );
+
+    let msg = slack_actions::perform_action(&config, &github, &the_user(), "merge", "some-user", "some-repo", 32);
+    assert_eq!("Pull Request #32 will be merged once all checks pass", msg.unwrap());
+    assert_eq!(vec![the_merge()], config.auto_merges.get_all().unwrap());
+
+    // still pending
+    let mut pr = the_pr("open");
+    pr.mergeable_state = Some("blocked".into());
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(pr.clone()));
+    let outcome = auto_merge::try_merge(&github, &config.auto_merges, &vec![], &the_merge()).unwrap();
+    assert_eq!(MergeOutcome::Waiting, outcome);
+
+    // now green
+    pr.mergeable_state = Some("clean".into());
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(pr));
+    github.mock_merge_pull_request("some-user", "some-repo", 32, "abcdef0123456", &Default::default(), Ok(()));
+    let outcome = auto_merge::try_merge(&github, &config.auto_merges, &vec![], &the_merge()).unwrap();
+    assert_eq!(MergeOutcome::Merged, outcome);
+    assert_eq!(0, config.auto_merges.get_all().unwrap().len());
+}
+
+#[test]
+fn test_merge_when_green_with_strategy() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+    config.auto_merges.add(&the_merge()).unwrap();
+
+    let strategies = vec![
+        RepoMergeStrategy::new("release/*", "rebase", ""),
+        RepoMergeStrategy::new(
+            "",
+            "squash",
+            "{{pr_title}} (#{{pr_number}})\n\n{{#each commits}}* {{title}}\n{{/each}}",
+        ),
+    ];
+
+    let mut pr = the_pr("open");
+    pr.title = "Add the feature".into();
+    pr.base = github::BranchRef::new("main");
+    pr.mergeable_state = Some("clean".into());
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(pr));
+
+    let mut commit1 = github::Commit::new();
+    commit1.commit.message = "First part\n\ndetails".into();
+    let mut commit2 = github::Commit::new();
+    commit2.commit.message = "Second part".into();
+    github.mock_get_pull_request_commits("some-user", "some-repo", 32, Ok(vec![commit1, commit2]));
+
+    let options = github::MergeOptions {
+        merge_method: Some("squash".into()),
+        commit_title: Some("Add the feature (#32)".into()),
+        commit_message: Some("* First part\n* Second part".into()),
+    };
+    github.mock_merge_pull_request("some-user", "some-repo", 32, "abcdef0123456", &options, Ok(()));
+    let outcome = auto_merge::try_merge(&github, &config.auto_merges, &strategies, &the_merge()).unwrap();
+    assert_eq!(MergeOutcome::Merged, outcome);
+
+    // release branches are rebased, keeping the commits' messages
+    config.auto_merges.add(&the_merge()).unwrap();
+    let mut pr = the_pr("open");
+    pr.base = github::BranchRef::new("release/1.0");
+    pr.mergeable_state = Some("clean".into());
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(pr));
+
+    let options = github::MergeOptions {
+        merge_method: Some("rebase".into()),
+        commit_title: None,
+        commit_message: None,
+    };
+    github.mock_merge_pull_request("some-user", "some-repo", 32, "abcdef0123456", &options, Ok(()));
+    let outcome = auto_merge::try_merge(&github, &config.auto_merges, &strategies, &the_merge()).unwrap();
+    assert_eq!(MergeOutcome::Merged, outcome);
+}
+
+#[test]
+fn test_merge_when_green_new_commits() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+
+    config.auto_merges.add(&the_merge()).unwrap();
+
+    let mut pr = the_pr("open");
+    pr.head.sha = "fedcba9876543".into();
+    pr.mergeable_state = Some("clean".into());
+    github.mock_get_pull_request("some-user", "some-repo", 32, Ok(pr));
+    github.mock_comment_pull_request(
+        "some-user",
+        "some-repo",
+        32,
+        "Not merging as requested by @the-reviewer: new commits were pushed",
+        Ok(()),
+    );
+
+    assert_eq!(
+        MergeOutcome::Abandoned("new commits were pushed".into()),
+        auto_merge::try_merge(&github, &config.auto_merges, &vec![], &the_merge()).unwrap()
+    );
+    assert_eq!(0, config.auto_merges.get_all().unwrap().len());
+}
@@ -0,0 +1,90 @@
+mod mocks;
+
+use tempdir::TempDir;
+
+use octobot::db::Database;
+use octobot::diagnostics::{EventDiagnosis, EventDiagnostics, Trace};
+use octobot::github;
+use octobot::server::slack_command;
+
+use mocks::mock_github::MockGithub;
+
+fn the_pr(number: u32, reviewers: Option<Vec<github::User>>) -> github::PullRequest {
+    let mut pr = github::PullRequest::new();
+    pr.number = number;
+    pr.title = format!("The PR {}", number);
+    pr.html_url = format!("http://the-pr/{}", number);
+    pr.user = github::User::new("the-pr-owner");
+    pr.requested_reviewers = reviewers;
+    pr
+}
+
+#[test]
+fn test_status_message() {
+    let github = MockGithub::new();
+
+    let mut draft = the_pr(3, Some(vec![github::User::new("joe")]));
+    draft.draft = Some(true);
+    github.mock_get_pull_requests(
+        "some-org",
+        "some-repo",
+        Some("open"),
+        None,
+        Ok(vec![
+            the_pr(1, Some(vec![github::User::new("joe"), github::User::new("bob")])),
+            the_pr(2, Some(vec![])),
+            draft,
+            the_pr(4, None),
+        ]),
+    );
+
+    assert_eq!(
+        "1 pull request(s) in some-org/some-repo awaiting review:\n\
+         • <http://the-pr/1|#1: The PR 1> by the-pr-owner, waiting on joe, bob",
+        slack_command::status_message(&github, "some-org", "some-repo").unwrap()
+    );
+}
+
+#[test]
+fn test_status_message_none() {
+    let github = MockGithub::new();
+
+    github.mock_get_pull_requests("some-org", "some-repo", Some("open"), None, Ok(vec![the_pr(2, None)]));
+
+    assert_eq!(
+        "No open pull requests in some-org/some-repo are awaiting review",
+        slack_command::status_message(&github, "some-org", "some-repo").unwrap()
+    );
+}
+
+#[test]
+fn test_history_message() {
+    let temp_dir = TempDir::new("slack_command_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let diagnostics = EventDiagnostics::new(Database::new(&db_file.to_string_lossy()).unwrap());
+
+    assert_eq!(
+        "No recent events for some-org/some-repo#32: octobot has not received any, or they are too old to remember",
+        slack_command::history_message(&diagnostics, "some-org", "some-repo", 32).unwrap()
+    );
+
+    let trace = Trace::new();
+    trace.sent("Sent to channel 'reviews'");
+    trace.note("No slack user mapped to github user 'joe'");
+    let mut opened = EventDiagnosis::new("abc-1", "pull_request", "opened", "some-org/some-repo", 32, "pr", &trace);
+    opened.created_at = 0;
+    diagnostics.record(&opened).unwrap();
+
+    let mut status = EventDiagnosis::new("abc-2", "status", "", "some-org/some-repo", 32, "status", &Trace::new());
+    status.created_at = 60;
+    diagnostics.record(&status).unwrap();
+
+    assert_eq!(
+        "History of some-org/some-repo#32:\n\
+         • 1970-01-01 00:00 UTC: `pull_request opened`, 1 notification(s)\n\
+         \x20   ◦ Sent to channel 'reviews'\n\
+         \x20   ◦ No slack user mapped to github user 'joe'\n\
+         • 1970-01-01 00:01 UTC: `status`, 0 notification(s)",
+        slack_command::history_message(&diagnostics, "some-org", "some-repo", 32).unwrap()
+    );
+}
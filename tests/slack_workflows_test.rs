@@ -0,0 +1,89 @@
+mod mocks;
+
+use failure::format_err;
+use serde_json;
+
+use octobot::github;
+use octobot::slack_workflows::{self, ExecutedStep};
+
+use mocks::mock_github::MockGithub;
+
+fn step(inputs: &str) -> ExecutedStep {
+    serde_json::from_str(&format!(r#"{{"workflow_step_execute_id": "exec-1", "inputs": {}}}"#, inputs)).unwrap()
+}
+
+#[test]
+fn test_create_backport() {
+    let github = MockGithub::new();
+    let mut pr = github::PullRequest::new();
+    pr.number = 32;
+    pr.html_url = "http://the-pr".into();
+
+    github.mock_get_pull_request("some-org", "some-repo", 32, Ok(pr));
+    github.mock_add_pull_request_labels("some-org", "some-repo", 32, vec!["backport-1.2".into()], Ok(()));
+
+    let outputs = slack_workflows::perform_step(
+        &github,
+        slack_workflows::CREATE_BACKPORT,
+        &step(
+            r##"{"repo": {"value": "some-org/some-repo"}, "pull_request": {"value": "#32"},
+                 "branch": {"value": "1.2"}}"##,
+        ),
+    )
+    .unwrap();
+
+    assert_eq!("backport-1.2", outputs["label"]);
+    assert_eq!("http://the-pr", outputs["pull_request_url"]);
+}
+
+#[test]
+fn test_create_backport_bad_number() {
+    let github = MockGithub::new();
+
+    let res = slack_workflows::perform_step(
+        &github,
+        slack_workflows::CREATE_BACKPORT,
+        &step(
+            r#"{"repo": {"value": "some-org/some-repo"}, "pull_request": {"value": "abc"},
+                "branch": {"value": "1.2"}}"#,
+        ),
+    );
+
+    assert_eq!("Invalid pull request number 'abc'", format!("{}", res.unwrap_err()));
+}
+
+#[test]
+fn test_release_state() {
+    let github = MockGithub::new();
+    let mut release = github::Release::new("v1.2.0");
+    release.html_url = "http://the-release".into();
+    release.prerelease = true;
+
+    github.mock_get_latest_release("some-org", "some-repo", Ok(release));
+
+    let outputs = slack_workflows::perform_step(
+        &github,
+        slack_workflows::RELEASE_STATE,
+        &step(r#"{"repo": {"value": "some-org/some-repo"}}"#),
+    )
+    .unwrap();
+
+    assert_eq!("v1.2.0", outputs["tag_name"]);
+    assert_eq!("v1.2.0", outputs["name"]);
+    assert_eq!("http://the-release", outputs["url"]);
+    assert_eq!("true", outputs["prerelease"]);
+}
+
+#[test]
+fn test_release_state_no_releases() {
+    let github = MockGithub::new();
+    github.mock_get_latest_release("some-org", "some-repo", Err(format_err!("Not Found")));
+
+    let res = slack_workflows::perform_step(
+        &github,
+        slack_workflows::RELEASE_STATE,
+        &step(r#"{"repo": {"value": "some-org/some-repo"}}"#),
+    );
+
+    assert!(res.is_err());
+}
@@ -0,0 +1,115 @@
+mod mocks;
+
+use std::sync::Arc;
+
+use tempdir::TempDir;
+
+use octobot::config::Config;
+use octobot::db::Database;
+use octobot::github;
+use octobot::messenger;
+use octobot::repos::RepoInfo;
+use octobot::slack::{self, SlackAttachmentBuilder};
+use octobot::stale_prs;
+
+use mocks::mock_github::MockGithub;
+use mocks::mock_slack::MockSlack;
+
+// 2019-05-01T12:00:00Z
+const NOW: i64 = 1556712000;
+
+fn new_test() -> (Arc<Config>, TempDir) {
+    let temp_dir = TempDir::new("stale_prs_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let config = Arc::new(Config::new(db));
+    config.users_write().insert("joe-reviewer", "joe.reviewer").unwrap();
+    config.users_write().insert("smith-reviewer", "smith.reviewer").unwrap();
+
+    (config, temp_dir)
+}
+
+fn the_repo() -> github::Repo {
+    github::Repo::parse("http://the-github-host/some-user/some-repo").unwrap()
+}
+
+fn stale_pr() -> github::PullRequest {
+    let mut pr = github::PullRequest::new();
+    pr.number = 32;
+    pr.title = "The PR".into();
+    pr.html_url = "http://the-pr".into();
+    pr.created_at = Some("2019-04-26T12:00:00Z".into());
+    pr.requested_reviewers = Some(vec![github::User::new("joe-reviewer"), github::User::new("smith-reviewer")]);
+    pr
+}
+
+fn fresh_pr() -> github::PullRequest {
+    let mut pr = stale_pr();
+    pr.number = 33;
+    pr.created_at = Some("2019-04-30T12:00:00Z".into());
+    pr
+}
+
+fn attachment() -> slack::SlackAttachment {
+    SlackAttachmentBuilder::new("")
+        .title("Pull Request #32: \"The PR\"")
+        .title_link("http://the-pr")
+        .build()
+}
+
+#[test]
+fn test_send_reminders() {
+    let (config, _temp) = new_test();
+    let info = RepoInfo::new("some-user/some-repo", "the-reviews-channel").with_stale_pr_reminders(3, false, "");
+
+    let github = MockGithub::new();
+    github.mock_get_pull_requests("some-user", "some-repo", Some("open"), None, Ok(vec![stale_pr(), fresh_pr()]));
+
+    let msg = "Pull Request has been waiting for your review for 5 days";
+    let slack = MockSlack::new(vec![
+        slack::req("@joe.reviewer", msg, vec![attachment()]),
+        slack::req("@smith.reviewer", msg, vec![attachment()]),
+    ]);
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+
+    stale_prs::send_reminders(&github, &messenger, &the_repo(), &info, NOW).unwrap();
+}
+
+#[test]
+fn test_send_reminders_with_digest() {
+    let (config, _temp) = new_test();
+    let info = RepoInfo::new("some-user/some-repo", "the-reviews-channel").with_stale_pr_reminders(3, true, "");
+    config.repos_write().insert_info(&info).unwrap();
+
+    let github = MockGithub::new();
+    github.mock_get_pull_requests("some-user", "some-repo", Some("open"), None, Ok(vec![stale_pr(), fresh_pr()]));
+
+    let msg = "Pull Request has been waiting for your review for 5 days";
+    let slack = MockSlack::new(vec![
+        slack::req("@joe.reviewer", msg, vec![attachment()]),
+        slack::req("@smith.reviewer", msg, vec![attachment()]),
+        slack::req(
+            "the-reviews-channel",
+            "1 pull request(s) waiting more than 3 days for review (<http://the-github-host/some-user/some-repo|some-user/some-repo>)",
+            vec![attachment()],
+        ),
+    ]);
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+
+    stale_prs::send_reminders(&github, &messenger, &the_repo(), &info, NOW).unwrap();
+}
+
+#[test]
+fn test_send_reminders_nothing_stale() {
+    let (config, _temp) = new_test();
+    let info = RepoInfo::new("some-user/some-repo", "the-reviews-channel").with_stale_pr_reminders(3, true, "");
+
+    let github = MockGithub::new();
+    github.mock_get_pull_requests("some-user", "some-repo", Some("open"), None, Ok(vec![fresh_pr()]));
+
+    let slack = MockSlack::new(vec![]);
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+
+    stale_prs::send_reminders(&github, &messenger, &the_repo(), &info, NOW).unwrap();
+}
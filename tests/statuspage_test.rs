@@ -0,0 +1,176 @@
+mod mocks;
+
+use std::sync::{Arc, Mutex};
+
+use tempdir::TempDir;
+
+use octobot::config::{Config, StatuspageConfig};
+use octobot::db::{self, Database};
+use octobot::errors::*;
+use octobot::github;
+use octobot::messenger;
+use octobot::repos::RepoInfo;
+use octobot::slack::{self, SlackAttachmentBuilder};
+use octobot::statuspage::{self, IncidentWatcher, Status};
+use octobot::util;
+
+use mocks::mock_slack::MockSlack;
+
+const REPO_MSG: &'static str = "(<https://the-github-host/some-org/some-repo|some-org/some-repo>)";
+
+struct FakeStatuspage {
+    status: Mutex<Status>,
+}
+
+impl FakeStatuspage {
+    fn new() -> Arc<FakeStatuspage> {
+        Arc::new(FakeStatuspage {
+            status: Mutex::new(status("none", "All Systems Operational")),
+        })
+    }
+
+    fn set(&self, indicator: &str, description: &str) {
+        *self.status.lock().unwrap() = status(indicator, description);
+    }
+}
+
+impl statuspage::Session for FakeStatuspage {
+    fn get_status(&self) -> Result<Status> {
+        Ok(self.status.lock().unwrap().clone())
+    }
+}
+
+fn status(indicator: &str, description: &str) -> Status {
+    Status {
+        indicator: indicator.into(),
+        description: description.into(),
+    }
+}
+
+fn new_test() -> (Arc<Config>, TempDir) {
+    let temp_dir = TempDir::new("statuspage_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let mut config = Config::new(db);
+    config.github.host = "the-github-host".into();
+    config.statuspage = Some(StatuspageConfig {
+        url: "https://www.githubstatus.com/api/v2/status.json".into(),
+        provider: None,
+        min_indicator: None,
+        channel: Some("eng-announcements".into()),
+    });
+    config.users_write().insert("the-owner", "the.owner").unwrap();
+    config.repos_write().insert_info(&RepoInfo::new("some-org/some-repo", "the-reviews-channel")).unwrap();
+
+    (Arc::new(config), temp_dir)
+}
+
+fn the_repo() -> github::Repo {
+    github::Repo::parse("https://the-github-host/some-org/some-repo").unwrap()
+}
+
+#[test]
+fn test_notifications_paused_during_incident() {
+    let (config, _temp) = new_test();
+    let session = FakeStatuspage::new();
+    let now = db::now();
+
+    let mut slack = MockSlack::new(vec![]);
+    let watcher = IncidentWatcher::new(config.clone(), session.clone(), slack.new_sender());
+
+    // minor incidents don't count by default
+    session.set("minor", "Minor Service Outage");
+    watcher.run(now).unwrap();
+    assert!(config.provider_incidents.current().unwrap().is_none());
+
+    slack.expect(vec![slack::req(
+        "eng-announcements",
+        "GitHub is having an incident: Partial System Outage. Notifications other than failures are paused until it is \
+         resolved.",
+        vec![],
+    )]);
+    session.set("major", "Partial System Outage");
+    watcher.run(now).unwrap();
+
+    let messenger = messenger::new(config.clone(), slack.new_sender());
+    slack.expect(vec![]);
+    messenger.send_to_owner(
+        "Pull Request merged",
+        &vec![],
+        &github::User::new("the-owner"),
+        &the_repo(),
+        "master",
+        &Vec::<github::Commit>::new(),
+    );
+
+    // failures are still sent, with the incident
+    let attach = SlackAttachmentBuilder::new("502 Bad Gateway").color("danger").build();
+    let msg = "Error creating merge PR from feature to release (GitHub is having an incident: Partial System Outage)";
+    slack.expect(vec![
+        slack::req("the-reviews-channel", &format!("{} {}", msg, REPO_MSG), vec![attach.clone()]),
+        slack::req("@the.owner", msg, vec![attach.clone()]),
+    ]);
+    messenger.send_to_owner(
+        "Error creating merge PR from feature to release",
+        &vec![attach.clone()],
+        &github::User::new("the-owner"),
+        &the_repo(),
+        "release",
+        &Vec::<github::Commit>::new(),
+    );
+
+    let incident = config.provider_incidents.current().unwrap().unwrap();
+    let paused = config.provider_incidents.paused(incident.id).unwrap();
+    assert_eq!(vec!["Pull Request merged"], paused.iter().map(|s| s.msg.as_str()).collect::<Vec<_>>());
+
+    let lines = paused
+        .iter()
+        .map(|s| format!("• {}: {}", util::format_timestamp(s.created_at), s.msg))
+        .collect::<Vec<_>>();
+    slack.expect(vec![slack::req(
+        "eng-announcements",
+        &format!(
+            "GitHub's incident is resolved: notifications were paused from {} until {}, and 1 message(s) were not sent",
+            util::format_timestamp(now),
+            util::format_timestamp(now + 60)
+        ),
+        vec![SlackAttachmentBuilder::new(&lines.join("\n")).title("Partial System Outage").build()],
+    )]);
+    session.set("none", "All Systems Operational");
+    watcher.run(now + 60).unwrap();
+    assert!(config.provider_incidents.current().unwrap().is_none());
+
+    // and messages are sent again
+    slack.expect(vec![slack::req("the-reviews-channel", &format!("Branch pushed {}", REPO_MSG), vec![])]);
+    messenger.send_to_channel("Branch pushed", &vec![], &the_repo(), "master", &Vec::<github::Commit>::new());
+}
+
+#[test]
+fn test_incident_updates() {
+    let (config, _temp) = new_test();
+    let session = FakeStatuspage::new();
+    let now = db::now();
+
+    let mut slack = MockSlack::new(vec![slack::req(
+        "eng-announcements",
+        "GitHub is having an incident: Partial System Outage. Notifications other than failures are paused until it is \
+         resolved.",
+        vec![],
+    )]);
+    let watcher = IncidentWatcher::new(config.clone(), session.clone(), slack.new_sender());
+
+    session.set("major", "Partial System Outage");
+    watcher.run(now).unwrap();
+
+    // updates aren't announced
+    slack.expect(vec![]);
+    session.set("critical", "Major System Outage");
+    watcher.run(now + 60).unwrap();
+
+    let incident = config.provider_incidents.current().unwrap().unwrap();
+    assert_eq!("critical", incident.indicator);
+    assert_eq!("Major System Outage", incident.description);
+    assert_eq!(now, incident.started_at);
+    assert_eq!(1, config.provider_incidents.get_all().unwrap().len());
+}
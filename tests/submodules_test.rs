@@ -0,0 +1,144 @@
+mod git_helper;
+mod mocks;
+
+use tempdir::TempDir;
+
+use octobot::config::Config;
+use octobot::db::Database;
+use octobot::github;
+use octobot::repos::{RepoInfo, RepoSubmodule};
+use octobot::submodules;
+
+use git_helper::temp_git::TempGit;
+use mocks::mock_github::MockGithub;
+
+struct SubmoduleTest {
+    upstream: TempGit,
+    downstream: TempGit,
+    github: MockGithub,
+    config: Config,
+    _temp_dir: TempDir,
+}
+
+fn new_test() -> SubmoduleTest {
+    let temp_dir = TempDir::new("submodules_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    SubmoduleTest {
+        upstream: TempGit::new(),
+        downstream: TempGit::new(),
+        github: MockGithub::new(),
+        config: Config::new(db),
+        _temp_dir: temp_dir,
+    }
+}
+
+impl SubmoduleTest {
+    fn upstream_url(&self) -> String {
+        self.upstream.repo_dir.join("../remote").to_string_lossy().into_owned()
+    }
+
+    // pins the downstream repo's "lib" submodule at upstream's current commit
+    fn pin_upstream(&self) -> String {
+        let commit = self.upstream.git.current_commit().unwrap();
+        self.downstream.run_git(&["update-index", "--add", "--cacheinfo", &format!("160000,{},lib", commit)]);
+        self.downstream.run_git(&["commit", "-m", "Add lib submodule"]);
+        self.downstream.run_git(&["push", "origin", "master"]);
+        commit
+    }
+
+    fn release_upstream(&self, tag: &str) -> String {
+        self.upstream.add_repo_file("lib.txt", tag, &format!("Release {}", tag));
+        self.upstream.run_git(&["tag", "-a", tag, "-m", tag]);
+        self.upstream.run_git(&["push", "origin", "master", "--tags"]);
+        self.upstream.git.current_commit().unwrap()
+    }
+}
+
+#[test]
+fn test_remote_tag_commit() {
+    let test = new_test();
+    let commit = test.release_upstream("v1.0");
+
+    let url = test.upstream_url();
+    assert_eq!(commit, submodules::remote_tag_commit(&test.downstream.git, &url, "v1.0").unwrap());
+    assert!(submodules::remote_tag_commit(&test.downstream.git, &url, "v2.0").is_err());
+}
+
+#[test]
+fn test_bump_submodule() {
+    let test = new_test();
+    let old_commit = test.pin_upstream();
+    let new_commit = test.release_upstream("v1.0");
+
+    let upstream = github::Repo::parse("http://the-github-host/some-user/some-lib").unwrap();
+    let release = github::Release::new("v1.0");
+    let req = submodules::req(&upstream, &release);
+    let submodule = RepoSubmodule::new("some-user/some-lib", "lib").with_auto_merge(true);
+    let downstream = RepoInfo::new("some-user/some-app", "reviews").with_submodule(submodule.clone());
+
+    let mut new_pr = github::PullRequest::new();
+    new_pr.number = 5;
+    new_pr.head.sha = "abcdef".into();
+    test.github.mock_create_pull_request(
+        "some-user",
+        "some-app",
+        "Bump lib to v1.0",
+        "Updates the some-user/some-lib submodule to v1.0.\n\n\
+         Release notes: http://the-github-host/some-user/some-lib/releases/tag/v1.0",
+        "octobot/bump-lib-v1.0",
+        "master",
+        Ok(new_pr),
+    );
+
+    assert_eq!(old_commit, submodules::pinned_commit(&test.downstream.git, "lib").unwrap());
+
+    let pr = submodules::bump_submodule(
+        &test.downstream.git,
+        &test.github,
+        &test.config,
+        &downstream,
+        &submodule,
+        &req,
+        &new_commit,
+    )
+    .unwrap();
+    assert_eq!(Some(5), pr.map(|p| p.number));
+
+    test.downstream.run_git(&["fetch"]);
+    assert_eq!(new_commit, submodules::pinned_commit(&test.downstream.git, "lib").unwrap());
+    assert_eq!(
+        "Bump lib to v1.0",
+        test.downstream.git.get_commit_desc("origin/octobot/bump-lib-v1.0").unwrap().0
+    );
+
+    let merges = test.config.auto_merges.get_all().unwrap();
+    assert_eq!(1, merges.len());
+    assert_eq!(("some-user/some-app", 5, "abcdef"), (merges[0].repo.as_str(), merges[0].number, merges[0].head_sha.as_str()));
+}
+
+#[test]
+fn test_bump_submodule_already_pinned() {
+    let test = new_test();
+    test.release_upstream("v1.0");
+    let commit = test.pin_upstream();
+
+    let upstream = github::Repo::parse("http://the-github-host/some-user/some-lib").unwrap();
+    let req = submodules::req(&upstream, &github::Release::new("v1.0"));
+    let submodule = RepoSubmodule::new("some-user/some-lib", "lib");
+    let downstream = RepoInfo::new("some-user/some-app", "reviews").with_submodule(submodule.clone());
+
+    let pr = submodules::bump_submodule(
+        &test.downstream.git,
+        &test.github,
+        &test.config,
+        &downstream,
+        &submodule,
+        &req,
+        &commit,
+    )
+    .unwrap();
+    assert_eq!(None, pr);
+    assert_eq!(0, test.config.auto_merges.get_all().unwrap().len());
+}
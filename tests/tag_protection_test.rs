@@ -0,0 +1,61 @@
+mod git_helper;
+
+use octobot::tag_protection;
+
+use git_helper::temp_git::TempGit;
+
+fn remote_tag(git: &TempGit, tag: &str) -> String {
+    git.run_git(&["ls-remote", "origin", &format!("refs/tags/{}", tag)])
+}
+
+#[test]
+fn test_restore_deleted_tag() {
+    let git = TempGit::new();
+    git.add_repo_file("file.txt", "v1", "Release v1.0");
+    git.run_git(&["tag", "-a", "v1.0", "-m", "v1.0"]);
+    git.run_git(&["push", "origin", "v1.0"]);
+    let tag_object = git.run_git(&["rev-parse", "v1.0"]).trim().to_string();
+
+    // delete it from the remote only, as if someone else had
+    git.run_git(&["push", "origin", ":refs/tags/v1.0"]);
+    assert_eq!("", remote_tag(&git, "v1.0"));
+
+    assert!(tag_protection::restore_tag(&git.git, "v1.0", &tag_object).unwrap());
+    assert!(remote_tag(&git, "v1.0").starts_with(&tag_object));
+}
+
+#[test]
+fn test_restore_moved_tag() {
+    let git = TempGit::new();
+    git.add_repo_file("file.txt", "v1", "Release v1.0");
+    git.run_git(&["tag", "v1.0"]);
+    git.run_git(&["push", "origin", "master", "v1.0"]);
+    let original = git.git.current_commit().unwrap();
+
+    git.add_repo_file("file.txt", "sneaky", "Not v1.0");
+    git.run_git(&["tag", "-f", "v1.0"]);
+    git.run_git(&["push", "-f", "origin", "master", "v1.0"]);
+    assert!(!remote_tag(&git, "v1.0").starts_with(&original));
+
+    assert!(tag_protection::restore_tag(&git.git, "v1.0", &original).unwrap());
+    assert!(remote_tag(&git, "v1.0").starts_with(&original));
+}
+
+#[test]
+fn test_restore_tag_unknown_commit() {
+    let git = TempGit::new();
+    let missing = "1234567890123456789012345678901234567890";
+    assert!(!tag_protection::restore_tag(&git.git, "v1.0", missing).unwrap());
+}
+
+#[test]
+fn test_delete_tag() {
+    let git = TempGit::new();
+    git.add_repo_file("file.txt", "v1", "Release v1.0");
+    git.run_git(&["tag", "v1.0"]);
+    git.run_git(&["push", "origin", "master", "v1.0"]);
+    assert!(remote_tag(&git, "v1.0") != "");
+
+    assert!(tag_protection::restore_tag(&git.git, "v1.0", "").unwrap());
+    assert_eq!("", remote_tag(&git, "v1.0"));
+}
@@ -0,0 +1,121 @@
+mod mocks;
+
+use tempdir::TempDir;
+
+use octobot::config::Config;
+use octobot::db::Database;
+use octobot::github;
+use octobot::jira;
+use octobot::user_discovery::{self, SlackUser};
+use octobot::users::UserInfo;
+
+use mocks::mock_github::MockGithub;
+use mocks::mock_jira::MockJira;
+
+const NOW: i64 = 1556712000;
+
+fn new_test() -> (Config, TempDir) {
+    let temp_dir = TempDir::new("user_discovery_test.rs").unwrap();
+    let db_file = temp_dir.path().join("db.sqlite3");
+    let db = Database::new(&db_file.to_string_lossy()).expect("create temp database");
+
+    let config = Config::new(db);
+    let mut mapped = UserInfo::new("mapped", "mapped.slack");
+    mapped.email = "mapped@company.com".into();
+    config.users_write().insert_info(&mapped).unwrap();
+
+    (config, temp_dir)
+}
+
+fn github_user(login: &str, email: Option<&str>) -> github::User {
+    let mut user = github::User::new(login);
+    user.email = email.map(|e| e.to_string());
+    user
+}
+
+fn jira_user(name: &str, email: &str) -> jira::User {
+    jira::User {
+        name: Some(name.into()),
+        account_id: None,
+        display_name: None,
+        email_address: Some(email.into()),
+    }
+}
+
+fn slack_users() -> Vec<SlackUser> {
+    vec![
+        SlackUser {
+            name: "joe.slack".into(),
+            email: "joe@company.com".into(),
+        },
+        SlackUser {
+            name: "mapped.slack".into(),
+            email: "mapped@company.com".into(),
+        },
+    ]
+}
+
+fn mock_org(github: &MockGithub) {
+    let mut bot = github::User::new("ci[bot]");
+    bot.user_type = Some("Bot".into());
+    github.mock_get_org_members(
+        "some-org",
+        Ok(vec![github::User::new("joe"), github::User::new("mapped"), bot, github::User::new("private")]),
+    );
+    github.mock_get_user("joe", Ok(github_user("joe", Some("Joe@Company.com"))));
+    github.mock_get_user("private", Ok(github_user("private", None)));
+}
+
+#[test]
+fn test_discover_proposes_mappings() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+    let jira = MockJira::new();
+
+    mock_org(&github);
+    jira.mock_search_users(
+        "joe@company.com",
+        Ok(vec![jira_user("joseph", "joseph@company.com"), jira_user("joe", "JOE@company.com")]),
+    );
+
+    let count = user_discovery::discover(&config, &github, "some-org", &slack_users(), Some(&jira), NOW).unwrap();
+    assert_eq!(1, count);
+
+    let proposals = config.user_mappings.list(Some(user_discovery::PENDING)).unwrap();
+    assert_eq!(1, proposals.len());
+    assert_eq!(
+        ("joe", "joe.slack", "joe@company.com", "joe", NOW),
+        (
+            proposals[0].github.as_str(),
+            proposals[0].slack.as_str(),
+            proposals[0].email.as_str(),
+            proposals[0].jira.as_str(),
+            proposals[0].proposed_at
+        )
+    );
+
+    // nothing new the next time around
+    mock_org(&github);
+    jira.mock_search_users("joe@company.com", Ok(vec![]));
+    let count = user_discovery::discover(&config, &github, "some-org", &slack_users(), Some(&jira), NOW).unwrap();
+    assert_eq!(0, count);
+}
+
+#[test]
+fn test_discover_without_jira() {
+    let (config, _temp) = new_test();
+    let github = MockGithub::new();
+
+    mock_org(&github);
+    let count = user_discovery::discover(&config, &github, "some-org", &slack_users(), None, NOW).unwrap();
+    assert_eq!(1, count);
+
+    let proposal = config.user_mappings.list(None).unwrap().remove(0);
+    assert_eq!("", proposal.jira);
+
+    user_discovery::apply(&config, &proposal).unwrap();
+    assert!(config.user_mappings.decide(proposal.id, user_discovery::CONFIRMED, "admin", NOW).unwrap());
+
+    let joe = config.users().lookup_info("joe").unwrap();
+    assert_eq!(("joe.slack", "joe@company.com"), (joe.slack.as_str(), joe.email.as_str()));
+}